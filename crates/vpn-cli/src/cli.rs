@@ -1,4 +1,6 @@
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -36,6 +38,11 @@ pub struct Cli {
     /// Output format (json, table, plain)
     #[arg(long, default_value = "table")]
     pub format: OutputFormat,
+
+    /// Correlation ID to report this run under, e.g. one passed down by a
+    /// calling script; a new one is generated by default
+    #[arg(long, value_name = "ID")]
+    pub correlation_id: Option<String>,
 }
 
 #[derive(Subcommand, Clone)]
@@ -69,6 +76,27 @@ pub enum Commands {
         /// Interactive subnet selection
         #[arg(long)]
         interactive_subnet: bool,
+
+        /// Gate management ports behind port knocking or single packet authorization
+        #[arg(long)]
+        access_gate: Option<AccessGateMode>,
+
+        /// TCP ports to gate (defaults to the protocol's management ports)
+        #[arg(long, value_delimiter = ',')]
+        gate_ports: Vec<u16>,
+
+        /// UDP knock sequence for knock mode (random if omitted)
+        #[arg(long, value_delimiter = ',')]
+        knock_ports: Vec<u16>,
+
+        /// UDP port receiving SPA packets
+        #[arg(long, default_value = "62201")]
+        spa_port: u16,
+
+        /// Remove containers not labeled as managed by this tool that hold
+        /// the container names the install needs
+        #[arg(long)]
+        take_ownership: bool,
     },
 
     /// Uninstall VPN server
@@ -132,6 +160,9 @@ pub enum Commands {
     /// Interactive menu mode
     Menu,
 
+    /// Recommend a protocol for your threat model and networks, and install it
+    Recommend,
+
     /// Run diagnostics
     Diagnostics {
         /// Fix issues automatically
@@ -158,6 +189,58 @@ pub enum Commands {
     /// Check Docker network status and available subnets
     NetworkCheck,
 
+    /// Kernel network tuning for the host's workload
+    #[command(subcommand)]
+    Tune(TuneCommands),
+
+    /// Pull newer images and recreate the server, then run the canary test
+    Upgrade {
+        /// Skip the canary connection test after the upgrade
+        #[arg(long)]
+        no_canary: bool,
+
+        /// What to do if the canary cannot connect after the upgrade
+        #[arg(long, value_enum, default_value = "rollback")]
+        on_canary_failure: CanaryFailure,
+
+        /// POST a JSON alert here when the canary fails
+        #[arg(long, value_name = "URL")]
+        canary_webhook: Option<String>,
+    },
+
+    /// Connect as a user through a throwaway client and check the exit path
+    VerifyClient {
+        /// Username or ID
+        user: String,
+
+        /// URL downloaded through the tunnel to measure throughput
+        #[arg(long, default_value = vpn_server::client_check::DEFAULT_CANARY_URL)]
+        canary_url: String,
+
+        /// Service returning the egress IP as plain text
+        #[arg(long, default_value = vpn_server::client_check::DEFAULT_IP_ECHO_URL)]
+        ip_echo_url: String,
+
+        /// Run the client on another host over SSH (user@host) instead of locally
+        #[arg(long, value_name = "DEST")]
+        remote: Option<String>,
+
+        /// Timeout for each request through the tunnel in seconds
+        #[arg(long, default_value = "30")]
+        timeout: u64,
+    },
+
+    /// Describe this installation as Ansible or Terraform to recreate it elsewhere
+    ExportIac {
+        /// Infrastructure-as-code format
+        #[arg(short, long, value_enum, default_value = "ansible")]
+        format: IacFormat,
+
+        /// Write to this file instead of standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
     /// Generate shell completion scripts
     Completions {
         /// Shell to generate completions for
@@ -172,6 +255,179 @@ pub enum Commands {
     /// Proxy server management commands
     #[command(subcommand)]
     Proxy(ProxyCommands),
+
+    /// Telemetry inspection and control commands
+    Telemetry {
+        /// Telemetry admin endpoint (defaults to the local endpoint)
+        #[arg(long, value_name = "URL")]
+        endpoint: Option<String>,
+
+        #[command(subcommand)]
+        command: TelemetryCommands,
+    },
+
+    /// Planned maintenance windows and announcements
+    #[command(subcommand)]
+    Maintenance(MaintenanceCommands),
+
+    /// Cluster administration commands
+    Cluster {
+        /// Node to send the command to (its cluster gRPC address)
+        #[arg(long, value_name = "ADDR")]
+        node: Option<SocketAddr>,
+
+        /// Cluster configuration providing TLS material and the default node address
+        #[arg(long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        #[command(subcommand)]
+        command: ClusterCommands,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ClusterCommands {
+    /// Show cluster members and the current leader
+    Status {
+        /// Output format
+        #[arg(long, default_value = "table")]
+        format: StatusFormat,
+    },
+
+    /// Dump the node's consensus metrics
+    Metrics {
+        /// Output format
+        #[arg(long, default_value = "table")]
+        format: StatusFormat,
+    },
+
+    /// Hand leadership to another node (send to the current leader)
+    TransferLeader {
+        /// Node ID of the new leader
+        target_node: String,
+    },
+
+    /// Remove a node from the cluster (send to the current leader)
+    Evict {
+        /// Node ID to evict
+        node_id: String,
+    },
+
+    /// Snapshot committed state and truncate the node's consensus log
+    CompactLog,
+
+    /// Show how membership, leadership and configuration changed, from the
+    /// state history kept in the node's data directory
+    History {
+        /// Show the cluster state at this time instead (RFC 3339, or e.g. 2h for two hours ago)
+        #[arg(long, value_parser = parse_point_in_time, conflicts_with_all = ["since", "until"])]
+        at: Option<DateTime<Utc>>,
+
+        /// Start of the range (RFC 3339, or e.g. 24h for a day ago)
+        #[arg(long, default_value = "24h", value_parser = parse_point_in_time)]
+        since: DateTime<Utc>,
+
+        /// End of the range (defaults to now)
+        #[arg(long, value_parser = parse_point_in_time)]
+        until: Option<DateTime<Utc>>,
+
+        /// Only show changes involving this node (ID or name)
+        #[arg(long)]
+        member: Option<String>,
+
+        /// Output format
+        #[arg(long, default_value = "table")]
+        format: StatusFormat,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum TuneCommands {
+    /// Apply recommended sysctls (BBR, buffer sizes, TCP fast open,
+    /// backlogs) for a workload, replacing any profile applied before
+    Apply {
+        /// Workload to tune for
+        #[arg(value_enum)]
+        profile: TuningWorkload,
+    },
+
+    /// Restore the values the applied profile replaced
+    Revert,
+
+    /// Show the applied profile and the current values
+    Status,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum MaintenanceCommands {
+    /// Start a maintenance window now, holding back alert notifications
+    Start {
+        /// How long the window lasts (e.g. 30m, 1h, 1h30m, 2d)
+        #[arg(short, long, value_parser = parse_maintenance_duration)]
+        duration: chrono::Duration,
+
+        /// Announcement shown to users
+        #[arg(short, long, default_value = "Scheduled maintenance in progress")]
+        message: String,
+
+        /// Serve the announcement at /status through the proxy's Traefik
+        #[arg(long)]
+        status_page: bool,
+    },
+
+    /// End the active maintenance window early
+    End,
+
+    /// Show the active maintenance window
+    Status,
+
+    /// Report planned downtime and availability with maintenance excluded
+    Report {
+        /// Number of days to report on
+        #[arg(long, default_value = "30")]
+        days: u32,
+
+        /// Unplanned outage as START/END in RFC 3339 (repeatable)
+        #[arg(long = "outage", value_parser = parse_outage)]
+        outages: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+
+        /// Output format
+        #[arg(long, default_value = "table")]
+        format: StatusFormat,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum TelemetryCommands {
+    /// Show exporter, tracing and dashboard status
+    Status {
+        /// Output format
+        #[arg(long, default_value = "table")]
+        format: StatusFormat,
+    },
+
+    /// Collect metrics now and push them to all exporters
+    Flush,
+
+    /// Dump current metric values
+    Metrics {
+        /// Only show metrics whose name contains this text
+        #[arg(short, long)]
+        filter: Option<String>,
+    },
+
+    /// Change the trace sampling ratio at runtime
+    Sampling {
+        /// Fraction of traces to keep (0.0 to 1.0)
+        ratio: f32,
+    },
+
+    /// Turn the telemetry dashboard on or off
+    Dashboard {
+        /// on/off (also accepts true/false, yes/no)
+        #[arg(value_parser = clap::builder::BoolishValueParser::new())]
+        enabled: bool,
+    },
 }
 
 #[derive(Subcommand, Clone)]
@@ -290,7 +546,83 @@ pub enum ProxyConfigCommands {
 #[derive(Subcommand, Clone)]
 pub enum ProxyAccessCommands {
     /// List access rules
-    List,
+    List {
+        /// Only rules of this user
+        #[arg(short, long)]
+        user: Option<String>,
+    },
+
+    /// Allow connections to a destination
+    Allow {
+        /// Domain pattern (example.com, *.example.com, .example.com),
+        /// address or CIDR
+        destination: String,
+
+        /// Ports or port ranges, e.g. 443 or 8000-9000 (repeatable)
+        #[arg(short, long = "port")]
+        ports: Vec<String>,
+
+        /// Apply to this user only
+        #[arg(short, long)]
+        user: Option<String>,
+
+        /// Note stored with the rule
+        #[arg(short, long)]
+        comment: Option<String>,
+    },
+
+    /// Deny connections to a destination
+    Deny {
+        /// Domain pattern (example.com, *.example.com, .example.com),
+        /// address or CIDR
+        destination: String,
+
+        /// Ports or port ranges, e.g. 25 or 6660-6669 (repeatable)
+        #[arg(short, long = "port")]
+        ports: Vec<String>,
+
+        /// Apply to this user only
+        #[arg(short, long)]
+        user: Option<String>,
+
+        /// Note stored with the rule
+        #[arg(short, long)]
+        comment: Option<String>,
+    },
+
+    /// Remove an access rule by its number in `list`
+    Remove {
+        /// Rule number
+        number: usize,
+
+        /// Remove from this user's rules
+        #[arg(short, long)]
+        user: Option<String>,
+    },
+
+    /// Set the action for destinations no rule matches
+    Default {
+        /// allow or deny
+        #[arg(value_parser = ["allow", "deny"])]
+        action: String,
+
+        /// Set this user's default instead of the global one
+        #[arg(short, long)]
+        user: Option<String>,
+    },
+
+    /// Show whether a user may reach a destination
+    Check {
+        /// User name or ID
+        user: String,
+
+        /// Destination host or address
+        destination: String,
+
+        /// Destination port
+        #[arg(short, long, default_value = "443")]
+        port: u16,
+    },
 
     /// Add IP to whitelist
     AddIp {
@@ -338,6 +670,14 @@ pub enum UserCommands {
         /// Show detailed information
         #[arg(short, long)]
         detailed: bool,
+
+        /// Only users with this tag (key or key:value, repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<vpn_users::TagFilter>,
+
+        /// Search names, emails, IDs, notes and tags
+        #[arg(long)]
+        search: Option<String>,
     },
 
     /// Create a new user
@@ -352,6 +692,17 @@ pub enum UserCommands {
         /// VPN protocol
         #[arg(short, long, default_value = "vless")]
         protocol: Protocol,
+        /// Routing profile
+        #[arg(long, value_enum)]
+        routing: Option<RoutingMode>,
+
+        /// Domains sent through the VPN by a split tunnel (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        route_domains: Vec<String>,
+
+        /// Networks sent through the VPN by a split tunnel (comma-separated CIDRs)
+        #[arg(long, value_delimiter = ',')]
+        route_cidrs: Vec<String>,
     },
 
     /// Delete a user
@@ -368,6 +719,15 @@ pub enum UserCommands {
         /// Show QR code
         #[arg(short, long)]
         qr: bool,
+
+        /// QR code size in the terminal
+        #[arg(long, value_enum, default_value = "auto")]
+        qr_size: QrSize,
+
+        /// Cluster config of this node; traffic is then the total over all
+        /// cluster nodes
+        #[arg(long)]
+        cluster_config: Option<PathBuf>,
     },
 
     /// Generate connection link
@@ -382,9 +742,13 @@ pub enum UserCommands {
         /// Save QR code to file
         #[arg(long)]
         qr_file: Option<PathBuf>,
+
+        /// QR code size in the terminal
+        #[arg(long, value_enum, default_value = "auto")]
+        qr_size: QrSize,
     },
 
-    /// Update user status
+    /// Update user status, email or routing profile
     Update {
         /// User name or ID
         user: String,
@@ -396,6 +760,81 @@ pub enum UserCommands {
         /// New email
         #[arg(short, long)]
         email: Option<String>,
+        /// Routing profile
+        #[arg(long, value_enum)]
+        routing: Option<RoutingMode>,
+
+        /// Domains sent through the VPN by a split tunnel (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        route_domains: Vec<String>,
+
+        /// Networks sent through the VPN by a split tunnel (comma-separated CIDRs)
+        #[arg(long, value_delimiter = ',')]
+        route_cidrs: Vec<String>,
+
+        /// Free-form notes (an empty value clears them)
+        #[arg(long)]
+        notes: Option<String>,
+
+        /// Set a tag (key=value, repeatable)
+        #[arg(long = "tag", value_parser = parse_user_tag)]
+        tags: Vec<(String, String)>,
+
+        /// Remove a tag by key (repeatable)
+        #[arg(long = "untag")]
+        untags: Vec<String>,
+
+        /// Server-side traffic handling: direct, block, throttle or
+        /// outbound:<tag>
+        #[arg(long)]
+        traffic: Option<vpn_users::TrafficPolicy>,
+    },
+
+    /// Short share links with a QR code page
+    Share {
+        /// Share link command
+        #[command(subcommand)]
+        command: ShareCommands,
+    },
+
+    /// Print the user's client configuration
+    Config {
+        /// User name or ID
+        user: String,
+
+        /// Write the configuration to a file instead
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Export a printable onboarding sheet with setup steps and QR code
+    ExportSheet {
+        /// User name or ID
+        user: String,
+
+        /// Sheet format
+        #[arg(long, value_enum, default_value = "pdf")]
+        format: SheetFormat,
+
+        /// Output file (defaults to <user>.<format> in the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// HTML template replacing the built-in one (HTML format only)
+        #[arg(long)]
+        template: Option<PathBuf>,
+
+        /// Name shown as the sheet's title
+        #[arg(long, default_value = "VPN")]
+        brand: String,
+
+        /// Support contact printed on the sheet
+        #[arg(long)]
+        support: Option<String>,
+
+        /// Date access ends (YYYY-MM-DD); defaults to the user's `expires` tag
+        #[arg(long, value_parser = parse_sheet_expiry)]
+        expires: Option<chrono::DateTime<chrono::Utc>>,
     },
 
     /// Batch operations
@@ -410,6 +849,64 @@ pub enum UserCommands {
         /// User name or ID
         user: String,
     },
+
+    /// Merge users duplicated across protocol install paths
+    Dedupe {
+        /// Only report the duplicates found
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Other install path to look in (repeatable); defaults to every
+        /// installation next to the install path
+        #[arg(long = "source")]
+        sources: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ShareCommands {
+    /// Create a share link for a user's connection link
+    Create {
+        /// User name or ID
+        user: String,
+
+        /// Hours until the link expires
+        #[arg(long, default_value = "24")]
+        ttl_hours: u32,
+
+        /// Public URL the share server is reachable at
+        #[arg(long, default_value = "http://localhost:8088")]
+        base_url: String,
+    },
+
+    /// List share links
+    List,
+
+    /// Revoke a share link
+    Revoke {
+        /// Link token
+        token: String,
+    },
+
+    /// Show who opened a share link
+    Log {
+        /// Link token
+        token: String,
+    },
+
+    /// Remove expired share links
+    Purge,
+
+    /// Serve share pages
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:8088")]
+        bind: std::net::SocketAddr,
+
+        /// Name shown on share pages
+        #[arg(long, default_value = "VPN")]
+        brand: String,
+    },
 }
 
 #[derive(Subcommand, Clone)]
@@ -434,6 +931,19 @@ pub enum BatchCommands {
         /// New status
         #[arg(short, long)]
         status: UserStatus,
+
+        /// Most users updated at the same time
+        #[arg(long, default_value = "8")]
+        concurrency: usize,
+
+        /// Most users updated per second
+        #[arg(long)]
+        rate: Option<u32>,
+
+        /// Token identifying the batch; re-running with the same token
+        /// skips users it already changed
+        #[arg(long)]
+        token: Option<String>,
     },
 
     /// Export users to file
@@ -529,6 +1039,21 @@ pub enum MonitorCommands {
         #[command(subcommand)]
         command: AlertCommands,
     },
+
+    /// Export a redacted support bundle for bug reports
+    Export {
+        /// Output tarball; defaults to vpn-support-<timestamp>.tar.gz
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Log lines per container
+        #[arg(short, long, default_value = "500")]
+        lines: usize,
+
+        /// Also replace IP addresses
+        #[arg(long)]
+        redact_addresses: bool,
+    },
 }
 
 #[derive(Subcommand, Clone)]
@@ -563,6 +1088,18 @@ pub enum SecurityCommands {
         /// Create backup before rotation
         #[arg(long, default_value = "true")]
         backup: bool,
+
+        /// Skip the canary connection test after the rotation
+        #[arg(long)]
+        no_canary: bool,
+
+        /// What to do if the canary cannot connect after the rotation
+        #[arg(long, value_enum, default_value = "rollback")]
+        on_canary_failure: CanaryFailure,
+
+        /// POST a JSON alert here when the canary fails
+        #[arg(long, value_name = "URL")]
+        canary_webhook: Option<String>,
     },
 
     /// Validate all keys
@@ -573,6 +1110,55 @@ pub enum SecurityCommands {
 
     /// Generate new server keys
     Generate,
+
+    /// Run the access gate listener for gated management ports
+    Gate,
+
+    /// Knock on a server's access gate
+    Knock {
+        /// Server address
+        server: IpAddr,
+
+        /// Access gate configuration copied from the server
+        #[arg(short, long)]
+        config: PathBuf,
+    },
+
+    /// Config file integrity manifest and drift checks
+    #[command(subcommand)]
+    Integrity(IntegrityCommands),
+}
+
+#[derive(Subcommand, Clone)]
+pub enum IntegrityCommands {
+    /// Record the current config files as the trusted baseline
+    Sign,
+
+    /// Compare config files with the signed baseline
+    Check {
+        /// Restore drifted files from this configuration backup
+        #[arg(long, value_name = "DIR")]
+        restore_from: Option<PathBuf>,
+
+        /// POST a JSON alert here when drift is found
+        #[arg(long, value_name = "URL")]
+        webhook: Option<String>,
+    },
+
+    /// Run the check every night in the foreground
+    Watch {
+        /// UTC hour to run the check at
+        #[arg(long, default_value = "3", value_parser = clap::value_parser!(u32).range(0..24))]
+        hour: u32,
+
+        /// Restore drifted files from this configuration backup
+        #[arg(long, value_name = "DIR")]
+        restore_from: Option<PathBuf>,
+
+        /// POST a JSON alert here when drift is found
+        #[arg(long, value_name = "URL")]
+        webhook: Option<String>,
+    },
 }
 
 #[derive(Subcommand, Clone)]
@@ -900,6 +1486,71 @@ fn parse_service_scale(s: &str) -> Result<ServiceScale, String> {
     Ok(ServiceScale { service, replicas })
 }
 
+/// Parse a user tag (key=value)
+fn parse_user_tag(s: &str) -> Result<(String, String), String> {
+    match s.split_once(['=', ':']) {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err("Invalid format. Use: key=value".to_string()),
+    }
+}
+
+/// Parse an expiry date or RFC 3339 timestamp
+fn parse_sheet_expiry(s: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    vpn_server::signage::parse_expiry(s)
+        .ok_or_else(|| format!("Invalid date '{}'. Use YYYY-MM-DD", s))
+}
+
+/// Parse a duration such as 90m, 1h or 1h30m
+fn parse_maintenance_duration(s: &str) -> Result<chrono::Duration, String> {
+    let invalid = || format!("Invalid duration '{}'. Use e.g. 30m, 1h or 1h30m", s);
+
+    let mut total = chrono::Duration::zero();
+    let mut number = String::new();
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let value: i64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+        total = total
+            + match c {
+                's' => chrono::Duration::seconds(value),
+                'm' => chrono::Duration::minutes(value),
+                'h' => chrono::Duration::hours(value),
+                'd' => chrono::Duration::days(value),
+                _ => return Err(invalid()),
+            };
+    }
+
+    if !number.is_empty() || total <= chrono::Duration::zero() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// Parse an outage (START/END in RFC 3339)
+fn parse_point_in_time(s: &str) -> Result<DateTime<Utc>, String> {
+    match DateTime::parse_from_rfc3339(s.trim()) {
+        Ok(time) => Ok(time.with_timezone(&Utc)),
+        Err(_) => Ok(Utc::now() - parse_maintenance_duration(s)?),
+    }
+}
+
+fn parse_outage(s: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
+    let invalid = || "Invalid format. Use: START/END in RFC 3339".to_string();
+    let (start, end) = s.split_once('/').ok_or_else(invalid)?;
+    let start = DateTime::parse_from_rfc3339(start.trim()).map_err(|_| invalid())?;
+    let end = DateTime::parse_from_rfc3339(end.trim()).map_err(|_| invalid())?;
+    if end <= start {
+        return Err("Outage must end after it starts".to_string());
+    }
+    Ok((start.with_timezone(&Utc), end.with_timezone(&Utc)))
+}
+
 #[derive(clap::ValueEnum, Clone, Debug)]
 pub enum Shell {
     Bash,
@@ -916,7 +1567,7 @@ pub enum StatusFormat {
     Yaml,
 }
 
-#[derive(clap::ValueEnum, Clone, Debug)]
+#[derive(clap::ValueEnum, Clone, Debug, PartialEq)]
 pub enum Protocol {
     Vless,
     Shadowsocks,
@@ -926,6 +1577,30 @@ pub enum Protocol {
     ProxyServer,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum AccessGateMode {
+    /// UDP port knock sequence
+    Knock,
+    /// HMAC-signed single packet authorization
+    Spa,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TuningWorkload {
+    /// Many short TCP connections through the proxy or Xray
+    Proxy,
+    /// Few long-lived UDP tunnels carrying bulk traffic
+    Wireguard,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum CanaryFailure {
+    /// Restore the configuration from before the change
+    Rollback,
+    /// Keep the change and only alert
+    Alert,
+}
+
 #[derive(clap::ValueEnum, Clone, Debug)]
 pub enum UserStatus {
     Active,
@@ -933,6 +1608,16 @@ pub enum UserStatus {
     Suspended,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum RoutingMode {
+    /// All traffic through the VPN
+    FullTunnel,
+    /// Only the listed domains and networks through the VPN
+    SplitTunnel,
+    /// Everything but private and link-local networks through the VPN
+    BypassLocal,
+}
+
 #[derive(clap::ValueEnum, Clone, Debug, PartialEq)]
 pub enum OutputFormat {
     Json,
@@ -940,6 +1625,24 @@ pub enum OutputFormat {
     Plain,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum QrSize {
+    /// Full size if it fits the terminal, compact otherwise, a PNG file as a last resort
+    Auto,
+    /// Two columns per module
+    Full,
+    /// Half-block characters, a quarter of the full size
+    Compact,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SheetFormat {
+    /// Single-page PDF
+    Pdf,
+    /// HTML page, printable from a browser
+    Html,
+}
+
 #[derive(clap::ValueEnum, Clone, Debug)]
 pub enum ExportFormat {
     Json,
@@ -949,6 +1652,14 @@ pub enum ExportFormat {
     V2ray,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum IacFormat {
+    /// Ansible playbook
+    Ansible,
+    /// Terraform configuration provisioning over SSH
+    Terraform,
+}
+
 #[derive(clap::ValueEnum, Clone, Debug)]
 pub enum ImportFormat {
     Json,
@@ -969,6 +1680,24 @@ impl From<Protocol> for vpn_types::protocol::VpnProtocol {
     }
 }
 
+impl From<SheetFormat> for vpn_server::SheetFormat {
+    fn from(format: SheetFormat) -> Self {
+        match format {
+            SheetFormat::Pdf => vpn_server::SheetFormat::Pdf,
+            SheetFormat::Html => vpn_server::SheetFormat::Html,
+        }
+    }
+}
+
+impl From<CanaryFailure> for vpn_server::CanaryFailureAction {
+    fn from(action: CanaryFailure) -> Self {
+        match action {
+            CanaryFailure::Rollback => vpn_server::CanaryFailureAction::Rollback,
+            CanaryFailure::Alert => vpn_server::CanaryFailureAction::Alert,
+        }
+    }
+}
+
 impl From<UserStatus> for vpn_users::user::UserStatus {
     fn from(status: UserStatus) -> Self {
         match status {
@@ -979,6 +1708,35 @@ impl From<UserStatus> for vpn_users::user::UserStatus {
     }
 }
 
+impl RoutingMode {
+    /// Routing profile selected by `--routing`, `--route-domains` and `--route-cidrs`
+    pub fn profile_from_args(
+        mode: Option<Self>,
+        domains: Vec<String>,
+        cidrs: Vec<String>,
+    ) -> vpn_users::Result<Option<vpn_users::RoutingProfile>> {
+        let has_routes = !(domains.is_empty() && cidrs.is_empty());
+
+        match mode {
+            None if !has_routes => Ok(None),
+            Some(RoutingMode::SplitTunnel) => {
+                vpn_users::RoutingProfile::split_tunnel(domains, cidrs).map(Some)
+            }
+            Some(RoutingMode::FullTunnel) if !has_routes => {
+                Ok(Some(vpn_users::RoutingProfile::FullTunnel))
+            }
+            Some(RoutingMode::BypassLocal) if !has_routes => {
+                Ok(Some(vpn_users::RoutingProfile::BypassLocal))
+            }
+            _ => Err(vpn_users::UserError::ValidationError {
+                field: "routing".to_string(),
+                message: "--route-domains and --route-cidrs require --routing split-tunnel"
+                    .to_string(),
+            }),
+        }
+    }
+}
+
 impl OutputFormat {
     pub fn as_str(&self) -> &'static str {
         match self {
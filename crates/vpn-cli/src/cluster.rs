@@ -0,0 +1,456 @@
+//! Cluster administration command handlers
+
+use crate::cli::{ClusterCommands, StatusFormat};
+use crate::utils::confirm::confirm_by_name;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tabled::{Table, Tabled};
+use vpn_cluster::communication::cluster::{ConsensusMetricsResponse, StatusResponse};
+use vpn_cluster::history::history_dir;
+use vpn_cluster::{
+    ClusterConfig, ClusterGrpcClient, ClusterTls, NodeId, StateDiff, StateHistory, StateSnapshot,
+    TraceContext, UserTraffic,
+};
+
+/// Node a command is sent to and how to reach it
+struct Target {
+    client: ClusterGrpcClient,
+    address: SocketAddr,
+}
+
+impl Target {
+    /// Use the node's cluster config for its address, TLS material and
+    /// message limits; `node` overrides the address
+    fn connect(node: Option<SocketAddr>, config: &ClusterConfig) -> Result<Self> {
+        let mut client =
+            ClusterGrpcClient::new(NodeId::new()).with_transport(config.transport.clone());
+        if let Some(tls_config) = config.tls.clone() {
+            let tls = ClusterTls::load(tls_config, config.cluster_name.clone())
+                .context("Failed to load cluster TLS material")?;
+            client = client.with_tls(Arc::new(tls));
+        }
+
+        Ok(Self {
+            client,
+            address: node.unwrap_or(config.bind_address),
+        })
+    }
+}
+
+/// The node's cluster config when given, the defaults otherwise
+fn load_config(config: Option<PathBuf>) -> Result<ClusterConfig> {
+    match config {
+        Some(path) => ClusterConfig::from_file(&path)
+            .with_context(|| format!("Failed to load {}", path.display())),
+        None => Ok(ClusterConfig::default()),
+    }
+}
+
+/// Handle cluster commands. Each command starts a trace that the RPCs it
+/// makes carry to the nodes they reach. Evicting a node asks for its ID to
+/// be typed unless `force` is set.
+pub async fn handle_cluster_command(
+    command: ClusterCommands,
+    node: Option<SocketAddr>,
+    config: Option<PathBuf>,
+    force: bool,
+) -> Result<()> {
+    let trace = TraceContext::new_root();
+    tracing::debug!(trace_id = %trace.trace_id(), "Tracing cluster command");
+    trace
+        .scope(run_cluster_command(command, node, config, force))
+        .await
+}
+
+async fn run_cluster_command(
+    command: ClusterCommands,
+    node: Option<SocketAddr>,
+    config: Option<PathBuf>,
+    force: bool,
+) -> Result<()> {
+    let config = load_config(config)?;
+    let target = Target::connect(node, &config)?;
+
+    match command {
+        ClusterCommands::Status { format } => {
+            let status = target
+                .client
+                .get_cluster_status(target.address)
+                .await
+                .context("Failed to get cluster status")?;
+
+            match format {
+                StatusFormat::Table => display_status(&status),
+                StatusFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&status_json(&status))?)
+                }
+                StatusFormat::Yaml => println!("{}", serde_yaml::to_string(&status_json(&status))?),
+            }
+        }
+        ClusterCommands::Metrics { format } => {
+            let metrics = target
+                .client
+                .consensus_metrics(target.address)
+                .await
+                .context("Failed to get consensus metrics")?;
+
+            match format {
+                StatusFormat::Table => display_metrics(&metrics),
+                StatusFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&metrics_json(&metrics))?)
+                }
+                StatusFormat::Yaml => {
+                    println!("{}", serde_yaml::to_string(&metrics_json(&metrics))?)
+                }
+            }
+        }
+        ClusterCommands::TransferLeader { target_node } => {
+            let target_node = parse_node_id(&target_node)?;
+            let response = target
+                .client
+                .transfer_leadership(target.address, &target_node)
+                .await
+                .context("Failed to transfer leadership")?;
+
+            if !response.success {
+                bail!(response.message);
+            }
+            println!("{} {}", "✓".green(), response.message);
+        }
+        ClusterCommands::Evict { node_id } => {
+            confirm_by_name(
+                &format!("Removing node {} from the cluster", node_id),
+                &node_id,
+                force,
+            )?;
+            let node_id = parse_node_id(&node_id)?;
+            let response = target
+                .client
+                .evict_node(target.address, &node_id)
+                .await
+                .context("Failed to evict node")?;
+
+            if !response.success {
+                bail!(response.message);
+            }
+            println!("{} {}", "✓".green(), response.message);
+        }
+        ClusterCommands::CompactLog => {
+            let snapshot_index = target
+                .client
+                .compact_log(target.address)
+                .await
+                .context("Failed to compact the consensus log")?;
+            println!(
+                "{} Consensus log compacted up to index {}",
+                "✓".green(),
+                snapshot_index
+            );
+        }
+        ClusterCommands::History {
+            at,
+            since,
+            until,
+            member,
+            format,
+        } => {
+            // Recorded by the node itself, so read from its data directory
+            // rather than over RPC
+            let history = StateHistory::read(history_dir(&config.data_dir))
+                .await
+                .context("Failed to read the cluster state history")?;
+
+            match at {
+                Some(at) => {
+                    let snapshot = history
+                        .state_at(unix_seconds(at))
+                        .await
+                        .with_context(|| format!("No cluster state recorded before {}", at))?;
+                    match format {
+                        StatusFormat::Table => display_snapshot(&snapshot),
+                        StatusFormat::Json => {
+                            println!("{}", serde_json::to_string_pretty(&snapshot)?)
+                        }
+                        StatusFormat::Yaml => println!("{}", serde_yaml::to_string(&snapshot)?),
+                    }
+                }
+                None => {
+                    let until = until.unwrap_or_else(Utc::now);
+                    let changes: Vec<StateDiff> = history
+                        .changes(unix_seconds(since), unix_seconds(until))
+                        .await
+                        .into_iter()
+                        .filter(|diff| member.as_deref().is_none_or(|m| diff.involves(m)))
+                        .collect();
+                    match format {
+                        StatusFormat::Table => display_changes(&changes),
+                        StatusFormat::Json => {
+                            println!("{}", serde_json::to_string_pretty(&changes)?)
+                        }
+                        StatusFormat::Yaml => println!("{}", serde_yaml::to_string(&changes)?),
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Cluster-wide traffic of a user, as aggregated by the cluster the node
+/// configured in `config` belongs to
+pub async fn cluster_user_traffic(config: PathBuf, user_id: &str) -> Result<Option<UserTraffic>> {
+    let target = Target::connect(None, &load_config(Some(config))?)?;
+    let mut traffic = target
+        .client
+        .get_cluster_traffic(target.address, vec![user_id.to_string()])
+        .await
+        .context("Failed to get cluster traffic")?;
+
+    Ok(traffic.remove(user_id))
+}
+
+fn parse_node_id(node_id: &str) -> Result<NodeId> {
+    NodeId::from_string(node_id).with_context(|| format!("Invalid node ID '{}'", node_id))
+}
+
+fn unix_seconds(time: DateTime<Utc>) -> u64 {
+    time.timestamp().max(0) as u64
+}
+
+fn format_time(timestamp: u64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// Display cluster status in table format
+fn display_status(status: &StatusResponse) {
+    let state = status.cluster_state.clone().unwrap_or_default();
+    println!(
+        "\n{}",
+        format!("Cluster Status - {}", state.cluster_name).bold()
+    );
+    println!("  Term:           {}", state.term);
+    println!("  Config version: {}", state.config_version);
+    println!(
+        "  Leader:         {}",
+        if state.leader_id.is_empty() {
+            "none".red().to_string()
+        } else {
+            state.leader_id.clone()
+        }
+    );
+
+    if status.nodes.is_empty() {
+        println!("\n  No nodes known");
+        return;
+    }
+
+    #[derive(Tabled)]
+    struct NodeRow {
+        #[tabled(rename = "Node ID")]
+        id: String,
+        #[tabled(rename = "Name")]
+        name: String,
+        #[tabled(rename = "Address")]
+        address: String,
+        #[tabled(rename = "Role")]
+        role: String,
+        #[tabled(rename = "Status")]
+        status: String,
+    }
+
+    let rows: Vec<NodeRow> = status
+        .nodes
+        .iter()
+        .map(|node| NodeRow {
+            id: node.node_id.clone(),
+            name: node.name.clone(),
+            address: node.address.clone(),
+            role: node.role.clone(),
+            status: if node.cordoned {
+                format!("{} (cordoned)", node.status)
+            } else {
+                node.status.clone()
+            },
+        })
+        .collect();
+
+    println!("\n{}", Table::new(rows));
+}
+
+/// Display consensus metrics in table format
+fn display_metrics(metrics: &ConsensusMetricsResponse) {
+    println!(
+        "\n{}",
+        format!("Consensus Metrics - {}", metrics.node_id).bold()
+    );
+    println!("  Leader:            {}", metrics.is_leader);
+    println!(
+        "  Known leader:      {}",
+        if metrics.leader_id.is_empty() {
+            "none"
+        } else {
+            &metrics.leader_id
+        }
+    );
+    println!("  Term:              {}", metrics.current_term);
+    println!("  Last log index:    {}", metrics.last_log_index);
+    println!("  Commit index:      {}", metrics.commit_index);
+    println!("  Cluster size:      {}", metrics.cluster_size);
+    println!("  Since election:    {}ms", metrics.election_elapsed_ms);
+    println!("  Since heartbeat:   {}ms", metrics.heartbeat_elapsed_ms);
+}
+
+/// Display a recorded cluster state
+fn display_snapshot(snapshot: &StateSnapshot) {
+    let state = &snapshot.state;
+    println!(
+        "\n{}",
+        format!("Cluster State at {}", format_time(snapshot.taken_at)).bold()
+    );
+    println!("  Term:           {}", state.term);
+    println!(
+        "  Leader:         {}",
+        state
+            .leader_id
+            .as_ref()
+            .map_or_else(|| "none".red().to_string(), |id| id.to_string())
+    );
+
+    #[derive(Tabled)]
+    struct NodeRow {
+        #[tabled(rename = "Node ID")]
+        id: String,
+        #[tabled(rename = "Name")]
+        name: String,
+        #[tabled(rename = "Address")]
+        address: String,
+        #[tabled(rename = "Role")]
+        role: String,
+        #[tabled(rename = "Status")]
+        status: String,
+    }
+
+    let rows: Vec<NodeRow> = state
+        .nodes
+        .values()
+        .map(|node| NodeRow {
+            id: node.id.to_string(),
+            name: node.name.clone(),
+            address: node.address.to_string(),
+            role: node.role.to_string(),
+            status: node.status.to_string(),
+        })
+        .collect();
+
+    if rows.is_empty() {
+        println!("\n  No nodes known");
+    } else {
+        println!("\n{}", Table::new(rows));
+    }
+}
+
+/// Display recorded state changes, oldest first
+fn display_changes(changes: &[StateDiff]) {
+    if changes.is_empty() {
+        println!("No cluster state changes recorded in this range");
+        return;
+    }
+
+    let or_none = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+    for diff in changes {
+        println!("\n{}", format_time(diff.to).bold());
+        for node in &diff.joined {
+            println!(
+                "  {} {} ({}) joined at {}",
+                "+".green(),
+                node.name,
+                node.id,
+                node.address
+            );
+        }
+        for departed in &diff.left {
+            let reason = departed
+                .reason
+                .as_ref()
+                .map(|reason| format!(" ({:?})", reason).to_lowercase())
+                .unwrap_or_default();
+            println!(
+                "  {} {} ({}) left{}",
+                "-".red(),
+                departed.node.name,
+                departed.node.id,
+                reason
+            );
+        }
+        for change in &diff.node_changes {
+            println!(
+                "  {} {} {}: {} → {}",
+                "~".yellow(),
+                change.node.name,
+                change.field,
+                change.change.before,
+                change.change.after
+            );
+        }
+        if let Some(leader) = &diff.leader {
+            println!(
+                "  {} leader: {} → {}",
+                "~".yellow(),
+                or_none(leader.before.as_ref().map(ToString::to_string)),
+                or_none(leader.after.as_ref().map(ToString::to_string))
+            );
+        }
+        if let Some(term) = &diff.term {
+            println!("  {} term: {} → {}", "~".yellow(), term.before, term.after);
+        }
+        for config in &diff.config_changes {
+            println!(
+                "  {} config {}: {} → {}",
+                "~".yellow(),
+                config.key,
+                or_none(config.change.before.as_ref().map(ToString::to_string)),
+                or_none(config.change.after.as_ref().map(ToString::to_string))
+            );
+        }
+    }
+}
+
+fn status_json(status: &StatusResponse) -> serde_json::Value {
+    let state = status.cluster_state.clone().unwrap_or_default();
+    serde_json::json!({
+        "cluster_name": state.cluster_name,
+        "leader_id": state.leader_id,
+        "term": state.term,
+        "config_version": state.config_version,
+        "nodes": status.nodes.iter().map(|node| serde_json::json!({
+            "node_id": node.node_id,
+            "name": node.name,
+            "address": node.address,
+            "role": node.role,
+            "status": node.status,
+            "cordoned": node.cordoned,
+        })).collect::<Vec<_>>(),
+        "timestamp": status.timestamp,
+    })
+}
+
+fn metrics_json(metrics: &ConsensusMetricsResponse) -> serde_json::Value {
+    serde_json::json!({
+        "node_id": metrics.node_id,
+        "is_leader": metrics.is_leader,
+        "leader_id": metrics.leader_id,
+        "current_term": metrics.current_term,
+        "last_log_index": metrics.last_log_index,
+        "commit_index": metrics.commit_index,
+        "cluster_size": metrics.cluster_size,
+        "election_elapsed_ms": metrics.election_elapsed_ms,
+        "heartbeat_elapsed_ms": metrics.heartbeat_elapsed_ms,
+    })
+}
@@ -53,6 +53,9 @@ pub enum CliError {
     #[error("Crypto error: {0}")]
     CryptoError(#[from] vpn_crypto::CryptoError),
 
+    #[error("Proxy error: {0}")]
+    ProxyError(#[from] vpn_proxy::ProxyError),
+
     #[error("Docker Compose error: {0}")]
     ComposeError(String),
 
@@ -0,0 +1,451 @@
+//! Infrastructure-as-code export of the current installation
+//!
+//! `vpn export-iac` describes the running server as an Ansible playbook or
+//! a Terraform configuration: the protocol and port it was installed with,
+//! its users as variables and the inbound firewall rules the VPN recorded
+//! creating. Applying the output to a fresh host recreates the server with
+//! the same shape. Keys and user credentials are not exported; the new
+//! server generates its own and users get new client configs.
+
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use vpn_network::firewall::{Direction, Protocol as FirewallProtocol};
+use vpn_network::ledger::LedgerEntry;
+use vpn_types::protocol::VpnProtocol;
+use vpn_users::config::{ServerConfig, XrayConfig};
+use vpn_users::User;
+
+use crate::cli::IacFormat;
+use crate::{CliError, Result};
+
+/// A user to recreate
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IacUser {
+    pub name: String,
+    pub email: Option<String>,
+    /// Protocol as `vpn users create --protocol` takes it
+    pub protocol: String,
+}
+
+/// An inbound firewall rule to recreate
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IacFirewallRule {
+    pub port: u16,
+    /// `tcp`, `udp` or `any`
+    pub proto: String,
+    /// Source address the rule is limited to, `any` otherwise
+    pub from: String,
+    pub comment: Option<String>,
+}
+
+/// What `vpn export-iac` describes about an installation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Installation {
+    /// Protocol as `vpn install --protocol` takes it
+    pub protocol: String,
+    pub port: u16,
+    pub sni: Option<String>,
+    pub install_path: PathBuf,
+    pub users: Vec<IacUser>,
+    pub firewall_rules: Vec<IacFirewallRule>,
+}
+
+impl Installation {
+    /// Snapshot of an installation from its Xray config, when it has one,
+    /// its users and its firewall ledger
+    pub fn from_parts(
+        install_path: &Path,
+        xray: Option<&XrayConfig>,
+        server: &ServerConfig,
+        users: &[User],
+        firewall: &[LedgerEntry],
+    ) -> Self {
+        let inbound = xray.and_then(|config| {
+            config
+                .inbounds
+                .iter()
+                .find(|inbound| matches!(inbound.protocol.as_str(), "vless" | "shadowsocks"))
+        });
+
+        let protocol = match inbound {
+            Some(inbound) => inbound.protocol.clone(),
+            None => most_common_protocol(users).unwrap_or_else(|| "vless".to_string()),
+        };
+        let sni = server
+            .sni
+            .clone()
+            .or_else(|| server.reality_server_names.first().cloned())
+            .filter(|_| protocol == "vless");
+
+        let mut users: Vec<IacUser> = users
+            .iter()
+            .map(|user| IacUser {
+                name: user.name.clone(),
+                email: user.email.clone(),
+                protocol: cli_protocol(&user.protocol).to_string(),
+            })
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut firewall_rules: Vec<IacFirewallRule> = Vec::new();
+        let rules = firewall
+            .iter()
+            .filter(|entry| entry.rule.direction != Direction::Out)
+            .map(|entry| IacFirewallRule {
+                port: entry.rule.port,
+                proto: match entry.rule.protocol {
+                    FirewallProtocol::Tcp => "tcp",
+                    FirewallProtocol::Udp => "udp",
+                    FirewallProtocol::Both => "any",
+                }
+                .to_string(),
+                from: entry
+                    .rule
+                    .source
+                    .map(|source| source.to_string())
+                    .unwrap_or_else(|| "any".to_string()),
+                comment: entry.rule.comment.clone(),
+            });
+        // The same rule may be recorded once per backend
+        for rule in rules {
+            if !firewall_rules.contains(&rule) {
+                firewall_rules.push(rule);
+            }
+        }
+
+        Self {
+            protocol,
+            port: inbound.map(|inbound| inbound.port).unwrap_or(server.port),
+            sni,
+            install_path: install_path.to_path_buf(),
+            users,
+            firewall_rules,
+        }
+    }
+
+    pub fn render(&self, format: IacFormat) -> Result<String> {
+        match format {
+            IacFormat::Ansible => self.render_ansible(),
+            IacFormat::Terraform => Ok(self.render_terraform()),
+        }
+    }
+
+    /// Playbook for the `vpn_servers` inventory group
+    pub fn render_ansible(&self) -> Result<String> {
+        let install = if self.sni.is_some() {
+            "vpn install --protocol {{ vpn_protocol }} --port {{ vpn_port }} --sni {{ vpn_sni }}"
+        } else {
+            "vpn install --protocol {{ vpn_protocol }} --port {{ vpn_port }}"
+        };
+
+        let play = AnsiblePlay {
+            name: "VPN server",
+            hosts: "vpn_servers",
+            privileged: true,
+            vars: AnsibleVars {
+                vpn_protocol: &self.protocol,
+                vpn_port: self.port,
+                vpn_sni: self.sni.as_deref(),
+                vpn_users: &self.users,
+                vpn_firewall_rules: &self.firewall_rules,
+            },
+            tasks: vec![
+                AnsibleTask {
+                    name: "Allow VPN traffic through the firewall",
+                    action: json!({
+                        "community.general.ufw": {
+                            "rule": "allow",
+                            "port": "{{ item.port }}",
+                            "proto": "{{ item.proto }}",
+                            "from_ip": "{{ item.from }}",
+                            "comment": "{{ item.comment | default(omit, true) }}",
+                        }
+                    }),
+                    loop_items: Some("{{ vpn_firewall_rules }}"),
+                    ..Default::default()
+                },
+                AnsibleTask {
+                    name: "Install the VPN server",
+                    action: json!({
+                        "ansible.builtin.command": {
+                            "cmd": install,
+                            "creates": self.install_path.join("config").join("config.json"),
+                        }
+                    }),
+                    ..Default::default()
+                },
+                AnsibleTask {
+                    name: "Create VPN users",
+                    action: json!({
+                        "ansible.builtin.command": {
+                            "argv": "{{ ['vpn', 'users', 'create', item.name, '--protocol', item.protocol] + (['--email', item.email] if item.email else []) }}",
+                        }
+                    }),
+                    loop_items: Some("{{ vpn_users }}"),
+                    register: Some("vpn_user_created"),
+                    changed_when: Some("vpn_user_created.rc == 0"),
+                    // Users left from an earlier run are not an error
+                    failed_when: Some(
+                        "vpn_user_created.rc != 0 and 'already exists' not in vpn_user_created.stderr",
+                    ),
+                },
+            ],
+        };
+
+        serde_yaml::to_string(&[play])
+            .map_err(|e| CliError::ConfigError(format!("Failed to render playbook: {}", e)))
+    }
+
+    /// Configuration setting up a host reached over SSH with a
+    /// `terraform_data` resource
+    pub fn render_terraform(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str(concat!(
+            "variable \"host\" {\n",
+            "  description = \"Address of the host to set up\"\n",
+            "  type        = string\n",
+            "}\n\n",
+            "variable \"ssh_user\" {\n",
+            "  type    = string\n",
+            "  default = \"root\"\n",
+            "}\n\n",
+            "variable \"ssh_private_key_path\" {\n",
+            "  type    = string\n",
+            "  default = \"~/.ssh/id_ed25519\"\n",
+            "}\n\n",
+        ));
+
+        out.push_str(&format!(
+            "variable \"vpn_protocol\" {{\n  type    = string\n  default = {}\n}}\n\n",
+            hcl_string(&self.protocol)
+        ));
+        out.push_str(&format!(
+            "variable \"vpn_port\" {{\n  type    = number\n  default = {}\n}}\n\n",
+            self.port
+        ));
+        out.push_str(&format!(
+            "variable \"vpn_sni\" {{\n  type    = string\n  default = {}\n}}\n\n",
+            self.sni
+                .as_deref()
+                .map(hcl_string)
+                .unwrap_or_else(|| "null".to_string())
+        ));
+
+        out.push_str(concat!(
+            "variable \"vpn_users\" {\n",
+            "  type = list(object({\n",
+            "    name     = string\n",
+            "    email    = optional(string)\n",
+            "    protocol = string\n",
+            "  }))\n",
+            "  default = [\n",
+        ));
+        for user in &self.users {
+            out.push_str(&format!(
+                "    {{ name = {}, email = {}, protocol = {} }},\n",
+                hcl_string(&user.name),
+                user.email
+                    .as_deref()
+                    .map(hcl_string)
+                    .unwrap_or_else(|| "null".to_string()),
+                hcl_string(&user.protocol)
+            ));
+        }
+        out.push_str("  ]\n}\n\n");
+
+        out.push_str(concat!(
+            "variable \"vpn_firewall_rules\" {\n",
+            "  type = list(object({\n",
+            "    port  = number\n",
+            "    proto = string\n",
+            "    from  = string\n",
+            "  }))\n",
+            "  default = [\n",
+        ));
+        for rule in &self.firewall_rules {
+            out.push_str(&format!(
+                "    {{ port = {}, proto = {}, from = {} }},\n",
+                rule.port,
+                hcl_string(&rule.proto),
+                hcl_string(&rule.from)
+            ));
+        }
+        out.push_str("  ]\n}\n\n");
+
+        out.push_str(&format!(
+            concat!(
+                "resource \"terraform_data\" \"vpn_server\" {{\n",
+                "  triggers_replace = [var.vpn_protocol, var.vpn_port]\n",
+                "\n",
+                "  connection {{\n",
+                "    type        = \"ssh\"\n",
+                "    host        = var.host\n",
+                "    user        = var.ssh_user\n",
+                "    private_key = file(pathexpand(var.ssh_private_key_path))\n",
+                "  }}\n",
+                "\n",
+                "  provisioner \"remote-exec\" {{\n",
+                "    inline = concat(\n",
+                "      [for rule in var.vpn_firewall_rules :\n",
+                "        \"ufw allow ${{rule.proto == \"any\" ? \"\" : \"proto ${{rule.proto}} \"}}from ${{rule.from}} to any port ${{rule.port}}\"],\n",
+                "      [\"test -f {config} || vpn install --protocol ${{var.vpn_protocol}} --port ${{var.vpn_port}}${{var.vpn_sni == null ? \"\" : \" --sni ${{var.vpn_sni}}\"}}\"],\n",
+                "      [for user in var.vpn_users :\n",
+                "        \"vpn users create ${{user.name}} --protocol ${{user.protocol}}${{user.email == null ? \"\" : \" --email ${{user.email}}\"}} || true\"],\n",
+                "    )\n",
+                "  }}\n",
+                "}}\n",
+            ),
+            config = hcl_escape(
+                &self
+                    .install_path
+                    .join("config")
+                    .join("config.json")
+                    .display()
+                    .to_string()
+            ),
+        ));
+
+        out
+    }
+}
+
+/// A play, with its keys in the order playbooks are usually written
+#[derive(Serialize)]
+struct AnsiblePlay<'a> {
+    name: &'a str,
+    hosts: &'a str,
+    #[serde(rename = "become")]
+    privileged: bool,
+    vars: AnsibleVars<'a>,
+    tasks: Vec<AnsibleTask<'a>>,
+}
+
+#[derive(Serialize)]
+struct AnsibleVars<'a> {
+    vpn_protocol: &'a str,
+    vpn_port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vpn_sni: Option<&'a str>,
+    vpn_users: &'a [IacUser],
+    vpn_firewall_rules: &'a [IacFirewallRule],
+}
+
+#[derive(Serialize, Default)]
+struct AnsibleTask<'a> {
+    name: &'a str,
+    /// Module name and its arguments
+    #[serde(flatten)]
+    action: serde_json::Value,
+    #[serde(rename = "loop", skip_serializing_if = "Option::is_none")]
+    loop_items: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    register: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed_when: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed_when: Option<&'a str>,
+}
+
+/// Protocol name as the CLI takes it, which calls Outline `shadowsocks`
+fn cli_protocol(protocol: &VpnProtocol) -> &'static str {
+    match protocol {
+        VpnProtocol::Outline => "shadowsocks",
+        other => other.as_str(),
+    }
+}
+
+fn most_common_protocol(users: &[User]) -> Option<String> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for user in users {
+        *counts.entry(cli_protocol(&user.protocol)).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(protocol, _)| protocol.to_string())
+}
+
+/// Escape `value` for a quoted HCL string, including template sequences
+fn hcl_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace("${", "$${")
+        .replace("%{", "%%{")
+}
+
+fn hcl_string(value: &str) -> String {
+    format!("\"{}\"", hcl_escape(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installation() -> Installation {
+        Installation {
+            protocol: "vless".to_string(),
+            port: 8443,
+            sni: Some("www.example.com".to_string()),
+            install_path: PathBuf::from("/opt/vpn"),
+            users: vec![
+                IacUser {
+                    name: "alice".to_string(),
+                    email: Some("alice@example.com".to_string()),
+                    protocol: "vless".to_string(),
+                },
+                IacUser {
+                    name: "bob".to_string(),
+                    email: None,
+                    protocol: "shadowsocks".to_string(),
+                },
+            ],
+            firewall_rules: vec![IacFirewallRule {
+                port: 8443,
+                proto: "tcp".to_string(),
+                from: "any".to_string(),
+                comment: Some("VPN".to_string()),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_ansible_playbook_parses_with_variables() {
+        let playbook = installation().render_ansible().unwrap();
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&playbook).unwrap();
+        let vars = &parsed[0]["vars"];
+
+        assert_eq!(vars["vpn_protocol"], "vless");
+        assert_eq!(vars["vpn_port"], 8443);
+        assert_eq!(vars["vpn_users"][1]["name"], "bob");
+        assert_eq!(vars["vpn_firewall_rules"][0]["proto"], "tcp");
+        assert_eq!(
+            parsed[0]["tasks"][1]["ansible.builtin.command"]["creates"],
+            "/opt/vpn/config/config.json"
+        );
+    }
+
+    #[test]
+    fn test_terraform_variables() {
+        let config = installation().render_terraform();
+
+        assert!(config.contains("default = \"vless\""));
+        assert!(config.contains("default = 8443"));
+        assert!(config
+            .contains("{ name = \"alice\", email = \"alice@example.com\", protocol = \"vless\" }"));
+        assert!(config.contains("{ name = \"bob\", email = null, protocol = \"shadowsocks\" }"));
+        assert!(config.contains("{ port = 8443, proto = \"tcp\", from = \"any\" }"));
+        assert!(config.contains("test -f /opt/vpn/config/config.json"));
+    }
+
+    #[test]
+    fn test_hcl_escape() {
+        assert_eq!(hcl_string("a\"b"), "\"a\\\"b\"");
+        assert_eq!(hcl_string("${x}"), "\"$${x}\"");
+    }
+}
@@ -1,12 +1,17 @@
 pub mod cli;
+pub mod cluster;
 pub mod commands;
 pub mod compose;
 pub mod config;
 pub mod error;
+pub mod iac;
+pub mod maintenance;
 pub mod menu;
 pub mod migration;
 pub mod privileges;
+pub mod recommend;
 pub mod runtime;
+pub mod telemetry;
 pub mod utils;
 
 pub use cli::{Cli, Commands, Shell};
@@ -5,6 +5,8 @@ use std::io;
 use std::path::PathBuf;
 use std::process;
 use tokio;
+use tracing::Instrument;
+use vpn_types::CorrelationId;
 
 use vpn_cli::{
     Cli, CliError, CommandHandler, Commands, ConfigManager, InteractiveMenu, PrivilegeManager,
@@ -24,17 +26,27 @@ async fn main() {
         return;
     }
 
+    // One ID for everything this run does, shown with errors as their
+    // reference ID
+    let correlation_id = match cli.correlation_id.as_deref() {
+        Some(id) => match CorrelationId::parse(id) {
+            Some(id) => id,
+            None => {
+                eprintln!("{} Invalid correlation ID '{}'", "Error:".red(), id);
+                process::exit(1);
+            }
+        },
+        None => CorrelationId::from_env_or_generate(),
+    }
+    .init_process();
+
     // Initialize privilege manager
     let mut privilege_manager = match PrivilegeManager::new() {
         Ok(pm) => pm,
-        Err(e) => {
-            eprintln!(
-                "{} Failed to initialize privilege manager: {}",
-                "Error:".red(),
-                e
-            );
-            process::exit(1);
-        }
+        Err(e) => fail(
+            format!("Failed to initialize privilege manager: {}", e),
+            &correlation_id,
+        ),
     };
 
     // Check and request privileges if needed for specific commands
@@ -44,8 +56,7 @@ async fn main() {
             let args: Vec<String> = std::env::args().collect();
             if PrivilegeManager::command_needs_root(&args) {
                 if let Err(e) = privilege_manager.ensure_root_privileges() {
-                    eprintln!("{} {}", "Error:".red(), e);
-                    process::exit(1);
+                    fail(e, &correlation_id);
                 }
             }
         }
@@ -55,19 +66,16 @@ async fn main() {
     // Initialize configuration
     let config_manager = match ConfigManager::new(cli.config.clone()) {
         Ok(manager) => manager,
-        Err(e) => {
-            eprintln!("{} {}", "Error:".red(), e);
-            process::exit(1);
-        }
+        Err(e) => fail(e, &correlation_id),
     };
 
     // Set up logging based on verbosity
     setup_logging(cli.verbose, cli.quiet);
+    let span = tracing::info_span!("command", correlation_id = %correlation_id);
 
     // Check installation directory permissions
     if let Err(e) = check_installation_permissions(&cli).await {
-        eprintln!("{} {}", "Error:".red(), e);
-        process::exit(1);
+        fail(e, &correlation_id);
     }
 
     // Show privilege status if verbose
@@ -77,29 +85,41 @@ async fn main() {
     }
 
     // Initialize command handler
-    let command_handler = match CommandHandler::new(config_manager, cli.install_path.clone()).await
+    let command_handler = match CommandHandler::new(config_manager, cli.install_path.clone())
+        .instrument(span.clone())
+        .await
     {
         Ok(handler) => handler,
-        Err(e) => {
-            eprintln!("{} {}", "Error:".red(), e);
-            process::exit(1);
-        }
+        Err(e) => fail(e, &correlation_id),
     };
 
     // Execute command or start interactive menu
     let result = match cli.command {
         Some(ref command) => {
-            execute_command(command_handler, command.clone(), &cli, privilege_manager).await
+            execute_command(command_handler, command.clone(), &cli, privilege_manager)
+                .instrument(span)
+                .await
+        }
+        None => {
+            start_interactive_menu(command_handler)
+                .instrument(span)
+                .await
         }
-        None => start_interactive_menu(command_handler).await,
     };
 
     if let Err(e) = result {
-        eprintln!("{} {}", "Error:".red(), e);
-        process::exit(1);
+        fail(e, &correlation_id);
     }
 }
 
+/// Print `error` with the reference ID to find this run in the logs by, and
+/// exit
+fn fail(error: impl std::fmt::Display, correlation_id: &CorrelationId) -> ! {
+    eprintln!("{} {}", "Error:".red(), error);
+    eprintln!("{} {}", "Reference ID:".dimmed(), correlation_id);
+    process::exit(1);
+}
+
 async fn execute_command(
     mut handler: CommandHandler,
     command: Commands,
@@ -118,7 +138,18 @@ async fn execute_command(
             auto_start,
             subnet,
             interactive_subnet,
+            access_gate,
+            gate_ports,
+            knock_ports,
+            spa_port,
+            take_ownership,
         } => {
+            let access_gate = vpn_cli::commands::access_gate_config(
+                access_gate,
+                gate_ports,
+                knock_ports,
+                spa_port,
+            )?;
             handler
                 .install_server(
                     protocol,
@@ -128,6 +159,8 @@ async fn execute_command(
                     auto_start,
                     subnet,
                     interactive_subnet,
+                    access_gate,
+                    take_ownership,
                 )
                 .await
         }
@@ -152,7 +185,26 @@ async fn execute_command(
         .await
         .map_err(CliError::from),
         Commands::Proxy(proxy_cmd) => handler.handle_proxy_command(proxy_cmd).await,
+        Commands::Telemetry { endpoint, command } => {
+            vpn_cli::telemetry::handle_telemetry_command(command, endpoint)
+                .await
+                .map_err(CliError::from)
+        }
+        Commands::Maintenance(maintenance_cmd) => vpn_cli::maintenance::handle_maintenance_command(
+            maintenance_cmd,
+            cli.install_path.clone(),
+        )
+        .await
+        .map_err(CliError::from),
+        Commands::Cluster {
+            node,
+            config,
+            command,
+        } => vpn_cli::cluster::handle_cluster_command(command, node, config, cli.force)
+            .await
+            .map_err(CliError::from),
         Commands::Menu => start_interactive_menu(handler).await,
+        Commands::Recommend => vpn_cli::recommend::run_wizard(&mut handler).await,
         Commands::Diagnostics { fix } => handler.run_diagnostics(fix).await,
         Commands::Doctor { fix } => handler.run_diagnostics(fix).await,
         Commands::Info => handler.show_system_info().await,
@@ -162,6 +214,28 @@ async fn execute_command(
             Ok(())
         }
         Commands::NetworkCheck => handler.check_network_status().await,
+        Commands::Tune(tune_cmd) => handler.handle_tune_command(tune_cmd).await,
+        Commands::Upgrade {
+            no_canary,
+            on_canary_failure,
+            canary_webhook,
+        } => {
+            handler
+                .upgrade_server(no_canary, on_canary_failure, canary_webhook)
+                .await
+        }
+        Commands::VerifyClient {
+            user,
+            canary_url,
+            ip_echo_url,
+            remote,
+            timeout,
+        } => {
+            handler
+                .verify_client(user, canary_url, ip_echo_url, remote, timeout)
+                .await
+        }
+        Commands::ExportIac { format, output } => handler.export_iac(format, output).await,
         Commands::Completions { shell, output } => generate_completions(shell, output),
     }
 }
@@ -0,0 +1,170 @@
+//! Maintenance command handlers
+
+use crate::cli::{MaintenanceCommands, StatusFormat};
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use colored::Colorize;
+use std::path::PathBuf;
+use tabled::{Table, Tabled};
+use vpn_server::{AvailabilityReport, MaintenanceSchedule, StatusPage};
+
+/// Handle maintenance commands
+pub async fn handle_maintenance_command(
+    command: MaintenanceCommands,
+    install_path: PathBuf,
+) -> Result<()> {
+    let mut schedule =
+        MaintenanceSchedule::load(&install_path).context("Failed to read maintenance schedule")?;
+
+    match command {
+        MaintenanceCommands::Start {
+            duration,
+            message,
+            status_page,
+        } => {
+            let window = schedule
+                .start(Utc::now(), duration, message, status_page)?
+                .clone();
+
+            if status_page {
+                StatusPage::new(install_path.clone())
+                    .publish(&window)
+                    .await
+                    .context("Failed to publish status page")?;
+            }
+            schedule
+                .save(&install_path)
+                .context("Failed to save maintenance schedule")?;
+
+            println!(
+                "{} Maintenance window {} started, ends at {}",
+                "✓".green(),
+                window.id,
+                window.ends_at.format("%Y-%m-%d %H:%M UTC")
+            );
+            println!("  Alert notifications are held back until then");
+            if status_page {
+                println!("  Announcement served at /status");
+            }
+        }
+        MaintenanceCommands::End => {
+            let Some(window) = schedule.end_active(Utc::now()) else {
+                println!("No maintenance window is active");
+                return Ok(());
+            };
+            schedule
+                .save(&install_path)
+                .context("Failed to save maintenance schedule")?;
+
+            if window.status_page {
+                StatusPage::new(install_path)
+                    .withdraw()
+                    .await
+                    .context("Failed to withdraw status page")?;
+            }
+            println!(
+                "{} Maintenance window {} ended after {}",
+                "✓".green(),
+                window.id,
+                format_duration(window.duration())
+            );
+        }
+        MaintenanceCommands::Status => match schedule.active(Utc::now()) {
+            Some(window) => {
+                println!("{}", "Maintenance in progress".yellow().bold());
+                println!("  Window:  {}", window.id);
+                println!("  Message: {}", window.message);
+                println!(
+                    "  Ends:    {} ({} left)",
+                    window.end().format("%Y-%m-%d %H:%M UTC"),
+                    format_duration(window.end() - Utc::now())
+                );
+                println!(
+                    "  Status page: {}",
+                    if window.status_page { "on" } else { "off" }
+                );
+            }
+            None => println!("No maintenance window is active"),
+        },
+        MaintenanceCommands::Report {
+            days,
+            outages,
+            format,
+        } => {
+            let to = Utc::now();
+            let from = to - Duration::days(days.into());
+            let report = schedule.availability(from, to, &outages);
+
+            match format {
+                StatusFormat::Table => display_report(&report),
+                StatusFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+                StatusFormat::Yaml => println!("{}", serde_yaml::to_string(&report)?),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn display_report(report: &AvailabilityReport) {
+    println!(
+        "\n{}",
+        format!(
+            "Availability {} - {}",
+            report.from.format("%Y-%m-%d"),
+            report.to.format("%Y-%m-%d")
+        )
+        .bold()
+    );
+    println!(
+        "  Planned downtime:   {} (excluded)",
+        format_duration(report.planned_downtime)
+    );
+    println!(
+        "  Unplanned downtime: {}",
+        format_duration(report.unplanned_downtime)
+    );
+    println!(
+        "  Availability (SLA): {:.3}%",
+        report.availability_percent()
+    );
+
+    if report.windows.is_empty() {
+        println!("\n  No maintenance windows in this period");
+        return;
+    }
+
+    #[derive(Tabled)]
+    struct WindowRow {
+        #[tabled(rename = "Window")]
+        id: String,
+        #[tabled(rename = "Start")]
+        start: String,
+        #[tabled(rename = "Duration")]
+        duration: String,
+        #[tabled(rename = "Message")]
+        message: String,
+    }
+
+    let rows: Vec<WindowRow> = report
+        .windows
+        .iter()
+        .map(|window| WindowRow {
+            id: window.id.clone(),
+            start: window.starts_at.format("%Y-%m-%d %H:%M").to_string(),
+            duration: format_duration(window.duration()),
+            message: window.message.clone(),
+        })
+        .collect();
+
+    println!("\n{}", Table::new(rows));
+}
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes().max(0);
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, 0) => format!("{}h", hours),
+        (hours, minutes) => format!("{}h{}m", hours, minutes),
+    }
+}
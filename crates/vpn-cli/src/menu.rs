@@ -308,7 +308,9 @@ impl InteractiveMenu {
             self.check_admin_privileges("VPN server installation")?;
             display::info("Starting installation...");
             self.handler
-                .install_server(protocol, port, sni, firewall, auto_start, None, false)
+                .install_server(
+                    protocol, port, sni, firewall, auto_start, None, false, None, false,
+                )
                 .await?;
             display::success("Server installed successfully!");
 
@@ -415,7 +417,9 @@ impl InteractiveMenu {
 
         match selection {
             0 => {
-                self.handler.list_users(None, true).await?;
+                self.handler
+                    .list_users(None, true, Vec::new(), None)
+                    .await?;
             }
             1 => {
                 self.create_user_interactive().await?;
@@ -498,7 +502,7 @@ impl InteractiveMenu {
 
         self.check_admin_privileges("User creation")?;
         display::info("Creating user...");
-        self.handler.create_user(name, email, protocol).await?;
+        self.handler.create_user(name, email, protocol, None).await?;
         display::success("User created successfully!");
 
         Ok(())
@@ -528,7 +532,7 @@ impl InteractiveMenu {
             .interact()?;
 
         self.handler
-            .show_user_details(user_name.clone(), show_qr)
+            .show_user_details(user_name.clone(), show_qr, crate::cli::QrSize::Auto, None)
             .await?;
         Ok(())
     }
@@ -606,7 +610,12 @@ impl InteractiveMenu {
         };
 
         self.handler
-            .generate_user_link(user_name.clone(), show_qr, qr_file)
+            .generate_user_link(
+                user_name.clone(),
+                show_qr,
+                qr_file,
+                crate::cli::QrSize::Auto,
+            )
             .await?;
         Ok(())
     }
@@ -1102,11 +1111,12 @@ impl InteractiveMenu {
             println!();
         }
 
-        // Final confirmation
-        let confirm = Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt("Are you sure you want to proceed with uninstallation?")
-            .default(false)
-            .interact()?;
+        // Final confirmation; a purge asks for the server name instead
+        let confirm = purge
+            || Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Are you sure you want to proceed with uninstallation?")
+                .default(false)
+                .interact()?;
 
         if confirm {
             self.check_admin_privileges("Server uninstallation")?;
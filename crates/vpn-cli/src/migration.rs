@@ -1,11 +1,15 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 // use std::collections::HashMap;
 use crate::error::{CliError, Result};
 use crate::utils::display;
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::{Deserialize, Serialize};
+use vpn_network::firewall::{Direction, Protocol as FirewallProtocol};
+use vpn_network::{FirewallLedger, FirewallManager, FirewallRule};
 use vpn_types::protocol::VpnProtocol;
-use vpn_users::{User, UserStatus};
+use vpn_users::config::ServerConfig;
+use vpn_users::{User, UserManager, UserStatus};
 
 #[derive(Debug, Clone)]
 pub struct MigrationOptions {
@@ -15,6 +19,7 @@ pub struct MigrationOptions {
     pub migrate_users: bool,
     pub migrate_config: bool,
     pub migrate_logs: bool,
+    pub migrate_firewall: bool,
     pub validate_after_migration: bool,
 }
 
@@ -24,6 +29,8 @@ pub struct MigrationReport {
     pub users_migrated: u32,
     pub configs_migrated: u32,
     pub files_migrated: u32,
+    pub firewall_rules_migrated: u32,
+    pub parity_verified: bool,
     pub errors: Vec<String>,
     pub warnings: Vec<String>,
     pub migration_time_seconds: u64,
@@ -65,6 +72,8 @@ impl MigrationManager {
             users_migrated: 0,
             configs_migrated: 0,
             files_migrated: 0,
+            firewall_rules_migrated: 0,
+            parity_verified: false,
             errors: Vec::new(),
             warnings: Vec::new(),
             migration_time_seconds: 0,
@@ -104,9 +113,11 @@ impl MigrationManager {
         pb.set_message("Migrating server configuration...");
         pb.set_position(30);
 
+        let bash_config = self.read_bash_config(&options.source_path)?;
+
         if options.migrate_config {
             match self
-                .migrate_server_config(&options.source_path, &options.target_path)
+                .migrate_server_config(&bash_config, &options.source_path, &options.target_path)
                 .await
             {
                 Ok(_) => {
@@ -135,13 +146,21 @@ impl MigrationManager {
             pb.set_message("Migrating users...");
             pb.set_position(50);
 
+            let user_manager = UserManager::new(
+                &options.target_path,
+                self.server_config_from_bash(&bash_config),
+            )?;
+
             for (i, bash_user) in discovered_users.iter().enumerate() {
-                let progress = 50 + (30 * i / discovered_users.len()) as u64;
+                let progress = 50 + (20 * i / discovered_users.len()) as u64;
                 pb.set_position(progress);
                 let message = format!("Migrating user: {}", bash_user.name);
                 pb.set_message(message);
 
-                match self.migrate_user(bash_user, &options.target_path).await {
+                match self
+                    .migrate_user(bash_user, &user_manager, &options.target_path)
+                    .await
+                {
                     Ok(_) => {
                         report.users_migrated += 1;
                     }
@@ -154,7 +173,25 @@ impl MigrationManager {
             }
         }
 
-        // Step 5: Migrate logs and additional files
+        // Step 5: Translate firewall rules
+        pb.set_message("Translating firewall rules...");
+        pb.set_position(70);
+
+        if options.migrate_firewall {
+            let rules = self.translate_firewall_rules(&options.source_path, &bash_config);
+            match self.apply_firewall_rules(&rules).await {
+                Ok(count) => {
+                    report.firewall_rules_migrated += count;
+                }
+                Err(e) => {
+                    report
+                        .warnings
+                        .push(format!("Firewall rule migration failed: {}", e));
+                }
+            }
+        }
+
+        // Step 6: Migrate logs and additional files
         pb.set_message("Migrating logs and files...");
         pb.set_position(80);
 
@@ -171,7 +208,7 @@ impl MigrationManager {
             }
         }
 
-        // Step 6: Validation
+        // Step 7: Validation
         pb.set_message("Validating migration...");
         pb.set_position(90);
 
@@ -183,12 +220,30 @@ impl MigrationManager {
             }
         }
 
-        // Step 7: Cleanup (if not keeping original)
+        // The original is only removed once the new installation matches it
+        let migrated_anything = options.migrate_config || options.migrate_users;
+        if migrated_anything && (options.validate_after_migration || !options.keep_original) {
+            let users = if options.migrate_users {
+                discovered_users.as_slice()
+            } else {
+                &[]
+            };
+            match self.verify_parity(&bash_config, users, &options.target_path) {
+                Ok(()) => report.parity_verified = true,
+                Err(e) => report.errors.push(format!("Parity check failed: {}", e)),
+            }
+        }
+
+        // Step 8: Cleanup (if not keeping original)
         pb.set_message("Finalizing...");
         pb.set_position(100);
 
         if !options.keep_original {
-            if let Err(e) = self.cleanup_original(&options.source_path) {
+            if !report.errors.is_empty() {
+                report.warnings.push(
+                    "Original installation kept because the migration had errors".to_string(),
+                );
+            } else if let Err(e) = self.cleanup_original(&options.source_path) {
                 report.warnings.push(format!("Cleanup warning: {}", e));
             }
         }
@@ -248,12 +303,14 @@ impl MigrationManager {
         Ok(())
     }
 
-    async fn migrate_server_config(&self, source_path: &Path, target_path: &Path) -> Result<()> {
-        // Read Bash configuration
-        let bash_config = self.read_bash_config(source_path)?;
-
+    async fn migrate_server_config(
+        &self,
+        bash_config: &BashVpnConfig,
+        source_path: &Path,
+        target_path: &Path,
+    ) -> Result<()> {
         // Convert to Rust format
-        let rust_config = self.convert_config_format(&bash_config)?;
+        let rust_config = self.convert_config_format(bash_config)?;
 
         // Save to target location
         let target_config_file = target_path.join("config").join("config.json");
@@ -270,6 +327,21 @@ impl MigrationManager {
     }
 
     fn read_bash_config(&self, source_path: &Path) -> Result<BashVpnConfig> {
+        if let Some(content) = self.read_legacy_config_file(source_path) {
+            // Try to parse as JSON first
+            if let Ok(json_config) = serde_json::from_str::<serde_json::Value>(&content) {
+                return self.parse_json_config(&json_config);
+            }
+
+            // Fall back to parsing as key-value pairs
+            return self.parse_text_config(&content);
+        }
+
+        // If no config file found, try to infer from other files
+        self.infer_config_from_files(source_path)
+    }
+
+    fn read_legacy_config_file(&self, source_path: &Path) -> Option<String> {
         let config_dir = source_path.join("config");
 
         // Try to read from various possible locations
@@ -279,22 +351,16 @@ impl MigrationManager {
             source_path.join("server.conf"),
         ];
 
-        for config_file in &config_sources {
-            if config_file.exists() {
-                if let Ok(content) = std::fs::read_to_string(config_file) {
-                    // Try to parse as JSON first
-                    if let Ok(json_config) = serde_json::from_str::<serde_json::Value>(&content) {
-                        return self.parse_json_config(&json_config);
-                    }
-
-                    // Fall back to parsing as key-value pairs
-                    return self.parse_text_config(&content);
-                }
-            }
-        }
+        config_sources
+            .iter()
+            .filter(|config_file| config_file.exists())
+            .find_map(|config_file| std::fs::read_to_string(config_file).ok())
+    }
 
-        // If no config file found, try to infer from other files
-        self.infer_config_from_files(source_path)
+    /// Read the Xray JSON configuration of the legacy installation, if any
+    fn read_legacy_xray_config(&self, source_path: &Path) -> Option<serde_json::Value> {
+        self.read_legacy_config_file(source_path)
+            .and_then(|content| serde_json::from_str(&content).ok())
     }
 
     fn parse_json_config(&self, json: &serde_json::Value) -> Result<BashVpnConfig> {
@@ -492,7 +558,7 @@ impl MigrationManager {
         Ok(())
     }
 
-    fn discover_bash_users(&self, source_path: &Path) -> Result<Vec<BashUser>> {
+    fn discover_user_directories(&self, source_path: &Path) -> Result<Vec<BashUser>> {
         let mut users = Vec::new();
         let users_dir = source_path.join("users");
 
@@ -516,6 +582,77 @@ impl MigrationManager {
         Ok(users)
     }
 
+    /// Users of the legacy installation: the `users` directory merged with the
+    /// clients listed in the server config.json
+    fn discover_bash_users(&self, source_path: &Path) -> Result<Vec<BashUser>> {
+        let mut users = self.discover_user_directories(source_path)?;
+        let clients = self.read_config_clients(source_path);
+
+        // Directories without a UUID take it from the matching client
+        for user in users.iter_mut() {
+            if uuid::Uuid::parse_str(&user.id).is_err() {
+                if let Some(client) = clients.iter().find(|c| c.name == user.name) {
+                    user.id = client.id.clone();
+                    if user.email.is_none() {
+                        user.email = client.email.clone();
+                    }
+                }
+            }
+        }
+
+        let known_ids: HashSet<String> = users.iter().map(|u| u.id.clone()).collect();
+        users.extend(
+            clients
+                .into_iter()
+                .filter(|client| !known_ids.contains(&client.id)),
+        );
+
+        Ok(users)
+    }
+
+    fn read_config_clients(&self, source_path: &Path) -> Vec<BashUser> {
+        let Some(json) = self.read_legacy_xray_config(source_path) else {
+            return Vec::new();
+        };
+
+        let mut users = Vec::new();
+        for inbound in json["inbounds"].as_array().into_iter().flatten() {
+            for client in inbound["settings"]["clients"]
+                .as_array()
+                .into_iter()
+                .flatten()
+            {
+                let Some(id) = client["id"].as_str() else {
+                    continue;
+                };
+
+                // The bash scripts stored the user name in the email field
+                let (name, email) = match client["email"].as_str() {
+                    Some(email) if email.contains('@') => (
+                        email.split('@').next().unwrap_or(email).to_string(),
+                        Some(email.to_string()),
+                    ),
+                    Some(name) if !name.is_empty() => (name.to_string(), None),
+                    _ => (
+                        format!("user-{}", id.chars().take(8).collect::<String>()),
+                        None,
+                    ),
+                };
+
+                users.push(BashUser {
+                    name,
+                    id: id.to_string(),
+                    email,
+                    config_file: None,
+                    link_file: None,
+                    qr_file: None,
+                });
+            }
+        }
+
+        users
+    }
+
     fn parse_bash_user(&self, name: &str, user_dir: &Path) -> Result<BashUser> {
         let mut user = BashUser {
             name: name.to_string(),
@@ -547,6 +684,14 @@ impl MigrationManager {
         // Check for connection link file
         let link_file = user_dir.join("connection.link");
         if link_file.exists() {
+            // vless://<uuid>@host:port?...
+            if uuid::Uuid::parse_str(&user.id).is_err() {
+                if let Ok(link) = std::fs::read_to_string(&link_file) {
+                    if let Some(id) = parse_link_uuid(&link) {
+                        user.id = id;
+                    }
+                }
+            }
             user.link_file = Some(link_file);
         }
 
@@ -559,12 +704,23 @@ impl MigrationManager {
         Ok(user)
     }
 
-    async fn migrate_user(&self, bash_user: &BashUser, target_path: &Path) -> Result<()> {
-        // Create new user in Rust format
+    async fn migrate_user(
+        &self,
+        bash_user: &BashUser,
+        user_manager: &UserManager,
+        target_path: &Path,
+    ) -> Result<()> {
+        if uuid::Uuid::parse_str(&bash_user.id).is_err() {
+            return Err(CliError::MigrationError(format!(
+                "No UUID found for user {}",
+                bash_user.name
+            )));
+        }
+
+        // Recreate the user with the same UUID so existing clients keep working
         let protocol = VpnProtocol::Vless; // Default, could be inferred from config
 
-        let mut user = User::new(bash_user.name.clone(), protocol);
-        user.id = bash_user.id.clone();
+        let mut user = User::new(bash_user.name.clone(), protocol).with_id(bash_user.id.clone());
         user.email = bash_user.email.clone();
         user.status = UserStatus::Active;
 
@@ -583,28 +739,161 @@ impl MigrationManager {
             }
         }
 
-        // Save user to target directory
-        let target_user_dir = target_path.join("users").join(&user.id);
-        std::fs::create_dir_all(&target_user_dir).map_err(|e| {
-            CliError::MigrationError(format!("Failed to create user directory: {}", e))
+        let user = user_manager.import_user(user).await?;
+
+        // Keep the QR code the user already has
+        if let Some(qr_file) = &bash_user.qr_file {
+            let target_qr_file = target_path.join("users").join(&user.id).join("qr.png");
+            std::fs::copy(qr_file, &target_qr_file)?;
+        }
+
+        Ok(())
+    }
+
+    fn server_config_from_bash(&self, bash_config: &BashVpnConfig) -> ServerConfig {
+        let defaults = ServerConfig::default();
+
+        ServerConfig {
+            host: bash_config.server_host.clone(),
+            port: bash_config.server_port,
+            sni: bash_config.sni.clone(),
+            public_key: bash_config.public_key.clone(),
+            private_key: bash_config.private_key.clone(),
+            short_id: bash_config.short_id.clone(),
+            reality_dest: bash_config.reality_dest.clone().or(defaults.reality_dest),
+            reality_server_names: match &bash_config.sni {
+                Some(sni) => vec![sni.clone()],
+                None => defaults.reality_server_names,
+            },
+        }
+    }
+
+    /// Firewall rules the bash installer opened for the legacy inbounds
+    fn translate_firewall_rules(
+        &self,
+        source_path: &Path,
+        bash_config: &BashVpnConfig,
+    ) -> Vec<FirewallRule> {
+        let mut rules: Vec<FirewallRule> = Vec::new();
+
+        if let Some(json) = self.read_legacy_xray_config(source_path) {
+            for inbound in json["inbounds"].as_array().into_iter().flatten() {
+                let Some(port) = inbound["port"].as_u64().and_then(|p| u16::try_from(p).ok())
+                else {
+                    continue;
+                };
+
+                let protocol = match inbound["streamSettings"]["network"].as_str() {
+                    Some("kcp") | Some("quic") => FirewallProtocol::Udp,
+                    _ => FirewallProtocol::Tcp,
+                };
+
+                if rules
+                    .iter()
+                    .any(|r| r.port == port && r.protocol == protocol)
+                {
+                    continue;
+                }
+
+                rules.push(FirewallRule {
+                    port,
+                    protocol,
+                    direction: Direction::In,
+                    source: None,
+                    comment: Some(format!(
+                        "VPN {} (migrated)",
+                        inbound["protocol"].as_str().unwrap_or("server")
+                    )),
+                });
+            }
+        }
+
+        if rules.is_empty() {
+            rules.push(FirewallRule {
+                port: bash_config.server_port,
+                protocol: FirewallProtocol::Tcp,
+                direction: Direction::In,
+                source: None,
+                comment: Some(format!("VPN {} (migrated)", bash_config.protocol)),
+            });
+        }
+
+        rules
+    }
+
+    async fn apply_firewall_rules(&self, rules: &[FirewallRule]) -> Result<u32> {
+        let Some(backend) = FirewallManager::detect_backend().await else {
+            return Err(CliError::MigrationError(
+                "No supported firewall (ufw or iptables) found".to_string(),
+            ));
+        };
+
+        let mut ledger = FirewallLedger::load_default()?;
+        let mut applied = 0;
+        for rule in rules {
+            FirewallManager::add_tracked_rule(&mut ledger, backend, rule).await?;
+            applied += 1;
+        }
+
+        Ok(applied)
+    }
+
+    /// Check that the new installation serves the same port, keys and users
+    fn verify_parity(
+        &self,
+        bash_config: &BashVpnConfig,
+        users: &[BashUser],
+        target_path: &Path,
+    ) -> Result<()> {
+        let config_file = target_path.join("config").join("config.json");
+        let content = std::fs::read_to_string(&config_file).map_err(|e| {
+            CliError::MigrationError(format!("Cannot read {}: {}", config_file.display(), e))
         })?;
+        let config: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|e| CliError::MigrationError(format!("Invalid migrated config: {}", e)))?;
 
-        let user_config_file = target_user_dir.join("config.json");
-        let user_json = serde_json::to_string_pretty(&user)
-            .map_err(|e| CliError::MigrationError(format!("Failed to serialize user: {}", e)))?;
+        let inbound = &config["inbounds"][0];
+        if inbound["port"].as_u64() != Some(bash_config.server_port as u64) {
+            return Err(CliError::MigrationError(format!(
+                "Port mismatch: expected {}, found {}",
+                bash_config.server_port, inbound["port"]
+            )));
+        }
 
-        std::fs::write(&user_config_file, user_json)
-            .map_err(|e| CliError::MigrationError(format!("Failed to write user config: {}", e)))?;
+        if let Some(private_key) = &bash_config.private_key {
+            // The user manager writes snake_case keys, the config converter camelCase
+            let stream = json_field(inbound, "streamSettings", "stream_settings");
+            let reality = json_field(stream, "realitySettings", "reality_settings");
+            let migrated_key = json_field(reality, "privateKey", "private_key").as_str();
+            if migrated_key != Some(private_key.as_str()) {
+                return Err(CliError::MigrationError(
+                    "Reality private key differs from the original".to_string(),
+                ));
+            }
+        }
 
-        // Copy additional files if they exist
-        if let Some(link_file) = &bash_user.link_file {
-            let target_link_file = target_user_dir.join("connection.link");
-            std::fs::copy(link_file, &target_link_file)?;
+        if users.is_empty() {
+            return Ok(());
         }
 
-        if let Some(qr_file) = &bash_user.qr_file {
-            let target_qr_file = target_user_dir.join("qr.png");
-            std::fs::copy(qr_file, &target_qr_file)?;
+        let client_ids: HashSet<&str> = inbound["settings"]["clients"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|client| client["id"].as_str())
+            .collect();
+
+        let missing: Vec<&str> = users
+            .iter()
+            .filter(|user| !client_ids.contains(user.id.as_str()))
+            .map(|user| user.name.as_str())
+            .collect();
+
+        if !missing.is_empty() {
+            return Err(CliError::MigrationError(format!(
+                "Users missing from the migrated config: {}",
+                missing.join(", ")
+            )));
         }
 
         Ok(())
@@ -712,6 +1001,14 @@ impl MigrationManager {
         println!("  Users migrated: {}", report.users_migrated);
         println!("  Configs migrated: {}", report.configs_migrated);
         println!("  Files migrated: {}", report.files_migrated);
+        println!(
+            "  Firewall rules migrated: {}",
+            report.firewall_rules_migrated
+        );
+        println!(
+            "  Parity verified: {}",
+            if report.parity_verified { "yes" } else { "no" }
+        );
         println!("  Migration time: {}s", report.migration_time_seconds);
 
         if !report.errors.is_empty() {
@@ -747,11 +1044,27 @@ impl Default for MigrationOptions {
             migrate_users: true,
             migrate_config: true,
             migrate_logs: true,
+            migrate_firewall: true,
             validate_after_migration: true,
         }
     }
 }
 
+/// Look up an object key written either in camelCase or snake_case
+fn json_field<'a>(value: &'a serde_json::Value, camel: &str, snake: &str) -> &'a serde_json::Value {
+    match value.get(camel) {
+        Some(field) => field,
+        None => &value[snake],
+    }
+}
+
+/// Extract the user UUID from a `vless://<uuid>@host:port` link
+fn parse_link_uuid(link: &str) -> Option<String> {
+    let rest = link.trim().split_once("://")?.1;
+    let id = rest.split_once('@')?.0;
+    uuid::Uuid::parse_str(id).ok().map(|uuid| uuid.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -824,6 +1137,7 @@ mod tests {
             migrate_users: true,
             migrate_config: true,
             migrate_logs: false,
+            migrate_firewall: false,
             validate_after_migration: true,
         };
 
@@ -834,4 +1148,129 @@ mod tests {
         assert!(report.success);
         assert_eq!(report.configs_migrated, 1);
     }
+
+    const ALICE_ID: &str = "6f1d2c3a-8b4e-4f5a-9c7d-1e2f3a4b5c6d";
+    const BOB_ID: &str = "0a9b8c7d-6e5f-4a3b-8c1d-0e9f8a7b6c5d";
+
+    fn create_legacy_installation(source_path: &Path) {
+        let config_dir = source_path.join("config");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(
+            source_path.join("docker-compose.yml"),
+            "version: '3'\nservices:\n  xray:\n    image: xray",
+        )
+        .unwrap();
+
+        let config = serde_json::json!({
+            "inbounds": [{
+                "port": 8443,
+                "protocol": "vless",
+                "settings": {
+                    "clients": [
+                        {"id": ALICE_ID, "email": "alice"},
+                        {"id": BOB_ID, "email": "bob@example.com"}
+                    ]
+                },
+                "streamSettings": {
+                    "network": "tcp",
+                    "realitySettings": {
+                        "privateKey": "legacy-private-key",
+                        "dest": "www.microsoft.com:443",
+                        "serverNames": ["www.microsoft.com"],
+                        "shortId": ["abcd1234"]
+                    }
+                }
+            }]
+        });
+        std::fs::write(config_dir.join("config.json"), config.to_string()).unwrap();
+
+        // Alice also has a user directory with only a connection link
+        let alice_dir = source_path.join("users").join("alice");
+        std::fs::create_dir_all(&alice_dir).unwrap();
+        std::fs::write(
+            alice_dir.join("connection.link"),
+            format!(
+                "vless://{}@203.0.113.1:8443?security=reality#alice",
+                ALICE_ID
+            ),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migration_preserves_user_ids() {
+        let temp_source = tempdir().unwrap();
+        let temp_target = tempdir().unwrap();
+        let manager = MigrationManager::new();
+        create_legacy_installation(temp_source.path());
+
+        let options = MigrationOptions {
+            source_path: temp_source.path().to_path_buf(),
+            target_path: temp_target.path().to_path_buf(),
+            migrate_logs: false,
+            migrate_firewall: false,
+            ..Default::default()
+        };
+
+        let report = manager.migrate_from_bash(options).await.unwrap();
+        assert!(report.success, "errors: {:?}", report.errors);
+        assert_eq!(report.users_migrated, 2);
+        assert!(report.parity_verified);
+
+        let user_manager = UserManager::new(temp_target.path(), ServerConfig::default()).unwrap();
+        let alice = user_manager.get_user(ALICE_ID).await.unwrap();
+        assert_eq!(alice.name, "alice");
+        let bob = user_manager.get_user(BOB_ID).await.unwrap();
+        assert_eq!(bob.name, "bob");
+        assert_eq!(bob.email, Some("bob@example.com".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_migration_removes_original_after_parity() {
+        let temp_source = tempdir().unwrap();
+        let temp_target = tempdir().unwrap();
+        let manager = MigrationManager::new();
+
+        let source_path = temp_source.path().join("v2ray");
+        create_legacy_installation(&source_path);
+
+        let options = MigrationOptions {
+            source_path: source_path.clone(),
+            target_path: temp_target.path().to_path_buf(),
+            keep_original: false,
+            migrate_logs: false,
+            migrate_firewall: false,
+            ..Default::default()
+        };
+
+        let report = manager.migrate_from_bash(options).await.unwrap();
+        assert!(report.parity_verified);
+        assert!(!source_path.exists());
+        assert!(source_path.with_extension("backup").exists());
+    }
+
+    #[test]
+    fn test_translate_firewall_rules() {
+        let temp_source = tempdir().unwrap();
+        let manager = MigrationManager::new();
+        create_legacy_installation(temp_source.path());
+
+        let bash_config = manager.read_bash_config(temp_source.path()).unwrap();
+        let rules = manager.translate_firewall_rules(temp_source.path(), &bash_config);
+
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].port, 8443);
+        assert_eq!(rules[0].protocol, FirewallProtocol::Tcp);
+        assert_eq!(rules[0].direction, Direction::In);
+    }
+
+    #[test]
+    fn test_parse_link_uuid() {
+        assert_eq!(
+            parse_link_uuid(&format!("vless://{}@1.2.3.4:443?type=tcp#a", ALICE_ID)),
+            Some(ALICE_ID.to_string())
+        );
+        assert_eq!(parse_link_uuid("vless://alice@1.2.3.4:443"), None);
+        assert_eq!(parse_link_uuid("not a link"), None);
+    }
 }
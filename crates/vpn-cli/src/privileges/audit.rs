@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use std::fs::{create_dir_all, OpenOptions};
 use std::io::Write;
 use std::path::PathBuf;
+use vpn_types::CorrelationId;
 
 /// Privilege escalation event
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +29,9 @@ pub struct PrivilegeEvent {
     pub pid: u32,
     /// Session ID
     pub session_id: String,
+    /// Correlation ID of the command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<CorrelationId>,
 }
 
 /// Audit logger for privilege events
@@ -85,6 +89,7 @@ impl PrivilegeAuditor {
             denial_reason: None,
             pid: std::process::id(),
             session_id: generate_session_id(),
+            correlation_id: CorrelationId::current(),
         };
 
         self.log_event(event)
@@ -108,6 +113,7 @@ impl PrivilegeAuditor {
             denial_reason: Some(reason),
             pid: std::process::id(),
             session_id: generate_session_id(),
+            correlation_id: CorrelationId::current(),
         };
 
         self.log_event(event)
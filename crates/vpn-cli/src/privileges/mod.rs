@@ -52,9 +52,11 @@ impl PrivilegeManager {
             "stop",
             "restart",
             "reload",
+            "upgrade",
             "diagnostics",
             "security",
             "fix-networks",
+            "verify-client",
         ];
 
         // User management write operations also need root
@@ -72,6 +74,11 @@ impl PrivilegeManager {
             }
         }
 
+        // Applying or reverting network tuning writes sysctls
+        if args.len() >= 3 && args[1] == "tune" && args[2] != "status" {
+            return true;
+        }
+
         false
     }
 
@@ -88,8 +95,11 @@ impl PrivilegeManager {
             "stop" => "Stop VPN Server".to_string(),
             "restart" => "Restart VPN Server".to_string(),
             "reload" => "Reload VPN Configuration".to_string(),
+            "upgrade" => "Upgrade VPN Server".to_string(),
             "diagnostics" => "System Diagnostics".to_string(),
             "security" => "Security Operations".to_string(),
+            "verify-client" => "Verify Client Connection".to_string(),
+            "tune" => "Network Tuning".to_string(),
             "menu" => "VPN Management Menu".to_string(),
             "users" => {
                 if args.len() >= 3 {
@@ -177,6 +187,13 @@ impl PrivilegeManager {
 
         // Prepare command for sudo execution
         let mut cmd_args = vec![current_exe.to_string_lossy().to_string()];
+        // sudo resets the environment, so the correlation ID is passed on
+        // as an argument unless the command line already has one
+        if let Some(id) = vpn_types::CorrelationId::current() {
+            if !args.iter().any(|arg| arg.starts_with("--correlation-id")) {
+                cmd_args.push(format!("--correlation-id={}", id));
+            }
+        }
         cmd_args.extend_from_slice(&args[1..]);
 
         println!(
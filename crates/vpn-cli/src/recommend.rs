@@ -0,0 +1,450 @@
+//! Protocol recommendation wizard
+//!
+//! `vpn recommend` asks what the server has to protect against, which
+//! clients will connect and what the clients' networks let through, scores
+//! every protocol against the answers and offers to install the best one
+//! with matching defaults.
+
+use colored::Colorize;
+use dialoguer::{theme::ColorfulTheme, Confirm, MultiSelect, Select};
+use tabled::{Table, Tabled};
+
+use crate::cli::{AccessGateMode, Protocol};
+use crate::commands::access_gate_config;
+use crate::utils::display;
+use crate::{CommandHandler, Result};
+
+/// What the server has to protect against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreatModel {
+    /// Eavesdropping on public Wi-Fi or by the ISP
+    Privacy,
+    /// Blocking and deep packet inspection by a censor
+    Censorship,
+    /// Nothing in particular; apps or browsers only need another exit
+    Routing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientPlatform {
+    Windows,
+    MacOs,
+    Linux,
+    Android,
+    Ios,
+    /// OpenWrt and other routers
+    Router,
+    /// A browser without a dedicated client
+    Browser,
+}
+
+impl ClientPlatform {
+    pub const ALL: [Self; 7] = [
+        Self::Windows,
+        Self::MacOs,
+        Self::Linux,
+        Self::Android,
+        Self::Ios,
+        Self::Router,
+        Self::Browser,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Windows => "Windows",
+            Self::MacOs => "macOS",
+            Self::Linux => "Linux",
+            Self::Android => "Android",
+            Self::Ios => "iOS",
+            Self::Router => "Router",
+            Self::Browser => "Browser only",
+        }
+    }
+}
+
+/// Answers to the wizard's questions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answers {
+    pub threat: ThreatModel,
+    pub platforms: Vec<ClientPlatform>,
+    /// Client networks drop UDP
+    pub udp_blocked: bool,
+    /// Client networks inspect or fingerprint traffic
+    pub dpi: bool,
+    /// Client networks only let web ports (80/443) out
+    pub web_ports_only: bool,
+}
+
+/// How well one protocol fits the answers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub protocol: Protocol,
+    pub transport: &'static str,
+    /// Higher is better; `None` when the protocol cannot work at all
+    pub score: Option<i32>,
+    pub notes: Vec<String>,
+}
+
+/// The best candidate and the install defaults that go with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recommendation {
+    pub protocol: Protocol,
+    pub transport: &'static str,
+    pub port: Option<u16>,
+    pub access_gate: Option<AccessGateMode>,
+    pub reasons: Vec<String>,
+    /// Every protocol, best first
+    pub candidates: Vec<Candidate>,
+}
+
+/// Score every protocol against `answers`; `None` when none can work
+pub fn recommend(answers: &Answers) -> Option<Recommendation> {
+    let mut candidates: Vec<Candidate> = [
+        Protocol::Vless,
+        Protocol::Shadowsocks,
+        Protocol::Wireguard,
+        Protocol::ProxyServer,
+    ]
+    .into_iter()
+    .map(|protocol| score(protocol, answers))
+    .collect();
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.score));
+
+    let best = candidates.first().filter(|c| c.score.is_some())?.clone();
+
+    // Blend in with HTTPS where only web ports get out, or where a censor
+    // looks closely at unusual ports
+    let port = match best.protocol {
+        Protocol::Vless => Some(443),
+        _ if answers.web_ports_only || answers.dpi => Some(443),
+        _ => None,
+    };
+    // Active probes of the management ports give a server away
+    let access_gate = (answers.threat == ThreatModel::Censorship).then_some(AccessGateMode::Spa);
+
+    Some(Recommendation {
+        protocol: best.protocol,
+        transport: best.transport,
+        port,
+        access_gate,
+        reasons: best.notes,
+        candidates,
+    })
+}
+
+fn score(protocol: Protocol, answers: &Answers) -> Candidate {
+    let mut notes = Vec::new();
+    let mut score = 0;
+    let supported = |platform: &ClientPlatform| match (&protocol, platform) {
+        (Protocol::ProxyServer, _) => true,
+        (_, ClientPlatform::Browser) => false,
+        _ => true,
+    };
+    let unsupported: Vec<&str> = answers
+        .platforms
+        .iter()
+        .filter(|platform| !supported(platform))
+        .map(|platform| platform.label())
+        .collect();
+
+    let transport = match protocol {
+        Protocol::Vless => {
+            score += match answers.threat {
+                ThreatModel::Censorship => 40,
+                ThreatModel::Privacy => 25,
+                ThreatModel::Routing => 10,
+            };
+            if answers.dpi {
+                score += 30;
+                notes.push("Reality borrows a real site's TLS handshake, so DPI sees HTTPS".into());
+            }
+            if answers.web_ports_only {
+                score += 10;
+                notes.push("Runs over TCP 443 like any HTTPS server".into());
+            }
+            "TCP + Reality TLS"
+        }
+        Protocol::Shadowsocks => {
+            score += match answers.threat {
+                ThreatModel::Censorship => 25,
+                ThreatModel::Privacy => 20,
+                ThreatModel::Routing => 15,
+            };
+            if answers.dpi {
+                score -= 10;
+                notes.push("Looks like random bytes, which strict DPI flags".into());
+            }
+            notes.push("Outline clients need no configuration beyond an access key".into());
+            "TCP/UDP, AEAD encrypted"
+        }
+        Protocol::Wireguard => {
+            if answers.udp_blocked {
+                return Candidate {
+                    protocol,
+                    transport: "UDP",
+                    score: None,
+                    notes: vec!["Needs UDP, which the client networks block".into()],
+                };
+            }
+            score += match answers.threat {
+                ThreatModel::Censorship => 0,
+                ThreatModel::Privacy => 40,
+                ThreatModel::Routing => 20,
+            };
+            if answers.dpi {
+                score -= 40;
+                notes.push("Its handshake is trivial for DPI to fingerprint".into());
+            } else {
+                notes.push("Fastest option, with native clients on every platform".into());
+            }
+            if answers.platforms.contains(&ClientPlatform::Router) {
+                score += 10;
+                notes.push("Routers support it out of the box".into());
+            }
+            "UDP"
+        }
+        _ => {
+            score += match answers.threat {
+                ThreatModel::Censorship => -30,
+                ThreatModel::Privacy => -20,
+                ThreatModel::Routing => 30,
+            };
+            if answers.threat != ThreatModel::Routing {
+                notes.push("Plain HTTP/SOCKS5 does not hide traffic from the network".into());
+            }
+            if answers.platforms.contains(&ClientPlatform::Browser) {
+                score += 20;
+                notes.push("Browsers and apps use it without extra software".into());
+            }
+            "HTTP/SOCKS5 over TCP"
+        }
+    };
+
+    if !unsupported.is_empty() {
+        score -= 25 * unsupported.len() as i32;
+        notes.push(format!("No usable client on {}", unsupported.join(", ")));
+    }
+
+    Candidate {
+        protocol,
+        transport,
+        score: Some(score),
+        notes,
+    }
+}
+
+fn protocol_name(protocol: &Protocol) -> &'static str {
+    match protocol {
+        Protocol::Vless => "VLESS+Reality",
+        Protocol::Shadowsocks => "Shadowsocks",
+        Protocol::Wireguard => "WireGuard",
+        Protocol::HttpProxy => "HTTP Proxy",
+        Protocol::Socks5Proxy => "SOCKS5 Proxy",
+        Protocol::ProxyServer => "HTTP/SOCKS5 Proxy",
+    }
+}
+
+/// Ask the questions, show the comparison and offer to install the result
+pub async fn run_wizard(handler: &mut CommandHandler) -> Result<()> {
+    let theme = ColorfulTheme::default();
+    println!("{}", "Protocol Recommendation".cyan().bold());
+    println!();
+
+    let threats = [
+        "Privacy on public Wi-Fi or from my ISP",
+        "Censorship: blocked sites, VPN blocking, DPI",
+        "Just routing apps or browsers through the server",
+    ];
+    let threat = match Select::with_theme(&theme)
+        .with_prompt("What do you need protection from?")
+        .items(&threats)
+        .default(0)
+        .interact()?
+    {
+        0 => ThreatModel::Privacy,
+        1 => ThreatModel::Censorship,
+        _ => ThreatModel::Routing,
+    };
+
+    let labels: Vec<&str> = ClientPlatform::ALL.iter().map(|p| p.label()).collect();
+    let platforms: Vec<ClientPlatform> = MultiSelect::with_theme(&theme)
+        .with_prompt("Which clients will connect? (space to select)")
+        .items(&labels)
+        .interact()?
+        .into_iter()
+        .map(|index| ClientPlatform::ALL[index])
+        .collect();
+
+    let udp_blocked = Confirm::with_theme(&theme)
+        .with_prompt("Do client networks block UDP?")
+        .default(false)
+        .interact()?;
+    let dpi = Confirm::with_theme(&theme)
+        .with_prompt("Do client networks use DPI or block known VPN protocols?")
+        .default(threat == ThreatModel::Censorship)
+        .interact()?;
+    let web_ports_only = Confirm::with_theme(&theme)
+        .with_prompt("Are only web ports (80/443) allowed out?")
+        .default(false)
+        .interact()?;
+
+    let answers = Answers {
+        threat,
+        platforms,
+        udp_blocked,
+        dpi,
+        web_ports_only,
+    };
+    let Some(recommendation) = recommend(&answers) else {
+        display::warning("No protocol works with these restrictions");
+        return Ok(());
+    };
+
+    show_comparison(&recommendation);
+
+    println!();
+    println!(
+        "{} {} ({})",
+        "Recommended:".green().bold(),
+        protocol_name(&recommendation.protocol),
+        recommendation.transport
+    );
+    for reason in &recommendation.reasons {
+        println!("  • {}", reason);
+    }
+    if let Some(port) = recommendation.port {
+        println!("  Port: {}", port);
+    }
+    if recommendation.access_gate.is_some() {
+        println!("  Management ports gated behind single packet authorization");
+    }
+    println!();
+
+    let install = Confirm::with_theme(&theme)
+        .with_prompt("Install it now?")
+        .default(true)
+        .interact()?;
+    if !install {
+        println!(
+            "Install later with: vpn install --protocol {}",
+            clap::ValueEnum::to_possible_value(&recommendation.protocol)
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_default()
+        );
+        return Ok(());
+    }
+
+    if handler.is_server_installed().await? {
+        display::warning("A server is already installed; reinstalling replaces it");
+        let reinstall = Confirm::with_theme(&theme)
+            .with_prompt("Continue?")
+            .default(false)
+            .interact()?;
+        if !reinstall {
+            return Ok(());
+        }
+    }
+    if !crate::PrivilegeManager::is_root() {
+        return Err(crate::CliError::PermissionError(
+            "Installation requires administrator privileges: sudo vpn recommend".to_string(),
+        ));
+    }
+
+    let access_gate = access_gate_config(recommendation.access_gate, vec![], vec![], 62201)?;
+    handler
+        .install_server(
+            recommendation.protocol,
+            recommendation.port,
+            None,
+            true,
+            true,
+            None,
+            false,
+            access_gate,
+            false,
+        )
+        .await
+}
+
+fn show_comparison(recommendation: &Recommendation) {
+    #[derive(Tabled)]
+    struct Row {
+        #[tabled(rename = "Protocol")]
+        protocol: &'static str,
+        #[tabled(rename = "Transport")]
+        transport: &'static str,
+        #[tabled(rename = "Fit")]
+        fit: String,
+        #[tabled(rename = "Notes")]
+        notes: String,
+    }
+
+    let rows: Vec<Row> = recommendation
+        .candidates
+        .iter()
+        .map(|candidate| Row {
+            protocol: protocol_name(&candidate.protocol),
+            transport: candidate.transport,
+            fit: candidate
+                .score
+                .map(|score| score.to_string())
+                .unwrap_or_else(|| "unusable".to_string()),
+            notes: candidate.notes.join("; "),
+        })
+        .collect();
+
+    println!();
+    println!("{}", Table::new(rows));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answers(threat: ThreatModel) -> Answers {
+        Answers {
+            threat,
+            platforms: vec![ClientPlatform::Android, ClientPlatform::Windows],
+            udp_blocked: false,
+            dpi: false,
+            web_ports_only: false,
+        }
+    }
+
+    #[test]
+    fn test_censorship_prefers_reality() {
+        let mut answers = answers(ThreatModel::Censorship);
+        answers.dpi = true;
+
+        let recommendation = recommend(&answers).unwrap();
+        assert_eq!(recommendation.protocol, Protocol::Vless);
+        assert_eq!(recommendation.port, Some(443));
+        assert_eq!(recommendation.access_gate, Some(AccessGateMode::Spa));
+    }
+
+    #[test]
+    fn test_privacy_prefers_wireguard_unless_udp_blocked() {
+        let mut answers = answers(ThreatModel::Privacy);
+        assert_eq!(recommend(&answers).unwrap().protocol, Protocol::Wireguard);
+
+        answers.udp_blocked = true;
+        let recommendation = recommend(&answers).unwrap();
+        assert_ne!(recommendation.protocol, Protocol::Wireguard);
+        let wireguard = recommendation
+            .candidates
+            .iter()
+            .find(|c| c.protocol == Protocol::Wireguard)
+            .unwrap();
+        assert_eq!(wireguard.score, None);
+    }
+
+    #[test]
+    fn test_browser_routing_prefers_proxy() {
+        let mut answers = answers(ThreatModel::Routing);
+        answers.platforms = vec![ClientPlatform::Browser];
+
+        let recommendation = recommend(&answers).unwrap();
+        assert_eq!(recommendation.protocol, Protocol::ProxyServer);
+        assert_eq!(recommendation.access_gate, None);
+    }
+}
@@ -0,0 +1,238 @@
+//! Telemetry command handlers
+
+use crate::cli::{StatusFormat, TelemetryCommands};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::sync::Arc;
+use tabled::{Table, Tabled};
+use vpn_telemetry::{AdminClient, TelemetryStatus, TelemetrySystem};
+
+/// Telemetry system a command talks to
+enum Target {
+    /// Telemetry running in this process
+    Local(Arc<TelemetrySystem>),
+    /// A server's admin endpoint
+    Remote(AdminClient),
+}
+
+impl Target {
+    /// Prefer the in-process telemetry system; otherwise use the admin
+    /// endpoint given on the command line or configured in the environment
+    fn connect(endpoint: Option<String>) -> Result<Self> {
+        if let Some(system) = vpn_telemetry::telemetry() {
+            return Ok(Target::Local(system));
+        }
+
+        let endpoint = endpoint.unwrap_or_else(|| vpn_telemetry::config::from_env().admin.url());
+        let client = AdminClient::new(endpoint.clone())
+            .with_context(|| format!("Failed to create client for {}", endpoint))?;
+        Ok(Target::Remote(client))
+    }
+
+    async fn status(&self) -> Result<TelemetryStatus> {
+        match self {
+            Target::Local(system) => Ok(system.status().await),
+            Target::Remote(client) => Ok(client.status().await?),
+        }
+    }
+
+    async fn flush(&self) -> Result<TelemetryStatus> {
+        match self {
+            Target::Local(system) => {
+                system.flush().await?;
+                Ok(system.status().await)
+            }
+            Target::Remote(client) => Ok(client.flush().await?),
+        }
+    }
+
+    async fn metrics(&self) -> Result<String> {
+        match self {
+            Target::Local(system) => Ok(system.export_metrics().await?),
+            Target::Remote(client) => Ok(client.metrics().await?),
+        }
+    }
+
+    async fn set_sampling_ratio(&self, ratio: f32) -> Result<TelemetryStatus> {
+        match self {
+            Target::Local(system) => {
+                system.set_sampling_ratio(ratio).await?;
+                Ok(system.status().await)
+            }
+            Target::Remote(client) => Ok(client.set_sampling_ratio(ratio).await?),
+        }
+    }
+
+    async fn set_dashboard_enabled(&self, enabled: bool) -> Result<TelemetryStatus> {
+        match self {
+            Target::Local(system) => {
+                system.set_dashboard_enabled(enabled).await?;
+                Ok(system.status().await)
+            }
+            Target::Remote(client) => Ok(client.set_dashboard_enabled(enabled).await?),
+        }
+    }
+}
+
+/// Handle telemetry commands
+pub async fn handle_telemetry_command(
+    command: TelemetryCommands,
+    endpoint: Option<String>,
+) -> Result<()> {
+    let target = Target::connect(endpoint)?;
+
+    match command {
+        TelemetryCommands::Status { format } => {
+            let status = target
+                .status()
+                .await
+                .context("Failed to get telemetry status")?;
+
+            match format {
+                StatusFormat::Table => display_status(&status),
+                StatusFormat::Json => println!("{}", serde_json::to_string_pretty(&status)?),
+                StatusFormat::Yaml => println!("{}", serde_yaml::to_string(&status)?),
+            }
+        }
+        TelemetryCommands::Flush => {
+            let status = target.flush().await.context("Failed to flush telemetry")?;
+            println!(
+                "{} Flushed metrics to {} exporter(s)",
+                "✓".green(),
+                status.exporters.iter().filter(|e| e.enabled).count()
+            );
+        }
+        TelemetryCommands::Metrics { filter } => {
+            let metrics = target.metrics().await.context("Failed to dump metrics")?;
+            match filter {
+                Some(filter) => print!("{}", filter_metrics(&metrics, &filter)),
+                None => print!("{}", metrics),
+            }
+        }
+        TelemetryCommands::Sampling { ratio } => {
+            let status = target
+                .set_sampling_ratio(ratio)
+                .await
+                .context("Failed to change sampling ratio")?;
+            println!(
+                "{} Trace sampling ratio set to {}",
+                "✓".green(),
+                status.sampling_ratio
+            );
+        }
+        TelemetryCommands::Dashboard { enabled } => {
+            let status = target
+                .set_dashboard_enabled(enabled)
+                .await
+                .context("Failed to toggle dashboard")?;
+
+            match (status.dashboard_enabled, status.dashboard_url) {
+                (true, Some(url)) => println!("{} Dashboard enabled at {}", "✓".green(), url),
+                (true, None) => println!("{} Dashboard enabled", "✓".green()),
+                (false, _) => println!("{} Dashboard disabled", "✓".green()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Display telemetry status in table format
+fn display_status(status: &TelemetryStatus) {
+    println!(
+        "\n{}",
+        format!("Telemetry Status - {}", status.service_name).bold()
+    );
+    println!("  Running:        {}", format_flag(status.running));
+    println!(
+        "  Tracing:        {}",
+        format_flag(status.tracing_initialized)
+    );
+    println!("  Sampling ratio: {}", status.sampling_ratio);
+    println!(
+        "  Dashboard:      {}{}",
+        format_flag(status.dashboard_enabled && status.dashboard_running),
+        status
+            .dashboard_url
+            .as_deref()
+            .map(|url| format!(" ({})", url))
+            .unwrap_or_default()
+    );
+
+    if status.exporters.is_empty() {
+        println!("\n  No exporters configured");
+        return;
+    }
+
+    #[derive(Tabled)]
+    struct ExporterRow {
+        #[tabled(rename = "Exporter")]
+        name: String,
+        #[tabled(rename = "Endpoint")]
+        endpoint: String,
+        #[tabled(rename = "Enabled")]
+        enabled: String,
+        #[tabled(rename = "Healthy")]
+        healthy: String,
+    }
+
+    let rows: Vec<ExporterRow> = status
+        .exporters
+        .iter()
+        .map(|exporter| ExporterRow {
+            name: exporter.name.clone(),
+            endpoint: exporter.endpoint.clone(),
+            enabled: exporter.enabled.to_string(),
+            healthy: exporter.healthy.to_string(),
+        })
+        .collect();
+
+    println!("\n{}", Table::new(rows));
+}
+
+fn format_flag(value: bool) -> colored::ColoredString {
+    if value {
+        "on".green()
+    } else {
+        "off".red()
+    }
+}
+
+/// Keep only the Prometheus text lines that belong to metrics whose name
+/// contains `filter`
+fn filter_metrics(metrics: &str, filter: &str) -> String {
+    metrics
+        .lines()
+        .filter(|line| {
+            let name = match line.strip_prefix("# ") {
+                // "# HELP name ..." and "# TYPE name ..."
+                Some(comment) => comment.split_whitespace().nth(1).unwrap_or(""),
+                None => line.split(['{', ' ']).next().unwrap_or(""),
+            };
+            name.contains(filter)
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_metrics() {
+        let metrics = "\
+# HELP vpn_user_connections_total Total VPN user connections
+# TYPE vpn_user_connections_total counter
+vpn_user_connections_total{protocol=\"vless\",status=\"success\"} 3
+# HELP vpn_system_cpu_usage System CPU usage
+# TYPE vpn_system_cpu_usage gauge
+vpn_system_cpu_usage 12.5
+";
+
+        let filtered = filter_metrics(metrics, "cpu");
+        assert_eq!(filtered.lines().count(), 3);
+        assert!(filtered.contains("vpn_system_cpu_usage 12.5"));
+        assert!(!filtered.contains("connections"));
+    }
+}
@@ -0,0 +1,76 @@
+//! Typed confirmation for destructive operations
+//!
+//! Instead of a y/N prompt that is easy to answer on autopilot, the user
+//! has to type the name of what is about to be destroyed. `--force` skips
+//! the prompt for automation; without a terminal and without `--force`
+//! the operation is refused rather than left waiting for input.
+
+use crate::error::{CliError, Result};
+use crate::utils::display;
+use dialoguer::{theme::ColorfulTheme, Input};
+
+/// Ask the user to type `expected` before `action` goes ahead. Succeeds
+/// immediately when `force` is set; fails if the typed text differs.
+pub fn confirm_by_name(action: &str, expected: &str, force: bool) -> Result<()> {
+    if force {
+        return Ok(());
+    }
+
+    if !console::user_attended() {
+        return Err(CliError::InputError(format!(
+            "{} needs confirmation; pass --force to run it non-interactively",
+            action
+        )));
+    }
+
+    display::warning(&format!("{} cannot be undone.", action));
+    let typed: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Type '{}' to confirm", expected))
+        .allow_empty(true)
+        .interact_text()?;
+
+    if is_confirmed(&typed, expected) {
+        Ok(())
+    } else {
+        Err(CliError::InputError(format!(
+            "Confirmation did not match '{}'; {} cancelled",
+            expected,
+            action.to_lowercase()
+        )))
+    }
+}
+
+/// Whether `typed` confirms `expected`; only surrounding whitespace is
+/// forgiven
+pub fn is_confirmed(typed: &str, expected: &str) -> bool {
+    !expected.is_empty() && typed.trim() == expected
+}
+
+/// Name of this server as the user knows it, to type when destroying it
+pub fn server_name() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "vpn-server".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_confirmed() {
+        assert!(is_confirmed("vpn-1", "vpn-1"));
+        assert!(is_confirmed("  vpn-1\n", "vpn-1"));
+        assert!(!is_confirmed("VPN-1", "vpn-1"));
+        assert!(!is_confirmed("y", "vpn-1"));
+        assert!(!is_confirmed("", ""));
+    }
+
+    #[test]
+    fn test_force_skips_prompt() {
+        assert!(confirm_by_name("Purging the server", "vpn-1", true).is_ok());
+    }
+}
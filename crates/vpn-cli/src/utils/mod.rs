@@ -1,3 +1,5 @@
+pub mod confirm;
 pub mod display;
 pub mod format_utils;
+pub mod qr;
 pub mod validation;
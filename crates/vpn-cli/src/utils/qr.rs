@@ -0,0 +1,118 @@
+use crate::cli::QrSize;
+use crate::utils::display;
+use crate::Result;
+use std::path::{Path, PathBuf};
+use vpn_crypto::{QrCodeGenerator, TerminalQrMode};
+
+/// Print a QR code for `data` sized to the current terminal.
+///
+/// When the code does not fit the terminal even in compact mode it is saved
+/// as a PNG to `fallback_path` instead.
+pub fn print_qr_code(data: &str, size: QrSize, fallback_path: &Path) -> Result<()> {
+    let qr_gen = QrCodeGenerator::new();
+    let terminal = crossterm::terminal::size().ok();
+
+    let full = qr_gen.terminal_qr_dimensions(data, TerminalQrMode::Full)?;
+    let compact = qr_gen.terminal_qr_dimensions(data, TerminalQrMode::Compact)?;
+
+    match select_qr_mode(size, full, compact, terminal) {
+        Some(mode) => {
+            let qr_string = qr_gen.render_terminal_qr(data, mode)?;
+            println!("\nQR Code:");
+            println!("{}", qr_string);
+        }
+        None => {
+            qr_gen.save_qr_code_png(data, fallback_path)?;
+            display::warning("Terminal is too small to display the QR code");
+            display::success(&format!("QR code saved to: {}", fallback_path.display()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Where a QR code that does not fit the terminal is saved
+pub fn fallback_path(user_name: &str) -> PathBuf {
+    PathBuf::from(format!("{}_qr.png", user_name))
+}
+
+/// Pick how to render a QR code of the given full and compact dimensions
+/// (columns, rows) on a terminal of `terminal` (columns, rows).
+///
+/// Returns `None` when the code does not fit and should be saved to a file.
+/// An explicit size is always honoured; without a terminal, full size is used.
+pub fn select_qr_mode(
+    size: QrSize,
+    full: (usize, usize),
+    compact: (usize, usize),
+    terminal: Option<(u16, u16)>,
+) -> Option<TerminalQrMode> {
+    match size {
+        QrSize::Full => Some(TerminalQrMode::Full),
+        QrSize::Compact => Some(TerminalQrMode::Compact),
+        QrSize::Auto => {
+            let Some((columns, rows)) = terminal else {
+                return Some(TerminalQrMode::Full);
+            };
+            let fits = |(width, height): (usize, usize)| {
+                width <= columns as usize && height <= rows as usize
+            };
+
+            if fits(full) {
+                Some(TerminalQrMode::Full)
+            } else if fits(compact) {
+                Some(TerminalQrMode::Compact)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: (usize, usize) = (74, 37);
+    const COMPACT: (usize, usize) = (37, 19);
+
+    #[test]
+    fn test_auto_prefers_full_size() {
+        assert_eq!(
+            select_qr_mode(QrSize::Auto, FULL, COMPACT, Some((120, 50))),
+            Some(TerminalQrMode::Full)
+        );
+        assert_eq!(
+            select_qr_mode(QrSize::Auto, FULL, COMPACT, None),
+            Some(TerminalQrMode::Full)
+        );
+    }
+
+    #[test]
+    fn test_auto_falls_back_to_compact() {
+        assert_eq!(
+            select_qr_mode(QrSize::Auto, FULL, COMPACT, Some((80, 24))),
+            Some(TerminalQrMode::Compact)
+        );
+    }
+
+    #[test]
+    fn test_auto_saves_file_on_tiny_terminal() {
+        assert_eq!(
+            select_qr_mode(QrSize::Auto, FULL, COMPACT, Some((30, 15))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_explicit_size_is_honoured() {
+        assert_eq!(
+            select_qr_mode(QrSize::Full, FULL, COMPACT, Some((30, 15))),
+            Some(TerminalQrMode::Full)
+        );
+        assert_eq!(
+            select_qr_mode(QrSize::Compact, FULL, COMPACT, Some((120, 50))),
+            Some(TerminalQrMode::Compact)
+        );
+    }
+}
@@ -166,6 +166,7 @@ async fn test_migration_manager() -> Result<(), Box<dyn std::error::Error>> {
         migrate_users: true,
         migrate_config: true,
         migrate_logs: false,
+        migrate_firewall: false,
         validate_after_migration: true,
     };
 
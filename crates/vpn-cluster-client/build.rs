@@ -0,0 +1,19 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The protocol is vendored from vpn-cluster so the crate builds on its
+    // own; inside the workspace the copy must match the cluster's
+    let upstream = std::path::Path::new("../vpn-cluster/proto/cluster.proto");
+    if upstream.exists() {
+        println!("cargo:rerun-if-changed={}", upstream.display());
+        if std::fs::read(upstream)? != std::fs::read("proto/cluster.proto")? {
+            return Err("proto/cluster.proto differs from vpn-cluster's; copy it over".into());
+        }
+    }
+
+    // Only the client half is generated
+    tonic_build::configure()
+        .build_server(false)
+        .build_client(true)
+        .compile(&["proto/cluster.proto"], &["proto"])?;
+
+    Ok(())
+}
@@ -0,0 +1,411 @@
+//! Connection to one cluster node
+
+use crate::error::{ClientError, Result};
+use crate::proto::{
+    cluster_admin_service_client::ClusterAdminServiceClient,
+    cluster_service_client::ClusterServiceClient, AdminResponse, DrainNodeRequest,
+    GetConfigRequest, SetConfigRequest, StatusRequest, WatchRequest,
+};
+use crate::types::{ClusterEvent, ClusterStatus, Consistency, Node};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_stream::{Stream, StreamExt};
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+/// Name this client reports to nodes in place of a node ID
+const CLIENT_ID: &str = concat!("vpn-cluster-client/", env!("CARGO_PKG_VERSION"));
+
+/// Replay protection headers nodes require on mutating calls, as checked
+/// by the cluster's `ReplayGuard`
+const SENDER_HEADER: &str = "x-cluster-sender";
+const SEQUENCE_HEADER: &str = "x-cluster-sequence";
+const ISSUED_AT_HEADER: &str = "x-cluster-issued-at";
+
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Milliseconds since the Unix epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Sender name of this process; nodes track sequence numbers per sender,
+/// so concurrent clients must not share one
+fn sender() -> &'static str {
+    static SENDER: OnceLock<String> = OnceLock::new();
+    SENDER.get_or_init(|| format!("{}/{}-{}", CLIENT_ID, std::process::id(), now_millis()))
+}
+
+/// Next sequence number of this process, seeded with the start time so it
+/// keeps growing across restarts
+fn next_sequence() -> u64 {
+    let start = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    let _ = NEXT_SEQUENCE.compare_exchange(0, start, Ordering::SeqCst, Ordering::SeqCst);
+    NEXT_SEQUENCE.fetch_add(1, Ordering::SeqCst)
+}
+
+/// Cluster state changes, as returned by [`ClusterClient::watch_events`]
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<ClusterEvent>> + Send>>;
+
+/// Certificates for a cluster that requires mutual TLS
+#[derive(Debug, Clone)]
+pub struct ClientTls {
+    /// Name nodes present in their certificates, the cluster name
+    pub cluster_name: String,
+    pub ca_pem: Vec<u8>,
+    /// Client certificate signed by the cluster CA
+    pub cert_pem: Vec<u8>,
+    pub key_pem: Vec<u8>,
+}
+
+/// Builder for a [`ClusterClient`]
+#[derive(Debug, Clone)]
+pub struct ClusterClientBuilder {
+    endpoint: String,
+    tls: Option<ClientTls>,
+    connect_timeout: Duration,
+    timeout: Option<Duration>,
+}
+
+impl ClusterClientBuilder {
+    /// `endpoint` is a node's cluster address, `host:port` or a URL
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            tls: None,
+            connect_timeout: Duration::from_secs(10),
+            timeout: None,
+        }
+    }
+
+    pub fn tls(mut self, tls: ClientTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Deadline for each unary call; watches are not limited
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub async fn connect(self) -> Result<ClusterClient> {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        let url = if self.endpoint.contains("://") {
+            self.endpoint.clone()
+        } else {
+            format!("{}://{}", scheme, self.endpoint)
+        };
+
+        let mut endpoint = Endpoint::from_shared(url)
+            .map_err(|e| ClientError::InvalidEndpoint(format!("{}: {}", self.endpoint, e)))?
+            .connect_timeout(self.connect_timeout);
+        if let Some(tls) = &self.tls {
+            endpoint = endpoint.tls_config(
+                ClientTlsConfig::new()
+                    .domain_name(tls.cluster_name.clone())
+                    .ca_certificate(Certificate::from_pem(&tls.ca_pem))
+                    .identity(Identity::from_pem(&tls.cert_pem, &tls.key_pem)),
+            )?;
+        }
+
+        let channel = endpoint.connect().await?;
+        Ok(ClusterClient {
+            channel,
+            timeout: self.timeout,
+        })
+    }
+}
+
+/// Async client for one cluster node.
+///
+/// Membership and events are served by every node. Configuration and
+/// drains go through the node's admin service, which only nodes started
+/// with it enabled serve; others answer with
+/// [`tonic::Code::Unimplemented`]. Cloning is cheap and clones share the
+/// connection.
+#[derive(Debug, Clone)]
+pub struct ClusterClient {
+    channel: Channel,
+    timeout: Option<Duration>,
+}
+
+impl ClusterClient {
+    /// Connect to `endpoint` without TLS; see [`ClusterClient::builder`]
+    /// for other options
+    pub async fn connect(endpoint: impl Into<String>) -> Result<Self> {
+        ClusterClientBuilder::new(endpoint).connect().await
+    }
+
+    pub fn builder(endpoint: impl Into<String>) -> ClusterClientBuilder {
+        ClusterClientBuilder::new(endpoint)
+    }
+
+    fn cluster(&self) -> ClusterServiceClient<Channel> {
+        ClusterServiceClient::new(self.channel.clone()).accept_compressed(CompressionEncoding::Gzip)
+    }
+
+    fn admin(&self) -> ClusterAdminServiceClient<Channel> {
+        ClusterAdminServiceClient::new(self.channel.clone())
+            .accept_compressed(CompressionEncoding::Gzip)
+    }
+
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(timeout) = self.timeout {
+            request.set_timeout(timeout);
+        }
+        request
+    }
+
+    /// Request for a mutating call, stamped so nodes can reject replays
+    fn stamped<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = self.request(message);
+        let values = [
+            (SENDER_HEADER, sender().to_string()),
+            (SEQUENCE_HEADER, next_sequence().to_string()),
+            (ISSUED_AT_HEADER, now_millis().to_string()),
+        ];
+        for (key, value) in values {
+            if let Ok(value) = value.try_into() {
+                request.metadata_mut().insert(key, value);
+            }
+        }
+        request
+    }
+
+    /// Membership and leadership as known to the node
+    pub async fn status(&self) -> Result<ClusterStatus> {
+        let response = self
+            .cluster()
+            .get_cluster_status(self.request(StatusRequest {
+                node_id: CLIENT_ID.to_string(),
+            }))
+            .await?
+            .into_inner();
+
+        let mut status: ClusterStatus = response.cluster_state.unwrap_or_default().into();
+        status.nodes = response.nodes.into_iter().map(Node::from).collect();
+        Ok(status)
+    }
+
+    /// Members of the cluster
+    pub async fn list_nodes(&self) -> Result<Vec<Node>> {
+        Ok(self.status().await?.nodes)
+    }
+
+    /// Follow cluster state changes. The first event is a `snapshot` of the
+    /// current state; the stream ends when the node goes away.
+    pub async fn watch_events(&self) -> Result<EventStream> {
+        let updates = self
+            .cluster()
+            .watch_cluster_state(WatchRequest {
+                node_id: CLIENT_ID.to_string(),
+            })
+            .await?
+            .into_inner();
+
+        Ok(Box::pin(updates.map(|update| {
+            update
+                .map_err(ClientError::from)
+                .and_then(ClusterEvent::try_from)
+        })))
+    }
+
+    /// Cluster configuration value of `key`, `None` when unset
+    pub async fn get_config(
+        &self,
+        key: &str,
+        consistency: Consistency,
+    ) -> Result<Option<serde_json::Value>> {
+        let response = self
+            .admin()
+            .get_config(self.request(GetConfigRequest {
+                key: key.to_string(),
+                consistency: consistency.as_str().to_string(),
+            }))
+            .await?
+            .into_inner();
+
+        if !response.found {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&response.value_json)?))
+    }
+
+    /// Set cluster configuration `key` to `value`; nodes are notified with
+    /// a `configuration_changed` event
+    pub async fn set_config(
+        &self,
+        key: &str,
+        value: &serde_json::Value,
+        consistency: Consistency,
+    ) -> Result<()> {
+        let response = self
+            .admin()
+            .set_config(self.stamped(SetConfigRequest {
+                key: key.to_string(),
+                value_json: value.to_string(),
+                consistency: consistency.as_str().to_string(),
+            }))
+            .await?
+            .into_inner();
+
+        accepted(response)
+    }
+
+    /// Cordon `node_id` so new users and proxy connections go to other
+    /// nodes; existing sessions are left to finish
+    pub async fn drain(&self, node_id: &str) -> Result<()> {
+        self.set_drain(node_id, true).await
+    }
+
+    /// Return a drained node to service
+    pub async fn undrain(&self, node_id: &str) -> Result<()> {
+        self.set_drain(node_id, false).await
+    }
+
+    async fn set_drain(&self, node_id: &str, drain: bool) -> Result<()> {
+        let response = self
+            .admin()
+            .drain_node(self.stamped(DrainNodeRequest {
+                node_id: node_id.to_string(),
+                drain,
+            }))
+            .await?
+            .into_inner();
+
+        accepted(response)
+    }
+}
+
+fn accepted(response: AdminResponse) -> Result<()> {
+    if response.success {
+        Ok(())
+    } else {
+        Err(ClientError::Rejected(response.message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use vpn_cluster::config::GossipConfig;
+    use vpn_cluster::consensus::SimpleConsensus;
+    use vpn_cluster::distributed_storage::MemoryStorage;
+    use vpn_cluster::{
+        ClusterEventBus, ClusterGrpcClient, ClusterGrpcServer, ClusterState, GossipManager, Node,
+        NodeCordon, NodeId,
+    };
+
+    /// Node serving the admin service in this process
+    async fn serve() -> (SocketAddr, NodeId, Arc<NodeCordon>) {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        state
+            .write()
+            .await
+            .add_node(Node::with_id(
+                node_id.clone(),
+                "edge-1".to_string(),
+                address,
+            ))
+            .unwrap();
+
+        let events = ClusterEventBus::new(16);
+        let gossip = Arc::new(GossipManager::new(
+            node_id.clone(),
+            GossipConfig::default(),
+            state.clone(),
+            ClusterGrpcClient::new(node_id.clone()),
+        ));
+        let cordon = Arc::new(NodeCordon::new(state.clone(), events.clone(), gossip));
+        let server = ClusterGrpcServer::new(
+            node_id.clone(),
+            state,
+            Arc::new(SimpleConsensus::new(node_id.clone())),
+            address,
+        )
+        .with_admin()
+        .with_event_bus(events)
+        .with_config_store(Arc::new(MemoryStorage::new()))
+        .with_cordon(cordon.clone());
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        (address, node_id, cordon)
+    }
+
+    #[test]
+    fn test_vendored_proto_matches_cluster() {
+        assert_eq!(
+            include_str!("../proto/cluster.proto"),
+            include_str!("../../vpn-cluster/proto/cluster.proto")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_round_trip() {
+        let (address, _, _) = serve().await;
+        let client = ClusterClient::connect(address.to_string()).await.unwrap();
+
+        assert_eq!(
+            client
+                .get_config("dns", Consistency::Default)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let value = json!({"upstream": "system"});
+        client
+            .set_config("dns", &value, Consistency::All)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.get_config("dns", Consistency::One).await.unwrap(),
+            Some(value)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain_round_trip() {
+        let (address, node_id, cordon) = serve().await;
+        let client = ClusterClient::connect(address.to_string()).await.unwrap();
+
+        client.drain(&node_id.to_string()).await.unwrap();
+        assert!(cordon.is_cordoned(&node_id).await.unwrap());
+
+        client.undrain(&node_id.to_string()).await.unwrap();
+        assert!(!cordon.is_cordoned(&node_id).await.unwrap());
+
+        // Unknown nodes are rejected rather than silently ignored
+        let unknown = NodeId::new().to_string();
+        assert!(matches!(
+            client.drain(&unknown).await,
+            Err(ClientError::Rejected(_))
+        ));
+    }
+}
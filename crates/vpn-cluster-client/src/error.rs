@@ -0,0 +1,45 @@
+//! Client error types
+
+/// Result type for client calls
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// Errors returned by [`ClusterClient`](crate::ClusterClient)
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Invalid endpoint: {0}")]
+    InvalidEndpoint(String),
+
+    #[error("Connection failed: {0}")]
+    Transport(#[from] tonic::transport::Error),
+
+    /// The node answered the call with an error status, e.g. `Unimplemented`
+    /// from a node without the admin service
+    #[error("Call failed: {}", .0.message())]
+    Status(Box<tonic::Status>),
+
+    /// The node accepted the call but could not carry out the action
+    #[error("Rejected by node: {0}")]
+    Rejected(String),
+
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl From<tonic::Status> for ClientError {
+    fn from(status: tonic::Status) -> Self {
+        Self::Status(Box::new(status))
+    }
+}
+
+impl ClientError {
+    /// gRPC status code, when the node answered with an error status
+    pub fn code(&self) -> Option<tonic::Code> {
+        match self {
+            Self::Status(status) => Some(status.code()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Client for VPN cluster nodes
+//!
+//! A small async API over the cluster's gRPC services for dashboards and
+//! automation that should not link the full `vpn-cluster` crate: list
+//! nodes, follow cluster events, read and write configuration at a chosen
+//! consistency level, and drain nodes.
+//!
+//! ```no_run
+//! use vpn_cluster_client::{ClusterClient, Consistency};
+//!
+//! # async fn run() -> vpn_cluster_client::Result<()> {
+//! let client = ClusterClient::connect("10.0.0.5:8080").await?;
+//! for node in client.list_nodes().await? {
+//!     println!("{} {} {}", node.name, node.role, node.status);
+//! }
+//! client
+//!     .set_config("dns", &serde_json::json!({"upstream": "system"}), Consistency::Quorum)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The crate is versioned separately from the cluster. Its API changes only
+//! with its own version, and it keeps talking to nodes that add fields to
+//! the protocol.
+
+pub mod client;
+pub mod error;
+pub mod types;
+
+/// Generated protocol types, for calls this crate does not wrap
+pub mod proto {
+    tonic::include_proto!("cluster");
+}
+
+pub use client::{ClientTls, ClusterClient, ClusterClientBuilder, EventStream};
+pub use error::{ClientError, Result};
+pub use types::{ClusterEvent, ClusterStatus, Consistency, Node};
@@ -0,0 +1,231 @@
+//! Cluster data as seen from outside the cluster
+//!
+//! These mirror the protobuf messages with plain Rust types, so callers do
+//! not depend on the generated code and keep compiling when fields are
+//! added to the protocol.
+
+use crate::error::{ClientError, Result};
+use crate::proto;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Replicas a configuration read or write must reach
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Consistency {
+    /// Whatever the node's storage does by default
+    #[default]
+    Default,
+    /// Any single replica
+    One,
+    /// A majority of replicas
+    Quorum,
+    /// Every replica
+    All,
+}
+
+impl Consistency {
+    /// Name sent on the wire; empty for [`Consistency::Default`]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Consistency::Default => "",
+            Consistency::One => "one",
+            Consistency::Quorum => "quorum",
+            Consistency::All => "all",
+        }
+    }
+}
+
+impl fmt::Display for Consistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Consistency::Default => f.write_str("default"),
+            other => f.write_str(other.as_str()),
+        }
+    }
+}
+
+impl FromStr for Consistency {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "" | "default" => Ok(Consistency::Default),
+            "one" => Ok(Consistency::One),
+            "quorum" => Ok(Consistency::Quorum),
+            "all" => Ok(Consistency::All),
+            other => Err(ClientError::InvalidResponse(format!(
+                "Unknown consistency level: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// A cluster member
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub name: String,
+    /// Cluster address, `host:port`
+    pub address: String,
+    /// e.g. `leader`, `follower` or `learner`
+    pub role: String,
+    /// e.g. `active`, `draining` or `failed`
+    pub status: String,
+    pub region: Option<String>,
+    pub zone: Option<String>,
+    pub version: String,
+    /// Whether the node is drained of new users and connections
+    pub cordoned: bool,
+    /// Unix seconds
+    pub joined_at: u64,
+    /// Unix seconds
+    pub last_seen: u64,
+    pub metadata: HashMap<String, String>,
+}
+
+impl From<proto::NodeInfo> for Node {
+    fn from(info: proto::NodeInfo) -> Self {
+        Self {
+            id: info.node_id,
+            name: info.name,
+            address: info.address,
+            role: info.role,
+            status: info.status,
+            region: non_empty(info.region),
+            zone: non_empty(info.zone),
+            version: info.version,
+            cordoned: info.cordoned,
+            joined_at: info.joined_at,
+            last_seen: info.last_seen,
+            metadata: info.metadata,
+        }
+    }
+}
+
+/// Cluster membership and leadership as known to one node
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClusterStatus {
+    pub cluster_name: String,
+    pub leader_id: Option<String>,
+    pub term: u64,
+    pub config_version: u64,
+    pub nodes: Vec<Node>,
+}
+
+impl From<proto::ClusterState> for ClusterStatus {
+    fn from(state: proto::ClusterState) -> Self {
+        Self {
+            cluster_name: state.cluster_name,
+            leader_id: non_empty(state.leader_id),
+            term: state.term,
+            config_version: state.config_version,
+            nodes: state.nodes.into_iter().map(Node::from).collect(),
+        }
+    }
+}
+
+impl ClusterStatus {
+    pub fn leader(&self) -> Option<&Node> {
+        let leader_id = self.leader_id.as_deref()?;
+        self.nodes.iter().find(|node| node.id == leader_id)
+    }
+}
+
+/// A change of cluster state pushed by a watched node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterEvent {
+    /// Increases by one per update on a watch
+    pub sequence: u64,
+    /// Coordination event that caused the update, e.g. `node_joined` or
+    /// `configuration_changed`; `snapshot` for the first update and
+    /// `state_changed` for changes seen without an event
+    pub kind: String,
+    /// The coordination event itself, when `kind` names one; its `type`
+    /// field repeats `kind`
+    pub event: Option<serde_json::Value>,
+    /// Cluster state after the change
+    pub status: ClusterStatus,
+    /// Unix seconds
+    pub timestamp: u64,
+}
+
+impl TryFrom<proto::ClusterStateUpdate> for ClusterEvent {
+    type Error = ClientError;
+
+    fn try_from(update: proto::ClusterStateUpdate) -> Result<Self> {
+        let event = match update.event.as_str() {
+            "" => None,
+            event => Some(serde_json::from_str(event)?),
+        };
+
+        Ok(Self {
+            sequence: update.sequence,
+            kind: update.event_type,
+            event,
+            status: update.cluster_state.map(Into::into).unwrap_or_default(),
+            timestamp: update.timestamp,
+        })
+    }
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consistency_round_trip() {
+        for level in [
+            Consistency::Default,
+            Consistency::One,
+            Consistency::Quorum,
+            Consistency::All,
+        ] {
+            assert_eq!(level.to_string().parse::<Consistency>().unwrap(), level);
+        }
+        assert_eq!(Consistency::Default.as_str(), "");
+        assert!("most".parse::<Consistency>().is_err());
+    }
+
+    #[test]
+    fn test_event_from_update() {
+        let update = proto::ClusterStateUpdate {
+            sequence: 3,
+            cluster_state: Some(proto::ClusterState {
+                cluster_name: "edge".to_string(),
+                leader_id: "n1".to_string(),
+                nodes: vec![proto::NodeInfo {
+                    node_id: "n1".to_string(),
+                    name: "edge-1".to_string(),
+                    role: "leader".to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            event_type: "configuration_changed".to_string(),
+            event: r#"{"type":"configuration_changed","key":"dns","timestamp":7}"#.to_string(),
+            timestamp: 7,
+        };
+
+        let event = ClusterEvent::try_from(update).unwrap();
+        assert_eq!(event.kind, "configuration_changed");
+        assert_eq!(event.event.unwrap()["key"], "dns");
+        assert_eq!(event.status.leader().unwrap().name, "edge-1");
+        assert_eq!(event.status.nodes[0].region, None);
+
+        let snapshot = ClusterEvent::try_from(proto::ClusterStateUpdate {
+            event_type: "snapshot".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(snapshot.event, None);
+        assert_eq!(snapshot.status, ClusterStatus::default());
+    }
+}
@@ -115,7 +115,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         };
 
         match client.send_heartbeat(address, resources).await {
-            Ok(response) => {
+            Ok((response, _)) => {
                 println!(
                     "✅ Heartbeat to node {} successful (term: {})",
                     port, response.term
@@ -181,9 +181,25 @@ fn create_node_config(
         storage_backend: StorageBackendConfig::Memory,
         consensus_algorithm: vpn_cluster::config::ConsensusAlgorithm::Simple,
         is_initial_node: is_initial,
+        learner: false,
         bootstrap_nodes,
         gossip_interval: Duration::from_secs(5),
         heartbeat_interval: Duration::from_secs(1),
         election_timeout: Duration::from_secs(10),
+        tls: None,
+        admin_api: false,
+        transport: Default::default(),
+        gossip: Default::default(),
+        health_scoring: Default::default(),
+        rolling_restart: Default::default(),
+        clock_skew: Default::default(),
+        load_balancer: None,
+        region: None,
+        zone: None,
+        labels: Default::default(),
+        data_dir: std::env::temp_dir(),
+        consensus_log: Default::default(),
+        config_conflicts: Default::default(),
+        history: Default::default(),
     }
 }
@@ -0,0 +1,345 @@
+//! Anti-entropy between gossip and consensus membership
+//!
+//! The gossip view (the nodes in [`ClusterState`]) and the membership
+//! committed through consensus can drift apart, e.g. when a node removed
+//! from the consensus configuration keeps being reported alive.
+//! [`MembershipReconciler`] periodically diffs the two, publishes every
+//! confirmed divergence on the event bus and, when auto-heal is on, brings
+//! the views back in line.
+
+use crate::consensus::ConsensusEngine;
+use crate::coordination::{current_timestamp, CoordinationEvent};
+use crate::error::Result;
+use crate::events::ClusterEventBus;
+use crate::node::NodeId;
+use crate::state::{ClusterState, TombstoneReason, DEFAULT_TOMBSTONE_TTL};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DivergenceKind {
+    /// Gossip still lists a node that consensus no longer has
+    StaleInGossip,
+    /// Consensus has a node that gossip does not know about
+    MissingFromGossip,
+}
+
+impl DivergenceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DivergenceKind::StaleInGossip => "stale_in_gossip",
+            DivergenceKind::MissingFromGossip => "missing_from_gossip",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MembershipDivergence {
+    pub node_id: NodeId,
+    pub kind: DivergenceKind,
+    /// Whether the reconciler repaired it
+    pub healed: bool,
+}
+
+/// Periodically reconciles gossip membership with consensus membership
+pub struct MembershipReconciler {
+    local_node: NodeId,
+    state: Arc<RwLock<ClusterState>>,
+    consensus: Arc<dyn ConsensusEngine>,
+    events: ClusterEventBus,
+    interval: Duration,
+    /// Consecutive rounds a divergence must be seen before acting on it,
+    /// so joins and removals still in flight are not reported
+    confirmations: u32,
+    auto_heal: bool,
+    pending: Mutex<HashMap<(NodeId, DivergenceKind), u32>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl MembershipReconciler {
+    pub fn new(
+        local_node: NodeId,
+        state: Arc<RwLock<ClusterState>>,
+        consensus: Arc<dyn ConsensusEngine>,
+        events: ClusterEventBus,
+    ) -> Self {
+        Self {
+            local_node,
+            state,
+            consensus,
+            events,
+            interval: Duration::from_secs(30),
+            confirmations: 2,
+            auto_heal: true,
+            pending: Mutex::new(HashMap::new()),
+            task: Mutex::new(None),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_confirmations(mut self, confirmations: u32) -> Self {
+        self.confirmations = confirmations.max(1);
+        self
+    }
+
+    /// Only report divergences instead of repairing them
+    pub fn with_auto_heal(mut self, auto_heal: bool) -> Self {
+        self.auto_heal = auto_heal;
+        self
+    }
+
+    /// Current differences between the two views, or `None` when consensus
+    /// has no committed membership to compare against
+    pub async fn diff(&self) -> Option<Vec<(NodeId, DivergenceKind)>> {
+        let members = self.consensus.committed_members().await?;
+        let state = self.state.read().await;
+        let mut divergences = Vec::new();
+
+        for node_id in state.nodes.keys() {
+            if *node_id != self.local_node && !members.contains(node_id) {
+                divergences.push((node_id.clone(), DivergenceKind::StaleInGossip));
+            }
+        }
+        for node_id in members {
+            if node_id != self.local_node && !state.nodes.contains_key(&node_id) {
+                divergences.push((node_id, DivergenceKind::MissingFromGossip));
+            }
+        }
+
+        Some(divergences)
+    }
+
+    /// Run one reconciliation round, returning the confirmed divergences
+    pub async fn reconcile(&self) -> Result<Vec<MembershipDivergence>> {
+        let Some(current) = self.diff().await else {
+            return Ok(Vec::new());
+        };
+
+        let confirmed: Vec<(NodeId, DivergenceKind)> = {
+            let mut pending = self.pending.lock().await;
+            let mut seen = HashMap::new();
+            for divergence in current {
+                let rounds = pending.get(&divergence).copied().unwrap_or(0) + 1;
+                seen.insert(divergence, rounds);
+            }
+            *pending = seen;
+
+            pending
+                .iter()
+                .filter(|(_, rounds)| **rounds >= self.confirmations)
+                .map(|(divergence, _)| divergence.clone())
+                .collect()
+        };
+
+        let mut divergences = Vec::new();
+        for (node_id, kind) in confirmed {
+            let healed = self.auto_heal && self.heal(&node_id, kind).await?;
+            if healed {
+                self.pending.lock().await.remove(&(node_id.clone(), kind));
+            }
+
+            tracing::warn!(
+                "Membership divergence for node {}: {}{}",
+                node_id,
+                kind.as_str(),
+                if healed { " (healed)" } else { "" }
+            );
+            self.events.publish(CoordinationEvent::MembershipDiverged {
+                node_id: node_id.clone(),
+                divergence: kind.as_str().to_string(),
+                healed,
+                timestamp: current_timestamp(),
+            });
+            divergences.push(MembershipDivergence {
+                node_id,
+                kind,
+                healed,
+            });
+        }
+
+        Ok(divergences)
+    }
+
+    /// Repair one divergence, returning whether anything changed.
+    ///
+    /// Consensus is authoritative: a node it no longer has is evicted from
+    /// gossip. A node gossip has tombstoned but consensus still has is
+    /// removed from consensus, which only the leader can do. A node gossip
+    /// has simply not heard of yet is left for gossip to discover.
+    async fn heal(&self, node_id: &NodeId, kind: DivergenceKind) -> Result<bool> {
+        match kind {
+            DivergenceKind::StaleInGossip => {
+                let mut state = self.state.write().await;
+                Ok(state
+                    .tombstone_node(node_id, TombstoneReason::Evicted, DEFAULT_TOMBSTONE_TTL)
+                    .is_ok())
+            }
+            DivergenceKind::MissingFromGossip => {
+                let tombstoned = self.state.read().await.get_tombstone(node_id).is_some();
+                if !tombstoned || !self.consensus.is_leader().await {
+                    return Ok(false);
+                }
+
+                self.consensus.remove_node(node_id.clone()).await?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Reconcile every interval until stopped
+    pub async fn start(self: Arc<Self>) {
+        let reconciler = self.clone();
+        let handle = tokio::spawn(async move {
+            let start = tokio::time::Instant::now() + reconciler.interval;
+            let mut ticker = tokio::time::interval_at(start, reconciler.interval);
+
+            loop {
+                ticker.tick().await;
+                if let Err(e) = reconciler.reconcile().await {
+                    tracing::warn!("Membership reconciliation failed: {}", e);
+                }
+            }
+        });
+
+        if let Some(previous) = self.task.lock().await.replace(handle) {
+            previous.abort();
+        }
+    }
+
+    pub async fn stop(&self) {
+        if let Some(handle) = self.task.lock().await.take() {
+            handle.abort();
+        }
+    }
+}
+
+impl Drop for MembershipReconciler {
+    fn drop(&mut self) {
+        if let Some(handle) = self.task.get_mut().take() {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::RaftConsensus;
+    use crate::node::{Node, NodeStatus};
+
+    async fn leader_raft(node_id: &NodeId) -> Arc<RaftConsensus> {
+        let raft = Arc::new(RaftConsensus::new(node_id.clone()).await.unwrap());
+        raft.start().await.unwrap();
+        raft.elect_leader().await.unwrap();
+        raft
+    }
+
+    fn node(status: NodeStatus) -> Node {
+        let mut node = Node::new("node".to_string(), "127.0.0.1:8001".parse().unwrap());
+        node.set_status(status);
+        node
+    }
+
+    #[tokio::test]
+    async fn test_stale_gossip_node_is_evicted() {
+        let local = NodeId::new();
+        let raft = leader_raft(&local).await;
+        let state = Arc::new(RwLock::new(ClusterState::new(local.clone())));
+        let events = ClusterEventBus::default();
+        let mut stream = events.stream();
+
+        // Alive in gossip but never part of the consensus configuration
+        let stale = node(NodeStatus::Healthy);
+        let stale_id = stale.id.clone();
+        state.write().await.add_node(stale).unwrap();
+
+        let reconciler = MembershipReconciler::new(local, state.clone(), raft, events);
+
+        // Needs two rounds to confirm
+        assert!(reconciler.reconcile().await.unwrap().is_empty());
+        let divergences = reconciler.reconcile().await.unwrap();
+        assert_eq!(
+            divergences,
+            vec![MembershipDivergence {
+                node_id: stale_id.clone(),
+                kind: DivergenceKind::StaleInGossip,
+                healed: true,
+            }]
+        );
+
+        let state = state.read().await;
+        assert!(state.get_node(&stale_id).is_none());
+        assert_eq!(
+            state.get_tombstone(&stale_id).unwrap().reason,
+            TombstoneReason::Evicted
+        );
+        assert_eq!(
+            stream.next_event().await.unwrap().kind(),
+            "membership_diverged"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tombstoned_node_is_removed_from_consensus() {
+        let local = NodeId::new();
+        let raft = leader_raft(&local).await;
+        let state = Arc::new(RwLock::new(ClusterState::new(local.clone())));
+
+        let departed = node(NodeStatus::Healthy);
+        let departed_id = departed.id.clone();
+        raft.add_node(departed_id.clone(), "127.0.0.1:8001".to_string())
+            .await
+            .unwrap();
+        {
+            let mut state = state.write().await;
+            state.add_node(departed).unwrap();
+            state.remove_node(&departed_id).unwrap();
+        }
+
+        let reconciler =
+            MembershipReconciler::new(local, state, raft.clone(), ClusterEventBus::default())
+                .with_confirmations(1);
+
+        let divergences = reconciler.reconcile().await.unwrap();
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].kind, DivergenceKind::MissingFromGossip);
+        assert!(divergences[0].healed);
+        assert!(!raft
+            .committed_members()
+            .await
+            .unwrap()
+            .contains(&departed_id));
+        assert!(reconciler.diff().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_report_only_mode() {
+        let local = NodeId::new();
+        let raft = leader_raft(&local).await;
+        let state = Arc::new(RwLock::new(ClusterState::new(local.clone())));
+        state
+            .write()
+            .await
+            .add_node(node(NodeStatus::Healthy))
+            .unwrap();
+
+        let reconciler =
+            MembershipReconciler::new(local, state.clone(), raft, ClusterEventBus::default())
+                .with_confirmations(1)
+                .with_auto_heal(false);
+
+        let divergences = reconciler.reconcile().await.unwrap();
+        assert_eq!(divergences.len(), 1);
+        assert!(!divergences[0].healed);
+        assert_eq!(state.read().await.size(), 1);
+    }
+}
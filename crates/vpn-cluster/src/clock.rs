@@ -0,0 +1,222 @@
+//! Clock skew between cluster nodes
+//!
+//! Leader leases and lease-based reads assume node clocks run close
+//! together. Every heartbeat doubles as an NTP-style time exchange: the
+//! sender stamps the request, the receiver stamps its arrival and its reply,
+//! and the sender stamps the reply's arrival. From the four stamps,
+//! [`ClockSample::from_exchange`] estimates the peer's clock offset
+//! independently of the (symmetric part of the) network delay.
+//!
+//! [`ClockSkewMonitor`] keeps the latest sample per peer and reports when a
+//! peer's offset crosses the configured limit and when it is back within
+//! it. While a peer is skewed, its contact no longer extends leader leases.
+
+use crate::error::{ClusterError, Result};
+use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Clock skew limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockSkewConfig {
+    /// Largest tolerated offset between this node's clock and a peer's
+    pub max_skew: Duration,
+}
+
+impl Default for ClockSkewConfig {
+    fn default() -> Self {
+        Self {
+            max_skew: Duration::from_millis(500),
+        }
+    }
+}
+
+impl ClockSkewConfig {
+    /// Validate the limit
+    pub fn validate(&self) -> Result<()> {
+        if self.max_skew.is_zero() {
+            return Err(ClusterError::configuration(
+                "Clock skew limit must be greater than zero",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// One time exchange with a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockSample {
+    /// Peer clock minus local clock, in milliseconds
+    pub offset_ms: i64,
+    /// Network round trip, without the peer's processing time
+    pub round_trip_ms: u64,
+    /// Local time the sample was taken, in milliseconds since the epoch
+    pub measured_at_ms: u64,
+}
+
+impl ClockSample {
+    /// Estimate the offset from the four stamps of a request/reply pair, in
+    /// milliseconds since the epoch: request `sent` and reply `returned`
+    /// by the local clock, request `received` and reply `replied` by the
+    /// peer's
+    pub fn from_exchange(sent: u64, received: u64, replied: u64, returned: u64) -> Self {
+        let (sent, received, replied, returned) = (
+            sent as i64,
+            received as i64,
+            replied as i64,
+            returned as i64,
+        );
+        let offset_ms = ((received - sent) + (replied - returned)) / 2;
+        let round_trip_ms = ((returned - sent) - (replied - received)).max(0) as u64;
+
+        Self {
+            offset_ms,
+            round_trip_ms,
+            measured_at_ms: returned as u64,
+        }
+    }
+
+    /// Absolute offset
+    pub fn skew(&self) -> Duration {
+        Duration::from_millis(self.offset_ms.unsigned_abs())
+    }
+}
+
+/// How a new sample changed a peer's standing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkewChange {
+    /// The peer's offset crossed the limit
+    Exceeded,
+    /// The peer's offset is back within the limit
+    Recovered,
+}
+
+/// Latest clock sample of every peer
+#[derive(Debug, Clone, Default)]
+pub struct ClockSkewMonitor {
+    config: ClockSkewConfig,
+    samples: HashMap<NodeId, ClockSample>,
+}
+
+impl ClockSkewMonitor {
+    pub fn new(config: ClockSkewConfig) -> Self {
+        Self {
+            config,
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Record `sample` for `peer`, returning whether it crossed the limit
+    /// in either direction
+    pub fn record(&mut self, peer: NodeId, sample: ClockSample) -> Option<SkewChange> {
+        let was_skewed = self.is_skewed(&peer);
+        let skewed = sample.skew() > self.config.max_skew;
+        self.samples.insert(peer, sample);
+
+        match (was_skewed, skewed) {
+            (false, true) => Some(SkewChange::Exceeded),
+            (true, false) => Some(SkewChange::Recovered),
+            _ => None,
+        }
+    }
+
+    /// Forget a peer that left the cluster
+    pub fn remove(&mut self, peer: &NodeId) {
+        self.samples.remove(peer);
+    }
+
+    pub fn sample(&self, peer: &NodeId) -> Option<&ClockSample> {
+        self.samples.get(peer)
+    }
+
+    /// Latest sample of every peer
+    pub fn samples(&self) -> &HashMap<NodeId, ClockSample> {
+        &self.samples
+    }
+
+    /// Whether `peer`'s latest offset exceeds the limit
+    pub fn is_skewed(&self, peer: &NodeId) -> bool {
+        self.samples
+            .get(peer)
+            .is_some_and(|sample| sample.skew() > self.config.max_skew)
+    }
+
+    /// Peers whose latest offset exceeds the limit
+    pub fn skewed_peers(&self) -> Vec<NodeId> {
+        self.samples
+            .keys()
+            .filter(|peer| self.is_skewed(peer))
+            .cloned()
+            .collect()
+    }
+
+    /// Largest absolute offset to any peer
+    pub fn max_skew(&self) -> Duration {
+        self.samples
+            .values()
+            .map(ClockSample::skew)
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn limit(&self) -> Duration {
+        self.config.max_skew
+    }
+}
+
+/// Current time in milliseconds since the epoch, as stamped on heartbeats
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_from_exchange() {
+        // Peer runs 300ms ahead; 20ms each way, 5ms to answer
+        let sample = ClockSample::from_exchange(1_000, 1_320, 1_325, 1_045);
+        assert_eq!(sample.offset_ms, 300);
+        assert_eq!(sample.round_trip_ms, 40);
+        assert_eq!(sample.measured_at_ms, 1_045);
+
+        let behind = ClockSample::from_exchange(1_000, 810, 811, 1_021);
+        assert_eq!(behind.offset_ms, -200);
+        assert_eq!(behind.skew(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_skew_transitions() {
+        let mut monitor = ClockSkewMonitor::new(ClockSkewConfig {
+            max_skew: Duration::from_millis(250),
+        });
+        let peer = NodeId::new();
+        let sample = |offset_ms| ClockSample {
+            offset_ms,
+            round_trip_ms: 10,
+            measured_at_ms: 0,
+        };
+
+        assert_eq!(monitor.record(peer.clone(), sample(100)), None);
+        assert_eq!(
+            monitor.record(peer.clone(), sample(-400)),
+            Some(SkewChange::Exceeded)
+        );
+        assert_eq!(monitor.record(peer.clone(), sample(-300)), None);
+        assert_eq!(monitor.skewed_peers(), vec![peer.clone()]);
+        assert_eq!(monitor.max_skew(), Duration::from_millis(300));
+
+        assert_eq!(
+            monitor.record(peer.clone(), sample(50)),
+            Some(SkewChange::Recovered)
+        );
+        assert!(!monitor.is_skewed(&peer));
+    }
+}
@@ -1,14 +1,73 @@
 //! Cross-node communication protocols
-
+//!
+//! Messages are gzip-compressed and bounded by the configured
+//! [`TransportConfig`]. Payloads larger than one message, such as Raft
+//! snapshots or user-table syncs, are streamed through the `Transfer` RPC in
+//! chunks and reassembled and checksummed on the receiving node.
+//!
+//! `WatchClusterState` pushes the cluster state to watchers when a
+//! coordination event is published or the state changes between polls.
+//!
+//! Cluster states on the wire carry the sender's node tombstones.
+//! `SyncState` merges the caller's tombstones before answering with its own,
+//! so a removal seen by either side wins over stale membership on both.
+//!
+//! `Gossip` carries batches built by [`crate::gossip`]; the server only hands
+//! them to the gossip manager and refuses them while its queue is full, so
+//! senders back off instead of piling up requests.
+//!
+//! `GetNodeTraffic` and `GetClusterTraffic` serve the per-user traffic
+//! counters of one node and the cluster-wide totals kept by
+//! [`crate::traffic`].
+//!
+//! `RestartService` and `GetServiceStatus` let the leader drive a rolling
+//! restart through the serving node's [`ServiceController`].
+//!
+//! Every call carries the caller's W3C trace context; see
+//! [`crate::trace_context`]. Joins, leaves, forwarded writes and transfers
+//! also carry a replay protection stamp, checked by the server's
+//! [`ReplayGuard`] so that retried or replayed calls are not applied twice.
+//!
+//! `ClusterAdminService` exposes operator actions (leadership transfer,
+//! eviction, log compaction, metrics) on nodes started with
+//! [`ClusterGrpcServer::with_admin`]. It shares the listener, and therefore
+//! the mutual TLS requirement, with the peer services. Configuration reads
+//! and writes and node drains additionally need
+//! [`ClusterGrpcServer::with_config_store`] and
+//! [`ClusterGrpcServer::with_cordon`]; external tools reach them through the
+//! `vpn-cluster-client` crate.
+
+use crate::clock::{now_millis, ClockSample};
+use crate::config::{CompressionCodec, TransportConfig};
+use crate::consensus::{ConsensusEngine, ConsensusMetrics};
+use crate::coordination::CoordinationEvent;
+use crate::cordon::NodeCordon;
+use crate::distributed_storage::{ConsistencyLevel, DistributedConfigStorage};
 use crate::error::{ClusterError, Result};
+use crate::events::ClusterEventBus;
 use crate::node::{Node, NodeId};
-use crate::state::ClusterState;
+use crate::replay::{Admission, ReplayGuard, RpcStamp};
+use crate::restart::{ServiceController, ServiceStatus};
+use crate::state::{ClusterState, NodeTombstone, TombstoneReason, DEFAULT_TOMBSTONE_TTL};
+use crate::tls::ClusterTls;
+use crate::trace_context::{TraceInterceptor, TraceLayer};
+use crate::traffic::{TrafficAggregator, UserTraffic};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
-use tonic::{transport::Server, Request, Response, Status};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, RwLock};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{
+    codec::CompressionEncoding,
+    codegen::InterceptedService,
+    transport::{Channel, Endpoint, Server},
+    Request, Response, Status, Streaming,
+};
 
 // Include generated protobuf code
 pub mod cluster {
@@ -16,36 +75,170 @@ pub mod cluster {
 }
 
 use cluster::{
+    cluster_admin_service_client::ClusterAdminServiceClient,
+    cluster_admin_service_server::{ClusterAdminService, ClusterAdminServiceServer},
+    cluster_service_client::ClusterServiceClient,
     cluster_service_server::{ClusterService, ClusterServiceServer},
     consensus_service_server::{ConsensusService, ConsensusServiceServer},
     *,
 };
 
+/// A payload reassembled from a chunked transfer
+#[derive(Debug, Clone)]
+pub struct ReceivedTransfer {
+    pub transfer_id: String,
+    /// Caller-defined payload type, e.g. `snapshot` or `users`
+    pub kind: String,
+    pub from_node: NodeId,
+    pub data: Vec<u8>,
+}
+
 /// gRPC server for cluster communication
 pub struct ClusterGrpcServer {
     node_id: NodeId,
     state: Arc<RwLock<ClusterState>>,
+    consensus: Arc<dyn ConsensusEngine>,
     bind_address: SocketAddr,
+    tls: Option<Arc<ClusterTls>>,
+    transport: TransportConfig,
+    transfer_sink: Option<mpsc::Sender<ReceivedTransfer>>,
+    gossip_sink: Option<mpsc::Sender<GossipBatch>>,
+    traffic: Option<Arc<TrafficAggregator>>,
+    services: Option<Arc<dyn ServiceController>>,
+    events: Option<ClusterEventBus>,
+    watch_interval: Duration,
+    admin: bool,
+    config_store: Option<Arc<dyn DistributedConfigStorage>>,
+    cordon: Option<Arc<NodeCordon>>,
+    replay: Arc<ReplayGuard>,
 }
 
+/// How often watchers are checked for state changes not announced by an event
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
 impl ClusterGrpcServer {
+    /// Serve cluster RPCs for `node_id`, recording joining members in
+    /// `consensus`
     pub fn new(
         node_id: NodeId,
         state: Arc<RwLock<ClusterState>>,
+        consensus: Arc<dyn ConsensusEngine>,
         bind_address: SocketAddr,
     ) -> Self {
         Self {
             node_id,
             state,
+            consensus,
             bind_address,
+            tls: None,
+            transport: TransportConfig::default(),
+            transfer_sink: None,
+            gossip_sink: None,
+            traffic: None,
+            services: None,
+            events: None,
+            watch_interval: DEFAULT_WATCH_INTERVAL,
+            admin: false,
+            config_store: None,
+            cordon: None,
+            replay: Arc::new(ReplayGuard::default()),
         }
     }
 
+    /// Require mutual TLS from connecting peers
+    pub fn with_tls(mut self, tls: Arc<ClusterTls>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_transport(mut self, transport: TransportConfig) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Deliver completed chunked transfers to `sink`; transfers are refused
+    /// while no sink is set
+    pub fn with_transfer_sink(mut self, sink: mpsc::Sender<ReceivedTransfer>) -> Self {
+        self.transfer_sink = Some(sink);
+        self
+    }
+
+    /// Deliver gossip batches to `sink`, usually
+    /// [`GossipManager::inbound`](crate::gossip::GossipManager::inbound);
+    /// gossip is refused while no sink is set
+    pub fn with_gossip_sink(mut self, sink: mpsc::Sender<GossipBatch>) -> Self {
+        self.gossip_sink = Some(sink);
+        self
+    }
+
+    /// Serve traffic statistics from `traffic`; traffic RPCs are answered
+    /// as unimplemented without it
+    pub fn with_traffic(mut self, traffic: Arc<TrafficAggregator>) -> Self {
+        self.traffic = Some(traffic);
+        self
+    }
+
+    /// Restart and health-check local services through `services`; service
+    /// RPCs are answered as unimplemented without it
+    pub fn with_services(mut self, services: Arc<dyn ServiceController>) -> Self {
+        self.services = Some(services);
+        self
+    }
+
+    /// Push state to watchers as soon as an event is published on `events`
+    /// rather than on the next poll
+    pub fn with_event_bus(mut self, events: ClusterEventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn with_watch_interval(mut self, interval: Duration) -> Self {
+        self.watch_interval = interval;
+        self
+    }
+
+    /// Serve `ClusterAdminService`, acting on the server's consensus;
+    /// without it admin RPCs are answered as unimplemented
+    pub fn with_admin(mut self) -> Self {
+        self.admin = true;
+        self
+    }
+
+    /// Serve admin configuration reads and writes from `store`; they are
+    /// answered as unimplemented without it
+    pub fn with_config_store(mut self, store: Arc<dyn DistributedConfigStorage>) -> Self {
+        self.config_store = Some(store);
+        self
+    }
+
+    /// Drain nodes through `cordon`; drains are answered as unimplemented
+    /// without it
+    pub fn with_cordon(mut self, cordon: Arc<NodeCordon>) -> Self {
+        self.cordon = Some(cordon);
+        self
+    }
+
+    /// Check mutating calls with `guard` instead of one with the default
+    /// limits
+    pub fn with_replay_guard(mut self, guard: ReplayGuard) -> Self {
+        self.replay = Arc::new(guard);
+        self
+    }
+
     /// Start the gRPC server
     pub async fn start(&self) -> Result<()> {
         let cluster_service = ClusterServiceImpl {
-            _node_id: self.node_id.clone(),
+            node_id: self.node_id.clone(),
             state: self.state.clone(),
+            max_transfer_size: self.transport.max_transfer_size,
+            transfer_sink: self.transfer_sink.clone(),
+            gossip_sink: self.gossip_sink.clone(),
+            traffic: self.traffic.clone(),
+            services: self.services.clone(),
+            events: self.events.clone(),
+            watch_interval: self.watch_interval,
+            replay: self.replay.clone(),
+            consensus: self.consensus.clone(),
         };
 
         let consensus_service = ConsensusServiceImpl {
@@ -53,14 +246,59 @@ impl ClusterGrpcServer {
             state: self.state.clone(),
         };
 
-        tracing::info!("Starting gRPC server on {}", self.bind_address);
+        // Every codec is accepted so peers with other settings interoperate
+        let max_message_size = self.transport.max_message_size;
+        let mut cluster_service = ClusterServiceServer::new(cluster_service)
+            .accept_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Zstd)
+            .max_decoding_message_size(max_message_size)
+            .max_encoding_message_size(max_message_size);
+        let mut consensus_service = ConsensusServiceServer::new(consensus_service)
+            .accept_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Zstd)
+            .max_decoding_message_size(max_message_size)
+            .max_encoding_message_size(max_message_size);
+        if let Some(encoding) = compression_encoding(self.transport.compression) {
+            cluster_service = cluster_service.send_compressed(encoding);
+            consensus_service = consensus_service.send_compressed(encoding);
+        }
 
-        Server::builder()
-            .add_service(ClusterServiceServer::new(cluster_service))
-            .add_service(ConsensusServiceServer::new(consensus_service))
-            .serve(self.bind_address)
-            .await
-            .map_err(|e| ClusterError::network(format!("gRPC server error: {}", e)))?;
+        let admin_service = self.admin.then(|| {
+            ClusterAdminServiceServer::new(ClusterAdminServiceImpl {
+                node_id: self.node_id.clone(),
+                state: self.state.clone(),
+                consensus: self.consensus.clone(),
+                config_store: self.config_store.clone(),
+                cordon: self.cordon.clone(),
+                events: self.events.clone(),
+                replay: self.replay.clone(),
+            })
+            .accept_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Zstd)
+        });
+
+        let router = Server::builder()
+            .layer(TraceLayer)
+            .add_service(cluster_service)
+            .add_service(consensus_service)
+            .add_optional_service(admin_service);
+
+        let result = match &self.tls {
+            Some(tls) => {
+                tracing::info!(
+                    "Starting gRPC server with mutual TLS on {}",
+                    self.bind_address
+                );
+                let incoming = tls.clone().incoming(self.bind_address).await?;
+                router.serve_with_incoming(incoming).await
+            }
+            None => {
+                tracing::info!("Starting gRPC server on {}", self.bind_address);
+                router.serve(self.bind_address).await
+            }
+        };
+
+        result.map_err(|e| ClusterError::network(format!("gRPC server error: {}", e)))?;
 
         Ok(())
     }
@@ -69,16 +307,34 @@ impl ClusterGrpcServer {
 /// Implementation of ClusterService
 #[derive(Clone)]
 struct ClusterServiceImpl {
-    _node_id: NodeId,
+    node_id: NodeId,
     state: Arc<RwLock<ClusterState>>,
+    max_transfer_size: usize,
+    transfer_sink: Option<mpsc::Sender<ReceivedTransfer>>,
+    gossip_sink: Option<mpsc::Sender<GossipBatch>>,
+    traffic: Option<Arc<TrafficAggregator>>,
+    services: Option<Arc<dyn ServiceController>>,
+    events: Option<ClusterEventBus>,
+    watch_interval: Duration,
+    replay: Arc<ReplayGuard>,
+    consensus: Arc<dyn ConsensusEngine>,
 }
 
+type ClusterStateUpdateStream =
+    Pin<Box<dyn Stream<Item = std::result::Result<ClusterStateUpdate, Status>> + Send>>;
+
 #[tonic::async_trait]
 impl ClusterService for ClusterServiceImpl {
+    type WatchClusterStateStream = ClusterStateUpdateStream;
+
     async fn join_cluster(
         &self,
         request: Request<JoinClusterRequest>,
     ) -> std::result::Result<Response<JoinClusterResponse>, Status> {
+        let ticket = match self.replay.admit("join_cluster", request.metadata())? {
+            Admission::Replayed(response) => return replayed_response(&response),
+            Admission::Fresh(ticket) => ticket,
+        };
         let req = request.into_inner();
 
         tracing::info!(
@@ -92,65 +348,81 @@ impl ClusterService for ClusterServiceImpl {
             .ok_or_else(|| Status::invalid_argument("Missing node info"))?;
 
         let node = convert_proto_to_node(node_info)?;
+        let (node_id, address) = (node.id.clone(), node.address.to_string());
 
         // Add node to cluster state
-        let mut state = self.state.write().await;
-        match state.add_node(node) {
-            Ok(_) => {
-                let cluster_state = convert_state_to_proto(&*state);
-
-                let response = JoinClusterResponse {
+        let response = {
+            let mut state = self.state.write().await;
+            match state.add_node(node) {
+                Ok(_) => JoinClusterResponse {
                     success: true,
                     message: "Successfully joined cluster".to_string(),
-                    cluster_state: Some(cluster_state),
-                };
-
-                Ok(Response::new(response))
-            }
-            Err(e) => {
-                let response = JoinClusterResponse {
+                    cluster_state: Some(convert_state_to_proto(&state)),
+                },
+                Err(e) => JoinClusterResponse {
                     success: false,
                     message: format!("Failed to join cluster: {}", e),
                     cluster_state: None,
-                };
+                },
+            }
+        };
 
-                Ok(Response::new(response))
+        // Record the member in consensus too, or membership reconciliation
+        // would take it for a node gossip should forget
+        if response.success {
+            if let Err(e) = self.consensus.add_node(node_id.clone(), address).await {
+                tracing::warn!("Failed to add node {} to consensus: {}", node_id, e);
             }
         }
+        if let (true, Some(events)) = (response.success, &self.events) {
+            events.publish(CoordinationEvent::NodeJoined {
+                node_id,
+                timestamp: current_timestamp(),
+            });
+        }
+
+        if let Some(ticket) = ticket {
+            ticket.complete(&response);
+        }
+        Ok(Response::new(response))
     }
 
     async fn leave_cluster(
         &self,
         request: Request<LeaveClusterRequest>,
     ) -> std::result::Result<Response<LeaveClusterResponse>, Status> {
+        let ticket = match self.replay.admit("leave_cluster", request.metadata())? {
+            Admission::Replayed(response) => return replayed_response(&response),
+            Admission::Fresh(ticket) => ticket,
+        };
         let req = request.into_inner();
 
         let node_id = NodeId::from_string(&req.node_id)
             .map_err(|e| Status::invalid_argument(format!("Invalid node ID: {}", e)))?;
 
         let mut state = self.state.write().await;
-        match state.remove_node(&node_id) {
-            Ok(_) => {
-                let response = LeaveClusterResponse {
-                    success: true,
-                    message: "Successfully left cluster".to_string(),
-                };
-                Ok(Response::new(response))
-            }
-            Err(e) => {
-                let response = LeaveClusterResponse {
-                    success: false,
-                    message: format!("Failed to leave cluster: {}", e),
-                };
-                Ok(Response::new(response))
-            }
+        let response = match state.remove_node(&node_id) {
+            Ok(_) => LeaveClusterResponse {
+                success: true,
+                message: "Successfully left cluster".to_string(),
+            },
+            Err(e) => LeaveClusterResponse {
+                success: false,
+                message: format!("Failed to leave cluster: {}", e),
+            },
+        };
+
+        if let Some(ticket) = ticket {
+            ticket.complete(&response);
         }
+        Ok(Response::new(response))
     }
 
     async fn heartbeat(
         &self,
         request: Request<HeartbeatRequest>,
     ) -> std::result::Result<Response<HeartbeatResponse>, Status> {
+        let received_at_ms = now_millis();
         let req = request.into_inner();
 
         let node_id = NodeId::from_string(&req.node_id)
@@ -172,6 +444,8 @@ impl ClusterService for ClusterServiceImpl {
                 .map(|id| id.to_string())
                 .unwrap_or_default(),
             term: state.term,
+            received_at_ms,
+            replied_at_ms: now_millis(),
         };
 
         Ok(Response::new(response))
@@ -181,10 +455,16 @@ impl ClusterService for ClusterServiceImpl {
         &self,
         request: Request<SyncStateRequest>,
     ) -> std::result::Result<Response<SyncStateResponse>, Status> {
-        let _req = request.into_inner();
+        let req = request.into_inner();
+        let tombstones = req
+            .tombstones
+            .into_iter()
+            .map(convert_proto_to_tombstone)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
 
-        let state = self.state.read().await;
-        let cluster_state = convert_state_to_proto(&*state);
+        let mut state = self.state.write().await;
+        state.merge_tombstones(tombstones);
+        let cluster_state = convert_state_to_proto(&state);
 
         let response = SyncStateResponse {
             success: true,
@@ -198,6 +478,10 @@ impl ClusterService for ClusterServiceImpl {
         &self,
         request: Request<ForwardMessage>,
     ) -> std::result::Result<Response<ForwardResponse>, Status> {
+        let ticket = match self.replay.admit("forward_to_leader", request.metadata())? {
+            Admission::Replayed(response) => return replayed_response(&response),
+            Admission::Fresh(ticket) => ticket,
+        };
         let req = request.into_inner();
 
         tracing::debug!("Forwarding message type '{}' to leader", req.message_type);
@@ -210,6 +494,9 @@ impl ClusterService for ClusterServiceImpl {
             response_payload: vec![],
         };
 
+        if let Some(ticket) = ticket {
+            ticket.complete(&response);
+        }
         Ok(Response::new(response))
     }
 
@@ -220,7 +507,7 @@ impl ClusterService for ClusterServiceImpl {
         let _req = request.into_inner();
 
         let state = self.state.read().await;
-        let cluster_state = convert_state_to_proto(&*state);
+        let cluster_state = convert_state_to_proto(&state);
         let nodes: Vec<NodeInfo> = state
             .get_all_nodes()
             .into_iter()
@@ -235,6 +522,374 @@ impl ClusterService for ClusterServiceImpl {
 
         Ok(Response::new(response))
     }
+
+    async fn transfer(
+        &self,
+        request: Request<Streaming<PayloadChunk>>,
+    ) -> std::result::Result<Response<TransferResponse>, Status> {
+        let sink = self
+            .transfer_sink
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Chunked transfers are not accepted"))?;
+        let ticket = match self.replay.admit("transfer", request.metadata())? {
+            Admission::Replayed(response) => return replayed_response(&response),
+            Admission::Fresh(ticket) => ticket,
+        };
+
+        let mut stream = request.into_inner();
+        let mut assembly: Option<TransferAssembly> = None;
+        while let Some(chunk) = stream.message().await? {
+            match assembly.as_mut() {
+                Some(assembly) => assembly.push(chunk)?,
+                None => assembly = Some(TransferAssembly::start(chunk, self.max_transfer_size)?),
+            }
+        }
+
+        let transfer = assembly
+            .ok_or_else(|| Status::invalid_argument("Empty transfer"))?
+            .finish()?;
+        let received_bytes = transfer.data.len() as u64;
+
+        tracing::debug!(
+            "Received {} transfer {} ({} bytes) from {}",
+            transfer.kind,
+            transfer.transfer_id,
+            received_bytes,
+            transfer.from_node
+        );
+
+        sink.send(transfer)
+            .await
+            .map_err(|_| Status::unavailable("Transfer receiver has shut down"))?;
+
+        let response = TransferResponse {
+            success: true,
+            message: "Transfer complete".to_string(),
+            received_bytes,
+        };
+        if let Some(ticket) = ticket {
+            ticket.complete(&response);
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn gossip(
+        &self,
+        request: Request<GossipBatch>,
+    ) -> std::result::Result<Response<GossipAck>, Status> {
+        let sink = self
+            .gossip_sink
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Gossip is not accepted"))?;
+
+        let batch = request.into_inner();
+        let accepted = batch.message_count;
+        sink.try_send(batch).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => {
+                Status::resource_exhausted("Gossip queue is full")
+            }
+            mpsc::error::TrySendError::Closed(_) => {
+                Status::unavailable("Gossip receiver has shut down")
+            }
+        })?;
+
+        Ok(Response::new(GossipAck {
+            success: true,
+            accepted,
+        }))
+    }
+
+    async fn get_node_traffic(
+        &self,
+        request: Request<NodeTrafficRequest>,
+    ) -> std::result::Result<Response<NodeTrafficResponse>, Status> {
+        let traffic = self.traffic.as_ref().ok_or_else(traffic_not_served)?;
+        tracing::debug!(
+            "Node {} requested traffic statistics",
+            request.into_inner().node_id
+        );
+
+        let users = traffic
+            .local_traffic()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read traffic: {}", e)))?;
+
+        Ok(Response::new(NodeTrafficResponse {
+            node_id: self.node_id.to_string(),
+            users: convert_traffic_to_proto(users),
+        }))
+    }
+
+    async fn get_cluster_traffic(
+        &self,
+        request: Request<ClusterTrafficRequest>,
+    ) -> std::result::Result<Response<ClusterTrafficResponse>, Status> {
+        let traffic = self.traffic.as_ref().ok_or_else(traffic_not_served)?;
+        let req = request.into_inner();
+
+        let mut users = traffic
+            .cluster_traffic()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read traffic: {}", e)))?;
+        if !req.user_ids.is_empty() {
+            users.retain(|user_id, _| req.user_ids.contains(user_id));
+        }
+
+        Ok(Response::new(ClusterTrafficResponse {
+            users: convert_traffic_to_proto(users),
+        }))
+    }
+
+    async fn restart_service(
+        &self,
+        request: Request<ServiceRequest>,
+    ) -> std::result::Result<Response<RestartServiceResponse>, Status> {
+        let services = self.services.as_ref().ok_or_else(services_not_served)?;
+        let req = request.into_inner();
+        tracing::info!(
+            "Node {} requested a restart of {}",
+            req.node_id,
+            req.service
+        );
+
+        let response = match services.restart(&req.service).await {
+            Ok(()) => RestartServiceResponse {
+                success: true,
+                message: format!("{} restarted", req.service),
+            },
+            Err(e) => RestartServiceResponse {
+                success: false,
+                message: e.to_string(),
+            },
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_service_status(
+        &self,
+        request: Request<ServiceRequest>,
+    ) -> std::result::Result<Response<ServiceStatusResponse>, Status> {
+        let services = self.services.as_ref().ok_or_else(services_not_served)?;
+        let req = request.into_inner();
+
+        let status = services
+            .status(&req.service)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to check {}: {}", req.service, e)))?;
+
+        Ok(Response::new(ServiceStatusResponse {
+            healthy: status.healthy,
+            connections_known: status.active_connections.is_some(),
+            active_connections: status.active_connections.unwrap_or(0),
+        }))
+    }
+
+    async fn watch_cluster_state(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> std::result::Result<Response<Self::WatchClusterStateStream>, Status> {
+        let req = request.into_inner();
+        tracing::debug!("Node {} is watching cluster state", req.node_id);
+
+        let state = self.state.clone();
+        let mut events = self.events.as_ref().map(ClusterEventBus::stream);
+        let watch_interval = self.watch_interval;
+        let (tx, rx) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let mut sequence = 0u64;
+            let mut fingerprint = None;
+            let mut ticker = tokio::time::interval(watch_interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            // The first tick fires immediately and sends the initial snapshot
+            loop {
+                let event = tokio::select! {
+                    _ = ticker.tick() => None,
+                    event = next_event(&mut events) => match event {
+                        Some(event) => Some(event),
+                        None => {
+                            // Bus is gone; keep polling
+                            events = None;
+                            continue;
+                        }
+                    },
+                    _ = tx.closed() => break,
+                };
+
+                let (cluster_state, current) = {
+                    let state = state.read().await;
+                    (convert_state_to_proto(&state), state_fingerprint(&state))
+                };
+                if event.is_none() && fingerprint == Some(current) {
+                    continue;
+                }
+
+                let event_type = match (&event, fingerprint) {
+                    (Some(event), _) => event.kind().to_string(),
+                    (None, None) => "snapshot".to_string(),
+                    (None, Some(_)) => "state_changed".to_string(),
+                };
+                fingerprint = Some(current);
+                sequence += 1;
+
+                let update = ClusterStateUpdate {
+                    sequence,
+                    cluster_state: Some(cluster_state),
+                    event_type,
+                    event: event
+                        .and_then(|event| serde_json::to_string(&event).ok())
+                        .unwrap_or_default(),
+                    timestamp: current_timestamp(),
+                };
+                if tx.send(Ok(update)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
+
+fn traffic_not_served() -> Status {
+    Status::unimplemented("Traffic statistics are not served")
+}
+
+fn services_not_served() -> Status {
+    Status::unimplemented("Service control is not served")
+}
+
+/// Next event from the bus; never resolves without a bus
+async fn next_event(
+    events: &mut Option<crate::events::ClusterEventStream>,
+) -> Option<crate::coordination::CoordinationEvent> {
+    match events {
+        Some(events) => events.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Summary of the parts of the state watchers care about; heartbeat
+/// timestamps are left out so they do not trigger an update every tick
+fn state_fingerprint(state: &ClusterState) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.cluster_name.hash(&mut hasher);
+    state.term.hash(&mut hasher);
+    state.config_version.hash(&mut hasher);
+    state
+        .leader_id
+        .as_ref()
+        .map(NodeId::to_string)
+        .hash(&mut hasher);
+
+    let mut nodes: Vec<(String, String, String, u8)> = state
+        .get_all_nodes()
+        .into_iter()
+        .map(|node| {
+            (
+                node.id.to_string(),
+                node.status.to_string(),
+                node.role.to_string(),
+                node.health.score,
+            )
+        })
+        .collect();
+    nodes.sort();
+    nodes.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// Reassembles the chunks of one transfer, enforcing order and size limits
+struct TransferAssembly {
+    transfer_id: String,
+    kind: String,
+    from_node: NodeId,
+    total_size: usize,
+    checksum: Vec<u8>,
+    data: Vec<u8>,
+}
+
+// Errors map straight onto gRPC responses
+#[allow(clippy::result_large_err)]
+impl TransferAssembly {
+    fn start(first: PayloadChunk, max_transfer_size: usize) -> std::result::Result<Self, Status> {
+        let from_node = NodeId::from_string(&first.from_node_id)
+            .map_err(|e| Status::invalid_argument(format!("Invalid node ID: {}", e)))?;
+
+        let total_size = usize::try_from(first.total_size)
+            .ok()
+            .filter(|size| *size <= max_transfer_size)
+            .ok_or_else(|| {
+                Status::resource_exhausted(format!(
+                    "Transfer of {} bytes exceeds the {} byte limit",
+                    first.total_size, max_transfer_size
+                ))
+            })?;
+
+        if first.checksum.len() != ring::digest::SHA256_OUTPUT_LEN {
+            return Err(Status::invalid_argument("Missing transfer checksum"));
+        }
+
+        let mut assembly = Self {
+            transfer_id: first.transfer_id.clone(),
+            kind: first.kind.clone(),
+            from_node,
+            total_size,
+            checksum: first.checksum.clone(),
+            data: Vec::with_capacity(total_size),
+        };
+        assembly.push(first)?;
+        Ok(assembly)
+    }
+
+    fn push(&mut self, chunk: PayloadChunk) -> std::result::Result<(), Status> {
+        if chunk.transfer_id != self.transfer_id {
+            return Err(Status::invalid_argument(
+                "Chunk belongs to another transfer",
+            ));
+        }
+
+        if chunk.offset != self.data.len() as u64 {
+            return Err(Status::invalid_argument(format!(
+                "Chunk at offset {} does not follow {} received bytes",
+                chunk.offset,
+                self.data.len()
+            )));
+        }
+
+        if self.data.len() + chunk.data.len() > self.total_size {
+            return Err(Status::invalid_argument(
+                "Chunk extends past the announced transfer size",
+            ));
+        }
+
+        self.data.extend_from_slice(&chunk.data);
+        Ok(())
+    }
+
+    fn finish(self) -> std::result::Result<ReceivedTransfer, Status> {
+        if self.data.len() != self.total_size {
+            return Err(Status::data_loss(format!(
+                "Transfer ended after {} of {} bytes",
+                self.data.len(),
+                self.total_size
+            )));
+        }
+
+        if sha256(&self.data) != self.checksum {
+            return Err(Status::data_loss("Transfer checksum mismatch"));
+        }
+
+        Ok(ReceivedTransfer {
+            transfer_id: self.transfer_id,
+            kind: self.kind,
+            from_node: self.from_node,
+            data: self.data,
+        })
+    }
 }
 
 /// Implementation of ConsensusService
@@ -319,85 +974,449 @@ impl ConsensusService for ConsensusServiceImpl {
     }
 }
 
-/// gRPC client for communicating with other nodes
-pub struct ClusterGrpcClient {
+/// Implementation of ClusterAdminService
+#[derive(Clone)]
+struct ClusterAdminServiceImpl {
     node_id: NodeId,
+    state: Arc<RwLock<ClusterState>>,
+    consensus: Arc<dyn ConsensusEngine>,
+    config_store: Option<Arc<dyn DistributedConfigStorage>>,
+    cordon: Option<Arc<NodeCordon>>,
+    events: Option<ClusterEventBus>,
+    replay: Arc<ReplayGuard>,
 }
 
-impl ClusterGrpcClient {
-    pub fn new(node_id: NodeId) -> Self {
-        Self { node_id }
+// Errors map straight onto gRPC responses
+#[allow(clippy::result_large_err)]
+impl ClusterAdminServiceImpl {
+    fn config_store(&self) -> std::result::Result<&Arc<dyn DistributedConfigStorage>, Status> {
+        self.config_store
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Configuration access is not enabled"))
     }
+}
 
-    /// Send join cluster request to a node
-    pub async fn join_cluster(
+/// Consistency level named in a request; empty leaves it to the storage
+#[allow(clippy::result_large_err)]
+fn parse_consistency(consistency: &str) -> std::result::Result<Option<ConsistencyLevel>, Status> {
+    if consistency.is_empty() {
+        return Ok(None);
+    }
+    consistency
+        .parse()
+        .map(Some)
+        .map_err(|e: ClusterError| Status::invalid_argument(e.to_string()))
+}
+
+#[tonic::async_trait]
+impl ClusterAdminService for ClusterAdminServiceImpl {
+    async fn transfer_leadership(
         &self,
-        target_address: SocketAddr,
-        node_info: Node,
-        cluster_name: String,
-    ) -> Result<JoinClusterResponse> {
-        let mut client = cluster::cluster_service_client::ClusterServiceClient::connect(format!(
-            "http://{}",
-            target_address
-        ))
-        .await
-        .map_err(|e| ClusterError::network(format!("Failed to connect: {}", e)))?;
+        request: Request<TransferLeadershipRequest>,
+    ) -> std::result::Result<Response<AdminResponse>, Status> {
+        let req = request.into_inner();
 
-        let request = JoinClusterRequest {
-            node_info: Some(convert_node_to_proto(&node_info)),
-            cluster_name,
-            timestamp: current_timestamp(),
-        };
+        let target = NodeId::from_string(&req.target_node_id)
+            .map_err(|e| Status::invalid_argument(format!("Invalid node ID: {}", e)))?;
 
-        let response = client
-            .join_cluster(request)
-            .await
-            .map_err(|e| ClusterError::network(format!("Join cluster failed: {}", e)))?
-            .into_inner();
+        tracing::info!("Admin request to transfer leadership to {}", target);
+
+        let response = match self.consensus.transfer_leadership(target.clone()).await {
+            Ok(()) => AdminResponse {
+                success: true,
+                message: format!("Leadership transferred to {}", target),
+            },
+            Err(e) => AdminResponse {
+                success: false,
+                message: format!("Failed to transfer leadership: {}", e),
+            },
+        };
 
-        Ok(response)
+        Ok(Response::new(response))
     }
 
-    /// Send heartbeat to a node
-    pub async fn send_heartbeat(
+    async fn evict_node(
         &self,
-        target_address: SocketAddr,
-        resources: crate::node::NodeResources,
-    ) -> Result<HeartbeatResponse> {
-        let mut client = cluster::cluster_service_client::ClusterServiceClient::connect(format!(
-            "http://{}",
-            target_address
-        ))
-        .await
-        .map_err(|e| ClusterError::network(format!("Failed to connect: {}", e)))?;
+        request: Request<EvictNodeRequest>,
+    ) -> std::result::Result<Response<AdminResponse>, Status> {
+        let ticket = match self.replay.admit("evict_node", request.metadata())? {
+            Admission::Replayed(response) => return replayed_response(&response),
+            Admission::Fresh(ticket) => ticket,
+        };
+        let req = request.into_inner();
 
-        let request = HeartbeatRequest {
-            node_id: self.node_id.to_string(),
-            timestamp: current_timestamp(),
-            resources: Some(convert_resources_to_proto(&resources)),
+        let node_id = NodeId::from_string(&req.node_id)
+            .map_err(|e| Status::invalid_argument(format!("Invalid node ID: {}", e)))?;
+
+        if node_id == self.node_id {
+            return Err(Status::invalid_argument(
+                "A node cannot evict itself; transfer leadership and leave instead",
+            ));
+        }
+
+        tracing::info!("Admin request to evict node {}", node_id);
+
+        let response = match self.consensus.remove_node(node_id.clone()).await {
+            Ok(()) => {
+                // The tombstone keeps gossip from bringing the node back; it
+                // may already be gone from this node's view
+                let mut state = self.state.write().await;
+                let message = match state.tombstone_node(
+                    &node_id,
+                    TombstoneReason::Evicted,
+                    DEFAULT_TOMBSTONE_TTL,
+                ) {
+                    Ok(node) => format!("Evicted node {}", node.summary()),
+                    Err(_) => format!("Evicted node {}", node_id),
+                };
+                AdminResponse {
+                    success: true,
+                    message,
+                }
+            }
+            Err(e) => AdminResponse {
+                success: false,
+                message: format!("Failed to evict node: {}", e),
+            },
         };
 
-        let response = client
-            .heartbeat(request)
+        if let Some(ticket) = ticket {
+            ticket.complete(&response);
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn compact_log(
+        &self,
+        _request: Request<CompactLogRequest>,
+    ) -> std::result::Result<Response<CompactLogResponse>, Status> {
+        tracing::info!("Admin request to compact the consensus log");
+
+        let snapshot_index = self
+            .consensus
+            .compact()
             .await
-            .map_err(|e| ClusterError::network(format!("Heartbeat failed: {}", e)))?
-            .into_inner();
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
 
-        Ok(response)
+        Ok(Response::new(CompactLogResponse { snapshot_index }))
     }
 
-    /// Get cluster status from a node
-    pub async fn get_cluster_status(&self, target_address: SocketAddr) -> Result<StatusResponse> {
-        let mut client = cluster::cluster_service_client::ClusterServiceClient::connect(format!(
-            "http://{}",
-            target_address
-        ))
-        .await
-        .map_err(|e| ClusterError::network(format!("Failed to connect: {}", e)))?;
+    async fn get_consensus_metrics(
+        &self,
+        _request: Request<ConsensusMetricsRequest>,
+    ) -> std::result::Result<Response<ConsensusMetricsResponse>, Status> {
+        let metrics = self.consensus.get_metrics().await;
+        Ok(Response::new(convert_metrics_to_proto(
+            &self.node_id,
+            &metrics,
+        )))
+    }
 
-        let request = StatusRequest {
-            node_id: self.node_id.to_string(),
-        };
+    async fn get_config(
+        &self,
+        request: Request<GetConfigRequest>,
+    ) -> std::result::Result<Response<GetConfigResponse>, Status> {
+        let req = request.into_inner();
+        let store = self.config_store()?;
+
+        let value = match parse_consistency(&req.consistency)? {
+            Some(consistency) => {
+                store
+                    .get_config_with_consistency(&req.key, consistency)
+                    .await
+            }
+            None => store.get_config(&req.key).await,
+        }
+        .map_err(|e| Status::unavailable(format!("Failed to read config: {}", e)))?;
+
+        let response = match value {
+            Some(value) => GetConfigResponse {
+                found: true,
+                value_json: value.to_string(),
+            },
+            None => GetConfigResponse {
+                found: false,
+                value_json: String::new(),
+            },
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn set_config(
+        &self,
+        request: Request<SetConfigRequest>,
+    ) -> std::result::Result<Response<AdminResponse>, Status> {
+        let store = self.config_store()?;
+        let ticket = match self.replay.admit("set_config", request.metadata())? {
+            Admission::Replayed(response) => return replayed_response(&response),
+            Admission::Fresh(ticket) => ticket,
+        };
+        let req = request.into_inner();
+
+        let consistency = parse_consistency(&req.consistency)?;
+        let value: serde_json::Value = serde_json::from_str(&req.value_json)
+            .map_err(|e| Status::invalid_argument(format!("Invalid config value: {}", e)))?;
+
+        tracing::info!("Admin request to set config key {}", req.key);
+
+        let result = match consistency {
+            Some(consistency) => {
+                store
+                    .store_config_with_consistency(&req.key, value, consistency)
+                    .await
+            }
+            None => store.store_config(&req.key, value).await,
+        };
+        let response = match result {
+            Ok(()) => {
+                if let Some(events) = &self.events {
+                    events.publish(CoordinationEvent::ConfigurationChanged {
+                        key: req.key.clone(),
+                        timestamp: current_timestamp(),
+                    });
+                }
+                AdminResponse {
+                    success: true,
+                    message: format!("Set config key {}", req.key),
+                }
+            }
+            Err(e) => AdminResponse {
+                success: false,
+                message: format!("Failed to set config: {}", e),
+            },
+        };
+
+        if let Some(ticket) = ticket {
+            ticket.complete(&response);
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn drain_node(
+        &self,
+        request: Request<DrainNodeRequest>,
+    ) -> std::result::Result<Response<AdminResponse>, Status> {
+        let cordon = self
+            .cordon
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Node drains are not enabled"))?;
+        let ticket = match self.replay.admit("drain_node", request.metadata())? {
+            Admission::Replayed(response) => return replayed_response(&response),
+            Admission::Fresh(ticket) => ticket,
+        };
+        let req = request.into_inner();
+
+        let node_id = NodeId::from_string(&req.node_id)
+            .map_err(|e| Status::invalid_argument(format!("Invalid node ID: {}", e)))?;
+
+        let response = if req.drain {
+            tracing::info!("Admin request to drain node {}", node_id);
+            match cordon.cordon(&node_id).await {
+                Ok(()) => AdminResponse {
+                    success: true,
+                    message: format!("Draining node {}", node_id),
+                },
+                Err(e) => AdminResponse {
+                    success: false,
+                    message: format!("Failed to drain node: {}", e),
+                },
+            }
+        } else {
+            tracing::info!("Admin request to return node {} to service", node_id);
+            match cordon.uncordon(&node_id).await {
+                Ok(()) => AdminResponse {
+                    success: true,
+                    message: format!("Returned node {} to service", node_id),
+                },
+                Err(e) => AdminResponse {
+                    success: false,
+                    message: format!("Failed to return node to service: {}", e),
+                },
+            }
+        };
+
+        if let Some(ticket) = ticket {
+            ticket.complete(&response);
+        }
+        Ok(Response::new(response))
+    }
+}
+
+/// Channel whose calls carry the caller's trace context
+type TracedChannel = InterceptedService<Channel, TraceInterceptor>;
+
+/// gRPC client for communicating with other nodes
+#[derive(Clone)]
+pub struct ClusterGrpcClient {
+    node_id: NodeId,
+    tls: Option<Arc<ClusterTls>>,
+    transport: TransportConfig,
+}
+
+impl ClusterGrpcClient {
+    pub fn new(node_id: NodeId) -> Self {
+        Self {
+            node_id,
+            tls: None,
+            transport: TransportConfig::default(),
+        }
+    }
+
+    /// Connect to peers over mutual TLS
+    pub fn with_tls(mut self, tls: Arc<ClusterTls>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn with_transport(mut self, transport: TransportConfig) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Open a channel to a node, using the current certificates if TLS is enabled
+    async fn channel(&self, target_address: SocketAddr) -> Result<Channel> {
+        let endpoint = match &self.tls {
+            Some(tls) => Endpoint::from_shared(format!("https://{}", target_address))
+                .and_then(|endpoint| endpoint.tls_config(tls.client_config())),
+            None => Endpoint::from_shared(format!("http://{}", target_address)),
+        }
+        .map_err(|e| ClusterError::network(format!("Invalid endpoint: {}", e)))?;
+
+        endpoint
+            .connect()
+            .await
+            .map_err(|e| ClusterError::network(format!("Failed to connect: {}", e)))
+    }
+
+    async fn connect(
+        &self,
+        target_address: SocketAddr,
+    ) -> Result<ClusterServiceClient<TracedChannel>> {
+        let channel = self.channel(target_address).await?;
+
+        let mut client = ClusterServiceClient::with_interceptor(channel, TraceInterceptor)
+            .accept_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Zstd)
+            .max_decoding_message_size(self.transport.max_message_size)
+            .max_encoding_message_size(self.transport.max_message_size);
+        if let Some(encoding) = compression_encoding(self.transport.compression) {
+            client = client.send_compressed(encoding);
+        }
+
+        Ok(client)
+    }
+
+    /// Send join cluster request to a node
+    pub async fn join_cluster(
+        &self,
+        target_address: SocketAddr,
+        node_info: Node,
+        cluster_name: String,
+    ) -> Result<JoinClusterResponse> {
+        let mut client = self.connect(target_address).await?;
+
+        // Retries of the same join share a key, so a node that applied the
+        // first attempt answers them without adding the node again
+        let key = format!("join/{}/{}", node_info.id, node_info.incarnation);
+        let request = RpcStamp::new(&self.node_id, Some(key)).request(JoinClusterRequest {
+            node_info: Some(convert_node_to_proto(&node_info)),
+            cluster_name,
+            timestamp: current_timestamp(),
+        });
+
+        let response = client
+            .join_cluster(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Join cluster failed: {}", e)))?
+            .into_inner();
+
+        Ok(response)
+    }
+
+    /// Send heartbeat to a node, returning its answer and the clock offset
+    /// measured with it; no offset from nodes that do not stamp replies
+    pub async fn send_heartbeat(
+        &self,
+        target_address: SocketAddr,
+        resources: crate::node::NodeResources,
+    ) -> Result<(HeartbeatResponse, Option<ClockSample>)> {
+        let mut client = self.connect(target_address).await?;
+
+        let sent_at_ms = now_millis();
+        let request = HeartbeatRequest {
+            node_id: self.node_id.to_string(),
+            timestamp: current_timestamp(),
+            resources: Some(convert_resources_to_proto(&resources)),
+            sent_at_ms,
+        };
+
+        let response = client
+            .heartbeat(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Heartbeat failed: {}", e)))?
+            .into_inner();
+        let sample = (response.received_at_ms != 0).then(|| {
+            ClockSample::from_exchange(
+                sent_at_ms,
+                response.received_at_ms,
+                response.replied_at_ms,
+                now_millis(),
+            )
+        });
+
+        Ok((response, sample))
+    }
+
+    /// Exchange tombstones with a node: it merges the tombstones of `state`
+    /// and the ones in its answer are merged into `state`
+    pub async fn sync_state(
+        &self,
+        target_address: SocketAddr,
+        state: &RwLock<ClusterState>,
+    ) -> Result<SyncStateResponse> {
+        let mut client = self.connect(target_address).await?;
+
+        let request = {
+            let state = state.read().await;
+            SyncStateRequest {
+                node_id: self.node_id.to_string(),
+                last_known_version: state.config_version,
+                tombstones: state
+                    .tombstones
+                    .values()
+                    .map(convert_tombstone_to_proto)
+                    .collect(),
+            }
+        };
+
+        let response = client
+            .sync_state(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Sync state failed: {}", e)))?
+            .into_inner();
+
+        if let Some(remote) = &response.cluster_state {
+            let tombstones = remote
+                .tombstones
+                .iter()
+                .cloned()
+                .map(convert_proto_to_tombstone)
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| ClusterError::network(e.message().to_string()))?;
+            state.write().await.merge_tombstones(tombstones);
+        }
+
+        Ok(response)
+    }
+
+    /// Get cluster status from a node
+    pub async fn get_cluster_status(&self, target_address: SocketAddr) -> Result<StatusResponse> {
+        let mut client = self.connect(target_address).await?;
+
+        let request = StatusRequest {
+            node_id: self.node_id.to_string(),
+        };
 
         let response = client
             .get_cluster_status(request)
@@ -407,6 +1426,364 @@ impl ClusterGrpcClient {
 
         Ok(response)
     }
+
+    /// Follow cluster state changes on a node; the first update is the
+    /// current state
+    pub async fn watch_cluster_state(
+        &self,
+        target_address: SocketAddr,
+    ) -> Result<Streaming<ClusterStateUpdate>> {
+        let mut client = self.connect(target_address).await?;
+
+        let request = WatchRequest {
+            node_id: self.node_id.to_string(),
+        };
+
+        let stream = client
+            .watch_cluster_state(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Watch cluster state failed: {}", e)))?
+            .into_inner();
+
+        Ok(stream)
+    }
+
+    async fn connect_admin(
+        &self,
+        target_address: SocketAddr,
+    ) -> Result<ClusterAdminServiceClient<TracedChannel>> {
+        let channel = self.channel(target_address).await?;
+        Ok(
+            ClusterAdminServiceClient::with_interceptor(channel, TraceInterceptor)
+                .accept_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Zstd),
+        )
+    }
+
+    /// Ask the leader at `target_address` to hand leadership to `target`
+    pub async fn transfer_leadership(
+        &self,
+        target_address: SocketAddr,
+        target: &NodeId,
+    ) -> Result<AdminResponse> {
+        let mut client = self.connect_admin(target_address).await?;
+
+        let request = TransferLeadershipRequest {
+            target_node_id: target.to_string(),
+        };
+
+        let response = client
+            .transfer_leadership(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Transfer leadership failed: {}", e)))?
+            .into_inner();
+
+        Ok(response)
+    }
+
+    /// Ask the leader at `target_address` to evict `node_id`
+    pub async fn evict_node(
+        &self,
+        target_address: SocketAddr,
+        node_id: &NodeId,
+    ) -> Result<AdminResponse> {
+        let mut client = self.connect_admin(target_address).await?;
+
+        let request = RpcStamp::new(&self.node_id, None).request(EvictNodeRequest {
+            node_id: node_id.to_string(),
+        });
+
+        let response = client
+            .evict_node(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Evict node failed: {}", e)))?
+            .into_inner();
+
+        Ok(response)
+    }
+
+    /// Compact the consensus log of the node at `target_address`, returning
+    /// the index the log now starts after
+    pub async fn compact_log(&self, target_address: SocketAddr) -> Result<u64> {
+        let mut client = self.connect_admin(target_address).await?;
+
+        let response = client
+            .compact_log(CompactLogRequest {})
+            .await
+            .map_err(|e| ClusterError::network(format!("Compact log failed: {}", e)))?
+            .into_inner();
+
+        Ok(response.snapshot_index)
+    }
+
+    /// Consensus metrics of the node at `target_address`
+    pub async fn consensus_metrics(
+        &self,
+        target_address: SocketAddr,
+    ) -> Result<ConsensusMetricsResponse> {
+        let mut client = self.connect_admin(target_address).await?;
+
+        let response = client
+            .get_consensus_metrics(ConsensusMetricsRequest {})
+            .await
+            .map_err(|e| ClusterError::network(format!("Get consensus metrics failed: {}", e)))?
+            .into_inner();
+
+        Ok(response)
+    }
+
+    /// Read `key` from the node at `target_address`
+    pub async fn get_config(
+        &self,
+        target_address: SocketAddr,
+        key: &str,
+        consistency: Option<ConsistencyLevel>,
+    ) -> Result<Option<serde_json::Value>> {
+        let mut client = self.connect_admin(target_address).await?;
+
+        let request = GetConfigRequest {
+            key: key.to_string(),
+            consistency: consistency.map(|c| c.to_string()).unwrap_or_default(),
+        };
+
+        let response = client
+            .get_config(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Get config failed: {}", e)))?
+            .into_inner();
+
+        if !response.found {
+            return Ok(None);
+        }
+        serde_json::from_str(&response.value_json)
+            .map(Some)
+            .map_err(|e| ClusterError::network(format!("Invalid config value: {}", e)))
+    }
+
+    /// Write `key` through the node at `target_address`
+    pub async fn set_config(
+        &self,
+        target_address: SocketAddr,
+        key: &str,
+        value: &serde_json::Value,
+        consistency: Option<ConsistencyLevel>,
+    ) -> Result<AdminResponse> {
+        let mut client = self.connect_admin(target_address).await?;
+
+        let request = RpcStamp::new(&self.node_id, None).request(SetConfigRequest {
+            key: key.to_string(),
+            value_json: value.to_string(),
+            consistency: consistency.map(|c| c.to_string()).unwrap_or_default(),
+        });
+
+        let response = client
+            .set_config(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Set config failed: {}", e)))?
+            .into_inner();
+
+        Ok(response)
+    }
+
+    /// Drain `node_id` through the node at `target_address`, or return it
+    /// to service when `drain` is false
+    pub async fn drain_node(
+        &self,
+        target_address: SocketAddr,
+        node_id: &NodeId,
+        drain: bool,
+    ) -> Result<AdminResponse> {
+        let mut client = self.connect_admin(target_address).await?;
+
+        let request = RpcStamp::new(&self.node_id, None).request(DrainNodeRequest {
+            node_id: node_id.to_string(),
+            drain,
+        });
+
+        let response = client
+            .drain_node(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Drain node failed: {}", e)))?
+            .into_inner();
+
+        Ok(response)
+    }
+
+    /// Send a gossip batch to a node
+    pub async fn gossip(
+        &self,
+        target_address: SocketAddr,
+        batch: GossipBatch,
+    ) -> Result<GossipAck> {
+        let mut client = self.connect(target_address).await?;
+
+        let response = client
+            .gossip(batch)
+            .await
+            .map_err(|e| ClusterError::network(format!("Gossip failed: {}", e)))?
+            .into_inner();
+
+        Ok(response)
+    }
+
+    /// Per-user traffic counters of a node
+    pub async fn get_node_traffic(
+        &self,
+        target_address: SocketAddr,
+    ) -> Result<HashMap<String, UserTraffic>> {
+        let mut client = self.connect(target_address).await?;
+
+        let request = NodeTrafficRequest {
+            node_id: self.node_id.to_string(),
+        };
+
+        let response = client
+            .get_node_traffic(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Traffic request failed: {}", e)))?
+            .into_inner();
+
+        Ok(convert_proto_to_traffic(response.users))
+    }
+
+    /// Cluster-wide traffic totals known to a node; all users when
+    /// `user_ids` is empty
+    pub async fn get_cluster_traffic(
+        &self,
+        target_address: SocketAddr,
+        user_ids: Vec<String>,
+    ) -> Result<HashMap<String, UserTraffic>> {
+        let mut client = self.connect(target_address).await?;
+
+        let response = client
+            .get_cluster_traffic(ClusterTrafficRequest { user_ids })
+            .await
+            .map_err(|e| ClusterError::network(format!("Traffic request failed: {}", e)))?
+            .into_inner();
+
+        Ok(convert_proto_to_traffic(response.users))
+    }
+
+    /// Restart `service` on a node
+    pub async fn restart_service(&self, target_address: SocketAddr, service: &str) -> Result<()> {
+        let mut client = self.connect(target_address).await?;
+
+        let request = ServiceRequest {
+            node_id: self.node_id.to_string(),
+            service: service.to_string(),
+        };
+
+        let response = client
+            .restart_service(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Restart request failed: {}", e)))?
+            .into_inner();
+
+        if !response.success {
+            return Err(ClusterError::coordination(response.message));
+        }
+
+        Ok(())
+    }
+
+    /// Health of `service` on a node
+    pub async fn get_service_status(
+        &self,
+        target_address: SocketAddr,
+        service: &str,
+    ) -> Result<ServiceStatus> {
+        let mut client = self.connect(target_address).await?;
+
+        let request = ServiceRequest {
+            node_id: self.node_id.to_string(),
+            service: service.to_string(),
+        };
+
+        let response = client
+            .get_service_status(request)
+            .await
+            .map_err(|e| ClusterError::network(format!("Service status request failed: {}", e)))?
+            .into_inner();
+
+        Ok(ServiceStatus {
+            healthy: response.healthy,
+            active_connections: response
+                .connections_known
+                .then_some(response.active_connections),
+        })
+    }
+
+    /// Stream a payload of any size to a node in `chunk_size` pieces
+    pub async fn send_payload(
+        &self,
+        target_address: SocketAddr,
+        kind: &str,
+        data: &[u8],
+    ) -> Result<TransferResponse> {
+        let mut client = self.connect(target_address).await?;
+
+        let transfer_id = uuid::Uuid::new_v4().to_string();
+        let checksum = sha256(data);
+        let chunk_size = self.transport.chunk_size.max(1);
+
+        // An empty payload still needs one chunk to carry the metadata
+        let pieces: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(chunk_size).collect()
+        };
+
+        let chunks: Vec<PayloadChunk> = pieces
+            .into_iter()
+            .enumerate()
+            .map(|(index, piece)| PayloadChunk {
+                transfer_id: transfer_id.clone(),
+                kind: kind.to_string(),
+                from_node_id: self.node_id.to_string(),
+                offset: (index * chunk_size) as u64,
+                total_size: data.len() as u64,
+                data: piece.to_vec(),
+                checksum: if index == 0 {
+                    checksum.clone()
+                } else {
+                    Vec::new()
+                },
+            })
+            .collect();
+
+        let stamp = RpcStamp::new(&self.node_id, Some(transfer_id));
+        let response = client
+            .transfer(stamp.request(tokio_stream::iter(chunks)))
+            .await
+            .map_err(|e| ClusterError::network(format!("Transfer failed: {}", e)))?
+            .into_inner();
+
+        Ok(response)
+    }
+}
+
+/// Answer a retried call with the response recorded for its first attempt
+#[allow(clippy::result_large_err)]
+fn replayed_response<M: prost::Message + Default>(
+    response: &[u8],
+) -> std::result::Result<Response<M>, Status> {
+    M::decode(response)
+        .map(Response::new)
+        .map_err(|e| Status::internal(format!("Corrupt recorded response: {}", e)))
+}
+
+fn compression_encoding(codec: CompressionCodec) -> Option<CompressionEncoding> {
+    match codec {
+        CompressionCodec::None => None,
+        CompressionCodec::Gzip => Some(CompressionEncoding::Gzip),
+        CompressionCodec::Zstd => Some(CompressionEncoding::Zstd),
+    }
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, data)
+        .as_ref()
+        .to_vec()
 }
 
 // Helper functions for converting between protobuf and internal types
@@ -424,9 +1801,14 @@ fn convert_node_to_proto(node: &Node) -> NodeInfo {
         version: node.version.clone(),
         region: node.region.clone().unwrap_or_default(),
         resources: Some(convert_resources_to_proto(&node.resources)),
+        incarnation: node.incarnation,
+        zone: node.zone.clone().unwrap_or_default(),
+        cordoned: node.cordoned,
     }
 }
 
+// Errors map straight onto gRPC responses
+#[allow(clippy::result_large_err)]
 fn convert_proto_to_node(proto: NodeInfo) -> std::result::Result<Node, Status> {
     let node_id = NodeId::from_string(&proto.node_id)
         .map_err(|e| Status::invalid_argument(format!("Invalid node ID: {}", e)))?;
@@ -441,9 +1823,14 @@ fn convert_proto_to_node(proto: NodeInfo) -> std::result::Result<Node, Status> {
     node.last_seen = proto.last_seen;
     node.metadata = proto.metadata;
     node.version = proto.version;
+    node.incarnation = proto.incarnation;
+    node.cordoned = proto.cordoned;
     if !proto.region.is_empty() {
         node.region = Some(proto.region);
     }
+    if !proto.zone.is_empty() {
+        node.zone = Some(proto.zone);
+    }
 
     if let Some(resources) = proto.resources {
         node.resources = convert_proto_to_resources(resources);
@@ -452,6 +1839,40 @@ fn convert_proto_to_node(proto: NodeInfo) -> std::result::Result<Node, Status> {
     Ok(node)
 }
 
+fn convert_traffic_to_proto(
+    users: HashMap<String, UserTraffic>,
+) -> HashMap<String, UserTrafficStats> {
+    users
+        .into_iter()
+        .map(|(user_id, traffic)| {
+            let stats = UserTrafficStats {
+                bytes_sent: traffic.bytes_sent,
+                bytes_received: traffic.bytes_received,
+                connections: traffic.connections,
+                last_activity: traffic.last_activity.unwrap_or(0),
+            };
+            (user_id, stats)
+        })
+        .collect()
+}
+
+fn convert_proto_to_traffic(
+    users: HashMap<String, UserTrafficStats>,
+) -> HashMap<String, UserTraffic> {
+    users
+        .into_iter()
+        .map(|(user_id, stats)| {
+            let traffic = UserTraffic {
+                bytes_sent: stats.bytes_sent,
+                bytes_received: stats.bytes_received,
+                connections: stats.connections,
+                last_activity: (stats.last_activity > 0).then_some(stats.last_activity),
+            };
+            (user_id, traffic)
+        })
+        .collect()
+}
+
 fn convert_resources_to_proto(resources: &crate::node::NodeResources) -> NodeResources {
     NodeResources {
         cpu_cores: resources.cpu_cores,
@@ -504,6 +1925,77 @@ fn convert_state_to_proto(state: &ClusterState) -> cluster::ClusterState {
         last_updated: state.last_updated,
         created_at: state.created_at,
         metadata: state.metadata.clone(),
+        tombstones: state
+            .tombstones
+            .values()
+            .map(convert_tombstone_to_proto)
+            .collect(),
+    }
+}
+
+fn convert_tombstone_to_proto(tombstone: &NodeTombstone) -> cluster::NodeTombstone {
+    let reason = match tombstone.reason {
+        TombstoneReason::Left => "left",
+        TombstoneReason::Failed => "failed",
+        TombstoneReason::Evicted => "evicted",
+    };
+
+    cluster::NodeTombstone {
+        node_id: tombstone.node_id.to_string(),
+        incarnation: tombstone.incarnation,
+        reason: reason.to_string(),
+        created_at: tombstone.created_at,
+        expires_at: tombstone.expires_at,
+    }
+}
+
+// Errors map straight onto gRPC responses
+#[allow(clippy::result_large_err)]
+fn convert_proto_to_tombstone(
+    proto: cluster::NodeTombstone,
+) -> std::result::Result<NodeTombstone, Status> {
+    let node_id = NodeId::from_string(&proto.node_id)
+        .map_err(|e| Status::invalid_argument(format!("Invalid node ID: {}", e)))?;
+
+    let reason = match proto.reason.as_str() {
+        "left" => TombstoneReason::Left,
+        "failed" => TombstoneReason::Failed,
+        "evicted" => TombstoneReason::Evicted,
+        other => {
+            return Err(Status::invalid_argument(format!(
+                "Invalid tombstone reason: {}",
+                other
+            )))
+        }
+    };
+
+    Ok(NodeTombstone {
+        node_id,
+        incarnation: proto.incarnation,
+        reason,
+        created_at: proto.created_at,
+        expires_at: proto.expires_at,
+    })
+}
+
+fn convert_metrics_to_proto(
+    node_id: &NodeId,
+    metrics: &ConsensusMetrics,
+) -> ConsensusMetricsResponse {
+    ConsensusMetricsResponse {
+        node_id: node_id.to_string(),
+        current_term: metrics.current_term,
+        last_log_index: metrics.last_log_index,
+        commit_index: metrics.commit_index,
+        leader_id: metrics
+            .leader_id
+            .as_ref()
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        cluster_size: metrics.cluster_size as u64,
+        is_leader: metrics.is_leader,
+        election_elapsed_ms: metrics.election_elapsed.as_millis() as u64,
+        heartbeat_elapsed_ms: metrics.heartbeat_elapsed.as_millis() as u64,
     }
 }
 
@@ -518,8 +2010,13 @@ fn current_timestamp() -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::consensus::SimpleConsensus;
     use std::net::SocketAddr;
 
+    fn consensus(node_id: &NodeId) -> Arc<dyn ConsensusEngine> {
+        Arc::new(SimpleConsensus::new(node_id.clone()))
+    }
+
     #[test]
     fn test_node_conversion() {
         let address: SocketAddr = "127.0.0.1:8080".parse().unwrap();
@@ -559,4 +2056,446 @@ mod tests {
         let client = ClusterGrpcClient::new(node_id);
         assert!(!client.node_id.to_string().is_empty());
     }
+
+    #[tokio::test]
+    async fn test_mutual_tls_rejects_foreign_cluster() {
+        let ca = crate::tls::tests::TestCa::new();
+        ca.issue("server", "vpn-cluster");
+        ca.issue("member", "vpn-cluster");
+        ca.issue("outsider", "other-cluster");
+
+        let load = |file: &str| Arc::new(ClusterTls::load(ca.config(file), "vpn-cluster").unwrap());
+
+        // Reserve a free port for the server
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::with_cluster_name(
+            node_id.clone(),
+            "vpn-cluster".to_string(),
+        )));
+        let server = ClusterGrpcServer::new(node_id.clone(), state, consensus(&node_id), address)
+            .with_tls(load("server"));
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let member = ClusterGrpcClient::new(NodeId::new()).with_tls(load("member"));
+        let status = member.get_cluster_status(address).await.unwrap();
+        assert_eq!(status.cluster_state.unwrap().cluster_name, "vpn-cluster");
+
+        let outsider = ClusterGrpcClient::new(NodeId::new()).with_tls(load("outsider"));
+        assert!(outsider.get_cluster_status(address).await.is_err());
+
+        let plaintext = ClusterGrpcClient::new(NodeId::new());
+        assert!(plaintext.get_cluster_status(address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replayed_join_not_applied_twice() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::with_cluster_name(
+            node_id.clone(),
+            "vpn-cluster".to_string(),
+        )));
+        // Joins reach consensus without the admin service
+        let members = consensus(&node_id);
+        members.elect_leader().await.unwrap();
+        let server = ClusterGrpcServer::new(node_id, state.clone(), members.clone(), address);
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let mut client = ClusterServiceClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        let joining = Node::new("joining".to_string(), "127.0.0.1:9400".parse().unwrap());
+        let request = JoinClusterRequest {
+            node_info: Some(convert_node_to_proto(&joining)),
+            cluster_name: "vpn-cluster".to_string(),
+            timestamp: current_timestamp(),
+        };
+
+        // Calls without a stamp are refused
+        assert!(client.join_cluster(request.clone()).await.is_err());
+
+        let stamp = RpcStamp::new(&joining.id, Some("join-1".to_string()));
+        let first = client
+            .join_cluster(stamp.request(request.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(first.success);
+        let committed = members.committed_members().await.unwrap();
+        assert!(committed.contains(&joining.id));
+
+        // The node leaves; replaying the captured join must not bring it back
+        state.write().await.remove_node(&joining.id).unwrap();
+        let replay = client.join_cluster(stamp.request(request.clone())).await;
+        assert_eq!(replay.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+        // A retry of the same join is answered from the first attempt
+        let retry = RpcStamp::new(&joining.id, Some("join-1".to_string()));
+        let answer = client
+            .join_cluster(retry.request(request))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(answer, first);
+        assert!(!state.read().await.nodes.contains_key(&joining.id));
+    }
+
+    fn chunk(offset: u64, data: &[u8], payload: &[u8]) -> PayloadChunk {
+        PayloadChunk {
+            transfer_id: "transfer".to_string(),
+            kind: "snapshot".to_string(),
+            from_node_id: NodeId::new().to_string(),
+            offset,
+            total_size: payload.len() as u64,
+            data: data.to_vec(),
+            checksum: sha256(payload),
+        }
+    }
+
+    #[test]
+    fn test_transfer_assembly_validation() {
+        let payload = b"snapshot-bytes".to_vec();
+
+        let mut assembly =
+            TransferAssembly::start(chunk(0, &payload[..8], &payload), 1024).unwrap();
+        // Gaps and overlaps are rejected
+        assert!(assembly.push(chunk(4, &payload[4..], &payload)).is_err());
+        assembly.push(chunk(8, &payload[8..], &payload)).unwrap();
+        assert_eq!(assembly.finish().unwrap().data, payload);
+
+        // Truncated transfers fail the size check
+        let assembly = TransferAssembly::start(chunk(0, &payload[..8], &payload), 1024).unwrap();
+        assert!(assembly.finish().is_err());
+
+        // Corrupted data fails the checksum
+        let mut corrupted = chunk(0, &payload, &payload);
+        corrupted.data[0] ^= 0xff;
+        let assembly = TransferAssembly::start(corrupted, 1024).unwrap();
+        assert!(assembly.finish().is_err());
+
+        // Oversized transfers are refused up front
+        assert!(TransferAssembly::start(chunk(0, &payload, &payload), 4).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chunked_transfer_exceeds_message_limit() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let transport = TransportConfig {
+            max_message_size: 64 * 1024,
+            chunk_size: 16 * 1024,
+            ..TransportConfig::default()
+        };
+
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        let (sink, mut received) = mpsc::channel(1);
+        let server = ClusterGrpcServer::new(node_id.clone(), state, consensus(&node_id), address)
+            .with_transport(transport.clone())
+            .with_transfer_sink(sink);
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        // Several times the message limit
+        let payload: Vec<u8> = (0..300 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let sender = NodeId::new();
+        let client = ClusterGrpcClient::new(sender.clone()).with_transport(transport.clone());
+
+        let response = client
+            .send_payload(address, "snapshot", &payload)
+            .await
+            .unwrap();
+        assert!(response.success);
+        assert_eq!(response.received_bytes, payload.len() as u64);
+
+        let transfer = received.recv().await.unwrap();
+        assert_eq!(transfer.kind, "snapshot");
+        assert_eq!(transfer.from_node, sender);
+        assert_eq!(transfer.data, payload);
+
+        // A peer compressing with zstd is understood by a gzip node
+        let client = ClusterGrpcClient::new(sender).with_transport(TransportConfig {
+            compression: CompressionCodec::Zstd,
+            ..transport
+        });
+        let response = client
+            .send_payload(address, "users", &payload)
+            .await
+            .unwrap();
+        assert!(response.success);
+        assert_eq!(received.recv().await.unwrap().data, payload);
+    }
+
+    #[tokio::test]
+    async fn test_sync_state_exchanges_tombstones() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // The server still lists a node the client saw leave
+        let departed = Node::new("departed".to_string(), "127.0.0.1:8001".parse().unwrap());
+        let node_id = NodeId::new();
+        let server_state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        server_state
+            .write()
+            .await
+            .add_node(departed.clone())
+            .unwrap();
+
+        // ...and has reaped a node the client still lists
+        let failed = Node::new("failed".to_string(), "127.0.0.1:8002".parse().unwrap());
+        server_state.write().await.add_node(failed.clone()).unwrap();
+        server_state
+            .write()
+            .await
+            .tombstone_node(&failed.id, TombstoneReason::Failed, DEFAULT_TOMBSTONE_TTL)
+            .unwrap();
+
+        let server = ClusterGrpcServer::new(
+            node_id.clone(),
+            server_state.clone(),
+            consensus(&node_id),
+            address,
+        );
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let client_state = RwLock::new(ClusterState::new(NodeId::new()));
+        {
+            let mut state = client_state.write().await;
+            state.add_node(departed.clone()).unwrap();
+            state.remove_node(&departed.id).unwrap();
+            state.add_node(failed.clone()).unwrap();
+        }
+
+        let client = ClusterGrpcClient::new(NodeId::new());
+        let response = client.sync_state(address, &client_state).await.unwrap();
+        assert!(response.success);
+        assert_eq!(response.cluster_state.unwrap().tombstones.len(), 2);
+
+        let server_state = server_state.read().await;
+        assert!(server_state.get_node(&departed.id).is_none());
+        assert_eq!(
+            server_state.get_tombstone(&departed.id).unwrap().reason,
+            TombstoneReason::Left
+        );
+
+        let client_state = client_state.read().await;
+        assert!(client_state.get_node(&failed.id).is_none());
+        assert_eq!(
+            client_state.get_tombstone(&failed.id).unwrap().reason,
+            TombstoneReason::Failed
+        );
+    }
+
+    #[test]
+    fn test_tombstone_conversion() {
+        let tombstone = NodeTombstone {
+            node_id: NodeId::new(),
+            incarnation: 3,
+            reason: TombstoneReason::Evicted,
+            created_at: 100,
+            expires_at: 200,
+        };
+
+        let converted = convert_proto_to_tombstone(convert_tombstone_to_proto(&tombstone)).unwrap();
+        assert_eq!(converted.node_id, tombstone.node_id);
+        assert_eq!(converted.incarnation, 3);
+        assert_eq!(converted.reason, TombstoneReason::Evicted);
+        assert_eq!(converted.expires_at, 200);
+
+        let mut invalid = convert_tombstone_to_proto(&tombstone);
+        invalid.reason = "vanished".to_string();
+        assert!(convert_proto_to_tombstone(invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_watch_cluster_state() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        let events = ClusterEventBus::default();
+        let server =
+            ClusterGrpcServer::new(node_id.clone(), state.clone(), consensus(&node_id), address)
+                .with_event_bus(events.clone())
+                .with_watch_interval(std::time::Duration::from_millis(50));
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let client = ClusterGrpcClient::new(NodeId::new());
+        let mut updates = client.watch_cluster_state(address).await.unwrap();
+
+        let snapshot = updates.message().await.unwrap().unwrap();
+        assert_eq!(snapshot.sequence, 1);
+        assert_eq!(snapshot.event_type, "snapshot");
+        assert!(snapshot.cluster_state.unwrap().nodes.is_empty());
+
+        // Changes are picked up without an event
+        let node = Node::new("joined".to_string(), "127.0.0.1:8001".parse().unwrap());
+        state.write().await.add_node(node).unwrap();
+        let changed = updates.message().await.unwrap().unwrap();
+        assert_eq!(changed.event_type, "state_changed");
+        assert_eq!(changed.cluster_state.unwrap().nodes.len(), 1);
+
+        // Events are pushed immediately, with the event attached
+        events.publish(
+            crate::coordination::CoordinationEvent::ConfigurationChanged {
+                key: "routing".to_string(),
+                timestamp: 0,
+            },
+        );
+        let pushed = updates.message().await.unwrap().unwrap();
+        assert_eq!(pushed.sequence, 3);
+        assert_eq!(pushed.event_type, "configuration_changed");
+        assert!(pushed.event.contains("routing"));
+    }
+
+    #[tokio::test]
+    async fn test_admin_service() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        let consensus = Arc::new(SimpleConsensus::new(node_id.clone()));
+        let server =
+            ClusterGrpcServer::new(node_id.clone(), state.clone(), consensus, address).with_admin();
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let client = ClusterGrpcClient::new(NodeId::new());
+        let metrics = client.consensus_metrics(address).await.unwrap();
+        assert_eq!(metrics.node_id, node_id.to_string());
+
+        let node = Node::new("doomed".to_string(), "127.0.0.1:8001".parse().unwrap());
+        let doomed = node.id.clone();
+        state.write().await.add_node(node).unwrap();
+
+        let evicted = client.evict_node(address, &doomed).await.unwrap();
+        assert!(evicted.success, "{}", evicted.message);
+        let state = state.read().await;
+        assert!(state.get_node(&doomed).is_none());
+        assert_eq!(
+            state.get_tombstone(&doomed).unwrap().reason,
+            TombstoneReason::Evicted
+        );
+        drop(state);
+
+        assert!(client.evict_node(address, &node_id).await.is_err());
+
+        // A captured eviction cannot be replayed
+        let mut admin = ClusterAdminServiceClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        let request = EvictNodeRequest {
+            node_id: NodeId::new().to_string(),
+        };
+        assert!(admin.evict_node(request.clone()).await.is_err());
+        let stamp = RpcStamp::new(&node_id, None);
+        let first = admin.evict_node(stamp.request(request.clone())).await;
+        assert!(first.unwrap().into_inner().success);
+        let replay = admin.evict_node(stamp.request(request)).await;
+        assert_eq!(replay.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+        // Simple consensus keeps no log to compact
+        assert!(client.compact_log(address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_admin_service_disabled_by_default() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        let server = ClusterGrpcServer::new(node_id.clone(), state, consensus(&node_id), address);
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let client = ClusterGrpcClient::new(NodeId::new());
+        assert!(client.consensus_metrics(address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_admin_config_access() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        let consensus = Arc::new(SimpleConsensus::new(node_id.clone()));
+        let events = ClusterEventBus::new(16);
+        let mut changes = events.subscribe();
+        let server = ClusterGrpcServer::new(node_id.clone(), state, consensus, address)
+            .with_admin()
+            .with_event_bus(events)
+            .with_config_store(Arc::new(crate::distributed_storage::MemoryStorage::new()));
+        tokio::spawn(async move { server.start().await });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let client = ClusterGrpcClient::new(NodeId::new());
+        assert_eq!(client.get_config(address, "dns", None).await.unwrap(), None);
+
+        let value = serde_json::json!({"servers": ["1.1.1.1"]});
+        let written = client
+            .set_config(address, "dns", &value, Some(ConsistencyLevel::All))
+            .await
+            .unwrap();
+        assert!(written.success, "{}", written.message);
+        assert!(matches!(
+            changes.try_recv().unwrap(),
+            CoordinationEvent::ConfigurationChanged { key, .. } if key == "dns"
+        ));
+        assert_eq!(
+            client
+                .get_config(address, "dns", Some(ConsistencyLevel::One))
+                .await
+                .unwrap(),
+            Some(value)
+        );
+
+        // A captured config change cannot be replayed
+        let mut admin = ClusterAdminServiceClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        let request = SetConfigRequest {
+            key: "dns".to_string(),
+            value_json: r#"{"servers": ["9.9.9.9"]}"#.to_string(),
+            consistency: String::new(),
+        };
+        assert!(admin.set_config(request.clone()).await.is_err());
+        let stamp = RpcStamp::new(&node_id, None);
+        let first = admin.set_config(stamp.request(request.clone())).await;
+        assert!(first.unwrap().into_inner().success);
+        let replay = admin.set_config(stamp.request(request)).await;
+        assert_eq!(replay.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+        // Drains need a cordon
+        assert!(client.drain_node(address, &node_id, true).await.is_err());
+    }
 }
@@ -1,7 +1,15 @@
 //! Cluster configuration management
 
+use crate::clock::ClockSkewConfig;
+use crate::distributed_storage::ConflictStrategy;
 use crate::error::{ClusterError, Result};
+use crate::history::HistoryConfig;
+use crate::load_balancer::LoadBalancerExportConfig;
+use crate::node::Placement;
+use crate::selector;
+use crate::wal::WalConfig;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
@@ -18,6 +26,19 @@ pub struct ClusterConfig {
     /// Address this node binds to
     pub bind_address: SocketAddr,
 
+    /// Region this node runs in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+
+    /// Availability zone within the region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+
+    /// Labels selecting which pinned configuration this node applies, in
+    /// addition to the built-in `name`, `region` and `zone` labels
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub labels: HashMap<String, String>,
+
     /// Storage backend configuration
     pub storage_backend: StorageBackendConfig,
 
@@ -27,6 +48,11 @@ pub struct ClusterConfig {
     /// Whether this is the initial node (bootstrap node)
     pub is_initial_node: bool,
 
+    /// Join as a non-voting learner that only receives replicated state,
+    /// e.g. a reporting node for dashboards and backups
+    #[serde(default)]
+    pub learner: bool,
+
     /// List of bootstrap nodes to join
     pub bootstrap_nodes: Vec<SocketAddr>,
 
@@ -38,6 +64,57 @@ pub struct ClusterConfig {
 
     /// Leader election timeout
     pub election_timeout: Duration,
+
+    /// Mutual TLS for cluster gRPC traffic; plaintext when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+
+    /// Serve the admin service (eviction, config writes, drains) on the
+    /// cluster port; requires mutual TLS, as admin calls are not
+    /// otherwise authenticated
+    #[serde(default)]
+    pub admin_api: bool,
+
+    /// Compression and message size limits for cluster gRPC traffic
+    #[serde(default)]
+    pub transport: TransportConfig,
+
+    /// Batching, compression and per-peer rate limits for gossip
+    #[serde(default)]
+    pub gossip: GossipConfig,
+
+    /// Health scoring and automatic draining of overloaded nodes
+    #[serde(default)]
+    pub health_scoring: HealthScoringConfig,
+
+    /// Pacing of cluster-coordinated service restarts
+    #[serde(default)]
+    pub rolling_restart: RollingRestartConfig,
+
+    /// Largest clock offset to a peer before leases stop trusting it
+    #[serde(default)]
+    pub clock_skew: ClockSkewConfig,
+
+    /// HAProxy/Nginx fragment listing healthy nodes for an external L4
+    /// balancer; not exported when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_balancer: Option<LoadBalancerExportConfig>,
+
+    /// Directory for this node's persistent state
+    #[serde(default = "default_data_dir")]
+    pub data_dir: PathBuf,
+
+    /// Write-ahead log for the consensus log, kept under `data_dir`
+    #[serde(default)]
+    pub consensus_log: WalConfig,
+
+    /// How concurrent writes to the same config key are settled
+    #[serde(default)]
+    pub config_conflicts: ConflictStrategy,
+
+    /// Timeline of cluster state snapshots, kept under `data_dir`
+    #[serde(default)]
+    pub history: HistoryConfig,
 }
 
 impl Default for ClusterConfig {
@@ -49,16 +126,36 @@ impl Default for ClusterConfig {
             storage_backend: StorageBackendConfig::default(),
             consensus_algorithm: ConsensusAlgorithm::Raft,
             is_initial_node: false,
+            learner: false,
             bootstrap_nodes: vec![],
             gossip_interval: Duration::from_secs(5),
             heartbeat_interval: Duration::from_secs(1),
             election_timeout: Duration::from_secs(10),
+            tls: None,
+            admin_api: false,
+            transport: TransportConfig::default(),
+            gossip: GossipConfig::default(),
+            health_scoring: HealthScoringConfig::default(),
+            rolling_restart: RollingRestartConfig::default(),
+            clock_skew: ClockSkewConfig::default(),
+            load_balancer: None,
+            region: None,
+            zone: None,
+            labels: HashMap::new(),
+            data_dir: default_data_dir(),
+            consensus_log: WalConfig::default(),
+            config_conflicts: ConflictStrategy::default(),
+            history: HistoryConfig::default(),
         }
     }
 }
 
+fn default_data_dir() -> PathBuf {
+    PathBuf::from("/var/lib/vpn-cluster")
+}
+
 /// Storage backend configuration options
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum StorageBackendConfig {
     /// Local embedded storage using Sled
@@ -101,7 +198,7 @@ pub enum StorageBackendConfig {
 impl Default for StorageBackendConfig {
     fn default() -> Self {
         Self::Sled {
-            path: PathBuf::from("/tmp/vpn-cluster"),
+            path: default_data_dir().join("storage"),
         }
     }
 }
@@ -156,7 +253,9 @@ impl Default for NetworkConfig {
 }
 
 /// TLS configuration for secure cluster communication
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Node certificates must be issued by `ca_cert` for the cluster name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TlsConfig {
     /// CA certificate file
     pub ca_cert: PathBuf,
@@ -175,12 +274,228 @@ pub struct TlsConfig {
 
     /// Verify peer certificates
     pub verify_peer: bool,
+
+    /// How often certificate files are checked for rotation
+    #[serde(default = "default_tls_reload_interval")]
+    pub reload_interval: Duration,
+}
+
+fn default_tls_reload_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Compression applied to cluster gRPC messages
+///
+/// Peers always accept both gzip and zstd regardless of what they send, so
+/// nodes with different settings interoperate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionCodec {
+    None,
+    #[default]
+    Gzip,
+    /// Smaller and faster than gzip; best for snapshots over slow links
+    Zstd,
+}
+
+/// Room reserved in each message for chunk metadata
+const CHUNK_METADATA_ALLOWANCE: usize = 1024;
+
+/// gRPC transport settings for cluster communication
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransportConfig {
+    /// Compression for outgoing messages
+    #[serde(default)]
+    pub compression: CompressionCodec,
+
+    /// Largest single gRPC message sent or accepted, in bytes
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+
+    /// Size of the chunks large payloads (snapshots, user-table syncs) are
+    /// split into, in bytes
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+
+    /// Largest payload accepted through a chunked transfer, in bytes
+    #[serde(default = "default_max_transfer_size")]
+    pub max_transfer_size: usize,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            compression: CompressionCodec::default(),
+            max_message_size: default_max_message_size(),
+            chunk_size: default_chunk_size(),
+            max_transfer_size: default_max_transfer_size(),
+        }
+    }
+}
+
+impl TransportConfig {
+    /// Validate transport limits
+    pub fn validate(&self) -> Result<()> {
+        if self.chunk_size == 0 {
+            return Err(ClusterError::configuration(
+                "Transfer chunk size must be greater than zero",
+            ));
+        }
+
+        if self.chunk_size + CHUNK_METADATA_ALLOWANCE > self.max_message_size {
+            return Err(ClusterError::configuration(format!(
+                "Transfer chunk size must be at least {} bytes below the max message size",
+                CHUNK_METADATA_ALLOWANCE
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn default_max_message_size() -> usize {
+    4 * 1024 * 1024 // 4MB, tonic's default
+}
+
+fn default_chunk_size() -> usize {
+    1024 * 1024 // 1MB
+}
+
+fn default_max_transfer_size() -> usize {
+    256 * 1024 * 1024 // 256MB
+}
+
+/// How node health scores are computed from load, and when nodes are drained
+///
+/// The score is 100 minus the weighted average of CPU, memory and connection
+/// pressure. Nodes scoring below `drain_below` stop taking new VPN users
+/// until they reach `restore_above` again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthScoringConfig {
+    /// Drain and re-admit nodes automatically
+    pub auto_drain: bool,
+
+    /// Score below which a healthy node is drained
+    pub drain_below: u8,
+
+    /// Score a draining node must reach before taking new users again;
+    /// kept above `drain_below` so nodes do not flap
+    pub restore_above: u8,
+
+    /// Connection count treated as fully loaded
+    pub max_connections: u64,
+
+    /// Weight of CPU usage in the score
+    pub cpu_weight: f64,
+
+    /// Weight of memory usage in the score
+    pub memory_weight: f64,
+
+    /// Weight of the connection count in the score
+    pub connection_weight: f64,
+}
+
+impl Default for HealthScoringConfig {
+    fn default() -> Self {
+        Self {
+            auto_drain: true,
+            drain_below: 30,
+            restore_above: 60,
+            max_connections: 1000,
+            cpu_weight: 0.4,
+            memory_weight: 0.3,
+            connection_weight: 0.3,
+        }
+    }
+}
+
+impl HealthScoringConfig {
+    /// Validate thresholds and weights
+    pub fn validate(&self) -> Result<()> {
+        if self.drain_below >= self.restore_above || self.restore_above > 100 {
+            return Err(ClusterError::configuration(
+                "Health scoring requires drain_below < restore_above <= 100",
+            ));
+        }
+
+        if self.max_connections == 0 {
+            return Err(ClusterError::configuration(
+                "Health scoring max_connections must be greater than zero",
+            ));
+        }
+
+        let weights = [self.cpu_weight, self.memory_weight, self.connection_weight];
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) || weights.iter().sum::<f64>() <= 0.0
+        {
+            return Err(ClusterError::configuration(
+                "Health scoring weights must be non-negative and not all zero",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Compression applied to gossip batches before they are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GossipCompression {
+    None,
+    #[default]
+    Zstd,
+}
+
+/// Rolling restart pacing
+///
+/// Each node is drained for up to `drain_timeout` before its service is
+/// restarted, and must report healthy within `health_timeout` afterwards or
+/// the rollout stops with that node still drained.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RollingRestartConfig {
+    /// Longest wait for a node's open connections to close
+    pub drain_timeout: Duration,
+
+    /// Longest wait for a restarted service to become healthy
+    pub health_timeout: Duration,
+
+    /// How often drain and health progress is checked
+    pub poll_interval: Duration,
+}
+
+impl Default for RollingRestartConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: Duration::from_secs(60),
+            health_timeout: Duration::from_secs(120),
+            poll_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RollingRestartConfig {
+    /// Validate timeouts
+    pub fn validate(&self) -> Result<()> {
+        if self.poll_interval.is_zero() || self.health_timeout < self.poll_interval {
+            return Err(ClusterError::configuration(
+                "Rolling restart requires 0 < poll_interval <= health_timeout",
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 /// Gossip protocol configuration
+///
+/// Messages are queued and flushed every `interval` in batches of at most
+/// `max_message_size` bytes (before compression), so a burst of state
+/// changes costs a few requests per peer instead of one per change.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GossipConfig {
-    /// Gossip interval
+    /// How often queued messages are flushed
     pub interval: Duration,
 
     /// Number of nodes to gossip with per round
@@ -194,6 +509,28 @@ pub struct GossipConfig {
 
     /// Maximum number of missed heartbeats before failure
     pub max_missed_heartbeats: usize,
+
+    /// Most messages packed into one batch
+    pub max_batch_messages: usize,
+
+    /// Messages kept queued while peers are rate limited; the oldest are
+    /// dropped beyond this and left to state sync to repair
+    pub max_pending_messages: usize,
+
+    /// Compression for batch payloads
+    pub compression: GossipCompression,
+
+    /// zstd compression level (1-22)
+    pub compression_level: i32,
+
+    /// Batches sent to a single peer per second
+    pub peer_rate_limit: u32,
+
+    /// Batches a peer may receive at once after being idle
+    pub peer_burst: u32,
+
+    /// How many times a message is relayed before it stops spreading
+    pub max_hops: u8,
 }
 
 impl Default for GossipConfig {
@@ -204,7 +541,45 @@ impl Default for GossipConfig {
             max_message_size: 65536, // 64KB
             suspicion_timeout: Duration::from_secs(5),
             max_missed_heartbeats: 3,
+            max_batch_messages: 512,
+            max_pending_messages: 10_000,
+            compression: GossipCompression::default(),
+            compression_level: 3,
+            peer_rate_limit: 10,
+            peer_burst: 20,
+            max_hops: 4,
+        }
+    }
+}
+
+impl GossipConfig {
+    /// Validate batching and rate limits
+    pub fn validate(&self) -> Result<()> {
+        if self.fanout == 0 {
+            return Err(ClusterError::configuration(
+                "Gossip fanout must be greater than zero",
+            ));
+        }
+
+        if self.max_message_size == 0 || self.max_batch_messages == 0 {
+            return Err(ClusterError::configuration(
+                "Gossip batch size limits must be greater than zero",
+            ));
+        }
+
+        if self.peer_rate_limit == 0 || self.peer_burst == 0 {
+            return Err(ClusterError::configuration(
+                "Gossip peer rate limit and burst must be greater than zero",
+            ));
+        }
+
+        if !(1..=22).contains(&self.compression_level) {
+            return Err(ClusterError::configuration(
+                "Gossip compression level must be between 1 and 22",
+            ));
         }
+
+        Ok(())
     }
 }
 
@@ -293,9 +668,56 @@ impl ClusterConfig {
             ));
         }
 
+        if let Some(tls) = &self.tls {
+            if tls.client_cert.is_some() != tls.client_key.is_some() {
+                return Err(ClusterError::configuration(
+                    "TLS client certificate and key must be configured together",
+                ));
+            }
+        }
+
+        self.validate_admin_api()?;
+
+        self.transport.validate()?;
+        self.gossip.validate()?;
+        self.health_scoring.validate()?;
+        self.rolling_restart.validate()?;
+        self.clock_skew.validate()?;
+        if let Some(load_balancer) = &self.load_balancer {
+            load_balancer.validate()?;
+        }
+        self.consensus_log.validate()?;
+
         Ok(())
     }
 
+    /// Refuse to serve the admin API without mutual TLS to authenticate it
+    pub fn validate_admin_api(&self) -> Result<()> {
+        if self.admin_api && !self.tls.as_ref().is_some_and(|tls| tls.verify_peer) {
+            return Err(ClusterError::configuration(
+                "The admin API requires TLS with peer verification",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Failure domain of this node
+    pub fn placement(&self) -> Placement {
+        Placement::new(self.region.clone(), self.zone.clone())
+    }
+
+    /// Labels this node is matched on by a [`LabelSelector`]
+    ///
+    /// [`LabelSelector`]: crate::selector::LabelSelector
+    pub fn node_labels(&self) -> HashMap<String, String> {
+        selector::node_labels(&self.labels, &self.node_name, &self.placement())
+    }
+
+    /// Directory holding the consensus write-ahead log and snapshots
+    pub fn consensus_log_dir(&self) -> PathBuf {
+        self.data_dir.join("raft")
+    }
+
     /// Get quorum size for consensus
     pub fn quorum_size(&self, total_nodes: usize) -> usize {
         (total_nodes / 2) + 1
@@ -364,6 +786,88 @@ mod tests {
         assert_eq!(config.bind_address, deserialized.bind_address);
     }
 
+    #[test]
+    fn test_tls_config_parsing() {
+        let config = ClusterConfig {
+            is_initial_node: true,
+            ..ClusterConfig::default()
+        };
+        let mut toml_config = toml::to_string(&config).unwrap();
+        toml_config.push_str(
+            r#"
+[tls]
+ca_cert = "/etc/vpn/cluster/ca.pem"
+server_cert = "/etc/vpn/cluster/node.pem"
+server_key = "/etc/vpn/cluster/node.key"
+verify_peer = true
+"#,
+        );
+
+        let mut config: ClusterConfig = toml::from_str(&toml_config).unwrap();
+        let tls = config.tls.as_ref().unwrap();
+        assert_eq!(tls.ca_cert, PathBuf::from("/etc/vpn/cluster/ca.pem"));
+        assert_eq!(tls.reload_interval, Duration::from_secs(30));
+        assert!(config.validate().is_ok());
+
+        // Client certificate without a key
+        config.tls.as_mut().unwrap().client_key =
+            Some(PathBuf::from("/etc/vpn/cluster/client.key"));
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_admin_api_requires_mutual_tls() {
+        let mut config = ClusterConfig {
+            is_initial_node: true,
+            admin_api: true,
+            ..ClusterConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        config.tls = Some(TlsConfig {
+            ca_cert: PathBuf::from("/etc/vpn/cluster/ca.pem"),
+            server_cert: PathBuf::from("/etc/vpn/cluster/node.pem"),
+            server_key: PathBuf::from("/etc/vpn/cluster/node.key"),
+            client_cert: None,
+            client_key: None,
+            verify_peer: false,
+            reload_interval: Duration::from_secs(30),
+        });
+        assert!(config.validate().is_err());
+
+        config.tls.as_mut().unwrap().verify_peer = true;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_transport_config() {
+        let mut config = ClusterConfig {
+            is_initial_node: true,
+            ..ClusterConfig::default()
+        };
+        let serialized = toml::to_string(&config).unwrap();
+
+        // Configs written before the transport section existed get defaults
+        let legacy = serialized.split("[transport]").next().unwrap();
+        let parsed: ClusterConfig = toml::from_str(legacy).unwrap();
+        assert_eq!(parsed.transport.compression, CompressionCodec::Gzip);
+        assert_eq!(parsed.transport.chunk_size, 1024 * 1024);
+
+        let uncompressed = serialized.replace("compression = \"gzip\"", "compression = \"none\"");
+        let parsed: ClusterConfig = toml::from_str(&uncompressed).unwrap();
+        assert_eq!(parsed.transport.compression, CompressionCodec::None);
+
+        let zstd = serialized.replace("compression = \"gzip\"", "compression = \"zstd\"");
+        let parsed: ClusterConfig = toml::from_str(&zstd).unwrap();
+        assert_eq!(parsed.transport.compression, CompressionCodec::Zstd);
+
+        // Chunks must fit into a single message
+        config.transport.chunk_size = config.transport.max_message_size;
+        assert!(config.validate().is_err());
+        config.transport.chunk_size = 0;
+        assert!(config.validate().is_err());
+    }
+
     #[test]
     fn test_config_file_operations() {
         let temp_dir = tempdir().unwrap();
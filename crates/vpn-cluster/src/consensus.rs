@@ -1,10 +1,19 @@
 //! Consensus mechanisms for cluster coordination
 
 use crate::error::{ClusterError, Result};
+use crate::leader_election::{
+    log_is_up_to_date, ElectionConfig, ElectionRound, FencingToken, LeaderLease, VoteRequest,
+    VoteResponse,
+};
+use crate::membership::{Configuration, MembershipChange};
 use crate::node::NodeId;
+use crate::wal::{HardState, SnapshotMeta, Wal, WalConfig};
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 /// Trait for consensus algorithms
@@ -48,6 +57,31 @@ pub trait ConsensusEngine: Send + Sync {
 
     /// Get consensus metrics
     async fn get_metrics(&self) -> ConsensusMetrics;
+
+    /// Members (voters and learners) of the committed configuration.
+    /// `None` while a membership change is in flight or if the engine does
+    /// not track membership.
+    async fn committed_members(&self) -> Option<HashSet<NodeId>> {
+        None
+    }
+
+    /// Token to attach to writes while this node believes it is leader.
+    /// `None` when it is not leader or cannot be sure it still is.
+    async fn current_fencing_token(&self) -> Option<FencingToken> {
+        None
+    }
+
+    /// Mark `peer`'s clock as too far off to back a lease, or as back in
+    /// line. Engines without leases ignore it.
+    async fn set_clock_skewed(&self, _peer: NodeId, _skewed: bool) {}
+
+    /// Snapshot the committed state and drop the log entries it covers.
+    /// Returns the index the log now starts after.
+    async fn compact(&self) -> Result<u64> {
+        Err(ClusterError::consensus(
+            "Log compaction is not supported by this consensus engine",
+        ))
+    }
 }
 
 /// Consensus algorithm metrics
@@ -78,6 +112,9 @@ pub async fn create_consensus_engine(
 /// Raft consensus implementation
 pub struct RaftConsensus {
     node_id: NodeId,
+    election_config: ElectionConfig,
+    /// This node only follows the log and never votes or campaigns
+    learner: bool,
     state: Arc<RwLock<RaftState>>,
     // In a real implementation, this would contain Raft-specific structures
 }
@@ -95,7 +132,67 @@ struct RaftState {
     leader_id: Option<NodeId>,
     election_timeout: std::time::Instant,
     heartbeat_timeout: std::time::Instant,
-    cluster_members: std::collections::HashSet<NodeId>,
+    /// Latest voter configuration in the log (takes effect once appended)
+    configuration: Configuration,
+    /// Log index of the configuration entry that is not yet committed
+    pending_config_index: Option<u64>,
+    /// Non-voting members catching up before being promoted to voters
+    learners: HashSet<NodeId>,
+    /// Learners that are never promoted, e.g. reporting nodes
+    permanent_learners: HashSet<NodeId>,
+    /// Follower: running while the leader keeps in touch. Leader: running
+    /// while a quorum keeps answering.
+    lease: LeaderLease,
+    /// Leader: when each member last answered
+    last_contact: HashMap<NodeId, Instant>,
+    /// Peers whose clock is too far off for their contact to extend a lease
+    clock_skewed: HashSet<NodeId>,
+    /// Pre-vote or election this node is currently running
+    election: Option<ElectionRound>,
+    /// Durable copy of the log and hard state; `None` keeps state in memory
+    wal: Option<Wal>,
+    /// Last log index covered by the latest snapshot; `log` starts after it
+    snapshot_index: u64,
+    /// Term of the entry at `snapshot_index`
+    snapshot_term: u64,
+}
+
+impl RaftState {
+    fn last_log_index(&self) -> u64 {
+        self.snapshot_index + self.log.len() as u64
+    }
+
+    fn last_log_term(&self) -> u64 {
+        self.log
+            .last()
+            .map(|entry| entry.term)
+            .unwrap_or(self.snapshot_term)
+    }
+
+    /// Term of the entry at `index`, if it has not been compacted away
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        let position = index.checked_sub(self.snapshot_index + 1)?;
+        self.log.get(position as usize).map(|entry| entry.term)
+    }
+
+    fn hard_state(&self) -> HardState {
+        HardState {
+            term: self.current_term,
+            voted_for: self.voted_for.clone(),
+        }
+    }
+
+    /// Write the current term and vote to the WAL
+    fn persist_hard_state(&mut self) -> Result<()> {
+        let hard_state = self.hard_state();
+        match self.wal.as_mut() {
+            Some(wal) => wal.save_hard_state(&hard_state),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -105,16 +202,32 @@ enum RaftRole {
     Leader,
 }
 
-#[derive(Debug, Clone)]
-struct LogEntry {
-    _term: u64,
-    index: u64,
-    _data: Vec<u8>,
-    _timestamp: u64,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct LogEntry {
+    pub(crate) term: u64,
+    pub(crate) index: u64,
+    pub(crate) payload: LogPayload,
+    pub(crate) timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum LogPayload {
+    /// Application state change
+    Command(Vec<u8>),
+    /// Voter configuration change
+    Configuration(Configuration),
 }
 
 impl RaftConsensus {
     pub async fn new(node_id: NodeId) -> Result<Self> {
+        Self::with_election_config(node_id, ElectionConfig::default()).await
+    }
+
+    /// Create a Raft engine with custom pre-vote and lease settings
+    pub async fn with_election_config(
+        node_id: NodeId,
+        election_config: ElectionConfig,
+    ) -> Result<Self> {
         let state = RaftState {
             current_term: 0,
             voted_for: None,
@@ -128,24 +241,168 @@ impl RaftConsensus {
             election_timeout: std::time::Instant::now()
                 + Duration::from_millis(150 + rand::random::<u64>() % 150),
             heartbeat_timeout: std::time::Instant::now(),
-            cluster_members: std::collections::HashSet::new(),
+            configuration: Configuration::default(),
+            pending_config_index: None,
+            learners: HashSet::new(),
+            permanent_learners: HashSet::new(),
+            lease: LeaderLease::new(election_config.lease_duration),
+            last_contact: HashMap::new(),
+            clock_skewed: HashSet::new(),
+            election: None,
+            wal: None,
+            snapshot_index: 0,
+            snapshot_term: 0,
         };
 
         Ok(Self {
             node_id,
+            election_config,
+            learner: false,
             state: Arc::new(RwLock::new(state)),
         })
     }
 
+    /// Run this node as a learner: it receives the replicated log for
+    /// read-only use but never votes, campaigns or bootstraps a cluster of
+    /// its own. The leader adds it with [`RaftConsensus::add_learner`].
+    pub fn with_learner_role(mut self) -> Self {
+        self.learner = true;
+        self
+    }
+
+    pub fn is_learner(&self) -> bool {
+        self.learner
+    }
+
+    fn check_can_campaign(&self) -> Result<()> {
+        if self.learner {
+            return Err(ClusterError::leader_election_failed(format!(
+                "Node {} is a learner and never stands for election",
+                self.node_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Add a learner that receives the replicated log but, unlike nodes
+    /// added with `add_node`, is never promoted to a voter
+    pub async fn add_learner(&self, node_id: NodeId) -> Result<()> {
+        let mut state = self.state.write().await;
+
+        if state.role != RaftRole::Leader {
+            return Err(ClusterError::membership(
+                "Only leader can change cluster membership",
+            ));
+        }
+
+        if state.configuration.contains(&node_id) || state.learners.contains(&node_id) {
+            return Err(ClusterError::node_already_exists(node_id.to_string()));
+        }
+
+        state.learners.insert(node_id.clone());
+        state.permanent_learners.insert(node_id.clone());
+        let last_log_index = state.last_log_index();
+        state.next_index.insert(node_id.clone(), last_log_index + 1);
+        state.match_index.insert(node_id.clone(), 0);
+
+        tracing::info!("Added learner {} to Raft cluster", node_id);
+        Ok(())
+    }
+
+    /// Members that receive the log without voting
+    pub async fn learners(&self) -> HashSet<NodeId> {
+        self.state.read().await.learners.clone()
+    }
+
+    /// Create a Raft engine whose log and hard state are kept in a
+    /// write-ahead log under `dir`, recovering whatever an earlier run left
+    /// there. With the WAL disabled this is the same as
+    /// [`RaftConsensus::with_election_config`].
+    pub async fn open(
+        node_id: NodeId,
+        election_config: ElectionConfig,
+        dir: impl AsRef<Path>,
+        wal_config: WalConfig,
+    ) -> Result<Self> {
+        let raft = Self::with_election_config(node_id, election_config).await?;
+        Self::open_wal(raft, dir, wal_config).await
+    }
+
+    async fn open_wal(raft: Self, dir: impl AsRef<Path>, wal_config: WalConfig) -> Result<Self> {
+        if !wal_config.enabled {
+            return Ok(raft);
+        }
+
+        let (wal, recovered) = Wal::open(dir, wal_config)?;
+        {
+            let mut state = raft.state.write().await;
+
+            if let Some((_, data)) = &recovered.snapshot {
+                let snapshot_data: serde_json::Value = serde_json::from_slice(data)?;
+                Self::restore_snapshot(&mut state, &snapshot_data);
+            }
+
+            if recovered.hard_state.term >= state.current_term {
+                state.current_term = recovered.hard_state.term;
+                state.voted_for = recovered.hard_state.voted_for;
+            }
+            state.log = recovered.entries;
+
+            // The newest configuration in the log is in effect; it is still
+            // pending unless the snapshot shows it committed
+            let latest_config = state
+                .log
+                .iter()
+                .rev()
+                .find_map(|entry| match &entry.payload {
+                    LogPayload::Configuration(configuration) => Some((entry.index, configuration)),
+                    LogPayload::Command(_) => None,
+                });
+            if let Some((index, configuration)) = latest_config {
+                let configuration = configuration.clone();
+                state.configuration = configuration;
+                state.pending_config_index = (index > state.commit_index).then_some(index);
+            }
+
+            state.wal = Some(wal);
+        }
+
+        Ok(raft)
+    }
+
+    /// Create a Raft engine persisting to the node's data directory
+    pub async fn from_config(
+        node_id: NodeId,
+        config: &crate::config::ClusterConfig,
+    ) -> Result<Self> {
+        let mut raft = Self::with_election_config(node_id, ElectionConfig::default()).await?;
+        if config.learner {
+            raft = raft.with_learner_role();
+        }
+        Self::open_wal(
+            raft,
+            config.consensus_log_dir(),
+            config.consensus_log.clone(),
+        )
+        .await
+    }
+
     async fn become_leader(&self) -> Result<()> {
         let mut state = self.state.write().await;
         state.role = RaftRole::Leader;
         state.leader_id = Some(self.node_id.clone());
         state.heartbeat_timeout = std::time::Instant::now();
+        state.election = None;
+        self.renew_leader_lease(&mut state);
 
         // Initialize next_index and match_index for all followers
-        let last_log_index = state.log.len() as u64;
-        let cluster_members: Vec<NodeId> = state.cluster_members.iter().cloned().collect();
+        let last_log_index = state.last_log_index();
+        let cluster_members: Vec<NodeId> = state
+            .configuration
+            .voters()
+            .into_iter()
+            .chain(state.learners.iter().cloned())
+            .collect();
         for member in cluster_members {
             if member != self.node_id {
                 state.next_index.insert(member.clone(), last_log_index + 1);
@@ -153,6 +410,14 @@ impl RaftConsensus {
             }
         }
 
+        // Finish a membership change interrupted by the previous leader
+        if state.configuration.is_joint() && state.pending_config_index.is_none() {
+            let joint = state.configuration.clone();
+            let index = Self::append_entry(&mut state, LogPayload::Configuration(joint))?;
+            state.pending_config_index = Some(index);
+            self.advance_commit_index(&mut state)?;
+        }
+
         tracing::info!(
             "Node {} became leader for term {}",
             self.node_id,
@@ -166,7 +431,11 @@ impl RaftConsensus {
         state.role = RaftRole::Follower;
         state.current_term = term;
         state.voted_for = None;
+        state.persist_hard_state()?;
         state.leader_id = leader_id;
+        state.election = None;
+        state.lease.expire();
+        state.last_contact.clear();
         state.election_timeout =
             std::time::Instant::now() + Duration::from_millis(150 + rand::random::<u64>() % 150);
 
@@ -179,7 +448,15 @@ impl RaftConsensus {
         state.role = RaftRole::Candidate;
         state.current_term += 1;
         state.voted_for = Some(self.node_id.clone());
+        state.persist_hard_state()?;
         state.leader_id = None;
+        state.lease.expire();
+        state.last_contact.clear();
+        state.election = Some(ElectionRound::new(
+            state.current_term,
+            false,
+            self.node_id.clone(),
+        ));
         state.election_timeout =
             std::time::Instant::now() + Duration::from_millis(150 + rand::random::<u64>() % 150);
 
@@ -191,31 +468,633 @@ impl RaftConsensus {
         Ok(())
     }
 
-    async fn append_log_entry(&self, data: Vec<u8>) -> Result<u64> {
-        let mut state = self.state.write().await;
+    async fn append_log_entry(&self, data: Vec<u8>) -> Result<u64> {
+        let mut state = self.state.write().await;
+
+        if state.role != RaftRole::Leader {
+            return Err(ClusterError::consensus(
+                "Only leader can append log entries",
+            ));
+        }
+
+        let index = Self::append_entry(&mut state, LogPayload::Command(data))?;
+
+        tracing::debug!(
+            "Leader {} appended log entry at index {}",
+            self.node_id,
+            index
+        );
+
+        self.advance_commit_index(&mut state)?;
+        Ok(index)
+    }
+
+    /// Append an entry for the current term, writing it to the WAL first
+    fn append_entry(state: &mut RaftState, payload: LogPayload) -> Result<u64> {
+        let entry = LogEntry {
+            term: state.current_term,
+            index: state.last_log_index() + 1,
+            payload,
+            timestamp: current_timestamp(),
+        };
+
+        if let Some(wal) = state.wal.as_mut() {
+            wal.append_entry(&entry)?;
+        }
+
+        let index = entry.index;
+        state.log.push(entry);
+        Ok(index)
+    }
+
+    /// Compact once enough committed entries follow the last snapshot
+    fn maybe_compact(state: &mut RaftState) -> Result<()> {
+        let Some(threshold) = state
+            .wal
+            .as_ref()
+            .map(|wal| wal.config().snapshot_threshold)
+        else {
+            return Ok(());
+        };
+
+        // The snapshot records the current configuration, which is only
+        // the one at the commit index when no change is pending
+        if state.pending_config_index.is_none()
+            && state.commit_index - state.snapshot_index >= threshold
+        {
+            Self::compact_log(state)?;
+        }
+        Ok(())
+    }
+
+    fn compact_log(state: &mut RaftState) -> Result<()> {
+        let last_index = state.commit_index;
+        if last_index <= state.snapshot_index {
+            return Ok(());
+        }
+        let last_term = state
+            .term_at(last_index)
+            .ok_or_else(|| ClusterError::storage(format!("Log entry {} missing", last_index)))?;
+
+        let data = serde_json::to_vec(&Self::snapshot_data(state))?;
+        let hard_state = state.hard_state();
+        if let Some(wal) = state.wal.as_mut() {
+            let meta = SnapshotMeta {
+                last_index,
+                last_term,
+            };
+            wal.compact(meta, &data, &hard_state)?;
+        }
+
+        state
+            .log
+            .drain(..(last_index - state.snapshot_index) as usize);
+        state.snapshot_index = last_index;
+        state.snapshot_term = last_term;
+
+        tracing::debug!("Compacted Raft log through index {}", last_index);
+        Ok(())
+    }
+
+    /// Seed the voter configuration of a fresh cluster whose initial
+    /// members were agreed on out of band
+    #[cfg(any(test, feature = "simulation"))]
+    pub(crate) async fn bootstrap_voters(&self, voters: impl IntoIterator<Item = NodeId>) {
+        let mut state = self.state.write().await;
+        state.configuration = Configuration::stable(voters);
+    }
+
+    /// Get the current voter configuration
+    pub async fn configuration(&self) -> Configuration {
+        let state = self.state.read().await;
+        state.configuration.clone()
+    }
+
+    /// Check whether a membership change has not completed yet
+    pub async fn is_membership_change_pending(&self) -> bool {
+        let state = self.state.read().await;
+        state.configuration.is_joint() || state.pending_config_index.is_some()
+    }
+
+    /// Handle a successful AppendEntries response from a follower
+    ///
+    /// Advances the commit index, drives pending joint configurations to
+    /// completion and promotes learners once they have caught up.
+    pub async fn handle_append_response(&self, from: NodeId, match_index: u64) -> Result<()> {
+        let mut state = self.state.write().await;
+
+        if state.role != RaftRole::Leader {
+            return Err(ClusterError::consensus(
+                "Only leader can handle append responses",
+            ));
+        }
+
+        if !state.configuration.contains(&from) && !state.learners.contains(&from) {
+            return Err(ClusterError::node_not_found(from.to_string()));
+        }
+
+        let match_index = match_index.min(state.last_log_index());
+        state.match_index.insert(from.clone(), match_index);
+        state.next_index.insert(from.clone(), match_index + 1);
+        state.last_contact.insert(from.clone(), Instant::now());
+        self.renew_leader_lease(&mut state);
+
+        self.advance_commit_index(&mut state)?;
+
+        // Promote a learner once it has caught up with the leader's log
+        let caught_up = match_index >= state.last_log_index();
+        let change_pending = state.configuration.is_joint() || state.pending_config_index.is_some();
+        let promotable =
+            state.learners.contains(&from) && !state.permanent_learners.contains(&from);
+        if promotable && caught_up && !change_pending {
+            self.begin_membership_change(&mut state, MembershipChange::AddVoter(from))?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a heartbeat or AppendEntries from `leader_id`, renewing its lease
+    pub async fn handle_heartbeat(&self, leader_id: NodeId, term: u64) -> Result<()> {
+        let mut state = self.state.write().await;
+
+        if term < state.current_term {
+            return Err(ClusterError::consensus(format!(
+                "Stale heartbeat from {} for term {} (current term {})",
+                leader_id, term, state.current_term
+            )));
+        }
+
+        if term > state.current_term {
+            state.current_term = term;
+            state.voted_for = None;
+            state.persist_hard_state()?;
+        }
+        if state.role != RaftRole::Follower {
+            state.role = RaftRole::Follower;
+            state.last_contact.clear();
+        }
+        if state.clock_skewed.contains(&leader_id) {
+            tracing::warn!(
+                "Not extending lease of leader {}: clock skew over the limit",
+                leader_id
+            );
+        } else {
+            state.lease.renew(Instant::now());
+        }
+        state.leader_id = Some(leader_id);
+        state.election = None;
+        state.election_timeout =
+            Instant::now() + Duration::from_millis(150 + rand::random::<u64>() % 150);
+
+        Ok(())
+    }
+
+    /// Whether this node may act on its current view of the leader: as a
+    /// leader, a quorum answered within the lease; as a follower, the leader
+    /// was heard from within the lease
+    pub async fn has_leader_lease(&self) -> bool {
+        let state = self.state.read().await;
+        state.role != RaftRole::Candidate && state.lease.is_held(Instant::now())
+    }
+
+    /// Step down if this leader has lost contact with a quorum. Returns
+    /// whether it is still the leader.
+    pub async fn check_quorum(&self) -> bool {
+        let mut state = self.state.write().await;
+        if state.role != RaftRole::Leader {
+            return false;
+        }
+
+        if state.lease.is_held(Instant::now()) {
+            return true;
+        }
+
+        tracing::warn!(
+            "Leader {} lost contact with a quorum, stepping down in term {}",
+            self.node_id,
+            state.current_term
+        );
+        state.role = RaftRole::Follower;
+        state.leader_id = None;
+        state.last_contact.clear();
+        false
+    }
+
+    /// Begin a pre-vote round: ask the voters whether they would elect this
+    /// node in the next term, without changing anybody's term
+    pub async fn start_pre_vote(&self) -> Result<VoteRequest> {
+        self.check_can_campaign()?;
+        let mut state = self.state.write().await;
+
+        if state.role == RaftRole::Leader {
+            return Err(ClusterError::leader_election_failed(
+                "Node is already the leader",
+            ));
+        }
+        if state.lease.is_held(Instant::now()) {
+            return Err(ClusterError::leader_election_failed(format!(
+                "Leader {} still holds its lease",
+                state
+                    .leader_id
+                    .as_ref()
+                    .map(NodeId::to_string)
+                    .unwrap_or_default()
+            )));
+        }
+
+        let term = state.current_term + 1;
+        state.election = Some(ElectionRound::new(term, true, self.node_id.clone()));
+        Ok(self.vote_request(&state, term, true))
+    }
+
+    /// Become a candidate in the next term. With pre-vote enabled, a quorum
+    /// must have granted the pre-vote first.
+    pub async fn start_election(&self) -> Result<VoteRequest> {
+        self.check_can_campaign()?;
+        {
+            let state = self.state.read().await;
+            if state.role == RaftRole::Leader {
+                return Err(ClusterError::leader_election_failed(
+                    "Node is already the leader",
+                ));
+            }
+
+            let pre_vote_won = state.election.as_ref().is_some_and(|round| {
+                round.is_pre_vote()
+                    && round.term() == state.current_term + 1
+                    && round.is_won(&state.configuration)
+            });
+            if self.election_config.pre_vote && !pre_vote_won {
+                return Err(ClusterError::leader_election_failed(
+                    "Pre-vote has not reached a quorum",
+                ));
+            }
+        }
+
+        self.become_candidate().await?;
+
+        let state = self.state.read().await;
+        Ok(self.vote_request(&state, state.current_term, false))
+    }
+
+    /// Count a vote or pre-vote from `from`. Returns true once the round is
+    /// won: a won pre-vote lets the node start its election, a won election
+    /// makes it leader.
+    pub async fn handle_vote_response(&self, from: NodeId, response: VoteResponse) -> Result<bool> {
+        let mut state = self.state.write().await;
+
+        if response.term > state.current_term && !response.vote_granted {
+            drop(state);
+            self.become_follower(response.term, None).await?;
+            return Ok(false);
+        }
+
+        let Some(round) = state.election.as_mut() else {
+            return Ok(false);
+        };
+        round.record(from.clone(), &response);
+        if !response.pre_vote && response.vote_granted {
+            state.last_contact.insert(from, Instant::now());
+        }
+
+        let round = state.election.as_ref().expect("election round");
+        if !round.is_won(&state.configuration) {
+            return Ok(false);
+        }
+
+        if round.is_pre_vote() {
+            return Ok(true);
+        }
+
+        if state.role != RaftRole::Candidate || round.term() != state.current_term {
+            return Ok(false);
+        }
+
+        drop(state);
+        self.become_leader().await?;
+        Ok(true)
+    }
+
+    /// Decide on a vote or pre-vote request from a candidate
+    pub async fn handle_vote_request(&self, request: VoteRequest) -> VoteResponse {
+        let mut state = self.state.write().await;
+        let deny = |term| VoteResponse {
+            term,
+            vote_granted: false,
+            pre_vote: request.pre_vote,
+        };
+
+        if request.term < state.current_term
+            || (request.pre_vote && request.term == state.current_term)
+        {
+            return deny(state.current_term);
+        }
+
+        // Learners have no say in elections
+        if self.learner {
+            return deny(state.current_term);
+        }
+
+        // Leader stickiness: while the lease runs, the current leader is
+        // known to be alive, so nobody else gets a vote and the term stays
+        let leader_alive = state.lease.is_held(Instant::now())
+            && state.leader_id.as_ref() != Some(&request.candidate_id);
+        if leader_alive {
+            tracing::debug!(
+                "Node {} rejected {}vote from {}: leader lease held",
+                self.node_id,
+                if request.pre_vote { "pre-" } else { "" },
+                request.candidate_id
+            );
+            return deny(state.current_term);
+        }
+
+        let up_to_date = log_is_up_to_date(
+            request.last_log_term,
+            request.last_log_index,
+            state.last_log_term(),
+            state.last_log_index(),
+        );
+
+        if request.pre_vote {
+            // A pre-vote changes nothing on the voter
+            return VoteResponse {
+                term: state.current_term,
+                vote_granted: up_to_date,
+                pre_vote: true,
+            };
+        }
+
+        let term_changed = request.term > state.current_term;
+        if term_changed {
+            state.current_term = request.term;
+            state.voted_for = None;
+            state.leader_id = None;
+            state.role = RaftRole::Follower;
+            state.election = None;
+            state.lease.expire();
+            state.last_contact.clear();
+        }
+
+        let can_vote = state
+            .voted_for
+            .as_ref()
+            .is_none_or(|voted_for| *voted_for == request.candidate_id);
+        let vote_granted = can_vote && up_to_date;
+        if vote_granted {
+            state.voted_for = Some(request.candidate_id.clone());
+        }
+
+        // The new term and vote must be durable before the vote is sent
+        if term_changed || vote_granted {
+            if let Err(e) = state.persist_hard_state() {
+                tracing::error!("Failed to persist vote, rejecting request: {}", e);
+                return deny(state.current_term);
+            }
+        }
+        if vote_granted {
+            state.election_timeout =
+                Instant::now() + Duration::from_millis(150 + rand::random::<u64>() % 150);
+        }
+
+        VoteResponse {
+            term: state.current_term,
+            vote_granted,
+            pre_vote: false,
+        }
+    }
+
+    fn vote_request(&self, state: &RaftState, term: u64, pre_vote: bool) -> VoteRequest {
+        VoteRequest {
+            term,
+            candidate_id: self.node_id.clone(),
+            last_log_index: state.last_log_index(),
+            last_log_term: state.last_log_term(),
+            pre_vote,
+        }
+    }
+
+    /// Renew the leader's lease from the oldest answer among a quorum of
+    /// members heard from within the lease
+    fn renew_leader_lease(&self, state: &mut RaftState) {
+        let now = Instant::now();
+        let duration = state.lease.duration();
+        let mut recent: Vec<(&NodeId, Instant)> = state
+            .last_contact
+            .iter()
+            .filter(|(_, at)| now.saturating_duration_since(**at) < duration)
+            .filter(|(node_id, _)| !state.clock_skewed.contains(*node_id))
+            .map(|(node_id, at)| (node_id, *at))
+            .collect();
+        // Newest first, so the shortest prefix that is a quorum has the
+        // latest possible oldest contact
+        recent.sort_by_key(|(_, at)| std::cmp::Reverse(*at));
+
+        let mut acks = HashSet::from([self.node_id.clone()]);
+        let mut oldest = now;
+        for (node_id, at) in std::iter::once((&self.node_id, now)).chain(recent) {
+            acks.insert(node_id.clone());
+            oldest = oldest.min(at);
+            if state.configuration.has_quorum(&acks) {
+                state.lease.renew(oldest);
+                return;
+            }
+        }
+    }
+
+    /// Append a joint configuration for the change to the log
+    fn begin_membership_change(
+        &self,
+        state: &mut RaftState,
+        change: MembershipChange,
+    ) -> Result<()> {
+        if state.pending_config_index.is_some() {
+            return Err(ClusterError::membership(
+                "Membership change already in progress",
+            ));
+        }
+
+        let joint = state.configuration.begin_change(&change)?;
+        if let MembershipChange::AddVoter(node_id) = &change {
+            state.learners.remove(node_id);
+        }
+
+        // A configuration takes effect as soon as it is appended
+        let index = Self::append_entry(state, LogPayload::Configuration(joint.clone()))?;
+        state.configuration = joint;
+        state.pending_config_index = Some(index);
+
+        tracing::info!(
+            "Leader {} entered joint consensus for {:?} at index {}",
+            self.node_id,
+            change,
+            index
+        );
+
+        self.advance_commit_index(state)
+    }
+
+    /// Commit the highest log index replicated to a quorum of the current configuration
+    fn advance_commit_index(&self, state: &mut RaftState) -> Result<()> {
+        if state.role != RaftRole::Leader {
+            return Ok(());
+        }
+
+        let last_log_index = state.last_log_index();
+        let mut new_commit_index = state.commit_index;
+        for index in (state.commit_index + 1..=last_log_index).rev() {
+            // Only entries from the current term are committed by counting replicas
+            if state.term_at(index) != Some(state.current_term) {
+                break;
+            }
+
+            let mut acks: HashSet<NodeId> = state
+                .match_index
+                .iter()
+                .filter(|(_, &matched)| matched >= index)
+                .map(|(node_id, _)| node_id.clone())
+                .collect();
+            acks.insert(self.node_id.clone());
+
+            if state.configuration.has_quorum(&acks) {
+                new_commit_index = index;
+                break;
+            }
+        }
+
+        if new_commit_index == state.commit_index {
+            return Ok(());
+        }
+        state.commit_index = new_commit_index;
+
+        let Some(config_index) = state.pending_config_index else {
+            return Self::maybe_compact(state);
+        };
+        if config_index > state.commit_index {
+            return Ok(());
+        }
+        state.pending_config_index = None;
+
+        match state.configuration.finalize() {
+            // C_old,new is committed: move on to C_new
+            Some(new_config) => {
+                let index =
+                    Self::append_entry(state, LogPayload::Configuration(new_config.clone()))?;
+                state.configuration = new_config;
+                state.pending_config_index = Some(index);
+
+                tracing::info!(
+                    "Leader {} appended final configuration at index {}",
+                    self.node_id,
+                    index
+                );
+                self.advance_commit_index(state)
+            }
+            // C_new is committed: the membership change is complete
+            None => {
+                self.complete_membership_change(state);
+                Self::maybe_compact(state)
+            }
+        }
+    }
+
+    fn snapshot_data(state: &RaftState) -> serde_json::Value {
+        // In a real implementation, this would include the state machine
+        serde_json::json!({
+            "term": state.current_term,
+            "commit_index": state.commit_index,
+            "last_index": state.commit_index,
+            "last_term": state.term_at(state.commit_index).unwrap_or(0),
+            "cluster_members": state.configuration.voters().iter().map(|id| id.to_string()).collect::<Vec<_>>(),
+            "configuration": state.configuration,
+            "permanent_learners": state.permanent_learners,
+            "timestamp": current_timestamp()
+        })
+    }
+
+    /// Load a snapshot into `state`. When it covers log entries beyond the
+    /// current snapshot, those entries are dropped and the new snapshot
+    /// position is returned.
+    fn restore_snapshot(
+        state: &mut RaftState,
+        snapshot_data: &serde_json::Value,
+    ) -> Option<SnapshotMeta> {
+        if let Some(term) = snapshot_data["term"].as_u64() {
+            state.current_term = term;
+        }
+
+        if let Some(commit_index) = snapshot_data["commit_index"].as_u64() {
+            state.commit_index = commit_index;
+        }
+
+        if let Ok(configuration) =
+            serde_json::from_value::<Configuration>(snapshot_data["configuration"].clone())
+        {
+            state.configuration = configuration;
+            state.pending_config_index = None;
+        } else if let Some(members) = snapshot_data["cluster_members"].as_array() {
+            let voters = members
+                .iter()
+                .filter_map(|member| member.as_str())
+                .filter_map(|member| NodeId::from_string(member).ok());
+            state.configuration = Configuration::stable(voters);
+            state.pending_config_index = None;
+        }
+
+        if let Ok(learners) =
+            serde_json::from_value::<HashSet<NodeId>>(snapshot_data["permanent_learners"].clone())
+        {
+            state.learners.extend(learners.iter().cloned());
+            state.permanent_learners = learners;
+        }
+
+        let last_index = snapshot_data["last_index"].as_u64()?;
+        let last_term = snapshot_data["last_term"].as_u64().unwrap_or(0);
+        if last_index <= state.snapshot_index {
+            return None;
+        }
 
-        if state.role != RaftRole::Leader {
-            return Err(ClusterError::consensus(
-                "Only leader can append log entries",
-            ));
+        // Entries after the snapshot survive only if the logs agree on it
+        if state.term_at(last_index) == Some(last_term) {
+            state
+                .log
+                .drain(..(last_index - state.snapshot_index) as usize);
+        } else {
+            state.log.clear();
         }
+        state.snapshot_index = last_index;
+        state.snapshot_term = last_term;
 
-        let entry = LogEntry {
-            _term: state.current_term,
-            index: state.log.len() as u64 + 1,
-            _data: data,
-            _timestamp: current_timestamp(),
-        };
+        Some(SnapshotMeta {
+            last_index,
+            last_term,
+        })
+    }
 
-        let index = entry.index;
-        state.log.push(entry);
+    fn complete_membership_change(&self, state: &mut RaftState) {
+        let voters = state.configuration.voters();
+        let learners = state.learners.clone();
+        state
+            .next_index
+            .retain(|node_id, _| voters.contains(node_id) || learners.contains(node_id));
+        state
+            .match_index
+            .retain(|node_id, _| voters.contains(node_id) || learners.contains(node_id));
 
-        tracing::debug!(
-            "Leader {} appended log entry at index {}",
-            self.node_id,
-            index
+        tracing::info!(
+            "Membership change committed, cluster has {} voters",
+            voters.len()
         );
-        Ok(index)
+
+        // A leader that is no longer part of the configuration steps down
+        if !voters.contains(&self.node_id) {
+            tracing::info!(
+                "Leader {} removed from cluster, stepping down",
+                self.node_id
+            );
+            state.role = RaftRole::Follower;
+            state.leader_id = None;
+        }
     }
 }
 
@@ -224,10 +1103,13 @@ impl ConsensusEngine for RaftConsensus {
     async fn start(&self) -> Result<()> {
         tracing::info!("Starting Raft consensus engine for node {}", self.node_id);
 
-        // Add self to cluster members
-        {
+        // Bootstrap a single-voter configuration; a learner waits for the
+        // leader to add it instead
+        if !self.learner {
             let mut state = self.state.write().await;
-            state.cluster_members.insert(self.node_id.clone());
+            if state.configuration.voters().is_empty() {
+                state.configuration = Configuration::stable([self.node_id.clone()]);
+            }
         }
 
         // In a real implementation, this would start background tasks for:
@@ -276,66 +1158,76 @@ impl ConsensusEngine for RaftConsensus {
     }
 
     async fn elect_leader(&self) -> Result<NodeId> {
-        self.become_candidate().await?;
-
-        // In a real implementation, this would:
-        // - Send RequestVote RPCs to all other nodes
-        // - Wait for majority of votes
-        // - Become leader if majority votes received
-        // - Fall back to follower if another leader emerges
-
-        // For simplicity, assume we win the election if we're the only node
-        let state = self.state.read().await;
-        if state.cluster_members.len() == 1 {
-            drop(state);
-            self.become_leader().await?;
-            Ok(self.node_id.clone())
-        } else {
-            Err(ClusterError::leader_election_failed(
-                "Multi-node election not implemented",
-            ))
+        self.check_can_campaign()?;
+
+        // Multi-node elections are driven by exchanging VoteRequests through
+        // start_pre_vote/start_election; bumping the term here without them
+        // would only disrupt the current leader
+        let single_voter = {
+            let state = self.state.read().await;
+            state.configuration.voters().len() == 1
+        };
+        if !single_voter {
+            return Err(ClusterError::leader_election_failed(
+                "Multi-node election requires a vote round",
+            ));
         }
+
+        // The only voter always wins
+        self.become_candidate().await?;
+        self.become_leader().await?;
+        Ok(self.node_id.clone())
     }
 
     async fn add_node(&self, node_id: NodeId, _address: String) -> Result<()> {
         let mut state = self.state.write().await;
 
-        if state.cluster_members.contains(&node_id) {
-            return Err(ClusterError::node_already_exists(node_id.to_string()));
+        if state.role != RaftRole::Leader {
+            return Err(ClusterError::membership(
+                "Only leader can change cluster membership",
+            ));
         }
 
-        state.cluster_members.insert(node_id.clone());
-
-        if state.role == RaftRole::Leader {
-            let last_log_index = state.log.len() as u64;
-            state.next_index.insert(node_id.clone(), last_log_index + 1);
-            state.match_index.insert(node_id.clone(), 0);
+        if state.configuration.contains(&node_id) || state.learners.contains(&node_id) {
+            return Err(ClusterError::node_already_exists(node_id.to_string()));
         }
 
-        tracing::info!("Added node {} to Raft cluster", node_id);
+        // New nodes join as learners and become voters through joint
+        // consensus once they have caught up with the log
+        state.learners.insert(node_id.clone());
+        let last_log_index = state.last_log_index();
+        state.next_index.insert(node_id.clone(), last_log_index + 1);
+        state.match_index.insert(node_id.clone(), 0);
+
+        tracing::info!("Added node {} to Raft cluster as learner", node_id);
         Ok(())
     }
 
     async fn remove_node(&self, node_id: NodeId) -> Result<()> {
         let mut state = self.state.write().await;
 
-        if !state.cluster_members.contains(&node_id) {
-            return Err(ClusterError::node_not_found(node_id.to_string()));
+        // Learners do not vote, so they can be dropped directly
+        if state.learners.remove(&node_id) {
+            state.permanent_learners.remove(&node_id);
+            state.next_index.remove(&node_id);
+            state.match_index.remove(&node_id);
+            tracing::info!("Removed learner {} from Raft cluster", node_id);
+            return Ok(());
         }
 
-        state.cluster_members.remove(&node_id);
-        state.next_index.remove(&node_id);
-        state.match_index.remove(&node_id);
+        if !state.configuration.contains(&node_id) {
+            return Err(ClusterError::node_not_found(node_id.to_string()));
+        }
 
-        // If we removed the current leader, clear leadership
-        if state.leader_id.as_ref() == Some(&node_id) {
-            state.leader_id = None;
-            if state.role == RaftRole::Leader {
-                state.role = RaftRole::Follower;
-            }
+        if state.role != RaftRole::Leader {
+            return Err(ClusterError::membership(
+                "Only leader can change cluster membership",
+            ));
         }
 
-        tracing::info!("Removed node {} from Raft cluster", node_id);
+        self.begin_membership_change(&mut state, MembershipChange::RemoveVoter(node_id.clone()))?;
+
+        tracing::info!("Removing node {} from Raft cluster", node_id);
         Ok(())
     }
 
@@ -353,7 +1245,7 @@ impl ConsensusEngine for RaftConsensus {
             ));
         }
 
-        if !state.cluster_members.contains(&target) {
+        if !state.configuration.contains(&target) {
             return Err(ClusterError::node_not_found(target.to_string()));
         }
 
@@ -373,40 +1265,20 @@ impl ConsensusEngine for RaftConsensus {
 
     async fn snapshot(&self) -> Result<Vec<u8>> {
         let state = self.state.read().await;
-
-        // In a real implementation, this would create a proper snapshot
-        let snapshot_data = serde_json::json!({
-            "term": state.current_term,
-            "commit_index": state.commit_index,
-            "cluster_members": state.cluster_members.iter().map(|id| id.to_string()).collect::<Vec<_>>(),
-            "timestamp": current_timestamp()
-        });
-
-        Ok(serde_json::to_vec(&snapshot_data)?)
+        Ok(serde_json::to_vec(&Self::snapshot_data(&state))?)
     }
 
     async fn apply_snapshot(&self, snapshot: Vec<u8>) -> Result<()> {
         let snapshot_data: serde_json::Value = serde_json::from_slice(&snapshot)?;
 
         let mut state = self.state.write().await;
+        let meta = Self::restore_snapshot(&mut state, &snapshot_data);
 
-        if let Some(term) = snapshot_data["term"].as_u64() {
-            state.current_term = term;
-        }
-
-        if let Some(commit_index) = snapshot_data["commit_index"].as_u64() {
-            state.commit_index = commit_index;
-        }
-
-        if let Some(members) = snapshot_data["cluster_members"].as_array() {
-            state.cluster_members.clear();
-            for member in members {
-                if let Some(member_str) = member.as_str() {
-                    if let Ok(node_id) = NodeId::from_string(member_str) {
-                        state.cluster_members.insert(node_id);
-                    }
-                }
-            }
+        // Keep the snapshot so the entries it replaced stay covered after a restart
+        let hard_state = state.hard_state();
+        match (meta, state.wal.as_mut()) {
+            (Some(meta), Some(wal)) => wal.compact(meta, &snapshot, &hard_state)?,
+            _ => state.persist_hard_state()?,
         }
 
         tracing::info!("Applied Raft snapshot for term {}", state.current_term);
@@ -418,15 +1290,67 @@ impl ConsensusEngine for RaftConsensus {
 
         ConsensusMetrics {
             current_term: state.current_term,
-            last_log_index: state.log.len() as u64,
+            last_log_index: state.last_log_index(),
             commit_index: state.commit_index,
             leader_id: state.leader_id.clone(),
-            cluster_size: state.cluster_members.len(),
+            cluster_size: state.configuration.voters().len(),
             is_leader: state.role == RaftRole::Leader,
             election_elapsed: state.election_timeout.elapsed(),
             heartbeat_elapsed: state.heartbeat_timeout.elapsed(),
         }
     }
+
+    async fn committed_members(&self) -> Option<HashSet<NodeId>> {
+        let state = self.state.read().await;
+        if state.configuration.is_joint() || state.pending_config_index.is_some() {
+            return None;
+        }
+
+        let mut members = state.configuration.voters();
+        members.extend(state.learners.iter().cloned());
+        Some(members)
+    }
+
+    async fn current_fencing_token(&self) -> Option<FencingToken> {
+        let state = self.state.read().await;
+        if state.role != RaftRole::Leader {
+            return None;
+        }
+
+        // Without a quorum in touch another leader may already exist
+        if !state.lease.is_held(Instant::now()) {
+            tracing::warn!(
+                "Leader {} lost its lease in term {}, withholding fencing token",
+                self.node_id,
+                state.current_term
+            );
+            return None;
+        }
+
+        Some(FencingToken::from_term(state.current_term))
+    }
+
+    async fn set_clock_skewed(&self, peer: NodeId, skewed: bool) {
+        let mut state = self.state.write().await;
+        if skewed {
+            state.clock_skewed.insert(peer);
+        } else {
+            state.clock_skewed.remove(&peer);
+        }
+    }
+
+    /// Also deletes WAL segments that are no longer needed
+    async fn compact(&self) -> Result<u64> {
+        let mut state = self.state.write().await;
+        if state.pending_config_index.is_some() {
+            return Err(ClusterError::consensus(
+                "Cannot compact the log while a membership change is pending",
+            ));
+        }
+
+        Self::compact_log(&mut state)?;
+        Ok(state.snapshot_index)
+    }
 }
 
 /// PBFT consensus implementation (placeholder)
@@ -523,6 +1447,7 @@ impl ConsensusEngine for PbftConsensus {
 /// Simple consensus implementation (for testing)
 pub struct SimpleConsensus {
     node_id: NodeId,
+    learner: bool,
     state: Arc<RwLock<SimpleState>>,
 }
 
@@ -531,6 +1456,7 @@ struct SimpleState {
     is_leader: bool,
     term: u64,
     proposals: Vec<Vec<u8>>,
+    members: HashSet<NodeId>,
 }
 
 impl SimpleConsensus {
@@ -539,13 +1465,21 @@ impl SimpleConsensus {
             is_leader: false,
             term: 0,
             proposals: vec![],
+            members: HashSet::from([node_id.clone()]),
         };
 
         Self {
             node_id,
+            learner: false,
             state: Arc::new(RwLock::new(state)),
         }
     }
+
+    /// Never become leader; see [`RaftConsensus::with_learner_role`]
+    pub fn with_learner_role(mut self) -> Self {
+        self.learner = true;
+        self
+    }
 }
 
 #[async_trait]
@@ -589,6 +1523,13 @@ impl ConsensusEngine for SimpleConsensus {
     }
 
     async fn elect_leader(&self) -> Result<NodeId> {
+        if self.learner {
+            return Err(ClusterError::leader_election_failed(format!(
+                "Node {} is a learner and never stands for election",
+                self.node_id
+            )));
+        }
+
         let mut state = self.state.write().await;
         state.is_leader = true;
         state.term += 1;
@@ -601,13 +1542,16 @@ impl ConsensusEngine for SimpleConsensus {
         Ok(self.node_id.clone())
     }
 
-    async fn add_node(&self, _node_id: NodeId, _address: String) -> Result<()> {
-        // Simple consensus doesn't manage cluster membership
+    async fn add_node(&self, node_id: NodeId, _address: String) -> Result<()> {
+        // Membership changes take effect at once, there is no log to commit
+        let mut state = self.state.write().await;
+        state.members.insert(node_id);
         Ok(())
     }
 
-    async fn remove_node(&self, _node_id: NodeId) -> Result<()> {
-        // Simple consensus doesn't manage cluster membership
+    async fn remove_node(&self, node_id: NodeId) -> Result<()> {
+        let mut state = self.state.write().await;
+        state.members.remove(&node_id);
         Ok(())
     }
 
@@ -659,12 +1603,24 @@ impl ConsensusEngine for SimpleConsensus {
             } else {
                 None
             },
-            cluster_size: 1,
+            cluster_size: state.members.len(),
             is_leader: state.is_leader,
             election_elapsed: Duration::from_secs(0),
             heartbeat_elapsed: Duration::from_secs(0),
         }
     }
+
+    async fn current_fencing_token(&self) -> Option<FencingToken> {
+        let state = self.state.read().await;
+        state.is_leader.then(|| FencingToken::from_term(state.term))
+    }
+
+    /// Membership is not replicated, so only the leader's view is taken
+    /// as committed
+    async fn committed_members(&self) -> Option<HashSet<NodeId>> {
+        let state = self.state.read().await;
+        state.is_leader.then(|| state.members.clone())
+    }
 }
 
 /// Get current timestamp in seconds since UNIX epoch
@@ -745,6 +1701,348 @@ mod tests {
         assert!(raft.remove_node(new_node).await.is_err());
     }
 
+    /// Acknowledge the leader's whole log from each of the given nodes
+    async fn replicate(raft: &RaftConsensus, nodes: &[NodeId]) {
+        for node_id in nodes {
+            let last_log_index = raft.get_metrics().await.last_log_index;
+            raft.handle_append_response(node_id.clone(), last_log_index)
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Add a node and replicate until it is a committed voter
+    async fn add_voter(raft: &RaftConsensus, node_id: &NodeId, followers: &[NodeId]) {
+        raft.add_node(node_id.clone(), "127.0.0.1:8081".to_string())
+            .await
+            .unwrap();
+
+        let mut nodes = followers.to_vec();
+        nodes.push(node_id.clone());
+        while raft.is_membership_change_pending().await
+            || !raft.configuration().await.contains(node_id)
+        {
+            replicate(raft, &nodes).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raft_joint_consensus_add_voter() {
+        let leader = NodeId::new();
+        let raft = RaftConsensus::new(leader.clone()).await.unwrap();
+
+        raft.start().await.unwrap();
+        raft.elect_leader().await.unwrap();
+
+        let new_node = NodeId::new();
+        raft.add_node(new_node.clone(), "127.0.0.1:8081".to_string())
+            .await
+            .unwrap();
+
+        // Learners do not vote
+        assert_eq!(
+            raft.configuration().await,
+            Configuration::stable([leader.clone()])
+        );
+
+        // Caught-up learner is promoted through a joint configuration
+        raft.handle_append_response(new_node.clone(), 0)
+            .await
+            .unwrap();
+        assert!(raft.configuration().await.is_joint());
+        assert_eq!(raft.get_metrics().await.commit_index, 0);
+
+        // C_old,new commits with majorities of both sets, then C_new is appended
+        raft.handle_append_response(new_node.clone(), 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            raft.configuration().await,
+            Configuration::stable([leader.clone(), new_node.clone()])
+        );
+        assert!(raft.is_membership_change_pending().await);
+
+        raft.handle_append_response(new_node.clone(), 2)
+            .await
+            .unwrap();
+        assert!(!raft.is_membership_change_pending().await);
+
+        let metrics = raft.get_metrics().await;
+        assert_eq!(metrics.commit_index, 2);
+        assert_eq!(metrics.cluster_size, 2);
+
+        // Commands now need the new voter's acknowledgement
+        raft.propose(b"after join".to_vec()).await.unwrap();
+        assert_eq!(raft.get_metrics().await.commit_index, 2);
+        raft.handle_append_response(new_node, 3).await.unwrap();
+        assert_eq!(raft.get_metrics().await.commit_index, 3);
+    }
+
+    #[tokio::test]
+    async fn test_raft_learner_is_never_promoted() {
+        let leader = NodeId::new();
+        let raft = RaftConsensus::new(leader.clone()).await.unwrap();
+        raft.start().await.unwrap();
+        raft.elect_leader().await.unwrap();
+
+        let reporter = NodeId::new();
+        raft.add_learner(reporter.clone()).await.unwrap();
+        assert!(raft.add_learner(reporter.clone()).await.is_err());
+
+        // Caught up, yet still outside the voter configuration
+        replicate(&raft, std::slice::from_ref(&reporter)).await;
+        assert!(!raft.is_membership_change_pending().await);
+        assert_eq!(
+            raft.configuration().await,
+            Configuration::stable([leader.clone()])
+        );
+        assert!(raft.committed_members().await.unwrap().contains(&reporter));
+
+        // Commits need no acknowledgement from the learner
+        raft.propose(b"report".to_vec()).await.unwrap();
+        assert_eq!(raft.get_metrics().await.commit_index, 1);
+
+        // A new leader restored from a snapshot still knows the learner
+        let snapshot = raft.snapshot().await.unwrap();
+        let successor = RaftConsensus::new(leader).await.unwrap();
+        successor.apply_snapshot(snapshot).await.unwrap();
+        assert!(successor.learners().await.contains(&reporter));
+
+        raft.remove_node(reporter.clone()).await.unwrap();
+        assert!(!raft.learners().await.contains(&reporter));
+    }
+
+    #[tokio::test]
+    async fn test_raft_learner_never_votes() {
+        let learner = RaftConsensus::new(NodeId::new())
+            .await
+            .unwrap()
+            .with_learner_role();
+        learner.start().await.unwrap();
+
+        assert!(learner.configuration().await.voters().is_empty());
+        assert!(learner.elect_leader().await.is_err());
+        assert!(learner.start_pre_vote().await.is_err());
+        assert!(learner.start_election().await.is_err());
+
+        let response = learner
+            .handle_vote_request(VoteRequest {
+                term: 1,
+                candidate_id: NodeId::new(),
+                last_log_index: 0,
+                last_log_term: 0,
+                pre_vote: false,
+            })
+            .await;
+        assert!(!response.vote_granted);
+        assert_eq!(learner.get_term().await, 0);
+
+        let simple = SimpleConsensus::new(NodeId::new()).with_learner_role();
+        assert!(simple.elect_leader().await.is_err());
+        assert!(!simple.is_leader().await);
+    }
+
+    #[tokio::test]
+    async fn test_raft_single_membership_change_at_a_time() {
+        let raft = RaftConsensus::new(NodeId::new()).await.unwrap();
+
+        raft.start().await.unwrap();
+        raft.elect_leader().await.unwrap();
+
+        let first = NodeId::new();
+        let second = NodeId::new();
+        add_voter(&raft, &first, &[]).await;
+        add_voter(&raft, &second, std::slice::from_ref(&first)).await;
+
+        // The leader alone is not a majority of either {leader, first, second}
+        // or {leader, first}, so the removal stays pending
+        raft.remove_node(second.clone()).await.unwrap();
+        assert!(raft.is_membership_change_pending().await);
+
+        let err = raft.remove_node(first.clone()).await.unwrap_err();
+        assert!(matches!(err, ClusterError::Membership(_)));
+
+        // Joint configuration, then the final configuration
+        replicate(&raft, std::slice::from_ref(&first)).await;
+        replicate(&raft, &[first]).await;
+
+        assert!(!raft.is_membership_change_pending().await);
+        assert!(!raft.configuration().await.contains(&second));
+        assert_eq!(raft.get_metrics().await.cluster_size, 2);
+    }
+
+    #[tokio::test]
+    async fn test_raft_removed_leader_steps_down() {
+        let leader = NodeId::new();
+        let raft = RaftConsensus::new(leader.clone()).await.unwrap();
+
+        raft.start().await.unwrap();
+        raft.elect_leader().await.unwrap();
+
+        let follower = NodeId::new();
+        add_voter(&raft, &follower, &[]).await;
+
+        // The leader keeps managing the change until C_new commits
+        raft.remove_node(leader).await.unwrap();
+        assert!(raft.is_leader().await);
+
+        while raft.is_membership_change_pending().await {
+            replicate(&raft, std::slice::from_ref(&follower)).await;
+        }
+
+        assert_eq!(
+            raft.configuration().await,
+            Configuration::stable([follower])
+        );
+        assert!(!raft.is_leader().await);
+    }
+
+    /// Raft engines sharing one three-voter configuration
+    async fn three_voters(lease_duration: Duration) -> Vec<RaftConsensus> {
+        let ids = [NodeId::new(), NodeId::new(), NodeId::new()];
+        let config = ElectionConfig {
+            lease_duration,
+            ..ElectionConfig::default()
+        };
+
+        let mut nodes = Vec::new();
+        for id in &ids {
+            let raft = RaftConsensus::with_election_config(id.clone(), config.clone())
+                .await
+                .unwrap();
+            raft.state.write().await.configuration = Configuration::stable(ids.clone());
+            nodes.push(raft);
+        }
+        nodes
+    }
+
+    /// Run a pre-vote and then an election for `candidate`
+    async fn campaign(candidate: &RaftConsensus, voters: &[&RaftConsensus]) -> Result<bool> {
+        let request = candidate.start_pre_vote().await?;
+        let mut pre_vote_won = false;
+        for voter in voters {
+            let response = voter.handle_vote_request(request.clone()).await;
+            pre_vote_won |= candidate
+                .handle_vote_response(voter.node_id.clone(), response)
+                .await?;
+        }
+        if !pre_vote_won {
+            return Ok(false);
+        }
+
+        let request = candidate.start_election().await?;
+        let mut elected = false;
+        for voter in voters {
+            let response = voter.handle_vote_request(request.clone()).await;
+            elected |= candidate
+                .handle_vote_response(voter.node_id.clone(), response)
+                .await?;
+        }
+        Ok(elected)
+    }
+
+    #[tokio::test]
+    async fn test_raft_pre_vote_protects_leader() {
+        let nodes = three_voters(Duration::from_secs(5)).await;
+        let (a, b, c) = (&nodes[0], &nodes[1], &nodes[2]);
+
+        assert!(campaign(a, &[b, c]).await.unwrap());
+        assert!(a.is_leader().await);
+        assert_eq!(a.get_term().await, 1);
+        b.handle_heartbeat(a.node_id.clone(), 1).await.unwrap();
+
+        // c is partitioned from a but can still reach b
+        let request = c.start_pre_vote().await.unwrap();
+        assert!(request.pre_vote);
+        assert_eq!(request.term, 2);
+        let response = b.handle_vote_request(request).await;
+        assert!(!response.vote_granted);
+        assert!(!c
+            .handle_vote_response(b.node_id.clone(), response)
+            .await
+            .unwrap());
+
+        // Without a won pre-vote c never becomes a candidate
+        assert!(c.start_election().await.is_err());
+        assert_eq!(c.get_term().await, 1);
+        assert_eq!(b.get_term().await, 1);
+
+        // Even a real vote at a higher term is refused while the lease runs
+        let vote = VoteRequest {
+            term: 5,
+            candidate_id: c.node_id.clone(),
+            last_log_index: 0,
+            last_log_term: 0,
+            pre_vote: false,
+        };
+        assert!(!b.handle_vote_request(vote).await.vote_granted);
+        assert_eq!(b.get_term().await, 1);
+        assert_eq!(b.get_leader().await, Some(a.node_id.clone()));
+        assert!(a.is_leader().await);
+    }
+
+    #[tokio::test]
+    async fn test_raft_leader_lease() {
+        let lease = Duration::from_millis(300);
+        let nodes = three_voters(lease).await;
+        let (a, b, c) = (&nodes[0], &nodes[1], &nodes[2]);
+
+        assert!(campaign(a, &[b, c]).await.unwrap());
+        assert!(a.has_leader_lease().await);
+        b.handle_heartbeat(a.node_id.clone(), 1).await.unwrap();
+        assert!(b.has_leader_lease().await);
+
+        // An answer from one follower is a quorum with the leader
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        a.handle_append_response(b.node_id.clone(), 0)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(a.check_quorum().await);
+
+        // Once followers stop answering the leader steps down
+        tokio::time::sleep(lease).await;
+        assert!(!a.has_leader_lease().await);
+        assert!(!b.has_leader_lease().await);
+        assert!(!a.check_quorum().await);
+        assert!(!a.is_leader().await);
+
+        // ...and the rest of the cluster may elect someone else
+        assert!(campaign(c, &[a, b]).await.unwrap());
+        assert!(c.is_leader().await);
+        assert_eq!(c.get_term().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_raft_clock_skew_blocks_lease_extension() {
+        let lease = Duration::from_millis(300);
+        let nodes = three_voters(lease).await;
+        let (a, b, c) = (&nodes[0], &nodes[1], &nodes[2]);
+
+        assert!(campaign(a, &[b, c]).await.unwrap());
+        a.set_clock_skewed(b.node_id.clone(), true).await;
+        a.set_clock_skewed(c.node_id.clone(), true).await;
+        b.set_clock_skewed(a.node_id.clone(), true).await;
+
+        // Answers from skewed followers no longer extend the leader's lease
+        tokio::time::sleep(lease).await;
+        a.handle_append_response(b.node_id.clone(), 0)
+            .await
+            .unwrap();
+        assert!(!a.has_leader_lease().await);
+
+        // ...nor does a skewed leader's heartbeat extend the follower's
+        b.handle_heartbeat(a.node_id.clone(), 1).await.unwrap();
+        assert_eq!(b.get_leader().await, Some(a.node_id.clone()));
+        assert!(!b.has_leader_lease().await);
+
+        a.set_clock_skewed(b.node_id.clone(), false).await;
+        a.handle_append_response(b.node_id.clone(), 0)
+            .await
+            .unwrap();
+        assert!(a.has_leader_lease().await);
+    }
+
     #[tokio::test]
     async fn test_raft_snapshot() {
         let node_id = NodeId::new();
@@ -760,6 +2058,104 @@ mod tests {
         assert!(raft.apply_snapshot(snapshot).await.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_raft_recovers_from_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let node_id = NodeId::new();
+
+        {
+            let raft = RaftConsensus::open(
+                node_id.clone(),
+                ElectionConfig::default(),
+                dir.path(),
+                WalConfig::default(),
+            )
+            .await
+            .unwrap();
+            raft.start().await.unwrap();
+            raft.elect_leader().await.unwrap();
+            raft.propose(b"first".to_vec()).await.unwrap();
+            raft.propose(b"second".to_vec()).await.unwrap();
+        }
+
+        let raft = RaftConsensus::open(
+            node_id.clone(),
+            ElectionConfig::default(),
+            dir.path(),
+            WalConfig::default(),
+        )
+        .await
+        .unwrap();
+        let metrics = raft.get_metrics().await;
+        assert_eq!(metrics.current_term, 1);
+        assert_eq!(metrics.last_log_index, 2);
+        assert!(!metrics.is_leader);
+
+        // The vote cast for itself in term 1 is remembered
+        let response = raft
+            .handle_vote_request(VoteRequest {
+                term: 1,
+                candidate_id: NodeId::new(),
+                last_log_index: 2,
+                last_log_term: 1,
+                pre_vote: false,
+            })
+            .await;
+        assert!(!response.vote_granted);
+
+        raft.start().await.unwrap();
+        raft.elect_leader().await.unwrap();
+        assert_eq!(raft.get_term().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_raft_compacts_log_into_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let node_id = NodeId::new();
+        let wal_config = WalConfig {
+            snapshot_threshold: 3,
+            ..WalConfig::default()
+        };
+
+        {
+            let raft = RaftConsensus::open(
+                node_id.clone(),
+                ElectionConfig::default(),
+                dir.path(),
+                wal_config.clone(),
+            )
+            .await
+            .unwrap();
+            raft.start().await.unwrap();
+            raft.elect_leader().await.unwrap();
+            for i in 0..4u8 {
+                raft.propose(vec![i]).await.unwrap();
+            }
+
+            // Three committed entries triggered a snapshot; one follows it
+            let state = raft.state.read().await;
+            assert_eq!(state.snapshot_index, 3);
+            assert_eq!(state.log.len(), 1);
+        }
+
+        let raft = RaftConsensus::open(
+            node_id.clone(),
+            ElectionConfig::default(),
+            dir.path(),
+            wal_config,
+        )
+        .await
+        .unwrap();
+        let metrics = raft.get_metrics().await;
+        assert_eq!(metrics.last_log_index, 4);
+        assert_eq!(metrics.commit_index, 3);
+        assert_eq!(
+            raft.configuration().await.voters(),
+            HashSet::from([node_id])
+        );
+        assert_eq!(raft.compact().await.unwrap(), 3);
+    }
+
     #[tokio::test]
     async fn test_simple_consensus() {
         let node_id = NodeId::new();
@@ -774,16 +2170,33 @@ mod tests {
         let leader = simple.elect_leader().await.unwrap();
         assert_eq!(leader, node_id);
         assert!(simple.is_leader().await);
-        assert_eq!(simple.get_leader().await, Some(node_id));
+        assert_eq!(simple.get_leader().await, Some(node_id.clone()));
 
         // Propose as leader
         let data = b"simple proposal".to_vec();
         assert!(simple.propose(data).await.is_ok());
 
+        // Membership
+        let peer = NodeId::new();
+        simple
+            .add_node(peer.clone(), "127.0.0.1:7001".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            simple.committed_members().await,
+            Some(HashSet::from([node_id.clone(), peer.clone()]))
+        );
+        simple.remove_node(peer).await.unwrap();
+        assert_eq!(
+            simple.committed_members().await,
+            Some(HashSet::from([node_id]))
+        );
+
         // Transfer leadership
         let target = NodeId::new();
         assert!(simple.transfer_leadership(target).await.is_ok());
         assert!(!simple.is_leader().await);
+        assert_eq!(simple.committed_members().await, None);
     }
 
     #[tokio::test]
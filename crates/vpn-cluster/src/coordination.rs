@@ -1,25 +1,38 @@
 //! Cluster coordination and event management
 
+use crate::clock::{ClockSample, ClockSkewMonitor, SkewChange};
+use crate::communication::ClusterGrpcClient;
 use crate::config::ClusterConfig;
-use crate::consensus::{ConsensusEngine, SimpleConsensus};
+use crate::consensus::ConsensusEngine;
 use crate::error::{ClusterError, Result};
-use crate::node::{Node, NodeId, NodeStatus};
-use crate::state::ClusterState;
+use crate::events::ClusterEventBus;
+use crate::node::{Node, NodeId, NodeLoad, NodeStatus};
+use crate::restart::{
+    DockerServiceController, RollingRestartReport, ServiceController, ServiceStatus,
+};
+use crate::selector::{self, LabelSelector};
+use crate::state::{ClusterState, ScalePlan, DEFAULT_TOMBSTONE_TTL};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{broadcast, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio::task::JoinHandle;
 
 /// Coordinates cluster operations and manages events
 pub struct ClusterCoordinator {
     node_id: NodeId,
     config: ClusterConfig,
     state: Arc<RwLock<ClusterState>>,
-    consensus: Arc<SimpleConsensus>,
+    consensus: Arc<dyn ConsensusEngine>,
     event_tx: broadcast::Sender<CoordinationEvent>,
     active_operations: Arc<RwLock<HashMap<String, OperationStatus>>>,
+    client: ClusterGrpcClient,
+    services: Arc<dyn ServiceController>,
+    clock: Arc<RwLock<ClockSkewMonitor>>,
 }
 
 impl ClusterCoordinator {
@@ -28,20 +41,43 @@ impl ClusterCoordinator {
         node_id: NodeId,
         config: ClusterConfig,
         state: Arc<RwLock<ClusterState>>,
-        consensus: Arc<SimpleConsensus>,
+        consensus: Arc<dyn ConsensusEngine>,
     ) -> Result<Self> {
         let (event_tx, _) = broadcast::channel(1000);
 
         Ok(Self {
+            client: ClusterGrpcClient::new(node_id.clone()),
+            clock: Arc::new(RwLock::new(ClockSkewMonitor::new(
+                config.clock_skew.clone(),
+            ))),
             node_id,
             config,
             state,
             consensus,
             event_tx,
             active_operations: Arc::new(RwLock::new(HashMap::new())),
+            services: Arc::new(DockerServiceController),
         })
     }
 
+    /// Reach other nodes through `client`, e.g. one configured for TLS
+    pub fn with_client(mut self, client: ClusterGrpcClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Restart this node's services through `services` instead of the
+    /// Docker CLI. Set it before the node's gRPC server starts so peers
+    /// reach the same controller.
+    pub fn set_service_controller(&mut self, services: Arc<dyn ServiceController>) {
+        self.services = services;
+    }
+
+    /// Controller restarting this node's services
+    pub fn service_controller(&self) -> Arc<dyn ServiceController> {
+        self.services.clone()
+    }
+
     /// Start the coordinator
     pub async fn start(&mut self) -> Result<()> {
         tracing::info!("Starting cluster coordinator for node {}", self.node_id);
@@ -158,6 +194,107 @@ impl ClusterCoordinator {
         Ok(())
     }
 
+    /// Record a node's load and drain or re-admit it as its health score
+    /// crosses the configured thresholds. Returns the new score.
+    pub async fn report_node_load(&self, node_id: &NodeId, load: NodeLoad) -> Result<u8> {
+        let scoring = &self.config.health_scoring;
+
+        let (score, transition) = {
+            let mut state = self.state.write().await;
+            let node = state
+                .get_node_mut(node_id)
+                .ok_or_else(|| ClusterError::node_not_found(node_id.to_string()))?;
+            let score = node.record_load(&load, scoring);
+
+            let transition = match node.status {
+                NodeStatus::Healthy if scoring.auto_drain && score < scoring.drain_below => {
+                    Some(NodeStatus::Draining)
+                }
+                NodeStatus::Draining if score >= scoring.restore_above => Some(NodeStatus::Healthy),
+                _ => None,
+            };
+            if let Some(status) = &transition {
+                node.set_status(status.clone());
+            }
+
+            (score, transition)
+        };
+
+        let timestamp = current_timestamp();
+        match transition {
+            Some(NodeStatus::Draining) => {
+                tracing::warn!(
+                    "Draining node {}: health score {} below {}",
+                    node_id,
+                    score,
+                    scoring.drain_below
+                );
+                let _ = self.event_tx.send(CoordinationEvent::NodeDraining {
+                    node_id: node_id.clone(),
+                    health_score: score,
+                    timestamp,
+                });
+            }
+            Some(_) => {
+                tracing::info!("Node {} re-admitted with health score {}", node_id, score);
+                let _ = self.event_tx.send(CoordinationEvent::NodeReadmitted {
+                    node_id: node_id.clone(),
+                    health_score: score,
+                    timestamp,
+                });
+            }
+            None => {}
+        }
+
+        Ok(score)
+    }
+
+    /// Node new VPN users should be routed to; draining nodes are skipped
+    /// and nodes in this node's zone are preferred
+    pub async fn select_node_for_user(&self) -> Option<Node> {
+        self.state
+            .read()
+            .await
+            .select_node_for_user_near(&self.config.placement())
+            .cloned()
+    }
+
+    /// Nodes to hold `replicas` copies of a user, spread across zones with
+    /// the first copy in this node's zone for local reads
+    pub async fn place_user_replicas(&self, replicas: usize) -> Vec<Node> {
+        self.state
+            .read()
+            .await
+            .place_user_replicas(replicas, &self.config.placement())
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Value of a replicated config entry on this node: pinned entries
+    /// resolve against this node's labels, and `None` means the entry does
+    /// not apply here
+    pub fn resolve_config(&self, raw: serde_json::Value) -> Option<serde_json::Value> {
+        selector::resolve_config(raw, &self.config.node_labels())
+    }
+
+    /// Known nodes that configuration pinned to `selector` applies to
+    pub async fn nodes_matching(&self, selector: &LabelSelector) -> Vec<Node> {
+        self.state
+            .read()
+            .await
+            .nodes
+            .values()
+            .filter(|node| selector.matches(&node.labels()))
+            .cloned()
+            .collect()
+    }
+
+    /// Zone-aware plan for scaling to `target_nodes`
+    pub async fn plan_scale(&self, target_nodes: usize) -> ScalePlan {
+        self.state.read().await.plan_scale(target_nodes)
+    }
+
     /// Scale cluster to target number of nodes
     pub async fn scale_cluster(&mut self, target_nodes: usize) -> Result<()> {
         let current_size = {
@@ -170,22 +307,129 @@ impl ClusterCoordinator {
             return Ok(());
         }
 
+        let plan = self.plan_scale(target_nodes).await;
         if target_nodes > current_size {
-            let scale_up_count = target_nodes - current_size;
-            tracing::info!("Scaling up cluster by {} nodes", scale_up_count);
-            self.scale_up(scale_up_count).await
+            tracing::info!("Scaling up cluster by {} nodes", plan.add.len());
+            self.scale_up(plan).await
         } else {
-            let scale_down_count = current_size - target_nodes;
-            tracing::info!("Scaling down cluster by {} nodes", scale_down_count);
-            self.scale_down(scale_down_count).await
+            tracing::info!("Scaling down cluster by {} nodes", plan.remove.len());
+            self.scale_down(plan).await
         }
     }
 
+    /// Restart `service` on every node, one node at a time: the node is
+    /// drained, its service restarted, and it is re-admitted once healthy.
+    /// The rollout stops at the first node that does not come back healthy,
+    /// leaving that node drained. Must be started on the leader; the leader
+    /// restarts itself last.
+    pub async fn rolling_restart(&self, service: &str) -> Result<RollingRestartReport> {
+        if !self.consensus.is_leader().await {
+            return Err(ClusterError::coordination(
+                "Rolling restarts must be started on the leader",
+            ));
+        }
+
+        let operation_id = format!("rolling_restart_{}", uuid::Uuid::new_v4());
+        let description = format!("rolling_restart:{}", service);
+        {
+            let mut operations = self.active_operations.write().await;
+            if operations.values().any(|status| {
+                status.state == OperationState::Running && status.description == description
+            }) {
+                return Err(ClusterError::invalid_state(format!(
+                    "A rolling restart of {} is already running",
+                    service
+                )));
+            }
+            operations.insert(
+                operation_id.clone(),
+                OperationStatus {
+                    id: operation_id.clone(),
+                    description,
+                    state: OperationState::Running,
+                    started_at: current_timestamp(),
+                    completed_at: None,
+                    error: None,
+                },
+            );
+        }
+
+        let nodes: Vec<(NodeId, SocketAddr)> = {
+            let state = self.state.read().await;
+            let mut nodes: Vec<&Node> = state
+                .get_all_nodes()
+                .into_iter()
+                .filter(|node| matches!(node.status, NodeStatus::Healthy | NodeStatus::Draining))
+                .collect();
+            nodes.sort_by_key(|node| (node.id == self.node_id, node.name.clone()));
+            nodes
+                .into_iter()
+                .map(|node| (node.id.clone(), node.address))
+                .collect()
+        };
+
+        let mut report = RollingRestartReport {
+            service: service.to_string(),
+            ..Default::default()
+        };
+        for (node_id, address) in nodes {
+            tracing::info!("Rolling restart of {}: node {}", service, node_id);
+            if let Err(e) = self
+                .restart_node_service(&node_id, address, service, &mut report)
+                .await
+            {
+                tracing::error!(
+                    "Rolling restart of {} stopped at node {}: {}",
+                    service,
+                    node_id,
+                    e
+                );
+                let mut operations = self.active_operations.write().await;
+                if let Some(status) = operations.get_mut(&operation_id) {
+                    status.state = OperationState::Failed;
+                    status.completed_at = Some(current_timestamp());
+                    status.error = Some(e.to_string());
+                }
+                return Err(e);
+            }
+            report.restarted.push(node_id);
+        }
+
+        self.complete_operation(operation_id, OperationState::Completed)
+            .await?;
+        Ok(report)
+    }
+
     /// Subscribe to coordination events
     pub fn subscribe_to_events(&self) -> broadcast::Receiver<CoordinationEvent> {
         self.event_tx.subscribe()
     }
 
+    /// Record a clock offset measured against `peer`. Crossing the skew
+    /// limit raises an alert and stops the peer's contact from extending
+    /// leader leases until its offset is back within the limit.
+    pub async fn record_clock_sample(&self, peer: NodeId, sample: ClockSample) {
+        Self::apply_clock_sample(
+            &self.clock,
+            self.consensus.as_ref(),
+            &self.event_tx,
+            peer,
+            sample,
+        )
+        .await;
+    }
+
+    /// Latest clock offset to every peer, for skew metrics
+    pub async fn clock_skew(&self) -> ClockSkewMonitor {
+        self.clock.read().await.clone()
+    }
+
+    /// Handle on the event bus, for publishing events from other components
+    /// and for consumers outside this crate
+    pub fn event_bus(&self) -> ClusterEventBus {
+        ClusterEventBus::from(self.event_tx.clone())
+    }
+
     /// Get current operation status
     pub async fn get_operation_status(&self, operation_id: &str) -> Option<OperationStatus> {
         let operations = self.active_operations.read().await;
@@ -229,6 +473,10 @@ impl ClusterCoordinator {
         let node_id = self.node_id.clone();
         let state = self.state.clone();
         let interval = self.config.heartbeat_interval;
+        let client = self.client.clone();
+        let clock = self.clock.clone();
+        let consensus = self.consensus.clone();
+        let event_tx = self.event_tx.clone();
 
         tokio::spawn(async move {
             let mut interval_timer = tokio::time::interval(interval);
@@ -237,12 +485,40 @@ impl ClusterCoordinator {
                 interval_timer.tick().await;
 
                 // Update our last seen timestamp
-                {
+                let (resources, peers) = {
                     let mut cluster_state = state.write().await;
                     cluster_state.update_node_last_seen(&node_id);
-                }
+                    let resources = cluster_state
+                        .nodes
+                        .get(&node_id)
+                        .map(|node| node.resources.clone())
+                        .unwrap_or_default();
+                    let peers: Vec<(NodeId, SocketAddr)> = cluster_state
+                        .nodes
+                        .values()
+                        .filter(|node| node.id != node_id && node.status != NodeStatus::Failed)
+                        .map(|node| (node.id.clone(), node.address))
+                        .collect();
+                    (resources, peers)
+                };
 
-                // In a real implementation, this would send heartbeats to other nodes
+                // Each heartbeat also measures the peer's clock offset
+                for (peer, address) in peers {
+                    match client.send_heartbeat(address, resources.clone()).await {
+                        Ok((_, Some(sample))) => {
+                            Self::apply_clock_sample(
+                                &clock,
+                                consensus.as_ref(),
+                                &event_tx,
+                                peer,
+                                sample,
+                            )
+                            .await;
+                        }
+                        Ok((_, None)) => {}
+                        Err(e) => tracing::debug!("Heartbeat to {} failed: {}", peer, e),
+                    }
+                }
                 tracing::trace!("Heartbeat sent from node {}", node_id);
             }
         });
@@ -250,6 +526,52 @@ impl ClusterCoordinator {
         Ok(())
     }
 
+    async fn apply_clock_sample(
+        clock: &RwLock<ClockSkewMonitor>,
+        consensus: &dyn ConsensusEngine,
+        event_tx: &broadcast::Sender<CoordinationEvent>,
+        peer: NodeId,
+        sample: ClockSample,
+    ) {
+        let (change, limit) = {
+            let mut clock = clock.write().await;
+            (clock.record(peer.clone(), sample), clock.limit())
+        };
+
+        let event = match change {
+            Some(SkewChange::Exceeded) => {
+                tracing::warn!(
+                    "Clock of node {} is {}ms off (limit {}ms); its contact no longer extends leases",
+                    peer,
+                    sample.offset_ms,
+                    limit.as_millis()
+                );
+                consensus.set_clock_skewed(peer.clone(), true).await;
+                CoordinationEvent::ClockSkewDetected {
+                    node_id: peer,
+                    offset_ms: sample.offset_ms,
+                    timestamp: current_timestamp(),
+                }
+            }
+            Some(SkewChange::Recovered) => {
+                tracing::info!(
+                    "Clock of node {} is back within {}ms ({}ms off)",
+                    peer,
+                    limit.as_millis(),
+                    sample.offset_ms
+                );
+                consensus.set_clock_skewed(peer.clone(), false).await;
+                CoordinationEvent::ClockSkewResolved {
+                    node_id: peer,
+                    offset_ms: sample.offset_ms,
+                    timestamp: current_timestamp(),
+                }
+            }
+            None => return,
+        };
+        let _ = event_tx.send(event);
+    }
+
     async fn start_failure_detection_task(&self) -> Result<()> {
         let state = self.state.clone();
         let interval = self.config.heartbeat_interval * 3; // Check every 3 heartbeat intervals
@@ -264,6 +586,8 @@ impl ClusterCoordinator {
                 {
                     let mut cluster_state = state.write().await;
                     cluster_state.detect_failed_nodes(timeout);
+                    cluster_state.reap_failed_nodes(timeout * 10, DEFAULT_TOMBSTONE_TTL);
+                    cluster_state.purge_expired_tombstones();
                 }
             }
         });
@@ -318,16 +642,45 @@ impl ClusterCoordinator {
         // 2. Add ourselves to the cluster
         // 3. Start participating in consensus
 
-        let our_node = Node::new(self.config.node_name.clone(), self.config.bind_address);
-
         {
             let mut state = self.state.write().await;
-            state.add_node(our_node)?;
+            let listed = state.get_node(&self.node_id).cloned();
+            let tombstoned = state.get_tombstone(&self.node_id).map(|t| t.incarnation);
+            let left = listed
+                .as_ref()
+                .is_some_and(|node| node.status == NodeStatus::Stopping);
+
+            // Already listed unless it left or was removed since
+            if listed.is_none() || left || tombstoned.is_some() {
+                let mut our_node = listed.unwrap_or_else(|| {
+                    Node::with_id(
+                        self.node_id.clone(),
+                        self.config.node_name.clone(),
+                        self.config.bind_address,
+                    )
+                });
+
+                // A node rejoining after it left or was removed comes back at
+                // a higher incarnation, or its tombstone would keep it out
+                if left || tombstoned.is_some() {
+                    our_node.incarnation = our_node.incarnation.max(tombstoned.unwrap_or(0));
+                    our_node.bump_incarnation();
+                    our_node.set_status(NodeStatus::Starting);
+                    let _ = state.remove_node(&self.node_id);
+                }
+
+                state.add_node(our_node)?;
+            }
         }
 
-        self.consensus
-            .add_node(self.node_id.clone(), self.config.bind_address.to_string())
-            .await?;
+        // Consensus membership is the leader's to change; it admits us while
+        // serving our join request
+
+        let event = CoordinationEvent::NodeJoined {
+            node_id: self.node_id.clone(),
+            timestamp: current_timestamp(),
+        };
+        let _ = self.event_tx.send(event);
 
         tracing::info!("Successfully integrated with cluster");
         Ok(())
@@ -392,13 +745,18 @@ impl ClusterCoordinator {
         Ok(())
     }
 
-    async fn scale_up(&mut self, count: usize) -> Result<()> {
+    async fn scale_up(&mut self, plan: ScalePlan) -> Result<()> {
         let operation_id = format!("scale_up_{}", uuid::Uuid::new_v4());
-        self.start_operation(operation_id.clone(), format!("scale_up_{}", count))
+        self.start_operation(operation_id.clone(), format!("scale_up_{}", plan.add.len()))
             .await?;
 
+        for placement in &plan.add {
+            tracing::info!("Scale up {}: new node in {}", operation_id, placement);
+        }
+
         // In a real implementation, this would:
         // 1. Request new nodes from orchestrator (Kubernetes, Docker Swarm, etc.)
+        //    in the planned zones
         // 2. Wait for nodes to come online
         // 3. Add them to the cluster
 
@@ -409,15 +767,21 @@ impl ClusterCoordinator {
         Ok(())
     }
 
-    async fn scale_down(&mut self, count: usize) -> Result<()> {
+    async fn scale_down(&mut self, plan: ScalePlan) -> Result<()> {
         let operation_id = format!("scale_down_{}", uuid::Uuid::new_v4());
-        self.start_operation(operation_id.clone(), format!("scale_down_{}", count))
-            .await?;
+        self.start_operation(
+            operation_id.clone(),
+            format!("scale_down_{}", plan.remove.len()),
+        )
+        .await?;
+
+        for node_id in &plan.remove {
+            tracing::info!("Scale down {}: retiring node {}", operation_id, node_id);
+        }
 
         // In a real implementation, this would:
-        // 1. Select nodes to remove (prefer non-voting or unhealthy nodes)
-        // 2. Gracefully remove them from consensus
-        // 3. Signal orchestrator to terminate the nodes
+        // 1. Gracefully remove them from consensus
+        // 2. Signal orchestrator to terminate the nodes
 
         tracing::info!(
             "Scale down operation {} not fully implemented",
@@ -429,6 +793,106 @@ impl ClusterCoordinator {
         Ok(())
     }
 
+    /// One step of a rolling restart: drain, restart, wait for health,
+    /// re-admit
+    async fn restart_node_service(
+        &self,
+        node_id: &NodeId,
+        address: SocketAddr,
+        service: &str,
+        report: &mut RollingRestartReport,
+    ) -> Result<()> {
+        let settings = &self.config.rolling_restart;
+
+        let previous = {
+            let mut state = self.state.write().await;
+            let node = state
+                .get_node_mut(node_id)
+                .ok_or_else(|| ClusterError::node_not_found(node_id.to_string()))?;
+            let previous = node.status.clone();
+            node.set_status(NodeStatus::Draining);
+            previous
+        };
+        let _ = self.event_tx.send(CoordinationEvent::ServiceRestarting {
+            node_id: node_id.clone(),
+            service: service.to_string(),
+            timestamp: current_timestamp(),
+        });
+
+        // Existing sessions get until the drain timeout to finish
+        let deadline = Instant::now() + settings.drain_timeout;
+        loop {
+            let status = self.service_status(node_id, address, service).await?;
+            match status.active_connections {
+                None | Some(0) => break,
+                Some(open) if Instant::now() >= deadline => {
+                    tracing::warn!(
+                        "Restarting {} on node {} with {} connections still open",
+                        service,
+                        node_id,
+                        open
+                    );
+                    report.drain_timeouts.push(node_id.clone());
+                    break;
+                }
+                Some(_) => tokio::time::sleep(settings.poll_interval).await,
+            }
+        }
+
+        if node_id == &self.node_id {
+            self.services.restart(service).await?;
+        } else {
+            self.client.restart_service(address, service).await?;
+        }
+
+        let deadline = Instant::now() + settings.health_timeout;
+        loop {
+            tokio::time::sleep(settings.poll_interval).await;
+            // The node may not answer while its service comes back up
+            match self.service_status(node_id, address, service).await {
+                Ok(status) if status.healthy => break,
+                Ok(_) => {}
+                Err(e) => tracing::debug!("Health check of node {} failed: {}", node_id, e),
+            }
+            if Instant::now() >= deadline {
+                return Err(ClusterError::timeout(format!(
+                    "{} on node {} not healthy after {:?}",
+                    service, node_id, settings.health_timeout
+                )));
+            }
+        }
+
+        // Leave the node alone if something else changed its status meanwhile
+        {
+            let mut state = self.state.write().await;
+            if let Some(node) = state.get_node_mut(node_id) {
+                if node.status == NodeStatus::Draining {
+                    node.set_status(previous);
+                }
+            }
+        }
+        let _ = self.event_tx.send(CoordinationEvent::ServiceRestarted {
+            node_id: node_id.clone(),
+            service: service.to_string(),
+            timestamp: current_timestamp(),
+        });
+
+        Ok(())
+    }
+
+    async fn service_status(
+        &self,
+        node_id: &NodeId,
+        address: SocketAddr,
+        service: &str,
+    ) -> Result<ServiceStatus> {
+        if node_id == &self.node_id {
+            self.services.status(service).await
+        } else {
+            self.client.get_service_status(address, service).await
+        }
+    }
+
     async fn start_operation(&self, operation_id: String, description: String) -> Result<()> {
         let status = OperationStatus {
             id: operation_id.clone(),
@@ -468,6 +932,10 @@ pub enum CoordinationEvent {
         node_id: NodeId,
         timestamp: u64,
     },
+    NodeJoined {
+        node_id: NodeId,
+        timestamp: u64,
+    },
     NodeStopping {
         node_id: NodeId,
         timestamp: u64,
@@ -484,6 +952,28 @@ pub enum CoordinationEvent {
         node_id: NodeId,
         timestamp: u64,
     },
+    /// Health score fell below the drain threshold; no new users are routed here
+    NodeDraining {
+        node_id: NodeId,
+        health_score: u8,
+        timestamp: u64,
+    },
+    /// A draining node recovered enough to take new users again
+    NodeReadmitted {
+        node_id: NodeId,
+        health_score: u8,
+        timestamp: u64,
+    },
+    /// An operator cordoned the node; no new users or connections go there
+    NodeCordoned {
+        node_id: NodeId,
+        timestamp: u64,
+    },
+    /// A cordoned node takes new users and connections again
+    NodeUncordoned {
+        node_id: NodeId,
+        timestamp: u64,
+    },
     LeaderElected {
         node_id: NodeId,
         term: u64,
@@ -503,6 +993,73 @@ pub enum CoordinationEvent {
         key: String,
         timestamp: u64,
     },
+    /// A user record was written to or removed from the replicated user store
+    UserReplicated {
+        user_id: String,
+        revision: u64,
+        removed: bool,
+        timestamp: u64,
+    },
+    /// Gossip and consensus disagreed about a node's membership
+    MembershipDiverged {
+        node_id: NodeId,
+        divergence: String,
+        healed: bool,
+        timestamp: u64,
+    },
+    /// A rolling restart drained the node and is restarting its service
+    ServiceRestarting {
+        node_id: NodeId,
+        service: String,
+        timestamp: u64,
+    },
+    /// The node's service is healthy again after a rolling restart step
+    ServiceRestarted {
+        node_id: NodeId,
+        service: String,
+        timestamp: u64,
+    },
+    /// The node's clock is further off than the skew limit allows
+    ClockSkewDetected {
+        node_id: NodeId,
+        /// Node clock minus this node's clock
+        offset_ms: i64,
+        timestamp: u64,
+    },
+    /// The node's clock is back within the skew limit
+    ClockSkewResolved {
+        node_id: NodeId,
+        offset_ms: i64,
+        timestamp: u64,
+    },
+}
+
+impl CoordinationEvent {
+    /// Event name as used in its serialized `type` tag
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CoordinationEvent::NodeStarted { .. } => "node_started",
+            CoordinationEvent::NodeJoined { .. } => "node_joined",
+            CoordinationEvent::NodeStopping { .. } => "node_stopping",
+            CoordinationEvent::NodeLeft { .. } => "node_left",
+            CoordinationEvent::NodeFailed { .. } => "node_failed",
+            CoordinationEvent::NodeRecovered { .. } => "node_recovered",
+            CoordinationEvent::NodeDraining { .. } => "node_draining",
+            CoordinationEvent::NodeReadmitted { .. } => "node_readmitted",
+            CoordinationEvent::NodeCordoned { .. } => "node_cordoned",
+            CoordinationEvent::NodeUncordoned { .. } => "node_uncordoned",
+            CoordinationEvent::LeaderElected { .. } => "leader_elected",
+            CoordinationEvent::LeadershipTransferred { .. } => "leadership_transferred",
+            CoordinationEvent::ClusterScaling { .. } => "cluster_scaling",
+            CoordinationEvent::ConfigurationChanged { .. } => "configuration_changed",
+            CoordinationEvent::UserReplicated { .. } => "user_replicated",
+            CoordinationEvent::MembershipDiverged { .. } => "membership_diverged",
+            CoordinationEvent::ServiceRestarting { .. } => "service_restarting",
+            CoordinationEvent::ServiceRestarted { .. } => "service_restarted",
+            CoordinationEvent::ClockSkewDetected { .. } => "clock_skew_detected",
+            CoordinationEvent::ClockSkewResolved { .. } => "clock_skew_resolved",
+        }
+    }
 }
 
 /// Information about a cluster obtained from bootstrap nodes
@@ -534,8 +1091,192 @@ pub enum OperationState {
     Cancelled,
 }
 
+type LeaderJobFn = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+/// A periodic job registered with a [`LeaderTaskRunner`]
+#[derive(Clone)]
+struct LeaderJob {
+    name: String,
+    interval: Duration,
+    run: LeaderJobFn,
+}
+
+/// Runs background jobs on the current leader only.
+///
+/// The runner watches the consensus engine and starts every registered job
+/// when this node becomes leader, and stops them when it loses leadership,
+/// so a job runs on exactly one node and moves with the leader on failover.
+/// A job first runs one interval after its node took over leadership.
+pub struct LeaderTaskRunner {
+    consensus: Arc<dyn ConsensusEngine>,
+    check_interval: Duration,
+    jobs: RwLock<Vec<LeaderJob>>,
+    /// Tasks of the running jobs, `Some` while this node is leader
+    running: Mutex<Option<Vec<JoinHandle<()>>>>,
+    supervisor: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl LeaderTaskRunner {
+    /// Create a runner following the leadership of `consensus`
+    pub fn new(consensus: Arc<dyn ConsensusEngine>) -> Self {
+        Self {
+            consensus,
+            check_interval: Duration::from_secs(1),
+            jobs: RwLock::new(Vec::new()),
+            running: Mutex::new(None),
+            supervisor: Mutex::new(None),
+        }
+    }
+
+    /// How often leadership is checked between coordination events
+    pub fn with_check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+
+    /// Register a job to run every `interval` while this node is leader
+    pub async fn register<F, Fut>(&self, name: impl Into<String>, interval: Duration, job: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let job = LeaderJob {
+            name: name.into(),
+            interval,
+            run: Arc::new(move || Box::pin(job())),
+        };
+
+        // Jobs registered while leading start right away
+        if let Some(handles) = self.running.lock().await.as_mut() {
+            handles.push(self.spawn_job(job.clone()));
+        }
+
+        self.jobs.write().await.push(job);
+    }
+
+    /// Names of the registered jobs
+    pub async fn job_names(&self) -> Vec<String> {
+        self.jobs
+            .read()
+            .await
+            .iter()
+            .map(|job| job.name.clone())
+            .collect()
+    }
+
+    /// Whether the jobs are currently running on this node
+    pub async fn is_active(&self) -> bool {
+        self.running.lock().await.is_some()
+    }
+
+    /// Follow leadership changes in the background. Leadership is checked
+    /// every check interval and whenever `events` reports a new leader.
+    pub async fn start(self: Arc<Self>, events: Option<broadcast::Receiver<CoordinationEvent>>) {
+        let runner = self.clone();
+        let handle = tokio::spawn(async move {
+            let mut events = events;
+            let mut ticker = tokio::time::interval(runner.check_interval);
+
+            loop {
+                match events.as_mut() {
+                    Some(rx) => {
+                        tokio::select! {
+                            _ = ticker.tick() => {}
+                            event = rx.recv() => match event {
+                                Ok(CoordinationEvent::LeaderElected { .. })
+                                | Ok(CoordinationEvent::LeadershipTransferred { .. })
+                                | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                                Ok(_) => continue,
+                                Err(broadcast::error::RecvError::Closed) => events = None,
+                            },
+                        }
+                    }
+                    None => {
+                        ticker.tick().await;
+                    }
+                }
+
+                runner.reconcile().await;
+            }
+        });
+
+        if let Some(previous) = self.supervisor.lock().await.replace(handle) {
+            previous.abort();
+        }
+    }
+
+    /// Stop following leadership and stop all running jobs
+    pub async fn stop(&self) {
+        if let Some(supervisor) = self.supervisor.lock().await.take() {
+            supervisor.abort();
+        }
+        self.stop_jobs().await;
+    }
+
+    /// Start or stop the jobs to match the current leadership
+    pub async fn reconcile(&self) {
+        let is_leader = self.consensus.is_leader().await;
+        let mut running = self.running.lock().await;
+
+        match (is_leader, running.is_some()) {
+            (true, false) => {
+                let jobs = self.jobs.read().await.clone();
+                tracing::info!("Became leader, starting {} leader job(s)", jobs.len());
+                *running = Some(jobs.into_iter().map(|job| self.spawn_job(job)).collect());
+            }
+            (false, true) => {
+                tracing::info!("Lost leadership, stopping leader jobs");
+                for handle in running.take().into_iter().flatten() {
+                    handle.abort();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn stop_jobs(&self) {
+        for handle in self.running.lock().await.take().into_iter().flatten() {
+            handle.abort();
+        }
+    }
+
+    fn spawn_job(&self, job: LeaderJob) -> JoinHandle<()> {
+        let consensus = self.consensus.clone();
+
+        tokio::spawn(async move {
+            let start = tokio::time::Instant::now() + job.interval;
+            let mut ticker = tokio::time::interval_at(start, job.interval);
+
+            loop {
+                ticker.tick().await;
+
+                // Leadership may have moved since the supervisor last checked
+                if !consensus.is_leader().await {
+                    continue;
+                }
+
+                tracing::debug!("Running leader job '{}'", job.name);
+                if let Err(e) = (job.run)().await {
+                    tracing::warn!("Leader job '{}' failed: {}", job.name, e);
+                }
+            }
+        })
+    }
+}
+
+impl Drop for LeaderTaskRunner {
+    fn drop(&mut self) {
+        if let Some(supervisor) = self.supervisor.get_mut().take() {
+            supervisor.abort();
+        }
+        for handle in self.running.get_mut().take().into_iter().flatten() {
+            handle.abort();
+        }
+    }
+}
+
 /// Get current timestamp in seconds since UNIX epoch
-fn current_timestamp() -> u64 {
+pub(crate) fn current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
@@ -576,6 +1317,38 @@ mod tests {
         assert!(coordinator.shutdown().await.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_rejoin_outranks_tombstone() {
+        let mut coordinator = create_test_coordinator().await;
+        let bootstrap: SocketAddr = "127.0.0.1:7946".parse().unwrap();
+        let node_id = coordinator.node_id.clone();
+
+        coordinator.join_cluster(&[bootstrap]).await.unwrap();
+        assert_eq!(
+            coordinator
+                .state
+                .read()
+                .await
+                .get_node(&node_id)
+                .unwrap()
+                .incarnation,
+            0
+        );
+
+        // Removed by the cluster, e.g. reaped while partitioned
+        coordinator
+            .state
+            .write()
+            .await
+            .remove_node(&node_id)
+            .unwrap();
+
+        coordinator.join_cluster(&[bootstrap]).await.unwrap();
+        let state = coordinator.state.read().await;
+        assert_eq!(state.get_node(&node_id).unwrap().incarnation, 1);
+        assert!(state.get_tombstone(&node_id).is_none());
+    }
+
     #[tokio::test]
     async fn test_event_subscription() {
         let coordinator = create_test_coordinator().await;
@@ -643,4 +1416,257 @@ mod tests {
         // Test no scaling needed
         assert!(coordinator.scale_cluster(2).await.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_overloaded_node_is_drained() {
+        let coordinator = create_test_coordinator().await;
+        let mut event_rx = coordinator.subscribe_to_events();
+
+        let mut ids = Vec::new();
+        {
+            let mut state = coordinator.state.write().await;
+            for name in ["busy", "idle"] {
+                let mut node = Node::new(name.to_string(), "127.0.0.1:8001".parse().unwrap());
+                node.set_status(NodeStatus::Healthy);
+                ids.push(node.id.clone());
+                state.add_node(node).unwrap();
+            }
+        }
+        let (busy, idle) = (&ids[0], &ids[1]);
+
+        let overloaded = NodeLoad {
+            cpu_usage: 95.0,
+            memory_usage: 90.0,
+            connections: 950,
+        };
+        let score = coordinator
+            .report_node_load(busy, overloaded)
+            .await
+            .unwrap();
+        assert!(score < 30);
+        assert_eq!(
+            coordinator
+                .state
+                .read()
+                .await
+                .get_node(busy)
+                .unwrap()
+                .status,
+            NodeStatus::Draining
+        );
+        assert_eq!(event_rx.recv().await.unwrap().kind(), "node_draining");
+
+        // New users go to the remaining healthy node
+        assert_eq!(&coordinator.select_node_for_user().await.unwrap().id, idle);
+
+        // A small improvement is not enough to re-admit it
+        let easing = NodeLoad {
+            cpu_usage: 60.0,
+            memory_usage: 60.0,
+            connections: 500,
+        };
+        coordinator.report_node_load(busy, easing).await.unwrap();
+        assert_eq!(
+            coordinator
+                .state
+                .read()
+                .await
+                .get_node(busy)
+                .unwrap()
+                .status,
+            NodeStatus::Draining
+        );
+
+        coordinator
+            .report_node_load(busy, NodeLoad::default())
+            .await
+            .unwrap();
+        assert!(coordinator
+            .state
+            .read()
+            .await
+            .get_node(busy)
+            .unwrap()
+            .is_routable());
+        assert_eq!(event_rx.recv().await.unwrap().kind(), "node_readmitted");
+
+        assert!(coordinator
+            .report_node_load(&NodeId::new(), NodeLoad::default())
+            .await
+            .is_err());
+    }
+
+    /// Records restarts and reports the configured health
+    struct FakeServices {
+        restarts: Mutex<Vec<String>>,
+        healthy: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl ServiceController for FakeServices {
+        async fn restart(&self, service: &str) -> Result<()> {
+            self.restarts.lock().await.push(service.to_string());
+            Ok(())
+        }
+
+        async fn status(&self, _service: &str) -> Result<ServiceStatus> {
+            Ok(ServiceStatus {
+                healthy: self.healthy.load(std::sync::atomic::Ordering::SeqCst),
+                active_connections: Some(0),
+            })
+        }
+    }
+
+    async fn restart_test_coordinator(healthy: bool) -> (ClusterCoordinator, Arc<FakeServices>) {
+        let node_id = NodeId::new();
+        let mut config = ClusterConfig::default();
+        config.rolling_restart.poll_interval = Duration::from_millis(10);
+        config.rolling_restart.health_timeout = Duration::from_millis(50);
+        let state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        let consensus = Arc::new(SimpleConsensus::new(node_id.clone()));
+
+        let mut node = Node::with_id(
+            node_id.clone(),
+            "local".to_string(),
+            "127.0.0.1:8001".parse().unwrap(),
+        );
+        node.set_status(NodeStatus::Healthy);
+        state.write().await.add_node(node).unwrap();
+
+        let services = Arc::new(FakeServices {
+            restarts: Mutex::new(Vec::new()),
+            healthy: std::sync::atomic::AtomicBool::new(healthy),
+        });
+        let mut coordinator = ClusterCoordinator::new(node_id, config, state, consensus)
+            .await
+            .unwrap();
+        coordinator.set_service_controller(services.clone());
+        (coordinator, services)
+    }
+
+    #[tokio::test]
+    async fn test_rolling_restart() {
+        let (coordinator, services) = restart_test_coordinator(true).await;
+
+        // Only the leader drives restarts
+        assert!(coordinator.rolling_restart("xray").await.is_err());
+        coordinator.consensus.elect_leader().await.unwrap();
+
+        let mut event_rx = coordinator.subscribe_to_events();
+        let report = coordinator.rolling_restart("xray").await.unwrap();
+        assert_eq!(report.restarted, vec![coordinator.node_id.clone()]);
+        assert!(report.drain_timeouts.is_empty());
+        assert_eq!(*services.restarts.lock().await, vec!["xray".to_string()]);
+        assert_eq!(event_rx.recv().await.unwrap().kind(), "service_restarting");
+        assert_eq!(event_rx.recv().await.unwrap().kind(), "service_restarted");
+
+        // Re-admitted after the restart
+        let state = coordinator.state.read().await;
+        assert_eq!(
+            state.get_node(&coordinator.node_id).unwrap().status,
+            NodeStatus::Healthy
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rolling_restart_stops_at_unhealthy_node() {
+        let (coordinator, _services) = restart_test_coordinator(false).await;
+        coordinator.consensus.elect_leader().await.unwrap();
+
+        assert!(coordinator.rolling_restart("xray").await.is_err());
+
+        // The node stays out of rotation and the operation is marked failed
+        assert_eq!(
+            coordinator
+                .state
+                .read()
+                .await
+                .get_node(&coordinator.node_id)
+                .unwrap()
+                .status,
+            NodeStatus::Draining
+        );
+        let operations = coordinator.list_active_operations().await;
+        let status = operations.values().next().unwrap();
+        assert_eq!(status.state, OperationState::Failed);
+        assert!(status.error.is_some());
+    }
+
+    fn counting_runner(
+        consensus: Arc<SimpleConsensus>,
+    ) -> (Arc<LeaderTaskRunner>, Arc<std::sync::atomic::AtomicUsize>) {
+        let runner = Arc::new(
+            LeaderTaskRunner::new(consensus).with_check_interval(Duration::from_millis(10)),
+        );
+        (runner, Arc::new(std::sync::atomic::AtomicUsize::new(0)))
+    }
+
+    async fn register_counter(
+        runner: &LeaderTaskRunner,
+        count: &Arc<std::sync::atomic::AtomicUsize>,
+    ) {
+        let count = count.clone();
+        runner
+            .register("key-rotation", Duration::from_millis(10), move || {
+                let count = count.clone();
+                async move {
+                    count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    Ok(())
+                }
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_leader_jobs_run_only_on_leader() {
+        use std::sync::atomic::Ordering;
+
+        let consensus = Arc::new(SimpleConsensus::new(NodeId::new()));
+        let (runner, count) = counting_runner(consensus.clone());
+        register_counter(&runner, &count).await;
+        runner.clone().start(None).await;
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        assert!(!runner.is_active().await);
+
+        consensus.elect_leader().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(runner.is_active().await);
+        assert!(count.load(Ordering::SeqCst) > 0);
+
+        runner.stop().await;
+        assert!(!runner.is_active().await);
+    }
+
+    #[tokio::test]
+    async fn test_leader_jobs_fail_over() {
+        use std::sync::atomic::Ordering;
+
+        let node_b = NodeId::new();
+        let consensus_a = Arc::new(SimpleConsensus::new(NodeId::new()));
+        let consensus_b = Arc::new(SimpleConsensus::new(node_b.clone()));
+        let (runner_a, count_a) = counting_runner(consensus_a.clone());
+        let (runner_b, count_b) = counting_runner(consensus_b.clone());
+        register_counter(&runner_a, &count_a).await;
+        register_counter(&runner_b, &count_b).await;
+
+        consensus_a.elect_leader().await.unwrap();
+        runner_a.reconcile().await;
+        runner_b.reconcile().await;
+        assert!(runner_a.is_active().await);
+        assert!(!runner_b.is_active().await);
+
+        consensus_a.transfer_leadership(node_b).await.unwrap();
+        consensus_b.elect_leader().await.unwrap();
+        runner_a.reconcile().await;
+        runner_b.reconcile().await;
+        assert!(!runner_a.is_active().await);
+        assert!(runner_b.is_active().await);
+
+        let before = count_a.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(count_a.load(Ordering::SeqCst), before);
+        assert!(count_b.load(Ordering::SeqCst) > 0);
+    }
 }
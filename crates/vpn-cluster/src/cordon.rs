@@ -0,0 +1,242 @@
+//! Node cordoning for maintenance windows
+//!
+//! A cordoned node keeps serving the sessions it already has but gets no
+//! new users ([`ClusterState::routable_nodes`] skips it) and no new proxy
+//! connections (the load balancer export drops it from the backends).
+//! Unlike draining, which the health score turns on and off, a cordon stays
+//! until an operator lifts it.
+//!
+//! The node applying a cordon announces it over gossip and every node that
+//! runs [`NodeCordon::start`] applies what it hears, so the whole cluster
+//! stops routing to the node.
+
+use crate::coordination::{current_timestamp, CoordinationEvent};
+use crate::error::{ClusterError, Result};
+use crate::events::ClusterEventBus;
+use crate::gossip::{GossipManager, GossipMessage};
+use crate::node::NodeId;
+use crate::state::ClusterState;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+/// Gossip message kind carrying a [`CordonUpdate`]
+pub const CORDON_GOSSIP_KIND: &str = "node_cordon";
+
+/// Cordon flag of one node as announced over gossip
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CordonUpdate {
+    pub node_id: NodeId,
+    pub cordoned: bool,
+}
+
+/// Cordons nodes and keeps this node's view in line with cordons applied
+/// elsewhere
+pub struct NodeCordon {
+    state: Arc<RwLock<ClusterState>>,
+    events: ClusterEventBus,
+    gossip: Arc<GossipManager>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl NodeCordon {
+    pub fn new(
+        state: Arc<RwLock<ClusterState>>,
+        events: ClusterEventBus,
+        gossip: Arc<GossipManager>,
+    ) -> Self {
+        Self {
+            state,
+            events,
+            gossip,
+            task: Mutex::new(None),
+        }
+    }
+
+    /// Keep new users and proxy connections off `node_id`
+    pub async fn cordon(&self, node_id: &NodeId) -> Result<()> {
+        self.set(node_id, true).await
+    }
+
+    /// Let new users and proxy connections back onto `node_id`
+    pub async fn uncordon(&self, node_id: &NodeId) -> Result<()> {
+        self.set(node_id, false).await
+    }
+
+    /// Whether `node_id` is currently cordoned
+    pub async fn is_cordoned(&self, node_id: &NodeId) -> Result<bool> {
+        self.state
+            .read()
+            .await
+            .get_node(node_id)
+            .map(|node| node.cordoned)
+            .ok_or_else(|| ClusterError::node_not_found(node_id.to_string()))
+    }
+
+    async fn set(&self, node_id: &NodeId, cordoned: bool) -> Result<()> {
+        let update = CordonUpdate {
+            node_id: node_id.clone(),
+            cordoned,
+        };
+        self.apply(&update).await?;
+
+        // Announced even when nothing changed here, so peers that missed
+        // an earlier announcement catch up
+        self.gossip
+            .publish(CORDON_GOSSIP_KIND, serde_json::to_value(&update)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Apply a cordon update to the local view, returning whether it changed
+    pub async fn apply(&self, update: &CordonUpdate) -> Result<bool> {
+        let changed = {
+            let mut state = self.state.write().await;
+            let node = state
+                .get_node_mut(&update.node_id)
+                .ok_or_else(|| ClusterError::node_not_found(update.node_id.to_string()))?;
+            if update.cordoned {
+                node.cordon()
+            } else {
+                node.uncordon()
+            }
+        };
+
+        if changed {
+            let node_id = update.node_id.clone();
+            let timestamp = current_timestamp();
+            let event = if update.cordoned {
+                tracing::info!("Node {} cordoned", node_id);
+                CoordinationEvent::NodeCordoned { node_id, timestamp }
+            } else {
+                tracing::info!("Node {} uncordoned", node_id);
+                CoordinationEvent::NodeUncordoned { node_id, timestamp }
+            };
+            self.events.publish(event);
+        }
+
+        Ok(changed)
+    }
+
+    async fn receive(&self, message: &GossipMessage) -> Result<()> {
+        let update: CordonUpdate = serde_json::from_value(message.payload.clone())?;
+        self.apply(&update).await.map(|_| ())
+    }
+
+    /// Apply cordons announced by other nodes until stopped
+    pub async fn start(self: Arc<Self>) {
+        let mut messages = self.gossip.subscribe();
+        let cordon = self.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                match messages.recv().await {
+                    Ok(message) if message.kind == CORDON_GOSSIP_KIND => {
+                        if let Err(e) = cordon.receive(&message).await {
+                            tracing::debug!(
+                                "Ignoring cordon update from node {}: {}",
+                                message.origin,
+                                e
+                            );
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!(
+                            "Missed {} gossip message(s) while applying cordons",
+                            skipped
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        if let Some(previous) = self.task.lock().await.replace(handle) {
+            previous.abort();
+        }
+    }
+
+    pub async fn stop(&self) {
+        if let Some(handle) = self.task.lock().await.take() {
+            handle.abort();
+        }
+    }
+}
+
+impl Drop for NodeCordon {
+    fn drop(&mut self) {
+        if let Some(handle) = self.task.get_mut().take() {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::ClusterGrpcClient;
+    use crate::config::GossipConfig;
+    use crate::node::{Node, NodeStatus};
+
+    fn setup() -> (NodeCordon, Arc<RwLock<ClusterState>>, NodeId, NodeId) {
+        let local = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(local.clone())));
+        let gossip = Arc::new(GossipManager::new(
+            local.clone(),
+            GossipConfig::default(),
+            state.clone(),
+            ClusterGrpcClient::new(local.clone()),
+        ));
+        let cordon = NodeCordon::new(state.clone(), ClusterEventBus::default(), gossip);
+
+        let mut a = Node::new("node-a".to_string(), "127.0.0.1:8001".parse().unwrap());
+        let mut b = Node::new("node-b".to_string(), "127.0.0.1:8002".parse().unwrap());
+        a.set_status(NodeStatus::Healthy);
+        b.set_status(NodeStatus::Healthy);
+        let (a_id, b_id) = (a.id.clone(), b.id.clone());
+        {
+            let mut state = state.try_write().unwrap();
+            state.add_node(a).unwrap();
+            state.add_node(b).unwrap();
+        }
+
+        (cordon, state, a_id, b_id)
+    }
+
+    #[tokio::test]
+    async fn test_cordoned_node_gets_no_new_users() {
+        let (cordon, state, a_id, b_id) = setup();
+        let mut events = cordon.events.stream();
+
+        cordon.cordon(&a_id).await.unwrap();
+        assert!(cordon.is_cordoned(&a_id).await.unwrap());
+        assert_eq!(events.next_event().await.unwrap().kind(), "node_cordoned");
+        assert_eq!(cordon.gossip.pending_count().await, 1);
+
+        {
+            let state = state.read().await;
+            let routable: Vec<&NodeId> = state.routable_nodes().iter().map(|n| &n.id).collect();
+            assert_eq!(routable, vec![&b_id]);
+            // Still a healthy member that keeps its sessions
+            assert_eq!(state.get_node(&a_id).unwrap().status, NodeStatus::Healthy);
+        }
+
+        // Cordoning twice is a no-op for the local view
+        let repeat = CordonUpdate {
+            node_id: a_id.clone(),
+            cordoned: true,
+        };
+        assert!(!cordon.apply(&repeat).await.unwrap());
+
+        cordon.uncordon(&a_id).await.unwrap();
+        assert_eq!(events.next_event().await.unwrap().kind(), "node_uncordoned");
+        assert_eq!(state.read().await.routable_nodes().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cordon_unknown_node() {
+        let (cordon, _, _, _) = setup();
+        assert!(cordon.cordon(&NodeId::new()).await.is_err());
+    }
+}
@@ -1,13 +1,21 @@
 //! Distributed configuration storage implementations
 
 use crate::config::StorageBackendConfig;
+use crate::consensus::ConsensusEngine;
 use crate::error::{ClusterError, Result};
+use crate::leader_election::FencingToken;
+use crate::node::NodeId;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 
 /// Trait for distributed configuration storage
 #[async_trait]
@@ -18,6 +26,27 @@ pub trait DistributedConfigStorage: Send + Sync {
     /// Retrieve a configuration value
     async fn get_config(&self, key: &str) -> Result<Option<Value>>;
 
+    /// Store a configuration value once `consistency` replicas acknowledge
+    /// it. Backends that keep a single copy ignore the level.
+    async fn store_config_with_consistency(
+        &self,
+        key: &str,
+        value: Value,
+        _consistency: ConsistencyLevel,
+    ) -> Result<()> {
+        self.store_config(key, value).await
+    }
+
+    /// Retrieve a configuration value from `consistency` replicas. Backends
+    /// that keep a single copy ignore the level.
+    async fn get_config_with_consistency(
+        &self,
+        key: &str,
+        _consistency: ConsistencyLevel,
+    ) -> Result<Option<Value>> {
+        self.get_config(key).await
+    }
+
     /// Remove a configuration value
     async fn remove_config(&self, key: &str) -> Result<Option<Value>>;
 
@@ -63,6 +92,60 @@ pub enum TransactionOp {
     },
 }
 
+/// Number of replicas that must answer a read or acknowledge a write
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsistencyLevel {
+    /// Any single replica
+    One,
+    /// A majority of replicas
+    #[default]
+    Quorum,
+    /// Every replica
+    All,
+}
+
+impl ConsistencyLevel {
+    /// Replicas required out of `replicas`
+    pub fn required(&self, replicas: usize) -> usize {
+        match self {
+            ConsistencyLevel::One => replicas.min(1),
+            ConsistencyLevel::Quorum => replicas / 2 + 1,
+            ConsistencyLevel::All => replicas,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsistencyLevel::One => "one",
+            ConsistencyLevel::Quorum => "quorum",
+            ConsistencyLevel::All => "all",
+        }
+    }
+}
+
+impl fmt::Display for ConsistencyLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ConsistencyLevel {
+    type Err = ClusterError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "one" => Ok(ConsistencyLevel::One),
+            "quorum" => Ok(ConsistencyLevel::Quorum),
+            "all" => Ok(ConsistencyLevel::All),
+            other => Err(ClusterError::configuration(format!(
+                "unknown consistency level: {}",
+                other
+            ))),
+        }
+    }
+}
+
 /// Storage backend health information
 #[derive(Debug, Clone)]
 pub struct StorageHealth {
@@ -78,8 +161,24 @@ pub async fn create_storage_backend(
 ) -> Result<Arc<dyn DistributedConfigStorage>> {
     match config {
         StorageBackendConfig::Sled { path } => Ok(Arc::new(SledStorage::new(path).await?)),
-        StorageBackendConfig::Etcd { endpoints, .. } => {
-            Ok(Arc::new(EtcdStorage::new(endpoints).await?))
+        StorageBackendConfig::Etcd {
+            endpoints,
+            username,
+            password,
+            tls_enabled,
+            ca_cert,
+            client_cert,
+            client_key,
+        } => {
+            let options = EtcdStorage::connect_options(
+                username.as_deref(),
+                password.as_deref(),
+                *tls_enabled,
+                ca_cert.as_deref(),
+                client_cert.as_deref(),
+                client_key.as_deref(),
+            )?;
+            Ok(Arc::new(EtcdStorage::new(endpoints, options).await?))
         }
         StorageBackendConfig::Consul { address, .. } => {
             Ok(Arc::new(ConsulStorage::new(address).await?))
@@ -248,15 +347,20 @@ impl DistributedConfigStorage for MemoryStorage {
 /// Sled-based storage implementation
 pub struct SledStorage {
     db: sled::Db,
+    path: std::path::PathBuf,
     watchers: Arc<RwLock<HashMap<String, tokio::sync::broadcast::Sender<ConfigChange>>>>,
 }
 
 impl SledStorage {
+    /// Open the database at `path`, creating its directory readable by
+    /// this user only
     pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let db = sled::open(path)?;
+        create_private_dir(path.as_ref())?;
+        let db = sled::open(path.as_ref())?;
 
         Ok(Self {
             db,
+            path: path.as_ref().to_path_buf(),
             watchers: Arc::new(RwLock::new(HashMap::new())),
         })
     }
@@ -275,11 +379,24 @@ impl SledStorage {
     }
 }
 
+/// Create `path` with mode 0700, tightening it if it already exists, so
+/// other users cannot read the state or plant files in it
+fn create_private_dir(path: &Path) -> Result<()> {
+    std::fs::create_dir_all(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl DistributedConfigStorage for SledStorage {
     async fn store_config(&self, key: &str, value: Value) -> Result<()> {
         let serialized = serde_json::to_vec(&value)?;
         let old_data = self.db.insert(key, serialized)?;
+        self.db.flush_async().await?;
 
         let old_value = if let Some(data) = old_data {
             serde_json::from_slice(&data).ok()
@@ -302,6 +419,7 @@ impl DistributedConfigStorage for SledStorage {
 
     async fn remove_config(&self, key: &str) -> Result<Option<Value>> {
         let old_data = self.db.remove(key)?;
+        self.db.flush_async().await?;
 
         let old_value = if let Some(data) = old_data {
             serde_json::from_slice(&data).ok()
@@ -409,6 +527,7 @@ impl DistributedConfigStorage for SledStorage {
 
         // Apply batch
         self.db.apply_batch(batch)?;
+        self.db.flush_async().await?;
 
         // Notify watchers
         for (key, old_value, new_value) in changes {
@@ -437,7 +556,7 @@ impl DistributedConfigStorage for SledStorage {
                     metadata: {
                         let mut meta = HashMap::new();
                         meta.insert("backend".to_string(), "sled".to_string());
-                        meta.insert("path".to_string(), "sled_db".to_string());
+                        meta.insert("path".to_string(), self.path.display().to_string());
                         meta
                     },
                 })
@@ -452,50 +571,291 @@ impl DistributedConfigStorage for SledStorage {
     }
 }
 
-/// Placeholder for etcd storage implementation
-pub struct EtcdStorage;
+/// Key prefix under which cluster configuration is stored in etcd
+const ETCD_KEY_PREFIX: &str = "/vpn-cluster/config/";
+
+/// etcd-based storage implementation
+pub struct EtcdStorage {
+    client: etcd_client::Client,
+    endpoints: Vec<String>,
+}
 
 impl EtcdStorage {
-    pub async fn new(_endpoints: &[String]) -> Result<Self> {
-        // TODO: Implement etcd client
-        Err(ClusterError::configuration(
-            "etcd storage not yet implemented",
-        ))
+    pub async fn new(
+        endpoints: &[String],
+        options: Option<etcd_client::ConnectOptions>,
+    ) -> Result<Self> {
+        if endpoints.is_empty() {
+            return Err(ClusterError::configuration(
+                "etcd storage requires at least one endpoint",
+            ));
+        }
+
+        let client = etcd_client::Client::connect(endpoints, options).await?;
+
+        Ok(Self {
+            client,
+            endpoints: endpoints.to_vec(),
+        })
+    }
+
+    /// Build etcd connection options from the storage backend settings
+    pub fn connect_options(
+        username: Option<&str>,
+        password: Option<&str>,
+        tls_enabled: bool,
+        ca_cert: Option<&Path>,
+        client_cert: Option<&Path>,
+        client_key: Option<&Path>,
+    ) -> Result<Option<etcd_client::ConnectOptions>> {
+        let mut options = None;
+
+        if let Some(username) = username {
+            options = Some(
+                etcd_client::ConnectOptions::new()
+                    .with_user(username, password.unwrap_or_default()),
+            );
+        }
+
+        if tls_enabled {
+            let mut tls = etcd_client::TlsOptions::new();
+
+            if let Some(ca_cert) = ca_cert {
+                let pem = std::fs::read(ca_cert)?;
+                tls = tls.ca_certificate(etcd_client::Certificate::from_pem(pem));
+            }
+
+            match (client_cert, client_key) {
+                (Some(cert), Some(key)) => {
+                    let cert = std::fs::read(cert)?;
+                    let key = std::fs::read(key)?;
+                    tls = tls.identity(etcd_client::Identity::from_pem(cert, key));
+                }
+                (None, None) => {}
+                _ => {
+                    return Err(ClusterError::configuration(
+                        "etcd client_cert and client_key must be set together",
+                    ))
+                }
+            }
+
+            options = Some(options.unwrap_or_default().with_tls(tls));
+        }
+
+        Ok(options)
+    }
+
+    fn prefixed_key(key: &str) -> String {
+        format!("{}{}", ETCD_KEY_PREFIX, key)
+    }
+
+    fn strip_prefix(key: &[u8]) -> Option<String> {
+        let key = std::str::from_utf8(key).ok()?;
+        key.strip_prefix(ETCD_KEY_PREFIX).map(|k| k.to_string())
+    }
+
+    fn decode_value(kv: Option<&etcd_client::KeyValue>) -> Option<Value> {
+        kv.and_then(|kv| serde_json::from_slice(kv.value()).ok())
     }
 }
 
 #[async_trait]
 impl DistributedConfigStorage for EtcdStorage {
-    async fn store_config(&self, _key: &str, _value: Value) -> Result<()> {
-        unimplemented!("etcd storage not yet implemented")
+    async fn store_config(&self, key: &str, value: Value) -> Result<()> {
+        let serialized = serde_json::to_vec(&value)?;
+        let mut kv = self.client.kv_client();
+        kv.put(Self::prefixed_key(key), serialized, None).await?;
+        Ok(())
     }
 
-    async fn get_config(&self, _key: &str) -> Result<Option<Value>> {
-        unimplemented!("etcd storage not yet implemented")
+    async fn get_config(&self, key: &str) -> Result<Option<Value>> {
+        let mut kv = self.client.kv_client();
+        let response = kv.get(Self::prefixed_key(key), None).await?;
+
+        match response.kvs().first() {
+            Some(item) => Ok(Some(serde_json::from_slice(item.value())?)),
+            None => Ok(None),
+        }
     }
 
-    async fn remove_config(&self, _key: &str) -> Result<Option<Value>> {
-        unimplemented!("etcd storage not yet implemented")
+    async fn remove_config(&self, key: &str) -> Result<Option<Value>> {
+        let mut kv = self.client.kv_client();
+        let response = kv
+            .delete(
+                Self::prefixed_key(key),
+                Some(etcd_client::DeleteOptions::new().with_prev_key()),
+            )
+            .await?;
+
+        Ok(Self::decode_value(response.prev_kvs().first()))
     }
 
     async fn list_keys(&self) -> Result<Vec<String>> {
-        unimplemented!("etcd storage not yet implemented")
+        let mut kv = self.client.kv_client();
+        let response = kv
+            .get(
+                ETCD_KEY_PREFIX,
+                Some(
+                    etcd_client::GetOptions::new()
+                        .with_prefix()
+                        .with_keys_only(),
+                ),
+            )
+            .await?;
+
+        Ok(response
+            .kvs()
+            .iter()
+            .filter_map(|item| Self::strip_prefix(item.key()))
+            .collect())
     }
 
     async fn get_all_config(&self) -> Result<HashMap<String, Value>> {
-        unimplemented!("etcd storage not yet implemented")
+        let mut kv = self.client.kv_client();
+        let response = kv
+            .get(
+                ETCD_KEY_PREFIX,
+                Some(etcd_client::GetOptions::new().with_prefix()),
+            )
+            .await?;
+
+        let mut result = HashMap::new();
+        for item in response.kvs() {
+            if let (Some(key), Ok(value)) = (
+                Self::strip_prefix(item.key()),
+                serde_json::from_slice::<Value>(item.value()),
+            ) {
+                result.insert(key, value);
+            }
+        }
+
+        Ok(result)
     }
 
-    async fn watch_config(&self, _key: &str) -> Result<tokio::sync::mpsc::Receiver<ConfigChange>> {
-        unimplemented!("etcd storage not yet implemented")
+    async fn watch_config(&self, key: &str) -> Result<tokio::sync::mpsc::Receiver<ConfigChange>> {
+        let mut watch_client = self.client.watch_client();
+        let (watcher, mut stream) = watch_client
+            .watch(
+                Self::prefixed_key(key),
+                Some(etcd_client::WatchOptions::new().with_prev_key()),
+            )
+            .await?;
+
+        let key = key.to_string();
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+        tokio::spawn(async move {
+            // Keep the watcher alive for as long as the stream is being consumed
+            let _watcher = watcher;
+
+            while let Ok(Some(response)) = stream.message().await {
+                for event in response.events() {
+                    let new_value = match event.event_type() {
+                        etcd_client::EventType::Put => Self::decode_value(event.kv()),
+                        etcd_client::EventType::Delete => None,
+                    };
+
+                    let change = ConfigChange {
+                        key: key.clone(),
+                        old_value: Self::decode_value(event.prev_kv()),
+                        new_value,
+                        timestamp: current_timestamp(),
+                    };
+
+                    if tx.send(change).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(rx)
     }
 
-    async fn transaction(&self, _ops: Vec<TransactionOp>) -> Result<()> {
-        unimplemented!("etcd storage not yet implemented")
+    async fn transaction(&self, ops: Vec<TransactionOp>) -> Result<()> {
+        let mut compares = Vec::new();
+        let mut txn_ops = Vec::new();
+
+        for op in ops {
+            match op {
+                TransactionOp::Set { key, value } => {
+                    txn_ops.push(etcd_client::TxnOp::put(
+                        Self::prefixed_key(&key),
+                        serde_json::to_vec(&value)?,
+                        None,
+                    ));
+                }
+                TransactionOp::Delete { key } => {
+                    txn_ops.push(etcd_client::TxnOp::delete(Self::prefixed_key(&key), None));
+                }
+                TransactionOp::ConditionalSet {
+                    key,
+                    value,
+                    expected,
+                } => {
+                    let etcd_key = Self::prefixed_key(&key);
+                    compares.push(match expected {
+                        // A version of zero means the key does not exist
+                        None => etcd_client::Compare::version(
+                            etcd_key.clone(),
+                            etcd_client::CompareOp::Equal,
+                            0,
+                        ),
+                        Some(expected) => etcd_client::Compare::value(
+                            etcd_key.clone(),
+                            etcd_client::CompareOp::Equal,
+                            serde_json::to_vec(&expected)?,
+                        ),
+                    });
+                    txn_ops.push(etcd_client::TxnOp::put(
+                        etcd_key,
+                        serde_json::to_vec(&value)?,
+                        None,
+                    ));
+                }
+            }
+        }
+
+        let txn = etcd_client::Txn::new().when(compares).and_then(txn_ops);
+        let mut kv = self.client.kv_client();
+        let response = kv.txn(txn).await?;
+
+        if !response.succeeded() {
+            return Err(ClusterError::invalid_state(
+                "Conditional set failed: etcd transaction comparison did not match",
+            ));
+        }
+
+        Ok(())
     }
 
     async fn health_check(&self) -> Result<StorageHealth> {
-        unimplemented!("etcd storage not yet implemented")
+        let start = std::time::Instant::now();
+        let mut maintenance = self.client.maintenance_client();
+
+        let mut metadata = HashMap::new();
+        metadata.insert("backend".to_string(), "etcd".to_string());
+        metadata.insert("endpoints".to_string(), self.endpoints.join(","));
+
+        match maintenance.status().await {
+            Ok(status) => {
+                metadata.insert("version".to_string(), status.version().to_string());
+                metadata.insert("db_size".to_string(), status.db_size().to_string());
+
+                Ok(StorageHealth {
+                    healthy: true,
+                    latency_ms: start.elapsed().as_millis() as u64,
+                    error: None,
+                    metadata,
+                })
+            }
+            Err(e) => Ok(StorageHealth {
+                healthy: false,
+                latency_ms: start.elapsed().as_millis() as u64,
+                error: Some(e.to_string()),
+                metadata,
+            }),
+        }
     }
 }
 
@@ -593,137 +953,1192 @@ impl DistributedConfigStorage for TiKVStorage {
     }
 }
 
-/// Get current timestamp in seconds since UNIX epoch
-fn current_timestamp() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs()
+/// Value as stored on each replica of a [`ReplicatedStorage`]. Removed keys
+/// keep a tombstone so a replica that missed the delete cannot bring the
+/// value back during read repair.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VersionedValue {
+    #[serde(rename = "_version")]
+    version: u64,
+    #[serde(rename = "_value")]
+    value: Option<Value>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
-    use tokio::time::{timeout, Duration};
-
-    #[tokio::test]
-    async fn test_memory_storage() {
-        let storage = MemoryStorage::new();
+impl VersionedValue {
+    /// Values written without an envelope count as the oldest version
+    fn decode(raw: Value) -> Self {
+        serde_json::from_value(raw.clone()).unwrap_or(Self {
+            version: 0,
+            value: Some(raw),
+        })
+    }
 
-        // Test store and get
-        let value = serde_json::json!({"test": "value"});
-        storage
-            .store_config("test_key", value.clone())
-            .await
-            .unwrap();
+    fn encode(&self) -> Value {
+        serde_json::json!({ "_version": self.version, "_value": self.value })
+    }
+}
 
-        let retrieved = storage.get_config("test_key").await.unwrap();
-        assert_eq!(retrieved, Some(value));
+/// Storage replicated over several backends with tunable consistency.
+///
+/// Every write carries a version (microseconds since the epoch, kept
+/// monotonic per writer) and the newest version wins. Reads wait for as
+/// many replicas as the consistency level requires and write the newest
+/// value back to any of them that returned an older one (read repair).
+/// Writes keep going to the remaining replicas after the required
+/// acknowledgements arrive.
+pub struct ReplicatedStorage {
+    replicas: Vec<Arc<dyn DistributedConfigStorage>>,
+    read_consistency: ConsistencyLevel,
+    write_consistency: ConsistencyLevel,
+    last_version: AtomicU64,
+}
 
-        // Test list keys
-        let keys = storage.list_keys().await.unwrap();
-        assert!(keys.contains(&"test_key".to_string()));
+impl ReplicatedStorage {
+    pub fn new(replicas: Vec<Arc<dyn DistributedConfigStorage>>) -> Result<Self> {
+        if replicas.is_empty() {
+            return Err(ClusterError::configuration(
+                "replicated storage needs at least one replica",
+            ));
+        }
 
-        // Test remove
-        let removed = storage.remove_config("test_key").await.unwrap();
-        assert_eq!(removed, Some(serde_json::json!({"test": "value"})));
+        Ok(Self {
+            replicas,
+            read_consistency: ConsistencyLevel::default(),
+            write_consistency: ConsistencyLevel::default(),
+            last_version: AtomicU64::new(0),
+        })
+    }
 
-        let after_remove = storage.get_config("test_key").await.unwrap();
-        assert_eq!(after_remove, None);
+    /// Levels used by `get_config`/`store_config` and the other operations
+    /// that do not take one explicitly
+    pub fn with_consistency(mut self, read: ConsistencyLevel, write: ConsistencyLevel) -> Self {
+        self.read_consistency = read;
+        self.write_consistency = write;
+        self
     }
 
-    #[tokio::test]
-    async fn test_memory_storage_watch() {
-        let storage = MemoryStorage::new();
+    pub fn replica_count(&self) -> usize {
+        self.replicas.len()
+    }
 
-        let mut watcher = storage.watch_config("watch_key").await.unwrap();
+    pub fn read_consistency(&self) -> ConsistencyLevel {
+        self.read_consistency
+    }
 
-        // Store value and check notification
-        let value = serde_json::json!("watched_value");
-        storage
-            .store_config("watch_key", value.clone())
-            .await
-            .unwrap();
+    pub fn write_consistency(&self) -> ConsistencyLevel {
+        self.write_consistency
+    }
 
-        let change = timeout(Duration::from_millis(100), watcher.recv())
-            .await
-            .unwrap()
-            .unwrap();
-        assert_eq!(change.key, "watch_key");
-        assert_eq!(change.new_value, Some(value));
-        assert_eq!(change.old_value, None);
+    fn next_version(&self) -> u64 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let mut last = self.last_version.load(Ordering::Relaxed);
+        loop {
+            let next = now.max(last + 1);
+            match self.last_version.compare_exchange_weak(
+                last,
+                next,
+                Ordering::SeqCst,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return next,
+                Err(actual) => last = actual,
+            }
+        }
     }
 
-    #[tokio::test]
-    async fn test_memory_storage_transaction() {
-        let storage = MemoryStorage::new();
+    /// Run `op` against every replica and wait until `consistency` of them
+    /// succeed
+    async fn replicate<F, Fut>(&self, consistency: ConsistencyLevel, op: F) -> Result<()>
+    where
+        F: Fn(Arc<dyn DistributedConfigStorage>) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let required = consistency.required(self.replicas.len());
+        let (tx, mut rx) = mpsc::channel(self.replicas.len());
+
+        for replica in &self.replicas {
+            let operation = op(replica.clone());
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _ = tx.send(operation.await).await;
+            });
+        }
+        drop(tx);
+
+        let mut acks = 0;
+        while let Some(result) = rx.recv().await {
+            match result {
+                Ok(()) => {
+                    acks += 1;
+                    if acks >= required {
+                        return Ok(());
+                    }
+                }
+                Err(e) => tracing::warn!("Replica write failed: {}", e),
+            }
+        }
 
-        // Set initial value
-        storage
-            .store_config("key1", serde_json::json!("value1"))
-            .await
-            .unwrap();
+        Err(ClusterError::quorum_not_available(acks, required))
+    }
 
-        // Test successful transaction
-        let ops = vec![
-            TransactionOp::Set {
-                key: "key2".to_string(),
-                value: serde_json::json!("value2"),
-            },
-            TransactionOp::ConditionalSet {
-                key: "key1".to_string(),
-                value: serde_json::json!("updated_value1"),
-                expected: Some(serde_json::json!("value1")),
-            },
-        ];
+    async fn write_versioned(
+        &self,
+        key: &str,
+        value: Option<Value>,
+        consistency: ConsistencyLevel,
+    ) -> Result<()> {
+        let raw = VersionedValue {
+            version: self.next_version(),
+            value,
+        }
+        .encode();
 
-        storage.transaction(ops).await.unwrap();
+        self.replicate(consistency, |replica| {
+            let key = key.to_string();
+            let raw = raw.clone();
+            async move { replica.store_config(&key, raw).await }
+        })
+        .await
+    }
 
-        assert_eq!(
-            storage.get_config("key1").await.unwrap(),
-            Some(serde_json::json!("updated_value1"))
-        );
-        assert_eq!(
-            storage.get_config("key2").await.unwrap(),
-            Some(serde_json::json!("value2"))
-        );
+    /// Read `key` from `consistency` replicas, returning the newest version
+    /// and repairing replicas that answered with an older one
+    async fn read_versioned(
+        &self,
+        key: &str,
+        consistency: ConsistencyLevel,
+    ) -> Result<Option<VersionedValue>> {
+        let required = consistency.required(self.replicas.len());
+        let (tx, mut rx) = mpsc::channel(self.replicas.len());
+
+        for (index, replica) in self.replicas.iter().enumerate() {
+            let replica = replica.clone();
+            let key = key.to_string();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let result = replica.get_config(&key).await;
+                let _ = tx.send((index, result)).await;
+            });
+        }
+        drop(tx);
+
+        let mut responses = Vec::new();
+        while responses.len() < required {
+            match rx.recv().await {
+                Some((index, Ok(raw))) => responses.push((index, raw.map(VersionedValue::decode))),
+                Some((index, Err(e))) => tracing::warn!("Replica {} read failed: {}", index, e),
+                None => {
+                    return Err(ClusterError::quorum_not_available(
+                        responses.len(),
+                        required,
+                    ))
+                }
+            }
+        }
 
-        // Test failed conditional transaction
-        let failing_ops = vec![TransactionOp::ConditionalSet {
-            key: "key1".to_string(),
-            value: serde_json::json!("should_not_update"),
-            expected: Some(serde_json::json!("wrong_expected_value")),
-        }];
+        let newest = responses
+            .iter()
+            .filter_map(|(_, entry)| entry.as_ref())
+            .max_by_key(|entry| entry.version)
+            .cloned();
+
+        if let Some(newest) = &newest {
+            for (index, entry) in &responses {
+                if entry.as_ref().is_some_and(|e| e.version >= newest.version) {
+                    continue;
+                }
 
-        assert!(storage.transaction(failing_ops).await.is_err());
-        assert_eq!(
-            storage.get_config("key1").await.unwrap(),
-            Some(serde_json::json!("updated_value1"))
-        );
+                tracing::debug!("Repairing stale replica {} for key {}", index, key);
+                let replica = self.replicas[*index].clone();
+                let key = key.to_string();
+                let raw = newest.encode();
+                tokio::spawn(async move {
+                    if let Err(e) = replica.store_config(&key, raw).await {
+                        tracing::warn!("Read repair of key {} failed: {}", key, e);
+                    }
+                });
+            }
+        }
+
+        Ok(newest)
     }
 
-    #[tokio::test]
-    async fn test_sled_storage() {
-        let temp_dir = tempdir().unwrap();
-        let storage = SledStorage::new(temp_dir.path()).await.unwrap();
+    /// Newest version of every key across `consistency` replicas, including
+    /// tombstones
+    async fn read_all_versioned(
+        &self,
+        consistency: ConsistencyLevel,
+    ) -> Result<HashMap<String, VersionedValue>> {
+        let required = consistency.required(self.replicas.len());
+        let mut merged: HashMap<String, VersionedValue> = HashMap::new();
+        let mut answered = 0;
+
+        for replica in &self.replicas {
+            let values = match replica.get_all_config().await {
+                Ok(values) => values,
+                Err(e) => {
+                    tracing::warn!("Replica read failed: {}", e);
+                    continue;
+                }
+            };
 
-        // Test basic operations
-        let value = serde_json::json!({"sled": "test"});
-        storage
-            .store_config("sled_key", value.clone())
-            .await
-            .unwrap();
+            for (key, raw) in values {
+                let entry = VersionedValue::decode(raw);
+                match merged.get(&key) {
+                    Some(existing) if existing.version >= entry.version => {}
+                    _ => {
+                        merged.insert(key, entry);
+                    }
+                }
+            }
 
-        let retrieved = storage.get_config("sled_key").await.unwrap();
-        assert_eq!(retrieved, Some(value));
+            answered += 1;
+            if answered >= required {
+                return Ok(merged);
+            }
+        }
 
-        // Test persistence by creating new instance
-        let storage2 = SledStorage::new(temp_dir.path()).await.unwrap();
+        Err(ClusterError::quorum_not_available(answered, required))
+    }
+}
+
+#[async_trait]
+impl DistributedConfigStorage for ReplicatedStorage {
+    async fn store_config(&self, key: &str, value: Value) -> Result<()> {
+        self.store_config_with_consistency(key, value, self.write_consistency)
+            .await
+    }
+
+    async fn get_config(&self, key: &str) -> Result<Option<Value>> {
+        self.get_config_with_consistency(key, self.read_consistency)
+            .await
+    }
+
+    async fn store_config_with_consistency(
+        &self,
+        key: &str,
+        value: Value,
+        consistency: ConsistencyLevel,
+    ) -> Result<()> {
+        self.write_versioned(key, Some(value), consistency).await
+    }
+
+    async fn get_config_with_consistency(
+        &self,
+        key: &str,
+        consistency: ConsistencyLevel,
+    ) -> Result<Option<Value>> {
+        Ok(self
+            .read_versioned(key, consistency)
+            .await?
+            .and_then(|entry| entry.value))
+    }
+
+    async fn remove_config(&self, key: &str) -> Result<Option<Value>> {
+        let old_value = self.get_config(key).await?;
+        if old_value.is_some() {
+            self.write_versioned(key, None, self.write_consistency)
+                .await?;
+        }
+        Ok(old_value)
+    }
+
+    async fn list_keys(&self) -> Result<Vec<String>> {
+        let mut keys: Vec<String> = self.get_all_config().await?.into_keys().collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn get_all_config(&self) -> Result<HashMap<String, Value>> {
+        Ok(self
+            .read_all_versioned(self.read_consistency)
+            .await?
+            .into_iter()
+            .filter_map(|(key, entry)| entry.value.map(|value| (key, value)))
+            .collect())
+    }
+
+    /// Follows the first replica that accepts the watch
+    async fn watch_config(&self, key: &str) -> Result<tokio::sync::mpsc::Receiver<ConfigChange>> {
+        let mut last_error = None;
+        for replica in &self.replicas {
+            let mut changes = match replica.watch_config(key).await {
+                Ok(changes) => changes,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+
+            let (tx, rx) = tokio::sync::mpsc::channel(100);
+            tokio::spawn(async move {
+                while let Some(change) = changes.recv().await {
+                    let old_value = change
+                        .old_value
+                        .and_then(|raw| VersionedValue::decode(raw).value);
+                    let new_value = change
+                        .new_value
+                        .and_then(|raw| VersionedValue::decode(raw).value);
+                    // Read repair rewrites values without changing them
+                    if old_value == new_value {
+                        continue;
+                    }
+
+                    let change = ConfigChange {
+                        key: change.key,
+                        old_value,
+                        new_value,
+                        timestamp: change.timestamp,
+                    };
+                    if tx.send(change).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            return Ok(rx);
+        }
+
+        Err(last_error.unwrap_or_else(|| ClusterError::storage("no replica accepted the watch")))
+    }
+
+    /// Conditions are checked against a read at the write consistency
+    /// level; the writes are then applied as one transaction per replica.
+    async fn transaction(&self, ops: Vec<TransactionOp>) -> Result<()> {
+        for op in &ops {
+            if let TransactionOp::ConditionalSet { key, expected, .. } = op {
+                let current = self
+                    .get_config_with_consistency(key, self.write_consistency)
+                    .await?;
+                if current != *expected {
+                    return Err(ClusterError::invalid_state(format!(
+                        "Conditional set failed for key {}: expected {:?}, got {:?}",
+                        key, expected, current
+                    )));
+                }
+            }
+        }
+
+        let version = self.next_version();
+        let replica_ops: Vec<TransactionOp> = ops
+            .into_iter()
+            .map(|op| {
+                let (key, value) = match op {
+                    TransactionOp::Set { key, value }
+                    | TransactionOp::ConditionalSet { key, value, .. } => (key, Some(value)),
+                    TransactionOp::Delete { key } => (key, None),
+                };
+                TransactionOp::Set {
+                    key,
+                    value: VersionedValue { version, value }.encode(),
+                }
+            })
+            .collect();
+
+        self.replicate(self.write_consistency, |replica| {
+            let ops = replica_ops.clone();
+            async move { replica.transaction(ops).await }
+        })
+        .await
+    }
+
+    async fn health_check(&self) -> Result<StorageHealth> {
+        let start = std::time::Instant::now();
+        let mut healthy_replicas = 0;
+        let mut errors = Vec::new();
+
+        for (index, replica) in self.replicas.iter().enumerate() {
+            match replica.health_check().await {
+                Ok(health) if health.healthy => healthy_replicas += 1,
+                Ok(health) => errors.push(format!(
+                    "replica {}: {}",
+                    index,
+                    health.error.unwrap_or_else(|| "unhealthy".to_string())
+                )),
+                Err(e) => errors.push(format!("replica {}: {}", index, e)),
+            }
+        }
+
+        let required = self
+            .read_consistency
+            .required(self.replicas.len())
+            .max(self.write_consistency.required(self.replicas.len()));
+
+        let mut metadata = HashMap::new();
+        metadata.insert("backend".to_string(), "replicated".to_string());
+        metadata.insert("replicas".to_string(), self.replicas.len().to_string());
+        metadata.insert("healthy_replicas".to_string(), healthy_replicas.to_string());
+        metadata.insert(
+            "read_consistency".to_string(),
+            self.read_consistency.to_string(),
+        );
+        metadata.insert(
+            "write_consistency".to_string(),
+            self.write_consistency.to_string(),
+        );
+
+        Ok(StorageHealth {
+            healthy: healthy_replicas >= required,
+            latency_ms: start.elapsed().as_millis() as u64,
+            error: (!errors.is_empty()).then(|| errors.join("; ")),
+            metadata,
+        })
+    }
+}
+
+/// Get current timestamp in seconds since UNIX epoch
+fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Storage key holding the newest fencing token accepted by the backend
+const FENCING_TOKEN_KEY: &str = "__fencing_token";
+
+/// Storage that only accepts writes from the current leader.
+///
+/// Every mutation asks the consensus engine for its fencing token and is
+/// applied in one transaction with a conditional update of the highest
+/// token the backend has seen. A leader deposed during a partition still
+/// holds its old, smaller token once it wakes up, so its writes are refused
+/// with [`ClusterError::StaleFencingToken`] instead of overwriting its
+/// successor's. Reads are passed through unchanged.
+pub struct FencedStorage {
+    inner: Arc<dyn DistributedConfigStorage>,
+    consensus: Arc<dyn ConsensusEngine>,
+}
+
+impl FencedStorage {
+    pub fn new(
+        inner: Arc<dyn DistributedConfigStorage>,
+        consensus: Arc<dyn ConsensusEngine>,
+    ) -> Self {
+        Self { inner, consensus }
+    }
+
+    /// Highest fencing token the backend has accepted
+    pub async fn highest_token(&self) -> Result<Option<FencingToken>> {
+        Ok(self
+            .inner
+            .get_config(FENCING_TOKEN_KEY)
+            .await?
+            .and_then(|raw| serde_json::from_value(raw).ok()))
+    }
+
+    /// Apply `ops` if this node's token is not older than the backend's
+    async fn fenced_transaction(&self, mut ops: Vec<TransactionOp>) -> Result<()> {
+        if ops.iter().any(|op| op_key(op) == FENCING_TOKEN_KEY) {
+            return Err(ClusterError::invalid_state(format!(
+                "Key {} is reserved for fencing",
+                FENCING_TOKEN_KEY
+            )));
+        }
+
+        let token = self
+            .consensus
+            .current_fencing_token()
+            .await
+            .ok_or_else(|| {
+                ClusterError::consensus("Not the leader, refusing to write to fenced storage")
+            })?;
+
+        let expected = self.inner.get_config(FENCING_TOKEN_KEY).await?;
+        self.check_token(token, expected.as_ref())?;
+
+        ops.insert(
+            0,
+            TransactionOp::ConditionalSet {
+                key: FENCING_TOKEN_KEY.to_string(),
+                value: serde_json::to_value(token)?,
+                expected,
+            },
+        );
+
+        match self.inner.transaction(ops).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // A newer leader may have written in between
+                let current = self.inner.get_config(FENCING_TOKEN_KEY).await?;
+                self.check_token(token, current.as_ref())?;
+                Err(e)
+            }
+        }
+    }
+
+    fn check_token(&self, token: FencingToken, stored: Option<&Value>) -> Result<()> {
+        let Some(highest) =
+            stored.and_then(|raw| serde_json::from_value::<FencingToken>(raw.clone()).ok())
+        else {
+            return Ok(());
+        };
+
+        if token < highest {
+            tracing::error!(
+                "Split brain: write with fencing token {} after storage accepted {}",
+                token,
+                highest
+            );
+            return Err(ClusterError::stale_fencing_token(
+                token.value(),
+                highest.value(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn op_key(op: &TransactionOp) -> &str {
+    match op {
+        TransactionOp::Set { key, .. }
+        | TransactionOp::Delete { key }
+        | TransactionOp::ConditionalSet { key, .. } => key,
+    }
+}
+
+#[async_trait]
+impl DistributedConfigStorage for FencedStorage {
+    async fn store_config(&self, key: &str, value: Value) -> Result<()> {
+        self.fenced_transaction(vec![TransactionOp::Set {
+            key: key.to_string(),
+            value,
+        }])
+        .await
+    }
+
+    async fn get_config(&self, key: &str) -> Result<Option<Value>> {
+        self.inner.get_config(key).await
+    }
+
+    async fn get_config_with_consistency(
+        &self,
+        key: &str,
+        consistency: ConsistencyLevel,
+    ) -> Result<Option<Value>> {
+        self.inner
+            .get_config_with_consistency(key, consistency)
+            .await
+    }
+
+    async fn remove_config(&self, key: &str) -> Result<Option<Value>> {
+        let old_value = self.inner.get_config(key).await?;
+        if old_value.is_some() {
+            self.fenced_transaction(vec![TransactionOp::Delete {
+                key: key.to_string(),
+            }])
+            .await?;
+        }
+        Ok(old_value)
+    }
+
+    async fn list_keys(&self) -> Result<Vec<String>> {
+        let mut keys = self.inner.list_keys().await?;
+        keys.retain(|key| key != FENCING_TOKEN_KEY);
+        Ok(keys)
+    }
+
+    async fn get_all_config(&self) -> Result<HashMap<String, Value>> {
+        let mut values = self.inner.get_all_config().await?;
+        values.remove(FENCING_TOKEN_KEY);
+        Ok(values)
+    }
+
+    async fn watch_config(&self, key: &str) -> Result<tokio::sync::mpsc::Receiver<ConfigChange>> {
+        self.inner.watch_config(key).await
+    }
+
+    async fn transaction(&self, ops: Vec<TransactionOp>) -> Result<()> {
+        self.fenced_transaction(ops).await
+    }
+
+    async fn health_check(&self) -> Result<StorageHealth> {
+        self.inner.health_check().await
+    }
+}
+
+/// How [`ClockedStorage`] settles a write that races another node's write
+/// to the same key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// The later write replaces the other one; logged, but data is lost
+    #[default]
+    LastWriterWins,
+    /// Combine both values with the storage's [`ConfigMerge`]
+    Merge,
+    /// Refuse the write with [`ClusterError::ConfigConflict`] so the caller
+    /// can re-read and retry
+    Reject,
+}
+
+/// Combines two concurrently written values of a config key
+pub trait ConfigMerge: Send + Sync {
+    /// Value to store when `incoming` races `current`
+    fn merge(&self, key: &str, current: &Value, incoming: &Value) -> Result<Value>;
+}
+
+impl<F> ConfigMerge for F
+where
+    F: Fn(&str, &Value, &Value) -> Result<Value> + Send + Sync,
+{
+    fn merge(&self, key: &str, current: &Value, incoming: &Value) -> Result<Value> {
+        self(key, current, incoming)
+    }
+}
+
+/// Merges JSON objects field by field, recursively. Where both sides set a
+/// field to something other than an object, the incoming value wins.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonMerge;
+
+impl ConfigMerge for JsonMerge {
+    fn merge(&self, _key: &str, current: &Value, incoming: &Value) -> Result<Value> {
+        Ok(json_merge(current, incoming))
+    }
+}
+
+fn json_merge(current: &Value, incoming: &Value) -> Value {
+    match (current, incoming) {
+        (Value::Object(current), Value::Object(incoming)) => {
+            let mut merged = current.clone();
+            for (field, value) in incoming {
+                let value = match current.get(field) {
+                    Some(existing) => json_merge(existing, value),
+                    None => value.clone(),
+                };
+                merged.insert(field.clone(), value);
+            }
+            Value::Object(merged)
+        }
+        (_, incoming) => incoming.clone(),
+    }
+}
+
+/// Value as stored by a [`ClockedStorage`]: the Lamport timestamp of the
+/// write and the node that made it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClockedValue {
+    #[serde(rename = "_clock")]
+    clock: u64,
+    #[serde(rename = "_writer")]
+    writer: Option<NodeId>,
+    #[serde(rename = "_value")]
+    value: Value,
+}
+
+impl ClockedValue {
+    /// Values written without an envelope count as the oldest write
+    fn decode(raw: Value) -> Self {
+        serde_json::from_value(raw.clone()).unwrap_or(Self {
+            clock: 0,
+            writer: None,
+            value: raw,
+        })
+    }
+
+    fn encode(&self) -> Value {
+        serde_json::json!({
+            "_clock": self.clock,
+            "_writer": self.writer,
+            "_value": self.value,
+        })
+    }
+}
+
+/// Attempts at a conditional write before giving up on a key that keeps
+/// changing underneath it
+const CLOCKED_WRITE_ATTEMPTS: usize = 5;
+
+/// Storage that detects concurrent writes to the same key.
+///
+/// Every value is stamped with a Lamport timestamp and the writing node.
+/// The storage remembers the newest timestamp it has read or written per
+/// key, so a stored value with a newer timestamp from another node is a
+/// write this node has not seen: overwriting it blindly would drop it.
+/// Such conflicts are settled by the configured [`ConflictStrategy`], and
+/// the write itself is a conditional update, so two nodes racing past the
+/// check cannot both succeed. Reads return the bare value.
+pub struct ClockedStorage {
+    inner: Arc<dyn DistributedConfigStorage>,
+    node_id: NodeId,
+    strategy: ConflictStrategy,
+    merge: std::sync::RwLock<Arc<dyn ConfigMerge>>,
+    clock: AtomicU64,
+    observed: RwLock<HashMap<String, u64>>,
+}
+
+impl ClockedStorage {
+    pub fn new(inner: Arc<dyn DistributedConfigStorage>, node_id: NodeId) -> Self {
+        Self {
+            inner,
+            node_id,
+            strategy: ConflictStrategy::default(),
+            merge: std::sync::RwLock::new(Arc::new(JsonMerge)),
+            clock: AtomicU64::new(0),
+            observed: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_strategy(mut self, strategy: ConflictStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Merge used by [`ConflictStrategy::Merge`]; [`JsonMerge`] by default
+    pub fn with_merge(self, merge: Arc<dyn ConfigMerge>) -> Self {
+        self.set_merge(merge);
+        self
+    }
+
+    /// Replace the merge; the storage may already be shared
+    pub fn set_merge(&self, merge: Arc<dyn ConfigMerge>) {
+        *self.merge.write().unwrap_or_else(|e| e.into_inner()) = merge;
+    }
+
+    pub fn strategy(&self) -> ConflictStrategy {
+        self.strategy
+    }
+
+    /// Advance the Lamport clock past `seen`, returning the new timestamp
+    fn tick(&self, seen: u64) -> u64 {
+        self.clock.fetch_max(seen, Ordering::SeqCst);
+        self.clock.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    async fn observe(&self, key: &str, clock: u64) {
+        self.clock.fetch_max(clock, Ordering::SeqCst);
+        let mut observed = self.observed.write().await;
+        let entry = observed.entry(key.to_string()).or_insert(0);
+        *entry = (*entry).max(clock);
+    }
+
+    async fn observed(&self, key: &str) -> u64 {
+        self.observed.read().await.get(key).copied().unwrap_or(0)
+    }
+
+    fn stamp(&self, value: Value, seen: u64) -> ClockedValue {
+        ClockedValue {
+            clock: self.tick(seen),
+            writer: Some(self.node_id.clone()),
+            value,
+        }
+    }
+
+    /// Value to write over `current` given the strategy, or the rejection
+    async fn resolve(&self, key: &str, current: &ClockedValue, incoming: Value) -> Result<Value> {
+        let concurrent = current.clock > self.observed(key).await
+            && current.writer.as_ref() != Some(&self.node_id);
+        if !concurrent {
+            return Ok(incoming);
+        }
+
+        let writer = current
+            .writer
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_else(|| "unknown".to_string());
+        match self.strategy {
+            ConflictStrategy::LastWriterWins => {
+                tracing::warn!(
+                    "Overwriting concurrent write to config key {} by node {}",
+                    key,
+                    writer
+                );
+                Ok(incoming)
+            }
+            ConflictStrategy::Merge => {
+                tracing::info!(
+                    "Merging concurrent writes to config key {} with node {}",
+                    key,
+                    writer
+                );
+                let merge = self.merge.read().unwrap_or_else(|e| e.into_inner()).clone();
+                merge.merge(key, &current.value, &incoming)
+            }
+            ConflictStrategy::Reject => Err(ClusterError::config_conflict(key, writer)),
+        }
+    }
+
+    async fn write(
+        &self,
+        key: &str,
+        value: Value,
+        consistency: Option<ConsistencyLevel>,
+    ) -> Result<()> {
+        let mut raw = self.read_raw(key, consistency).await?;
+
+        for _ in 0..CLOCKED_WRITE_ATTEMPTS {
+            let current = raw.clone().map(ClockedValue::decode);
+            let (resolved, seen) = match &current {
+                Some(current) => (
+                    self.resolve(key, current, value.clone()).await?,
+                    current.clock,
+                ),
+                None => (value.clone(), 0),
+            };
+            let stamped = self.stamp(resolved, seen);
+
+            let op = TransactionOp::ConditionalSet {
+                key: key.to_string(),
+                value: stamped.encode(),
+                expected: raw.clone(),
+            };
+            match self.inner.transaction(vec![op]).await {
+                Ok(()) => {
+                    self.observe(key, stamped.clock).await;
+                    return Ok(());
+                }
+                Err(e) => {
+                    // Only a write racing ours is worth another attempt
+                    let latest = self.read_raw(key, consistency).await?;
+                    if latest == raw {
+                        return Err(e);
+                    }
+                    raw = latest;
+                }
+            }
+        }
+
+        let writer = raw
+            .map(ClockedValue::decode)
+            .and_then(|current| current.writer)
+            .map(|writer| writer.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        Err(ClusterError::config_conflict(key, writer))
+    }
+
+    async fn read_raw(
+        &self,
+        key: &str,
+        consistency: Option<ConsistencyLevel>,
+    ) -> Result<Option<Value>> {
+        match consistency {
+            Some(consistency) => {
+                self.inner
+                    .get_config_with_consistency(key, consistency)
+                    .await
+            }
+            None => self.inner.get_config(key).await,
+        }
+    }
+
+    async fn read(
+        &self,
+        key: &str,
+        consistency: Option<ConsistencyLevel>,
+    ) -> Result<Option<Value>> {
+        let Some(raw) = self.read_raw(key, consistency).await? else {
+            return Ok(None);
+        };
+        let current = ClockedValue::decode(raw);
+        self.observe(key, current.clock).await;
+        Ok(Some(current.value))
+    }
+}
+
+#[async_trait]
+impl DistributedConfigStorage for ClockedStorage {
+    async fn store_config(&self, key: &str, value: Value) -> Result<()> {
+        self.write(key, value, None).await
+    }
+
+    async fn get_config(&self, key: &str) -> Result<Option<Value>> {
+        self.read(key, None).await
+    }
+
+    async fn store_config_with_consistency(
+        &self,
+        key: &str,
+        value: Value,
+        consistency: ConsistencyLevel,
+    ) -> Result<()> {
+        self.write(key, value, Some(consistency)).await
+    }
+
+    async fn get_config_with_consistency(
+        &self,
+        key: &str,
+        consistency: ConsistencyLevel,
+    ) -> Result<Option<Value>> {
+        self.read(key, Some(consistency)).await
+    }
+
+    async fn remove_config(&self, key: &str) -> Result<Option<Value>> {
+        Ok(self
+            .inner
+            .remove_config(key)
+            .await?
+            .map(|raw| ClockedValue::decode(raw).value))
+    }
+
+    async fn list_keys(&self) -> Result<Vec<String>> {
+        self.inner.list_keys().await
+    }
+
+    async fn get_all_config(&self) -> Result<HashMap<String, Value>> {
+        let mut values = HashMap::new();
+        for (key, raw) in self.inner.get_all_config().await? {
+            let current = ClockedValue::decode(raw);
+            self.observe(&key, current.clock).await;
+            values.insert(key, current.value);
+        }
+        Ok(values)
+    }
+
+    async fn watch_config(&self, key: &str) -> Result<tokio::sync::mpsc::Receiver<ConfigChange>> {
+        let mut changes = self.inner.watch_config(key).await?;
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        tokio::spawn(async move {
+            while let Some(change) = changes.recv().await {
+                let change = ConfigChange {
+                    key: change.key,
+                    old_value: change.old_value.map(|raw| ClockedValue::decode(raw).value),
+                    new_value: change.new_value.map(|raw| ClockedValue::decode(raw).value),
+                    timestamp: change.timestamp,
+                };
+                if tx.send(change).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    /// Stamps the written values. Conflicts are not resolved here; use
+    /// [`TransactionOp::ConditionalSet`] to guard against concurrent writes.
+    async fn transaction(&self, ops: Vec<TransactionOp>) -> Result<()> {
+        let mut stamped_ops = Vec::with_capacity(ops.len());
+        let mut written = Vec::new();
+        for op in ops {
+            let op = match op {
+                TransactionOp::Set { key, value } => {
+                    let stamped = self.stamp(value, self.observed(&key).await);
+                    written.push((key.clone(), stamped.clock));
+                    TransactionOp::Set {
+                        key,
+                        value: stamped.encode(),
+                    }
+                }
+                TransactionOp::ConditionalSet {
+                    key,
+                    value,
+                    expected,
+                } => {
+                    // The caller expects a bare value; compare it with the
+                    // stored one and guard on the stored envelope instead
+                    let raw = self.inner.get_config(&key).await?;
+                    let current = raw.clone().map(ClockedValue::decode);
+                    if current.as_ref().map(|current| &current.value) != expected.as_ref() {
+                        return Err(ClusterError::invalid_state(format!(
+                            "Conditional set failed for key {}",
+                            key
+                        )));
+                    }
+                    let seen = current.map(|current| current.clock).unwrap_or(0);
+                    let stamped = self.stamp(value, seen);
+                    written.push((key.clone(), stamped.clock));
+                    TransactionOp::ConditionalSet {
+                        key,
+                        value: stamped.encode(),
+                        expected: raw,
+                    }
+                }
+                delete @ TransactionOp::Delete { .. } => delete,
+            };
+            stamped_ops.push(op);
+        }
+
+        self.inner.transaction(stamped_ops).await?;
+        for (key, clock) in written {
+            self.observe(&key, clock).await;
+        }
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<StorageHealth> {
+        self.inner.health_check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use tokio::time::{timeout, Duration};
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        let storage = MemoryStorage::new();
+
+        // Test store and get
+        let value = serde_json::json!({"test": "value"});
+        storage
+            .store_config("test_key", value.clone())
+            .await
+            .unwrap();
+
+        let retrieved = storage.get_config("test_key").await.unwrap();
+        assert_eq!(retrieved, Some(value));
+
+        // Test list keys
+        let keys = storage.list_keys().await.unwrap();
+        assert!(keys.contains(&"test_key".to_string()));
+
+        // Test remove
+        let removed = storage.remove_config("test_key").await.unwrap();
+        assert_eq!(removed, Some(serde_json::json!({"test": "value"})));
+
+        let after_remove = storage.get_config("test_key").await.unwrap();
+        assert_eq!(after_remove, None);
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage_watch() {
+        let storage = MemoryStorage::new();
+
+        let mut watcher = storage.watch_config("watch_key").await.unwrap();
+
+        // Store value and check notification
+        let value = serde_json::json!("watched_value");
+        storage
+            .store_config("watch_key", value.clone())
+            .await
+            .unwrap();
+
+        let change = timeout(Duration::from_millis(100), watcher.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.key, "watch_key");
+        assert_eq!(change.new_value, Some(value));
+        assert_eq!(change.old_value, None);
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage_transaction() {
+        let storage = MemoryStorage::new();
+
+        // Set initial value
+        storage
+            .store_config("key1", serde_json::json!("value1"))
+            .await
+            .unwrap();
+
+        // Test successful transaction
+        let ops = vec![
+            TransactionOp::Set {
+                key: "key2".to_string(),
+                value: serde_json::json!("value2"),
+            },
+            TransactionOp::ConditionalSet {
+                key: "key1".to_string(),
+                value: serde_json::json!("updated_value1"),
+                expected: Some(serde_json::json!("value1")),
+            },
+        ];
+
+        storage.transaction(ops).await.unwrap();
+
+        assert_eq!(
+            storage.get_config("key1").await.unwrap(),
+            Some(serde_json::json!("updated_value1"))
+        );
+        assert_eq!(
+            storage.get_config("key2").await.unwrap(),
+            Some(serde_json::json!("value2"))
+        );
+
+        // Test failed conditional transaction
+        let failing_ops = vec![TransactionOp::ConditionalSet {
+            key: "key1".to_string(),
+            value: serde_json::json!("should_not_update"),
+            expected: Some(serde_json::json!("wrong_expected_value")),
+        }];
+
+        assert!(storage.transaction(failing_ops).await.is_err());
+        assert_eq!(
+            storage.get_config("key1").await.unwrap(),
+            Some(serde_json::json!("updated_value1"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sled_storage() {
+        let temp_dir = tempdir().unwrap();
+        let storage = SledStorage::new(temp_dir.path()).await.unwrap();
+
+        // Test basic operations
+        let value = serde_json::json!({"sled": "test"});
+        storage
+            .store_config("sled_key", value.clone())
+            .await
+            .unwrap();
+
+        let retrieved = storage.get_config("sled_key").await.unwrap();
+        assert_eq!(retrieved, Some(value));
+
+        // Test persistence by reopening the database
+        drop(storage);
+        let storage2 = SledStorage::new(temp_dir.path()).await.unwrap();
         let retrieved2 = storage2.get_config("sled_key").await.unwrap();
         assert_eq!(retrieved2, Some(serde_json::json!({"sled": "test"})));
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sled_storage_dir_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("storage");
+        let _storage = SledStorage::new(&path).await.unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[tokio::test]
+    async fn test_etcd_storage_requires_endpoints() {
+        assert!(EtcdStorage::new(&[], None).await.is_err());
+    }
+
+    #[test]
+    fn test_etcd_connect_options() {
+        // No credentials and no TLS: use client defaults
+        let options = EtcdStorage::connect_options(None, None, false, None, None, None).unwrap();
+        assert!(options.is_none());
+
+        // Credentials only
+        let options =
+            EtcdStorage::connect_options(Some("root"), Some("secret"), false, None, None, None)
+                .unwrap();
+        assert!(options.is_some());
+
+        // Client certificate without a key is rejected
+        let temp_dir = tempdir().unwrap();
+        let cert_path = temp_dir.path().join("client.pem");
+        std::fs::write(&cert_path, b"cert").unwrap();
+        let result = EtcdStorage::connect_options(None, None, true, None, Some(&cert_path), None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_etcd_key_prefix() {
+        let key = EtcdStorage::prefixed_key("server/port");
+        assert_eq!(key, "/vpn-cluster/config/server/port");
+        assert_eq!(
+            EtcdStorage::strip_prefix(key.as_bytes()),
+            Some("server/port".to_string())
+        );
+        assert_eq!(EtcdStorage::strip_prefix(b"/other/key"), None);
+    }
+
     #[tokio::test]
     async fn test_storage_health_check() {
         let storage = MemoryStorage::new();
@@ -762,4 +2177,355 @@ mod tests {
         let sled_value = sled_storage.get_config("sled_test").await.unwrap();
         assert_eq!(sled_value, Some(serde_json::json!("sled_value")));
     }
+    /// Replica that is down
+    struct UnreachableStorage;
+
+    #[async_trait]
+    impl DistributedConfigStorage for UnreachableStorage {
+        async fn store_config(&self, _key: &str, _value: Value) -> Result<()> {
+            Err(ClusterError::network("replica unreachable"))
+        }
+
+        async fn get_config(&self, _key: &str) -> Result<Option<Value>> {
+            Err(ClusterError::network("replica unreachable"))
+        }
+
+        async fn remove_config(&self, _key: &str) -> Result<Option<Value>> {
+            Err(ClusterError::network("replica unreachable"))
+        }
+
+        async fn list_keys(&self) -> Result<Vec<String>> {
+            Err(ClusterError::network("replica unreachable"))
+        }
+
+        async fn get_all_config(&self) -> Result<HashMap<String, Value>> {
+            Err(ClusterError::network("replica unreachable"))
+        }
+
+        async fn watch_config(
+            &self,
+            _key: &str,
+        ) -> Result<tokio::sync::mpsc::Receiver<ConfigChange>> {
+            Err(ClusterError::network("replica unreachable"))
+        }
+
+        async fn transaction(&self, _ops: Vec<TransactionOp>) -> Result<()> {
+            Err(ClusterError::network("replica unreachable"))
+        }
+
+        async fn health_check(&self) -> Result<StorageHealth> {
+            Err(ClusterError::network("replica unreachable"))
+        }
+    }
+
+    #[test]
+    fn test_consistency_levels() {
+        assert_eq!(ConsistencyLevel::One.required(3), 1);
+        assert_eq!(ConsistencyLevel::Quorum.required(3), 2);
+        assert_eq!(ConsistencyLevel::Quorum.required(4), 3);
+        assert_eq!(ConsistencyLevel::All.required(3), 3);
+        assert_eq!(
+            "QUORUM".parse::<ConsistencyLevel>().unwrap(),
+            ConsistencyLevel::Quorum
+        );
+        assert!("most".parse::<ConsistencyLevel>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replicated_storage_consistency() {
+        let storage = ReplicatedStorage::new(vec![
+            Arc::new(MemoryStorage::new()),
+            Arc::new(MemoryStorage::new()),
+            Arc::new(UnreachableStorage),
+        ])
+        .unwrap();
+        let value = serde_json::json!({"mtu": 1420});
+
+        storage.store_config("net", value.clone()).await.unwrap();
+        assert_eq!(
+            storage.get_config("net").await.unwrap(),
+            Some(value.clone())
+        );
+
+        let err = storage
+            .store_config_with_consistency("net", value.clone(), ConsistencyLevel::All)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClusterError::QuorumNotAvailable {
+                current: 2,
+                required: 3
+            }
+        ));
+        assert!(storage
+            .get_config_with_consistency("net", ConsistencyLevel::All)
+            .await
+            .is_err());
+
+        assert_eq!(storage.remove_config("net").await.unwrap(), Some(value));
+        assert_eq!(storage.get_config("net").await.unwrap(), None);
+        assert!(storage.list_keys().await.unwrap().is_empty());
+        assert!(storage.health_check().await.unwrap().error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_replicated_storage_read_repair() {
+        let replicas: Vec<Arc<MemoryStorage>> =
+            (0..3).map(|_| Arc::new(MemoryStorage::new())).collect();
+        let storage = ReplicatedStorage::new(
+            replicas
+                .iter()
+                .map(|r| r.clone() as Arc<dyn DistributedConfigStorage>)
+                .collect(),
+        )
+        .unwrap();
+
+        storage
+            .store_config_with_consistency("key", serde_json::json!("new"), ConsistencyLevel::All)
+            .await
+            .unwrap();
+        // Replica 2 missed the write and still holds an older version
+        replicas[2]
+            .store_config("key", serde_json::json!({"_version": 1, "_value": "old"}))
+            .await
+            .unwrap();
+
+        let value = storage
+            .get_config_with_consistency("key", ConsistencyLevel::All)
+            .await
+            .unwrap();
+        assert_eq!(value, Some(serde_json::json!("new")));
+
+        timeout(Duration::from_secs(1), async {
+            loop {
+                let raw = replicas[2].get_config("key").await.unwrap().unwrap();
+                if raw["_value"] == "new" {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("stale replica was not repaired");
+    }
+
+    #[tokio::test]
+    async fn test_replicated_storage_transaction() {
+        let storage = ReplicatedStorage::new(vec![
+            Arc::new(MemoryStorage::new()),
+            Arc::new(MemoryStorage::new()),
+            Arc::new(MemoryStorage::new()),
+        ])
+        .unwrap()
+        .with_consistency(ConsistencyLevel::One, ConsistencyLevel::All);
+
+        storage
+            .transaction(vec![
+                TransactionOp::Set {
+                    key: "a".to_string(),
+                    value: serde_json::json!(1),
+                },
+                TransactionOp::ConditionalSet {
+                    key: "b".to_string(),
+                    value: serde_json::json!(2),
+                    expected: None,
+                },
+            ])
+            .await
+            .unwrap();
+        assert_eq!(storage.list_keys().await.unwrap(), vec!["a", "b"]);
+
+        let conflict = storage
+            .transaction(vec![TransactionOp::ConditionalSet {
+                key: "b".to_string(),
+                value: serde_json::json!(3),
+                expected: None,
+            }])
+            .await;
+        assert!(conflict.is_err());
+        assert_eq!(
+            storage.get_config("b").await.unwrap(),
+            Some(serde_json::json!(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fenced_storage_rejects_stale_leader() {
+        use crate::consensus::SimpleConsensus;
+
+        let backend: Arc<dyn DistributedConfigStorage> = Arc::new(MemoryStorage::new());
+        let old_leader = Arc::new(SimpleConsensus::new(NodeId::new()));
+        let new_leader = Arc::new(SimpleConsensus::new(NodeId::new()));
+        let old_storage = FencedStorage::new(backend.clone(), old_leader.clone());
+        let new_storage = FencedStorage::new(backend.clone(), new_leader.clone());
+
+        // Followers cannot write at all
+        assert!(old_storage
+            .store_config("a", serde_json::json!(1))
+            .await
+            .is_err());
+
+        old_leader.elect_leader().await.unwrap();
+        old_storage
+            .store_config("a", serde_json::json!(1))
+            .await
+            .unwrap();
+
+        // A partition elects a new leader in a later term
+        new_leader.elect_leader().await.unwrap();
+        new_leader.elect_leader().await.unwrap();
+        new_storage
+            .store_config("a", serde_json::json!(2))
+            .await
+            .unwrap();
+
+        // The old leader still believes it leads, but its token is stale
+        let err = old_storage
+            .store_config("a", serde_json::json!(3))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ClusterError::StaleFencingToken {
+                token: 1,
+                highest: 2
+            }
+        ));
+        assert!(old_storage.remove_config("a").await.is_err());
+        assert_eq!(
+            backend.get_config("a").await.unwrap(),
+            Some(serde_json::json!(2))
+        );
+
+        assert_eq!(
+            new_storage.list_keys().await.unwrap(),
+            vec!["a".to_string()]
+        );
+        assert_eq!(
+            new_storage.highest_token().await.unwrap(),
+            Some(FencingToken::from_term(2))
+        );
+    }
+
+    fn clocked_pair(
+        strategy: ConflictStrategy,
+    ) -> (Arc<MemoryStorage>, ClockedStorage, ClockedStorage) {
+        let backend = Arc::new(MemoryStorage::new());
+        let a = ClockedStorage::new(backend.clone(), NodeId::new()).with_strategy(strategy);
+        let b = ClockedStorage::new(backend.clone(), NodeId::new()).with_strategy(strategy);
+        (backend, a, b)
+    }
+
+    #[tokio::test]
+    async fn test_clocked_storage_sequential_writes() {
+        let (backend, a, b) = clocked_pair(ConflictStrategy::Reject);
+
+        a.store_config("vpn", serde_json::json!({"port": 443}))
+            .await
+            .unwrap();
+        // b read the value first, so its write is not concurrent
+        assert_eq!(
+            b.get_config("vpn").await.unwrap(),
+            Some(serde_json::json!({"port": 443}))
+        );
+        b.store_config("vpn", serde_json::json!({"port": 8443}))
+            .await
+            .unwrap();
+
+        let stored = ClockedValue::decode(backend.get_config("vpn").await.unwrap().unwrap());
+        assert_eq!(stored.clock, 2);
+        assert_eq!(stored.writer.as_ref(), Some(&b.node_id));
+        assert_eq!(
+            a.get_all_config().await.unwrap()["vpn"],
+            serde_json::json!({"port": 8443})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clocked_storage_conflict_strategies() {
+        let (_, a, b) = clocked_pair(ConflictStrategy::Reject);
+        a.store_config("vpn", serde_json::json!({"port": 443}))
+            .await
+            .unwrap();
+        let err = b
+            .store_config("vpn", serde_json::json!({"protocol": "vless"}))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ClusterError::ConfigConflict { ref key, .. } if key == "vpn"));
+        assert_eq!(
+            b.get_config("vpn").await.unwrap(),
+            Some(serde_json::json!({"port": 443}))
+        );
+
+        let (_, a, b) = clocked_pair(ConflictStrategy::Merge);
+        a.store_config("vpn", serde_json::json!({"port": 443, "tls": {"sni": "a"}}))
+            .await
+            .unwrap();
+        b.store_config(
+            "vpn",
+            serde_json::json!({"protocol": "vless", "tls": {"alpn": "h2"}}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            a.get_config("vpn").await.unwrap(),
+            Some(serde_json::json!({
+                "port": 443,
+                "protocol": "vless",
+                "tls": {"sni": "a", "alpn": "h2"}
+            }))
+        );
+
+        let (_, a, b) = clocked_pair(ConflictStrategy::LastWriterWins);
+        a.store_config("vpn", serde_json::json!(1)).await.unwrap();
+        b.store_config("vpn", serde_json::json!(2)).await.unwrap();
+        assert_eq!(
+            a.get_config("vpn").await.unwrap(),
+            Some(serde_json::json!(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clocked_storage_merge_callback() {
+        let (_, a, b) = clocked_pair(ConflictStrategy::Merge);
+        let b = b.with_merge(Arc::new(
+            |_: &str, current: &Value, incoming: &Value| -> Result<Value> {
+                Ok(serde_json::json!(
+                    current.as_u64().unwrap_or(0) + incoming.as_u64().unwrap_or(0)
+                ))
+            },
+        ));
+
+        a.store_config("limit", serde_json::json!(10))
+            .await
+            .unwrap();
+        b.store_config("limit", serde_json::json!(5)).await.unwrap();
+        assert_eq!(
+            b.get_config("limit").await.unwrap(),
+            Some(serde_json::json!(15))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clocked_storage_legacy_values() {
+        let (backend, a, _) = clocked_pair(ConflictStrategy::Reject);
+        backend
+            .store_config("vpn", serde_json::json!({"port": 443}))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            a.get_config("vpn").await.unwrap(),
+            Some(serde_json::json!({"port": 443}))
+        );
+        // Unstamped values are never treated as concurrent
+        a.store_config("vpn", serde_json::json!({"port": 8443}))
+            .await
+            .unwrap();
+        assert_eq!(
+            a.remove_config("vpn").await.unwrap(),
+            Some(serde_json::json!({"port": 8443}))
+        );
+    }
 }
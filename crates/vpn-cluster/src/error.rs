@@ -53,6 +53,12 @@ pub enum ClusterError {
     #[error("Split brain detected: multiple leaders found")]
     SplitBrain,
 
+    #[error("Stale fencing token {token}: storage already accepted {highest}")]
+    StaleFencingToken { token: u64, highest: u64 },
+
+    #[error("Concurrent write to config key {key} by node {writer}")]
+    ConfigConflict { key: String, writer: String },
+
     #[error("Quorum not available: {current}/{required} nodes")]
     QuorumNotAvailable { current: usize, required: usize },
 
@@ -122,6 +128,17 @@ impl ClusterError {
         Self::QuorumNotAvailable { current, required }
     }
 
+    pub fn stale_fencing_token(token: u64, highest: u64) -> Self {
+        Self::StaleFencingToken { token, highest }
+    }
+
+    pub fn config_conflict<K: Into<String>, W: Into<String>>(key: K, writer: W) -> Self {
+        Self::ConfigConflict {
+            key: key.into(),
+            writer: writer.into(),
+        }
+    }
+
     pub fn node_already_exists<T: Into<String>>(node_id: T) -> Self {
         Self::NodeAlreadyExists(node_id.into())
     }
@@ -141,6 +158,12 @@ impl From<sled::Error> for ClusterError {
     }
 }
 
+impl From<etcd_client::Error> for ClusterError {
+    fn from(err: etcd_client::Error) -> Self {
+        Self::storage(format!("etcd error: {}", err))
+    }
+}
+
 impl From<tonic::Status> for ClusterError {
     fn from(err: tonic::Status) -> Self {
         Self::network(format!("gRPC error: {}", err))
@@ -0,0 +1,153 @@
+//! Cluster event bus
+//!
+//! [`ClusterEventBus`] is a cloneable handle on the coordinator's broadcast
+//! channel. Cluster components publish [`CoordinationEvent`]s on it and other
+//! crates consume them either as a raw receiver or as a [`ClusterEventStream`].
+
+use crate::coordination::CoordinationEvent;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::Stream;
+
+/// Events buffered per subscriber before slow subscribers start lagging
+pub const DEFAULT_EVENT_CAPACITY: usize = 1000;
+
+/// Publish/subscribe handle for cluster events
+#[derive(Debug, Clone)]
+pub struct ClusterEventBus {
+    tx: broadcast::Sender<CoordinationEvent>,
+}
+
+impl ClusterEventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    /// Publish an event, returning how many subscribers will see it
+    pub fn publish(&self, event: CoordinationEvent) -> usize {
+        self.tx.send(event).unwrap_or(0)
+    }
+
+    /// Subscribe to events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<CoordinationEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Subscribe to events published from now on as a stream
+    pub fn stream(&self) -> ClusterEventStream {
+        ClusterEventStream {
+            inner: BroadcastStream::new(self.tx.subscribe()),
+        }
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.tx.receiver_count()
+    }
+}
+
+impl Default for ClusterEventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_CAPACITY)
+    }
+}
+
+impl From<broadcast::Sender<CoordinationEvent>> for ClusterEventBus {
+    fn from(tx: broadcast::Sender<CoordinationEvent>) -> Self {
+        Self { tx }
+    }
+}
+
+/// Stream of cluster events.
+///
+/// A subscriber that falls more than the bus capacity behind skips the
+/// events it missed; the stream ends when every bus handle is dropped.
+pub struct ClusterEventStream {
+    inner: BroadcastStream<CoordinationEvent>,
+}
+
+impl ClusterEventStream {
+    /// Wait for the next event; `None` once the bus is gone
+    pub async fn next_event(&mut self) -> Option<CoordinationEvent> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for ClusterEventStream {
+    type Item = CoordinationEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(event))) => return Poll::Ready(Some(event)),
+                Poll::Ready(Some(Err(BroadcastStreamRecvError::Lagged(skipped)))) => {
+                    tracing::warn!(
+                        "Cluster event subscriber lagged, skipped {} events",
+                        skipped
+                    );
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::NodeId;
+
+    fn config_changed(key: &str) -> CoordinationEvent {
+        CoordinationEvent::ConfigurationChanged {
+            key: key.to_string(),
+            timestamp: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_receives_published_events() {
+        let bus = ClusterEventBus::default();
+        let mut stream = bus.stream();
+
+        assert_eq!(bus.publish(config_changed("a")), 1);
+        bus.publish(CoordinationEvent::NodeJoined {
+            node_id: NodeId::new(),
+            timestamp: 0,
+        });
+
+        assert_eq!(
+            stream.next_event().await.unwrap().kind(),
+            "configuration_changed"
+        );
+        assert_eq!(stream.next_event().await.unwrap().kind(), "node_joined");
+
+        drop(bus);
+        assert!(stream.next_event().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_skips_missed_events() {
+        let bus = ClusterEventBus::new(2);
+        let mut stream = bus.stream();
+
+        for key in ["a", "b", "c"] {
+            bus.publish(config_changed(key));
+        }
+
+        match stream.next_event().await.unwrap() {
+            CoordinationEvent::ConfigurationChanged { key, .. } => assert_eq!(key, "b"),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_publish_without_subscribers() {
+        let bus = ClusterEventBus::default();
+        assert_eq!(bus.publish(config_changed("a")), 0);
+        assert_eq!(bus.subscriber_count(), 0);
+    }
+}
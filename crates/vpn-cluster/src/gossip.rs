@@ -1,26 +1,800 @@
 //! Gossip protocol implementation for cluster communication
+//!
+//! Messages are not sent as they are published. They are queued and flushed
+//! every [`GossipConfig::interval`] to `fanout` random peers, packed into
+//! batches no larger than [`GossipConfig::max_message_size`] and compressed
+//! with zstd. Each peer has a token bucket limiting how many batches it is
+//! sent per second; messages a rate-limited round could not deliver stay
+//! queued for the next one. A burst of user creations on a large cluster
+//! therefore costs a handful of requests per peer per round rather than one
+//! request per change per peer.
+//!
+//! Received messages are delivered once to [`GossipManager::subscribe`]
+//! receivers and relayed onwards until their hop budget runs out.
+//!
+//! Node tombstones are gossiped by the manager itself: every round it
+//! announces the tombstones of its cluster state that it has not announced
+//! yet as a [`TOMBSTONE_GOSSIP_KIND`] message, and merges the ones it
+//! receives, so a removal reaches nodes that never talk to the node that
+//! made it.
 
-use crate::error::Result;
-use crate::node::NodeId;
+use crate::communication::cluster::GossipBatch;
+use crate::communication::ClusterGrpcClient;
+use crate::config::{GossipCompression, GossipConfig};
+use crate::coordination::current_timestamp;
+use crate::error::{ClusterError, Result};
+use crate::node::{NodeId, NodeStatus};
+use crate::state::{ClusterState, NodeTombstone};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use uuid::Uuid;
 
-/// Gossip protocol manager (placeholder)
+/// Batches waiting to be processed before peers are told to back off
+const INBOUND_QUEUE_SIZE: usize = 64;
+
+/// Kind of the messages carrying node tombstones
+pub const TOMBSTONE_GOSSIP_KIND: &str = "node_tombstones";
+
+/// A single piece of gossiped state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GossipMessage {
+    pub id: Uuid,
+    pub origin: NodeId,
+    /// Caller-defined message type, e.g. `user_created`
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub created_at: u64,
+    /// Remaining relays before the message stops spreading
+    pub hops: u8,
+}
+
+impl GossipMessage {
+    pub fn new(origin: NodeId, kind: &str, payload: serde_json::Value, hops: u8) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            origin,
+            kind: kind.to_string(),
+            payload,
+            created_at: current_timestamp(),
+            hops,
+        }
+    }
+}
+
+impl GossipCompression {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GossipCompression::None => "none",
+            GossipCompression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Pack `messages` into batches in order, each holding at most
+/// `max_batch_messages` messages and `max_message_size` bytes of JSON.
+///
+/// The size limit applies to the uncompressed payload; when compression
+/// does not shrink a batch it is sent uncompressed, so the wire payload
+/// never exceeds it either. A message too large for any batch is sent on
+/// its own; [`GossipManager`] refuses to queue such messages.
+pub fn encode_batches(
+    from_node: &NodeId,
+    messages: &[GossipMessage],
+    config: &GossipConfig,
+) -> Result<Vec<GossipBatch>> {
+    let mut batches = Vec::new();
+    let mut encoded: Vec<Vec<u8>> = Vec::new();
+    let mut size = 0;
+
+    for message in messages {
+        let json = serde_json::to_vec(message)?;
+        // Brackets plus a separator per message
+        let added = json.len() + 1;
+        let full = encoded.len() >= config.max_batch_messages
+            || size + added + 1 > config.max_message_size;
+        if !encoded.is_empty() && full {
+            batches.push(seal_batch(from_node, &encoded, config)?);
+            encoded.clear();
+            size = 0;
+        }
+        size += added;
+        encoded.push(json);
+    }
+
+    if !encoded.is_empty() {
+        batches.push(seal_batch(from_node, &encoded, config)?);
+    }
+
+    Ok(batches)
+}
+
+fn seal_batch(
+    from_node: &NodeId,
+    encoded: &[Vec<u8>],
+    config: &GossipConfig,
+) -> Result<GossipBatch> {
+    let mut raw = Vec::with_capacity(encoded.iter().map(|json| json.len() + 1).sum::<usize>() + 1);
+    raw.push(b'[');
+    for (index, json) in encoded.iter().enumerate() {
+        if index > 0 {
+            raw.push(b',');
+        }
+        raw.extend_from_slice(json);
+    }
+    raw.push(b']');
+
+    let (encoding, payload) = match config.compression {
+        GossipCompression::None => (GossipCompression::None, raw),
+        GossipCompression::Zstd => {
+            let compressed = zstd::bulk::compress(&raw, config.compression_level)
+                .map_err(|e| ClusterError::network(format!("Gossip compression failed: {}", e)))?;
+            if compressed.len() < raw.len() {
+                (GossipCompression::Zstd, compressed)
+            } else {
+                (GossipCompression::None, raw)
+            }
+        }
+    };
+
+    Ok(GossipBatch {
+        from_node_id: from_node.to_string(),
+        encoding: encoding.as_str().to_string(),
+        message_count: encoded.len() as u32,
+        payload,
+    })
+}
+
+/// Unpack a received batch, refusing payloads that would decompress to more
+/// than `max_message_size` bytes
+pub fn decode_batch(batch: &GossipBatch, max_message_size: usize) -> Result<Vec<GossipMessage>> {
+    let raw = match batch.encoding.as_str() {
+        "zstd" => zstd::bulk::decompress(&batch.payload, max_message_size).map_err(|e| {
+            ClusterError::network(format!(
+                "Invalid gossip batch from {}: {}",
+                batch.from_node_id, e
+            ))
+        })?,
+        "none" | "" => {
+            if batch.payload.len() > max_message_size {
+                return Err(ClusterError::network(format!(
+                    "Gossip batch from {} exceeds {} bytes",
+                    batch.from_node_id, max_message_size
+                )));
+            }
+            batch.payload.clone()
+        }
+        other => {
+            return Err(ClusterError::network(format!(
+                "Unsupported gossip encoding '{}'",
+                other
+            )))
+        }
+    };
+
+    let messages: Vec<GossipMessage> = serde_json::from_slice(&raw)?;
+    if messages.len() != batch.message_count as usize {
+        return Err(ClusterError::network(format!(
+            "Gossip batch from {} announced {} messages but carried {}",
+            batch.from_node_id,
+            batch.message_count,
+            messages.len()
+        )));
+    }
+
+    Ok(messages)
+}
+
+/// Token bucket limiting the batches sent to one peer
+#[derive(Debug, Clone)]
+struct PeerLimiter {
+    tokens: f64,
+    capacity: f64,
+    rate: f64,
+    refilled_at: Instant,
+}
+
+impl PeerLimiter {
+    fn new(rate: u32, burst: u32) -> Self {
+        Self {
+            tokens: burst as f64,
+            capacity: burst as f64,
+            rate: rate as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Take up to `wanted` tokens, returning how many were granted
+    fn take(&mut self, wanted: usize) -> usize {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+
+        let granted = (self.tokens.floor() as usize).min(wanted);
+        self.tokens -= granted as f64;
+        granted
+    }
+}
+
+/// Ids of recently seen messages, so relayed copies are delivered once
+struct SeenMessages {
+    ids: HashSet<Uuid>,
+    order: VecDeque<Uuid>,
+    capacity: usize,
+}
+
+impl SeenMessages {
+    fn new(capacity: usize) -> Self {
+        Self {
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Record `id`, returning false if it was already seen
+    fn insert(&mut self, id: Uuid) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Gossip protocol manager
 pub struct GossipManager {
     node_id: NodeId,
+    config: GossipConfig,
+    state: Arc<RwLock<ClusterState>>,
+    client: ClusterGrpcClient,
+    pending: Mutex<VecDeque<GossipMessage>>,
+    limiters: Mutex<HashMap<NodeId, PeerLimiter>>,
+    seen: Mutex<SeenMessages>,
+    /// Tombstones already gossiped or received, by node and incarnation
+    announced: Mutex<HashSet<(NodeId, u64)>>,
+    delivered: broadcast::Sender<GossipMessage>,
+    inbound_tx: mpsc::Sender<GossipBatch>,
+    inbound_rx: Mutex<Option<mpsc::Receiver<GossipBatch>>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl GossipManager {
-    pub fn new(node_id: NodeId) -> Self {
-        Self { node_id }
+    pub fn new(
+        node_id: NodeId,
+        config: GossipConfig,
+        state: Arc<RwLock<ClusterState>>,
+        client: ClusterGrpcClient,
+    ) -> Self {
+        let (delivered, _) = broadcast::channel(1024);
+        let (inbound_tx, inbound_rx) = mpsc::channel(INBOUND_QUEUE_SIZE);
+        let seen = SeenMessages::new(config.max_pending_messages.saturating_mul(4));
+
+        Self {
+            node_id,
+            config,
+            state,
+            client,
+            pending: Mutex::new(VecDeque::new()),
+            limiters: Mutex::new(HashMap::new()),
+            seen: Mutex::new(seen),
+            announced: Mutex::new(HashSet::new()),
+            delivered,
+            inbound_tx,
+            inbound_rx: Mutex::new(Some(inbound_rx)),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Channel the gRPC server hands received batches to
+    pub fn inbound(&self) -> mpsc::Sender<GossipBatch> {
+        self.inbound_tx.clone()
+    }
+
+    /// Messages received from other nodes, each delivered once
+    pub fn subscribe(&self) -> broadcast::Receiver<GossipMessage> {
+        self.delivered.subscribe()
+    }
+
+    /// Queue a message for the next gossip round
+    pub async fn publish(&self, kind: &str, payload: serde_json::Value) -> Result<Uuid> {
+        let message = GossipMessage::new(self.node_id.clone(), kind, payload, self.config.max_hops);
+        let id = message.id;
+        self.seen.lock().await.insert(id);
+        self.enqueue(message).await?;
+        Ok(id)
+    }
+
+    /// Number of messages waiting to be sent
+    pub async fn pending_count(&self) -> usize {
+        self.pending.lock().await.len()
+    }
+
+    /// Messages queued for the next round, oldest first
+    pub(crate) async fn queued(&self) -> Vec<GossipMessage> {
+        self.pending.lock().await.iter().cloned().collect()
+    }
+
+    /// Remove up to `count` of the oldest queued messages once a peer has
+    /// accepted them, returning how many were removed
+    pub(crate) async fn dequeue(&self, count: usize) -> usize {
+        // Only the round loop removes from the front; anything queued since
+        // the snapshot was taken sits behind it
+        let mut pending = self.pending.lock().await;
+        let count = count.min(pending.len());
+        pending.drain(..count);
+        count
+    }
+
+    async fn enqueue(&self, message: GossipMessage) -> Result<()> {
+        // Brackets around a batch of one
+        let size = serde_json::to_vec(&message)?.len() + 2;
+        if size > self.config.max_message_size {
+            return Err(ClusterError::configuration(format!(
+                "Gossip message '{}' is {} bytes, above the {} byte limit",
+                message.kind, size, self.config.max_message_size
+            )));
+        }
+
+        let mut pending = self.pending.lock().await;
+        pending.push_back(message);
+        if pending.len() > self.config.max_pending_messages {
+            let dropped = pending.len() - self.config.max_pending_messages;
+            pending.drain(..dropped);
+            tracing::warn!(
+                "Gossip queue full, dropped {} oldest message(s) on node {}",
+                dropped,
+                self.node_id
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Deliver the new messages of a received batch and queue them for relay,
+    /// returning how many were new
+    pub async fn receive(&self, batch: GossipBatch) -> Result<usize> {
+        let messages = decode_batch(&batch, self.config.max_message_size)?;
+
+        let mut fresh = 0;
+        for message in messages {
+            if message.origin == self.node_id || !self.seen.lock().await.insert(message.id) {
+                continue;
+            }
+            fresh += 1;
+
+            if message.kind == TOMBSTONE_GOSSIP_KIND {
+                self.merge_tombstones(&message.payload).await;
+            }
+
+            // Nobody listening is not an error
+            let _ = self.delivered.send(message.clone());
+
+            if message.hops > 0 {
+                let relayed = GossipMessage {
+                    hops: message.hops - 1,
+                    ..message
+                };
+                if let Err(e) = self.enqueue(relayed).await {
+                    tracing::debug!("Not relaying gossip message: {}", e);
+                }
+            }
+        }
+
+        Ok(fresh)
+    }
+
+    /// Queue the tombstones of the cluster state not gossiped yet, returning
+    /// how many were queued
+    pub async fn announce_tombstones(&self) -> Result<usize> {
+        let tombstones: Vec<NodeTombstone> = self
+            .state
+            .read()
+            .await
+            .tombstones
+            .values()
+            .cloned()
+            .collect();
+
+        let fresh: Vec<NodeTombstone> = {
+            let mut announced = self.announced.lock().await;
+            announced.retain(|(node_id, incarnation)| {
+                tombstones
+                    .iter()
+                    .any(|t| &t.node_id == node_id && t.incarnation == *incarnation)
+            });
+            tombstones
+                .into_iter()
+                .filter(|t| announced.insert((t.node_id.clone(), t.incarnation)))
+                .collect()
+        };
+
+        if fresh.is_empty() {
+            return Ok(0);
+        }
+        self.publish(TOMBSTONE_GOSSIP_KIND, serde_json::to_value(&fresh)?)
+            .await?;
+        Ok(fresh.len())
+    }
+
+    async fn merge_tombstones(&self, payload: &serde_json::Value) {
+        let tombstones: Vec<NodeTombstone> = match serde_json::from_value(payload.clone()) {
+            Ok(tombstones) => tombstones,
+            Err(e) => {
+                tracing::debug!("Ignoring malformed tombstone gossip: {}", e);
+                return;
+            }
+        };
+
+        // Received tombstones spread by relay; announcing them again would
+        // only add traffic
+        self.announced.lock().await.extend(
+            tombstones
+                .iter()
+                .map(|t| (t.node_id.clone(), t.incarnation)),
+        );
+        self.state.write().await.merge_tombstones(tombstones);
+    }
+
+    /// Up to `fanout` random peers that can take gossip
+    async fn select_peers(&self) -> Vec<(NodeId, SocketAddr)> {
+        let state = self.state.read().await;
+        let mut peers: Vec<(NodeId, SocketAddr)> = state
+            .get_all_nodes()
+            .into_iter()
+            .filter(|node| node.id != self.node_id)
+            .filter(|node| matches!(node.status, NodeStatus::Healthy | NodeStatus::Draining))
+            .map(|node| (node.id.clone(), node.address))
+            .collect();
+
+        peers.shuffle(&mut rand::thread_rng());
+        peers.truncate(self.config.fanout);
+        peers
+    }
+
+    /// Send queued messages to this round's peers, returning how many
+    /// messages left the queue.
+    ///
+    /// A message leaves the queue once at least one peer accepted it; peers
+    /// that were rate limited or unreachable will hear of it by relay.
+    pub async fn flush(&self) -> Result<usize> {
+        let messages = self.queued().await;
+        if messages.is_empty() {
+            return Ok(0);
+        }
+
+        let peers = self.select_peers().await;
+        if peers.is_empty() {
+            return Ok(0);
+        }
+
+        let batches = encode_batches(&self.node_id, &messages, &self.config)?;
+        let mut delivered = 0;
+
+        for (peer_id, address) in peers {
+            let allowed = self
+                .limiters
+                .lock()
+                .await
+                .entry(peer_id.clone())
+                .or_insert_with(|| {
+                    PeerLimiter::new(self.config.peer_rate_limit, self.config.peer_burst)
+                })
+                .take(batches.len());
+            if allowed < batches.len() {
+                tracing::debug!(
+                    "Gossip to {} rate limited to {} of {} batches",
+                    peer_id,
+                    allowed,
+                    batches.len()
+                );
+            }
+
+            let mut sent = 0;
+            for batch in batches.iter().take(allowed) {
+                match self.client.gossip(address, batch.clone()).await {
+                    Ok(_) => sent += batch.message_count as usize,
+                    Err(e) => {
+                        tracing::debug!("Gossip to {} failed: {}", peer_id, e);
+                        break;
+                    }
+                }
+            }
+            delivered = delivered.max(sent);
+        }
+
+        Ok(self.dequeue(delivered).await)
     }
 
-    pub async fn start(&self) -> Result<()> {
+    /// Start the gossip rounds and processing of received batches
+    pub async fn start(self: Arc<Self>) -> Result<()> {
         tracing::info!("Starting gossip manager for node {}", self.node_id);
-        // TODO: Implement gossip protocol
+
+        let mut tasks = self.tasks.lock().await;
+
+        if let Some(mut inbound) = self.inbound_rx.lock().await.take() {
+            let manager = self.clone();
+            tasks.push(tokio::spawn(async move {
+                while let Some(batch) = inbound.recv().await {
+                    if let Err(e) = manager.receive(batch).await {
+                        tracing::warn!("Dropping gossip batch: {}", e);
+                    }
+                }
+            }));
+        }
+
+        let manager = self.clone();
+        tasks.push(tokio::spawn(async move {
+            let interval = manager.config.interval.max(Duration::from_millis(10));
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                if let Err(e) = manager.announce_tombstones().await {
+                    tracing::warn!("Failed to announce tombstones: {}", e);
+                }
+                if let Err(e) = manager.flush().await {
+                    tracing::warn!("Gossip round failed: {}", e);
+                }
+            }
+        }));
+
         Ok(())
     }
 
     pub async fn shutdown(&self) -> Result<()> {
         tracing::info!("Shutting down gossip manager for node {}", self.node_id);
+        for task in self.tasks.lock().await.drain(..) {
+            task.abort();
+        }
         Ok(())
     }
 }
+
+impl Drop for GossipManager {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::ClusterGrpcServer;
+    use crate::consensus::SimpleConsensus;
+    use crate::node::Node;
+
+    fn user_created(origin: &NodeId, index: usize) -> GossipMessage {
+        GossipMessage::new(
+            origin.clone(),
+            "user_created",
+            serde_json::json!({ "username": format!("user-{}", index), "protocol": "vless" }),
+            4,
+        )
+    }
+
+    #[test]
+    fn test_batches_respect_size_and_count_limits() {
+        let node_id = NodeId::new();
+        let messages: Vec<GossipMessage> = (0..500).map(|i| user_created(&node_id, i)).collect();
+        let config = GossipConfig {
+            max_message_size: 8 * 1024,
+            max_batch_messages: 100,
+            compression: GossipCompression::None,
+            ..Default::default()
+        };
+
+        let batches = encode_batches(&node_id, &messages, &config).unwrap();
+        assert!(batches.len() > 1);
+        assert!(batches
+            .iter()
+            .all(|batch| batch.payload.len() <= config.max_message_size));
+        assert!(batches.iter().all(|batch| batch.message_count <= 100));
+
+        let decoded: Vec<GossipMessage> = batches
+            .iter()
+            .flat_map(|batch| decode_batch(batch, config.max_message_size).unwrap())
+            .collect();
+        assert_eq!(decoded, messages);
+    }
+
+    #[test]
+    fn test_zstd_batches_roundtrip_and_are_bounded() {
+        let node_id = NodeId::new();
+        let messages: Vec<GossipMessage> = (0..200).map(|i| user_created(&node_id, i)).collect();
+        let config = GossipConfig::default();
+
+        let batches = encode_batches(&node_id, &messages, &config).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].encoding, "zstd");
+        let raw_size = serde_json::to_vec(&messages).unwrap().len();
+        assert!(batches[0].payload.len() < raw_size / 2);
+        assert_eq!(
+            decode_batch(&batches[0], config.max_message_size).unwrap(),
+            messages
+        );
+
+        // A payload that inflates beyond the limit is refused
+        assert!(decode_batch(&batches[0], 1024).is_err());
+
+        let mut miscounted = batches[0].clone();
+        miscounted.message_count += 1;
+        assert!(decode_batch(&miscounted, config.max_message_size).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peer_limiter_caps_batches() {
+        let mut limiter = PeerLimiter::new(1, 3);
+        assert_eq!(limiter.take(5), 3);
+        assert_eq!(limiter.take(5), 0);
+
+        tokio::time::pause();
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(limiter.take(5), 2);
+    }
+
+    #[tokio::test]
+    async fn test_receive_delivers_once_and_relays() {
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(node_id.clone())));
+        let manager = GossipManager::new(
+            node_id,
+            GossipConfig::default(),
+            state,
+            ClusterGrpcClient::new(NodeId::new()),
+        );
+        let mut delivered = manager.subscribe();
+
+        let origin = NodeId::new();
+        let mut last_hop = user_created(&origin, 1);
+        last_hop.hops = 0;
+        let messages = vec![user_created(&origin, 0), last_hop];
+        let batch = encode_batches(&origin, &messages, &GossipConfig::default())
+            .unwrap()
+            .remove(0);
+
+        assert_eq!(manager.receive(batch.clone()).await.unwrap(), 2);
+        assert_eq!(manager.receive(batch).await.unwrap(), 0);
+        assert_eq!(delivered.recv().await.unwrap().id, messages[0].id);
+        assert_eq!(delivered.recv().await.unwrap().id, messages[1].id);
+
+        // Only the message with hops left is relayed, one hop shorter
+        let pending = manager.pending.lock().await.clone();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, messages[0].id);
+        assert_eq!(pending[0].hops, 3);
+    }
+
+    #[tokio::test]
+    async fn test_tombstones_are_announced_and_merged() {
+        use crate::node::Node;
+        use crate::state::{TombstoneReason, DEFAULT_TOMBSTONE_TTL};
+
+        let departed = Node::new("departed".to_string(), "127.0.0.1:8001".parse().unwrap());
+        let manager = |state: &Arc<RwLock<ClusterState>>| {
+            let node_id = state.try_read().unwrap().node_id.clone();
+            GossipManager::new(
+                node_id,
+                GossipConfig::default(),
+                state.clone(),
+                ClusterGrpcClient::new(NodeId::new()),
+            )
+        };
+
+        let sender_state = Arc::new(RwLock::new(ClusterState::new(NodeId::new())));
+        {
+            let mut state = sender_state.write().await;
+            state.add_node(departed.clone()).unwrap();
+            state
+                .tombstone_node(&departed.id, TombstoneReason::Failed, DEFAULT_TOMBSTONE_TTL)
+                .unwrap();
+        }
+        let sender = manager(&sender_state);
+        assert_eq!(sender.announce_tombstones().await.unwrap(), 1);
+        // Nothing new to announce in the next round
+        assert_eq!(sender.announce_tombstones().await.unwrap(), 0);
+
+        let receiver_state = Arc::new(RwLock::new(ClusterState::new(NodeId::new())));
+        receiver_state
+            .write()
+            .await
+            .add_node(departed.clone())
+            .unwrap();
+        let receiver = manager(&receiver_state);
+
+        let batch = encode_batches(&sender.node_id, &sender.queued().await, &sender.config)
+            .unwrap()
+            .remove(0);
+        assert_eq!(receiver.receive(batch).await.unwrap(), 1);
+
+        let state = receiver_state.read().await;
+        assert!(state.get_node(&departed.id).is_none());
+        assert_eq!(
+            state.get_tombstone(&departed.id).unwrap().reason,
+            TombstoneReason::Failed
+        );
+        drop(state);
+
+        // Received tombstones are relayed rather than announced again
+        assert_eq!(receiver.announce_tombstones().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_flush_batches_to_peer() {
+        let receiver_id = NodeId::new();
+        let receiver_address: SocketAddr = "127.0.0.1:19431".parse().unwrap();
+        let receiver_state = Arc::new(RwLock::new(ClusterState::new(receiver_id.clone())));
+        let receiver = Arc::new(GossipManager::new(
+            receiver_id.clone(),
+            GossipConfig::default(),
+            receiver_state.clone(),
+            ClusterGrpcClient::new(receiver_id.clone()),
+        ));
+        let mut delivered = receiver.subscribe();
+        let server = ClusterGrpcServer::new(
+            receiver_id.clone(),
+            receiver_state,
+            Arc::new(SimpleConsensus::new(receiver_id.clone())),
+            receiver_address,
+        )
+        .with_gossip_sink(receiver.inbound());
+        tokio::spawn(async move { server.start().await });
+        receiver.clone().start().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let sender_id = NodeId::new();
+        let sender_state = Arc::new(RwLock::new(ClusterState::new(sender_id.clone())));
+        let mut peer = Node::with_id(receiver_id, "receiver".to_string(), receiver_address);
+        peer.status = NodeStatus::Healthy;
+        sender_state.write().await.add_node(peer).unwrap();
+
+        // One batch per round: 300 messages take three rounds
+        let config = GossipConfig {
+            max_batch_messages: 100,
+            peer_rate_limit: 1,
+            peer_burst: 1,
+            ..Default::default()
+        };
+        let sender = GossipManager::new(
+            sender_id.clone(),
+            config,
+            sender_state,
+            ClusterGrpcClient::new(sender_id),
+        );
+        for i in 0..300 {
+            sender
+                .publish("user_created", serde_json::json!({ "index": i }))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(sender.flush().await.unwrap(), 100);
+        assert_eq!(sender.flush().await.unwrap(), 0);
+        assert_eq!(sender.pending_count().await, 200);
+
+        for _ in 0..100 {
+            let message = tokio::time::timeout(Duration::from_secs(5), delivered.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(message.kind, "user_created");
+        }
+
+        receiver.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,509 @@
+//! Historical cluster state timeline
+//!
+//! [`StateHistory`] keeps timestamped snapshots of [`ClusterState`] in a
+//! JSON lines file under the node's data directory, so operators can look
+//! back at what the cluster looked like at a given time and what changed
+//! between two points. A snapshot is only written when membership,
+//! leadership or configuration differ from the previous one; the state at
+//! any time is the newest snapshot taken at or before it.
+
+use crate::error::Result;
+use crate::node::{Node, NodeId};
+use crate::state::{ClusterState, TombstoneReason};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+
+/// File the snapshots are kept in, under the history directory
+const SNAPSHOT_FILE: &str = "snapshots.jsonl";
+
+/// Snapshot recording and retention
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Record state snapshots
+    pub enabled: bool,
+
+    /// How often the state is checked for changes worth a snapshot
+    pub interval: Duration,
+
+    /// How long snapshots are kept
+    pub retention: Duration,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::from_secs(30),
+            retention: Duration::from_secs(14 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Cluster state as of `taken_at` (seconds since the epoch)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub taken_at: u64,
+    pub state: ClusterState,
+}
+
+/// Value of a field before and after a change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub before: T,
+    pub after: T,
+}
+
+/// Node as listed in a diff
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeSummary {
+    pub id: NodeId,
+    pub name: String,
+    pub address: String,
+}
+
+impl From<&Node> for NodeSummary {
+    fn from(node: &Node) -> Self {
+        Self {
+            id: node.id.clone(),
+            name: node.name.clone(),
+            address: node.address.to_string(),
+        }
+    }
+}
+
+/// Node that left between two snapshots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepartedNode {
+    pub node: NodeSummary,
+    /// Why it left, if its tombstone was still around
+    pub reason: Option<TombstoneReason>,
+}
+
+/// Change of a node present in both snapshots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeChange {
+    pub node: NodeSummary,
+    /// Changed field: `status`, `role`, `address`, `version` or `zone`
+    pub field: String,
+    pub change: Change<String>,
+}
+
+/// Configuration key whose value changed; `None` when unset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub key: String,
+    pub change: Change<Option<serde_json::Value>>,
+}
+
+/// What changed between the snapshots taken at `from` and `to`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateDiff {
+    pub from: u64,
+    pub to: u64,
+    pub joined: Vec<NodeSummary>,
+    pub left: Vec<DepartedNode>,
+    pub node_changes: Vec<NodeChange>,
+    pub leader: Option<Change<Option<NodeId>>>,
+    pub term: Option<Change<u64>>,
+    pub config_changes: Vec<ConfigChange>,
+}
+
+impl StateDiff {
+    /// Compare two states
+    pub fn between(before: &StateSnapshot, after: &StateSnapshot) -> Self {
+        let (old, new) = (&before.state, &after.state);
+        let mut diff = Self {
+            from: before.taken_at,
+            to: after.taken_at,
+            ..Default::default()
+        };
+
+        let mut node_ids: Vec<&NodeId> = old.nodes.keys().chain(new.nodes.keys()).collect();
+        node_ids.sort_by_key(|id| id.to_string());
+        node_ids.dedup();
+
+        for id in node_ids {
+            match (old.nodes.get(id), new.nodes.get(id)) {
+                (None, Some(node)) => diff.joined.push(NodeSummary::from(node)),
+                (Some(node), None) => diff.left.push(DepartedNode {
+                    node: NodeSummary::from(node),
+                    reason: new.tombstones.get(id).map(|tombstone| tombstone.reason),
+                }),
+                (Some(was), Some(is)) => diff.node_changes.extend(node_changes(was, is)),
+                (None, None) => {}
+            }
+        }
+
+        if old.leader_id != new.leader_id {
+            diff.leader = Some(Change {
+                before: old.leader_id.clone(),
+                after: new.leader_id.clone(),
+            });
+        }
+        if old.term != new.term {
+            diff.term = Some(Change {
+                before: old.term,
+                after: new.term,
+            });
+        }
+
+        let keys: BTreeSet<&String> = old
+            .config_data
+            .keys()
+            .chain(new.config_data.keys())
+            .collect();
+        for key in keys {
+            let (was, is) = (old.config_data.get(key), new.config_data.get(key));
+            if was != is {
+                diff.config_changes.push(ConfigChange {
+                    key: key.clone(),
+                    change: Change {
+                        before: was.cloned(),
+                        after: is.cloned(),
+                    },
+                });
+            }
+        }
+
+        diff
+    }
+
+    /// Whether nothing worth recording changed
+    pub fn is_empty(&self) -> bool {
+        self.joined.is_empty()
+            && self.left.is_empty()
+            && self.node_changes.is_empty()
+            && self.leader.is_none()
+            && self.term.is_none()
+            && self.config_changes.is_empty()
+    }
+
+    /// Whether the node with ID or name `node` joined, left, changed or
+    /// gained or lost leadership
+    pub fn involves(&self, node: &str) -> bool {
+        let is = |summary: &NodeSummary| summary.name == node || summary.id.to_string() == node;
+        let is_id = |id: &Option<NodeId>| id.as_ref().is_some_and(|id| id.to_string() == node);
+
+        self.joined.iter().any(is)
+            || self.left.iter().any(|departed| is(&departed.node))
+            || self.node_changes.iter().any(|change| is(&change.node))
+            || self
+                .leader
+                .as_ref()
+                .is_some_and(|leader| is_id(&leader.before) || is_id(&leader.after))
+    }
+}
+
+fn node_changes(was: &Node, is: &Node) -> Vec<NodeChange> {
+    let fields = [
+        ("status", was.status.to_string(), is.status.to_string()),
+        ("role", was.role.to_string(), is.role.to_string()),
+        ("address", was.address.to_string(), is.address.to_string()),
+        ("version", was.version.clone(), is.version.clone()),
+        (
+            "zone",
+            was.zone.clone().unwrap_or_default(),
+            is.zone.clone().unwrap_or_default(),
+        ),
+    ];
+
+    fields
+        .into_iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|(field, before, after)| NodeChange {
+            node: NodeSummary::from(is),
+            field: field.to_string(),
+            change: Change { before, after },
+        })
+        .collect()
+}
+
+/// Timestamped cluster state snapshots persisted under a directory
+pub struct StateHistory {
+    path: PathBuf,
+    retention: Duration,
+    snapshots: RwLock<VecDeque<StateSnapshot>>,
+}
+
+impl StateHistory {
+    /// Open the history kept in `dir`, loading the snapshots still within
+    /// `retention`
+    pub async fn open(dir: impl AsRef<Path>, retention: Duration) -> Result<Self> {
+        let dir = dir.as_ref();
+        tokio::fs::create_dir_all(dir).await?;
+        let path = dir.join(SNAPSHOT_FILE);
+
+        let history = Self {
+            snapshots: RwLock::new(load_snapshots(&path).await?),
+            path,
+            retention,
+        };
+        history.prune(now()).await?;
+        Ok(history)
+    }
+
+    /// Read the history kept in `dir` without pruning it, e.g. from the
+    /// CLI while the node keeps recording
+    pub async fn read(dir: impl AsRef<Path>) -> Result<Self> {
+        let path = dir.as_ref().join(SNAPSHOT_FILE);
+        Ok(Self {
+            snapshots: RwLock::new(load_snapshots(&path).await?),
+            path,
+            retention: Duration::MAX,
+        })
+    }
+
+    /// Record `state` as of now; skipped when nothing changed since the
+    /// last snapshot. Returns whether a snapshot was written.
+    pub async fn record(&self, state: &ClusterState) -> Result<bool> {
+        self.record_at(state, now()).await
+    }
+
+    /// Record `state` as of `taken_at`
+    pub async fn record_at(&self, state: &ClusterState, taken_at: u64) -> Result<bool> {
+        let snapshot = StateSnapshot {
+            taken_at,
+            state: state.clone(),
+        };
+
+        {
+            let mut snapshots = self.snapshots.write().await;
+            if let Some(last) = snapshots.back() {
+                if StateDiff::between(last, &snapshot).is_empty() {
+                    return Ok(false);
+                }
+            }
+
+            let mut line = serde_json::to_string(&snapshot)?;
+            line.push('\n');
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await?;
+            snapshots.push_back(snapshot);
+        }
+
+        self.prune(taken_at).await?;
+        Ok(true)
+    }
+
+    /// State as of `timestamp`: the newest snapshot taken at or before it
+    pub async fn state_at(&self, timestamp: u64) -> Option<StateSnapshot> {
+        self.snapshots
+            .read()
+            .await
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.taken_at <= timestamp)
+            .cloned()
+    }
+
+    /// What changed between the states as of `t1` and `t2`. `None` when
+    /// no snapshot was taken by `t1`.
+    pub async fn diff(&self, t1: u64, t2: u64) -> Option<StateDiff> {
+        let before = self.state_at(t1).await?;
+        let after = self.state_at(t2).await?;
+        Some(StateDiff::between(&before, &after))
+    }
+
+    /// Each recorded change between `from` and `to`, oldest first
+    pub async fn changes(&self, from: u64, to: u64) -> Vec<StateDiff> {
+        let snapshots = self.snapshots.read().await;
+        let in_range: Vec<&StateSnapshot> = snapshots
+            .iter()
+            .filter(|snapshot| snapshot.taken_at <= to)
+            .collect();
+        // Start from the state in effect at `from`
+        let start = in_range
+            .iter()
+            .rposition(|snapshot| snapshot.taken_at <= from)
+            .unwrap_or(0);
+
+        in_range[start..]
+            .windows(2)
+            .map(|pair| StateDiff::between(pair[0], pair[1]))
+            .collect()
+    }
+
+    /// Time range covered, oldest and newest snapshot
+    pub async fn span(&self) -> Option<(u64, u64)> {
+        let snapshots = self.snapshots.read().await;
+        Some((snapshots.front()?.taken_at, snapshots.back()?.taken_at))
+    }
+
+    /// Record the state every `interval`
+    pub fn start(
+        self: Arc<Self>,
+        state: Arc<RwLock<ClusterState>>,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let current = state.read().await.clone();
+                if let Err(e) = self.record(&current).await {
+                    tracing::warn!("Failed to record cluster state snapshot: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Drop snapshots older than the retention, keeping the newest of them
+    /// as the state in effect at the start of the retained range
+    async fn prune(&self, now: u64) -> Result<()> {
+        let cutoff = now.saturating_sub(self.retention.as_secs());
+        let mut snapshots = self.snapshots.write().await;
+
+        let mut dropped = 0;
+        while snapshots.len() > 1 && snapshots[1].taken_at <= cutoff {
+            snapshots.pop_front();
+            dropped += 1;
+        }
+        if dropped == 0 {
+            return Ok(());
+        }
+
+        let mut content = String::new();
+        for snapshot in snapshots.iter() {
+            content.push_str(&serde_json::to_string(snapshot)?);
+            content.push('\n');
+        }
+        let temp = self.path.with_extension("tmp");
+        tokio::fs::write(&temp, content).await?;
+        tokio::fs::rename(&temp, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// Snapshots from a JSON lines file, sorted by time; none if the file
+/// does not exist. A torn last line from an interrupted write is skipped.
+async fn load_snapshots(path: &Path) -> Result<VecDeque<StateSnapshot>> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(VecDeque::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut snapshots: Vec<StateSnapshot> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                tracing::warn!("Skipping unreadable state snapshot: {}", e);
+                None
+            }
+        })
+        .collect();
+    snapshots.sort_by_key(|snapshot| snapshot.taken_at);
+    Ok(snapshots.into())
+}
+
+/// Directory the history of a node with data directory `data_dir` is kept in
+pub fn history_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("history")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::NodeStatus;
+    use tempfile::TempDir;
+
+    const RETENTION: Duration = Duration::from_secs(3600);
+
+    fn node(name: &str, port: u16) -> Node {
+        Node::new(
+            name.to_string(),
+            format!("10.0.0.1:{}", port).parse().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_state_at_and_diff() {
+        let dir = TempDir::new().unwrap();
+        let history = StateHistory::open(dir.path(), RETENTION).await.unwrap();
+        let start = now();
+
+        let a = node("a", 7000);
+        let b = node("b", 7001);
+        let mut state = ClusterState::new(a.id.clone());
+        state.add_node(a.clone()).unwrap();
+        state.add_node(b.clone()).unwrap();
+        assert!(history.record_at(&state, start).await.unwrap());
+        // Unchanged state is not recorded again
+        assert!(!history.record_at(&state, start + 10).await.unwrap());
+
+        state.remove_node(&b.id).unwrap();
+        state.leader_id = Some(a.id.clone());
+        assert!(history.record_at(&state, start + 20).await.unwrap());
+
+        state.nodes.get_mut(&a.id).unwrap().status = NodeStatus::Draining;
+        state
+            .config_data
+            .insert("mtu".to_string(), serde_json::json!(1400));
+        assert!(history.record_at(&state, start + 30).await.unwrap());
+
+        assert!(history.state_at(start - 1).await.is_none());
+        let then = history.state_at(start + 15).await.unwrap();
+        assert_eq!(then.taken_at, start);
+        assert_eq!(then.state.nodes.len(), 2);
+
+        let diff = history.diff(start, start + 30).await.unwrap();
+        assert_eq!(diff.left.len(), 1);
+        assert_eq!(diff.left[0].node.name, "b");
+        assert_eq!(diff.left[0].reason, Some(TombstoneReason::Left));
+        assert_eq!(diff.leader.as_ref().unwrap().after, Some(a.id.clone()));
+        assert_eq!(diff.node_changes.len(), 1);
+        assert_eq!(diff.node_changes[0].field, "status");
+        assert_eq!(diff.config_changes[0].key, "mtu");
+
+        // When did b leave, and what happened afterwards
+        let changes = history.changes(start, start + 30).await;
+        assert_eq!(changes.len(), 2);
+        let departure = changes.iter().find(|c| c.involves("b")).unwrap();
+        assert_eq!(departure.to, start + 20);
+        assert!(changes[1].involves(&a.id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_history_survives_reopen_and_prunes() {
+        let dir = TempDir::new().unwrap();
+        let old = now() - 2 * RETENTION.as_secs();
+        let a = node("a", 7000);
+        let mut state = ClusterState::new(a.id.clone());
+
+        {
+            let history = StateHistory::open(dir.path(), RETENTION).await.unwrap();
+            history.record_at(&state, old).await.unwrap();
+            state.add_node(a.clone()).unwrap();
+            history.record_at(&state, old + 1).await.unwrap();
+            state.term = 2;
+            history.record_at(&state, now()).await.unwrap();
+        }
+
+        let history = StateHistory::open(dir.path(), RETENTION).await.unwrap();
+        let (oldest, _) = history.span().await.unwrap();
+        // The last snapshot before the cutoff stays as the starting state
+        assert_eq!(oldest, old + 1);
+        assert_eq!(history.state_at(now()).await.unwrap().state.term, 2);
+    }
+}
@@ -1,21 +1,226 @@
 //! Leader election utilities
+//!
+//! Two additions to plain Raft elections keep a node that was cut off from
+//! the cluster from forcing a needless election when it comes back:
+//!
+//! - **Pre-vote**: before bumping its term, a candidate asks whether the
+//!   voters would elect it. Nobody changes state for a pre-vote, so a node
+//!   that cannot win never raises the cluster's term.
+//! - **Leader lease**: a follower that heard from its leader within the lease
+//!   refuses to vote for anyone else, and a leader only trusts its leadership
+//!   while a quorum has answered within the lease.
+//!
+//! Neither stops a leader that was paused (a long GC, a frozen VM) from
+//! acting after a new leader took over. For that, every leader write carries
+//! a [`FencingToken`] derived from its term, and shared storage refuses
+//! tokens older than the newest one it has seen.
 
-use crate::error::Result;
+use crate::membership::Configuration;
 use crate::node::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 
-/// Leader election manager (placeholder)
-pub struct LeaderElection {
-    node_id: NodeId,
+/// Lower bound of the randomized election timeout. The lease must not
+/// outlast it, or a crashed leader would delay the next election.
+pub const DEFAULT_LEASE_DURATION: Duration = Duration::from_millis(150);
+
+/// Election settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElectionConfig {
+    /// Run a pre-vote round before each election
+    pub pre_vote: bool,
+
+    /// How long a leader's contact keeps other candidates out
+    pub lease_duration: Duration,
+}
+
+impl Default for ElectionConfig {
+    fn default() -> Self {
+        Self {
+            pre_vote: true,
+            lease_duration: DEFAULT_LEASE_DURATION,
+        }
+    }
+}
+
+/// Proof of leadership attached to writes. Tokens grow with the term, so a
+/// write from a deposed leader carries a smaller token than its successor's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct FencingToken(u64);
+
+impl FencingToken {
+    /// Token for the leader of `term`
+    pub fn from_term(term: u64) -> Self {
+        Self(term)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for FencingToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// RequestVote message. For a pre-vote, `term` is the term the candidate
+/// would campaign in; its actual term is unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate_id: NodeId,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+    pub pre_vote: bool,
+}
+
+/// Answer to a [`VoteRequest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteResponse {
+    /// Voter's current term
+    pub term: u64,
+    pub vote_granted: bool,
+    pub pre_vote: bool,
+}
+
+/// Time-bound assurance that the current leader is still in charge
+#[derive(Debug, Clone)]
+pub struct LeaderLease {
+    duration: Duration,
+    renewed_at: Option<Instant>,
+}
+
+impl LeaderLease {
+    /// Create an expired lease
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            renewed_at: None,
+        }
+    }
+
+    /// Extend the lease from `at`; an older renewal never shortens it
+    pub fn renew(&mut self, at: Instant) {
+        if self.renewed_at.is_none_or(|renewed_at| at > renewed_at) {
+            self.renewed_at = Some(at);
+        }
+    }
+
+    /// Drop the lease
+    pub fn expire(&mut self) {
+        self.renewed_at = None;
+    }
+
+    /// Whether the lease is still running at `now`
+    pub fn is_held(&self, now: Instant) -> bool {
+        self.renewed_at
+            .is_some_and(|renewed_at| now.saturating_duration_since(renewed_at) < self.duration)
+    }
+
+    /// Length of the lease
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// Votes gathered in one pre-vote or election round
+#[derive(Debug, Clone)]
+pub struct ElectionRound {
+    term: u64,
+    pre_vote: bool,
+    granted: HashSet<NodeId>,
+}
+
+impl ElectionRound {
+    /// Start a round; the candidate votes for itself
+    pub fn new(term: u64, pre_vote: bool, candidate: NodeId) -> Self {
+        Self {
+            term,
+            pre_vote,
+            granted: HashSet::from([candidate]),
+        }
+    }
+
+    /// Term the round campaigns for
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    /// Whether this is a pre-vote round
+    pub fn is_pre_vote(&self) -> bool {
+        self.pre_vote
+    }
+
+    /// Count a response; answers for another round are ignored
+    pub fn record(&mut self, from: NodeId, response: &VoteResponse) {
+        if response.vote_granted && response.pre_vote == self.pre_vote {
+            self.granted.insert(from);
+        }
+    }
+
+    /// Whether the granted votes form a quorum of `configuration`
+    pub fn is_won(&self, configuration: &Configuration) -> bool {
+        configuration.has_quorum(&self.granted)
+    }
+}
+
+/// Whether a candidate's log is at least as up to date as ours
+pub fn log_is_up_to_date(
+    candidate_last_term: u64,
+    candidate_last_index: u64,
+    last_term: u64,
+    last_index: u64,
+) -> bool {
+    (candidate_last_term, candidate_last_index) >= (last_term, last_index)
 }
 
-impl LeaderElection {
-    pub fn new(node_id: NodeId) -> Self {
-        Self { node_id }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_expires() {
+        let start = Instant::now();
+        let mut lease = LeaderLease::new(Duration::from_millis(100));
+        assert!(!lease.is_held(start));
+
+        lease.renew(start);
+        assert!(lease.is_held(start + Duration::from_millis(99)));
+        assert!(!lease.is_held(start + Duration::from_millis(100)));
+
+        // A late-arriving older renewal does not move the lease back
+        lease.renew(start + Duration::from_millis(50));
+        lease.renew(start);
+        assert!(lease.is_held(start + Duration::from_millis(120)));
+
+        lease.expire();
+        assert!(!lease.is_held(start));
     }
 
-    pub async fn run_election(&self) -> Result<NodeId> {
-        tracing::info!("Running leader election for node {}", self.node_id);
-        // TODO: Implement proper leader election
-        Ok(self.node_id.clone())
+    #[test]
+    fn test_election_round_quorum() {
+        let (a, b, c) = (NodeId::new(), NodeId::new(), NodeId::new());
+        let configuration = Configuration::stable([a.clone(), b.clone(), c.clone()]);
+        let mut round = ElectionRound::new(3, true, a);
+        assert!(!round.is_won(&configuration));
+
+        // Real votes do not count towards a pre-vote
+        let vote = VoteResponse {
+            term: 2,
+            vote_granted: true,
+            pre_vote: false,
+        };
+        round.record(b.clone(), &vote);
+        assert!(!round.is_won(&configuration));
+
+        let pre_vote = VoteResponse {
+            pre_vote: true,
+            ..vote
+        };
+        round.record(b, &pre_vote);
+        assert!(round.is_won(&configuration));
     }
 }
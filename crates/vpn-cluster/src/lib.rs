@@ -3,27 +3,67 @@
 //! This crate provides distributed state management, cluster coordination,
 //! and horizontal scaling capabilities for the VPN system.
 
+pub mod anti_entropy;
+pub mod clock;
 pub mod communication;
 pub mod config;
 pub mod consensus;
 pub mod coordination;
+pub mod cordon;
 pub mod distributed_storage;
 pub mod error;
+pub mod events;
 pub mod gossip;
+pub mod history;
 pub mod leader_election;
+pub mod load_balancer;
 pub mod membership;
 pub mod node;
+pub mod replay;
+pub mod restart;
+pub mod selector;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
 pub mod state;
-
-pub use communication::{ClusterGrpcClient, ClusterGrpcServer};
+pub mod state_transfer;
+pub mod tls;
+pub mod trace_context;
+pub mod traffic;
+pub mod user_store;
+pub mod wal;
+
+pub use anti_entropy::{DivergenceKind, MembershipDivergence, MembershipReconciler};
+pub use clock::{ClockSample, ClockSkewConfig, ClockSkewMonitor, SkewChange};
+pub use communication::{ClusterGrpcClient, ClusterGrpcServer, ReceivedTransfer};
 pub use config::ClusterConfig;
 pub use consensus::{ConsensusEngine, RaftConsensus};
-pub use coordination::{ClusterCoordinator, CoordinationEvent};
-pub use distributed_storage::DistributedConfigStorage;
+pub use coordination::{ClusterCoordinator, CoordinationEvent, LeaderTaskRunner};
+pub use cordon::{CordonUpdate, NodeCordon};
+pub use distributed_storage::{
+    ClockedStorage, ConfigMerge, ConflictStrategy, ConsistencyLevel, DistributedConfigStorage,
+    FencedStorage, JsonMerge, ReplicatedStorage,
+};
 pub use error::{ClusterError, Result};
-pub use node::{Node, NodeId, NodeRole, NodeStatus};
-pub use state::{ClusterState, DistributedState};
-
+pub use events::{ClusterEventBus, ClusterEventStream};
+pub use gossip::{GossipManager, GossipMessage};
+pub use history::{HistoryConfig, StateDiff, StateHistory, StateSnapshot};
+pub use leader_election::{ElectionConfig, FencingToken, LeaderLease, VoteRequest, VoteResponse};
+pub use load_balancer::{LoadBalancerExportConfig, LoadBalancerExporter, LoadBalancerFormat};
+pub use node::{Node, NodeId, NodeLoad, NodeRole, NodeStatus, Placement};
+pub use replay::{ReplayGuard, RpcStamp};
+pub use restart::{
+    DockerServiceController, RollingRestartReport, ServiceController, ServiceStatus,
+};
+pub use selector::{ConfigTarget, LabelSelector, PinnedConfig};
+pub use state::{ClusterState, DistributedState, NodeTombstone, ScalePlan, TombstoneReason};
+pub use state_transfer::StateTransfer;
+pub use tls::ClusterTls;
+pub use trace_context::{TraceContext, TraceInterceptor, TraceLayer};
+pub use traffic::{TrafficAggregator, TrafficSource, UserTraffic};
+pub use user_store::ReplicatedUserStore;
+pub use wal::{FsyncPolicy, WalConfig};
+
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 // use uuid::Uuid;  // Not needed directly
@@ -34,24 +74,52 @@ pub struct ClusterManager {
     pub config: ClusterConfig,
     pub state: Arc<RwLock<ClusterState>>,
     pub coordinator: ClusterCoordinator,
-    pub storage: Arc<distributed_storage::MemoryStorage>,
-    pub consensus: Arc<consensus::SimpleConsensus>,
+    pub storage: Arc<dyn DistributedConfigStorage>,
+    /// Cluster configuration on `storage`, settling concurrent writes per
+    /// `config.config_conflicts`
+    pub config_store: Arc<ClockedStorage>,
+    pub consensus: Arc<dyn ConsensusEngine>,
+    pub leader_tasks: Arc<LeaderTaskRunner>,
+    pub reconciler: Arc<MembershipReconciler>,
+    pub gossip: Arc<GossipManager>,
+    pub cordon: Arc<NodeCordon>,
+    pub load_balancer: Option<Arc<LoadBalancerExporter>>,
+    pub traffic: Arc<TrafficAggregator>,
+    /// Snapshot and user table shipping to joining nodes
+    pub transfers: Arc<StateTransfer>,
+    /// Timeline of state snapshots, once started with history enabled
+    pub history: Option<Arc<StateHistory>>,
+    tls: Option<Arc<ClusterTls>>,
 }
 
 impl ClusterManager {
     /// Create a new cluster manager
     pub async fn new(config: ClusterConfig) -> Result<Self> {
-        let node_id = NodeId::new();
+        let node_id = load_node_id(&config)?;
         let state = Arc::new(RwLock::new(ClusterState::with_cluster_name(
             node_id.clone(),
             config.cluster_name.clone(),
         )));
 
-        // Initialize storage backend (simplified to memory storage for now)
-        let storage = Arc::new(distributed_storage::MemoryStorage::new());
+        // Initialize the configured storage backend
+        let storage = distributed_storage::create_storage_backend(&config.storage_backend).await?;
 
-        // Initialize consensus engine (simplified to simple consensus for now)
-        let consensus = Arc::new(consensus::SimpleConsensus::new(node_id.clone()));
+        // Raft recovers its term, vote and log from the WAL under data_dir
+        let consensus: Arc<dyn ConsensusEngine> = match config.consensus_algorithm {
+            config::ConsensusAlgorithm::Raft => {
+                Arc::new(consensus::RaftConsensus::from_config(node_id.clone(), &config).await?)
+            }
+            config::ConsensusAlgorithm::PBFT => {
+                Arc::new(consensus::PbftConsensus::new(node_id.clone()).await?)
+            }
+            config::ConsensusAlgorithm::Simple => {
+                let mut simple = consensus::SimpleConsensus::new(node_id.clone());
+                if config.learner {
+                    simple = simple.with_learner_role();
+                }
+                Arc::new(simple)
+            }
+        };
 
         // Initialize coordinator
         let coordinator = ClusterCoordinator::new(
@@ -64,18 +132,100 @@ impl ClusterManager {
 
         // Add this node to its own cluster state
         {
-            let self_node = Node::new(config.node_name.clone(), config.bind_address);
+            let mut self_node = Node::with_id(
+                node_id.clone(),
+                config.node_name.clone(),
+                config.bind_address,
+            );
+            self_node.region = config.region.clone();
+            self_node.zone = config.zone.clone();
+            self_node.metadata.extend(config.labels.clone());
+            if config.learner {
+                self_node.set_role(NodeRole::Learner);
+            }
             let mut cluster_state = state.write().await;
             cluster_state.add_node(self_node)?;
         }
 
+        // Loaded up front so the gossip client and the server share it
+        let tls = match &config.tls {
+            Some(tls_config) => Some(Arc::new(ClusterTls::load(
+                tls_config.clone(),
+                config.cluster_name.clone(),
+            )?)),
+            None => None,
+        };
+
+        let mut client =
+            ClusterGrpcClient::new(node_id.clone()).with_transport(config.transport.clone());
+        if let Some(tls) = &tls {
+            client = client.with_tls(tls.clone());
+        }
+        let coordinator = coordinator.with_client(client.clone());
+        let gossip = Arc::new(GossipManager::new(
+            node_id.clone(),
+            config.gossip.clone(),
+            state.clone(),
+            client.clone(),
+        ));
+
+        // Aggregation runs on the leader, so its writes are fenced
+        let traffic = Arc::new(TrafficAggregator::new(
+            node_id.clone(),
+            state.clone(),
+            Arc::new(FencedStorage::new(storage.clone(), consensus.clone())),
+            client.clone(),
+        ));
+
+        let transfers = Arc::new(StateTransfer::new(
+            node_id.clone(),
+            state.clone(),
+            consensus.clone(),
+            ReplicatedUserStore::new(storage.clone()).with_events(coordinator.event_bus()),
+            client,
+        ));
+
+        let cordon = Arc::new(NodeCordon::new(
+            state.clone(),
+            coordinator.event_bus(),
+            gossip.clone(),
+        ));
+
+        let load_balancer = config
+            .load_balancer
+            .clone()
+            .map(|lb_config| Arc::new(LoadBalancerExporter::new(lb_config, state.clone())));
+
+        let config_store = Arc::new(
+            ClockedStorage::new(storage.clone(), node_id.clone())
+                .with_strategy(config.config_conflicts),
+        );
+
+        let leader_tasks = Arc::new(LeaderTaskRunner::new(consensus.clone()));
+        let reconciler = Arc::new(MembershipReconciler::new(
+            node_id.clone(),
+            state.clone(),
+            consensus.clone(),
+            coordinator.event_bus(),
+        ));
+
         Ok(Self {
             node_id,
             config,
             state,
             coordinator,
             storage,
+            config_store,
             consensus,
+            leader_tasks,
+            reconciler,
+            gossip,
+            cordon,
+            load_balancer,
+            traffic,
+            transfers,
+            history: None,
+            tls,
         })
     }
 
@@ -84,11 +234,33 @@ impl ClusterManager {
         tracing::info!("Starting cluster manager for node {}", self.node_id);
 
         // Start gRPC server first
-        let grpc_server = ClusterGrpcServer::new(
+        let (transfer_sink, inbound_transfers) = tokio::sync::mpsc::channel(16);
+        let mut grpc_server = ClusterGrpcServer::new(
             self.node_id.clone(),
             self.state.clone(),
+            self.consensus.clone(),
             self.config.bind_address,
-        );
+        )
+        .with_transport(self.config.transport.clone())
+        .with_event_bus(self.coordinator.event_bus())
+        .with_gossip_sink(self.gossip.inbound())
+        .with_transfer_sink(transfer_sink)
+        .with_traffic(self.traffic.clone())
+        .with_services(self.coordinator.service_controller());
+
+        // Admin calls are only authenticated by mutual TLS
+        self.config.validate_admin_api()?;
+        if self.config.admin_api {
+            grpc_server = grpc_server
+                .with_admin()
+                .with_config_store(self.config_store.clone())
+                .with_cordon(self.cordon.clone());
+        }
+
+        if let Some(tls) = self.tls.clone() {
+            tls.clone().spawn_reloader();
+            grpc_server = grpc_server.with_tls(tls);
+        }
 
         tokio::spawn(async move {
             if let Err(e) = grpc_server.start().await {
@@ -102,9 +274,76 @@ impl ClusterManager {
         // Start consensus engine
         self.consensus.start().await?;
 
+        // Apply shipped state and ship ours to nodes joining through us
+        self.transfers
+            .clone()
+            .start(inbound_transfers, self.events().subscribe())
+            .await;
+
         // Start coordinator
         self.coordinator.start().await?;
 
+        // File targets feed a balancer on this host; a shared API target
+        // is pushed by the leader alone
+        if let Some(exporter) = self.load_balancer.clone() {
+            match exporter.config().target {
+                load_balancer::LoadBalancerTarget::File { .. } => {
+                    exporter.start(Some(self.events())).await;
+                }
+                load_balancer::LoadBalancerTarget::Api { .. } => {
+                    let interval = exporter.config().interval;
+                    self.leader_tasks
+                        .register("load_balancer_export", interval, move || {
+                            let exporter = exporter.clone();
+                            async move { exporter.export().await.map(|_| ()) }
+                        })
+                        .await;
+                }
+            }
+        }
+
+        let traffic = self.traffic.clone();
+        self.leader_tasks
+            .register(
+                "traffic_aggregation",
+                traffic::DEFAULT_AGGREGATION_INTERVAL,
+                move || {
+                    let traffic = traffic.clone();
+                    async move { traffic.aggregate().await.map(|_| ()) }
+                },
+            )
+            .await;
+
+        // Run leader-only jobs wherever the leader currently is
+        self.leader_tasks
+            .clone()
+            .start(Some(self.coordinator.subscribe_to_events()))
+            .await;
+
+        // Flush queued gossip in batches
+        self.gossip.clone().start().await?;
+
+        // Follow cordons applied from other nodes
+        self.cordon.clone().start().await;
+
+        // Keep gossip membership in line with consensus membership
+        self.reconciler.clone().start().await;
+
+        // Keep a timeline of membership, leadership and config changes
+        if self.config.history.enabled {
+            let dir = history::history_dir(&self.config.data_dir);
+            match StateHistory::open(&dir, self.config.history.retention).await {
+                Ok(history) => {
+                    let history = Arc::new(history);
+                    history
+                        .clone()
+                        .start(self.state.clone(), self.config.history.interval);
+                    self.history = Some(history);
+                }
+                Err(e) => tracing::warn!("Cluster state history unavailable: {}", e),
+            }
+        }
+
         // Join cluster if not the initial node
         if !self.config.is_initial_node {
             self.join_cluster().await?;
@@ -131,14 +370,118 @@ impl ClusterManager {
         self.state.read().await.clone()
     }
 
-    /// Update cluster configuration
+    /// Update cluster configuration on every node, replacing any values
+    /// pinned to node subsets. A write racing another node's write to
+    /// `key` is settled by `config.config_conflicts` and fails with
+    /// [`ClusterError::ConfigConflict`] under [`ConflictStrategy::Reject`].
     pub async fn update_config(&mut self, key: &str, value: serde_json::Value) -> Result<()> {
-        self.storage.store_config(key, value).await
+        self.config_store.store_config(key, value).await?;
+        self.publish_config_change(key);
+        Ok(())
+    }
+
+    /// Set `key` to `value` on the nodes matching `selector` only. Other
+    /// nodes keep what `key` held before, so a plain value becomes the
+    /// default of the pinned entry.
+    pub async fn update_config_for(
+        &mut self,
+        key: &str,
+        selector: LabelSelector,
+        value: serde_json::Value,
+    ) -> Result<()> {
+        let mut pinned = self.pinned_config(key).await?;
+        pinned.pin(selector, value);
+        self.store_pinned_config(key, pinned).await
+    }
+
+    /// Drop the value pinned to `selector` for `key`; the nodes it matched
+    /// fall back to the next matching target or the default. Returns
+    /// whether anything was pinned to `selector`.
+    pub async fn unpin_config(&mut self, key: &str, selector: &LabelSelector) -> Result<bool> {
+        let mut pinned = self.pinned_config(key).await?;
+        if !pinned.unpin(selector) {
+            return Ok(false);
+        }
+        self.store_pinned_config(key, pinned).await?;
+        Ok(true)
     }
 
-    /// Get configuration value
+    /// Pinned form of `key` as stored, wrapping a plain value as the default
+    async fn pinned_config(&self, key: &str) -> Result<PinnedConfig> {
+        let raw = self.config_store.get_config(key).await?;
+        Ok(match raw {
+            Some(raw) => PinnedConfig::decode(&raw).unwrap_or(PinnedConfig {
+                targets: Vec::new(),
+                default: Some(raw),
+            }),
+            None => PinnedConfig::default(),
+        })
+    }
+
+    async fn store_pinned_config(&mut self, key: &str, pinned: PinnedConfig) -> Result<()> {
+        match pinned.encode() {
+            Some(value) => self.config_store.store_config(key, value).await?,
+            None => {
+                self.config_store.remove_config(key).await?;
+            }
+        }
+        self.publish_config_change(key);
+        Ok(())
+    }
+
+    fn publish_config_change(&self, key: &str) {
+        self.events()
+            .publish(CoordinationEvent::ConfigurationChanged {
+                key: key.to_string(),
+                timestamp: coordination::current_timestamp(),
+            });
+    }
+
+    /// Cluster event bus; subscribe to it to follow membership, leadership,
+    /// configuration and user replication changes
+    pub fn events(&self) -> ClusterEventBus {
+        self.coordinator.event_bus()
+    }
+
+    /// Replicated user store on this cluster's storage, publishing its
+    /// writes on the event bus
+    pub fn user_store(&self) -> ReplicatedUserStore {
+        ReplicatedUserStore::new(self.storage.clone()).with_events(self.events())
+    }
+
+    /// This cluster's storage, accepting writes only while this node is
+    /// the leader holding the newest fencing token. Use it for leader-only
+    /// work so a deposed leader cannot overwrite its successor.
+    pub fn fenced_storage(&self) -> FencedStorage {
+        FencedStorage::new(self.storage.clone(), self.consensus.clone())
+    }
+
+    /// Get configuration value as it applies to this node; `None` when
+    /// `key` is unset or only pinned to nodes this one does not match
     pub async fn get_config(&self, key: &str) -> Result<Option<serde_json::Value>> {
-        self.storage.get_config(key).await
+        Ok(self
+            .config_store
+            .get_config(key)
+            .await?
+            .and_then(|raw| self.coordinator.resolve_config(raw)))
+    }
+
+    /// Every configuration entry that applies to this node, resolved
+    /// against its labels
+    pub async fn effective_config(&self) -> Result<HashMap<String, serde_json::Value>> {
+        Ok(self
+            .config_store
+            .get_all_config()
+            .await?
+            .into_iter()
+            .filter_map(|(key, raw)| Some((key, self.coordinator.resolve_config(raw)?)))
+            .collect())
+    }
+
+    /// Combine concurrent config writes with `merge` under
+    /// [`ConflictStrategy::Merge`] instead of the default [`JsonMerge`]
+    pub fn set_config_merge(&mut self, merge: Arc<dyn ConfigMerge>) {
+        self.config_store.set_merge(merge);
     }
 
     /// Get all nodes in the cluster
@@ -159,7 +502,14 @@ impl ClusterManager {
 
     /// Perform leader election
     pub async fn elect_leader(&mut self) -> Result<NodeId> {
-        self.consensus.elect_leader().await
+        let leader = self.consensus.elect_leader().await?;
+
+        self.events().publish(CoordinationEvent::LeaderElected {
+            node_id: leader.clone(),
+            term: self.consensus.get_term().await,
+            timestamp: coordination::current_timestamp(),
+        });
+        Ok(leader)
     }
 
     /// Handle node failure
@@ -168,6 +518,17 @@ impl ClusterManager {
         self.coordinator.handle_node_failure(failed_node).await
     }
 
+    /// Stop placing new users and proxy connections on a node, e.g. for a
+    /// maintenance window; its existing sessions stay up
+    pub async fn cordon(&self, node_id: &NodeId) -> Result<()> {
+        self.cordon.cordon(node_id).await
+    }
+
+    /// Return a cordoned node to service
+    pub async fn uncordon(&self, node_id: &NodeId) -> Result<()> {
+        self.cordon.uncordon(node_id).await
+    }
+
     /// Scale cluster by adding nodes
     pub async fn scale_up(&mut self, target_nodes: usize) -> Result<()> {
         self.coordinator.scale_cluster(target_nodes).await
@@ -177,6 +538,14 @@ impl ClusterManager {
     pub async fn shutdown(&mut self) -> Result<()> {
         tracing::info!("Shutting down cluster manager");
 
+        self.leader_tasks.stop().await;
+        self.reconciler.stop().await;
+        self.cordon.stop().await;
+        self.transfers.stop().await;
+        self.gossip.shutdown().await?;
+        if let Some(exporter) = &self.load_balancer {
+            exporter.stop().await;
+        }
         self.leave_cluster().await?;
         self.coordinator.shutdown().await?;
         self.consensus.shutdown().await?;
@@ -185,6 +554,28 @@ impl ClusterManager {
     }
 }
 
+/// This node's identity. A persisted Raft log names its node, so with the
+/// WAL on the id is kept under `data_dir` and reused across restarts.
+fn load_node_id(config: &ClusterConfig) -> Result<NodeId> {
+    let persisted = matches!(config.consensus_algorithm, config::ConsensusAlgorithm::Raft)
+        && config.consensus_log.enabled;
+    if !persisted {
+        return Ok(NodeId::new());
+    }
+
+    let path = config.data_dir.join("node_id");
+    match std::fs::read_to_string(&path) {
+        Ok(id) => NodeId::from_string(id.trim()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let node_id = NodeId::new();
+            std::fs::create_dir_all(&config.data_dir)?;
+            std::fs::write(&path, node_id.to_string())?;
+            Ok(node_id)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -202,13 +593,166 @@ mod tests {
             },
             consensus_algorithm: config::ConsensusAlgorithm::Raft,
             is_initial_node: true,
+            learner: false,
             bootstrap_nodes: vec![],
             gossip_interval: std::time::Duration::from_secs(5),
             heartbeat_interval: std::time::Duration::from_secs(1),
             election_timeout: std::time::Duration::from_secs(10),
+            tls: None,
+            admin_api: false,
+            transport: Default::default(),
+            gossip: Default::default(),
+            health_scoring: Default::default(),
+            rolling_restart: Default::default(),
+            clock_skew: Default::default(),
+            load_balancer: None,
+            region: None,
+            zone: None,
+            labels: Default::default(),
+            data_dir: temp_dir.path().to_path_buf(),
+            consensus_log: Default::default(),
+            config_conflicts: Default::default(),
+            history: Default::default(),
         };
 
         let manager = ClusterManager::new(config).await;
         assert!(manager.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_cluster_config_survives_restart() {
+        let temp_dir = tempdir().unwrap();
+        let config = ClusterConfig {
+            node_name: "persistent-node".to_string(),
+            cluster_name: "test-cluster".to_string(),
+            storage_backend: config::StorageBackendConfig::Sled {
+                path: temp_dir.path().to_path_buf(),
+            },
+            data_dir: temp_dir.path().join("data"),
+            is_initial_node: true,
+            ..ClusterConfig::default()
+        };
+
+        let node_id = {
+            let mut manager = ClusterManager::new(config.clone()).await.unwrap();
+            manager
+                .update_config("vpn.port", serde_json::json!(443))
+                .await
+                .unwrap();
+            manager.consensus.start().await.unwrap();
+            manager.consensus.elect_leader().await.unwrap();
+            manager.node_id.clone()
+        };
+
+        let manager = ClusterManager::new(config).await.unwrap();
+        assert_eq!(
+            manager.get_config("vpn.port").await.unwrap(),
+            Some(serde_json::json!(443))
+        );
+
+        // The Raft log comes back under the same identity
+        assert_eq!(manager.node_id, node_id);
+        assert_eq!(manager.consensus.get_term().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_config_pinned_to_node_labels() {
+        let temp_dir = tempdir().unwrap();
+        let config = ClusterConfig {
+            node_name: "eu-edge".to_string(),
+            region: Some("eu".to_string()),
+            labels: HashMap::from([("tier".to_string(), "edge".to_string())]),
+            storage_backend: config::StorageBackendConfig::Sled {
+                path: temp_dir.path().to_path_buf(),
+            },
+            data_dir: temp_dir.path().join("data"),
+            is_initial_node: true,
+            ..ClusterConfig::default()
+        };
+        let mut manager = ClusterManager::new(config).await.unwrap();
+
+        let us = LabelSelector::parse("region=us").unwrap();
+        let eu_edge = LabelSelector::parse("region=eu,tier=edge").unwrap();
+        manager
+            .update_config("sni.pool", serde_json::json!(["global.example.com"]))
+            .await
+            .unwrap();
+        manager
+            .update_config_for(
+                "sni.pool",
+                us.clone(),
+                serde_json::json!(["us.example.com"]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            manager.get_config("sni.pool").await.unwrap(),
+            Some(serde_json::json!(["global.example.com"]))
+        );
+
+        manager
+            .update_config_for(
+                "sni.pool",
+                eu_edge.clone(),
+                serde_json::json!(["eu.example.com"]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            manager.effective_config().await.unwrap().get("sni.pool"),
+            Some(&serde_json::json!(["eu.example.com"]))
+        );
+
+        // Entries pinned elsewhere with no default do not apply here
+        manager
+            .update_config_for("relay.port", us, serde_json::json!(8443))
+            .await
+            .unwrap();
+        assert_eq!(manager.get_config("relay.port").await.unwrap(), None);
+
+        let nodes = manager.coordinator.nodes_matching(&eu_edge).await;
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].name, "eu-edge");
+
+        assert!(manager.unpin_config("sni.pool", &eu_edge).await.unwrap());
+        assert!(!manager.unpin_config("sni.pool", &eu_edge).await.unwrap());
+        assert_eq!(
+            manager.get_config("sni.pool").await.unwrap(),
+            Some(serde_json::json!(["global.example.com"]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_membership_reconciled_with_consensus() {
+        let temp_dir = tempdir().unwrap();
+        let config = ClusterConfig {
+            node_name: "leader".to_string(),
+            storage_backend: config::StorageBackendConfig::Sled {
+                path: temp_dir.path().to_path_buf(),
+            },
+            data_dir: temp_dir.path().join("data"),
+            is_initial_node: true,
+            ..ClusterConfig::default()
+        };
+        let manager = ClusterManager::new(config).await.unwrap();
+        manager.consensus.start().await.unwrap();
+        manager.consensus.elect_leader().await.unwrap();
+
+        // Gossip still has a node consensus never admitted
+        let stale = Node::new("stale".to_string(), "127.0.0.1:7999".parse().unwrap());
+        let stale_id = stale.id.clone();
+        manager.state.write().await.add_node(stale).unwrap();
+
+        // The first sighting is only remembered
+        assert!(manager.reconciler.reconcile().await.unwrap().is_empty());
+        let divergences = manager.reconciler.reconcile().await.unwrap();
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].node_id, stale_id);
+        assert!(divergences[0].healed);
+
+        let state = manager.get_cluster_state().await;
+        assert!(state.get_node(&stale_id).is_none());
+        assert!(state.get_tombstone(&stale_id).is_some());
+        assert!(state.get_node(&manager.node_id).is_some());
+    }
 }
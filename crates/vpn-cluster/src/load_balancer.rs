@@ -0,0 +1,624 @@
+//! Load balancer config export
+//!
+//! Renders an HAProxy or Nginx `stream` fragment listing the healthy nodes
+//! of the cluster, so an external L4 balancer can front the VPN services.
+//! A node is reached on its `vpn_host` metadata, falling back to the IP of
+//! its cluster address, and on the service port unless its
+//! `vpn_port.<service>` metadata overrides it.
+//!
+//! The fragment is rewritten only when its content changes. File targets
+//! are kept up to date by every node that has them configured, since each
+//! node may feed a balancer on its own host; API targets are pushed by the
+//! leader only.
+
+use crate::error::{ClusterError, Result};
+use crate::events::ClusterEventBus;
+use crate::node::Node;
+use crate::state::ClusterState;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+/// Node metadata key holding the host VPN clients should connect to
+pub const VPN_HOST_METADATA: &str = "vpn_host";
+
+/// Prefix of node metadata keys overriding a service's port on that node
+pub const VPN_PORT_METADATA_PREFIX: &str = "vpn_port.";
+
+/// Balancer the fragment is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadBalancerFormat {
+    Haproxy,
+    Nginx,
+}
+
+/// A VPN service exposed through the balancer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadBalancerService {
+    /// Name used for the generated frontend and backend
+    pub name: String,
+
+    /// Port the balancer listens on
+    pub listen_port: u16,
+
+    /// Port the service listens on on each node; defaults to `listen_port`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_port: Option<u16>,
+
+    /// Balance UDP instead of TCP (Nginx only)
+    #[serde(default)]
+    pub udp: bool,
+}
+
+/// Where the rendered fragment goes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LoadBalancerTarget {
+    /// Write to a local file, then run `reload_command` if set
+    File {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reload_command: Option<Vec<String>>,
+    },
+
+    /// PUT the fragment as plain text to an HTTP endpoint
+    Api {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+}
+
+/// Load balancer export settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadBalancerExportConfig {
+    pub format: LoadBalancerFormat,
+
+    pub target: LoadBalancerTarget,
+
+    pub services: Vec<LoadBalancerService>,
+
+    /// How often the fragment is regenerated between membership events
+    #[serde(default = "default_export_interval")]
+    pub interval: Duration,
+}
+
+fn default_export_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl LoadBalancerExportConfig {
+    /// Validate services and target
+    pub fn validate(&self) -> Result<()> {
+        if self.services.is_empty() {
+            return Err(ClusterError::configuration(
+                "Load balancer export needs at least one service",
+            ));
+        }
+
+        let mut listeners = HashSet::new();
+        for service in &self.services {
+            if service.name.is_empty() || service.listen_port == 0 {
+                return Err(ClusterError::configuration(
+                    "Load balancer services need a name and a listen port",
+                ));
+            }
+
+            if !listeners.insert((service.listen_port, service.udp)) {
+                return Err(ClusterError::configuration(format!(
+                    "Load balancer port {} is used by more than one service",
+                    service.listen_port
+                )));
+            }
+
+            if service.udp && self.format == LoadBalancerFormat::Haproxy {
+                return Err(ClusterError::configuration(format!(
+                    "HAProxy cannot balance UDP service '{}'",
+                    service.name
+                )));
+            }
+        }
+
+        match &self.target {
+            LoadBalancerTarget::File { reload_command, .. } => {
+                if reload_command
+                    .as_ref()
+                    .is_some_and(|command| command.is_empty())
+                {
+                    return Err(ClusterError::configuration(
+                        "Load balancer reload command cannot be empty",
+                    ));
+                }
+            }
+            LoadBalancerTarget::Api { url, .. } => {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(ClusterError::configuration(
+                        "Load balancer API URL must be http(s)",
+                    ));
+                }
+            }
+        }
+
+        if self.interval < Duration::from_secs(1) {
+            return Err(ClusterError::configuration(
+                "Load balancer export interval must be at least 1 second",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// One backend server of a service
+struct Backend {
+    name: String,
+    address: String,
+}
+
+/// Render the fragment for the healthy, uncordoned nodes of `state`.
+///
+/// Nodes are sorted by name so the output only changes when the set of
+/// healthy nodes or their addresses do. Dropping a cordoned node from the
+/// backends stops new connections only; the balancers keep established
+/// sessions across a reload.
+pub fn render(config: &LoadBalancerExportConfig, state: &ClusterState) -> String {
+    let mut nodes = state.get_healthy_nodes();
+    nodes.retain(|node| !node.cordoned);
+    nodes.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| a.id.to_string().cmp(&b.id.to_string()))
+    });
+
+    let mut output = format!(
+        "# Generated by vpn-cluster for cluster '{}'; changes will be overwritten\n",
+        state.cluster_name
+    );
+
+    for service in &config.services {
+        let backends: Vec<Backend> = nodes
+            .iter()
+            .map(|node| Backend {
+                name: identifier(&node.name),
+                address: format!("{}:{}", node_host(node), node_port(node, service)),
+            })
+            .collect();
+
+        output.push('\n');
+        match config.format {
+            LoadBalancerFormat::Haproxy => render_haproxy(&mut output, service, &backends),
+            LoadBalancerFormat::Nginx => render_nginx(&mut output, service, &backends),
+        }
+    }
+
+    output
+}
+
+fn render_haproxy(output: &mut String, service: &LoadBalancerService, backends: &[Backend]) {
+    let name = identifier(&service.name);
+
+    output.push_str(&format!("frontend vpn_{}\n", name));
+    output.push_str(&format!("    bind *:{}\n", service.listen_port));
+    output.push_str("    mode tcp\n");
+    output.push_str(&format!("    default_backend vpn_{}_nodes\n\n", name));
+
+    output.push_str(&format!("backend vpn_{}_nodes\n", name));
+    output.push_str("    mode tcp\n");
+    output.push_str("    balance leastconn\n");
+    for backend in backends {
+        output.push_str(&format!(
+            "    server {} {} check\n",
+            backend.name, backend.address
+        ));
+    }
+}
+
+fn render_nginx(output: &mut String, service: &LoadBalancerService, backends: &[Backend]) {
+    let name = identifier(&service.name);
+
+    output.push_str(&format!("upstream vpn_{} {{\n", name));
+    if !service.udp {
+        output.push_str("    least_conn;\n");
+    }
+    for backend in backends {
+        output.push_str(&format!(
+            "    server {} max_fails=3 fail_timeout=10s; # {}\n",
+            backend.address, backend.name
+        ));
+    }
+    if backends.is_empty() {
+        // Nginx refuses an upstream without servers
+        output.push_str(&format!(
+            "    server 127.0.0.1:{} down; # no healthy nodes\n",
+            service.listen_port
+        ));
+    }
+    output.push_str("}\n\n");
+
+    output.push_str("server {\n");
+    output.push_str(&format!(
+        "    listen {}{};\n",
+        service.listen_port,
+        if service.udp { " udp" } else { "" }
+    ));
+    output.push_str(&format!("    proxy_pass vpn_{};\n", name));
+    output.push_str("}\n");
+}
+
+fn node_host(node: &Node) -> String {
+    match node.metadata.get(VPN_HOST_METADATA) {
+        Some(host) if !host.is_empty() => host.clone(),
+        _ => match node.address.ip() {
+            std::net::IpAddr::V6(ip) => format!("[{}]", ip),
+            ip => ip.to_string(),
+        },
+    }
+}
+
+fn node_port(node: &Node, service: &LoadBalancerService) -> u16 {
+    node.metadata
+        .get(&format!("{}{}", VPN_PORT_METADATA_PREFIX, service.name))
+        .and_then(|port| port.parse().ok())
+        .or(service.node_port)
+        .unwrap_or(service.listen_port)
+}
+
+/// Name usable as a balancer identifier
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Keeps a balancer fragment in line with the healthy cluster nodes
+pub struct LoadBalancerExporter {
+    config: LoadBalancerExportConfig,
+    state: Arc<RwLock<ClusterState>>,
+    http: reqwest::Client,
+    /// Last fragment written or pushed successfully
+    last: Mutex<Option<String>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl LoadBalancerExporter {
+    pub fn new(config: LoadBalancerExportConfig, state: Arc<RwLock<ClusterState>>) -> Self {
+        Self {
+            config,
+            state,
+            http: reqwest::Client::new(),
+            last: Mutex::new(None),
+            task: Mutex::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &LoadBalancerExportConfig {
+        &self.config
+    }
+
+    /// Render the current fragment and write or push it if it changed,
+    /// returning whether anything was sent
+    pub async fn export(&self) -> Result<bool> {
+        let fragment = {
+            let state = self.state.read().await;
+            render(&self.config, &state)
+        };
+
+        let mut last = self.last.lock().await;
+        if last.as_deref() == Some(fragment.as_str()) {
+            return Ok(false);
+        }
+
+        let changed = match &self.config.target {
+            LoadBalancerTarget::File {
+                path,
+                reload_command,
+            } => write_fragment(path, &fragment, reload_command.as_deref()).await?,
+            LoadBalancerTarget::Api { url, token } => {
+                self.push_fragment(url, token.as_deref(), &fragment).await?;
+                true
+            }
+        };
+
+        if changed {
+            tracing::info!("Exported load balancer config to {}", self.target_name());
+        }
+        *last = Some(fragment);
+        Ok(changed)
+    }
+
+    async fn push_fragment(&self, url: &str, token: Option<&str>, fragment: &str) -> Result<()> {
+        let mut request = self
+            .http
+            .put(url)
+            .header(reqwest::header::CONTENT_TYPE, "text/plain")
+            .body(fragment.to_string());
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(ClusterError::network(format!(
+                "Load balancer API returned {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn target_name(&self) -> String {
+        match &self.config.target {
+            LoadBalancerTarget::File { path, .. } => path.display().to_string(),
+            LoadBalancerTarget::Api { url, .. } => url.clone(),
+        }
+    }
+
+    /// Export every interval and whenever `events` reports a change, until
+    /// stopped
+    pub async fn start(self: Arc<Self>, events: Option<ClusterEventBus>) {
+        let exporter = self.clone();
+        let handle = tokio::spawn(async move {
+            let mut events = events.as_ref().map(ClusterEventBus::stream);
+            let mut ticker = tokio::time::interval(exporter.config.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                match events.as_mut() {
+                    Some(stream) => {
+                        tokio::select! {
+                            _ = ticker.tick() => {}
+                            event = stream.next_event() => {
+                                if event.is_none() {
+                                    events = None;
+                                }
+                            }
+                        }
+                    }
+                    None => {
+                        ticker.tick().await;
+                    }
+                }
+
+                if let Err(e) = exporter.export().await {
+                    tracing::warn!("Load balancer config export failed: {}", e);
+                }
+            }
+        });
+
+        if let Some(previous) = self.task.lock().await.replace(handle) {
+            previous.abort();
+        }
+    }
+
+    pub async fn stop(&self) {
+        if let Some(handle) = self.task.lock().await.take() {
+            handle.abort();
+        }
+    }
+}
+
+impl Drop for LoadBalancerExporter {
+    fn drop(&mut self) {
+        if let Some(handle) = self.task.get_mut().take() {
+            handle.abort();
+        }
+    }
+}
+
+/// Atomically replace `path` with `fragment` unless it already holds it,
+/// then reload the balancer; returns whether the file changed
+async fn write_fragment(
+    path: &Path,
+    fragment: &str,
+    reload_command: Option<&[String]>,
+) -> Result<bool> {
+    if tokio::fs::read_to_string(path).await.ok().as_deref() == Some(fragment) {
+        return Ok(false);
+    }
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, fragment).await?;
+    tokio::fs::rename(&tmp, path).await?;
+
+    if let Some([program, args @ ..]) = reload_command {
+        let status = tokio::process::Command::new(program)
+            .args(args)
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(ClusterError::configuration(format!(
+                "Load balancer reload command '{}' exited with {}",
+                program, status
+            )));
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{NodeId, NodeStatus};
+    use tempfile::tempdir;
+
+    fn node(name: &str, address: &str, status: NodeStatus) -> Node {
+        let mut node = Node::new(name.to_string(), address.parse().unwrap());
+        node.status = status;
+        node
+    }
+
+    fn cluster() -> ClusterState {
+        let mut state = ClusterState::with_cluster_name(NodeId::new(), "edge".to_string());
+        state
+            .add_node(node("node-b", "10.0.0.2:8080", NodeStatus::Healthy))
+            .unwrap();
+        let mut node_a = node("node-a", "10.0.0.1:8080", NodeStatus::Healthy);
+        node_a
+            .metadata
+            .insert(VPN_HOST_METADATA.to_string(), "a.vpn.example".to_string());
+        node_a.metadata.insert(
+            format!("{}vless", VPN_PORT_METADATA_PREFIX),
+            "9443".to_string(),
+        );
+        state.add_node(node_a).unwrap();
+        state
+            .add_node(node("node-c", "10.0.0.3:8080", NodeStatus::Failed))
+            .unwrap();
+        state
+    }
+
+    fn export_config(
+        format: LoadBalancerFormat,
+        target: LoadBalancerTarget,
+    ) -> LoadBalancerExportConfig {
+        LoadBalancerExportConfig {
+            format,
+            target,
+            services: vec![
+                LoadBalancerService {
+                    name: "vless".to_string(),
+                    listen_port: 443,
+                    node_port: Some(8443),
+                    udp: false,
+                },
+                LoadBalancerService {
+                    name: "wireguard".to_string(),
+                    listen_port: 51820,
+                    node_port: None,
+                    udp: true,
+                },
+            ],
+            interval: default_export_interval(),
+        }
+    }
+
+    #[test]
+    fn test_render_lists_healthy_nodes() {
+        let state = cluster();
+        let mut config = export_config(
+            LoadBalancerFormat::Nginx,
+            LoadBalancerTarget::Api {
+                url: "http://lb.internal/config".to_string(),
+                token: None,
+            },
+        );
+        config.validate().unwrap();
+
+        let nginx = render(&config, &state);
+        assert!(nginx.contains("server a.vpn.example:9443 max_fails=3 fail_timeout=10s; # node-a"));
+        assert!(nginx.contains("server 10.0.0.2:8443 max_fails=3 fail_timeout=10s; # node-b"));
+        assert!(nginx.contains("server 10.0.0.2:51820"));
+        assert!(nginx.contains("listen 51820 udp;"));
+        assert!(!nginx.contains("10.0.0.3"));
+        assert!(nginx.find("node-a").unwrap() < nginx.find("node-b").unwrap());
+
+        // HAProxy only balances TCP
+        config.format = LoadBalancerFormat::Haproxy;
+        assert!(config.validate().is_err());
+        config.services.truncate(1);
+        config.validate().unwrap();
+
+        let haproxy = render(&config, &state);
+        assert!(haproxy.contains("frontend vpn_vless\n    bind *:443\n"));
+        assert!(haproxy.contains("    server node-a a.vpn.example:9443 check\n"));
+        assert!(haproxy.contains("    server node-b 10.0.0.2:8443 check\n"));
+        assert!(!haproxy.contains("node-c"));
+    }
+
+    #[test]
+    fn test_render_skips_cordoned_nodes() {
+        let mut state = cluster();
+        let node_b = state
+            .get_all_nodes()
+            .into_iter()
+            .find(|node| node.name == "node-b")
+            .map(|node| node.id.clone())
+            .unwrap();
+        state.get_node_mut(&node_b).unwrap().cordon();
+
+        let config = export_config(
+            LoadBalancerFormat::Nginx,
+            LoadBalancerTarget::Api {
+                url: "http://lb.internal/config".to_string(),
+                token: None,
+            },
+        );
+
+        let nginx = render(&config, &state);
+        assert!(nginx.contains("# node-a"));
+        assert!(!nginx.contains("node-b"));
+    }
+
+    #[test]
+    fn test_render_without_healthy_nodes() {
+        let state = ClusterState::with_cluster_name(NodeId::new(), "edge".to_string());
+        let config = export_config(
+            LoadBalancerFormat::Nginx,
+            LoadBalancerTarget::Api {
+                url: "http://lb.internal/config".to_string(),
+                token: None,
+            },
+        );
+
+        let nginx = render(&config, &state);
+        assert!(nginx.contains("server 127.0.0.1:443 down;"));
+    }
+
+    #[tokio::test]
+    async fn test_file_export_is_change_only() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("haproxy").join("vpn.cfg");
+        let mut config = export_config(
+            LoadBalancerFormat::Haproxy,
+            LoadBalancerTarget::File {
+                path: path.clone(),
+                reload_command: None,
+            },
+        );
+        config.services.truncate(1);
+
+        let state = Arc::new(RwLock::new(cluster()));
+        let exporter = LoadBalancerExporter::new(config.clone(), state.clone());
+
+        assert!(exporter.export().await.unwrap());
+        assert!(!exporter.export().await.unwrap());
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.contains("node-b"));
+
+        // A restarted exporter leaves an up-to-date file alone
+        let restarted = LoadBalancerExporter::new(config, state.clone());
+        assert!(!restarted.export().await.unwrap());
+
+        {
+            let mut state = state.write().await;
+            let node_b = state
+                .get_all_nodes()
+                .into_iter()
+                .find(|node| node.name == "node-b")
+                .map(|node| node.id.clone())
+                .unwrap();
+            state.get_node_mut(&node_b).unwrap().status = NodeStatus::Failed;
+        }
+
+        assert!(exporter.export().await.unwrap());
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(!written.contains("node-b"));
+        assert!(written.contains("node-a"));
+    }
+}
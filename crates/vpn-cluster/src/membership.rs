@@ -1,8 +1,124 @@
 //! Cluster membership management
+//!
+//! Voter set changes go through the consensus log using joint consensus:
+//! the leader first commits a joint configuration (C_old,new) in which every
+//! decision needs a majority of both the old and the new voter sets, and only
+//! then commits the new configuration (C_new). At no point can two disjoint
+//! majorities make decisions independently.
 
-use crate::error::Result;
+use crate::error::{ClusterError, Result};
 use crate::node::{Node, NodeId};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Voting configuration of the cluster
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Configuration {
+    /// A single set of voters
+    Stable(HashSet<NodeId>),
+    /// Transitional configuration requiring majorities of both voter sets
+    Joint {
+        old: HashSet<NodeId>,
+        new: HashSet<NodeId>,
+    },
+}
+
+/// A requested change to the voter set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MembershipChange {
+    AddVoter(NodeId),
+    RemoveVoter(NodeId),
+}
+
+impl Configuration {
+    /// Create a stable configuration from a set of voters
+    pub fn stable(voters: impl IntoIterator<Item = NodeId>) -> Self {
+        Self::Stable(voters.into_iter().collect())
+    }
+
+    /// All nodes that vote in this configuration
+    pub fn voters(&self) -> HashSet<NodeId> {
+        match self {
+            Self::Stable(voters) => voters.clone(),
+            Self::Joint { old, new } => old.union(new).cloned().collect(),
+        }
+    }
+
+    /// Check whether a node votes in this configuration
+    pub fn contains(&self, node_id: &NodeId) -> bool {
+        match self {
+            Self::Stable(voters) => voters.contains(node_id),
+            Self::Joint { old, new } => old.contains(node_id) || new.contains(node_id),
+        }
+    }
+
+    /// Check whether this is a joint configuration
+    pub fn is_joint(&self) -> bool {
+        matches!(self, Self::Joint { .. })
+    }
+
+    /// Check whether the acknowledging nodes form a quorum
+    pub fn has_quorum(&self, acks: &HashSet<NodeId>) -> bool {
+        match self {
+            Self::Stable(voters) => is_majority(voters, acks),
+            Self::Joint { old, new } => is_majority(old, acks) && is_majority(new, acks),
+        }
+    }
+
+    /// Enter joint consensus for a membership change
+    pub fn begin_change(&self, change: &MembershipChange) -> Result<Self> {
+        let old = match self {
+            Self::Stable(voters) => voters,
+            Self::Joint { .. } => {
+                return Err(ClusterError::membership(
+                    "Membership change already in progress",
+                ))
+            }
+        };
+
+        let mut new = old.clone();
+        match change {
+            MembershipChange::AddVoter(node_id) => {
+                if !new.insert(node_id.clone()) {
+                    return Err(ClusterError::node_already_exists(node_id.to_string()));
+                }
+            }
+            MembershipChange::RemoveVoter(node_id) => {
+                if !new.remove(node_id) {
+                    return Err(ClusterError::node_not_found(node_id.to_string()));
+                }
+                if new.is_empty() {
+                    return Err(ClusterError::membership("Cannot remove the last voter"));
+                }
+            }
+        }
+
+        Ok(Self::Joint {
+            old: old.clone(),
+            new,
+        })
+    }
+
+    /// The configuration that follows a committed joint configuration
+    pub fn finalize(&self) -> Option<Self> {
+        match self {
+            Self::Stable(_) => None,
+            Self::Joint { new, .. } => Some(Self::Stable(new.clone())),
+        }
+    }
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self::Stable(HashSet::new())
+    }
+}
+
+/// Check whether `acks` contains a strict majority of `voters`
+fn is_majority(voters: &HashSet<NodeId>, acks: &HashSet<NodeId>) -> bool {
+    let count = voters.iter().filter(|id| acks.contains(id)).count();
+    count * 2 > voters.len()
+}
 
 /// Manages cluster membership (placeholder)
 pub struct MembershipManager {
@@ -34,3 +150,71 @@ impl MembershipManager {
         self.members.values().collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stable_quorum() {
+        let nodes: Vec<NodeId> = (0..3).map(|_| NodeId::new()).collect();
+        let config = Configuration::stable(nodes.clone());
+
+        let acks: HashSet<NodeId> = nodes[..1].iter().cloned().collect();
+        assert!(!config.has_quorum(&acks));
+
+        let acks: HashSet<NodeId> = nodes[..2].iter().cloned().collect();
+        assert!(config.has_quorum(&acks));
+
+        assert!(!Configuration::default().has_quorum(&acks));
+    }
+
+    #[test]
+    fn test_joint_quorum_requires_both_majorities() {
+        let a = NodeId::new();
+        let b = NodeId::new();
+        let c = NodeId::new();
+        let d = NodeId::new();
+
+        // {a, b, c} -> {a, b, c, d}
+        let config = Configuration::stable([a.clone(), b.clone(), c.clone()])
+            .begin_change(&MembershipChange::AddVoter(d.clone()))
+            .unwrap();
+        assert!(config.is_joint());
+        assert!(config.contains(&d));
+
+        // Majority of old but not of new
+        let acks: HashSet<NodeId> = [a.clone(), b.clone()].into_iter().collect();
+        assert!(!config.has_quorum(&acks));
+
+        let acks: HashSet<NodeId> = [a.clone(), b.clone(), d.clone()].into_iter().collect();
+        assert!(config.has_quorum(&acks));
+
+        assert_eq!(config.finalize(), Some(Configuration::stable([a, b, c, d])));
+    }
+
+    #[test]
+    fn test_invalid_changes() {
+        let a = NodeId::new();
+        let b = NodeId::new();
+        let config = Configuration::stable([a.clone()]);
+
+        assert!(config
+            .begin_change(&MembershipChange::AddVoter(a.clone()))
+            .is_err());
+        assert!(config
+            .begin_change(&MembershipChange::RemoveVoter(b.clone()))
+            .is_err());
+        assert!(config
+            .begin_change(&MembershipChange::RemoveVoter(a))
+            .is_err());
+
+        // Only one change at a time
+        let joint = config
+            .begin_change(&MembershipChange::AddVoter(b.clone()))
+            .unwrap();
+        assert!(joint
+            .begin_change(&MembershipChange::RemoveVoter(b))
+            .is_err());
+    }
+}
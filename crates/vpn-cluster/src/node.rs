@@ -1,6 +1,8 @@
 //! Cluster node management
 
+use crate::config::HealthScoringConfig;
 use crate::error::{ClusterError, Result};
+use crate::selector;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
@@ -68,6 +70,10 @@ pub enum NodeRole {
     /// Observer node - read-only, doesn't participate in consensus
     Observer,
 
+    /// Learner node - receives replicated state for read-only use, such as
+    /// dashboards and backups, but never votes or stands for election
+    Learner,
+
     /// Bootstrap node - helps new nodes join the cluster
     Bootstrap,
 }
@@ -85,6 +91,7 @@ impl fmt::Display for NodeRole {
             Self::Follower => write!(f, "follower"),
             Self::Candidate => write!(f, "candidate"),
             Self::Observer => write!(f, "observer"),
+            Self::Learner => write!(f, "learner"),
             Self::Bootstrap => write!(f, "bootstrap"),
         }
     }
@@ -111,6 +118,9 @@ pub enum NodeStatus {
     /// Node is temporarily unavailable
     Unavailable,
 
+    /// Node keeps serving its existing users but takes no new ones
+    Draining,
+
     /// Node status is unknown
     Unknown,
 }
@@ -130,11 +140,43 @@ impl fmt::Display for NodeStatus {
             Self::Starting => write!(f, "starting"),
             Self::Stopping => write!(f, "stopping"),
             Self::Unavailable => write!(f, "unavailable"),
+            Self::Draining => write!(f, "draining"),
             Self::Unknown => write!(f, "unknown"),
         }
     }
 }
 
+/// Region and availability zone a node runs in. Nodes sharing both form one
+/// failure domain.
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Placement {
+    pub region: Option<String>,
+    pub zone: Option<String>,
+}
+
+impl Placement {
+    /// Create a placement
+    pub fn new(region: Option<String>, zone: Option<String>) -> Self {
+        Self { region, zone }
+    }
+
+    /// Whether neither region nor zone is known
+    pub fn is_unknown(&self) -> bool {
+        self.region.is_none() && self.zone.is_none()
+    }
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}",
+            self.region.as_deref().unwrap_or("-"),
+            self.zone.as_deref().unwrap_or("-")
+        )
+    }
+}
+
 /// Represents a node in the cluster
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node {
@@ -168,11 +210,25 @@ pub struct Node {
     /// Region/datacenter where this node is located
     pub region: Option<String>,
 
+    /// Availability zone within the region
+    #[serde(default)]
+    pub zone: Option<String>,
+
     /// Available resources on this node
     pub resources: NodeResources,
 
     /// Health information
     pub health: NodeHealth,
+
+    /// Raised each time the node rejoins after being removed; a node whose
+    /// incarnation is not above its tombstone's cannot re-add itself
+    #[serde(default)]
+    pub incarnation: u64,
+
+    /// Set by an operator to keep new users and proxy connections off this
+    /// node while its existing sessions carry on; survives status changes
+    #[serde(default)]
+    pub cordoned: bool,
 }
 
 impl Node {
@@ -191,8 +247,11 @@ impl Node {
             metadata: HashMap::new(),
             version: env!("CARGO_PKG_VERSION").to_string(),
             region: None,
+            zone: None,
             resources: NodeResources::default(),
             health: NodeHealth::default(),
+            incarnation: 0,
+            cordoned: false,
         }
     }
 
@@ -203,6 +262,11 @@ impl Node {
         node
     }
 
+    /// Move to the next incarnation, e.g. before rejoining after a removal
+    pub fn bump_incarnation(&mut self) {
+        self.incarnation += 1;
+    }
+
     /// Update last seen timestamp
     pub fn update_last_seen(&mut self) {
         self.last_seen = current_timestamp();
@@ -221,7 +285,10 @@ impl Node {
         matches!(
             self.role,
             NodeRole::Leader | NodeRole::Follower | NodeRole::Candidate
-        ) && matches!(self.status, NodeStatus::Healthy | NodeStatus::Starting)
+        ) && matches!(
+            self.status,
+            NodeStatus::Healthy | NodeStatus::Starting | NodeStatus::Draining
+        )
     }
 
     /// Get node uptime in seconds
@@ -244,6 +311,24 @@ impl Node {
         self.region = Some(region);
     }
 
+    /// Set node availability zone
+    pub fn set_zone(&mut self, zone: String) {
+        self.zone = Some(zone);
+    }
+
+    /// Failure domain this node belongs to
+    pub fn placement(&self) -> Placement {
+        Placement::new(self.region.clone(), self.zone.clone())
+    }
+
+    /// Labels matched by a [`LabelSelector`]: the node's metadata plus its
+    /// name, region and zone
+    ///
+    /// [`LabelSelector`]: crate::selector::LabelSelector
+    pub fn labels(&self) -> HashMap<String, String> {
+        selector::node_labels(&self.metadata, &self.name, &self.placement())
+    }
+
     /// Update node role
     pub fn set_role(&mut self, role: NodeRole) {
         tracing::info!(
@@ -278,6 +363,36 @@ impl Node {
         self.status == NodeStatus::Healthy
     }
 
+    /// Check if new VPN users may be placed on this node
+    pub fn is_routable(&self) -> bool {
+        self.status == NodeStatus::Healthy && !self.cordoned
+    }
+
+    /// Keep new users and connections off this node; returns `false` if it
+    /// was already cordoned
+    pub fn cordon(&mut self) -> bool {
+        !std::mem::replace(&mut self.cordoned, true)
+    }
+
+    /// Let new users and connections back onto this node; returns `false`
+    /// if it was not cordoned
+    pub fn uncordon(&mut self) -> bool {
+        std::mem::replace(&mut self.cordoned, false)
+    }
+
+    /// Record the node's current load and the health score derived from it
+    pub fn record_load(&mut self, load: &NodeLoad, scoring: &HealthScoringConfig) -> u8 {
+        let score = load.health_score(scoring);
+
+        self.resources.cpu_usage = load.cpu_usage;
+        self.resources.memory_usage = load.memory_usage;
+        self.health.connections = load.connections;
+        self.health.score = score;
+        self.health.last_check = current_timestamp();
+
+        score
+    }
+
     /// Get a summary of this node for logging
     pub fn summary(&self) -> String {
         format!(
@@ -348,6 +463,10 @@ pub struct NodeHealth {
 
     /// Health check details
     pub checks: HashMap<String, HealthCheck>,
+
+    /// Active VPN connections at the last load report
+    #[serde(default)]
+    pub connections: u64,
 }
 
 impl Default for NodeHealth {
@@ -357,7 +476,45 @@ impl Default for NodeHealth {
             consecutive_failures: 0,
             last_check: current_timestamp(),
             checks: HashMap::new(),
+            connections: 0,
+        }
+    }
+}
+
+/// Load figures a node's health score is computed from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeLoad {
+    /// CPU usage percentage
+    pub cpu_usage: f64,
+
+    /// Memory usage percentage
+    pub memory_usage: f64,
+
+    /// Active VPN connections
+    pub connections: u64,
+}
+
+impl NodeLoad {
+    /// Health score (0-100) for this load; higher is healthier
+    pub fn health_score(&self, scoring: &HealthScoringConfig) -> u8 {
+        let pressure = |value: f64| {
+            if value.is_finite() {
+                value.clamp(0.0, 1.0)
+            } else {
+                1.0
+            }
+        };
+
+        let weighted = scoring.cpu_weight * pressure(self.cpu_usage / 100.0)
+            + scoring.memory_weight * pressure(self.memory_usage / 100.0)
+            + scoring.connection_weight
+                * pressure(self.connections as f64 / scoring.max_connections.max(1) as f64);
+        let total_weight = scoring.cpu_weight + scoring.memory_weight + scoring.connection_weight;
+        if total_weight <= 0.0 {
+            return 100;
         }
+
+        (100.0 * (1.0 - weighted / total_weight)).round() as u8
     }
 }
 
@@ -464,6 +621,10 @@ mod tests {
         node.role = NodeRole::Observer;
         assert!(!node.can_vote());
 
+        // Learner cannot vote
+        node.role = NodeRole::Learner;
+        assert!(!node.can_vote());
+
         // Failed node cannot vote
         node.role = NodeRole::Follower;
         node.status = NodeStatus::Failed;
@@ -520,6 +681,33 @@ mod tests {
         assert!(node.is_healthy());
     }
 
+    #[test]
+    fn test_health_score_from_load() {
+        let scoring = HealthScoringConfig::default();
+        let address = "127.0.0.1:8080".parse().unwrap();
+        let mut node = Node::new("test-node".to_string(), address);
+
+        assert_eq!(NodeLoad::default().health_score(&scoring), 100);
+
+        let load = NodeLoad {
+            cpu_usage: 50.0,
+            memory_usage: 50.0,
+            connections: 500,
+        };
+        assert_eq!(node.record_load(&load, &scoring), 50);
+        assert_eq!(node.health.score, 50);
+        assert_eq!(node.health.connections, 500);
+        assert_eq!(node.resources.cpu_usage, 50.0);
+
+        // Readings past full load are capped
+        let overloaded = NodeLoad {
+            cpu_usage: 250.0,
+            memory_usage: 100.0,
+            connections: 5000,
+        };
+        assert_eq!(overloaded.health_score(&scoring), 0);
+    }
+
     #[test]
     fn test_health_check() {
         let check = HealthCheck::new("ping".to_string(), true, 50);
@@ -0,0 +1,445 @@
+//! Replay protection and idempotency for mutating cluster RPCs
+//!
+//! After a network flap a peer may retry a join, leave, forwarded write or
+//! user transfer that already reached this node, and a captured message may
+//! be sent again long after the fact. Applied twice, such a message could
+//! double-apply a change or bring back a node that has been removed since.
+//!
+//! Every mutating call carries an [`RpcStamp`] in its gRPC metadata: the
+//! sending node, a per-process sequence number, the time it was issued and,
+//! optionally, an idempotency key naming the logical operation. The
+//! [`ReplayGuard`] on the receiving node rejects stamps older than
+//! [`DEFAULT_MAX_AGE`] and sequence numbers it has seen from that sender
+//! before. A retry goes out with a new stamp but the same idempotency key,
+//! so the guard answers it with the response recorded for the first
+//! attempt instead of applying it again.
+//!
+//! Sequence numbers start at the process start time in microseconds, so
+//! they keep growing across restarts of the sending node.
+
+use crate::clock::now_millis;
+use crate::node::NodeId;
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Status};
+
+/// Metadata key carrying the sending node
+pub const SENDER_HEADER: &str = "x-cluster-sender";
+
+/// Metadata key carrying the sender's sequence number
+pub const SEQUENCE_HEADER: &str = "x-cluster-sequence";
+
+/// Metadata key carrying when the call was issued, in Unix milliseconds
+pub const ISSUED_AT_HEADER: &str = "x-cluster-issued-at";
+
+/// Metadata key naming the logical operation across retries
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Oldest stamp accepted by default, allowing for clock skew
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// How long responses are kept for retries by default
+pub const DEFAULT_IDEMPOTENCY_TTL: Duration = Duration::from_secs(600);
+
+/// Sequence numbers this far below a sender's highest are rejected
+const SEQUENCE_WINDOW: u64 = 64;
+
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Next sequence number of this process
+fn next_sequence() -> u64 {
+    let start = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    // The first caller seeds the counter with the start time
+    let _ = NEXT_SEQUENCE.compare_exchange(0, start, Ordering::SeqCst, Ordering::SeqCst);
+    NEXT_SEQUENCE.fetch_add(1, Ordering::SeqCst)
+}
+
+/// Who sent a call and when, attached to every mutating RPC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcStamp {
+    pub sender: String,
+    pub sequence: u64,
+    pub issued_at_ms: u64,
+    pub idempotency_key: Option<String>,
+}
+
+// Errors map straight onto gRPC responses
+#[allow(clippy::result_large_err)]
+impl RpcStamp {
+    /// Fresh stamp for a call from `sender`
+    pub fn new(sender: &NodeId, idempotency_key: Option<String>) -> Self {
+        Self {
+            sender: sender.to_string(),
+            sequence: next_sequence(),
+            issued_at_ms: now_millis(),
+            idempotency_key,
+        }
+    }
+
+    /// Wrap `message` in a request carrying this stamp
+    pub fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        self.apply(request.metadata_mut());
+        request
+    }
+
+    pub fn apply(&self, metadata: &mut MetadataMap) {
+        let values = [
+            (SENDER_HEADER, self.sender.clone()),
+            (SEQUENCE_HEADER, self.sequence.to_string()),
+            (ISSUED_AT_HEADER, self.issued_at_ms.to_string()),
+        ]
+        .into_iter()
+        .chain(
+            self.idempotency_key
+                .clone()
+                .map(|key| (IDEMPOTENCY_KEY_HEADER, key)),
+        );
+        for (key, value) in values {
+            if let Ok(value) = MetadataValue::try_from(value) {
+                metadata.insert(key, value);
+            }
+        }
+    }
+
+    /// Stamp of a received call; `None` if it carries none
+    pub fn from_metadata(metadata: &MetadataMap) -> Result<Option<Self>, Status> {
+        let text = |key: &str| {
+            metadata
+                .get(key)
+                .map(|value| {
+                    value
+                        .to_str()
+                        .map(str::to_string)
+                        .map_err(|_| Status::invalid_argument(format!("Malformed {}", key)))
+                })
+                .transpose()
+        };
+        let number = |key: &str| {
+            text(key)?
+                .map(|value| {
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| Status::invalid_argument(format!("Malformed {}", key)))
+                })
+                .transpose()
+        };
+
+        let Some(sender) = text(SENDER_HEADER)? else {
+            return Ok(None);
+        };
+        let sequence = number(SEQUENCE_HEADER)?
+            .ok_or_else(|| Status::invalid_argument(format!("Missing {}", SEQUENCE_HEADER)))?;
+        let issued_at_ms = number(ISSUED_AT_HEADER)?
+            .ok_or_else(|| Status::invalid_argument(format!("Missing {}", ISSUED_AT_HEADER)))?;
+
+        Ok(Some(Self {
+            sender,
+            sequence,
+            issued_at_ms,
+            idempotency_key: text(IDEMPOTENCY_KEY_HEADER)?.filter(|key| !key.is_empty()),
+        }))
+    }
+}
+
+/// Sequence numbers seen from one sender: the highest one and which of the
+/// [`SEQUENCE_WINDOW`] below it
+#[derive(Debug, Default)]
+struct SequenceWindow {
+    highest: u64,
+    seen: u64,
+}
+
+impl SequenceWindow {
+    /// Record `sequence`, false if it was seen before or is too old to tell
+    fn accept(&mut self, sequence: u64) -> bool {
+        if sequence > self.highest {
+            let shift = sequence - self.highest;
+            self.seen = if shift >= SEQUENCE_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.highest = sequence;
+            return true;
+        }
+
+        let offset = self.highest - sequence;
+        if offset >= SEQUENCE_WINDOW {
+            return false;
+        }
+        let bit = 1 << offset;
+        if self.seen & bit != 0 {
+            return false;
+        }
+        self.seen |= bit;
+        true
+    }
+}
+
+#[derive(Debug)]
+enum Outcome {
+    /// The first attempt is still running
+    Pending,
+    /// Encoded response of the first attempt
+    Done(Vec<u8>),
+}
+
+/// How a received call may proceed
+#[derive(Debug)]
+pub enum Admission<'a> {
+    /// Apply the call, recording its response through the ticket if it
+    /// carried an idempotency key
+    Fresh(Option<IdempotencyTicket<'a>>),
+    /// A retry of an operation already applied, with its encoded response
+    Replayed(Vec<u8>),
+}
+
+/// Rejects replayed stamps and answers retries from recorded responses
+#[derive(Debug)]
+pub struct ReplayGuard {
+    max_age: Duration,
+    idempotency_ttl: Duration,
+    allow_unstamped: bool,
+    senders: Mutex<HashMap<String, SequenceWindow>>,
+    outcomes: Mutex<HashMap<(String, String), (Instant, Outcome)>>,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_AGE, DEFAULT_IDEMPOTENCY_TTL)
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl ReplayGuard {
+    pub fn new(max_age: Duration, idempotency_ttl: Duration) -> Self {
+        Self {
+            max_age,
+            idempotency_ttl,
+            allow_unstamped: false,
+            senders: Mutex::new(HashMap::new()),
+            outcomes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Accept calls without a stamp from nodes predating replay protection,
+    /// e.g. during a rolling upgrade
+    pub fn allow_unstamped(mut self, allow: bool) -> Self {
+        self.allow_unstamped = allow;
+        self
+    }
+
+    /// Check the stamp of a `method` call
+    pub fn admit(&self, method: &str, metadata: &MetadataMap) -> Result<Admission<'_>, Status> {
+        let Some(stamp) = RpcStamp::from_metadata(metadata)? else {
+            if self.allow_unstamped {
+                tracing::debug!("Accepting unstamped {} call", method);
+                return Ok(Admission::Fresh(None));
+            }
+            return Err(Status::failed_precondition(format!(
+                "{} requires a replay protection stamp",
+                method
+            )));
+        };
+
+        let age = now_millis().saturating_sub(stamp.issued_at_ms);
+        if age > self.max_age.as_millis() as u64 {
+            return Err(Status::failed_precondition(format!(
+                "Stale {} call from {} issued {}s ago",
+                method,
+                stamp.sender,
+                age / 1000
+            )));
+        }
+
+        let accepted = self
+            .senders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(stamp.sender.clone())
+            .or_default()
+            .accept(stamp.sequence);
+        if !accepted {
+            tracing::warn!(
+                "Rejected replayed {} call from {} (sequence {})",
+                method,
+                stamp.sender,
+                stamp.sequence
+            );
+            return Err(Status::already_exists(format!(
+                "Replayed {} call from {}",
+                method, stamp.sender
+            )));
+        }
+
+        let Some(key) = stamp.idempotency_key else {
+            return Ok(Admission::Fresh(None));
+        };
+        let key = (method.to_string(), key);
+
+        let mut outcomes = self.outcomes.lock().unwrap_or_else(|e| e.into_inner());
+        let ttl = self.idempotency_ttl;
+        outcomes
+            .retain(|_, (at, outcome)| matches!(outcome, Outcome::Pending) || at.elapsed() < ttl);
+        match outcomes.get(&key) {
+            Some((_, Outcome::Done(response))) => {
+                tracing::debug!("Answering retried {} call {} from record", method, key.1);
+                Ok(Admission::Replayed(response.clone()))
+            }
+            Some((_, Outcome::Pending)) => Err(Status::aborted(format!(
+                "{} call {} is still being applied",
+                method, key.1
+            ))),
+            None => {
+                outcomes.insert(key.clone(), (Instant::now(), Outcome::Pending));
+                Ok(Admission::Fresh(Some(IdempotencyTicket {
+                    guard: self,
+                    key: Some(key),
+                })))
+            }
+        }
+    }
+}
+
+/// Claim on an idempotency key while its first attempt runs. Dropping it
+/// without [`complete`](Self::complete), e.g. when the call fails, frees
+/// the key for a retry.
+#[derive(Debug)]
+pub struct IdempotencyTicket<'a> {
+    guard: &'a ReplayGuard,
+    key: Option<(String, String)>,
+}
+
+impl IdempotencyTicket<'_> {
+    /// Record `response` as the answer to retries of the operation
+    pub fn complete<M: Message>(mut self, response: &M) {
+        if let Some(key) = self.key.take() {
+            self.guard
+                .outcomes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(
+                    key,
+                    (Instant::now(), Outcome::Done(response.encode_to_vec())),
+                );
+        }
+    }
+}
+
+impl Drop for IdempotencyTicket<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.guard
+                .outcomes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(stamp: &RpcStamp) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        stamp.apply(&mut metadata);
+        metadata
+    }
+
+    #[test]
+    fn test_sequence_window() {
+        let mut window = SequenceWindow::default();
+        assert!(window.accept(10));
+        assert!(window.accept(8));
+        assert!(!window.accept(8));
+        assert!(!window.accept(10));
+        assert!(window.accept(100));
+        // Too far below the highest to know whether it was seen
+        assert!(!window.accept(30));
+        assert!(window.accept(99));
+    }
+
+    #[test]
+    fn test_stamp_roundtrip() {
+        let stamp = RpcStamp::new(&NodeId::new(), Some("join-1".to_string()));
+        let next = RpcStamp::new(&NodeId::new(), None);
+        assert!(next.sequence > stamp.sequence);
+
+        let parsed = RpcStamp::from_metadata(&metadata(&stamp)).unwrap();
+        assert_eq!(parsed, Some(stamp));
+        assert_eq!(RpcStamp::from_metadata(&MetadataMap::new()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_replays_rejected_and_retries_answered() {
+        let guard = ReplayGuard::default();
+        let node = NodeId::new();
+        let response = crate::communication::cluster::LeaveClusterResponse {
+            success: true,
+            message: "left".to_string(),
+        };
+
+        let first = RpcStamp::new(&node, Some("leave".to_string()));
+        match guard.admit("leave", &metadata(&first)).unwrap() {
+            Admission::Fresh(Some(ticket)) => {
+                // A retry while the first attempt runs has to wait
+                let retry = RpcStamp::new(&node, Some("leave".to_string()));
+                assert_eq!(
+                    guard.admit("leave", &metadata(&retry)).unwrap_err().code(),
+                    tonic::Code::Aborted
+                );
+                ticket.complete(&response);
+            }
+            other => panic!("unexpected admission {:?}", other),
+        }
+
+        // The same stamp again is a replay
+        assert_eq!(
+            guard.admit("leave", &metadata(&first)).unwrap_err().code(),
+            tonic::Code::AlreadyExists
+        );
+
+        // A retry gets the recorded response
+        let retry = RpcStamp::new(&node, Some("leave".to_string()));
+        match guard.admit("leave", &metadata(&retry)).unwrap() {
+            Admission::Replayed(bytes) => assert_eq!(
+                crate::communication::cluster::LeaveClusterResponse::decode(&bytes[..]).unwrap(),
+                response
+            ),
+            other => panic!("unexpected admission {:?}", other),
+        }
+
+        // Stale and unstamped calls are refused
+        let mut stale = RpcStamp::new(&node, None);
+        stale.issued_at_ms -= DEFAULT_MAX_AGE.as_millis() as u64 + 1000;
+        assert!(guard.admit("leave", &metadata(&stale)).is_err());
+        assert!(guard.admit("leave", &MetadataMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_failed_attempt_frees_key() {
+        let guard = ReplayGuard::default();
+        let node = NodeId::new();
+
+        let first = RpcStamp::new(&node, Some("join".to_string()));
+        let admission = guard.admit("join", &metadata(&first)).unwrap();
+        drop(admission);
+
+        let retry = RpcStamp::new(&node, Some("join".to_string()));
+        assert!(matches!(
+            guard.admit("join", &metadata(&retry)).unwrap(),
+            Admission::Fresh(Some(_))
+        ));
+    }
+}
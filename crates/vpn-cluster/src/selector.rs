@@ -0,0 +1,324 @@
+//! Node label selectors and per-node configuration
+//!
+//! A configuration entry can be pinned to the nodes matching a
+//! [`LabelSelector`], e.g. an SNI pool that only `region=eu` nodes should
+//! serve. Pinned entries are replicated to every node like any other value,
+//! wrapped in a [`PinnedConfig`]; each node resolves the envelope against
+//! its own labels, so heterogeneous nodes are configured from one place.
+
+use crate::error::{ClusterError, Result};
+use crate::node::Placement;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Label every node carries with its name
+pub const NAME_LABEL: &str = "name";
+
+/// Label carrying the node's region, when known
+pub const REGION_LABEL: &str = "region";
+
+/// Label carrying the node's availability zone, when known
+pub const ZONE_LABEL: &str = "zone";
+
+/// One term of a selector
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn parse(term: &str) -> Result<Self> {
+        let requirement = if let Some((key, value)) = term.split_once("!=") {
+            Self::NotEquals(key.trim().to_string(), value.trim().to_string())
+        } else if let Some((key, value)) = term.split_once('=') {
+            Self::Equals(key.trim().to_string(), value.trim().to_string())
+        } else if let Some(key) = term.strip_prefix('!') {
+            Self::NotExists(key.trim().to_string())
+        } else {
+            Self::Exists(term.to_string())
+        };
+
+        if requirement.key().is_empty() || requirement.key().contains(['=', '!', ',']) {
+            return Err(ClusterError::configuration(format!(
+                "Invalid label selector term '{}'",
+                term
+            )));
+        }
+        Ok(requirement)
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            Self::Equals(key, _)
+            | Self::NotEquals(key, _)
+            | Self::Exists(key)
+            | Self::NotExists(key) => key,
+        }
+    }
+
+    fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        match self {
+            Self::Equals(key, value) => labels.get(key) == Some(value),
+            Self::NotEquals(key, value) => labels.get(key) != Some(value),
+            Self::Exists(key) => labels.contains_key(key),
+            Self::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Equals(key, value) => write!(f, "{}={}", key, value),
+            Self::NotEquals(key, value) => write!(f, "{}!={}", key, value),
+            Self::Exists(key) => write!(f, "{}", key),
+            Self::NotExists(key) => write!(f, "!{}", key),
+        }
+    }
+}
+
+/// Comma-separated label requirements a node must all meet, e.g.
+/// `region=eu,tier!=canary,gpu`. Terms are `key=value`, `key!=value`,
+/// `key` (label present) and `!key` (label absent). The empty selector
+/// matches every node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+impl LabelSelector {
+    /// Selector matching every node
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(selector: &str) -> Result<Self> {
+        let requirements = selector
+            .split(',')
+            .map(str::trim)
+            .filter(|term| !term.is_empty())
+            .map(Requirement::parse)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { requirements })
+    }
+
+    /// Whether the selector matches every node
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+
+    /// Whether a node with `labels` meets every requirement
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement.matches(labels))
+    }
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms: Vec<String> = self.requirements.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", terms.join(","))
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = ClusterError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<String> for LabelSelector {
+    type Error = ClusterError;
+
+    fn try_from(s: String) -> Result<Self> {
+        Self::parse(&s)
+    }
+}
+
+impl From<LabelSelector> for String {
+    fn from(selector: LabelSelector) -> Self {
+        selector.to_string()
+    }
+}
+
+/// Value a config entry takes on the nodes matching `selector`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigTarget {
+    pub selector: LabelSelector,
+    pub value: Value,
+}
+
+/// Config entry whose value depends on the node reading it. The first
+/// target whose selector matches the node wins; nodes matching none get
+/// `default`, or no value at all when it is unset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinnedConfig {
+    #[serde(rename = "_targets")]
+    pub targets: Vec<ConfigTarget>,
+    #[serde(rename = "_default", default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+}
+
+impl PinnedConfig {
+    /// Pinned entry held in `raw`, or `None` for a plain value
+    pub fn decode(raw: &Value) -> Option<Self> {
+        serde_json::from_value(raw.clone()).ok()
+    }
+
+    /// Envelope to store; a plain value when nothing is pinned
+    pub fn encode(&self) -> Option<Value> {
+        if self.targets.is_empty() {
+            return self.default.clone();
+        }
+        Some(serde_json::json!({
+            "_targets": self.targets,
+            "_default": self.default,
+        }))
+    }
+
+    /// Pin `value` to `selector`, replacing what the same selector had
+    pub fn pin(&mut self, selector: LabelSelector, value: Value) {
+        match self.targets.iter_mut().find(|t| t.selector == selector) {
+            Some(target) => target.value = value,
+            None => self.targets.push(ConfigTarget { selector, value }),
+        }
+    }
+
+    /// Drop the value pinned to `selector`, returning whether there was one
+    pub fn unpin(&mut self, selector: &LabelSelector) -> bool {
+        let before = self.targets.len();
+        self.targets.retain(|t| &t.selector != selector);
+        self.targets.len() != before
+    }
+
+    /// Value for a node with `labels`
+    pub fn resolve(&self, labels: &HashMap<String, String>) -> Option<&Value> {
+        self.targets
+            .iter()
+            .find(|t| t.selector.matches(labels))
+            .map(|t| &t.value)
+            .or(self.default.as_ref())
+    }
+}
+
+/// Labels of a node with `metadata`, `name` and `placement`; the built-in
+/// name, region and zone labels take precedence over metadata
+pub fn node_labels(
+    metadata: &HashMap<String, String>,
+    name: &str,
+    placement: &Placement,
+) -> HashMap<String, String> {
+    let mut labels = metadata.clone();
+    labels.insert(NAME_LABEL.to_string(), name.to_string());
+    if let Some(region) = &placement.region {
+        labels.insert(REGION_LABEL.to_string(), region.clone());
+    }
+    if let Some(zone) = &placement.zone {
+        labels.insert(ZONE_LABEL.to_string(), zone.clone());
+    }
+    labels
+}
+
+/// Value of a stored config entry for a node with `labels`. Plain values
+/// apply to every node.
+pub fn resolve_config(raw: Value, labels: &HashMap<String, String>) -> Option<Value> {
+    match PinnedConfig::decode(&raw) {
+        Some(pinned) => pinned.resolve(labels).cloned(),
+        None => Some(raw),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_selector_parse_and_match() {
+        let selector = LabelSelector::parse("region=eu, tier!=canary,gpu,!draining").unwrap();
+        assert_eq!(selector.to_string(), "region=eu,tier!=canary,gpu,!draining");
+
+        assert!(selector.matches(&labels(&[("region", "eu"), ("gpu", "a100")])));
+        assert!(!selector.matches(&labels(&[("region", "us"), ("gpu", "a100")])));
+        assert!(!selector.matches(&labels(&[
+            ("region", "eu"),
+            ("gpu", "a100"),
+            ("tier", "canary")
+        ])));
+        assert!(!selector.matches(&labels(&[("region", "eu")])));
+        assert!(!selector.matches(&labels(&[
+            ("region", "eu"),
+            ("gpu", "a100"),
+            ("draining", "")
+        ])));
+
+        assert!(LabelSelector::parse("").unwrap().matches(&labels(&[])));
+        assert!(LabelSelector::parse("=eu").is_err());
+        assert!(LabelSelector::parse("!").is_err());
+
+        let json = serde_json::to_value(&selector).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!("region=eu,tier!=canary,gpu,!draining")
+        );
+        assert_eq!(
+            serde_json::from_value::<LabelSelector>(json).unwrap(),
+            selector
+        );
+    }
+
+    #[test]
+    fn test_pinned_config_resolution() {
+        let eu = LabelSelector::parse("region=eu").unwrap();
+        let mut pinned = PinnedConfig {
+            default: Some(serde_json::json!(["global.example.com"])),
+            ..Default::default()
+        };
+        pinned.pin(eu.clone(), serde_json::json!(["eu.example.com"]));
+        let raw = pinned.encode().unwrap();
+
+        assert_eq!(
+            resolve_config(raw.clone(), &labels(&[("region", "eu")])),
+            Some(serde_json::json!(["eu.example.com"]))
+        );
+        assert_eq!(
+            resolve_config(raw.clone(), &labels(&[("region", "us")])),
+            Some(serde_json::json!(["global.example.com"]))
+        );
+        assert_eq!(
+            resolve_config(serde_json::json!(443), &labels(&[])),
+            Some(serde_json::json!(443))
+        );
+
+        // Without a default, unmatched nodes get nothing
+        let mut pinned = PinnedConfig::decode(&raw).unwrap();
+        pinned.default = None;
+        assert_eq!(pinned.resolve(&labels(&[("region", "us")])), None);
+
+        // Unpinning the last target leaves the plain default
+        let mut pinned = PinnedConfig::decode(&raw).unwrap();
+        assert!(pinned.unpin(&eu));
+        assert!(!pinned.unpin(&eu));
+        assert_eq!(
+            pinned.encode(),
+            Some(serde_json::json!(["global.example.com"]))
+        );
+    }
+}
@@ -0,0 +1,1197 @@
+//! Deterministic simulation of the cluster protocols
+//!
+//! Raft elections and gossip dissemination run here against a virtual clock
+//! and an in-memory network instead of timers and gRPC. Latency, reordering,
+//! loss, duplication, partitions and election timeouts are all drawn from
+//! one seeded RNG, so a run is a function of its seed alone: a seed that
+//! breaks an invariant replays the same schedule every time it is run.
+//! Tests sweep a few hundred seeds; [`run_raft`] and [`run_gossip`] take any
+//! range for longer soaks.
+//!
+//! The Raft model covers pre-vote, elections, heartbeats and leader-side
+//! commit tracking. Followers keep no replicated entries (the engine has no
+//! follower append path), so a heartbeat acknowledges the leader's last
+//! index directly.
+//!
+//! Built for tests, and for other crates with the `simulation` feature.
+
+use crate::communication::cluster::GossipBatch;
+use crate::communication::ClusterGrpcClient;
+use crate::config::GossipConfig;
+use crate::consensus::{ConsensusEngine, RaftConsensus};
+use crate::error::{ClusterError, Result};
+use crate::gossip::{encode_batches, GossipManager, GossipMessage};
+use crate::leader_election::{ElectionConfig, VoteRequest, VoteResponse};
+use crate::node::NodeId;
+use crate::state::ClusterState;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Behaviour of the links in a [`SimNetwork`]
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    /// Shortest delivery delay
+    pub min_latency: Duration,
+
+    /// Longest delivery delay; messages sent close together may overtake
+    /// each other within the range
+    pub max_latency: Duration,
+
+    /// Chance a message is lost
+    pub drop_rate: f64,
+
+    /// Chance a message is delivered twice
+    pub duplicate_rate: f64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(20),
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Links that lose, duplicate and reorder messages
+    pub fn lossy() -> Self {
+        Self {
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(40),
+            drop_rate: 0.05,
+            duplicate_rate: 0.02,
+        }
+    }
+}
+
+/// What the network did with the messages it was given
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkStats {
+    pub sent: u64,
+    pub delivered: u64,
+    /// Lost to loss or to a cut link
+    pub dropped: u64,
+    pub duplicated: u64,
+}
+
+struct InFlight<M> {
+    deliver_at: Duration,
+    /// Send order, breaking ties between messages arriving together
+    seq: u64,
+    from: usize,
+    to: usize,
+    message: M,
+}
+
+impl<M> PartialEq for InFlight<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<M> Eq for InFlight<M> {}
+
+impl<M> PartialOrd for InFlight<M> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<M> Ord for InFlight<M> {
+    // Reversed so the heap pops the earliest arrival first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .deliver_at
+            .cmp(&self.deliver_at)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// In-memory network between nodes identified by their index, running on
+/// a virtual clock
+pub struct SimNetwork<M> {
+    config: NetworkConfig,
+    rng: StdRng,
+    now: Duration,
+    next_seq: u64,
+    in_flight: BinaryHeap<InFlight<M>>,
+    /// Directed links that are cut
+    cut: HashSet<(usize, usize)>,
+    /// Loss and duplication only apply while faults are enabled
+    faulty: bool,
+    stats: NetworkStats,
+}
+
+impl<M: Clone> SimNetwork<M> {
+    pub fn new(config: NetworkConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            now: Duration::ZERO,
+            next_seq: 0,
+            in_flight: BinaryHeap::new(),
+            cut: HashSet::new(),
+            faulty: true,
+            stats: NetworkStats::default(),
+        }
+    }
+
+    /// Current virtual time
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn stats(&self) -> &NetworkStats {
+        &self.stats
+    }
+
+    /// Enable or disable loss and duplication; latency always applies
+    pub fn set_faulty(&mut self, faulty: bool) {
+        self.faulty = faulty;
+    }
+
+    /// Send `message`, returning false if it was lost on the way out. A
+    /// message whose link is cut by the time it arrives is lost as well.
+    pub fn send(&mut self, from: usize, to: usize, message: M) -> bool {
+        self.stats.sent += 1;
+
+        let lost = self.faulty && self.rng.gen_bool(self.config.drop_rate);
+        if lost || !self.is_connected(from, to) {
+            self.stats.dropped += 1;
+            return false;
+        }
+
+        if self.faulty && self.rng.gen_bool(self.config.duplicate_rate) {
+            self.stats.duplicated += 1;
+            self.schedule(from, to, message.clone());
+        }
+        self.schedule(from, to, message);
+        true
+    }
+
+    fn schedule(&mut self, from: usize, to: usize, message: M) {
+        let min = self.config.min_latency.as_micros() as u64;
+        let max = (self.config.max_latency.as_micros() as u64).max(min);
+        let latency = Duration::from_micros(self.rng.gen_range(min..=max));
+
+        self.in_flight.push(InFlight {
+            deliver_at: self.now + latency,
+            seq: self.next_seq,
+            from,
+            to,
+            message,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Whether messages from `from` currently reach `to`
+    pub fn is_connected(&self, from: usize, to: usize) -> bool {
+        !self.cut.contains(&(from, to))
+    }
+
+    /// Split the nodes into groups that cannot reach each other, replacing
+    /// any earlier partition
+    pub fn partition(&mut self, groups: &[Vec<usize>]) {
+        self.cut.clear();
+        for (i, group) in groups.iter().enumerate() {
+            for other in groups.iter().skip(i + 1) {
+                for &a in group {
+                    for &b in other {
+                        self.cut.insert((a, b));
+                        self.cut.insert((b, a));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Cut `node` off from the other `nodes - 1` nodes
+    pub fn isolate(&mut self, node: usize, nodes: usize) {
+        for other in (0..nodes).filter(|&other| other != node) {
+            self.cut.insert((node, other));
+            self.cut.insert((other, node));
+        }
+    }
+
+    /// Restore every link
+    pub fn heal(&mut self) {
+        self.cut.clear();
+    }
+
+    /// Move the clock forward to `time`; it never goes back
+    pub fn advance_to(&mut self, time: Duration) {
+        self.now = self.now.max(time);
+    }
+
+    /// Deliver the earliest message arriving by `until`, moving the clock to
+    /// its arrival. Returns the sender, the receiver and the message.
+    pub fn deliver_next(&mut self, until: Duration) -> Option<(usize, usize, M)> {
+        loop {
+            if self.in_flight.peek()?.deliver_at > until {
+                return None;
+            }
+
+            let in_flight = self.in_flight.pop()?;
+            self.advance_to(in_flight.deliver_at);
+            if !self.is_connected(in_flight.from, in_flight.to) {
+                self.stats.dropped += 1;
+                continue;
+            }
+
+            self.stats.delivered += 1;
+            return Some((in_flight.from, in_flight.to, in_flight.message));
+        }
+    }
+}
+
+/// Rearrange the links of `network` at random: heal it, split it in two,
+/// cut off `target` (or a random node), or leave it alone
+fn inject_fault<M: Clone>(
+    network: &mut SimNetwork<M>,
+    rng: &mut StdRng,
+    nodes: usize,
+    target: Option<usize>,
+) {
+    match rng.gen_range(0..4) {
+        0 => network.heal(),
+        1 => {
+            let mut order: Vec<usize> = (0..nodes).collect();
+            order.shuffle(rng);
+            let (left, right) = order.split_at(rng.gen_range(1..nodes));
+            network.partition(&[left.to_vec(), right.to_vec()]);
+        }
+        2 => {
+            let node = target.unwrap_or_else(|| rng.gen_range(0..nodes));
+            network.heal();
+            network.isolate(node, nodes);
+        }
+        _ => {}
+    }
+}
+
+/// Node IDs drawn from the simulation RNG, so logs of a replayed seed match
+fn node_ids(rng: &mut StdRng, nodes: usize) -> Vec<NodeId> {
+    (0..nodes)
+        .map(|_| NodeId::from_uuid(Uuid::from_u128(rng.gen())))
+        .collect()
+}
+
+/// Settings for a randomized Raft run
+#[derive(Debug, Clone)]
+pub struct RaftSimConfig {
+    /// Voters in the cluster, at least two
+    pub nodes: usize,
+
+    pub network: NetworkConfig,
+
+    /// Run a pre-vote before each election
+    pub pre_vote: bool,
+
+    /// Election timeouts are drawn between these bounds
+    pub min_election_timeout: Duration,
+    pub max_election_timeout: Duration,
+
+    pub heartbeat_interval: Duration,
+
+    /// Chance the leader proposes an entry with each heartbeat
+    pub proposal_rate: f64,
+
+    /// How often the network is rearranged while faults are injected
+    pub fault_interval: Duration,
+
+    /// Virtual time spent injecting faults
+    pub chaos_duration: Duration,
+
+    /// Fault-free time afterwards, by the end of which every node must
+    /// follow the same leader
+    pub settle_duration: Duration,
+}
+
+impl Default for RaftSimConfig {
+    fn default() -> Self {
+        Self {
+            nodes: 5,
+            network: NetworkConfig::lossy(),
+            pre_vote: true,
+            min_election_timeout: Duration::from_millis(150),
+            max_election_timeout: Duration::from_millis(300),
+            heartbeat_interval: Duration::from_millis(50),
+            proposal_rate: 0.3,
+            fault_interval: Duration::from_millis(500),
+            chaos_duration: Duration::from_secs(5),
+            settle_duration: Duration::from_secs(3),
+        }
+    }
+}
+
+/// Messages exchanged by simulated Raft nodes
+#[derive(Debug, Clone)]
+pub enum RaftMessage {
+    Vote(VoteRequest),
+    VoteReply(VoteResponse),
+    /// Heartbeat carrying the leader's last log index
+    Append {
+        term: u64,
+        last_index: u64,
+    },
+    AppendReply {
+        term: u64,
+        match_index: u64,
+    },
+}
+
+/// Outcome of one Raft run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaftSimReport {
+    pub seed: u64,
+    /// Terms that had a leader
+    pub elections: usize,
+    pub highest_term: u64,
+    pub highest_commit: u64,
+    pub network: NetworkStats,
+    /// Broken invariants; the run stops at the first one
+    pub violations: Vec<String>,
+}
+
+impl RaftSimReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+struct RaftSimNode {
+    id: NodeId,
+    engine: RaftConsensus,
+    election_deadline: Duration,
+    next_heartbeat: Duration,
+    /// Last observed state, to check it only moves forward
+    is_leader: bool,
+    term: u64,
+    commit_index: u64,
+}
+
+/// One seeded Raft run
+///
+/// Checks after every event that no term has two leaders and that terms
+/// and commit indexes never go back, and at the end that a single leader
+/// emerged once the faults stopped.
+pub struct RaftSimulation {
+    seed: u64,
+    config: RaftSimConfig,
+    rng: StdRng,
+    network: SimNetwork<RaftMessage>,
+    nodes: Vec<RaftSimNode>,
+    /// Leader of each term that had one
+    leaders: HashMap<u64, usize>,
+    next_fault: Duration,
+    violations: Vec<String>,
+}
+
+impl RaftSimulation {
+    pub async fn new(config: RaftSimConfig, seed: u64) -> Result<Self> {
+        if config.nodes < 2 {
+            return Err(ClusterError::configuration(
+                "Raft simulation needs at least two nodes",
+            ));
+        }
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let network = SimNetwork::new(config.network.clone(), rng.gen());
+        let ids = node_ids(&mut rng, config.nodes);
+
+        // Leases run on the wall clock, which the simulation does not
+        // control; without them every vote goes through the log checks
+        let election_config = ElectionConfig {
+            pre_vote: config.pre_vote,
+            lease_duration: Duration::ZERO,
+        };
+
+        let mut simulation = Self {
+            seed,
+            network,
+            nodes: Vec::with_capacity(ids.len()),
+            leaders: HashMap::new(),
+            next_fault: config.fault_interval,
+            violations: Vec::new(),
+            rng,
+            config,
+        };
+
+        for id in &ids {
+            let engine =
+                RaftConsensus::with_election_config(id.clone(), election_config.clone()).await?;
+            engine.bootstrap_voters(ids.iter().cloned()).await;
+            let election_deadline = simulation.election_timeout();
+            simulation.nodes.push(RaftSimNode {
+                id: id.clone(),
+                engine,
+                election_deadline,
+                next_heartbeat: Duration::ZERO,
+                is_leader: false,
+                term: 0,
+                commit_index: 0,
+            });
+        }
+
+        Ok(simulation)
+    }
+
+    fn election_timeout(&mut self) -> Duration {
+        let min = self.config.min_election_timeout.as_millis() as u64;
+        let max = (self.config.max_election_timeout.as_millis() as u64).max(min);
+        Duration::from_millis(self.rng.gen_range(min..=max))
+    }
+
+    fn violation(&mut self, message: String) {
+        let now = self.network.now();
+        self.violations
+            .push(format!("seed {} at {:?}: {}", self.seed, now, message));
+    }
+
+    /// Run the schedule to the end
+    pub async fn run(mut self) -> RaftSimReport {
+        let chaos_end = self.config.chaos_duration;
+        let end = chaos_end + self.config.settle_duration;
+        let mut settled = false;
+
+        while self.violations.is_empty() {
+            let (timer_at, node) = self.next_timer();
+            let mut horizon = timer_at.min(end);
+            if !settled {
+                horizon = horizon.min(self.next_fault).min(chaos_end);
+            }
+
+            if let Some((from, to, message)) = self.network.deliver_next(horizon) {
+                self.handle_message(from, to, message).await;
+            } else {
+                self.network.advance_to(horizon);
+                if horizon >= end {
+                    break;
+                }
+
+                if !settled && horizon >= chaos_end {
+                    self.network.heal();
+                    self.network.set_faulty(false);
+                    settled = true;
+                } else if !settled && horizon >= self.next_fault {
+                    let leader = self.nodes.iter().position(|node| node.is_leader);
+                    inject_fault(&mut self.network, &mut self.rng, self.nodes.len(), leader);
+                    self.next_fault += self.config.fault_interval;
+                } else {
+                    self.fire_timer(node).await;
+                }
+            }
+
+            self.observe().await;
+        }
+
+        if self.violations.is_empty() {
+            self.check_settled().await;
+        }
+        self.report()
+    }
+
+    /// Earliest heartbeat or election timer, with its node
+    fn next_timer(&self) -> (Duration, usize) {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let at = if node.is_leader {
+                    node.next_heartbeat
+                } else {
+                    node.election_deadline
+                };
+                (at, index)
+            })
+            .min()
+            .expect("simulation has nodes")
+    }
+
+    async fn fire_timer(&mut self, index: usize) {
+        let now = self.network.now();
+        if self.nodes[index].engine.is_leader().await {
+            self.nodes[index].next_heartbeat = now + self.config.heartbeat_interval;
+            self.send_heartbeats(index).await;
+            return;
+        }
+
+        self.nodes[index].election_deadline = now + self.election_timeout();
+        let engine = &self.nodes[index].engine;
+        let request = if self.config.pre_vote {
+            engine.start_pre_vote().await
+        } else {
+            engine.start_election().await
+        };
+        // A refused campaign simply waits for the next timeout
+        if let Ok(request) = request {
+            self.broadcast(index, RaftMessage::Vote(request));
+        }
+    }
+
+    async fn send_heartbeats(&mut self, index: usize) {
+        if self.rng.gen_bool(self.config.proposal_rate) {
+            let data = self.rng.gen::<u64>().to_be_bytes().to_vec();
+            let proposed = self.nodes[index].engine.propose(data).await;
+            if let Err(e) = proposed {
+                self.violation(format!("leader {} failed to propose: {}", index, e));
+            }
+        }
+
+        let metrics = self.nodes[index].engine.get_metrics().await;
+        self.broadcast(
+            index,
+            RaftMessage::Append {
+                term: metrics.current_term,
+                last_index: metrics.last_log_index,
+            },
+        );
+    }
+
+    fn broadcast(&mut self, from: usize, message: RaftMessage) {
+        for to in (0..self.nodes.len()).filter(|&to| to != from) {
+            self.network.send(from, to, message.clone());
+        }
+    }
+
+    async fn handle_message(&mut self, from: usize, to: usize, message: RaftMessage) {
+        let from_id = self.nodes[from].id.clone();
+        let now = self.network.now();
+
+        match message {
+            RaftMessage::Vote(request) => {
+                let response = self.nodes[to].engine.handle_vote_request(request).await;
+                if response.vote_granted && !response.pre_vote {
+                    self.nodes[to].election_deadline = now + self.election_timeout();
+                }
+                self.network
+                    .send(to, from, RaftMessage::VoteReply(response));
+            }
+            RaftMessage::VoteReply(response) => {
+                let pre_vote = response.pre_vote;
+                let counted = self.nodes[to]
+                    .engine
+                    .handle_vote_response(from_id, response)
+                    .await;
+                match counted {
+                    Ok(true) if pre_vote => {
+                        if let Ok(request) = self.nodes[to].engine.start_election().await {
+                            self.broadcast(to, RaftMessage::Vote(request));
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => self.violation(format!("node {} failed to count a vote: {}", to, e)),
+                }
+            }
+            RaftMessage::Append { term, last_index } => {
+                // Stale leaders get no answer and learn of the new term from
+                // the current leader's heartbeats
+                if self.nodes[to]
+                    .engine
+                    .handle_heartbeat(from_id, term)
+                    .await
+                    .is_ok()
+                {
+                    self.nodes[to].election_deadline = now + self.election_timeout();
+                    self.network.send(
+                        to,
+                        from,
+                        RaftMessage::AppendReply {
+                            term,
+                            match_index: last_index,
+                        },
+                    );
+                }
+            }
+            RaftMessage::AppendReply { term, match_index } => {
+                let engine = &self.nodes[to].engine;
+                if !engine.is_leader().await || engine.get_term().await != term {
+                    return;
+                }
+                let handled = engine.handle_append_response(from_id, match_index).await;
+                if let Err(e) = handled {
+                    self.violation(format!("leader {} rejected an append reply: {}", to, e));
+                }
+            }
+        }
+    }
+
+    /// Check the safety invariants against every node's current state
+    async fn observe(&mut self) {
+        let now = self.network.now();
+
+        for index in 0..self.nodes.len() {
+            let metrics = self.nodes[index].engine.get_metrics().await;
+            let (term, commit_index, was_leader) = {
+                let node = &self.nodes[index];
+                (node.term, node.commit_index, node.is_leader)
+            };
+
+            if metrics.current_term < term {
+                self.violation(format!(
+                    "node {} went back from term {} to {}",
+                    index, term, metrics.current_term
+                ));
+            }
+            if metrics.commit_index < commit_index {
+                self.violation(format!(
+                    "node {} commit index went back from {} to {}",
+                    index, commit_index, metrics.commit_index
+                ));
+            }
+            if metrics.commit_index > metrics.last_log_index {
+                self.violation(format!(
+                    "node {} committed index {} beyond its last entry {}",
+                    index, metrics.commit_index, metrics.last_log_index
+                ));
+            }
+
+            if metrics.is_leader {
+                match self.leaders.get(&metrics.current_term).copied() {
+                    Some(leader) if leader != index => self.violation(format!(
+                        "nodes {} and {} both lead term {}",
+                        leader, index, metrics.current_term
+                    )),
+                    Some(_) => {}
+                    None => {
+                        self.leaders.insert(metrics.current_term, index);
+                    }
+                }
+                if !was_leader {
+                    self.nodes[index].next_heartbeat = now;
+                }
+            } else if was_leader {
+                // A deposed leader waits a full timeout before campaigning
+                self.nodes[index].election_deadline = now + self.election_timeout();
+            }
+
+            let node = &mut self.nodes[index];
+            node.is_leader = metrics.is_leader;
+            node.term = metrics.current_term;
+            node.commit_index = metrics.commit_index;
+        }
+    }
+
+    /// Liveness: once the network has been fault-free for the settle
+    /// period, exactly one leader is in charge and every node follows it
+    async fn check_settled(&mut self) {
+        let leaders: Vec<usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.is_leader)
+            .map(|(index, _)| index)
+            .collect();
+
+        let &[leader] = leaders.as_slice() else {
+            self.violation(format!(
+                "{} leaders after settling: {:?}",
+                leaders.len(),
+                leaders
+            ));
+            return;
+        };
+
+        let leader_id = self.nodes[leader].id.clone();
+        for index in (0..self.nodes.len()).filter(|&index| index != leader) {
+            let follows = self.nodes[index].engine.get_leader().await;
+            if follows.as_ref() != Some(&leader_id) {
+                self.violation(format!(
+                    "node {} follows {:?} instead of node {}",
+                    index, follows, leader
+                ));
+            }
+        }
+    }
+
+    fn report(self) -> RaftSimReport {
+        RaftSimReport {
+            seed: self.seed,
+            elections: self.leaders.len(),
+            highest_term: self.nodes.iter().map(|node| node.term).max().unwrap_or(0),
+            highest_commit: self
+                .nodes
+                .iter()
+                .map(|node| node.commit_index)
+                .max()
+                .unwrap_or(0),
+            network: self.network.stats().clone(),
+            violations: self.violations,
+        }
+    }
+}
+
+/// Run the Raft simulation once per seed, returning the runs that broke an
+/// invariant
+pub async fn run_raft(config: &RaftSimConfig, seeds: Range<u64>) -> Result<Vec<RaftSimReport>> {
+    let mut failures = Vec::new();
+    for seed in seeds {
+        let report = RaftSimulation::new(config.clone(), seed).await?.run().await;
+        if !report.is_ok() {
+            failures.push(report);
+        }
+    }
+    Ok(failures)
+}
+
+/// Settings for a randomized gossip run
+#[derive(Debug, Clone)]
+pub struct GossipSimConfig {
+    /// Nodes in the cluster, at least two
+    pub nodes: usize,
+
+    /// Latencies should stay below `gossip.interval` so each round's
+    /// batches arrive before the next round
+    pub network: NetworkConfig,
+
+    pub gossip: GossipConfig,
+
+    /// Chance each node publishes a message per round
+    pub publish_rate: f64,
+
+    /// Chance the network is rearranged each round while faults are injected
+    pub fault_rate: f64,
+
+    /// Rounds with publishing and faults
+    pub chaos_rounds: usize,
+
+    /// Fault-free rounds afterwards; more than `gossip.max_hops` so relays
+    /// run out and every queue drains
+    pub settle_rounds: usize,
+}
+
+impl Default for GossipSimConfig {
+    fn default() -> Self {
+        let gossip = GossipConfig::default();
+        Self {
+            nodes: 7,
+            network: NetworkConfig::lossy(),
+            settle_rounds: gossip.max_hops as usize + 2,
+            gossip,
+            publish_rate: 0.2,
+            fault_rate: 0.1,
+            chaos_rounds: 30,
+        }
+    }
+}
+
+/// Outcome of one gossip run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipSimReport {
+    pub seed: u64,
+    pub published: usize,
+    /// Messages handed to subscribers across all nodes
+    pub deliveries: usize,
+    /// Message and node pairs that never met; partitions leave these to
+    /// state sync
+    pub missed: usize,
+    pub network: NetworkStats,
+    /// Broken invariants; the run stops at the first round that had one
+    pub violations: Vec<String>,
+}
+
+impl GossipSimReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+struct GossipSimNode {
+    id: NodeId,
+    manager: GossipManager,
+    deliveries: broadcast::Receiver<GossipMessage>,
+}
+
+/// One seeded gossip run
+///
+/// Each round every node sends its queue to `fanout` random peers the way
+/// [`GossipManager::flush`] does, without rate limiting. Checks that
+/// subscribers see each published message at most once, intact and never
+/// on its origin, and that all queues drain once the faults stop.
+pub struct GossipSimulation {
+    seed: u64,
+    config: GossipSimConfig,
+    rng: StdRng,
+    network: SimNetwork<GossipBatch>,
+    nodes: Vec<GossipSimNode>,
+    /// Origin and payload of every published message
+    published: HashMap<Uuid, (usize, serde_json::Value)>,
+    delivered: HashSet<(usize, Uuid)>,
+    violations: Vec<String>,
+}
+
+impl GossipSimulation {
+    pub fn new(config: GossipSimConfig, seed: u64) -> Result<Self> {
+        if config.nodes < 2 {
+            return Err(ClusterError::configuration(
+                "Gossip simulation needs at least two nodes",
+            ));
+        }
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let network = SimNetwork::new(config.network.clone(), rng.gen());
+        let nodes = node_ids(&mut rng, config.nodes)
+            .into_iter()
+            .map(|id| {
+                let state = Arc::new(RwLock::new(ClusterState::new(id.clone())));
+                let manager = GossipManager::new(
+                    id.clone(),
+                    config.gossip.clone(),
+                    state,
+                    ClusterGrpcClient::new(id.clone()),
+                );
+                let deliveries = manager.subscribe();
+                GossipSimNode {
+                    id,
+                    manager,
+                    deliveries,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            seed,
+            config,
+            rng,
+            network,
+            nodes,
+            published: HashMap::new(),
+            delivered: HashSet::new(),
+            violations: Vec::new(),
+        })
+    }
+
+    fn violation(&mut self, message: String) {
+        let now = self.network.now();
+        self.violations
+            .push(format!("seed {} at {:?}: {}", self.seed, now, message));
+    }
+
+    /// Run every round
+    pub async fn run(mut self) -> GossipSimReport {
+        let rounds = self.config.chaos_rounds + self.config.settle_rounds;
+
+        for round in 0..rounds {
+            if round < self.config.chaos_rounds {
+                if self.rng.gen_bool(self.config.fault_rate) {
+                    inject_fault(&mut self.network, &mut self.rng, self.nodes.len(), None);
+                }
+                self.publish().await;
+            } else if round == self.config.chaos_rounds {
+                self.network.heal();
+                self.network.set_faulty(false);
+            }
+
+            for index in 0..self.nodes.len() {
+                self.gossip_round(index).await;
+            }
+
+            let round_end = self.config.gossip.interval * (round as u32 + 1);
+            while let Some((_, to, batch)) = self.network.deliver_next(round_end) {
+                if let Err(e) = self.nodes[to].manager.receive(batch).await {
+                    self.violation(format!("node {} rejected a batch: {}", to, e));
+                }
+            }
+            self.network.advance_to(round_end);
+
+            self.collect_deliveries();
+            if !self.violations.is_empty() {
+                return self.report();
+            }
+        }
+
+        for index in 0..self.nodes.len() {
+            let queued = self.nodes[index].manager.pending_count().await;
+            if queued > 0 {
+                self.violation(format!(
+                    "node {} still has {} queued message(s) after settling",
+                    index, queued
+                ));
+            }
+        }
+        self.report()
+    }
+
+    async fn publish(&mut self) {
+        for index in 0..self.nodes.len() {
+            if !self.rng.gen_bool(self.config.publish_rate) {
+                continue;
+            }
+
+            let payload = serde_json::json!({ "node": index, "seq": self.published.len() });
+            match self.nodes[index]
+                .manager
+                .publish("simulation", payload.clone())
+                .await
+            {
+                Ok(id) => {
+                    self.published.insert(id, (index, payload));
+                }
+                Err(e) => self.violation(format!("node {} failed to publish: {}", index, e)),
+            }
+        }
+    }
+
+    /// Send the node's queue to random peers, dropping what a peer accepted
+    async fn gossip_round(&mut self, index: usize) {
+        let messages = self.nodes[index].manager.queued().await;
+        if messages.is_empty() {
+            return;
+        }
+
+        let batches = match encode_batches(&self.nodes[index].id, &messages, &self.config.gossip) {
+            Ok(batches) => batches,
+            Err(e) => {
+                self.violation(format!("node {} failed to encode: {}", index, e));
+                return;
+            }
+        };
+
+        let mut peers: Vec<usize> = (0..self.nodes.len())
+            .filter(|&peer| peer != index)
+            .collect();
+        peers.shuffle(&mut self.rng);
+        peers.truncate(self.config.gossip.fanout);
+
+        let mut accepted = 0;
+        for peer in peers {
+            let mut sent = 0;
+            for batch in &batches {
+                if !self.network.send(index, peer, batch.clone()) {
+                    break;
+                }
+                sent += batch.message_count as usize;
+            }
+            accepted = accepted.max(sent);
+        }
+
+        self.nodes[index].manager.dequeue(accepted).await;
+    }
+
+    /// Check what subscribers received this round
+    fn collect_deliveries(&mut self) {
+        for index in 0..self.nodes.len() {
+            loop {
+                let message = match self.nodes[index].deliveries.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Lagged(missed)) => {
+                        self.violation(format!("node {} subscriber lagged by {}", index, missed));
+                        continue;
+                    }
+                    Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+                };
+
+                let problem = match self.published.get(&message.id) {
+                    None => Some("an unknown message"),
+                    Some((origin, _)) if *origin == index => Some("its own message"),
+                    Some((_, payload)) if *payload != message.payload => {
+                        Some("a changed payload of message")
+                    }
+                    Some(_) => {
+                        (!self.delivered.insert((index, message.id))).then_some("a second copy of")
+                    }
+                };
+                if let Some(problem) = problem {
+                    self.violation(format!(
+                        "node {} delivered {} {}",
+                        index, problem, message.id
+                    ));
+                }
+            }
+        }
+    }
+
+    fn report(self) -> GossipSimReport {
+        let nodes = self.nodes.len();
+        let missed = self
+            .published
+            .iter()
+            .map(|(id, (origin, _))| {
+                (0..nodes)
+                    .filter(|node| node != origin && !self.delivered.contains(&(*node, *id)))
+                    .count()
+            })
+            .sum();
+
+        GossipSimReport {
+            seed: self.seed,
+            published: self.published.len(),
+            deliveries: self.delivered.len(),
+            missed,
+            network: self.network.stats().clone(),
+            violations: self.violations,
+        }
+    }
+}
+
+/// Run the gossip simulation once per seed, returning the runs that broke
+/// an invariant
+pub async fn run_gossip(
+    config: &GossipSimConfig,
+    seeds: Range<u64>,
+) -> Result<Vec<GossipSimReport>> {
+    let mut failures = Vec::new();
+    for seed in seeds {
+        let report = GossipSimulation::new(config.clone(), seed)?.run().await;
+        if !report.is_ok() {
+            failures.push(report);
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seeds swept by the regular test run; the ignored soak tests go further
+    const SEEDS: Range<u64> = 0..200;
+
+    fn assert_no_failures<R: std::fmt::Debug>(failures: &[R]) {
+        assert!(
+            failures.is_empty(),
+            "{} seed(s) failed, first: {:?}",
+            failures.len(),
+            failures.first()
+        );
+    }
+
+    #[test]
+    fn test_network_is_deterministic() {
+        let deliveries = |seed| {
+            let mut network = SimNetwork::new(NetworkConfig::lossy(), seed);
+            for i in 0..100 {
+                network.send(i % 3, (i + 1) % 3, i);
+            }
+            let mut delivered = Vec::new();
+            while let Some(delivery) = network.deliver_next(Duration::MAX) {
+                delivered.push(delivery);
+            }
+            delivered
+        };
+
+        assert_eq!(deliveries(7), deliveries(7));
+        assert_ne!(deliveries(7), deliveries(8));
+    }
+
+    #[test]
+    fn test_network_partition_and_latency() {
+        let config = NetworkConfig {
+            min_latency: Duration::from_millis(5),
+            max_latency: Duration::from_millis(5),
+            ..NetworkConfig::default()
+        };
+        let mut network = SimNetwork::new(config, 1);
+        network.partition(&[vec![0, 1, 3], vec![2]]);
+
+        assert!(network.send(0, 1, "a"));
+        assert!(!network.send(0, 2, "b"));
+        assert!(!network.send(2, 1, "c"));
+
+        // Cut while in flight
+        assert!(network.send(3, 0, "d"));
+        network.isolate(3, 4);
+
+        assert_eq!(network.deliver_next(Duration::from_millis(4)), None);
+        assert_eq!(
+            network.deliver_next(Duration::from_millis(5)),
+            Some((0, 1, "a"))
+        );
+        assert_eq!(network.deliver_next(Duration::MAX), None);
+        assert_eq!(network.now(), Duration::from_millis(5));
+        assert_eq!(network.stats().dropped, 3);
+
+        network.heal();
+        assert!(network.send(2, 0, "e"));
+    }
+
+    #[tokio::test]
+    async fn test_raft_invariants_hold_across_seeds() {
+        let failures = run_raft(&RaftSimConfig::default(), SEEDS).await.unwrap();
+        assert_no_failures(&failures);
+    }
+
+    #[tokio::test]
+    async fn test_raft_without_pre_vote_across_seeds() {
+        let config = RaftSimConfig {
+            pre_vote: false,
+            nodes: 3,
+            ..RaftSimConfig::default()
+        };
+        let failures = run_raft(&config, SEEDS).await.unwrap();
+        assert_no_failures(&failures);
+    }
+
+    #[tokio::test]
+    async fn test_raft_run_replays_from_seed() {
+        let config = RaftSimConfig::default();
+        let first = RaftSimulation::new(config.clone(), 42)
+            .await
+            .unwrap()
+            .run()
+            .await;
+        let second = RaftSimulation::new(config, 42).await.unwrap().run().await;
+
+        assert_eq!(first, second);
+        assert!(first.elections > 0);
+        assert!(first.highest_commit > 0);
+    }
+
+    #[tokio::test]
+    async fn test_gossip_invariants_hold_across_seeds() {
+        let failures = run_gossip(&GossipSimConfig::default(), SEEDS)
+            .await
+            .unwrap();
+        assert_no_failures(&failures);
+    }
+
+    #[tokio::test]
+    async fn test_gossip_reaches_every_node_without_faults() {
+        let nodes = 5;
+        let config = GossipSimConfig {
+            nodes,
+            network: NetworkConfig::default(),
+            gossip: GossipConfig {
+                fanout: nodes - 1,
+                ..GossipConfig::default()
+            },
+            fault_rate: 0.0,
+            ..GossipSimConfig::default()
+        };
+
+        for seed in 0..20 {
+            let report = GossipSimulation::new(config.clone(), seed)
+                .unwrap()
+                .run()
+                .await;
+            assert!(report.is_ok(), "{:?}", report.violations);
+            assert!(report.published > 0);
+            assert_eq!(report.missed, 0);
+            assert_eq!(report.deliveries, report.published * (nodes - 1));
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "soak test; run with --ignored"]
+    async fn soak_raft_and_gossip() {
+        let failures = run_raft(&RaftSimConfig::default(), 0..5_000).await.unwrap();
+        assert_no_failures(&failures);
+
+        let failures = run_gossip(&GossipSimConfig::default(), 0..5_000)
+            .await
+            .unwrap();
+        assert_no_failures(&failures);
+    }
+}
@@ -1,13 +1,57 @@
 //! Cluster state management
 
 use crate::error::{ClusterError, Result};
-use crate::node::{Node, NodeId, NodeRole, NodeStatus};
+use crate::node::{Node, NodeId, NodeRole, NodeStatus, Placement};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 
+/// How long a departed node's tombstone is kept by default
+pub const DEFAULT_TOMBSTONE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Upper bound on kept tombstones; the ones closest to expiry go first
+pub const MAX_TOMBSTONES: usize = 1024;
+
+/// Why a node was removed from the cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TombstoneReason {
+    /// The node left gracefully
+    Left,
+    /// The node failed and was reaped
+    Failed,
+    /// The node was no longer in the committed consensus configuration
+    Evicted,
+}
+
+/// Record of a removed node, kept until it expires so the removal wins over
+/// stale state still circulating for that node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeTombstone {
+    pub node_id: NodeId,
+    /// Incarnation of the node when it was removed
+    pub incarnation: u64,
+    pub reason: TombstoneReason,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+impl NodeTombstone {
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+}
+
+/// Nodes to start and retire to reach a target cluster size
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScalePlan {
+    /// Failure domain for each node to start
+    pub add: Vec<Placement>,
+    /// Nodes to retire
+    pub remove: Vec<NodeId>,
+}
+
 /// Represents the complete state of the cluster
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClusterState {
@@ -40,6 +84,10 @@ pub struct ClusterState {
 
     /// Cluster metadata
     pub metadata: HashMap<String, String>,
+
+    /// Nodes removed from the cluster, until their tombstones expire
+    #[serde(default)]
+    pub tombstones: HashMap<NodeId, NodeTombstone>,
 }
 
 impl ClusterState {
@@ -58,6 +106,7 @@ impl ClusterState {
             last_updated: now,
             created_at: now,
             metadata: HashMap::new(),
+            tombstones: HashMap::new(),
         }
     }
 
@@ -76,15 +125,27 @@ impl ClusterState {
             last_updated: now,
             created_at: now,
             metadata: HashMap::new(),
+            tombstones: HashMap::new(),
         }
     }
 
-    /// Add a node to the cluster
+    /// Add a node to the cluster. A node with a live tombstone is only
+    /// accepted at a higher incarnation than the one it was removed at.
     pub fn add_node(&mut self, node: Node) -> Result<()> {
         if self.nodes.contains_key(&node.id) {
             return Err(ClusterError::node_already_exists(node.id.to_string()));
         }
 
+        if let Some(tombstone) = self.tombstones.get(&node.id) {
+            if node.incarnation <= tombstone.incarnation {
+                return Err(ClusterError::membership(format!(
+                    "Node {} was removed at incarnation {} and cannot rejoin at incarnation {}",
+                    node.id, tombstone.incarnation, node.incarnation
+                )));
+            }
+            self.tombstones.remove(&node.id);
+        }
+
         tracing::info!("Adding node {} to cluster", node.summary());
         self.nodes.insert(node.id.clone(), node);
         self.update_timestamp();
@@ -93,13 +154,33 @@ impl ClusterState {
         Ok(())
     }
 
-    /// Remove a node from the cluster
+    /// Remove a node that left the cluster, leaving a tombstone for
+    /// [`DEFAULT_TOMBSTONE_TTL`]
     pub fn remove_node(&mut self, node_id: &NodeId) -> Result<Node> {
+        self.tombstone_node(node_id, TombstoneReason::Left, DEFAULT_TOMBSTONE_TTL)
+    }
+
+    /// Remove a node and keep a tombstone for it for `ttl`
+    pub fn tombstone_node(
+        &mut self,
+        node_id: &NodeId,
+        reason: TombstoneReason,
+        ttl: Duration,
+    ) -> Result<Node> {
         let node = self
             .nodes
             .remove(node_id)
             .ok_or_else(|| ClusterError::node_not_found(node_id.to_string()))?;
 
+        let now = current_timestamp();
+        self.insert_tombstone(NodeTombstone {
+            node_id: node_id.clone(),
+            incarnation: node.incarnation,
+            reason,
+            created_at: now,
+            expires_at: now + ttl.as_secs(),
+        });
+
         // If removed node was leader, clear leader
         if self.leader_id.as_ref() == Some(node_id) {
             self.leader_id = None;
@@ -163,6 +244,148 @@ impl ClusterState {
         self.get_nodes_by_status(NodeStatus::Healthy)
     }
 
+    /// Nodes that may take new VPN users, healthiest first
+    pub fn routable_nodes(&self) -> Vec<&Node> {
+        let mut nodes: Vec<&Node> = self
+            .nodes
+            .values()
+            .filter(|node| node.is_routable())
+            .collect();
+        nodes.sort_by(|a, b| {
+            b.health
+                .score
+                .cmp(&a.health.score)
+                .then(a.health.connections.cmp(&b.health.connections))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        nodes
+    }
+
+    /// Node a new VPN user should be placed on
+    pub fn select_node_for_user(&self) -> Option<&Node> {
+        self.routable_nodes().into_iter().next()
+    }
+
+    /// Node a new VPN user should be placed on, staying in `near` when a
+    /// routable node runs there
+    pub fn select_node_for_user_near(&self, near: &Placement) -> Option<&Node> {
+        let nodes = self.routable_nodes();
+        if near.is_unknown() {
+            return nodes.first().copied();
+        }
+
+        nodes
+            .iter()
+            .find(|node| node.placement() == *near)
+            .or_else(|| nodes.first())
+            .copied()
+    }
+
+    /// Up to `replicas` routable nodes to hold a user, spread so that no
+    /// failure domain gets a second replica before every domain has one.
+    /// The first node is the best one in `near`, if any, so reads stay local.
+    pub fn place_user_replicas(&self, replicas: usize, near: &Placement) -> Vec<&Node> {
+        // Domains in order of their healthiest node
+        let mut domains: Vec<(Placement, Vec<&Node>)> = Vec::new();
+        for node in self.routable_nodes() {
+            let placement = node.placement();
+            match domains.iter_mut().find(|(domain, _)| *domain == placement) {
+                Some((_, nodes)) => nodes.push(node),
+                None => domains.push((placement, vec![node])),
+            }
+        }
+
+        if !near.is_unknown() {
+            if let Some(index) = domains.iter().position(|(domain, _)| domain == near) {
+                let local = domains.remove(index);
+                domains.insert(0, local);
+            }
+        }
+
+        let mut placed = Vec::new();
+        for round in 0.. {
+            let before = placed.len();
+            for (_, nodes) in &domains {
+                if placed.len() == replicas {
+                    return placed;
+                }
+                if let Some(node) = nodes.get(round) {
+                    placed.push(*node);
+                }
+            }
+            if placed.len() == before {
+                break;
+            }
+        }
+        placed
+    }
+
+    /// Plan scaling to `target` nodes. New nodes go to the failure domains
+    /// with the fewest nodes; removals come from the most crowded domains,
+    /// unhealthy and least healthy nodes first, and never the leader.
+    pub fn plan_scale(&self, target: usize) -> ScalePlan {
+        let mut plan = ScalePlan::default();
+        let current = self.nodes.len();
+
+        let mut domains: BTreeMap<Placement, Vec<&Node>> = BTreeMap::new();
+        for node in self.nodes.values() {
+            domains.entry(node.placement()).or_default().push(node);
+        }
+
+        if target > current {
+            let mut counts: Vec<(Placement, usize)> = domains
+                .iter()
+                .map(|(placement, nodes)| (placement.clone(), nodes.len()))
+                .collect();
+            if counts.is_empty() {
+                counts.push((Placement::default(), 0));
+            }
+
+            for _ in current..target {
+                // min_by_key keeps the first of equal counts
+                if let Some((placement, count)) = counts.iter_mut().min_by_key(|(_, count)| *count)
+                {
+                    *count += 1;
+                    plan.add.push(placement.clone());
+                }
+            }
+        } else {
+            let mut candidates: Vec<(usize, VecDeque<&Node>)> = domains
+                .into_values()
+                .map(|mut nodes| {
+                    let count = nodes.len();
+                    nodes.retain(|node| Some(&node.id) != self.leader_id.as_ref());
+                    nodes.sort_by(|a, b| {
+                        a.is_routable()
+                            .cmp(&b.is_routable())
+                            .then(a.health.score.cmp(&b.health.score))
+                            .then_with(|| a.name.cmp(&b.name))
+                    });
+                    (count, nodes.into())
+                })
+                .collect();
+
+            for _ in target..current {
+                let mut crowded: Option<&mut (usize, VecDeque<&Node>)> = None;
+                for domain in candidates.iter_mut().filter(|(_, nodes)| !nodes.is_empty()) {
+                    if crowded.as_ref().is_none_or(|best| domain.0 > best.0) {
+                        crowded = Some(domain);
+                    }
+                }
+
+                let Some((count, nodes)) = crowded else {
+                    break;
+                };
+                if let Some(node) = nodes.pop_front() {
+                    *count -= 1;
+                    plan.remove.push(node.id.clone());
+                }
+            }
+        }
+
+        plan
+    }
+
     /// Get voting nodes (can participate in consensus)
     pub fn get_voting_nodes(&self) -> Vec<&Node> {
         self.nodes.values().filter(|node| node.can_vote()).collect()
@@ -259,7 +482,10 @@ impl ClusterState {
         let healthy_voting = self
             .nodes
             .values()
-            .filter(|node| node.can_vote() && node.is_healthy())
+            .filter(|node| {
+                // Draining nodes are overloaded, not unreachable
+                node.can_vote() && matches!(node.status, NodeStatus::Healthy | NodeStatus::Draining)
+            })
             .count();
 
         healthy_voting > voting_nodes / 2
@@ -300,9 +526,12 @@ impl ClusterState {
         let mut failed_nodes = Vec::new();
 
         for (node_id, node) in &mut self.nodes {
-            if node.status == NodeStatus::Healthy || node.status == NodeStatus::Suspected {
+            if matches!(
+                node.status,
+                NodeStatus::Healthy | NodeStatus::Draining | NodeStatus::Suspected
+            ) {
                 if current_time.saturating_sub(node.last_seen) > timeout_secs {
-                    if node.status == NodeStatus::Healthy {
+                    if node.status != NodeStatus::Suspected {
                         node.set_status(NodeStatus::Suspected);
                         tracing::warn!("Node {} is suspected to have failed", node_id);
                     } else if current_time.saturating_sub(node.last_seen) > timeout_secs * 2 {
@@ -332,14 +561,122 @@ impl ClusterState {
         if let Some(node) = self.get_node_mut(node_id) {
             node.update_last_seen();
 
-            // If node was suspected/failed but is now responding, mark as healthy
+            // A suspected or failed node that responds has refuted the
+            // suspicion; the new incarnation outranks tombstones made from it
             if matches!(node.status, NodeStatus::Suspected | NodeStatus::Failed) {
+                node.bump_incarnation();
                 node.set_status(NodeStatus::Healthy);
-                tracing::info!("Node {} has recovered and is now healthy", node_id);
+                tracing::info!(
+                    "Node {} has recovered and is now healthy at incarnation {}",
+                    node_id,
+                    node.incarnation
+                );
+            }
+        }
+    }
+
+    /// Remove nodes that have stayed failed for longer than `grace` and
+    /// tombstone them for `ttl`. Nothing is reaped while the cluster lacks
+    /// quorum, since the remaining nodes cannot agree on the removal then.
+    pub fn reap_failed_nodes(&mut self, grace: Duration, ttl: Duration) -> Vec<NodeId> {
+        if !self.has_quorum() {
+            return Vec::new();
+        }
+
+        let now = current_timestamp();
+        let reaped: Vec<NodeId> = self
+            .nodes
+            .values()
+            .filter(|node| {
+                node.status == NodeStatus::Failed
+                    && now.saturating_sub(node.last_seen) > grace.as_secs()
+            })
+            .map(|node| node.id.clone())
+            .collect();
+
+        for node_id in &reaped {
+            if self
+                .tombstone_node(node_id, TombstoneReason::Failed, ttl)
+                .is_ok()
+            {
+                tracing::info!("Reaped failed node {}", node_id);
+            }
+        }
+
+        reaped
+    }
+
+    /// Merge tombstones received from another node, dropping any node they
+    /// cover. The tombstone with the higher incarnation wins.
+    pub fn merge_tombstones<I>(&mut self, tombstones: I)
+    where
+        I: IntoIterator<Item = NodeTombstone>,
+    {
+        let now = current_timestamp();
+        for tombstone in tombstones {
+            if tombstone.is_expired(now) {
+                continue;
+            }
+            if self
+                .tombstones
+                .get(&tombstone.node_id)
+                .is_some_and(|existing| existing.incarnation >= tombstone.incarnation)
+            {
+                continue;
+            }
+
+            let covers_node = self
+                .nodes
+                .get(&tombstone.node_id)
+                .is_some_and(|node| node.incarnation <= tombstone.incarnation);
+            if covers_node {
+                let _ = self.tombstone_node(
+                    &tombstone.node_id,
+                    tombstone.reason,
+                    Duration::from_secs(tombstone.expires_at.saturating_sub(now)),
+                );
             }
+            self.insert_tombstone(tombstone);
         }
     }
 
+    /// Drop expired tombstones, returning how many were removed
+    pub fn purge_expired_tombstones(&mut self) -> usize {
+        let now = current_timestamp();
+        let before = self.tombstones.len();
+        self.tombstones
+            .retain(|_, tombstone| !tombstone.is_expired(now));
+
+        let purged = before - self.tombstones.len();
+        if purged > 0 {
+            self.update_timestamp();
+        }
+        purged
+    }
+
+    pub fn get_tombstone(&self, node_id: &NodeId) -> Option<&NodeTombstone> {
+        self.tombstones.get(node_id)
+    }
+
+    fn insert_tombstone(&mut self, tombstone: NodeTombstone) {
+        self.tombstones.insert(tombstone.node_id.clone(), tombstone);
+
+        while self.tombstones.len() > MAX_TOMBSTONES {
+            let oldest = self
+                .tombstones
+                .values()
+                .min_by_key(|tombstone| tombstone.expires_at)
+                .map(|tombstone| tombstone.node_id.clone());
+            match oldest {
+                Some(node_id) => {
+                    self.tombstones.remove(&node_id);
+                }
+                None => break,
+            }
+        }
+        self.update_timestamp();
+    }
+
     /// Get cluster health summary
     pub fn health_summary(&self) -> ClusterHealthSummary {
         let total_nodes = self.size();
@@ -732,4 +1069,174 @@ mod tests {
         let current_state = distributed_state.get_state().await.unwrap();
         assert_eq!(current_state.size(), 1);
     }
+    #[test]
+    fn test_removed_node_needs_higher_incarnation() {
+        let mut state = ClusterState::new(NodeId::new());
+        let node = create_test_node("node1", "127.0.0.1:8001");
+        let node_id = node.id.clone();
+
+        state.add_node(node.clone()).unwrap();
+        state.remove_node(&node_id).unwrap();
+        let tombstone = state.get_tombstone(&node_id).unwrap();
+        assert_eq!(tombstone.reason, TombstoneReason::Left);
+        assert_eq!(tombstone.incarnation, 0);
+
+        // Stale copy of the node trying to re-add itself
+        assert!(matches!(
+            state.add_node(node.clone()),
+            Err(ClusterError::Membership(_))
+        ));
+
+        let mut rejoining = node;
+        rejoining.bump_incarnation();
+        state.add_node(rejoining).unwrap();
+        assert!(state.get_tombstone(&node_id).is_none());
+    }
+
+    #[test]
+    fn test_recovery_refutes_suspicion() {
+        let mut state = ClusterState::new(NodeId::new());
+        let mut node = create_test_node("node1", "127.0.0.1:8001");
+        node.set_status(NodeStatus::Suspected);
+        let node_id = node.id.clone();
+        state.add_node(node).unwrap();
+
+        state.update_node_last_seen(&node_id);
+        let node = state.get_node(&node_id).unwrap();
+        assert_eq!(node.status, NodeStatus::Healthy);
+        assert_eq!(node.incarnation, 1);
+
+        // Heartbeats from a healthy node keep its incarnation
+        state.update_node_last_seen(&node_id);
+        assert_eq!(state.get_node(&node_id).unwrap().incarnation, 1);
+    }
+
+    #[test]
+    fn test_failed_nodes_are_reaped() {
+        let mut state = ClusterState::new(NodeId::new());
+        for i in 0..3 {
+            let mut node = create_test_node(&format!("node{}", i), "127.0.0.1:8001");
+            node.set_status(NodeStatus::Healthy);
+            state.add_node(node).unwrap();
+        }
+
+        let mut failed = create_test_node("failed", "127.0.0.1:8004");
+        failed.set_status(NodeStatus::Failed);
+        failed.last_seen = current_timestamp() - 600;
+        let failed_id = failed.id.clone();
+        state.add_node(failed).unwrap();
+
+        assert!(state
+            .reap_failed_nodes(Duration::from_secs(900), DEFAULT_TOMBSTONE_TTL)
+            .is_empty());
+        let reaped = state.reap_failed_nodes(Duration::from_secs(300), Duration::from_secs(0));
+        assert_eq!(reaped, vec![failed_id.clone()]);
+        assert_eq!(state.size(), 3);
+        assert_eq!(
+            state.get_tombstone(&failed_id).unwrap().reason,
+            TombstoneReason::Failed
+        );
+
+        // A zero TTL expires immediately
+        assert_eq!(state.purge_expired_tombstones(), 1);
+        assert!(state.tombstones.is_empty());
+    }
+
+    #[test]
+    fn test_merge_tombstones() {
+        let mut state = ClusterState::new(NodeId::new());
+        let node = create_test_node("node1", "127.0.0.1:8001");
+        let node_id = node.id.clone();
+        state.add_node(node).unwrap();
+
+        let mut peer = state.clone();
+        peer.remove_node(&node_id).unwrap();
+
+        state.merge_tombstones(peer.tombstones.values().cloned());
+        assert!(state.get_node(&node_id).is_none());
+        assert!(state.get_tombstone(&node_id).is_some());
+    }
+
+    #[test]
+    fn test_tombstones_are_bounded() {
+        let mut state = ClusterState::new(NodeId::new());
+        for _ in 0..MAX_TOMBSTONES + 10 {
+            let node = create_test_node("node", "127.0.0.1:8001");
+            let node_id = node.id.clone();
+            state.add_node(node).unwrap();
+            state.remove_node(&node_id).unwrap();
+        }
+
+        assert_eq!(state.tombstones.len(), MAX_TOMBSTONES);
+        assert_eq!(state.size(), 0);
+    }
+
+    fn create_zoned_node(name: &str, zone: &str, score: u8) -> Node {
+        let mut node = create_test_node(name, "127.0.0.1:8001");
+        node.set_region("eu-west".to_string());
+        node.set_zone(zone.to_string());
+        node.status = NodeStatus::Healthy;
+        node.health.score = score;
+        node
+    }
+
+    #[test]
+    fn test_zone_aware_user_placement() {
+        let mut state = ClusterState::new(NodeId::new());
+        let a1 = create_zoned_node("a1", "eu-west-1a", 90);
+        let a2 = create_zoned_node("a2", "eu-west-1a", 80);
+        let b1 = create_zoned_node("b1", "eu-west-1b", 70);
+        let (a1_id, a2_id, b1_id) = (a1.id.clone(), a2.id.clone(), b1.id.clone());
+        for node in [a1, a2, b1] {
+            state.add_node(node).unwrap();
+        }
+
+        let zone_b = Placement::new(Some("eu-west".to_string()), Some("eu-west-1b".to_string()));
+        assert_eq!(state.select_node_for_user_near(&zone_b).unwrap().id, b1_id);
+        assert_eq!(
+            state
+                .select_node_for_user_near(&Placement::default())
+                .unwrap()
+                .id,
+            a1_id
+        );
+
+        // One replica per zone before doubling up, local zone first
+        let replicas: Vec<NodeId> = state
+            .place_user_replicas(3, &zone_b)
+            .into_iter()
+            .map(|node| node.id.clone())
+            .collect();
+        assert_eq!(replicas, vec![b1_id, a1_id, a2_id]);
+        assert_eq!(state.place_user_replicas(5, &zone_b).len(), 3);
+    }
+
+    #[test]
+    fn test_zone_aware_scale_plan() {
+        let mut state = ClusterState::new(NodeId::new());
+        let a1 = create_zoned_node("a1", "eu-west-1a", 90);
+        let a2 = create_zoned_node("a2", "eu-west-1a", 40);
+        let a3 = create_zoned_node("a3", "eu-west-1a", 60);
+        let b1 = create_zoned_node("b1", "eu-west-1b", 10);
+        let (a1_id, a2_id, a3_id) = (a1.id.clone(), a2.id.clone(), a3.id.clone());
+        for node in [a1, a2, a3, b1] {
+            state.add_node(node).unwrap();
+        }
+        state.set_leader(Some(a2_id.clone())).unwrap();
+
+        let zone_a = Placement::new(Some("eu-west".to_string()), Some("eu-west-1a".to_string()));
+        let zone_b = Placement::new(Some("eu-west".to_string()), Some("eu-west-1b".to_string()));
+
+        // Growing fills the sparse zone first, then alternates
+        let grow = state.plan_scale(7);
+        assert_eq!(grow.add, vec![zone_b.clone(), zone_b, zone_a]);
+        assert!(grow.remove.is_empty());
+
+        // Shrinking takes from the crowded zone, least healthy first, sparing the leader
+        let shrink = state.plan_scale(2);
+        assert!(shrink.add.is_empty());
+        assert_eq!(shrink.remove, vec![a3_id, a1_id]);
+
+        assert_eq!(state.plan_scale(4), ScalePlan::default());
+    }
 }
@@ -0,0 +1,236 @@
+//! Snapshot and user table shipping
+//!
+//! A node accepting a join pushes its consensus snapshot and user table to
+//! the new node as chunked transfers, so neither has to fit into a single
+//! gRPC message. Received transfers are applied as they arrive.
+
+use crate::communication::{ClusterGrpcClient, ReceivedTransfer};
+use crate::consensus::ConsensusEngine;
+use crate::coordination::CoordinationEvent;
+use crate::error::{ClusterError, Result};
+use crate::node::NodeId;
+use crate::state::ClusterState;
+use crate::user_store::ReplicatedUserStore;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+/// Transfer kind of a consensus snapshot
+pub const SNAPSHOT_TRANSFER: &str = "snapshot";
+
+/// Transfer kind of the user table, a JSON object of records keyed by ID
+pub const USERS_TRANSFER: &str = "users";
+
+/// Sends state to joining nodes and applies state sent to this one
+pub struct StateTransfer {
+    node_id: NodeId,
+    state: Arc<RwLock<ClusterState>>,
+    consensus: Arc<dyn ConsensusEngine>,
+    users: ReplicatedUserStore,
+    client: ClusterGrpcClient,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl StateTransfer {
+    pub fn new(
+        node_id: NodeId,
+        state: Arc<RwLock<ClusterState>>,
+        consensus: Arc<dyn ConsensusEngine>,
+        users: ReplicatedUserStore,
+        client: ClusterGrpcClient,
+    ) -> Self {
+        Self {
+            node_id,
+            state,
+            consensus,
+            users,
+            client,
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Ship the consensus snapshot and the user table to `target`
+    pub async fn send(&self, target: SocketAddr) -> Result<()> {
+        let snapshot = self.consensus.snapshot().await?;
+        self.client
+            .send_payload(target, SNAPSHOT_TRANSFER, &snapshot)
+            .await?;
+
+        let users = serde_json::to_vec(&self.users.list().await?)?;
+        self.client
+            .send_payload(target, USERS_TRANSFER, &users)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Apply a transfer received from another node
+    pub async fn apply(&self, transfer: ReceivedTransfer) -> Result<()> {
+        match transfer.kind.as_str() {
+            SNAPSHOT_TRANSFER => self.consensus.apply_snapshot(transfer.data).await,
+            USERS_TRANSFER => {
+                let users: HashMap<String, serde_json::Value> =
+                    serde_json::from_slice(&transfer.data)?;
+                let written = self.users.import(users).await?;
+                tracing::info!(
+                    "Imported {} user records from {}",
+                    written,
+                    transfer.from_node
+                );
+                Ok(())
+            }
+            kind => Err(ClusterError::invalid_state(format!(
+                "Unknown transfer kind '{}' from {}",
+                kind, transfer.from_node
+            ))),
+        }
+    }
+
+    /// Apply transfers from `inbound` and ship state to every node joining
+    /// through this one, as announced on `events`
+    pub async fn start(
+        self: Arc<Self>,
+        mut inbound: mpsc::Receiver<ReceivedTransfer>,
+        mut events: broadcast::Receiver<CoordinationEvent>,
+    ) {
+        let transfer = self.clone();
+        let receiver = tokio::spawn(async move {
+            while let Some(received) = inbound.recv().await {
+                if let Err(e) = transfer.apply(received).await {
+                    tracing::warn!("Failed to apply transfer: {}", e);
+                }
+            }
+        });
+
+        let transfer = self.clone();
+        let sender = tokio::spawn(async move {
+            loop {
+                let node_id = match events.recv().await {
+                    Ok(CoordinationEvent::NodeJoined { node_id, .. }) => node_id,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if node_id == transfer.node_id {
+                    continue;
+                }
+
+                let address = match transfer.state.read().await.get_node(&node_id) {
+                    Some(node) => node.address,
+                    None => continue,
+                };
+                if let Err(e) = transfer.send(address).await {
+                    tracing::warn!("Failed to ship state to {}: {}", node_id, e);
+                }
+            }
+        });
+
+        self.tasks.lock().await.extend([receiver, sender]);
+    }
+
+    pub async fn stop(&self) {
+        for handle in self.tasks.lock().await.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+impl Drop for StateTransfer {
+    fn drop(&mut self) {
+        for handle in self.tasks.get_mut().drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::ClusterGrpcServer;
+    use crate::consensus::SimpleConsensus;
+    use crate::distributed_storage::MemoryStorage;
+    use crate::events::ClusterEventBus;
+    use crate::node::Node;
+    use serde_json::json;
+
+    fn transfer(node_id: NodeId, state: Arc<RwLock<ClusterState>>) -> Arc<StateTransfer> {
+        Arc::new(StateTransfer::new(
+            node_id.clone(),
+            state,
+            Arc::new(SimpleConsensus::new(node_id.clone())),
+            ReplicatedUserStore::new(Arc::new(MemoryStorage::new())),
+            ClusterGrpcClient::new(node_id),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_joining_node_receives_users_and_snapshot() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // The joining node serves transfers
+        let joiner_id = NodeId::new();
+        let joiner_state = Arc::new(RwLock::new(ClusterState::new(joiner_id.clone())));
+        let joiner = transfer(joiner_id.clone(), joiner_state.clone());
+        let (sink, inbound) = mpsc::channel(4);
+        let server = ClusterGrpcServer::new(
+            joiner_id.clone(),
+            joiner_state,
+            joiner.consensus.clone(),
+            address,
+        )
+        .with_transfer_sink(sink);
+        tokio::spawn(async move { server.start().await });
+        let joiner_events = ClusterEventBus::new(16);
+        joiner
+            .clone()
+            .start(inbound, joiner_events.subscribe())
+            .await;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        // The node it joins through has users and a snapshot to share
+        let member_id = NodeId::new();
+        let member_state = Arc::new(RwLock::new(ClusterState::new(member_id.clone())));
+        let member = transfer(member_id.clone(), member_state.clone());
+        member
+            .users
+            .put("alice", json!({"name": "alice"}))
+            .await
+            .unwrap();
+        member.consensus.elect_leader().await.unwrap();
+        member_state
+            .write()
+            .await
+            .add_node(Node::with_id(
+                joiner_id.clone(),
+                "joiner".to_string(),
+                address,
+            ))
+            .unwrap();
+
+        let events = ClusterEventBus::new(16);
+        member
+            .clone()
+            .start(mpsc::channel(1).1, events.subscribe())
+            .await;
+        events.publish(CoordinationEvent::NodeJoined {
+            node_id: joiner_id,
+            timestamp: 0,
+        });
+
+        for _ in 0..50 {
+            if joiner.users.get("alice").await.unwrap().is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(
+            joiner.users.get("alice").await.unwrap(),
+            Some(json!({"name": "alice"}))
+        );
+        assert_eq!(joiner.consensus.get_term().await, 1);
+    }
+}
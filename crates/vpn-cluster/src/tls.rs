@@ -0,0 +1,465 @@
+//! Mutual TLS for cluster gRPC traffic
+//!
+//! Every node presents a certificate issued by the cluster CA whose subject
+//! alternative names include the cluster name. Peers presenting a certificate
+//! for any other name are rejected, on both the server and the client side.
+//! Certificate files are watched and reloaded without restarting the node.
+
+use crate::config::TlsConfig;
+use crate::error::{ClusterError, Result};
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::client::verify_server_name;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ParsedCertificate, WebPkiClientVerifier};
+use rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+/// ALPN protocol identifier for HTTP/2, required by gRPC clients
+const ALPN_H2: &[u8] = b"h2";
+
+/// Reloadable TLS material for a cluster node
+pub struct ClusterTls {
+    cluster_name: String,
+    config: TlsConfig,
+    material: RwLock<Arc<TlsMaterial>>,
+}
+
+/// Certificates loaded from disk at one point in time
+struct TlsMaterial {
+    ca_pem: Vec<u8>,
+    client_cert_pem: Vec<u8>,
+    client_key_pem: Vec<u8>,
+    server_config: Arc<ServerConfig>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl ClusterTls {
+    /// Load the certificates referenced by `config` for `cluster_name`
+    pub fn load(config: TlsConfig, cluster_name: impl Into<String>) -> Result<Self> {
+        let cluster_name = cluster_name.into();
+        let material = TlsMaterial::load(&config, &cluster_name)?;
+
+        Ok(Self {
+            cluster_name,
+            config,
+            material: RwLock::new(Arc::new(material)),
+        })
+    }
+
+    /// Name peer certificates must be issued for
+    pub fn cluster_name(&self) -> &str {
+        &self.cluster_name
+    }
+
+    /// Reload the certificates if any of the files changed on disk.
+    ///
+    /// Returns `true` when new certificates were loaded. On error the
+    /// previously loaded certificates stay in use.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let modified = modification_times(&self.config);
+        if self.current().modified == modified {
+            return Ok(false);
+        }
+
+        let material = TlsMaterial::load(&self.config, &self.cluster_name)?;
+        *self.material.write().unwrap() = Arc::new(material);
+
+        tracing::info!("Reloaded cluster TLS certificates");
+        Ok(true)
+    }
+
+    /// Periodically reload the certificates in the background
+    pub fn spawn_reloader(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.config.reload_interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.reload_if_changed() {
+                    tracing::error!("Failed to reload cluster TLS certificates: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Server configuration for newly accepted connections
+    pub fn server_config(&self) -> Arc<ServerConfig> {
+        self.current().server_config.clone()
+    }
+
+    /// Client configuration for new outgoing connections
+    pub fn client_config(&self) -> ClientTlsConfig {
+        let material = self.current();
+
+        ClientTlsConfig::new()
+            .domain_name(self.cluster_name.clone())
+            .ca_certificate(Certificate::from_pem(&material.ca_pem))
+            .identity(Identity::from_pem(
+                &material.client_cert_pem,
+                &material.client_key_pem,
+            ))
+    }
+
+    /// Accept TLS connections on `bind_address`, dropping peers that fail the
+    /// handshake. Each handshake uses the certificates current at accept time.
+    pub async fn incoming(
+        self: Arc<Self>,
+        bind_address: std::net::SocketAddr,
+    ) -> Result<ReceiverStream<std::io::Result<TlsStream<TcpStream>>>> {
+        let listener = TcpListener::bind(bind_address).await.map_err(|e| {
+            ClusterError::network(format!("Failed to bind {}: {}", bind_address, e))
+        })?;
+        let (tx, rx) = mpsc::channel(32);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        if tx.send(Err(e)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+
+                if tx.is_closed() {
+                    break;
+                }
+
+                let acceptor = TlsAcceptor::from(self.server_config());
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(tls_stream) => {
+                            let _ = tx.send(Ok(tls_stream)).await;
+                        }
+                        Err(e) => {
+                            tracing::warn!("Rejected cluster connection from {}: {}", peer, e);
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    fn current(&self) -> Arc<TlsMaterial> {
+        self.material.read().unwrap().clone()
+    }
+}
+
+impl TlsMaterial {
+    fn load(config: &TlsConfig, cluster_name: &str) -> Result<Self> {
+        // Capture timestamps before reading so a write racing with the load
+        // is picked up by the next reload
+        let modified = modification_times(config);
+
+        let ca_pem = read_file(&config.ca_cert)?;
+        let server_cert_pem = read_file(&config.server_cert)?;
+        let server_key_pem = read_file(&config.server_key)?;
+        let client_cert_pem = match &config.client_cert {
+            Some(path) => read_file(path)?,
+            None => server_cert_pem.clone(),
+        };
+        let client_key_pem = match &config.client_key {
+            Some(path) => read_file(path)?,
+            None => server_key_pem.clone(),
+        };
+
+        let mut roots = RootCertStore::empty();
+        for cert in parse_certs(&ca_pem, &config.ca_cert)? {
+            roots.add(cert).map_err(|e| {
+                ClusterError::configuration(format!("Invalid CA certificate: {}", e))
+            })?;
+        }
+
+        // Fail early on an unusable client identity rather than on first connect
+        parse_certs(
+            &client_cert_pem,
+            config.client_cert.as_deref().unwrap_or(&config.server_cert),
+        )?;
+        parse_key(
+            &client_key_pem,
+            config.client_key.as_deref().unwrap_or(&config.server_key),
+        )?;
+
+        let verifier: Arc<dyn ClientCertVerifier> = if config.verify_peer {
+            let inner = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| {
+                    ClusterError::configuration(format!("Invalid client verifier: {}", e))
+                })?;
+            Arc::new(ClusterNameVerifier::new(inner, cluster_name)?)
+        } else {
+            WebPkiClientVerifier::no_client_auth()
+        };
+
+        let mut server_config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                parse_certs(&server_cert_pem, &config.server_cert)?,
+                parse_key(&server_key_pem, &config.server_key)?,
+            )
+            .map_err(|e| {
+                ClusterError::configuration(format!("Invalid server certificate: {}", e))
+            })?;
+        server_config.alpn_protocols = vec![ALPN_H2.to_vec()];
+
+        Ok(Self {
+            ca_pem,
+            client_cert_pem,
+            client_key_pem,
+            server_config: Arc::new(server_config),
+            modified,
+        })
+    }
+}
+
+/// Client certificate verifier that also requires the certificate to be
+/// issued for the cluster name
+#[derive(Debug)]
+struct ClusterNameVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    cluster_name: ServerName<'static>,
+}
+
+impl ClusterNameVerifier {
+    fn new(inner: Arc<dyn ClientCertVerifier>, cluster_name: &str) -> Result<Self> {
+        let cluster_name = ServerName::try_from(cluster_name.to_string()).map_err(|e| {
+            ClusterError::configuration(format!(
+                "Cluster name '{}' is not a valid certificate name: {}",
+                cluster_name, e
+            ))
+        })?;
+
+        Ok(Self {
+            inner,
+            cluster_name,
+        })
+    }
+}
+
+impl ClientCertVerifier for ClusterNameVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.inner.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> std::result::Result<ClientCertVerified, rustls::Error> {
+        let verified = self
+            .inner
+            .verify_client_cert(end_entity, intermediates, now)?;
+        verify_server_name(
+            &ParsedCertificate::try_from(end_entity)?,
+            &self.cluster_name,
+        )?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+fn watched_paths(config: &TlsConfig) -> Vec<&PathBuf> {
+    [&config.ca_cert, &config.server_cert, &config.server_key]
+        .into_iter()
+        .chain(config.client_cert.iter())
+        .chain(config.client_key.iter())
+        .collect()
+}
+
+fn modification_times(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    watched_paths(config)
+        .into_iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        ClusterError::configuration(format!("Failed to read {}: {}", path.display(), e))
+    })
+}
+
+fn parse_certs(pem: &[u8], path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| {
+            ClusterError::configuration(format!("Invalid PEM in {}: {}", path.display(), e))
+        })?;
+
+    if certs.is_empty() {
+        return Err(ClusterError::configuration(format!(
+            "No certificates found in {}",
+            path.display()
+        )));
+    }
+
+    Ok(certs)
+}
+
+fn parse_key(pem: &[u8], path: &Path) -> Result<PrivateKeyDer<'static>> {
+    rustls_pemfile::private_key(&mut &pem[..])
+        .map_err(|e| {
+            ClusterError::configuration(format!("Invalid PEM in {}: {}", path.display(), e))
+        })?
+        .ok_or_else(|| {
+            ClusterError::configuration(format!("No private key found in {}", path.display()))
+        })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, Certificate as RcgenCertificate, CertificateParams,
+        ExtendedKeyUsagePurpose, IsCa,
+    };
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    /// Certificate authority for generating test certificates
+    pub(crate) struct TestCa {
+        ca: RcgenCertificate,
+        pub dir: TempDir,
+    }
+
+    impl TestCa {
+        pub fn new() -> Self {
+            let mut params = CertificateParams::new(vec![]);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = RcgenCertificate::from_params(params).unwrap();
+
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+
+            Self { ca, dir }
+        }
+
+        /// Issue a node certificate for `name` and write it as `<file>.pem`/`<file>.key`
+        pub fn issue(&self, file: &str, name: &str) {
+            let mut params = CertificateParams::new(vec![name.to_string()]);
+            params.extended_key_usages = vec![
+                ExtendedKeyUsagePurpose::ServerAuth,
+                ExtendedKeyUsagePurpose::ClientAuth,
+            ];
+            let cert = RcgenCertificate::from_params(params).unwrap();
+
+            std::fs::write(
+                self.dir.path().join(format!("{}.pem", file)),
+                cert.serialize_pem_with_signer(&self.ca).unwrap(),
+            )
+            .unwrap();
+            std::fs::write(
+                self.dir.path().join(format!("{}.key", file)),
+                cert.serialize_private_key_pem(),
+            )
+            .unwrap();
+        }
+
+        pub fn config(&self, file: &str) -> TlsConfig {
+            TlsConfig {
+                ca_cert: self.dir.path().join("ca.pem"),
+                server_cert: self.dir.path().join(format!("{}.pem", file)),
+                server_key: self.dir.path().join(format!("{}.key", file)),
+                client_cert: None,
+                client_key: None,
+                verify_peer: true,
+                reload_interval: Duration::from_secs(30),
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_tls_material() {
+        let ca = TestCa::new();
+        ca.issue("node", "vpn-cluster");
+
+        let tls = ClusterTls::load(ca.config("node"), "vpn-cluster").unwrap();
+        assert_eq!(tls.cluster_name(), "vpn-cluster");
+        assert!(tls
+            .server_config()
+            .alpn_protocols
+            .contains(&ALPN_H2.to_vec()));
+
+        // Unchanged files are not reloaded
+        assert!(!tls.reload_if_changed().unwrap());
+    }
+
+    #[test]
+    fn test_load_rejects_missing_files() {
+        let ca = TestCa::new();
+        assert!(ClusterTls::load(ca.config("missing"), "vpn-cluster").is_err());
+    }
+
+    #[test]
+    fn test_reload_after_rotation() {
+        let ca = TestCa::new();
+        ca.issue("node", "vpn-cluster");
+        let tls = ClusterTls::load(ca.config("node"), "vpn-cluster").unwrap();
+        let before = tls.server_config();
+
+        // Make sure the rewritten files get a different modification time
+        std::thread::sleep(Duration::from_millis(20));
+        ca.issue("node", "vpn-cluster");
+
+        assert!(tls.reload_if_changed().unwrap());
+        assert!(!Arc::ptr_eq(&before, &tls.server_config()));
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_previous_certificates() {
+        let ca = TestCa::new();
+        ca.issue("node", "vpn-cluster");
+        let tls = ClusterTls::load(ca.config("node"), "vpn-cluster").unwrap();
+        let before = tls.server_config();
+
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(ca.dir.path().join("node.key"), "not a key").unwrap();
+
+        assert!(tls.reload_if_changed().is_err());
+        assert!(Arc::ptr_eq(&before, &tls.server_config()));
+    }
+}
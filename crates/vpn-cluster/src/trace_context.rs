@@ -0,0 +1,130 @@
+//! W3C trace context propagation over gRPC
+//!
+//! [`ClusterGrpcClient`] stamps every outgoing call with a child of the
+//! current [`TraceContext`] through [`TraceInterceptor`], and [`TraceLayer`]
+//! on the server side runs each call under the context it received, inside
+//! a `cluster_rpc` span carrying the trace and span ids.
+//!
+//! [`ClusterGrpcClient`]: crate::communication::ClusterGrpcClient
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tonic::codegen::http;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+use tower::{Layer, Service};
+use tracing::Instrument;
+
+pub use vpn_types::trace_context::{TraceContext, TRACEPARENT_HEADER};
+
+/// Client interceptor adding a child of the current context to each call
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceInterceptor;
+
+impl Interceptor for TraceInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        let context = TraceContext::current_child();
+        if let Ok(value) = context.to_string().parse() {
+            request.metadata_mut().insert(TRACEPARENT_HEADER, value);
+        }
+        Ok(request)
+    }
+}
+
+/// Server layer running each call under the context its caller sent, or a
+/// new trace when it sent none
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceLayer;
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = TraceService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceService { inner }
+    }
+}
+
+/// Service produced by [`TraceLayer`]
+#[derive(Debug, Clone)]
+pub struct TraceService<S> {
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for TraceService<S>
+where
+    S: Service<http::Request<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let parent = request
+            .headers()
+            .get(TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(TraceContext::parse);
+        let context = parent
+            .map(|parent| parent.child())
+            .unwrap_or_else(TraceContext::new_root);
+
+        let span = tracing::info_span!(
+            "cluster_rpc",
+            method = %request.uri().path(),
+            trace_id = %context.trace_id(),
+            span_id = %context.span_id(),
+            parent_span_id = parent.map(|p| p.span_id()).unwrap_or_default(),
+        );
+        let future = self.inner.call(request);
+        Box::pin(context.scope(future.instrument(span)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_context_propagates_to_outgoing_calls() {
+        assert!(TraceContext::current().is_none());
+
+        let root = TraceContext::new_root();
+        let sent = root
+            .scope(async {
+                assert_eq!(TraceContext::current(), Some(root));
+                TraceInterceptor.call(Request::new(())).unwrap()
+            })
+            .await;
+
+        let header = sent.metadata().get(TRACEPARENT_HEADER).unwrap();
+        let sent = TraceContext::parse(header.to_str().unwrap()).unwrap();
+        assert_eq!(sent.trace_id(), root.trace_id());
+        assert_ne!(sent.span_id(), root.span_id());
+    }
+
+    #[tokio::test]
+    async fn test_layer_adopts_caller_context() {
+        let caller = TraceContext::new_root();
+        let mut service = TraceLayer.layer(tower::service_fn(|_: http::Request<()>| async {
+            Ok::<_, std::convert::Infallible>(TraceContext::current())
+        }));
+
+        let request = http::Request::builder()
+            .header(TRACEPARENT_HEADER, caller.to_string())
+            .body(())
+            .unwrap();
+        let served = service.call(request).await.unwrap().unwrap();
+        assert_eq!(served.trace_id(), caller.trace_id());
+        assert_ne!(served.span_id(), caller.span_id());
+
+        // Calls without a context start their own trace
+        let served = service.call(http::Request::new(())).await.unwrap().unwrap();
+        assert_ne!(served.trace_id(), caller.trace_id());
+    }
+}
@@ -0,0 +1,381 @@
+//! Cluster-wide traffic statistics
+//!
+//! Each node reports cumulative per-user counters through a
+//! [`TrafficSource`], usually backed by vpn-monitor. The leader periodically
+//! pulls them from every node over `GetNodeTraffic` and keeps one record per
+//! node in distributed storage, so a node that is briefly unreachable still
+//! counts with its last report. Counters that go backwards (a restarted
+//! proxy, a rotated log) are treated as a reset: what was counted before is
+//! carried over instead of lost.
+//!
+//! The per-user sums over all node records are stored under
+//! `traffic/users/<user_id>` and rewritten only when they change.
+
+use crate::communication::ClusterGrpcClient;
+use crate::coordination::current_timestamp;
+use crate::distributed_storage::{DistributedConfigStorage, TransactionOp};
+use crate::error::Result;
+use crate::node::{NodeId, NodeStatus};
+use crate::state::ClusterState;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Key prefix of the per-node traffic records
+const NODE_TRAFFIC_PREFIX: &str = "traffic/nodes/";
+
+/// Key prefix of the cluster-wide per-user totals
+const USER_TRAFFIC_PREFIX: &str = "traffic/users/";
+
+/// How often the leader collects traffic from the nodes
+pub const DEFAULT_AGGREGATION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Traffic counters of one user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserTraffic {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub connections: u64,
+    /// Unix timestamp of the last activity, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_activity: Option<u64>,
+}
+
+impl UserTraffic {
+    /// Add `other`'s counters to these
+    pub fn add(&mut self, other: &UserTraffic) {
+        self.bytes_sent = self.bytes_sent.saturating_add(other.bytes_sent);
+        self.bytes_received = self.bytes_received.saturating_add(other.bytes_received);
+        self.connections = self.connections.saturating_add(other.connections);
+        self.last_activity = self.last_activity.max(other.last_activity);
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_sent.saturating_add(self.bytes_received)
+    }
+
+    /// Whether any counter is below `previous`, i.e. the source was reset
+    fn is_reset_from(&self, previous: &UserTraffic) -> bool {
+        self.bytes_sent < previous.bytes_sent
+            || self.bytes_received < previous.bytes_received
+            || self.connections < previous.connections
+    }
+}
+
+/// Per-user traffic counters of the local node
+#[async_trait]
+pub trait TrafficSource: Send + Sync {
+    /// Cumulative counters keyed by user ID
+    async fn user_traffic(&self) -> Result<HashMap<String, UserTraffic>>;
+}
+
+/// Counters of one user on one node
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct NodeUserCounters {
+    /// Counters from the node's latest report
+    last: UserTraffic,
+    /// Traffic counted before the node's counters were last reset
+    carried: UserTraffic,
+}
+
+impl NodeUserCounters {
+    fn total(&self) -> UserTraffic {
+        let mut total = self.carried;
+        total.add(&self.last);
+        total
+    }
+}
+
+/// What the leader keeps per node
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct NodeTrafficRecord {
+    collected_at: u64,
+    users: HashMap<String, NodeUserCounters>,
+}
+
+impl NodeTrafficRecord {
+    /// Fold a new report into the record
+    fn apply(&mut self, report: HashMap<String, UserTraffic>) {
+        // Users missing from the report were reset to zero
+        for (user_id, counters) in self.users.iter_mut() {
+            if !report.contains_key(user_id) {
+                let last = std::mem::take(&mut counters.last);
+                counters.carried.add(&last);
+            }
+        }
+
+        for (user_id, traffic) in report {
+            let counters = self.users.entry(user_id).or_default();
+            if traffic.is_reset_from(&counters.last) {
+                let last = counters.last;
+                counters.carried.add(&last);
+            }
+            counters.last = traffic;
+        }
+
+        self.collected_at = current_timestamp();
+    }
+}
+
+/// Outcome of one aggregation round
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AggregationReport {
+    pub nodes_polled: usize,
+    pub nodes_failed: usize,
+    /// Users whose cluster-wide totals changed
+    pub users_updated: usize,
+}
+
+/// Collects per-node traffic into cluster-wide per-user totals
+pub struct TrafficAggregator {
+    node_id: NodeId,
+    state: Arc<RwLock<ClusterState>>,
+    storage: Arc<dyn DistributedConfigStorage>,
+    client: ClusterGrpcClient,
+    source: RwLock<Option<Arc<dyn TrafficSource>>>,
+}
+
+impl TrafficAggregator {
+    pub fn new(
+        node_id: NodeId,
+        state: Arc<RwLock<ClusterState>>,
+        storage: Arc<dyn DistributedConfigStorage>,
+        client: ClusterGrpcClient,
+    ) -> Self {
+        Self {
+            node_id,
+            state,
+            storage,
+            client,
+            source: RwLock::new(None),
+        }
+    }
+
+    /// Report this node's traffic from `source`; without one the node
+    /// reports no traffic
+    pub async fn set_source(&self, source: Arc<dyn TrafficSource>) {
+        *self.source.write().await = Some(source);
+    }
+
+    /// This node's per-user counters
+    pub async fn local_traffic(&self) -> Result<HashMap<String, UserTraffic>> {
+        let source = self.source.read().await.clone();
+        match source {
+            Some(source) => source.user_traffic().await,
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    /// Pull every reachable node's counters and update the cluster-wide
+    /// totals. Meant to run on the leader only.
+    pub async fn aggregate(&self) -> Result<AggregationReport> {
+        let nodes: Vec<(NodeId, std::net::SocketAddr)> = {
+            let state = self.state.read().await;
+            state
+                .get_all_nodes()
+                .into_iter()
+                .filter(|node| matches!(node.status, NodeStatus::Healthy | NodeStatus::Draining))
+                .map(|node| (node.id.clone(), node.address))
+                .collect()
+        };
+        let mut report = AggregationReport::default();
+        let mut records = self.node_records().await?;
+        let mut ops = Vec::new();
+
+        for (node_id, address) in nodes {
+            let traffic = if node_id == self.node_id {
+                self.local_traffic().await
+            } else {
+                self.client.get_node_traffic(address).await
+            };
+
+            let traffic = match traffic {
+                Ok(traffic) => traffic,
+                Err(e) => {
+                    tracing::warn!("Failed to collect traffic from node {}: {}", node_id, e);
+                    report.nodes_failed += 1;
+                    continue;
+                }
+            };
+            report.nodes_polled += 1;
+
+            let record = records.entry(node_id.to_string()).or_default();
+            record.apply(traffic);
+            ops.push(TransactionOp::Set {
+                key: format!("{}{}", NODE_TRAFFIC_PREFIX, node_id),
+                value: serde_json::to_value(&*record)?,
+            });
+        }
+
+        let mut totals: HashMap<String, UserTraffic> = HashMap::new();
+        for record in records.values() {
+            for (user_id, counters) in &record.users {
+                totals
+                    .entry(user_id.clone())
+                    .or_default()
+                    .add(&counters.total());
+            }
+        }
+
+        let current = self.cluster_traffic().await?;
+        for (user_id, total) in totals {
+            if current.get(&user_id) != Some(&total) {
+                report.users_updated += 1;
+                ops.push(TransactionOp::Set {
+                    key: format!("{}{}", USER_TRAFFIC_PREFIX, user_id),
+                    value: serde_json::to_value(total)?,
+                });
+            }
+        }
+
+        if !ops.is_empty() {
+            self.storage.transaction(ops).await?;
+        }
+
+        Ok(report)
+    }
+
+    /// Cluster-wide totals of one user, as of the last aggregation
+    pub async fn user_total(&self, user_id: &str) -> Result<Option<UserTraffic>> {
+        self.storage
+            .get_config(&format!("{}{}", USER_TRAFFIC_PREFIX, user_id))
+            .await?
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Cluster-wide totals of every user, as of the last aggregation
+    pub async fn cluster_traffic(&self) -> Result<HashMap<String, UserTraffic>> {
+        let mut totals = HashMap::new();
+        for key in self.storage.list_keys().await? {
+            let Some(user_id) = key.strip_prefix(USER_TRAFFIC_PREFIX) else {
+                continue;
+            };
+            if let Some(value) = self.storage.get_config(&key).await? {
+                totals.insert(user_id.to_string(), serde_json::from_value(value)?);
+            }
+        }
+        Ok(totals)
+    }
+
+    async fn node_records(&self) -> Result<HashMap<String, NodeTrafficRecord>> {
+        let mut records = HashMap::new();
+        for key in self.storage.list_keys().await? {
+            let Some(node_id) = key.strip_prefix(NODE_TRAFFIC_PREFIX) else {
+                continue;
+            };
+            if let Some(value) = self.storage.get_config(&key).await? {
+                records.insert(node_id.to_string(), serde_json::from_value(value)?);
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed_storage::MemoryStorage;
+    use crate::node::Node;
+    use tokio::sync::Mutex;
+
+    struct FakeSource(Mutex<HashMap<String, UserTraffic>>);
+
+    #[async_trait]
+    impl TrafficSource for FakeSource {
+        async fn user_traffic(&self) -> Result<HashMap<String, UserTraffic>> {
+            Ok(self.0.lock().await.clone())
+        }
+    }
+
+    fn traffic(bytes_sent: u64, bytes_received: u64, connections: u64) -> UserTraffic {
+        UserTraffic {
+            bytes_sent,
+            bytes_received,
+            connections,
+            last_activity: None,
+        }
+    }
+
+    #[test]
+    fn test_counter_resets_are_carried_over() {
+        let mut record = NodeTrafficRecord::default();
+        record.apply(HashMap::from([
+            ("alice".to_string(), traffic(100, 1000, 2)),
+            ("bob".to_string(), traffic(10, 10, 1)),
+        ]));
+
+        // alice's counters restarted, bob dropped out of the report
+        record.apply(HashMap::from([("alice".to_string(), traffic(5, 50, 1))]));
+        assert_eq!(record.users["alice"].total(), traffic(105, 1050, 3));
+        assert_eq!(record.users["bob"].total(), traffic(10, 10, 1));
+
+        // Growth after a reset is not counted twice
+        record.apply(HashMap::from([("alice".to_string(), traffic(6, 60, 1))]));
+        assert_eq!(record.users["alice"].total(), traffic(106, 1060, 3));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_sums_nodes_and_keeps_unreachable_ones() {
+        let local_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::new(local_id.clone())));
+        let mut local = Node::with_id(
+            local_id.clone(),
+            "local".to_string(),
+            "127.0.0.1:19441".parse().unwrap(),
+        );
+        local.status = NodeStatus::Healthy;
+        state.write().await.add_node(local).unwrap();
+
+        let storage: Arc<dyn DistributedConfigStorage> = Arc::new(MemoryStorage::new());
+        // A node that reported earlier and is now unreachable
+        let mut remote = NodeTrafficRecord::default();
+        remote.apply(HashMap::from([("alice".to_string(), traffic(1, 2, 1))]));
+        storage
+            .store_config(
+                &format!("{}{}", NODE_TRAFFIC_PREFIX, NodeId::new()),
+                serde_json::to_value(&remote).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let aggregator = TrafficAggregator::new(
+            local_id.clone(),
+            state,
+            storage,
+            ClusterGrpcClient::new(local_id),
+        );
+        let source = Arc::new(FakeSource(Mutex::new(HashMap::from([(
+            "alice".to_string(),
+            traffic(10, 20, 1),
+        )]))));
+        aggregator.set_source(source.clone()).await;
+
+        let report = aggregator.aggregate().await.unwrap();
+        assert_eq!(report.nodes_polled, 1);
+        assert_eq!(report.users_updated, 1);
+        assert_eq!(
+            aggregator.user_total("alice").await.unwrap(),
+            Some(traffic(11, 22, 2))
+        );
+
+        // Nothing changed, nothing rewritten
+        let report = aggregator.aggregate().await.unwrap();
+        assert_eq!(report.users_updated, 0);
+
+        source
+            .0
+            .lock()
+            .await
+            .insert("bob".to_string(), traffic(3, 3, 1));
+        aggregator.aggregate().await.unwrap();
+        let totals = aggregator.cluster_traffic().await.unwrap();
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals["bob"], traffic(3, 3, 1));
+        assert_eq!(totals["alice"], traffic(11, 22, 2));
+    }
+}
@@ -0,0 +1,347 @@
+//! Replicated user store
+//!
+//! User records live in the distributed storage backend so every node sees
+//! the same set of users. Records are opaque JSON documents keyed by user ID.
+//! Every write also bumps a revision counter, which lets nodes watch a single
+//! key to learn that the user set changed. The store backs
+//! [`vpn_users::ClusterUserManager`] through its [`UserReplication`] trait.
+
+use crate::coordination::{current_timestamp, CoordinationEvent};
+use crate::distributed_storage::{ConfigChange, DistributedConfigStorage, TransactionOp};
+use crate::error::{ClusterError, Result};
+use crate::events::ClusterEventBus;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use vpn_users::{UserError, UserReplication};
+
+/// Key prefix for user records
+const USER_PREFIX: &str = "users/";
+
+/// Key holding the revision counter of the user set
+const REVISION_KEY: &str = "user-store/revision";
+
+/// Attempts at committing a write when racing with other nodes
+const MAX_COMMIT_ATTEMPTS: usize = 5;
+
+/// User records shared by all nodes of the cluster
+#[derive(Clone)]
+pub struct ReplicatedUserStore {
+    storage: Arc<dyn DistributedConfigStorage>,
+    events: Option<ClusterEventBus>,
+}
+
+impl ReplicatedUserStore {
+    /// Create a store on top of the cluster storage backend
+    pub fn new(storage: Arc<dyn DistributedConfigStorage>) -> Self {
+        Self {
+            storage,
+            events: None,
+        }
+    }
+
+    /// Publish a `UserReplicated` event on `events` for every write
+    pub fn with_events(mut self, events: ClusterEventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Insert or replace a user record, returning the new revision
+    pub async fn put(&self, id: &str, record: Value) -> Result<u64> {
+        let revision = self
+            .commit(TransactionOp::Set {
+                key: Self::user_key(id)?,
+                value: record,
+            })
+            .await?;
+
+        self.publish(id, revision, false);
+        Ok(revision)
+    }
+
+    /// Remove a user record, returning it if it existed
+    pub async fn remove(&self, id: &str) -> Result<Option<Value>> {
+        let key = Self::user_key(id)?;
+        let existing = self.storage.get_config(&key).await?;
+
+        if existing.is_some() {
+            let revision = self.commit(TransactionOp::Delete { key }).await?;
+            self.publish(id, revision, true);
+        }
+
+        Ok(existing)
+    }
+
+    /// Get a single user record
+    pub async fn get(&self, id: &str) -> Result<Option<Value>> {
+        self.storage.get_config(&Self::user_key(id)?).await
+    }
+
+    /// All user records keyed by user ID
+    pub async fn list(&self) -> Result<HashMap<String, Value>> {
+        let all = self.storage.get_all_config().await?;
+
+        Ok(all
+            .into_iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(USER_PREFIX)
+                    .map(|id| (id.to_string(), value))
+            })
+            .collect())
+    }
+
+    /// Write records received from another node, skipping those already
+    /// held unchanged. Records missing from `users` are kept. Returns how
+    /// many records were written.
+    pub async fn import(&self, users: HashMap<String, Value>) -> Result<usize> {
+        let mut written = 0;
+        for (id, record) in users {
+            if self.get(&id).await?.as_ref() != Some(&record) {
+                self.put(&id, record).await?;
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Revision of the user set; zero before the first write
+    pub async fn revision(&self) -> Result<u64> {
+        Ok(self
+            .storage
+            .get_config(REVISION_KEY)
+            .await?
+            .and_then(|value| value.as_u64())
+            .unwrap_or(0))
+    }
+
+    /// Receive a change event whenever the user set is modified
+    pub async fn watch(&self) -> Result<mpsc::Receiver<ConfigChange>> {
+        self.storage.watch_config(REVISION_KEY).await
+    }
+
+    /// Apply `op` together with a revision bump, retrying if another node
+    /// bumped the revision first
+    async fn commit(&self, op: TransactionOp) -> Result<u64> {
+        let mut last_error = None;
+
+        for _ in 0..MAX_COMMIT_ATTEMPTS {
+            let current = self.storage.get_config(REVISION_KEY).await?;
+            let next = current.as_ref().and_then(|v| v.as_u64()).unwrap_or(0) + 1;
+
+            let ops = vec![
+                op.clone(),
+                TransactionOp::ConditionalSet {
+                    key: REVISION_KEY.to_string(),
+                    value: Value::from(next),
+                    expected: current,
+                },
+            ];
+
+            match self.storage.transaction(ops).await {
+                Ok(()) => return Ok(next),
+                Err(e) => {
+                    tracing::debug!("User store commit conflicted, retrying: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| ClusterError::coordination("User store commit failed")))
+    }
+
+    fn publish(&self, id: &str, revision: u64, removed: bool) {
+        if let Some(events) = &self.events {
+            events.publish(CoordinationEvent::UserReplicated {
+                user_id: id.to_string(),
+                revision,
+                removed,
+                timestamp: current_timestamp(),
+            });
+        }
+    }
+
+    fn user_key(id: &str) -> Result<String> {
+        if id.is_empty() || id.contains('/') {
+            return Err(ClusterError::invalid_state(format!(
+                "Invalid user ID: '{}'",
+                id
+            )));
+        }
+
+        Ok(format!("{}{}", USER_PREFIX, id))
+    }
+}
+
+#[async_trait]
+impl UserReplication for ReplicatedUserStore {
+    async fn list(&self) -> vpn_users::Result<HashMap<String, Value>> {
+        ReplicatedUserStore::list(self)
+            .await
+            .map_err(replication_error)
+    }
+
+    async fn put(&self, id: &str, record: Value) -> vpn_users::Result<()> {
+        ReplicatedUserStore::put(self, id, record)
+            .await
+            .map(|_| ())
+            .map_err(replication_error)
+    }
+
+    async fn remove(&self, id: &str) -> vpn_users::Result<bool> {
+        ReplicatedUserStore::remove(self, id)
+            .await
+            .map(|existing| existing.is_some())
+            .map_err(replication_error)
+    }
+
+    async fn watch(&self) -> vpn_users::Result<mpsc::Receiver<()>> {
+        let mut changes = ReplicatedUserStore::watch(self)
+            .await
+            .map_err(replication_error)?;
+
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while changes.recv().await.is_some() {
+                if tx.send(()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+}
+
+fn replication_error(e: ClusterError) -> UserError {
+    UserError::ReplicationError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed_storage::MemoryStorage;
+    use serde_json::json;
+
+    fn store() -> ReplicatedUserStore {
+        ReplicatedUserStore::new(Arc::new(MemoryStorage::new()))
+    }
+
+    #[tokio::test]
+    async fn test_put_and_list_users() {
+        let store = store();
+        assert_eq!(store.revision().await.unwrap(), 0);
+
+        assert_eq!(
+            store.put("alice", json!({"name": "alice"})).await.unwrap(),
+            1
+        );
+        assert_eq!(store.put("bob", json!({"name": "bob"})).await.unwrap(), 2);
+
+        let users = store.list().await.unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users["alice"], json!({"name": "alice"}));
+        assert_eq!(store.revision().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_remove_user() {
+        let store = store();
+        store.put("alice", json!({"name": "alice"})).await.unwrap();
+
+        assert!(store.remove("alice").await.unwrap().is_some());
+        assert!(store.get("alice").await.unwrap().is_none());
+        assert_eq!(store.revision().await.unwrap(), 2);
+
+        // Removing a missing user does not bump the revision
+        assert!(store.remove("alice").await.unwrap().is_none());
+        assert_eq!(store.revision().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_import_skips_unchanged_records() {
+        let store = store();
+        store.put("alice", json!({"name": "alice"})).await.unwrap();
+        store.put("carol", json!({"name": "carol"})).await.unwrap();
+
+        let received = HashMap::from([
+            ("alice".to_string(), json!({"name": "alice"})),
+            ("bob".to_string(), json!({"name": "bob"})),
+        ]);
+        assert_eq!(store.import(received).await.unwrap(), 1);
+        assert_eq!(store.list().await.unwrap().len(), 3);
+        assert_eq!(store.revision().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_watch_reports_changes() {
+        let store = store();
+        let mut changes = store.watch().await.unwrap();
+
+        store.put("alice", json!({"name": "alice"})).await.unwrap();
+
+        let change = tokio::time::timeout(std::time::Duration::from_secs(1), changes.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.new_value, Some(json!(1)));
+    }
+
+    #[tokio::test]
+    async fn test_writes_publish_events() {
+        let events = ClusterEventBus::default();
+        let store = store().with_events(events.clone());
+        let mut stream = events.stream();
+
+        store.put("alice", json!({"name": "alice"})).await.unwrap();
+        store.remove("alice").await.unwrap();
+
+        for (expected_revision, expected_removed) in [(1, false), (2, true)] {
+            match stream.next_event().await.unwrap() {
+                CoordinationEvent::UserReplicated {
+                    user_id,
+                    revision,
+                    removed,
+                    ..
+                } => {
+                    assert_eq!(user_id, "alice");
+                    assert_eq!(revision, expected_revision);
+                    assert_eq!(removed, expected_removed);
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_user_id() {
+        let store = store();
+        assert!(store.put("", json!({})).await.is_err());
+        assert!(store.put("a/b", json!({})).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_backs_cluster_user_manager() {
+        use vpn_users::config::ServerConfig;
+        use vpn_users::{ClusterUserManager, UserManager, VpnProtocol};
+
+        let store = Arc::new(store());
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let node = |dir: &tempfile::TempDir| {
+            let local = UserManager::new(dir.path(), ServerConfig::default()).unwrap();
+            ClusterUserManager::new(Arc::new(local), store.clone())
+        };
+        let (node_a, node_b) = (node(&dir_a), node(&dir_b));
+
+        let user = node_a
+            .create_user("alice".to_string(), VpnProtocol::Vless)
+            .await
+            .unwrap();
+        assert!(store.get(&user.id).await.unwrap().is_some());
+
+        assert!(node_b.sync().await.unwrap());
+        assert_eq!(node_b.get_user(&user.id).await.unwrap().name, "alice");
+    }
+}
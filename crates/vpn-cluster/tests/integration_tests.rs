@@ -22,10 +22,26 @@ async fn test_cluster_formation() {
         storage_backend: StorageBackendConfig::Memory,
         consensus_algorithm: ConsensusAlgorithm::Simple,
         is_initial_node: true,
+        learner: false,
         bootstrap_nodes: vec![],
         gossip_interval: Duration::from_secs(1),
         heartbeat_interval: Duration::from_millis(500),
         election_timeout: Duration::from_secs(5),
+        tls: None,
+        admin_api: false,
+        transport: Default::default(),
+        gossip: Default::default(),
+        health_scoring: Default::default(),
+        rolling_restart: Default::default(),
+        clock_skew: Default::default(),
+        load_balancer: None,
+        region: None,
+        zone: None,
+        labels: Default::default(),
+        data_dir: std::env::temp_dir(),
+        consensus_log: Default::default(),
+        config_conflicts: Default::default(),
+        history: Default::default(),
     };
 
     let mut node1 = ClusterManager::new(node1_config).await.unwrap();
@@ -69,10 +85,26 @@ async fn test_node_join_cluster() {
         storage_backend: StorageBackendConfig::Memory,
         consensus_algorithm: ConsensusAlgorithm::Simple,
         is_initial_node: true,
+        learner: false,
         bootstrap_nodes: vec![],
         gossip_interval: Duration::from_secs(1),
         heartbeat_interval: Duration::from_millis(500),
         election_timeout: Duration::from_secs(5),
+        tls: None,
+        admin_api: false,
+        transport: Default::default(),
+        gossip: Default::default(),
+        health_scoring: Default::default(),
+        rolling_restart: Default::default(),
+        clock_skew: Default::default(),
+        load_balancer: None,
+        region: None,
+        zone: None,
+        labels: Default::default(),
+        data_dir: std::env::temp_dir(),
+        consensus_log: Default::default(),
+        config_conflicts: Default::default(),
+        history: Default::default(),
     };
 
     let mut node1 = ClusterManager::new(node1_config).await.unwrap();
@@ -89,10 +121,26 @@ async fn test_node_join_cluster() {
         storage_backend: StorageBackendConfig::Memory,
         consensus_algorithm: ConsensusAlgorithm::Simple,
         is_initial_node: false,
+        learner: false,
         bootstrap_nodes: vec!["127.0.0.1:9101".parse().unwrap()],
         gossip_interval: Duration::from_secs(1),
         heartbeat_interval: Duration::from_millis(500),
         election_timeout: Duration::from_secs(5),
+        tls: None,
+        admin_api: false,
+        transport: Default::default(),
+        gossip: Default::default(),
+        health_scoring: Default::default(),
+        rolling_restart: Default::default(),
+        clock_skew: Default::default(),
+        load_balancer: None,
+        region: None,
+        zone: None,
+        labels: Default::default(),
+        data_dir: std::env::temp_dir(),
+        consensus_log: Default::default(),
+        config_conflicts: Default::default(),
+        history: Default::default(),
     };
 
     let mut node2 = ClusterManager::new(node2_config).await.unwrap();
@@ -146,10 +194,26 @@ async fn test_heartbeat_communication() {
         storage_backend: StorageBackendConfig::Memory,
         consensus_algorithm: ConsensusAlgorithm::Simple,
         is_initial_node: true,
+        learner: false,
         bootstrap_nodes: vec![],
         gossip_interval: Duration::from_secs(1),
         heartbeat_interval: Duration::from_millis(500),
         election_timeout: Duration::from_secs(5),
+        tls: None,
+        admin_api: false,
+        transport: Default::default(),
+        gossip: Default::default(),
+        health_scoring: Default::default(),
+        rolling_restart: Default::default(),
+        clock_skew: Default::default(),
+        load_balancer: None,
+        region: None,
+        zone: None,
+        labels: Default::default(),
+        data_dir: std::env::temp_dir(),
+        consensus_log: Default::default(),
+        config_conflicts: Default::default(),
+        history: Default::default(),
     };
 
     let mut node = ClusterManager::new(node_config).await.unwrap();
@@ -177,7 +241,7 @@ async fn test_heartbeat_communication() {
     .await;
 
     assert!(heartbeat_response.is_ok(), "Heartbeat should succeed");
-    let response = heartbeat_response.unwrap().unwrap();
+    let (response, _) = heartbeat_response.unwrap().unwrap();
     assert!(response.success);
     assert!(response.server_time > 0);
 
@@ -198,10 +262,26 @@ async fn test_client_join_operations() {
         storage_backend: StorageBackendConfig::Memory,
         consensus_algorithm: ConsensusAlgorithm::Simple,
         is_initial_node: true,
+        learner: false,
         bootstrap_nodes: vec![],
         gossip_interval: Duration::from_secs(1),
         heartbeat_interval: Duration::from_millis(500),
         election_timeout: Duration::from_secs(5),
+        tls: None,
+        admin_api: false,
+        transport: Default::default(),
+        gossip: Default::default(),
+        health_scoring: Default::default(),
+        rolling_restart: Default::default(),
+        clock_skew: Default::default(),
+        load_balancer: None,
+        region: None,
+        zone: None,
+        labels: Default::default(),
+        data_dir: std::env::temp_dir(),
+        consensus_log: Default::default(),
+        config_conflicts: Default::default(),
+        history: Default::default(),
     };
 
     let mut node = ClusterManager::new(node_config).await.unwrap();
@@ -261,10 +341,26 @@ async fn test_concurrent_operations() {
         storage_backend: StorageBackendConfig::Memory,
         consensus_algorithm: ConsensusAlgorithm::Simple,
         is_initial_node: true,
+        learner: false,
         bootstrap_nodes: vec![],
         gossip_interval: Duration::from_secs(1),
         heartbeat_interval: Duration::from_millis(500),
         election_timeout: Duration::from_secs(5),
+        tls: None,
+        admin_api: false,
+        transport: Default::default(),
+        gossip: Default::default(),
+        health_scoring: Default::default(),
+        rolling_restart: Default::default(),
+        clock_skew: Default::default(),
+        load_balancer: None,
+        region: None,
+        zone: None,
+        labels: Default::default(),
+        data_dir: std::env::temp_dir(),
+        consensus_log: Default::default(),
+        config_conflicts: Default::default(),
+        history: Default::default(),
     };
 
     let mut node = ClusterManager::new(node_config).await.unwrap();
@@ -303,7 +399,7 @@ async fn test_concurrent_operations() {
     );
 
     let status = status_result.unwrap();
-    let heartbeat = heartbeat_result.unwrap();
+    let (heartbeat, _) = heartbeat_result.unwrap();
 
     assert!(status.cluster_state.is_some());
     assert!(heartbeat.success);
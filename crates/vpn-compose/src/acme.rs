@@ -0,0 +1,490 @@
+//! Traefik ACME certificate configuration
+//!
+//! Builds the `certificatesResolvers` section of Traefik's static
+//! configuration and a compose override that hands DNS provider credentials
+//! to the proxy as Docker secrets and requests a certificate for every
+//! configured domain from its resolver.
+
+use crate::error::{ComposeError, Result};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::path::PathBuf;
+
+/// Let's Encrypt staging directory, for testing without rate limits
+pub const LETSENCRYPT_STAGING_CA: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// ACME settings for the Traefik proxy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeConfig {
+    /// Account email registered with the CA
+    pub email: String,
+
+    /// Where Traefik stores issued certificates
+    #[serde(default = "default_storage")]
+    pub storage: String,
+
+    /// Name of the proxy service in the compose file
+    #[serde(default = "default_proxy_service")]
+    pub proxy_service: String,
+
+    /// Entry point HTTPS routers listen on
+    #[serde(default = "default_tls_entry_point")]
+    pub tls_entry_point: String,
+
+    /// Certificate resolvers by name
+    pub resolvers: Vec<CertificateResolver>,
+
+    /// Domains to obtain certificates for
+    #[serde(default)]
+    pub domains: Vec<DomainCertificate>,
+}
+
+/// A named ACME certificate resolver
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateResolver {
+    pub name: String,
+
+    pub challenge: AcmeChallenge,
+
+    /// CA directory URL; Let's Encrypt production when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_server: Option<String>,
+}
+
+/// How the CA verifies domain ownership
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AcmeChallenge {
+    /// HTTP-01 on a plain HTTP entry point
+    Http {
+        #[serde(default = "default_http_entry_point")]
+        entry_point: String,
+    },
+
+    /// DNS-01 through a lego DNS provider; required for wildcard certificates
+    Dns {
+        /// lego provider name, e.g. `cloudflare` or `route53`
+        provider: String,
+
+        /// Provider credentials, passed to the proxy as Docker secrets
+        #[serde(default)]
+        credentials: Vec<AcmeCredential>,
+
+        /// DNS servers used to check propagation
+        #[serde(default)]
+        resolvers: Vec<String>,
+    },
+}
+
+/// A DNS provider credential read from a Docker secret
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeCredential {
+    /// Variable the provider expects, e.g. `CF_DNS_API_TOKEN`; the proxy gets
+    /// `<env>_FILE` pointing at the secret
+    pub env: String,
+
+    /// Docker secret name
+    pub secret: String,
+
+    /// Local file backing the secret; the secret is external when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// A certificate to request, with the resolver that issues it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainCertificate {
+    /// Main domain, e.g. `vpn.example.com` or `*.example.com`
+    pub domain: String,
+
+    /// Subject alternative names
+    #[serde(default)]
+    pub sans: Vec<String>,
+
+    /// Resolver name; the first resolver when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<String>,
+}
+
+fn default_storage() -> String {
+    "/etc/traefik/acme.json".to_string()
+}
+
+fn default_proxy_service() -> String {
+    "traefik".to_string()
+}
+
+fn default_tls_entry_point() -> String {
+    "websecure".to_string()
+}
+
+fn default_http_entry_point() -> String {
+    "web".to_string()
+}
+
+impl AcmeConfig {
+    /// Single Let's Encrypt resolver using the HTTP challenge
+    pub fn http(email: impl Into<String>) -> Self {
+        Self {
+            email: email.into(),
+            storage: default_storage(),
+            proxy_service: default_proxy_service(),
+            tls_entry_point: default_tls_entry_point(),
+            resolvers: vec![CertificateResolver {
+                name: "letsencrypt".to_string(),
+                challenge: AcmeChallenge::Http {
+                    entry_point: default_http_entry_point(),
+                },
+                ca_server: None,
+            }],
+            domains: Vec::new(),
+        }
+    }
+
+    /// Add a resolver
+    pub fn with_resolver(mut self, resolver: CertificateResolver) -> Self {
+        self.resolvers.push(resolver);
+        self
+    }
+
+    /// Request a certificate for `domain` from `resolver`
+    pub fn with_domain(
+        mut self,
+        domain: impl Into<String>,
+        sans: Vec<String>,
+        resolver: Option<&str>,
+    ) -> Self {
+        self.domains.push(DomainCertificate {
+            domain: domain.into(),
+            sans,
+            resolver: resolver.map(str::to_string),
+        });
+        self
+    }
+
+    /// Validate resolvers and domain assignments
+    pub fn validate(&self) -> Result<()> {
+        if !self.email.contains('@') {
+            return Err(ComposeError::config_error(format!(
+                "Invalid ACME email: {}",
+                self.email
+            )));
+        }
+
+        if self.resolvers.is_empty() {
+            return Err(ComposeError::config_error(
+                "ACME requires at least one certificate resolver",
+            ));
+        }
+
+        for (index, resolver) in self.resolvers.iter().enumerate() {
+            if self.resolvers[..index]
+                .iter()
+                .any(|other| other.name == resolver.name)
+            {
+                return Err(ComposeError::config_error(format!(
+                    "Duplicate certificate resolver: {}",
+                    resolver.name
+                )));
+            }
+        }
+
+        for certificate in &self.domains {
+            let resolver = self.resolver_for(certificate)?;
+            let wildcard = std::iter::once(&certificate.domain)
+                .chain(&certificate.sans)
+                .any(|name| name.starts_with("*."));
+            if wildcard && matches!(resolver.challenge, AcmeChallenge::Http { .. }) {
+                return Err(ComposeError::config_error(format!(
+                    "Wildcard certificate for {} needs a DNS challenge resolver, {} uses HTTP",
+                    certificate.domain, resolver.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolver that issues the certificate for `certificate`
+    pub fn resolver_for(&self, certificate: &DomainCertificate) -> Result<&CertificateResolver> {
+        match &certificate.resolver {
+            Some(name) => self
+                .resolvers
+                .iter()
+                .find(|resolver| &resolver.name == name)
+                .ok_or_else(|| {
+                    ComposeError::config_error(format!(
+                        "Unknown certificate resolver {} for {}",
+                        name, certificate.domain
+                    ))
+                }),
+            None => self
+                .resolvers
+                .first()
+                .ok_or_else(|| ComposeError::config_error("No certificate resolvers configured")),
+        }
+    }
+
+    /// `certificatesResolvers` section of Traefik's static configuration
+    pub fn certificates_resolvers(&self) -> Value {
+        let mut resolvers = Mapping::new();
+
+        for resolver in &self.resolvers {
+            let mut acme = Mapping::new();
+            acme.insert("email".into(), self.email.clone().into());
+            acme.insert("storage".into(), self.storage.clone().into());
+            if let Some(ca_server) = &resolver.ca_server {
+                acme.insert("caServer".into(), ca_server.clone().into());
+            }
+
+            match &resolver.challenge {
+                AcmeChallenge::Http { entry_point } => {
+                    let mut challenge = Mapping::new();
+                    challenge.insert("entryPoint".into(), entry_point.clone().into());
+                    acme.insert("httpChallenge".into(), challenge.into());
+                }
+                AcmeChallenge::Dns {
+                    provider,
+                    resolvers: dns_servers,
+                    ..
+                } => {
+                    let mut challenge = Mapping::new();
+                    challenge.insert("provider".into(), provider.clone().into());
+                    if !dns_servers.is_empty() {
+                        challenge.insert("resolvers".into(), string_sequence(dns_servers));
+                    }
+                    acme.insert("dnsChallenge".into(), challenge.into());
+                }
+            }
+
+            let mut entry = Mapping::new();
+            entry.insert("acme".into(), acme.into());
+            resolvers.insert(resolver.name.clone().into(), entry.into());
+        }
+
+        resolvers.into()
+    }
+
+    /// Replace the certificate resolvers in a rendered Traefik static
+    /// configuration and make the first resolver the TLS entry point's default
+    pub fn apply_to_static_config(&self, config: &mut Value) -> Result<()> {
+        let root = config.as_mapping_mut().ok_or_else(|| {
+            ComposeError::config_error("Traefik static configuration is not a mapping")
+        })?;
+        root.insert(
+            "certificatesResolvers".into(),
+            self.certificates_resolvers(),
+        );
+
+        if let Some(default) = self.resolvers.first() {
+            let tls = mapping_entry(
+                mapping_entry(
+                    mapping_entry(mapping_entry(root, "entryPoints"), &self.tls_entry_point),
+                    "http",
+                ),
+                "tls",
+            );
+            tls.insert("certResolver".into(), default.name.clone().into());
+        }
+
+        Ok(())
+    }
+
+    /// Compose override for the proxy service: credential secrets and one
+    /// router per domain, so certificates are requested at startup rather
+    /// than on first request
+    pub fn compose_override(&self) -> Result<Value> {
+        let mut environment = Mapping::new();
+        let mut service_secrets = Vec::new();
+        let mut secrets = Mapping::new();
+
+        for resolver in &self.resolvers {
+            if let AcmeChallenge::Dns { credentials, .. } = &resolver.challenge {
+                for credential in credentials {
+                    environment.insert(
+                        format!("{}_FILE", credential.env).into(),
+                        format!("/run/secrets/{}", credential.secret).into(),
+                    );
+                    service_secrets.push(credential.secret.clone());
+
+                    let mut secret = Mapping::new();
+                    match &credential.file {
+                        Some(file) => {
+                            secret.insert("file".into(), file.to_string_lossy().to_string().into())
+                        }
+                        None => secret.insert("external".into(), true.into()),
+                    };
+                    secrets.insert(credential.secret.clone().into(), secret.into());
+                }
+            }
+        }
+
+        let mut labels = Mapping::new();
+        labels.insert("traefik.enable".into(), "true".into());
+        for (index, certificate) in self.domains.iter().enumerate() {
+            let resolver = self.resolver_for(certificate)?;
+            let router = format!("traefik.http.routers.acme-{}", index);
+            let host = certificate.domain.trim_start_matches("*.");
+
+            labels.insert(
+                format!("{}.rule", router).into(),
+                format!("Host(`{}`)", host).into(),
+            );
+            labels.insert(
+                format!("{}.entrypoints", router).into(),
+                self.tls_entry_point.clone().into(),
+            );
+            labels.insert(format!("{}.service", router).into(), "noop@internal".into());
+            labels.insert(
+                format!("{}.tls.certresolver", router).into(),
+                resolver.name.clone().into(),
+            );
+            labels.insert(
+                format!("{}.tls.domains[0].main", router).into(),
+                certificate.domain.clone().into(),
+            );
+            if !certificate.sans.is_empty() {
+                labels.insert(
+                    format!("{}.tls.domains[0].sans", router).into(),
+                    certificate.sans.join(",").into(),
+                );
+            }
+        }
+
+        let mut service = Mapping::new();
+        service.insert("environment".into(), environment.into());
+        service.insert("labels".into(), labels.into());
+        if !service_secrets.is_empty() {
+            service.insert("secrets".into(), string_sequence(&service_secrets));
+        }
+
+        let mut services = Mapping::new();
+        services.insert(self.proxy_service.clone().into(), service.into());
+
+        let mut root = Mapping::new();
+        root.insert("services".into(), services.into());
+        if !secrets.is_empty() {
+            root.insert("secrets".into(), secrets.into());
+        }
+
+        Ok(root.into())
+    }
+}
+
+fn string_sequence(items: &[String]) -> Value {
+    Value::Sequence(items.iter().cloned().map(Value::from).collect())
+}
+
+/// Child mapping under `key`, created or replaced if missing or not a mapping
+fn mapping_entry<'a>(parent: &'a mut Mapping, key: &str) -> &'a mut Mapping {
+    let key = Value::from(key);
+    if !parent.get(&key).is_some_and(Value::is_mapping) {
+        parent.insert(key.clone(), Mapping::new().into());
+    }
+
+    match parent.get_mut(&key) {
+        Some(Value::Mapping(mapping)) => mapping,
+        _ => unreachable!("entry was just set to a mapping"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dns_config() -> AcmeConfig {
+        AcmeConfig::http("ops@example.com")
+            .with_resolver(CertificateResolver {
+                name: "cloudflare".to_string(),
+                challenge: AcmeChallenge::Dns {
+                    provider: "cloudflare".to_string(),
+                    credentials: vec![AcmeCredential {
+                        env: "CF_DNS_API_TOKEN".to_string(),
+                        secret: "cloudflare_api_token".to_string(),
+                        file: None,
+                    }],
+                    resolvers: vec!["1.1.1.1:53".to_string()],
+                },
+                ca_server: Some(LETSENCRYPT_STAGING_CA.to_string()),
+            })
+            .with_domain("vpn.example.com", vec![], None)
+            .with_domain(
+                "*.example.com",
+                vec!["example.com".to_string()],
+                Some("cloudflare"),
+            )
+    }
+
+    #[test]
+    fn test_static_config_resolvers() {
+        let config = dns_config();
+        config.validate().unwrap();
+
+        let mut traefik: Value =
+            serde_yaml::from_str("entryPoints:\n  websecure:\n    address: \":443\"\n").unwrap();
+        config.apply_to_static_config(&mut traefik).unwrap();
+
+        let resolvers = &traefik["certificatesResolvers"];
+        assert_eq!(
+            resolvers["letsencrypt"]["acme"]["httpChallenge"]["entryPoint"],
+            "web"
+        );
+        assert_eq!(
+            resolvers["cloudflare"]["acme"]["dnsChallenge"]["provider"],
+            "cloudflare"
+        );
+        assert_eq!(
+            resolvers["cloudflare"]["acme"]["caServer"],
+            LETSENCRYPT_STAGING_CA
+        );
+        assert_eq!(
+            traefik["entryPoints"]["websecure"]["http"]["tls"]["certResolver"],
+            "letsencrypt"
+        );
+        assert_eq!(traefik["entryPoints"]["websecure"]["address"], ":443");
+    }
+
+    #[test]
+    fn test_compose_override() {
+        let value = dns_config().compose_override().unwrap();
+        let traefik = &value["services"]["traefik"];
+
+        assert_eq!(
+            traefik["environment"]["CF_DNS_API_TOKEN_FILE"],
+            "/run/secrets/cloudflare_api_token"
+        );
+        assert_eq!(traefik["secrets"][0], "cloudflare_api_token");
+        assert_eq!(value["secrets"]["cloudflare_api_token"]["external"], true);
+
+        let labels = &traefik["labels"];
+        assert_eq!(
+            labels["traefik.http.routers.acme-0.tls.certresolver"],
+            "letsencrypt"
+        );
+        assert_eq!(
+            labels["traefik.http.routers.acme-1.tls.certresolver"],
+            "cloudflare"
+        );
+        assert_eq!(
+            labels["traefik.http.routers.acme-1.tls.domains[0].main"],
+            "*.example.com"
+        );
+        assert_eq!(
+            labels["traefik.http.routers.acme-1.rule"],
+            "Host(`example.com`)"
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        // Wildcards cannot be issued over HTTP-01
+        let config = AcmeConfig::http("ops@example.com").with_domain("*.example.com", vec![], None);
+        assert!(config.validate().is_err());
+
+        let config =
+            AcmeConfig::http("ops@example.com").with_domain("vpn.example.com", vec![], Some("dns"));
+        assert!(config.validate().is_err());
+
+        assert!(AcmeConfig::http("not-an-email").validate().is_err());
+    }
+}
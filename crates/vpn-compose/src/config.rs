@@ -1,9 +1,12 @@
 //! Configuration management for Docker Compose orchestration
 
+use crate::acme::AcmeConfig;
 use crate::error::{ComposeError, Result};
+use crate::preflight::PreflightConfig;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use vpn_docker::EnvEncryption;
 
 /// Main configuration for Docker Compose orchestration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +37,19 @@ pub struct ComposeConfig {
 
     /// Docker Compose version
     pub compose_version: String,
+
+    /// ACME certificates for the Traefik proxy; certificates are managed by
+    /// hand when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acme: Option<AcmeConfig>,
+
+    /// Host prerequisites verified before services are brought up
+    #[serde(default)]
+    pub preflight: PreflightConfig,
+
+    /// Encryption of the generated `.env`; written in plaintext when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_encryption: Option<EnvEncryption>,
 }
 
 impl Default for ComposeConfig {
@@ -68,6 +84,9 @@ impl Default for ComposeConfig {
             volumes,
             env_vars,
             compose_version: "3.8".to_string(),
+            acme: None,
+            preflight: PreflightConfig::default(),
+            env_encryption: None,
         }
     }
 }
@@ -501,6 +520,10 @@ impl ComposeConfig {
             }
         }
 
+        if let Some(acme) = &self.acme {
+            acme.validate()?;
+        }
+
         Ok(())
     }
 
@@ -72,6 +72,9 @@ pub enum ComposeError {
 
     #[error("High availability error: {message}")]
     HAError { message: String },
+
+    #[error("Preflight check failed: {message}")]
+    PreflightFailed { message: String },
 }
 
 impl ComposeError {
@@ -168,4 +171,11 @@ impl ComposeError {
             message: message.into(),
         }
     }
+
+    /// Create a preflight error
+    pub fn preflight_failed(message: impl Into<String>) -> Self {
+        Self::PreflightFailed {
+            message: message.into(),
+        }
+    }
 }
@@ -6,6 +6,7 @@ use crate::error::{ComposeError, Result};
 use crate::template::{TemplateContext, TemplateManager};
 use std::path::PathBuf;
 use tracing::{debug, info};
+use vpn_docker::{compose_schema, ComposeSchema, SealedEnvFile};
 
 /// Options for compose file generation
 #[derive(Debug, Clone)]
@@ -14,6 +15,9 @@ pub struct GeneratorOptions {
     pub environment: String,
     pub include_monitoring: bool,
     pub include_dev_tools: bool,
+    /// Compose file format to write, detected from the installed Compose
+    /// when unset
+    pub schema: Option<ComposeSchema>,
 }
 
 impl Default for GeneratorOptions {
@@ -23,6 +27,7 @@ impl Default for GeneratorOptions {
             environment: "development".to_string(),
             include_monitoring: true,
             include_dev_tools: false,
+            schema: None,
         }
     }
 }
@@ -87,6 +92,7 @@ impl ComposeGenerator {
         debug!("Generating main docker-compose.yml");
 
         let compose_content = self.template_manager.generate_compose_file(context)?;
+        let compose_content = self.schema().render(&compose_content)?;
         let output_path = self.options.output_dir.join("docker-compose.yml");
 
         tokio::fs::write(&output_path, compose_content)
@@ -96,6 +102,22 @@ impl ComposeGenerator {
             })?;
 
         info!("Generated docker-compose.yml");
+
+        // Standalone override for stacks that add the proxy separately
+        if let Some(acme) = &context.acme {
+            let acme_content = serde_yaml::to_string(&acme.compose_override()?)?;
+            let acme_content = self.schema().render(&acme_content)?;
+            let output_path = self.options.output_dir.join("docker-compose.acme.yml");
+
+            tokio::fs::write(&output_path, acme_content)
+                .await
+                .map_err(|_e| {
+                    ComposeError::file_operation_failed("write", output_path.to_string_lossy())
+                })?;
+
+            info!("Generated docker-compose.acme.yml");
+        }
+
         Ok(())
     }
 
@@ -108,6 +130,7 @@ impl ComposeGenerator {
 
         if let Some(env_name) = &context.environment {
             if let Ok(override_content) = self.template_manager.render_template(env_name, context) {
+                let override_content = self.schema().render(&override_content)?;
                 let filename = format!("docker-compose.{}.yml", env_name);
                 let output_path = self.options.output_dir.join(filename);
 
@@ -176,6 +199,10 @@ impl ComposeGenerator {
 
         env_content.push_str(&format!("JWT_SECRET={}\n", context.security.jwt_secret));
 
+        if let Some(acme) = &context.acme {
+            env_content.push_str(&format!("ACME_EMAIL={}\n", acme.email));
+        }
+
         if context.monitoring.enabled {
             env_content.push_str(&format!(
                 "PROMETHEUS_PORT={}\n",
@@ -193,6 +220,13 @@ impl ComposeGenerator {
             env_content.push_str(&format!("{}={}\n", key, value));
         }
 
+        if let Some(encryption) = &self.config.env_encryption {
+            let sealed =
+                SealedEnvFile::seal(&self.options.output_dir, &env_content, encryption).await?;
+            info!("Generated encrypted env file {}", sealed.path().display());
+            return Ok(());
+        }
+
         let output_path = self.options.output_dir.join(".env");
         tokio::fs::write(&output_path, env_content)
             .await
@@ -244,6 +278,7 @@ impl ComposeGenerator {
 
         // Add all environment variables to context
         context.env_vars = self.config.env_vars.clone();
+        context.acme = self.config.acme.clone();
 
         // Environment-specific adjustments
         match self.options.environment.as_str() {
@@ -274,6 +309,11 @@ impl ComposeGenerator {
         Ok(())
     }
 
+    /// Compose file format the generated files are written in
+    pub fn schema(&self) -> ComposeSchema {
+        self.options.schema.unwrap_or_else(compose_schema)
+    }
+
     /// Set generation options
     pub fn set_options(&mut self, options: GeneratorOptions) {
         self.options = options;
@@ -386,6 +426,100 @@ services:
         assert!(compose_file.exists());
     }
 
+    #[tokio::test]
+    async fn test_generate_for_legacy_compose() {
+        let (mut generator, _temp_dir) = create_test_generator().await;
+        let output_dir = generator.options.output_dir.clone();
+
+        generator.set_options(GeneratorOptions {
+            output_dir: output_dir.clone(),
+            schema: Some(ComposeSchema::Specification),
+            ..GeneratorOptions::default()
+        });
+        generator.generate_compose_files().await.unwrap();
+        let compose = tokio::fs::read_to_string(output_dir.join("docker-compose.yml"))
+            .await
+            .unwrap();
+        assert!(!compose.contains("version:"));
+
+        generator.set_options(GeneratorOptions {
+            output_dir: output_dir.clone(),
+            schema: Some(ComposeSchema::Legacy { minor: 7 }),
+            ..GeneratorOptions::default()
+        });
+        generator.generate_compose_files().await.unwrap();
+        let compose = tokio::fs::read_to_string(output_dir.join("docker-compose.yml"))
+            .await
+            .unwrap();
+        assert!(compose.starts_with("version: '3.7'"));
+    }
+
+    #[tokio::test]
+    async fn test_generate_acme_configuration() {
+        let temp_dir = TempDir::new().unwrap();
+        let templates_dir = temp_dir.path().join("templates");
+        let compose_dir = temp_dir.path().join("compose");
+        tokio::fs::create_dir_all(&templates_dir).await.unwrap();
+
+        let template_content = r#"
+services:
+  traefik:
+    image: traefik:v3.0
+"#;
+        tokio::fs::write(templates_dir.join("base.yml"), template_content)
+            .await
+            .unwrap();
+
+        let acme = crate::acme::AcmeConfig::http("ops@example.com").with_domain(
+            "vpn.example.com",
+            vec![],
+            None,
+        );
+        let config = ComposeConfig {
+            templates_dir,
+            compose_dir: compose_dir.clone(),
+            acme: Some(acme),
+            ..ComposeConfig::default()
+        };
+
+        let mut generator = ComposeGenerator::new(&config).await.unwrap();
+        generator.set_options(GeneratorOptions {
+            output_dir: compose_dir.clone(),
+            ..GeneratorOptions::default()
+        });
+        generator.generate_compose_files().await.unwrap();
+
+        let compose: serde_yaml::Value = serde_yaml::from_str(
+            &tokio::fs::read_to_string(compose_dir.join("docker-compose.yml"))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            compose["services"]["traefik"]["labels"]
+                ["traefik.http.routers.acme-0.tls.certresolver"],
+            "letsencrypt"
+        );
+        assert_eq!(compose["services"]["traefik"]["image"], "traefik:v3.0");
+
+        let traefik: serde_yaml::Value = serde_yaml::from_str(
+            &tokio::fs::read_to_string(compose_dir.join("configs/traefik/traefik.yml"))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            traefik["certificatesResolvers"]["letsencrypt"]["acme"]["email"],
+            "ops@example.com"
+        );
+
+        let env = tokio::fs::read_to_string(compose_dir.join(".env"))
+            .await
+            .unwrap();
+        assert!(env.contains("ACME_EMAIL=ops@example.com"));
+        assert!(compose_dir.join("docker-compose.acme.yml").exists());
+    }
+
     #[tokio::test]
     async fn test_template_context_creation() {
         let (generator, _temp_dir) = create_test_generator().await;
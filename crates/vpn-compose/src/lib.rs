@@ -3,16 +3,19 @@
 //! This crate provides comprehensive Docker Compose orchestration for the VPN system,
 //! replacing the complex containerd abstraction with a proven, reliable solution.
 
+pub mod acme;
 pub mod config;
 pub mod environment;
 pub mod error;
 pub mod generator;
 pub mod ha;
 pub mod manager;
+pub mod preflight;
 pub mod services;
 pub mod template;
 
 // Re-export commonly used types
+pub use acme::{AcmeChallenge, AcmeConfig, AcmeCredential, CertificateResolver, DomainCertificate};
 pub use config::EnvironmentConfig;
 pub use config::{ComposeConfig, NetworkConfig, ServiceConfig, VolumeConfig};
 pub use environment::Environment;
@@ -20,8 +23,12 @@ pub use error::{ComposeError, Result};
 pub use generator::{ComposeGenerator, GeneratorOptions};
 pub use ha::{HAConfig, HAHealthStatus, HAManager, MultiRegionConfig, RoutingPolicy};
 pub use manager::{ComposeManager, ComposeStatus, ServiceStatus as ComposeServiceStatus};
+pub use preflight::{
+    HostRequirement, HostSettings, LocalHost, Preflight, PreflightConfig, PreflightReport,
+};
 pub use services::{ServiceDefinition, ServiceManager, ServiceStatus as ServiceDefinitionStatus};
 pub use template::{TemplateContext, TemplateError, TemplateManager};
+pub use vpn_docker::{ComposeCli, ComposeFlavor, ComposeSchema, ComposeVersion};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -72,6 +79,11 @@ impl ComposeOrchestrator {
         self.manager.up().await
     }
 
+    /// Check host prerequisites without bringing services up
+    pub async fn preflight(&self) -> Result<PreflightReport> {
+        self.manager.preflight().await
+    }
+
     /// Stop the VPN system
     pub async fn stop(&self) -> Result<()> {
         self.manager.down().await
@@ -161,7 +173,9 @@ pub trait ComposeProvider {
 /// Docker Compose file structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ComposeFile {
-    pub version: String,
+    /// Only written for legacy Compose, see [`ComposeSchema`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
     pub services: HashMap<String, ServiceDefinition>,
     pub networks: HashMap<String, NetworkConfig>,
     pub volumes: HashMap<String, VolumeConfig>,
@@ -170,7 +184,7 @@ pub struct ComposeFile {
 impl Default for ComposeFile {
     fn default() -> Self {
         Self {
-            version: "3.8".to_string(),
+            version: None,
             services: HashMap::new(),
             networks: HashMap::new(),
             volumes: HashMap::new(),
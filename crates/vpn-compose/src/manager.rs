@@ -2,11 +2,13 @@
 
 use crate::config::ComposeConfig;
 use crate::error::{ComposeError, Result};
+use crate::preflight::{LocalHost, Preflight, PreflightReport};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::process::Stdio;
 use tokio::process::Command;
 use tracing::{debug, info};
+use vpn_docker::{compose_command, ComposeCli, SealedEnvFile};
 
 /// Docker Compose manager for executing compose operations
 pub struct ComposeManager {
@@ -44,13 +46,15 @@ impl ComposeManager {
 
     /// Start all services (docker-compose up)
     pub async fn up(&self) -> Result<()> {
+        if self.config.preflight.enabled {
+            self.preflight().await?.ensure_ready()?;
+        }
+
         info!("Starting VPN system with Docker Compose");
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
+        let output = self
+            .command()
+            .await?
             .arg("up")
             .arg("-d")
             .arg("--remove-orphans")
@@ -68,15 +72,40 @@ impl ComposeManager {
         Ok(())
     }
 
+    /// Verify (and, when configured, apply) host prerequisites, including
+    /// the kernel modules of the images deployed
+    pub async fn preflight(&self) -> Result<PreflightReport> {
+        let images = self.images().await?;
+        let config = self
+            .config
+            .preflight
+            .clone()
+            .with_images(images.iter().map(String::as_str));
+        let report = Preflight::new(config).run(&LocalHost::default()).await?;
+
+        let changes = report.changes();
+        if !changes.is_empty() {
+            info!(
+                "Preflight changed {} host setting(s): {}",
+                changes.len(),
+                changes
+                    .iter()
+                    .map(|check| check.requirement.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        Ok(report)
+    }
+
     /// Stop all services (docker-compose down)
     pub async fn down(&self) -> Result<()> {
         info!("Stopping VPN system");
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
+        let output = self
+            .command()
+            .await?
             .arg("down")
             .arg("--remove-orphans")
             .stdout(Stdio::piped())
@@ -97,11 +126,9 @@ impl ComposeManager {
     pub async fn restart_service(&self, service: &str) -> Result<()> {
         info!("Restarting service: {}", service);
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
+        let output = self
+            .command()
+            .await?
             .arg("restart")
             .arg(service)
             .stdout(Stdio::piped())
@@ -125,11 +152,9 @@ impl ComposeManager {
     pub async fn scale_service(&self, service: &str, replicas: u32) -> Result<()> {
         info!("Scaling service {} to {} replicas", service, replicas);
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
+        let output = self
+            .command()
+            .await?
             .arg("up")
             .arg("-d")
             .arg("--scale")
@@ -156,11 +181,9 @@ impl ComposeManager {
     pub async fn get_status(&self) -> Result<ComposeStatus> {
         debug!("Getting system status");
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
+        let output = self
+            .command()
+            .await?
             .arg("ps")
             .arg("--format")
             .arg("json")
@@ -194,14 +217,8 @@ impl ComposeManager {
     pub async fn get_logs(&self, service: Option<&str>) -> Result<String> {
         debug!("Getting logs for service: {:?}", service);
 
-        let mut cmd = Command::new("docker-compose");
-        cmd.arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
-            .arg("logs")
-            .arg("--tail")
-            .arg("100");
+        let mut cmd = self.command().await?;
+        cmd.arg("logs").arg("--tail").arg("100");
 
         if let Some(service_name) = service {
             cmd.arg(service_name);
@@ -225,11 +242,9 @@ impl ComposeManager {
     pub async fn pull(&self) -> Result<()> {
         info!("Pulling latest images");
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
+        let output = self
+            .command()
+            .await?
             .arg("pull")
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -249,12 +264,8 @@ impl ComposeManager {
     pub async fn build(&self, service: Option<&str>) -> Result<()> {
         info!("Building services");
 
-        let mut cmd = Command::new("docker-compose");
-        cmd.arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
-            .arg("build");
+        let mut cmd = self.command().await?;
+        cmd.arg("build");
 
         if let Some(service_name) = service {
             cmd.arg(service_name);
@@ -279,14 +290,8 @@ impl ComposeManager {
     pub async fn exec(&self, service: &str, command: &[&str]) -> Result<String> {
         debug!("Executing command in service {}: {:?}", service, command);
 
-        let mut cmd = Command::new("docker-compose");
-        cmd.arg("-f")
-            .arg(&self.compose_file_path)
-            .arg("-p")
-            .arg(&self.project_name)
-            .arg("exec")
-            .arg("-T")
-            .arg(service);
+        let mut cmd = self.command().await?;
+        cmd.arg("exec").arg("-T").arg(service);
 
         for arg in command {
             cmd.arg(arg);
@@ -320,23 +325,60 @@ impl ComposeManager {
         Ok(())
     }
 
-    /// Check if Docker Compose is available
-    async fn check_docker_compose(&self) -> Result<()> {
-        let output = Command::new("docker-compose")
-            .arg("--version")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .await?;
+    /// Images of the configured services and of those in the compose file
+    async fn images(&self) -> Result<Vec<String>> {
+        let mut images: Vec<String> = self
+            .config
+            .services
+            .values()
+            .map(|service| service.image.clone())
+            .collect();
+
+        let compose = match tokio::fs::read_to_string(&self.compose_file_path).await {
+            Ok(compose) => compose,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(images),
+            Err(e) => return Err(e.into()),
+        };
+        let document: serde_yaml::Value = serde_yaml::from_str(&compose)?;
+        if let Some(services) = document.get("services").and_then(|s| s.as_mapping()) {
+            images.extend(
+                services
+                    .values()
+                    .filter_map(|service| service.get("image")?.as_str())
+                    .map(str::to_string),
+            );
+        }
+        Ok(images)
+    }
 
-        if !output.status.success() {
-            return Err(ComposeError::manager_init_failed(
-                "Docker Compose not found. Please install Docker Compose.",
-            ));
+    /// Compose for this project, with the variables of an encrypted env
+    /// file decrypted into its environment
+    async fn command(&self) -> Result<Command> {
+        let mut cmd = Command::from(compose_command());
+        if let Some(sealed) = SealedEnvFile::find(&self.config.compose_dir) {
+            cmd.envs(sealed.open_default().await?);
         }
+        cmd.arg("-f")
+            .arg(&self.compose_file_path)
+            .arg("-p")
+            .arg(&self.project_name);
+        Ok(cmd)
+    }
 
-        let version = String::from_utf8_lossy(&output.stdout);
-        info!("Docker Compose detected: {}", version.trim());
+    /// Check if Docker Compose is available
+    async fn check_docker_compose(&self) -> Result<()> {
+        let cli = ComposeCli::detected().ok_or_else(|| {
+            ComposeError::manager_init_failed(
+                "Docker Compose not found. Please install Docker Compose.",
+            )
+        })?;
+
+        info!(
+            "Docker Compose detected: {} {} ({:?} schema)",
+            cli.flavor,
+            cli.version,
+            cli.schema()
+        );
         Ok(())
     }
 
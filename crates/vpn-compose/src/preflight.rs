@@ -0,0 +1,653 @@
+//! Host prerequisite checks run before services are brought up
+//!
+//! The VPN containers rely on a handful of host settings that Docker cannot
+//! configure from inside a container: IP forwarding, the kernel modules of
+//! the protocols deployed (WireGuard's for WireGuard stacks) and, for the
+//! monitoring stack, generous inotify limits. The preflight phase verifies
+//! each of them and, when allowed to, applies the missing ones, recording
+//! exactly what it changed.
+
+use crate::error::{ComposeError, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// Preflight configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreflightConfig {
+    /// Run the preflight phase before `up`
+    pub enabled: bool,
+
+    /// Apply missing settings instead of only reporting them (requires root)
+    pub apply: bool,
+
+    /// Host settings to verify
+    pub requirements: Vec<HostRequirement>,
+}
+
+impl Default for PreflightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            apply: false,
+            requirements: vec![
+                HostRequirement::sysctl("net.ipv4.ip_forward", 1),
+                HostRequirement::sysctl_minimum("fs.inotify.max_user_watches", 524_288).optional(),
+                HostRequirement::sysctl_minimum("fs.inotify.max_user_instances", 512).optional(),
+            ],
+        }
+    }
+}
+
+impl PreflightConfig {
+    /// Check-only configuration with the default requirements
+    pub fn check_only() -> Self {
+        Self::default()
+    }
+
+    /// Configuration that applies missing settings
+    pub fn applying() -> Self {
+        Self {
+            apply: true,
+            ..Self::default()
+        }
+    }
+
+    /// Also require the kernel modules the containers of `images` need,
+    /// unless a requirement for them is already configured
+    pub fn with_images<'a>(mut self, images: impl IntoIterator<Item = &'a str>) -> Self {
+        for image in images {
+            for module in kernel_modules(image) {
+                let listed = self.requirements.iter().any(|requirement| {
+                    matches!(requirement, HostRequirement::KernelModule { name, .. } if name == module)
+                });
+                if !listed {
+                    self.requirements
+                        .push(HostRequirement::kernel_module(*module));
+                }
+            }
+        }
+        self
+    }
+}
+
+/// Kernel modules the host must provide for containers of `image`.
+/// Userspace protocols such as Outline, Xray and the proxies need none.
+pub fn kernel_modules(image: &str) -> &'static [&'static str] {
+    // Repository part only: registry hosts and tags do not tell the protocol
+    let name = image.split(':').next().unwrap_or(image);
+    let name = name.rsplit('/').next().unwrap_or(name);
+    match name {
+        "wireguard" | "wg-easy" => &["wireguard"],
+        _ => &[],
+    }
+}
+
+/// A single host setting the deployment depends on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostRequirement {
+    /// A kernel parameter under /proc/sys
+    Sysctl {
+        key: String,
+        value: u64,
+        /// Accept any value greater than or equal to `value`
+        #[serde(default)]
+        minimum: bool,
+        #[serde(default = "default_required")]
+        required: bool,
+    },
+    /// A loaded (or built-in) kernel module
+    KernelModule {
+        name: String,
+        #[serde(default = "default_required")]
+        required: bool,
+    },
+}
+
+fn default_required() -> bool {
+    true
+}
+
+impl HostRequirement {
+    /// Require a sysctl to hold exactly `value`
+    pub fn sysctl(key: impl Into<String>, value: u64) -> Self {
+        Self::Sysctl {
+            key: key.into(),
+            value,
+            minimum: false,
+            required: true,
+        }
+    }
+
+    /// Require a sysctl to be at least `value`
+    pub fn sysctl_minimum(key: impl Into<String>, value: u64) -> Self {
+        Self::Sysctl {
+            key: key.into(),
+            value,
+            minimum: true,
+            required: true,
+        }
+    }
+
+    /// Require a kernel module to be available
+    pub fn kernel_module(name: impl Into<String>) -> Self {
+        Self::KernelModule {
+            name: name.into(),
+            required: true,
+        }
+    }
+
+    /// Report this requirement without blocking `up` when it is unmet
+    pub fn optional(mut self) -> Self {
+        match &mut self {
+            Self::Sysctl { required, .. } | Self::KernelModule { required, .. } => {
+                *required = false
+            }
+        }
+        self
+    }
+
+    /// Whether an unmet requirement blocks `up`
+    pub fn is_required(&self) -> bool {
+        match self {
+            Self::Sysctl { required, .. } | Self::KernelModule { required, .. } => *required,
+        }
+    }
+}
+
+impl fmt::Display for HostRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sysctl {
+                key,
+                value,
+                minimum: false,
+                ..
+            } => write!(f, "sysctl {} = {}", key, value),
+            Self::Sysctl {
+                key,
+                value,
+                minimum: true,
+                ..
+            } => write!(f, "sysctl {} >= {}", key, value),
+            Self::KernelModule { name, .. } => write!(f, "kernel module {}", name),
+        }
+    }
+}
+
+/// Access to the host settings checked by the preflight phase
+#[async_trait]
+pub trait HostSettings: Send + Sync {
+    /// Read a sysctl, `None` when the key does not exist
+    async fn read_sysctl(&self, key: &str) -> Result<Option<String>>;
+
+    /// Write a sysctl
+    async fn write_sysctl(&self, key: &str, value: &str) -> Result<()>;
+
+    /// Whether a kernel module is loaded or built in
+    async fn module_loaded(&self, name: &str) -> Result<bool>;
+
+    /// Load a kernel module
+    async fn load_module(&self, name: &str) -> Result<()>;
+}
+
+/// Host settings of the machine we are running on
+#[derive(Debug, Clone)]
+pub struct LocalHost {
+    proc_sys: PathBuf,
+    sys_module: PathBuf,
+}
+
+impl Default for LocalHost {
+    fn default() -> Self {
+        Self {
+            proc_sys: PathBuf::from("/proc/sys"),
+            sys_module: PathBuf::from("/sys/module"),
+        }
+    }
+}
+
+impl LocalHost {
+    fn sysctl_path(&self, key: &str) -> PathBuf {
+        self.proc_sys.join(key.replace('.', "/"))
+    }
+}
+
+#[async_trait]
+impl HostSettings for LocalHost {
+    async fn read_sysctl(&self, key: &str) -> Result<Option<String>> {
+        match tokio::fs::read_to_string(self.sysctl_path(key)).await {
+            Ok(value) => Ok(Some(value.trim().to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write_sysctl(&self, key: &str, value: &str) -> Result<()> {
+        let path = self.sysctl_path(key);
+        tokio::fs::write(&path, value).await.map_err(|e| {
+            ComposeError::file_operation_failed(
+                "write sysctl",
+                format!("{} ({})", path.display(), e),
+            )
+        })
+    }
+
+    async fn module_loaded(&self, name: &str) -> Result<bool> {
+        // Built-in modules also show up under /sys/module
+        Ok(tokio::fs::metadata(self.sys_module.join(name))
+            .await
+            .is_ok())
+    }
+
+    async fn load_module(&self, name: &str) -> Result<()> {
+        let output = Command::new("modprobe")
+            .arg(name)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(ComposeError::preflight_failed(format!(
+                "modprobe {} failed: {}",
+                name,
+                stderr.trim()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Result of checking a single requirement
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PreflightOutcome {
+    /// The host already satisfied the requirement
+    Satisfied,
+    /// The requirement was unmet and has been applied
+    Applied { previous: Option<String> },
+    /// The requirement is unmet and was left alone
+    Missing,
+    /// Applying the requirement failed
+    Failed { error: String },
+}
+
+/// A checked requirement and what happened to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreflightCheck {
+    pub requirement: HostRequirement,
+    /// Value observed before any change, `None` when absent
+    pub current: Option<String>,
+    pub outcome: PreflightOutcome,
+}
+
+impl PreflightCheck {
+    /// Whether the requirement holds after the preflight phase
+    pub fn is_met(&self) -> bool {
+        matches!(
+            self.outcome,
+            PreflightOutcome::Satisfied | PreflightOutcome::Applied { .. }
+        )
+    }
+}
+
+/// Outcome of a preflight run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PreflightReport {
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    /// Settings changed on the host during this run
+    pub fn changes(&self) -> Vec<&PreflightCheck> {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, PreflightOutcome::Applied { .. }))
+            .collect()
+    }
+
+    /// Requirements that still do not hold
+    pub fn unmet(&self) -> Vec<&PreflightCheck> {
+        self.checks.iter().filter(|check| !check.is_met()).collect()
+    }
+
+    /// Whether every required setting holds
+    pub fn is_ready(&self) -> bool {
+        self.unmet()
+            .iter()
+            .all(|check| !check.requirement.is_required())
+    }
+
+    /// Fail with the unmet required settings listed
+    pub fn ensure_ready(&self) -> Result<()> {
+        let blocking: Vec<String> = self
+            .unmet()
+            .into_iter()
+            .filter(|check| check.requirement.is_required())
+            .map(|check| match &check.outcome {
+                PreflightOutcome::Failed { error } => {
+                    format!("{} ({})", check.requirement, error)
+                }
+                _ => format!(
+                    "{} (found {})",
+                    check.requirement,
+                    check.current.as_deref().unwrap_or("nothing")
+                ),
+            })
+            .collect();
+
+        if blocking.is_empty() {
+            Ok(())
+        } else {
+            Err(ComposeError::preflight_failed(format!(
+                "host prerequisites not met: {}",
+                blocking.join(", ")
+            )))
+        }
+    }
+}
+
+/// Verifies and optionally applies host requirements
+pub struct Preflight {
+    config: PreflightConfig,
+}
+
+impl Preflight {
+    /// Create a preflight runner
+    pub fn new(config: PreflightConfig) -> Self {
+        Self { config }
+    }
+
+    /// Check every requirement against the given host
+    pub async fn run(&self, host: &dyn HostSettings) -> Result<PreflightReport> {
+        let mut report = PreflightReport::default();
+
+        for requirement in &self.config.requirements {
+            let check = match requirement {
+                HostRequirement::Sysctl {
+                    key,
+                    value,
+                    minimum,
+                    ..
+                } => {
+                    let current = host.read_sysctl(key).await?;
+                    let satisfied = current
+                        .as_deref()
+                        .and_then(|current| current.parse::<u64>().ok())
+                        .map(|current| {
+                            if *minimum {
+                                current >= *value
+                            } else {
+                                current == *value
+                            }
+                        })
+                        .unwrap_or(false);
+
+                    let outcome = if satisfied {
+                        PreflightOutcome::Satisfied
+                    } else if self.config.apply {
+                        match host.write_sysctl(key, &value.to_string()).await {
+                            Ok(()) => PreflightOutcome::Applied {
+                                previous: current.clone(),
+                            },
+                            Err(e) => PreflightOutcome::Failed {
+                                error: e.to_string(),
+                            },
+                        }
+                    } else {
+                        PreflightOutcome::Missing
+                    };
+
+                    PreflightCheck {
+                        requirement: requirement.clone(),
+                        current,
+                        outcome,
+                    }
+                }
+                HostRequirement::KernelModule { name, .. } => {
+                    let loaded = host.module_loaded(name).await?;
+                    let current = Some(if loaded { "loaded" } else { "not loaded" }.to_string());
+
+                    let outcome = if loaded {
+                        PreflightOutcome::Satisfied
+                    } else if self.config.apply {
+                        match host.load_module(name).await {
+                            Ok(()) => PreflightOutcome::Applied {
+                                previous: current.clone(),
+                            },
+                            Err(e) => PreflightOutcome::Failed {
+                                error: e.to_string(),
+                            },
+                        }
+                    } else {
+                        PreflightOutcome::Missing
+                    };
+
+                    PreflightCheck {
+                        requirement: requirement.clone(),
+                        current,
+                        outcome,
+                    }
+                }
+            };
+
+            match &check.outcome {
+                PreflightOutcome::Satisfied => debug!("Preflight: {} ok", check.requirement),
+                PreflightOutcome::Applied { previous } => info!(
+                    "Preflight: applied {} (was {})",
+                    check.requirement,
+                    previous.as_deref().unwrap_or("unset")
+                ),
+                PreflightOutcome::Missing => warn!(
+                    "Preflight: {} not met (found {})",
+                    check.requirement,
+                    check.current.as_deref().unwrap_or("nothing")
+                ),
+                PreflightOutcome::Failed { error } => {
+                    warn!(
+                        "Preflight: could not apply {}: {}",
+                        check.requirement, error
+                    )
+                }
+            }
+
+            report.checks.push(check);
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeHost {
+        sysctls: Mutex<HashMap<String, String>>,
+        modules: Mutex<HashSet<String>>,
+        loadable: HashSet<String>,
+    }
+
+    #[async_trait]
+    impl HostSettings for FakeHost {
+        async fn read_sysctl(&self, key: &str) -> Result<Option<String>> {
+            Ok(self.sysctls.lock().unwrap().get(key).cloned())
+        }
+
+        async fn write_sysctl(&self, key: &str, value: &str) -> Result<()> {
+            self.sysctls
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+
+        async fn module_loaded(&self, name: &str) -> Result<bool> {
+            Ok(self.modules.lock().unwrap().contains(name))
+        }
+
+        async fn load_module(&self, name: &str) -> Result<()> {
+            if !self.loadable.contains(name) {
+                return Err(ComposeError::preflight_failed(format!(
+                    "module {} not found",
+                    name
+                )));
+            }
+            self.modules.lock().unwrap().insert(name.to_string());
+            Ok(())
+        }
+    }
+
+    fn host(sysctls: &[(&str, &str)], loadable: &[&str]) -> FakeHost {
+        FakeHost {
+            sysctls: Mutex::new(
+                sysctls
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+            modules: Mutex::default(),
+            loadable: loadable.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_only_reports_without_changing() {
+        let host = host(
+            &[
+                ("net.ipv4.ip_forward", "0"),
+                ("fs.inotify.max_user_watches", "1048576"),
+                ("fs.inotify.max_user_instances", "128"),
+            ],
+            &["wireguard"],
+        );
+
+        let config = PreflightConfig::check_only().with_images(["linuxserver/wireguard:latest"]);
+        let report = Preflight::new(config)
+            .run(&host)
+            .await
+            .unwrap();
+
+        assert!(report.changes().is_empty());
+        assert!(!report.is_ready());
+        assert_eq!(report.unmet().len(), 3);
+        assert_eq!(
+            host.read_sysctl("net.ipv4.ip_forward").await.unwrap(),
+            Some("0".to_string())
+        );
+
+        let error = report.ensure_ready().unwrap_err().to_string();
+        assert!(error.contains("net.ipv4.ip_forward = 1 (found 0)"));
+        assert!(error.contains("kernel module wireguard"));
+        // Optional monitoring limits do not block up
+        assert!(!error.contains("max_user_instances"));
+    }
+
+    #[tokio::test]
+    async fn test_apply_records_changes() {
+        let host = host(
+            &[
+                ("net.ipv4.ip_forward", "0"),
+                ("fs.inotify.max_user_watches", "8192"),
+                ("fs.inotify.max_user_instances", "1024"),
+            ],
+            &["wireguard"],
+        );
+
+        let config = PreflightConfig::applying().with_images(["ghcr.io/wg-easy/wg-easy:14"]);
+        let report = Preflight::new(config)
+            .run(&host)
+            .await
+            .unwrap();
+
+        assert!(report.is_ready());
+        report.ensure_ready().unwrap();
+
+        let changes = report.changes();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(
+            changes[0].outcome,
+            PreflightOutcome::Applied {
+                previous: Some("0".to_string())
+            }
+        );
+        // Image modules come after the configured requirements
+        assert_eq!(
+            changes[2].requirement,
+            HostRequirement::kernel_module("wireguard")
+        );
+        assert_eq!(
+            host.read_sysctl("fs.inotify.max_user_watches")
+                .await
+                .unwrap(),
+            Some("524288".to_string())
+        );
+        // A higher limit than required is left untouched
+        assert_eq!(
+            host.read_sysctl("fs.inotify.max_user_instances")
+                .await
+                .unwrap(),
+            Some("1024".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_userspace_protocols_need_no_modules() {
+        let host = host(&[("net.ipv4.ip_forward", "1")], &[]);
+        let config = PreflightConfig::check_only().with_images([
+            "ghcr.io/xtls/xray-core:latest",
+            "quay.io/outline/shadowbox:stable",
+            "traefik:v3.0",
+        ]);
+        assert!(config
+            .requirements
+            .iter()
+            .all(|requirement| !matches!(requirement, HostRequirement::KernelModule { .. })));
+
+        let report = Preflight::new(config).run(&host).await.unwrap();
+        report.ensure_ready().unwrap();
+
+        // Listed once however many WireGuard images there are
+        let config = PreflightConfig::check_only()
+            .with_images(["linuxserver/wireguard", "wg-easy/wg-easy:latest"]);
+        let modules = config
+            .requirements
+            .iter()
+            .filter(|requirement| matches!(requirement, HostRequirement::KernelModule { .. }))
+            .count();
+        assert_eq!(modules, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_apply_blocks_up() {
+        let host = host(&[("net.ipv4.ip_forward", "1")], &[]);
+        let config = PreflightConfig {
+            apply: true,
+            requirements: vec![
+                HostRequirement::sysctl("net.ipv4.ip_forward", 1),
+                HostRequirement::kernel_module("wireguard"),
+            ],
+            ..PreflightConfig::default()
+        };
+
+        let report = Preflight::new(config).run(&host).await.unwrap();
+
+        assert!(report.changes().is_empty());
+        assert!(matches!(
+            report.checks[1].outcome,
+            PreflightOutcome::Failed { .. }
+        ));
+        assert!(report.ensure_ready().is_err());
+    }
+}
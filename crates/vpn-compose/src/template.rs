@@ -1,5 +1,6 @@
 //! Template management for Docker Compose files
 
+use crate::acme::AcmeConfig;
 use crate::config::ComposeConfig;
 use crate::error::{ComposeError, Result};
 use handlebars::Handlebars;
@@ -105,6 +106,10 @@ impl TemplateManager {
         )
         .await?;
 
+        // Load traefik static config templates
+        self.load_config_template("traefik-config", &configs_dir.join("traefik/traefik.yml"))
+            .await?;
+
         Ok(())
     }
 
@@ -175,6 +180,15 @@ impl TemplateManager {
             }
         }
 
+        // Wire ACME secrets and certificate routers into the proxy, if present
+        if let Some(acme) = &context.acme {
+            let compose: serde_yaml::Value = serde_yaml::from_str(&compose_content)?;
+            if compose["services"].get(&acme.proxy_service).is_some() {
+                let acme_content = serde_yaml::to_string(&acme.compose_override()?)?;
+                compose_content = self.merge_compose_files(&compose_content, &acme_content)?;
+            }
+        }
+
         Ok(compose_content)
     }
 
@@ -205,6 +219,28 @@ impl TemplateManager {
             std::fs::write(prometheus_dir.join("prometheus.yml"), prometheus_config)?;
         }
 
+        // Generate traefik configuration with the ACME certificate resolvers
+        let traefik_config = match (
+            self.render_template("traefik-config", context),
+            &context.acme,
+        ) {
+            (Ok(rendered), None) => Some(rendered),
+            (rendered, Some(acme)) => {
+                let mut config = match rendered {
+                    Ok(rendered) => serde_yaml::from_str(&rendered)?,
+                    Err(_) => default_traefik_config(),
+                };
+                acme.apply_to_static_config(&mut config)?;
+                Some(serde_yaml::to_string(&config)?)
+            }
+            (Err(_), None) => None,
+        };
+        if let Some(traefik_config) = traefik_config {
+            let traefik_dir = output_dir.join("configs/traefik");
+            std::fs::create_dir_all(&traefik_dir)?;
+            std::fs::write(traefik_dir.join("traefik.yml"), traefik_config)?;
+        }
+
         Ok(())
     }
 
@@ -271,6 +307,26 @@ impl TemplateManager {
     }
 }
 
+/// Minimal Traefik static configuration used when no template is available
+fn default_traefik_config() -> serde_yaml::Value {
+    serde_yaml::from_str(
+        r#"
+entryPoints:
+  web:
+    address: ":80"
+  websecure:
+    address: ":443"
+providers:
+  docker:
+    exposedByDefault: false
+  file:
+    filename: /etc/traefik/dynamic.yml
+    watch: true
+"#,
+    )
+    .expect("static Traefik configuration is valid YAML")
+}
+
 /// Context for template rendering
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TemplateContext {
@@ -283,6 +339,8 @@ pub struct TemplateContext {
     pub monitoring: MonitoringContext,
     pub services: Vec<ServiceContext>,
     pub env_vars: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acme: Option<AcmeConfig>,
 }
 
 impl Default for TemplateContext {
@@ -297,6 +355,7 @@ impl Default for TemplateContext {
             monitoring: MonitoringContext::default(),
             services: vec![],
             env_vars: HashMap::new(),
+            acme: None,
         }
     }
 }
@@ -80,6 +80,7 @@ async fn test_compose_file_generation() {
         environment: "development".to_string(),
         include_monitoring: true,
         include_dev_tools: false,
+        schema: None,
     });
 
     let result = generator.generate_compose_files().await;
@@ -206,7 +207,7 @@ async fn test_template_context_generation() {
 async fn test_compose_file_structure() {
     let compose_file = ComposeFile::default();
 
-    assert_eq!(compose_file.version, "3.8");
+    assert_eq!(compose_file.version, None);
     assert!(compose_file.services.is_empty());
     assert!(compose_file.networks.is_empty());
     assert!(compose_file.volumes.is_empty());
@@ -216,7 +217,7 @@ async fn test_compose_file_structure() {
     assert!(yaml.is_ok());
 
     let yaml_str = yaml.unwrap();
-    assert!(yaml_str.contains("version: '3.8'"));
+    assert!(!yaml_str.contains("version:"));
 }
 
 #[tokio::test]
@@ -321,6 +322,7 @@ services:
             environment: env.to_string(),
             include_monitoring: true,
             include_dev_tools: env == "development",
+            schema: None,
         });
 
         tokio::fs::create_dir_all(&config.compose_dir.join(env))
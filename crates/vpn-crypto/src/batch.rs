@@ -0,0 +1,202 @@
+//! Parallel key and UUID generation for bulk user provisioning
+//!
+//! Generating an X25519 keypair draws from the OS RNG and runs a scalar
+//! multiplication, which adds up when a thousand users are imported at
+//! once. [`BatchKeyGenerator`] splits the work across worker threads and
+//! returns results in a fixed order: item `i` of the output always belongs
+//! to the `i`-th item requested, however the work was scheduled.
+
+use crate::error::{CryptoError, Result};
+use crate::keys::{KeyPair, X25519KeyManager};
+use crate::uuid::UuidGenerator;
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// Batches smaller than this are generated on the calling thread
+const MIN_PARALLEL_BATCH: usize = 16;
+
+/// UUID, short ID and keypair for one new user
+#[derive(Debug, Clone)]
+pub struct GeneratedIdentity {
+    pub uuid: String,
+    pub short_id: String,
+    pub keypair: KeyPair,
+}
+
+/// Generates keypairs and identities in parallel
+#[derive(Debug, Clone, Copy)]
+pub struct BatchKeyGenerator {
+    workers: usize,
+}
+
+impl Default for BatchKeyGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatchKeyGenerator {
+    /// One worker per available CPU
+    pub fn new() -> Self {
+        let workers = thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1);
+        Self { workers }
+    }
+
+    pub fn with_workers(workers: usize) -> Self {
+        Self {
+            workers: workers.max(1),
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// `count` Xray-compatible X25519 keypairs
+    pub fn generate_keypairs(&self, count: usize) -> Result<Vec<KeyPair>> {
+        let key_manager = X25519KeyManager::new();
+        self.generate(count, || key_manager.generate_keypair())
+    }
+
+    /// `count` identities, each with a fresh v4 UUID, the short ID derived
+    /// from it and a keypair
+    pub fn generate_identities(&self, count: usize) -> Result<Vec<GeneratedIdentity>> {
+        let key_manager = X25519KeyManager::new();
+        let uuid_generator = UuidGenerator::new();
+        self.generate(count, || {
+            let uuid = uuid_generator.generate_v4()?;
+            Ok(GeneratedIdentity {
+                short_id: uuid_generator.generate_short_id(&uuid)?,
+                uuid,
+                keypair: key_manager.generate_keypair()?,
+            })
+        })
+    }
+
+    /// [`Self::generate_keypairs`] on the blocking pool, for async callers
+    pub async fn generate_keypairs_async(&self, count: usize) -> Result<Vec<KeyPair>> {
+        let generator = *self;
+        tokio::task::spawn_blocking(move || generator.generate_keypairs(count))
+            .await
+            .map_err(|e| CryptoError::KeyGenerationError(e.to_string()))?
+    }
+
+    /// [`Self::generate_identities`] on the blocking pool, for async callers
+    pub async fn generate_identities_async(&self, count: usize) -> Result<Vec<GeneratedIdentity>> {
+        let generator = *self;
+        tokio::task::spawn_blocking(move || generator.generate_identities(count))
+            .await
+            .map_err(|e| CryptoError::KeyGenerationError(e.to_string()))?
+    }
+
+    /// Run `generate` `count` times over contiguous chunks, one per worker,
+    /// concatenating the chunks in order
+    fn generate<T, F>(&self, count: usize, generate: F) -> Result<Vec<T>>
+    where
+        T: Send,
+        F: Fn() -> Result<T> + Sync,
+    {
+        if count < MIN_PARALLEL_BATCH || self.workers == 1 {
+            return (0..count).map(|_| generate()).collect();
+        }
+
+        let chunk_size = count.div_ceil(self.workers);
+        let generate = &generate;
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..count)
+                .step_by(chunk_size)
+                .map(|start| {
+                    let len = chunk_size.min(count - start);
+                    scope.spawn(move || (0..len).map(|_| generate()).collect::<Result<Vec<T>>>())
+                })
+                .collect();
+
+            let mut results = Vec::with_capacity(count);
+            for handle in handles {
+                let chunk = handle.join().map_err(|_| {
+                    CryptoError::KeyGenerationError("Key generation worker panicked".to_string())
+                })??;
+                results.extend(chunk);
+            }
+            Ok(results)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_generate_keypairs_parallel() {
+        let generator = BatchKeyGenerator::with_workers(4);
+        let keypairs = generator.generate_keypairs(100).unwrap();
+        assert_eq!(keypairs.len(), 100);
+
+        let key_manager = X25519KeyManager::new();
+        for keypair in &keypairs {
+            key_manager.validate_keypair(keypair).unwrap();
+        }
+        let unique: HashSet<_> = keypairs.iter().map(|k| k.public_key.clone()).collect();
+        assert_eq!(unique.len(), 100);
+
+        assert!(generator.generate_keypairs(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_results_keep_request_order() {
+        // Every item is generated once, and each worker's chunk comes back
+        // in the order the worker generated it
+        let counter = AtomicUsize::new(0);
+        let generator = BatchKeyGenerator::with_workers(3);
+        let items = generator
+            .generate(50, || Ok(counter.fetch_add(1, Ordering::SeqCst)))
+            .unwrap();
+        assert_eq!(items.len(), 50);
+        let mut sorted = items.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..50).collect::<Vec<_>>());
+
+        for chunk in items.chunks(50usize.div_ceil(3)) {
+            assert!(chunk.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    #[test]
+    fn test_generation_errors_propagate() {
+        let counter = AtomicUsize::new(0);
+        let result = BatchKeyGenerator::with_workers(4).generate(40, || {
+            if counter.fetch_add(1, Ordering::SeqCst) == 25 {
+                Err(CryptoError::KeyGenerationError("exhausted".to_string()))
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(
+            result.unwrap_err(),
+            CryptoError::KeyGenerationError("exhausted".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_identities_async() {
+        let identities = BatchKeyGenerator::with_workers(2)
+            .generate_identities_async(20)
+            .await
+            .unwrap();
+        assert_eq!(identities.len(), 20);
+
+        let uuid_generator = UuidGenerator::new();
+        for identity in &identities {
+            assert!(uuid_generator.is_valid(&identity.uuid));
+            assert_eq!(
+                identity.short_id,
+                uuid_generator.generate_short_id(&identity.uuid).unwrap()
+            );
+        }
+    }
+}
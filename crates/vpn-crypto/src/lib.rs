@@ -1,3 +1,4 @@
+pub mod batch;
 pub mod encoding;
 pub mod error;
 pub mod keys;
@@ -5,9 +6,10 @@ pub mod qr;
 pub mod secure_storage;
 pub mod uuid;
 
+pub use batch::{BatchKeyGenerator, GeneratedIdentity};
 pub use encoding::{Base64Encoder, EncodingUtils, HexEncoder};
 pub use error::{CryptoError, Result};
 pub use keys::{KeyPair, X25519KeyManager};
-pub use qr::{ErrorCorrectionLevel, QrCodeGenerator};
+pub use qr::{ErrorCorrectionLevel, QrCodeGenerator, TerminalQrMode};
 pub use secure_storage::{EncryptedKeyData, SecureKeyManager};
 pub use uuid::UuidGenerator;
@@ -1,7 +1,12 @@
 use crate::error::{CryptoError, Result};
 use qr2term::generate_qr_string;
+use qrcode::render::unicode::Dense1x2;
 use qrcode::{EcLevel, QrCode};
 use std::fs;
+use std::path::Path;
+
+/// Width of the quiet zone around a QR code, in modules
+const QUIET_ZONE: usize = 4;
 
 #[derive(Debug, Clone, Copy)]
 pub enum ErrorCorrectionLevel {
@@ -10,6 +15,15 @@ pub enum ErrorCorrectionLevel {
     High,
 }
 
+/// How QR modules are mapped to terminal cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalQrMode {
+    /// Two columns and one row per module
+    Full,
+    /// Half-block characters, one column and half a row per module
+    Compact,
+}
+
 pub struct QrCodeGenerator;
 
 impl QrCodeGenerator {
@@ -50,9 +64,87 @@ impl QrCodeGenerator {
         Ok(())
     }
 
+    pub fn save_qr_code_png(&self, data: &str, path: &Path) -> Result<()> {
+        let code = QrCode::new(data).map_err(|e| CryptoError::QrCodeError(e.to_string()))?;
+        let width = code.width();
+        let colors = code.to_colors();
+
+        // Scale modules up so the image is at least 300px wide
+        let modules = width + 2 * QUIET_ZONE;
+        let scale = 300usize.div_ceil(modules);
+        let size = (modules * scale) as u32;
+
+        let image = image::GrayImage::from_fn(size, size, |x, y| {
+            let column = (x as usize / scale).checked_sub(QUIET_ZONE);
+            let row = (y as usize / scale).checked_sub(QUIET_ZONE);
+            let dark = match (column, row) {
+                (Some(column), Some(row)) if column < width && row < width => {
+                    colors[row * width + column] == qrcode::Color::Dark
+                }
+                _ => false,
+            };
+            image::Luma([if dark { 0 } else { 255 }])
+        });
+
+        image
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(|e| CryptoError::QrCodeError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Module matrix of the code for `data`: its width and whether each
+    /// module, row by row, is dark. The quiet zone is not included.
+    pub fn modules(&self, data: &str) -> Result<(usize, Vec<bool>)> {
+        let code = QrCode::new(data).map_err(|e| CryptoError::QrCodeError(e.to_string()))?;
+        let dark = code
+            .to_colors()
+            .into_iter()
+            .map(|color| color == qrcode::Color::Dark)
+            .collect();
+        Ok((code.width(), dark))
+    }
+
     pub fn generate_terminal_qr(&self, data: &str) -> Result<String> {
         generate_qr_string(data).map_err(|e| CryptoError::QrCodeError(e.to_string()))
     }
+
+    /// Terminal columns and rows needed to render `data` in `mode`
+    pub fn terminal_qr_dimensions(
+        &self,
+        data: &str,
+        mode: TerminalQrMode,
+    ) -> Result<(usize, usize)> {
+        let code = QrCode::new(data).map_err(|e| CryptoError::QrCodeError(e.to_string()))?;
+        let modules = code.width() + 2 * QUIET_ZONE;
+
+        Ok(match mode {
+            TerminalQrMode::Full => (modules * 2, modules),
+            TerminalQrMode::Compact => (modules, modules.div_ceil(2)),
+        })
+    }
+
+    /// Render `data` with block characters for display on a dark terminal
+    pub fn render_terminal_qr(&self, data: &str, mode: TerminalQrMode) -> Result<String> {
+        let code = QrCode::new(data).map_err(|e| CryptoError::QrCodeError(e.to_string()))?;
+
+        // Light modules are drawn as blocks so the code reads correctly on
+        // a dark background
+        let rendered = match mode {
+            TerminalQrMode::Full => code
+                .render::<char>()
+                .dark_color(' ')
+                .light_color('█')
+                .module_dimensions(2, 1)
+                .build(),
+            TerminalQrMode::Compact => code
+                .render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build(),
+        };
+
+        Ok(rendered)
+    }
 }
 
 #[cfg(test)]
@@ -92,4 +184,41 @@ mod tests {
 
         assert!(!qr_data.is_empty());
     }
+
+    #[test]
+    fn test_save_qr_code_png() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("qr.png");
+        let gen = QrCodeGenerator::new();
+
+        gen.save_qr_code_png("vless://test@example.com:443", &path)
+            .expect("Failed to save QR code");
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(&bytes[1..4], b"PNG");
+    }
+
+    #[test]
+    fn test_terminal_qr_modes() {
+        let data = "vless://test@example.com:443";
+        let gen = QrCodeGenerator::new();
+
+        for mode in [TerminalQrMode::Full, TerminalQrMode::Compact] {
+            let (columns, rows) = gen.terminal_qr_dimensions(data, mode).unwrap();
+            let rendered = gen.render_terminal_qr(data, mode).unwrap();
+            let lines: Vec<&str> = rendered.lines().collect();
+
+            assert_eq!(lines.len(), rows);
+            assert!(lines.iter().all(|line| line.chars().count() == columns));
+        }
+
+        let (full_columns, full_rows) = gen
+            .terminal_qr_dimensions(data, TerminalQrMode::Full)
+            .unwrap();
+        let (compact_columns, compact_rows) = gen
+            .terminal_qr_dimensions(data, TerminalQrMode::Compact)
+            .unwrap();
+        assert_eq!(full_columns, compact_columns * 2);
+        assert!(compact_rows < full_rows);
+    }
 }
@@ -0,0 +1,403 @@
+//! Detection of the installed Docker Compose and the file format it accepts
+//!
+//! Compose ships either as the `docker compose` CLI plugin or as the
+//! standalone `docker-compose` binary. Compose v2, and v1 from 1.27.0,
+//! implement the Compose Specification, where the top-level `version` is
+//! obsolete and only produces a warning. Older v1 releases require a
+//! `version: '3.x'` and reject keys newer than that file format.
+//! [`ComposeCli`] finds which one is installed and [`ComposeSchema`]
+//! renders compose files it accepts, so generated files never need
+//! patching after the fact.
+
+use crate::error::{DockerError, Result};
+use serde_yaml::{Mapping, Value};
+use std::fmt;
+use std::process::Command;
+use vpn_types::CorrelationId;
+
+/// How Compose is invoked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComposeFlavor {
+    /// `docker compose`
+    Plugin,
+    /// `docker-compose`
+    Standalone,
+}
+
+impl ComposeFlavor {
+    /// A command running this flavor of Compose, ready for its arguments.
+    /// The current correlation ID is passed on in its environment.
+    pub fn command(self) -> Command {
+        let mut cmd = match self {
+            Self::Plugin => {
+                let mut cmd = Command::new("docker");
+                cmd.arg("compose");
+                cmd
+            }
+            Self::Standalone => Command::new("docker-compose"),
+        };
+        if let Some(id) = CorrelationId::current() {
+            cmd.env(CorrelationId::ENV, id.as_str());
+        }
+        cmd
+    }
+}
+
+impl fmt::Display for ComposeFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Plugin => write!(f, "docker compose"),
+            Self::Standalone => write!(f, "docker-compose"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ComposeVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ComposeVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse `version --short` output such as `2.24.5`, `v2.24.5-desktop.1`
+    /// or `1.29.2`
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let s = s.strip_prefix('v').unwrap_or(s);
+
+        let mut parts = s.split('.').map(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse::<u32>().ok()
+        });
+        let major = parts.next()??;
+        let minor = parts.next().flatten().unwrap_or(0);
+        let patch = parts.next().flatten().unwrap_or(0);
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for ComposeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The installed Docker Compose
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComposeCli {
+    pub flavor: ComposeFlavor,
+    pub version: ComposeVersion,
+}
+
+static DETECTED: once_cell::sync::Lazy<Option<ComposeCli>> =
+    once_cell::sync::Lazy::new(ComposeCli::detect);
+
+impl ComposeCli {
+    /// Find the installed Compose, preferring the plugin when both are
+    /// present
+    pub fn detect() -> Option<Self> {
+        [ComposeFlavor::Plugin, ComposeFlavor::Standalone]
+            .into_iter()
+            .find_map(|flavor| {
+                let output = flavor
+                    .command()
+                    .args(["version", "--short"])
+                    .output()
+                    .ok()?;
+                if !output.status.success() {
+                    return None;
+                }
+                let version = ComposeVersion::parse(&String::from_utf8_lossy(&output.stdout))?;
+                Some(Self { flavor, version })
+            })
+    }
+
+    /// [`Self::detect`], run once per process
+    pub fn detected() -> Option<Self> {
+        *DETECTED
+    }
+
+    pub fn schema(&self) -> ComposeSchema {
+        ComposeSchema::for_cli(self.flavor, self.version)
+    }
+
+    pub fn command(&self) -> Command {
+        self.flavor.command()
+    }
+}
+
+/// A command running the installed Compose, falling back to the plugin
+/// when none is detected so the failure names the modern command
+pub fn compose_command() -> Command {
+    ComposeCli::detected()
+        .map(|cli| cli.flavor)
+        .unwrap_or(ComposeFlavor::Plugin)
+        .command()
+}
+
+/// The schema of the installed Compose, the Compose Specification when
+/// none is detected
+pub fn compose_schema() -> ComposeSchema {
+    ComposeCli::detected()
+        .map(|cli| cli.schema())
+        .unwrap_or_default()
+}
+
+/// Compose file format a Compose release accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ComposeSchema {
+    /// The Compose Specification; `version` is obsolete
+    #[default]
+    Specification,
+    /// Legacy file format `3.<minor>`
+    Legacy { minor: u8 },
+}
+
+/// First standalone release implementing the Compose Specification
+const SPECIFICATION_SINCE: ComposeVersion = ComposeVersion::new(1, 27, 0);
+
+/// First standalone release supporting each `3.x` file format, newest first
+const LEGACY_FORMATS: [(ComposeVersion, u8); 9] = [
+    (ComposeVersion::new(1, 25, 5), 8),
+    (ComposeVersion::new(1, 22, 0), 7),
+    (ComposeVersion::new(1, 20, 0), 6),
+    (ComposeVersion::new(1, 18, 0), 5),
+    (ComposeVersion::new(1, 17, 0), 4),
+    (ComposeVersion::new(1, 13, 0), 3),
+    (ComposeVersion::new(1, 12, 0), 2),
+    (ComposeVersion::new(1, 11, 0), 1),
+    (ComposeVersion::new(1, 10, 0), 0),
+];
+
+impl ComposeSchema {
+    pub fn for_cli(flavor: ComposeFlavor, version: ComposeVersion) -> Self {
+        if flavor == ComposeFlavor::Plugin || version >= SPECIFICATION_SINCE {
+            return Self::Specification;
+        }
+
+        let minor = LEGACY_FORMATS
+            .iter()
+            .find(|(since, _)| version >= *since)
+            .map(|(_, minor)| *minor)
+            .unwrap_or(0);
+        Self::Legacy { minor }
+    }
+
+    /// The top-level `version` to write, if any
+    pub fn version(&self) -> Option<String> {
+        match self {
+            Self::Specification => None,
+            Self::Legacy { minor } => Some(format!("3.{}", minor)),
+        }
+    }
+
+    /// Adapt `compose` to this schema. The input is returned untouched when
+    /// nothing needs to change, keeping hand-written layout and comments.
+    pub fn render(&self, compose: &str) -> Result<String> {
+        let mut document: Value = serde_yaml::from_str(compose)
+            .map_err(|e| DockerError::ComposeError(format!("Invalid compose file: {}", e)))?;
+
+        if !self.apply(&mut document) {
+            return Ok(compose.to_string());
+        }
+        serde_yaml::to_string(&document)
+            .map_err(|e| DockerError::ComposeError(format!("Cannot render compose file: {}", e)))
+    }
+
+    /// Adapt a parsed compose file to this schema, returning whether it
+    /// changed
+    pub fn apply(&self, document: &mut Value) -> bool {
+        let Some(root) = document.as_mapping_mut() else {
+            return false;
+        };
+        let mut changed = false;
+
+        match self.version() {
+            None => changed |= root.remove("version").is_some(),
+            Some(version) => {
+                let current = root.get("version").and_then(Value::as_str);
+                if current != Some(version.as_str()) {
+                    // `version` goes first, as every v1 example has it
+                    let mut rendered = Mapping::new();
+                    rendered.insert("version".into(), version.into());
+                    for (key, value) in std::mem::take(root) {
+                        if key.as_str() != Some("version") {
+                            rendered.insert(key, value);
+                        }
+                    }
+                    *root = rendered;
+                    changed = true;
+                }
+                // Project names in the file are Specification only
+                changed |= root.remove("name").is_some();
+            }
+        }
+
+        if let Some(services) = root.get_mut("services").and_then(Value::as_mapping_mut) {
+            for service in services.values_mut() {
+                let Some(service) = service.as_mapping_mut() else {
+                    continue;
+                };
+                if let Some(healthcheck) = service
+                    .get_mut("healthcheck")
+                    .and_then(Value::as_mapping_mut)
+                {
+                    changed |= self.apply_healthcheck(healthcheck);
+                }
+                if let Some(ulimits) = service.get_mut("ulimits").and_then(Value::as_mapping_mut) {
+                    changed |= apply_ulimits(ulimits);
+                }
+            }
+        }
+
+        changed
+    }
+
+    fn apply_healthcheck(&self, healthcheck: &mut Mapping) -> bool {
+        let Self::Legacy { minor } = *self else {
+            return false;
+        };
+
+        // `start_interval` is Specification only and `start_period` arrived
+        // with 3.4
+        let mut changed = healthcheck.remove("start_interval").is_some();
+        if minor < 4 {
+            changed |= healthcheck.remove("start_period").is_some();
+        }
+        changed
+    }
+}
+
+/// Both formats take a limit as an integer or a `soft`/`hard` mapping of
+/// integers; rewrite `docker run` style strings such as `"65535"` or
+/// `"1024:65535"` into that form
+fn apply_ulimits(ulimits: &mut Mapping) -> bool {
+    let mut changed = false;
+
+    for limit in ulimits.values_mut() {
+        let Some(text) = limit.as_str() else {
+            continue;
+        };
+        let rewritten = match text.split_once(':') {
+            None => text.trim().parse::<i64>().ok().map(Value::from),
+            Some((soft, hard)) => match (soft.trim().parse::<i64>(), hard.trim().parse::<i64>()) {
+                (Ok(soft), Ok(hard)) => {
+                    let mut mapping = Mapping::new();
+                    mapping.insert("soft".into(), soft.into());
+                    mapping.insert("hard".into(), hard.into());
+                    Some(Value::Mapping(mapping))
+                }
+                _ => None,
+            },
+        };
+        if let Some(rewritten) = rewritten {
+            *limit = rewritten;
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPOSE: &str = r#"version: '3.8'
+name: vpn
+services:
+  xray:
+    image: ghcr.io/xtls/xray-core:latest
+    healthcheck:
+      test: ["CMD", "xray", "version"]
+      interval: 30s
+      start_period: 10s
+      start_interval: 2s
+    ulimits:
+      nproc: "65535"
+      nofile: "20000:40000"
+"#;
+
+    #[test]
+    fn test_version_parsing() {
+        assert_eq!(
+            ComposeVersion::parse("v2.24.5-desktop.1\n"),
+            Some(ComposeVersion::new(2, 24, 5))
+        );
+        assert_eq!(
+            ComposeVersion::parse("1.29.2"),
+            Some(ComposeVersion::new(1, 29, 2))
+        );
+        assert_eq!(ComposeVersion::parse("compose"), None);
+    }
+
+    #[test]
+    fn test_schema_for_cli() {
+        let plugin = ComposeSchema::for_cli(ComposeFlavor::Plugin, ComposeVersion::new(2, 0, 0));
+        assert_eq!(plugin, ComposeSchema::Specification);
+
+        let v1 = |minor, patch| {
+            ComposeSchema::for_cli(
+                ComposeFlavor::Standalone,
+                ComposeVersion::new(1, minor, patch),
+            )
+        };
+        assert_eq!(v1(29, 2), ComposeSchema::Specification);
+        assert_eq!(v1(25, 5), ComposeSchema::Legacy { minor: 8 });
+        assert_eq!(v1(25, 4), ComposeSchema::Legacy { minor: 7 });
+        assert_eq!(v1(17, 1), ComposeSchema::Legacy { minor: 4 });
+        assert_eq!(v1(16, 1), ComposeSchema::Legacy { minor: 3 });
+    }
+
+    #[test]
+    fn test_render_specification() {
+        let rendered = ComposeSchema::Specification.render(COMPOSE).unwrap();
+        let document: Value = serde_yaml::from_str(&rendered).unwrap();
+
+        assert!(document.get("version").is_none());
+        assert_eq!(document["name"], "vpn");
+        let xray = &document["services"]["xray"];
+        assert_eq!(xray["healthcheck"]["start_interval"], "2s");
+        assert_eq!(xray["ulimits"]["nproc"], 65535);
+        assert_eq!(xray["ulimits"]["nofile"]["soft"], 20000);
+        assert_eq!(xray["ulimits"]["nofile"]["hard"], 40000);
+
+        // Already compatible files come back verbatim
+        assert_eq!(
+            ComposeSchema::Specification.render(&rendered).unwrap(),
+            rendered
+        );
+        let plain = "services:\n  xray:\n    image: xray # pinned\n";
+        assert_eq!(ComposeSchema::Specification.render(plain).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_render_legacy() {
+        let rendered = ComposeSchema::Legacy { minor: 3 }.render(COMPOSE).unwrap();
+        assert!(rendered.starts_with("version: '3.3'"));
+
+        let document: Value = serde_yaml::from_str(&rendered).unwrap();
+        assert!(document.get("name").is_none());
+        let healthcheck = &document["services"]["xray"]["healthcheck"];
+        assert!(healthcheck.get("start_interval").is_none());
+        assert!(healthcheck.get("start_period").is_none());
+        assert_eq!(healthcheck["interval"], "30s");
+
+        let rendered = ComposeSchema::Legacy { minor: 8 }.render(COMPOSE).unwrap();
+        let document: Value = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(document["version"], "3.8");
+        assert_eq!(
+            document["services"]["xray"]["healthcheck"]["start_period"],
+            "10s"
+        );
+    }
+}
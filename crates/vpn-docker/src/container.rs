@@ -13,6 +13,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
 use tokio::task::JoinSet;
+use vpn_types::CorrelationId;
 
 /// Operations that can be performed on containers in batch mode
 #[derive(Debug, Clone)]
@@ -470,7 +471,15 @@ impl ContainerManager {
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn create_container(&self, name: &str, config: Config<String>) -> Result<String> {
+    pub async fn create_container(&self, name: &str, mut config: Config<String>) -> Result<String> {
+        // Containers record the command or request that created them
+        if let Some(id) = CorrelationId::current() {
+            config
+                .labels
+                .get_or_insert_with(HashMap::new)
+                .insert(CorrelationId::LABEL.to_string(), id.to_string());
+        }
+
         let options = CreateContainerOptions {
             name,
             ..Default::default()
@@ -0,0 +1,281 @@
+//! Compose environment files encrypted at rest
+//!
+//! Compose reads the `.env` next to a compose file in plaintext, so every
+//! local user who can read the project directory can read the secrets in
+//! it. [`SealedEnvFile::seal`] encrypts the file to age recipients instead,
+//! either with `age` itself (`.env.age`) or in the sops dotenv format
+//! (`.env.sops`), by running the respective binary. Before Compose runs,
+//! [`SealedEnvFile::open`] decrypts the file into memory and its variables
+//! are passed in the environment of the Compose process, which takes
+//! precedence over `.env`; the plaintext never touches the disk.
+
+use crate::error::{DockerError, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Variable naming the age identity file sealed env files are opened with
+pub const IDENTITY_ENV: &str = "VPN_ENV_IDENTITY";
+
+/// Identity file used when [`IDENTITY_ENV`] is unset
+pub const DEFAULT_IDENTITY: &str = "/etc/vpn/env-identity.txt";
+
+/// Format a sealed env file is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvCipher {
+    /// Whole file encrypted with `age`
+    #[default]
+    Age,
+    /// sops dotenv file, values encrypted to age recipients; diffable and
+    /// editable with `sops`
+    Sops,
+}
+
+impl EnvCipher {
+    /// Name of the sealed env file in a project directory
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            EnvCipher::Age => ".env.age",
+            EnvCipher::Sops => ".env.sops",
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            EnvCipher::Age => "age",
+            EnvCipher::Sops => "sops",
+        }
+    }
+}
+
+/// How generated env files are encrypted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvEncryption {
+    #[serde(default)]
+    pub cipher: EnvCipher,
+    /// age public keys (`age1...`) able to decrypt the file
+    pub recipients: Vec<String>,
+}
+
+/// An encrypted env file next to a compose file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedEnvFile {
+    path: PathBuf,
+    cipher: EnvCipher,
+}
+
+impl SealedEnvFile {
+    /// The sealed env file in `dir`, if there is one
+    pub fn find(dir: &Path) -> Option<Self> {
+        [EnvCipher::Age, EnvCipher::Sops]
+            .into_iter()
+            .map(|cipher| Self {
+                path: dir.join(cipher.file_name()),
+                cipher,
+            })
+            .find(|sealed| sealed.path.is_file())
+    }
+
+    /// Encrypt `content` into the sealed env file in `dir`, readable by the
+    /// owner only. A plaintext `.env` left in `dir` is removed.
+    pub async fn seal(dir: &Path, content: &str, encryption: &EnvEncryption) -> Result<Self> {
+        if encryption.recipients.is_empty() {
+            return Err(DockerError::EnvEncryptionError(
+                "No recipients to encrypt the env file to".to_string(),
+            ));
+        }
+
+        let cipher = encryption.cipher;
+        let mut cmd = Command::new(cipher.program());
+        match cipher {
+            EnvCipher::Age => {
+                cmd.arg("--encrypt");
+                for recipient in &encryption.recipients {
+                    cmd.arg("--recipient").arg(recipient);
+                }
+            }
+            EnvCipher::Sops => {
+                cmd.args([
+                    "--encrypt",
+                    "--input-type",
+                    "dotenv",
+                    "--output-type",
+                    "dotenv",
+                    "--age",
+                ])
+                .arg(encryption.recipients.join(","))
+                .arg("/dev/stdin");
+            }
+        }
+        let sealed = run(cipher, cmd, Some(content.as_bytes())).await?;
+
+        let path = dir.join(cipher.file_name());
+        write_private(&path, &sealed).await?;
+
+        let plaintext = dir.join(".env");
+        if tokio::fs::try_exists(&plaintext).await.unwrap_or(false) {
+            tokio::fs::remove_file(&plaintext).await?;
+        }
+
+        Ok(Self { path, cipher })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn cipher(&self) -> EnvCipher {
+        self.cipher
+    }
+
+    /// Decrypt the file in memory with the age identity in `identity`
+    pub async fn open(&self, identity: &Path) -> Result<Vec<(String, String)>> {
+        let mut cmd = Command::new(self.cipher.program());
+        match self.cipher {
+            EnvCipher::Age => {
+                cmd.arg("--decrypt").arg("--identity").arg(identity);
+            }
+            EnvCipher::Sops => {
+                cmd.env("SOPS_AGE_KEY_FILE", identity).args([
+                    "--decrypt",
+                    "--input-type",
+                    "dotenv",
+                    "--output-type",
+                    "dotenv",
+                ]);
+            }
+        }
+        cmd.arg(&self.path);
+
+        let plaintext = run(self.cipher, cmd, None).await?;
+        Ok(parse_env(&String::from_utf8_lossy(&plaintext)))
+    }
+
+    /// Decrypt the file with the identity named by [`IDENTITY_ENV`]
+    pub async fn open_default(&self) -> Result<Vec<(String, String)>> {
+        self.open(&identity_file()).await
+    }
+}
+
+/// Identity file sealed env files are opened with
+pub fn identity_file() -> PathBuf {
+    std::env::var_os(IDENTITY_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_IDENTITY))
+}
+
+/// Variables of the env file in `dir`, decrypted if it is sealed; `None`
+/// when there is neither a sealed nor a plaintext `.env`
+pub async fn read_env(dir: &Path) -> Result<Option<Vec<(String, String)>>> {
+    if let Some(sealed) = SealedEnvFile::find(dir) {
+        return sealed.open_default().await.map(Some);
+    }
+    match tokio::fs::read_to_string(dir.join(".env")).await {
+        Ok(content) => Ok(Some(parse_env(&content))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// `KEY=value` pairs of a dotenv file; comments, blank lines and lines
+/// without `=` are skipped and matching quotes around values removed
+pub fn parse_env(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let value = value.trim();
+            let value = ['"', '\'']
+                .iter()
+                .find_map(|quote| {
+                    value
+                        .strip_prefix(*quote)
+                        .and_then(|value| value.strip_suffix(*quote))
+                })
+                .unwrap_or(value);
+            Some((key.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+async fn run(cipher: EnvCipher, mut cmd: Command, input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut child = cmd
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            DockerError::EnvEncryptionError(format!("Cannot run {}: {}", cipher.program(), e))
+        })?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input).await?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(DockerError::EnvEncryptionError(format!(
+            "{} failed: {}",
+            cipher.program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+async fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path).await?;
+    file.write_all(content).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env() {
+        let vars = parse_env(
+            "# generated\n\nDOMAIN_NAME=vpn.example.com\nexport JWT_SECRET=\"s3=cr3t\"\nREDIS_PASSWORD='x'\nnot a variable\n",
+        );
+        assert_eq!(
+            vars,
+            vec![
+                ("DOMAIN_NAME".to_string(), "vpn.example.com".to_string()),
+                ("JWT_SECRET".to_string(), "s3=cr3t".to_string()),
+                ("REDIS_PASSWORD".to_string(), "x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_sealed_env_file() {
+        let dir = std::env::temp_dir().join(format!("vpn-env-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(".env"), "A=1\n").unwrap();
+        assert_eq!(SealedEnvFile::find(&dir), None);
+
+        std::fs::write(dir.join(".env.sops"), "A=ENC[...]\n").unwrap();
+        let sealed = SealedEnvFile::find(&dir).unwrap();
+        assert_eq!(sealed.cipher(), EnvCipher::Sops);
+        assert_eq!(sealed.path(), dir.join(".env.sops"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
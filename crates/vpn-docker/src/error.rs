@@ -11,9 +11,27 @@ pub enum DockerError {
     #[error("Volume operation failed: {0}")]
     VolumeError(String),
 
+    #[error("Network operation failed: {0}")]
+    NetworkError(String),
+
     #[error("Health check failed: {0}")]
     HealthCheckFailed(String),
 
+    #[error("Docker Compose error: {0}")]
+    ComposeError(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("Network address pool conflict: {0}")]
+    NetworkConflict(String),
+
+    #[error("Container name conflict: {0}")]
+    NameConflict(String),
+
+    #[error("Env file encryption failed: {0}")]
+    EnvEncryptionError(String),
+
     #[error("Docker API error: {0}")]
     ApiError(#[from] bollard::errors::Error),
 
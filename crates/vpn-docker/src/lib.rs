@@ -61,11 +61,18 @@
 //! ```
 
 pub mod cache;
+pub mod compose;
 pub mod container;
+pub mod env_file;
 pub mod error;
 pub mod health;
 pub mod logs;
+pub mod networks;
+pub mod oneshot;
+pub mod ownership;
 pub mod pool;
+pub mod project;
+pub mod system;
 pub mod volumes;
 
 #[cfg(test)]
@@ -75,11 +82,20 @@ pub mod proptest;
 pub use cache::{
     get_container_cache, start_cache_cleanup_task, CacheConfig, CacheStats, ContainerCache,
 };
+pub use compose::{
+    compose_command, compose_schema, ComposeCli, ComposeFlavor, ComposeSchema, ComposeVersion,
+};
 pub use container::{
     ContainerConfig, ContainerManager, ContainerStats, ContainerStatus, DockerManager,
 };
+pub use env_file::{EnvCipher, EnvEncryption, SealedEnvFile};
 pub use error::{DockerError, Result};
 pub use health::HealthChecker;
 pub use logs::LogStreamer;
+pub use networks::{ComposeNetwork, NetworkInfo, NetworkManager};
+pub use oneshot::{OneShotOutput, OneShotRunner};
+pub use ownership::{ConflictingContainer, Ownership, MANAGED_LABEL};
 pub use pool::{get_docker_connection, get_pool_stats, DockerPool, PoolConfig, PoolStats};
+pub use project::{ComposeProject, ProjectContainer};
+pub use system::{docker_available, prune, remove_image, PruneReport};
 pub use volumes::VolumeManager;
@@ -0,0 +1,364 @@
+//! Recovery of the Docker networks a compose project expects
+//!
+//! Docker Compose creates a project's networks on `up`, labelled with the
+//! project and network key. When such a network is removed behind
+//! Compose's back (e.g. by `docker network prune`), containers referencing
+//! it fail to start with "network ... not found". [`NetworkManager`] reads
+//! the compose file, works out which labelled networks it should have and
+//! recreates the missing ones the way Compose would, so a retried `up`
+//! succeeds.
+
+use crate::error::{DockerError, Result};
+use bollard::models::{Ipam, IpamConfig};
+use bollard::network::{CreateNetworkOptions, ListNetworksOptions, PruneNetworksOptions};
+use bollard::Docker;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Label Compose puts the project name under
+pub const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+
+/// Label Compose puts the network's key in the compose file under
+pub const COMPOSE_NETWORK_LABEL: &str = "com.docker.compose.network";
+
+/// A network declared by a compose file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComposeNetwork {
+    /// Key under `networks:` in the compose file
+    pub key: String,
+    /// Docker network name, `<project>_<key>` unless the file sets `name`
+    pub name: String,
+    pub driver: String,
+    pub internal: bool,
+    pub subnets: Vec<String>,
+    pub labels: HashMap<String, String>,
+}
+
+impl ComposeNetwork {
+    /// Networks `compose` declares for `project`, including the implicit
+    /// `default` network when a service joins no network explicitly.
+    /// External networks are not the project's to create and are skipped.
+    pub fn from_compose(compose: &str, project: &str) -> Result<Vec<Self>> {
+        let document: Value = serde_yaml::from_str(compose)
+            .map_err(|e| DockerError::NetworkError(format!("Invalid compose file: {}", e)))?;
+
+        let mut networks = Vec::new();
+        if let Some(declared) = document.get("networks").and_then(Value::as_mapping) {
+            for (key, spec) in declared {
+                let Some(key) = key.as_str() else { continue };
+                if spec.get("external").and_then(Value::as_bool) == Some(true) {
+                    continue;
+                }
+                networks.push(Self::from_spec(key, spec, project));
+            }
+        }
+
+        let uses_default = document
+            .get("services")
+            .and_then(Value::as_mapping)
+            .is_some_and(|services| {
+                services.values().any(|service| {
+                    service.get("networks").is_none() && service.get("network_mode").is_none()
+                })
+            });
+        if uses_default && !networks.iter().any(|network| network.key == "default") {
+            networks.push(Self::from_spec("default", &Value::Null, project));
+        }
+
+        Ok(networks)
+    }
+
+    fn from_spec(key: &str, spec: &Value, project: &str) -> Self {
+        let name = spec
+            .get("name")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}_{}", project, key));
+
+        let subnets = spec
+            .get("ipam")
+            .and_then(|ipam| ipam.get("config"))
+            .and_then(Value::as_sequence)
+            .map(|configs| {
+                configs
+                    .iter()
+                    .filter_map(|config| config.get("subnet").and_then(Value::as_str))
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Labels come as a map or as a list of `key=value`
+        let mut labels: HashMap<String, String> = match spec.get("labels") {
+            Some(Value::Mapping(map)) => map
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str()?.to_string(), yaml_scalar(v)?)))
+                .collect(),
+            Some(Value::Sequence(list)) => list
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|label| match label.split_once('=') {
+                    Some((k, v)) => (k.to_string(), v.to_string()),
+                    None => (label.to_string(), String::new()),
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+        labels.insert(COMPOSE_PROJECT_LABEL.to_string(), project.to_string());
+        labels.insert(COMPOSE_NETWORK_LABEL.to_string(), key.to_string());
+
+        Self {
+            key: key.to_string(),
+            name,
+            driver: spec
+                .get("driver")
+                .and_then(Value::as_str)
+                .unwrap_or("bridge")
+                .to_string(),
+            internal: spec
+                .get("internal")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            subnets,
+            labels,
+        }
+    }
+}
+
+fn yaml_scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Project name Compose uses for `compose_path`: the file's top-level
+/// `name`, else its directory name lowercased with only letters, digits,
+/// `-` and `_` kept
+pub fn compose_project_name(compose_path: &Path, compose: &str) -> String {
+    let declared = serde_yaml::from_str::<Value>(compose)
+        .ok()
+        .and_then(|document| document.get("name")?.as_str().map(str::to_string));
+    if let Some(name) = declared {
+        return name;
+    }
+
+    let directory = compose_path
+        .canonicalize()
+        .unwrap_or_else(|_| compose_path.to_path_buf())
+        .parent()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    directory
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect()
+}
+
+/// Whether Docker or Compose output reports a missing network
+pub fn is_missing_network_error(output: &str) -> bool {
+    let output = output.to_lowercase();
+    output.contains("network") && output.contains("not found")
+}
+
+/// A Docker network as `docker network ls` lists it, with its subnets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInfo {
+    pub name: String,
+    pub driver: String,
+    pub scope: String,
+    pub subnets: Vec<String>,
+}
+
+pub struct NetworkManager {
+    docker: Docker,
+}
+
+impl NetworkManager {
+    pub fn new() -> Result<Self> {
+        let docker = Docker::connect_with_local_defaults()
+            .map_err(|e| DockerError::ConnectionError(e.to_string()))?;
+        Ok(Self { docker })
+    }
+
+    pub async fn network_exists(&self, name: &str) -> bool {
+        self.docker
+            .inspect_network::<String>(name, None)
+            .await
+            .is_ok()
+    }
+
+    /// All Docker networks, sorted by name
+    pub async fn list_networks(&self) -> Result<Vec<NetworkInfo>> {
+        let mut networks: Vec<NetworkInfo> = self
+            .docker
+            .list_networks(None::<ListNetworksOptions<String>>)
+            .await?
+            .into_iter()
+            .map(|network| NetworkInfo {
+                name: network.name.unwrap_or_default(),
+                driver: network.driver.unwrap_or_default(),
+                scope: network.scope.unwrap_or_default(),
+                subnets: network
+                    .ipam
+                    .and_then(|ipam| ipam.config)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|config| config.subnet)
+                    .collect(),
+            })
+            .collect();
+        networks.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(networks)
+    }
+
+    /// Remove networks no container uses, returning their names
+    pub async fn prune_networks(&self) -> Result<Vec<String>> {
+        let response = self
+            .docker
+            .prune_networks(None::<PruneNetworksOptions<String>>)
+            .await?;
+        Ok(response.networks_deleted.unwrap_or_default())
+    }
+
+    /// Create `network` with the labels Compose expects on it
+    pub async fn create_network(&self, network: &ComposeNetwork) -> Result<()> {
+        let ipam = Ipam {
+            config: (!network.subnets.is_empty()).then(|| {
+                network
+                    .subnets
+                    .iter()
+                    .map(|subnet| IpamConfig {
+                        subnet: Some(subnet.clone()),
+                        ..Default::default()
+                    })
+                    .collect()
+            }),
+            ..Default::default()
+        };
+
+        let options = CreateNetworkOptions {
+            name: network.name.clone(),
+            check_duplicate: true,
+            driver: network.driver.clone(),
+            internal: network.internal,
+            ipam,
+            labels: network.labels.clone(),
+            ..Default::default()
+        };
+
+        self.docker.create_network(options).await.map_err(|e| {
+            DockerError::NetworkError(format!("Failed to create network {}: {}", network.name, e))
+        })?;
+        Ok(())
+    }
+
+    /// Networks of the compose file at `compose_path` that do not exist
+    pub async fn missing_compose_networks(
+        &self,
+        compose_path: &Path,
+        project: Option<&str>,
+    ) -> Result<Vec<ComposeNetwork>> {
+        let compose = std::fs::read_to_string(compose_path)?;
+        let project = project
+            .map(str::to_string)
+            .unwrap_or_else(|| compose_project_name(compose_path, &compose));
+
+        let mut missing = Vec::new();
+        for network in ComposeNetwork::from_compose(&compose, &project)? {
+            if !self.network_exists(&network.name).await {
+                missing.push(network);
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Recreate the missing networks of the compose file at `compose_path`,
+    /// returning the names of the networks created. `project` defaults to
+    /// the name Compose derives from the file.
+    pub async fn repair_compose_networks(
+        &self,
+        compose_path: &Path,
+        project: Option<&str>,
+    ) -> Result<Vec<String>> {
+        let mut created = Vec::new();
+        for network in self.missing_compose_networks(compose_path, project).await? {
+            tracing::info!("Recreating missing Docker network {}", network.name);
+            self.create_network(&network).await?;
+            created.push(network.name);
+        }
+        Ok(created)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPOSE: &str = r#"
+services:
+  xray:
+    image: ghcr.io/xtls/xray-core:latest
+    networks:
+      - vpn-network
+  watchtower:
+    image: containrrr/watchtower:latest
+  monitor:
+    image: prom/node-exporter
+    network_mode: host
+
+networks:
+  vpn-network:
+    driver: bridge
+    ipam:
+      config:
+        - subnet: 172.30.0.0/16
+    labels:
+      - "tier=edge"
+  shared:
+    external: true
+  backend:
+    name: vpn-backend
+    internal: true
+"#;
+
+    #[test]
+    fn test_networks_from_compose() {
+        let networks = ComposeNetwork::from_compose(COMPOSE, "vpn").unwrap();
+        let names: Vec<&str> = networks.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["vpn_vpn-network", "vpn-backend", "vpn_default"]);
+
+        let vpn = &networks[0];
+        assert_eq!(vpn.driver, "bridge");
+        assert_eq!(vpn.subnets, vec!["172.30.0.0/16"]);
+        assert_eq!(vpn.labels[COMPOSE_PROJECT_LABEL], "vpn");
+        assert_eq!(vpn.labels[COMPOSE_NETWORK_LABEL], "vpn-network");
+        assert_eq!(vpn.labels["tier"], "edge");
+
+        assert!(networks[1].internal);
+        assert_eq!(networks[1].labels[COMPOSE_NETWORK_LABEL], "backend");
+    }
+
+    #[test]
+    fn test_compose_project_name() {
+        let path = Path::new("/nonexistent/My VPN.d/docker-compose.yml");
+        assert_eq!(compose_project_name(path, "services: {}"), "myvpnd");
+        assert_eq!(
+            compose_project_name(path, "name: edge\nservices: {}"),
+            "edge"
+        );
+    }
+
+    #[test]
+    fn test_missing_network_error() {
+        assert!(is_missing_network_error(
+            "Error response from daemon: network 3f2a9c not found"
+        ));
+        assert!(!is_missing_network_error("port is already allocated"));
+    }
+}
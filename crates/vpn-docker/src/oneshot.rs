@@ -0,0 +1,255 @@
+//! Short-lived containers run for a single task, e.g. a probe
+//!
+//! [`OneShotRunner`] talks to the local daemon, or to a remote one whose
+//! socket it forwards over SSH, so the same probe can run from outside the
+//! server's own network without a Docker CLI on either end.
+
+use crate::error::{DockerError, Result};
+use bollard::container::{
+    AttachContainerOptions, Config, CreateContainerOptions, LogOutput, LogsOptions,
+    RemoveContainerOptions, WaitContainerOptions,
+};
+use bollard::errors::Error as BollardError;
+use bollard::{Docker, API_DEFAULT_VERSION};
+use futures_util::StreamExt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, Command};
+
+/// Docker socket on the remote host
+const REMOTE_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// Time the SSH forward gets to come up
+const TUNNEL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// API timeout of the forwarded connection, in seconds
+const TUNNEL_API_TIMEOUT: u64 = 120;
+
+static NEXT_TUNNEL: AtomicU64 = AtomicU64::new(0);
+
+/// What a container run to completion produced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OneShotOutput {
+    pub exit_code: i64,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl OneShotOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+/// A remote daemon's socket forwarded to a local one by `ssh -L`
+struct SshTunnel {
+    ssh: Child,
+    socket: PathBuf,
+}
+
+impl SshTunnel {
+    async fn open(destination: &str) -> Result<Self> {
+        let socket = std::env::temp_dir().join(format!(
+            "vpn-docker-{}-{}.sock",
+            std::process::id(),
+            NEXT_TUNNEL.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&socket);
+
+        let mut ssh = Command::new("ssh")
+            .arg("-o")
+            .arg("BatchMode=yes")
+            .arg("-o")
+            .arg("ExitOnForwardFailure=yes")
+            .arg("-N")
+            .arg("-L")
+            .arg(format!("{}:{}", socket.display(), REMOTE_DOCKER_SOCKET))
+            .arg(destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                DockerError::ConnectionError(format!(
+                    "Failed to start ssh to {}: {}",
+                    destination, e
+                ))
+            })?;
+
+        let deadline = tokio::time::Instant::now() + TUNNEL_TIMEOUT;
+        while !socket.exists() {
+            if let Some(status) = ssh.try_wait()? {
+                return Err(DockerError::ConnectionError(format!(
+                    "ssh to {} exited ({})",
+                    destination, status
+                )));
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(DockerError::ConnectionError(format!(
+                    "Timed out forwarding the Docker socket of {}",
+                    destination
+                )));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Ok(Self { ssh, socket })
+    }
+}
+
+impl Drop for SshTunnel {
+    fn drop(&mut self) {
+        let _ = self.ssh.start_kill();
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+/// Runs throwaway containers on one daemon
+pub struct OneShotRunner {
+    docker: Docker,
+    _tunnel: Option<SshTunnel>,
+}
+
+impl OneShotRunner {
+    /// Runner on the local daemon
+    pub fn local() -> Result<Self> {
+        let docker = Docker::connect_with_local_defaults()
+            .map_err(|e| DockerError::ConnectionError(e.to_string()))?;
+        Ok(Self {
+            docker,
+            _tunnel: None,
+        })
+    }
+
+    /// Runner on the daemon of `destination` (`user@host`), reached by
+    /// forwarding its socket over SSH for as long as the runner lives
+    pub async fn over_ssh(destination: &str) -> Result<Self> {
+        let tunnel = SshTunnel::open(destination).await?;
+        let docker = Docker::connect_with_socket(
+            &tunnel.socket.to_string_lossy(),
+            TUNNEL_API_TIMEOUT,
+            API_DEFAULT_VERSION,
+        )
+        .map_err(|e| DockerError::ConnectionError(e.to_string()))?;
+        Ok(Self {
+            docker,
+            _tunnel: Some(tunnel),
+        })
+    }
+
+    /// Create and start `name` from `config`, handing it `input` on stdin
+    /// the way `docker run -i` does: stdin is closed once it is written
+    pub async fn start_with_input(
+        &self,
+        name: &str,
+        mut config: Config<String>,
+        input: &[u8],
+    ) -> Result<()> {
+        config.attach_stdin = Some(true);
+        config.open_stdin = Some(true);
+        config.stdin_once = Some(true);
+        self.create(name, config).await?;
+
+        let mut attached = self
+            .docker
+            .attach_container(
+                name,
+                Some(AttachContainerOptions::<String> {
+                    stdin: Some(true),
+                    stream: Some(true),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        self.docker.start_container::<String>(name, None).await?;
+
+        attached.input.write_all(input).await?;
+        attached.input.shutdown().await?;
+        Ok(())
+    }
+
+    /// Run `name` from `config` until it exits and collect its output
+    pub async fn run(&self, name: &str, config: Config<String>) -> Result<OneShotOutput> {
+        self.create(name, config).await?;
+        let result = self.run_created(name).await;
+        let _ = self.remove(name).await;
+        result
+    }
+
+    async fn run_created(&self, name: &str) -> Result<OneShotOutput> {
+        self.docker.start_container::<String>(name, None).await?;
+
+        let mut exit_code = 0;
+        let mut waits = self
+            .docker
+            .wait_container(name, None::<WaitContainerOptions<String>>);
+        while let Some(wait) = waits.next().await {
+            match wait {
+                Ok(response) => exit_code = response.status_code,
+                // Non-zero exits come back as errors
+                Err(BollardError::DockerContainerWaitError { code, .. }) => exit_code = code,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut output = OneShotOutput {
+            exit_code,
+            stdout: String::new(),
+            stderr: String::new(),
+        };
+        let mut logs = self.docker.logs(
+            name,
+            Some(LogsOptions::<String> {
+                stdout: true,
+                stderr: true,
+                ..Default::default()
+            }),
+        );
+        while let Some(log) = logs.next().await {
+            match log? {
+                LogOutput::StdOut { message } => {
+                    output.stdout.push_str(&String::from_utf8_lossy(&message))
+                }
+                LogOutput::StdErr { message } => {
+                    output.stderr.push_str(&String::from_utf8_lossy(&message))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Whether `name` exists and is running
+    pub async fn is_running(&self, name: &str) -> bool {
+        self.docker
+            .inspect_container(name, None)
+            .await
+            .ok()
+            .and_then(|info| info.state?.running)
+            .unwrap_or(false)
+    }
+
+    /// Force-remove `name`, running or not
+    pub async fn remove(&self, name: &str) -> Result<()> {
+        let options = RemoveContainerOptions {
+            force: true,
+            ..Default::default()
+        };
+        self.docker.remove_container(name, Some(options)).await?;
+        Ok(())
+    }
+
+    async fn create(&self, name: &str, config: Config<String>) -> Result<()> {
+        let options = CreateContainerOptions {
+            name,
+            ..Default::default()
+        };
+        self.docker.create_container(Some(options), config).await?;
+        Ok(())
+    }
+}
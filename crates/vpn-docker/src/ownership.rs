@@ -0,0 +1,270 @@
+//! Ownership of containers standing in a compose project's way
+//!
+//! Compose cannot create a container whose name is taken, and the names the
+//! VPN stacks give their containers (`xray`, `wireguard`, `watchtower`) are
+//! generic enough for an unrelated stack to hold them. The names a compose
+//! file will use follow from the file alone, so before `up`
+//! [`ComposeProject::conflicting_containers`] finds the containers holding
+//! them and tells from their labels whom each belongs to.
+//! [`ComposeProject::remove_conflicting`] removes only those carrying
+//! [`MANAGED_LABEL`]; any other container, including those left by installs
+//! predating the label, is removed only when the operator confirms it or
+//! takes ownership of unlabeled containers.
+
+use crate::cache::get_container_cache;
+use crate::error::{DockerError, Result};
+use crate::networks::COMPOSE_PROJECT_LABEL;
+use crate::pool::get_docker_connection;
+use crate::project::ComposeProject;
+use bollard::container::{ListContainersOptions, RemoveContainerOptions};
+use bollard::models::ContainerSummary;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Label marking containers created by this tool
+pub const MANAGED_LABEL: &str = "vpn.managed";
+
+/// Whom a container belongs to, as far as its labels tell
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "owner", rename_all = "snake_case")]
+pub enum Ownership {
+    /// Created by this tool, possibly for another of its installs
+    Managed,
+    /// Without [`MANAGED_LABEL`]: created by hand, by another stack or by
+    /// an install predating the label
+    Unlabeled {
+        /// Compose project the container belongs to, if any
+        project: Option<String>,
+    },
+}
+
+impl Ownership {
+    pub fn of(labels: &HashMap<String, String>) -> Self {
+        if labels.get(MANAGED_LABEL).map(String::as_str) == Some("true") {
+            return Self::Managed;
+        }
+        Self::Unlabeled {
+            project: labels.get(COMPOSE_PROJECT_LABEL).cloned(),
+        }
+    }
+}
+
+impl fmt::Display for Ownership {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Managed => write!(f, "managed by this tool"),
+            Self::Unlabeled {
+                project: Some(project),
+            } => write!(f, "belongs to Compose project '{}'", project),
+            Self::Unlabeled { project: None } => write!(f, "has no owner labels"),
+        }
+    }
+}
+
+/// A container holding a name a compose project needs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictingContainer {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    /// Docker state, e.g. `running` or `exited`
+    pub state: String,
+    pub ownership: Ownership,
+}
+
+impl ConflictingContainer {
+    fn from_summary(summary: ContainerSummary) -> Self {
+        let name = summary
+            .names
+            .and_then(|names| names.into_iter().next())
+            .map(|name| name.trim_start_matches('/').to_string())
+            .unwrap_or_default();
+
+        Self {
+            id: summary.id.unwrap_or_default(),
+            name,
+            image: summary.image.unwrap_or_default(),
+            state: summary.state.unwrap_or_default(),
+            ownership: Ownership::of(&summary.labels.unwrap_or_default()),
+        }
+    }
+
+    /// Whether the container may go without asking: it is this tool's own,
+    /// or the operator takes ownership of unlabeled containers
+    pub fn removable(&self, take_ownership: bool) -> bool {
+        self.ownership == Ownership::Managed || take_ownership
+    }
+}
+
+/// Names of the containers the services of `compose` get when run as
+/// `project`: their `container_name`, or the `<project>-<service>-1`
+/// Compose chooses otherwise
+pub fn container_names(compose: &str, project: &str) -> Vec<String> {
+    let Ok(document) = serde_yaml::from_str::<Value>(compose) else {
+        return Vec::new();
+    };
+    let Some(services) = document.get("services").and_then(Value::as_mapping) else {
+        return Vec::new();
+    };
+
+    services
+        .iter()
+        .filter_map(|(service, spec)| {
+            let service = service.as_str()?;
+            let name = spec
+                .get("container_name")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{}-{}-1", project, service));
+            Some(name)
+        })
+        .collect()
+}
+
+impl ComposeProject {
+    /// Containers of other projects holding a name one of this project's
+    /// containers will get
+    pub async fn conflicting_containers(&self) -> Result<Vec<ConflictingContainer>> {
+        let compose = match tokio::fs::read_to_string(self.compose_path()).await {
+            Ok(compose) => compose,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let names = container_names(&compose, self.name());
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let options = ListContainersOptions::<String> {
+            all: true,
+            ..Default::default()
+        };
+        let connection = get_docker_connection().await?;
+        let containers = connection
+            .docker()
+            .list_containers(Some(options))
+            .await?
+            .into_iter()
+            .filter(|summary| {
+                let project = summary
+                    .labels
+                    .as_ref()
+                    .and_then(|labels| labels.get(COMPOSE_PROJECT_LABEL));
+                project.map(String::as_str) != Some(self.name())
+            })
+            .map(ConflictingContainer::from_summary)
+            .filter(|container| names.contains(&container.name))
+            .collect();
+        Ok(containers)
+    }
+
+    /// Remove the containers in the project's way that this tool owns, and
+    /// unlabeled ones when `take_ownership` is set or `confirm` accepts
+    /// them. Returns the removed names; if any container is left, nothing
+    /// is removed and [`DockerError::NameConflict`] lists what is left.
+    pub async fn remove_conflicting(
+        &self,
+        take_ownership: bool,
+        mut confirm: impl FnMut(&ConflictingContainer) -> bool,
+    ) -> Result<Vec<String>> {
+        let conflicting = self.conflicting_containers().await?;
+
+        let (removable, kept): (Vec<_>, Vec<_>) = conflicting
+            .into_iter()
+            .partition(|container| container.removable(take_ownership) || confirm(container));
+        if !kept.is_empty() {
+            let kept: Vec<String> = kept
+                .iter()
+                .map(|container| format!("'{}' ({})", container.name, container.ownership))
+                .collect();
+            return Err(DockerError::NameConflict(format!(
+                "{} already in use; remove them or take ownership of them",
+                kept.join(", ")
+            )));
+        }
+
+        let connection = get_docker_connection().await?;
+        let mut removed = Vec::with_capacity(removable.len());
+        for container in removable {
+            let options = RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            };
+            connection
+                .docker()
+                .remove_container(&container.id, Some(options))
+                .await?;
+            get_container_cache()
+                .invalidate_container(&container.name)
+                .await;
+            removed.push(container.name);
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(labels: &[(&str, &str)]) -> ConflictingContainer {
+        ConflictingContainer::from_summary(ContainerSummary {
+            id: Some("9c1e".to_string()),
+            names: Some(vec!["/wireguard".to_string()]),
+            image: Some("linuxserver/wireguard:latest".to_string()),
+            labels: Some(
+                labels
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            state: Some("running".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_container_names() {
+        let compose = r#"
+services:
+  wireguard:
+    image: linuxserver/wireguard:latest
+    container_name: wireguard
+  watchtower:
+    image: containrrr/watchtower:latest
+"#;
+        assert_eq!(
+            container_names(compose, "vpn"),
+            vec!["wireguard".to_string(), "vpn-watchtower-1".to_string()]
+        );
+        assert!(container_names("not: [valid", "vpn").is_empty());
+    }
+
+    #[test]
+    fn test_ownership_from_labels() {
+        let managed = container(&[(MANAGED_LABEL, "true"), (COMPOSE_PROJECT_LABEL, "vpn")]);
+        assert_eq!(managed.name, "wireguard");
+        assert_eq!(managed.ownership, Ownership::Managed);
+        assert!(managed.removable(false));
+
+        let other_stack = container(&[(COMPOSE_PROJECT_LABEL, "homelab")]);
+        assert_eq!(
+            other_stack.ownership,
+            Ownership::Unlabeled {
+                project: Some("homelab".to_string())
+            }
+        );
+        assert_eq!(
+            other_stack.ownership.to_string(),
+            "belongs to Compose project 'homelab'"
+        );
+        assert!(!other_stack.removable(false));
+        assert!(other_stack.removable(true));
+
+        let by_hand = container(&[(MANAGED_LABEL, "false")]);
+        assert_eq!(by_hand.ownership, Ownership::Unlabeled { project: None });
+        assert!(!by_hand.removable(false));
+    }
+}
@@ -0,0 +1,405 @@
+//! Typed operations on a Docker Compose project
+//!
+//! Compose labels every container, network and volume it creates with the
+//! project name, so a running project can be inspected, stopped and torn
+//! down through the Docker API alone: [`ComposeProject::ps`], `start`,
+//! `stop`, `restart` and `down` never run a binary or parse its output.
+//! Only creating containers from the compose file is left to the installed
+//! Compose; [`ComposeProject::up`] is the one place it is run from,
+//! recovering pruned networks and turning its failures into typed errors.
+
+use crate::cache::get_container_cache;
+use crate::compose::compose_command;
+use crate::env_file::SealedEnvFile;
+use crate::error::{DockerError, Result};
+use crate::networks::{
+    compose_project_name, is_missing_network_error, NetworkManager, COMPOSE_PROJECT_LABEL,
+};
+use crate::pool::get_docker_connection;
+use bollard::container::{
+    ListContainersOptions, RemoveContainerOptions, RestartContainerOptions, StopContainerOptions,
+};
+use bollard::models::ContainerSummary;
+use bollard::network::ListNetworksOptions;
+use bollard::volume::ListVolumesOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Label Compose puts the service name under
+pub const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
+/// Seconds a container gets to exit before it is killed
+const STOP_TIMEOUT: i64 = 10;
+
+/// A container belonging to a compose project
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectContainer {
+    pub id: String,
+    pub name: String,
+    pub service: String,
+    /// Docker state, e.g. `running`, `exited` or `restarting`
+    pub state: String,
+    /// Status as `docker ps` shows it, e.g. `Up 5 minutes (healthy)`
+    pub status: String,
+}
+
+impl ProjectContainer {
+    fn from_summary(summary: ContainerSummary) -> Self {
+        let name = summary
+            .names
+            .and_then(|names| names.into_iter().next())
+            .map(|name| name.trim_start_matches('/').to_string())
+            .unwrap_or_default();
+        let service = summary
+            .labels
+            .and_then(|mut labels| labels.remove(COMPOSE_SERVICE_LABEL))
+            .unwrap_or_else(|| name.clone());
+
+        Self {
+            id: summary.id.unwrap_or_default(),
+            name,
+            service,
+            state: summary.state.unwrap_or_default(),
+            status: summary.status.unwrap_or_default(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == "running"
+    }
+
+    pub fn is_restarting(&self) -> bool {
+        self.state == "restarting"
+    }
+
+    /// Health check result (`healthy`, `unhealthy` or `starting`); `None`
+    /// for containers without a health check
+    pub fn health(&self) -> Option<&str> {
+        let start = self.status.rfind('(')?;
+        let health = self.status[start + 1..].strip_suffix(')')?;
+        let health = health.strip_prefix("health: ").unwrap_or(health);
+        matches!(health, "healthy" | "unhealthy" | "starting").then_some(health)
+    }
+
+    pub fn is_unhealthy(&self) -> bool {
+        self.health() == Some("unhealthy")
+    }
+}
+
+/// A compose file and the project Compose runs it as
+#[derive(Debug, Clone)]
+pub struct ComposeProject {
+    compose_path: PathBuf,
+    name: String,
+}
+
+impl ComposeProject {
+    /// Project of the compose file at `compose_path`, named the way Compose
+    /// names it
+    pub fn new(compose_path: impl Into<PathBuf>) -> Self {
+        let compose_path = compose_path.into();
+        let compose = std::fs::read_to_string(&compose_path).unwrap_or_default();
+        let name = compose_project_name(&compose_path, &compose);
+        Self { compose_path, name }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn compose_path(&self) -> &Path {
+        &self.compose_path
+    }
+
+    /// Encrypted env file next to the compose file, decrypted into the
+    /// environment of Compose whenever it runs
+    pub fn sealed_env_file(&self) -> Option<SealedEnvFile> {
+        SealedEnvFile::find(self.compose_path.parent()?)
+    }
+
+    fn label_filter(&self) -> HashMap<String, Vec<String>> {
+        HashMap::from([(
+            "label".to_string(),
+            vec![format!("{}={}", COMPOSE_PROJECT_LABEL, self.name)],
+        )])
+    }
+
+    /// Containers of the project, stopped ones included, by service
+    pub async fn ps(&self) -> Result<Vec<ProjectContainer>> {
+        let options = ListContainersOptions {
+            all: true,
+            filters: self.label_filter(),
+            ..Default::default()
+        };
+
+        let connection = get_docker_connection().await?;
+        let mut containers: Vec<ProjectContainer> = connection
+            .docker()
+            .list_containers(Some(options))
+            .await?
+            .into_iter()
+            .map(ProjectContainer::from_summary)
+            .collect();
+        containers.sort_by(|a, b| a.service.cmp(&b.service).then(a.name.cmp(&b.name)));
+        Ok(containers)
+    }
+
+    /// Running containers of the project
+    pub async fn running(&self) -> Result<Vec<ProjectContainer>> {
+        let mut containers = self.ps().await?;
+        containers.retain(ProjectContainer::is_running);
+        Ok(containers)
+    }
+
+    /// Start the project's stopped containers
+    pub async fn start(&self) -> Result<()> {
+        let connection = get_docker_connection().await?;
+        for container in self.ps().await? {
+            if container.is_running() {
+                continue;
+            }
+            connection
+                .docker()
+                .start_container::<String>(&container.id, None)
+                .await?;
+            get_container_cache()
+                .invalidate_container(&container.name)
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Stop the project's running containers
+    pub async fn stop(&self) -> Result<()> {
+        let connection = get_docker_connection().await?;
+        for container in self.running().await? {
+            connection
+                .docker()
+                .stop_container(
+                    &container.id,
+                    Some(StopContainerOptions { t: STOP_TIMEOUT }),
+                )
+                .await?;
+            get_container_cache()
+                .invalidate_container(&container.name)
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Restart every container of the project
+    pub async fn restart(&self) -> Result<()> {
+        let connection = get_docker_connection().await?;
+        for container in self.ps().await? {
+            connection
+                .docker()
+                .restart_container(
+                    &container.id,
+                    Some(RestartContainerOptions {
+                        t: STOP_TIMEOUT as isize,
+                    }),
+                )
+                .await?;
+            get_container_cache()
+                .invalidate_container(&container.name)
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Remove the project's containers, orphans included, and its networks;
+    /// with `remove_volumes` its volumes too
+    pub async fn down(&self, remove_volumes: bool) -> Result<()> {
+        let connection = get_docker_connection().await?;
+        let docker = connection.docker();
+
+        for container in self.ps().await? {
+            let options = RemoveContainerOptions {
+                force: true,
+                v: remove_volumes,
+                ..Default::default()
+            };
+            docker
+                .remove_container(&container.id, Some(options))
+                .await?;
+            get_container_cache()
+                .invalidate_container(&container.name)
+                .await;
+        }
+
+        // Containers of other projects may still use a network; like
+        // Compose, leave it in place then
+        let networks = docker
+            .list_networks(Some(ListNetworksOptions {
+                filters: self.label_filter(),
+            }))
+            .await?;
+        for name in networks.into_iter().filter_map(|network| network.name) {
+            if let Err(e) = docker.remove_network(&name).await {
+                tracing::warn!("Could not remove network {}: {}", name, e);
+            }
+        }
+
+        if remove_volumes {
+            let volumes = docker
+                .list_volumes(Some(ListVolumesOptions {
+                    filters: self.label_filter(),
+                }))
+                .await?
+                .volumes
+                .unwrap_or_default();
+            for volume in volumes {
+                docker.remove_volume(&volume.name, None).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create and start the project's containers with the installed
+    /// Compose. A network pruned behind Compose's back is recreated and
+    /// `up` retried once.
+    pub async fn up(&self, force_recreate: bool) -> Result<()> {
+        let mut args = vec!["up", "-d"];
+        if force_recreate {
+            args.push("--force-recreate");
+        }
+
+        match self.compose(&args).await {
+            Err(DockerError::ComposeError(stderr)) if is_missing_network_error(&stderr) => {
+                let created = NetworkManager::new()?
+                    .repair_compose_networks(&self.compose_path, Some(&self.name))
+                    .await?;
+                if created.is_empty() {
+                    return Err(DockerError::ComposeError(stderr));
+                }
+                tracing::info!("Recreated missing Docker networks: {}", created.join(", "));
+                self.compose(&["up", "-d", "--force-recreate"]).await
+            }
+            result => result,
+        }
+    }
+
+    /// Pull the images of the project's services
+    pub async fn pull(&self) -> Result<()> {
+        self.compose(&["pull"]).await
+    }
+
+    /// Check the compose file with the installed Compose
+    pub async fn validate(&self) -> Result<()> {
+        self.compose(&["config", "--quiet"]).await
+    }
+
+    async fn compose(&self, args: &[&str]) -> Result<()> {
+        let mut cmd = Command::from(compose_command());
+        if let Some(sealed) = self.sealed_env_file() {
+            cmd.envs(sealed.open_default().await?);
+        }
+
+        let output = cmd
+            .arg("-f")
+            .arg(&self.compose_path)
+            .arg("-p")
+            .arg(&self.name)
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| DockerError::ComposeError(format!("Cannot run Docker Compose: {}", e)))?;
+
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(classify_compose_failure(
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ))
+    }
+}
+
+/// Typed error for what Compose wrote to stderr
+fn classify_compose_failure(stderr: &str) -> DockerError {
+    let lower = stderr.to_lowercase();
+    if lower.contains("permission denied") {
+        DockerError::PermissionDenied(stderr.to_string())
+    } else if lower.contains("pool overlaps with other one") || lower.contains("network conflicts")
+    {
+        DockerError::NetworkConflict(stderr.to_string())
+    } else {
+        DockerError::ComposeError(stderr.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(state: &str, status: &str) -> ContainerSummary {
+        ContainerSummary {
+            id: Some("3f2a".to_string()),
+            names: Some(vec!["/vpn-xray".to_string()]),
+            labels: Some(HashMap::from([(
+                COMPOSE_SERVICE_LABEL.to_string(),
+                "xray".to_string(),
+            )])),
+            state: Some(state.to_string()),
+            status: Some(status.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_project_container_state() {
+        let healthy = ProjectContainer::from_summary(summary("running", "Up 5 minutes (healthy)"));
+        assert_eq!(healthy.name, "vpn-xray");
+        assert_eq!(healthy.service, "xray");
+        assert!(healthy.is_running());
+        assert_eq!(healthy.health(), Some("healthy"));
+
+        let starting =
+            ProjectContainer::from_summary(summary("running", "Up 2 seconds (health: starting)"));
+        assert_eq!(starting.health(), Some("starting"));
+
+        let unhealthy =
+            ProjectContainer::from_summary(summary("running", "Up 3 minutes (unhealthy)"));
+        assert!(unhealthy.is_unhealthy());
+
+        let exited = ProjectContainer::from_summary(summary("exited", "Exited (1) 2 minutes ago"));
+        assert!(!exited.is_running());
+        assert_eq!(exited.health(), None);
+    }
+
+    #[test]
+    fn test_project_name() {
+        let project = ComposeProject::new("/nonexistent/vpn/docker-compose.yml");
+        assert_eq!(project.name(), "vpn");
+        assert_eq!(
+            project.label_filter()["label"],
+            vec![format!("{}=vpn", COMPOSE_PROJECT_LABEL)]
+        );
+        assert_eq!(project.with_name("proxy").name(), "proxy");
+    }
+
+    #[test]
+    fn test_classify_compose_failure() {
+        assert!(matches!(
+            classify_compose_failure("Got permission denied while trying to connect"),
+            DockerError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            classify_compose_failure(
+                "failed to create network: Pool overlaps with other one on this address space"
+            ),
+            DockerError::NetworkConflict(_)
+        ));
+        assert!(matches!(
+            classify_compose_failure("no such service: xray"),
+            DockerError::ComposeError(_)
+        ));
+    }
+}
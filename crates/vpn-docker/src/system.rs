@@ -0,0 +1,83 @@
+//! Daemon-wide Docker operations: availability, image removal and pruning
+
+use crate::error::Result;
+use crate::pool::get_docker_connection;
+use bollard::container::PruneContainersOptions;
+use bollard::errors::Error as BollardError;
+use bollard::image::{PruneImagesOptions, RemoveImageOptions};
+use bollard::network::PruneNetworksOptions;
+use bollard::volume::PruneVolumesOptions;
+
+/// What a [`prune`] removed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub containers: usize,
+    pub images: usize,
+    pub networks: usize,
+    pub volumes: usize,
+    pub space_reclaimed: u64,
+}
+
+/// Whether the Docker daemon is reachable
+pub async fn docker_available() -> bool {
+    match get_docker_connection().await {
+        Ok(connection) => connection.docker().ping().await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Force-remove `image`; `false` if it was not present
+pub async fn remove_image(image: &str) -> Result<bool> {
+    let connection = get_docker_connection().await?;
+    let options = RemoveImageOptions {
+        force: true,
+        ..Default::default()
+    };
+
+    match connection
+        .docker()
+        .remove_image(image, Some(options), None)
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(BollardError::DockerResponseServerError {
+            status_code: 404, ..
+        }) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove stopped containers, dangling images and unused networks, and
+/// with `volumes` unused volumes, like `docker system prune`
+pub async fn prune(volumes: bool) -> Result<PruneReport> {
+    let connection = get_docker_connection().await?;
+    let docker = connection.docker();
+    let mut report = PruneReport::default();
+
+    let containers = docker
+        .prune_containers(None::<PruneContainersOptions<String>>)
+        .await?;
+    report.containers = containers.containers_deleted.map_or(0, |c| c.len());
+    report.space_reclaimed += containers.space_reclaimed.unwrap_or(0).max(0) as u64;
+
+    let networks = docker
+        .prune_networks(None::<PruneNetworksOptions<String>>)
+        .await?;
+    report.networks = networks.networks_deleted.map_or(0, |n| n.len());
+
+    let images = docker
+        .prune_images(None::<PruneImagesOptions<String>>)
+        .await?;
+    report.images = images.images_deleted.map_or(0, |i| i.len());
+    report.space_reclaimed += images.space_reclaimed.unwrap_or(0).max(0) as u64;
+
+    if volumes {
+        let pruned = docker
+            .prune_volumes(None::<PruneVolumesOptions<String>>)
+            .await?;
+        report.volumes = pruned.volumes_deleted.map_or(0, |v| v.len());
+        report.space_reclaimed += pruned.space_reclaimed.unwrap_or(0).max(0) as u64;
+    }
+
+    Ok(report)
+}
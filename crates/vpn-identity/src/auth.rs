@@ -2,6 +2,7 @@
 
 use crate::{
     error::{IdentityError, Result},
+    lockout::LoginProtection,
     models::{AuthProvider as AuthProviderType, AuthToken, User, UserInfo},
     storage::Storage,
     ldap::LdapProvider,
@@ -10,6 +11,7 @@ use async_trait::async_trait;
 use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -120,6 +122,7 @@ pub struct AuthService {
     jwt_issuer: String,
     jwt_audience: Vec<String>,
     providers: Vec<AuthProviderEnum>,
+    protection: Option<LoginProtection>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -146,17 +149,49 @@ impl AuthService {
             jwt_issuer,
             jwt_audience,
             providers: Vec::new(),
+            protection: None,
         }
     }
 
+    /// Enforce lockouts on password logins
+    pub fn with_login_protection(mut self, protection: LoginProtection) -> Self {
+        self.protection = Some(protection);
+        self
+    }
+
     pub fn add_provider(&mut self, provider: AuthProviderEnum) {
         self.providers.push(provider);
     }
 
+    /// Log in with a password from `source`. Failed attempts count towards
+    /// a lockout of the address and the account
     pub async fn authenticate(
         &self,
         username: &str,
         password: &str,
+        source: IpAddr,
+        captcha_response: Option<&str>,
+    ) -> Result<AuthenticationResult> {
+        let Some(protection) = &self.protection else {
+            return self.authenticate_with_providers(username, password).await;
+        };
+        
+        protection.admit(source, username, captcha_response).await?;
+        let result = self.authenticate_with_providers(username, password).await;
+        match &result {
+            Ok(_) => protection.record_success(source, username),
+            Err(IdentityError::InvalidCredentials | IdentityError::AuthenticationFailed(_)) => {
+                protection.record_failure(source, username)
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    async fn authenticate_with_providers(
+        &self,
+        username: &str,
+        password: &str,
     ) -> Result<AuthenticationResult> {
         // Try each provider in order
         let mut last_error = None;
@@ -1,11 +1,11 @@
 //! VPN Identity Service Binary
 
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post},
     Json, Router,
 };
 use axum_extra::extract::cookie::CookieJar;
@@ -16,13 +16,14 @@ use tower_http::{
     cors::{Any, CorsLayer},
     trace::TraceLayer,
 };
-use tracing::{info, warn};
+use tracing::{info, warn, Instrument};
 use vpn_identity::{
     config::IdentityConfig,
     error::IdentityError,
     models::*,
     service::IdentityService,
 };
+use vpn_types::CorrelationId;
 
 #[derive(Clone)]
 struct AppState {
@@ -53,6 +54,15 @@ async fn main() -> anyhow::Result<()> {
         service: service.clone(),
     };
 
+    // SCIM provisioning, when configured
+    let scim = match &service.scim {
+        Some(scim) => {
+            info!("SCIM provisioning enabled at /scim/v2");
+            vpn_identity::scim::router(scim.clone())
+        }
+        None => Router::new(),
+    };
+
     // Build router
     let app = Router::new()
         // Health check
@@ -68,6 +78,13 @@ async fn main() -> anyhow::Result<()> {
         .route("/users/:id", get(get_user).put(update_user).delete(delete_user))
         .route("/users/me", get(get_current_user))
         .route("/users/:id/password", post(change_password))
+        // Self-service portal for the signed-in user
+        .route("/me/password", post(portal_change_password))
+        .route("/me/sessions", get(portal_list_sessions))
+        .route("/me/sessions/:id", delete(portal_revoke_session))
+        .route("/me/subscription", get(portal_subscription))
+        .route("/me/subscription/token", post(portal_regenerate_subscription_token))
+        .route("/me/configs", get(portal_download_configs))
         // Role management
         .route("/roles", get(list_roles).post(create_role))
         .route("/roles/:id", get(get_role).put(update_role).delete(delete_role))
@@ -79,9 +96,12 @@ async fn main() -> anyhow::Result<()> {
         .route("/sessions/:id", delete(delete_session))
         // Add state
         .with_state(state)
+        // SCIM endpoints authenticate with their own bearer token
+        .nest("/scim/v2", scim)
         // Add middleware
         .layer(
             ServiceBuilder::new()
+                .layer(middleware::from_fn(correlate))
                 .layer(TraceLayer::new_for_http())
                 .layer(
                     CorsLayer::new()
@@ -94,7 +114,7 @@ async fn main() -> anyhow::Result<()> {
     // Start server
     info!("Starting VPN Identity Service on {}", bind_addr);
     axum::Server::bind(&bind_addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
 
     Ok(())
@@ -136,10 +156,18 @@ async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
 
 async fn login(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     Json(req): Json<LoginRequest>,
 ) -> Result<Json<AuthenticationResult>, IdentityError> {
     let auth_service = state.service.auth_service.read().await;
-    let result = auth_service.authenticate(&req.username, &req.password).await?;
+    let result = auth_service
+        .authenticate(
+            &req.username,
+            &req.password,
+            peer.ip(),
+            req.captcha_response.as_deref(),
+        )
+        .await?;
     Ok(Json(result))
 }
 
@@ -251,6 +279,124 @@ async fn change_password(
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// User making a portal request, identified by the bearer token
+struct CurrentUser {
+    id: Uuid,
+    session_id: Option<String>,
+}
+
+async fn current_user(
+    state: &AppState,
+    headers: &HeaderMap,
+    cookies: &CookieJar,
+) -> Result<CurrentUser, IdentityError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| IdentityError::AuthenticationFailed("Missing bearer token".to_string()))?;
+    
+    let auth_service = state.service.auth_service.read().await;
+    let claims = auth_service.validate_token(token).await?;
+    let id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| IdentityError::AuthenticationFailed("Invalid token subject".to_string()))?;
+    
+    let session_id = cookies
+        .get(&state.service.config.session.cookie_name)
+        .map(|cookie| cookie.value().to_string());
+    
+    Ok(CurrentUser { id, session_id })
+}
+
+async fn portal_change_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    cookies: CookieJar,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<StatusCode, IdentityError> {
+    let user = current_user(&state, &headers, &cookies).await?;
+    state
+        .service
+        .portal
+        .change_password(user.id, user.session_id.as_deref(), &req)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn portal_list_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    cookies: CookieJar,
+) -> Result<Json<Vec<DeviceSession>>, IdentityError> {
+    let user = current_user(&state, &headers, &cookies).await?;
+    let sessions = state
+        .service
+        .portal
+        .list_sessions(user.id, user.session_id.as_deref())
+        .await?;
+    Ok(Json(sessions))
+}
+
+async fn portal_revoke_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    cookies: CookieJar,
+    Path(id): Path<String>,
+) -> Result<StatusCode, IdentityError> {
+    let user = current_user(&state, &headers, &cookies).await?;
+    state.service.portal.revoke_session(user.id, &id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn portal_subscription(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    cookies: CookieJar,
+) -> Result<Json<SubscriptionInfo>, IdentityError> {
+    let user = current_user(&state, &headers, &cookies).await?;
+    let subscription = state.service.portal.subscription(user.id).await?;
+    Ok(Json(subscription))
+}
+
+async fn portal_regenerate_subscription_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    cookies: CookieJar,
+) -> Result<Json<SubscriptionInfo>, IdentityError> {
+    let user = current_user(&state, &headers, &cookies).await?;
+    let subscription = state
+        .service
+        .portal
+        .regenerate_subscription_token(user.id)
+        .await?;
+    Ok(Json(subscription))
+}
+
+async fn portal_download_configs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    cookies: CookieJar,
+    Query(query): Query<DownloadConfigQuery>,
+) -> Result<impl IntoResponse, IdentityError> {
+    let user = current_user(&state, &headers, &cookies).await?;
+    let download = state
+        .service
+        .portal
+        .download_configs(user.id, query.format.as_deref())
+        .await?;
+    
+    Ok((
+        [
+            (header::CONTENT_TYPE, download.content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"vpn-configs.txt\"".to_string(),
+            ),
+        ],
+        download.body,
+    ))
+}
+
 async fn list_roles(
     State(state): State<AppState>,
 ) -> Result<Json<Vec<Role>>, IdentityError> {
@@ -347,6 +493,27 @@ async fn delete_session(
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Handle each request under the correlation ID sent in its header, or a
+/// new one, and return the ID in the response
+async fn correlate(
+    request: axum::extract::Request,
+    next: middleware::Next,
+) -> axum::response::Response {
+    let id = request
+        .headers()
+        .get(CorrelationId::HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(CorrelationId::parse)
+        .unwrap_or_else(CorrelationId::generate);
+    let span = tracing::info_span!("request", correlation_id = %id);
+
+    let mut response = id.clone().scope(next.run(request).instrument(span)).await;
+    if let Ok(value) = header::HeaderValue::from_str(id.as_str()) {
+        response.headers_mut().insert(CorrelationId::HEADER, value);
+    }
+    response
+}
+
 // Error handling
 impl IntoResponse for IdentityError {
     fn into_response(self) -> axum::response::Response {
@@ -354,14 +521,35 @@ impl IntoResponse for IdentityError {
             IdentityError::AuthenticationFailed(_) => (StatusCode::UNAUTHORIZED, self.to_string()),
             IdentityError::AuthorizationFailed(_) => (StatusCode::FORBIDDEN, self.to_string()),
             IdentityError::InvalidCredentials => (StatusCode::UNAUTHORIZED, self.to_string()),
+            IdentityError::LockedOut { retry_after_secs } => {
+                let body = Json(serde_json::json!({
+                    "error": self.to_string(),
+                    "reference_id": CorrelationId::current(),
+                }));
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
+            IdentityError::ChallengeRequired => (StatusCode::PRECONDITION_REQUIRED, self.to_string()),
             IdentityError::TokenExpired => (StatusCode::UNAUTHORIZED, self.to_string()),
             IdentityError::InsufficientPermissions => (StatusCode::FORBIDDEN, self.to_string()),
             IdentityError::UserNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
             IdentityError::ValidationError(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            IdentityError::SessionError(_) => (StatusCode::NOT_FOUND, self.to_string()),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string()),
         };
         
-        (status, Json(serde_json::json!({"error": message}))).into_response()
+        (
+            status,
+            Json(serde_json::json!({
+                "error": message,
+                "reference_id": CorrelationId::current(),
+            })),
+        )
+            .into_response()
     }
 }
 
@@ -3,6 +3,8 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use vpn_types::protocol::VpnProtocol;
+use vpn_types::LockoutPolicy;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IdentityConfig {
@@ -29,6 +31,18 @@ pub struct IdentityConfig {
     
     /// Service binding configuration
     pub server: ServerConfig,
+    
+    /// Self-service user portal configuration
+    #[serde(default)]
+    pub portal: PortalConfig,
+    
+    /// Brute-force protection for password logins
+    #[serde(default)]
+    pub lockout: LockoutConfig,
+    
+    /// SCIM provisioning endpoint (optional)
+    #[serde(default)]
+    pub scim: Option<ScimConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -175,6 +189,81 @@ pub struct ServerConfig {
     pub request_timeout_secs: u64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortalConfig {
+    /// Base URL of the VPN server's subscription endpoint; a user's
+    /// subscription URL is this followed by their token
+    pub subscription_base_url: String,
+    
+    /// Minimum length for passwords set through the portal
+    pub min_password_length: usize,
+    
+    /// Whether changing the password signs out the user's other sessions
+    pub revoke_sessions_on_password_change: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LockoutConfig {
+    /// Attempt limits per source IP and account
+    #[serde(flatten)]
+    pub policy: LockoutPolicy,
+    
+    /// Webhook receiving an event when an IP or account is escalated
+    pub escalation_webhook: Option<String>,
+    
+    /// CAPTCHA escalated logins must pass
+    pub captcha: Option<CaptchaConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptchaConfig {
+    /// Verification endpoint (e.g. https://hcaptcha.com/siteverify)
+    pub verify_url: String,
+    
+    /// Secret key for the verification endpoint
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimConfig {
+    /// Token identity providers send as `Authorization: Bearer <token>`
+    pub bearer_token: String,
+    
+    /// Directory of the VPN user database
+    pub users_path: PathBuf,
+    
+    /// File holding the groups pushed by the identity provider
+    pub groups_path: PathBuf,
+    
+    /// Protocol for users no group rule applies to
+    pub default_protocol: VpnProtocol,
+    
+    /// Group-to-protocol rules, first match wins
+    #[serde(default)]
+    pub group_protocols: Vec<GroupProtocolRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupProtocolRule {
+    /// Group display name, compared case-insensitively
+    pub group: String,
+    
+    /// Protocol for members of the group
+    pub protocol: VpnProtocol,
+}
+
+impl ScimConfig {
+    /// Protocol for a member of `groups`
+    pub fn protocol_for<'a>(&self, groups: impl IntoIterator<Item = &'a str>) -> VpnProtocol {
+        let groups: Vec<&str> = groups.into_iter().collect();
+        self.group_protocols
+            .iter()
+            .find(|rule| groups.iter().any(|group| group.eq_ignore_ascii_case(&rule.group)))
+            .map(|rule| rule.protocol)
+            .unwrap_or(self.default_protocol)
+    }
+}
+
 impl Default for IdentityConfig {
     fn default() -> Self {
         Self {
@@ -186,6 +275,9 @@ impl Default for IdentityConfig {
             session: SessionConfig::default(),
             rbac: RbacConfig::default(),
             server: ServerConfig::default(),
+            portal: PortalConfig::default(),
+            lockout: LockoutConfig::default(),
+            scim: None,
         }
     }
 }
@@ -236,4 +328,14 @@ impl Default for ServerConfig {
             request_timeout_secs: 30,
         }
     }
+}
+
+impl Default for PortalConfig {
+    fn default() -> Self {
+        Self {
+            subscription_base_url: "http://localhost:8443/sub".to_string(),
+            min_password_length: 12,
+            revoke_sessions_on_password_change: true,
+        }
+    }
 }
\ No newline at end of file
@@ -39,6 +39,12 @@ pub enum IdentityError {
     #[error("Invalid credentials")]
     InvalidCredentials,
 
+    #[error("Too many failed attempts; retry in {retry_after_secs}s")]
+    LockedOut { retry_after_secs: u64 },
+
+    #[error("CAPTCHA required")]
+    ChallengeRequired,
+
     #[error("Token expired")]
     TokenExpired,
 
@@ -7,14 +7,19 @@
 //! - Role-Based Access Control (RBAC)
 //! - Session management
 //! - JWT token handling
+//! - Self-service user portal
+//! - SCIM 2.0 provisioning of VPN users
 
 pub mod auth;
 pub mod config;
 pub mod error;
 pub mod ldap;
+pub mod lockout;
 pub mod models;
 pub mod oauth;
+pub mod portal;
 pub mod rbac;
+pub mod scim;
 pub mod service;
 pub mod session;
 pub mod storage;
@@ -22,8 +27,11 @@ pub mod storage;
 pub use auth::{AuthProvider, AuthService, AuthenticationResult};
 pub use config::IdentityConfig;
 pub use error::{IdentityError, Result};
+pub use lockout::LoginProtection;
 pub use models::{User, Role, Permission, Session};
 pub use oauth::{OAuth2Provider, OAuthConfig};
+pub use portal::PortalService;
 pub use rbac::RbacService;
+pub use scim::ScimService;
 pub use service::IdentityService;
 pub use session::SessionManager;
\ No newline at end of file
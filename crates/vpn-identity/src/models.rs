@@ -85,6 +85,9 @@ pub struct LoginRequest {
     #[validate(length(min = 1))]
     pub password: String,
     pub remember_me: Option<bool>,
+    /// CAPTCHA response, required once the login has been escalated
+    #[serde(default)]
+    pub captcha_response: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -132,6 +135,32 @@ pub struct ChangePasswordRequest {
     pub new_password: String,
 }
 
+/// A signed-in session or device, as shown to its owner
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceSession {
+    pub id: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_accessed: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscriptionInfo {
+    pub token: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadConfigQuery {
+    /// Client config format understood by the subscription endpoint,
+    /// e.g. `base64`, `clash` or `singbox`
+    pub format: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AssignRoleRequest {
     pub user_id: Uuid,
@@ -0,0 +1,258 @@
+//! Self-service portal operations for end users
+//!
+//! Everything here acts on the authenticated user's own account: changing
+//! their password, reviewing and revoking their sessions, and managing the
+//! subscription token their VPN clients use to fetch configs.
+
+use crate::{
+    config::PortalConfig,
+    error::{IdentityError, Result},
+    models::{AuthProvider, ChangePasswordRequest, DeviceSession, SubscriptionInfo, User},
+    session::SessionManager,
+    storage::Storage,
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use chrono::Utc;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Key in `User::attributes` holding the subscription token
+const SUBSCRIPTION_TOKEN_ATTRIBUTE: &str = "subscription_token";
+
+/// Downloaded client configuration
+pub struct ConfigDownload {
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+pub struct PortalService {
+    storage: Arc<Storage>,
+    session_manager: Arc<RwLock<SessionManager>>,
+    config: PortalConfig,
+    http: reqwest::Client,
+}
+
+impl PortalService {
+    pub fn new(
+        storage: Arc<Storage>,
+        session_manager: Arc<RwLock<SessionManager>>,
+        config: PortalConfig,
+    ) -> Self {
+        Self {
+            storage,
+            session_manager,
+            config,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Change a local user's password after checking the current one
+    pub async fn change_password(
+        &self,
+        user_id: Uuid,
+        current_session: Option<&str>,
+        req: &ChangePasswordRequest,
+    ) -> Result<()> {
+        let mut user = self.get_user(user_id).await?;
+
+        if user.provider != AuthProvider::Local {
+            return Err(IdentityError::ValidationError(
+                "Password is managed by the external identity provider".to_string(),
+            ));
+        }
+
+        if req.new_password.len() < self.config.min_password_length {
+            return Err(IdentityError::ValidationError(format!(
+                "Password must be at least {} characters",
+                self.config.min_password_length
+            )));
+        }
+
+        let current_hash = user
+            .password_hash
+            .as_deref()
+            .ok_or(IdentityError::InvalidCredentials)?;
+        let parsed_hash =
+            PasswordHash::new(current_hash).map_err(|_| IdentityError::InvalidCredentials)?;
+        Argon2::default()
+            .verify_password(req.current_password.as_bytes(), &parsed_hash)
+            .map_err(|_| IdentityError::InvalidCredentials)?;
+
+        let salt = SaltString::generate(&mut OsRng);
+        let new_hash = Argon2::default()
+            .hash_password(req.new_password.as_bytes(), &salt)
+            .map_err(|e| IdentityError::Internal(format!("Failed to hash password: {}", e)))?;
+
+        user.password_hash = Some(new_hash.to_string());
+        user.updated_at = Utc::now();
+        self.storage.update_user(&user).await?;
+
+        if self.config.revoke_sessions_on_password_change {
+            self.revoke_other_sessions(user_id, current_session).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sessions the user is signed in with, newest first
+    pub async fn list_sessions(
+        &self,
+        user_id: Uuid,
+        current_session: Option<&str>,
+    ) -> Result<Vec<DeviceSession>> {
+        let mut session_manager = self.session_manager.write().await;
+        let mut sessions: Vec<DeviceSession> = session_manager
+            .list_user_sessions(user_id)
+            .await?
+            .into_iter()
+            .map(|session| DeviceSession {
+                current: current_session == Some(session.id.as_str()),
+                id: session.id,
+                ip_address: session.ip_address,
+                user_agent: session.user_agent,
+                created_at: session.created_at,
+                last_accessed: session.last_accessed,
+                expires_at: session.expires_at,
+            })
+            .collect();
+
+        sessions.sort_by(|a, b| b.last_accessed.cmp(&a.last_accessed));
+        Ok(sessions)
+    }
+
+    /// Sign out one of the user's own sessions
+    pub async fn revoke_session(&self, user_id: Uuid, session_id: &str) -> Result<()> {
+        let mut session_manager = self.session_manager.write().await;
+        match session_manager.get_session(session_id).await? {
+            Some(session) if session.user_id == user_id => {
+                session_manager.delete_session(session_id).await
+            }
+            // Do not reveal whether another user's session exists
+            _ => Err(IdentityError::SessionError("Session not found".to_string())),
+        }
+    }
+
+    /// The user's subscription, issuing a token on first use
+    pub async fn subscription(&self, user_id: Uuid) -> Result<SubscriptionInfo> {
+        let mut user = self.get_user(user_id).await?;
+
+        let token = match subscription_token(&user) {
+            Some(token) => token.to_string(),
+            None => self.store_new_token(&mut user).await?,
+        };
+
+        Ok(self.subscription_info(token))
+    }
+
+    /// Replace the subscription token, invalidating the old subscription URL
+    pub async fn regenerate_subscription_token(&self, user_id: Uuid) -> Result<SubscriptionInfo> {
+        let mut user = self.get_user(user_id).await?;
+        let token = self.store_new_token(&mut user).await?;
+
+        tracing::info!("Regenerated subscription token for user {}", user_id);
+        Ok(self.subscription_info(token))
+    }
+
+    /// Fetch the user's client configs from the subscription endpoint
+    pub async fn download_configs(
+        &self,
+        user_id: Uuid,
+        format: Option<&str>,
+    ) -> Result<ConfigDownload> {
+        let subscription = self.subscription(user_id).await?;
+
+        let mut request = self.http.get(&subscription.url);
+        if let Some(format) = format {
+            request = request.query(&[("format", format)]);
+        }
+
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| IdentityError::Internal(format!("Failed to fetch configs: {}", e)))?;
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("text/plain")
+            .to_string();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| IdentityError::Internal(format!("Failed to fetch configs: {}", e)))?;
+
+        Ok(ConfigDownload {
+            content_type,
+            body: body.to_vec(),
+        })
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> Result<User> {
+        let user = self
+            .storage
+            .get_user(user_id)
+            .await?
+            .ok_or_else(|| IdentityError::UserNotFound(user_id.to_string()))?;
+
+        if !user.is_active {
+            return Err(IdentityError::AuthorizationFailed(
+                "Account is disabled".to_string(),
+            ));
+        }
+
+        Ok(user)
+    }
+
+    async fn store_new_token(&self, user: &mut User) -> Result<String> {
+        let token = generate_subscription_token();
+
+        if !user.attributes.is_object() {
+            user.attributes = serde_json::Value::Object(serde_json::Map::new());
+        }
+        user.attributes[SUBSCRIPTION_TOKEN_ATTRIBUTE] = serde_json::Value::String(token.clone());
+        user.updated_at = Utc::now();
+        self.storage.update_user(user).await?;
+
+        Ok(token)
+    }
+
+    async fn revoke_other_sessions(&self, user_id: Uuid, keep: Option<&str>) -> Result<()> {
+        let mut session_manager = self.session_manager.write().await;
+        for session in session_manager.list_user_sessions(user_id).await? {
+            if keep != Some(session.id.as_str()) {
+                session_manager.delete_session(&session.id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn subscription_info(&self, token: String) -> SubscriptionInfo {
+        SubscriptionInfo {
+            url: subscription_url(&self.config.subscription_base_url, &token),
+            token,
+        }
+    }
+}
+
+/// Subscription token stored on the user, if one was issued
+pub fn subscription_token(user: &User) -> Option<&str> {
+    user.attributes
+        .get(SUBSCRIPTION_TOKEN_ATTRIBUTE)
+        .and_then(|token| token.as_str())
+}
+
+/// Random, URL-safe subscription token
+pub fn generate_subscription_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Subscription URL for `token` under `base_url`
+pub fn subscription_url(base_url: &str, token: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), token)
+}
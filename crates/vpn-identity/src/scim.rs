@@ -0,0 +1,1160 @@
+//! SCIM 2.0 provisioning endpoint (RFC 7643, RFC 7644)
+//!
+//! Enterprise identity providers such as Okta and Azure AD push account
+//! changes to `/scim/v2`. Users they create become VPN users through
+//! vpn-users, deactivating a user suspends the VPN account and deleting it
+//! removes the account. Groups pushed through `/Groups` are kept in a file
+//! of their own, and each member's protocol follows the first
+//! [`GroupProtocolRule`] naming one of their groups.
+
+use crate::config::ScimConfig;
+use crate::error::{IdentityError, Result};
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use uuid::Uuid;
+use vpn_users::{User, UserError, UserManager};
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const PATCH_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+/// Tags on VPN users holding the provider's view of them. The VPN user
+/// name is derived from `userName` once and does not follow renames.
+const USER_NAME_TAG: &str = "scim.user_name";
+const EXTERNAL_ID_TAG: &str = "scim.external_id";
+const DISPLAY_NAME_TAG: &str = "scim.display_name";
+
+/// Most resources returned in one list response
+const MAX_RESULTS: usize = 200;
+
+/// SCIM User resource
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub user_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emails: Vec<ScimEmail>,
+    #[serde(default = "active_by_default")]
+    pub active: bool,
+    /// Groups the user belongs to; read-only, changed through `/Groups`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<ScimMember>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ScimMeta>,
+}
+
+fn active_by_default() -> bool {
+    true
+}
+
+impl ScimUser {
+    /// Primary email, or the first one listed
+    pub fn primary_email(&self) -> Option<&str> {
+        self.emails
+            .iter()
+            .find(|email| email.primary)
+            .or_else(|| self.emails.first())
+            .map(|email| email.value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(default)]
+    pub primary: bool,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+/// Reference from a group to a member or from a user to a group
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScimMember {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimMeta {
+    pub resource_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+/// SCIM Group resource
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<ScimMember>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ScimMeta>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: Vec<String>,
+    pub total_results: usize,
+    pub start_index: usize,
+    pub items_per_page: usize,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    /// Page of `items` starting at the 1-based `start_index`
+    fn page(items: Vec<T>, start_index: Option<usize>, count: Option<usize>) -> Self {
+        let total_results = items.len();
+        let start_index = start_index.unwrap_or(1).max(1);
+        let resources: Vec<T> = items
+            .into_iter()
+            .skip(start_index - 1)
+            .take(count.unwrap_or(MAX_RESULTS).min(MAX_RESULTS))
+            .collect();
+
+        Self {
+            schemas: vec![LIST_SCHEMA.to_string()],
+            total_results,
+            start_index,
+            items_per_page: resources.len(),
+            resources,
+        }
+    }
+}
+
+/// Query of a list request
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<usize>,
+    pub count: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchRequest {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatchKind {
+    Add,
+    Replace,
+    Remove,
+}
+
+impl PatchOperation {
+    /// Operation names are case-insensitive; Azure AD sends `Replace`
+    fn kind(&self) -> std::result::Result<PatchKind, ScimError> {
+        match self.op.to_ascii_lowercase().as_str() {
+            "add" => Ok(PatchKind::Add),
+            "replace" => Ok(PatchKind::Replace),
+            "remove" => Ok(PatchKind::Remove),
+            op => Err(ScimError::invalid_syntax(format!(
+                "Unknown patch operation: {}",
+                op
+            ))),
+        }
+    }
+}
+
+/// Error in the SCIM error format
+#[derive(Debug, thiserror::Error)]
+#[error("{detail}")]
+pub struct ScimError {
+    pub status: StatusCode,
+    pub scim_type: Option<&'static str>,
+    pub detail: String,
+}
+
+impl ScimError {
+    fn new(status: StatusCode, scim_type: Option<&'static str>, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            scim_type,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, None, detail)
+    }
+
+    pub fn uniqueness(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, Some("uniqueness"), detail)
+    }
+
+    pub fn invalid_value(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidValue"), detail)
+    }
+
+    pub fn invalid_filter(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidFilter"), detail)
+    }
+
+    pub fn invalid_syntax(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, Some("invalidSyntax"), detail)
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(
+            StatusCode::UNAUTHORIZED,
+            None,
+            "Missing or invalid bearer token",
+        )
+    }
+
+    pub fn internal(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, None, detail)
+    }
+}
+
+impl From<UserError> for ScimError {
+    fn from(error: UserError) -> Self {
+        match error {
+            UserError::UserNotFound(id) => Self::not_found(format!("User not found: {}", id)),
+            UserError::UserAlreadyExists(name) => {
+                Self::uniqueness(format!("VPN user already exists: {}", name))
+            }
+            UserError::ValidationError { .. } | UserError::UserLimitExceeded(_) => {
+                Self::invalid_value(error.to_string())
+            }
+            _ => Self::internal(error.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "schemas": [ERROR_SCHEMA],
+            "status": self.status.as_u16().to_string(),
+            "scimType": self.scim_type,
+            "detail": self.detail,
+        });
+        ScimJson(self.status, body).into_response()
+    }
+}
+
+type ScimResult<T> = std::result::Result<T, ScimError>;
+
+/// JSON response with the SCIM media type
+struct ScimJson<T>(StatusCode, T);
+
+impl<T: Serialize> IntoResponse for ScimJson<T> {
+    fn into_response(self) -> Response {
+        (
+            self.0,
+            [(header::CONTENT_TYPE, SCIM_CONTENT_TYPE)],
+            Json(self.1),
+        )
+            .into_response()
+    }
+}
+
+/// Group as kept in the group file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredGroup {
+    id: String,
+    external_id: Option<String>,
+    display_name: String,
+    /// IDs of member VPN users
+    members: BTreeSet<String>,
+    created: DateTime<Utc>,
+    last_modified: DateTime<Utc>,
+}
+
+impl StoredGroup {
+    fn to_scim(&self) -> ScimGroup {
+        ScimGroup {
+            schemas: vec![GROUP_SCHEMA.to_string()],
+            id: Some(self.id.clone()),
+            external_id: self.external_id.clone(),
+            display_name: self.display_name.clone(),
+            members: self
+                .members
+                .iter()
+                .map(|id| ScimMember {
+                    value: id.clone(),
+                    display: None,
+                })
+                .collect(),
+            meta: Some(ScimMeta {
+                resource_type: "Group".to_string(),
+                created: Some(self.created),
+                last_modified: Some(self.last_modified),
+                location: Some(format!("/scim/v2/Groups/{}", self.id)),
+            }),
+        }
+    }
+}
+
+/// Provisions VPN users on behalf of an identity provider
+pub struct ScimService {
+    users: UserManager,
+    groups: RwLock<BTreeMap<String, StoredGroup>>,
+    config: ScimConfig,
+}
+
+impl ScimService {
+    pub async fn new(config: ScimConfig) -> Result<Self> {
+        let users = UserManager::new(
+            &config.users_path,
+            vpn_users::config::ServerConfig::default(),
+        )
+        .map_err(|e| IdentityError::ConfigError(format!("Cannot open VPN users: {}", e)))?;
+
+        let groups = match tokio::fs::read_to_string(&config.groups_path).await {
+            Ok(content) => serde_json::from_str::<Vec<StoredGroup>>(&content)
+                .map_err(|e| {
+                    IdentityError::ConfigError(format!(
+                        "Invalid SCIM group file {}: {}",
+                        config.groups_path.display(),
+                        e
+                    ))
+                })?
+                .into_iter()
+                .map(|group| (group.id.clone(), group))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(IdentityError::ConfigError(format!(
+                    "Cannot read SCIM group file {}: {}",
+                    config.groups_path.display(),
+                    e
+                )))
+            }
+        };
+
+        Ok(Self {
+            users,
+            groups: RwLock::new(groups),
+            config,
+        })
+    }
+
+    /// Whether `token` is the configured bearer token
+    pub fn accepts_token(&self, token: &str) -> bool {
+        let expected = self.config.bearer_token.as_bytes();
+        !expected.is_empty()
+            && expected.len() == token.len()
+            && expected
+                .iter()
+                .zip(token.as_bytes())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    pub async fn create_user(&self, scim: ScimUser) -> ScimResult<ScimUser> {
+        if self.find_user(&scim.user_name).await?.is_some() {
+            return Err(ScimError::uniqueness(format!(
+                "User {} already exists",
+                scim.user_name
+            )));
+        }
+
+        let groups = self.groups.read().await;
+        let mut user = self
+            .users
+            .create_user(vpn_username(&scim.user_name), self.config.default_protocol)
+            .await?;
+        apply_scim_user(&mut user, &scim);
+        self.users.update_user(user.clone()).await?;
+
+        info!(
+            "Provisioned VPN user {} for {} over SCIM",
+            user.name, scim.user_name
+        );
+        Ok(to_scim_user(&user, &groups))
+    }
+
+    pub async fn get_user(&self, id: &str) -> ScimResult<ScimUser> {
+        let user = self.users.get_user(id).await?;
+        Ok(to_scim_user(&user, &*self.groups.read().await))
+    }
+
+    pub async fn list_users(&self, query: &ListQuery) -> ScimResult<ListResponse<ScimUser>> {
+        let filter = query.filter.as_deref().map(parse_eq_filter).transpose()?;
+        let groups = self.groups.read().await;
+
+        let users: Vec<ScimUser> = self
+            .users
+            .list_users(None)
+            .await?
+            .iter()
+            .map(|user| to_scim_user(user, &groups))
+            .filter(|user| match &filter {
+                Some((attribute, value)) => user_matches(user, attribute, value),
+                None => true,
+            })
+            .collect();
+
+        Ok(ListResponse::page(users, query.start_index, query.count))
+    }
+
+    pub async fn replace_user(&self, id: &str, scim: ScimUser) -> ScimResult<ScimUser> {
+        let mut user = self.users.get_user(id).await?;
+        apply_scim_user(&mut user, &scim);
+        self.users.update_user(user.clone()).await?;
+        Ok(to_scim_user(&user, &*self.groups.read().await))
+    }
+
+    pub async fn patch_user(&self, id: &str, patch: PatchRequest) -> ScimResult<ScimUser> {
+        let mut user = self.users.get_user(id).await?;
+        let mut scim = to_scim_user(&user, &*self.groups.read().await);
+
+        for operation in &patch.operations {
+            patch_user_resource(&mut scim, operation)?;
+        }
+
+        let was_active = user.is_active();
+        apply_scim_user(&mut user, &scim);
+        self.users.update_user(user.clone()).await?;
+
+        if was_active != user.is_active() {
+            info!(
+                "{} VPN user {} over SCIM",
+                if user.is_active() {
+                    "Reactivated"
+                } else {
+                    "Suspended"
+                },
+                user.name
+            );
+        }
+        Ok(scim)
+    }
+
+    pub async fn delete_user(&self, id: &str) -> ScimResult<()> {
+        let user = self.users.get_user(id).await?;
+        self.users.delete_user(id).await?;
+
+        let mut groups = self.groups.write().await;
+        let mut changed = false;
+        for group in groups.values_mut() {
+            changed |= group.members.remove(id);
+        }
+        if changed {
+            self.save_groups(&groups).await?;
+        }
+
+        info!("Removed VPN user {} over SCIM", user.name);
+        Ok(())
+    }
+
+    pub async fn create_group(&self, scim: ScimGroup) -> ScimResult<ScimGroup> {
+        let mut groups = self.groups.write().await;
+        if groups
+            .values()
+            .any(|group| group.display_name == scim.display_name)
+        {
+            return Err(ScimError::uniqueness(format!(
+                "Group {} already exists",
+                scim.display_name
+            )));
+        }
+
+        let now = Utc::now();
+        let group = StoredGroup {
+            id: Uuid::new_v4().to_string(),
+            external_id: scim.external_id,
+            display_name: scim.display_name,
+            members: self.known_members(&scim.members).await,
+            created: now,
+            last_modified: now,
+        };
+        let members = group.members.clone();
+        groups.insert(group.id.clone(), group.clone());
+
+        self.save_groups(&groups).await?;
+        self.apply_group_protocols(&groups, &members).await?;
+        Ok(group.to_scim())
+    }
+
+    pub async fn get_group(&self, id: &str) -> ScimResult<ScimGroup> {
+        self.groups
+            .read()
+            .await
+            .get(id)
+            .map(StoredGroup::to_scim)
+            .ok_or_else(|| ScimError::not_found(format!("Group not found: {}", id)))
+    }
+
+    pub async fn list_groups(&self, query: &ListQuery) -> ScimResult<ListResponse<ScimGroup>> {
+        let filter = query.filter.as_deref().map(parse_eq_filter).transpose()?;
+
+        let groups: Vec<ScimGroup> = self
+            .groups
+            .read()
+            .await
+            .values()
+            .filter(|group| match &filter {
+                Some((attribute, value)) => match attribute.to_ascii_lowercase().as_str() {
+                    "displayname" => group.display_name.eq_ignore_ascii_case(value),
+                    "externalid" => group.external_id.as_deref() == Some(value.as_str()),
+                    "id" => group.id == *value,
+                    _ => false,
+                },
+                None => true,
+            })
+            .map(StoredGroup::to_scim)
+            .collect();
+
+        Ok(ListResponse::page(groups, query.start_index, query.count))
+    }
+
+    pub async fn replace_group(&self, id: &str, scim: ScimGroup) -> ScimResult<ScimGroup> {
+        let members = self.known_members(&scim.members).await;
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .get_mut(id)
+            .ok_or_else(|| ScimError::not_found(format!("Group not found: {}", id)))?;
+
+        let affected: BTreeSet<String> = group.members.union(&members).cloned().collect();
+        group.display_name = scim.display_name;
+        group.external_id = scim.external_id;
+        group.members = members;
+        group.last_modified = Utc::now();
+        let result = group.to_scim();
+
+        self.save_groups(&groups).await?;
+        self.apply_group_protocols(&groups, &affected).await?;
+        Ok(result)
+    }
+
+    pub async fn patch_group(&self, id: &str, patch: PatchRequest) -> ScimResult<ScimGroup> {
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .get_mut(id)
+            .ok_or_else(|| ScimError::not_found(format!("Group not found: {}", id)))?;
+
+        let before = group.members.clone();
+        for operation in &patch.operations {
+            patch_group_resource(group, operation)?;
+        }
+        group.members = self.known_members_of(&group.members).await;
+        group.last_modified = Utc::now();
+
+        let affected: BTreeSet<String> = before
+            .symmetric_difference(&group.members)
+            .cloned()
+            .collect();
+        let result = group.to_scim();
+
+        self.save_groups(&groups).await?;
+        self.apply_group_protocols(&groups, &affected).await?;
+        Ok(result)
+    }
+
+    pub async fn delete_group(&self, id: &str) -> ScimResult<()> {
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .remove(id)
+            .ok_or_else(|| ScimError::not_found(format!("Group not found: {}", id)))?;
+
+        self.save_groups(&groups).await?;
+        self.apply_group_protocols(&groups, &group.members).await?;
+        Ok(())
+    }
+
+    /// User provisioned for `user_name`, matched case-insensitively
+    async fn find_user(&self, user_name: &str) -> ScimResult<Option<User>> {
+        Ok(self.users.list_users(None).await?.into_iter().find(|user| {
+            user.tags
+                .get(USER_NAME_TAG)
+                .unwrap_or(&user.name)
+                .eq_ignore_ascii_case(user_name)
+        }))
+    }
+
+    /// IDs of `members` that are VPN users; others are dropped
+    async fn known_members(&self, members: &[ScimMember]) -> BTreeSet<String> {
+        let ids: BTreeSet<String> = members.iter().map(|member| member.value.clone()).collect();
+        self.known_members_of(&ids).await
+    }
+
+    async fn known_members_of(&self, ids: &BTreeSet<String>) -> BTreeSet<String> {
+        let mut known = BTreeSet::new();
+        for id in ids {
+            if self.users.get_user(id).await.is_ok() {
+                known.insert(id.clone());
+            } else {
+                warn!("Ignoring unknown SCIM group member {}", id);
+            }
+        }
+        known
+    }
+
+    /// Give each of `user_ids` the protocol its groups call for
+    async fn apply_group_protocols(
+        &self,
+        groups: &BTreeMap<String, StoredGroup>,
+        user_ids: &BTreeSet<String>,
+    ) -> ScimResult<()> {
+        for id in user_ids {
+            let mut user = match self.users.get_user(id).await {
+                Ok(user) => user,
+                Err(_) => continue,
+            };
+
+            let protocol = self.config.protocol_for(
+                groups
+                    .values()
+                    .filter(|group| group.members.contains(id))
+                    .map(|group| group.display_name.as_str()),
+            );
+            if user.protocol != protocol {
+                info!(
+                    "Moving VPN user {} from {} to {} after a group change",
+                    user.name,
+                    user.protocol.as_str(),
+                    protocol.as_str()
+                );
+                user.protocol = protocol;
+                self.users.update_user(user).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn save_groups(&self, groups: &BTreeMap<String, StoredGroup>) -> ScimResult<()> {
+        let groups: Vec<&StoredGroup> = groups.values().collect();
+        let content = serde_json::to_string_pretty(&groups)
+            .map_err(|e| ScimError::internal(format!("Cannot save groups: {}", e)))?;
+
+        let path = &self.config.groups_path;
+        let temp = path.with_extension("json.tmp");
+        let saved = match tokio::fs::write(&temp, content).await {
+            Ok(()) => tokio::fs::rename(&temp, path).await,
+            Err(e) => Err(e),
+        };
+        saved.map_err(|e| ScimError::internal(format!("Cannot save groups: {}", e)))
+    }
+}
+
+/// VPN user name for a SCIM `userName`: the local part of an email
+/// address, with characters VPN user names cannot contain replaced
+pub fn vpn_username(user_name: &str) -> String {
+    let local = user_name.split('@').next().unwrap_or(user_name);
+    let name: String = local
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(32)
+        .collect();
+
+    if name.len() < 3 {
+        format!("{:-<3}", name)
+    } else {
+        name
+    }
+}
+
+/// Attribute and value of an `attribute eq "value"` filter, the only kind
+/// identity providers send when looking up resources
+pub fn parse_eq_filter(filter: &str) -> ScimResult<(String, String)> {
+    let invalid = || ScimError::invalid_filter(format!("Unsupported filter: {}", filter));
+
+    let mut parts = filter.trim().splitn(3, char::is_whitespace);
+    let attribute = parts.next().filter(|a| !a.is_empty()).ok_or_else(invalid)?;
+    let operator = parts.next().ok_or_else(invalid)?;
+    let value = parts.next().ok_or_else(invalid)?.trim();
+
+    if !operator.eq_ignore_ascii_case("eq") {
+        return Err(invalid());
+    }
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        .replace("\\\"", "\"");
+
+    Ok((attribute.to_string(), value))
+}
+
+fn user_matches(user: &ScimUser, attribute: &str, value: &str) -> bool {
+    match attribute.to_ascii_lowercase().as_str() {
+        "username" => user.user_name.eq_ignore_ascii_case(value),
+        "externalid" => user.external_id.as_deref() == Some(value),
+        "id" => user.id.as_deref() == Some(value),
+        "emails" | "emails.value" => user
+            .emails
+            .iter()
+            .any(|email| email.value.eq_ignore_ascii_case(value)),
+        _ => false,
+    }
+}
+
+fn to_scim_user(user: &User, groups: &BTreeMap<String, StoredGroup>) -> ScimUser {
+    ScimUser {
+        schemas: vec![USER_SCHEMA.to_string()],
+        id: Some(user.id.clone()),
+        external_id: user.tags.get(EXTERNAL_ID_TAG).cloned(),
+        user_name: user
+            .tags
+            .get(USER_NAME_TAG)
+            .cloned()
+            .unwrap_or_else(|| user.name.clone()),
+        display_name: user.tags.get(DISPLAY_NAME_TAG).cloned(),
+        emails: user
+            .email
+            .iter()
+            .map(|email| ScimEmail {
+                value: email.clone(),
+                primary: true,
+                kind: Some("work".to_string()),
+            })
+            .collect(),
+        active: user.is_active(),
+        groups: groups
+            .values()
+            .filter(|group| group.members.contains(&user.id))
+            .map(|group| ScimMember {
+                value: group.id.clone(),
+                display: Some(group.display_name.clone()),
+            })
+            .collect(),
+        meta: Some(ScimMeta {
+            resource_type: "User".to_string(),
+            created: Some(user.created_at),
+            last_modified: None,
+            location: Some(format!("/scim/v2/Users/{}", user.id)),
+        }),
+    }
+}
+
+/// Copy what the provider manages from `scim` onto `user`
+fn apply_scim_user(user: &mut User, scim: &ScimUser) {
+    set_tag(user, USER_NAME_TAG, Some(&scim.user_name));
+    set_tag(user, EXTERNAL_ID_TAG, scim.external_id.as_deref());
+    set_tag(user, DISPLAY_NAME_TAG, scim.display_name.as_deref());
+    user.email = scim
+        .primary_email()
+        .or_else(|| {
+            scim.user_name
+                .contains('@')
+                .then_some(scim.user_name.as_str())
+        })
+        .map(str::to_string);
+
+    match (scim.active, user.is_active()) {
+        (true, false) => user.activate(),
+        (false, true) => user.suspend(),
+        _ => {}
+    }
+}
+
+fn set_tag(user: &mut User, key: &str, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            user.set_tag(key.to_string(), value.to_string());
+        }
+        None => {
+            user.remove_tag(key);
+        }
+    }
+}
+
+/// Apply one patch operation to a user. Attributes the VPN does not keep,
+/// such as `name.givenName` or enterprise extension attributes, are
+/// accepted and ignored.
+fn patch_user_resource(user: &mut ScimUser, operation: &PatchOperation) -> ScimResult<()> {
+    let kind = operation.kind()?;
+
+    match operation.path.as_deref() {
+        Some(path) => {
+            let value = match kind {
+                PatchKind::Remove => None,
+                PatchKind::Add | PatchKind::Replace => Some(
+                    operation
+                        .value
+                        .as_ref()
+                        .ok_or_else(|| ScimError::invalid_value("Patch without a value"))?,
+                ),
+            };
+            set_user_attribute(user, path, value)
+        }
+        None => {
+            let attributes = operation
+                .value
+                .as_ref()
+                .and_then(Value::as_object)
+                .filter(|_| kind != PatchKind::Remove)
+                .ok_or_else(|| ScimError::invalid_value("Patch without a path needs an object"))?;
+            for (path, value) in attributes {
+                set_user_attribute(user, path, Some(value))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn set_user_attribute(user: &mut ScimUser, path: &str, value: Option<&Value>) -> ScimResult<()> {
+    let attribute = path
+        .split(['[', '.'])
+        .next()
+        .unwrap_or(path)
+        .to_ascii_lowercase();
+
+    match attribute.as_str() {
+        "active" => {
+            user.active = match value {
+                Some(value) => parse_bool(value)?,
+                None => false,
+            }
+        }
+        "username" => {
+            user.user_name = value
+                .and_then(Value::as_str)
+                .ok_or_else(|| ScimError::invalid_value("userName must be a string"))?
+                .to_string()
+        }
+        "displayname" => user.display_name = value.and_then(Value::as_str).map(str::to_string),
+        "externalid" => user.external_id = value.and_then(Value::as_str).map(str::to_string),
+        "emails" => user.emails = parse_emails(value)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Booleans as JSON booleans or, from Azure AD, as `"True"` and `"False"`
+fn parse_bool(value: &Value) -> ScimResult<bool> {
+    match value {
+        Value::Bool(value) => Ok(*value),
+        Value::String(value) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(value) if value.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(ScimError::invalid_value(format!(
+            "Expected a boolean, got {}",
+            value
+        ))),
+    }
+}
+
+/// Emails given as a list of email objects, one object or, for paths such
+/// as `emails[type eq "work"].value`, a bare address
+fn parse_emails(value: Option<&Value>) -> ScimResult<Vec<ScimEmail>> {
+    let invalid = |e: serde_json::Error| ScimError::invalid_value(format!("Invalid emails: {}", e));
+
+    match value {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::String(address)) => Ok(vec![ScimEmail {
+            value: address.clone(),
+            primary: true,
+            kind: Some("work".to_string()),
+        }]),
+        Some(value @ Value::Array(_)) => serde_json::from_value(value.clone()).map_err(invalid),
+        Some(value) => serde_json::from_value(value.clone())
+            .map(|email| vec![email])
+            .map_err(invalid),
+    }
+}
+
+/// Apply one patch operation to a group's name or members
+fn patch_group_resource(group: &mut StoredGroup, operation: &PatchOperation) -> ScimResult<()> {
+    let kind = operation.kind()?;
+    let path = operation.path.as_deref().unwrap_or("");
+
+    if path.is_empty() {
+        let attributes = operation
+            .value
+            .as_ref()
+            .and_then(Value::as_object)
+            .ok_or_else(|| ScimError::invalid_value("Patch without a path needs an object"))?;
+        for (path, value) in attributes {
+            let operation = PatchOperation {
+                op: operation.op.clone(),
+                path: Some(path.clone()),
+                value: Some(value.clone()),
+            };
+            patch_group_resource(group, &operation)?;
+        }
+        return Ok(());
+    }
+
+    let attribute = path.split('[').next().unwrap_or(path).to_ascii_lowercase();
+    match attribute.as_str() {
+        "members" => {
+            // members[value eq "id"] names the member to remove
+            if let Some(filter) = path
+                .split_once('[')
+                .and_then(|(_, rest)| rest.strip_suffix(']'))
+            {
+                let (_, id) = parse_eq_filter(filter)?;
+                if kind == PatchKind::Remove {
+                    group.members.remove(&id);
+                } else {
+                    group.members.insert(id);
+                }
+                return Ok(());
+            }
+
+            let ids: Vec<String> = match &operation.value {
+                None | Some(Value::Null) => Vec::new(),
+                Some(value) => serde_json::from_value::<Vec<ScimMember>>(value.clone())
+                    .map_err(|e| ScimError::invalid_value(format!("Invalid members: {}", e)))?
+                    .into_iter()
+                    .map(|member| member.value)
+                    .collect(),
+            };
+            match kind {
+                PatchKind::Add => group.members.extend(ids),
+                PatchKind::Replace => group.members = ids.into_iter().collect(),
+                // Without a value every member is removed
+                PatchKind::Remove if ids.is_empty() => group.members.clear(),
+                PatchKind::Remove => {
+                    for id in &ids {
+                        group.members.remove(id);
+                    }
+                }
+            }
+        }
+        "displayname" if kind != PatchKind::Remove => {
+            group.display_name = operation
+                .value
+                .as_ref()
+                .and_then(Value::as_str)
+                .ok_or_else(|| ScimError::invalid_value("displayName must be a string"))?
+                .to_string();
+        }
+        "externalid" => {
+            group.external_id = operation
+                .value
+                .as_ref()
+                .and_then(Value::as_str)
+                .filter(|_| kind != PatchKind::Remove)
+                .map(str::to_string);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Routes of the SCIM endpoint, to be nested under `/scim/v2`
+pub fn router(service: Arc<ScimService>) -> Router {
+    Router::new()
+        .route("/ServiceProviderConfig", get(service_provider_config))
+        .route("/Users", get(list_users).post(create_user))
+        .route(
+            "/Users/:id",
+            get(get_user)
+                .put(replace_user)
+                .patch(patch_user)
+                .delete(delete_user),
+        )
+        .route("/Groups", get(list_groups).post(create_group))
+        .route(
+            "/Groups/:id",
+            get(get_group)
+                .put(replace_group)
+                .patch(patch_group)
+                .delete(delete_group),
+        )
+        .layer(middleware::from_fn_with_state(
+            service.clone(),
+            require_token,
+        ))
+        .with_state(service)
+}
+
+async fn require_token(
+    State(service): State<Arc<ScimService>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| service.accepts_token(token));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        ScimError::unauthorized().into_response()
+    }
+}
+
+async fn service_provider_config() -> impl IntoResponse {
+    ScimJson(
+        StatusCode::OK,
+        serde_json::json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig"],
+            "patch": { "supported": true },
+            "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+            "filter": { "supported": true, "maxResults": MAX_RESULTS },
+            "changePassword": { "supported": false },
+            "sort": { "supported": false },
+            "etag": { "supported": false },
+            "authenticationSchemes": [{
+                "type": "oauthbearertoken",
+                "name": "OAuth Bearer Token",
+                "description": "Bearer token from the identity service configuration",
+            }],
+        }),
+    )
+}
+
+async fn list_users(
+    State(service): State<Arc<ScimService>>,
+    Query(query): Query<ListQuery>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(StatusCode::OK, service.list_users(&query).await?))
+}
+
+async fn create_user(
+    State(service): State<Arc<ScimService>>,
+    Json(user): Json<ScimUser>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(
+        StatusCode::CREATED,
+        service.create_user(user).await?,
+    ))
+}
+
+async fn get_user(
+    State(service): State<Arc<ScimService>>,
+    Path(id): Path<String>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(StatusCode::OK, service.get_user(&id).await?))
+}
+
+async fn replace_user(
+    State(service): State<Arc<ScimService>>,
+    Path(id): Path<String>,
+    Json(user): Json<ScimUser>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(
+        StatusCode::OK,
+        service.replace_user(&id, user).await?,
+    ))
+}
+
+async fn patch_user(
+    State(service): State<Arc<ScimService>>,
+    Path(id): Path<String>,
+    Json(patch): Json<PatchRequest>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(
+        StatusCode::OK,
+        service.patch_user(&id, patch).await?,
+    ))
+}
+
+async fn delete_user(
+    State(service): State<Arc<ScimService>>,
+    Path(id): Path<String>,
+) -> ScimResult<StatusCode> {
+    service.delete_user(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_groups(
+    State(service): State<Arc<ScimService>>,
+    Query(query): Query<ListQuery>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(StatusCode::OK, service.list_groups(&query).await?))
+}
+
+async fn create_group(
+    State(service): State<Arc<ScimService>>,
+    Json(group): Json<ScimGroup>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(
+        StatusCode::CREATED,
+        service.create_group(group).await?,
+    ))
+}
+
+async fn get_group(
+    State(service): State<Arc<ScimService>>,
+    Path(id): Path<String>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(StatusCode::OK, service.get_group(&id).await?))
+}
+
+async fn replace_group(
+    State(service): State<Arc<ScimService>>,
+    Path(id): Path<String>,
+    Json(group): Json<ScimGroup>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(
+        StatusCode::OK,
+        service.replace_group(&id, group).await?,
+    ))
+}
+
+async fn patch_group(
+    State(service): State<Arc<ScimService>>,
+    Path(id): Path<String>,
+    Json(patch): Json<PatchRequest>,
+) -> ScimResult<impl IntoResponse> {
+    Ok(ScimJson(
+        StatusCode::OK,
+        service.patch_group(&id, patch).await?,
+    ))
+}
+
+async fn delete_group(
+    State(service): State<Arc<ScimService>>,
+    Path(id): Path<String>,
+) -> ScimResult<StatusCode> {
+    service.delete_group(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
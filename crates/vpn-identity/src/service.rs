@@ -5,8 +5,11 @@ use crate::{
     config::IdentityConfig,
     error::Result,
     ldap::LdapProvider,
+    lockout::LoginProtection,
     oauth::{OAuth2Provider, OidcProvider},
+    portal::PortalService,
     rbac::RbacService,
+    scim::ScimService,
     session::SessionManager,
     storage::Storage,
 };
@@ -19,6 +22,8 @@ pub struct IdentityService {
     pub auth_service: Arc<RwLock<AuthService>>,
     pub rbac_service: Arc<RbacService>,
     pub session_manager: Arc<RwLock<SessionManager>>,
+    pub portal: Arc<PortalService>,
+    pub scim: Option<Arc<ScimService>>,
 }
 
 impl IdentityService {
@@ -42,7 +47,8 @@ impl IdentityService {
             config.jwt.refresh_expiration_secs,
             config.jwt.issuer.clone(),
             config.jwt.audience.clone(),
-        );
+        )
+        .with_login_protection(LoginProtection::new(&config.lockout));
         
         let auth_service = Arc::new(RwLock::new(auth_service));
         
@@ -53,12 +59,27 @@ impl IdentityService {
             config.rbac.cache_ttl_secs,
         ));
         
+        // Initialize self-service portal
+        let portal = Arc::new(PortalService::new(
+            storage.clone(),
+            session_manager.clone(),
+            config.portal.clone(),
+        ));
+        
+        // Initialize SCIM provisioning
+        let scim = match &config.scim {
+            Some(scim_config) => Some(Arc::new(ScimService::new(scim_config.clone()).await?)),
+            None => None,
+        };
+        
         let service = Self {
             config,
             storage,
             auth_service,
             rbac_service,
             session_manager,
+            portal,
+            scim,
         };
         
         // Initialize auth providers
@@ -71,4 +71,71 @@ fn test_error_types() {
     
     let err = IdentityError::InsufficientPermissions;
     assert_eq!(err.to_string(), "Insufficient permissions");
-}
\ No newline at end of file
+}
+#[test]
+fn test_portal_subscription_token() {
+    use vpn_identity::portal::{generate_subscription_token, subscription_token, subscription_url};
+    
+    let token = generate_subscription_token();
+    assert_eq!(token.len(), 64);
+    assert!(token.chars().all(|c| c.is_ascii_alphanumeric()));
+    assert_ne!(token, generate_subscription_token());
+    
+    let mut user = User::default();
+    assert_eq!(subscription_token(&user), None);
+    user.attributes["subscription_token"] = serde_json::Value::String(token.clone());
+    assert_eq!(subscription_token(&user), Some(token.as_str()));
+    
+    assert_eq!(
+        subscription_url("https://vpn.example.com/sub/", &token),
+        format!("https://vpn.example.com/sub/{}", token)
+    );
+}
+#[test]
+fn test_scim_group_protocol_mapping() {
+    use vpn_identity::config::{GroupProtocolRule, ScimConfig};
+    use vpn_types::protocol::VpnProtocol;
+    
+    let config = ScimConfig {
+        bearer_token: "secret".to_string(),
+        users_path: "/tmp/users".into(),
+        groups_path: "/tmp/scim-groups.json".into(),
+        default_protocol: VpnProtocol::Vless,
+        group_protocols: vec![
+            GroupProtocolRule { group: "Contractors".to_string(), protocol: VpnProtocol::Outline },
+            GroupProtocolRule { group: "Engineering".to_string(), protocol: VpnProtocol::Wireguard },
+        ],
+    };
+    
+    assert_eq!(config.protocol_for(["engineering"]), VpnProtocol::Wireguard);
+    assert_eq!(config.protocol_for(["Engineering", "contractors"]), VpnProtocol::Outline);
+    assert_eq!(config.protocol_for(["Sales"]), VpnProtocol::Vless);
+}
+#[test]
+fn test_scim_request_parsing() {
+    use vpn_identity::scim::{parse_eq_filter, vpn_username, PatchRequest, ScimUser};
+    
+    let user: ScimUser = serde_json::from_value(serde_json::json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+        "userName": "jane.doe@example.com",
+        "externalId": "00u1",
+        "emails": [{ "value": "jane@example.com", "primary": true }]
+    }))
+    .unwrap();
+    assert!(user.active);
+    assert_eq!(user.primary_email(), Some("jane@example.com"));
+    assert_eq!(vpn_username(&user.user_name), "jane-doe");
+    
+    let patch: PatchRequest = serde_json::from_value(serde_json::json!({
+        "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+        "Operations": [{ "op": "Replace", "path": "active", "value": "False" }]
+    }))
+    .unwrap();
+    assert_eq!(patch.operations[0].path.as_deref(), Some("active"));
+    
+    assert_eq!(
+        parse_eq_filter("userName eq \"jane.doe@example.com\"").unwrap(),
+        ("userName".to_string(), "jane.doe@example.com".to_string())
+    );
+    assert!(parse_eq_filter("userName sw \"jane\"").is_err());
+}
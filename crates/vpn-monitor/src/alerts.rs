@@ -1,11 +1,22 @@
 use crate::error::{MonitorError, Result};
+use crate::forecast::CapacityForecast;
 use crate::health::HealthStatus;
+use crate::kernel::{KernelEvent, KernelEventKind};
 use crate::metrics::PerformanceMetrics;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
 
+/// Rule id of the advisory alerts raised from capacity forecasts
+pub const CAPACITY_FORECAST_RULE: &str = "capacity_forecast";
+
+/// Rule id of the alerts raised from kernel OOM kills
+pub const KERNEL_OOM_RULE: &str = "kernel_oom";
+
+/// Rule id of the alerts raised when the conntrack table overflows
+pub const CONNTRACK_FULL_RULE: &str = "conntrack_table_full";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Alert {
     pub id: String,
@@ -661,6 +672,167 @@ impl AlertManager {
         Ok(())
     }
 
+    /// Raise advisory alerts for quotas forecast to run out within
+    /// `warn_within` and resolve those that are no longer predicted.
+    /// Returns the newly raised alerts.
+    pub fn apply_capacity_forecasts(
+        &mut self,
+        forecasts: &[CapacityForecast],
+        warn_within: chrono::Duration,
+    ) -> Vec<Alert> {
+        let now = Utc::now();
+        let mut new_alerts = Vec::new();
+        let mut predicted = Vec::new();
+
+        for forecast in forecasts {
+            let Some(exhausted_at) = forecast.exhausted_at else {
+                continue;
+            };
+            if !forecast.exhausted_within(warn_within) {
+                continue;
+            }
+
+            let alert_id = format!("capacity:{}", forecast.resource);
+            predicted.push(alert_id.clone());
+
+            let severity = if exhausted_at - now <= chrono::Duration::hours(24) {
+                AlertSeverity::Medium
+            } else {
+                AlertSeverity::Low
+            };
+
+            let mut metadata = HashMap::new();
+            metadata.insert("resource".to_string(), forecast.resource.clone());
+            metadata.insert("exhausted_at".to_string(), exhausted_at.to_rfc3339());
+            metadata.insert(
+                "utilization".to_string(),
+                format!("{:.1}", forecast.utilization()),
+            );
+
+            if let Some(alert) = self.active_alerts.get_mut(&alert_id) {
+                alert.severity = severity;
+                alert.metadata.extend(metadata);
+                continue;
+            }
+
+            let alert = Alert {
+                id: alert_id.clone(),
+                rule_id: CAPACITY_FORECAST_RULE.to_string(),
+                severity,
+                title: format!("Capacity forecast: {}", forecast.resource),
+                description: format!(
+                    "{} is {:.1}% used and expected to be exhausted by {}",
+                    forecast.resource,
+                    forecast.utilization(),
+                    exhausted_at.format("%Y-%m-%d %H:%M UTC")
+                ),
+                timestamp: now,
+                status: AlertStatus::Active,
+                metadata,
+                resolved_at: None,
+                resolved_by: None,
+            };
+            new_alerts.push(alert.clone());
+            self.active_alerts.insert(alert_id, alert);
+        }
+
+        let stale: Vec<String> = self
+            .active_alerts
+            .values()
+            .filter(|alert| {
+                alert.rule_id == CAPACITY_FORECAST_RULE && !predicted.contains(&alert.id)
+            })
+            .map(|alert| alert.id.clone())
+            .collect();
+        for alert_id in stale {
+            if let Some(mut alert) = self.active_alerts.remove(&alert_id) {
+                alert.status = AlertStatus::Resolved;
+                alert.resolved_at = Some(now);
+                alert.resolved_by = Some("system".to_string());
+                self.alert_history.push(alert);
+            }
+        }
+
+        new_alerts
+    }
+
+    /// Raise alerts for kernel events, one per kind and container. Repeats
+    /// of an event whose alert is still active bump its `occurrences`
+    /// instead. Returns the newly raised alerts.
+    pub fn apply_kernel_events(&mut self, events: &[KernelEvent]) -> Vec<Alert> {
+        let mut new_alerts = Vec::new();
+
+        for event in events {
+            let target = event.container.as_deref().unwrap_or("host");
+            let alert_id = format!("kernel:{}:{}", event.kind.as_str(), target);
+
+            if let Some(alert) = self.active_alerts.get_mut(&alert_id) {
+                let occurrences = alert
+                    .metadata
+                    .get("occurrences")
+                    .and_then(|count| count.parse::<u64>().ok())
+                    .unwrap_or(1);
+                alert
+                    .metadata
+                    .insert("occurrences".to_string(), (occurrences + 1).to_string());
+                alert
+                    .metadata
+                    .insert("last_seen".to_string(), event.timestamp.to_rfc3339());
+                continue;
+            }
+
+            let (rule_id, severity, title, description) = match event.kind {
+                KernelEventKind::OomKill => {
+                    let process = event.process.as_deref().unwrap_or("a process");
+                    (
+                        KERNEL_OOM_RULE,
+                        if event.container.is_some() {
+                            AlertSeverity::High
+                        } else {
+                            AlertSeverity::Medium
+                        },
+                        format!("Out of memory: {}", target),
+                        format!("The kernel OOM killer killed {} in {}", process, target),
+                    )
+                }
+                KernelEventKind::ConntrackTableFull => (
+                    CONNTRACK_FULL_RULE,
+                    AlertSeverity::High,
+                    "Conntrack table full".to_string(),
+                    "The conntrack table is full; new connections are being dropped".to_string(),
+                ),
+            };
+
+            let mut metadata = HashMap::new();
+            metadata.insert("occurrences".to_string(), "1".to_string());
+            metadata.insert("last_seen".to_string(), event.timestamp.to_rfc3339());
+            metadata.insert("remediation".to_string(), event.remediation());
+            if let Some(container) = &event.container {
+                metadata.insert("container".to_string(), container.clone());
+            }
+            if let Some(process) = &event.process {
+                metadata.insert("process".to_string(), process.clone());
+            }
+
+            let alert = Alert {
+                id: alert_id.clone(),
+                rule_id: rule_id.to_string(),
+                severity,
+                title,
+                description: format!("{}. {}", description, event.remediation()),
+                timestamp: event.timestamp,
+                status: AlertStatus::Active,
+                metadata,
+                resolved_at: None,
+                resolved_by: None,
+            };
+            new_alerts.push(alert.clone());
+            self.active_alerts.insert(alert_id, alert);
+        }
+
+        new_alerts
+    }
+
     pub fn get_active_alerts(&self) -> Vec<&Alert> {
         self.active_alerts.values().collect()
     }
@@ -0,0 +1,566 @@
+//! Support bundles
+//!
+//! [`SupportBundleCollector`] gathers what is needed to diagnose a broken
+//! installation offline: recent container logs, a metrics snapshot, a
+//! health report, the configuration files and the output of a few system
+//! commands. Everything passes through a [`Redactor`] before it is added,
+//! and `manifest.json` at the top of the tarball lists every file that was
+//! included, how many secrets were stripped from it, and what was left out
+//! and why.
+
+use crate::error::Result;
+use crate::health::HealthMonitor;
+use crate::logs::LogAnalyzer;
+use crate::metrics::{MetricsCollector, MetricsConfig};
+use crate::traffic::{MonitoringConfig, TrafficMonitor};
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use vpn_docker::ComposeProject;
+
+/// Configuration files larger than this are left out
+const MAX_CONFIG_BYTES: u64 = 1024 * 1024;
+
+/// Extensions of the files collected as configuration
+const CONFIG_EXTENSIONS: [&str; 7] = ["json", "yml", "yaml", "toml", "conf", "env", "cfg"];
+
+/// Directories under the install path that are never collected
+const EXCLUDED_DIRS: [(&str, &str); 3] = [
+    ("users", "user database"),
+    ("backups", "backups"),
+    ("certs", "certificates and keys"),
+];
+
+/// Commands whose output goes into `diagnostics/`
+const DIAGNOSTICS: [(&str, &str, &[&str]); 6] = [
+    ("uname", "uname", &["-a"]),
+    ("uptime", "uptime", &[]),
+    ("disk", "df", &["-h"]),
+    ("memory", "free", &["-m"]),
+    ("listening-sockets", "ss", &["-tuln"]),
+    ("docker-version", "docker", &["version"]),
+];
+
+/// Strips secrets from text before it goes into a bundle
+pub struct Redactor {
+    rules: Vec<(&'static str, Regex, &'static str)>,
+}
+
+impl Redactor {
+    /// Redactor for keys, passwords, tokens, user IDs and credentials in
+    /// connection links
+    pub fn new() -> Result<Self> {
+        let rules = vec![
+            (
+                "secret_value",
+                Regex::new(
+                    r#"(?i)("?[\w-]*(?:private_?key|password|passwd|secret|token|api_?key|preshared_?key|psk)[\w-]*"?\s*[:=]\s*)("[^"]*"|'[^']*'|[^\s,}\]]+)"#,
+                )?,
+                r#"${1}"[REDACTED]""#,
+            ),
+            (
+                "link_credentials",
+                Regex::new(r"(?i)\b((?:vless|vmess|trojan|ss|socks5?|https?)://)[^@\s/]+@")?,
+                "${1}[REDACTED]@",
+            ),
+            (
+                "bearer_token",
+                Regex::new(r"(?i)(bearer\s+)[A-Za-z0-9._~+/=-]+")?,
+                "${1}[REDACTED]",
+            ),
+            (
+                "user_id",
+                Regex::new(
+                    r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b",
+                )?,
+                "[USER-ID]",
+            ),
+        ];
+
+        Ok(Self { rules })
+    }
+
+    /// Also replace IPv4 addresses, e.g. of clients in access logs
+    pub fn with_addresses(mut self) -> Result<Self> {
+        self.rules.push((
+            "ip_address",
+            Regex::new(r"\b(?:\d{1,3}\.){3}\d{1,3}\b")?,
+            "[IP]",
+        ));
+        Ok(self)
+    }
+
+    /// `text` with secrets replaced, and how many were replaced by each rule
+    pub fn redact(&self, text: &str) -> (String, BTreeMap<String, usize>) {
+        let mut redacted = text.to_string();
+        let mut counts = BTreeMap::new();
+
+        for (name, regex, replacement) in &self.rules {
+            let matches = regex.find_iter(&redacted).count();
+            if matches == 0 {
+                continue;
+            }
+            redacted = regex.replace_all(&redacted, *replacement).into_owned();
+            counts.insert(name.to_string(), matches);
+        }
+
+        (redacted, counts)
+    }
+}
+
+/// A file in the bundle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleEntry {
+    /// Path inside the bundle
+    pub path: String,
+    /// Where the contents came from
+    pub source: String,
+    pub bytes: u64,
+    /// Secrets replaced, by kind
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redactions: BTreeMap<String, usize>,
+}
+
+/// Something deliberately or necessarily left out of the bundle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExcludedItem {
+    pub source: String,
+    pub reason: String,
+}
+
+/// Contents of `manifest.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub created_at: DateTime<Utc>,
+    pub version: String,
+    pub entries: Vec<BundleEntry>,
+    pub excluded: Vec<ExcludedItem>,
+}
+
+impl BundleManifest {
+    /// Secrets replaced across the bundle, by kind
+    pub fn total_redactions(&self) -> BTreeMap<String, usize> {
+        let mut totals = BTreeMap::new();
+        for entry in &self.entries {
+            for (kind, count) in &entry.redactions {
+                *totals.entry(kind.clone()).or_insert(0) += count;
+            }
+        }
+        totals
+    }
+}
+
+/// Redacted files waiting to be written as a tarball
+pub struct SupportBundle {
+    redactor: Redactor,
+    files: Vec<(String, Vec<u8>)>,
+    manifest: BundleManifest,
+}
+
+impl SupportBundle {
+    pub fn new(redactor: Redactor) -> Self {
+        Self {
+            redactor,
+            files: Vec::new(),
+            manifest: BundleManifest {
+                created_at: Utc::now(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                entries: Vec::new(),
+                excluded: Vec::new(),
+            },
+        }
+    }
+
+    /// Add `text` at `path`, redacted
+    pub fn add_text(&mut self, path: &str, source: &str, text: &str) {
+        let (text, redactions) = self.redactor.redact(text);
+        self.manifest.entries.push(BundleEntry {
+            path: path.to_string(),
+            source: source.to_string(),
+            bytes: text.len() as u64,
+            redactions,
+        });
+        self.files.push((path.to_string(), text.into_bytes()));
+    }
+
+    /// Add `value` as pretty-printed JSON at `path`, redacted
+    pub fn add_json<T: Serialize>(&mut self, path: &str, source: &str, value: &T) -> Result<()> {
+        let json = serde_json::to_string_pretty(value)?;
+        self.add_text(path, source, &json);
+        Ok(())
+    }
+
+    /// Add the text file at `file`, redacted; binary, oversized and
+    /// unreadable files are recorded as excluded instead
+    pub fn add_file(&mut self, path: &str, file: &Path) {
+        let source = file.display().to_string();
+        match std::fs::metadata(file) {
+            Ok(metadata) if metadata.len() > MAX_CONFIG_BYTES => {
+                self.exclude(&source, format!("larger than {} bytes", MAX_CONFIG_BYTES));
+                return;
+            }
+            Err(e) => {
+                self.exclude(&source, e.to_string());
+                return;
+            }
+            Ok(_) => {}
+        }
+
+        match std::fs::read(file).map(String::from_utf8) {
+            Ok(Ok(text)) => self.add_text(path, &source, &text),
+            Ok(Err(_)) => self.exclude(&source, "binary file"),
+            Err(e) => self.exclude(&source, e.to_string()),
+        }
+    }
+
+    /// Record that `source` was left out
+    pub fn exclude(&mut self, source: &str, reason: impl Into<String>) {
+        self.manifest.excluded.push(ExcludedItem {
+            source: source.to_string(),
+            reason: reason.into(),
+        });
+    }
+
+    pub fn manifest(&self) -> &BundleManifest {
+        &self.manifest
+    }
+
+    /// Write the bundle as a gzipped tarball to `output`, with every file
+    /// under a `vpn-support-<timestamp>/` directory
+    pub fn write(self, output: &Path) -> Result<BundleManifest> {
+        let root = format!(
+            "vpn-support-{}",
+            self.manifest.created_at.format("%Y%m%d-%H%M%S")
+        );
+        let mtime = self.manifest.created_at.timestamp().max(0) as u64;
+        let manifest = serde_json::to_vec_pretty(&self.manifest)?;
+
+        let encoder = GzEncoder::new(File::create(output)?, Compression::default());
+        let mut tar = tar::Builder::new(encoder);
+        let files = std::iter::once(("manifest.json", manifest.as_slice())).chain(
+            self.files
+                .iter()
+                .map(|(path, contents)| (path.as_str(), contents.as_slice())),
+        );
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(mtime);
+            header.set_cksum();
+            tar.append_data(&mut header, format!("{}/{}", root, path), contents)?;
+        }
+        tar.into_inner()?.finish()?;
+
+        Ok(self.manifest)
+    }
+}
+
+/// What goes into a support bundle
+#[derive(Debug, Clone)]
+pub struct SupportBundleOptions {
+    /// Log lines per container
+    pub log_lines: usize,
+    /// Replace IPv4 addresses too
+    pub redact_addresses: bool,
+}
+
+impl Default for SupportBundleOptions {
+    fn default() -> Self {
+        Self {
+            log_lines: 500,
+            redact_addresses: false,
+        }
+    }
+}
+
+/// Collects a support bundle for the installation at `install_path`
+pub struct SupportBundleCollector {
+    install_path: PathBuf,
+    options: SupportBundleOptions,
+}
+
+impl SupportBundleCollector {
+    pub fn new(install_path: impl Into<PathBuf>, options: SupportBundleOptions) -> Self {
+        Self {
+            install_path: install_path.into(),
+            options,
+        }
+    }
+
+    /// Collect everything available. A part that cannot be collected, e.g.
+    /// logs while Docker is down, is recorded in the manifest rather than
+    /// failing the bundle.
+    pub async fn collect(&self) -> Result<SupportBundle> {
+        let mut redactor = Redactor::new()?;
+        if self.options.redact_addresses {
+            redactor = redactor.with_addresses()?;
+        }
+        let mut bundle = SupportBundle::new(redactor);
+
+        self.collect_configs(&mut bundle);
+        self.collect_health(&mut bundle).await;
+        self.collect_metrics(&mut bundle).await;
+        self.collect_logs(&mut bundle).await;
+        collect_diagnostics(&mut bundle).await;
+
+        Ok(bundle)
+    }
+
+    /// Configuration files in the install path and one level below
+    fn collect_configs(&self, bundle: &mut SupportBundle) {
+        let mut pending = vec![(self.install_path.clone(), 0)];
+        while let Some((dir, depth)) = pending.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    bundle.exclude(&dir.display().to_string(), e.to_string());
+                    continue;
+                }
+            };
+
+            let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+            paths.sort();
+            for path in paths {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+
+                if path.is_dir() {
+                    if let Some((_, reason)) = EXCLUDED_DIRS.iter().find(|(dir, _)| *dir == name) {
+                        bundle.exclude(&path.display().to_string(), *reason);
+                    } else if depth == 0 {
+                        pending.push((path, depth + 1));
+                    }
+                    continue;
+                }
+
+                if is_key_material(&name) {
+                    bundle.exclude(&path.display().to_string(), "key material");
+                } else if is_config_file(&name) {
+                    let relative = path.strip_prefix(&self.install_path).unwrap_or(&path);
+                    bundle.add_file(&format!("config/{}", relative.display()), &path);
+                }
+            }
+        }
+    }
+
+    async fn collect_health(&self, bundle: &mut SupportBundle) {
+        let health = match HealthMonitor::new() {
+            Ok(monitor) => monitor.check_overall_health().await,
+            Err(e) => Err(e),
+        };
+        match health {
+            Ok(health) => {
+                if let Err(e) = bundle.add_json("health.json", "health check", &health) {
+                    bundle.exclude("health check", e.to_string());
+                }
+            }
+            Err(e) => bundle.exclude("health check", e.to_string()),
+        }
+    }
+
+    async fn collect_metrics(&self, bundle: &mut SupportBundle) {
+        let collector = HealthMonitor::new().and_then(|health| {
+            Ok(MetricsCollector::new(
+                health,
+                TrafficMonitor::new(MonitoringConfig::default())?,
+                MetricsConfig::default(),
+            ))
+        });
+        let metrics = match collector {
+            Ok(mut collector) => collector
+                .collect_metrics()
+                .await
+                .map(|metrics| (collector.export_prometheus_metrics(&metrics), metrics)),
+            Err(e) => Err(e),
+        };
+
+        match metrics {
+            Ok((prometheus, metrics)) => {
+                bundle.add_text("metrics/snapshot.prom", "metrics collector", &prometheus);
+                if let Err(e) =
+                    bundle.add_json("metrics/snapshot.json", "metrics collector", &metrics)
+                {
+                    bundle.exclude("metrics collector", e.to_string());
+                }
+            }
+            Err(e) => bundle.exclude("metrics snapshot", e.to_string()),
+        }
+    }
+
+    /// The last lines of every container of the compose project
+    async fn collect_logs(&self, bundle: &mut SupportBundle) {
+        let project = ComposeProject::new(self.install_path.join("docker-compose.yml"));
+        let containers = match project.ps().await {
+            Ok(containers) => containers,
+            Err(e) => {
+                bundle.exclude("container logs", e.to_string());
+                return;
+            }
+        };
+        if let Err(e) = bundle.add_json("diagnostics/containers.json", "docker", &containers) {
+            bundle.exclude("container list", e.to_string());
+        }
+
+        let analyzer = match LogAnalyzer::new() {
+            Ok(analyzer) => analyzer,
+            Err(e) => {
+                bundle.exclude("container logs", e.to_string());
+                return;
+            }
+        };
+        for container in containers {
+            let source = format!("container {}", container.name);
+            match analyzer
+                .tail_logs(&container.name, self.options.log_lines)
+                .await
+            {
+                Ok(lines) => bundle.add_text(
+                    &format!("logs/{}.log", container.name),
+                    &source,
+                    &lines.join("\n"),
+                ),
+                Err(e) => bundle.exclude(&source, e.to_string()),
+            }
+        }
+    }
+}
+
+async fn collect_diagnostics(bundle: &mut SupportBundle) {
+    for (name, program, args) in DIAGNOSTICS {
+        let source = std::iter::once(program)
+            .chain(args.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        match Command::new(program).args(args).output().await {
+            Ok(output) if output.status.success() => bundle.add_text(
+                &format!("diagnostics/{}.txt", name),
+                &source,
+                &String::from_utf8_lossy(&output.stdout),
+            ),
+            Ok(output) => bundle.exclude(
+                &source,
+                format!(
+                    "exited with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ),
+            Err(e) => bundle.exclude(&source, e.to_string()),
+        }
+    }
+}
+
+fn is_config_file(name: &str) -> bool {
+    name == ".env"
+        || Path::new(name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| CONFIG_EXTENSIONS.contains(&extension))
+}
+
+fn is_key_material(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    lower.ends_with(".key")
+        || lower.ends_with(".pem")
+        || lower.ends_with(".token")
+        || lower.contains("private")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_redact_secrets() {
+        let redactor = Redactor::new().unwrap();
+        let config =
+            r#"{"id": "0f8c3b52-8d3e-4c3a-9e57-0a3f1e6b2d44", "privateKey": "sK3x9", "port": 443}"#;
+        let (redacted, counts) = redactor.redact(config);
+        assert_eq!(
+            redacted,
+            r#"{"id": "[USER-ID]", "privateKey": "[REDACTED]", "port": 443}"#
+        );
+        assert_eq!(counts["secret_value"], 1);
+        assert_eq!(counts["user_id"], 1);
+
+        let (redacted, _) = redactor.redact("PROXY_ADMIN_TOKEN=abc123\nLOG_LEVEL=info");
+        assert_eq!(redacted, "PROXY_ADMIN_TOKEN=\"[REDACTED]\"\nLOG_LEVEL=info");
+
+        let (redacted, _) =
+            redactor.redact("link: ss://YWVzLTI1Ni1nY206cGFzcw@203.0.113.7:8388#alice");
+        assert_eq!(redacted, "link: ss://[REDACTED]@203.0.113.7:8388#alice");
+
+        let redactor = Redactor::new().unwrap().with_addresses().unwrap();
+        let (redacted, counts) = redactor.redact("accepted tcp:198.51.100.4:51234");
+        assert_eq!(redacted, "accepted tcp:[IP]:51234");
+        assert_eq!(counts["ip_address"], 1);
+    }
+
+    #[test]
+    fn test_configs_skip_users_and_keys() {
+        let install = TempDir::new().unwrap();
+        let root = install.path();
+        std::fs::write(root.join("docker-compose.yml"), "services: {}\n").unwrap();
+        std::fs::write(root.join("server.key"), "key").unwrap();
+        std::fs::create_dir(root.join("users")).unwrap();
+        std::fs::write(root.join("users/alice.json"), "{}").unwrap();
+        std::fs::create_dir(root.join("config")).unwrap();
+        std::fs::write(root.join("config/config.json"), r#"{"password": "x"}"#).unwrap();
+
+        let collector = SupportBundleCollector::new(root, SupportBundleOptions::default());
+        let mut bundle = SupportBundle::new(Redactor::new().unwrap());
+        collector.collect_configs(&mut bundle);
+
+        let manifest = bundle.manifest();
+        let paths: Vec<&str> = manifest.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["config/docker-compose.yml", "config/config/config.json"]
+        );
+        assert_eq!(manifest.total_redactions()["secret_value"], 1);
+        let excluded: Vec<&str> = manifest
+            .excluded
+            .iter()
+            .map(|e| e.reason.as_str())
+            .collect();
+        assert_eq!(excluded, ["key material", "user database"]);
+    }
+
+    #[test]
+    fn test_write_bundle() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("bundle.tar.gz");
+        let mut bundle = SupportBundle::new(Redactor::new().unwrap());
+        bundle.add_text("logs/vpn-xray.log", "container vpn-xray", "token=abc");
+        bundle.exclude("container logs", "Docker is not running");
+        let manifest = bundle.write(&output).unwrap();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(&output).unwrap()));
+        let mut contents = BTreeMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut text = String::new();
+            entry.read_to_string(&mut text).unwrap();
+            let path = path.split_once('/').unwrap().1.to_string();
+            contents.insert(path, text);
+        }
+
+        assert_eq!(contents["logs/vpn-xray.log"], "token=\"[REDACTED]\"");
+        let written: BundleManifest = serde_json::from_str(&contents["manifest.json"]).unwrap();
+        assert_eq!(written.entries.len(), manifest.entries.len());
+        assert_eq!(written.excluded[0].reason, "Docker is not running");
+    }
+}
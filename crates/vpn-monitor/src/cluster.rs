@@ -0,0 +1,98 @@
+//! Traffic reporting to the cluster layer
+//!
+//! [`MonitorTrafficSource`] feeds this node's per-user traffic into
+//! vpn-cluster's aggregation, which sums it with the other nodes' so a
+//! user's totals do not depend on which node they connected to.
+
+use crate::traffic::{TrafficMonitor, TrafficStats};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use vpn_cluster::{ClusterError, TrafficSource, UserTraffic};
+
+/// Reports the traffic [`TrafficMonitor`] collects for an installation
+pub struct MonitorTrafficSource {
+    monitor: TrafficMonitor,
+    install_path: PathBuf,
+}
+
+impl MonitorTrafficSource {
+    pub fn new(monitor: TrafficMonitor, install_path: PathBuf) -> Self {
+        Self {
+            monitor,
+            install_path,
+        }
+    }
+}
+
+#[async_trait]
+impl TrafficSource for MonitorTrafficSource {
+    async fn user_traffic(&self) -> vpn_cluster::Result<HashMap<String, UserTraffic>> {
+        let summary = self
+            .monitor
+            .collect_traffic_stats(&self.install_path)
+            .await
+            .map_err(|e| ClusterError::Generic(e.into()))?;
+
+        Ok(to_user_traffic(&summary.user_stats))
+    }
+}
+
+/// Per-user counters in the cluster's format
+pub fn to_user_traffic(stats: &[TrafficStats]) -> HashMap<String, UserTraffic> {
+    let mut traffic: HashMap<String, UserTraffic> = HashMap::new();
+    for stat in stats {
+        traffic
+            .entry(stat.user_id.clone())
+            .or_default()
+            .add(&UserTraffic {
+                bytes_sent: stat.bytes_sent,
+                bytes_received: stat.bytes_received,
+                connections: stat.connections,
+                last_activity: u64::try_from(stat.last_activity.timestamp()).ok(),
+            });
+    }
+    traffic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn stats(user_id: &str, bytes_sent: u64, minute: u32) -> TrafficStats {
+        TrafficStats {
+            user_id: user_id.to_string(),
+            bytes_sent,
+            bytes_received: bytes_sent * 10,
+            packets_sent: 0,
+            packets_received: 0,
+            connections: 1,
+            last_activity: Utc.with_ymd_and_hms(2024, 1, 1, 12, minute, 0).unwrap(),
+            session_duration: Duration::zero(),
+        }
+    }
+
+    #[test]
+    fn test_to_user_traffic_merges_entries_per_user() {
+        let traffic = to_user_traffic(&[
+            stats("alice", 100, 0),
+            stats("bob", 5, 1),
+            stats("alice", 50, 2),
+        ]);
+
+        assert_eq!(traffic.len(), 2);
+        assert_eq!(traffic["alice"].bytes_sent, 150);
+        assert_eq!(traffic["alice"].bytes_received, 1500);
+        assert_eq!(traffic["alice"].connections, 2);
+        assert_eq!(
+            traffic["alice"].last_activity,
+            Some(
+                Utc.with_ymd_and_hms(2024, 1, 1, 12, 2, 0)
+                    .unwrap()
+                    .timestamp() as u64
+            )
+        );
+        assert_eq!(traffic["bob"].total_bytes(), 55);
+    }
+}
@@ -0,0 +1,571 @@
+//! Traffic baselines and capacity forecasting
+//!
+//! [`BaselineLearner`] aggregates traffic into hourly buckets per protocol and
+//! learns the typical daily and weekly curves. [`CapacityPlanner`] fits an
+//! additive Holt-Winters model to that history and predicts when a bandwidth
+//! or user quota will run out.
+
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+const HOURS_PER_DAY: usize = 24;
+const HOURS_PER_WEEK: usize = 7 * HOURS_PER_DAY;
+const SECONDS_PER_HOUR: i64 = 3600;
+
+/// Typical traffic of a protocol by hour of day and hour of week
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficBaseline {
+    pub protocol: String,
+    /// Average bytes per hour for each hour of the day (UTC)
+    pub daily: Vec<f64>,
+    /// Average bytes per hour for each hour of the week, Monday 00:00 first
+    pub weekly: Vec<f64>,
+    /// Number of hourly buckets the baseline was learned from
+    pub hours_observed: usize,
+}
+
+impl TrafficBaseline {
+    /// Expected bytes in the hour starting at `at`
+    pub fn expected_at(&self, at: DateTime<Utc>) -> f64 {
+        self.weekly[hour_of_week(at)]
+    }
+
+    /// Hour of the day with the most traffic
+    pub fn peak_hour(&self) -> usize {
+        self.daily
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(hour, _)| hour)
+            .unwrap_or(0)
+    }
+
+    pub fn average_hourly(&self) -> f64 {
+        self.daily.iter().sum::<f64>() / HOURS_PER_DAY as f64
+    }
+}
+
+/// Learns traffic baselines from observed byte counts
+#[derive(Debug, Default)]
+pub struct BaselineLearner {
+    /// Bytes per protocol, keyed by hours since the UNIX epoch
+    series: HashMap<String, BTreeMap<i64, u64>>,
+}
+
+impl BaselineLearner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `bytes` transferred over `protocol` at `timestamp`
+    pub fn record(&mut self, protocol: &str, timestamp: DateTime<Utc>, bytes: u64) {
+        let hour = timestamp.timestamp().div_euclid(SECONDS_PER_HOUR);
+        *self
+            .series
+            .entry(protocol.to_string())
+            .or_default()
+            .entry(hour)
+            .or_default() += bytes;
+    }
+
+    pub fn protocols(&self) -> Vec<String> {
+        let mut protocols: Vec<String> = self.series.keys().cloned().collect();
+        protocols.sort();
+        protocols
+    }
+
+    pub fn baseline(&self, protocol: &str) -> Option<TrafficBaseline> {
+        let (start, values) = self.hourly_series(Some(protocol))?;
+
+        let mut daily = vec![(0.0, 0usize); HOURS_PER_DAY];
+        let mut weekly = vec![(0.0, 0usize); HOURS_PER_WEEK];
+        for (offset, value) in values.iter().enumerate() {
+            let at = start + Duration::hours(offset as i64);
+            let day_slot = &mut daily[at.hour() as usize];
+            day_slot.0 += value;
+            day_slot.1 += 1;
+            let week_slot = &mut weekly[hour_of_week(at)];
+            week_slot.0 += value;
+            week_slot.1 += 1;
+        }
+
+        let average =
+            |(sum, count): (f64, usize)| if count == 0 { 0.0 } else { sum / count as f64 };
+        let daily: Vec<f64> = daily.into_iter().map(average).collect();
+        // Hours of the week not seen yet fall back to the daily curve
+        let weekly = weekly
+            .into_iter()
+            .enumerate()
+            .map(|(slot, bucket)| {
+                if bucket.1 == 0 {
+                    daily[slot % HOURS_PER_DAY]
+                } else {
+                    average(bucket)
+                }
+            })
+            .collect();
+
+        Some(TrafficBaseline {
+            protocol: protocol.to_string(),
+            daily,
+            weekly,
+            hours_observed: values.len(),
+        })
+    }
+
+    /// Contiguous hourly byte counts for one protocol, or all protocols
+    /// combined, with the start of the first hour. Hours without traffic
+    /// are zero.
+    pub fn hourly_series(&self, protocol: Option<&str>) -> Option<(DateTime<Utc>, Vec<f64>)> {
+        let mut combined: BTreeMap<i64, u64> = BTreeMap::new();
+        for (name, buckets) in &self.series {
+            if protocol.is_none_or(|p| p == name) {
+                for (hour, bytes) in buckets {
+                    *combined.entry(*hour).or_default() += bytes;
+                }
+            }
+        }
+
+        let first = *combined.keys().next()?;
+        let last = *combined.keys().next_back()?;
+        let values = (first..=last)
+            .map(|hour| combined.get(&hour).copied().unwrap_or(0) as f64)
+            .collect();
+
+        let start = Utc.timestamp_opt(first * SECONDS_PER_HOUR, 0).single()?;
+        Some((start, values))
+    }
+}
+
+/// Additive Holt-Winters (triple exponential smoothing)
+#[derive(Debug, Clone, Copy)]
+pub struct HoltWinters {
+    pub alpha: f64,
+    pub beta: f64,
+    pub gamma: f64,
+    pub season_length: usize,
+}
+
+impl HoltWinters {
+    pub fn new(season_length: usize) -> Self {
+        Self {
+            alpha: 0.3,
+            beta: 0.05,
+            gamma: 0.2,
+            season_length,
+        }
+    }
+
+    /// Fit the model; needs at least two full seasons of data
+    pub fn fit(&self, series: &[f64]) -> Option<HoltWintersModel> {
+        let m = self.season_length;
+        if m == 0 || series.len() < 2 * m {
+            return None;
+        }
+
+        let first_mean = series[..m].iter().sum::<f64>() / m as f64;
+        let second_mean = series[m..2 * m].iter().sum::<f64>() / m as f64;
+
+        let mut level = first_mean;
+        let mut trend = (second_mean - first_mean) / m as f64;
+        let mut seasonal: Vec<f64> = series[..m].iter().map(|y| y - first_mean).collect();
+
+        for (t, &y) in series.iter().enumerate() {
+            let season = seasonal[t % m];
+            let previous_level = level;
+            level = self.alpha * (y - season) + (1.0 - self.alpha) * (level + trend);
+            trend = self.beta * (level - previous_level) + (1.0 - self.beta) * trend;
+            seasonal[t % m] = self.gamma * (y - level) + (1.0 - self.gamma) * season;
+        }
+
+        Some(HoltWintersModel {
+            level,
+            trend,
+            seasonal,
+            next_index: series.len(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HoltWintersModel {
+    level: f64,
+    trend: f64,
+    seasonal: Vec<f64>,
+    /// Position of the first forecast step in the season
+    next_index: usize,
+}
+
+impl HoltWintersModel {
+    /// Forecast the next `steps` values; traffic never goes negative
+    pub fn forecast(&self, steps: usize) -> Vec<f64> {
+        let m = self.seasonal.len();
+        (1..=steps)
+            .map(|h| {
+                let season = self.seasonal[(self.next_index + h - 1) % m];
+                (self.level + h as f64 * self.trend + season).max(0.0)
+            })
+            .collect()
+    }
+}
+
+/// Predicted exhaustion of a quota
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapacityForecast {
+    /// What runs out, e.g. `bandwidth` or `bandwidth:vless` or `users`
+    pub resource: String,
+    pub current: f64,
+    pub limit: f64,
+    /// When the limit is expected to be reached; `None` if not within the horizon
+    pub exhausted_at: Option<DateTime<Utc>>,
+    pub horizon_end: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+}
+
+impl CapacityForecast {
+    pub fn utilization(&self) -> f64 {
+        if self.limit <= 0.0 {
+            return 100.0;
+        }
+        self.current / self.limit * 100.0
+    }
+
+    /// Whether the limit is expected to be reached within `window` from now
+    pub fn exhausted_within(&self, window: Duration) -> bool {
+        self.exhausted_at
+            .is_some_and(|at| at <= self.generated_at + window)
+    }
+}
+
+/// Forecasts quota exhaustion from learned traffic
+#[derive(Debug, Clone)]
+pub struct CapacityPlanner {
+    /// How far ahead to look
+    pub horizon: Duration,
+}
+
+impl Default for CapacityPlanner {
+    fn default() -> Self {
+        Self {
+            horizon: Duration::days(30),
+        }
+    }
+}
+
+impl CapacityPlanner {
+    pub fn new(horizon: Duration) -> Self {
+        Self { horizon }
+    }
+
+    /// Forecast when `used` bytes grow past `quota`, for one protocol or
+    /// all of them. Uses a weekly season once two weeks of history exist and
+    /// a daily season before that; `None` with less than two days of data.
+    pub fn bandwidth_forecast(
+        &self,
+        learner: &BaselineLearner,
+        protocol: Option<&str>,
+        used: u64,
+        quota: u64,
+    ) -> Option<CapacityForecast> {
+        let (start, series) = learner.hourly_series(protocol)?;
+        let season = if series.len() >= 2 * HOURS_PER_WEEK {
+            HOURS_PER_WEEK
+        } else {
+            HOURS_PER_DAY
+        };
+        let model = HoltWinters::new(season).fit(&series)?;
+
+        let next_hour = start + Duration::hours(series.len() as i64);
+        let steps = self.horizon.num_hours().max(0) as usize;
+        let resource = match protocol {
+            Some(protocol) => format!("bandwidth:{}", protocol),
+            None => "bandwidth".to_string(),
+        };
+
+        Some(self.cumulative_forecast(
+            resource,
+            used as f64,
+            quota as f64,
+            next_hour,
+            Duration::hours(1),
+            &model.forecast(steps),
+        ))
+    }
+
+    /// Forecast when the number of users reaches `max_users` from daily
+    /// user counts starting at `start`, with a weekly season. Needs two
+    /// weeks of counts.
+    pub fn user_forecast(
+        &self,
+        start: DateTime<Utc>,
+        daily_counts: &[f64],
+        max_users: u64,
+    ) -> Option<CapacityForecast> {
+        let model = HoltWinters::new(7).fit(daily_counts)?;
+        let current = *daily_counts.last()?;
+        let steps = self.horizon.num_days().max(0) as usize;
+        let next_day = start + Duration::days(daily_counts.len() as i64);
+        let limit = max_users as f64;
+        let generated_at = Utc::now();
+
+        let exhausted_at = if current >= limit {
+            Some(generated_at)
+        } else {
+            model
+                .forecast(steps)
+                .iter()
+                .position(|count| *count >= limit)
+                .map(|day| next_day + Duration::days(day as i64))
+        };
+
+        Some(CapacityForecast {
+            resource: "users".to_string(),
+            current,
+            limit,
+            exhausted_at,
+            horizon_end: generated_at + self.horizon,
+            generated_at,
+        })
+    }
+
+    fn cumulative_forecast(
+        &self,
+        resource: String,
+        used: f64,
+        limit: f64,
+        first_step: DateTime<Utc>,
+        step: Duration,
+        forecast: &[f64],
+    ) -> CapacityForecast {
+        let generated_at = Utc::now();
+        let mut total = used;
+        let mut exhausted_at = (total >= limit).then_some(generated_at);
+
+        if exhausted_at.is_none() {
+            for (i, value) in forecast.iter().enumerate() {
+                total += value;
+                if total >= limit {
+                    // The step during which the quota runs out ends at i + 1
+                    exhausted_at = Some(first_step + step * (i as i32 + 1));
+                    break;
+                }
+            }
+        }
+
+        CapacityForecast {
+            resource,
+            current: used,
+            limit,
+            exhausted_at,
+            horizon_end: generated_at + self.horizon,
+            generated_at,
+        }
+    }
+}
+
+/// Baselines and forecasts for a capacity report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapacityReport {
+    pub generated_at: DateTime<Utc>,
+    pub baselines: Vec<TrafficBaseline>,
+    pub forecasts: Vec<CapacityForecast>,
+}
+
+impl CapacityReport {
+    pub fn new(baselines: Vec<TrafficBaseline>, forecasts: Vec<CapacityForecast>) -> Self {
+        Self {
+            generated_at: Utc::now(),
+            baselines,
+            forecasts,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut report = String::new();
+
+        report.push_str("Capacity Report\n");
+        report.push_str(&format!(
+            "Generated: {}\n",
+            self.generated_at.format("%Y-%m-%d %H:%M:%S")
+        ));
+
+        report.push_str("\nTraffic baselines:\n");
+        for baseline in &self.baselines {
+            report.push_str(&format!(
+                "  {}: {:.0} bytes/hour on average, peak at {:02}:00 UTC ({} hours observed)\n",
+                baseline.protocol,
+                baseline.average_hourly(),
+                baseline.peak_hour(),
+                baseline.hours_observed
+            ));
+        }
+
+        report.push_str("\nForecasts:\n");
+        for forecast in &self.forecasts {
+            let outlook = match forecast.exhausted_at {
+                Some(at) => format!("exhausted by {}", at.format("%Y-%m-%d %H:%M")),
+                None => format!(
+                    "not exhausted before {}",
+                    forecast.horizon_end.format("%Y-%m-%d")
+                ),
+            };
+            report.push_str(&format!(
+                "  {}: {:.1}% used, {}\n",
+                forecast.resource,
+                forecast.utilization(),
+                outlook
+            ));
+        }
+
+        report
+    }
+}
+
+/// Hour of the week, Monday 00:00 UTC being 0
+fn hour_of_week(at: DateTime<Utc>) -> usize {
+    at.weekday().num_days_from_monday() as usize * HOURS_PER_DAY + at.hour() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hourly traffic peaking in the evening, starting on a Monday
+    fn learner_with_daily_pattern(days: i64, growth_per_day: f64) -> BaselineLearner {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut learner = BaselineLearner::new();
+
+        for hour in 0..days * 24 {
+            let at = start + Duration::hours(hour);
+            let base = if (18..23).contains(&at.hour()) {
+                5000.0
+            } else {
+                1000.0
+            };
+            let bytes = base + growth_per_day * (hour / 24) as f64;
+            learner.record("vless", at, bytes as u64);
+        }
+
+        learner
+    }
+
+    #[test]
+    fn test_baseline_learns_daily_curve() {
+        let learner = learner_with_daily_pattern(14, 0.0);
+        let baseline = learner.baseline("vless").unwrap();
+
+        assert_eq!(baseline.hours_observed, 14 * 24);
+        assert_eq!(baseline.daily[20], 5000.0);
+        assert_eq!(baseline.daily[3], 1000.0);
+        assert!((18..23).contains(&baseline.peak_hour()));
+        assert_eq!(
+            baseline.expected_at(Utc.with_ymd_and_hms(2024, 2, 7, 20, 30, 0).unwrap()),
+            5000.0
+        );
+        assert!(learner.baseline("wireguard").is_none());
+    }
+
+    #[test]
+    fn test_holt_winters_follows_season() {
+        let series: Vec<f64> = (0..96)
+            .map(|t| if t % 24 >= 18 { 50.0 } else { 10.0 })
+            .collect();
+        let model = HoltWinters::new(24).fit(&series).unwrap();
+        let forecast = model.forecast(24);
+
+        assert!((forecast[20] - 50.0).abs() < 5.0);
+        assert!((forecast[5] - 10.0).abs() < 5.0);
+        assert!(HoltWinters::new(24).fit(&series[..30]).is_none());
+    }
+
+    #[test]
+    fn test_bandwidth_exhaustion_forecast() {
+        let learner = learner_with_daily_pattern(21, 100.0);
+        let planner = CapacityPlanner::new(Duration::days(30));
+
+        // Traffic ends at about 94 KB/day and grows 2.4 KB/day, so 300 KB of
+        // headroom lasts a little over three days
+        let forecast = planner
+            .bandwidth_forecast(&learner, Some("vless"), 1_000_000, 1_300_000)
+            .unwrap();
+        assert_eq!(forecast.resource, "bandwidth:vless");
+        let exhausted_at = forecast.exhausted_at.unwrap();
+        let end_of_data = Utc.with_ymd_and_hms(2024, 1, 22, 0, 0, 0).unwrap();
+        assert!(exhausted_at > end_of_data + Duration::days(2));
+        assert!(exhausted_at < end_of_data + Duration::days(5));
+
+        let forecast = planner
+            .bandwidth_forecast(&learner, None, 0, u64::MAX)
+            .unwrap();
+        assert!(forecast.exhausted_at.is_none());
+    }
+
+    #[test]
+    fn test_user_forecast() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let counts: Vec<f64> = (0..28).map(|day| 50.0 + day as f64).collect();
+        let planner = CapacityPlanner::new(Duration::days(30));
+
+        let forecast = planner.user_forecast(start, &counts, 90).unwrap();
+        let exhausted_at = forecast.exhausted_at.unwrap();
+        let expected = start + Duration::days(40);
+        assert!((exhausted_at - expected).num_days().abs() <= 2);
+
+        assert!(planner.user_forecast(start, &counts[..10], 90).is_none());
+    }
+
+    #[test]
+    fn test_report_text() {
+        let learner = learner_with_daily_pattern(3, 0.0);
+        let forecast = CapacityPlanner::default()
+            .bandwidth_forecast(&learner, None, 500, 1000)
+            .unwrap();
+        let report = CapacityReport::new(vec![learner.baseline("vless").unwrap()], vec![forecast]);
+
+        let text = report.to_text();
+        assert!(text.contains("vless:"));
+        assert!(text.contains("bandwidth: 50.0% used, exhausted by"));
+    }
+
+    #[test]
+    fn test_capacity_alerts() {
+        let now = Utc::now();
+        let forecast = |resource: &str, exhausted_at: Option<DateTime<Utc>>| CapacityForecast {
+            resource: resource.to_string(),
+            current: 80.0,
+            limit: 100.0,
+            exhausted_at,
+            horizon_end: now + Duration::days(30),
+            generated_at: now,
+        };
+        let mut manager = crate::alerts::AlertManager::new();
+
+        let raised = manager.apply_capacity_forecasts(
+            &[
+                forecast("bandwidth", Some(now + Duration::hours(6))),
+                forecast("users", Some(now + Duration::days(5))),
+                forecast("bandwidth:vless", Some(now + Duration::days(20))),
+            ],
+            Duration::days(7),
+        );
+        assert_eq!(raised.len(), 2);
+        let bandwidth = raised
+            .iter()
+            .find(|a| a.id == "capacity:bandwidth")
+            .unwrap();
+        assert_eq!(bandwidth.severity, crate::alerts::AlertSeverity::Medium);
+        assert_eq!(bandwidth.metadata["utilization"], "80.0");
+        let users = raised.iter().find(|a| a.id == "capacity:users").unwrap();
+        assert_eq!(users.severity, crate::alerts::AlertSeverity::Low);
+
+        // Still predicted: no new alert. No longer predicted: resolved.
+        let raised = manager.apply_capacity_forecasts(
+            &[forecast("bandwidth", Some(now + Duration::hours(6)))],
+            Duration::days(7),
+        );
+        assert!(raised.is_empty());
+        assert_eq!(manager.get_active_alerts().len(), 1);
+        assert_eq!(manager.get_alert_history(None)[0].id, "capacity:users");
+    }
+}
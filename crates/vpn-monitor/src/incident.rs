@@ -0,0 +1,457 @@
+//! Incident timelines
+//!
+//! [`IncidentCorrelator`] keeps a rolling buffer of log entries, container
+//! events and metric samples. When an alert fires it collects everything
+//! recorded within the correlation window around the alert into an
+//! [`Incident`], which renders as Markdown or JSON for postmortems.
+
+use crate::alerts::Alert;
+use crate::error::Result;
+use crate::health::HealthStatus;
+use crate::logs::{LogEntry, LogLevel};
+use crate::metrics::PerformanceMetrics;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Something that happened to a container
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerEvent {
+    pub timestamp: DateTime<Utc>,
+    pub container: String,
+    /// e.g. `restarted`, `status_changed`, `disappeared`
+    pub action: String,
+    pub detail: Option<String>,
+}
+
+impl ContainerEvent {
+    /// Events implied by the difference between two health checks
+    pub fn from_health_change(previous: &HealthStatus, current: &HealthStatus) -> Vec<Self> {
+        let mut events = Vec::new();
+
+        for container in &current.containers {
+            let event = |action: &str, detail: String| ContainerEvent {
+                timestamp: current.last_check,
+                container: container.name.clone(),
+                action: action.to_string(),
+                detail: Some(detail),
+            };
+
+            match previous
+                .containers
+                .iter()
+                .find(|c| c.name == container.name)
+            {
+                Some(before) => {
+                    if container.restart_count > before.restart_count {
+                        events.push(event(
+                            "restarted",
+                            format!("restart count {}", container.restart_count),
+                        ));
+                    }
+                    if container.status != before.status {
+                        events.push(event(
+                            "status_changed",
+                            format!(
+                                "{} -> {}",
+                                before.status.as_str(),
+                                container.status.as_str()
+                            ),
+                        ));
+                    }
+                }
+                None => events.push(event("appeared", container.status.as_str().to_string())),
+            }
+        }
+
+        for before in &previous.containers {
+            if !current.containers.iter().any(|c| c.name == before.name) {
+                events.push(ContainerEvent {
+                    timestamp: current.last_check,
+                    container: before.name.clone(),
+                    action: "disappeared".to_string(),
+                    detail: None,
+                });
+            }
+        }
+
+        events
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineSource {
+    Alert,
+    Log,
+    Container,
+    Metrics,
+}
+
+impl TimelineSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimelineSource::Alert => "alert",
+            TimelineSource::Log => "log",
+            TimelineSource::Container => "container",
+            TimelineSource::Metrics => "metrics",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimelineEntry {
+    pub timestamp: DateTime<Utc>,
+    pub source: TimelineSource,
+    /// Container, log source or subsystem the entry comes from
+    pub subsystem: String,
+    pub summary: String,
+    pub details: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncidentFormat {
+    Markdown,
+    Json,
+}
+
+/// An alert together with everything that happened around it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Incident {
+    pub id: String,
+    pub alert: Alert,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub timeline: Vec<TimelineEntry>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Incident {
+    pub fn render(&self, format: IncidentFormat) -> Result<String> {
+        match format {
+            IncidentFormat::Markdown => Ok(self.to_markdown()),
+            IncidentFormat::Json => self.to_json(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+
+        md.push_str(&format!("# Incident: {}\n\n", self.alert.title));
+        md.push_str(&format!("- **Incident:** {}\n", self.id));
+        md.push_str(&format!(
+            "- **Alert:** {} (rule `{}`)\n",
+            self.alert.id, self.alert.rule_id
+        ));
+        md.push_str(&format!(
+            "- **Severity:** {}\n",
+            self.alert.severity.as_str()
+        ));
+        md.push_str(&format!(
+            "- **Fired at:** {}\n",
+            self.alert.timestamp.format("%Y-%m-%d %H:%M:%S UTC")
+        ));
+        md.push_str(&format!(
+            "- **Window:** {} to {}\n\n",
+            self.window_start.format("%Y-%m-%d %H:%M:%S"),
+            self.window_end.format("%Y-%m-%d %H:%M:%S UTC")
+        ));
+
+        if !self.alert.description.is_empty() {
+            md.push_str("## Description\n\n");
+            md.push_str(&self.alert.description);
+            md.push_str("\n\n");
+        }
+
+        md.push_str("## Timeline\n\n");
+        md.push_str("| Time (UTC) | Source | Subsystem | Event |\n");
+        md.push_str("|---|---|---|---|\n");
+        for entry in &self.timeline {
+            md.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                entry.timestamp.format("%H:%M:%S"),
+                entry.source.as_str(),
+                escape_cell(&entry.subsystem),
+                escape_cell(&entry.summary)
+            ));
+        }
+
+        md
+    }
+}
+
+fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Buffers recent observations and correlates them with alerts
+#[derive(Debug)]
+pub struct IncidentCorrelator {
+    /// How far before and after an alert to look
+    window: Duration,
+    /// Log entries below this level are left out of timelines
+    min_log_level: LogLevel,
+    logs: Vec<LogEntry>,
+    container_events: Vec<ContainerEvent>,
+    metrics: Vec<PerformanceMetrics>,
+}
+
+impl Default for IncidentCorrelator {
+    fn default() -> Self {
+        Self::new(Duration::minutes(10))
+    }
+}
+
+impl IncidentCorrelator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            min_log_level: LogLevel::Info,
+            logs: Vec::new(),
+            container_events: Vec::new(),
+            metrics: Vec::new(),
+        }
+    }
+
+    pub fn with_min_log_level(mut self, level: LogLevel) -> Self {
+        self.min_log_level = level;
+        self
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn record_log(&mut self, entry: LogEntry) {
+        if entry.level.severity() >= self.min_log_level.severity() {
+            self.logs.push(entry);
+        }
+    }
+
+    pub fn record_container_event(&mut self, event: ContainerEvent) {
+        self.container_events.push(event);
+    }
+
+    pub fn record_metrics(&mut self, metrics: PerformanceMetrics) {
+        self.metrics.push(metrics);
+    }
+
+    /// Drop observations too old to fall into the window of an alert
+    /// firing at `now` or later
+    pub fn prune(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.window;
+        self.logs.retain(|entry| entry.timestamp >= cutoff);
+        self.container_events
+            .retain(|event| event.timestamp >= cutoff);
+        self.metrics.retain(|metrics| metrics.timestamp >= cutoff);
+    }
+
+    /// Build the incident for `alert` from observations within the window
+    pub fn correlate(&self, alert: &Alert) -> Incident {
+        let window_start = alert.timestamp - self.window;
+        let window_end = alert.timestamp + self.window;
+        let in_window = |at: &DateTime<Utc>| *at >= window_start && *at <= window_end;
+
+        let mut timeline = vec![TimelineEntry {
+            timestamp: alert.timestamp,
+            source: TimelineSource::Alert,
+            subsystem: alert.rule_id.clone(),
+            summary: format!("[{}] {}", alert.severity.as_str(), alert.title),
+            details: alert.metadata.clone(),
+        }];
+
+        for entry in self.logs.iter().filter(|e| in_window(&e.timestamp)) {
+            let mut details = HashMap::new();
+            details.insert("level".to_string(), entry.level.as_str().to_string());
+            if let Some(user_id) = &entry.user_id {
+                details.insert("user_id".to_string(), user_id.clone());
+            }
+            if let Some(ip) = &entry.ip_address {
+                details.insert("ip_address".to_string(), ip.clone());
+            }
+
+            timeline.push(TimelineEntry {
+                timestamp: entry.timestamp,
+                source: TimelineSource::Log,
+                subsystem: entry.source.clone(),
+                summary: format!("[{}] {}", entry.level.as_str(), entry.message),
+                details,
+            });
+        }
+
+        for event in self
+            .container_events
+            .iter()
+            .filter(|e| in_window(&e.timestamp))
+        {
+            let summary = match &event.detail {
+                Some(detail) => format!("{}: {}", event.action, detail),
+                None => event.action.clone(),
+            };
+
+            timeline.push(TimelineEntry {
+                timestamp: event.timestamp,
+                source: TimelineSource::Container,
+                subsystem: event.container.clone(),
+                summary,
+                details: HashMap::new(),
+            });
+        }
+
+        for metrics in self.metrics.iter().filter(|m| in_window(&m.timestamp)) {
+            timeline.push(metrics_entry(metrics));
+        }
+
+        // Stable sort keeps the alert ahead of observations at the same instant
+        timeline.sort_by_key(|entry| entry.timestamp);
+
+        Incident {
+            id: format!("incident_{}", uuid::Uuid::new_v4()),
+            alert: alert.clone(),
+            window_start,
+            window_end,
+            timeline,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+fn metrics_entry(metrics: &PerformanceMetrics) -> TimelineEntry {
+    let system = &metrics.system_metrics;
+    let app = &metrics.application_metrics;
+
+    let mut details = HashMap::new();
+    details.insert("cpu_usage".to_string(), format!("{:.1}", system.cpu_usage));
+    details.insert(
+        "memory_usage".to_string(),
+        format!("{:.1}", system.memory_usage),
+    );
+    details.insert(
+        "disk_usage".to_string(),
+        format!("{:.1}", system.disk_usage),
+    );
+    details.insert("error_rate".to_string(), format!("{:.2}", app.error_rate));
+    details.insert(
+        "active_connections".to_string(),
+        app.active_connections.to_string(),
+    );
+
+    TimelineEntry {
+        timestamp: metrics.timestamp,
+        source: TimelineSource::Metrics,
+        subsystem: "system".to_string(),
+        summary: format!(
+            "cpu {:.1}%, memory {:.1}%, disk {:.1}%, {} connections, error rate {:.2}%",
+            system.cpu_usage,
+            system.memory_usage,
+            system.disk_usage,
+            app.active_connections,
+            app.error_rate
+        ),
+        details,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::{AlertSeverity, AlertStatus};
+
+    fn alert_at(timestamp: DateTime<Utc>) -> Alert {
+        Alert {
+            id: "alert_1".to_string(),
+            rule_id: "container_down".to_string(),
+            severity: AlertSeverity::Critical,
+            title: "Container Down".to_string(),
+            description: "xray is not running".to_string(),
+            timestamp,
+            status: AlertStatus::Active,
+            metadata: HashMap::new(),
+            resolved_at: None,
+            resolved_by: None,
+        }
+    }
+
+    fn log_at(timestamp: DateTime<Utc>, level: LogLevel, message: &str) -> LogEntry {
+        LogEntry {
+            timestamp,
+            level,
+            source: "xray".to_string(),
+            message: message.to_string(),
+            user_id: None,
+            ip_address: None,
+            bytes_transferred: None,
+        }
+    }
+
+    #[test]
+    fn test_correlate_collects_window() {
+        let fired = Utc::now();
+        let mut correlator = IncidentCorrelator::default();
+
+        correlator.record_log(log_at(
+            fired - Duration::minutes(30),
+            LogLevel::Error,
+            "too early",
+        ));
+        correlator.record_log(log_at(
+            fired - Duration::minutes(3),
+            LogLevel::Error,
+            "bind failed",
+        ));
+        correlator.record_log(log_at(
+            fired - Duration::minutes(2),
+            LogLevel::Debug,
+            "noise",
+        ));
+        correlator.record_container_event(ContainerEvent {
+            timestamp: fired - Duration::minutes(1),
+            container: "xray".to_string(),
+            action: "restarted".to_string(),
+            detail: Some("restart count 3".to_string()),
+        });
+
+        let incident = correlator.correlate(&alert_at(fired));
+        let summaries: Vec<&str> = incident
+            .timeline
+            .iter()
+            .map(|e| e.summary.as_str())
+            .collect();
+        assert_eq!(
+            summaries,
+            vec![
+                "[error] bind failed",
+                "restarted: restart count 3",
+                "[critical] Container Down"
+            ]
+        );
+        assert_eq!(
+            incident.window_end - incident.window_start,
+            Duration::minutes(20)
+        );
+
+        correlator.prune(fired);
+        assert_eq!(correlator.logs.len(), 1);
+    }
+
+    #[test]
+    fn test_incident_export() {
+        let fired = Utc::now();
+        let mut correlator = IncidentCorrelator::default();
+        correlator.record_log(log_at(fired, LogLevel::Warning, "pipe | in message"));
+
+        let incident = correlator.correlate(&alert_at(fired));
+        let markdown = incident.render(IncidentFormat::Markdown).unwrap();
+        assert!(markdown.starts_with("# Incident: Container Down"));
+        assert!(markdown.contains("| log | xray | [warning] pipe \\| in message |"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&incident.render(IncidentFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["timeline"][1]["source"], "log");
+        assert_eq!(json["alert"]["rule_id"], "container_down");
+    }
+}
@@ -0,0 +1,429 @@
+//! Kernel event correlation
+//!
+//! Health checks only see containers that are up or down; they miss the
+//! kernel killing a process for running out of memory or dropping packets
+//! because the conntrack table is full. [`KernelEventMonitor`] tails the
+//! kernel log (journald, falling back to `dmesg`), picks out those events
+//! and attributes them to VPN containers where the log line allows it.
+//! [`AlertManager::apply_kernel_events`](crate::AlertManager::apply_kernel_events)
+//! turns them into alerts with a remediation hint.
+
+use crate::error::{MonitorError, Result};
+use crate::incident::ContainerEvent;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::process::Command;
+use vpn_docker::ContainerManager;
+
+/// Processes run by VPN containers, keyed by process name
+const VPN_PROCESSES: &[(&str, &str)] = &[
+    ("xray", "xray"),
+    ("outline-ss-serv", "shadowbox"),
+    ("ss-server", "shadowbox"),
+    ("node", "shadowbox"),
+    ("wireguard-go", "wireguard"),
+    ("traefik", "traefik"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KernelEventKind {
+    /// The OOM killer killed a process
+    OomKill,
+    /// The conntrack table was full and new connections were dropped
+    ConntrackTableFull,
+}
+
+impl KernelEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KernelEventKind::OomKill => "oom_kill",
+            KernelEventKind::ConntrackTableFull => "conntrack_table_full",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KernelEvent {
+    pub timestamp: DateTime<Utc>,
+    pub kind: KernelEventKind,
+    /// VPN container the event was attributed to
+    pub container: Option<String>,
+    /// Killed process, for OOM kills
+    pub process: Option<String>,
+    pub pid: Option<u32>,
+    /// Memory cgroup the process was charged to, for OOM kills
+    pub cgroup: Option<String>,
+    pub message: String,
+}
+
+impl KernelEvent {
+    /// The event as seen from the container it hit, for incident timelines
+    pub fn to_container_event(&self) -> Option<ContainerEvent> {
+        let container = self.container.clone()?;
+        let detail = match (&self.process, self.pid) {
+            (Some(process), Some(pid)) => Some(format!("killed {} (pid {})", process, pid)),
+            (Some(process), None) => Some(format!("killed {}", process)),
+            _ => None,
+        };
+
+        Some(ContainerEvent {
+            timestamp: self.timestamp,
+            container,
+            action: self.kind.as_str().to_string(),
+            detail,
+        })
+    }
+
+    /// What to change so this does not happen again
+    pub fn remediation(&self) -> String {
+        match self.kind {
+            KernelEventKind::OomKill => match &self.container {
+                Some(container) => format!(
+                    "Raise the memory limit of the {} container (mem_limit in its compose \
+                     service) or reduce its load, e.g. by moving users to another node",
+                    container
+                ),
+                None => "Add memory or swap to the host, or lower the memory limits of \
+                         other services"
+                    .to_string(),
+            },
+            KernelEventKind::ConntrackTableFull => {
+                let suggested = conntrack_max().map_or(262144, |max| max * 2);
+                format!(
+                    "Raise net.netfilter.nf_conntrack_max (sysctl -w \
+                     net.netfilter.nf_conntrack_max={}) and persist it in /etc/sysctl.d, \
+                     or shorten nf_conntrack_tcp_timeout_established",
+                    suggested
+                )
+            }
+        }
+    }
+}
+
+/// Current `nf_conntrack_max`, if the module is loaded
+pub fn conntrack_max() -> Option<u64> {
+    std::fs::read_to_string("/proc/sys/net/netfilter/nf_conntrack_max")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Where kernel messages are read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelLogSource {
+    Journald,
+    Dmesg,
+}
+
+/// Extracts OOM and conntrack events from kernel log lines
+pub struct KernelEventParser {
+    timestamp_regex: Regex,
+    oom_kill_regex: Regex,
+    killed_process_regex: Regex,
+    conntrack_regex: Regex,
+    container_id_regex: Regex,
+}
+
+impl KernelEventParser {
+    pub fn new() -> Result<Self> {
+        // journalctl -o short-iso: 2024-01-01T12:00:00+0000 host kernel: ...
+        // dmesg --time-format iso:  2024-01-01T12:00:00,123456+00:00 ...
+        let timestamp_regex =
+            Regex::new(r"^(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:[.,]\d+)?[+-]\d{2}:?\d{2})")?;
+
+        // oom-kill:constraint=CONSTRAINT_MEMCG,...,oom_memcg=/docker/<id>,task_memcg=/docker/<id>,task=xray,pid=1234,uid=0
+        let oom_kill_regex = Regex::new(r"oom-kill:(\S+)")?;
+
+        // Memory cgroup out of memory: Killed process 1234 (xray) total-vm:...
+        let killed_process_regex = Regex::new(r"Killed process (\d+) \(([^)]+)\)")?;
+
+        // nf_conntrack: nf_conntrack: table full, dropping packet
+        let conntrack_regex = Regex::new(r"nf_conntrack: (?:nf_conntrack: )?table full")?;
+
+        // /docker/<id> or /system.slice/docker-<id>.scope
+        let container_id_regex = Regex::new(r"docker[/-]([0-9a-f]{64})")?;
+
+        Ok(Self {
+            timestamp_regex,
+            oom_kill_regex,
+            killed_process_regex,
+            conntrack_regex,
+            container_id_regex,
+        })
+    }
+
+    /// Parse one line; lines that are neither OOM kills nor conntrack
+    /// overflows yield `None`
+    pub fn parse_line(&self, line: &str) -> Option<KernelEvent> {
+        let timestamp = self.parse_timestamp(line).unwrap_or_else(Utc::now);
+        let event = |kind| KernelEvent {
+            timestamp,
+            kind,
+            container: None,
+            process: None,
+            pid: None,
+            cgroup: None,
+            message: line.trim().to_string(),
+        };
+
+        if self.conntrack_regex.is_match(line) {
+            return Some(event(KernelEventKind::ConntrackTableFull));
+        }
+
+        if let Some(captures) = self.oom_kill_regex.captures(line) {
+            let fields: HashMap<&str, &str> = captures[1]
+                .split(',')
+                .filter_map(|field| field.split_once('='))
+                .collect();
+
+            let mut event = event(KernelEventKind::OomKill);
+            event.process = fields.get("task").map(|task| task.to_string());
+            event.pid = fields.get("pid").and_then(|pid| pid.parse().ok());
+            event.cgroup = fields
+                .get("task_memcg")
+                .or_else(|| fields.get("oom_memcg"))
+                .map(|cgroup| cgroup.to_string());
+            return Some(event);
+        }
+
+        if let Some(captures) = self.killed_process_regex.captures(line) {
+            let mut event = event(KernelEventKind::OomKill);
+            event.pid = captures[1].parse().ok();
+            event.process = Some(captures[2].to_string());
+            return Some(event);
+        }
+
+        None
+    }
+
+    /// Parse a batch of lines, dropping the `Killed process` duplicate of
+    /// each `oom-kill:` summary
+    pub fn parse(&self, output: &str) -> Vec<KernelEvent> {
+        let mut events: Vec<KernelEvent> = Vec::new();
+
+        for event in output.lines().filter_map(|line| self.parse_line(line)) {
+            let duplicate = event.kind == KernelEventKind::OomKill
+                && events.iter().any(|seen| {
+                    seen.kind == KernelEventKind::OomKill
+                        && seen.pid.is_some()
+                        && seen.pid == event.pid
+                });
+            if !duplicate {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+
+    /// Full container id from a memory cgroup path
+    pub fn container_id(&self, cgroup: &str) -> Option<String> {
+        self.container_id_regex
+            .captures(cgroup)
+            .map(|captures| captures[1].to_string())
+    }
+
+    fn parse_timestamp(&self, line: &str) -> Option<DateTime<Utc>> {
+        let raw = self.timestamp_regex.captures(line)?[1].replace(',', ".");
+        DateTime::parse_from_str(&raw, "%Y-%m-%dT%H:%M:%S%.f%z")
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+    }
+}
+
+/// Tails the kernel log for events that affect VPN containers
+pub struct KernelEventMonitor {
+    parser: KernelEventParser,
+    source: Option<KernelLogSource>,
+    /// Container names by full id
+    containers: HashMap<String, String>,
+    last_seen: DateTime<Utc>,
+}
+
+impl KernelEventMonitor {
+    /// Monitor reporting events from now on
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            parser: KernelEventParser::new()?,
+            source: None,
+            containers: HashMap::new(),
+            last_seen: Utc::now(),
+        })
+    }
+
+    /// Read from `source` instead of detecting it
+    pub fn with_source(mut self, source: KernelLogSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Report events since `since` on the next poll
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.last_seen = since;
+        self
+    }
+
+    /// Set the container ids OOM kills are attributed by
+    pub fn set_containers<I>(&mut self, containers: I)
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.containers = containers.into_iter().collect();
+    }
+
+    /// Refresh container ids from Docker
+    pub async fn refresh_containers(&mut self, manager: &ContainerManager) -> Result<()> {
+        let containers = manager.list_containers(true).await?;
+        self.set_containers(containers.into_iter().filter_map(|container| {
+            let id = container.id?;
+            let name = container
+                .names?
+                .into_iter()
+                .next()?
+                .trim_start_matches('/')
+                .to_string();
+            Some((id, name))
+        }));
+        Ok(())
+    }
+
+    /// Events logged since the last poll, attributed to containers
+    pub fn poll(&mut self) -> Result<Vec<KernelEvent>> {
+        let source = match self.source {
+            Some(source) => source,
+            None => {
+                let source = detect_source();
+                self.source = Some(source);
+                source
+            }
+        };
+
+        let output = read_kernel_log(source, self.last_seen)?;
+        let mut events: Vec<KernelEvent> = self
+            .parser
+            .parse(&output)
+            .into_iter()
+            .filter(|event| event.timestamp > self.last_seen)
+            .collect();
+
+        for event in &mut events {
+            event.container = self.attribute(event);
+        }
+        if let Some(latest) = events.iter().map(|event| event.timestamp).max() {
+            self.last_seen = latest;
+        }
+
+        Ok(events)
+    }
+
+    /// Container `event` hit, by cgroup id first and process name second
+    pub fn attribute(&self, event: &KernelEvent) -> Option<String> {
+        if let Some(name) = event
+            .cgroup
+            .as_deref()
+            .and_then(|cgroup| self.parser.container_id(cgroup))
+            .and_then(|id| self.containers.get(&id))
+        {
+            return Some(name.clone());
+        }
+
+        let process = event.process.as_deref()?;
+        VPN_PROCESSES
+            .iter()
+            .find(|(name, _)| *name == process)
+            .map(|(_, container)| container.to_string())
+    }
+}
+
+fn detect_source() -> KernelLogSource {
+    let journald = Command::new("journalctl")
+        .args(["-k", "-n", "0", "--no-pager"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+
+    if journald {
+        KernelLogSource::Journald
+    } else {
+        KernelLogSource::Dmesg
+    }
+}
+
+fn read_kernel_log(source: KernelLogSource, since: DateTime<Utc>) -> Result<String> {
+    let output = match source {
+        KernelLogSource::Journald => Command::new("journalctl")
+            .args(["-k", "-o", "short-iso", "--no-pager", "--utc", "--since"])
+            .arg(since.format("%Y-%m-%d %H:%M:%S").to_string())
+            .output()?,
+        KernelLogSource::Dmesg => Command::new("dmesg")
+            .args(["--time-format", "iso"])
+            .output()?,
+    };
+
+    if !output.status.success() {
+        return Err(MonitorError::HealthCheckError(format!(
+            "Failed to read kernel log: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTAINER_ID: &str = "4f2c9a0b1d3e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8";
+
+    #[test]
+    fn test_oom_kill_attributed_by_cgroup() {
+        let parser = KernelEventParser::new().unwrap();
+        let log = format!(
+            "2024-01-01T12:00:00+0000 vpn kernel: oom-kill:constraint=CONSTRAINT_MEMCG,nodemask=(null),\
+             oom_memcg=/system.slice/docker-{id}.scope,task_memcg=/system.slice/docker-{id}.scope,\
+             task=node,pid=4242,uid=0\n\
+             2024-01-01T12:00:00+0000 vpn kernel: Memory cgroup out of memory: Killed process 4242 (node) \
+             total-vm:1048576kB, anon-rss:524288kB\n",
+            id = CONTAINER_ID
+        );
+
+        let events = parser.parse(&log);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, KernelEventKind::OomKill);
+        assert_eq!(events[0].pid, Some(4242));
+        assert_eq!(
+            events[0].timestamp.to_rfc3339(),
+            "2024-01-01T12:00:00+00:00"
+        );
+
+        let mut monitor = KernelEventMonitor::new().unwrap();
+        monitor.set_containers([(CONTAINER_ID.to_string(), "outline-vpn".to_string())]);
+        assert_eq!(
+            monitor.attribute(&events[0]).as_deref(),
+            Some("outline-vpn")
+        );
+
+        // Unknown cgroups fall back to the process name
+        monitor.set_containers([]);
+        assert_eq!(monitor.attribute(&events[0]).as_deref(), Some("shadowbox"));
+    }
+
+    #[test]
+    fn test_conntrack_table_full() {
+        let parser = KernelEventParser::new().unwrap();
+        let event = parser
+            .parse_line(
+                "2024-01-01T12:00:00,123456+00:00 nf_conntrack: nf_conntrack: table full, dropping packet",
+            )
+            .unwrap();
+
+        assert_eq!(event.kind, KernelEventKind::ConntrackTableFull);
+        assert!(event.container.is_none());
+        assert!(event.remediation().contains("nf_conntrack_max"));
+        assert!(parser.parse_line("eth0: link up").is_none());
+    }
+}
@@ -1,13 +1,31 @@
 pub mod alerts;
+pub mod bundle;
+pub mod cluster;
 pub mod error;
+pub mod forecast;
 pub mod health;
+pub mod incident;
+pub mod kernel;
 pub mod logs;
 pub mod metrics;
+pub mod schedule;
 pub mod traffic;
 
 pub use alerts::{Alert, AlertManager, AlertRule};
+pub use bundle::{
+    BundleEntry, BundleManifest, ExcludedItem, Redactor, SupportBundle, SupportBundleCollector,
+    SupportBundleOptions,
+};
+pub use cluster::MonitorTrafficSource;
 pub use error::{MonitorError, Result};
+pub use forecast::{
+    BaselineLearner, CapacityForecast, CapacityPlanner, CapacityReport, HoltWinters,
+    TrafficBaseline,
+};
 pub use health::{HealthMonitor, HealthStatus, SystemMetrics};
+pub use incident::{ContainerEvent, Incident, IncidentCorrelator, IncidentFormat};
+pub use kernel::{KernelEvent, KernelEventKind, KernelEventMonitor, KernelLogSource};
 pub use logs::{LogAnalyzer, LogEntry, LogStats};
 pub use metrics::{MetricsCollector, PerformanceMetrics};
+pub use schedule::{AdaptivePolling, AdaptiveScheduler, SystemPressure};
 pub use traffic::{TrafficMonitor, TrafficStats, TrafficSummary};
@@ -1,5 +1,7 @@
+use crate::alerts::AlertManager;
 use crate::error::Result;
 use crate::health::{HealthMonitor, HealthStatus};
+use crate::schedule::{AdaptivePolling, AdaptiveScheduler};
 use crate::traffic::{TrafficMonitor, TrafficSummary};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -78,6 +80,7 @@ pub struct MetricsCollector {
     config: MetricsConfig,
     last_metrics: Option<PerformanceMetrics>,
     collection_history: Vec<PerformanceMetrics>,
+    scheduler: AdaptiveScheduler,
 }
 
 impl MetricsCollector {
@@ -86,15 +89,31 @@ impl MetricsCollector {
         traffic_monitor: TrafficMonitor,
         config: MetricsConfig,
     ) -> Self {
+        let scheduler = AdaptiveScheduler::new(AdaptivePolling::around(config.collection_interval));
         Self {
             health_monitor,
             traffic_monitor,
             config,
             last_metrics: None,
             collection_history: Vec::new(),
+            scheduler,
         }
     }
 
+    /// Adapt the collection interval within other bounds than those derived
+    /// from `collection_interval`
+    pub fn with_adaptive_polling(mut self, polling: AdaptivePolling) -> Self {
+        self.scheduler = AdaptiveScheduler::new(polling);
+        self
+    }
+
+    /// Sleep until the next collection is due. The interval backs off while
+    /// the host is under CPU or IO pressure and tightens while `alerts` has
+    /// active alerts.
+    pub async fn wait_for_next_collection(&mut self, alerts: &AlertManager) -> Duration {
+        self.scheduler.wait(alerts.get_active_alerts().len()).await
+    }
+
     pub async fn collect_metrics(&mut self) -> Result<PerformanceMetrics> {
         let start_time = Instant::now();
 
@@ -0,0 +1,208 @@
+//! Adaptive polling intervals for health and metrics collection
+//!
+//! Collecting health and metrics costs CPU and disk time of its own, which
+//! matters on small VPSes already at their limits. [`AdaptiveScheduler`]
+//! stretches the interval between polls while the host is under CPU or IO
+//! pressure and shortens it while alerts are active, so an incident is
+//! followed closely without adding load to a host that is already busy.
+//!
+//! Pressure is read from the kernel's pressure stall information
+//! (`/proc/pressure`) where available and estimated from the load average
+//! per CPU otherwise.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Bounds and thresholds of adaptive polling
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdaptivePolling {
+    /// Interval on a quiet host without active alerts
+    pub base_interval: Duration,
+    /// Shortest interval, used during incidents
+    pub min_interval: Duration,
+    /// Longest interval polling backs off to under pressure
+    pub max_interval: Duration,
+    /// Pressure (0.0 to 1.0) above which polling backs off
+    pub high_pressure: f64,
+    /// Factor the interval grows by per poll under pressure
+    pub backoff_factor: f64,
+}
+
+impl Default for AdaptivePolling {
+    fn default() -> Self {
+        Self {
+            base_interval: Duration::from_secs(60),
+            min_interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(600),
+            high_pressure: 0.7,
+            backoff_factor: 2.0,
+        }
+    }
+}
+
+impl AdaptivePolling {
+    /// Polling around `base_interval` with the default bounds scaled to it
+    pub fn around(base_interval: Duration) -> Self {
+        Self {
+            base_interval,
+            min_interval: (base_interval / 6).max(Duration::from_secs(1)),
+            max_interval: base_interval * 10,
+            ..Self::default()
+        }
+    }
+}
+
+/// CPU and IO pressure on the host, each from 0.0 (idle) to 1.0 (saturated)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SystemPressure {
+    pub cpu: f64,
+    pub io: f64,
+}
+
+impl SystemPressure {
+    /// Current pressure; what cannot be read counts as no pressure
+    pub async fn read() -> Self {
+        let cpu = match read_psi("/proc/pressure/cpu").await {
+            Some(cpu) => Some(cpu),
+            None => tokio::fs::read_to_string("/proc/loadavg")
+                .await
+                .ok()
+                .and_then(|content| load_pressure(&content, cpu_count())),
+        };
+        let io = read_psi("/proc/pressure/io").await;
+
+        Self {
+            cpu: cpu.unwrap_or(0.0),
+            io: io.unwrap_or(0.0),
+        }
+    }
+
+    /// The higher of CPU and IO pressure
+    pub fn level(&self) -> f64 {
+        self.cpu.max(self.io)
+    }
+}
+
+async fn read_psi(path: &str) -> Option<f64> {
+    parse_psi(&tokio::fs::read_to_string(path).await.ok()?)
+}
+
+/// Share of time some tasks stalled over the last 10 seconds, from a
+/// pressure stall information file
+fn parse_psi(content: &str) -> Option<f64> {
+    content
+        .lines()
+        .find(|line| line.starts_with("some "))?
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse::<f64>()
+        .ok()
+        .map(|percent| (percent / 100.0).clamp(0.0, 1.0))
+}
+
+/// One-minute load average per CPU as pressure, saturating at one runnable
+/// task per CPU
+fn load_pressure(loadavg: &str, cpus: usize) -> Option<f64> {
+    let load1: f64 = loadavg.split_whitespace().next()?.parse().ok()?;
+    Some((load1 / cpus.max(1) as f64).clamp(0.0, 1.0))
+}
+
+fn cpu_count() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Picks the interval before each poll from host pressure and alerts
+#[derive(Debug, Clone)]
+pub struct AdaptiveScheduler {
+    config: AdaptivePolling,
+    current: Duration,
+}
+
+impl AdaptiveScheduler {
+    pub fn new(config: AdaptivePolling) -> Self {
+        let current = config.base_interval;
+        Self { config, current }
+    }
+
+    /// Interval chosen last
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    /// Interval before the next poll. Under pressure the interval grows by
+    /// the backoff factor each poll up to the maximum; during an incident it
+    /// drops to the minimum, or to the base interval while the host is also
+    /// under pressure. Otherwise it returns to the base interval.
+    pub fn next_interval(&mut self, pressure: SystemPressure, active_alerts: usize) -> Duration {
+        let config = &self.config;
+        let under_pressure = pressure.level() >= config.high_pressure;
+
+        self.current = match (active_alerts > 0, under_pressure) {
+            (true, false) => config.min_interval,
+            (true, true) => config.base_interval,
+            (false, true) => self
+                .current
+                .max(config.base_interval)
+                .mul_f64(config.backoff_factor.max(1.0)),
+            (false, false) => config.base_interval,
+        }
+        .clamp(
+            config.min_interval,
+            config.max_interval.max(config.min_interval),
+        );
+
+        self.current
+    }
+
+    /// Read host pressure and sleep for the next interval
+    pub async fn wait(&mut self, active_alerts: usize) -> Duration {
+        let interval = self.next_interval(SystemPressure::read().await, active_alerts);
+        tokio::time::sleep(interval).await;
+        interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUIET: SystemPressure = SystemPressure { cpu: 0.1, io: 0.0 };
+    const BUSY: SystemPressure = SystemPressure { cpu: 0.2, io: 0.9 };
+
+    #[test]
+    fn test_backs_off_under_pressure_and_tightens_during_incidents() {
+        let mut scheduler = AdaptiveScheduler::new(AdaptivePolling::default());
+
+        assert_eq!(scheduler.next_interval(QUIET, 0), Duration::from_secs(60));
+        assert_eq!(scheduler.next_interval(BUSY, 0), Duration::from_secs(120));
+        assert_eq!(scheduler.next_interval(BUSY, 0), Duration::from_secs(240));
+        assert_eq!(scheduler.next_interval(BUSY, 0), Duration::from_secs(480));
+        assert_eq!(scheduler.next_interval(BUSY, 0), Duration::from_secs(600));
+
+        // An incident on a busy host polls at the base interval, on a quiet
+        // one at the minimum
+        assert_eq!(scheduler.next_interval(BUSY, 2), Duration::from_secs(60));
+        assert_eq!(scheduler.next_interval(QUIET, 2), Duration::from_secs(10));
+        assert_eq!(scheduler.next_interval(QUIET, 0), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_parse_pressure() {
+        let psi = "some avg10=42.50 avg60=10.00 avg300=2.00 total=123456\n\
+                   full avg10=5.00 avg60=1.00 avg300=0.00 total=1234\n";
+        assert_eq!(parse_psi(psi), Some(0.425));
+        assert_eq!(parse_psi("garbage"), None);
+
+        assert_eq!(load_pressure("3.00 2.00 1.00 2/345 6789\n", 4), Some(0.75));
+        assert_eq!(load_pressure("9.00 2.00 1.00 2/345 6789\n", 4), Some(1.0));
+    }
+
+    #[test]
+    fn test_around_scales_bounds() {
+        let polling = AdaptivePolling::around(Duration::from_secs(30));
+        assert_eq!(polling.min_interval, Duration::from_secs(5));
+        assert_eq!(polling.max_interval, Duration::from_secs(300));
+    }
+}
@@ -29,6 +29,9 @@ pub enum NetworkError {
     #[error("HTTP request failed: {0}")]
     HttpError(#[from] reqwest::Error),
 
+    #[error("Docker error: {0}")]
+    DockerError(#[from] vpn_docker::DockerError),
+
     #[error("No available subnets found for VPN")]
     NoAvailableSubnets,
 
@@ -37,6 +40,12 @@ pub enum NetworkError {
 
     #[error("Command execution failed: {0}")]
     CommandError(String),
+
+    #[error("Access gate error: {0}")]
+    AccessGateError(String),
+
+    #[error("Network tuning failed: {0}")]
+    TuningError(String),
 }
 
 pub type Result<T> = std::result::Result<T, NetworkError>;
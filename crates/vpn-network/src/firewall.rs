@@ -1,8 +1,13 @@
 use crate::error::{NetworkError, Result};
+use crate::ledger::{FirewallLedger, LedgerEntry};
+use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 use tokio::process::Command;
 
-#[derive(Debug, Clone)]
+/// Comment prefix marking a firewall rule as created by the VPN
+pub const RULE_COMMENT_PREFIX: &str = "VPN";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FirewallRule {
     pub port: u16,
     pub protocol: Protocol,
@@ -11,20 +16,85 @@ pub struct FirewallRule {
     pub comment: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Protocol {
     Tcp,
     Udp,
     Both,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Direction {
     In,
     Out,
     Both,
 }
 
+/// Tool a firewall rule is managed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallBackend {
+    Ufw,
+    Iptables,
+}
+
+/// A VPN rule found on the host
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostRule {
+    pub backend: FirewallBackend,
+    pub rule: FirewallRule,
+    handle: RuleHandle,
+}
+
+/// How a rule found on the host is addressed for deletion
+#[derive(Debug, Clone, PartialEq)]
+enum RuleHandle {
+    /// Position in `ufw status numbered`
+    UfwNumber(usize),
+    /// Rule specification as printed by `iptables -S`, without the `-A`
+    Iptables(Vec<String>),
+}
+
+/// Differences between the firewall ledger and the host's rules
+#[derive(Debug, Clone, Default)]
+pub struct FirewallAudit {
+    /// Ledger rules absent from the host. Rules iptables holds as one rule
+    /// per protocol are listed per protocol.
+    pub missing: Vec<LedgerEntry>,
+    /// VPN rules on the host that the ledger does not know
+    pub orphaned: Vec<HostRule>,
+}
+
+impl FirewallAudit {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+impl FirewallRule {
+    /// This rule with a comment marking it as a VPN rule
+    pub fn tagged(&self) -> Self {
+        let comment = match &self.comment {
+            Some(comment) if comment.starts_with(RULE_COMMENT_PREFIX) => comment.clone(),
+            Some(comment) => format!("{}: {}", RULE_COMMENT_PREFIX, comment),
+            None => RULE_COMMENT_PREFIX.to_string(),
+        };
+        Self {
+            comment: Some(comment),
+            ..self.clone()
+        }
+    }
+
+    /// Whether the comment marks this as a VPN rule
+    pub fn is_vpn_rule(&self) -> bool {
+        self.comment
+            .as_deref()
+            .is_some_and(|comment| comment.starts_with(RULE_COMMENT_PREFIX))
+    }
+}
+
 pub struct FirewallManager;
 
 impl FirewallManager {
@@ -84,6 +154,10 @@ impl FirewallManager {
         cmd.arg("ufw");
         cmd.arg("delete").arg("allow");
 
+        if let Some(source) = &rule.source {
+            cmd.arg("from").arg(source.to_string());
+        }
+
         cmd.arg("to").arg("any");
         cmd.arg("port").arg(rule.port.to_string());
 
@@ -105,6 +179,25 @@ impl FirewallManager {
     }
 
     pub async fn add_iptables_rule(rule: &FirewallRule) -> Result<()> {
+        for spec in Self::iptables_rule_specs(rule)? {
+            let spec: Vec<&str> = spec.iter().map(String::as_str).collect();
+            Self::run_iptables_with("-A", &spec).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete every copy of `rule` from iptables; a rule that is already
+    /// gone is not an error
+    pub async fn remove_iptables_rule(rule: &FirewallRule) -> Result<()> {
+        for spec in Self::iptables_rule_specs(rule)? {
+            let spec: Vec<&str> = spec.iter().map(String::as_str).collect();
+            while Self::run_iptables_with("-D", &spec).await.is_ok() {}
+        }
+        Ok(())
+    }
+
+    /// iptables arguments after the operation, one rule per protocol
+    fn iptables_rule_specs(rule: &FirewallRule) -> Result<Vec<Vec<String>>> {
         let chain = match rule.direction {
             Direction::In => "INPUT",
             Direction::Out => "OUTPUT",
@@ -115,51 +208,31 @@ impl FirewallManager {
             }
         };
 
-        let mut cmd = Command::new("sudo");
-        cmd.arg("iptables");
-        cmd.arg("-A").arg(chain);
-
-        if let Some(source) = &rule.source {
-            cmd.arg("-s").arg(source.to_string());
-        }
-
-        match rule.protocol {
-            Protocol::Tcp => cmd.arg("-p").arg("tcp"),
-            Protocol::Udp => cmd.arg("-p").arg("udp"),
-            Protocol::Both => {
-                // Handle TCP first
-                let tcp_rule = FirewallRule {
-                    protocol: Protocol::Tcp,
-                    ..rule.clone()
-                };
-                Box::pin(Self::add_iptables_rule(&tcp_rule)).await?;
-
-                // Handle UDP second
-                let udp_rule = FirewallRule {
-                    protocol: Protocol::Udp,
-                    ..rule.clone()
-                };
-                return Box::pin(Self::add_iptables_rule(&udp_rule)).await;
-            }
+        let protocols: &[&str] = match rule.protocol {
+            Protocol::Tcp => &["tcp"],
+            Protocol::Udp => &["udp"],
+            Protocol::Both => &["tcp", "udp"],
         };
 
-        cmd.arg("--dport").arg(rule.port.to_string());
-        cmd.arg("-j").arg("ACCEPT");
-
-        if let Some(comment) = &rule.comment {
-            cmd.arg("-m").arg("comment");
-            cmd.arg("--comment").arg(comment);
-        }
-
-        let output = cmd.output().await?;
-
-        if !output.status.success() {
-            return Err(NetworkError::FirewallError(
-                String::from_utf8_lossy(&output.stderr).to_string(),
-            ));
-        }
+        let specs = protocols
+            .iter()
+            .map(|protocol| {
+                let mut spec = vec![chain.to_string()];
+                if let Some(source) = &rule.source {
+                    spec.extend(["-s".to_string(), source.to_string()]);
+                }
+                spec.extend(["-p".to_string(), protocol.to_string()]);
+                spec.extend(["--dport".to_string(), rule.port.to_string()]);
+                spec.extend(["-j".to_string(), "ACCEPT".to_string()]);
+                if let Some(comment) = &rule.comment {
+                    spec.extend(["-m".to_string(), "comment".to_string()]);
+                    spec.extend(["--comment".to_string(), comment.clone()]);
+                }
+                spec
+            })
+            .collect();
 
-        Ok(())
+        Ok(specs)
     }
 
     pub async fn enable_ufw() -> Result<()> {
@@ -234,6 +307,530 @@ impl FirewallManager {
     }
 }
 
+/// iptables chain holding the access gate for management ports
+pub const ACCESS_GATE_CHAIN: &str = "VPN-ACCESS-GATE";
+
+/// Chains jumping into the gate. Docker publishes container ports through
+/// DNAT, so their traffic never passes INPUT and is caught in DOCKER-USER.
+const ACCESS_GATE_PARENT_CHAINS: [&str; 2] = ["INPUT", "DOCKER-USER"];
+
+impl FirewallManager {
+    /// Drop TCP traffic to `ports` unless its source was let through with
+    /// [`FirewallManager::allow_gate_source`]. Allowed sources return to the
+    /// calling chain, so the regular firewall still applies to them.
+    pub async fn create_access_gate(ports: &[u16]) -> Result<()> {
+        // The chain may be left over from an earlier installation
+        if Self::run_iptables(&["-N", ACCESS_GATE_CHAIN])
+            .await
+            .is_err()
+        {
+            Self::run_iptables(&["-F", ACCESS_GATE_CHAIN]).await?;
+        }
+
+        for port in ports {
+            let mut drop = vec![ACCESS_GATE_CHAIN];
+            let port_match = Self::gate_port_match(*port);
+            drop.extend(port_match.iter().map(String::as_str));
+            drop.extend(["-j", "DROP"]);
+            Self::run_iptables_with("-A", &drop).await?;
+
+            for parent in ACCESS_GATE_PARENT_CHAINS {
+                let mut jump = vec![parent];
+                jump.extend(port_match.iter().map(String::as_str));
+                jump.extend(["-j", ACCESS_GATE_CHAIN]);
+
+                // Jump into the gate ahead of any ACCEPT rule for the port
+                if Self::run_iptables_with("-C", &jump).await.is_ok() {
+                    continue;
+                }
+                if let Err(e) = Self::run_iptables_with("-I", &jump).await {
+                    // DOCKER-USER only exists once Docker has started
+                    if parent == "INPUT" {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Let `source` through the gate for `ports`
+    pub async fn allow_gate_source(source: IpAddr, ports: &[u16]) -> Result<()> {
+        for port in ports {
+            let rule = Self::gate_source_rule(source, *port);
+            let rule: Vec<&str> = rule.iter().map(String::as_str).collect();
+            if Self::run_iptables_with("-C", &rule).await.is_err() {
+                Self::run_iptables_with("-I", &rule).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Close the gate for `source` again
+    pub async fn revoke_gate_source(source: IpAddr, ports: &[u16]) -> Result<()> {
+        for port in ports {
+            let rule = Self::gate_source_rule(source, *port);
+            let rule: Vec<&str> = rule.iter().map(String::as_str).collect();
+            // Deleting a rule that already expired is not an error
+            while Self::run_iptables_with("-D", &rule).await.is_ok() {}
+        }
+        Ok(())
+    }
+
+    /// Remove the gate, reopening `ports` to the regular firewall rules
+    pub async fn remove_access_gate(ports: &[u16]) -> Result<()> {
+        for port in ports {
+            let port_match = Self::gate_port_match(*port);
+            for parent in ACCESS_GATE_PARENT_CHAINS {
+                let mut jump = vec![parent];
+                jump.extend(port_match.iter().map(String::as_str));
+                jump.extend(["-j", ACCESS_GATE_CHAIN]);
+                while Self::run_iptables_with("-D", &jump).await.is_ok() {}
+            }
+        }
+
+        Self::run_iptables(&["-F", ACCESS_GATE_CHAIN]).await?;
+        Self::run_iptables(&["-X", ACCESS_GATE_CHAIN]).await
+    }
+
+    /// Match on the port the client connected to, before any Docker DNAT
+    fn gate_port_match(port: u16) -> Vec<String> {
+        [
+            "-p",
+            "tcp",
+            "-m",
+            "conntrack",
+            "--ctorigdstport",
+            &port.to_string(),
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect()
+    }
+
+    fn gate_source_rule(source: IpAddr, port: u16) -> Vec<String> {
+        let mut rule = vec![
+            ACCESS_GATE_CHAIN.to_string(),
+            "-s".to_string(),
+            source.to_string(),
+        ];
+        rule.extend(Self::gate_port_match(port));
+        rule.extend(["-j".to_string(), "RETURN".to_string()]);
+        rule
+    }
+
+    async fn run_iptables_with(operation: &str, rule: &[&str]) -> Result<()> {
+        let mut args = vec![operation];
+        args.extend_from_slice(rule);
+        Self::run_iptables(&args).await
+    }
+
+    async fn run_iptables(args: &[&str]) -> Result<()> {
+        let output = Command::new("sudo")
+            .arg("iptables")
+            .args(args)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            return Err(NetworkError::FirewallError(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl FirewallManager {
+    /// Backend new rules go to: ufw when installed, otherwise iptables
+    pub async fn detect_backend() -> Option<FirewallBackend> {
+        if Self::is_ufw_installed().await {
+            Some(FirewallBackend::Ufw)
+        } else if Self::is_iptables_installed().await {
+            Some(FirewallBackend::Iptables)
+        } else {
+            None
+        }
+    }
+
+    /// Add `rule` with `backend` and record it in `ledger`. The rule is
+    /// tagged with [`RULE_COMMENT_PREFIX`] so reconciliation recognises it.
+    pub async fn add_tracked_rule(
+        ledger: &mut FirewallLedger,
+        backend: FirewallBackend,
+        rule: &FirewallRule,
+    ) -> Result<()> {
+        let rule = rule.tagged();
+        match backend {
+            FirewallBackend::Ufw => Self::add_ufw_rule(&rule).await?,
+            FirewallBackend::Iptables => Self::add_iptables_rule(&rule).await?,
+        }
+
+        if ledger.record(backend, &rule) {
+            ledger.save()?;
+        }
+        Ok(())
+    }
+
+    /// Remove a rule added with [`FirewallManager::add_tracked_rule`] from
+    /// the host and from `ledger`
+    pub async fn remove_tracked_rule(
+        ledger: &mut FirewallLedger,
+        backend: FirewallBackend,
+        rule: &FirewallRule,
+    ) -> Result<()> {
+        let rule = rule.tagged();
+        match backend {
+            FirewallBackend::Ufw => Self::remove_ufw_rule(&rule).await?,
+            FirewallBackend::Iptables => Self::remove_iptables_rule(&rule).await?,
+        }
+
+        if ledger.forget(backend, &rule) {
+            ledger.save()?;
+        }
+        Ok(())
+    }
+
+    /// VPN rules `backend` currently holds on the host
+    pub async fn list_host_rules(backend: FirewallBackend) -> Result<Vec<HostRule>> {
+        let rules = match backend {
+            FirewallBackend::Ufw => Self::list_ufw_rules()
+                .await?
+                .iter()
+                .filter_map(|line| parse_ufw_rule(line))
+                .collect(),
+            FirewallBackend::Iptables => {
+                let mut rules = Vec::new();
+                for chain in ["INPUT", "OUTPUT"] {
+                    let listing = Self::iptables_output(&["-S", chain]).await?;
+                    rules.extend(listing.lines().filter_map(parse_iptables_rule));
+                }
+                rules
+            }
+        };
+
+        Ok(rules
+            .into_iter()
+            .filter(|rule: &HostRule| rule.rule.is_vpn_rule())
+            .collect())
+    }
+
+    /// Compare `ledger` with the rules on the host. Backends that are not
+    /// installed are skipped.
+    pub async fn audit(ledger: &FirewallLedger) -> Result<FirewallAudit> {
+        let mut host = Vec::new();
+        let mut installed = Vec::new();
+
+        if Self::is_ufw_installed().await {
+            host.extend(Self::list_host_rules(FirewallBackend::Ufw).await?);
+            installed.push(FirewallBackend::Ufw);
+        }
+        if Self::is_iptables_installed().await {
+            host.extend(Self::list_host_rules(FirewallBackend::Iptables).await?);
+            installed.push(FirewallBackend::Iptables);
+        }
+
+        let entries: Vec<LedgerEntry> = ledger
+            .entries()
+            .iter()
+            .filter(|entry| {
+                let available = installed.contains(&entry.backend);
+                if !available {
+                    tracing::warn!(
+                        "Skipping firewall rule for port {}: {:?} is not installed",
+                        entry.rule.port,
+                        entry.backend
+                    );
+                }
+                available
+            })
+            .cloned()
+            .collect();
+
+        Ok(audit_rules(&entries, &host))
+    }
+
+    /// Re-add ledger rules missing from the host and delete VPN rules the
+    /// ledger does not know, returning what was changed
+    pub async fn reconcile(ledger: &FirewallLedger) -> Result<FirewallAudit> {
+        let mut audit = Self::audit(ledger).await?;
+
+        for entry in &audit.missing {
+            match entry.backend {
+                FirewallBackend::Ufw => Self::add_ufw_rule(&entry.rule).await?,
+                FirewallBackend::Iptables => Self::add_iptables_rule(&entry.rule).await?,
+            }
+            tracing::info!("Restored firewall rule for port {}", entry.rule.port);
+        }
+
+        // Deleting by number shifts the rules behind, so go from the back
+        audit.orphaned.sort_by_key(|rule| match rule.handle {
+            RuleHandle::UfwNumber(number) => std::cmp::Reverse(number),
+            RuleHandle::Iptables(_) => std::cmp::Reverse(0),
+        });
+        for orphan in &audit.orphaned {
+            match &orphan.handle {
+                RuleHandle::UfwNumber(number) => {
+                    let number = number.to_string();
+                    let output = Command::new("sudo")
+                        .args(["ufw", "--force", "delete", number.as_str()])
+                        .output()
+                        .await?;
+                    if !output.status.success() {
+                        return Err(NetworkError::FirewallError(
+                            String::from_utf8_lossy(&output.stderr).to_string(),
+                        ));
+                    }
+                }
+                RuleHandle::Iptables(spec) => {
+                    let spec: Vec<&str> = spec.iter().map(String::as_str).collect();
+                    Self::run_iptables_with("-D", &spec).await?;
+                }
+            }
+            tracing::info!(
+                "Removed orphaned firewall rule for port {}",
+                orphan.rule.port
+            );
+        }
+
+        Ok(audit)
+    }
+
+    async fn iptables_output(args: &[&str]) -> Result<String> {
+        let output = Command::new("sudo")
+            .arg("iptables")
+            .args(args)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            return Err(NetworkError::FirewallError(
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+/// The rules `entry` puts on the host: ufw adds incoming rules whatever the
+/// direction, iptables one rule per protocol
+fn expected_rules(entry: &LedgerEntry) -> Vec<FirewallRule> {
+    match (entry.backend, entry.rule.protocol) {
+        (FirewallBackend::Ufw, _) => vec![FirewallRule {
+            direction: Direction::In,
+            ..entry.rule.clone()
+        }],
+        (FirewallBackend::Iptables, Protocol::Both) => [Protocol::Tcp, Protocol::Udp]
+            .into_iter()
+            .map(|protocol| FirewallRule {
+                protocol,
+                ..entry.rule.clone()
+            })
+            .collect(),
+        (FirewallBackend::Iptables, _) => vec![entry.rule.clone()],
+    }
+}
+
+fn audit_rules(entries: &[LedgerEntry], host: &[HostRule]) -> FirewallAudit {
+    let expected: Vec<(FirewallBackend, FirewallRule)> = entries
+        .iter()
+        .flat_map(|entry| {
+            expected_rules(entry)
+                .into_iter()
+                .map(|rule| (entry.backend, rule))
+        })
+        .collect();
+
+    let on_host = |backend: FirewallBackend, rule: &FirewallRule| {
+        host.iter()
+            .any(|found| found.backend == backend && found.rule == *rule)
+    };
+
+    let mut missing: Vec<LedgerEntry> = Vec::new();
+    for entry in entries {
+        for rule in expected_rules(entry) {
+            let already_listed = missing
+                .iter()
+                .any(|listed| listed.backend == entry.backend && listed.rule == rule);
+            if !on_host(entry.backend, &rule) && !already_listed {
+                missing.push(LedgerEntry {
+                    rule,
+                    ..entry.clone()
+                });
+            }
+        }
+    }
+
+    let orphaned = host
+        .iter()
+        .filter(|found| found.rule.is_vpn_rule())
+        .filter(|found| {
+            !expected
+                .iter()
+                .any(|(backend, rule)| *backend == found.backend && *rule == found.rule)
+        })
+        .cloned()
+        .collect();
+
+    FirewallAudit { missing, orphaned }
+}
+
+/// Parse a line of `ufw status numbered`, e.g.
+/// `[ 2] 8443/tcp (v6)   ALLOW IN    Anywhere (v6)   # VPN Server`.
+/// Rules this crate does not create (port ranges, app profiles, interface
+/// rules) give `None`.
+fn parse_ufw_rule(line: &str) -> Option<HostRule> {
+    let line = line.trim_start().strip_prefix('[')?;
+    let (number, rest) = line.split_once(']')?;
+    let number = number.trim().parse().ok()?;
+
+    let (body, comment) = match rest.split_once(" # ") {
+        Some((body, comment)) => (body, Some(comment.trim().to_string())),
+        None => (rest, None),
+    };
+    let tokens: Vec<&str> = body
+        .split_whitespace()
+        .filter(|token| *token != "(v6)")
+        .collect();
+
+    let (port, protocol) = match tokens.first()?.split_once('/') {
+        Some((port, "tcp")) => (port, Protocol::Tcp),
+        Some((port, "udp")) => (port, Protocol::Udp),
+        Some(_) => return None,
+        None => (tokens[0], Protocol::Both),
+    };
+    let port = port.parse().ok()?;
+
+    if tokens.get(1) != Some(&"ALLOW") {
+        return None;
+    }
+    let (direction, from) = match tokens.get(2) {
+        Some(&"IN") => (Direction::In, &tokens[3..]),
+        Some(&"OUT") => (Direction::Out, &tokens[3..]),
+        _ => (Direction::In, &tokens[2..]),
+    };
+    let source = match from {
+        ["Anywhere"] => None,
+        [address] => Some(address.parse().ok()?),
+        _ => return None,
+    };
+
+    Some(HostRule {
+        backend: FirewallBackend::Ufw,
+        rule: FirewallRule {
+            port,
+            protocol,
+            direction,
+            source,
+            comment,
+        },
+        handle: RuleHandle::UfwNumber(number),
+    })
+}
+
+/// Parse a line of `iptables -S`, e.g.
+/// `-A INPUT -p tcp -m tcp --dport 8443 -m comment --comment "VPN Server" -j ACCEPT`
+fn parse_iptables_rule(line: &str) -> Option<HostRule> {
+    let tokens = split_iptables_args(line);
+    if tokens.first().map(String::as_str) != Some("-A") {
+        return None;
+    }
+
+    let direction = match tokens.get(1)?.as_str() {
+        "INPUT" => Direction::In,
+        "OUTPUT" => Direction::Out,
+        _ => return None,
+    };
+
+    let mut port = None;
+    let mut protocol = None;
+    let mut source = None;
+    let mut comment = None;
+    let mut target = None;
+    let mut args = tokens[2..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" => {
+                let address = args.next()?;
+                let address = address
+                    .strip_suffix("/32")
+                    .or_else(|| address.strip_suffix("/128"))
+                    .unwrap_or(address);
+                // Whole networks are not rules this crate creates
+                source = Some(address.parse::<IpAddr>().ok()?);
+            }
+            "-p" => {
+                protocol = match args.next()?.as_str() {
+                    "tcp" => Some(Protocol::Tcp),
+                    "udp" => Some(Protocol::Udp),
+                    _ => return None,
+                };
+            }
+            "--dport" => port = Some(args.next()?.parse().ok()?),
+            "--comment" => comment = Some(args.next()?.clone()),
+            "-j" => target = Some(args.next()?.clone()),
+            _ => {}
+        }
+    }
+
+    if target.as_deref() != Some("ACCEPT") {
+        return None;
+    }
+
+    Some(HostRule {
+        backend: FirewallBackend::Iptables,
+        rule: FirewallRule {
+            port: port?,
+            protocol: protocol?,
+            direction,
+            source,
+            comment,
+        },
+        handle: RuleHandle::Iptables(tokens[1..].to_vec()),
+    })
+}
+
+/// Split an `iptables -S` line into arguments, honouring double quotes
+fn split_iptables_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_arg = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+
+    args
+}
+
 impl Protocol {
     pub fn as_str(&self) -> &str {
         match self {
@@ -253,3 +850,182 @@ impl Direction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(port: u16, protocol: Protocol, comment: &str) -> FirewallRule {
+        FirewallRule {
+            port,
+            protocol,
+            direction: Direction::In,
+            source: None,
+            comment: Some(comment.to_string()),
+        }
+    }
+
+    fn entry(backend: FirewallBackend, rule: FirewallRule) -> LedgerEntry {
+        LedgerEntry {
+            backend,
+            rule,
+            added_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_tagged_marks_vpn_rules() {
+        let untagged = FirewallRule {
+            comment: None,
+            ..rule(8443, Protocol::Tcp, "")
+        };
+        assert_eq!(untagged.tagged().comment.as_deref(), Some("VPN"));
+        assert_eq!(
+            rule(8443, Protocol::Tcp, "custom")
+                .tagged()
+                .comment
+                .as_deref(),
+            Some("VPN: custom")
+        );
+
+        let tagged = rule(8443, Protocol::Tcp, "VPN Server");
+        assert_eq!(tagged.tagged(), tagged);
+        assert!(tagged.is_vpn_rule());
+        assert!(!untagged.is_vpn_rule());
+    }
+
+    #[test]
+    fn test_parse_ufw_rules() {
+        let found = parse_ufw_rule(
+            "[ 2] 8443                       ALLOW IN    Anywhere                   # VPN Server",
+        )
+        .unwrap();
+        assert_eq!(found.rule, rule(8443, Protocol::Both, "VPN Server"));
+        assert_eq!(found.handle, RuleHandle::UfwNumber(2));
+
+        let found = parse_ufw_rule("[ 5] 9000/udp (v6)             ALLOW IN    2001:db8::1                # VPN access gate").unwrap();
+        assert_eq!(found.rule.protocol, Protocol::Udp);
+        assert_eq!(found.rule.source, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(found.handle, RuleHandle::UfwNumber(5));
+
+        let found = parse_ufw_rule("[ 1] 22/tcp                     ALLOW IN    Anywhere").unwrap();
+        assert_eq!(found.rule.comment, None);
+
+        assert!(parse_ufw_rule("[ 3] OpenSSH                    ALLOW IN    Anywhere").is_none());
+        assert!(parse_ufw_rule("[ 4] 6000:6007/tcp              ALLOW IN    Anywhere").is_none());
+        assert!(parse_ufw_rule("[ 6] 80/tcp                     DENY IN     Anywhere").is_none());
+        assert!(parse_ufw_rule("Status: active").is_none());
+    }
+
+    #[test]
+    fn test_parse_iptables_rules() {
+        let found = parse_iptables_rule(
+            r#"-A INPUT -s 203.0.113.7/32 -p udp -m udp --dport 7000 -m comment --comment "VPN: knock \"a\"" -j ACCEPT"#,
+        )
+        .unwrap();
+        assert_eq!(found.rule.port, 7000);
+        assert_eq!(found.rule.protocol, Protocol::Udp);
+        assert_eq!(found.rule.source, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(found.rule.comment.as_deref(), Some("VPN: knock \"a\""));
+        assert_eq!(
+            found.handle,
+            RuleHandle::Iptables(
+                [
+                    "INPUT",
+                    "-s",
+                    "203.0.113.7/32",
+                    "-p",
+                    "udp",
+                    "-m",
+                    "udp",
+                    "--dport",
+                    "7000",
+                    "-m",
+                    "comment",
+                    "--comment",
+                    "VPN: knock \"a\"",
+                    "-j",
+                    "ACCEPT"
+                ]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect()
+            )
+        );
+
+        assert!(parse_iptables_rule("-P INPUT ACCEPT").is_none());
+        assert!(parse_iptables_rule("-A INPUT -j VPN-ACCESS-GATE").is_none());
+        assert!(
+            parse_iptables_rule("-A INPUT -s 10.0.0.0/8 -p tcp --dport 22 -j ACCEPT").is_none()
+        );
+    }
+
+    #[test]
+    fn test_iptables_specs_round_trip_through_parser() {
+        let expected = rule(8443, Protocol::Both, "VPN Server");
+        let specs = FirewallManager::iptables_rule_specs(&expected).unwrap();
+        assert_eq!(specs.len(), 2);
+
+        for spec in specs {
+            let quoted: Vec<String> = spec
+                .iter()
+                .map(|arg| {
+                    if arg.contains(' ') {
+                        format!("\"{}\"", arg)
+                    } else {
+                        arg.clone()
+                    }
+                })
+                .collect();
+            let found = parse_iptables_rule(&format!("-A {}", quoted.join(" "))).unwrap();
+            assert_eq!(found.rule.port, 8443);
+            assert_eq!(found.rule.comment, expected.comment);
+        }
+    }
+
+    #[test]
+    fn test_audit_finds_missing_and_orphaned_rules() {
+        let entries = vec![
+            entry(
+                FirewallBackend::Iptables,
+                rule(8443, Protocol::Both, "VPN Server"),
+            ),
+            entry(
+                FirewallBackend::Ufw,
+                rule(7000, Protocol::Udp, "VPN access gate"),
+            ),
+        ];
+        let host = vec![
+            HostRule {
+                backend: FirewallBackend::Iptables,
+                rule: rule(8443, Protocol::Tcp, "VPN Server"),
+                handle: RuleHandle::Iptables(Vec::new()),
+            },
+            // Custom port left behind by an earlier installation
+            HostRule {
+                backend: FirewallBackend::Ufw,
+                rule: rule(9443, Protocol::Both, "VPN Server"),
+                handle: RuleHandle::UfwNumber(3),
+            },
+            // Not ours
+            HostRule {
+                backend: FirewallBackend::Ufw,
+                rule: rule(22, Protocol::Tcp, "ssh"),
+                handle: RuleHandle::UfwNumber(1),
+            },
+        ];
+
+        let audit = audit_rules(&entries, &host);
+        assert!(!audit.is_clean());
+
+        let missing: Vec<(u16, Protocol)> = audit
+            .missing
+            .iter()
+            .map(|entry| (entry.rule.port, entry.rule.protocol))
+            .collect();
+        assert_eq!(missing, vec![(8443, Protocol::Udp), (7000, Protocol::Udp)]);
+
+        assert_eq!(audit.orphaned.len(), 1);
+        assert_eq!(audit.orphaned[0].rule.port, 9443);
+    }
+}
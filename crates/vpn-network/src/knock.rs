@@ -0,0 +1,473 @@
+//! Access gating for management ports
+//!
+//! Sensitive TCP ports (the Outline management API, the proxy admin API) sit
+//! behind the [`ACCESS_GATE_CHAIN`](crate::firewall::ACCESS_GATE_CHAIN)
+//! iptables chain, which drops everything by default. The [`AccessGate`]
+//! listener opens the ports for a single source address for a short time once
+//! that source has either knocked on a sequence of UDP ports or sent a valid
+//! single packet authorization (SPA) datagram.
+
+use crate::error::{NetworkError, Result};
+use crate::firewall::FirewallManager;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Largest datagram the listener reads
+const MAX_PACKET_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessGateConfig {
+    pub mode: KnockMode,
+    /// TCP ports kept closed until a source passes the gate
+    pub gated_ports: Vec<u16>,
+    /// How long a passing source may reach the gated ports
+    #[serde(default = "default_open_secs")]
+    pub open_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum KnockMode {
+    /// UDP datagrams to `ports` in order, each within `step_timeout_secs`
+    Sequence {
+        ports: Vec<u16>,
+        #[serde(default = "default_step_timeout_secs")]
+        step_timeout_secs: u64,
+    },
+    /// One UDP datagram to `port` signed with the shared `secret`
+    Spa {
+        port: u16,
+        secret: String,
+        #[serde(default = "default_max_skew_secs")]
+        max_skew_secs: u64,
+    },
+}
+
+fn default_open_secs() -> u64 {
+    30
+}
+
+fn default_step_timeout_secs() -> u64 {
+    10
+}
+
+fn default_max_skew_secs() -> u64 {
+    30
+}
+
+impl AccessGateConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.gated_ports.is_empty() {
+            return Err(NetworkError::AccessGateError(
+                "No ports to gate".to_string(),
+            ));
+        }
+
+        match &self.mode {
+            KnockMode::Sequence { ports, .. } => {
+                if ports.len() < 2 {
+                    return Err(NetworkError::AccessGateError(
+                        "Knock sequence needs at least two ports".to_string(),
+                    ));
+                }
+                if let Some(port) = ports.iter().find(|port| self.gated_ports.contains(port)) {
+                    return Err(NetworkError::AccessGateError(format!(
+                        "Knock port {} is also gated",
+                        port
+                    )));
+                }
+            }
+            KnockMode::Spa { port, secret, .. } => {
+                if secret.len() < 32 {
+                    return Err(NetworkError::AccessGateError(
+                        "SPA secret must be at least 32 characters".to_string(),
+                    ));
+                }
+                if self.gated_ports.contains(port) {
+                    return Err(NetworkError::AccessGateError(format!(
+                        "SPA port {} is also gated",
+                        port
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// UDP ports the listener needs open in the firewall
+    pub fn listen_ports(&self) -> Vec<u16> {
+        match &self.mode {
+            KnockMode::Sequence { ports, .. } => {
+                let mut ports = ports.clone();
+                ports.sort_unstable();
+                ports.dedup();
+                ports
+            }
+            KnockMode::Spa { port, .. } => vec![*port],
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let config: Self = serde_json::from_str(&content)
+            .map_err(|e| NetworkError::AccessGateError(format!("Invalid gate config: {}", e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| NetworkError::AccessGateError(e.to_string()))?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+/// Random hex secret for SPA mode
+pub fn generate_spa_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    hex::encode(secret)
+}
+
+/// Tracks each source's progress through a knock sequence
+pub struct KnockSequenceTracker {
+    sequence: Vec<u16>,
+    step_timeout: Duration,
+    progress: HashMap<IpAddr, (usize, Instant)>,
+}
+
+impl KnockSequenceTracker {
+    pub fn new(sequence: Vec<u16>, step_timeout: Duration) -> Self {
+        Self {
+            sequence,
+            step_timeout,
+            progress: HashMap::new(),
+        }
+    }
+
+    /// Record a knock; returns true when `source` completed the sequence
+    pub fn record(&mut self, source: IpAddr, port: u16, now: Instant) -> bool {
+        let step = match self.progress.get(&source) {
+            Some((step, last)) if now.duration_since(*last) <= self.step_timeout => *step,
+            _ => 0,
+        };
+
+        let next = if self.sequence.get(step) == Some(&port) {
+            step + 1
+        } else if self.sequence.first() == Some(&port) {
+            // A wrong knock restarts the sequence, possibly with this one
+            1
+        } else {
+            0
+        };
+
+        if next == self.sequence.len() {
+            self.progress.remove(&source);
+            return true;
+        }
+
+        if next == 0 {
+            self.progress.remove(&source);
+        } else {
+            self.progress.insert(source, (next, now));
+        }
+        false
+    }
+
+    /// Forget sources that stalled mid-sequence
+    pub fn prune(&mut self, now: Instant) {
+        let step_timeout = self.step_timeout;
+        self.progress
+            .retain(|_, (_, last)| now.duration_since(*last) <= step_timeout);
+    }
+}
+
+/// Build an SPA datagram: `timestamp:nonce:hex(hmac-sha256(timestamp:nonce))`
+pub fn build_spa_packet(secret: &str, timestamp: u64) -> String {
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let message = format!("{}:{}", timestamp, hex::encode(nonce));
+    format!("{}:{}", message, spa_signature(secret, &message))
+}
+
+fn spa_signature(secret: &str, message: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Verified SPA datagram
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpaToken {
+    pub timestamp: u64,
+    pub nonce: String,
+}
+
+/// Check an SPA datagram's signature and that it was sent within `max_skew`
+pub fn verify_spa_packet(secret: &str, packet: &str, now: u64, max_skew: u64) -> Result<SpaToken> {
+    let invalid = || NetworkError::AccessGateError("Malformed SPA packet".to_string());
+
+    let mut parts = packet.trim().splitn(3, ':');
+    let (timestamp, nonce, signature) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(timestamp), Some(nonce), Some(signature)) => (timestamp, nonce, signature),
+        _ => return Err(invalid()),
+    };
+    let timestamp: u64 = timestamp.parse().map_err(|_| invalid())?;
+    let signature = hex::decode(signature).map_err(|_| invalid())?;
+
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", timestamp, nonce).as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| NetworkError::AccessGateError("SPA signature mismatch".to_string()))?;
+
+    if timestamp.abs_diff(now) > max_skew {
+        return Err(NetworkError::AccessGateError(
+            "SPA packet outside the allowed time window".to_string(),
+        ));
+    }
+
+    Ok(SpaToken {
+        timestamp,
+        nonce: nonce.to_string(),
+    })
+}
+
+/// Nonces seen inside the SPA time window, so captured packets cannot be replayed
+pub struct SpaReplayCache {
+    max_skew: u64,
+    seen: HashMap<String, u64>,
+}
+
+impl SpaReplayCache {
+    pub fn new(max_skew: u64) -> Self {
+        Self {
+            max_skew,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Returns false if the token's nonce was already used
+    pub fn check_and_insert(&mut self, token: &SpaToken, now: u64) -> bool {
+        let max_skew = self.max_skew;
+        self.seen
+            .retain(|_, timestamp| timestamp.abs_diff(now) <= max_skew);
+        self.seen
+            .insert(token.nonce.clone(), token.timestamp)
+            .is_none()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Send a knock sequence or SPA datagram to `server`
+pub async fn knock(server: IpAddr, mode: &KnockMode) -> Result<()> {
+    let bind_addr: SocketAddr = match server {
+        IpAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+        IpAddr::V6(_) => "[::]:0".parse().unwrap(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+
+    match mode {
+        KnockMode::Sequence { ports, .. } => {
+            for port in ports {
+                socket.send_to(&[0u8], (server, *port)).await?;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+        KnockMode::Spa { port, secret, .. } => {
+            let packet = build_spa_packet(secret, unix_now());
+            socket.send_to(packet.as_bytes(), (server, *port)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Listener that opens the gated ports for sources passing the knock
+pub struct AccessGate {
+    config: AccessGateConfig,
+}
+
+impl AccessGate {
+    pub fn new(config: AccessGateConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+
+    pub fn config(&self) -> &AccessGateConfig {
+        &self.config
+    }
+
+    /// Listen for knocks until an I/O error occurs
+    pub async fn run(&self) -> Result<()> {
+        let (tx, mut rx) = mpsc::channel::<(IpAddr, u16, Vec<u8>)>(256);
+
+        for port in self.config.listen_ports() {
+            let socket = UdpSocket::bind(("0.0.0.0", port)).await?;
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; MAX_PACKET_SIZE];
+                while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                    if tx
+                        .send((peer.ip(), port, buf[..len].to_vec()))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        tracing::info!(
+            "Access gate listening on UDP {:?} for ports {:?}",
+            self.config.listen_ports(),
+            self.config.gated_ports
+        );
+
+        match &self.config.mode {
+            KnockMode::Sequence {
+                ports,
+                step_timeout_secs,
+            } => {
+                let mut tracker = KnockSequenceTracker::new(
+                    ports.clone(),
+                    Duration::from_secs(*step_timeout_secs),
+                );
+                while let Some((source, port, _)) = rx.recv().await {
+                    let now = Instant::now();
+                    tracker.prune(now);
+                    if tracker.record(source, port, now) {
+                        self.open_for(source).await;
+                    }
+                }
+            }
+            KnockMode::Spa {
+                secret,
+                max_skew_secs,
+                ..
+            } => {
+                let mut replay_cache = SpaReplayCache::new(*max_skew_secs);
+                while let Some((source, _, packet)) = rx.recv().await {
+                    let now = unix_now();
+                    let packet = String::from_utf8_lossy(&packet);
+                    match verify_spa_packet(secret, &packet, now, *max_skew_secs) {
+                        Ok(token) if replay_cache.check_and_insert(&token, now) => {
+                            self.open_for(source).await;
+                        }
+                        Ok(_) => tracing::warn!("Replayed SPA packet from {}", source),
+                        Err(e) => tracing::debug!("Rejected SPA packet from {}: {}", source, e),
+                    }
+                }
+            }
+        }
+
+        Err(NetworkError::AccessGateError(
+            "Knock listeners stopped".to_string(),
+        ))
+    }
+
+    async fn open_for(&self, source: IpAddr) {
+        let ports = self.config.gated_ports.clone();
+        if let Err(e) = FirewallManager::allow_gate_source(source, &ports).await {
+            tracing::error!("Failed to open access gate for {}: {}", source, e);
+            return;
+        }
+        tracing::info!(
+            "Opened access gate for {} for {}s",
+            source,
+            self.config.open_secs
+        );
+
+        let open_for = Duration::from_secs(self.config.open_secs);
+        tokio::spawn(async move {
+            tokio::time::sleep(open_for).await;
+            if let Err(e) = FirewallManager::revoke_gate_source(source, &ports).await {
+                tracing::error!("Failed to close access gate for {}: {}", source, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> IpAddr {
+        "203.0.113.7".parse().unwrap()
+    }
+
+    #[test]
+    fn test_knock_sequence_completes_in_order() {
+        let mut tracker = KnockSequenceTracker::new(vec![7000, 8000, 9000], Duration::from_secs(5));
+        let now = Instant::now();
+
+        assert!(!tracker.record(source(), 7000, now));
+        assert!(!tracker.record(source(), 8000, now));
+        assert!(tracker.record(source(), 9000, now));
+
+        // Out of order or stale knocks start over
+        assert!(!tracker.record(source(), 8000, now));
+        assert!(!tracker.record(source(), 7000, now));
+        assert!(!tracker.record(source(), 9000, now));
+        assert!(!tracker.record(source(), 7000, now));
+        assert!(!tracker.record(source(), 8000, now + Duration::from_secs(10)));
+        assert!(!tracker.record(source(), 9000, now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_spa_packet_roundtrip_and_replay() {
+        let secret = generate_spa_secret();
+        let now = 1_700_000_000;
+        let packet = build_spa_packet(&secret, now);
+
+        let token = verify_spa_packet(&secret, &packet, now + 5, 30).unwrap();
+        assert_eq!(token.timestamp, now);
+        assert!(verify_spa_packet(&secret, &packet, now + 60, 30).is_err());
+        assert!(verify_spa_packet(&generate_spa_secret(), &packet, now, 30).is_err());
+        assert!(verify_spa_packet(&secret, "garbage", now, 30).is_err());
+
+        let mut cache = SpaReplayCache::new(30);
+        assert!(cache.check_and_insert(&token, now));
+        assert!(!cache.check_and_insert(&token, now));
+    }
+
+    #[test]
+    fn test_config_rejects_knocking_on_gated_port() {
+        let config = AccessGateConfig {
+            mode: KnockMode::Sequence {
+                ports: vec![7000, 8080],
+                step_timeout_secs: 10,
+            },
+            gated_ports: vec![8080],
+            open_secs: 30,
+        };
+        assert!(config.validate().is_err());
+
+        let json = r#"{"mode":{"type":"spa","port":62201,"secret":"0123456789abcdef0123456789abcdef"},"gated_ports":[8080]}"#;
+        let config: AccessGateConfig = serde_json::from_str(json).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.open_secs, 30);
+        assert_eq!(config.listen_ports(), vec![62201]);
+    }
+}
@@ -0,0 +1,152 @@
+//! Record of the firewall rules the VPN created on this host
+//!
+//! The ledger lives outside the installation directory so it survives a
+//! failed or partial uninstall. It is the desired state for
+//! [`FirewallManager::reconcile`](crate::firewall::FirewallManager::reconcile):
+//! rules in the ledger are kept on the host, and VPN rules on the host that
+//! are not in the ledger are removed.
+
+use crate::error::{NetworkError, Result};
+use crate::firewall::{FirewallBackend, FirewallRule};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the ledger is kept unless a caller picks another path
+pub const DEFAULT_LEDGER_PATH: &str = "/var/lib/vpn/firewall-rules.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// Tool the rule was added with
+    pub backend: FirewallBackend,
+    pub rule: FirewallRule,
+    /// Unix time the rule was recorded
+    pub added_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FirewallLedger {
+    #[serde(skip)]
+    path: PathBuf,
+    entries: Vec<LedgerEntry>,
+}
+
+impl FirewallLedger {
+    /// Load the ledger at `path`; a missing file is an empty ledger
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut ledger = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str::<Self>(&content).map_err(|e| {
+                NetworkError::FirewallError(format!(
+                    "Invalid firewall ledger {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+        ledger.path = path.to_path_buf();
+        Ok(ledger)
+    }
+
+    /// Load the ledger at [`DEFAULT_LEDGER_PATH`]
+    pub fn load_default() -> Result<Self> {
+        Self::load(Path::new(DEFAULT_LEDGER_PATH))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the ledger, replacing the file atomically
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| NetworkError::FirewallError(e.to_string()))?;
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Record `rule` as added with `backend`. Returns false if it was
+    /// already recorded.
+    pub fn record(&mut self, backend: FirewallBackend, rule: &FirewallRule) -> bool {
+        if self.contains(backend, rule) {
+            return false;
+        }
+
+        let added_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        self.entries.push(LedgerEntry {
+            backend,
+            rule: rule.clone(),
+            added_at,
+        });
+        true
+    }
+
+    /// Drop `rule` from the ledger, returning whether it was recorded
+    pub fn forget(&mut self, backend: FirewallBackend, rule: &FirewallRule) -> bool {
+        let before = self.entries.len();
+        self.entries
+            .retain(|entry| entry.backend != backend || entry.rule != *rule);
+        self.entries.len() != before
+    }
+
+    pub fn contains(&self, backend: FirewallBackend, rule: &FirewallRule) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.backend == backend && entry.rule == *rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::{Direction, Protocol};
+
+    fn rule(port: u16) -> FirewallRule {
+        FirewallRule {
+            port,
+            protocol: Protocol::Tcp,
+            direction: Direction::In,
+            source: None,
+            comment: Some("VPN Server".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_ledger_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("firewall-rules.json");
+
+        let mut ledger = FirewallLedger::load(&path).unwrap();
+        assert!(ledger.is_empty());
+
+        assert!(ledger.record(FirewallBackend::Ufw, &rule(8443)));
+        assert!(!ledger.record(FirewallBackend::Ufw, &rule(8443)));
+        assert!(ledger.record(FirewallBackend::Iptables, &rule(8443)));
+        ledger.save().unwrap();
+
+        let mut loaded = FirewallLedger::load(&path).unwrap();
+        assert_eq!(loaded.entries(), ledger.entries());
+
+        assert!(loaded.forget(FirewallBackend::Ufw, &rule(8443)));
+        assert!(!loaded.forget(FirewallBackend::Ufw, &rule(8443)));
+        assert!(loaded.contains(FirewallBackend::Iptables, &rule(8443)));
+    }
+}
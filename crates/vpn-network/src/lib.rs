@@ -1,18 +1,24 @@
 pub mod error;
 pub mod firewall;
 pub mod ip;
+pub mod knock;
+pub mod ledger;
 pub mod manager;
 pub mod port;
 pub mod sni;
 pub mod subnet;
+pub mod tuning;
 
 #[cfg(test)]
 pub mod proptest;
 
 pub use error::{NetworkError, Result};
-pub use firewall::{FirewallManager, FirewallRule};
+pub use firewall::{FirewallAudit, FirewallBackend, FirewallManager, FirewallRule, HostRule};
 pub use ip::IpDetector;
+pub use knock::{AccessGate, AccessGateConfig, KnockMode};
+pub use ledger::{FirewallLedger, LedgerEntry};
 pub use manager::{NetworkInterface, NetworkInterfaceType, NetworkManager};
 pub use port::{PortChecker, PortStatus};
 pub use sni::SniValidator;
 pub use subnet::{SubnetManager, VpnSubnet};
+pub use tuning::{NetworkTuner, TuningProfile};
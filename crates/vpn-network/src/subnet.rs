@@ -1,14 +1,14 @@
 use crate::error::{NetworkError, Result};
 use std::collections::HashSet;
 use std::io::{self, Write};
-use std::process::Command;
+use vpn_docker::NetworkManager;
 
 pub struct SubnetManager;
 
 impl SubnetManager {
     /// Get list of available VPN subnet ranges that don't conflict with existing networks
-    pub fn get_available_subnets() -> Result<Vec<VpnSubnet>> {
-        let used_subnets = Self::get_used_docker_subnets()?;
+    pub async fn get_available_subnets() -> Result<Vec<VpnSubnet>> {
+        let used_subnets = Self::get_used_docker_subnets().await?;
         let candidate_subnets = Self::get_candidate_subnets();
 
         let available = candidate_subnets
@@ -62,65 +62,12 @@ impl SubnetManager {
     }
 
     /// Get currently used Docker subnets
-    fn get_used_docker_subnets() -> Result<HashSet<String>> {
-        let output = Command::new("docker")
-            .arg("network")
-            .arg("ls")
-            .arg("--format")
-            .arg("{{.ID}}")
-            .output()
-            .map_err(|e| {
-                NetworkError::CommandError(format!("Failed to list Docker networks: {}", e))
-            })?;
-
-        if !output.status.success() {
-            return Err(NetworkError::CommandError(
-                "Docker network ls failed".to_string(),
-            ));
-        }
-
-        let network_ids = String::from_utf8_lossy(&output.stdout);
-        let mut used_subnets = HashSet::new();
-
-        for network_id in network_ids.lines() {
-            if network_id.trim().is_empty() {
-                continue;
-            }
-
-            // Get network details
-            let inspect_output = Command::new("docker")
-                .arg("network")
-                .arg("inspect")
-                .arg(network_id.trim())
-                .output();
-
-            if let Ok(inspect_result) = inspect_output {
-                let network_info = String::from_utf8_lossy(&inspect_result.stdout);
-
-                // Extract subnet information from JSON
-                if let Ok(network_data) = serde_json::from_str::<serde_json::Value>(&network_info) {
-                    if let Some(networks) = network_data.as_array() {
-                        for network in networks {
-                            if let Some(ipam) = network.get("IPAM") {
-                                if let Some(config) = ipam.get("Config") {
-                                    if let Some(config_array) = config.as_array() {
-                                        for config_item in config_array {
-                                            if let Some(subnet) = config_item.get("Subnet") {
-                                                if let Some(subnet_str) = subnet.as_str() {
-                                                    used_subnets.insert(subnet_str.to_string());
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(used_subnets)
+    async fn get_used_docker_subnets() -> Result<HashSet<String>> {
+        let networks = NetworkManager::new()?.list_networks().await?;
+        Ok(networks
+            .into_iter()
+            .flat_map(|network| network.subnets)
+            .collect())
     }
 
     /// Check if candidate subnet conflicts with used subnets
@@ -167,10 +114,10 @@ impl SubnetManager {
     }
 
     /// Interactive subnet selection for user
-    pub fn select_subnet_interactive() -> Result<VpnSubnet> {
+    pub async fn select_subnet_interactive() -> Result<VpnSubnet> {
         println!("🔍 Detecting available VPN subnets...");
 
-        let available_subnets = Self::get_available_subnets()?;
+        let available_subnets = Self::get_available_subnets().await?;
 
         if available_subnets.is_empty() {
             return Err(NetworkError::NoAvailableSubnets);
@@ -215,8 +162,8 @@ impl SubnetManager {
     }
 
     /// Automatically select best available subnet (non-interactive)
-    pub fn select_subnet_auto() -> Result<VpnSubnet> {
-        let available_subnets = Self::get_available_subnets()?;
+    pub async fn select_subnet_auto() -> Result<VpnSubnet> {
+        let available_subnets = Self::get_available_subnets().await?;
 
         if available_subnets.is_empty() {
             return Err(NetworkError::NoAvailableSubnets);
@@ -227,8 +174,8 @@ impl SubnetManager {
     }
 
     /// Check if a specific subnet is available
-    pub fn is_subnet_available(subnet: &str) -> Result<bool> {
-        let used_subnets = Self::get_used_docker_subnets()?;
+    pub async fn is_subnet_available(subnet: &str) -> Result<bool> {
+        let used_subnets = Self::get_used_docker_subnets().await?;
         Ok(!Self::conflicts_with_used(subnet, &used_subnets))
     }
 }
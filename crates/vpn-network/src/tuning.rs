@@ -0,0 +1,473 @@
+//! Kernel network tuning profiles
+//!
+//! A profile is a set of sysctls suited to a workload. Applying one writes
+//! the values under `/proc/sys`, persists them in a sysctl.d drop-in so
+//! they survive a reboot, and records the values they replaced so
+//! [`NetworkTuner::revert`] can restore the host as it was.
+
+use crate::error::{NetworkError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the replaced values are kept unless a caller picks another path
+pub const DEFAULT_TUNING_STATE_PATH: &str = "/var/lib/vpn/network-tuning.json";
+
+/// Drop-in re-applying the profile at boot
+pub const DEFAULT_SYSCTL_DROP_IN: &str = "/etc/sysctl.d/99-vpn-tuning.conf";
+
+const SYSCTL_ROOT: &str = "/proc/sys";
+const CONGESTION_CONTROL: &str = "net.ipv4.tcp_congestion_control";
+const AVAILABLE_CONGESTION_CONTROL: &str = "net.ipv4.tcp_available_congestion_control";
+
+/// Settings shown by [`NetworkTuner::report`] whether or not a profile is
+/// applied
+pub const REPORTED_SETTINGS: &[&str] = &[
+    CONGESTION_CONTROL,
+    "net.core.default_qdisc",
+    "net.ipv4.tcp_fastopen",
+    "net.core.rmem_max",
+    "net.core.wmem_max",
+    "net.core.somaxconn",
+];
+
+/// Workload a host is tuned for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TuningProfile {
+    /// Many short TCP connections through the HTTP/SOCKS5 proxy or Xray
+    Proxy,
+    /// Few long-lived UDP tunnels carrying bulk traffic
+    Wireguard,
+}
+
+impl TuningProfile {
+    /// Sysctls the profile sets, as (key, value)
+    pub fn settings(self) -> Vec<(&'static str, &'static str)> {
+        let mut settings = vec![
+            ("net.core.default_qdisc", "fq"),
+            (CONGESTION_CONTROL, "bbr"),
+            ("net.ipv4.tcp_fastopen", "3"),
+            ("net.ipv4.tcp_mtu_probing", "1"),
+        ];
+
+        match self {
+            Self::Proxy => settings.extend([
+                ("net.core.somaxconn", "65535"),
+                ("net.ipv4.tcp_max_syn_backlog", "65535"),
+                ("net.core.rmem_max", "16777216"),
+                ("net.core.wmem_max", "16777216"),
+                ("net.ipv4.tcp_rmem", "4096 87380 16777216"),
+                ("net.ipv4.tcp_wmem", "4096 65536 16777216"),
+                ("net.ipv4.ip_local_port_range", "1024 65535"),
+                ("net.ipv4.tcp_fin_timeout", "15"),
+                ("net.ipv4.tcp_tw_reuse", "1"),
+                ("net.ipv4.tcp_slow_start_after_idle", "0"),
+            ]),
+            Self::Wireguard => settings.extend([
+                ("net.core.somaxconn", "4096"),
+                ("net.core.rmem_max", "26214400"),
+                ("net.core.wmem_max", "26214400"),
+                ("net.core.rmem_default", "1048576"),
+                ("net.core.wmem_default", "1048576"),
+                ("net.core.netdev_max_backlog", "16384"),
+                ("net.ipv4.ip_forward", "1"),
+                ("net.ipv6.conf.all.forwarding", "1"),
+            ]),
+        }
+
+        settings
+    }
+}
+
+impl fmt::Display for TuningProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Proxy => write!(f, "proxy"),
+            Self::Wireguard => write!(f, "wireguard"),
+        }
+    }
+}
+
+impl FromStr for TuningProfile {
+    type Err = NetworkError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "proxy" => Ok(Self::Proxy),
+            "wireguard" => Ok(Self::Wireguard),
+            other => Err(NetworkError::TuningError(format!(
+                "Unknown tuning profile: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// What is recorded while a profile is applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TuningState {
+    pub profile: TuningProfile,
+    /// Unix time the profile was applied
+    pub applied_at: u64,
+    /// Values the profile replaced, to restore on revert. Kept from the
+    /// first profile applied when switching profiles.
+    pub original: BTreeMap<String, String>,
+}
+
+/// Outcome of applying a profile
+#[derive(Debug, Clone, Default)]
+pub struct TuningOutcome {
+    /// Settings written, as (key, previous value, new value)
+    pub changed: Vec<(String, String, String)>,
+    /// Settings this kernel does not support, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+/// A reported setting and what the applied profile wants it to be
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingReport {
+    pub key: String,
+    /// None if the kernel does not have the setting
+    pub current: Option<String>,
+    /// None unless a profile setting it is applied
+    pub recommended: Option<String>,
+}
+
+impl SettingReport {
+    /// Whether the setting has drifted from the applied profile
+    pub fn drifted(&self) -> bool {
+        match (&self.current, &self.recommended) {
+            (Some(current), Some(recommended)) => current != recommended,
+            _ => false,
+        }
+    }
+}
+
+/// Tuning report for `vpn info`
+#[derive(Debug, Clone)]
+pub struct TuningReport {
+    pub state: Option<TuningState>,
+    pub settings: Vec<SettingReport>,
+}
+
+/// Applies and reverts tuning profiles on this host
+pub struct NetworkTuner {
+    sysctl_root: PathBuf,
+    state_path: PathBuf,
+    drop_in: PathBuf,
+}
+
+impl Default for NetworkTuner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkTuner {
+    /// Tuner for the running kernel with the default state and drop-in paths
+    pub fn new() -> Self {
+        Self::with_paths(
+            SYSCTL_ROOT,
+            DEFAULT_TUNING_STATE_PATH,
+            DEFAULT_SYSCTL_DROP_IN,
+        )
+    }
+
+    pub fn with_paths(
+        sysctl_root: impl Into<PathBuf>,
+        state_path: impl Into<PathBuf>,
+        drop_in: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            sysctl_root: sysctl_root.into(),
+            state_path: state_path.into(),
+            drop_in: drop_in.into(),
+        }
+    }
+
+    /// Current value of a sysctl; None if the kernel does not have it
+    pub fn read(&self, key: &str) -> Result<Option<String>> {
+        match std::fs::read_to_string(self.sysctl_path(key)) {
+            Ok(value) => Ok(Some(normalize(&value))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The applied profile, if any
+    pub fn state(&self) -> Result<Option<TuningState>> {
+        match std::fs::read_to_string(&self.state_path) {
+            Ok(content) => serde_json::from_str(&content).map(Some).map_err(|e| {
+                NetworkError::TuningError(format!(
+                    "Invalid tuning state {}: {}",
+                    self.state_path.display(),
+                    e
+                ))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Apply `profile`, replacing any profile applied before. Settings the
+    /// kernel lacks, and BBR where it is not available, are skipped.
+    pub fn apply(&self, profile: TuningProfile) -> Result<TuningOutcome> {
+        let mut state = self.state()?.unwrap_or_else(|| TuningState {
+            profile,
+            applied_at: 0,
+            original: BTreeMap::new(),
+        });
+        let previous_keys: Vec<String> = state.original.keys().cloned().collect();
+        let settings = profile.settings();
+
+        // Restore what the previous profile set and this one does not
+        for key in previous_keys {
+            if !settings.iter().any(|(k, _)| *k == key) {
+                if let Some(value) = state.original.remove(&key) {
+                    self.write(&key, &value)?;
+                }
+            }
+        }
+
+        let mut outcome = TuningOutcome::default();
+        let mut persisted = Vec::new();
+        for (key, value) in settings {
+            let Some(current) = self.read(key)? else {
+                outcome
+                    .skipped
+                    .push((key.to_string(), "not supported by this kernel".to_string()));
+                continue;
+            };
+            if key == CONGESTION_CONTROL && !self.congestion_control_available(value)? {
+                outcome
+                    .skipped
+                    .push((key.to_string(), format!("{} is not available", value)));
+                continue;
+            }
+
+            state
+                .original
+                .entry(key.to_string())
+                .or_insert_with(|| current.clone());
+            if current != normalize(value) {
+                self.write(key, value)?;
+                outcome
+                    .changed
+                    .push((key.to_string(), current, value.to_string()));
+            }
+            persisted.push((key, value));
+        }
+
+        state.profile = profile;
+        state.applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        self.save_state(&state)?;
+        self.write_drop_in(profile, &persisted)?;
+
+        Ok(outcome)
+    }
+
+    /// Restore the values replaced by the applied profile. Returns the
+    /// profile that was reverted, or None if none was applied.
+    pub fn revert(&self) -> Result<Option<TuningProfile>> {
+        let Some(state) = self.state()? else {
+            return Ok(None);
+        };
+
+        for (key, value) in &state.original {
+            self.write(key, value)?;
+        }
+        remove_if_exists(&self.drop_in)?;
+        remove_if_exists(&self.state_path)?;
+
+        Ok(Some(state.profile))
+    }
+
+    /// Applied profile and the current value of the reported settings,
+    /// plus any others the profile sets
+    pub fn report(&self) -> Result<TuningReport> {
+        let state = self.state()?;
+        let profile_settings = state
+            .as_ref()
+            .map(|state| state.profile.settings())
+            .unwrap_or_default();
+
+        let mut keys: Vec<&str> = REPORTED_SETTINGS.to_vec();
+        for (key, _) in &profile_settings {
+            if !keys.contains(key) {
+                keys.push(key);
+            }
+        }
+
+        let settings = keys
+            .into_iter()
+            .map(|key| {
+                Ok(SettingReport {
+                    key: key.to_string(),
+                    current: self.read(key)?,
+                    recommended: profile_settings
+                        .iter()
+                        .find(|(k, _)| *k == key)
+                        .map(|(_, value)| normalize(value)),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(TuningReport { state, settings })
+    }
+
+    fn congestion_control_available(&self, algorithm: &str) -> Result<bool> {
+        Ok(self
+            .read(AVAILABLE_CONGESTION_CONTROL)?
+            .is_some_and(|available| available.split(' ').any(|a| a == algorithm)))
+    }
+
+    fn write(&self, key: &str, value: &str) -> Result<()> {
+        std::fs::write(self.sysctl_path(key), value).map_err(|e| {
+            NetworkError::TuningError(format!("Failed to set {} = {}: {}", key, value, e))
+        })
+    }
+
+    fn save_state(&self, state: &TuningState) -> Result<()> {
+        if let Some(parent) = self.state_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let content = serde_json::to_string_pretty(state)
+            .map_err(|e| NetworkError::TuningError(e.to_string()))?;
+        let tmp = self.state_path.with_extension("json.tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.state_path)?;
+        Ok(())
+    }
+
+    fn write_drop_in(&self, profile: TuningProfile, settings: &[(&str, &str)]) -> Result<()> {
+        if let Some(parent) = self.drop_in.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut content = format!(
+            "# Written by `vpn tune apply {}`; removed by `vpn tune revert`\n",
+            profile
+        );
+        for (key, value) in settings {
+            content.push_str(&format!("{} = {}\n", key, value));
+        }
+        std::fs::write(&self.drop_in, content)?;
+        Ok(())
+    }
+
+    fn sysctl_path(&self, key: &str) -> PathBuf {
+        self.sysctl_root.join(key.replace('.', "/"))
+    }
+}
+
+/// Sysctl values with whitespace collapsed, as the kernel separates
+/// multi-value settings with tabs
+fn normalize(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn tuner(dir: &TempDir, sysctls: &[(&str, &str)]) -> NetworkTuner {
+        let root = dir.path().join("proc");
+        for (key, value) in sysctls {
+            let path = root.join(key.replace('.', "/"));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, format!("{}\n", value)).unwrap();
+        }
+        NetworkTuner::with_paths(
+            root,
+            dir.path().join("state/network-tuning.json"),
+            dir.path().join("sysctl.d/99-vpn-tuning.conf"),
+        )
+    }
+
+    #[test]
+    fn test_apply_and_revert() {
+        let dir = TempDir::new().unwrap();
+        let tuner = tuner(
+            &dir,
+            &[
+                (CONGESTION_CONTROL, "cubic"),
+                (AVAILABLE_CONGESTION_CONTROL, "reno cubic bbr"),
+                ("net.core.somaxconn", "4096"),
+                ("net.ipv4.tcp_rmem", "4096\t131072\t6291456"),
+                ("net.core.rmem_default", "212992"),
+            ],
+        );
+
+        let outcome = tuner.apply(TuningProfile::Proxy).unwrap();
+        assert_eq!(outcome.changed.len(), 3);
+        assert!(outcome
+            .skipped
+            .iter()
+            .any(|(key, _)| key == "net.core.default_qdisc"));
+        assert_eq!(tuner.read(CONGESTION_CONTROL).unwrap().unwrap(), "bbr");
+        assert_eq!(
+            tuner.read("net.ipv4.tcp_rmem").unwrap().unwrap(),
+            "4096 87380 16777216"
+        );
+
+        // Switching keeps the original values and restores settings the
+        // new profile does not touch
+        tuner.apply(TuningProfile::Wireguard).unwrap();
+        assert_eq!(
+            tuner.read("net.ipv4.tcp_rmem").unwrap().unwrap(),
+            "4096 131072 6291456"
+        );
+        let state = tuner.state().unwrap().unwrap();
+        assert_eq!(state.profile, TuningProfile::Wireguard);
+        assert_eq!(state.original["net.core.somaxconn"], "4096");
+        let report = tuner.report().unwrap();
+        assert!(report.settings.iter().all(|s| !s.drifted()));
+
+        assert_eq!(tuner.revert().unwrap(), Some(TuningProfile::Wireguard));
+        assert_eq!(tuner.read(CONGESTION_CONTROL).unwrap().unwrap(), "cubic");
+        assert_eq!(tuner.read("net.core.somaxconn").unwrap().unwrap(), "4096");
+        assert_eq!(
+            tuner.read("net.core.rmem_default").unwrap().unwrap(),
+            "212992"
+        );
+        assert!(tuner.state().unwrap().is_none());
+        assert!(!dir.path().join("sysctl.d/99-vpn-tuning.conf").exists());
+        assert_eq!(tuner.revert().unwrap(), None);
+    }
+
+    #[test]
+    fn test_bbr_skipped_when_unavailable() {
+        let dir = TempDir::new().unwrap();
+        let tuner = tuner(
+            &dir,
+            &[
+                (CONGESTION_CONTROL, "cubic"),
+                (AVAILABLE_CONGESTION_CONTROL, "reno cubic"),
+            ],
+        );
+
+        let outcome = tuner.apply(TuningProfile::Wireguard).unwrap();
+        assert!(outcome.changed.is_empty());
+        assert!(outcome
+            .skipped
+            .iter()
+            .any(|(key, reason)| key == CONGESTION_CONTROL && reason.contains("bbr")));
+        assert_eq!(tuner.read(CONGESTION_CONTROL).unwrap().unwrap(), "cubic");
+    }
+}
@@ -0,0 +1,149 @@
+//! Hot reload of operator configuration from a ConfigMap
+
+use crate::{
+    error::{OperatorError, Result},
+    OperatorConfig,
+};
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::Api,
+    client::Client,
+    runtime::{watcher, WatchStreamExt},
+};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// ConfigMap key holding the operator configuration
+pub const CONFIG_KEY: &str = "config.yaml";
+
+/// Watches the operator ConfigMap and publishes configuration changes
+pub struct ConfigWatcher {
+    /// Kubernetes client
+    client: Client,
+    /// Namespace of the ConfigMap
+    namespace: String,
+    /// Name of the ConfigMap
+    name: String,
+    /// Channel the controller receives configuration from
+    sender: Arc<watch::Sender<OperatorConfig>>,
+}
+
+impl ConfigWatcher {
+    /// Create a new config watcher
+    pub fn new(
+        client: Client,
+        namespace: impl Into<String>,
+        name: impl Into<String>,
+        sender: Arc<watch::Sender<OperatorConfig>>,
+    ) -> Self {
+        Self {
+            client,
+            namespace: namespace.into(),
+            name: name.into(),
+            sender,
+        }
+    }
+
+    /// Run the watcher until the watch stream ends
+    pub async fn run(&self) -> Result<()> {
+        tracing::info!(
+            "Watching ConfigMap {}/{} for configuration changes",
+            self.namespace,
+            self.name
+        );
+
+        let api = Api::<ConfigMap>::namespaced(self.client.clone(), &self.namespace);
+        let config = watcher::Config::default().fields(&format!("metadata.name={}", self.name));
+
+        let mut stream = watcher(api, config)
+            .default_backoff()
+            .applied_objects()
+            .boxed();
+
+        while let Some(event) = stream.next().await {
+            match event {
+                Ok(config_map) => self.apply(&config_map),
+                Err(e) => tracing::warn!("ConfigMap watch error: {}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publish the configuration from a ConfigMap if it is valid and changed
+    fn apply(&self, config_map: &ConfigMap) {
+        let config = match parse_config_map(config_map) {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::error!(
+                    "Ignoring invalid configuration in ConfigMap {}/{}: {}",
+                    self.namespace,
+                    self.name,
+                    e
+                );
+                return;
+            }
+        };
+
+        let changed = self.sender.send_if_modified(|current| {
+            if *current == config {
+                false
+            } else {
+                *current = config;
+                true
+            }
+        });
+
+        if changed {
+            tracing::info!(
+                "Reloaded operator configuration from ConfigMap {}/{}",
+                self.namespace,
+                self.name
+            );
+        }
+    }
+}
+
+/// Parse the operator configuration stored in a ConfigMap
+pub fn parse_config_map(config_map: &ConfigMap) -> Result<OperatorConfig> {
+    let yaml = config_map
+        .data
+        .as_ref()
+        .and_then(|data| data.get(CONFIG_KEY))
+        .ok_or_else(|| {
+            OperatorError::config(format!("ConfigMap is missing the {} key", CONFIG_KEY))
+        })?;
+
+    OperatorConfig::from_yaml(yaml)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn config_map(data: Option<&str>) -> ConfigMap {
+        ConfigMap {
+            data: data.map(|yaml| BTreeMap::from([(CONFIG_KEY.to_string(), yaml.to_string())])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_config_map() {
+        let config = parse_config_map(&config_map(Some(
+            "vpnImage: vpn-server:v2\nenableHA: true\n",
+        )))
+        .unwrap();
+
+        assert_eq!(config.vpn_image, "vpn-server:v2");
+        assert!(config.enable_ha);
+    }
+
+    #[test]
+    fn test_parse_config_map_missing_key() {
+        assert!(parse_config_map(&config_map(None)).is_err());
+        assert!(parse_config_map(&config_map(Some("defaultProtocol: ipsec\n"))).is_err());
+    }
+}
@@ -18,13 +18,14 @@ use kube::{
     ResourceExt,
 };
 use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
 
 /// Controller for managing VPN resources
 pub struct VpnOperatorController {
     /// Kubernetes client
     client: Client,
-    /// Operator configuration
-    config: OperatorConfig,
+    /// Operator configuration, updated on hot reload
+    config: watch::Receiver<OperatorConfig>,
     /// Reconciler for VPN resources
     _reconciler: Arc<VpnReconciler>,
     /// Event recorder
@@ -34,7 +35,14 @@ pub struct VpnOperatorController {
 /// Controller context passed to reconciliation
 pub struct Context {
     pub client: Client,
-    pub config: OperatorConfig,
+    pub config: watch::Receiver<OperatorConfig>,
+}
+
+impl Context {
+    /// Snapshot of the current operator configuration
+    pub fn current_config(&self) -> OperatorConfig {
+        self.config.borrow().clone()
+    }
 }
 
 /// Finalizer name for VPN resources
@@ -42,8 +50,8 @@ const FINALIZER_NAME: &str = "vpnservers.vpn.io/finalizer";
 
 impl VpnOperatorController {
     /// Create a new controller
-    pub async fn new(client: Client, config: OperatorConfig) -> Result<Self> {
-        let reconciler = Arc::new(VpnReconciler::new(client.clone(), config.clone()));
+    pub async fn new(client: Client, config: watch::Receiver<OperatorConfig>) -> Result<Self> {
+        let reconciler = Arc::new(VpnReconciler::new(client.clone(), config.borrow().clone()));
 
         // Create event recorder
         let reporter = Reporter {
@@ -65,32 +73,65 @@ impl VpnOperatorController {
     }
 
     /// Run the controller
+    ///
+    /// The controller is restarted whenever a configuration reload changes
+    /// the watched namespace.
     pub async fn run(&self) -> Result<()> {
-        tracing::info!("Starting VPN operator controller");
-
-        let api = match &self.config.namespace {
-            Some(ns) => Api::<VpnServer>::namespaced(self.client.clone(), ns),
-            None => Api::<VpnServer>::all(self.client.clone()),
-        };
-
-        let context = Arc::new(Context {
-            client: self.client.clone(),
-            config: self.config.clone(),
-        });
-
-        Controller::new(api.clone(), kube::runtime::watcher::Config::default())
-            .run(Self::reconcile, Self::error_policy, context)
-            .for_each(|res| async {
-                match res {
-                    Ok(o) => tracing::debug!("Reconciled {:?}", o),
-                    Err(e) => tracing::error!("Reconciliation error: {:?}", e),
-                }
-            })
-            .await;
+        let mut config = self.config.clone();
+
+        loop {
+            let namespace = config.borrow_and_update().namespace.clone();
+            tracing::info!(
+                "Starting VPN operator controller (namespace: {})",
+                namespace.as_deref().unwrap_or("all")
+            );
+
+            let api = match &namespace {
+                Some(ns) => Api::<VpnServer>::namespaced(self.client.clone(), ns),
+                None => Api::<VpnServer>::all(self.client.clone()),
+            };
+
+            let context = Arc::new(Context {
+                client: self.client.clone(),
+                config: self.config.clone(),
+            });
+
+            Controller::new(api.clone(), kube::runtime::watcher::Config::default())
+                .graceful_shutdown_on(Self::namespace_changed(config.clone(), namespace.clone()))
+                .run(Self::reconcile, Self::error_policy, context)
+                .for_each(|res| async {
+                    match res {
+                        Ok(o) => tracing::debug!("Reconciled {:?}", o),
+                        Err(e) => tracing::error!("Reconciliation error: {:?}", e),
+                    }
+                })
+                .await;
+
+            if config.borrow().namespace == namespace {
+                break;
+            }
+            tracing::info!("Watched namespace changed, restarting controller");
+        }
 
         Ok(())
     }
 
+    /// Resolve once the configured namespace differs from `current`
+    async fn namespace_changed(
+        mut config: watch::Receiver<OperatorConfig>,
+        current: Option<String>,
+    ) {
+        loop {
+            if config.changed().await.is_err() {
+                // No more reloads can arrive
+                return std::future::pending().await;
+            }
+            if config.borrow_and_update().namespace != current {
+                return;
+            }
+        }
+    }
+
     /// Main reconciliation function
     async fn reconcile(vpn: Arc<VpnServer>, ctx: Arc<Context>) -> Result<Action> {
         let name = vpn.name_any();
@@ -130,7 +171,7 @@ impl VpnOperatorController {
         tracing::info!("Applying VPN server {}/{}", namespace, name);
 
         // Create reconciler and perform reconciliation
-        let reconciler = VpnReconciler::new(ctx.client.clone(), ctx.config.clone());
+        let reconciler = VpnReconciler::new(ctx.client.clone(), ctx.current_config());
 
         match reconciler.reconcile(vpn.clone()).await {
             Ok(_) => {
@@ -164,7 +205,7 @@ impl VpnOperatorController {
         Self::update_status(vpn.clone(), ctx.clone(), VpnPhase::Terminating, None).await?;
 
         // Create reconciler and perform cleanup
-        let reconciler = VpnReconciler::new(ctx.client.clone(), ctx.config.clone());
+        let reconciler = VpnReconciler::new(ctx.client.clone(), ctx.current_config());
 
         match reconciler.cleanup(vpn.clone()).await {
             Ok(_) => {
@@ -224,7 +265,7 @@ mod tests {
             client: Client::try_default()
                 .await
                 .unwrap_or_else(|_| panic!("Test requires kube config")),
-            config: OperatorConfig::default(),
+            config: watch::channel(OperatorConfig::default()).1,
         };
     }
 }
@@ -35,9 +35,13 @@ pub struct VpnServerSpec {
     #[serde(default)]
     pub high_availability: bool,
 
-    /// Resource requirements
+    /// Disruption budget and pod spreading applied in high availability mode
     #[serde(default)]
-    pub resources: ResourceRequirements,
+    pub availability: AvailabilityConfig,
+
+    /// Resource requirements (operator defaults when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceRequirements>,
 
     /// User management configuration
     pub users: UserManagement,
@@ -158,6 +162,55 @@ pub struct NetworkConfig {
     pub enable_ipv6: bool,
 }
 
+/// How HA replicas are protected from node drains and zone outages
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AvailabilityConfig {
+    /// Pods that must stay up during voluntary disruptions, as a count or
+    /// percentage (e.g. "2" or "50%"); exclusive with `max_unavailable`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_available: Option<String>,
+
+    /// Pods that may be down during voluntary disruptions, as a count or
+    /// percentage; 1 when neither limit is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_unavailable: Option<String>,
+
+    /// Spread replicas across zones
+    #[serde(default = "default_true")]
+    pub zone_spread: bool,
+
+    /// Node label that identifies a zone
+    #[serde(default = "default_zone_topology_key")]
+    pub zone_topology_key: String,
+
+    /// Spread replicas across nodes within a zone
+    #[serde(default = "default_true")]
+    pub node_spread: bool,
+
+    /// Largest allowed difference in replicas between two zones or nodes
+    #[serde(default = "default_max_skew")]
+    pub max_skew: i32,
+
+    /// Refuse to schedule a replica that would break the spread
+    /// (`DoNotSchedule`) rather than prefer not to (`ScheduleAnyway`)
+    #[serde(default)]
+    pub strict_spread: bool,
+}
+
+impl Default for AvailabilityConfig {
+    fn default() -> Self {
+        Self {
+            min_available: None,
+            max_unavailable: None,
+            zone_spread: true,
+            zone_topology_key: default_zone_topology_key(),
+            node_spread: true,
+            max_skew: default_max_skew(),
+            strict_spread: false,
+        }
+    }
+}
+
 /// Security configuration
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
 pub struct SecurityConfig {
@@ -279,6 +332,52 @@ pub struct Condition {
     pub last_transition_time: String,
 }
 
+/// VPN user custom resource
+#[derive(CustomResource, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "vpn.io",
+    version = "v1alpha1",
+    kind = "VpnUser",
+    plural = "vpnusers",
+    shortname = "vpnuser",
+    namespaced,
+    status = "VpnUserStatus",
+    printcolumn = r#"{"name": "Server", "type": "string", "jsonPath": ".spec.serverRef"}"#,
+    printcolumn = r#"{"name": "Enabled", "type": "boolean", "jsonPath": ".spec.enabled"}"#,
+    printcolumn = r#"{"name": "Age", "type": "date", "jsonPath": ".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct VpnUserSpec {
+    /// Name of the VpnServer this user connects to
+    pub server_ref: String,
+
+    /// Contact email of the user
+    #[serde(default)]
+    pub email: Option<String>,
+
+    /// Whether the user is allowed to connect
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Traffic quota (in GB, 0 for unlimited)
+    #[serde(default)]
+    pub quota_gb: u64,
+}
+
+/// VPN user status
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct VpnUserStatus {
+    /// Whether the user has been provisioned on its server
+    pub provisioned: bool,
+
+    /// Status message
+    pub message: Option<String>,
+
+    /// Last update time (RFC3339 format)
+    pub last_updated: Option<String>,
+}
+
 // Default functions
 fn default_replicas() -> i32 {
     1
@@ -307,6 +406,12 @@ fn default_true() -> bool {
 fn default_metrics_port() -> u16 {
     9090
 }
+fn default_zone_topology_key() -> String {
+    "topology.kubernetes.io/zone".to_string()
+}
+fn default_max_skew() -> i32 {
+    1
+}
 
 impl Default for ResourceRequirements {
     fn default() -> Self {
@@ -331,7 +436,8 @@ mod tests {
             port: 8443,
             replicas: 3,
             high_availability: true,
-            resources: ResourceRequirements::default(),
+            availability: AvailabilityConfig::default(),
+            resources: Some(ResourceRequirements::default()),
             users: UserManagement {
                 max_users: 100,
                 auto_create: true,
@@ -348,4 +454,23 @@ mod tests {
         let json = serde_json::to_string(&spec).unwrap();
         let _deserialized: VpnServerSpec = serde_json::from_str(&json).unwrap();
     }
+
+    #[test]
+    fn test_vpn_user_serialization() {
+        let spec = VpnUserSpec {
+            server_ref: "vpn-sample".to_string(),
+            email: Some("alice@example.com".to_string()),
+            enabled: true,
+            quota_gb: 10,
+        };
+
+        let json = serde_json::to_value(&spec).unwrap();
+        assert_eq!(json["serverRef"], "vpn-sample");
+        assert_eq!(json["quotaGb"], 10);
+
+        let deserialized: VpnUserSpec =
+            serde_json::from_value(serde_json::json!({"serverRef": "vpn-sample"})).unwrap();
+        assert!(deserialized.enabled);
+        assert_eq!(deserialized.quota_gb, 0);
+    }
 }
@@ -74,6 +74,11 @@ impl OperatorError {
         Self::ConfigError(msg.into())
     }
 
+    /// Create a network error
+    pub fn network(msg: impl Into<String>) -> Self {
+        Self::NetworkError(msg.into())
+    }
+
     /// Create an internal error
     pub fn internal(msg: impl Into<String>) -> Self {
         Self::InternalError(msg.into())
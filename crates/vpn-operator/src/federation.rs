@@ -0,0 +1,597 @@
+//! Multi-cluster federation of VPN resources
+//!
+//! The federation controller runs in one of two roles. A hub cluster
+//! publishes a snapshot of its federated `VpnServer` and `VpnUser` objects
+//! through the vpn-cluster gRPC layer; spoke clusters poll the hub, apply
+//! the snapshot locally and prune objects that were removed on the hub.
+//!
+//! The hub and its spokes authenticate each other with mutual TLS: every
+//! cluster presents a certificate issued by the federation CA for the
+//! federation name. Plaintext is only used when explicitly allowed.
+
+use crate::{
+    crd::{VpnServer, VpnServerSpec, VpnUser, VpnUserSpec},
+    error::{OperatorError, Result},
+};
+use kube::{
+    api::{Api, DeleteParams, ListParams, Patch, PatchParams},
+    client::Client,
+    ResourceExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::RwLock;
+use vpn_cluster::config::TlsConfig;
+use vpn_cluster::consensus::SimpleConsensus;
+use vpn_cluster::{ClusterGrpcClient, ClusterGrpcServer, ClusterState, ClusterTls, NodeId};
+
+/// Label set on objects created by federation, holding the source cluster name
+pub const FEDERATED_FROM_LABEL: &str = "vpn.io/federated-from";
+
+/// Cluster state key under which the hub publishes its snapshot
+const SNAPSHOT_KEY: &str = "federation/snapshot";
+
+/// Field manager used for server-side apply of federated objects
+const FIELD_MANAGER: &str = "vpn-operator-federation";
+
+/// Role of this cluster in the federation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FederationRole {
+    /// Publishes objects to spoke clusters
+    Hub,
+    /// Mirrors objects published by the hub
+    Spoke,
+}
+
+/// Federation configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FederationConfig {
+    /// Role of this cluster
+    pub role: FederationRole,
+    /// Name identifying this cluster in the federation
+    pub cluster_name: String,
+    /// Address the hub serves snapshots on
+    #[serde(default = "default_bind_address")]
+    pub bind_address: SocketAddr,
+    /// Hub address (spoke role only)
+    #[serde(default)]
+    pub hub_address: Option<SocketAddr>,
+    /// Label selector for objects the hub federates (empty for all)
+    #[serde(default)]
+    pub label_selector: Option<String>,
+    /// Namespace federated objects are applied to on spokes (defaults to the hub namespace)
+    #[serde(default)]
+    pub target_namespace: Option<String>,
+    /// Interval between sync rounds in seconds
+    #[serde(default = "default_sync_interval")]
+    pub sync_interval_secs: u64,
+    /// Certificates for mutual TLS between the hub and its spokes
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Name the federation certificates are issued for, shared by the hub
+    /// and all spokes
+    #[serde(default = "default_federation_name")]
+    pub federation_name: String,
+    /// Serve and fetch snapshots over plaintext, unauthenticated gRPC when
+    /// no TLS is configured
+    #[serde(default)]
+    pub allow_insecure: bool,
+}
+
+fn default_bind_address() -> SocketAddr {
+    "0.0.0.0:7946".parse().unwrap()
+}
+
+fn default_federation_name() -> String {
+    "vpn-federation".to_string()
+}
+
+fn default_sync_interval() -> u64 {
+    30
+}
+
+impl FederationConfig {
+    /// Validate the configuration for the selected role
+    pub fn validate(&self) -> Result<()> {
+        if self.cluster_name.is_empty() {
+            return Err(OperatorError::config(
+                "Federation cluster name cannot be empty",
+            ));
+        }
+
+        if self.role == FederationRole::Spoke && self.hub_address.is_none() {
+            return Err(OperatorError::config(
+                "Spoke clusters must configure a hub address",
+            ));
+        }
+
+        if self.sync_interval_secs == 0 {
+            return Err(OperatorError::config(
+                "Federation sync interval must be greater than 0",
+            ));
+        }
+
+        match &self.tls {
+            Some(tls) if !tls.verify_peer => {
+                return Err(OperatorError::config(
+                    "Federation TLS must verify peer certificates",
+                ));
+            }
+            None if !self.allow_insecure => {
+                return Err(OperatorError::config(
+                    "Federation requires TLS; set allowInsecure to run over plaintext",
+                ));
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Load the configured certificates, `None` when running insecure
+    fn load_tls(&self) -> Result<Option<Arc<ClusterTls>>> {
+        match &self.tls {
+            Some(tls) => {
+                let tls = ClusterTls::load(tls.clone(), self.federation_name.clone())
+                    .map_err(|e| OperatorError::config(e.to_string()))?;
+                Ok(Some(Arc::new(tls)))
+            }
+            None => {
+                tracing::warn!(
+                    "Federation traffic is plaintext and unauthenticated (allowInsecure)"
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// A federated object as published by the hub
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedObject<S> {
+    pub namespace: String,
+    pub name: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    pub spec: S,
+}
+
+/// Snapshot of all federated objects on the hub
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationSnapshot {
+    /// Hub cluster name
+    pub source_cluster: String,
+    /// When the snapshot was taken (RFC3339 format)
+    pub generated_at: String,
+    pub servers: Vec<FederatedObject<VpnServerSpec>>,
+    pub users: Vec<FederatedObject<VpnUserSpec>>,
+}
+
+/// Controller synchronising VPN resources between clusters
+pub struct FederationController {
+    client: Client,
+    config: FederationConfig,
+    namespace: Option<String>,
+}
+
+impl FederationController {
+    /// Create a new federation controller
+    pub fn new(
+        client: Client,
+        config: FederationConfig,
+        namespace: Option<String>,
+    ) -> Result<Self> {
+        config.validate()?;
+
+        Ok(Self {
+            client,
+            config,
+            namespace,
+        })
+    }
+
+    /// Run the controller until an unrecoverable error occurs
+    pub async fn run(&self) -> Result<()> {
+        tracing::info!(
+            "Starting federation controller for cluster {} as {:?}",
+            self.config.cluster_name,
+            self.config.role
+        );
+
+        match self.config.role {
+            FederationRole::Hub => self.run_hub().await,
+            FederationRole::Spoke => self.run_spoke().await,
+        }
+    }
+
+    /// Serve snapshots of local objects to spoke clusters
+    async fn run_hub(&self) -> Result<()> {
+        let node_id = NodeId::new();
+        let state = Arc::new(RwLock::new(ClusterState::with_cluster_name(
+            node_id.clone(),
+            self.config.cluster_name.clone(),
+        )));
+
+        // Spokes only read state; nothing joins the hub's consensus
+        let consensus = Arc::new(SimpleConsensus::new(node_id.clone()));
+        let mut server =
+            ClusterGrpcServer::new(node_id, state.clone(), consensus, self.config.bind_address);
+        if let Some(tls) = self.config.load_tls()? {
+            tls.clone().spawn_reloader();
+            server = server.with_tls(tls);
+        }
+        tokio::spawn(async move {
+            if let Err(e) = server.start().await {
+                tracing::error!("Federation gRPC server failed: {}", e);
+            }
+        });
+
+        let mut interval = tokio::time::interval(self.sync_interval());
+        loop {
+            interval.tick().await;
+
+            match self.collect_snapshot().await {
+                Ok(snapshot) => {
+                    tracing::debug!(
+                        "Publishing federation snapshot with {} servers and {} users",
+                        snapshot.servers.len(),
+                        snapshot.users.len()
+                    );
+                    let value = serde_json::to_value(&snapshot)?;
+                    state
+                        .write()
+                        .await
+                        .set_config(SNAPSHOT_KEY.to_string(), value);
+                }
+                Err(e) => tracing::error!("Failed to collect federation snapshot: {}", e),
+            }
+        }
+    }
+
+    /// Pull snapshots from the hub and apply them locally
+    async fn run_spoke(&self) -> Result<()> {
+        let hub_address = self
+            .config
+            .hub_address
+            .ok_or_else(|| OperatorError::config("Spoke clusters must configure a hub address"))?;
+        let mut grpc_client = ClusterGrpcClient::new(NodeId::new());
+        if let Some(tls) = self.config.load_tls()? {
+            tls.clone().spawn_reloader();
+            grpc_client = grpc_client.with_tls(tls);
+        }
+
+        let mut interval = tokio::time::interval(self.sync_interval());
+        loop {
+            interval.tick().await;
+
+            let snapshot = match Self::fetch_snapshot(&grpc_client, hub_address).await {
+                Ok(Some(snapshot)) => snapshot,
+                Ok(None) => {
+                    tracing::debug!("Hub {} has not published a snapshot yet", hub_address);
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to fetch federation snapshot: {}", e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = self.apply_snapshot(&snapshot).await {
+                tracing::error!("Failed to apply federation snapshot: {}", e);
+            }
+        }
+    }
+
+    fn sync_interval(&self) -> Duration {
+        Duration::from_secs(self.config.sync_interval_secs)
+    }
+
+    fn list_params(&self) -> ListParams {
+        match &self.config.label_selector {
+            Some(selector) => ListParams::default().labels(selector),
+            None => ListParams::default(),
+        }
+    }
+
+    fn api<K>(&self) -> Api<K>
+    where
+        K: kube::Resource<Scope = k8s_openapi::NamespaceResourceScope>,
+        K::DynamicType: Default,
+    {
+        match &self.namespace {
+            Some(ns) => Api::namespaced(self.client.clone(), ns),
+            None => Api::all(self.client.clone()),
+        }
+    }
+
+    /// Collect local objects eligible for federation
+    async fn collect_snapshot(&self) -> Result<FederationSnapshot> {
+        let params = self.list_params();
+
+        let servers = self
+            .api::<VpnServer>()
+            .list(&params)
+            .await?
+            .items
+            .into_iter()
+            .filter(|server| !is_federated_copy(server.labels()))
+            .map(|server| FederatedObject {
+                namespace: server.namespace().unwrap_or_default(),
+                name: server.name_any(),
+                labels: server.labels().clone(),
+                spec: server.spec,
+            })
+            .collect();
+
+        let users = self
+            .api::<VpnUser>()
+            .list(&params)
+            .await?
+            .items
+            .into_iter()
+            .filter(|user| !is_federated_copy(user.labels()))
+            .map(|user| FederatedObject {
+                namespace: user.namespace().unwrap_or_default(),
+                name: user.name_any(),
+                labels: user.labels().clone(),
+                spec: user.spec,
+            })
+            .collect();
+
+        Ok(FederationSnapshot {
+            source_cluster: self.config.cluster_name.clone(),
+            generated_at: chrono::Utc::now().to_rfc3339(),
+            servers,
+            users,
+        })
+    }
+
+    /// Fetch the latest snapshot published by the hub
+    async fn fetch_snapshot(
+        client: &ClusterGrpcClient,
+        hub_address: SocketAddr,
+    ) -> Result<Option<FederationSnapshot>> {
+        let status = client
+            .get_cluster_status(hub_address)
+            .await
+            .map_err(|e| OperatorError::network(e.to_string()))?;
+
+        let Some(raw) = status
+            .cluster_state
+            .and_then(|state| state.config_data.get(SNAPSHOT_KEY).cloned())
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_str(&raw)?))
+    }
+
+    /// Apply a hub snapshot and prune objects removed on the hub
+    async fn apply_snapshot(&self, snapshot: &FederationSnapshot) -> Result<()> {
+        let params = PatchParams::apply(FIELD_MANAGER).force();
+
+        for object in &snapshot.servers {
+            let namespace = self.target_namespace(&object.namespace);
+            let mut server = VpnServer::new(&object.name, object.spec.clone());
+            server.metadata.namespace = Some(namespace.clone());
+            server.metadata.labels =
+                Some(federated_labels(&object.labels, &snapshot.source_cluster));
+
+            let api: Api<VpnServer> = Api::namespaced(self.client.clone(), &namespace);
+            api.patch(&object.name, &params, &Patch::Apply(&server))
+                .await?;
+        }
+
+        for object in &snapshot.users {
+            let namespace = self.target_namespace(&object.namespace);
+            let mut user = VpnUser::new(&object.name, object.spec.clone());
+            user.metadata.namespace = Some(namespace.clone());
+            user.metadata.labels = Some(federated_labels(&object.labels, &snapshot.source_cluster));
+
+            let api: Api<VpnUser> = Api::namespaced(self.client.clone(), &namespace);
+            api.patch(&object.name, &params, &Patch::Apply(&user))
+                .await?;
+        }
+
+        self.prune::<VpnUser, _>(snapshot, &snapshot.users).await?;
+        self.prune::<VpnServer, _>(snapshot, &snapshot.servers)
+            .await?;
+
+        tracing::info!(
+            "Applied federation snapshot from {} ({} servers, {} users)",
+            snapshot.source_cluster,
+            snapshot.servers.len(),
+            snapshot.users.len()
+        );
+
+        Ok(())
+    }
+
+    /// Delete local copies of objects that no longer exist on the hub
+    async fn prune<K, S>(
+        &self,
+        snapshot: &FederationSnapshot,
+        desired: &[FederatedObject<S>],
+    ) -> Result<()>
+    where
+        K: kube::Resource<Scope = k8s_openapi::NamespaceResourceScope>
+            + Clone
+            + std::fmt::Debug
+            + serde::de::DeserializeOwned,
+        K::DynamicType: Default,
+    {
+        let selector = format!("{}={}", FEDERATED_FROM_LABEL, snapshot.source_cluster);
+        let existing = self
+            .api::<K>()
+            .list(&ListParams::default().labels(&selector))
+            .await?;
+
+        let desired: Vec<(String, String)> = desired
+            .iter()
+            .map(|object| {
+                (
+                    self.target_namespace(&object.namespace),
+                    object.name.clone(),
+                )
+            })
+            .collect();
+
+        for object in existing.items {
+            let key = (object.namespace().unwrap_or_default(), object.name_any());
+            if desired.contains(&key) {
+                continue;
+            }
+
+            tracing::info!(
+                "Pruning federated {} {}/{} removed on hub {}",
+                K::kind(&Default::default()),
+                key.0,
+                key.1,
+                snapshot.source_cluster
+            );
+            let api: Api<K> = Api::namespaced(self.client.clone(), &key.0);
+            api.delete(&key.1, &DeleteParams::default()).await?;
+        }
+
+        Ok(())
+    }
+
+    fn target_namespace(&self, source_namespace: &str) -> String {
+        self.config
+            .target_namespace
+            .clone()
+            .unwrap_or_else(|| source_namespace.to_string())
+    }
+}
+
+/// Whether an object was itself created by federation and must not be re-exported
+fn is_federated_copy(labels: &BTreeMap<String, String>) -> bool {
+    labels.contains_key(FEDERATED_FROM_LABEL)
+}
+
+/// Labels for a federated copy, marking the cluster it originates from
+fn federated_labels(
+    labels: &BTreeMap<String, String>,
+    source_cluster: &str,
+) -> BTreeMap<String, String> {
+    let mut labels = labels.clone();
+    labels.insert(FEDERATED_FROM_LABEL.to_string(), source_cluster.to_string());
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spoke_config() -> FederationConfig {
+        FederationConfig {
+            role: FederationRole::Spoke,
+            cluster_name: "eu-west".to_string(),
+            bind_address: default_bind_address(),
+            hub_address: Some("10.0.0.1:7946".parse().unwrap()),
+            label_selector: None,
+            target_namespace: None,
+            sync_interval_secs: 30,
+            tls: None,
+            federation_name: default_federation_name(),
+            allow_insecure: true,
+        }
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(spoke_config().validate().is_ok());
+
+        let mut config = spoke_config();
+        config.hub_address = None;
+        assert!(config.validate().is_err());
+
+        let mut config = spoke_config();
+        config.role = FederationRole::Hub;
+        config.hub_address = None;
+        assert!(config.validate().is_ok());
+
+        let mut config = spoke_config();
+        config.cluster_name = String::new();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_plaintext_requires_opt_in() {
+        let mut config = spoke_config();
+        config.allow_insecure = false;
+        assert!(config.validate().is_err());
+
+        config.tls = Some(TlsConfig {
+            ca_cert: "/etc/vpn/federation/ca.pem".into(),
+            server_cert: "/etc/vpn/federation/tls.pem".into(),
+            server_key: "/etc/vpn/federation/tls.key".into(),
+            client_cert: None,
+            client_key: None,
+            verify_peer: true,
+            reload_interval: Duration::from_secs(30),
+        });
+        assert!(config.validate().is_ok());
+
+        config.tls.as_mut().unwrap().verify_peer = false;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config: FederationConfig =
+            serde_yaml::from_str("role: spoke\nclusterName: us-east\nhubAddress: 10.0.0.1:7946\n")
+                .unwrap();
+
+        assert_eq!(config.role, FederationRole::Spoke);
+        assert_eq!(config.sync_interval_secs, 30);
+        assert_eq!(config.bind_address, default_bind_address());
+    }
+
+    #[test]
+    fn test_federated_labels() {
+        let mut labels = BTreeMap::new();
+        labels.insert("team".to_string(), "ops".to_string());
+
+        assert!(!is_federated_copy(&labels));
+
+        let federated = federated_labels(&labels, "hub");
+        assert_eq!(federated.get("team"), Some(&"ops".to_string()));
+        assert_eq!(
+            federated.get(FEDERATED_FROM_LABEL),
+            Some(&"hub".to_string())
+        );
+        assert!(is_federated_copy(&federated));
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let snapshot = FederationSnapshot {
+            source_cluster: "hub".to_string(),
+            generated_at: chrono::Utc::now().to_rfc3339(),
+            servers: vec![],
+            users: vec![FederatedObject {
+                namespace: "vpn".to_string(),
+                name: "alice".to_string(),
+                labels: BTreeMap::new(),
+                spec: VpnUserSpec {
+                    server_ref: "vpn-sample".to_string(),
+                    email: None,
+                    enabled: true,
+                    quota_gb: 0,
+                },
+            }],
+        };
+
+        // The hub publishes the snapshot as a JSON value; spokes receive its string form
+        let raw = serde_json::to_value(&snapshot).unwrap().to_string();
+        let parsed: FederationSnapshot = serde_json::from_str(&raw).unwrap();
+
+        assert_eq!(parsed.source_cluster, "hub");
+        assert_eq!(parsed.users.len(), 1);
+        assert_eq!(parsed.users[0].name, "alice");
+        assert_eq!(parsed.users[0].spec.server_ref, "vpn-sample");
+    }
+}
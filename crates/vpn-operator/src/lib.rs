@@ -3,33 +3,49 @@
 //! This crate provides a Kubernetes operator that manages VPN deployments,
 //! including automated provisioning, scaling, and lifecycle management.
 
+pub mod config_watcher;
 pub mod controller;
 pub mod crd;
 pub mod error;
+pub mod federation;
 pub mod reconciler;
 pub mod resources;
 pub mod webhook;
 
+pub use config_watcher::ConfigWatcher;
 pub use controller::VpnOperatorController;
-pub use crd::{VpnServer, VpnServerSpec, VpnServerStatus};
+pub use crd::{VpnServer, VpnServerSpec, VpnServerStatus, VpnUser, VpnUserSpec};
 pub use error::{OperatorError, Result};
+pub use federation::{FederationConfig, FederationController, FederationRole};
 pub use reconciler::VpnReconciler;
 
 use kube::Client;
 use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Protocols accepted as `defaultProtocol`
+const SUPPORTED_PROTOCOLS: &[&str] = &["vless", "outline", "wireguard", "openvpn"];
 
 /// Main operator that orchestrates VPN deployments in Kubernetes
 pub struct VpnOperator {
     /// Kubernetes client
-    _client: Client,
-    /// Operator configuration
+    client: Client,
+    /// Operator configuration at startup
     config: OperatorConfig,
+    /// Publishes configuration reloads to the controller
+    config_tx: Arc<watch::Sender<OperatorConfig>>,
+    /// ConfigMap (namespace, name) to hot-reload configuration from
+    config_map: Option<(String, String)>,
     /// Controller for managing resources
     controller: Arc<VpnOperatorController>,
 }
 
 /// Operator configuration
-#[derive(Debug, Clone, serde::Deserialize)]
+///
+/// Field names follow the Helm chart's `config.yaml` (camelCase); missing
+/// fields fall back to their defaults.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct OperatorConfig {
     /// Namespace to watch (empty for all namespaces)
     pub namespace: Option<String>,
@@ -38,6 +54,7 @@ pub struct OperatorConfig {
     /// Default VPN protocol
     pub default_protocol: String,
     /// Enable high availability mode
+    #[serde(rename = "enableHA")]
     pub enable_ha: bool,
     /// Metrics port
     pub metrics_port: u16,
@@ -45,12 +62,15 @@ pub struct OperatorConfig {
     pub webhook_port: u16,
     /// Leader election enabled
     pub leader_election: bool,
-    /// Resource limits
+    /// Default resources for VPN servers that do not set `spec.resources`
     pub resource_limits: ResourceLimits,
+    /// Multi-cluster federation (disabled when absent)
+    pub federation: Option<FederationConfig>,
 }
 
 /// Resource limits configuration
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct ResourceLimits {
     /// Default CPU request
     pub cpu_request: String,
@@ -72,30 +92,100 @@ impl Default for OperatorConfig {
             metrics_port: 8080,
             webhook_port: 9443,
             leader_election: true,
-            resource_limits: ResourceLimits {
-                cpu_request: "100m".to_string(),
-                cpu_limit: "500m".to_string(),
-                memory_request: "128Mi".to_string(),
-                memory_limit: "512Mi".to_string(),
-            },
+            resource_limits: ResourceLimits::default(),
+            federation: None,
+        }
+    }
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            cpu_request: "100m".to_string(),
+            cpu_limit: "500m".to_string(),
+            memory_request: "128Mi".to_string(),
+            memory_limit: "512Mi".to_string(),
         }
     }
 }
 
+impl OperatorConfig {
+    /// Parse and validate configuration from the chart's `config.yaml`
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let mut config: Self = serde_yaml::from_str(yaml)
+            .map_err(|e| OperatorError::config(format!("Invalid operator config: {}", e)))?;
+
+        // The chart renders an empty string to watch all namespaces
+        if config.namespace.as_deref() == Some("") {
+            config.namespace = None;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<()> {
+        if self.vpn_image.is_empty() {
+            return Err(OperatorError::config("VPN image cannot be empty"));
+        }
+
+        if !SUPPORTED_PROTOCOLS.contains(&self.default_protocol.as_str()) {
+            return Err(OperatorError::config(format!(
+                "Unsupported default protocol: {}",
+                self.default_protocol
+            )));
+        }
+
+        let limits = &self.resource_limits;
+        for (field, value) in [
+            ("cpuRequest", &limits.cpu_request),
+            ("cpuLimit", &limits.cpu_limit),
+            ("memoryRequest", &limits.memory_request),
+            ("memoryLimit", &limits.memory_limit),
+        ] {
+            if value.is_empty() {
+                return Err(OperatorError::config(format!(
+                    "Resource limit {} cannot be empty",
+                    field
+                )));
+            }
+        }
+
+        if let Some(federation) = &self.federation {
+            federation.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
 impl VpnOperator {
     /// Create a new VPN operator
     pub async fn new(config: OperatorConfig) -> Result<Self> {
         let client = Client::try_default().await?;
-        let controller =
-            Arc::new(VpnOperatorController::new(client.clone(), config.clone()).await?);
+        let (config_tx, config_rx) = watch::channel(config.clone());
+        let controller = Arc::new(VpnOperatorController::new(client.clone(), config_rx).await?);
 
         Ok(Self {
-            _client: client,
+            client,
             config,
+            config_tx: Arc::new(config_tx),
+            config_map: None,
             controller,
         })
     }
 
+    /// Hot-reload configuration from the given ConfigMap
+    pub fn with_config_map(
+        mut self,
+        namespace: impl Into<String>,
+        name: impl Into<String>,
+    ) -> Self {
+        self.config_map = Some((namespace.into(), name.into()));
+        self
+    }
+
     /// Start the operator
     pub async fn run(&self) -> Result<()> {
         tracing::info!("Starting VPN Kubernetes Operator");
@@ -110,9 +200,37 @@ impl VpnOperator {
             None
         };
 
+        // Watch the configuration ConfigMap if configured
+        let config_watcher_handle = self.config_map.as_ref().map(|(namespace, name)| {
+            let watcher = ConfigWatcher::new(
+                self.client.clone(),
+                namespace.clone(),
+                name.clone(),
+                self.config_tx.clone(),
+            );
+            tokio::spawn(async move {
+                if let Err(e) = watcher.run().await {
+                    tracing::error!("Config watcher failed: {}", e);
+                }
+            })
+        });
+
+        // Start the federation controller if configured
+        let federation_handle = match &self.config.federation {
+            Some(federation) => Some(self.start_federation_controller(federation.clone())?),
+            None => None,
+        };
+
         // Start the controller
         self.controller.run().await?;
 
+        if let Some(federation) = federation_handle {
+            federation.abort();
+        }
+        if let Some(config_watcher) = config_watcher_handle {
+            config_watcher.abort();
+        }
+
         // Wait for shutdown
         if let Some(webhook) = webhook_handle {
             webhook
@@ -135,6 +253,21 @@ impl VpnOperator {
         })
     }
 
+    /// Start the multi-cluster federation controller
+    fn start_federation_controller(
+        &self,
+        config: FederationConfig,
+    ) -> Result<tokio::task::JoinHandle<()>> {
+        let controller =
+            FederationController::new(self.client.clone(), config, self.config.namespace.clone())?;
+
+        Ok(tokio::spawn(async move {
+            if let Err(e) = controller.run().await {
+                tracing::error!("Federation controller failed: {}", e);
+            }
+        }))
+    }
+
     /// Start webhook server
     fn start_webhook_server(&self) -> tokio::task::JoinHandle<()> {
         let port = self.config.webhook_port;
@@ -156,5 +289,50 @@ mod tests {
         assert_eq!(config.default_protocol, "vless");
         assert!(!config.enable_ha);
         assert_eq!(config.metrics_port, 8080);
+        assert!(config.federation.is_none());
+    }
+
+    #[test]
+    fn test_helm_config() {
+        let yaml = r#"
+namespace: ""
+vpnImage: "registry.example.com/vpn-server:v2"
+defaultProtocol: "outline"
+enableHA: true
+metricsPort: 9090
+webhookPort: 9443
+leaderElection: true
+resourceLimits:
+  cpuRequest: "200m"
+  cpuLimit: "1"
+  memoryRequest: "256Mi"
+  memoryLimit: "1Gi"
+"#;
+        let config = OperatorConfig::from_yaml(yaml).unwrap();
+
+        assert_eq!(config.namespace, None);
+        assert_eq!(config.vpn_image, "registry.example.com/vpn-server:v2");
+        assert_eq!(config.default_protocol, "outline");
+        assert!(config.enable_ha);
+        assert_eq!(config.metrics_port, 9090);
+        assert_eq!(config.resource_limits.cpu_limit, "1");
+        assert_eq!(config.resource_limits.memory_request, "256Mi");
+    }
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let config = OperatorConfig::from_yaml("namespace: vpn-system\n").unwrap();
+
+        assert_eq!(config.namespace, Some("vpn-system".to_string()));
+        assert_eq!(config.vpn_image, "vpn-server:latest");
+        assert_eq!(config.resource_limits, ResourceLimits::default());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(OperatorConfig::from_yaml("defaultProtocol: ipsec\n").is_err());
+        assert!(OperatorConfig::from_yaml("vpnImage: \"\"\n").is_err());
+        assert!(OperatorConfig::from_yaml("resourceLimits:\n  cpuLimit: \"\"\n").is_err());
+        assert!(OperatorConfig::from_yaml("metricsPort: not-a-port\n").is_err());
     }
 }
@@ -18,6 +18,10 @@ struct Args {
     #[clap(short, long, default_value = "/etc/vpn-operator/config.yaml")]
     config: String,
 
+    /// ConfigMap to hot-reload configuration from (in the operator namespace)
+    #[clap(long)]
+    config_map: Option<String>,
+
     /// Namespace to watch (empty for all namespaces)
     #[clap(short, long)]
     namespace: Option<String>,
@@ -64,7 +68,7 @@ async fn main() -> Result<()> {
     let config = if std::path::Path::new(&args.config).exists() {
         info!("Loading configuration from {}", args.config);
         let config_str = std::fs::read_to_string(&args.config)?;
-        serde_yaml::from_str(&config_str)?
+        OperatorConfig::from_yaml(&config_str)?
     } else {
         info!("Using default configuration");
         OperatorConfig {
@@ -77,7 +81,17 @@ async fn main() -> Result<()> {
     };
 
     // Create and run operator
-    let operator = VpnOperator::new(config).await?;
+    let mut operator = VpnOperator::new(config).await?;
+
+    if let Some(config_map) = args.config_map {
+        let namespace =
+            std::env::var("OPERATOR_NAMESPACE").unwrap_or_else(|_| "vpn-system".to_string());
+        info!(
+            "Hot-reloading configuration from ConfigMap {}/{}",
+            namespace, config_map
+        );
+        operator = operator.with_config_map(namespace, config_map);
+    }
 
     // Handle shutdown gracefully
     let shutdown = tokio::signal::ctrl_c();
@@ -112,10 +126,13 @@ mod tests {
             "9090",
             "--vpn-image",
             "custom-vpn:v1.0",
+            "--config-map",
+            "vpn-operator",
         ]);
 
         assert_eq!(args.namespace, Some("vpn-system".to_string()));
         assert_eq!(args.metrics_port, 9090);
         assert_eq!(args.vpn_image, "custom-vpn:v1.0");
+        assert_eq!(args.config_map, Some("vpn-operator".to_string()));
     }
 }
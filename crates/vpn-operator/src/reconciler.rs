@@ -3,12 +3,13 @@
 use crate::{
     crd::VpnServer,
     error::Result,
-    resources::{configmap, deployment, secret, service},
+    resources::{availability, configmap, deployment, secret, service},
     OperatorConfig,
 };
 use k8s_openapi::api::{
     apps::v1::Deployment,
     core::v1::{ConfigMap, Secret, Service},
+    policy::v1::PodDisruptionBudget,
 };
 use kube::{
     api::{Api, DeleteParams, Patch, PatchParams, PostParams},
@@ -53,6 +54,8 @@ impl VpnReconciler {
         // Create additional resources based on configuration
         if vpn.spec.high_availability {
             self.reconcile_ha_resources(&vpn).await?;
+        } else {
+            self.delete_pdb(&name, &namespace).await?;
         }
 
         if vpn.spec.monitoring.enable_metrics {
@@ -70,6 +73,7 @@ impl VpnReconciler {
         tracing::info!("Cleaning up VPN server {}/{}", namespace, name);
 
         // Delete in reverse order
+        self.delete_pdb(&name, &namespace).await?;
         self.delete_service(&name, &namespace).await?;
         self.delete_deployment(&name, &namespace).await?;
         self.delete_secret(&name, &namespace).await?;
@@ -197,12 +201,33 @@ impl VpnReconciler {
     }
 
     /// Reconcile HA resources
+    ///
+    /// Topology spread constraints are part of the Deployment; this keeps
+    /// the PodDisruptionBudget in line with the spec.
     async fn reconcile_ha_resources(&self, vpn: &VpnServer) -> Result<()> {
         tracing::info!("Reconciling HA resources for {}", vpn.name_any());
 
-        // TODO: Create PodDisruptionBudget
-        // TODO: Configure anti-affinity rules
-        // TODO: Setup health checks
+        let namespace = vpn.namespace().unwrap_or_default();
+        let api: Api<PodDisruptionBudget> = Api::namespaced(self.client.clone(), &namespace);
+        let pdb_name = availability::pdb_name(&vpn.name_any());
+
+        let pdb = availability::create_pod_disruption_budget(vpn)?;
+
+        match api.get(&pdb_name).await {
+            Ok(_existing) => {
+                // Update existing PodDisruptionBudget
+                let patch = Patch::Apply(&pdb);
+                api.patch(&pdb_name, &PatchParams::apply("vpn-operator"), &patch)
+                    .await?;
+                tracing::debug!("Updated PodDisruptionBudget {}/{}", namespace, pdb_name);
+            }
+            Err(kube::Error::Api(e)) if e.code == 404 => {
+                // Create new PodDisruptionBudget
+                api.create(&PostParams::default(), &pdb).await?;
+                tracing::info!("Created PodDisruptionBudget {}/{}", namespace, pdb_name);
+            }
+            Err(e) => return Err(e.into()),
+        }
 
         Ok(())
     }
@@ -271,6 +296,24 @@ impl VpnReconciler {
         }
     }
 
+    /// Delete PodDisruptionBudget
+    async fn delete_pdb(&self, name: &str, namespace: &str) -> Result<()> {
+        let api: Api<PodDisruptionBudget> = Api::namespaced(self.client.clone(), namespace);
+        let pdb_name = availability::pdb_name(name);
+
+        match api.delete(&pdb_name, &DeleteParams::default()).await {
+            Ok(_) => {
+                tracing::info!("Deleted PodDisruptionBudget {}/{}", namespace, pdb_name);
+                Ok(())
+            }
+            Err(kube::Error::Api(e)) if e.code == 404 => {
+                // Already deleted
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
     /// Delete Service
     async fn delete_service(&self, name: &str, namespace: &str) -> Result<()> {
         let api: Api<Service> = Api::namespaced(self.client.clone(), namespace);
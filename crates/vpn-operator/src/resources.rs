@@ -1,5 +1,6 @@
 //! Resource generation for VPN deployments
 
+pub mod availability;
 pub mod configmap;
 pub mod deployment;
 pub mod secret;
@@ -0,0 +1,224 @@
+//! PodDisruptionBudget and topology spread generation for HA deployments
+
+use crate::{
+    crd::VpnServer,
+    error::{OperatorError, Result},
+    resources::{common_annotations, common_labels, owner_reference},
+};
+use k8s_openapi::{
+    api::{
+        core::v1::TopologySpreadConstraint,
+        policy::v1::{PodDisruptionBudget, PodDisruptionBudgetSpec},
+    },
+    apimachinery::pkg::{
+        apis::meta::v1::{LabelSelector, ObjectMeta},
+        util::intstr::IntOrString,
+    },
+};
+use kube::ResourceExt;
+
+/// Node label that identifies a node
+const NODE_TOPOLOGY_KEY: &str = "kubernetes.io/hostname";
+
+/// Name of the PodDisruptionBudget of a VPN server
+pub fn pdb_name(vpn_name: &str) -> String {
+    format!("{}-pdb", vpn_name)
+}
+
+/// Create PodDisruptionBudget for VPN server
+pub fn create_pod_disruption_budget(vpn: &VpnServer) -> Result<PodDisruptionBudget> {
+    let availability = &vpn.spec.availability;
+    let labels = common_labels(vpn);
+
+    let (min_available, max_unavailable) =
+        match (&availability.min_available, &availability.max_unavailable) {
+            (Some(_), Some(_)) => {
+                return Err(OperatorError::InvalidSpec(
+                    "Only one of minAvailable and maxUnavailable may be set".to_string(),
+                ))
+            }
+            (Some(min), None) => (Some(parse_int_or_percent(min)?), None),
+            (None, Some(max)) => (None, Some(parse_int_or_percent(max)?)),
+            (None, None) => (None, Some(IntOrString::Int(1))),
+        };
+
+    Ok(PodDisruptionBudget {
+        metadata: ObjectMeta {
+            name: Some(pdb_name(&vpn.name_any())),
+            namespace: Some(vpn.namespace().unwrap_or_default()),
+            labels: Some(labels.clone()),
+            annotations: Some(common_annotations(vpn)),
+            owner_references: Some(owner_reference(vpn)),
+            ..Default::default()
+        },
+        spec: Some(PodDisruptionBudgetSpec {
+            min_available,
+            max_unavailable,
+            selector: Some(LabelSelector {
+                match_labels: Some(labels),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+/// Topology spread constraints keeping replicas apart across zones and
+/// nodes; empty unless high availability is enabled
+pub fn topology_spread_constraints(vpn: &VpnServer) -> Vec<TopologySpreadConstraint> {
+    if !vpn.spec.high_availability {
+        return Vec::new();
+    }
+
+    let availability = &vpn.spec.availability;
+    let when_unsatisfiable = if availability.strict_spread {
+        "DoNotSchedule"
+    } else {
+        "ScheduleAnyway"
+    };
+    let constraint = |topology_key: &str| TopologySpreadConstraint {
+        label_selector: Some(LabelSelector {
+            match_labels: Some(common_labels(vpn)),
+            ..Default::default()
+        }),
+        max_skew: availability.max_skew,
+        topology_key: topology_key.to_string(),
+        when_unsatisfiable: when_unsatisfiable.to_string(),
+        ..Default::default()
+    };
+
+    let mut constraints = Vec::new();
+    if availability.zone_spread {
+        constraints.push(constraint(&availability.zone_topology_key));
+    }
+    if availability.node_spread {
+        constraints.push(constraint(NODE_TOPOLOGY_KEY));
+    }
+    constraints
+}
+
+/// Parse a pod count ("2") or percentage ("50%")
+pub fn parse_int_or_percent(value: &str) -> Result<IntOrString> {
+    let value = value.trim();
+    let invalid = || OperatorError::InvalidSpec(format!("Invalid pod count: {}", value));
+
+    match value.strip_suffix('%') {
+        Some(percent) => {
+            let percent: u8 = percent.parse().map_err(|_| invalid())?;
+            if percent > 100 {
+                return Err(invalid());
+            }
+            Ok(IntOrString::String(format!("{}%", percent)))
+        }
+        None => {
+            let count: i32 = value.parse().map_err(|_| invalid())?;
+            if count < 0 {
+                return Err(invalid());
+            }
+            Ok(IntOrString::Int(count))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::{
+        AvailabilityConfig, MonitoringConfig, NetworkConfig, SecurityConfig, UserManagement,
+        VpnProtocol, VpnServerSpec,
+    };
+    use std::collections::BTreeMap;
+
+    fn vpn_server(availability: AvailabilityConfig) -> VpnServer {
+        let mut vpn = VpnServer::new(
+            "edge",
+            VpnServerSpec {
+                protocol: VpnProtocol::Vless,
+                port: 8443,
+                replicas: 3,
+                high_availability: true,
+                availability,
+                resources: None,
+                users: UserManagement {
+                    max_users: 100,
+                    auto_create: false,
+                    quota_gb: 0,
+                    external_auth: None,
+                },
+                network: NetworkConfig::default(),
+                security: SecurityConfig::default(),
+                monitoring: MonitoringConfig::default(),
+                labels: BTreeMap::new(),
+                annotations: BTreeMap::new(),
+            },
+        );
+        vpn.metadata.namespace = Some("vpn".to_string());
+        vpn
+    }
+
+    #[test]
+    fn test_pod_disruption_budget() {
+        let pdb = create_pod_disruption_budget(&vpn_server(Default::default())).unwrap();
+        assert_eq!(pdb.metadata.name.as_deref(), Some("edge-pdb"));
+        let spec = pdb.spec.unwrap();
+        assert_eq!(spec.max_unavailable, Some(IntOrString::Int(1)));
+        assert_eq!(spec.min_available, None);
+
+        let pdb = create_pod_disruption_budget(&vpn_server(AvailabilityConfig {
+            min_available: Some("50%".to_string()),
+            ..Default::default()
+        }))
+        .unwrap();
+        assert_eq!(
+            pdb.spec.unwrap().min_available,
+            Some(IntOrString::String("50%".to_string()))
+        );
+
+        assert!(
+            create_pod_disruption_budget(&vpn_server(AvailabilityConfig {
+                min_available: Some("2".to_string()),
+                max_unavailable: Some("1".to_string()),
+                ..Default::default()
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_topology_spread_constraints() {
+        let constraints = topology_spread_constraints(&vpn_server(Default::default()));
+        let keys: Vec<&str> = constraints
+            .iter()
+            .map(|c| c.topology_key.as_str())
+            .collect();
+        assert_eq!(keys, ["topology.kubernetes.io/zone", NODE_TOPOLOGY_KEY]);
+        assert!(constraints
+            .iter()
+            .all(|c| c.when_unsatisfiable == "ScheduleAnyway" && c.max_skew == 1));
+
+        let strict = topology_spread_constraints(&vpn_server(AvailabilityConfig {
+            node_spread: false,
+            strict_spread: true,
+            ..Default::default()
+        }));
+        assert_eq!(strict.len(), 1);
+        assert_eq!(strict[0].when_unsatisfiable, "DoNotSchedule");
+
+        let mut single = vpn_server(Default::default());
+        single.spec.high_availability = false;
+        assert!(topology_spread_constraints(&single).is_empty());
+    }
+
+    #[test]
+    fn test_parse_int_or_percent() {
+        assert_eq!(parse_int_or_percent("2").unwrap(), IntOrString::Int(2));
+        assert_eq!(
+            parse_int_or_percent(" 25% ").unwrap(),
+            IntOrString::String("25%".to_string())
+        );
+        assert!(parse_int_or_percent("150%").is_err());
+        assert!(parse_int_or_percent("-1").is_err());
+        assert!(parse_int_or_percent("two").is_err());
+    }
+}
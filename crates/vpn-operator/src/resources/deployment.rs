@@ -3,7 +3,7 @@
 use crate::{
     crd::{VpnProtocol, VpnServer},
     error::Result,
-    resources::{common_annotations, common_labels, owner_reference},
+    resources::{availability, common_annotations, common_labels, owner_reference},
     OperatorConfig,
 };
 use k8s_openapi::{
@@ -44,6 +44,8 @@ pub fn create_vpn_deployment(vpn: &VpnServer, config: &OperatorConfig) -> Result
             fs_group: Some(1000),
             ..Default::default()
         }),
+        topology_spread_constraints: Some(availability::topology_spread_constraints(vpn))
+            .filter(|constraints| !constraints.is_empty()),
         ..Default::default()
     };
 
@@ -107,7 +109,9 @@ fn create_vpn_container(vpn: &VpnServer, config: &OperatorConfig) -> Result<Cont
         }]),
         env: Some(create_env_vars(vpn)),
         volume_mounts: Some(create_volume_mounts(vpn)),
-        resources: Some(create_resource_requirements(&vpn.spec.resources)),
+        resources: Some(create_resource_requirements(&effective_resources(
+            vpn, config,
+        ))),
         ..Default::default()
     };
 
@@ -230,6 +234,23 @@ fn create_volumes(vpn: &VpnServer) -> Vec<Volume> {
     ]
 }
 
+/// Resources from the spec, falling back to the operator defaults
+fn effective_resources(
+    vpn: &VpnServer,
+    config: &OperatorConfig,
+) -> crate::crd::ResourceRequirements {
+    vpn.spec.resources.clone().unwrap_or_else(|| {
+        let limits = &config.resource_limits;
+        crate::crd::ResourceRequirements {
+            cpu_request: limits.cpu_request.clone(),
+            cpu_limit: limits.cpu_limit.clone(),
+            memory_request: limits.memory_request.clone(),
+            memory_limit: limits.memory_limit.clone(),
+            ..Default::default()
+        }
+    })
+}
+
 /// Create resource requirements
 fn create_resource_requirements(
     resources: &crate::crd::ResourceRequirements,
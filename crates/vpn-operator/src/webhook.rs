@@ -3,6 +3,7 @@
 use crate::{
     crd::{VpnServer, VpnServerSpec},
     error::{OperatorError, Result},
+    resources::availability::parse_int_or_percent,
 };
 use kube::core::admission::{AdmissionRequest, AdmissionResponse};
 use serde_json::json;
@@ -65,6 +66,29 @@ fn validate_spec(spec: &VpnServerSpec) -> Result<()> {
         ));
     }
 
+    // Validate disruption budget and spread settings
+    let availability = &spec.availability;
+    if availability.min_available.is_some() && availability.max_unavailable.is_some() {
+        return Err(OperatorError::validation(
+            "Only one of minAvailable and maxUnavailable may be set",
+        ));
+    }
+    for value in [&availability.min_available, &availability.max_unavailable]
+        .into_iter()
+        .flatten()
+    {
+        if parse_int_or_percent(value).is_err() {
+            return Err(OperatorError::validation(
+                "minAvailable and maxUnavailable must be a pod count or a percentage",
+            ));
+        }
+    }
+    if availability.max_skew < 1 {
+        return Err(OperatorError::validation(
+            "Topology spread maxSkew must be at least 1",
+        ));
+    }
+
     // Validate resource requests don't exceed limits
     // This would require parsing the quantity strings
 
@@ -113,7 +137,8 @@ mod tests {
             port: 8443,
             replicas: 1,
             high_availability: false,
-            resources: ResourceRequirements::default(),
+            availability: Default::default(),
+            resources: Some(ResourceRequirements::default()),
             users: UserManagement {
                 max_users: 100,
                 auto_create: false,
@@ -158,4 +183,22 @@ mod tests {
         spec.replicas = 2;
         assert!(validate_spec(&spec).is_ok());
     }
+
+    #[test]
+    fn test_validate_availability() {
+        let mut spec = create_test_spec();
+        spec.availability.min_available = Some("50%".to_string());
+        assert!(validate_spec(&spec).is_ok());
+
+        spec.availability.max_unavailable = Some("1".to_string());
+        assert!(validate_spec(&spec).is_err());
+
+        spec.availability.max_unavailable = None;
+        spec.availability.min_available = Some("most".to_string());
+        assert!(validate_spec(&spec).is_err());
+
+        spec.availability.min_available = None;
+        spec.availability.max_skew = 0;
+        assert!(validate_spec(&spec).is_err());
+    }
 }
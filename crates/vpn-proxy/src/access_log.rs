@@ -0,0 +1,600 @@
+//! Per-request access log
+//!
+//! Every HTTP request, CONNECT tunnel and SOCKS5 session gets one line
+//! naming the user, source address, destination, bytes moved each way,
+//! duration and why it ended. Lines are written in JSON or in a format
+//! modelled on Apache's combined log to [`AccessLogConfig::path`], which is
+//! rotated by size, and optionally as JSON to standard output, where
+//! vpn-telemetry's log shipping picks them up from the proxy container.
+//!
+//! Handlers open an [`AccessEntry`] per request with
+//! [`ProxyManager::begin_request`](crate::manager::ProxyManager::begin_request)
+//! and fill it in through the free functions of this module, which act on
+//! the request the current task serves. The line is written once the last
+//! clone of the entry, including those held by tunnel tasks, is dropped.
+
+use crate::block_page::BlockReason;
+use crate::config::{AccessLogConfig, AccessLogFormat};
+use crate::error::Result;
+use crate::limits::TunnelExpiry;
+use crate::shaping::Direction;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
+use tracing::warn;
+
+/// Records waiting for the writer before new ones are dropped
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Value of the `log` field identifying access lines among other output
+pub const LOG_KIND: &str = "proxy_access";
+
+/// Why a request or tunnel ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    /// The request was served in full
+    Completed,
+    /// The client closed the tunnel first
+    ClientClosed,
+    /// The destination closed the tunnel first
+    UpstreamClosed,
+    AuthFailed,
+    /// Refused by the access list
+    Blocked,
+    RateLimited,
+    ConnectionLimit,
+    /// The destination could not be reached
+    ConnectFailed,
+    /// The tunnel was closed after idling
+    IdleTimeout,
+    /// The tunnel was closed at its maximum lifetime
+    LifetimeExceeded,
+    /// Handling the request failed
+    Error,
+}
+
+impl Termination {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Completed => "completed",
+            Self::ClientClosed => "client_closed",
+            Self::UpstreamClosed => "upstream_closed",
+            Self::AuthFailed => "auth_failed",
+            Self::Blocked => "blocked",
+            Self::RateLimited => "rate_limited",
+            Self::ConnectionLimit => "connection_limit",
+            Self::ConnectFailed => "connect_failed",
+            Self::IdleTimeout => "idle_timeout",
+            Self::LifetimeExceeded => "lifetime_exceeded",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl From<BlockReason> for Termination {
+    fn from(reason: BlockReason) -> Self {
+        match reason {
+            BlockReason::Acl => Self::Blocked,
+            BlockReason::RateLimit => Self::RateLimited,
+            BlockReason::ConnectionLimit => Self::ConnectionLimit,
+        }
+    }
+}
+
+impl From<TunnelExpiry> for Termination {
+    fn from(expiry: TunnelExpiry) -> Self {
+        match expiry {
+            TunnelExpiry::Idle => Self::IdleTimeout,
+            TunnelExpiry::Lifetime => Self::LifetimeExceeded,
+        }
+    }
+}
+
+/// One line of the access log
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessRecord {
+    /// When the request arrived
+    pub timestamp: DateTime<Utc>,
+    /// `http` or `socks5`
+    pub protocol: &'static str,
+    pub user: Option<String>,
+    pub source: SocketAddr,
+    /// `host:port` the client asked for
+    pub destination: Option<String>,
+    /// HTTP method or SOCKS5 command
+    pub method: Option<String>,
+    /// HTTP status sent to the client
+    pub status: Option<u16>,
+    /// Bytes from the client to the destination
+    pub bytes_up: u64,
+    /// Bytes from the destination to the client
+    pub bytes_down: u64,
+    pub duration_ms: u64,
+    pub termination: Termination,
+}
+
+impl AccessRecord {
+    /// The record as a JSON object, tagged with [`LOG_KIND`]
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Line<'a> {
+            log: &'static str,
+            #[serde(flatten)]
+            record: &'a AccessRecord,
+        }
+
+        serde_json::to_string(&Line {
+            log: LOG_KIND,
+            record: self,
+        })
+        .unwrap_or_default()
+    }
+
+    /// The record in Apache's combined format, with the referer and user
+    /// agent left out and upload bytes, duration and termination appended:
+    ///
+    /// `1.2.3.4 - alice [18/Oct/2026:12:00:00 +0000] "CONNECT example.com:443 HTTP/1.1" 200 5120 "-" "-" 1024 350 client_closed`
+    pub fn to_combined(&self) -> String {
+        let request_protocol = match self.protocol {
+            "socks5" => "SOCKS5",
+            _ => "HTTP/1.1",
+        };
+        format!(
+            "{} - {} [{}] \"{} {} {}\" {} {} \"-\" \"-\" {} {} {}",
+            self.source.ip(),
+            self.user.as_deref().unwrap_or("-"),
+            self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method.as_deref().unwrap_or("-"),
+            self.destination.as_deref().unwrap_or("-"),
+            request_protocol,
+            self.status
+                .map(|status| status.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            self.bytes_down,
+            self.bytes_up,
+            self.duration_ms,
+            self.termination.as_str()
+        )
+    }
+}
+
+/// Writes records on a thread of its own, so handlers never wait on disk
+pub struct AccessLog {
+    sender: mpsc::SyncSender<AccessRecord>,
+    dropped: AtomicU64,
+}
+
+impl AccessLog {
+    /// Open the log described by `config`; `None` when it writes nowhere
+    pub fn open(config: &AccessLogConfig) -> Result<Option<Arc<Self>>> {
+        if config.path.is_none() && !config.ship {
+            return Ok(None);
+        }
+
+        let file = match &config.path {
+            Some(path) => Some(RotatingFile::open(path, config.max_size, config.max_files)?),
+            None => None,
+        };
+        let mut writer = Writer {
+            file,
+            format: config.format,
+            ship: config.ship,
+        };
+
+        let (sender, receiver) = mpsc::sync_channel::<AccessRecord>(QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                while let Ok(record) = receiver.recv() {
+                    writer.write(&record);
+                    // Flush once the queue is drained
+                    while let Ok(record) = receiver.try_recv() {
+                        writer.write(&record);
+                    }
+                    writer.flush();
+                }
+            })?;
+
+        Ok(Some(Arc::new(Self {
+            sender,
+            dropped: AtomicU64::new(0),
+        })))
+    }
+
+    /// Queue `record`, dropping it when the writer has fallen behind
+    pub fn write(&self, record: AccessRecord) {
+        if self.sender.try_send(record).is_err() {
+            // Report the first drop and every thousandth after it
+            if self
+                .dropped
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(1000)
+            {
+                warn!("Access log writer is behind, dropping records");
+            }
+        }
+    }
+
+    /// Records dropped since the log was opened
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+struct Writer {
+    file: Option<RotatingFile>,
+    format: AccessLogFormat,
+    ship: bool,
+}
+
+impl Writer {
+    fn write(&mut self, record: &AccessRecord) {
+        let json = record.to_json();
+        if let Some(file) = &mut self.file {
+            let line = match self.format {
+                AccessLogFormat::Json => &json,
+                AccessLogFormat::Combined => &record.to_combined(),
+            };
+            if let Err(e) = file.write_line(line) {
+                warn!("Failed to write access log: {}", e);
+            }
+        }
+        if self.ship {
+            println!("{}", json);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(file) = &mut self.file {
+            if let Err(e) = file.writer.flush() {
+                warn!("Failed to write access log: {}", e);
+            }
+        }
+    }
+}
+
+/// Log file moved aside as `<path>.1` once it reaches `max_size` bytes,
+/// keeping `max_files` old files
+struct RotatingFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: usize) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+
+        if self.max_files > 0 {
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    std::fs::rename(&from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// A request being served, written to its log when the last clone drops
+#[derive(Clone)]
+pub struct AccessEntry {
+    state: Arc<EntryState>,
+}
+
+struct EntryState {
+    log: Arc<AccessLog>,
+    timestamp: DateTime<Utc>,
+    started: Instant,
+    protocol: &'static str,
+    source: SocketAddr,
+    details: Mutex<Details>,
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+}
+
+#[derive(Default)]
+struct Details {
+    user: Option<String>,
+    destination: Option<String>,
+    method: Option<String>,
+    status: Option<u16>,
+    termination: Option<Termination>,
+}
+
+impl AccessEntry {
+    pub fn new(log: Arc<AccessLog>, protocol: &'static str, source: SocketAddr) -> Self {
+        Self {
+            state: Arc::new(EntryState {
+                log,
+                timestamp: Utc::now(),
+                started: Instant::now(),
+                protocol,
+                source,
+                details: Mutex::new(Details::default()),
+                bytes_up: AtomicU64::new(0),
+                bytes_down: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Details)) {
+        update(&mut self.state.details.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+}
+
+impl EntryState {
+    fn record(&self) -> AccessRecord {
+        let details = std::mem::take(&mut *self.details.lock().unwrap_or_else(|e| e.into_inner()));
+        AccessRecord {
+            timestamp: self.timestamp,
+            protocol: self.protocol,
+            user: details.user,
+            source: self.source,
+            destination: details.destination,
+            method: details.method,
+            status: details.status,
+            bytes_up: self.bytes_up.load(Ordering::Relaxed),
+            bytes_down: self.bytes_down.load(Ordering::Relaxed),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            termination: details.termination.unwrap_or(Termination::Completed),
+        }
+    }
+}
+
+impl Drop for EntryState {
+    fn drop(&mut self) {
+        let record = self.record();
+        self.log.write(record);
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: RefCell<Option<AccessEntry>>;
+}
+
+/// Run a connection's handler. Requests it begins are logged through the
+/// functions of this module; a handler failing marks the request it was
+/// serving as [`Termination::Error`] unless it ended otherwise.
+pub async fn connection<T, F>(future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    CURRENT
+        .scope(RefCell::new(None), async {
+            let result = future.await;
+            if result.is_err() {
+                terminate(Termination::Error);
+            }
+            finish();
+            result
+        })
+        .await
+}
+
+/// Run `future`, e.g. a spawned tunnel direction, as part of the request
+/// the current task serves
+pub fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let entry = CURRENT
+        .try_with(|current| current.borrow().clone())
+        .ok()
+        .flatten();
+    CURRENT.scope(RefCell::new(entry), future)
+}
+
+/// Make `entry` the request the current task serves, ending the previous one
+pub fn begin(entry: AccessEntry) {
+    let previous = CURRENT.try_with(|current| current.replace(Some(entry)));
+    drop(previous);
+}
+
+/// End the request the current task serves
+pub fn finish() {
+    let previous = CURRENT.try_with(|current| current.take());
+    drop(previous);
+}
+
+fn with_current(f: impl FnOnce(&AccessEntry)) {
+    let _ = CURRENT.try_with(|current| {
+        if let Some(entry) = current.borrow().as_ref() {
+            f(entry);
+        }
+    });
+}
+
+pub fn set_user(user: &str) {
+    with_current(|entry| entry.update(|details| details.user = Some(user.to_string())));
+}
+
+/// Destination as the client named it, usually `host:port`
+pub fn set_destination(destination: &str) {
+    with_current(|entry| {
+        entry.update(|details| details.destination = Some(destination.to_string()))
+    });
+}
+
+pub fn set_method(method: &str) {
+    with_current(|entry| entry.update(|details| details.method = Some(method.to_string())));
+}
+
+pub fn set_status(status: u16) {
+    with_current(|entry| entry.update(|details| details.status = Some(status)));
+}
+
+/// Count `bytes` moved in `direction`
+pub fn add_bytes(direction: Direction, bytes: u64) {
+    with_current(|entry| {
+        let counter = match direction {
+            Direction::Upload => &entry.state.bytes_up,
+            Direction::Download => &entry.state.bytes_down,
+        };
+        counter.fetch_add(bytes, Ordering::Relaxed);
+    });
+}
+
+/// Record why the request ended; the first reason given is kept
+pub fn terminate(reason: Termination) {
+    with_current(|entry| {
+        entry.update(|details| {
+            details.termination.get_or_insert(reason);
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> AccessRecord {
+        AccessRecord {
+            timestamp: DateTime::parse_from_rfc3339("2026-10-18T12:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            protocol: "http",
+            user: Some("alice".to_string()),
+            source: "203.0.113.5:40000".parse().unwrap(),
+            destination: Some("example.com:443".to_string()),
+            method: Some("CONNECT".to_string()),
+            status: Some(200),
+            bytes_up: 1024,
+            bytes_down: 5120,
+            duration_ms: 350,
+            termination: Termination::ClientClosed,
+        }
+    }
+
+    #[test]
+    fn test_record_formats() {
+        let record = record();
+        assert_eq!(
+            record.to_combined(),
+            "203.0.113.5 - alice [18/Oct/2026:12:00:00 +0000] \
+             \"CONNECT example.com:443 HTTP/1.1\" 200 5120 \"-\" \"-\" 1024 350 client_closed"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&record.to_json()).unwrap();
+        assert_eq!(json["log"], LOG_KIND);
+        assert_eq!(json["user"], "alice");
+        assert_eq!(json["bytes_down"], 5120);
+        assert_eq!(json["termination"], "client_closed");
+    }
+
+    #[test]
+    fn test_rotation_keeps_max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let mut file = RotatingFile::open(&path, 16, 2).unwrap();
+
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line).unwrap();
+        }
+        file.writer.flush().unwrap();
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "fourth line\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "third line\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "second line\n");
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[tokio::test]
+    async fn test_entry_written_when_request_ends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let log = AccessLog::open(&AccessLogConfig {
+            path: Some(path.clone()),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+
+        let source: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let served: Result<()> = connection(async {
+            begin(AccessEntry::new(log.clone(), "socks5", source));
+            set_user("bob");
+            set_destination("example.com:80");
+            // Bytes counted by tunnel tasks belong to the same request
+            tokio::spawn(inherit(async {
+                add_bytes(Direction::Download, 300);
+            }))
+            .await
+            .unwrap();
+            add_bytes(Direction::Upload, 100);
+            terminate(Termination::UpstreamClosed);
+            terminate(Termination::Error);
+            Ok(())
+        })
+        .await;
+        served.unwrap();
+
+        let mut content = String::new();
+        for _ in 0..50 {
+            content = std::fs::read_to_string(&path).unwrap();
+            if !content.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let json: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(json["user"], "bob");
+        assert_eq!(json["destination"], "example.com:80");
+        assert_eq!(json["bytes_up"], 100);
+        assert_eq!(json["bytes_down"], 300);
+        assert_eq!(json["termination"], "upstream_closed");
+    }
+}
@@ -0,0 +1,737 @@
+//! Destination access control lists
+//!
+//! An [`AccessList`] decides per request whether a user may reach a
+//! destination. Rules match on destination domain, address range, country
+//! and port and are evaluated in order, first match wins: the user's own rules
+//! first, then the global rules, then the user's default, then the global
+//! default. The list is kept in an `acl.yaml` file:
+//!
+//! ```yaml
+//! default: allow
+//! rules:
+//!   - action: deny
+//!     cidrs: ["10.0.0.0/8", "169.254.0.0/16"]
+//!   - action: deny
+//!     ports: ["25"]
+//!   - action: deny
+//!     countries: ["KP"]
+//! users:
+//!   alice:
+//!     default: deny
+//!     rules:
+//!       - action: allow
+//!         domains: ["*.example.com"]
+//!         ports: ["443"]
+//! ```
+//!
+//! Domain patterns are exact (`example.com`), subdomains only
+//! (`*.example.com`) or a suffix covering the domain and its subdomains
+//! (`.example.com`). Countries are ISO 3166-1 alpha-2 codes, looked up for
+//! the destination's addresses in the GeoIP database (see [`crate::geoip`]);
+//! without one, country rules match nothing. [`AclStore`] serves the
+//! current list and reloads the file when it changes.
+
+use crate::error::{ProxyError, Result};
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// What a rule does with a matching request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl fmt::Display for AclAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allow"),
+            Self::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// Single port or inclusive port range, written `443` or `8000-9000`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+impl std::str::FromStr for PortRange {
+    type Err = ProxyError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || ProxyError::config(format!("Invalid port range: {}", s));
+        let (start, end) = match s.trim().split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (s.trim(), s.trim()),
+        };
+        let start: u16 = start.parse().map_err(|_| invalid())?;
+        let end: u16 = end.parse().map_err(|_| invalid())?;
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = ProxyError;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(range: PortRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Access rule. A rule without domains, CIDRs and countries matches every
+/// destination, one without ports every port.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AclRule {
+    pub action: AclAction,
+
+    /// Domain patterns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domains: Vec<String>,
+
+    /// Address ranges, matched against literal and resolved addresses
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cidrs: Vec<IpNetwork>,
+
+    /// Country codes, matched against the countries of the addresses
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub countries: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortRange>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl AclRule {
+    /// Rule for `target`, an address, CIDR or domain pattern
+    pub fn for_target(action: AclAction, target: &str, ports: Vec<PortRange>) -> Result<Self> {
+        let target = target.trim();
+        let mut rule = Self {
+            action,
+            domains: Vec::new(),
+            cidrs: Vec::new(),
+            countries: Vec::new(),
+            ports,
+            comment: None,
+        };
+
+        if let Ok(network) = target.parse::<IpNetwork>() {
+            rule.cidrs.push(network);
+        } else {
+            validate_pattern(target)?;
+            rule.domains.push(target.to_ascii_lowercase());
+        }
+        Ok(rule)
+    }
+
+    fn matches(&self, destination: &Destination<'_>) -> bool {
+        if !self.ports.is_empty() && !self.ports.iter().any(|p| p.contains(destination.port)) {
+            return false;
+        }
+        if self.domains.is_empty() && self.cidrs.is_empty() && self.countries.is_empty() {
+            return true;
+        }
+
+        let domain_match = destination.domain().is_some_and(|domain| {
+            self.domains
+                .iter()
+                .any(|pattern| domain_matches(pattern, &domain))
+        });
+        let country_match = destination.countries.iter().any(|country| {
+            self.countries
+                .iter()
+                .any(|code| code.eq_ignore_ascii_case(country))
+        });
+        domain_match
+            || country_match
+            || destination
+                .addrs
+                .iter()
+                .any(|addr| self.cidrs.iter().any(|cidr| cidr.contains(*addr)))
+    }
+}
+
+impl fmt::Display for AclRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut targets: Vec<String> = self.domains.clone();
+        targets.extend(self.cidrs.iter().map(ToString::to_string));
+        targets.extend(
+            self.countries
+                .iter()
+                .map(|code| format!("country {}", code)),
+        );
+        let targets = if targets.is_empty() {
+            "*".to_string()
+        } else {
+            targets.join(", ")
+        };
+        write!(f, "{} {}", self.action, targets)?;
+        if !self.ports.is_empty() {
+            let ports: Vec<String> = self.ports.iter().map(ToString::to_string).collect();
+            write!(f, " port {}", ports.join(","))?;
+        }
+        if let Some(comment) = &self.comment {
+            write!(f, " ({})", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rules of a single user
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserAcl {
+    /// Action when none of the user's or the global rules match; falls
+    /// back to the global default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<AclAction>,
+
+    #[serde(default)]
+    pub rules: Vec<AclRule>,
+}
+
+/// Global and per-user destination rules
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccessList {
+    #[serde(default)]
+    pub default: AclAction,
+
+    #[serde(default)]
+    pub rules: Vec<AclRule>,
+
+    /// Rules by the user ID the proxy authenticates a user as (the
+    /// username for the `file` backend)
+    #[serde(default)]
+    pub users: BTreeMap<String, UserAcl>,
+}
+
+/// Where a request wants to go
+#[derive(Debug, Clone)]
+pub struct Destination<'a> {
+    pub host: &'a str,
+    /// Addresses of the host; the host itself if it is an address
+    pub addrs: Vec<IpAddr>,
+    /// Countries of `addrs`
+    pub countries: Vec<String>,
+    pub port: u16,
+}
+
+impl<'a> Destination<'a> {
+    /// Destination `host:port`, with the addresses `host` resolved to
+    pub fn new(host: &'a str, resolved: &[IpAddr], port: u16) -> Self {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let mut addrs = resolved.to_vec();
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            addrs.push(ip);
+        }
+        Self {
+            host,
+            addrs,
+            countries: Vec::new(),
+            port,
+        }
+    }
+
+    /// The destination with its addresses placed in `countries`
+    pub fn with_countries(mut self, countries: Vec<String>) -> Self {
+        self.countries = countries;
+        self
+    }
+
+    /// Normalized domain name, unless the host is an address
+    fn domain(&self) -> Option<String> {
+        if self.host.parse::<IpAddr>().is_ok() {
+            return None;
+        }
+        Some(self.host.trim_end_matches('.').to_ascii_lowercase())
+    }
+}
+
+/// Which part of the list decided a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclScope {
+    /// The user's rule at this index
+    User(usize),
+    /// The global rule at this index
+    Global(usize),
+    /// The user's default
+    UserDefault,
+    /// The global default
+    Default,
+}
+
+/// Outcome of evaluating a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclDecision {
+    pub action: AclAction,
+    pub scope: AclScope,
+}
+
+impl AclDecision {
+    pub fn allowed(&self) -> bool {
+        self.action == AclAction::Allow
+    }
+}
+
+impl AccessList {
+    /// Decide whether `user_id` may reach `destination`
+    pub fn evaluate(&self, user_id: &str, destination: &Destination<'_>) -> AclDecision {
+        let user = self.users.get(user_id);
+
+        if let Some(user) = user {
+            if let Some((index, rule)) = first_match(&user.rules, destination) {
+                return AclDecision {
+                    action: rule.action,
+                    scope: AclScope::User(index),
+                };
+            }
+        }
+        if let Some((index, rule)) = first_match(&self.rules, destination) {
+            return AclDecision {
+                action: rule.action,
+                scope: AclScope::Global(index),
+            };
+        }
+        match user.and_then(|user| user.default) {
+            Some(action) => AclDecision {
+                action,
+                scope: AclScope::UserDefault,
+            },
+            None => AclDecision {
+                action: self.default,
+                scope: AclScope::Default,
+            },
+        }
+    }
+
+    /// Whether rules applying to `user_id` match on addresses or
+    /// countries, so domain destinations have to be resolved before
+    /// evaluation
+    pub fn needs_addresses(&self, user_id: &str) -> bool {
+        self.rules_for(user_id)
+            .any(|rule| !rule.cidrs.is_empty() || !rule.countries.is_empty())
+    }
+
+    /// Whether rules applying to `user_id` match on countries, so the
+    /// destination's addresses have to be looked up before evaluation
+    pub fn needs_countries(&self, user_id: &str) -> bool {
+        self.rules_for(user_id)
+            .any(|rule| !rule.countries.is_empty())
+    }
+
+    /// Whether any rule matches on countries
+    pub fn has_country_rules(&self) -> bool {
+        let user_rules = self.users.values().flat_map(|user| user.rules.iter());
+        self.rules
+            .iter()
+            .chain(user_rules)
+            .any(|rule| !rule.countries.is_empty())
+    }
+
+    fn rules_for<'a>(&'a self, user_id: &str) -> impl Iterator<Item = &'a AclRule> {
+        let user_rules = self.users.get(user_id).map(|user| user.rules.as_slice());
+        self.rules.iter().chain(user_rules.unwrap_or_default())
+    }
+
+    /// Whether the list lets everything through
+    pub fn is_empty(&self) -> bool {
+        self.default == AclAction::Allow && self.rules.is_empty() && self.users.is_empty()
+    }
+
+    /// Check domain patterns and country codes
+    pub fn validate(&self) -> Result<()> {
+        let user_rules = self.users.values().flat_map(|user| user.rules.iter());
+        for rule in self.rules.iter().chain(user_rules) {
+            for pattern in &rule.domains {
+                validate_pattern(pattern)?;
+            }
+            for code in &rule.countries {
+                validate_country(code)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load a list from `path`; a missing file is an empty list
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let acl: Self = serde_yaml::from_str(&content).map_err(|e| {
+            ProxyError::config(format!("Failed to parse {}: {}", path.display(), e))
+        })?;
+        acl.validate()?;
+        Ok(acl)
+    }
+
+    /// Save the list to `path`, replacing it atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_yaml::to_string(self)
+            .map_err(|e| ProxyError::config(format!("Failed to serialize ACL: {}", e)))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, content)?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+}
+
+fn first_match<'r>(
+    rules: &'r [AclRule],
+    destination: &Destination<'_>,
+) -> Option<(usize, &'r AclRule)> {
+    rules
+        .iter()
+        .enumerate()
+        .find(|(_, rule)| rule.matches(destination))
+}
+
+/// Whether `domain` (normalized) matches `pattern`
+fn domain_matches(pattern: &str, domain: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+
+    if pattern == "*" {
+        true
+    } else if let Some(parent) = pattern.strip_prefix("*.") {
+        domain
+            .strip_suffix(parent)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+    } else if let Some(parent) = pattern.strip_prefix('.') {
+        domain == parent || domain.ends_with(&pattern)
+    } else {
+        domain == pattern
+    }
+}
+
+fn validate_pattern(pattern: &str) -> Result<()> {
+    let name = pattern
+        .strip_prefix("*.")
+        .or_else(|| pattern.strip_prefix('.'))
+        .unwrap_or(pattern)
+        .trim_end_matches('.');
+    let valid = pattern == "*"
+        || (!name.is_empty()
+            && name.split('.').all(|label| {
+                !label.is_empty()
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            }));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ProxyError::config(format!(
+            "Invalid domain pattern: {}",
+            pattern
+        )))
+    }
+}
+
+fn validate_country(code: &str) -> Result<()> {
+    if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(())
+    } else {
+        Err(ProxyError::config(format!(
+            "Invalid country code: {} (expected ISO 3166-1 alpha-2, e.g. DE)",
+            code
+        )))
+    }
+}
+
+/// Current access list, reloaded from its file when the file changes
+pub struct AclStore {
+    path: Option<PathBuf>,
+    current: RwLock<Arc<AccessList>>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl AclStore {
+    /// Store that lets everything through
+    pub fn allow_all() -> Self {
+        Self {
+            path: None,
+            current: RwLock::new(Arc::new(AccessList::default())),
+            modified: Mutex::new(None),
+        }
+    }
+
+    /// Store backed by the file at `path`
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let modified = modified_time(&path);
+        let acl = AccessList::load(&path)?;
+        info!(
+            "Loaded access list {} ({} global rules, {} users)",
+            path.display(),
+            acl.rules.len(),
+            acl.users.len()
+        );
+
+        Ok(Self {
+            path: Some(path),
+            current: RwLock::new(Arc::new(acl)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// The list in effect
+    pub fn current(&self) -> Arc<AccessList> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Reload the file if it changed since it was last read. An invalid
+    /// file is reported and the list in effect is kept.
+    pub fn reload(&self) -> Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+
+        let modified = modified_time(path);
+        {
+            let mut last = self.modified.lock().unwrap_or_else(|e| e.into_inner());
+            if *last == modified {
+                return Ok(false);
+            }
+            *last = modified;
+        }
+
+        let acl = AccessList::load(path)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(acl);
+        info!("Reloaded access list {}", path.display());
+        Ok(true)
+    }
+
+    /// Check the file for changes every `interval`
+    pub fn watch(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.reload() {
+                    warn!("Keeping previous access list: {}", e);
+                }
+            }
+        })
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const ACL: &str = r#"
+default: allow
+rules:
+  - action: deny
+    cidrs: ["10.0.0.0/8"]
+  - action: deny
+    ports: ["25", "6660-6669"]
+users:
+  alice:
+    default: deny
+    rules:
+      - action: allow
+        domains: ["*.example.com", "example.org"]
+        ports: ["443"]
+  bob:
+    rules:
+      - action: allow
+        cidrs: ["10.1.0.0/16"]
+"#;
+
+    fn acl() -> AccessList {
+        let acl: AccessList = serde_yaml::from_str(ACL).unwrap();
+        acl.validate().unwrap();
+        acl
+    }
+
+    fn decide(acl: &AccessList, user: &str, host: &str, resolved: &[IpAddr], port: u16) -> bool {
+        acl.evaluate(user, &Destination::new(host, resolved, port))
+            .allowed()
+    }
+
+    #[test]
+    fn test_domain_patterns() {
+        assert!(domain_matches("example.com", "example.com"));
+        assert!(!domain_matches("example.com", "www.example.com"));
+        assert!(domain_matches("*.example.com", "www.example.com"));
+        assert!(!domain_matches("*.example.com", "example.com"));
+        assert!(!domain_matches("*.example.com", "badexample.com"));
+        assert!(domain_matches(".example.com", "example.com"));
+        assert!(domain_matches(".example.com", "a.b.example.com"));
+        assert!(!domain_matches(".example.com", "notexample.com"));
+        assert!(domain_matches("*", "anything.test"));
+
+        assert!(validate_pattern("*.example.com").is_ok());
+        assert!(validate_pattern("exa*mple.com").is_err());
+        assert!(validate_pattern("").is_err());
+    }
+
+    #[test]
+    fn test_evaluation_order() {
+        let acl = acl();
+        let private: IpAddr = "10.2.3.4".parse().unwrap();
+        let public: IpAddr = "93.184.216.34".parse().unwrap();
+
+        // Global rules
+        assert!(decide(&acl, "carol", "example.net", &[public], 443));
+        assert!(!decide(&acl, "carol", "10.2.3.4", &[], 443));
+        assert!(!decide(&acl, "carol", "internal.test", &[private], 443));
+        assert!(!decide(&acl, "carol", "mail.test", &[public], 25));
+        assert!(!decide(&acl, "carol", "irc.test", &[public], 6667));
+
+        // User rules come first, then the user's default
+        assert!(decide(&acl, "alice", "www.example.com", &[public], 443));
+        assert!(decide(&acl, "alice", "EXAMPLE.ORG.", &[public], 443));
+        assert!(!decide(&acl, "alice", "www.example.com", &[public], 80));
+        assert!(!decide(&acl, "alice", "example.net", &[public], 443));
+        assert!(decide(&acl, "bob", "10.1.0.5", &[], 22));
+        assert!(!decide(&acl, "bob", "10.2.0.5", &[], 22));
+
+        assert_eq!(
+            acl.evaluate("alice", &Destination::new("example.net", &[], 443))
+                .scope,
+            AclScope::UserDefault
+        );
+        assert!(acl.needs_addresses("carol"));
+    }
+
+    #[test]
+    fn test_country_rules() {
+        let acl: AccessList = serde_yaml::from_str(
+            r#"
+default: allow
+rules:
+  - action: deny
+    countries: ["kp", "IR"]
+users:
+  alice:
+    default: deny
+    rules:
+      - action: allow
+        countries: ["DE"]
+        ports: ["443"]
+"#,
+        )
+        .unwrap();
+        acl.validate().unwrap();
+        assert!(acl.has_country_rules());
+        assert!(acl.needs_countries("carol"));
+        assert!(acl.needs_addresses("carol"));
+
+        let public: IpAddr = "93.184.216.34".parse().unwrap();
+        let to = |countries: &[&str], port| {
+            Destination::new("example.net", &[public], port)
+                .with_countries(countries.iter().map(|c| c.to_string()).collect())
+        };
+        assert!(!acl.evaluate("carol", &to(&["KP"], 443)).allowed());
+        assert!(acl.evaluate("carol", &to(&["US"], 443)).allowed());
+        // Without a country, as without a GeoIP database
+        assert!(acl.evaluate("carol", &to(&[], 443)).allowed());
+
+        assert!(acl.evaluate("alice", &to(&["DE"], 443)).allowed());
+        assert!(!acl.evaluate("alice", &to(&["DE"], 80)).allowed());
+        assert!(!acl.evaluate("alice", &to(&["US", "IR"], 443)).allowed());
+        assert_eq!(acl.rules[0].to_string(), "deny country kp, country IR");
+
+        let mut invalid = acl.clone();
+        invalid.rules[0].countries.push("DEU".to_string());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_rule_for_target() {
+        let rule = AclRule::for_target(AclAction::Deny, "192.168.0.0/16", Vec::new()).unwrap();
+        assert_eq!(rule.cidrs.len(), 1);
+        let rule = AclRule::for_target(
+            AclAction::Allow,
+            "*.Example.com",
+            vec!["443".parse().unwrap()],
+        )
+        .unwrap();
+        assert_eq!(rule.domains, ["*.example.com"]);
+        assert_eq!(rule.to_string(), "allow *.example.com port 443");
+        assert!(AclRule::for_target(AclAction::Deny, "not a host", Vec::new()).is_err());
+        assert!("9000-8000".parse::<PortRange>().is_err());
+    }
+
+    #[test]
+    fn test_store_reloads_changed_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("acl.yaml");
+        let store = AclStore::open(&path).unwrap();
+        assert!(store.current().is_empty());
+
+        let mut acl = AccessList::default();
+        acl.rules
+            .push(AclRule::for_target(AclAction::Deny, "blocked.test", Vec::new()).unwrap());
+        acl.save(&path).unwrap();
+        assert!(store.reload().unwrap());
+        let current = store.current();
+        assert!(!decide(&current, "anyone", "blocked.test", &[], 443));
+        assert!(!store.reload().unwrap());
+
+        // A broken file keeps the list in effect
+        std::fs::write(&path, "rules: [").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert!(store.reload().is_err());
+        assert_eq!(store.current().rules.len(), 1);
+    }
+}
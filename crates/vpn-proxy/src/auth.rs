@@ -1,243 +1,230 @@
 //! Authentication module for proxy server
 
 use crate::{
-    config::{AuthBackend, AuthConfig},
+    auth_backend::{self, verify_password, AuthBackend, CredentialCache, VerifiedUser},
+    config::AuthConfig,
     error::{ProxyError, Result},
 };
+use argon2::PasswordHash;
 use dashmap::DashMap;
+use ipnetwork::IpNetwork;
+use std::net::IpAddr;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tracing::debug;
-use vpn_users::UserManager;
+use tracing::{debug, info, warn};
+use vpn_types::{AuthAuditEvent, AuthObserver, LoginGuard};
 
-/// Cached authentication entry
+/// Credential registered at runtime through [`AuthManager::provision`]
 #[derive(Clone, Debug)]
-struct CachedAuth {
+struct ProvisionedCredential {
     user_id: String,
-    expires_at: Instant,
+    /// Argon2 hash of the password
+    password_hash: String,
 }
 
 /// Authentication manager
 pub struct AuthManager {
     config: AuthConfig,
-    cache: Arc<DashMap<String, CachedAuth>>,
-    user_manager: Option<Arc<UserManager>>,
+    backend: Arc<dyn AuthBackend>,
+    cache: CredentialCache,
+    /// Credentials of users created after startup, checked before the
+    /// backend; `None` for users deleted since, whom the backend may still
+    /// know
+    provisioned: Arc<DashMap<String, Option<ProvisionedCredential>>>,
+    guard: Arc<LoginGuard>,
+}
+
+/// Writes authentication audit events to the `audit` log target
+struct AuditLog;
+
+impl AuthObserver for AuditLog {
+    fn observe(&self, event: &AuthAuditEvent) {
+        if let Ok(event) = serde_json::to_string(event) {
+            info!(target: "audit", "{}", event);
+        }
+    }
 }
 
 impl AuthManager {
     /// Create a new authentication manager
     pub fn new(config: &AuthConfig) -> Result<Self> {
-        let user_manager = match &config.backend {
-            AuthBackend::VpnUsers => {
-                // Initialize user manager with a default server config
-                let server_config = vpn_users::config::ServerConfig {
-                    host: "proxy.local".to_string(),
-                    port: 8080,
-                    sni: None,
-                    public_key: None,
-                    private_key: None,
-                    short_id: None,
-                    reality_dest: None,
-                    reality_server_names: vec![],
-                };
-                let user_mgr =
-                    UserManager::new(std::path::Path::new("/var/lib/vpn/users"), server_config)
-                        .map_err(|e| {
-                            ProxyError::config(format!("Failed to init user manager: {}", e))
-                        })?;
-                Some(Arc::new(user_mgr))
-            }
-            _ => None,
-        };
+        Self::with_backend(config, auth_backend::from_config(&config.backend)?)
+    }
+
+    /// Create an authentication manager checking credentials with
+    /// `backend` instead of the configured one
+    pub fn with_backend(config: &AuthConfig, backend: Arc<dyn AuthBackend>) -> Result<Self> {
+        let guard =
+            LoginGuard::new(config.lockout.clone(), "proxy").with_observer(Arc::new(AuditLog));
 
         Ok(Self {
             config: config.clone(),
-            cache: Arc::new(DashMap::new()),
-            user_manager,
+            backend,
+            cache: CredentialCache::new(config.cache_ttl),
+            provisioned: Arc::new(DashMap::new()),
+            guard: Arc::new(guard),
         })
     }
 
-    /// Authenticate a user with username and password
-    pub async fn authenticate(&self, username: &str, password: &str) -> Result<String> {
-        // Check cache first
-        let cache_key = format!("{}:{}", username, password);
-        if let Some(cached) = self.cache.get(&cache_key) {
-            if cached.expires_at > Instant::now() {
-                debug!("Authentication cache hit for user: {}", username);
-                return Ok(cached.user_id.clone());
-            } else {
-                // Remove expired entry
-                self.cache.remove(&cache_key);
-            }
-        }
-
-        // Authenticate based on backend
-        let user_id = match &self.config.backend {
-            AuthBackend::VpnUsers => self.authenticate_vpn_user(username, password).await?,
-            AuthBackend::File { path } => {
-                self.authenticate_from_file(username, password, path)
-                    .await?
-            }
-            AuthBackend::Ldap { url } => self.authenticate_ldap(username, password, url).await?,
-            AuthBackend::Http { url } => self.authenticate_http(username, password, url).await?,
-        };
-
-        // Cache successful authentication
-        let cached = CachedAuth {
-            user_id: user_id.clone(),
-            expires_at: Instant::now() + self.config.cache_ttl,
-        };
-        self.cache.insert(cache_key, cached);
-
-        Ok(user_id)
-    }
-
-    /// Authenticate using VPN user database
-    async fn authenticate_vpn_user(&self, username: &str, password: &str) -> Result<String> {
-        let user_manager = self
-            .user_manager
-            .as_ref()
-            .ok_or_else(|| ProxyError::config("User manager not initialized"))?;
-
-        // Find user by name
-        let users = user_manager
-            .list_users(None)
-            .await
-            .map_err(|e| ProxyError::auth_failed(format!("Failed to list users: {}", e)))?;
-
-        let user = users
-            .iter()
-            .find(|u| u.name == username)
-            .ok_or_else(|| ProxyError::auth_failed("User not found"))?;
-
-        // Check if user is active
-        if !user.is_active() {
-            return Err(ProxyError::auth_failed("User is not active"));
+    /// Authentication manager for `config` that keeps the credentials
+    /// provisioned here. Lockouts carry over unless the lockout policy
+    /// changed; cached credentials are dropped.
+    pub fn reconfigured(&self, config: &AuthConfig) -> Result<Self> {
+        let mut manager = Self::new(config)?;
+        manager.provisioned = self.provisioned.clone();
+        if config.lockout == self.config.lockout {
+            manager.guard = self.guard.clone();
         }
-
-        // Verify password (using user's private key as password for now)
-        let expected_password = user.config.private_key.as_deref().unwrap_or(&user.id);
-
-        if password != expected_password {
-            return Err(ProxyError::auth_failed("Invalid password"));
-        }
-
-        Ok(user.id.clone())
+        Ok(manager)
     }
 
-    /// Authenticate from a static file
-    async fn authenticate_from_file(
+    /// Authenticate a user with username and password connecting from
+    /// `source`. Credentials bound to source networks are rejected anywhere
+    /// else, even when the password is correct. While `source` or the
+    /// username is locked out after failed attempts every attempt is
+    /// rejected.
+    pub async fn authenticate(
         &self,
         username: &str,
         password: &str,
-        path: &std::path::Path,
+        source: IpAddr,
     ) -> Result<String> {
-        use tokio::fs::File;
-        use tokio::io::{AsyncBufReadExt, BufReader};
-
-        let file = File::open(path)
-            .await
-            .map_err(|e| ProxyError::config(format!("Failed to open auth file: {}", e)))?;
-
-        let reader = BufReader::new(file);
-        let mut lines = reader.lines();
-
-        while let Some(line) = lines.next_line().await? {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
-
-            // Format: username:password_hash
-            let parts: Vec<&str> = line.splitn(2, ':').collect();
-            if parts.len() != 2 {
-                continue;
-            }
-
-            if parts[0] == username {
-                // Verify password using argon2
-                if verify_password(password, parts[1])? {
-                    return Ok(username.to_string());
-                } else {
-                    return Err(ProxyError::auth_failed("Invalid password"));
+        self.guard
+            .check(source, username)
+            .map_err(|lockout| ProxyError::auth_failed(lockout.to_string()))?;
+
+        let result = self.verify_credentials(username, password, source).await;
+        match &result {
+            Ok(_) => self.guard.record_success(source, username),
+            Err(ProxyError::AuthenticationFailed(_)) => {
+                if let Some(lockout) = self.guard.record_failure(source, username) {
+                    warn!("Locked out {} after failed attempts", lockout.key);
                 }
             }
+            Err(_) => {}
         }
-
-        Err(ProxyError::auth_failed("User not found"))
+        result
     }
 
-    /// Authenticate via LDAP
-    async fn authenticate_ldap(
+    async fn verify_credentials(
         &self,
-        _username: &str,
-        _password: &str,
-        _url: &str,
+        username: &str,
+        password: &str,
+        source: IpAddr,
     ) -> Result<String> {
-        // TODO: Implement LDAP authentication
-        Err(ProxyError::config(
-            "LDAP authentication not yet implemented",
-        ))
-    }
-
-    /// Authenticate via HTTP API
-    async fn authenticate_http(&self, username: &str, password: &str, url: &str) -> Result<String> {
-        use reqwest::Client;
-        use serde_json::json;
-
-        let client = Client::new();
-        let response = client
-            .post(url)
-            .json(&json!({
-                "username": username,
-                "password": password
-            }))
-            .timeout(Duration::from_secs(10))
-            .send()
-            .await
-            .map_err(|e| ProxyError::auth_failed(format!("HTTP auth request failed: {}", e)))?;
+        if let Some(cached) = self.cache.get(username, password) {
+            debug!("Authentication cache hit for user: {}", username);
+            self.check_source(username, &cached.allowed_sources, source)?;
+            return Ok(cached.user_id);
+        }
 
-        if response.status().is_success() {
-            let body: serde_json::Value = response
-                .json()
+        let provisioned = self
+            .provisioned
+            .get(username)
+            .map(|entry| entry.value().clone());
+        let verified = if let Some(provisioned) = provisioned {
+            let provisioned =
+                provisioned.ok_or_else(|| ProxyError::auth_failed("User not found"))?;
+            if !verify_password(password, &provisioned.password_hash)? {
+                return Err(ProxyError::auth_failed("Invalid password"));
+            }
+            VerifiedUser {
+                user_id: provisioned.user_id,
+                allowed_sources: Vec::new(),
+            }
+        } else {
+            self.backend
+                .verify(username, password)
                 .await
-                .map_err(|e| ProxyError::auth_failed(format!("Invalid auth response: {}", e)))?;
+                .inspect_err(|e| {
+                    if matches!(e, ProxyError::AuthBackend(_)) {
+                        warn!("{} auth backend failed: {}", self.backend.name(), e);
+                    }
+                })?
+        };
 
-            let user_id = body["user_id"]
-                .as_str()
-                .ok_or_else(|| ProxyError::auth_failed("Missing user_id in response"))?;
+        self.cache.insert(username, password, verified.clone());
+        self.check_source(username, &verified.allowed_sources, source)?;
+        Ok(verified.user_id)
+    }
+
+    /// Reject `source` when the credential is bound to networks that do not
+    /// contain it. Bindings from the config and the backend are combined.
+    fn check_source(
+        &self,
+        username: &str,
+        backend_sources: &[IpNetwork],
+        source: IpAddr,
+    ) -> Result<()> {
+        let configured = self
+            .config
+            .source_bindings
+            .get(username)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        if configured.is_empty() && backend_sources.is_empty() {
+            return Ok(());
+        }
 
-            Ok(user_id.to_string())
+        // IPv4 clients on a dual-stack listener show up as mapped addresses
+        let source = source.to_canonical();
+        if configured
+            .iter()
+            .chain(backend_sources)
+            .any(|network| network.contains(source))
+        {
+            Ok(())
         } else {
-            Err(ProxyError::auth_failed("Authentication failed"))
+            warn!(
+                "Rejected credential for user {} from unbound address {}",
+                username, source
+            );
+            Err(ProxyError::auth_failed(format!(
+                "Credential not allowed from {}",
+                source
+            )))
         }
     }
 
+    /// Accept `username` with the password hashed to `password_hash` from
+    /// now on, without restarting the proxy. Takes precedence over the
+    /// backend's entry for the same username.
+    pub fn provision(&self, username: &str, user_id: &str, password_hash: &str) -> Result<()> {
+        PasswordHash::new(password_hash)
+            .map_err(|e| ProxyError::config(format!("Invalid password hash: {}", e)))?;
+
+        self.provisioned.insert(
+            username.to_string(),
+            Some(ProvisionedCredential {
+                user_id: user_id.to_string(),
+                password_hash: password_hash.to_string(),
+            }),
+        );
+        self.cache.forget(username);
+        info!("Provisioned credential for user: {}", username);
+        Ok(())
+    }
+
+    /// Reject `username` from now on, whether its credential was
+    /// provisioned or comes from the backend, until it is provisioned
+    /// again. Sessions cached for the username end too.
+    pub fn revoke(&self, username: &str) {
+        self.provisioned.insert(username.to_string(), None);
+        self.cache.forget(username);
+        info!("Revoked credential for user: {}", username);
+    }
+
     /// Clear authentication cache
     pub fn clear_cache(&self) {
         self.cache.clear();
     }
 
-    /// Remove expired cache entries
+    /// Remove expired cache entries and forgotten lockout counters
     pub fn cleanup_cache(&self) {
-        let now = Instant::now();
-        self.cache.retain(|_, v| v.expires_at > now);
+        self.cache.prune();
+        self.guard.prune();
     }
 }
 
-/// Verify password using argon2
-fn verify_password(password: &str, hash: &str) -> Result<bool> {
-    use argon2::{Argon2, PasswordHash, PasswordVerifier};
-
-    let parsed_hash = PasswordHash::new(hash)
-        .map_err(|e| ProxyError::internal(format!("Invalid password hash: {}", e)))?;
-
-    let argon2 = Argon2::default();
-    Ok(argon2
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .is_ok())
-}
-
 /// Hash password using argon2
 pub fn hash_password(password: &str) -> Result<String> {
     use argon2::{
@@ -0,0 +1,581 @@
+//! Pluggable credential checks behind the [`AuthManager`](crate::auth::AuthManager)
+//!
+//! Each [`AuthBackend`] decides whether a username and password are valid.
+//! Wrong credentials are reported as [`ProxyError::AuthenticationFailed`],
+//! which counts towards lockouts. A backend that cannot be asked reports
+//! [`ProxyError::AuthBackend`] instead, so an outage does not lock users
+//! out. Accepted credentials are kept in a [`CredentialCache`] for a short
+//! while so busy users do not reach the backend on every connection.
+
+use crate::config::{AuthBackendConfig, LdapBackendConfig};
+use crate::error::{ProxyError, Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::PasswordHash;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use ipnetwork::IpNetwork;
+use ldap3::{LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vpn_types::CorrelationId;
+use vpn_users::UserManager;
+
+/// Timeout of requests to HTTP backends
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// LDAP result code for wrong credentials
+const LDAP_INVALID_CREDENTIALS: u32 = 49;
+
+/// A user whose credentials were accepted
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedUser {
+    pub user_id: String,
+    /// Source networks the backend binds the credential to; empty if it may
+    /// be used from anywhere
+    pub allowed_sources: Vec<IpNetwork>,
+}
+
+impl VerifiedUser {
+    fn new(user_id: impl Into<String>) -> Self {
+        Self {
+            user_id: user_id.into(),
+            allowed_sources: Vec::new(),
+        }
+    }
+}
+
+/// Checks usernames and passwords against a user store
+#[async_trait]
+pub trait AuthBackend: Send + Sync {
+    /// Short name for logs
+    fn name(&self) -> &'static str;
+
+    /// Accept or reject `password` for `username`
+    async fn verify(&self, username: &str, password: &str) -> Result<VerifiedUser>;
+}
+
+/// Backend selected by `config`
+pub fn from_config(config: &AuthBackendConfig) -> Result<Arc<dyn AuthBackend>> {
+    Ok(match config {
+        AuthBackendConfig::VpnUsers => {
+            Arc::new(VpnUsersBackend::open(Path::new("/var/lib/vpn/users"))?)
+        }
+        AuthBackendConfig::File { path } => Arc::new(HtpasswdBackend::new(path)),
+        AuthBackendConfig::Ldap(ldap) => Arc::new(LdapBackend::new(ldap.clone())?),
+        AuthBackendConfig::Identity { url } => Arc::new(IdentityBackend::new(url)?),
+        AuthBackendConfig::Http { url } => Arc::new(HttpBackend::new(url)?),
+    })
+}
+
+/// Users of the VPN user database
+pub struct VpnUsersBackend {
+    user_manager: UserManager,
+}
+
+impl VpnUsersBackend {
+    /// Open the user database in `users_dir`
+    pub fn open(users_dir: &Path) -> Result<Self> {
+        // Initialize user manager with a default server config
+        let server_config = vpn_users::config::ServerConfig {
+            host: "proxy.local".to_string(),
+            port: 8080,
+            sni: None,
+            public_key: None,
+            private_key: None,
+            short_id: None,
+            reality_dest: None,
+            reality_server_names: vec![],
+        };
+        let user_manager = UserManager::new(users_dir, server_config)
+            .map_err(|e| ProxyError::config(format!("Failed to init user manager: {}", e)))?;
+        Ok(Self { user_manager })
+    }
+}
+
+#[async_trait]
+impl AuthBackend for VpnUsersBackend {
+    fn name(&self) -> &'static str {
+        "vpn-users"
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<VerifiedUser> {
+        let users = self
+            .user_manager
+            .list_users(None)
+            .await
+            .map_err(|e| ProxyError::auth_backend(format!("Failed to list users: {}", e)))?;
+
+        let user = users
+            .iter()
+            .find(|u| u.name == username)
+            .ok_or_else(|| ProxyError::auth_failed("User not found"))?;
+
+        if !user.is_active() {
+            return Err(ProxyError::auth_failed("User is not active"));
+        }
+
+        // Verify password (using user's private key as password for now)
+        let expected_password = user.config.private_key.as_deref().unwrap_or(&user.id);
+        if password != expected_password {
+            return Err(ProxyError::auth_failed("Invalid password"));
+        }
+
+        Ok(VerifiedUser::new(user.id.clone()))
+    }
+}
+
+/// htpasswd-style file of `username:hash[:cidr,...]` lines. Hashes are
+/// Argon2 or bcrypt (`htpasswd -B`); the optional networks bind the
+/// credential to those source addresses. The file is read on every check,
+/// so edits take effect without a restart.
+pub struct HtpasswdBackend {
+    path: PathBuf,
+}
+
+impl HtpasswdBackend {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for HtpasswdBackend {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<VerifiedUser> {
+        let contents = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|e| ProxyError::config(format!("Failed to open auth file: {}", e)))?;
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.splitn(3, ':').collect();
+            if parts.len() < 2 || parts[0] != username {
+                continue;
+            }
+
+            if !verify_password(password, parts[1])? {
+                return Err(ProxyError::auth_failed("Invalid password"));
+            }
+            let allowed_sources = match parts.get(2) {
+                Some(sources) => parse_sources(sources)?,
+                None => Vec::new(),
+            };
+            return Ok(VerifiedUser {
+                user_id: username.to_string(),
+                allowed_sources,
+            });
+        }
+
+        Err(ProxyError::auth_failed("User not found"))
+    }
+}
+
+/// LDAP directory, checking passwords by binding as the user
+pub struct LdapBackend {
+    config: LdapBackendConfig,
+}
+
+impl LdapBackend {
+    pub fn new(config: LdapBackendConfig) -> Result<Self> {
+        if config.user_dn.is_none() && config.base_dn.is_none() {
+            return Err(ProxyError::config(
+                "LDAP backend needs user_dn or base_dn to find users",
+            ));
+        }
+        Ok(Self { config })
+    }
+
+    /// DN of `username`, found with the configured search
+    async fn find_user(&self, ldap: &mut ldap3::Ldap, username: &str) -> Result<String> {
+        let timeout = self.config.timeout;
+        if let (Some(bind_dn), Some(bind_password)) =
+            (&self.config.bind_dn, &self.config.bind_password)
+        {
+            ldap.with_timeout(timeout)
+                .simple_bind(bind_dn, bind_password)
+                .await
+                .and_then(|result| result.success())
+                .map_err(ldap_error)?;
+        }
+
+        let base_dn = self.config.base_dn.as_deref().unwrap_or_default();
+        let filter = self
+            .config
+            .user_filter
+            .replace("{username}", &ldap3::ldap_escape(username));
+        let (mut entries, _) = ldap
+            .with_timeout(timeout)
+            .search(base_dn, Scope::Subtree, &filter, vec!["1.1"])
+            .await
+            .and_then(|result| result.success())
+            .map_err(ldap_error)?;
+
+        match entries.len() {
+            0 => Err(ProxyError::auth_failed("User not found")),
+            1 => Ok(SearchEntry::construct(entries.remove(0)).dn),
+            n => Err(ProxyError::auth_backend(format!(
+                "LDAP search for {} matched {} entries",
+                username, n
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for LdapBackend {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<VerifiedUser> {
+        // An empty password makes a bind unauthenticated, which servers
+        // accept for any DN
+        if password.is_empty() {
+            return Err(ProxyError::auth_failed("Empty password"));
+        }
+
+        let timeout = self.config.timeout;
+        let settings = LdapConnSettings::new().set_conn_timeout(timeout);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+            .await
+            .map_err(ldap_error)?;
+        ldap3::drive!(conn);
+
+        let user_dn = match &self.config.user_dn {
+            Some(template) => template.replace("{username}", &ldap3::dn_escape(username)),
+            None => self.find_user(&mut ldap, username).await?,
+        };
+        let bind = ldap
+            .with_timeout(timeout)
+            .simple_bind(&user_dn, password)
+            .await
+            .map_err(ldap_error);
+        let _ = ldap.unbind().await;
+
+        let bind = bind?;
+        match bind.rc {
+            0 => Ok(VerifiedUser::new(username)),
+            LDAP_INVALID_CREDENTIALS => Err(ProxyError::auth_failed("Invalid credentials")),
+            rc => Err(ProxyError::auth_backend(format!(
+                "LDAP bind failed with result code {}: {}",
+                rc, bind.text
+            ))),
+        }
+    }
+}
+
+fn ldap_error(error: ldap3::LdapError) -> ProxyError {
+    ProxyError::auth_backend(format!("LDAP: {}", error))
+}
+
+/// The vpn-identity service, asked through its login endpoint. Users are
+/// identified by their identity service ID. The service applies its own
+/// lockouts, which are reported as failed attempts.
+pub struct IdentityBackend {
+    login_url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct IdentityLogin {
+    user: IdentityUser,
+}
+
+#[derive(Deserialize)]
+struct IdentityUser {
+    id: String,
+}
+
+impl IdentityBackend {
+    /// Backend for the identity service at `base_url`
+    pub fn new(base_url: &str) -> Result<Self> {
+        Ok(Self {
+            login_url: format!("{}/auth/login", base_url.trim_end_matches('/')),
+            client: http_client()?,
+        })
+    }
+}
+
+#[async_trait]
+impl AuthBackend for IdentityBackend {
+    fn name(&self) -> &'static str {
+        "identity"
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<VerifiedUser> {
+        use reqwest::StatusCode;
+
+        let mut request = self.client.post(&self.login_url).json(&serde_json::json!({
+            "username": username,
+            "password": password,
+        }));
+        if let Some(id) = CorrelationId::current() {
+            request = request.header(CorrelationId::HEADER, id.as_str());
+        }
+        let response = request.send().await.map_err(|e| {
+            ProxyError::auth_backend(format!("Identity service request failed: {}", e))
+        })?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let login: IdentityLogin = response.json().await.map_err(|e| {
+                    ProxyError::auth_backend(format!("Invalid identity service response: {}", e))
+                })?;
+                Ok(VerifiedUser::new(login.user.id))
+            }
+            StatusCode::BAD_REQUEST
+            | StatusCode::UNAUTHORIZED
+            | StatusCode::FORBIDDEN
+            | StatusCode::NOT_FOUND => Err(ProxyError::auth_failed("Invalid credentials")),
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::PRECONDITION_REQUIRED => Err(
+                ProxyError::auth_failed("Locked out by the identity service"),
+            ),
+            status => Err(ProxyError::auth_backend(format!(
+                "Identity service answered {}",
+                status
+            ))),
+        }
+    }
+}
+
+/// External HTTP API taking `{"username", "password"}` and answering
+/// `{"user_id"}` for valid credentials
+pub struct HttpBackend {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpBackend {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            client: http_client()?,
+        })
+    }
+}
+
+#[async_trait]
+impl AuthBackend for HttpBackend {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<VerifiedUser> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({
+                "username": username,
+                "password": password
+            }))
+            .send()
+            .await
+            .map_err(|e| ProxyError::auth_backend(format!("HTTP auth request failed: {}", e)))?;
+
+        if response.status().is_success() {
+            let body: serde_json::Value = response
+                .json()
+                .await
+                .map_err(|e| ProxyError::auth_backend(format!("Invalid auth response: {}", e)))?;
+
+            let user_id = body["user_id"]
+                .as_str()
+                .ok_or_else(|| ProxyError::auth_backend("Missing user_id in response"))?;
+
+            Ok(VerifiedUser::new(user_id))
+        } else if response.status().is_server_error() {
+            Err(ProxyError::auth_backend(format!(
+                "HTTP auth service answered {}",
+                response.status()
+            )))
+        } else {
+            Err(ProxyError::auth_failed("Authentication failed"))
+        }
+    }
+}
+
+fn http_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .build()
+        .map_err(|e| ProxyError::config(format!("Failed to build HTTP client: {}", e)))
+}
+
+/// Recently accepted credentials. Passwords are only kept as salted
+/// SHA-256 digests, and an entry is used only for the same password.
+pub struct CredentialCache {
+    ttl: Duration,
+    salt: [u8; 16],
+    entries: DashMap<String, CachedCredential>,
+}
+
+struct CachedCredential {
+    digest: [u8; 32],
+    user: VerifiedUser,
+    expires_at: Instant,
+}
+
+impl CredentialCache {
+    /// Cache keeping credentials for `ttl`; zero keeps none
+    pub fn new(ttl: Duration) -> Self {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            ttl,
+            salt,
+            entries: DashMap::new(),
+        }
+    }
+
+    /// User accepted with `password` within the TTL
+    pub fn get(&self, username: &str, password: &str) -> Option<VerifiedUser> {
+        let entry = self.entries.get(username)?;
+        if entry.expires_at <= Instant::now() {
+            drop(entry);
+            self.entries
+                .remove_if(username, |_, cached| cached.expires_at <= Instant::now());
+            return None;
+        }
+        (entry.digest == self.digest(password)).then(|| entry.user.clone())
+    }
+
+    /// Remember that `password` was accepted for `username`
+    pub fn insert(&self, username: &str, password: &str, user: VerifiedUser) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entries.insert(
+            username.to_string(),
+            CachedCredential {
+                digest: self.digest(password),
+                user,
+                expires_at: Instant::now() + self.ttl,
+            },
+        );
+    }
+
+    /// Drop the cached credential of `username`
+    pub fn forget(&self, username: &str) {
+        self.entries.remove(username);
+    }
+
+    /// Drop every cached credential
+    pub fn clear(&self) {
+        self.entries.clear();
+    }
+
+    /// Drop expired credentials
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.entries.retain(|_, cached| cached.expires_at > now);
+    }
+
+    fn digest(&self, password: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt);
+        hasher.update(password.as_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Parse a comma-separated list of source addresses or networks
+fn parse_sources(sources: &str) -> Result<Vec<IpNetwork>> {
+    sources
+        .split(',')
+        .map(str::trim)
+        .filter(|source| !source.is_empty())
+        .map(|source| {
+            source.parse::<IpNetwork>().map_err(|e| {
+                ProxyError::config(format!("Invalid source binding {}: {}", source, e))
+            })
+        })
+        .collect()
+}
+
+/// Verify a password against an Argon2 or bcrypt hash
+pub(crate) fn verify_password(password: &str, hash: &str) -> Result<bool> {
+    use argon2::{Argon2, PasswordVerifier};
+
+    if ["$2a$", "$2b$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+    {
+        return bcrypt::verify(password, hash)
+            .map_err(|e| ProxyError::internal(format!("Invalid password hash: {}", e)));
+    }
+
+    let parsed_hash = PasswordHash::new(hash)
+        .map_err(|e| ProxyError::internal(format!("Invalid password hash: {}", e)))?;
+
+    let argon2 = Argon2::default();
+    Ok(argon2
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::hash_password;
+
+    #[tokio::test]
+    async fn test_htpasswd_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("htpasswd");
+        let argon2 = hash_password("secret").unwrap();
+        let bcrypt = bcrypt::hash("hunter2", 4)
+            .unwrap()
+            .replacen("$2b$", "$2y$", 1);
+        std::fs::write(
+            &path,
+            format!("# users\nalice:{}:10.0.0.0/8\nbob:{}\n", argon2, bcrypt),
+        )
+        .unwrap();
+        let backend = HtpasswdBackend::new(&path);
+
+        let alice = backend.verify("alice", "secret").await.unwrap();
+        assert_eq!(alice.user_id, "alice");
+        assert_eq!(alice.allowed_sources, vec!["10.0.0.0/8".parse().unwrap()]);
+        assert_eq!(
+            backend.verify("bob", "hunter2").await.unwrap(),
+            VerifiedUser::new("bob")
+        );
+
+        for (username, password) in [("alice", "wrong"), ("bob", "secret"), ("carol", "x")] {
+            assert!(matches!(
+                backend.verify(username, password).await,
+                Err(ProxyError::AuthenticationFailed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_credential_cache() {
+        let cache = CredentialCache::new(Duration::from_secs(60));
+        cache.insert("alice", "secret", VerifiedUser::new("alice-id"));
+
+        assert_eq!(
+            cache.get("alice", "secret"),
+            Some(VerifiedUser::new("alice-id"))
+        );
+        assert_eq!(cache.get("alice", "other"), None);
+        assert_eq!(cache.get("bob", "secret"), None);
+
+        cache.forget("alice");
+        assert_eq!(cache.get("alice", "secret"), None);
+
+        let disabled = CredentialCache::new(Duration::ZERO);
+        disabled.insert("alice", "secret", VerifiedUser::new("alice-id"));
+        assert_eq!(disabled.get("alice", "secret"), None);
+    }
+}
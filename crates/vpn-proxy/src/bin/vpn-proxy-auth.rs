@@ -4,14 +4,14 @@
 
 use anyhow::Result;
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Json},
-    routing::{get, post},
+    routing::{get, post, put},
     Router,
 };
 use serde::{Deserialize, Serialize};
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 use tokio::net::TcpListener;
 use tracing::{error, info};
 use vpn_proxy::{
@@ -19,11 +19,17 @@ use vpn_proxy::{
     error::ProxyError,
     manager::ProxyManager,
     metrics::ProxyMetrics,
+    provisioning::ProvisionRequest,
 };
 
 #[derive(Clone)]
 struct AppState {
     manager: Arc<ProxyManager>,
+    /// Bearer token of the admin API; the API is disabled without one
+    admin_token: Option<Arc<str>>,
+    /// Configuration file reloaded on request; unset when configured
+    /// through the environment
+    config_path: Option<Arc<PathBuf>>,
 }
 
 #[derive(Deserialize)]
@@ -55,7 +61,11 @@ async fn main() -> Result<()> {
     info!("Starting VPN Proxy Authentication Service");
 
     // Load configuration
-    let config = load_config().await?;
+    let config_path = PathBuf::from(
+        std::env::var("CONFIG_PATH").unwrap_or_else(|_| "/etc/proxy/config.toml".to_string()),
+    );
+    let config_path = config_path.exists().then_some(config_path);
+    let config = load_config(config_path.as_deref()).await?;
 
     // Create metrics
     let metrics =
@@ -65,8 +75,26 @@ async fn main() -> Result<()> {
     let manager = ProxyManager::new(config.clone(), metrics)
         .map_err(|e| anyhow::anyhow!("Failed to create proxy manager: {}", e))?;
 
+    let admin_token = std::env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(Arc::from);
+    if admin_token.is_none() {
+        info!("ADMIN_TOKEN not set, admin API disabled");
+    }
+
+    // Apply configuration changes on SIGHUP without dropping connections
+    #[cfg(unix)]
+    if let Some(path) = &config_path {
+        manager
+            .reload_on_hangup(path.clone())
+            .map_err(|e| anyhow::anyhow!("Failed to watch for SIGHUP: {}", e))?;
+    }
+
     let state = AppState {
         manager: Arc::new(manager),
+        admin_token,
+        config_path: config_path.map(Arc::new),
     };
 
     // Build router
@@ -74,6 +102,11 @@ async fn main() -> Result<()> {
         .route("/health", get(health_check))
         .route("/auth/verify", post(verify_auth).get(verify_auth))
         .route("/metrics", get(metrics_handler))
+        .route(
+            "/admin/credentials/:username",
+            put(provision_credential).delete(revoke_credential),
+        )
+        .route("/admin/reload", post(reload_config))
         .with_state(state);
 
     // Start server
@@ -139,6 +172,7 @@ async fn verify_auth(
             let status = match e {
                 ProxyError::RateLimitExceeded => StatusCode::TOO_MANY_REQUESTS,
                 ProxyError::AuthenticationFailed(_) => StatusCode::UNAUTHORIZED,
+                ProxyError::AuthBackend(_) => StatusCode::SERVICE_UNAVAILABLE,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
             };
 
@@ -170,6 +204,72 @@ async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
     }
 }
 
+async fn provision_credential(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<ProvisionRequest>,
+) -> StatusCode {
+    if let Err(status) = check_admin_token(&state, &headers) {
+        return status;
+    }
+
+    match state
+        .manager
+        .auth()
+        .provision(&username, &request.user_id, &request.password_hash)
+    {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            error!("Rejected credential for {}: {}", username, e);
+            StatusCode::BAD_REQUEST
+        }
+    }
+}
+
+async fn revoke_credential(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if let Err(status) = check_admin_token(&state, &headers) {
+        return status;
+    }
+
+    state.manager.auth().revoke(&username);
+    StatusCode::NO_CONTENT
+}
+
+async fn reload_config(State(state): State<AppState>, headers: HeaderMap) -> StatusCode {
+    if let Err(status) = check_admin_token(&state, &headers) {
+        return status;
+    }
+
+    let Some(path) = &state.config_path else {
+        return StatusCode::CONFLICT;
+    };
+    match state.manager.reload_from_file(path).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            error!("Rejected configuration reload: {}", e);
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+    }
+}
+
+fn check_admin_token(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let expected = state.admin_token.as_deref().ok_or(StatusCode::NOT_FOUND)?;
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match token {
+        Some(token) if token == expected => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
 fn extract_credentials(headers: &HeaderMap, query: AuthQuery) -> Option<(String, String)> {
     // First check Authorization header
     if let Some(auth_header) = headers.get(header::AUTHORIZATION) {
@@ -237,13 +337,10 @@ fn extract_client_ip(headers: &HeaderMap) -> String {
     "0.0.0.0".to_string()
 }
 
-async fn load_config() -> Result<ProxyConfig> {
+async fn load_config(config_path: Option<&std::path::Path>) -> Result<ProxyConfig> {
     // Try to load from file first
-    let config_path =
-        std::env::var("CONFIG_PATH").unwrap_or_else(|_| "/etc/proxy/config.toml".to_string());
-
-    if std::path::Path::new(&config_path).exists() {
-        ProxyConfig::load_from_file(std::path::Path::new(&config_path))
+    if let Some(config_path) = config_path {
+        ProxyConfig::load_from_file(config_path)
             .await
             .map_err(|e| anyhow::anyhow!("Failed to load config: {}", e))
     } else {
@@ -254,10 +351,12 @@ async fn load_config() -> Result<ProxyConfig> {
                     .unwrap_or_else(|_| "true".to_string())
                     .parse()
                     .unwrap_or(true),
-                backend: vpn_proxy::config::AuthBackend::VpnUsers,
-                cache_ttl: std::time::Duration::from_secs(300),
+                backend: vpn_proxy::config::AuthBackendConfig::VpnUsers,
+                cache_ttl: std::time::Duration::from_secs(60),
                 allow_anonymous: false,
                 ip_whitelist: vec![],
+                source_bindings: Default::default(),
+                lockout: Default::default(),
             },
             rate_limit: RateLimitConfig {
                 enabled: std::env::var("RATE_LIMIT_ENABLED")
@@ -271,6 +370,10 @@ async fn load_config() -> Result<ProxyConfig> {
                 burst_size: 200,
                 bandwidth_limit: None,
                 global_limit: Some(10000),
+                max_connections_per_user: std::env::var("MAX_CONNECTIONS_PER_USER")
+                    .ok()
+                    .and_then(|limit| limit.parse().ok()),
+                user_connection_limits: Default::default(),
             },
             ..Default::default()
         })
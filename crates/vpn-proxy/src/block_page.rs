@@ -0,0 +1,250 @@
+//! Pages explaining why a request was blocked
+//!
+//! An HTTP client whose request is refused by the access list or a rate or
+//! connection limit gets an HTML page naming the reason and whom to
+//! contact, rather than an empty status response. Operators can brand the
+//! page with templates in [`BlockPageConfig::template_dir`]: `<reason>.html`
+//! for one reason (`acl.html`, `rate-limit.html`, `connection-limit.html`)
+//! or `blocked.html` for all. Templates may use `{{brand}}`, `{{status}}`,
+//! `{{title}}`, `{{message}}`, `{{host}}` and `{{contact}}`, which are
+//! HTML-escaped. SOCKS5 clients get [`BlockPageConfig::socks_reply`].
+
+use crate::config::BlockPageConfig;
+use crate::socks5::Reply;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Why a request was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockReason {
+    /// Denied by the access list
+    Acl,
+    /// The user sent too many requests
+    RateLimit,
+    /// The user has too many connections open
+    ConnectionLimit,
+}
+
+impl BlockReason {
+    const ALL: [BlockReason; 3] = [Self::Acl, Self::RateLimit, Self::ConnectionLimit];
+
+    /// Template file name without extension
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Acl => "acl",
+            Self::RateLimit => "rate-limit",
+            Self::ConnectionLimit => "connection-limit",
+        }
+    }
+
+    pub fn status(&self) -> (u16, &'static str) {
+        match self {
+            Self::Acl => (403, "Forbidden"),
+            Self::RateLimit | Self::ConnectionLimit => (429, "Too Many Requests"),
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Acl => "Destination blocked",
+            Self::RateLimit => "Too many requests",
+            Self::ConnectionLimit => "Too many connections",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Acl => "The proxy's access policy does not allow connections to this site.",
+            Self::RateLimit => "You have sent too many requests. Wait a moment and try again.",
+            Self::ConnectionLimit => {
+                "You have too many connections open. Close some and try again."
+            }
+        }
+    }
+}
+
+/// Block pages with their templates loaded
+#[derive(Debug, Clone)]
+pub struct BlockPages {
+    config: BlockPageConfig,
+    templates: HashMap<BlockReason, String>,
+}
+
+impl BlockPages {
+    /// Load templates from the configured directory. Templates that are
+    /// missing fall back to the built-in page; unreadable ones are reported.
+    pub fn load(config: &BlockPageConfig) -> Self {
+        let mut templates = HashMap::new();
+
+        if let Some(dir) = &config.template_dir {
+            let fallback = read_template(&dir.join("blocked.html"));
+            for reason in BlockReason::ALL {
+                let path = dir.join(format!("{}.html", reason.as_str()));
+                if let Some(template) = read_template(&path).or_else(|| fallback.clone()) {
+                    templates.insert(reason, template);
+                }
+            }
+        }
+
+        Self {
+            config: config.clone(),
+            templates,
+        }
+    }
+
+    /// SOCKS5 reply for refused requests
+    pub fn socks_reply(&self) -> Reply {
+        Reply::from_byte(self.config.socks_reply).unwrap_or(Reply::ConnectionNotAllowed)
+    }
+
+    /// Page for a request to `host` refused for `reason`
+    pub fn render(&self, reason: BlockReason, host: &str) -> String {
+        let (status, _) = reason.status();
+        let brand = escape_html(&self.config.brand);
+        let host = escape_html(host);
+        let contact = self
+            .config
+            .contact_url
+            .as_deref()
+            .map(escape_html)
+            .unwrap_or_default();
+
+        if let Some(template) = self.templates.get(&reason) {
+            return template
+                .replace("{{brand}}", &brand)
+                .replace("{{status}}", &status.to_string())
+                .replace("{{title}}", reason.title())
+                .replace("{{message}}", reason.message())
+                .replace("{{host}}", &host)
+                .replace("{{contact}}", &contact);
+        }
+
+        let contact = if contact.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "<p>If you think this is a mistake, <a href=\"{0}\">{0}</a>.</p>\n",
+                contact
+            )
+        };
+        format!(
+            concat!(
+                "<!DOCTYPE html>\n",
+                "<html>\n<head>\n<meta charset=\"utf-8\">\n",
+                "<title>{status} {title} - {brand}</title>\n",
+                "</head>\n<body>\n",
+                "<h1>{title}</h1>\n",
+                "<p>{message}</p>\n",
+                "<p>Requested: <code>{host}</code></p>\n",
+                "{contact}",
+                "<hr>\n<p>{brand}</p>\n",
+                "</body>\n</html>\n",
+            ),
+            status = status,
+            title = reason.title(),
+            message = reason.message(),
+            host = host,
+            contact = contact,
+            brand = brand,
+        )
+    }
+
+    /// Complete HTTP response carrying the page
+    pub fn response(&self, reason: BlockReason, host: &str) -> String {
+        let (status, status_text) = reason.status();
+        let body = self.render(reason, host);
+        format!(
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Cache-Control: no-store\r\n\
+             Connection: close\r\n\r\n{}",
+            status,
+            status_text,
+            body.len(),
+            body
+        )
+    }
+}
+
+fn read_template(path: &std::path::Path) -> Option<String> {
+    match std::fs::read_to_string(path) {
+        Ok(template) => Some(template),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("Ignoring block page template {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_page_escapes_host() {
+        let pages = BlockPages::load(&BlockPageConfig {
+            contact_url: Some("mailto:ops@example.com".to_string()),
+            ..Default::default()
+        });
+
+        let response = pages.response(BlockReason::Acl, "<script>.example");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(response.contains("&lt;script&gt;.example"));
+        assert!(!response.contains("<script>"));
+        assert!(response.contains("mailto:ops@example.com"));
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+    }
+
+    #[test]
+    fn test_templates_from_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("blocked.html"), "{{brand}}: {{title}}").unwrap();
+        std::fs::write(dir.path().join("acl.html"), "{{status}} {{host}}").unwrap();
+
+        let pages = BlockPages::load(&BlockPageConfig {
+            template_dir: Some(dir.path().to_path_buf()),
+            brand: "Acme VPN".to_string(),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            pages.render(BlockReason::Acl, "example.com"),
+            "403 example.com"
+        );
+        assert_eq!(
+            pages.render(BlockReason::RateLimit, "example.com"),
+            "Acme VPN: Too many requests"
+        );
+    }
+
+    #[test]
+    fn test_socks_reply() {
+        let pages = BlockPages::load(&BlockPageConfig::default());
+        assert_eq!(pages.socks_reply(), Reply::ConnectionNotAllowed);
+
+        let pages = BlockPages::load(&BlockPageConfig {
+            socks_reply: 0x01,
+            ..Default::default()
+        });
+        assert_eq!(pages.socks_reply(), Reply::GeneralFailure);
+    }
+}
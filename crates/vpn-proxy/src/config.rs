@@ -1,9 +1,14 @@
 //! Proxy server configuration
 
+use ipnetwork::IpNetwork;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::time::Duration;
+#[cfg(feature = "cluster")]
+use vpn_cluster::config::StorageBackendConfig;
+use vpn_types::LockoutPolicy;
 
 /// Proxy protocol type
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -15,6 +20,8 @@ pub enum ProxyProtocol {
     Socks5,
     /// Both HTTP and SOCKS5
     Both,
+    /// HTTP and SOCKS5 on the HTTP port, told apart by the client's first byte
+    Shared,
 }
 
 /// Proxy server configuration
@@ -61,6 +68,38 @@ pub struct ProxyConfig {
 
     /// Timeout settings
     pub timeouts: TimeoutConfig,
+
+    /// Dual-stack upstream connection settings
+    #[serde(default)]
+    pub happy_eyeballs: HappyEyeballsConfig,
+
+    /// Destination access control
+    #[serde(default)]
+    pub acl: AclConfig,
+
+    /// Bandwidth shaping
+    #[serde(default)]
+    pub bandwidth: BandwidthConfig,
+
+    /// Responses to requests refused by the access list or limits
+    #[serde(default)]
+    pub block_pages: BlockPageConfig,
+
+    /// Per-request access log
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+
+    /// Resolution of destination names
+    #[serde(default)]
+    pub dns: DnsConfig,
+
+    /// Destination country lookup for access lists and metrics
+    #[serde(default)]
+    pub geoip: GeoIpConfig,
+
+    /// Session state shared with other replicas of this proxy
+    #[serde(default)]
+    pub sessions: SessionConfig,
 }
 
 /// Authentication configuration
@@ -70,9 +109,10 @@ pub struct AuthConfig {
     pub enabled: bool,
 
     /// Authentication backend
-    pub backend: AuthBackend,
+    pub backend: AuthBackendConfig,
 
-    /// Cache authenticated sessions
+    /// How long accepted credentials are trusted without asking the
+    /// backend again; zero disables the cache
     pub cache_ttl: Duration,
 
     /// Allow anonymous access
@@ -80,22 +120,63 @@ pub struct AuthConfig {
 
     /// IP whitelist (no auth required)
     pub ip_whitelist: Vec<IpAddr>,
+
+    /// Source addresses each username may authenticate from; users not
+    /// listed may connect from anywhere
+    #[serde(default)]
+    pub source_bindings: HashMap<String, Vec<IpNetwork>>,
+
+    /// Lockout after repeated failed attempts per source IP and username
+    #[serde(default)]
+    pub lockout: LockoutPolicy,
 }
 
 /// Authentication backend type
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
-pub enum AuthBackend {
+pub enum AuthBackendConfig {
     /// Use VPN user database
     VpnUsers,
-    /// Static file with users
+    /// htpasswd-style file of `username:hash[:cidr,...]` lines with Argon2
+    /// or bcrypt hashes
     File { path: PathBuf },
-    /// LDAP authentication
-    Ldap { url: String },
+    /// Bind to an LDAP directory as the user
+    Ldap(LdapBackendConfig),
+    /// Log in to the vpn-identity service at its base URL
+    Identity { url: String },
     /// External HTTP API
     Http { url: String },
 }
 
+/// LDAP directory checking passwords by binding as the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LdapBackendConfig {
+    /// Server URL (ldap:// or ldaps://)
+    pub url: String,
+
+    /// DN to bind as, `{username}` replaced by the escaped username, e.g.
+    /// `uid={username},ou=people,dc=example,dc=org`. When unset the user
+    /// is searched for under `base_dn` first.
+    pub user_dn: Option<String>,
+
+    /// Base DN of the user search
+    pub base_dn: Option<String>,
+
+    /// Filter finding the user, `{username}` replaced by the escaped
+    /// username
+    pub user_filter: String,
+
+    /// Account to search with; searches anonymously when unset
+    pub bind_dn: Option<String>,
+
+    /// Password of `bind_dn`
+    pub bind_password: Option<String>,
+
+    /// Timeout of connecting and of each LDAP operation
+    pub timeout: Duration,
+}
+
 /// Rate limiting configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RateLimitConfig {
@@ -113,6 +194,15 @@ pub struct RateLimitConfig {
 
     /// Global rate limit
     pub global_limit: Option<u32>,
+
+    /// Concurrent connections per user; unlimited when unset
+    #[serde(default)]
+    pub max_connections_per_user: Option<u32>,
+
+    /// Per-user overrides of `max_connections_per_user`, keyed by user ID;
+    /// 0 means unlimited
+    #[serde(default)]
+    pub user_connection_limits: HashMap<String, u32>,
 }
 
 /// Connection pool configuration
@@ -189,8 +279,215 @@ pub struct TimeoutConfig {
     /// Write timeout
     pub write: Duration,
 
-    /// Idle timeout
+    /// Close tunnels without traffic in either direction for this long;
+    /// zero disables
     pub idle: Duration,
+
+    /// Close tunnels open for this long regardless of traffic
+    #[serde(default)]
+    pub tunnel_lifetime: Option<Duration>,
+}
+
+/// Happy Eyeballs (RFC 8305) configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HappyEyeballsConfig {
+    /// Race IPv6 and IPv4 attempts for dual-stack destinations
+    pub enabled: bool,
+
+    /// Delay before starting the next connection attempt
+    pub attempt_delay: Duration,
+}
+
+/// Destination access control configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AclConfig {
+    /// Access list file (`acl.yaml`); everything is allowed without one
+    pub path: Option<PathBuf>,
+
+    /// How often to check the file for changes
+    pub reload_interval: Duration,
+}
+
+/// Bandwidth shaping configuration. Traffic is held back once any limit
+/// that applies to it is reached.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    /// Limits shared by all connections of a user
+    pub per_user: BandwidthLimits,
+
+    /// Limits shared by all connections from a client address
+    pub per_ip: BandwidthLimits,
+
+    /// Limits of each connection
+    pub per_connection: BandwidthLimits,
+
+    /// Limits shared by all connections through the proxy
+    pub global: BandwidthLimits,
+
+    /// Seconds of traffic at the limit that may be sent at once after a
+    /// quiet period
+    pub burst_seconds: f64,
+}
+
+/// Responses to refused requests, see [`crate::block_page`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockPageConfig {
+    /// Directory with HTML templates; the built-in page is used without one
+    pub template_dir: Option<PathBuf>,
+
+    /// Name shown on the page
+    pub brand: String,
+
+    /// Link offered to users who think the block is a mistake
+    pub contact_url: Option<String>,
+
+    /// SOCKS5 reply code for refused requests
+    pub socks_reply: u8,
+}
+
+/// Line format of the access log file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// One JSON object per line
+    #[default]
+    Json,
+    /// Apache combined log format with transfer details appended
+    Combined,
+}
+
+/// Per-request access log, see [`crate::access_log`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    /// Log file; none is written without one
+    pub path: Option<PathBuf>,
+
+    /// Line format of the file
+    pub format: AccessLogFormat,
+
+    /// Size in bytes at which the file is rotated; zero never rotates
+    pub max_size: u64,
+
+    /// Rotated files kept, as `<path>.1` (newest) to `<path>.<max_files>`
+    pub max_files: usize,
+
+    /// Also write JSON lines to standard output for vpn-telemetry's log
+    /// shipping to collect from the proxy container
+    pub ship: bool,
+}
+
+/// Where remote name lookups go
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsUpstream {
+    /// The host's resolver
+    #[default]
+    System,
+    /// DNS servers queried over UDP, the next one tried when one fails
+    Servers { servers: Vec<SocketAddr> },
+    /// A DNS-over-HTTPS endpoint (RFC 8484), e.g.
+    /// `https://1.1.1.1/dns-query`; a host name in the URL is looked up
+    /// with the host's resolver
+    Doh { url: String },
+}
+
+/// Resolution of destination names, see [`crate::dns`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    /// Resolver for users that resolve remotely
+    pub upstream: DnsUpstream,
+
+    /// Whether users resolve through `upstream` only, never falling back
+    /// to the host's resolver; users resolving locally use the host's
+    pub resolve_remotely: bool,
+
+    /// Per-user override of `resolve_remotely`
+    pub users: HashMap<String, bool>,
+
+    /// Timeout of one query
+    pub timeout: Duration,
+
+    /// Names cached at most; zero disables the cache
+    pub cache_size: usize,
+
+    /// Shortest time an answer is cached, whatever its TTL; also used for
+    /// the host resolver's answers, which carry none
+    pub min_ttl: Duration,
+
+    /// Longest time an answer is cached, whatever its TTL
+    pub max_ttl: Duration,
+
+    /// How long names without addresses are cached
+    pub negative_ttl: Duration,
+}
+
+/// Destination country lookup, see [`crate::geoip`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeoIpConfig {
+    /// MaxMind-format country database (`.mmdb`); destinations have no
+    /// country without one
+    pub database: Option<PathBuf>,
+
+    /// Where to download the database from: a `.mmdb` file, plain,
+    /// gzip-compressed or in a `.tar.gz` archive as MaxMind publishes it.
+    /// The download replaces `database`.
+    pub update_url: Option<String>,
+
+    /// How often to download the database
+    pub update_interval: Duration,
+}
+
+/// Where the session table is kept
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SessionStoreConfig {
+    /// In this process; nothing is shared with other replicas
+    #[default]
+    Local,
+    /// The cluster's distributed storage
+    #[cfg(feature = "cluster")]
+    Cluster { storage: StorageBackendConfig },
+    /// A Redis server, e.g. `redis://sessions.internal:6379/0`
+    Redis { url: String },
+}
+
+/// Session state shared between replicas, see [`crate::sessions`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Where the session table is kept; rate limits are per replica while
+    /// it is [`SessionStoreConfig::Local`]
+    pub store: SessionStoreConfig,
+
+    /// UDP port every replica relays SOCKS5 UDP associations on, so a
+    /// client's datagrams are relayed by whichever replica they reach.
+    /// Without it each association gets a port of its own on the replica
+    /// that accepted it.
+    pub udp_relay_port: Option<u16>,
+
+    /// How long an association outlives the last renewal by the replica
+    /// holding its control connection
+    pub association_ttl: Duration,
+
+    /// Prefix of the table's keys, keeping proxies that share a store
+    /// apart
+    pub key_prefix: String,
+}
+
+/// Upload and download rates in KB/s; unlimited when unset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthLimits {
+    /// Client to upstream rate
+    pub upload_kbps: Option<u64>,
+
+    /// Upstream to client rate
+    pub download_kbps: Option<u64>,
 }
 
 impl Default for ProxyConfig {
@@ -210,6 +507,14 @@ impl Default for ProxyConfig {
             log_level: "info".to_string(),
             metrics: MetricsConfig::default(),
             timeouts: TimeoutConfig::default(),
+            happy_eyeballs: HappyEyeballsConfig::default(),
+            acl: AclConfig::default(),
+            bandwidth: BandwidthConfig::default(),
+            block_pages: BlockPageConfig::default(),
+            access_log: AccessLogConfig::default(),
+            dns: DnsConfig::default(),
+            geoip: GeoIpConfig::default(),
+            sessions: SessionConfig::default(),
         }
     }
 }
@@ -236,11 +541,18 @@ impl ProxyConfig {
         }
     }
 
-    /// Load configuration from file
+    /// Check settings the parser cannot
+    pub fn validate(&self) -> crate::Result<()> {
+        self.rate_limit.validate()
+    }
+
+    /// Load and validate configuration from file
     pub async fn load_from_file(path: &std::path::Path) -> crate::Result<Self> {
         let content = tokio::fs::read_to_string(path).await?;
-        toml::from_str(&content)
-            .map_err(|e| crate::ProxyError::config(format!("Failed to parse config: {}", e)))
+        let config: Self = toml::from_str(&content)
+            .map_err(|e| crate::ProxyError::config(format!("Failed to parse config: {}", e)))?;
+        config.validate()?;
+        Ok(config)
     }
 
     /// Save configuration to file
@@ -256,10 +568,26 @@ impl Default for AuthConfig {
     fn default() -> Self {
         Self {
             enabled: true,
-            backend: AuthBackend::VpnUsers,
-            cache_ttl: Duration::from_secs(300),
+            backend: AuthBackendConfig::VpnUsers,
+            cache_ttl: Duration::from_secs(60),
             allow_anonymous: false,
             ip_whitelist: Vec::new(),
+            source_bindings: HashMap::new(),
+            lockout: LockoutPolicy::default(),
+        }
+    }
+}
+
+impl Default for LdapBackendConfig {
+    fn default() -> Self {
+        Self {
+            url: "ldap://localhost:389".to_string(),
+            user_dn: None,
+            base_dn: None,
+            user_filter: "(uid={username})".to_string(),
+            bind_dn: None,
+            bind_password: None,
+            timeout: Duration::from_secs(10),
         }
     }
 }
@@ -272,10 +600,32 @@ impl Default for RateLimitConfig {
             burst_size: 200,
             bandwidth_limit: Some(10 * 1024 * 1024), // 10 MB/s
             global_limit: Some(10000),
+            max_connections_per_user: None,
+            user_connection_limits: HashMap::new(),
         }
     }
 }
 
+impl RateLimitConfig {
+    /// Refuse limits that would never refill a bucket
+    pub fn validate(&self) -> crate::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if self.requests_per_second == 0 {
+            return Err(crate::ProxyError::config(
+                "Rate limit requests_per_second must be greater than 0",
+            ));
+        }
+        if self.global_limit == Some(0) {
+            return Err(crate::ProxyError::config(
+                "Rate limit global_limit must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
 impl Default for PoolConfig {
     fn default() -> Self {
         Self {
@@ -304,6 +654,97 @@ impl Default for TimeoutConfig {
             read: Duration::from_secs(30),
             write: Duration::from_secs(30),
             idle: Duration::from_secs(300),
+            tunnel_lifetime: None,
+        }
+    }
+}
+
+impl Default for HappyEyeballsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            attempt_delay: Duration::from_millis(250),
+        }
+    }
+}
+
+impl Default for AclConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            reload_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Default for BlockPageConfig {
+    fn default() -> Self {
+        Self {
+            template_dir: None,
+            brand: "VPN Proxy".to_string(),
+            contact_url: None,
+            // Connection not allowed by ruleset
+            socks_reply: 0x02,
+        }
+    }
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            format: AccessLogFormat::Json,
+            max_size: 100 * 1024 * 1024,
+            max_files: 5,
+            ship: false,
+        }
+    }
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            upstream: DnsUpstream::System,
+            resolve_remotely: true,
+            users: HashMap::new(),
+            timeout: Duration::from_secs(5),
+            cache_size: 10_000,
+            min_ttl: Duration::from_secs(30),
+            max_ttl: Duration::from_secs(3600),
+            negative_ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            store: SessionStoreConfig::Local,
+            udp_relay_port: None,
+            association_ttl: Duration::from_secs(60),
+            key_prefix: "vpn-proxy/".to_string(),
+        }
+    }
+}
+
+impl Default for GeoIpConfig {
+    fn default() -> Self {
+        Self {
+            database: None,
+            update_url: None,
+            update_interval: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl Default for BandwidthConfig {
+    fn default() -> Self {
+        Self {
+            per_user: BandwidthLimits::default(),
+            per_ip: BandwidthLimits::default(),
+            per_connection: BandwidthLimits::default(),
+            global: BandwidthLimits::default(),
+            burst_seconds: 1.0,
         }
     }
 }
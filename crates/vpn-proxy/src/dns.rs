@@ -0,0 +1,581 @@
+//! Resolution of destination names
+//!
+//! SOCKS5 domain targets, CONNECT hosts and the origins of plain HTTP
+//! requests are looked up here rather than with the host's resolver
+//! directly, so lookups can go to DNS servers or a DNS-over-HTTPS endpoint
+//! of the operator's choosing ([`DnsUpstream`]). Users that resolve
+//! remotely, all of them unless [`DnsConfig::users`] says otherwise, only
+//! ever use that upstream: a failed lookup fails the connection instead of
+//! falling back to the host's resolver and revealing the name to its
+//! network. Users resolving locally use the host's resolver.
+//!
+//! Answers are cached for their TTL, kept within
+//! [`DnsConfig::min_ttl`] and [`DnsConfig::max_ttl`]; names that do not
+//! exist or have no addresses are cached for [`DnsConfig::negative_ttl`].
+
+use crate::config::{DnsConfig, DnsUpstream};
+use crate::error::{ProxyError, Result};
+use dashmap::DashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+
+/// Media type of DNS-over-HTTPS requests and responses
+const DOH_CONTENT_TYPE: &str = "application/dns-message";
+
+/// Largest response read over UDP; queries carry no EDNS, so servers send
+/// at most 512 bytes
+const MAX_UDP_RESPONSE: usize = 512;
+
+/// Addresses a query returned, with the lowest TTL among their records;
+/// no addresses for names that do not exist or have no address records
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Answer {
+    pub addrs: Vec<IpAddr>,
+    pub ttl: Option<u32>,
+}
+
+impl Answer {
+    /// Addresses of both answers, IPv6 first as the host's resolver orders
+    /// them for dual-stack hosts
+    fn merge(v6: Answer, v4: Answer) -> Answer {
+        let ttl = match (v6.ttl, v4.ttl) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let mut addrs = v6.addrs;
+        addrs.extend(v4.addrs);
+        Answer { addrs, ttl }
+    }
+}
+
+/// Wire format query for `name` with record type `qtype`, asking for
+/// recursion
+pub fn encode_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > 253 {
+        return Err(ProxyError::invalid_request(format!(
+            "Invalid domain name '{}'",
+            name
+        )));
+    }
+
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // Standard query, recursion desired
+    query.extend_from_slice(&0x0100u16.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+    query.extend_from_slice(&[0; 6]);
+
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(ProxyError::invalid_request(format!(
+                "Invalid domain name '{}'",
+                name
+            )));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Addresses in the response to query `id`. A name that does not exist
+/// is an empty answer; other failures reported by the server are errors.
+pub fn decode_response(message: &[u8], id: u16) -> Result<Answer> {
+    let malformed = || ProxyError::upstream("Malformed DNS response");
+    let u16_at = |pos: usize| -> Result<u16> {
+        let bytes = message.get(pos..pos + 2).ok_or_else(malformed)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    if u16_at(0)? != id {
+        return Err(ProxyError::upstream("DNS response to another query"));
+    }
+    let flags = u16_at(2)?;
+    if flags & 0x8000 == 0 {
+        return Err(malformed());
+    }
+    match flags & 0x000f {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(Answer::default()),
+        rcode => {
+            return Err(ProxyError::upstream(format!(
+                "DNS server failed the query (rcode {})",
+                rcode
+            )))
+        }
+    }
+
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(message, pos).ok_or_else(malformed)? + 4;
+    }
+
+    let mut answer = Answer::default();
+    for _ in 0..answers {
+        pos = skip_name(message, pos).ok_or_else(malformed)?;
+        let record = message.get(pos..pos + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([record[0], record[1]]);
+        let class = u16::from_be_bytes([record[2], record[3]]);
+        let ttl = u32::from_be_bytes([record[4], record[5], record[6], record[7]]);
+        let length = u16::from_be_bytes([record[8], record[9]]) as usize;
+        pos += 10;
+        let data = message.get(pos..pos + length).ok_or_else(malformed)?;
+        pos += length;
+
+        // CNAME records lead to the address records that follow them
+        let addr = match (rtype, class, data.len()) {
+            (TYPE_A, CLASS_IN, 4) => IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            (TYPE_AAAA, CLASS_IN, 16) => {
+                let octets: [u8; 16] = data.try_into().map_err(|_| malformed())?;
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => continue,
+        };
+        answer.addrs.push(addr);
+        answer.ttl = Some(answer.ttl.map_or(ttl, |lowest| lowest.min(ttl)));
+    }
+    Ok(answer)
+}
+
+/// Position after the name starting at `pos`
+fn skip_name(message: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(pos)?;
+        match length & 0xc0 {
+            // A compression pointer ends the name
+            0xc0 => {
+                message.get(pos + 1)?;
+                return Some(pos + 2);
+            }
+            0x00 if length == 0 => return Some(pos + 1),
+            0x00 => pos += 1 + length as usize,
+            _ => return None,
+        }
+    }
+}
+
+/// A cached answer; negative when it has no addresses
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+}
+
+/// Resolver shared by all connections, see the module documentation
+pub struct Resolver {
+    config: RwLock<Arc<DnsConfig>>,
+    /// Answers by lowercase name and whether they came from the upstream
+    cache: DashMap<(String, bool), CacheEntry>,
+    doh_client: reqwest::Client,
+}
+
+impl Resolver {
+    pub fn new(config: &DnsConfig) -> Result<Self> {
+        // Lookups of the endpoint's own name must not come back here
+        let doh_client = reqwest::Client::builder()
+            .no_proxy()
+            .build()
+            .map_err(|e| ProxyError::config(format!("Failed to create DoH client: {}", e)))?;
+
+        Ok(Self {
+            config: RwLock::new(Arc::new(config.clone())),
+            cache: DashMap::new(),
+            doh_client,
+        })
+    }
+
+    fn config(&self) -> Arc<DnsConfig> {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Apply `config` to lookups from now on; a changed configuration
+    /// drops the cache
+    pub fn reconfigure(&self, config: &DnsConfig) {
+        let mut current = self.config.write().unwrap_or_else(|e| e.into_inner());
+        if **current != *config {
+            *current = Arc::new(config.clone());
+            self.cache.clear();
+        }
+    }
+
+    /// Whether lookups for `user_id` go to the upstream only
+    pub fn resolves_remotely(&self, user_id: &str) -> bool {
+        let config = self.config();
+        config
+            .users
+            .get(user_id)
+            .copied()
+            .unwrap_or(config.resolve_remotely)
+    }
+
+    /// Whether lookups that serve several users at once go to the upstream
+    /// only: unless every user resolves locally, they do
+    pub fn resolves_remotely_for_all(&self) -> bool {
+        let config = self.config();
+        config.resolve_remotely || config.users.values().any(|remote| *remote)
+    }
+
+    /// Resolve `host:port` for a connection of `user_id`
+    pub async fn resolve(&self, user_id: &str, host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+        self.resolve_with(self.resolves_remotely(user_id), host, port)
+            .await
+    }
+
+    /// Resolve `host:port` with the upstream when `remote` is set, with the
+    /// host's resolver otherwise
+    pub async fn resolve_with(
+        &self,
+        remote: bool,
+        host: &str,
+        port: u16,
+    ) -> Result<Vec<SocketAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+
+        let config = self.config();
+        let key = (host.to_ascii_lowercase(), remote);
+        let cached = self
+            .cache
+            .get(&key)
+            .filter(|entry| entry.expires > Instant::now())
+            .map(|entry| entry.addrs.clone());
+        let addrs = match cached {
+            Some(addrs) => addrs,
+            None => {
+                let answer = self.lookup(&config, &key.0, remote).await?;
+                self.store(&config, key, &answer);
+                answer.addrs
+            }
+        };
+
+        if addrs.is_empty() {
+            return Err(ProxyError::upstream(format!(
+                "No addresses found for {}",
+                host
+            )));
+        }
+        Ok(addrs
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+
+    async fn lookup(&self, config: &DnsConfig, name: &str, remote: bool) -> Result<Answer> {
+        let upstream = if remote {
+            &config.upstream
+        } else {
+            &DnsUpstream::System
+        };
+
+        let result = match upstream {
+            DnsUpstream::System => self.lookup_system(config, name).await,
+            DnsUpstream::Servers { servers } => {
+                let (v6, v4) = futures::join!(
+                    self.query_servers(config, servers, name, TYPE_AAAA),
+                    self.query_servers(config, servers, name, TYPE_A)
+                );
+                Ok(Answer::merge(v6?, v4?))
+            }
+            DnsUpstream::Doh { url } => {
+                let (v6, v4) = futures::join!(
+                    self.query_doh(config, url, name, TYPE_AAAA),
+                    self.query_doh(config, url, name, TYPE_A)
+                );
+                Ok(Answer::merge(v6?, v4?))
+            }
+        };
+        result.map_err(|e| ProxyError::upstream(format!("Failed to resolve {}: {}", name, e)))
+    }
+
+    async fn lookup_system(&self, config: &DnsConfig, name: &str) -> Result<Answer> {
+        let lookup = tokio::net::lookup_host((name, 0));
+        match tokio::time::timeout(config.timeout, lookup).await {
+            Err(_) => Err(ProxyError::Timeout),
+            Ok(Ok(addrs)) => Ok(Answer {
+                addrs: addrs.map(|addr| addr.ip()).collect(),
+                ttl: None,
+            }),
+            // getaddrinfo does not tell a missing name from other failures
+            Ok(Err(e)) => Err(e.into()),
+        }
+    }
+
+    /// Ask each server in turn until one answers
+    async fn query_servers(
+        &self,
+        config: &DnsConfig,
+        servers: &[SocketAddr],
+        name: &str,
+        qtype: u16,
+    ) -> Result<Answer> {
+        let mut last_error = ProxyError::config("No DNS servers configured");
+        for server in servers {
+            match self.query_server(config, *server, name, qtype).await {
+                Ok(answer) => return Ok(answer),
+                Err(e) => {
+                    debug!("DNS server {} failed for {}: {}", server, name, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn query_server(
+        &self,
+        config: &DnsConfig,
+        server: SocketAddr,
+        name: &str,
+        qtype: u16,
+    ) -> Result<Answer> {
+        // Random IDs and source ports make forged responses hard to place
+        let id = rand::random::<u16>();
+        let query = encode_query(id, name, qtype)?;
+
+        let local = match server {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(server).await?;
+        socket.send(&query).await?;
+
+        let exchange = async {
+            let mut buf = [0u8; MAX_UDP_RESPONSE];
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                // Stray datagrams for other queries are skipped
+                if buf[..len].starts_with(&id.to_be_bytes()) {
+                    return decode_response(&buf[..len], id);
+                }
+            }
+        };
+        tokio::time::timeout(config.timeout, exchange)
+            .await
+            .map_err(|_| ProxyError::Timeout)?
+    }
+
+    async fn query_doh(
+        &self,
+        config: &DnsConfig,
+        url: &str,
+        name: &str,
+        qtype: u16,
+    ) -> Result<Answer> {
+        // RFC 8484 asks for ID 0 so that responses can be cached by HTTP
+        let query = encode_query(0, name, qtype)?;
+        let response = self
+            .doh_client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, DOH_CONTENT_TYPE)
+            .header(reqwest::header::ACCEPT, DOH_CONTENT_TYPE)
+            .timeout(config.timeout)
+            .body(query)
+            .send()
+            .await
+            .map_err(|e| ProxyError::upstream(format!("DoH request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(ProxyError::upstream(format!(
+                "DoH endpoint answered {}",
+                response.status()
+            )));
+        }
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| ProxyError::upstream(format!("DoH response failed: {}", e)))?;
+        decode_response(&body, 0)
+    }
+
+    fn store(&self, config: &DnsConfig, key: (String, bool), answer: &Answer) {
+        if config.cache_size == 0 {
+            return;
+        }
+
+        let ttl = if answer.addrs.is_empty() {
+            config.negative_ttl
+        } else {
+            answer
+                .ttl
+                .map_or(config.min_ttl, |ttl| Duration::from_secs(ttl as u64))
+                .max(config.min_ttl)
+                .min(config.max_ttl)
+        };
+
+        let now = Instant::now();
+        if self.cache.len() >= config.cache_size {
+            self.cache.retain(|_, entry| entry.expires > now);
+        }
+        if self.cache.len() >= config.cache_size {
+            // Still full of live answers; make room for this one
+            let evicted = self.cache.iter().next().map(|entry| entry.key().clone());
+            if let Some(evicted) = evicted {
+                self.cache.remove(&evicted);
+            }
+        }
+
+        self.cache.insert(
+            key,
+            CacheEntry {
+                addrs: answer.addrs.clone(),
+                expires: now + ttl,
+            },
+        );
+    }
+}
+
+/// Lookups of the shared client for `https://` origin requests. Its
+/// connections serve several users, so names go to the upstream unless
+/// every user resolves locally.
+pub struct OriginResolver(pub Arc<Resolver>);
+
+impl reqwest::dns::Resolve for OriginResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let remote = resolver.resolves_remotely_for_all();
+            let addrs = resolver
+                .resolve_with(remote, name.as_str(), 0)
+                .await
+                .inspect_err(|e| warn!("Origin lookup failed: {}", e))?;
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Response to `query` carrying `records` of (type, rdata) with
+    /// compressed owner names
+    fn response(query: &[u8], rcode: u16, records: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut message = query.to_vec();
+        message[2..4].copy_from_slice(&(0x8180 | rcode).to_be_bytes());
+        message[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for (rtype, data) in records {
+            message.extend_from_slice(&[0xc0, 0x0c]);
+            message.extend_from_slice(&rtype.to_be_bytes());
+            message.extend_from_slice(&CLASS_IN.to_be_bytes());
+            message.extend_from_slice(&300u32.to_be_bytes());
+            message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            message.extend_from_slice(data);
+        }
+        message
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query(0x1234, "example.com.", TYPE_A).unwrap();
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..25], b"\x07example\x03com\x00");
+        assert_eq!(&query[25..], &[0, 1, 0, 1]);
+
+        assert!(encode_query(1, "", TYPE_A).is_err());
+        assert!(encode_query(1, "a..b", TYPE_A).is_err());
+        assert!(encode_query(1, &"a".repeat(64), TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let query = encode_query(7, "www.example.com", TYPE_AAAA).unwrap();
+        let v6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let message = response(
+            &query,
+            0,
+            &[
+                (5, b"\x07example\x03com\x00"),
+                (TYPE_AAAA, &v6.octets()),
+                (TYPE_A, &[192, 0, 2, 1]),
+            ],
+        );
+
+        let answer = decode_response(&message, 7).unwrap();
+        assert_eq!(
+            answer.addrs,
+            vec![IpAddr::V6(v6), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+        );
+        assert_eq!(answer.ttl, Some(300));
+
+        assert!(decode_response(&message, 8).is_err());
+        assert!(decode_response(&message[..message.len() - 1], 7).is_err());
+
+        let missing = response(&query, RCODE_NXDOMAIN, &[]);
+        assert_eq!(decode_response(&missing, 7).unwrap(), Answer::default());
+        let refused = response(&query, 5, &[]);
+        assert!(decode_response(&refused, 7).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_servers_and_cache() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+                let query = &buf[..len];
+                let qtype = u16::from_be_bytes([query[len - 4], query[len - 3]]);
+                let message = if query[12..].starts_with(b"\x07missing") {
+                    response(query, RCODE_NXDOMAIN, &[])
+                } else if qtype == TYPE_A {
+                    response(query, 0, &[(TYPE_A, &[198, 51, 100, 7])])
+                } else {
+                    response(query, 0, &[])
+                };
+                server.send_to(&message, peer).await.unwrap();
+            }
+        });
+
+        let resolver = Resolver::new(&DnsConfig {
+            upstream: DnsUpstream::Servers {
+                servers: vec![addr],
+            },
+            users: HashMap::from([("local".to_string(), false)]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(resolver.resolves_remotely("alice"));
+        assert!(!resolver.resolves_remotely("local"));
+
+        let addrs = resolver.resolve("alice", "proxy.test", 443).await.unwrap();
+        assert_eq!(addrs, vec!["198.51.100.7:443".parse().unwrap()]);
+        assert!(resolver
+            .cache
+            .contains_key(&("proxy.test".to_string(), true)));
+
+        assert!(resolver.resolve("alice", "missing.test", 80).await.is_err());
+        let negative = resolver
+            .cache
+            .get(&("missing.test".to_string(), true))
+            .unwrap();
+        assert!(negative.addrs.is_empty());
+        drop(negative);
+
+        let literal = resolver.resolve("alice", "::1", 80).await.unwrap();
+        assert_eq!(literal, vec!["[::1]:80".parse().unwrap()]);
+
+        resolver.reconfigure(&DnsConfig::default());
+        assert!(resolver.cache.is_empty());
+    }
+}
@@ -15,12 +15,21 @@ pub enum ProxyError {
     #[error("Authentication failed: {0}")]
     AuthenticationFailed(String),
 
+    #[error("Authentication backend error: {0}")]
+    AuthBackend(String),
+
+    #[error("Session store error: {0}")]
+    SessionStore(String),
+
     #[error("Authorization denied: {0}")]
     AuthorizationDenied(String),
 
     #[error("Rate limit exceeded")]
     RateLimitExceeded,
 
+    #[error("Too many concurrent connections")]
+    ConnectionLimitExceeded,
+
     #[error("Connection pool exhausted")]
     ConnectionPoolExhausted,
 
@@ -73,6 +82,14 @@ impl ProxyError {
         Self::AuthenticationFailed(msg.into())
     }
 
+    pub fn auth_backend(msg: impl Into<String>) -> Self {
+        Self::AuthBackend(msg.into())
+    }
+
+    pub fn session_store(msg: impl Into<String>) -> Self {
+        Self::SessionStore(msg.into())
+    }
+
     pub fn auth_denied(msg: impl Into<String>) -> Self {
         Self::AuthorizationDenied(msg.into())
     }
@@ -0,0 +1,367 @@
+//! Destination countries from a GeoIP database
+//!
+//! [`GeoIpStore`] looks up the country of destination addresses in a
+//! MaxMind-format database (GeoLite2 Country, GeoIP2 Country or City, or a
+//! compatible one) so access list rules can match on `countries` and
+//! metrics can label traffic by destination country. Countries are ISO
+//! 3166-1 alpha-2 codes; addresses the database does not place are
+//! [`UNKNOWN_COUNTRY`].
+//!
+//! With [`GeoIpConfig::update_url`] set, [`GeoIpStore::watch`] downloads the
+//! database every [`GeoIpConfig::update_interval`], replacing the file and
+//! the database in use. A download that fails or is not a database keeps
+//! the one in use.
+
+use crate::config::GeoIpConfig;
+use crate::error::{ProxyError, Result};
+use flate2::read::GzDecoder;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Country label of addresses the database does not place
+pub const UNKNOWN_COUNTRY: &str = "unknown";
+
+/// Largest database accepted from a download, unpacked
+const MAX_DATABASE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// The GeoIP database in use, replaced when a newer one is downloaded
+pub struct GeoIpStore {
+    config: GeoIpConfig,
+    reader: RwLock<Option<Arc<Reader<Vec<u8>>>>>,
+    client: reqwest::Client,
+}
+
+impl GeoIpStore {
+    /// Store without a database; no destination has a country
+    pub fn disabled() -> Self {
+        Self {
+            config: GeoIpConfig::default(),
+            reader: RwLock::new(None),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Store with the database at [`GeoIpConfig::database`]. A missing file
+    /// is fine when it is downloaded from [`GeoIpConfig::update_url`].
+    pub fn open(config: &GeoIpConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .build()
+            .map_err(|e| ProxyError::config(format!("Failed to create GeoIP client: {}", e)))?;
+
+        let reader = match &config.database {
+            Some(path) => load_database(path, config.update_url.is_some())?.map(Arc::new),
+            None => None,
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            reader: RwLock::new(reader),
+            client,
+        })
+    }
+
+    fn reader(&self) -> Option<Arc<Reader<Vec<u8>>>> {
+        self.reader
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Whether a database is loaded
+    pub fn is_loaded(&self) -> bool {
+        self.reader().is_some()
+    }
+
+    /// Country of `ip`, `None` when the database does not place it or no
+    /// database is loaded
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let reader = self.reader()?;
+        lookup_country(&reader, ip)
+    }
+
+    /// Countries of `addrs`, without duplicates
+    pub fn countries(&self, addrs: &[IpAddr]) -> Vec<String> {
+        let Some(reader) = self.reader() else {
+            return Vec::new();
+        };
+
+        let mut countries: Vec<String> = Vec::new();
+        for country in addrs.iter().filter_map(|ip| lookup_country(&reader, *ip)) {
+            if !countries.contains(&country) {
+                countries.push(country);
+            }
+        }
+        countries
+    }
+
+    /// Metrics label for traffic to `ip`: its country or
+    /// [`UNKNOWN_COUNTRY`]; `None` when no database is loaded
+    pub fn country_label(&self, ip: IpAddr) -> Option<String> {
+        let reader = self.reader()?;
+        Some(lookup_country(&reader, ip).unwrap_or_else(|| UNKNOWN_COUNTRY.to_string()))
+    }
+
+    /// Download the database from [`GeoIpConfig::update_url`] and put it in
+    /// use, saving it to [`GeoIpConfig::database`]
+    pub async fn update(&self) -> Result<()> {
+        let Some(url) = &self.config.update_url else {
+            return Err(ProxyError::config("No GeoIP update URL configured"));
+        };
+
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| ProxyError::upstream(format!("GeoIP download failed: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(ProxyError::upstream(format!(
+                "GeoIP download answered {}",
+                response.status()
+            )));
+        }
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| ProxyError::upstream(format!("GeoIP download failed: {}", e)))?;
+
+        let data = unpack(&body)?;
+        let reader = open_database(data.clone())?;
+        if let Some(path) = &self.config.database {
+            save_database(path, &data)?;
+        }
+        info!(
+            "Updated GeoIP database ({}, built {})",
+            reader.metadata.database_type, reader.metadata.build_epoch
+        );
+        *self.reader.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(reader));
+        Ok(())
+    }
+
+    /// Download the database every [`GeoIpConfig::update_interval`],
+    /// starting once the file on disk is that old; `None` without an
+    /// update URL
+    pub fn watch(self: Arc<Self>) -> Option<JoinHandle<()>> {
+        self.config.update_url.as_ref()?;
+        let interval = self.config.update_interval.max(Duration::from_secs(60));
+
+        Some(tokio::spawn(async move {
+            let age = self
+                .config
+                .database
+                .as_deref()
+                .filter(|_| self.is_loaded())
+                .and_then(database_age);
+            if let Some(age) = age {
+                tokio::time::sleep(interval.saturating_sub(age)).await;
+            }
+
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.update().await {
+                    warn!("Keeping previous GeoIP database: {}", e);
+                }
+            }
+        }))
+    }
+}
+
+fn lookup_country(reader: &Reader<Vec<u8>>, ip: IpAddr) -> Option<String> {
+    let record = match reader.lookup::<geoip2::Country>(ip) {
+        Ok(record) => record,
+        Err(MaxMindDBError::AddressNotFoundError(_)) => return None,
+        Err(e) => {
+            debug!("GeoIP lookup of {} failed: {}", ip, e);
+            return None;
+        }
+    };
+
+    // Anycast and satellite ranges often only have a registered country
+    record
+        .country
+        .and_then(|country| country.iso_code)
+        .or_else(|| {
+            record
+                .registered_country
+                .and_then(|country| country.iso_code)
+        })
+        .map(str::to_string)
+}
+
+/// Database at `path`; `None` when the file is missing but can be
+/// downloaded
+fn load_database(path: &Path, downloadable: bool) -> Result<Option<Reader<Vec<u8>>>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && downloadable => {
+            info!(
+                "GeoIP database {} not present yet, downloading it",
+                path.display()
+            );
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(ProxyError::config(format!(
+                "Failed to read GeoIP database {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+
+    let reader = open_database(data)?;
+    info!(
+        "Loaded GeoIP database {} ({}, built {})",
+        path.display(),
+        reader.metadata.database_type,
+        reader.metadata.build_epoch
+    );
+    Ok(Some(reader))
+}
+
+fn open_database(data: Vec<u8>) -> Result<Reader<Vec<u8>>> {
+    Reader::from_source(data)
+        .map_err(|e| ProxyError::config(format!("Invalid GeoIP database: {}", e)))
+}
+
+/// The database in a download: the file itself, gzip-compressed, or the
+/// first `.mmdb` file of a `.tar.gz` archive
+fn unpack(body: &[u8]) -> Result<Vec<u8>> {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    if !body.starts_with(&GZIP_MAGIC) {
+        return Ok(body.to_vec());
+    }
+
+    let mut data = Vec::new();
+    GzDecoder::new(body)
+        .take(MAX_DATABASE_SIZE)
+        .read_to_end(&mut data)
+        .map_err(|e| ProxyError::config(format!("Invalid GeoIP download: {}", e)))?;
+    if !is_tar(&data) {
+        return Ok(data);
+    }
+
+    let mut archive = tar::Archive::new(data.as_slice());
+    let entries = archive
+        .entries()
+        .map_err(|e| ProxyError::config(format!("Invalid GeoIP archive: {}", e)))?;
+    for entry in entries {
+        let mut entry =
+            entry.map_err(|e| ProxyError::config(format!("Invalid GeoIP archive: {}", e)))?;
+        let is_database = entry
+            .path()
+            .is_ok_and(|path| path.extension().is_some_and(|ext| ext == "mmdb"));
+        if !is_database {
+            continue;
+        }
+
+        let mut database = Vec::new();
+        entry
+            .read_to_end(&mut database)
+            .map_err(|e| ProxyError::config(format!("Invalid GeoIP archive: {}", e)))?;
+        return Ok(database);
+    }
+    Err(ProxyError::config("GeoIP archive holds no .mmdb file"))
+}
+
+fn is_tar(data: &[u8]) -> bool {
+    data.get(257..262) == Some(b"ustar")
+}
+
+/// Write `data` to `path`, replacing it atomically
+fn save_database(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp, data)?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+fn database_age(path: &Path) -> Option<Duration> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    SystemTime::now().duration_since(modified).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_unpack_downloads() {
+        let database = b"\xab\xcd\xefMaxMind.com database".to_vec();
+        assert_eq!(unpack(&database).unwrap(), database);
+        assert_eq!(unpack(&gzip(&database)).unwrap(), database);
+
+        // As MaxMind publishes it: a directory with the database and notes
+        let mut archive = tar::Builder::new(Vec::new());
+        for (name, data) in [
+            (
+                "GeoLite2-Country_20260101/COPYRIGHT.txt",
+                b"notes".as_slice(),
+            ),
+            ("GeoLite2-Country_20260101/GeoLite2-Country.mmdb", &database),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, name, data).unwrap();
+        }
+        let archive = archive.into_inner().unwrap();
+        assert_eq!(unpack(&gzip(&archive)).unwrap(), database);
+
+        let mut empty = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        empty
+            .append_data(&mut header, "README", b"notes".as_slice())
+            .unwrap();
+        assert!(unpack(&gzip(&empty.into_inner().unwrap())).is_err());
+    }
+
+    #[test]
+    fn test_store_without_database() {
+        let store = GeoIpStore::disabled();
+        let ip: IpAddr = "93.184.216.34".parse().unwrap();
+        assert!(!store.is_loaded());
+        assert_eq!(store.country(ip), None);
+        assert!(store.countries(&[ip]).is_empty());
+        assert_eq!(store.country_label(ip), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = GeoIpConfig {
+            database: Some(dir.path().join("GeoLite2-Country.mmdb")),
+            ..Default::default()
+        };
+        assert!(GeoIpStore::open(&config).is_err());
+
+        // Downloaded on the first update instead
+        let config = GeoIpConfig {
+            update_url: Some("https://geoip.example/GeoLite2-Country.tar.gz".to_string()),
+            ..config
+        };
+        assert!(!GeoIpStore::open(&config).unwrap().is_loaded());
+
+        std::fs::write(config.database.as_ref().unwrap(), b"not a database").unwrap();
+        assert!(GeoIpStore::open(&config).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! Dual-stack upstream connections (Happy Eyeballs v2, RFC 8305)
+//!
+//! Destinations that resolve to both IPv6 and IPv4 addresses are tried in
+//! interleaved order, starting a new attempt whenever the previous one fails
+//! or has not finished within the attempt delay. The first connection to
+//! complete wins and the others are dropped.
+
+use crate::error::{ProxyError, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tracing::debug;
+
+/// Address family label used in logs and metrics
+pub fn family(addr: &SocketAddr) -> &'static str {
+    if addr.is_ipv6() {
+        "ipv6"
+    } else {
+        "ipv4"
+    }
+}
+
+/// Whether the addresses cover both IPv6 and IPv4
+pub fn is_dual_stack(addrs: &[SocketAddr]) -> bool {
+    addrs.iter().any(SocketAddr::is_ipv6) && addrs.iter().any(SocketAddr::is_ipv4)
+}
+
+/// Resolve `host:port` to every address it has
+pub async fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| ProxyError::upstream(format!("Failed to resolve {}: {}", host, e)))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(ProxyError::upstream(format!(
+            "No addresses found for {}",
+            host
+        )));
+    }
+    Ok(addrs)
+}
+
+/// Order addresses for connection attempts: alternate families, starting
+/// with the family of the first resolved address, keeping the resolver's
+/// order within each family (RFC 8305 section 4)
+pub fn sort_addresses(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let prefer_v6 = addrs.first().is_none_or(SocketAddr::is_ipv6);
+    let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().partition(|addr| addr.is_ipv6());
+    let (first, second) = if prefer_v6 { (v6, v4) } else { (v4, v6) };
+
+    let mut sorted = Vec::with_capacity(addrs.len());
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
+        }
+    }
+    sorted
+}
+
+/// Race connection attempts to `addrs` in the given order, staggered by
+/// `attempt_delay`. Returns the first established connection and the address
+/// it went to.
+pub async fn connect(
+    addrs: &[SocketAddr],
+    attempt_delay: Duration,
+    connect_timeout: Duration,
+) -> Result<(TcpStream, SocketAddr)> {
+    let mut pending = addrs.iter().copied().peekable();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => attempts.push(attempt(addr, connect_timeout)),
+                None => break,
+            }
+        }
+
+        let delay = tokio::time::sleep(attempt_delay);
+        tokio::select! {
+            Some((addr, result)) = attempts.next() => match result {
+                Ok(stream) => {
+                    debug!("Connected to {} ({})", addr, family(&addr));
+                    return Ok((stream, addr));
+                }
+                Err(e) => {
+                    debug!("Connection attempt to {} failed: {}", addr, e);
+                    last_error = Some(e);
+                    // A failed attempt starts the next one right away
+                    if let Some(addr) = pending.next() {
+                        attempts.push(attempt(addr, connect_timeout));
+                    }
+                }
+            },
+            _ = delay, if pending.peek().is_some() => {
+                if let Some(addr) = pending.next() {
+                    attempts.push(attempt(addr, connect_timeout));
+                }
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| ProxyError::upstream("No addresses to connect to")))
+}
+
+async fn attempt(addr: SocketAddr, connect_timeout: Duration) -> (SocketAddr, Result<TcpStream>) {
+    let result = match tokio::time::timeout(connect_timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => stream.set_nodelay(true).map(|_| stream).map_err(Into::into),
+        Ok(Err(e)) => Err(ProxyError::upstream(format!(
+            "Failed to connect to {}: {}",
+            addr, e
+        ))),
+        Err(_) => Err(ProxyError::Timeout),
+    };
+    (addr, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort_addresses_interleaves_families() {
+        let addrs: Vec<SocketAddr> = [
+            "[2001:db8::1]:443",
+            "[2001:db8::2]:443",
+            "[2001:db8::3]:443",
+            "192.0.2.1:443",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+
+        let sorted: Vec<String> = sort_addresses(&addrs)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            sorted,
+            [
+                "[2001:db8::1]:443",
+                "192.0.2.1:443",
+                "[2001:db8::2]:443",
+                "[2001:db8::3]:443"
+            ]
+        );
+        assert!(is_dual_stack(&addrs));
+        assert!(!is_dual_stack(&addrs[..3]));
+    }
+
+    #[tokio::test]
+    async fn test_connect_falls_back_to_next_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap();
+
+        // A port nothing listens on refuses the first attempt
+        let closed = {
+            let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            probe.local_addr().unwrap()
+        };
+
+        let (_stream, addr) = connect(
+            &[closed, open],
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(addr, open);
+
+        let error = connect(&[closed], Duration::from_millis(10), Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ProxyError::UpstreamConnectionFailed(_)));
+    }
+}
@@ -1,15 +1,22 @@
 //! HTTP proxy request handler
 
-use super::{HttpMethod, HttpRequest};
+use super::response::{self, BodyLength};
+use super::{origin, HttpMethod, HttpRequest};
 use crate::{
+    access_log::{self, Termination},
+    block_page::BlockReason,
     error::{ProxyError, Result},
     manager::ProxyManager,
+    metrics::{TunnelSetupTimer, TunnelStage},
+    pool::UpstreamConnection,
+    shaping::{Direction, Throttle},
 };
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Instant;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use vpn_types::trace_context::{TraceContext, TRACEPARENT_HEADER};
 
 /// HTTP proxy handler
 #[derive(Clone)]
@@ -29,8 +36,22 @@ impl HttpProxy {
         let protocol = "http";
 
         self.manager.metrics().record_connection(protocol, true);
-
-        let result = self.handle_connection_inner(client, peer_addr).await;
+        let timer = self.manager.metrics().tunnel_timer(protocol);
+
+        let trace = TraceContext::new_root();
+        let span = info_span!(
+            "proxy_connection",
+            protocol,
+            peer = %peer_addr,
+            trace_id = %trace.trace_id(),
+            span_id = %trace.span_id(),
+        );
+        let result = trace
+            .scope(access_log::connection(
+                self.handle_connection_inner(client, peer_addr, timer),
+            ))
+            .instrument(span)
+            .await;
 
         // Record metrics
         let duration = start_time.elapsed().as_secs_f64();
@@ -46,8 +67,15 @@ impl HttpProxy {
         &self,
         mut client: TcpStream,
         peer_addr: SocketAddr,
+        timer: TunnelSetupTimer,
     ) -> Result<()> {
+        // Only the first request on a connection is timed from accept
+        let mut timer = Some(timer);
+
         loop {
+            // The previous request ends while waiting for the next one
+            access_log::finish();
+
             // Read request
             let request = match super::parser::parse_request(&mut client).await {
                 Ok(req) => req,
@@ -71,6 +99,10 @@ impl HttpProxy {
                 request.uri
             );
 
+            self.manager.begin_request("http", peer_addr);
+            access_log::set_method(request.method.as_str());
+            access_log::set_destination(request.host().unwrap_or(request.uri.as_str()));
+
             // Authenticate if required
             let user_id = match self
                 .manager
@@ -80,33 +112,100 @@ impl HttpProxy {
                 Ok(id) => id,
                 Err(e) => {
                     warn!("Authentication failed for {}: {}", peer_addr, e);
+                    access_log::terminate(Termination::AuthFailed);
+                    if let Some(mut timer) = timer.take() {
+                        timer.fail(TunnelStage::Auth, &e);
+                    }
                     self.send_auth_required_response(&mut client).await?;
                     continue;
                 }
             };
 
+            access_log::set_user(&user_id);
+
             // Check rate limit
             if let Err(e) = self.manager.check_rate_limit(&user_id).await {
                 warn!("Rate limit exceeded for user {}: {}", user_id, e);
-                self.send_error_response(&mut client, 429, "Too Many Requests")
+                if let Some(mut timer) = timer.take() {
+                    timer.fail(TunnelStage::Auth, &e);
+                }
+                let target = request.host().unwrap_or(request.uri.as_str()).to_string();
+                self.send_block_page(&mut client, BlockReason::RateLimit, &target)
                     .await?;
                 continue;
             }
 
+            // Held while the request or tunnel is served
+            let _permit = match self.manager.acquire_connection(&user_id) {
+                Ok(permit) => permit,
+                Err(e) => {
+                    if let Some(mut timer) = timer.take() {
+                        timer.fail(TunnelStage::Auth, &e);
+                    }
+                    let target = request.host().unwrap_or(request.uri.as_str()).to_string();
+                    self.send_block_page(&mut client, BlockReason::ConnectionLimit, &target)
+                        .await?;
+                    continue;
+                }
+            };
+
             // Handle the request
             let keep_alive = request.keep_alive();
 
+            // A request carrying a trace context continues the client's
+            // trace; others belong to the connection's
+            let trace = request
+                .trace_context()
+                .map(|parent| parent.child())
+                .unwrap_or_else(TraceContext::current_child);
+            let span = info_span!(
+                "proxy_request",
+                method = request.method.as_str(),
+                user = %user_id,
+                trace_id = %trace.trace_id(),
+                span_id = %trace.span_id(),
+            );
+
             match request.method {
                 HttpMethod::Connect => {
                     // HTTPS tunneling
-                    self.handle_connect(client, request, &user_id).await?;
+                    let mut timer = timer.take();
+                    if let Some(timer) = timer.as_mut() {
+                        timer.stage(TunnelStage::Auth);
+                    }
+                    trace
+                        .scope(self.handle_connect(
+                            client,
+                            request,
+                            &user_id,
+                            peer_addr.ip(),
+                            timer,
+                        ))
+                        .instrument(span)
+                        .await?;
                     // CONNECT always closes the connection after tunneling
                     return Ok(());
                 }
                 _ => {
-                    // Regular HTTP proxy
-                    self.handle_http_request(&mut client, request, &user_id)
+                    // Regular HTTP proxy; not a tunnel
+                    if let Some(timer) = timer.take() {
+                        timer.discard();
+                    }
+                    let framed = trace
+                        .scope(self.handle_http_request(
+                            &mut client,
+                            request,
+                            &user_id,
+                            peer_addr.ip(),
+                        ))
+                        .instrument(span)
                         .await?;
+                    // A response ending with the origin's connection ends
+                    // the client's too
+                    if !framed {
+                        debug!("Closing connection to {} (unframed response)", peer_addr);
+                        return Ok(());
+                    }
                 }
             }
 
@@ -124,17 +223,35 @@ impl HttpProxy {
         mut client: TcpStream,
         request: HttpRequest,
         user_id: &str,
+        peer: IpAddr,
+        mut timer: Option<TunnelSetupTimer>,
     ) -> Result<()> {
         // Parse target address
-        let target_addr = self.parse_connect_target(&request.uri)?;
+        let (host, port) = self.parse_connect_target(&request.uri)?;
 
-        info!("CONNECT tunnel from {} to {}", user_id, target_addr);
+        info!("CONNECT tunnel from {} to {}:{}", user_id, host, port);
+        access_log::set_destination(&format!("{}:{}", host, port));
+
+        if let Err(e) = self.manager.check_destination(user_id, &host, port).await {
+            if let Some(mut timer) = timer.take() {
+                timer.fail(TunnelStage::UpstreamConnect, &e);
+            }
+            self.send_block_page(&mut client, BlockReason::Acl, &host)
+                .await?;
+            return Err(e);
+        }
 
         // Connect to target
-        let upstream = match self.manager.get_connection(target_addr).await {
-            Ok(conn) => conn,
+        let connected = self.manager.connect_host(user_id, &host, port).await;
+        let connected = match timer.as_mut() {
+            Some(timer) => timer.track(TunnelStage::UpstreamConnect, connected),
+            None => connected,
+        };
+        let upstream = match connected {
+            Ok((conn, _)) => conn,
             Err(e) => {
-                error!("Failed to connect to {}: {}", target_addr, e);
+                error!("Failed to connect to {}:{}: {}", host, port, e);
+                access_log::terminate(Termination::ConnectFailed);
                 self.send_error_response(&mut client, 502, "Bad Gateway")
                     .await?;
                 return Err(e);
@@ -145,72 +262,223 @@ impl HttpProxy {
         client
             .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
             .await?;
+        access_log::set_status(200);
         client.flush().await?;
 
         // Start tunneling
-        super::tunnel::tunnel_data(client, upstream, user_id, &self.manager).await?;
+        super::tunnel::tunnel_data(client, upstream, user_id, peer, &self.manager, timer).await?;
 
         Ok(())
     }
 
-    /// Handle regular HTTP requests
+    /// Handle regular HTTP requests. Returns whether the response was
+    /// framed, so the client connection can carry the next request.
     async fn handle_http_request(
         &self,
         client: &mut TcpStream,
         request: HttpRequest,
         user_id: &str,
-    ) -> Result<()> {
+        peer: IpAddr,
+    ) -> Result<bool> {
+        if request.uri.starts_with("https://") {
+            return self
+                .handle_origin_request(client, request, user_id, peer)
+                .await;
+        }
+
         // Parse target URL
         let (host, port) = self.parse_http_target(&request)?;
-        let target_addr: SocketAddr = format!("{}:{}", host, port)
-            .parse()
-            .map_err(|e| ProxyError::invalid_request(format!("Invalid target address: {}", e)))?;
+        access_log::set_destination(&format!("{}:{}", host, port));
 
         debug!(
-            "HTTP {} request from {} to {}",
+            "HTTP {} request from {} to {}:{}",
             request.method.as_str(),
             user_id,
-            target_addr
+            host,
+            port
         );
 
-        // Connect to target
-        let mut upstream = match self.manager.get_connection(target_addr).await {
-            Ok(conn) => conn,
+        if let Err(e) = self.manager.check_destination(user_id, &host, port).await {
+            self.send_block_page(client, BlockReason::Acl, &host)
+                .await?;
+            return Err(e);
+        }
+
+        // Connect to target, reusing an idle connection when there is one
+        let upload = self.manager.throttle(user_id, peer, Direction::Upload);
+        let mut upstream = match self.manager.checkout_upstream(user_id, &host, port).await {
+            Ok(upstream) => upstream,
             Err(e) => {
-                error!("Failed to connect to {}: {}", target_addr, e);
+                error!("Failed to connect to {}:{}: {}", host, port, e);
+                access_log::terminate(Termination::ConnectFailed);
                 self.send_error_response(client, 502, "Bad Gateway").await?;
                 return Err(e);
             }
         };
 
-        // Forward the request
-        self.forward_http_request(&mut upstream, request).await?;
+        // Forward the request. An origin may close an idle connection just
+        // as it is reused; requests that are safe to repeat are then sent
+        // again on a new connection.
+        let sent = self
+            .forward_http_request(&mut upstream.stream, &request, &upload)
+            .await;
+        let retry = upstream.is_reused()
+            && request.method != HttpMethod::Post
+            && request.method != HttpMethod::Patch
+            && match sent {
+                Ok(_) => upstream.stream.peek(&mut [0u8; 1]).await.unwrap_or(0) == 0,
+                Err(_) => true,
+            };
+        if retry {
+            debug!(
+                "Pooled connection to {}:{} was closed, reconnecting",
+                host, port
+            );
+            let (stream, addr) = match self.manager.connect_host(user_id, &host, port).await {
+                Ok(connected) => connected,
+                Err(e) => {
+                    error!("Failed to connect to {}:{}: {}", host, port, e);
+                    access_log::terminate(Termination::ConnectFailed);
+                    self.send_error_response(client, 502, "Bad Gateway").await?;
+                    return Err(e);
+                }
+            };
+            upstream = UpstreamConnection::new(stream, addr);
+            let sent = self
+                .forward_http_request(&mut upstream.stream, &request, &upload)
+                .await?;
+            access_log::add_bytes(Direction::Upload, sent);
+        } else {
+            access_log::add_bytes(Direction::Upload, sent?);
+        }
+
+        // Read the response head, passing on interim responses
+        let mut reader = BufReader::new(&mut upstream.stream);
+        let mut head = match response::read_head(&mut reader).await {
+            Ok(head) => head,
+            Err(e) => {
+                error!("Invalid response from {}:{}: {}", host, port, e);
+                self.send_error_response(client, 502, "Bad Gateway").await?;
+                return Err(e);
+            }
+        };
+        let download = self.manager.throttle(user_id, peer, Direction::Download);
+        while head.is_interim() {
+            self.send(client, &head.raw, user_id, &download).await?;
+            head = response::read_head(&mut reader).await?;
+        }
 
-        // Read and forward the response
-        self.forward_http_response(&mut upstream, client, user_id)
+        // Forward the response
+        access_log::set_status(head.status);
+        self.send(client, &head.raw, user_id, &download).await?;
+        let length = head.body_length(&request.method);
+        self.forward_http_response(&mut reader, client, length, user_id, &download)
             .await?;
 
-        // Return connection to pool
-        self.manager.return_connection(target_addr, upstream).await;
+        // Return connection to pool when nothing is left to read from it
+        let reusable =
+            length != BodyLength::UntilClose && head.keep_alive() && reader.buffer().is_empty();
+        drop(reader);
+        if reusable {
+            self.manager.return_connection(&host, port, upstream);
+        }
 
-        Ok(())
+        Ok(length != BodyLength::UntilClose)
+    }
+
+    /// Fetch an `https://` URL on behalf of the client, through the shared
+    /// origin client
+    async fn handle_origin_request(
+        &self,
+        client: &mut TcpStream,
+        request: HttpRequest,
+        user_id: &str,
+        peer: IpAddr,
+    ) -> Result<bool> {
+        let (url, host, port) = origin::target(&request.uri)?;
+        access_log::set_destination(&format!("{}:{}", host, port));
+
+        debug!(
+            "HTTPS {} request from {} to {}:{}",
+            request.method.as_str(),
+            user_id,
+            host,
+            port
+        );
+
+        if let Err(e) = self.manager.check_destination(user_id, &host, port).await {
+            self.send_block_page(client, BlockReason::Acl, &host)
+                .await?;
+            return Err(e);
+        }
+
+        let mut builder = origin::build_request(self.manager.origin_client(), &request, url);
+        if let Some(body) = request.body {
+            let upload = self.manager.throttle(user_id, peer, Direction::Upload);
+            for chunk in body.chunks(upload.chunk_size(body.len().max(1))) {
+                upload.consume(chunk.len()).await;
+            }
+            access_log::add_bytes(Direction::Upload, body.len() as u64);
+            builder = builder.body(body);
+        }
+
+        let mut response = match builder.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Request to {}:{} failed: {}", host, port, e);
+                access_log::terminate(Termination::ConnectFailed);
+                self.send_error_response(client, 502, "Bad Gateway").await?;
+                return Err(ProxyError::upstream(e.to_string()));
+            }
+        };
+        debug!("{}:{} answered over {:?}", host, port, response.version());
+
+        let download = self.manager.throttle(user_id, peer, Direction::Download);
+        let (head, chunked) = origin::response_head(&response, &request.method);
+        access_log::set_status(response.status().as_u16());
+        self.send(client, head.as_bytes(), user_id, &download)
+            .await?;
+
+        while let Some(data) = response
+            .chunk()
+            .await
+            .map_err(|e| ProxyError::upstream(e.to_string()))?
+        {
+            if chunked {
+                let size = format!("{:x}\r\n", data.len());
+                self.send(client, size.as_bytes(), user_id, &download)
+                    .await?;
+                self.send(client, &data, user_id, &download).await?;
+                self.send(client, b"\r\n", user_id, &download).await?;
+            } else {
+                self.send(client, &data, user_id, &download).await?;
+            }
+        }
+        if chunked {
+            self.send(client, b"0\r\n\r\n", user_id, &download).await?;
+        }
+        client.flush().await?;
+
+        Ok(true)
     }
 
-    /// Parse CONNECT target address
-    fn parse_connect_target(&self, uri: &str) -> Result<SocketAddr> {
-        let parts: Vec<&str> = uri.split(':').collect();
-        if parts.len() != 2 {
+    /// Parse CONNECT target into host and port
+    fn parse_connect_target(&self, uri: &str) -> Result<(String, u16)> {
+        let (host, port) = uri
+            .rsplit_once(':')
+            .ok_or_else(|| ProxyError::invalid_request("Invalid CONNECT target"))?;
+
+        // IPv6 literals are bracketed: [2001:db8::1]:443
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
             return Err(ProxyError::invalid_request("Invalid CONNECT target"));
         }
 
-        let host = parts[0];
-        let port: u16 = parts[1]
+        let port: u16 = port
             .parse()
             .map_err(|_| ProxyError::invalid_request("Invalid port number"))?;
 
-        format!("{}:{}", host, port)
-            .parse()
-            .map_err(|e| ProxyError::invalid_request(format!("Invalid target address: {}", e)))
+        Ok((host.to_string(), port))
     }
 
     /// Parse HTTP target from request
@@ -252,12 +520,13 @@ impl HttpProxy {
         }
     }
 
-    /// Forward HTTP request to upstream
+    /// Forward HTTP request to upstream, returning the bytes sent
     async fn forward_http_request(
         &self,
         upstream: &mut TcpStream,
-        request: HttpRequest,
-    ) -> Result<()> {
+        request: &HttpRequest,
+        throttle: &Throttle,
+    ) -> Result<u64> {
         // Build request line
         let request_line = format!(
             "{} {} {}\r\n",
@@ -267,54 +536,170 @@ impl HttpProxy {
         );
 
         upstream.write_all(request_line.as_bytes()).await?;
+        let mut sent = request_line.len() as u64;
 
-        // Forward headers (skip Proxy-Authorization)
+        // Forward headers (skip Proxy-Authorization). A client's trace
+        // context is passed on with this proxy's span as the parent; traces
+        // are never started on behalf of clients that did not send one.
         for (name, value) in &request.headers {
-            if name.to_lowercase() != "proxy-authorization" {
+            if name.eq_ignore_ascii_case(TRACEPARENT_HEADER) {
+                let value = TraceContext::current()
+                    .map(|trace| trace.to_string())
+                    .unwrap_or_else(|| value.clone());
+                let header_line = format!("{}: {}\r\n", name, value);
+                upstream.write_all(header_line.as_bytes()).await?;
+                sent += header_line.len() as u64;
+            } else if name.to_lowercase() != "proxy-authorization" {
                 let header_line = format!("{}: {}\r\n", name, value);
                 upstream.write_all(header_line.as_bytes()).await?;
+                sent += header_line.len() as u64;
             }
         }
 
         upstream.write_all(b"\r\n").await?;
+        sent += 2;
 
         // Forward body if present
-        if let Some(body) = request.body {
-            upstream.write_all(&body).await?;
+        if let Some(body) = &request.body {
+            for chunk in body.chunks(throttle.chunk_size(body.len().max(1))) {
+                throttle.consume(chunk.len()).await;
+                upstream.write_all(chunk).await?;
+                sent += chunk.len() as u64;
+            }
         }
 
         upstream.flush().await?;
 
-        Ok(())
+        Ok(sent)
     }
 
-    /// Forward HTTP response from upstream to client
-    async fn forward_http_response(
+    /// Forward the body of an HTTP response from upstream to client,
+    /// through its end as given by `length`
+    async fn forward_http_response<R: AsyncBufRead + Unpin>(
         &self,
-        upstream: &mut TcpStream,
+        upstream: &mut R,
         client: &mut TcpStream,
+        length: BodyLength,
         user_id: &str,
+        throttle: &Throttle,
     ) -> Result<()> {
+        let total_bytes = match length {
+            BodyLength::Empty => 0,
+            BodyLength::Fixed(len) => {
+                self.copy_body(upstream, client, Some(len), user_id, throttle)
+                    .await?
+            }
+            BodyLength::UntilClose => {
+                self.copy_body(upstream, client, None, user_id, throttle)
+                    .await?
+            }
+            BodyLength::Chunked => {
+                let mut total = 0;
+                loop {
+                    let line = self.copy_line(upstream, client, user_id, throttle).await?;
+                    let size = response::chunk_size(&line)?;
+                    if size == 0 {
+                        // Trailer fields, through the closing blank line
+                        while !self
+                            .copy_line(upstream, client, user_id, throttle)
+                            .await?
+                            .trim_ascii()
+                            .is_empty()
+                        {}
+                        break;
+                    }
+                    // Chunk data and the line break after it
+                    total += self
+                        .copy_body(upstream, client, Some(size + 2), user_id, throttle)
+                        .await?;
+                }
+                total
+            }
+        };
+
+        client.flush().await?;
+
+        debug!("Forwarded {} bytes of response to {}", total_bytes, user_id);
+
+        Ok(())
+    }
+
+    /// Copy `limit` bytes, or everything until the origin closes the
+    /// connection, from upstream to client
+    async fn copy_body<R: AsyncBufRead + Unpin>(
+        &self,
+        upstream: &mut R,
+        client: &mut TcpStream,
+        limit: Option<u64>,
+        user_id: &str,
+        throttle: &Throttle,
+    ) -> Result<u64> {
         let mut buffer = vec![0u8; 8192];
         let mut total_bytes = 0u64;
 
         loop {
-            let n = upstream.read(&mut buffer).await?;
+            let mut chunk = throttle.chunk_size(buffer.len());
+            if let Some(limit) = limit {
+                if total_bytes == limit {
+                    break;
+                }
+                chunk = chunk.min((limit - total_bytes) as usize);
+            }
+
+            let n = upstream.read(&mut buffer[..chunk]).await?;
             if n == 0 {
+                if limit.is_some() {
+                    return Err(ProxyError::upstream(
+                        "Origin closed the connection mid-response",
+                    ));
+                }
                 break;
             }
 
-            client.write_all(&buffer[..n]).await?;
+            self.send(client, &buffer[..n], user_id, throttle).await?;
             total_bytes += n as u64;
-
-            // Record bandwidth
-            self.manager.record_bandwidth(user_id, n as u64).await?;
         }
 
-        client.flush().await?;
+        Ok(total_bytes)
+    }
 
-        debug!("Forwarded {} bytes of response to {}", total_bytes, user_id);
+    /// Copy one line of a chunked body from upstream to client
+    async fn copy_line<R: AsyncBufRead + Unpin>(
+        &self,
+        upstream: &mut R,
+        client: &mut TcpStream,
+        user_id: &str,
+        throttle: &Throttle,
+    ) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        if upstream.read_until(b'\n', &mut line).await? == 0 {
+            return Err(ProxyError::upstream(
+                "Origin closed the connection mid-response",
+            ));
+        }
+        self.send(client, &line, user_id, throttle).await?;
+        Ok(line)
+    }
 
+    /// Write response bytes to the client at the throttled rate and record
+    /// them against the user's bandwidth
+    async fn send(
+        &self,
+        client: &mut TcpStream,
+        data: &[u8],
+        user_id: &str,
+        throttle: &Throttle,
+    ) -> Result<()> {
+        for chunk in data.chunks(throttle.chunk_size(data.len().max(1))) {
+            throttle.consume(chunk.len()).await;
+            client.write_all(chunk).await?;
+            access_log::add_bytes(Direction::Download, chunk.len() as u64);
+
+            // Record bandwidth
+            self.manager
+                .record_bandwidth(user_id, chunk.len() as u64)
+                .await?;
+        }
         Ok(())
     }
 
@@ -325,6 +710,7 @@ impl HttpProxy {
         code: u16,
         reason: &str,
     ) -> Result<()> {
+        access_log::set_status(code);
         let response = format!(
             "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
             code, reason
@@ -336,8 +722,26 @@ impl HttpProxy {
         Ok(())
     }
 
+    /// Send the page explaining why a request to `host` was refused
+    async fn send_block_page(
+        &self,
+        client: &mut TcpStream,
+        reason: BlockReason,
+        host: &str,
+    ) -> Result<()> {
+        access_log::set_status(reason.status().0);
+        access_log::terminate(reason.into());
+        let response = self.manager.block_pages().response(reason, host);
+
+        client.write_all(response.as_bytes()).await?;
+        client.flush().await?;
+
+        Ok(())
+    }
+
     /// Send authentication required response
     async fn send_auth_required_response(&self, client: &mut TcpStream) -> Result<()> {
+        access_log::set_status(407);
         let response = "HTTP/1.1 407 Proxy Authentication Required\r\n\
                        Proxy-Authenticate: Basic realm=\"Proxy\"\r\n\
                        Content-Length: 0\r\n\
@@ -1,10 +1,15 @@
 //! HTTP/HTTPS proxy implementation
 
 mod handler;
+mod origin;
 mod parser;
+mod response;
 mod tunnel;
 
 pub use handler::HttpProxy;
+pub(crate) use origin::build_client as build_origin_client;
+
+use vpn_types::trace_context::{TraceContext, TRACEPARENT_HEADER};
 
 /// HTTP methods we support
 #[derive(Debug, Clone, PartialEq)]
@@ -92,6 +97,14 @@ impl HttpRequest {
             })
     }
 
+    /// Trace context the client sent in a `traceparent` header
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(TRACEPARENT_HEADER))
+            .and_then(|(_, value)| TraceContext::parse(value))
+    }
+
     /// Check if connection should be kept alive
     pub fn keep_alive(&self) -> bool {
         // HTTP/1.1 defaults to keep-alive
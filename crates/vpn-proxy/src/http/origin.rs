@@ -0,0 +1,161 @@
+//! Requests for `https://` URLs, sent by the proxy itself
+//!
+//! A client may ask the proxy to fetch an `https://` URL instead of opening
+//! a CONNECT tunnel. The proxy then talks TLS to the origin through one
+//! shared [`reqwest::Client`], which keeps connections alive between
+//! requests and speaks HTTP/2 with origins that offer it during the TLS
+//! handshake. The response goes back to the client as HTTP/1.1.
+
+use super::{HttpMethod, HttpRequest};
+use crate::config::ProxyConfig;
+use crate::dns::{OriginResolver, Resolver};
+use crate::error::{ProxyError, Result};
+use std::sync::Arc;
+use vpn_types::trace_context::{TraceContext, TRACEPARENT_HEADER};
+
+/// Headers that describe one connection rather than the message and are
+/// never passed between client and origin
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "host",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn is_hop_by_hop(name: &str) -> bool {
+    HOP_BY_HOP
+        .iter()
+        .any(|header| name.eq_ignore_ascii_case(header))
+}
+
+/// Client for origins reached over TLS, pooled per origin, looking up
+/// origins with `resolver`
+pub fn build_client(config: &ProxyConfig, resolver: Arc<Resolver>) -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .dns_resolver(Arc::new(OriginResolver(resolver)))
+        .pool_idle_timeout(config.pool.idle_timeout)
+        .pool_max_idle_per_host(config.pool.max_connections_per_host as usize)
+        .connect_timeout(config.timeouts.connect)
+        // Redirects are the client's to follow
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy()
+        .build()
+        .map_err(|e| ProxyError::config(format!("Failed to create origin client: {}", e)))
+}
+
+/// Host and port of an `https://` request target
+pub fn target(uri: &str) -> Result<(url::Url, String, u16)> {
+    let url = url::Url::parse(uri)
+        .map_err(|e| ProxyError::invalid_request(format!("Invalid URL: {}", e)))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| ProxyError::invalid_request("URL without host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    Ok((url, host, port))
+}
+
+/// The origin request for `request`; its body is passed separately. A
+/// client's trace context is passed on with this proxy's span as the parent.
+pub fn build_request(
+    client: &reqwest::Client,
+    request: &HttpRequest,
+    url: url::Url,
+) -> reqwest::RequestBuilder {
+    let method = match request.method {
+        HttpMethod::Get => reqwest::Method::GET,
+        HttpMethod::Post => reqwest::Method::POST,
+        HttpMethod::Put => reqwest::Method::PUT,
+        HttpMethod::Delete => reqwest::Method::DELETE,
+        HttpMethod::Head => reqwest::Method::HEAD,
+        HttpMethod::Options => reqwest::Method::OPTIONS,
+        HttpMethod::Patch => reqwest::Method::PATCH,
+        HttpMethod::Trace => reqwest::Method::TRACE,
+        HttpMethod::Connect => reqwest::Method::CONNECT,
+    };
+
+    request
+        .headers
+        .iter()
+        .filter(|(name, _)| !is_hop_by_hop(name))
+        .fold(client.request(method, url), |builder, (name, value)| {
+            if name.eq_ignore_ascii_case(TRACEPARENT_HEADER) {
+                let value = TraceContext::current()
+                    .map(|trace| trace.to_string())
+                    .unwrap_or_else(|| value.clone());
+                builder.header(name.as_str(), value)
+            } else {
+                builder.header(name.as_str(), value.as_str())
+            }
+        })
+}
+
+/// Status line and headers of `response` as HTTP/1.1, and whether the body
+/// is sent with chunked encoding because the origin gave no length
+pub fn response_head(response: &reqwest::Response, method: &HttpMethod) -> (String, bool) {
+    let status = response.status();
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("")
+    );
+
+    let mut has_length = false;
+    for (name, value) in response.headers() {
+        if is_hop_by_hop(name.as_str()) {
+            continue;
+        }
+        has_length |= name == reqwest::header::CONTENT_LENGTH;
+        head.push_str(&format!(
+            "{}: {}\r\n",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+
+    let bodiless = *method == HttpMethod::Head
+        || status.is_informational()
+        || status == reqwest::StatusCode::NO_CONTENT
+        || status == reqwest::StatusCode::NOT_MODIFIED;
+    let chunked = !has_length && !bodiless;
+    if chunked {
+        head.push_str("Transfer-Encoding: chunked\r\n");
+    }
+    head.push_str("\r\n");
+
+    (head, chunked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target() {
+        let (url, host, port) = target("https://Example.com/path?q=1").unwrap();
+        assert_eq!(host, "example.com");
+        assert_eq!(port, 443);
+        assert_eq!(url.path(), "/path");
+
+        let (_, host, port) = target("https://[2001:db8::1]:8443/").unwrap();
+        assert_eq!(host, "2001:db8::1");
+        assert_eq!(port, 8443);
+
+        assert!(target("https://:443/").is_err());
+    }
+
+    #[test]
+    fn test_hop_by_hop_headers() {
+        assert!(is_hop_by_hop("Proxy-Authorization"));
+        assert!(is_hop_by_hop("connection"));
+        assert!(!is_hop_by_hop("Content-Type"));
+    }
+}
@@ -119,4 +119,23 @@ mod tests {
         // This is a placeholder for the test structure
         assert!(true);
     }
+
+    #[test]
+    fn test_request_trace_context() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut request = HttpRequest {
+            method: HttpMethod::Get,
+            uri: "http://example.com/".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: vec![("Host".to_string(), "example.com".to_string())],
+            body: None,
+        };
+        assert!(request.trace_context().is_none());
+
+        request
+            .headers
+            .push(("Traceparent".to_string(), header.to_string()));
+        let trace = request.trace_context().unwrap();
+        assert_eq!(trace.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
 }
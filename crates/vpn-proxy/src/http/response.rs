@@ -0,0 +1,209 @@
+//! HTTP/1.x responses from origin servers
+//!
+//! Responses are relayed by their framing (RFC 9112, section 6.3) instead
+//! of until the origin closes the connection, so the connection can serve
+//! the next request once the body has been read.
+
+use super::HttpMethod;
+use crate::error::{ProxyError, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Upper bound on a response head, against origins that never end it
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Status line and headers of a response
+#[derive(Debug)]
+pub struct ResponseHead {
+    pub version: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// The head as received, relayed to the client unchanged
+    pub raw: Vec<u8>,
+}
+
+/// How the end of a response body is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLength {
+    Empty,
+    Fixed(u64),
+    Chunked,
+    /// The body ends when the origin closes the connection
+    UntilClose,
+}
+
+impl ResponseHead {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.headers
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// A 1xx response other than 101, followed by the final response
+    pub fn is_interim(&self) -> bool {
+        (100..200).contains(&self.status) && self.status != 101
+    }
+
+    /// Framing of the body that follows this head in answer to `method`
+    pub fn body_length(&self, method: &HttpMethod) -> BodyLength {
+        if *method == HttpMethod::Head
+            || self.is_interim()
+            || self.status == 204
+            || self.status == 304
+        {
+            return BodyLength::Empty;
+        }
+        if self.status == 101 {
+            return BodyLength::UntilClose;
+        }
+        if let Some(encoding) = self.header("transfer-encoding") {
+            // Only a final chunked coding delimits the body
+            return if encoding
+                .rsplit(',')
+                .next()
+                .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"))
+            {
+                BodyLength::Chunked
+            } else {
+                BodyLength::UntilClose
+            };
+        }
+        match self.header("content-length").map(|len| len.parse()) {
+            Some(Ok(0)) => BodyLength::Empty,
+            Some(Ok(len)) => BodyLength::Fixed(len),
+            _ => BodyLength::UntilClose,
+        }
+    }
+
+    /// Whether the origin keeps the connection open after this response
+    pub fn keep_alive(&self) -> bool {
+        if self.has_token("connection", "close") {
+            return false;
+        }
+        self.version == "HTTP/1.1" || self.has_token("connection", "keep-alive")
+    }
+}
+
+/// Read a response head, through the blank line ending it
+pub async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<ResponseHead> {
+    let mut raw = Vec::new();
+    let mut lines = Vec::new();
+
+    loop {
+        let start = raw.len();
+        let n = reader.read_until(b'\n', &mut raw).await?;
+        if n == 0 {
+            return Err(ProxyError::upstream(
+                "Origin closed the connection before responding",
+            ));
+        }
+        if raw.len() > MAX_HEAD_SIZE {
+            return Err(ProxyError::upstream("Response head too large"));
+        }
+
+        let line = String::from_utf8_lossy(&raw[start..])
+            .trim_end()
+            .to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+
+    let status_line = lines
+        .first()
+        .ok_or_else(|| ProxyError::upstream("Empty response"))?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default().to_string();
+    let status = parts
+        .next()
+        .and_then(|status| status.parse().ok())
+        .filter(|_| version.starts_with("HTTP/1."))
+        .ok_or_else(|| ProxyError::upstream(format!("Invalid status line: {}", status_line)))?;
+
+    let headers = lines[1..]
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    Ok(ResponseHead {
+        version,
+        status,
+        headers,
+        raw,
+    })
+}
+
+/// Size of a chunk from its size line, ignoring chunk extensions
+pub fn chunk_size(line: &[u8]) -> Result<u64> {
+    let line = String::from_utf8_lossy(line);
+    let size = line.split(';').next().unwrap_or_default().trim();
+    u64::from_str_radix(size, 16)
+        .map_err(|_| ProxyError::upstream(format!("Invalid chunk size: {}", size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn head(raw: &str) -> ResponseHead {
+        read_head(&mut raw.as_bytes()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_body_length() {
+        let fixed = head("HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nhello world!").await;
+        assert_eq!(fixed.body_length(&HttpMethod::Get), BodyLength::Fixed(12));
+        assert_eq!(fixed.body_length(&HttpMethod::Head), BodyLength::Empty);
+        assert!(fixed.keep_alive());
+        assert_eq!(
+            fixed.raw,
+            b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\n".to_vec()
+        );
+
+        let chunked = head("HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n").await;
+        assert_eq!(chunked.body_length(&HttpMethod::Get), BodyLength::Chunked);
+
+        let unframed = head("HTTP/1.0 200 OK\r\nServer: old\r\n\r\n").await;
+        assert_eq!(
+            unframed.body_length(&HttpMethod::Get),
+            BodyLength::UntilClose
+        );
+        assert!(!unframed.keep_alive());
+
+        let not_modified = head("HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(
+            not_modified.body_length(&HttpMethod::Get),
+            BodyLength::Empty
+        );
+        assert!(!not_modified.keep_alive());
+
+        assert!(head("HTTP/1.1 100 Continue\r\n\r\n").await.is_interim());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_heads() {
+        assert!(read_head(&mut "SSH-2.0-OpenSSH\r\n\r\n".as_bytes())
+            .await
+            .is_err());
+        assert!(read_head(&mut "HTTP/1.1 200 OK\r\n".as_bytes())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        assert_eq!(chunk_size(b"1a\r\n").unwrap(), 26);
+        assert_eq!(chunk_size(b"0;ext=1\r\n").unwrap(), 0);
+        assert!(chunk_size(b"zz\r\n").is_err());
+    }
+}
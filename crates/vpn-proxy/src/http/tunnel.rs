@@ -1,60 +1,110 @@
 //! HTTP tunnel implementation for CONNECT method
 
-use crate::{error::Result, manager::ProxyManager};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::{
+    access_log::{self, Termination},
+    error::Result,
+    limits::TunnelActivity,
+    manager::ProxyManager,
+    metrics::TunnelSetupTimer,
+    shaping::{Direction, Throttle},
+    zero_copy::Forwarder,
+};
+use std::net::IpAddr;
 use tokio::net::TcpStream;
 use tracing::{debug, error};
 
-/// Tunnel data between client and upstream server
+/// Tunnel data between client and upstream server, finishing `timer` when
+/// the first upstream bytes reach the client. Both directions are shaped
+/// to the bandwidth limits of `user_id` and `peer`, and the tunnel is
+/// closed once it idles or reaches its maximum lifetime.
 pub async fn tunnel_data(
     client: TcpStream,
     upstream: TcpStream,
     user_id: &str,
+    peer: IpAddr,
     manager: &ProxyManager,
+    timer: Option<TunnelSetupTimer>,
 ) -> Result<()> {
     let (client_reader, client_writer) = client.into_split();
+    let country = upstream
+        .peer_addr()
+        .ok()
+        .and_then(|addr| manager.geoip().country_label(addr.ip()));
     let (upstream_reader, upstream_writer) = upstream.into_split();
+    let watchdog = manager.tunnel_watchdog();
 
-    let client_to_upstream = tokio::spawn({
+    let mut client_to_upstream = tokio::spawn({
         let user_id = user_id.to_string();
         let manager = manager.clone();
-        async move {
+        let throttle = manager.throttle(&user_id, peer, Direction::Upload);
+        let activity = watchdog.activity();
+        let country = country.clone();
+        access_log::inherit(async move {
             tunnel_direction(
                 client_reader,
                 upstream_writer,
                 "client->upstream",
                 &user_id,
+                country.as_deref(),
                 &manager,
+                throttle,
+                activity,
+                None,
             )
             .await
-        }
+        })
     });
 
-    let upstream_to_client = tokio::spawn({
+    let mut upstream_to_client = tokio::spawn({
         let user_id = user_id.to_string();
         let manager = manager.clone();
-        async move {
+        let throttle = manager.throttle(&user_id, peer, Direction::Download);
+        let activity = watchdog.activity();
+        let country = country.clone();
+        access_log::inherit(async move {
             tunnel_direction(
                 upstream_reader,
                 client_writer,
                 "upstream->client",
                 &user_id,
+                country.as_deref(),
                 &manager,
+                throttle,
+                activity,
+                timer,
             )
             .await
-        }
+        })
     });
 
-    // Wait for either direction to complete
-    tokio::select! {
-        result = client_to_upstream => {
-            if let Err(e) = result {
-                error!("Client to upstream task failed: {}", e);
+    // Wait for both directions, so half-closed tunnels keep working, unless
+    // the watchdog closes the tunnel first
+    let expired = watchdog.expired();
+    tokio::pin!(expired);
+    let (mut upload_done, mut download_done) = (false, false);
+    while !(upload_done && download_done) {
+        tokio::select! {
+            result = &mut client_to_upstream, if !upload_done => {
+                upload_done = true;
+                access_log::terminate(Termination::ClientClosed);
+                if let Err(e) = result {
+                    error!("Client to upstream task failed: {}", e);
+                }
             }
-        }
-        result = upstream_to_client => {
-            if let Err(e) = result {
-                error!("Upstream to client task failed: {}", e);
+            result = &mut upstream_to_client, if !download_done => {
+                download_done = true;
+                access_log::terminate(Termination::UpstreamClosed);
+                if let Err(e) = result {
+                    error!("Upstream to client task failed: {}", e);
+                }
+            }
+            expiry = &mut expired => {
+                debug!("Closing tunnel of user {} ({} timeout)", user_id, expiry.as_str());
+                manager.metrics().record_tunnel_expired(expiry);
+                access_log::terminate(expiry.into());
+                client_to_upstream.abort();
+                upstream_to_client.abort();
+                break;
             }
         }
     }
@@ -65,22 +115,36 @@ pub async fn tunnel_data(
 }
 
 /// Tunnel data in one direction
+#[allow(clippy::too_many_arguments)]
 async fn tunnel_direction<R, W>(
-    mut reader: R,
-    mut writer: W,
+    reader: R,
+    writer: W,
     direction: &str,
     user_id: &str,
+    country: Option<&str>,
     manager: &ProxyManager,
+    throttle: Throttle,
+    activity: TunnelActivity,
+    mut timer: Option<TunnelSetupTimer>,
 ) -> Result<()>
 where
-    R: AsyncReadExt + Unpin,
-    W: AsyncWriteExt + Unpin,
+    R: AsRef<TcpStream>,
+    W: AsRef<TcpStream>,
 {
-    let mut buffer = vec![0u8; 8192];
+    let mut forwarder = Forwarder::new();
     let mut total_bytes = 0u64;
+    let traffic = if direction == "client->upstream" {
+        Direction::Upload
+    } else {
+        Direction::Download
+    };
 
     loop {
-        let n = match reader.read(&mut buffer).await {
+        let chunk = throttle.chunk_size(forwarder.chunk_size());
+        let n = match forwarder
+            .forward(reader.as_ref(), writer.as_ref(), chunk)
+            .await
+        {
             Ok(0) => {
                 debug!(
                     "Connection closed ({}) after {} bytes",
@@ -90,22 +154,19 @@ where
             }
             Ok(n) => n,
             Err(e) => {
-                debug!("Read error ({}): {}", direction, e);
+                debug!("Transfer error ({}): {}", direction, e);
                 break;
             }
         };
 
-        if let Err(e) = writer.write_all(&buffer[..n]).await {
-            debug!("Write error ({}): {}", direction, e);
-            break;
-        }
-
-        if let Err(e) = writer.flush().await {
-            debug!("Flush error ({}): {}", direction, e);
-            break;
-        }
+        throttle.consume(n).await;
 
         total_bytes += n as u64;
+        activity.touch();
+        access_log::add_bytes(traffic, n as u64);
+        if let Some(timer) = timer.take() {
+            timer.first_byte();
+        }
 
         // Record bandwidth for client->upstream direction
         if direction == "client->upstream" {
@@ -126,6 +187,12 @@ where
     manager
         .metrics()
         .record_bytes_transferred(total_bytes, metric_direction);
+    if let Some(country) = country {
+        manager
+            .metrics()
+            .record_destination_bytes(country, metric_direction, total_bytes);
+    }
+    forwarder.record(manager.metrics());
 
     Ok(())
 }
@@ -3,28 +3,47 @@
 //! This crate provides HTTP/HTTPS and SOCKS5 proxy server functionality
 //! with authentication, rate limiting, and monitoring capabilities.
 
+pub mod access_log;
+pub mod acl;
 pub mod auth;
+pub mod auth_backend;
+pub mod block_page;
 pub mod config;
+pub mod dns;
 pub mod error;
+pub mod geoip;
+pub mod happy_eyeballs;
 pub mod http;
+pub mod limits;
 pub mod manager;
 pub mod metrics;
 pub mod pool;
+pub mod provisioning;
 pub mod rate_limit;
+pub mod sessions;
+pub mod shaping;
+pub mod sniff;
 pub mod socks5;
 pub mod zero_copy;
 
+pub use acl::{AccessList, AclStore};
+pub use geoip::GeoIpStore;
 pub use config::{ProxyConfig, ProxyProtocol};
 pub use error::{ProxyError, Result};
 pub use manager::ProxyManager;
 pub use metrics::ProxyMetrics;
+pub use provisioning::{AdminApiProvisioner, FileProvisioner};
+pub use sessions::SessionTable;
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use tokio::net::TcpListener;
 use tracing::{error, info};
 
 /// Main proxy server that can handle both HTTP and SOCKS5 protocols
 pub struct ProxyServer {
     config: ProxyConfig,
+    config_path: Option<PathBuf>,
     manager: ProxyManager,
     metrics: ProxyMetrics,
 }
@@ -37,18 +56,89 @@ impl ProxyServer {
 
         Ok(Self {
             config,
+            config_path: None,
             manager,
             metrics,
         })
     }
 
+    /// Reload the configuration from the file at `path` on SIGHUP or
+    /// [`reload`](Self::reload), keeping established connections
+    pub fn with_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Re-read the configuration file and apply it to new connections
+    pub async fn reload(&self) -> Result<()> {
+        let path = self
+            .config_path
+            .as_ref()
+            .ok_or_else(|| ProxyError::config("No configuration file to reload"))?;
+        self.manager.reload_from_file(path).await
+    }
+
     /// Start the proxy server
     pub async fn start(&self) -> Result<()> {
-        match self.config.protocol {
+        // Pick up access list changes without a restart
+        let acl = &self.config.acl;
+        let acl_watcher = acl
+            .path
+            .is_some()
+            .then(|| self.manager.acl().clone().watch(acl.reload_interval));
+        // Keep the GeoIP database current when it has an update URL
+        let geoip_updater = self.manager.geoip().clone().watch();
+        let session_sweeper = self.manager.sessions().clone().sweep();
+        let udp_relay = self.start_udp_relay().await?;
+        #[cfg(unix)]
+        let reload_watcher = match &self.config_path {
+            Some(path) => Some(self.manager.reload_on_hangup(path.clone())?),
+            None => None,
+        };
+
+        let result = match self.config.protocol {
             ProxyProtocol::Http => self.start_http_proxy().await,
             ProxyProtocol::Socks5 => self.start_socks5_proxy().await,
             ProxyProtocol::Both => self.start_combined_proxy().await,
+            ProxyProtocol::Shared => self.start_shared_proxy().await,
+        };
+
+        if let Some(watcher) = acl_watcher {
+            watcher.abort();
         }
+        if let Some(updater) = geoip_updater {
+            updater.abort();
+        }
+        session_sweeper.abort();
+        if let Some(relay) = udp_relay {
+            relay.abort();
+        }
+        #[cfg(unix)]
+        if let Some(watcher) = reload_watcher {
+            watcher.abort();
+        }
+        result
+    }
+
+    /// Relay SOCKS5 UDP associations on the port every replica shares, if
+    /// one is configured
+    async fn start_udp_relay(&self) -> Result<Option<tokio::task::JoinHandle<()>>> {
+        let Some(port) = self.manager.sessions().udp_relay_port() else {
+            return Ok(None);
+        };
+        let listen = match self.config.protocol {
+            ProxyProtocol::Http => return Ok(None),
+            ProxyProtocol::Shared => self.config.bind_address()?,
+            ProxyProtocol::Socks5 | ProxyProtocol::Both => self.config.socks5_bind_address()?,
+        };
+
+        let addr = SocketAddr::new(listen.ip(), port);
+        let relay = socks5::UdpRelay::bind(self.manager.clone(), addr).await?;
+        Ok(Some(tokio::spawn(async move {
+            if let Err(e) = relay.run().await {
+                error!("SOCKS5 UDP relay failed: {}", e);
+            }
+        })))
     }
 
     /// Start HTTP/HTTPS proxy server
@@ -114,6 +204,39 @@ impl ProxyServer {
         Ok(())
     }
 
+    /// Serve HTTP and SOCKS5 on one port, dispatching each connection by
+    /// its first byte
+    async fn start_shared_proxy(&self) -> Result<()> {
+        let addr = self.config.bind_address()?;
+        info!("Starting shared HTTP and SOCKS5 proxy server on {}", addr);
+
+        let listener = TcpListener::bind(addr).await?;
+        let http_proxy = http::HttpProxy::new(self.manager.clone());
+        let socks_proxy = socks5::Socks5Server::new(self.manager.clone());
+        let sniff_timeout = self.config.timeouts.read;
+
+        loop {
+            let (socket, peer_addr) = listener.accept().await?;
+            let http_proxy = http_proxy.clone();
+            let socks_proxy = socks_proxy.clone();
+
+            tokio::spawn(async move {
+                let result = match sniff::sniff(&socket, sniff_timeout).await {
+                    Ok(sniff::SniffedProtocol::Http) => {
+                        http_proxy.handle_connection(socket, peer_addr).await
+                    }
+                    Ok(sniff::SniffedProtocol::Socks5) => {
+                        socks_proxy.handle_connection(socket, peer_addr).await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    error!("Proxy error from {}: {}", peer_addr, e);
+                }
+            });
+        }
+    }
+
     /// Get server metrics
     pub fn metrics(&self) -> &ProxyMetrics {
         &self.metrics
@@ -131,6 +254,7 @@ impl Clone for ProxyServer {
     fn clone(&self) -> Self {
         Self {
             config: self.config.clone(),
+            config_path: self.config_path.clone(),
             manager: self.manager.clone(),
             metrics: self.metrics.clone(),
         }
@@ -0,0 +1,256 @@
+//! Per-user concurrent connection limits and tunnel idle and lifetime
+//! timeouts
+
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Counts the open connections of each user against their limit
+#[derive(Debug, Default)]
+pub struct ConnectionLimiter {
+    limits: RwLock<ConnectionLimits>,
+    active: DashMap<String, u32>,
+}
+
+#[derive(Debug, Default)]
+struct ConnectionLimits {
+    default_limit: Option<u32>,
+    user_limits: HashMap<String, u32>,
+}
+
+impl ConnectionLimiter {
+    /// Limit users to `default_limit` connections each, or to their entry
+    /// in `user_limits`; 0 means unlimited
+    pub fn new(default_limit: Option<u32>, user_limits: HashMap<String, u32>) -> Self {
+        Self {
+            limits: RwLock::new(ConnectionLimits {
+                default_limit,
+                user_limits,
+            }),
+            active: DashMap::new(),
+        }
+    }
+
+    /// Replace the limits. Open connections stay counted, so users above a
+    /// lowered limit keep them but cannot open new ones.
+    pub fn set_limits(&self, default_limit: Option<u32>, user_limits: HashMap<String, u32>) {
+        *self.limits.write().unwrap_or_else(|e| e.into_inner()) = ConnectionLimits {
+            default_limit,
+            user_limits,
+        };
+    }
+
+    /// Limit applying to `user_id`, None if unlimited
+    pub fn limit(&self, user_id: &str) -> Option<u32> {
+        let limits = self.limits.read().unwrap_or_else(|e| e.into_inner());
+        limits
+            .user_limits
+            .get(user_id)
+            .copied()
+            .or(limits.default_limit)
+            .filter(|limit| *limit > 0)
+    }
+
+    /// Count a new connection of `user_id`, or return None if the user
+    /// already has as many as allowed. The connection is counted until the
+    /// permit is dropped.
+    pub fn acquire(self: &Arc<Self>, user_id: &str) -> Option<ConnectionPermit> {
+        let limit = self.limit(user_id);
+        let mut active = self.active.entry(user_id.to_string()).or_insert(0);
+        if limit.is_some_and(|limit| *active >= limit) {
+            return None;
+        }
+        *active += 1;
+
+        Some(ConnectionPermit {
+            limiter: self.clone(),
+            user_id: user_id.to_string(),
+        })
+    }
+
+    /// Open connections of `user_id`
+    pub fn active(&self, user_id: &str) -> u32 {
+        self.active.get(user_id).map(|active| *active).unwrap_or(0)
+    }
+}
+
+/// An open connection counted against its user's limit
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    user_id: String,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        // Users without open connections are forgotten
+        self.limiter
+            .active
+            .remove_if_mut(&self.user_id, |_, active| {
+                *active = active.saturating_sub(1);
+                *active == 0
+            });
+    }
+}
+
+/// Why a tunnel was closed by its [`TunnelWatchdog`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelExpiry {
+    /// No data moved in either direction for the idle timeout
+    Idle,
+    /// The tunnel was open for its maximum lifetime
+    Lifetime,
+}
+
+impl TunnelExpiry {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Lifetime => "lifetime",
+        }
+    }
+}
+
+/// Closes tunnels that sat idle or stayed open too long. Both directions
+/// of a tunnel report traffic through a shared [`TunnelActivity`].
+#[derive(Debug)]
+pub struct TunnelWatchdog {
+    activity: TunnelActivity,
+    idle_timeout: Option<Duration>,
+    max_lifetime: Option<Duration>,
+}
+
+/// Last time data moved through a tunnel
+#[derive(Debug, Clone)]
+pub struct TunnelActivity {
+    opened_at: Instant,
+    /// Milliseconds after `opened_at`
+    last_active: Arc<AtomicU64>,
+}
+
+impl TunnelActivity {
+    /// Record that data moved now
+    pub fn touch(&self) {
+        let elapsed = self.opened_at.elapsed().as_millis() as u64;
+        self.last_active.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    fn last_active(&self) -> Instant {
+        self.opened_at + Duration::from_millis(self.last_active.load(Ordering::Relaxed))
+    }
+}
+
+impl TunnelWatchdog {
+    /// Watchdog for a tunnel opened now; timeouts left unset never expire
+    pub fn new(idle_timeout: Option<Duration>, max_lifetime: Option<Duration>) -> Self {
+        Self {
+            activity: TunnelActivity {
+                opened_at: Instant::now(),
+                last_active: Arc::new(AtomicU64::new(0)),
+            },
+            idle_timeout,
+            max_lifetime,
+        }
+    }
+
+    /// Handle for the tunnel's directions to report traffic with
+    pub fn activity(&self) -> TunnelActivity {
+        self.activity.clone()
+    }
+
+    /// Wait until the tunnel should be closed; pending forever if neither
+    /// timeout is set
+    pub async fn expired(&self) -> TunnelExpiry {
+        let lifetime_end = self
+            .max_lifetime
+            .map(|lifetime| self.activity.opened_at + lifetime);
+
+        loop {
+            let idle_end = self
+                .idle_timeout
+                .map(|idle| self.activity.last_active() + idle);
+            let (deadline, expiry) = match (idle_end, lifetime_end) {
+                (Some(idle), Some(lifetime)) if lifetime <= idle => {
+                    (lifetime, TunnelExpiry::Lifetime)
+                }
+                (Some(idle), _) => (idle, TunnelExpiry::Idle),
+                (None, Some(lifetime)) => (lifetime, TunnelExpiry::Lifetime),
+                (None, None) => std::future::pending().await,
+            };
+
+            tokio::time::sleep_until(deadline).await;
+
+            // Traffic since the deadline was computed pushes it back
+            if expiry == TunnelExpiry::Lifetime
+                || self
+                    .idle_timeout
+                    .is_some_and(|idle| self.activity.last_active() + idle <= Instant::now())
+            {
+                return expiry;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_limits() {
+        let overrides = HashMap::from([("vip".to_string(), 0), ("small".to_string(), 1)]);
+        let limiter = Arc::new(ConnectionLimiter::new(Some(2), overrides));
+
+        let first = limiter.acquire("alice").unwrap();
+        let _second = limiter.acquire("alice").unwrap();
+        assert!(limiter.acquire("alice").is_none());
+        assert_eq!(limiter.active("alice"), 2);
+
+        drop(first);
+        assert!(limiter.acquire("alice").is_some());
+
+        let _small = limiter.acquire("small").unwrap();
+        assert!(limiter.acquire("small").is_none());
+        assert_eq!(limiter.limit("vip"), None);
+        let vip: Vec<_> = (0..5).filter_map(|_| limiter.acquire("vip")).collect();
+        assert_eq!(vip.len(), 5);
+
+        drop(vip);
+        assert_eq!(limiter.active("vip"), 0);
+        assert!(!limiter.active.contains_key("vip"));
+
+        // Lowering the limit keeps open connections counted
+        let _third = limiter.acquire("alice").unwrap();
+        limiter.set_limits(Some(1), HashMap::new());
+        assert_eq!(limiter.active("alice"), 2);
+        assert!(limiter.acquire("alice").is_none());
+        assert_eq!(limiter.limit("vip"), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_idle_and_lifetime() {
+        let watchdog = TunnelWatchdog::new(Some(Duration::from_millis(60)), None);
+        let activity = watchdog.activity();
+        let keep_alive = tokio::spawn(async move {
+            for _ in 0..4 {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                activity.touch();
+            }
+        });
+
+        let start = Instant::now();
+        assert_eq!(watchdog.expired().await, TunnelExpiry::Idle);
+        // Traffic kept it open past the first idle deadline
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        keep_alive.await.unwrap();
+
+        let watchdog = TunnelWatchdog::new(
+            Some(Duration::from_secs(60)),
+            Some(Duration::from_millis(20)),
+        );
+        assert_eq!(watchdog.expired().await, TunnelExpiry::Lifetime);
+    }
+}
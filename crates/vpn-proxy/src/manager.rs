@@ -1,69 +1,196 @@
 //! Proxy manager for handling authentication, rate limiting, and connection management
 
 use crate::{
+    access_log::{self, AccessEntry, AccessLog},
+    acl::{AclStore, Destination},
     auth::AuthManager,
+    block_page::BlockPages,
     config::ProxyConfig,
+    dns::Resolver,
     error::{ProxyError, Result},
+    geoip::GeoIpStore,
+    happy_eyeballs,
+    limits::{ConnectionLimiter, ConnectionPermit, TunnelWatchdog},
     metrics::ProxyMetrics,
-    pool::ConnectionPool,
+    pool::{ConnectionPool, UpstreamConnection},
     rate_limit::RateLimiter,
+    sessions::SessionTable,
+    shaping::{BandwidthShaper, Direction, Throttle},
 };
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
 
 /// Central manager for proxy operations
 #[derive(Clone)]
 pub struct ProxyManager {
-    config: Arc<ProxyConfig>,
-    auth_manager: Arc<AuthManager>,
-    rate_limiter: Arc<RateLimiter>,
+    runtime: Arc<std::sync::RwLock<Arc<Runtime>>>,
+    acl: Arc<AclStore>,
+    connection_limiter: Arc<ConnectionLimiter>,
     connection_pool: Arc<ConnectionPool>,
+    resolver: Arc<Resolver>,
+    geoip: Arc<GeoIpStore>,
+    sessions: Arc<SessionTable>,
+    origin_client: reqwest::Client,
+    access_log: Option<Arc<AccessLog>>,
     metrics: ProxyMetrics,
     shutdown_signal: Arc<RwLock<bool>>,
 }
 
+/// Configuration and the parts built from it, replaced as a whole on
+/// [`ProxyManager::reload`]. Connections take what they need when they
+/// are set up, so established tunnels keep the settings they started with.
+struct Runtime {
+    config: Arc<ProxyConfig>,
+    auth_manager: Arc<AuthManager>,
+    rate_limiter: Arc<RateLimiter>,
+    shaper: Arc<BandwidthShaper>,
+    block_pages: Arc<BlockPages>,
+}
+
 impl ProxyManager {
     /// Create a new proxy manager
     pub fn new(config: ProxyConfig, metrics: ProxyMetrics) -> Result<Self> {
-        let auth_manager = Arc::new(AuthManager::new(&config.auth)?);
-        let rate_limiter = Arc::new(RateLimiter::new(&config.rate_limit));
+        let acl = Arc::new(match &config.acl.path {
+            Some(path) => AclStore::open(path)?,
+            None => AclStore::allow_all(),
+        });
+        let connection_limiter = Arc::new(ConnectionLimiter::default());
+        set_connection_limits(&connection_limiter, &config);
         let connection_pool = Arc::new(ConnectionPool::new(&config.pool, metrics.clone()));
+        let resolver = Arc::new(Resolver::new(&config.dns)?);
+        let geoip = Arc::new(GeoIpStore::open(&config.geoip)?);
+        if acl.current().has_country_rules() && config.geoip.database.is_none() {
+            warn!("Access list has country rules but no GeoIP database is configured");
+        }
+        let sessions = Arc::new(SessionTable::open(&config.sessions)?);
+        let origin_client = crate::http::build_origin_client(&config, resolver.clone())?;
+        let access_log = AccessLog::open(&config.access_log)?;
+        let runtime = Runtime {
+            auth_manager: Arc::new(AuthManager::new(&config.auth)?),
+            rate_limiter: Arc::new(
+                RateLimiter::new(&config.rate_limit).with_sessions(sessions.clone()),
+            ),
+            shaper: Arc::new(BandwidthShaper::new(&config.bandwidth)),
+            block_pages: Arc::new(BlockPages::load(&config.block_pages)),
+            config: Arc::new(config),
+        };
 
         Ok(Self {
-            config: Arc::new(config),
-            auth_manager,
-            rate_limiter,
+            runtime: Arc::new(std::sync::RwLock::new(Arc::new(runtime))),
+            acl,
+            connection_limiter,
             connection_pool,
+            resolver,
+            geoip,
+            sessions,
+            origin_client,
+            access_log,
             metrics,
             shutdown_signal: Arc::new(RwLock::new(false)),
         })
     }
 
+    /// Re-read the configuration file at `path` and [`reload`](Self::reload) it
+    pub async fn reload_from_file(&self, path: &Path) -> Result<()> {
+        let config = ProxyConfig::load_from_file(path).await?;
+        self.reload(config)
+    }
+
+    /// Reload the configuration file at `path` whenever the process
+    /// receives SIGHUP. An invalid file is reported and the configuration
+    /// in effect is kept.
+    #[cfg(unix)]
+    pub fn reload_on_hangup(&self, path: PathBuf) -> Result<JoinHandle<()>> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        let manager = self.clone();
+        Ok(tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("Received SIGHUP, reloading {}", path.display());
+                if let Err(e) = manager.reload_from_file(&path).await {
+                    warn!("Keeping previous proxy configuration: {}", e);
+                }
+            }
+        }))
+    }
+
+    fn runtime(&self) -> Arc<Runtime> {
+        self.runtime
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Apply `config` to new connections without dropping established
+    /// ones. Authentication, access lists, rate, bandwidth and connection
+    /// limits, timeouts, block pages and name resolution are replaced,
+    /// keeping provisioned credentials.
+    /// Changed listen addresses, access list locations, access log,
+    /// GeoIP and session table settings need a restart and are only
+    /// reported; the connection pool keeps its settings.
+    pub fn reload(&self, config: ProxyConfig) -> Result<()> {
+        let current = self.runtime();
+        let auth_manager = current.auth_manager.reconfigured(&config.auth)?;
+
+        for setting in restart_required(&current.config, &config) {
+            warn!("Changed {} only takes effect after a restart", setting);
+        }
+        if let Err(e) = self.acl.reload() {
+            warn!("Keeping previous access list: {}", e);
+        }
+        set_connection_limits(&self.connection_limiter, &config);
+        self.resolver.reconfigure(&config.dns);
+
+        let runtime = Runtime {
+            auth_manager: Arc::new(auth_manager),
+            rate_limiter: Arc::new(
+                RateLimiter::new(&config.rate_limit).with_sessions(self.sessions.clone()),
+            ),
+            shaper: Arc::new(BandwidthShaper::new(&config.bandwidth)),
+            block_pages: Arc::new(BlockPages::load(&config.block_pages)),
+            config: Arc::new(config),
+        };
+        *self.runtime.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(runtime);
+
+        info!("Reloaded proxy configuration");
+        Ok(())
+    }
+
     /// Authenticate a connection
     pub async fn authenticate(
         &self,
         credentials: Option<(String, String)>,
         peer_addr: SocketAddr,
     ) -> Result<String> {
+        let runtime = self.runtime();
+        let config = &runtime.config;
+
         // Check IP whitelist first
-        if self.config.auth.ip_whitelist.contains(&peer_addr.ip()) {
+        if config.auth.ip_whitelist.contains(&peer_addr.ip()) {
             debug!("IP {} is whitelisted", peer_addr.ip());
             return Ok(format!("ip-{}", peer_addr.ip()));
         }
 
         // Check if authentication is required
-        if !self.config.auth.enabled {
+        if !config.auth.enabled {
             return Ok("anonymous".to_string());
         }
 
         // Authenticate with credentials
         if let Some((username, password)) = credentials {
-            let user_id = self.auth_manager.authenticate(&username, &password).await?;
+            let user_id = runtime
+                .auth_manager
+                .authenticate(&username, &password, peer_addr.ip())
+                .await?;
             self.metrics.record_auth_success();
             Ok(user_id)
-        } else if self.config.auth.allow_anonymous {
+        } else if config.auth.allow_anonymous {
             Ok("anonymous".to_string())
         } else {
             self.metrics.record_auth_failure();
@@ -73,11 +200,12 @@ impl ProxyManager {
 
     /// Check rate limit for a user
     pub async fn check_rate_limit(&self, user_id: &str) -> Result<()> {
-        if !self.config.rate_limit.enabled {
+        let runtime = self.runtime();
+        if !runtime.config.rate_limit.enabled {
             return Ok(());
         }
 
-        if self.rate_limiter.check_rate_limit(user_id).await? {
+        if runtime.rate_limiter.check_rate_limit(user_id).await? {
             Ok(())
         } else {
             self.metrics.record_rate_limit_exceeded();
@@ -85,12 +213,84 @@ impl ProxyManager {
         }
     }
 
+    /// Count a new connection of `user_id` against its concurrent
+    /// connection limit; it counts until the permit is dropped
+    pub fn acquire_connection(&self, user_id: &str) -> Result<ConnectionPermit> {
+        match self.connection_limiter.acquire(user_id) {
+            Some(permit) => Ok(permit),
+            None => {
+                warn!(
+                    "User {} reached the limit of {} concurrent connections",
+                    user_id,
+                    self.connection_limiter.limit(user_id).unwrap_or_default()
+                );
+                self.metrics.record_connection_rejected("concurrency");
+                Err(ProxyError::ConnectionLimitExceeded)
+            }
+        }
+    }
+
+    /// Watchdog closing a tunnel opened now once it idles or reaches its
+    /// maximum lifetime
+    pub fn tunnel_watchdog(&self) -> TunnelWatchdog {
+        let runtime = self.runtime();
+        let timeouts = &runtime.config.timeouts;
+        TunnelWatchdog::new(
+            Some(timeouts.idle).filter(|idle| !idle.is_zero()),
+            timeouts.tunnel_lifetime,
+        )
+    }
+
+    /// Check the access list for a connection of `user_id` to
+    /// `host:port`. Domains are resolved first when address rules apply.
+    pub async fn check_destination(&self, user_id: &str, host: &str, port: u16) -> Result<()> {
+        let acl = self.acl.current();
+        if acl.is_empty() {
+            return Ok(());
+        }
+
+        let mut resolved = Vec::new();
+        if host.parse::<IpAddr>().is_err() && acl.needs_addresses(user_id) {
+            resolved = self
+                .resolver
+                .resolve(user_id, host, port)
+                .await?
+                .iter()
+                .map(SocketAddr::ip)
+                .collect();
+        }
+
+        let mut destination = Destination::new(host, &resolved, port);
+        if acl.needs_countries(user_id) {
+            let countries = self.geoip.countries(&destination.addrs);
+            destination = destination.with_countries(countries);
+        }
+
+        let decision = acl.evaluate(user_id, &destination);
+        if decision.allowed() {
+            Ok(())
+        } else {
+            warn!(
+                "Access list denied {} to {}:{} ({:?})",
+                user_id, host, port, decision.scope
+            );
+            Err(ProxyError::auth_denied(format!(
+                "Access to {}:{} is not allowed",
+                host, port
+            )))
+        }
+    }
+
     /// Record bandwidth usage
     pub async fn record_bandwidth(&self, user_id: &str, bytes: u64) -> Result<()> {
-        if let Some(limit) = self.config.rate_limit.bandwidth_limit {
-            self.rate_limiter.record_bandwidth(user_id, bytes).await?;
+        let runtime = self.runtime();
+        if let Some(limit) = runtime.config.rate_limit.bandwidth_limit {
+            runtime
+                .rate_limiter
+                .record_bandwidth(user_id, bytes)
+                .await?;
 
-            let current_rate = self.rate_limiter.get_bandwidth_rate(user_id).await?;
+            let current_rate = runtime.rate_limiter.get_bandwidth_rate(user_id).await?;
             if current_rate > limit {
                 return Err(ProxyError::RateLimitExceeded);
             }
@@ -100,14 +300,100 @@ impl ProxyManager {
         Ok(())
     }
 
-    /// Get or create a connection to upstream
+    /// Bandwidth throttle for one direction of a connection of `user_id`
+    /// from `peer`
+    pub fn throttle(&self, user_id: &str, peer: IpAddr, direction: Direction) -> Throttle {
+        self.runtime().shaper.throttle(user_id, peer, direction)
+    }
+
+    /// Open a new connection to upstream
     pub async fn get_connection(&self, addr: SocketAddr) -> Result<tokio::net::TcpStream> {
-        self.connection_pool.get_or_create(addr).await
+        self.connection_pool.connect(addr).await
+    }
+
+    /// Connect to `host:port` for `user_id`, resolving the host the way
+    /// the user resolves names and racing IPv6 and IPv4 addresses when it
+    /// resolves to both. Returns the stream and the address it connected
+    /// to.
+    pub async fn connect_host(
+        &self,
+        user_id: &str,
+        host: &str,
+        port: u16,
+    ) -> Result<(tokio::net::TcpStream, SocketAddr)> {
+        if let Ok(ip) = host.parse::<std::net::IpAddr>() {
+            let addr = SocketAddr::new(ip, port);
+            let stream = self.get_connection(addr).await?;
+            self.record_destination(addr);
+            return Ok((stream, addr));
+        }
+
+        let addrs = self.resolver.resolve(user_id, host, port).await?;
+        let config = self.config();
+        let settings = &config.happy_eyeballs;
+        let connect_timeout = config.timeouts.connect;
+
+        if !settings.enabled || !happy_eyeballs::is_dual_stack(&addrs) {
+            // Plain sequential attempts in resolver order
+            let (stream, addr) =
+                happy_eyeballs::connect(&addrs, connect_timeout, connect_timeout).await?;
+            self.record_destination(addr);
+            return Ok((stream, addr));
+        }
+
+        let sorted = happy_eyeballs::sort_addresses(&addrs);
+        let (stream, addr) =
+            happy_eyeballs::connect(&sorted, settings.attempt_delay, connect_timeout).await?;
+        debug!(
+            "Happy eyeballs for {} won over {}",
+            host,
+            happy_eyeballs::family(&addr)
+        );
+        self.metrics
+            .record_happy_eyeballs_win(happy_eyeballs::family(&addr));
+        self.record_destination(addr);
+        Ok((stream, addr))
     }
 
-    /// Return a connection to the pool
-    pub async fn return_connection(&self, addr: SocketAddr, conn: tokio::net::TcpStream) {
-        self.connection_pool.return_connection(addr, conn).await;
+    fn record_destination(&self, addr: SocketAddr) {
+        if let Some(country) = self.geoip.country_label(addr.ip()) {
+            self.metrics.record_destination_connection(&country);
+        }
+    }
+
+    /// Take an idle keep-alive connection to `host:port` from the pool, or
+    /// connect to it for `user_id` when there is none
+    pub async fn checkout_upstream(
+        &self,
+        user_id: &str,
+        host: &str,
+        port: u16,
+    ) -> Result<UpstreamConnection> {
+        if let Some(conn) = self.connection_pool.checkout(host, port) {
+            return Ok(conn);
+        }
+        let (stream, addr) = self.connect_host(user_id, host, port).await?;
+        Ok(UpstreamConnection::new(stream, addr))
+    }
+
+    /// Return a connection to `host:port` to the pool once a response on
+    /// it was read in full and the origin keeps it open
+    pub fn return_connection(&self, host: &str, port: u16, conn: UpstreamConnection) {
+        self.connection_pool.checkin(host, port, conn);
+    }
+
+    /// Client for requests the proxy sends to `https://` origins itself,
+    /// keeping connections alive and using HTTP/2 where offered
+    pub fn origin_client(&self) -> &reqwest::Client {
+        &self.origin_client
+    }
+
+    /// Start the access log entry of a request from `source`; the current
+    /// task's previous request ends. Does nothing without an access log.
+    pub fn begin_request(&self, protocol: &'static str, source: SocketAddr) {
+        if let Some(log) = &self.access_log {
+            access_log::begin(AccessEntry::new(log.clone(), protocol, source));
+        }
     }
 
     /// Check if shutdown is requested
@@ -129,9 +415,39 @@ impl ProxyManager {
         Ok(())
     }
 
-    /// Get configuration
-    pub fn config(&self) -> &ProxyConfig {
-        &self.config
+    /// Get the authentication manager, e.g. to provision credentials
+    pub fn auth(&self) -> Arc<AuthManager> {
+        self.runtime().auth_manager.clone()
+    }
+
+    /// Resolver of destination names
+    pub fn resolver(&self) -> &Arc<Resolver> {
+        &self.resolver
+    }
+
+    /// GeoIP database of destination countries
+    pub fn geoip(&self) -> &Arc<GeoIpStore> {
+        &self.geoip
+    }
+
+    /// Session state shared with other replicas
+    pub fn sessions(&self) -> &Arc<SessionTable> {
+        &self.sessions
+    }
+
+    /// Get the access list store, e.g. to watch its file for changes
+    pub fn acl(&self) -> &Arc<AclStore> {
+        &self.acl
+    }
+
+    /// Get the configuration in effect
+    pub fn config(&self) -> Arc<ProxyConfig> {
+        self.runtime().config.clone()
+    }
+
+    /// Pages and SOCKS5 reply for refused requests
+    pub fn block_pages(&self) -> Arc<BlockPages> {
+        self.runtime().block_pages.clone()
     }
 
     /// Get metrics
@@ -139,3 +455,47 @@ impl ProxyManager {
         &self.metrics
     }
 }
+
+/// Apply the connection limits of `config`; unlimited when rate limiting
+/// is disabled
+fn set_connection_limits(limiter: &ConnectionLimiter, config: &ProxyConfig) {
+    let rate_limit = &config.rate_limit;
+    if rate_limit.enabled {
+        limiter.set_limits(
+            rate_limit.max_connections_per_user,
+            rate_limit.user_connection_limits.clone(),
+        );
+    } else {
+        limiter.set_limits(None, HashMap::new());
+    }
+}
+
+/// Settings that differ between `current` and `new` but cannot change
+/// while the proxy runs
+fn restart_required(current: &ProxyConfig, new: &ProxyConfig) -> Vec<&'static str> {
+    let mut settings = Vec::new();
+    if current.protocol != new.protocol {
+        settings.push("protocol");
+    }
+    if current.http_bind != new.http_bind
+        || current.socks5_bind != new.socks5_bind
+        || current.bind_host != new.bind_host
+        || current.http_port != new.http_port
+        || current.socks5_port != new.socks5_port
+    {
+        settings.push("listen address");
+    }
+    if current.acl.path != new.acl.path {
+        settings.push("access list path");
+    }
+    if current.access_log != new.access_log {
+        settings.push("access log");
+    }
+    if current.geoip != new.geoip {
+        settings.push("GeoIP database");
+    }
+    if current.sessions != new.sessions {
+        settings.push("session table");
+    }
+    settings
+}
@@ -1,11 +1,14 @@
 //! Prometheus metrics for proxy server
 
-use crate::error::Result;
+use crate::error::{ProxyError, Result};
+use crate::limits::TunnelExpiry;
+use crate::zero_copy::TransferPath;
 use prometheus::{
     register_counter, register_counter_vec, register_gauge_vec, register_histogram_vec, Counter,
     CounterVec, Encoder, GaugeVec, HistogramVec, Registry, TextEncoder,
 };
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::info;
 
 /// Proxy server metrics
@@ -29,11 +32,40 @@ pub struct ProxyMetrics {
     /// Rate limit hits
     pub rate_limit_hits_total: Counter,
 
+    /// Connections refused for exceeding a limit
+    pub connections_rejected_total: CounterVec,
+
+    /// Tunnels closed by their idle or lifetime timeout
+    pub tunnels_expired_total: CounterVec,
+
     /// Connection pool stats
     pub connection_pool_size: GaugeVec,
     pub connection_pool_hits: Counter,
     pub connection_pool_misses: Counter,
 
+    /// Address family that won dual-stack connection races
+    pub happy_eyeballs_wins_total: CounterVec,
+
+    /// Time from accepting a tunnel to its first upstream byte reaching
+    /// the client
+    pub tunnel_setup_duration_seconds: HistogramVec,
+
+    /// Time spent in each stage of tunnel setup
+    pub tunnel_stage_duration_seconds: HistogramVec,
+
+    /// Tunnel bytes moved per transfer path (splice or copy)
+    pub tunnel_transfer_bytes_total: CounterVec,
+
+    /// Rate tunnel directions moved their bytes at while in system calls,
+    /// per transfer path
+    pub tunnel_transfer_rate_bytes_per_second: HistogramVec,
+
+    /// Upstream connections per destination country
+    pub destination_connections_total: CounterVec,
+
+    /// Tunnel bytes per destination country
+    pub destination_bytes_total: CounterVec,
+
     /// Registry
     registry: Registry,
 }
@@ -79,6 +111,18 @@ impl ProxyMetrics {
             "Total number of rate limit hits"
         )?;
 
+        let connections_rejected_total = register_counter_vec!(
+            "proxy_connections_rejected_total",
+            "Connections refused for exceeding a limit",
+            &["reason"]
+        )?;
+
+        let tunnels_expired_total = register_counter_vec!(
+            "proxy_tunnels_expired_total",
+            "Tunnels closed by their idle or lifetime timeout",
+            &["reason"]
+        )?;
+
         let connection_pool_size = register_gauge_vec!(
             "proxy_connection_pool_size",
             "Size of connection pool",
@@ -95,6 +139,51 @@ impl ProxyMetrics {
             "Total connection pool misses"
         )?;
 
+        let happy_eyeballs_wins_total = register_counter_vec!(
+            "proxy_happy_eyeballs_wins_total",
+            "Dual-stack upstream connections won per address family",
+            &["family"]
+        )?;
+
+        let tunnel_setup_duration_seconds = register_histogram_vec!(
+            "proxy_tunnel_setup_duration_seconds",
+            "Time from accept to the first upstream byte reaching the client",
+            &["protocol", "outcome"],
+            vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+        )?;
+
+        let tunnel_stage_duration_seconds = register_histogram_vec!(
+            "proxy_tunnel_stage_duration_seconds",
+            "Time spent in each stage of tunnel setup",
+            &["protocol", "stage", "outcome"],
+            vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+        )?;
+
+        let tunnel_transfer_bytes_total = register_counter_vec!(
+            "proxy_tunnel_transfer_bytes_total",
+            "Tunnel bytes moved per transfer path",
+            &["path"]
+        )?;
+
+        let tunnel_transfer_rate_bytes_per_second = register_histogram_vec!(
+            "proxy_tunnel_transfer_rate_bytes_per_second",
+            "Rate tunnel directions moved their bytes at while in system calls",
+            &["path"],
+            vec![1e6, 1e7, 5e7, 1e8, 2.5e8, 5e8, 1e9, 2.5e9, 5e9, 1e10]
+        )?;
+
+        let destination_connections_total = register_counter_vec!(
+            "proxy_destination_connections_total",
+            "Upstream connections per destination country",
+            &["country"]
+        )?;
+
+        let destination_bytes_total = register_counter_vec!(
+            "proxy_destination_bytes_total",
+            "Tunnel bytes per destination country",
+            &["country", "direction"]
+        )?;
+
         // Register all metrics
         registry.register(Box::new(connections_total.clone()))?;
         registry.register(Box::new(connections_active.clone()))?;
@@ -102,9 +191,18 @@ impl ProxyMetrics {
         registry.register(Box::new(bytes_transferred_total.clone()))?;
         registry.register(Box::new(request_duration_seconds.clone()))?;
         registry.register(Box::new(rate_limit_hits_total.clone()))?;
+        registry.register(Box::new(connections_rejected_total.clone()))?;
+        registry.register(Box::new(tunnels_expired_total.clone()))?;
         registry.register(Box::new(connection_pool_size.clone()))?;
         registry.register(Box::new(connection_pool_hits.clone()))?;
         registry.register(Box::new(connection_pool_misses.clone()))?;
+        registry.register(Box::new(happy_eyeballs_wins_total.clone()))?;
+        registry.register(Box::new(tunnel_setup_duration_seconds.clone()))?;
+        registry.register(Box::new(tunnel_stage_duration_seconds.clone()))?;
+        registry.register(Box::new(tunnel_transfer_bytes_total.clone()))?;
+        registry.register(Box::new(tunnel_transfer_rate_bytes_per_second.clone()))?;
+        registry.register(Box::new(destination_connections_total.clone()))?;
+        registry.register(Box::new(destination_bytes_total.clone()))?;
 
         info!("Proxy metrics initialized");
 
@@ -115,9 +213,18 @@ impl ProxyMetrics {
             bytes_transferred_total,
             request_duration_seconds,
             rate_limit_hits_total,
+            connections_rejected_total,
+            tunnels_expired_total,
             connection_pool_size,
             connection_pool_hits,
             connection_pool_misses,
+            happy_eyeballs_wins_total,
+            tunnel_setup_duration_seconds,
+            tunnel_stage_duration_seconds,
+            tunnel_transfer_bytes_total,
+            tunnel_transfer_rate_bytes_per_second,
+            destination_connections_total,
+            destination_bytes_total,
             registry,
         })
     }
@@ -172,6 +279,20 @@ impl ProxyMetrics {
         self.rate_limit_hits_total.inc();
     }
 
+    /// Record a connection refused for exceeding a limit
+    pub fn record_connection_rejected(&self, reason: &str) {
+        self.connections_rejected_total
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    /// Record a tunnel closed by its idle or lifetime timeout
+    pub fn record_tunnel_expired(&self, expiry: TunnelExpiry) {
+        self.tunnels_expired_total
+            .with_label_values(&[expiry.as_str()])
+            .inc();
+    }
+
     /// Update connection pool stats
     pub fn update_connection_pool_stats(&self, total: usize, active: usize) {
         self.connection_pool_size
@@ -197,6 +318,55 @@ impl ProxyMetrics {
         self.connection_pool_misses.inc();
     }
 
+    /// Record the address family of a dual-stack connection race winner
+    pub fn record_happy_eyeballs_win(&self, family: &str) {
+        self.happy_eyeballs_wins_total
+            .with_label_values(&[family])
+            .inc();
+    }
+
+    /// Record the bytes a tunnel direction moved over `path` and the time
+    /// spent moving them. Rates of directions that moved less than 64 KiB
+    /// are left out, as their system call overhead dominates.
+    pub fn record_tunnel_transfer(&self, path: TransferPath, bytes: u64, busy: Duration) {
+        self.tunnel_transfer_bytes_total
+            .with_label_values(&[path.as_str()])
+            .inc_by(bytes as f64);
+
+        if bytes >= 64 * 1024 && !busy.is_zero() {
+            self.tunnel_transfer_rate_bytes_per_second
+                .with_label_values(&[path.as_str()])
+                .observe(bytes as f64 / busy.as_secs_f64());
+        }
+    }
+
+    /// Record an upstream connection to a destination in `country`
+    pub fn record_destination_connection(&self, country: &str) {
+        self.destination_connections_total
+            .with_label_values(&[country])
+            .inc();
+    }
+
+    /// Record tunnel bytes to or from a destination in `country`
+    pub fn record_destination_bytes(&self, country: &str, direction: &str, bytes: u64) {
+        self.destination_bytes_total
+            .with_label_values(&[country, direction])
+            .inc_by(bytes as f64);
+    }
+
+    /// Start timing the setup of a tunnel accepted now
+    pub fn tunnel_timer(&self, protocol: &'static str) -> TunnelSetupTimer {
+        let now = Instant::now();
+        TunnelSetupTimer {
+            setup: self.tunnel_setup_duration_seconds.clone(),
+            stages: self.tunnel_stage_duration_seconds.clone(),
+            protocol,
+            accepted_at: now,
+            stage_started_at: now,
+            finished: false,
+        }
+    }
+
     /// Export metrics in Prometheus format
     pub fn export(&self) -> Result<String> {
         let encoder = TextEncoder::new();
@@ -214,6 +384,146 @@ impl ProxyMetrics {
     }
 }
 
+/// Stages of tunnel setup, each timed from the end of the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelStage {
+    /// Protocol handshake, credentials and rate limiting
+    Auth,
+    /// Resolving and connecting to the target
+    UpstreamConnect,
+    /// Waiting for the target's first byte to reach the client
+    FirstByte,
+}
+
+impl TunnelStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::UpstreamConnect => "upstream_connect",
+            Self::FirstByte => "first_byte",
+        }
+    }
+}
+
+/// How a tunnel setup ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelOutcome {
+    Success,
+    AuthFailed,
+    RateLimited,
+    ConnectFailed,
+    Timeout,
+    /// Either side closed before the first byte
+    Closed,
+    Error,
+}
+
+impl TunnelOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::AuthFailed => "auth_failed",
+            Self::RateLimited => "rate_limited",
+            Self::ConnectFailed => "connect_failed",
+            Self::Timeout => "timeout",
+            Self::Closed => "closed",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl From<&ProxyError> for TunnelOutcome {
+    fn from(error: &ProxyError) -> Self {
+        match error {
+            ProxyError::AuthenticationFailed(_) | ProxyError::AuthorizationDenied(_) => {
+                Self::AuthFailed
+            }
+            ProxyError::RateLimitExceeded | ProxyError::ConnectionLimitExceeded => {
+                Self::RateLimited
+            }
+            ProxyError::UpstreamConnectionFailed(_) | ProxyError::ConnectionPoolExhausted => {
+                Self::ConnectFailed
+            }
+            ProxyError::Timeout => Self::Timeout,
+            ProxyError::Io(_) => Self::Closed,
+            _ => Self::Error,
+        }
+    }
+}
+
+/// Times one tunnel from accept, through [`TunnelStage`]s, to its first
+/// upstream byte. A timer dropped before finishing records the setup as
+/// [`TunnelOutcome::Closed`].
+pub struct TunnelSetupTimer {
+    setup: HistogramVec,
+    stages: HistogramVec,
+    protocol: &'static str,
+    accepted_at: Instant,
+    stage_started_at: Instant,
+    finished: bool,
+}
+
+impl TunnelSetupTimer {
+    /// Record `stage` as completed
+    pub fn stage(&mut self, stage: TunnelStage) {
+        self.observe_stage(stage, TunnelOutcome::Success);
+    }
+
+    /// Record `stage` and the whole setup as failed with `error`
+    pub fn fail(&mut self, stage: TunnelStage, error: &ProxyError) {
+        let outcome = TunnelOutcome::from(error);
+        self.observe_stage(stage, outcome);
+        self.finish(outcome);
+    }
+
+    /// Record `stage` as completed or failed depending on `result`
+    pub fn track<T>(&mut self, stage: TunnelStage, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.stage(stage),
+            Err(e) => self.fail(stage, e),
+        }
+        result
+    }
+
+    /// The first upstream byte reached the client; the tunnel is set up
+    pub fn first_byte(mut self) {
+        self.stage(TunnelStage::FirstByte);
+        self.finish(TunnelOutcome::Success);
+    }
+
+    /// Stop timing without recording, for connections that turn out not
+    /// to be tunnels
+    pub fn discard(mut self) {
+        self.finished = true;
+    }
+
+    fn observe_stage(&mut self, stage: TunnelStage, outcome: TunnelOutcome) {
+        if self.finished {
+            return;
+        }
+        self.stages
+            .with_label_values(&[self.protocol, stage.as_str(), outcome.as_str()])
+            .observe(self.stage_started_at.elapsed().as_secs_f64());
+        self.stage_started_at = Instant::now();
+    }
+
+    fn finish(&mut self, outcome: TunnelOutcome) {
+        if self.finished {
+            return;
+        }
+        self.setup
+            .with_label_values(&[self.protocol, outcome.as_str()])
+            .observe(self.accepted_at.elapsed().as_secs_f64());
+        self.finished = true;
+    }
+}
+
+impl Drop for TunnelSetupTimer {
+    fn drop(&mut self) {
+        self.finish(TunnelOutcome::Closed);
+    }
+}
+
 /// Start metrics HTTP server
 pub async fn start_metrics_server(
     metrics: ProxyMetrics,
@@ -267,13 +577,21 @@ async fn serve_metrics(metrics: Arc<ProxyMetrics>) -> impl axum::response::IntoR
     }
 }
 
+/// Metrics shared by unit tests. The collectors are registered globally,
+/// so they can only be created once per process.
+#[cfg(test)]
+pub(crate) fn test_metrics() -> ProxyMetrics {
+    static METRICS: std::sync::OnceLock<ProxyMetrics> = std::sync::OnceLock::new();
+    METRICS.get_or_init(|| ProxyMetrics::new().unwrap()).clone()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_metrics_creation() {
-        let metrics = ProxyMetrics::new().unwrap();
+        let metrics = test_metrics();
 
         // Test recording various metrics
         metrics.record_connection("http", true);
@@ -281,9 +599,44 @@ mod tests {
         metrics.record_bytes_transferred(1024, "upload");
         metrics.record_request_duration("http", "GET", 0.123);
 
+        // A tunnel that connects and relays
+        let mut timer = metrics.tunnel_timer("socks5");
+        timer.stage(TunnelStage::Auth);
+        timer.stage(TunnelStage::UpstreamConnect);
+        timer.first_byte();
+
+        // One refused by the target, and one closed before the first byte
+        let mut timer = metrics.tunnel_timer("http");
+        timer.stage(TunnelStage::Auth);
+        let _ = timer.track::<()>(
+            TunnelStage::UpstreamConnect,
+            Err(ProxyError::upstream("refused")),
+        );
+        drop(timer);
+        drop(metrics.tunnel_timer("http"));
+        metrics.tunnel_timer("http").discard();
+
+        let setup = |protocol: &str, outcome: &str| {
+            metrics
+                .tunnel_setup_duration_seconds
+                .with_label_values(&[protocol, outcome])
+                .get_sample_count()
+        };
+        assert_eq!(setup("socks5", "success"), 1);
+        assert_eq!(setup("http", "connect_failed"), 1);
+        assert_eq!(setup("http", "closed"), 1);
+        assert_eq!(
+            metrics
+                .tunnel_stage_duration_seconds
+                .with_label_values(&["socks5", "first_byte", "success"])
+                .get_sample_count(),
+            1
+        );
+
         // Export and check format
         let output = metrics.export().unwrap();
         assert!(output.contains("proxy_connections_total"));
         assert!(output.contains("proxy_auth_attempts_total"));
+        assert!(output.contains("proxy_tunnel_setup_duration_seconds"));
     }
 }
@@ -0,0 +1,255 @@
+//! Credential provisioning for a running proxy
+//!
+//! Implementations of [`CredentialProvisioner`] that make proxy users
+//! created through `vpn-users` work without restarting the proxy stack:
+//! [`AdminApiProvisioner`] registers the hashed credential with the auth
+//! service's admin API, [`FileProvisioner`] writes it to the credentials
+//! file of the `file` backend, which is read on every authentication.
+//! Only the argon2 hash leaves the process creating the user.
+
+use crate::auth::hash_password;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use vpn_users::{CredentialProvisioner, ProxyCredential, UserError};
+
+/// Body of `PUT /admin/credentials/{username}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProvisionRequest {
+    pub user_id: String,
+    /// Argon2 hash of the password
+    pub password_hash: String,
+}
+
+/// Registers credentials through the auth service's admin API
+pub struct AdminApiProvisioner {
+    base_url: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl AdminApiProvisioner {
+    /// Provisioner for the auth service at `base_url`, e.g.
+    /// `http://127.0.0.1:3001`, authenticating with the admin `token`
+    pub fn new(base_url: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: token.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Have the auth service re-read its configuration file. Established
+    /// connections are kept.
+    pub async fn reload_config(&self) -> Result<(), UserError> {
+        let request = self.client.post(format!("{}/admin/reload", self.base_url));
+        self.send("reload configuration", request).await
+    }
+
+    fn credential_url(&self, username: &str) -> String {
+        format!(
+            "{}/admin/credentials/{}",
+            self.base_url,
+            urlencoding::encode(username)
+        )
+    }
+
+    async fn send(
+        &self,
+        operation: &str,
+        request: reqwest::RequestBuilder,
+    ) -> Result<(), UserError> {
+        let response = request
+            .bearer_auth(&self.token)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .map_err(|e| operation_error(operation, e))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(operation_error(
+                operation,
+                format!("auth service answered {}", response.status()),
+            ))
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialProvisioner for AdminApiProvisioner {
+    async fn provision(&self, credential: &ProxyCredential) -> vpn_users::Result<()> {
+        let body = ProvisionRequest {
+            user_id: credential.user_id.clone(),
+            password_hash: hash_password(&credential.password)
+                .map_err(|e| operation_error("provision credential", e))?,
+        };
+        let request = self
+            .client
+            .put(self.credential_url(&credential.username))
+            .json(&body);
+        self.send("provision credential", request).await
+    }
+
+    async fn revoke(&self, username: &str) -> vpn_users::Result<()> {
+        let request = self.client.delete(self.credential_url(username));
+        self.send("revoke credential", request).await
+    }
+}
+
+/// Writes credentials to the credentials file of the `file` auth backend
+pub struct FileProvisioner {
+    path: PathBuf,
+}
+
+impl FileProvisioner {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the line of `username` with `line`, or drop it if `None`.
+    /// Source bindings of a replaced line are kept.
+    async fn rewrite(&self, username: &str, line: Option<String>) -> std::io::Result<()> {
+        let current = match tokio::fs::read_to_string(&self.path).await {
+            Ok(current) => current,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut sources = None;
+        let mut lines: Vec<String> = Vec::new();
+        for existing in current.lines() {
+            let mut parts = existing.trim().splitn(3, ':');
+            if parts.next() == Some(username) && !existing.trim_start().starts_with('#') {
+                sources = parts.nth(1).map(str::to_string);
+                continue;
+            }
+            lines.push(existing.to_string());
+        }
+        if let Some(line) = line {
+            lines.push(match sources {
+                Some(sources) => format!("{}:{}", line, sources),
+                None => line,
+            });
+        }
+
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        let temp = self.path.with_extension("tmp");
+        tokio::fs::write(&temp, contents).await?;
+        tokio::fs::rename(&temp, &self.path).await
+    }
+}
+
+#[async_trait]
+impl CredentialProvisioner for FileProvisioner {
+    async fn provision(&self, credential: &ProxyCredential) -> vpn_users::Result<()> {
+        let hash = hash_password(&credential.password)
+            .map_err(|e| operation_error("provision credential", e))?;
+        let line = format!("{}:{}", credential.username, hash);
+        self.rewrite(&credential.username, Some(line)).await?;
+        Ok(())
+    }
+
+    async fn revoke(&self, username: &str) -> vpn_users::Result<()> {
+        self.rewrite(username, None).await?;
+        Ok(())
+    }
+}
+
+fn operation_error(operation: &str, error: impl std::fmt::Display) -> UserError {
+    UserError::OperationError {
+        operation: operation.to_string(),
+        details: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthManager;
+    use crate::config::{AuthBackendConfig, AuthConfig};
+    use std::net::{IpAddr, Ipv4Addr};
+    use tempfile::TempDir;
+
+    const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+    fn credential(username: &str, password: &str) -> ProxyCredential {
+        ProxyCredential {
+            user_id: format!("{}-id", username),
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_provisioner_keeps_other_entries() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("users.txt");
+        tokio::fs::write(
+            &path,
+            "# proxy users\nbob:old-hash\nalice:old-hash:10.0.0.0/8\n",
+        )
+        .await
+        .unwrap();
+        let provisioner = FileProvisioner::new(&path);
+
+        provisioner
+            .provision(&credential("alice", "secret"))
+            .await
+            .unwrap();
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines[..2], ["# proxy users", "bob:old-hash"]);
+        assert!(lines[2].starts_with("alice:$argon2"));
+        assert!(lines[2].ends_with(":10.0.0.0/8"));
+
+        let config = AuthConfig {
+            backend: AuthBackendConfig::File { path: path.clone() },
+            ..Default::default()
+        };
+        let auth = AuthManager::new(&config).unwrap();
+        assert_eq!(
+            auth.authenticate("alice", "secret", "10.1.2.3".parse().unwrap())
+                .await
+                .unwrap(),
+            "alice"
+        );
+
+        provisioner.revoke("alice").await.unwrap();
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(contents, "# proxy users\nbob:old-hash\n");
+    }
+
+    #[tokio::test]
+    async fn test_provisioned_credentials_take_effect_immediately() {
+        let dir = TempDir::new().unwrap();
+        let config = AuthConfig {
+            backend: AuthBackendConfig::File {
+                path: dir.path().join("users.txt"),
+            },
+            ..Default::default()
+        };
+        let auth = AuthManager::new(&config).unwrap();
+
+        let hash = hash_password("secret").unwrap();
+        auth.provision("alice", "alice-id", &hash).unwrap();
+        assert_eq!(
+            auth.authenticate("alice", "secret", SOURCE).await.unwrap(),
+            "alice-id"
+        );
+        assert!(auth.authenticate("alice", "wrong", SOURCE).await.is_err());
+
+        // Revoking also ends the cached session
+        auth.revoke("alice");
+        assert!(auth.authenticate("alice", "secret", SOURCE).await.is_err());
+
+        assert!(auth.provision("bob", "bob-id", "not-a-hash").is_err());
+    }
+}
@@ -1,6 +1,15 @@
 //! Rate limiting implementation for proxy server
-
-use crate::{config::RateLimitConfig, error::Result};
+//!
+//! Request rates are token buckets. With a shared
+//! [`SessionTable`](crate::sessions::SessionTable) the buckets are kept in it,
+//! so a user's budget is the same on every replica; the replica's own
+//! buckets stand in while the table cannot be reached.
+
+use crate::{
+    config::RateLimitConfig,
+    error::Result,
+    sessions::{BucketLimit, SessionTable},
+};
 use dashmap::DashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -94,6 +103,7 @@ pub struct RateLimiter {
     user_buckets: Arc<DashMap<String, Arc<Mutex<TokenBucket>>>>,
     bandwidth_trackers: Arc<DashMap<String, Arc<Mutex<BandwidthTracker>>>>,
     global_bucket: Arc<Mutex<TokenBucket>>,
+    sessions: Option<Arc<SessionTable>>,
 }
 
 impl RateLimiter {
@@ -109,7 +119,28 @@ impl RateLimiter {
             user_buckets: Arc::new(DashMap::new()),
             bandwidth_trackers: Arc::new(DashMap::new()),
             global_bucket,
+            sessions: None,
+        }
+    }
+
+    /// Keep the request buckets in `sessions` when other replicas share it
+    pub fn with_sessions(mut self, sessions: Arc<SessionTable>) -> Self {
+        if sessions.is_shared() {
+            self.sessions = Some(sessions);
+        }
+        self
+    }
+
+    /// Take a token from the shared bucket `name`, or from `local` without
+    /// a shared table or while it fails
+    async fn take(&self, name: &str, limit: BucketLimit, local: &Mutex<TokenBucket>) -> bool {
+        if let Some(sessions) = &self.sessions {
+            match sessions.take_token(name, limit).await {
+                Ok(taken) => return taken,
+                Err(e) => warn!("Falling back to local rate limit for {}: {}", name, e),
+            }
         }
+        local.lock().await.try_consume(1)
     }
 
     /// Check if a request is allowed for a user
@@ -119,9 +150,9 @@ impl RateLimiter {
         }
 
         // Check global rate limit first
-        if self.config.global_limit.is_some() {
-            let mut global_bucket = self.global_bucket.lock().await;
-            if !global_bucket.try_consume(1) {
+        if let Some(limit) = self.config.global_limit {
+            let global = BucketLimit::new(limit * 2, limit as f64);
+            if !self.take("global", global, &self.global_bucket).await {
                 warn!("Global rate limit exceeded");
                 return Ok(false);
             }
@@ -139,8 +170,13 @@ impl RateLimiter {
             })
             .clone();
 
-        let mut bucket = bucket.lock().await;
-        let allowed = bucket.try_consume(1);
+        let limit = BucketLimit::new(
+            self.config.burst_size,
+            self.config.requests_per_second as f64,
+        );
+        let allowed = self
+            .take(&format!("user/{}", user_id), limit, &bucket)
+            .await;
 
         if !allowed {
             warn!("Rate limit exceeded for user: {}", user_id);
@@ -197,6 +233,8 @@ impl RateLimiter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::SessionConfig;
+    use crate::sessions::{MemorySessionStore, SessionStore};
 
     #[test]
     fn test_token_bucket() {
@@ -220,6 +258,8 @@ mod tests {
             burst_size: 20,
             bandwidth_limit: None,
             global_limit: None,
+            max_connections_per_user: None,
+            user_connection_limits: Default::default(),
         };
 
         let limiter = RateLimiter::new(&config);
@@ -232,4 +272,51 @@ mod tests {
         // Should deny after burst is exhausted
         assert!(!limiter.check_rate_limit("test_user").await.unwrap());
     }
+
+    #[test]
+    fn test_zero_limits_rejected() {
+        let mut config = RateLimitConfig {
+            requests_per_second: 0,
+            ..RateLimitConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        config.requests_per_second = 10;
+        config.global_limit = Some(0);
+        assert!(config.validate().is_err());
+
+        config.global_limit = None;
+        assert!(config.validate().is_ok());
+
+        // Unused while disabled
+        config.enabled = false;
+        config.requests_per_second = 0;
+        assert!(config.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_shared_between_replicas() {
+        let config = RateLimitConfig {
+            enabled: true,
+            requests_per_second: 1,
+            burst_size: 4,
+            bandwidth_limit: None,
+            global_limit: None,
+            max_connections_per_user: None,
+            user_connection_limits: Default::default(),
+        };
+        let store: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+        let replica = || {
+            let sessions = SessionTable::with_store(store.clone(), true, &SessionConfig::default());
+            RateLimiter::new(&config).with_sessions(Arc::new(sessions))
+        };
+        let (first, second) = (replica(), replica());
+
+        // The client moving between replicas spends one budget
+        for limiter in [&first, &second, &first, &second] {
+            assert!(limiter.check_rate_limit("test_user").await.unwrap());
+        }
+        assert!(!first.check_rate_limit("test_user").await.unwrap());
+        assert!(!second.check_rate_limit("test_user").await.unwrap());
+    }
 }
@@ -0,0 +1,711 @@
+//! Session state shared between proxy replicas
+//!
+//! Behind a load balancer a client's connections, and the datagrams of its
+//! SOCKS5 UDP associations, may reach different replicas of the proxy.
+//! [`SessionTable`] keeps what has to follow the client in a store every
+//! replica uses: the user each UDP association was authenticated as and the
+//! rate limit token buckets. The store is this process with
+//! [`SessionStoreConfig::Local`], the cluster's distributed storage (with
+//! the `cluster` feature), or a Redis server.
+//!
+//! An association is renewed by the replica holding its control connection
+//! and expires [`SessionConfig::association_ttl`] after the last renewal,
+//! so one left behind by a replica that went away does not outlive it for
+//! long.
+
+use crate::config::{SessionConfig, SessionStoreConfig};
+use crate::error::{ProxyError, Result};
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+#[cfg(feature = "cluster")]
+use vpn_cluster::config::StorageBackendConfig;
+#[cfg(feature = "cluster")]
+use vpn_cluster::distributed_storage::{
+    create_storage_backend, DistributedConfigStorage, TransactionOp,
+};
+#[cfg(feature = "cluster")]
+use vpn_cluster::ClusterError;
+
+/// How often expired entries are dropped from stores that keep them
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Attempts at updating a bucket in the cluster's storage before giving
+/// up to other replicas updating it at the same time
+#[cfg(feature = "cluster")]
+const CAS_ATTEMPTS: usize = 8;
+
+/// Token bucket of a rate limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketLimit {
+    /// Tokens a full bucket holds
+    pub capacity: u32,
+    /// Tokens added per second
+    pub refill_per_second: f64,
+}
+
+impl BucketLimit {
+    pub fn new(capacity: u32, refill_per_second: f64) -> Self {
+        Self {
+            capacity,
+            refill_per_second,
+        }
+    }
+
+    /// How long a bucket is kept unused: until it would be full again, at
+    /// most an hour, which is also how long one that never refills is kept
+    fn ttl(&self) -> Duration {
+        if self.refill_per_second.is_nan() || self.refill_per_second <= 0.0 {
+            return Duration::from_secs(3600);
+        }
+        let refill = self.capacity as f64 / self.refill_per_second;
+        Duration::from_secs_f64(refill.clamp(1.0, 3600.0))
+    }
+}
+
+/// A bucket as stored
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BucketState {
+    tokens: f64,
+    updated_ms: u64,
+}
+
+impl BucketState {
+    /// `state` refilled up to `now_ms`, less a token if it has one, and
+    /// whether it had
+    fn take(state: Option<Self>, limit: BucketLimit, now_ms: u64) -> (Self, bool) {
+        let capacity = limit.capacity as f64;
+        let tokens = match state {
+            Some(state) => {
+                let elapsed = now_ms.saturating_sub(state.updated_ms) as f64 / 1000.0;
+                (state.tokens + elapsed * limit.refill_per_second).min(capacity)
+            }
+            None => capacity,
+        };
+
+        let taken = tokens >= 1.0;
+        let state = Self {
+            tokens: if taken { tokens - 1.0 } else { tokens },
+            updated_ms: now_ms,
+        };
+        (state, taken)
+    }
+}
+
+/// A SOCKS5 UDP association, as any replica may relay its datagrams
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UdpAssociation {
+    /// Tells the association apart from later ones of the same client
+    pub id: String,
+    /// User the control connection was authenticated as
+    pub user_id: String,
+    /// Address the client sends from; port 0 when it did not say
+    pub client: SocketAddr,
+}
+
+/// Where a [`SessionTable`] keeps its entries. Entries expire on their
+/// own; a value is JSON.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// Value at `key`, unless it expired
+    async fn get(&self, key: &str) -> Result<Option<Value>>;
+
+    /// Set `key` to `value` for `ttl`
+    async fn put(&self, key: &str, value: Value, ttl: Duration) -> Result<()>;
+
+    /// Remove `key`
+    async fn remove(&self, key: &str) -> Result<()>;
+
+    /// Take a token from the bucket at `key`, atomically with respect to
+    /// other replicas; whether there was one
+    async fn take_token(&self, key: &str, limit: BucketLimit) -> Result<bool>;
+
+    /// Drop expired entries, for stores that do not on their own; returns
+    /// how many were dropped
+    async fn purge_expired(&self) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Store in this process
+#[derive(Default)]
+pub struct MemorySessionStore {
+    entries: Mutex<HashMap<String, (Value, Instant)>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, (Value, Instant)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        Ok(self
+            .entries()
+            .get(key)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(value, _)| value.clone()))
+    }
+
+    async fn put(&self, key: &str, value: Value, ttl: Duration) -> Result<()> {
+        self.entries()
+            .insert(key.to_string(), (value, Instant::now() + ttl));
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.entries().remove(key);
+        Ok(())
+    }
+
+    async fn take_token(&self, key: &str, limit: BucketLimit) -> Result<bool> {
+        let now = Instant::now();
+        let mut entries = self.entries();
+        let state = entries
+            .get(key)
+            .filter(|(_, expires)| *expires > now)
+            .and_then(|(value, _)| serde_json::from_value(value.clone()).ok());
+
+        let (state, taken) = BucketState::take(state, limit, now_ms());
+        entries.insert(
+            key.to_string(),
+            (
+                serde_json::to_value(state).map_err(encoding_error)?,
+                now + limit.ttl(),
+            ),
+        );
+        Ok(taken)
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        let now = Instant::now();
+        let mut entries = self.entries();
+        let before = entries.len();
+        entries.retain(|_, (_, expires)| *expires > now);
+        Ok(before - entries.len())
+    }
+}
+
+/// An entry in the cluster's storage, which has no expiry of its own
+#[cfg(feature = "cluster")]
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredEntry {
+    value: Value,
+    expires_ms: u64,
+}
+
+#[cfg(feature = "cluster")]
+impl StoredEntry {
+    fn new(value: Value, ttl: Duration) -> Self {
+        Self {
+            value,
+            expires_ms: now_ms().saturating_add(ttl.as_millis() as u64),
+        }
+    }
+
+    /// Value of the stored `raw` entry, unless it expired
+    fn live(raw: &Value) -> Option<Value> {
+        let entry: Self = serde_json::from_value(raw.clone()).ok()?;
+        (entry.expires_ms > now_ms()).then_some(entry.value)
+    }
+}
+
+/// Store in the cluster's distributed storage, connected on first use.
+/// Buckets are updated with conditional writes, retried when another
+/// replica wrote first.
+#[cfg(feature = "cluster")]
+pub struct ClusterSessionStore {
+    config: StorageBackendConfig,
+    prefix: String,
+    storage: OnceCell<Arc<dyn DistributedConfigStorage>>,
+}
+
+#[cfg(feature = "cluster")]
+impl ClusterSessionStore {
+    /// Store in the storage `config` describes; `prefix` is the prefix of
+    /// the table's keys, whose expired entries are dropped
+    pub fn new(config: StorageBackendConfig, prefix: &str) -> Self {
+        Self {
+            config,
+            prefix: prefix.to_string(),
+            storage: OnceCell::new(),
+        }
+    }
+
+    async fn storage(&self) -> Result<&Arc<dyn DistributedConfigStorage>> {
+        self.storage
+            .get_or_try_init(|| async {
+                create_storage_backend(&self.config)
+                    .await
+                    .map_err(cluster_error)
+            })
+            .await
+    }
+}
+
+#[cfg(feature = "cluster")]
+#[async_trait]
+impl SessionStore for ClusterSessionStore {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        let raw = self
+            .storage()
+            .await?
+            .get_config(key)
+            .await
+            .map_err(cluster_error)?;
+        Ok(raw.as_ref().and_then(StoredEntry::live))
+    }
+
+    async fn put(&self, key: &str, value: Value, ttl: Duration) -> Result<()> {
+        let entry = serde_json::to_value(StoredEntry::new(value, ttl)).map_err(encoding_error)?;
+        self.storage()
+            .await?
+            .store_config(key, entry)
+            .await
+            .map_err(cluster_error)
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.storage()
+            .await?
+            .remove_config(key)
+            .await
+            .map_err(cluster_error)?;
+        Ok(())
+    }
+
+    async fn take_token(&self, key: &str, limit: BucketLimit) -> Result<bool> {
+        let storage = self.storage().await?;
+        for _ in 0..CAS_ATTEMPTS {
+            let current = storage.get_config(key).await.map_err(cluster_error)?;
+            let state = current
+                .as_ref()
+                .and_then(StoredEntry::live)
+                .and_then(|value| serde_json::from_value(value).ok());
+
+            let (state, taken) = BucketState::take(state, limit, now_ms());
+            let entry = StoredEntry::new(
+                serde_json::to_value(state).map_err(encoding_error)?,
+                limit.ttl(),
+            );
+            let update = TransactionOp::ConditionalSet {
+                key: key.to_string(),
+                value: serde_json::to_value(entry).map_err(encoding_error)?,
+                expected: current,
+            };
+            match storage.transaction(vec![update]).await {
+                Ok(()) => return Ok(taken),
+                Err(ClusterError::InvalidState(_)) => continue,
+                Err(e) => return Err(cluster_error(e)),
+            }
+        }
+        Err(ProxyError::session_store(format!(
+            "Bucket {} kept changing while it was updated",
+            key
+        )))
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        let storage = self.storage().await?;
+        let keys = storage.list_keys().await.map_err(cluster_error)?;
+
+        let mut purged = 0;
+        for key in keys.iter().filter(|key| key.starts_with(&self.prefix)) {
+            let raw = storage.get_config(key).await.map_err(cluster_error)?;
+            if raw.as_ref().and_then(StoredEntry::live).is_none() {
+                storage.remove_config(key).await.map_err(cluster_error)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+}
+
+/// Refills and takes from a bucket hash on the server, by the server's
+/// clock. Reading the time before writing needs Redis 5 or later.
+const TOKEN_BUCKET_SCRIPT: &str = r"
+local capacity = tonumber(ARGV[1])
+local refill = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(state[1]) or capacity
+local updated = tonumber(state[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * refill / 1000)
+local taken = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    taken = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], ARGV[3])
+return taken
+";
+
+/// Store in Redis, connected on first use and reconnected when the
+/// connection drops
+pub struct RedisSessionStore {
+    client: redis::Client,
+    connection: OnceCell<ConnectionManager>,
+    token_bucket: redis::Script,
+}
+
+impl RedisSessionStore {
+    /// Store on the server at `url`, e.g. `redis://sessions.internal:6379/0`
+    pub fn open(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url)
+            .map_err(|e| ProxyError::config(format!("Invalid Redis URL {}: {}", url, e)))?;
+        Ok(Self {
+            client,
+            connection: OnceCell::new(),
+            token_bucket: redis::Script::new(TOKEN_BUCKET_SCRIPT),
+        })
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager> {
+        self.connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+            .map_err(redis_error)
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        let raw: Option<String> = self
+            .connection()
+            .await?
+            .get(key)
+            .await
+            .map_err(redis_error)?;
+        match raw {
+            Some(raw) => Ok(Some(serde_json::from_str(&raw).map_err(encoding_error)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, key: &str, value: Value, ttl: Duration) -> Result<()> {
+        self.connection()
+            .await?
+            .pset_ex(key, value.to_string(), ttl.as_millis() as u64)
+            .await
+            .map_err(redis_error)
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        self.connection().await?.del(key).await.map_err(redis_error)
+    }
+
+    async fn take_token(&self, key: &str, limit: BucketLimit) -> Result<bool> {
+        let taken: i64 = self
+            .token_bucket
+            .key(key)
+            .arg(limit.capacity)
+            .arg(limit.refill_per_second)
+            .arg(limit.ttl().as_millis() as u64)
+            .invoke_async(&mut self.connection().await?)
+            .await
+            .map_err(redis_error)?;
+        Ok(taken == 1)
+    }
+}
+
+/// Session state of this proxy, shared with its other replicas unless the
+/// store is local
+pub struct SessionTable {
+    store: Arc<dyn SessionStore>,
+    shared: bool,
+    prefix: String,
+    association_ttl: Duration,
+    udp_relay_port: Option<u16>,
+}
+
+impl SessionTable {
+    /// Table in the store `config` describes. Stores are connected on
+    /// first use, so an unreachable one does not keep the proxy from
+    /// starting.
+    pub fn open(config: &SessionConfig) -> Result<Self> {
+        let store: Arc<dyn SessionStore> = match &config.store {
+            SessionStoreConfig::Local => Arc::new(MemorySessionStore::new()),
+            #[cfg(feature = "cluster")]
+            SessionStoreConfig::Cluster { storage } => Arc::new(ClusterSessionStore::new(
+                storage.clone(),
+                &config.key_prefix,
+            )),
+            SessionStoreConfig::Redis { url } => Arc::new(RedisSessionStore::open(url)?),
+        };
+        let shared = config.store != SessionStoreConfig::Local;
+        Ok(Self::with_store(store, shared, config))
+    }
+
+    /// Table in `store`, which is `shared` when other replicas use it too
+    pub fn with_store(store: Arc<dyn SessionStore>, shared: bool, config: &SessionConfig) -> Self {
+        Self {
+            store,
+            shared,
+            prefix: config.key_prefix.clone(),
+            association_ttl: config.association_ttl,
+            udp_relay_port: config.udp_relay_port,
+        }
+    }
+
+    /// Whether other replicas see the table
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Port every replica relays UDP associations on, if any
+    pub fn udp_relay_port(&self) -> Option<u16> {
+        self.udp_relay_port
+    }
+
+    /// How long an association lasts without renewal
+    pub fn association_ttl(&self) -> Duration {
+        self.association_ttl
+    }
+
+    fn association_key(&self, client: SocketAddr) -> String {
+        match client.port() {
+            0 => format!("{}udp/{}", self.prefix, client.ip()),
+            port => format!("{}udp/{}/{}", self.prefix, client.ip(), port),
+        }
+    }
+
+    /// Record that datagrams from `client` belong to `user_id`; a client
+    /// port of 0 covers every port of the client's address. Replaces an
+    /// earlier association of the same client.
+    pub async fn register_association(
+        &self,
+        client: SocketAddr,
+        user_id: &str,
+    ) -> Result<UdpAssociation> {
+        let association = UdpAssociation {
+            id: format!("{:016x}", rand::random::<u64>()),
+            user_id: user_id.to_string(),
+            client,
+        };
+        self.renew_association(&association).await?;
+        Ok(association)
+    }
+
+    /// Keep `association` for another [`association_ttl`](Self::association_ttl)
+    pub async fn renew_association(&self, association: &UdpAssociation) -> Result<()> {
+        self.store
+            .put(
+                &self.association_key(association.client),
+                serde_json::to_value(association).map_err(encoding_error)?,
+                self.association_ttl,
+            )
+            .await
+    }
+
+    /// Association datagrams from `source` belong to: the one for its
+    /// address and port, else the one for its address
+    pub async fn association(&self, source: SocketAddr) -> Result<Option<UdpAssociation>> {
+        let any_port = SocketAddr::new(source.ip(), 0);
+        for client in [source, any_port] {
+            if let Some(value) = self.store.get(&self.association_key(client)).await? {
+                return Ok(Some(serde_json::from_value(value).map_err(encoding_error)?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Remove `association`, unless a later association of the client
+    /// replaced it
+    pub async fn remove_association(&self, association: &UdpAssociation) -> Result<()> {
+        let key = self.association_key(association.client);
+        let current = self.store.get(&key).await?;
+        let replaced = current
+            .and_then(|value| serde_json::from_value::<UdpAssociation>(value).ok())
+            .is_some_and(|current| current.id != association.id);
+        if replaced {
+            return Ok(());
+        }
+        self.store.remove(&key).await
+    }
+
+    /// Take a token from the rate limit bucket `name`; whether there was
+    /// one
+    pub async fn take_token(&self, name: &str, limit: BucketLimit) -> Result<bool> {
+        let key = format!("{}bucket/{}", self.prefix, name);
+        self.store.take_token(&key, limit).await
+    }
+
+    /// Drop expired entries periodically from stores that keep them
+    pub fn sweep(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match self.store.purge_expired().await {
+                    Ok(0) => {}
+                    Ok(purged) => debug!("Dropped {} expired session entries", purged),
+                    Err(e) => warn!("Failed to drop expired session entries: {}", e),
+                }
+            }
+        })
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "cluster")]
+fn cluster_error(error: ClusterError) -> ProxyError {
+    ProxyError::session_store(error.to_string())
+}
+
+fn redis_error(error: redis::RedisError) -> ProxyError {
+    ProxyError::session_store(error.to_string())
+}
+
+fn encoding_error(error: serde_json::Error) -> ProxyError {
+    ProxyError::session_store(format!("Invalid session entry: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_tables() -> (SessionTable, SessionTable) {
+        let store: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+        let config = SessionConfig::default();
+        (
+            SessionTable::with_store(store.clone(), true, &config),
+            SessionTable::with_store(store, true, &config),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_association_follows_client() {
+        let (first, second) = shared_tables();
+        let client: SocketAddr = "198.51.100.7:0".parse().unwrap();
+        let association = first.register_association(client, "alice").await.unwrap();
+
+        // Any port of the client's address, on either replica
+        let source: SocketAddr = "198.51.100.7:53124".parse().unwrap();
+        assert_eq!(
+            second.association(source).await.unwrap(),
+            Some(association.clone())
+        );
+        assert_eq!(
+            second
+                .association("198.51.100.8:53124".parse().unwrap())
+                .await
+                .unwrap(),
+            None
+        );
+
+        // An announced port takes precedence over the address-wide one
+        let bob = second.register_association(source, "bob").await.unwrap();
+        assert_eq!(second.association(source).await.unwrap(), Some(bob));
+        let other_port: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+        assert_eq!(
+            first
+                .association(other_port)
+                .await
+                .unwrap()
+                .unwrap()
+                .user_id,
+            "alice"
+        );
+
+        // A reconnect replaces the association; closing the old control
+        // connection leaves the new one
+        let renewed = second.register_association(client, "alice").await.unwrap();
+        first.remove_association(&association).await.unwrap();
+        assert_eq!(
+            first.association(other_port).await.unwrap(),
+            Some(renewed.clone())
+        );
+        second.remove_association(&renewed).await.unwrap();
+        assert_eq!(first.association(other_port).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_shared_token_bucket() {
+        let (first, second) = shared_tables();
+        let limit = BucketLimit::new(3, 0.001);
+
+        assert!(first.take_token("user/alice", limit).await.unwrap());
+        assert!(second.take_token("user/alice", limit).await.unwrap());
+        assert!(first.take_token("user/alice", limit).await.unwrap());
+        assert!(!second.take_token("user/alice", limit).await.unwrap());
+        assert!(second.take_token("user/bob", limit).await.unwrap());
+
+        let (state, taken) = BucketState::take(
+            Some(BucketState {
+                tokens: 0.0,
+                updated_ms: 1_000,
+            }),
+            BucketLimit::new(3, 2.0),
+            2_000,
+        );
+        assert!(taken);
+        assert_eq!(state.tokens, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_bucket_without_refill() {
+        assert_eq!(BucketLimit::new(0, 0.0).ttl(), Duration::from_secs(3600));
+        assert_eq!(BucketLimit::new(5, -1.0).ttl(), Duration::from_secs(3600));
+        assert_eq!(BucketLimit::new(10, 2.0).ttl(), Duration::from_secs(5));
+
+        let (first, _) = shared_tables();
+        let empty = BucketLimit::new(0, 0.0);
+        assert!(!first.take_token("user/alice", empty).await.unwrap());
+    }
+
+    #[cfg(feature = "cluster")]
+    #[tokio::test]
+    async fn test_cluster_store_expiry() {
+        let store = ClusterSessionStore::new(StorageBackendConfig::Memory, "vpn-proxy/");
+        let limit = BucketLimit::new(2, 0.001);
+        assert!(store
+            .take_token("vpn-proxy/bucket/alice", limit)
+            .await
+            .unwrap());
+        assert!(store
+            .take_token("vpn-proxy/bucket/alice", limit)
+            .await
+            .unwrap());
+        assert!(!store
+            .take_token("vpn-proxy/bucket/alice", limit)
+            .await
+            .unwrap());
+
+        store
+            .put(
+                "vpn-proxy/udp/198.51.100.7",
+                Value::Bool(true),
+                Duration::ZERO,
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.get("vpn-proxy/udp/198.51.100.7").await.unwrap(), None);
+        assert_eq!(store.purge_expired().await.unwrap(), 1);
+    }
+}
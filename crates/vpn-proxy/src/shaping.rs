@@ -0,0 +1,257 @@
+//! Token-bucket bandwidth shaping per connection, user, client address and
+//! for the whole proxy
+
+use crate::config::{BandwidthConfig, BandwidthLimits};
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shared buckets are dropped from their maps once no connection uses them
+/// and the maps grow past this size
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Smallest chunk a connection may send at once, so a fair share of a
+/// small burst does not degrade into byte-sized writes
+const MIN_CHUNK: usize = 1024;
+
+/// Direction of traffic through the proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Client to upstream
+    Upload,
+    /// Upstream to client
+    Download,
+}
+
+impl Direction {
+    fn limit(self, limits: &BandwidthLimits) -> Option<u64> {
+        match self {
+            Self::Upload => limits.upload_kbps,
+            Self::Download => limits.download_kbps,
+        }
+    }
+}
+
+/// Token bucket of bytes. Sending more than is available puts the bucket
+/// into debt, which later senders wait out in turn, so connections sharing
+/// a bucket are served in the order they asked.
+#[derive(Debug)]
+struct ByteBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl ByteBucket {
+    fn new(kbps: u64, burst_seconds: f64) -> Self {
+        let rate = (kbps * 1024) as f64;
+        let capacity = (rate * burst_seconds).max(MIN_CHUNK as f64);
+        Self {
+            rate,
+            capacity,
+            state: Mutex::new(BucketState {
+                tokens: capacity,
+                last_update: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `bytes` from the bucket, returning how long to wait before
+    /// sending them
+    fn reserve(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_update).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.capacity);
+        state.last_update = now;
+        state.tokens -= bytes as f64;
+
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        }
+    }
+}
+
+/// Limits applying to one direction of one connection
+#[derive(Debug, Default)]
+pub struct Throttle {
+    buckets: Vec<Arc<ByteBucket>>,
+}
+
+impl Throttle {
+    /// Whether no limit applies
+    pub fn is_unlimited(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Largest chunk to read before calling [`Throttle::consume`]: the
+    /// connection's share of the smallest burst among its buckets, so one
+    /// connection cannot take a shared burst for itself
+    pub fn chunk_size(&self, buffer_size: usize) -> usize {
+        self.buckets
+            .iter()
+            .map(|bucket| {
+                // One reference is held by the shaper's map for shared buckets
+                let sharers = Arc::strong_count(bucket).saturating_sub(1).max(1);
+                (bucket.capacity as usize / sharers).max(MIN_CHUNK)
+            })
+            .fold(buffer_size, usize::min)
+    }
+
+    /// Wait until `bytes` may be sent under every limit
+    pub async fn consume(&self, bytes: usize) {
+        let wait = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.reserve(bytes))
+            .max()
+            .unwrap_or_default();
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Hands out throttles backed by the configured limits
+#[derive(Debug)]
+pub struct BandwidthShaper {
+    config: BandwidthConfig,
+    users: DashMap<(String, Direction), Arc<ByteBucket>>,
+    ips: DashMap<(IpAddr, Direction), Arc<ByteBucket>>,
+    global_upload: Option<Arc<ByteBucket>>,
+    global_download: Option<Arc<ByteBucket>>,
+}
+
+impl BandwidthShaper {
+    /// Create a shaper enforcing `config`
+    pub fn new(config: &BandwidthConfig) -> Self {
+        let global = |direction: Direction| {
+            direction
+                .limit(&config.global)
+                .map(|kbps| Arc::new(ByteBucket::new(kbps, config.burst_seconds)))
+        };
+
+        Self {
+            config: config.clone(),
+            users: DashMap::new(),
+            ips: DashMap::new(),
+            global_upload: global(Direction::Upload),
+            global_download: global(Direction::Download),
+        }
+    }
+
+    /// Throttle for one direction of a new connection of `user_id` from
+    /// `peer`
+    pub fn throttle(&self, user_id: &str, peer: IpAddr, direction: Direction) -> Throttle {
+        let burst = self.config.burst_seconds;
+        let mut buckets = Vec::new();
+
+        if let Some(kbps) = direction.limit(&self.config.per_connection) {
+            buckets.push(Arc::new(ByteBucket::new(kbps, burst)));
+        }
+        if let Some(kbps) = direction.limit(&self.config.per_user) {
+            prune(&self.users);
+            let bucket = self
+                .users
+                .entry((user_id.to_string(), direction))
+                .or_insert_with(|| Arc::new(ByteBucket::new(kbps, burst)));
+            buckets.push(bucket.clone());
+        }
+        if let Some(kbps) = direction.limit(&self.config.per_ip) {
+            prune(&self.ips);
+            let bucket = self
+                .ips
+                .entry((peer, direction))
+                .or_insert_with(|| Arc::new(ByteBucket::new(kbps, burst)));
+            buckets.push(bucket.clone());
+        }
+        let global = match direction {
+            Direction::Upload => &self.global_upload,
+            Direction::Download => &self.global_download,
+        };
+        buckets.extend(global.clone());
+
+        Throttle { buckets }
+    }
+}
+
+fn prune<K: Eq + std::hash::Hash>(buckets: &DashMap<K, Arc<ByteBucket>>) {
+    if buckets.len() > PRUNE_THRESHOLD {
+        buckets.retain(|_, bucket| Arc::strong_count(bucket) > 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(kbps: u64) -> BandwidthLimits {
+        BandwidthLimits {
+            upload_kbps: Some(kbps),
+            download_kbps: None,
+        }
+    }
+
+    #[test]
+    fn test_bucket_debt() {
+        let bucket = ByteBucket::new(1, 1.0);
+        assert_eq!(bucket.reserve(1024), Duration::ZERO);
+
+        // Overdrawing by one second's worth makes the next sender wait it out
+        let wait = bucket.reserve(1024);
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+        assert!(bucket.reserve(1024) > Duration::from_millis(1900));
+    }
+
+    #[test]
+    fn test_throttle_limits_and_fair_share() {
+        let shaper = BandwidthShaper::new(&BandwidthConfig {
+            per_user: limits(64),
+            global: limits(1024),
+            ..Default::default()
+        });
+        let peer: IpAddr = "192.0.2.1".parse().unwrap();
+
+        assert!(shaper
+            .throttle("alice", peer, Direction::Download)
+            .is_unlimited());
+
+        let first = shaper.throttle("alice", peer, Direction::Upload);
+        assert_eq!(first.buckets.len(), 2);
+        assert_eq!(first.chunk_size(1 << 20), 64 * 1024);
+
+        // A second connection of the same user splits the user's burst
+        let second = shaper.throttle("alice", peer, Direction::Upload);
+        assert_eq!(first.chunk_size(1 << 20), 32 * 1024);
+        assert_eq!(second.chunk_size(8192), 8192);
+
+        drop(second);
+        assert_eq!(first.chunk_size(1 << 20), 64 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_consume_waits_for_tokens() {
+        let shaper = BandwidthShaper::new(&BandwidthConfig {
+            per_connection: limits(8),
+            ..Default::default()
+        });
+        let throttle = shaper.throttle("bob", "192.0.2.2".parse().unwrap(), Direction::Upload);
+
+        let start = Instant::now();
+        throttle.consume(8 * 1024).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        // An eighth of a second at 8 KB/s
+        throttle.consume(1024).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,92 @@
+//! Protocol detection for the shared HTTP and SOCKS5 listener
+//!
+//! A SOCKS5 client opens with its version byte, 0x05, while an HTTP client
+//! opens with a request method in uppercase ASCII. Peeking at the first
+//! byte of an accepted connection, without consuming it, is enough to hand
+//! the untouched socket to the right handler.
+
+use crate::error::{ProxyError, Result};
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// Protocol a client on the shared port speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SniffedProtocol {
+    Http,
+    Socks5,
+}
+
+impl SniffedProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Socks5 => "socks5",
+        }
+    }
+}
+
+/// Protocol opening with `first`, if it is one the proxy serves
+pub fn classify(first: u8) -> Option<SniffedProtocol> {
+    match first {
+        0x05 => Some(SniffedProtocol::Socks5),
+        b'A'..=b'Z' => Some(SniffedProtocol::Http),
+        _ => None,
+    }
+}
+
+/// Wait up to `timeout` for the client's first byte and tell its protocol
+pub async fn sniff(stream: &TcpStream, timeout: Duration) -> Result<SniffedProtocol> {
+    let mut first = [0u8; 1];
+    let read = tokio::time::timeout(timeout, stream.peek(&mut first))
+        .await
+        .map_err(|_| ProxyError::invalid_request("Client sent nothing"))??;
+
+    if read == 0 {
+        return Err(ProxyError::invalid_request(
+            "Client closed the connection before sending anything",
+        ));
+    }
+
+    classify(first[0]).ok_or_else(|| match first[0] {
+        0x04 => ProxyError::invalid_request("SOCKS4 is not supported"),
+        0x16 => ProxyError::invalid_request("TLS is not accepted on the proxy port"),
+        byte => ProxyError::invalid_request(format!("Unknown protocol (first byte {:#04x})", byte)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(0x05), Some(SniffedProtocol::Socks5));
+        assert_eq!(classify(b'C'), Some(SniffedProtocol::Http));
+        assert_eq!(classify(b'G'), Some(SniffedProtocol::Http));
+        assert_eq!(classify(0x04), None);
+        assert_eq!(classify(0x16), None);
+    }
+
+    #[tokio::test]
+    async fn test_sniff_leaves_bytes_for_the_handler() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+            stream
+        });
+        let (server, _) = listener.accept().await.unwrap();
+        let _client = client.await.unwrap();
+
+        let protocol = sniff(&server, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(protocol, SniffedProtocol::Socks5);
+
+        let mut buf = [0u8; 3];
+        server.peek(&mut buf).await.unwrap();
+        assert_eq!(buf[0], 0x05);
+    }
+}
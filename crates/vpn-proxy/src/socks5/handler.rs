@@ -1,15 +1,22 @@
 //! SOCKS5 server handler
 
+use super::protocol::{parse_udp_header, UdpTarget};
 use super::{AuthMethod, Command, Reply, Socks5Request};
 use crate::{
+    access_log::{self, Termination},
     error::{ProxyError, Result},
+    limits::{ConnectionPermit, TunnelActivity},
     manager::ProxyManager,
+    metrics::{TunnelSetupTimer, TunnelStage},
+    shaping::{Direction, Throttle},
+    zero_copy::Forwarder,
 };
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::time::Instant;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use vpn_types::trace_context::TraceContext;
 
 /// SOCKS5 server implementation
 #[derive(Clone)]
@@ -29,8 +36,22 @@ impl Socks5Server {
         let protocol = "socks5";
 
         self.manager.metrics().record_connection(protocol, true);
-
-        let result = self.handle_connection_inner(client, peer_addr).await;
+        let timer = self.manager.metrics().tunnel_timer(protocol);
+
+        let trace = TraceContext::new_root();
+        let span = info_span!(
+            "proxy_connection",
+            protocol,
+            peer = %peer_addr,
+            trace_id = %trace.trace_id(),
+            span_id = %trace.span_id(),
+        );
+        let result = trace
+            .scope(access_log::connection(
+                self.handle_connection_inner(client, peer_addr, timer),
+            ))
+            .instrument(span)
+            .await;
 
         // Record metrics
         let duration = start_time.elapsed().as_secs_f64();
@@ -46,37 +67,81 @@ impl Socks5Server {
         &self,
         mut client: TcpStream,
         peer_addr: SocketAddr,
+        mut timer: TunnelSetupTimer,
     ) -> Result<()> {
         debug!("New SOCKS5 connection from {}", peer_addr);
+        self.manager.begin_request("socks5", peer_addr);
+
+        let accepted = self.accept_request(&mut client, peer_addr).await;
+        // The permit is held until the connection closes
+        let (user_id, request, _permit) = timer.track(TunnelStage::Auth, accepted)?;
+
+        // Handle command; only CONNECT sets up a tunnel to time
+        match request.command {
+            Command::Connect => {
+                self.handle_connect(client, request, &user_id, peer_addr.ip(), timer)
+                    .await
+            }
+            Command::Bind => {
+                timer.discard();
+                self.handle_bind(client, request, &user_id, peer_addr.ip())
+                    .await
+            }
+            Command::UdpAssociate => {
+                timer.discard();
+                self.handle_udp_associate(client, request, &user_id, peer_addr)
+                    .await
+            }
+        }
+    }
 
+    /// Authenticate the client and read its request, if rate and
+    /// connection limits allow
+    async fn accept_request(
+        &self,
+        client: &mut TcpStream,
+        peer_addr: SocketAddr,
+    ) -> Result<(String, Socks5Request, ConnectionPermit)> {
         // Handle authentication
-        let user_id = self.handle_authentication(&mut client, peer_addr).await?;
+        let user_id = self.handle_authentication(client, peer_addr).await?;
+
+        access_log::set_user(&user_id);
 
         // Handle request
-        let request = super::protocol::read_request(&mut client).await?;
+        let request = super::protocol::read_request(client).await?;
+        access_log::set_method(match request.command {
+            Command::Connect => "CONNECT",
+            Command::Bind => "BIND",
+            Command::UdpAssociate => "UDP_ASSOCIATE",
+        });
+        access_log::set_destination(&format!("{}:{}", Self::target_host(&request), request.port));
 
         debug!(
             "SOCKS5 {:?} request from {} to {:?}:{}",
             request.command, user_id, request.address, request.port
         );
 
+        // Refusals get the configured reply code
+        let blocked = self.manager.block_pages().socks_reply();
+
         // Check rate limit
         if let Err(e) = self.manager.check_rate_limit(&user_id).await {
             warn!("Rate limit exceeded for user {}: {}", user_id, e);
-            super::protocol::send_reply(&mut client, Reply::ConnectionNotAllowed, peer_addr)
-                .await?;
+            access_log::terminate(Termination::RateLimited);
+            super::protocol::send_reply(client, blocked, peer_addr).await?;
             return Err(e);
         }
 
-        // Handle command
-        match request.command {
-            Command::Connect => self.handle_connect(client, request, &user_id).await,
-            Command::Bind => self.handle_bind(client, request, &user_id).await,
-            Command::UdpAssociate => {
-                self.handle_udp_associate(client, request, &user_id, peer_addr)
-                    .await
+        let permit = match self.manager.acquire_connection(&user_id) {
+            Ok(permit) => permit,
+            Err(e) => {
+                access_log::terminate(Termination::ConnectionLimit);
+                super::protocol::send_reply(client, blocked, peer_addr).await?;
+                return Err(e);
             }
-        }
+        };
+
+        Ok((user_id, request, permit))
     }
 
     /// Handle SOCKS5 authentication
@@ -125,6 +190,7 @@ impl Socks5Server {
                 }
                 Err(e) => {
                     // Send failure
+                    access_log::terminate(Termination::AuthFailed);
                     client.write_all(&[0x01, 0x01]).await?;
                     Err(e)
                 }
@@ -144,23 +210,49 @@ impl Socks5Server {
         mut client: TcpStream,
         request: Socks5Request,
         user_id: &str,
+        peer: IpAddr,
+        mut timer: TunnelSetupTimer,
     ) -> Result<()> {
-        // Resolve target address
-        let target_addr = self.resolve_address(&request).await?;
+        let target_host = Self::target_host(&request);
+
+        info!(
+            "SOCKS5 CONNECT from {} to {}:{}",
+            user_id, target_host, request.port
+        );
 
-        info!("SOCKS5 CONNECT from {} to {}", user_id, target_addr);
+        if let Err(e) = self
+            .manager
+            .check_destination(user_id, &target_host, request.port)
+            .await
+        {
+            timer.fail(TunnelStage::UpstreamConnect, &e);
+            access_log::terminate(Termination::Blocked);
+            let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+            let blocked = self.manager.block_pages().socks_reply();
+            super::protocol::send_reply(&mut client, blocked, unspecified).await?;
+            return Err(e);
+        }
 
-        // Connect to target
-        let upstream = match self.manager.get_connection(target_addr).await {
-            Ok(conn) => conn,
+        // Connect to target, racing address families for dual-stack hosts
+        let connected = self
+            .manager
+            .connect_host(user_id, &target_host, request.port)
+            .await;
+        let upstream = match timer.track(TunnelStage::UpstreamConnect, connected) {
+            Ok((conn, _)) => conn,
             Err(e) => {
-                error!("Failed to connect to {}: {}", target_addr, e);
+                error!(
+                    "Failed to connect to {}:{}: {}",
+                    target_host, request.port, e
+                );
+                access_log::terminate(Termination::ConnectFailed);
                 let reply = match e {
                     ProxyError::Timeout => Reply::TtlExpired,
                     ProxyError::UpstreamConnectionFailed(_) => Reply::ConnectionRefused,
                     _ => Reply::GeneralFailure,
                 };
-                super::protocol::send_reply(&mut client, reply, target_addr).await?;
+                let unspecified = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+                super::protocol::send_reply(&mut client, reply, unspecified).await?;
                 return Err(e);
             }
         };
@@ -172,83 +264,113 @@ impl Socks5Server {
         super::protocol::send_reply(&mut client, Reply::Success, local_addr).await?;
 
         // Start proxying data
-        self.proxy_data(client, upstream, user_id).await
+        self.proxy_data(client, upstream, user_id, peer, Some(timer))
+            .await
     }
 
-    /// Resolve address from SOCKS5 address type
-    async fn resolve_address(&self, request: &Socks5Request) -> Result<SocketAddr> {
+    /// Host to connect to for a SOCKS5 request; domains are resolved by
+    /// the manager so both address families can be tried
+    fn target_host(request: &Socks5Request) -> String {
         use super::AddressType;
 
         match &request.address {
-            AddressType::IPv4(bytes) => {
-                let ip = Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]);
-                Ok(SocketAddr::new(IpAddr::V4(ip), request.port))
-            }
-            AddressType::IPv6(bytes) => {
-                let ip = Ipv6Addr::from(*bytes);
-                Ok(SocketAddr::new(IpAddr::V6(ip), request.port))
-            }
-            AddressType::Domain(domain) => {
-                // DNS resolution
-                let addr = format!("{}:{}", domain, request.port);
-                let mut resolved = tokio::net::lookup_host(&addr).await?;
-                resolved
-                    .next()
-                    .ok_or_else(|| ProxyError::socks5(format!("Failed to resolve {}", domain)))
-            }
+            AddressType::IPv4(bytes) => Ipv4Addr::from(*bytes).to_string(),
+            AddressType::IPv6(bytes) => Ipv6Addr::from(*bytes).to_string(),
+            AddressType::Domain(domain) => domain.clone(),
         }
     }
 
-    /// Proxy data between client and upstream
+    /// Proxy data between client and upstream, shaped to the bandwidth
+    /// limits of `user_id` and `peer` and closed once it idles or reaches
+    /// its maximum lifetime
     async fn proxy_data(
         &self,
         client: TcpStream,
         upstream: TcpStream,
         user_id: &str,
+        peer: IpAddr,
+        timer: Option<TunnelSetupTimer>,
     ) -> Result<()> {
         let (client_reader, client_writer) = client.into_split();
+        let country = upstream
+            .peer_addr()
+            .ok()
+            .and_then(|addr| self.manager.geoip().country_label(addr.ip()));
         let (upstream_reader, upstream_writer) = upstream.into_split();
+        let watchdog = self.manager.tunnel_watchdog();
 
-        let client_to_upstream = tokio::spawn({
+        let mut client_to_upstream = tokio::spawn({
             let user_id = user_id.to_string();
             let manager = self.manager.clone();
-            async move {
+            let throttle = manager.throttle(&user_id, peer, Direction::Upload);
+            let activity = watchdog.activity();
+            let country = country.clone();
+            access_log::inherit(async move {
                 proxy_direction(
                     client_reader,
                     upstream_writer,
                     "client->upstream",
                     &user_id,
+                    country.as_deref(),
                     &manager,
+                    throttle,
+                    activity,
+                    None,
                 )
                 .await
-            }
+            })
         });
 
-        let upstream_to_client = tokio::spawn({
+        let mut upstream_to_client = tokio::spawn({
             let user_id = user_id.to_string();
             let manager = self.manager.clone();
-            async move {
+            let throttle = manager.throttle(&user_id, peer, Direction::Download);
+            let activity = watchdog.activity();
+            let country = country.clone();
+            access_log::inherit(async move {
                 proxy_direction(
                     upstream_reader,
                     client_writer,
                     "upstream->client",
                     &user_id,
+                    country.as_deref(),
                     &manager,
+                    throttle,
+                    activity,
+                    timer,
                 )
                 .await
-            }
+            })
         });
 
-        // Wait for either direction to complete
-        tokio::select! {
-            result = client_to_upstream => {
-                if let Err(e) = result {
-                    error!("Client to upstream task failed: {}", e);
+        // Wait for both directions, so half-closed proxys keep working, unless
+        // the watchdog closes the proxy first
+        let expired = watchdog.expired();
+        tokio::pin!(expired);
+        let (mut upload_done, mut download_done) = (false, false);
+        while !(upload_done && download_done) {
+            tokio::select! {
+                result = &mut client_to_upstream, if !upload_done => {
+                    upload_done = true;
+                    access_log::terminate(Termination::ClientClosed);
+                    if let Err(e) = result {
+                        error!("Client to upstream task failed: {}", e);
+                    }
                 }
-            }
-            result = upstream_to_client => {
-                if let Err(e) = result {
-                    error!("Upstream to client task failed: {}", e);
+                result = &mut upstream_to_client, if !download_done => {
+                    download_done = true;
+                    access_log::terminate(Termination::UpstreamClosed);
+                    if let Err(e) = result {
+                        error!("Upstream to client task failed: {}", e);
+                    }
+                }
+                expiry = &mut expired => {
+                    debug!("Closing proxy of user {} ({} timeout)", user_id, expiry.as_str());
+                    self.manager.metrics().record_tunnel_expired(expiry);
+                    access_log::terminate(expiry.into());
+                    client_to_upstream.abort();
+                    upstream_to_client.abort();
+                    break;
                 }
             }
         }
@@ -264,6 +386,7 @@ impl Socks5Server {
         mut client: TcpStream,
         request: Socks5Request,
         user_id: &str,
+        peer: IpAddr,
     ) -> Result<()> {
         // BIND is used for FTP-style protocols where the server initiates a connection back
         info!(
@@ -312,7 +435,7 @@ impl Socks5Server {
         super::protocol::send_reply(&mut client, Reply::Success, remote_addr).await?;
 
         // Start proxying data between client and inbound connection
-        self.proxy_data(client, inbound, user_id).await
+        self.proxy_data(client, inbound, user_id, peer, None).await
     }
 
     /// Handle UDP ASSOCIATE command
@@ -328,6 +451,12 @@ impl Socks5Server {
             user_id, request.address, request.port
         );
 
+        if let Some(port) = self.manager.sessions().udp_relay_port() {
+            return self
+                .handle_shared_udp_associate(client, request, user_id, peer_addr, port)
+                .await;
+        }
+
         // Create UDP socket for relay
         let udp_bind_addr = match peer_addr {
             SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
@@ -349,33 +478,122 @@ impl Socks5Server {
         let manager = self.manager.clone();
         let user_id_clone = user_id.to_string();
 
-        tokio::spawn(async move {
+        // The session is logged once the relay ends
+        tokio::spawn(access_log::inherit(async move {
             if let Err(e) = handle_udp_relay(udp_socket, client, &user_id_clone, manager).await {
                 error!("UDP relay error for user {}: {}", user_id_clone, e);
             }
-        });
+        }));
+
+        Ok(())
+    }
+
+    /// Register a UDP association in the session table for the relay on
+    /// `port`, which every replica runs, and keep it while the control
+    /// connection is open
+    async fn handle_shared_udp_associate(
+        &self,
+        mut client: TcpStream,
+        request: Socks5Request,
+        user_id: &str,
+        peer_addr: SocketAddr,
+        port: u16,
+    ) -> Result<()> {
+        let sessions = self.manager.sessions().clone();
+        // The client's address as seen here; the address it announces is
+        // often unset or its own view behind NAT, so only its port is used
+        let source = SocketAddr::new(peer_addr.ip(), request.port);
+        let association = sessions.register_association(source, user_id).await?;
+
+        // An unspecified address tells the client to send to the address
+        // it reached the proxy on, which is the load balancer's
+        let relay_addr = match peer_addr {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+        };
+        super::protocol::send_reply(&mut client, Reply::Success, relay_addr).await?;
+        info!(
+            "UDP association of user {} from {} registered for relay port {}",
+            user_id, source, port
+        );
+
+        tokio::spawn(access_log::inherit(async move {
+            let period = (sessions.association_ttl() / 3).max(Duration::from_secs(1));
+            let mut renewal = tokio::time::interval(period);
+            renewal.tick().await;
+            loop {
+                tokio::select! {
+                    _ = client.readable() => {
+                        let mut buf = [0u8; 1];
+                        match client.try_read(&mut buf) {
+                            Ok(0) => {
+                                access_log::terminate(Termination::ClientClosed);
+                                break;
+                            }
+                            Ok(_) => {}
+                            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                            Err(e) => {
+                                debug!("UDP association control connection failed: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                    _ = renewal.tick() => {
+                        if let Err(e) = sessions.renew_association(&association).await {
+                            warn!("Failed to renew UDP association of {}: {}", association.client, e);
+                        }
+                    }
+                }
+            }
+
+            if let Err(e) = sessions.remove_association(&association).await {
+                warn!(
+                    "Failed to remove UDP association of {}: {}",
+                    association.client, e
+                );
+            }
+            info!(
+                "UDP association of user {} from {} ended",
+                association.user_id, association.client
+            );
+        }));
 
         Ok(())
     }
 }
 
-/// Proxy data in one direction
+/// Proxy data in one direction, finishing `timer` once the first bytes
+/// have been written
+#[allow(clippy::too_many_arguments)]
 async fn proxy_direction<R, W>(
-    mut reader: R,
-    mut writer: W,
+    reader: R,
+    writer: W,
     direction: &str,
     user_id: &str,
+    country: Option<&str>,
     manager: &ProxyManager,
+    throttle: Throttle,
+    activity: TunnelActivity,
+    mut timer: Option<TunnelSetupTimer>,
 ) -> Result<()>
 where
-    R: AsyncReadExt + Unpin,
-    W: AsyncWriteExt + Unpin,
+    R: AsRef<TcpStream>,
+    W: AsRef<TcpStream>,
 {
-    let mut buffer = vec![0u8; 8192];
+    let mut forwarder = Forwarder::new();
     let mut total_bytes = 0u64;
+    let traffic = if direction == "client->upstream" {
+        Direction::Upload
+    } else {
+        Direction::Download
+    };
 
     loop {
-        let n = match reader.read(&mut buffer).await {
+        let chunk = throttle.chunk_size(forwarder.chunk_size());
+        let n = match forwarder
+            .forward(reader.as_ref(), writer.as_ref(), chunk)
+            .await
+        {
             Ok(0) => {
                 debug!(
                     "Connection closed ({}) after {} bytes",
@@ -385,22 +603,19 @@ where
             }
             Ok(n) => n,
             Err(e) => {
-                debug!("Read error ({}): {}", direction, e);
+                debug!("Transfer error ({}): {}", direction, e);
                 break;
             }
         };
 
-        if let Err(e) = writer.write_all(&buffer[..n]).await {
-            debug!("Write error ({}): {}", direction, e);
-            break;
-        }
-
-        if let Err(e) = writer.flush().await {
-            debug!("Flush error ({}): {}", direction, e);
-            break;
-        }
+        throttle.consume(n).await;
 
         total_bytes += n as u64;
+        activity.touch();
+        access_log::add_bytes(traffic, n as u64);
+        if let Some(timer) = timer.take() {
+            timer.first_byte();
+        }
 
         // Record bandwidth for client->upstream direction
         if direction == "client->upstream" {
@@ -421,6 +636,12 @@ where
     manager
         .metrics()
         .record_bytes_transferred(total_bytes, metric_direction);
+    if let Some(country) = country {
+        manager
+            .metrics()
+            .record_destination_bytes(country, metric_direction, total_bytes);
+    }
+    forwarder.record(manager.metrics());
 
     Ok(())
 }
@@ -446,6 +667,7 @@ async fn handle_udp_relay(
                 match tcp_client.try_read(&mut buf) {
                     Ok(0) => {
                         info!("TCP control connection closed for user {}", user_id);
+                        access_log::terminate(Termination::ClientClosed);
                         break;
                     }
                     Ok(_) => {
@@ -466,61 +688,20 @@ async fn handle_udp_relay(
             result = udp_socket.recv_from(&mut udp_buf) => {
                 match result {
                     Ok((len, from_addr)) => {
-                        // Parse SOCKS5 UDP header
-                        if len < 10 {
-                            warn!("UDP packet too small from {}", from_addr);
+                        let Some((target, header_len)) = parse_udp_header(&udp_buf[..len]) else {
+                            warn!("Dropping malformed or fragmented UDP packet from {}", from_addr);
                             continue;
-                        }
-
-                        // SOCKS5 UDP header format:
-                        // +----+------+------+----------+----------+----------+
-                        // |RSV | FRAG | ATYP | DST.ADDR | DST.PORT |   DATA   |
-                        // +----+------+------+----------+----------+----------+
-                        // | 2  |  1   |  1   | Variable |    2     | Variable |
-                        // +----+------+------+----------+----------+----------+
-
-                        let frag = udp_buf[2];
-                        if frag != 0 {
-                            warn!("Fragmented UDP packets not supported");
-                            continue;
-                        }
-
-                        // Parse destination address
-                        let atyp = udp_buf[3];
-                        let (dst_addr, header_len) = match atyp {
-                            0x01 => {
-                                // IPv4
-                                if len < 10 {
-                                    continue;
-                                }
-                                let ip = Ipv4Addr::new(udp_buf[4], udp_buf[5], udp_buf[6], udp_buf[7]);
-                                let port = u16::from_be_bytes([udp_buf[8], udp_buf[9]]);
-                                (SocketAddr::new(IpAddr::V4(ip), port), 10)
-                            }
-                            0x03 => {
-                                // Domain name
-                                let domain_len = udp_buf[4] as usize;
-                                if len < 7 + domain_len {
-                                    continue;
-                                }
-                                // For now, skip domain resolution in UDP relay
-                                warn!("Domain names in UDP relay not yet supported");
-                                continue;
-                            }
-                            0x04 => {
-                                // IPv6
-                                if len < 22 {
-                                    continue;
+                        };
+                        let dst_addr = match target {
+                            UdpTarget::Addr(addr) => addr,
+                            UdpTarget::Domain(domain, port) => {
+                                match manager.resolver().resolve(user_id, &domain, port).await {
+                                    Ok(addrs) => addrs[0],
+                                    Err(e) => {
+                                        warn!("Dropping UDP packet to {}: {}", domain, e);
+                                        continue;
+                                    }
                                 }
-                                let mut ipv6_bytes = [0u8; 16];
-                                ipv6_bytes.copy_from_slice(&udp_buf[4..20]);
-                                let ip = Ipv6Addr::from(ipv6_bytes);
-                                let port = u16::from_be_bytes([udp_buf[20], udp_buf[21]]);
-                                (SocketAddr::new(IpAddr::V6(ip), port), 22)
-                            }
-                            _ => {
-                                warn!("Invalid address type in UDP packet: {}", atyp);
-                                continue;
                             }
                         };
 
@@ -534,6 +715,7 @@ async fn handle_udp_relay(
                         }
 
                         // Record bandwidth
+                        access_log::add_bytes(Direction::Upload, data.len() as u64);
                         let _ = manager.record_bandwidth(user_id, data.len() as u64).await;
                     }
                     Err(e) => {
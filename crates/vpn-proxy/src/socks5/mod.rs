@@ -2,8 +2,10 @@
 
 mod handler;
 mod protocol;
+mod relay;
 
 pub use handler::Socks5Server;
+pub use relay::UdpRelay;
 
 /// SOCKS5 authentication methods
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -70,6 +72,23 @@ pub enum Reply {
     AddressTypeNotSupported = 0x08,
 }
 
+impl Reply {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x00 => Some(Self::Success),
+            0x01 => Some(Self::GeneralFailure),
+            0x02 => Some(Self::ConnectionNotAllowed),
+            0x03 => Some(Self::NetworkUnreachable),
+            0x04 => Some(Self::HostUnreachable),
+            0x05 => Some(Self::ConnectionRefused),
+            0x06 => Some(Self::TtlExpired),
+            0x07 => Some(Self::CommandNotSupported),
+            0x08 => Some(Self::AddressTypeNotSupported),
+            _ => None,
+        }
+    }
+}
+
 /// SOCKS5 request
 #[derive(Debug)]
 pub struct Socks5Request {
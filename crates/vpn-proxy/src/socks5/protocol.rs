@@ -2,7 +2,7 @@
 
 use super::{AddressType, AuthMethod, Command, Reply, Socks5Request};
 use crate::error::{ProxyError, Result};
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tracing::debug;
@@ -170,6 +170,70 @@ pub async fn send_reply(stream: &mut TcpStream, reply: Reply, addr: SocketAddr)
     Ok(())
 }
 
+/// Destination of a datagram of a UDP association
+#[derive(Debug, Clone, PartialEq)]
+pub enum UdpTarget {
+    Addr(SocketAddr),
+    Domain(String, u16),
+}
+
+/// Destination of a datagram of a UDP association and the length of its
+/// header; `None` when the header is malformed or the datagram is a
+/// fragment, which is not supported
+///
+/// ```text
+/// +----+------+------+----------+----------+----------+
+/// |RSV | FRAG | ATYP | DST.ADDR | DST.PORT |   DATA   |
+/// +----+------+------+----------+----------+----------+
+/// | 2  |  1   |  1   | Variable |    2     | Variable |
+/// +----+------+------+----------+----------+----------+
+/// ```
+pub fn parse_udp_header(packet: &[u8]) -> Option<(UdpTarget, usize)> {
+    if packet.len() < 4 || packet[2] != 0 {
+        return None;
+    }
+    let port = |at: usize| Some(u16::from_be_bytes([*packet.get(at)?, *packet.get(at + 1)?]));
+
+    match packet[3] {
+        0x01 => {
+            let octets: [u8; 4] = packet.get(4..8)?.try_into().ok()?;
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::from(octets)), port(8)?);
+            Some((UdpTarget::Addr(addr), 10))
+        }
+        0x03 => {
+            let len = *packet.get(4)? as usize;
+            let domain = std::str::from_utf8(packet.get(5..5 + len)?).ok()?;
+            Some((
+                UdpTarget::Domain(domain.to_string(), port(5 + len)?),
+                7 + len,
+            ))
+        }
+        0x04 => {
+            let octets: [u8; 16] = packet.get(4..20)?.try_into().ok()?;
+            let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port(20)?);
+            Some((UdpTarget::Addr(addr), 22))
+        }
+        _ => None,
+    }
+}
+
+/// Header of a datagram relayed to the client from `source`
+pub fn udp_header(source: SocketAddr) -> Vec<u8> {
+    let mut header = vec![0x00, 0x00, 0x00];
+    match source.ip() {
+        IpAddr::V4(ip) => {
+            header.push(0x01);
+            header.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            header.push(0x04);
+            header.extend_from_slice(&ip.octets());
+        }
+    }
+    header.extend_from_slice(&source.port().to_be_bytes());
+    header
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -189,4 +253,35 @@ mod tests {
         assert_eq!(Command::from_byte(0x03), Some(Command::UdpAssociate));
         assert_eq!(Command::from_byte(0x99), None);
     }
+
+    #[test]
+    fn test_udp_header() {
+        let source: SocketAddr = "203.0.113.9:53".parse().unwrap();
+        let mut packet = udp_header(source);
+        packet.extend_from_slice(b"payload");
+        assert_eq!(
+            parse_udp_header(&packet),
+            Some((UdpTarget::Addr(source), 10))
+        );
+
+        let v6: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        assert_eq!(
+            parse_udp_header(&udp_header(v6)),
+            Some((UdpTarget::Addr(v6), 22))
+        );
+
+        let mut domain = vec![0x00, 0x00, 0x00, 0x03, 11];
+        domain.extend_from_slice(b"example.com");
+        domain.extend_from_slice(&53u16.to_be_bytes());
+        assert_eq!(
+            parse_udp_header(&domain),
+            Some((UdpTarget::Domain("example.com".to_string(), 53), 18))
+        );
+
+        // Truncated, fragmented and unknown address types
+        assert_eq!(parse_udp_header(&domain[..10]), None);
+        packet[2] = 1;
+        assert_eq!(parse_udp_header(&packet), None);
+        assert_eq!(parse_udp_header(&[0x00, 0x00, 0x00, 0x02, 0x00]), None);
+    }
 }
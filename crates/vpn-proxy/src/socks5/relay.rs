@@ -0,0 +1,210 @@
+//! Relay of SOCKS5 UDP associations shared between replicas
+//!
+//! With a UDP relay port configured every replica listens on it, and UDP
+//! ASSOCIATE replies send clients there. A datagram is relayed for the user
+//! its association in the [`SessionTable`](crate::sessions::SessionTable)
+//! belongs to, whichever replica accepted the association, so the load
+//! balancer may hand it to any replica. The relaying replica sends it from
+//! a socket of the client's own and returns answers from the relay port;
+//! the socket closes once idle for the association TTL.
+
+use super::protocol::{parse_udp_header, udp_header, UdpTarget};
+use crate::error::Result;
+use crate::manager::ProxyManager;
+use crate::sessions::UdpAssociation;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+/// How long an association looked up in the session table is used before
+/// it is looked up again
+const ASSOCIATION_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Cached associations past which stale ones are dropped
+const ASSOCIATION_CACHE_SIZE: usize = 1024;
+
+/// Relay of the UDP associations of every replica
+pub struct UdpRelay {
+    socket: Arc<UdpSocket>,
+    manager: ProxyManager,
+    associations: Mutex<HashMap<SocketAddr, (UdpAssociation, Instant)>>,
+    /// Outbound socket per client and address family
+    outbound: Mutex<HashMap<(SocketAddr, bool), Arc<UdpSocket>>>,
+}
+
+impl UdpRelay {
+    /// Relay listening on `addr`
+    pub async fn bind(manager: ProxyManager, addr: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        info!(
+            "Relaying SOCKS5 UDP associations on {}",
+            socket.local_addr()?
+        );
+        Ok(Self {
+            socket: Arc::new(socket),
+            manager,
+            associations: Mutex::new(HashMap::new()),
+            outbound: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Relay datagrams until the socket fails
+    pub async fn run(self) -> Result<()> {
+        let relay = Arc::new(self);
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let (len, source) = relay.socket.recv_from(&mut buf).await?;
+            relay.relay(&buf[..len], source).await;
+        }
+    }
+
+    async fn relay(self: &Arc<Self>, packet: &[u8], source: SocketAddr) {
+        let Some((target, header_len)) = parse_udp_header(packet) else {
+            debug!(
+                "Dropping malformed or fragmented UDP packet from {}",
+                source
+            );
+            return;
+        };
+        let association = match self.association(source).await {
+            Ok(Some(association)) => association,
+            Ok(None) => {
+                debug!("Dropping UDP packet from {} without association", source);
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to look up UDP association of {}: {}", source, e);
+                return;
+            }
+        };
+
+        let user_id = association.user_id.as_str();
+        let destination = match target {
+            UdpTarget::Addr(addr) => addr,
+            UdpTarget::Domain(domain, port) => {
+                match self
+                    .manager
+                    .resolver()
+                    .resolve(user_id, &domain, port)
+                    .await
+                {
+                    Ok(addrs) => addrs[0],
+                    Err(e) => {
+                        warn!("Dropping UDP packet to {}: {}", domain, e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        let outbound = match self.outbound(source, destination.is_ipv4()).await {
+            Ok(outbound) => outbound,
+            Err(e) => {
+                warn!("Failed to open UDP socket for {}: {}", source, e);
+                return;
+            }
+        };
+        let data = &packet[header_len..];
+        if let Err(e) = outbound.send_to(data, destination).await {
+            warn!("Failed to forward UDP packet to {}: {}", destination, e);
+            return;
+        }
+        let _ = self
+            .manager
+            .record_bandwidth(user_id, data.len() as u64)
+            .await;
+    }
+
+    /// Association of `source`, from the cache while it is fresh
+    async fn association(&self, source: SocketAddr) -> Result<Option<UdpAssociation>> {
+        {
+            let cache = self.associations.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((association, cached_at)) = cache.get(&source) {
+                if cached_at.elapsed() < ASSOCIATION_CACHE_TTL {
+                    return Ok(Some(association.clone()));
+                }
+            }
+        }
+
+        let association = self.manager.sessions().association(source).await?;
+        let mut cache = self.associations.lock().unwrap_or_else(|e| e.into_inner());
+        match &association {
+            Some(association) => {
+                if cache.len() >= ASSOCIATION_CACHE_SIZE {
+                    cache.retain(|_, (_, cached_at)| cached_at.elapsed() < ASSOCIATION_CACHE_TTL);
+                }
+                cache.insert(source, (association.clone(), Instant::now()));
+            }
+            None => {
+                cache.remove(&source);
+            }
+        }
+        Ok(association)
+    }
+
+    /// Socket sending datagrams of `client` to destinations of one address
+    /// family, opened with a task returning the answers
+    async fn outbound(self: &Arc<Self>, client: SocketAddr, ipv4: bool) -> Result<Arc<UdpSocket>> {
+        let key = (client, ipv4);
+        if let Some(socket) = self
+            .outbound
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+        {
+            return Ok(socket.clone());
+        }
+
+        let bind_addr = if ipv4 {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+        } else {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0)
+        };
+        let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+        self.outbound
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, socket.clone());
+
+        let relay = self.clone();
+        let answers = socket.clone();
+        tokio::spawn(async move {
+            let idle = relay
+                .manager
+                .sessions()
+                .association_ttl()
+                .max(Duration::from_secs(1));
+            let mut buf = vec![0u8; 65535];
+            loop {
+                match tokio::time::timeout(idle, answers.recv_from(&mut buf)).await {
+                    Ok(Ok((len, from))) => {
+                        let mut packet = udp_header(from);
+                        packet.extend_from_slice(&buf[..len]);
+                        if let Err(e) = relay.socket.send_to(&packet, client).await {
+                            debug!("Failed to return UDP packet to {}: {}", client, e);
+                        }
+                        relay
+                            .manager
+                            .metrics()
+                            .record_bytes_transferred(len as u64, "download");
+                    }
+                    Ok(Err(e)) => {
+                        debug!("UDP socket of {} failed: {}", client, e);
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+            relay
+                .outbound
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&key);
+        });
+
+        Ok(socket)
+    }
+}
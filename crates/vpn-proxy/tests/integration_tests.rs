@@ -121,3 +121,133 @@ fn test_proxy_error_types() {
     let err_result: Result<i32> = Err(ProxyError::invalid_request("test"));
     assert!(err_result.is_err());
 }
+
+#[tokio::test]
+async fn test_credential_source_binding() {
+    use vpn_proxy::auth::{hash_password, AuthManager};
+    use vpn_proxy::config::{AuthBackendConfig, AuthConfig};
+
+    let dir = tempfile::tempdir().unwrap();
+    let users = dir.path().join("users");
+    let hash = hash_password("secret").unwrap();
+    std::fs::write(
+        &users,
+        format!(
+            "alice:{hash}:10.0.0.0/8, 192.168.1.5\nbob:{hash}\ncarol:{hash}\n",
+            hash = hash
+        ),
+    )
+    .unwrap();
+
+    let mut config = AuthConfig {
+        backend: AuthBackendConfig::File { path: users },
+        ..Default::default()
+    };
+    config
+        .source_bindings
+        .insert("bob".to_string(), vec!["2001:db8::/32".parse().unwrap()]);
+    let auth = AuthManager::new(&config).unwrap();
+
+    // Bound in the users file
+    assert!(auth
+        .authenticate("alice", "secret", "10.1.2.3".parse().unwrap())
+        .await
+        .is_ok());
+    assert!(auth
+        .authenticate("alice", "secret", "::ffff:192.168.1.5".parse().unwrap())
+        .await
+        .is_ok());
+    // A cached credential is still checked against the source
+    assert!(auth
+        .authenticate("alice", "secret", "203.0.113.7".parse().unwrap())
+        .await
+        .is_err());
+
+    // Bound in the config
+    assert!(auth
+        .authenticate("bob", "secret", "2001:db8::1".parse().unwrap())
+        .await
+        .is_ok());
+    assert!(auth
+        .authenticate("bob", "secret", "10.1.2.3".parse().unwrap())
+        .await
+        .is_err());
+
+    // Unbound credentials work from anywhere
+    assert!(auth
+        .authenticate("carol", "secret", "203.0.113.7".parse().unwrap())
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_failed_attempts_lock_out() {
+    use vpn_proxy::auth::{hash_password, AuthManager};
+    use vpn_proxy::config::{AuthBackendConfig, AuthConfig};
+
+    let dir = tempfile::tempdir().unwrap();
+    let users = dir.path().join("users");
+    let hash = hash_password("secret").unwrap();
+    std::fs::write(&users, format!("alice:{hash}\nbob:{hash}\n", hash = hash)).unwrap();
+
+    let mut config = AuthConfig {
+        backend: AuthBackendConfig::File { path: users },
+        ..Default::default()
+    };
+    config.lockout.max_attempts_per_account = 3;
+    let auth = AuthManager::new(&config).unwrap();
+    let source = "203.0.113.7".parse().unwrap();
+
+    for _ in 0..3 {
+        assert!(auth.authenticate("alice", "wrong", source).await.is_err());
+    }
+
+    // The right password is refused during the lockout
+    let err = auth
+        .authenticate("alice", "secret", source)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Too many failed attempts"));
+
+    // Other accounts are unaffected
+    assert!(auth.authenticate("bob", "secret", source).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_reconfigured_auth_keeps_provisioned_credentials() {
+    use vpn_proxy::auth::{hash_password, AuthManager};
+    use vpn_proxy::config::{AuthBackendConfig, AuthConfig};
+
+    let dir = tempfile::tempdir().unwrap();
+    let hash = hash_password("secret").unwrap();
+    let old_users = dir.path().join("old-users");
+    let new_users = dir.path().join("new-users");
+    std::fs::write(&old_users, format!("alice:{hash}\n", hash = hash)).unwrap();
+    std::fs::write(&new_users, format!("bob:{hash}\n", hash = hash)).unwrap();
+
+    let mut config = AuthConfig {
+        backend: AuthBackendConfig::File { path: old_users },
+        ..Default::default()
+    };
+    config.lockout.max_attempts_per_account = 2;
+    let auth = AuthManager::new(&config).unwrap();
+    auth.provision("carol", "carol-id", &hash).unwrap();
+    let source = "203.0.113.7".parse().unwrap();
+    for _ in 0..2 {
+        assert!(auth.authenticate("dave", "wrong", source).await.is_err());
+    }
+
+    config.backend = AuthBackendConfig::File { path: new_users };
+    let auth = auth.reconfigured(&config).unwrap();
+
+    // The new backend replaces the old one
+    assert!(auth.authenticate("alice", "secret", source).await.is_err());
+    assert!(auth.authenticate("bob", "secret", source).await.is_ok());
+    // Provisioned credentials and lockouts carry over
+    assert_eq!(
+        auth.authenticate("carol", "secret", source).await.unwrap(),
+        "carol-id"
+    );
+    let err = auth.authenticate("dave", "wrong", source).await.unwrap_err();
+    assert!(err.to_string().contains("Too many failed attempts"));
+}
@@ -0,0 +1,331 @@
+//! Canary connection test after config changes
+//!
+//! Key rotations, transport changes and upgrades can leave the server
+//! unreachable for every user at once. [`CanaryGuard`] backs up the
+//! configuration, applies the change and then connects through the server
+//! as a dedicated canary user with the [`ClientVerifier`]. If the canary
+//! cannot connect, the backup is restored or only an alert is sent,
+//! depending on [`CanaryOptions::on_failure`].
+//!
+//! The canary user is created on first use and kept afterwards, so it is
+//! part of the backup and goes through every change like a real user.
+
+use crate::client_check::{ClientCheckOptions, ClientCheckReport, ClientVerifier};
+use crate::error::Result;
+use crate::lifecycle::ServerLifecycle;
+use crate::xray_api::{UserSyncPlan, XrayUserSync};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use vpn_types::protocol::VpnProtocol;
+use vpn_users::config::{ConfigGenerator, ServerConfig, XrayConfig};
+use vpn_users::{User, UserManager};
+
+/// Name of the user the canary connects as
+pub const CANARY_USER: &str = "vpn-canary";
+
+/// Kind of change a canary test follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigChange {
+    KeyRotation,
+    TransportChange,
+    Upgrade,
+}
+
+impl ConfigChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigChange::KeyRotation => "key rotation",
+            ConfigChange::TransportChange => "transport change",
+            ConfigChange::Upgrade => "upgrade",
+        }
+    }
+}
+
+/// What to do when the canary cannot connect after a change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CanaryFailureAction {
+    /// Restore the configuration from before the change, then alert
+    Rollback,
+    /// Keep the change and alert
+    Alert,
+}
+
+#[derive(Debug, Clone)]
+pub struct CanaryOptions {
+    pub check: ClientCheckOptions,
+    /// Protocol of the canary user when it has to be created
+    pub protocol: VpnProtocol,
+    pub on_failure: CanaryFailureAction,
+    /// URL receiving the report as JSON when the canary fails
+    pub alert_webhook: Option<String>,
+}
+
+impl Default for CanaryOptions {
+    fn default() -> Self {
+        Self {
+            check: ClientCheckOptions::default(),
+            protocol: VpnProtocol::Vless,
+            on_failure: CanaryFailureAction::Rollback,
+            alert_webhook: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CanaryReport {
+    pub change: ConfigChange,
+    pub checked_at: DateTime<Utc>,
+    pub passed: bool,
+    /// Connection test result, unless the canary could not be run at all
+    pub check: Option<ClientCheckReport>,
+    /// Why the canary failed
+    pub error: Option<String>,
+    /// Backup taken before the change
+    pub backup_path: PathBuf,
+    pub rolled_back: bool,
+}
+
+/// Runs config changes with a canary connection test afterwards
+pub struct CanaryGuard {
+    install_path: PathBuf,
+    options: CanaryOptions,
+    lifecycle: ServerLifecycle,
+}
+
+impl CanaryGuard {
+    pub fn new(install_path: impl Into<PathBuf>, options: CanaryOptions) -> Result<Self> {
+        Ok(Self {
+            install_path: install_path.into(),
+            options,
+            lifecycle: ServerLifecycle::new()?,
+        })
+    }
+
+    /// Back up the configuration, run `apply` and test the result as the
+    /// canary user. `server_config` describes the server before the change;
+    /// keys rewritten by the change are read back from the install path.
+    ///
+    /// A failing canary is reported in the returned [`CanaryReport`], not
+    /// as an error. If `apply` itself fails, the backup is left in place
+    /// and the error is returned without a canary test.
+    pub async fn guard<T, F>(
+        &self,
+        change: ConfigChange,
+        server_config: &ServerConfig,
+        apply: F,
+    ) -> Result<(T, CanaryReport)>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        // Created before the backup, so a rollback keeps the canary
+        self.ensure_user(server_config).await?;
+
+        let backup_path = self.install_path.join(format!(
+            "backup_canary_{}",
+            Utc::now().format("%Y%m%d_%H%M%S")
+        ));
+        self.lifecycle
+            .backup_configuration(&self.install_path, &backup_path)
+            .await?;
+
+        let value = match apply.await {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::error!(
+                    "{} failed; configuration from before it is in {}",
+                    change.as_str(),
+                    backup_path.display()
+                );
+                return Err(e);
+            }
+        };
+
+        let report = self.check(change, server_config, backup_path).await;
+        Ok((value, report))
+    }
+
+    /// The canary user, created and pushed to the running Xray if missing
+    pub async fn ensure_user(&self, server_config: &ServerConfig) -> Result<User> {
+        let user_manager = UserManager::new(&self.install_path, server_config.clone())?;
+        if let Ok(user) = user_manager.get_user_by_name(CANARY_USER).await {
+            return Ok(user);
+        }
+
+        tracing::info!("Creating canary user '{}'", CANARY_USER);
+        let before = self.xray_config();
+        let user = user_manager
+            .create_user(CANARY_USER.to_string(), self.options.protocol)
+            .await?;
+
+        if let (Some(before), Some(after)) = (before, self.xray_config()) {
+            let plan = UserSyncPlan::between(&before, &after);
+            let result = match XrayUserSync::new() {
+                Ok(sync) => sync.apply(&self.install_path, &after, &plan).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                tracing::warn!("Could not add the canary user to the running server: {}", e);
+            }
+        }
+
+        Ok(user)
+    }
+
+    async fn check(
+        &self,
+        change: ConfigChange,
+        server_config: &ServerConfig,
+        backup_path: PathBuf,
+    ) -> CanaryReport {
+        let mut server_config = server_config.clone();
+        refresh_server_keys(&mut server_config, &self.install_path);
+
+        let result = self.connect(&server_config).await;
+        let error = match &result {
+            Ok(check) if check.is_success() => None,
+            Ok(check) if check.egress_matches_server == Some(false) => Some(format!(
+                "Canary traffic left through {}, not the server",
+                check.egress_ip
+            )),
+            Ok(check) => Some(format!(
+                "Canary request answered HTTP {}",
+                check.http_status
+            )),
+            Err(e) => Some(format!("Canary could not connect: {}", e)),
+        };
+
+        let mut report = CanaryReport {
+            change,
+            checked_at: Utc::now(),
+            passed: error.is_none(),
+            check: result.ok(),
+            error,
+            backup_path,
+            rolled_back: false,
+        };
+
+        let Some(error) = report.error.clone() else {
+            tracing::info!("Canary connected after {}", change.as_str());
+            return report;
+        };
+
+        if self.options.on_failure == CanaryFailureAction::Rollback {
+            match self.rollback(&report.backup_path).await {
+                Ok(()) => report.rolled_back = true,
+                Err(e) => tracing::error!("Rollback after failed canary failed: {}", e),
+            }
+        }
+
+        let outcome = if report.rolled_back {
+            "rolled back"
+        } else {
+            "kept"
+        };
+        self.alert(
+            &format!("{} after {}; change {}", error, change.as_str(), outcome),
+            &report,
+        )
+        .await;
+
+        report
+    }
+
+    async fn connect(&self, server_config: &ServerConfig) -> Result<ClientCheckReport> {
+        let user_manager = UserManager::new(&self.install_path, server_config.clone())?;
+        let user = user_manager.get_user_by_name(CANARY_USER).await?;
+        ClientVerifier::new(self.options.check.clone())
+            .verify(&user, server_config)
+            .await
+    }
+
+    async fn rollback(&self, backup_path: &Path) -> Result<()> {
+        tracing::warn!("Restoring configuration from {}", backup_path.display());
+        self.lifecycle
+            .restore_configuration(&self.install_path, backup_path)
+            .await?;
+        self.lifecycle.start(&self.install_path).await
+    }
+
+    async fn alert(&self, message: &str, report: &CanaryReport) {
+        tracing::error!("{}", message);
+
+        let Some(webhook) = &self.options.alert_webhook else {
+            return;
+        };
+
+        let body = serde_json::json!({
+            "alert": "canary_failed",
+            "install_path": self.install_path,
+            "message": message,
+            "report": report,
+        });
+        let result = reqwest::Client::new()
+            .post(webhook)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            tracing::error!("Failed to deliver canary alert: {}", e);
+        }
+    }
+
+    fn xray_config(&self) -> Option<XrayConfig> {
+        ConfigGenerator::load_config_from_file(self.install_path.join("config").join("config.json"))
+            .ok()
+    }
+}
+
+/// Replace the key material in `server_config` with what is on disk now
+fn refresh_server_keys(server_config: &mut ServerConfig, install_path: &Path) {
+    let config_dir = install_path.join("config");
+    let read = |file: &str| {
+        std::fs::read_to_string(config_dir.join(file))
+            .ok()
+            .map(|content| content.trim().to_string())
+            .filter(|content| !content.is_empty())
+    };
+
+    if let Some(public_key) = read("public_key.txt") {
+        server_config.public_key = Some(public_key);
+    }
+    if let Some(short_id) = read("short_id.txt") {
+        server_config.short_id = Some(short_id);
+    }
+    if let Some(sni) = read("sni.txt") {
+        server_config.sni = Some(sni);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_refresh_server_keys() {
+        let dir = TempDir::new().unwrap();
+        let config_dir = dir.path().join("config");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(config_dir.join("public_key.txt"), "new-public-key\n").unwrap();
+        std::fs::write(config_dir.join("sni.txt"), "").unwrap();
+
+        let mut server_config = ServerConfig {
+            host: "203.0.113.5".to_string(),
+            public_key: Some("old-public-key".to_string()),
+            short_id: Some("abcd".to_string()),
+            sni: Some("www.example.com".to_string()),
+            ..Default::default()
+        };
+        refresh_server_keys(&mut server_config, dir.path());
+
+        assert_eq!(server_config.public_key.as_deref(), Some("new-public-key"));
+        // Missing or empty files keep what was known
+        assert_eq!(server_config.short_id.as_deref(), Some("abcd"));
+        assert_eq!(server_config.sni.as_deref(), Some("www.example.com"));
+    }
+}
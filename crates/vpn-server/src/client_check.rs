@@ -0,0 +1,410 @@
+//! End-to-end client verification
+//!
+//! Starts a throwaway client container (Xray for VLESS, wg-quick for
+//! WireGuard) with the config generated for a user, then fetches a canary URL
+//! and an IP echo service through it from a curl container sharing the
+//! client's network namespace. Both containers run on this host, or on a
+//! remote checker reached over SSH, which shows whether the link works from
+//! outside the server's own network.
+//!
+//! The client config is fed to the container on stdin, so nothing has to be
+//! copied to the checker first.
+
+use crate::error::{Result, ServerError};
+use bollard::container::Config;
+use bollard::models::HostConfig;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use vpn_docker::OneShotRunner;
+use vpn_types::protocol::VpnProtocol;
+use vpn_users::config::ServerConfig;
+use vpn_users::links::ConnectionLinkGenerator;
+use vpn_users::User;
+
+/// Downloaded through the tunnel to measure throughput
+pub const DEFAULT_CANARY_URL: &str = "https://speed.cloudflare.com/__down?bytes=5000000";
+
+/// Returns the caller's public IP as plain text
+pub const DEFAULT_IP_ECHO_URL: &str = "https://api.ipify.org";
+
+const XRAY_IMAGE: &str = "ghcr.io/xtls/xray-core:latest";
+const WIREGUARD_IMAGE: &str = "lscr.io/linuxserver/wireguard:latest";
+const CURL_IMAGE: &str = "curlimages/curl:latest";
+
+/// SOCKS inbound of the generated Xray client config
+const XRAY_SOCKS_PROXY: &str = "socks5h://127.0.0.1:10808";
+
+/// curl `--write-out` fields, in the order [`CurlTimings::parse`] reads them
+const CURL_WRITE_OUT: &str =
+    "%{http_code} %{time_connect} %{time_appconnect} %{time_total} %{size_download} %{speed_download}";
+
+#[derive(Debug, Clone)]
+pub struct ClientCheckOptions {
+    pub canary_url: String,
+    pub ip_echo_url: String,
+    /// SSH destination (`user@host`) to run the client on instead of this host
+    pub remote: Option<String>,
+    /// Limit for each request made through the tunnel
+    pub request_timeout: Duration,
+    /// Time the client container gets to bring the tunnel up
+    pub startup_grace: Duration,
+    /// Canary requests made before giving up on the tunnel
+    pub attempts: u32,
+}
+
+impl Default for ClientCheckOptions {
+    fn default() -> Self {
+        Self {
+            canary_url: DEFAULT_CANARY_URL.to_string(),
+            ip_echo_url: DEFAULT_IP_ECHO_URL.to_string(),
+            remote: None,
+            request_timeout: Duration::from_secs(30),
+            startup_grace: Duration::from_secs(3),
+            attempts: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientCheckReport {
+    pub user: String,
+    pub protocol: String,
+    /// `local` or the SSH destination the client ran on
+    pub checker: String,
+    pub canary_url: String,
+    pub http_status: u16,
+    /// Time until the canary's TLS handshake through the tunnel completed
+    pub handshake_ms: f64,
+    pub total_ms: f64,
+    pub bytes_downloaded: u64,
+    pub throughput_bytes_per_sec: f64,
+    pub egress_ip: String,
+    /// Whether traffic left through the server; unknown when the server is
+    /// configured by hostname
+    pub egress_matches_server: Option<bool>,
+}
+
+impl ClientCheckReport {
+    pub fn is_success(&self) -> bool {
+        (200..400).contains(&self.http_status) && self.egress_matches_server != Some(false)
+    }
+}
+
+/// Timings reported by curl for one request
+#[derive(Debug, Clone, PartialEq)]
+struct CurlTimings {
+    http_status: u16,
+    connect_secs: f64,
+    appconnect_secs: f64,
+    total_secs: f64,
+    size_download: u64,
+    speed_download: f64,
+}
+
+impl CurlTimings {
+    fn parse(output: &str) -> Result<Self> {
+        let fields: Vec<&str> = output.split_whitespace().collect();
+        let [status, connect, appconnect, total, size, speed] = fields[..] else {
+            return Err(ServerError::ValidationError(format!(
+                "Unexpected curl output: {}",
+                output.trim()
+            )));
+        };
+
+        let number = |value: &str| {
+            value.parse::<f64>().map_err(|_| {
+                ServerError::ValidationError(format!("Invalid curl timing '{}'", value))
+            })
+        };
+
+        Ok(Self {
+            http_status: status.parse().map_err(|_| {
+                ServerError::ValidationError(format!("Invalid HTTP status '{}'", status))
+            })?,
+            connect_secs: number(connect)?,
+            appconnect_secs: number(appconnect)?,
+            total_secs: number(total)?,
+            size_download: number(size)? as u64,
+            speed_download: number(speed)?,
+        })
+    }
+
+    /// TLS handshake completion, or the TCP connect for plain HTTP
+    fn handshake_secs(&self) -> f64 {
+        if self.appconnect_secs > 0.0 {
+            self.appconnect_secs
+        } else {
+            self.connect_secs
+        }
+    }
+}
+
+/// How to run the client for a protocol
+struct ClientSpec {
+    image: &'static str,
+    cap_add: Vec<String>,
+    sysctls: HashMap<String, String>,
+    entrypoint: Option<Vec<String>>,
+    command: Vec<String>,
+    /// Proxy curl has to go through; WireGuard routes at the interface
+    proxy: Option<&'static str>,
+}
+
+impl ClientSpec {
+    fn for_protocol(protocol: VpnProtocol) -> Result<Self> {
+        match protocol {
+            VpnProtocol::Vless => Ok(Self {
+                image: XRAY_IMAGE,
+                cap_add: Vec::new(),
+                sysctls: HashMap::new(),
+                entrypoint: None,
+                command: vec!["run".into(), "-c".into(), "stdin:".into()],
+                proxy: Some(XRAY_SOCKS_PROXY),
+            }),
+            VpnProtocol::Wireguard => Ok(Self {
+                image: WIREGUARD_IMAGE,
+                cap_add: vec!["NET_ADMIN".into()],
+                sysctls: HashMap::from([("net.ipv4.conf.all.src_valid_mark".into(), "1".into())]),
+                entrypoint: Some(vec!["sh".into()]),
+                command: vec![
+                    "-c".into(),
+                    "cat > /etc/wireguard/wg0.conf && wg-quick up wg0 && exec sleep infinity"
+                        .into(),
+                ],
+                proxy: None,
+            }),
+            other => Err(ServerError::ValidationError(format!(
+                "Client verification is not supported for {}",
+                other.display_name()
+            ))),
+        }
+    }
+
+    /// Container config of the client; it removes itself once stopped
+    fn container_config(&self) -> Config<String> {
+        Config {
+            image: Some(self.image.to_string()),
+            entrypoint: self.entrypoint.clone(),
+            cmd: Some(self.command.clone()),
+            host_config: Some(HostConfig {
+                auto_remove: Some(true),
+                cap_add: (!self.cap_add.is_empty()).then(|| self.cap_add.clone()),
+                sysctls: (!self.sysctls.is_empty()).then(|| self.sysctls.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Proves a user's generated config works by connecting with it
+pub struct ClientVerifier {
+    options: ClientCheckOptions,
+}
+
+impl ClientVerifier {
+    pub fn new(options: ClientCheckOptions) -> Self {
+        Self { options }
+    }
+
+    pub async fn verify(
+        &self,
+        user: &User,
+        server_config: &ServerConfig,
+    ) -> Result<ClientCheckReport> {
+        let spec = ClientSpec::for_protocol(user.protocol)?;
+        let client_config = ConnectionLinkGenerator::generate_client_config(user, server_config)?;
+        let container = format!(
+            "vpn-verify-{}",
+            &uuid::Uuid::new_v4().simple().to_string()[..12]
+        );
+
+        let runner = match &self.options.remote {
+            Some(destination) => OneShotRunner::over_ssh(destination).await?,
+            None => OneShotRunner::local()?,
+        };
+
+        let result = match self
+            .start_client(&runner, &container, &spec, &client_config)
+            .await
+        {
+            Ok(()) => {
+                self.probe(&runner, &container, &spec, user, server_config)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        // The container is removed however the probe went
+        let _ = runner.remove(&container).await;
+
+        result
+    }
+
+    async fn start_client(
+        &self,
+        runner: &OneShotRunner,
+        container: &str,
+        spec: &ClientSpec,
+        config: &str,
+    ) -> Result<()> {
+        // Closing stdin hands the complete config to the client
+        runner
+            .start_with_input(container, spec.container_config(), config.as_bytes())
+            .await?;
+
+        tokio::time::sleep(self.options.startup_grace).await;
+        if !runner.is_running(container).await {
+            return Err(ServerError::LifecycleError(
+                "Client container exited during startup".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn probe(
+        &self,
+        runner: &OneShotRunner,
+        container: &str,
+        spec: &ClientSpec,
+        user: &User,
+        server_config: &ServerConfig,
+    ) -> Result<ClientCheckReport> {
+        let mut attempt = 1;
+        let timings = loop {
+            let output = self
+                .curl(
+                    runner,
+                    container,
+                    spec,
+                    &[
+                        "-o",
+                        "/dev/null",
+                        "-w",
+                        CURL_WRITE_OUT,
+                        &self.options.canary_url,
+                    ],
+                )
+                .await;
+            match output {
+                Ok(output) => break CurlTimings::parse(&output)?,
+                Err(e) if attempt >= self.options.attempts => return Err(e),
+                Err(e) => {
+                    tracing::debug!("Canary attempt {} failed: {}", attempt, e);
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                }
+            }
+        };
+
+        let egress_ip = self
+            .curl(runner, container, spec, &[&self.options.ip_echo_url])
+            .await?
+            .trim()
+            .to_string();
+
+        Ok(ClientCheckReport {
+            user: user.name.clone(),
+            protocol: user.protocol.display_name().to_string(),
+            checker: self
+                .options
+                .remote
+                .clone()
+                .unwrap_or_else(|| "local".to_string()),
+            canary_url: self.options.canary_url.clone(),
+            http_status: timings.http_status,
+            handshake_ms: timings.handshake_secs() * 1000.0,
+            total_ms: timings.total_secs * 1000.0,
+            bytes_downloaded: timings.size_download,
+            throughput_bytes_per_sec: timings.speed_download,
+            egress_matches_server: egress_matches(&egress_ip, &server_config.host),
+            egress_ip,
+        })
+    }
+
+    /// Run curl in the client's network namespace and return its stdout
+    async fn curl(
+        &self,
+        runner: &OneShotRunner,
+        container: &str,
+        spec: &ClientSpec,
+        args: &[&str],
+    ) -> Result<String> {
+        let max_time = self.options.request_timeout.as_secs().max(1).to_string();
+
+        let mut command = vec!["-sS", "--max-time", &max_time];
+        if let Some(proxy) = spec.proxy {
+            command.extend(["--proxy", proxy]);
+        }
+        command.extend(args);
+
+        let config = Config {
+            image: Some(CURL_IMAGE.to_string()),
+            cmd: Some(command.into_iter().map(String::from).collect()),
+            host_config: Some(HostConfig {
+                network_mode: Some(format!("container:{}", container)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let name = format!(
+            "{}-curl-{}",
+            container,
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        );
+
+        let output = runner.run(&name, config).await?;
+        if !output.success() {
+            return Err(ServerError::NetworkError(format!(
+                "Request through the tunnel failed: {}",
+                output.stderr.trim()
+            )));
+        }
+
+        Ok(output.stdout)
+    }
+}
+
+fn egress_matches(egress_ip: &str, server_host: &str) -> Option<bool> {
+    let server: IpAddr = server_host.parse().ok()?;
+    let egress: IpAddr = egress_ip.parse().ok()?;
+    Some(server == egress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_curl_timings() {
+        let timings = CurlTimings::parse("200 0.012 0.250 1.500 5000000 3333333.000").unwrap();
+        assert_eq!(timings.http_status, 200);
+        assert_eq!(timings.size_download, 5_000_000);
+        assert_eq!(timings.handshake_secs(), 0.25);
+
+        // Plain HTTP has no TLS handshake
+        let timings = CurlTimings::parse("200 0.012 0.000 0.100 10 100.0").unwrap();
+        assert_eq!(timings.handshake_secs(), 0.012);
+
+        assert!(CurlTimings::parse("curl: (7) Failed to connect").is_err());
+    }
+
+    #[test]
+    fn test_egress_matches() {
+        assert_eq!(
+            egress_matches("203.0.113.5\n".trim(), "203.0.113.5"),
+            Some(true)
+        );
+        assert_eq!(egress_matches("198.51.100.1", "203.0.113.5"), Some(false));
+        assert_eq!(egress_matches("198.51.100.1", "vpn.example.com"), None);
+    }
+
+    #[test]
+    fn test_unsupported_protocol() {
+        assert!(ClientSpec::for_protocol(VpnProtocol::Vless).is_ok());
+        assert!(ClientSpec::for_protocol(VpnProtocol::HttpProxy).is_err());
+    }
+}
@@ -14,6 +14,9 @@ pub enum ServerError {
     #[error("Key rotation failed: {0}")]
     KeyRotationError(String),
 
+    #[error("Config integrity check failed: {0}")]
+    IntegrityError(String),
+
     #[error("Template generation failed: {0}")]
     TemplateError(String),
 
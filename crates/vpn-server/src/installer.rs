@@ -1,15 +1,22 @@
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 // removed unused imports
 use crate::error::{Result, ServerError};
+use crate::integrity::{ConfigIntegrityChecker, IntegrityOptions};
 use crate::templates::DockerComposeTemplate;
 use crate::validator::ConfigValidator;
 use uuid::Uuid;
 use vpn_crypto::{UuidGenerator, X25519KeyManager};
-use vpn_docker::ContainerManager;
+use vpn_docker::networks::is_missing_network_error;
+use vpn_docker::{
+    docker_available, prune, remove_image, ComposeCli, ComposeProject, ConflictingContainer,
+    ContainerManager, DockerError, NetworkManager,
+};
 use vpn_network::firewall::{Direction, Protocol};
 use vpn_network::{
-    FirewallManager, FirewallRule, IpDetector, PortChecker, SubnetManager, VpnSubnet,
+    AccessGateConfig, FirewallBackend, FirewallLedger, FirewallManager, FirewallRule, IpDetector,
+    PortChecker, SubnetManager, VpnSubnet,
 };
 use vpn_types::protocol::VpnProtocol;
 use vpn_types::validation::{PathValidator, PortValidator};
@@ -27,8 +34,17 @@ pub struct InstallationOptions {
     pub reality_dest: Option<String>,
     pub subnet: Option<String>,
     pub interactive_subnet: bool,
+    /// Knock or SPA gating for management ports; an empty `gated_ports`
+    /// gates the protocol's default management ports
+    pub access_gate: Option<AccessGateConfig>,
+    /// Remove containers without the ownership label that hold the names
+    /// the install needs, as installs predating the label left them
+    pub take_ownership: bool,
 }
 
+/// Access gate configuration read by `vpn security gate`
+pub const ACCESS_GATE_FILE: &str = "access-gate.json";
+
 #[derive(Debug, Clone, Copy)]
 pub enum LogLevel {
     None,
@@ -134,12 +150,28 @@ impl ServerInstaller {
         // Download and start containers
         self.deploy_containers(&options).await?;
 
+        // Gate management ports now that Docker's chains exist
+        if let Some(gate) = &options.access_gate {
+            self.setup_access_gate(&options, &server_config, gate)
+                .await?;
+        }
+
         // Create initial user
         let initial_user = self.create_initial_user(&options, &server_config).await?;
 
         // Verify installation with actual server config
         self.verify_installation(&options, &server_config).await?;
 
+        // Baseline for the scheduled config integrity check
+        let integrity =
+            ConfigIntegrityChecker::new(&options.install_path, IntegrityOptions::default());
+        if let Err(e) = integrity.sign() {
+            println!(
+                "⚠️ Warning: Failed to sign config integrity manifest: {}",
+                e
+            );
+        }
+
         println!("VPN server installation completed successfully!");
 
         Ok(InstallationResult {
@@ -151,7 +183,7 @@ impl ServerInstaller {
 
     async fn check_dependencies(&self) -> Result<()> {
         // Check Docker
-        if !self.is_docker_installed() {
+        if !docker_available().await {
             return Err(ServerError::DependencyMissing("Docker".to_string()));
         }
 
@@ -192,21 +224,11 @@ impl ServerInstaller {
         if compose_path.exists() {
             println!("🛑 Stopping existing VPN containers...");
 
-            // Stop and remove containers
-            let output = Command::new("docker-compose")
-                .arg("-f")
-                .arg(&compose_path)
-                .arg("down")
-                .arg("--remove-orphans")
-                .arg("-v") // Remove volumes too
-                .output()?;
-
-            if !output.status.success() {
+            // Stop and remove containers and their volumes
+            match ComposeProject::new(&compose_path).down(true).await {
+                Ok(()) => println!("✓ Existing containers stopped"),
                 // Log the error but don't fail - containers might already be stopped
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                eprintln!("Warning: Failed to stop containers: {}", stderr);
-            } else {
-                println!("✓ Existing containers stopped");
+                Err(e) => eprintln!("Warning: Failed to stop containers: {}", e),
             }
 
             // Give Docker time to clean up
@@ -216,6 +238,51 @@ impl ServerInstaller {
         Ok(())
     }
 
+    /// Free the container names the compose file uses. Containers this
+    /// tool created are removed; any other needs `take_ownership` or the
+    /// operator's confirmation on a terminal.
+    async fn remove_conflicting_containers(
+        &self,
+        project: &ComposeProject,
+        options: &InstallationOptions,
+    ) -> Result<()> {
+        let interactive = io::stdin().is_terminal();
+        let removed = project
+            .remove_conflicting(options.take_ownership, |container| {
+                interactive && Self::confirm_takeover(container)
+            })
+            .await
+            .map_err(|e| match e {
+                DockerError::NameConflict(conflict) => ServerError::InstallationError(format!(
+                    "Container name conflict: {}. Use --take-ownership to remove unlabeled containers.",
+                    conflict
+                )),
+                e => e.into(),
+            })?;
+
+        for name in removed {
+            println!("✓ Removed conflicting container {}", name);
+        }
+        Ok(())
+    }
+
+    fn confirm_takeover(container: &ConflictingContainer) -> bool {
+        println!(
+            "⚠️ Container '{}' ({}, {}) {} and was not created by this tool",
+            container.name, container.image, container.state, container.ownership
+        );
+        print!("Remove it and take over its name? [y/N]: ");
+        if io::stdout().flush().is_err() {
+            return false;
+        }
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).is_err() {
+            return false;
+        }
+        matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
+    }
+
     async fn generate_server_config(&self, options: &InstallationOptions) -> Result<ServerConfig> {
         let port = match options.port {
             Some(p) => {
@@ -300,7 +367,8 @@ impl ServerInstaller {
                 comment: Some("VPN Server".to_string()),
             };
 
-            FirewallManager::add_ufw_rule(&rule).await?;
+            let mut ledger = FirewallLedger::load_default()?;
+            FirewallManager::add_tracked_rule(&mut ledger, FirewallBackend::Ufw, &rule).await?;
 
             if !FirewallManager::check_ufw_status().await? {
                 FirewallManager::enable_ufw().await?;
@@ -310,6 +378,55 @@ impl ServerInstaller {
         Ok(())
     }
 
+    /// Management ports exposed by `protocol`'s containers
+    fn management_ports(&self, protocol: VpnProtocol, port: u16) -> Vec<u16> {
+        match protocol {
+            // Outline management API, see `create_outline_compose_content`
+            VpnProtocol::Outline => vec![port + 1000],
+            // Traefik admin API
+            VpnProtocol::HttpProxy | VpnProtocol::Socks5Proxy | VpnProtocol::ProxyServer => {
+                vec![8090]
+            }
+            VpnProtocol::Vless | VpnProtocol::Wireguard | VpnProtocol::OpenVPN => Vec::new(),
+        }
+    }
+
+    async fn setup_access_gate(
+        &self,
+        options: &InstallationOptions,
+        server_config: &ServerConfig,
+        gate: &AccessGateConfig,
+    ) -> Result<()> {
+        let mut gate = gate.clone();
+        if gate.gated_ports.is_empty() {
+            gate.gated_ports = self.management_ports(options.protocol, server_config.port);
+        }
+        gate.validate()?;
+
+        gate.save(&options.install_path.join(ACCESS_GATE_FILE))?;
+        FirewallManager::create_access_gate(&gate.gated_ports).await?;
+
+        if options.enable_firewall && FirewallManager::is_ufw_installed().await {
+            let mut ledger = FirewallLedger::load_default()?;
+            for port in gate.listen_ports() {
+                let rule = FirewallRule {
+                    port,
+                    protocol: Protocol::Udp,
+                    direction: Direction::In,
+                    source: None,
+                    comment: Some("VPN access gate".to_string()),
+                };
+                FirewallManager::add_tracked_rule(&mut ledger, FirewallBackend::Ufw, &rule).await?;
+            }
+        }
+
+        println!(
+            "Management ports {:?} are gated; run `vpn security gate` to accept knocks",
+            gate.gated_ports
+        );
+        Ok(())
+    }
+
     async fn create_docker_configuration(
         &self,
         options: &InstallationOptions,
@@ -357,60 +474,41 @@ impl ServerInstaller {
 
         println!("🐳 Starting VPN containers...");
 
+        let project = ComposeProject::new(&compose_path);
+
         // Clean up any existing containers and networks first
-        let _ = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_path)
-            .arg("down")
-            .arg("--remove-orphans")
-            .output();
+        let _ = project.down(false).await;
+        self.remove_conflicting_containers(&project, options)
+            .await?;
 
         // Give Docker a moment to clean up
         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 
-        // Use docker-compose command
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_path)
-            .arg("up")
-            .arg("-d")
-            .output()?;
-
-        let stderr = String::from_utf8_lossy(&output.stderr);
-
-        // Handle Docker Compose version warnings (these are warnings, not errors)
-        if stderr.contains("the attribute `version` is obsolete") {
-            println!("⚠️ Note: Docker Compose version attribute warning (can be ignored)");
-        }
-
-        if !output.status.success() {
-            // Check for common permission issues
-            if stderr.contains("permission denied") || stderr.contains("Permission denied") {
+        // Missing networks are recreated and `up` retried by the project
+        match project.up(false).await {
+            Ok(()) => {}
+            Err(DockerError::PermissionDenied(_)) => {
                 return Err(ServerError::InstallationError(
                     "Docker permission denied. Please ensure your user is in the docker group or run with sudo.".to_string()
                 ));
             }
-
-            // Check for network conflicts
-            if stderr.contains("Pool overlaps with other one")
-                || stderr.contains("network conflicts")
-            {
+            Err(DockerError::NetworkConflict(_)) => {
                 return Err(ServerError::InstallationError(
                     "Docker network conflict detected. Try running 'vpn diagnostics --fix' to clean up Docker networks.".to_string()
                 ));
             }
-
-            // Check for network not found errors
-            if stderr.contains("network") && stderr.contains("not found") {
+            Err(DockerError::ComposeError(stderr)) if is_missing_network_error(&stderr) => {
                 return Err(ServerError::InstallationError(
                     "Docker network error detected. This often happens when containers are recreated. Try running 'vpn diagnostics --fix' to clean up Docker resources.".to_string()
                 ));
             }
-
-            return Err(ServerError::InstallationError(format!(
-                "Docker Compose failed: {}",
-                stderr
-            )));
+            Err(DockerError::ComposeError(stderr)) => {
+                return Err(ServerError::InstallationError(format!(
+                    "Docker Compose failed: {}",
+                    stderr
+                )));
+            }
+            Err(e) => return Err(e.into()),
         }
 
         println!("✓ Containers started, waiting for initialization...");
@@ -419,14 +517,7 @@ impl ServerInstaller {
         tokio::time::sleep(std::time::Duration::from_secs(15)).await;
 
         // Check if containers are actually running
-        let status_output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_path)
-            .arg("ps")
-            .arg("-q")
-            .output()?;
-
-        if status_output.stdout.is_empty() {
+        if project.running().await?.is_empty() {
             return Err(ServerError::InstallationError(
                 "Containers failed to start. Check 'docker-compose logs' for details.".to_string(),
             ));
@@ -441,7 +532,7 @@ impl ServerInstaller {
         if let Some(subnet) = &options.subnet {
             println!("🔍 Validating specified subnet: {}", subnet);
 
-            match SubnetManager::is_subnet_available(subnet) {
+            match SubnetManager::is_subnet_available(subnet).await {
                 Ok(true) => {
                     println!("✓ Specified subnet is available");
                     return Ok(VpnSubnet {
@@ -479,12 +570,14 @@ impl ServerInstaller {
         if options.interactive_subnet {
             println!("🔧 Interactive subnet selection requested");
             return SubnetManager::select_subnet_interactive()
+                .await
                 .map_err(|e| ServerError::NetworkError(format!("Subnet selection failed: {}", e)));
         }
 
         // Automatic subnet selection
         println!("🔍 Automatically selecting available VPN subnet...");
         SubnetManager::select_subnet_auto()
+            .await
             .map_err(|e| ServerError::NetworkError(format!("No available subnets found: {}", e)))
     }
 
@@ -492,27 +585,6 @@ impl ServerInstaller {
         let compose_path = options.install_path.join("docker-compose.yml");
 
         if let Ok(content) = std::fs::read_to_string(&compose_path) {
-            // Check if file contains obsolete version attribute
-            if content.contains("version:") {
-                println!("⚠️ Detected obsolete version attribute in Docker Compose file");
-
-                // Remove the version line
-                let lines: Vec<&str> = content.lines().collect();
-                let filtered_lines: Vec<&str> = lines
-                    .into_iter()
-                    .filter(|line| !line.trim().starts_with("version:"))
-                    .collect();
-
-                let new_content = filtered_lines.join("\n");
-
-                // Write back the cleaned content
-                if let Err(e) = std::fs::write(&compose_path, new_content) {
-                    println!("⚠️ Warning: Could not clean Docker Compose file: {}", e);
-                } else {
-                    println!("✓ Cleaned Docker Compose file (removed version attribute)");
-                }
-            }
-
             // Check for fixed subnet configuration
             if content.contains("subnet:") || content.contains("172.20.0.0") {
                 println!(
@@ -608,40 +680,20 @@ impl ServerInstaller {
         Ok(())
     }
 
-    fn is_docker_installed(&self) -> bool {
-        Command::new("docker")
-            .arg("--version")
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
-    }
-
     fn is_docker_compose_installed(&self) -> bool {
-        Command::new("docker-compose")
-            .arg("--version")
-            .output()
-            .map(|output| output.status.success())
-            .unwrap_or(false)
+        ComposeCli::detected().is_some()
     }
 
     async fn verify_containers_running(&self, install_path: &Path) -> Result<()> {
-        use std::process::Command;
-
         let compose_path = install_path.join("docker-compose.yml");
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_path)
-            .arg("ps")
-            .arg("-q")
-            .output()?;
-
-        if !output.status.success() {
-            return Err(ServerError::InstallationError(
-                "Failed to check container status".to_string(),
-            ));
-        }
-
-        if output.stdout.is_empty() {
+        let running = ComposeProject::new(&compose_path)
+            .running()
+            .await
+            .map_err(|_| {
+                ServerError::InstallationError("Failed to check container status".to_string())
+            })?;
+
+        if running.is_empty() {
             return Err(ServerError::InstallationError(
                 "No VPN containers are running. Installation may have failed.".to_string(),
             ));
@@ -651,31 +703,20 @@ impl ServerInstaller {
     }
 
     async fn verify_container_health(&self, install_path: &Path) -> Result<()> {
-        use std::process::Command;
-
         let compose_path = install_path.join("docker-compose.yml");
 
         // Wait a bit for containers to initialize
         tokio::time::sleep(std::time::Duration::from_secs(5)).await;
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_path)
-            .arg("ps")
-            .arg("--format")
-            .arg("table")
-            .output()?;
-
-        if !output.status.success() {
-            return Err(ServerError::InstallationError(
-                "Failed to check container health".to_string(),
-            ));
-        }
-
-        let output_str = String::from_utf8_lossy(&output.stdout);
+        let containers = ComposeProject::new(&compose_path).ps().await.map_err(|_| {
+            ServerError::InstallationError("Failed to check container health".to_string())
+        })?;
 
         // Check if any container is in "unhealthy" or "restarting" state
-        if output_str.contains("unhealthy") || output_str.contains("restarting") {
+        if containers
+            .iter()
+            .any(|container| container.is_unhealthy() || container.is_restarting())
+        {
             return Err(ServerError::InstallationError(
                 "One or more containers are unhealthy. Check logs with 'docker-compose logs'"
                     .to_string(),
@@ -683,7 +724,7 @@ impl ServerInstaller {
         }
 
         // Check if containers are actually up
-        if !output_str.contains("Up") {
+        if !containers.iter().any(|container| container.is_running()) {
             return Err(ServerError::InstallationError(
                 "Containers are not in running state".to_string(),
             ));
@@ -734,19 +775,9 @@ impl ServerInstaller {
         // 1. Stop and remove containers
         if compose_path.exists() {
             println!("🐳 Stopping and removing containers...");
-            let output = Command::new("docker-compose")
-                .arg("-f")
-                .arg(&compose_path)
-                .arg("down")
-                .arg("-v")
-                .arg("--remove-orphans")
-                .output()?;
-
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                println!("⚠️ Warning: Failed to cleanly stop containers: {}", stderr);
-            } else {
-                println!("✓ Containers stopped and removed");
+            match ComposeProject::new(&compose_path).down(true).await {
+                Ok(()) => println!("✓ Containers stopped and removed"),
+                Err(e) => println!("⚠️ Warning: Failed to cleanly stop containers: {}", e),
             }
         }
 
@@ -795,14 +826,8 @@ impl ServerInstaller {
         ];
 
         for image in &images_to_remove {
-            let output = Command::new("docker")
-                .arg("rmi")
-                .arg("-f")
-                .arg(image)
-                .output();
-
-            match output {
-                Ok(result) if result.status.success() => {
+            match remove_image(image).await {
+                Ok(true) => {
                     println!("✓ Removed Docker image: {}", image);
                 }
                 _ => {
@@ -812,12 +837,7 @@ impl ServerInstaller {
         }
 
         // Clean up unused Docker resources
-        let _cleanup_output = Command::new("docker")
-            .arg("system")
-            .arg("prune")
-            .arg("-f")
-            .arg("--volumes")
-            .output();
+        let _ = prune(true).await;
 
         println!("✓ Docker cleanup completed");
         Ok(())
@@ -826,6 +846,33 @@ impl ServerInstaller {
     async fn cleanup_firewall_rules(&self, install_path: &Path) -> Result<()> {
         println!("🔥 Cleaning up firewall rules...");
 
+        // Every rule the installer created, custom ports included
+        match FirewallLedger::load_default() {
+            Ok(mut ledger) => {
+                for entry in ledger.entries().to_vec() {
+                    match FirewallManager::remove_tracked_rule(
+                        &mut ledger,
+                        entry.backend,
+                        &entry.rule,
+                    )
+                    .await
+                    {
+                        Ok(()) => println!(
+                            "✓ Removed firewall rule for port {}/{}",
+                            entry.rule.port,
+                            entry.rule.protocol.as_str()
+                        ),
+                        Err(e) => println!(
+                            "⚠️ Warning: Failed to remove firewall rule for port {}: {}",
+                            entry.rule.port, e
+                        ),
+                    }
+                }
+            }
+            Err(e) => println!("⚠️ Warning: Cannot read firewall ledger: {}", e),
+        }
+
+        // Installations from before the ledger are cleaned up by guesswork
         // Try to detect which ports were used by reading the configuration
         let mut ports_to_clean = Vec::new();
 
@@ -841,6 +888,14 @@ impl ServerInstaller {
         // Also clean common VPN ports
         ports_to_clean.extend_from_slice(&[8443, 9443, 8080, 8090]);
 
+        if let Ok(gate) = AccessGateConfig::from_file(&install_path.join(ACCESS_GATE_FILE)) {
+            match FirewallManager::remove_access_gate(&gate.gated_ports).await {
+                Ok(()) => println!("✓ Removed access gate for ports {:?}", gate.gated_ports),
+                Err(e) => println!("⚠️ Warning: Failed to remove access gate: {}", e),
+            }
+            ports_to_clean.extend(gate.listen_ports());
+        }
+
         if FirewallManager::is_ufw_installed().await {
             for port in ports_to_clean {
                 // Remove both TCP and UDP rules
@@ -921,7 +976,7 @@ impl ServerInstaller {
         println!("🔍 Checking Docker network status...");
 
         // Show available subnets instead of aggressive cleanup
-        match SubnetManager::get_available_subnets() {
+        match SubnetManager::get_available_subnets().await {
             Ok(available_subnets) => {
                 if available_subnets.is_empty() {
                     println!("⚠️ No available subnet ranges found for VPN");
@@ -954,19 +1009,20 @@ impl ServerInstaller {
         }
 
         // Show current Docker networks status
-        let network_output = Command::new("docker")
-            .arg("network")
-            .arg("ls")
-            .arg("--format")
-            .arg("table {{.Name}}\\t{{.Driver}}\\t{{.Scope}}")
-            .output();
+        let networks = match NetworkManager::new() {
+            Ok(manager) => manager.list_networks().await,
+            Err(e) => Err(e),
+        };
 
-        if let Ok(output) = network_output {
-            if output.status.success() {
-                let networks = String::from_utf8_lossy(&output.stdout);
-                println!();
-                println!("Current Docker networks:");
-                println!("{}", networks);
+        if let Ok(networks) = networks {
+            println!();
+            println!("Current Docker networks:");
+            println!("{:<30} {:<10} SCOPE", "NAME", "DRIVER");
+            for network in networks {
+                println!(
+                    "{:<30} {:<10} {}",
+                    network.name, network.driver, network.scope
+                );
             }
         }
 
@@ -1011,6 +1067,8 @@ impl Default for InstallationOptions {
             reality_dest: None,
             subnet: None,
             interactive_subnet: false,
+            access_gate: None,
+            take_ownership: false,
         }
     }
 }
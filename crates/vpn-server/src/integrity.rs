@@ -0,0 +1,511 @@
+//! Config integrity checking
+//!
+//! A manifest records the SHA-256 of each critical config file and is signed
+//! with HMAC-SHA256 under a key kept outside the install directory, so
+//! someone who can write to the install path cannot re-sign a tampered
+//! config. A scheduled check compares the files with the manifest, alerts on
+//! drift and can restore drifted files from a configuration backup whose
+//! contents still match the manifest.
+//!
+//! Legitimate changes must be followed by [`ConfigIntegrityChecker::sign`].
+
+use crate::error::{Result, ServerError};
+use crate::installer::ACCESS_GATE_FILE;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Manifest location inside the install path
+pub const MANIFEST_FILE: &str = "integrity-manifest.json";
+
+/// Signing key location, outside the install path
+pub const DEFAULT_KEY_PATH: &str = "/etc/vpn/integrity.key";
+
+/// Files covered by the manifest, relative to the install path
+pub const CRITICAL_FILES: &[&str] = &[
+    "docker-compose.yml",
+    "config/config.json",
+    "config/private_key.txt",
+    "config/public_key.txt",
+    "config/short_id.txt",
+    "config/sni.txt",
+    ACCESS_GATE_FILE,
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityManifest {
+    pub created_at: DateTime<Utc>,
+    /// SHA-256 of each critical file present when the manifest was signed
+    pub files: BTreeMap<String, String>,
+    pub signature: String,
+}
+
+impl IntegrityManifest {
+    fn signing_payload(&self) -> String {
+        let mut payload = self.created_at.to_rfc3339();
+        for (path, hash) in &self.files {
+            payload.push('\n');
+            payload.push_str(path);
+            payload.push('=');
+            payload.push_str(hash);
+        }
+        payload
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FileDrift {
+    Modified {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+    Missing {
+        path: String,
+    },
+    /// A critical file that did not exist when the manifest was signed
+    Added {
+        path: String,
+    },
+}
+
+impl FileDrift {
+    pub fn path(&self) -> &str {
+        match self {
+            FileDrift::Modified { path, .. }
+            | FileDrift::Missing { path }
+            | FileDrift::Added { path } => path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityReport {
+    pub checked_at: DateTime<Utc>,
+    pub drift: Vec<FileDrift>,
+    /// Files put back from the backup
+    pub restored: Vec<String>,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.drift.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IntegrityOptions {
+    pub key_path: PathBuf,
+    /// Backup to restore drifted files from, as written by
+    /// [`ServerLifecycle::backup_configuration`](crate::ServerLifecycle::backup_configuration)
+    pub restore_from: Option<PathBuf>,
+    /// URL receiving the report as JSON when drift is found
+    pub alert_webhook: Option<String>,
+    /// UTC hour of the scheduled check
+    pub check_hour: u32,
+}
+
+impl Default for IntegrityOptions {
+    fn default() -> Self {
+        Self {
+            key_path: PathBuf::from(DEFAULT_KEY_PATH),
+            restore_from: None,
+            alert_webhook: None,
+            check_hour: 3,
+        }
+    }
+}
+
+pub struct ConfigIntegrityChecker {
+    install_path: PathBuf,
+    options: IntegrityOptions,
+}
+
+impl ConfigIntegrityChecker {
+    pub fn new(install_path: impl Into<PathBuf>, options: IntegrityOptions) -> Self {
+        Self {
+            install_path: install_path.into(),
+            options,
+        }
+    }
+
+    /// Hash the current files and write a freshly signed manifest, creating
+    /// the signing key on first use
+    pub fn sign(&self) -> Result<IntegrityManifest> {
+        let key = self.load_or_create_key()?;
+
+        let mut manifest = IntegrityManifest {
+            created_at: Utc::now(),
+            files: self.hash_files()?,
+            signature: String::new(),
+        };
+        manifest.signature = sign_payload(&key, &manifest.signing_payload());
+
+        std::fs::write(
+            self.install_path.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+
+        tracing::info!(
+            "Signed integrity manifest for {} files",
+            manifest.files.len()
+        );
+        Ok(manifest)
+    }
+
+    /// Load the manifest, refusing it if its signature does not verify
+    pub fn load_manifest(&self) -> Result<IntegrityManifest> {
+        let manifest_path = self.install_path.join(MANIFEST_FILE);
+        if !manifest_path.exists() {
+            return Err(ServerError::IntegrityError(format!(
+                "No integrity manifest at {}",
+                manifest_path.display()
+            )));
+        }
+
+        let manifest: IntegrityManifest =
+            serde_json::from_str(&std::fs::read_to_string(&manifest_path)?)?;
+        let key = self.load_key()?;
+
+        let signature = hex::decode(&manifest.signature).map_err(|_| {
+            ServerError::IntegrityError("Manifest signature is not valid hex".to_string())
+        })?;
+        let mut mac = HmacSha256::new_from_slice(&key).expect("HMAC accepts keys of any length");
+        mac.update(manifest.signing_payload().as_bytes());
+        mac.verify_slice(&signature).map_err(|_| {
+            ServerError::IntegrityError("Integrity manifest signature mismatch".to_string())
+        })?;
+
+        Ok(manifest)
+    }
+
+    /// Compare the current files with the signed manifest
+    pub fn check(&self) -> Result<IntegrityReport> {
+        let manifest = self.load_manifest()?;
+        let current = self.hash_files()?;
+
+        Ok(IntegrityReport {
+            checked_at: Utc::now(),
+            drift: diff_hashes(&manifest.files, &current),
+            restored: Vec::new(),
+        })
+    }
+
+    /// Check, restore drifted files if a backup is configured, and alert
+    pub async fn check_and_respond(&self) -> Result<IntegrityReport> {
+        let report = match self.check() {
+            Ok(report) => report,
+            Err(e) => {
+                // A missing or forged manifest is itself a tamper signal
+                self.alert(&format!("Integrity check failed: {}", e), None)
+                    .await;
+                return Err(e);
+            }
+        };
+
+        if report.is_clean() {
+            tracing::info!("Config integrity check passed");
+            return Ok(report);
+        }
+
+        let mut report = report;
+        if let Some(backup) = &self.options.restore_from {
+            let manifest = self.load_manifest()?;
+            report.restored = self.restore(&manifest, &report.drift, backup)?;
+        }
+
+        let summary = report
+            .drift
+            .iter()
+            .map(FileDrift::path)
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.alert(
+            &format!("Unauthorized config drift: {}", summary),
+            Some(&report),
+        )
+        .await;
+
+        Ok(report)
+    }
+
+    /// Run [`check_and_respond`](Self::check_and_respond) every day at
+    /// `check_hour` UTC
+    pub async fn run_scheduled(&self) {
+        loop {
+            let delay = delay_until_hour(Utc::now(), self.options.check_hour);
+            tracing::info!("Next config integrity check in {:?}", delay);
+            tokio::time::sleep(delay).await;
+
+            if let Err(e) = self.check_and_respond().await {
+                tracing::error!("Config integrity check failed: {}", e);
+            }
+        }
+    }
+
+    fn restore(
+        &self,
+        manifest: &IntegrityManifest,
+        drift: &[FileDrift],
+        backup: &Path,
+    ) -> Result<Vec<String>> {
+        let mut restored = Vec::new();
+
+        for item in drift {
+            let path = item.path();
+            let Some(expected) = manifest.files.get(path) else {
+                // Added files have nothing to go back to
+                continue;
+            };
+
+            let source = backup.join(path);
+            let Ok(content) = std::fs::read(&source) else {
+                tracing::warn!("No backup copy of {} to restore", path);
+                continue;
+            };
+
+            // Only restore a copy that matches what was signed
+            if &sha256_hex(&content) != expected {
+                tracing::warn!("Backup copy of {} does not match the manifest", path);
+                continue;
+            }
+
+            let target = self.install_path.join(path);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&target, content)?;
+            tracing::warn!("Restored {} from {}", path, backup.display());
+            restored.push(path.to_string());
+        }
+
+        Ok(restored)
+    }
+
+    async fn alert(&self, message: &str, report: Option<&IntegrityReport>) {
+        tracing::error!("{}", message);
+
+        let Some(webhook) = &self.options.alert_webhook else {
+            return;
+        };
+
+        let body = serde_json::json!({
+            "alert": "config_integrity",
+            "install_path": self.install_path,
+            "message": message,
+            "report": report,
+        });
+        let result = reqwest::Client::new()
+            .post(webhook)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            tracing::error!("Failed to deliver integrity alert: {}", e);
+        }
+    }
+
+    fn hash_files(&self) -> Result<BTreeMap<String, String>> {
+        let mut hashes = BTreeMap::new();
+        for path in CRITICAL_FILES {
+            let full_path = self.install_path.join(path);
+            if full_path.exists() {
+                hashes.insert(path.to_string(), sha256_hex(&std::fs::read(full_path)?));
+            }
+        }
+        Ok(hashes)
+    }
+
+    fn load_key(&self) -> Result<Vec<u8>> {
+        let content = std::fs::read_to_string(&self.options.key_path).map_err(|e| {
+            ServerError::IntegrityError(format!(
+                "Cannot read integrity key {}: {}",
+                self.options.key_path.display(),
+                e
+            ))
+        })?;
+        hex::decode(content.trim())
+            .map_err(|_| ServerError::IntegrityError("Integrity key is not valid hex".to_string()))
+    }
+
+    fn load_or_create_key(&self) -> Result<Vec<u8>> {
+        if self.options.key_path.exists() {
+            return self.load_key();
+        }
+
+        let mut key = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+
+        if let Some(parent) = self.options.key_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.options.key_path, hex::encode(&key))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(
+                &self.options.key_path,
+                std::fs::Permissions::from_mode(0o600),
+            )?;
+        }
+
+        Ok(key)
+    }
+}
+
+fn diff_hashes(
+    expected: &BTreeMap<String, String>,
+    actual: &BTreeMap<String, String>,
+) -> Vec<FileDrift> {
+    let mut drift = Vec::new();
+
+    for (path, expected_hash) in expected {
+        match actual.get(path) {
+            None => drift.push(FileDrift::Missing { path: path.clone() }),
+            Some(actual_hash) if actual_hash != expected_hash => drift.push(FileDrift::Modified {
+                path: path.clone(),
+                expected: expected_hash.clone(),
+                actual: actual_hash.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for path in actual.keys() {
+        if !expected.contains_key(path) {
+            drift.push(FileDrift::Added { path: path.clone() });
+        }
+    }
+
+    drift
+}
+
+fn sign_payload(key: &[u8], payload: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Time from `now` until the next `hour`:00 UTC
+fn delay_until_hour(now: DateTime<Utc>, hour: u32) -> Duration {
+    let today = now
+        .date_naive()
+        .and_hms_opt(hour.min(23), 0, 0)
+        .expect("valid time")
+        .and_utc();
+    let next = if today > now {
+        today
+    } else {
+        today + ChronoDuration::days(1)
+    };
+
+    (next - now).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn checker(
+        install_path: &Path,
+        key_dir: &Path,
+        restore_from: Option<PathBuf>,
+    ) -> ConfigIntegrityChecker {
+        ConfigIntegrityChecker::new(
+            install_path,
+            IntegrityOptions {
+                key_path: key_dir.join("integrity.key"),
+                restore_from,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_detects_and_restores_drift() {
+        let install = tempdir().unwrap();
+        let keys = tempdir().unwrap();
+        let backup = tempdir().unwrap();
+
+        std::fs::create_dir_all(install.path().join("config")).unwrap();
+        std::fs::write(install.path().join("config/config.json"), "{}").unwrap();
+        std::fs::write(install.path().join("docker-compose.yml"), "services: {}").unwrap();
+        std::fs::create_dir_all(backup.path().join("config")).unwrap();
+        std::fs::write(backup.path().join("config/config.json"), "{}").unwrap();
+
+        let checker = checker(
+            install.path(),
+            keys.path(),
+            Some(backup.path().to_path_buf()),
+        );
+        checker.sign().unwrap();
+        assert!(checker.check().unwrap().is_clean());
+
+        std::fs::write(install.path().join("config/config.json"), "{\"evil\":1}").unwrap();
+        std::fs::remove_file(install.path().join("docker-compose.yml")).unwrap();
+        std::fs::write(install.path().join("config/sni.txt"), "example.com").unwrap();
+
+        let report = checker.check_and_respond().await.unwrap();
+        assert_eq!(report.drift.len(), 3);
+        assert!(matches!(
+            report.drift[0],
+            FileDrift::Modified { ref path, .. } if path == "config/config.json"
+        ));
+        assert_eq!(report.restored, vec!["config/config.json".to_string()]);
+        assert_eq!(
+            std::fs::read_to_string(install.path().join("config/config.json")).unwrap(),
+            "{}"
+        );
+    }
+
+    #[test]
+    fn test_rejects_forged_manifest() {
+        let install = tempdir().unwrap();
+        let keys = tempdir().unwrap();
+        std::fs::write(install.path().join("docker-compose.yml"), "services: {}").unwrap();
+
+        let checker = checker(install.path(), keys.path(), None);
+        let mut manifest = checker.sign().unwrap();
+
+        // Re-hashing a tampered file without the key must not pass
+        std::fs::write(install.path().join("docker-compose.yml"), "evil").unwrap();
+        manifest
+            .files
+            .insert("docker-compose.yml".to_string(), sha256_hex(b"evil"));
+        std::fs::write(
+            install.path().join(MANIFEST_FILE),
+            serde_json::to_string(&manifest).unwrap(),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            checker.check(),
+            Err(ServerError::IntegrityError(_))
+        ));
+    }
+
+    #[test]
+    fn test_delay_until_hour() {
+        let now = "2024-05-01T02:30:00Z".parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(delay_until_hour(now, 3), Duration::from_secs(30 * 60));
+        assert_eq!(
+            delay_until_hour(now, 2),
+            Duration::from_secs(23 * 3600 + 30 * 60)
+        );
+    }
+}
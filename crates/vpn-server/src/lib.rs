@@ -1,15 +1,27 @@
+pub mod canary;
+pub mod client_check;
 pub mod error;
 pub mod installer;
+pub mod integrity;
 pub mod lifecycle;
+pub mod maintenance;
 pub mod proxy_installer;
 pub mod rotation;
+pub mod signage;
 pub mod templates;
 pub mod validator;
+pub mod xray_api;
 
+pub use canary::{CanaryFailureAction, CanaryGuard, CanaryOptions, CanaryReport, ConfigChange};
+pub use client_check::{ClientCheckOptions, ClientCheckReport, ClientVerifier};
 pub use error::{Result, ServerError};
 pub use installer::{InstallationOptions, ServerInstaller};
+pub use integrity::{ConfigIntegrityChecker, IntegrityOptions, IntegrityReport};
 pub use lifecycle::ServerLifecycle;
+pub use maintenance::{AvailabilityReport, MaintenanceSchedule, MaintenanceWindow, StatusPage};
 pub use proxy_installer::ProxyInstaller;
 pub use rotation::KeyRotationManager;
+pub use signage::{OnboardingSheet, SheetFormat};
 pub use templates::DockerComposeTemplate;
 pub use validator::ConfigValidator;
+pub use xray_api::{SyncOutcome, UserSyncPlan, XrayUserSync};
@@ -2,8 +2,7 @@ use crate::error::{Result, ServerError};
 use crate::validator::ConfigValidator;
 use std::path::Path;
 use std::time::Duration;
-use tokio::process::Command;
-use vpn_docker::{ContainerManager, HealthChecker};
+use vpn_docker::{ComposeProject, ContainerManager, HealthChecker};
 
 pub struct ServerLifecycle {
     container_manager: ContainerManager,
@@ -52,20 +51,10 @@ impl ServerLifecycle {
 
         println!("Starting VPN server...");
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_file)
-            .arg("up")
-            .arg("-d")
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            return Err(ServerError::LifecycleError(format!(
-                "Failed to start server: {}",
-                String::from_utf8_lossy(&output.stderr)
-            )));
-        }
+        ComposeProject::new(&compose_file)
+            .up(false)
+            .await
+            .map_err(|e| ServerError::LifecycleError(format!("Failed to start server: {}", e)))?;
 
         // Wait for containers to become healthy
         self.wait_for_healthy_state(Duration::from_secs(60)).await?;
@@ -83,19 +72,10 @@ impl ServerLifecycle {
 
         println!("Stopping VPN server...");
 
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_file)
-            .arg("stop")
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            return Err(ServerError::LifecycleError(format!(
-                "Failed to stop server: {}",
-                String::from_utf8_lossy(&output.stderr)
-            )));
-        }
+        ComposeProject::new(&compose_file)
+            .stop()
+            .await
+            .map_err(|e| ServerError::LifecycleError(format!("Failed to stop server: {}", e)))?;
 
         println!("VPN server stopped successfully");
         Ok(())
@@ -112,6 +92,31 @@ impl ServerLifecycle {
         Ok(())
     }
 
+    /// Pull newer images and recreate the containers whose image changed
+    pub async fn upgrade(&self, install_path: &Path) -> Result<()> {
+        let compose_file = install_path.join("docker-compose.yml");
+
+        if !compose_file.exists() {
+            return Err(ServerError::ServerNotFound);
+        }
+
+        println!("Upgrading VPN server...");
+
+        let project = ComposeProject::new(&compose_file);
+        project
+            .pull()
+            .await
+            .map_err(|e| ServerError::LifecycleError(format!("Failed to pull images: {}", e)))?;
+        project.up(false).await.map_err(|e| {
+            ServerError::LifecycleError(format!("Failed to start upgraded server: {}", e))
+        })?;
+
+        self.wait_for_healthy_state(Duration::from_secs(60)).await?;
+
+        println!("VPN server upgraded successfully");
+        Ok(())
+    }
+
     pub async fn reload_config(&self, install_path: &Path) -> Result<()> {
         // Validate new configuration first
         let validation_result = self.validator.validate_installation(install_path).await?;
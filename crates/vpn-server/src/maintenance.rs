@@ -0,0 +1,460 @@
+//! Planned maintenance windows
+//!
+//! Windows are recorded in `maintenance.json` in the installation
+//! directory. While one is active the alert engine holds back
+//! notifications, and availability reports leave the window out of the
+//! SLA: it counts neither towards the measured period nor as downtime.
+//!
+//! A window can also publish an announcement page. The page is served by a
+//! small nginx container behind the proxy's Traefik, which picks up the
+//! route from its dynamic configuration directory.
+
+use crate::error::{Result, ServerError};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use vpn_docker::{compose_schema, ComposeProject};
+
+pub const MAINTENANCE_FILE: &str = "maintenance.json";
+
+/// Directory holding the page and its compose file
+const STATUS_PAGE_DIR: &str = "status-page";
+/// Route file in the proxy's Traefik dynamic configuration
+const STATUS_PAGE_ROUTE: &str = "proxy/dynamic/maintenance-status.yml";
+const STATUS_PAGE_CONTAINER: &str = "vpn-status-page";
+/// Network of the proxy compose project, which Traefik is attached to
+const PROXY_NETWORK: &str = "proxy_proxy-network";
+
+/// A period of planned downtime
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub id: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub message: String,
+    /// Whether an announcement page was published for the window
+    #[serde(default)]
+    pub status_page: bool,
+    /// Set when the window was ended before `ends_at`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+impl MaintenanceWindow {
+    /// When the window actually ends
+    pub fn end(&self) -> DateTime<Utc> {
+        self.ended_at
+            .map_or(self.ends_at, |ended| ended.min(self.ends_at))
+    }
+
+    pub fn is_active(&self, at: DateTime<Utc>) -> bool {
+        self.starts_at <= at && at < self.end()
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end() - self.starts_at
+    }
+}
+
+/// Maintenance windows recorded for an installation
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceSchedule {
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+impl MaintenanceSchedule {
+    /// Schedule recorded in `install_path`; empty if none was recorded
+    pub fn load(install_path: &Path) -> Result<Self> {
+        let path = install_path.join(MAINTENANCE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, install_path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(install_path.join(MAINTENANCE_FILE), content)?;
+        Ok(())
+    }
+
+    /// Whether `install_path` is in a maintenance window at `at`; a schedule
+    /// that cannot be read counts as no maintenance
+    pub fn in_maintenance(install_path: &Path, at: DateTime<Utc>) -> bool {
+        Self::load(install_path)
+            .map(|schedule| schedule.active(at).is_some())
+            .unwrap_or(false)
+    }
+
+    pub fn active(&self, at: DateTime<Utc>) -> Option<&MaintenanceWindow> {
+        self.windows.iter().find(|window| window.is_active(at))
+    }
+
+    /// Open a window of `duration` starting at `at`
+    pub fn start(
+        &mut self,
+        at: DateTime<Utc>,
+        duration: Duration,
+        message: String,
+        status_page: bool,
+    ) -> Result<&MaintenanceWindow> {
+        if duration <= Duration::zero() {
+            return Err(ServerError::ValidationError(
+                "Maintenance duration must be positive".to_string(),
+            ));
+        }
+        if let Some(active) = self.active(at) {
+            return Err(ServerError::ValidationError(format!(
+                "Maintenance window {} is already active until {}",
+                active.id,
+                active.end()
+            )));
+        }
+
+        self.windows.push(MaintenanceWindow {
+            id: uuid::Uuid::new_v4().to_string()[..8].to_string(),
+            starts_at: at,
+            ends_at: at + duration,
+            message,
+            status_page,
+            ended_at: None,
+        });
+        Ok(&self.windows[self.windows.len() - 1])
+    }
+
+    /// End the window active at `at`, returning it
+    pub fn end_active(&mut self, at: DateTime<Utc>) -> Option<MaintenanceWindow> {
+        let window = self
+            .windows
+            .iter_mut()
+            .find(|window| window.is_active(at))?;
+        window.ended_at = Some(at);
+        Some(window.clone())
+    }
+
+    /// Windows overlapping `from..to`, oldest first
+    pub fn windows_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Vec<&MaintenanceWindow> {
+        let mut windows: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|window| window.starts_at < to && window.end() > from)
+            .collect();
+        windows.sort_by_key(|window| window.starts_at);
+        windows
+    }
+
+    /// Availability over `from..to` given the `outages` observed in it.
+    /// Maintenance windows are taken out of both the period and the outages
+    pub fn availability(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        outages: &[(DateTime<Utc>, DateTime<Utc>)],
+    ) -> AvailabilityReport {
+        let planned = merge(
+            self.windows
+                .iter()
+                .map(|window| (window.starts_at, window.end())),
+            from,
+            to,
+        );
+        let outages = merge(outages.iter().copied(), from, to);
+
+        let planned_downtime = total(&planned);
+        let outage_time = total(&outages);
+        let overlap = outages
+            .iter()
+            .map(|outage| total(&merge(planned.iter().copied(), outage.0, outage.1)))
+            .fold(Duration::zero(), |sum, overlap| sum + overlap);
+
+        AvailabilityReport {
+            from,
+            to,
+            planned_downtime,
+            unplanned_downtime: outage_time - overlap,
+            windows: self
+                .windows_between(from, to)
+                .into_iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Availability of a period with planned downtime excluded
+#[derive(Debug, Clone, Serialize)]
+pub struct AvailabilityReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub planned_downtime: Duration,
+    /// Downtime outside maintenance windows
+    #[serde(serialize_with = "serialize_seconds")]
+    pub unplanned_downtime: Duration,
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+impl AvailabilityReport {
+    /// Share of the period outside maintenance the service was up, in
+    /// percent
+    pub fn availability_percent(&self) -> f64 {
+        let measured = (self.to - self.from - self.planned_downtime).num_seconds();
+        if measured <= 0 {
+            return 100.0;
+        }
+
+        let up = measured - self.unplanned_downtime.num_seconds();
+        up.max(0) as f64 / measured as f64 * 100.0
+    }
+}
+
+fn serialize_seconds<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_i64(duration.num_seconds())
+}
+
+/// `intervals` clipped to `from..to`, sorted and with overlaps merged
+fn merge(
+    intervals: impl Iterator<Item = (DateTime<Utc>, DateTime<Utc>)>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut clipped: Vec<_> = intervals
+        .map(|(start, end)| (start.max(from), end.min(to)))
+        .filter(|(start, end)| start < end)
+        .collect();
+    clipped.sort();
+
+    let mut merged: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for (start, end) in clipped {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn total(intervals: &[(DateTime<Utc>, DateTime<Utc>)]) -> Duration {
+    intervals
+        .iter()
+        .fold(Duration::zero(), |sum, (start, end)| sum + (*end - *start))
+}
+
+/// Announcement page served through the proxy's Traefik
+pub struct StatusPage {
+    install_path: PathBuf,
+}
+
+impl StatusPage {
+    pub fn new(install_path: PathBuf) -> Self {
+        Self { install_path }
+    }
+
+    /// Publish the announcement for `window` and route `/status` to it
+    pub async fn publish(&self, window: &MaintenanceWindow) -> Result<()> {
+        let route = self.install_path.join(STATUS_PAGE_ROUTE);
+        if !route.parent().is_some_and(Path::exists) {
+            return Err(ServerError::DependencyMissing(
+                "the status page is served by the proxy's Traefik; install the proxy first"
+                    .to_string(),
+            ));
+        }
+
+        let dir = self.install_path.join(STATUS_PAGE_DIR);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("index.html"), render_page(window))?;
+        fs::write(
+            dir.join("docker-compose.yml"),
+            compose_schema().render(&compose_file())?,
+        )?;
+
+        self.project().up(false).await.map_err(|e| {
+            ServerError::LifecycleError(format!("Status page compose up failed: {}", e))
+        })?;
+        fs::write(route, route_config())?;
+        Ok(())
+    }
+
+    /// Remove the route and stop the page
+    pub async fn withdraw(&self) -> Result<()> {
+        let route = self.install_path.join(STATUS_PAGE_ROUTE);
+        if route.exists() {
+            fs::remove_file(route)?;
+        }
+
+        if self
+            .install_path
+            .join(STATUS_PAGE_DIR)
+            .join("docker-compose.yml")
+            .exists()
+        {
+            self.project().down(false).await.map_err(|e| {
+                ServerError::LifecycleError(format!("Status page compose down failed: {}", e))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn project(&self) -> ComposeProject {
+        ComposeProject::new(
+            self.install_path
+                .join(STATUS_PAGE_DIR)
+                .join("docker-compose.yml"),
+        )
+    }
+}
+
+fn compose_file() -> String {
+    format!(
+        r#"services:
+  status-page:
+    image: nginx:alpine
+    container_name: {container}
+    restart: unless-stopped
+    volumes:
+      - ./index.html:/usr/share/nginx/html/index.html:ro
+    networks:
+      - proxy
+
+networks:
+  proxy:
+    name: {network}
+    external: true
+"#,
+        container = STATUS_PAGE_CONTAINER,
+        network = PROXY_NETWORK,
+    )
+}
+
+fn route_config() -> String {
+    format!(
+        r#"# Maintenance announcement, removed when the window ends
+http:
+  routers:
+    maintenance-status:
+      rule: "PathPrefix(`/status`)"
+      entryPoints:
+        - http-proxy
+      priority: 1000
+      middlewares:
+        - maintenance-status-strip
+      service: maintenance-status
+
+  middlewares:
+    maintenance-status-strip:
+      stripPrefix:
+        prefixes:
+          - "/status"
+
+  services:
+    maintenance-status:
+      loadBalancer:
+        servers:
+          - url: "http://{}:80"
+"#,
+        STATUS_PAGE_CONTAINER
+    )
+}
+
+fn render_page(window: &MaintenanceWindow) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Scheduled maintenance</title>
+<style>
+body {{ font-family: sans-serif; max-width: 40em; margin: 4em auto; padding: 0 1em; color: #222; }}
+.until {{ color: #666; }}
+</style>
+</head>
+<body>
+<h1>Scheduled maintenance</h1>
+<p>{}</p>
+<p class="until">Expected to end at {} UTC.</p>
+</body>
+</html>
+"#,
+        html_escape(&window.message),
+        window.ends_at.format("%Y-%m-%d %H:%M"),
+    )
+}
+
+pub(crate) fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_schedule_windows() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut schedule = MaintenanceSchedule::load(dir.path()).unwrap();
+        assert!(schedule.windows.is_empty());
+
+        schedule
+            .start(at(2), Duration::hours(2), "Upgrade".to_string(), false)
+            .unwrap();
+        assert!(schedule
+            .start(at(3), Duration::hours(1), "Again".to_string(), false)
+            .is_err());
+        assert!(schedule.active(at(3)).is_some());
+        assert!(schedule.active(at(4)).is_none());
+
+        // Ending early shortens the window
+        let ended = schedule.end_active(at(3)).unwrap();
+        assert_eq!(ended.duration(), Duration::hours(1));
+        assert!(schedule.active(at(3)).is_none());
+
+        schedule.save(dir.path()).unwrap();
+        assert_eq!(MaintenanceSchedule::load(dir.path()).unwrap(), schedule);
+        assert!(!MaintenanceSchedule::in_maintenance(dir.path(), at(3)));
+    }
+
+    #[test]
+    fn test_availability_excludes_maintenance() {
+        let mut schedule = MaintenanceSchedule::default();
+        schedule
+            .start(at(2), Duration::hours(2), "Upgrade".to_string(), false)
+            .unwrap();
+
+        // Down 1:00-5:00; two of those hours were planned
+        let report = schedule.availability(at(0), at(10), &[(at(1), at(5))]);
+        assert_eq!(report.planned_downtime, Duration::hours(2));
+        assert_eq!(report.unplanned_downtime, Duration::hours(2));
+        assert_eq!(report.windows.len(), 1);
+        assert_eq!(report.availability_percent(), 75.0);
+
+        let report = schedule.availability(at(0), at(10), &[(at(2), at(3))]);
+        assert_eq!(report.availability_percent(), 100.0);
+    }
+}
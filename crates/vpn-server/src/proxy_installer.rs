@@ -1,10 +1,11 @@
 //! Proxy server installer implementation
 
 use crate::error::{Result, ServerError};
+use rand::RngCore;
 use std::path::PathBuf;
 use tokio::fs;
 use tracing::{debug, info};
-use vpn_docker::{ContainerManager, ContainerStatus};
+use vpn_docker::{ComposeProject, ContainerManager, ContainerStatus};
 
 pub struct ProxyInstaller {
     install_path: PathBuf,
@@ -50,6 +51,8 @@ impl ProxyInstaller {
             self.install_path.join("proxy/dynamic"),
             self.install_path.join("proxy/logs"),
             self.install_path.join("proxy/certs"),
+            // Templates of the pages shown for blocked requests
+            self.install_path.join("proxy/pages"),
         ];
 
         for dir in &dirs {
@@ -86,6 +89,25 @@ impl ProxyInstaller {
         let auth_path = self.install_path.join("proxy/auth-config.toml");
         fs::write(&auth_path, auth_config).await?;
 
+        // Token of the auth service's admin API, through which user
+        // management provisions new proxy credentials; kept on reinstall so
+        // running clients stay valid
+        let env_path = self.install_path.join("proxy/.env");
+        if !env_path.exists() {
+            let mut token = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut token);
+            fs::write(
+                &env_path,
+                format!("PROXY_ADMIN_TOKEN={}\n", hex::encode(token)),
+            )
+            .await?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&env_path, std::fs::Permissions::from_mode(0o600)).await?;
+            }
+        }
+
         // Generate Prometheus configuration
         let prometheus_config = self.generate_prometheus_config();
         let prometheus_path = self.install_path.join("proxy/prometheus.yml");
@@ -99,22 +121,10 @@ impl ProxyInstaller {
 
         let compose_path = self.install_path.join("proxy/docker-compose.yml");
 
-        // Run docker-compose up -d
-        let output = tokio::process::Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_path)
-            .arg("up")
-            .arg("-d")
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(ServerError::InstallationError(format!(
-                "Docker compose failed: {}",
-                stderr
-            )));
-        }
+        ComposeProject::new(&compose_path)
+            .up(false)
+            .await
+            .map_err(|e| ServerError::InstallationError(format!("Docker compose failed: {}", e)))?;
 
         // Wait for services to be healthy
         self.wait_for_health().await?;
@@ -226,9 +236,13 @@ services:
       - RATE_LIMIT_RPS=100
       - METRICS_ENABLED=true
       - LOG_LEVEL=info
+      - ADMIN_TOKEN=${{PROXY_ADMIN_TOKEN:-}}
+    ports:
+      - "127.0.0.1:3001:3000"
     volumes:
       - vpn-users-data:/var/lib/vpn/users:ro
       - ./auth-config.toml:/etc/proxy/config.toml:ro
+      - ./pages:/etc/proxy/pages:ro
     networks:
       - proxy-network
     labels:
@@ -418,6 +432,14 @@ enabled = true
 bind_address = "0.0.0.0:9090"
 path = "/metrics"
 
+[block_pages]
+template_dir = "/etc/proxy/pages"
+brand = "VPN Proxy"
+
+# One JSON line per request on stdout, collected by log shipping
+[access_log]
+ship = true
+
 [timeouts]
 connect = { secs = 10, nanos = 0 }
 read = { secs = 30, nanos = 0 }
@@ -471,22 +493,12 @@ scrape_configs:
         let compose_path = self.install_path.join("proxy/docker-compose.yml");
 
         if compose_path.exists() {
-            // Run docker-compose down
-            let output = tokio::process::Command::new("docker-compose")
-                .arg("-f")
-                .arg(&compose_path)
-                .arg("down")
-                .arg("-v")
-                .output()
-                .await?;
-
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                return Err(ServerError::InstallationError(format!(
-                    "Docker compose down failed: {}",
-                    stderr
-                )));
-            }
+            ComposeProject::new(&compose_path)
+                .down(true)
+                .await
+                .map_err(|e| {
+                    ServerError::InstallationError(format!("Docker compose down failed: {}", e))
+                })?;
         }
 
         // Remove configuration files
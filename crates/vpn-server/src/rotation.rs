@@ -1,4 +1,5 @@
 use crate::error::Result;
+use crate::integrity::{ConfigIntegrityChecker, IntegrityOptions, MANIFEST_FILE};
 use crate::lifecycle::ServerLifecycle;
 use std::collections::HashMap;
 use std::fs;
@@ -76,6 +77,17 @@ impl KeyRotationManager {
         // Update server configuration
         self.update_server_configuration(install_path).await?;
 
+        // Rotation is an authorized change, so move the integrity baseline
+        if install_path.join(MANIFEST_FILE).exists() {
+            let integrity = ConfigIntegrityChecker::new(install_path, IntegrityOptions::default());
+            if let Err(e) = integrity.sign() {
+                println!(
+                    "Warning: Failed to re-sign config integrity manifest: {}",
+                    e
+                );
+            }
+        }
+
         // Restart server if requested
         if options.restart_server {
             self.server_lifecycle.restart(install_path).await?;
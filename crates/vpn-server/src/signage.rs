@@ -0,0 +1,523 @@
+//! Printable onboarding sheets
+//!
+//! A sheet is a single page handed to a user who should not need to know
+//! what a connection link is: how to set up the client for their
+//! protocol, the connection QR code and link, when access ends and whom
+//! to ask for help. Sheets render to HTML from a template, which admins
+//! can replace with their own, or to a self-contained one-page PDF.
+
+use crate::error::{Result, ServerError};
+use crate::maintenance::html_escape;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::fmt::Write as _;
+use vpn_crypto::QrCodeGenerator;
+use vpn_users::{User, VpnProtocol};
+
+/// User tag holding the date access ends, e.g. `expires=2025-06-30`
+pub const EXPIRES_TAG: &str = "expires";
+
+/// Template used when none is given
+///
+/// Placeholders: `{{brand}}`, `{{user}}`, `{{protocol}}`, `{{qr}}` (an
+/// inline SVG), `{{link}}`, `{{instructions}}` (`<li>` items),
+/// `{{expires}}` and `{{support}}`.
+pub const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{{brand}} - {{user}}</title>
+<style>
+@page { size: A4; margin: 18mm; }
+body { font-family: -apple-system, system-ui, sans-serif; color: #222; max-width: 44rem; margin: 2rem auto; }
+h1 { font-size: 1.8rem; margin: 0 0 .25rem; }
+.qr { width: 8cm; margin: 1.5rem auto; }
+.qr svg { width: 100%; height: auto; }
+.link { font-family: monospace; font-size: .75rem; word-break: break-all; border: 1px solid #ccc; padding: .5rem; }
+.muted { color: #666; }
+</style>
+</head>
+<body>
+<h1>{{brand}}</h1>
+<p class="muted">Connection for {{user}} ({{protocol}})</p>
+<div class="qr">{{qr}}</div>
+<h2>How to connect</h2>
+<ol>
+{{instructions}}
+</ol>
+<h2>Connection link</h2>
+<p class="link">{{link}}</p>
+<p>Access valid until: <strong>{{expires}}</strong></p>
+<p>Need help? Contact <strong>{{support}}</strong></p>
+</body>
+</html>
+"#;
+
+/// What a sheet is rendered to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SheetFormat {
+    Html,
+    Pdf,
+}
+
+impl SheetFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SheetFormat::Html => "html",
+            SheetFormat::Pdf => "pdf",
+        }
+    }
+}
+
+/// Onboarding sheet for one user
+#[derive(Debug, Clone)]
+pub struct OnboardingSheet {
+    pub brand: String,
+    pub user_name: String,
+    pub protocol: VpnProtocol,
+    pub connection_link: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub support_contact: Option<String>,
+}
+
+impl OnboardingSheet {
+    /// Sheet for `user`, expiring as given by its [`EXPIRES_TAG`] tag
+    pub fn new(user: &User, connection_link: String) -> Self {
+        Self {
+            brand: "VPN".to_string(),
+            user_name: user.name.clone(),
+            protocol: user.protocol,
+            connection_link,
+            expires_at: user
+                .tags
+                .get(EXPIRES_TAG)
+                .and_then(|value| parse_expiry(value)),
+            support_contact: None,
+        }
+    }
+
+    pub fn with_brand(mut self, brand: String) -> Self {
+        self.brand = brand;
+        self
+    }
+
+    pub fn with_expiry(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn with_support_contact(mut self, contact: String) -> Self {
+        self.support_contact = Some(contact);
+        self
+    }
+
+    /// Steps to set up a client for the sheet's protocol
+    pub fn instructions(&self) -> Vec<&'static str> {
+        match self.protocol {
+            VpnProtocol::Vless => vec![
+                "Install a VLESS client: v2rayNG on Android, Streisand or FoXray on iOS, Nekoray or v2rayN on a computer.",
+                "In the app, choose to add a profile by scanning a QR code and scan the code on this page.",
+                "Select the new profile and connect.",
+            ],
+            VpnProtocol::Outline => vec![
+                "Install the Outline Client from getoutline.org or your app store.",
+                "Scan the QR code on this page, or copy the connection link and open the app.",
+                "Tap Connect.",
+            ],
+            VpnProtocol::Wireguard => vec![
+                "Install the WireGuard app from wireguard.com or your app store.",
+                "Add a tunnel by scanning the QR code on this page.",
+                "Switch the tunnel on.",
+            ],
+            VpnProtocol::OpenVPN => vec![
+                "Install OpenVPN Connect from openvpn.net or your app store.",
+                "Import the profile from the connection link on this page.",
+                "Connect to the imported profile.",
+            ],
+            VpnProtocol::HttpProxy | VpnProtocol::Socks5Proxy | VpnProtocol::ProxyServer => vec![
+                "Open the proxy settings of your browser or operating system.",
+                "Enter the server, port, user name and password from the connection link on this page.",
+                "Save the settings and open any website to check the connection.",
+            ],
+        }
+    }
+
+    pub fn render(&self, format: SheetFormat, template: Option<&str>) -> Result<Vec<u8>> {
+        match format {
+            SheetFormat::Html => Ok(self.render_html(template)?.into_bytes()),
+            SheetFormat::Pdf => self.render_pdf(),
+        }
+    }
+
+    /// Fill `template`, or [`DEFAULT_TEMPLATE`] when `None`
+    pub fn render_html(&self, template: Option<&str>) -> Result<String> {
+        let template = template.unwrap_or(DEFAULT_TEMPLATE);
+        if !template.contains("{{qr}}") && !template.contains("{{link}}") {
+            return Err(ServerError::TemplateError(
+                "sheet template must contain {{qr}} or {{link}}".to_string(),
+            ));
+        }
+
+        let qr = QrCodeGenerator::new().generate_qr_code(&self.connection_link)?;
+        let qr = String::from_utf8_lossy(&qr);
+        let qr = qr.find("<svg").map(|start| &qr[start..]).unwrap_or(&qr);
+
+        let instructions: String = self
+            .instructions()
+            .iter()
+            .map(|step| format!("<li>{}</li>\n", html_escape(step)))
+            .collect();
+
+        let values = [
+            ("brand", html_escape(&self.brand)),
+            ("user", html_escape(&self.user_name)),
+            ("protocol", self.protocol.display_name().to_string()),
+            ("qr", qr.to_string()),
+            ("link", html_escape(&self.connection_link)),
+            ("instructions", instructions.trim_end().to_string()),
+            ("expires", html_escape(&self.expiry_text())),
+            ("support", html_escape(&self.support_text())),
+        ];
+        Ok(fill_template(template, &values))
+    }
+
+    /// One A4 page with the same content as the default template
+    pub fn render_pdf(&self) -> Result<Vec<u8>> {
+        let (width, modules) = QrCodeGenerator::new().modules(&self.connection_link)?;
+        let mut page = PdfPage::default();
+        let mut y = PAGE_HEIGHT - MARGIN - 22.0;
+
+        page.text(Font::Bold, 22.0, MARGIN, y, &self.brand);
+        y -= 20.0;
+        page.text(
+            Font::Regular,
+            12.0,
+            MARGIN,
+            y,
+            &format!(
+                "Connection for {} ({})",
+                self.user_name,
+                self.protocol.display_name()
+            ),
+        );
+
+        // Dark modules as filled squares, quiet zone included in the size
+        let module = QR_SIZE / (width + 8) as f64;
+        let left = (PAGE_WIDTH - QR_SIZE) / 2.0 + 4.0 * module;
+        let top = y - 20.0 - 4.0 * module;
+        for (index, _) in modules.iter().enumerate().filter(|(_, dark)| **dark) {
+            let (row, column) = (index / width, index % width);
+            page.rect(
+                left + column as f64 * module,
+                top - (row + 1) as f64 * module,
+                module,
+            );
+        }
+        y -= 20.0 + QR_SIZE + 28.0;
+
+        page.text(Font::Bold, 14.0, MARGIN, y, "How to connect");
+        y -= 20.0;
+        for (step, instruction) in self.instructions().iter().enumerate() {
+            let text = format!("{}. {}", step + 1, instruction);
+            for line in wrap(&text, 90) {
+                page.text(Font::Regular, 11.0, MARGIN, y, &line);
+                y -= 15.0;
+            }
+            y -= 3.0;
+        }
+
+        y -= 10.0;
+        page.text(Font::Bold, 14.0, MARGIN, y, "Connection link");
+        y -= 16.0;
+        let link: Vec<char> = self.connection_link.chars().collect();
+        for chunk in link.chunks(100) {
+            page.text(
+                Font::Mono,
+                8.0,
+                MARGIN,
+                y,
+                &chunk.iter().collect::<String>(),
+            );
+            y -= 10.0;
+        }
+
+        y -= 14.0;
+        page.text(
+            Font::Regular,
+            11.0,
+            MARGIN,
+            y,
+            &format!("Access valid until: {}", self.expiry_text()),
+        );
+        y -= 16.0;
+        page.text(
+            Font::Regular,
+            11.0,
+            MARGIN,
+            y,
+            &format!("Need help? Contact {}", self.support_text()),
+        );
+
+        Ok(page.finish())
+    }
+
+    fn expiry_text(&self) -> String {
+        self.expires_at
+            .map(|at| at.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "no expiry".to_string())
+    }
+
+    fn support_text(&self) -> String {
+        self.support_contact
+            .clone()
+            .unwrap_or_else(|| "your administrator".to_string())
+    }
+}
+
+/// Expiry given as a date (end of that day, UTC) or an RFC 3339 timestamp
+pub fn parse_expiry(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return date.and_hms_opt(23, 59, 59).map(|at| at.and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+/// Replace `{{name}}` placeholders in one pass, so values containing
+/// placeholder syntax are left as they are; unknown names are kept
+fn fill_template(template: &str, values: &[(&str, String)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let value = placeholder.find("}}").and_then(|end| {
+            let name = &placeholder[2..end];
+            values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (value, end + 2))
+        });
+        match value {
+            Some((value, length)) => {
+                filled.push_str(value);
+                rest = &placeholder[length..];
+            }
+            None => {
+                filled.push_str("{{");
+                rest = &placeholder[2..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Split `text` into lines of at most `width` characters at spaces
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// A4 in points
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 56.0;
+const QR_SIZE: f64 = 230.0;
+
+#[derive(Debug, Clone, Copy)]
+enum Font {
+    Regular,
+    Bold,
+    Mono,
+}
+
+impl Font {
+    fn resource(&self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+            Font::Mono => "F3",
+        }
+    }
+}
+
+/// Content of a single PDF page using the standard fonts
+#[derive(Default)]
+struct PdfPage {
+    content: String,
+}
+
+impl PdfPage {
+    fn text(&mut self, font: Font, size: f64, x: f64, y: f64, text: &str) {
+        let _ = writeln!(
+            self.content,
+            "BT /{} {} Tf {:.2} {:.2} Td ({}) Tj ET",
+            font.resource(),
+            size,
+            x,
+            y,
+            pdf_string(text)
+        );
+    }
+
+    fn rect(&mut self, x: f64, y: f64, size: f64) {
+        let _ = writeln!(
+            self.content,
+            "{:.2} {:.2} {:.2} {:.2} re f",
+            x, y, size, size
+        );
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Contents 4 0 R \
+                 /Resources << /Font << /F1 5 0 R /F2 6 0 R /F3 7 0 R >> >> >>",
+                PAGE_WIDTH, PAGE_HEIGHT
+            ),
+            format!(
+                "<< /Length {} >>\nstream\n{}endstream",
+                self.content.len(),
+                self.content
+            ),
+            standard_font("Helvetica"),
+            standard_font("Helvetica-Bold"),
+            standard_font("Courier"),
+        ];
+
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (index, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            let _ = write!(pdf, "{} 0 obj\n{}\nendobj\n", index + 1, object);
+        }
+
+        let xref = pdf.len();
+        let _ = write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(pdf, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            pdf,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        );
+        pdf.into_bytes()
+    }
+}
+
+fn standard_font(name: &str) -> String {
+    format!(
+        "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+        name
+    )
+}
+
+/// Escape `text` for a PDF string literal; characters outside Latin-1
+/// cannot be shown by the standard fonts and become `?`
+fn pdf_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(escaped, "\\{:03o}", c as u32);
+            }
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sheet() -> OnboardingSheet {
+        let user = User::new("Zoë <admin> {{link}}".to_string(), VpnProtocol::Vless)
+            .with_tag(EXPIRES_TAG.to_string(), "2025-06-30".to_string());
+        OnboardingSheet::new(
+            &user,
+            "vless://id@vpn.example.com:443?sni=a&b=c".to_string(),
+        )
+        .with_brand("Acme VPN".to_string())
+        .with_support_contact("help@example.com".to_string())
+    }
+
+    #[test]
+    fn test_html_sheet() {
+        let sheet = sheet();
+        assert_eq!(
+            sheet.expires_at,
+            Some(Utc.with_ymd_and_hms(2025, 6, 30, 23, 59, 59).unwrap())
+        );
+
+        let html = sheet.render_html(None).unwrap();
+        assert!(html.contains("<h1>Acme VPN</h1>"));
+        assert!(html.contains("Zoë &lt;admin&gt; {{link}} (VLESS)"));
+        assert!(html.contains("<svg"));
+        assert!(html.contains("sni=a&amp;b=c"));
+        assert!(html.contains("<strong>2025-06-30</strong>"));
+        assert!(html.contains("<li>Install a VLESS client"));
+        assert!(!html.contains("{{brand}}"));
+
+        let custom = sheet.render_html(Some("{{user}}: {{link}}")).unwrap();
+        assert_eq!(
+            custom,
+            "Zoë &lt;admin&gt; {{link}}: vless://id@vpn.example.com:443?sni=a&amp;b=c"
+        );
+        assert!(sheet.render_html(Some("<p>{{user}}</p>")).is_err());
+    }
+
+    #[test]
+    fn test_pdf_sheet() {
+        let pdf = sheet().render(SheetFormat::Pdf, None).unwrap();
+        let pdf = String::from_utf8(pdf).unwrap();
+
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        assert!(pdf.contains("(Acme VPN) Tj"));
+        assert!(pdf.contains("(Connection for Zo\\353 <admin> {{link}} \\(VLESS\\)) Tj"));
+        assert!(pdf.contains(" re f\n"));
+
+        // Every xref entry points at its object
+        let xref = pdf.rfind("xref\n").unwrap();
+        let entries: Vec<&str> = pdf[xref..].lines().skip(3).take(7).collect();
+        for (index, entry) in entries.iter().enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj", index + 1)));
+        }
+    }
+
+    #[test]
+    fn test_parse_expiry() {
+        assert_eq!(
+            parse_expiry("2025-01-02T03:04:05Z"),
+            Some(Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap())
+        );
+        assert_eq!(parse_expiry("soon"), None);
+        assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+    }
+}
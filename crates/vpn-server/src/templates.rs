@@ -2,6 +2,11 @@ use crate::error::{Result, ServerError};
 use crate::installer::{InstallationOptions, LogLevel, ServerConfig};
 use std::fs;
 use std::path::Path;
+use vpn_docker::{compose_schema, MANAGED_LABEL};
+use vpn_users::config::{
+    API_PORT, API_TAG, BLOCK_OUTBOUND_TAG, DEFAULT_USER_LEVEL, DIRECT_OUTBOUND_TAG,
+    THROTTLED_USER_LEVEL, VLESS_INBOUND_TAG,
+};
 
 pub struct DockerComposeTemplate;
 
@@ -19,8 +24,7 @@ impl DockerComposeTemplate {
     ) -> Result<()> {
         let compose_content = self.create_xray_compose_content(server_config, options, subnet)?;
 
-        let compose_file = install_path.join("docker-compose.yml");
-        fs::write(&compose_file, compose_content)?;
+        self.write_compose_file(install_path, &compose_content)?;
 
         // Create directory structure
         self.create_xray_directories(install_path)?;
@@ -42,8 +46,7 @@ impl DockerComposeTemplate {
         let compose_content =
             self.create_outline_compose_content(server_config, options, subnet)?;
 
-        let compose_file = install_path.join("docker-compose.yml");
-        fs::write(&compose_file, compose_content)?;
+        self.write_compose_file(install_path, &compose_content)?;
 
         // Create directory structure
         self.create_outline_directories(install_path)?;
@@ -51,6 +54,14 @@ impl DockerComposeTemplate {
         Ok(())
     }
 
+    /// Write `docker-compose.yml` in the file format the installed Compose
+    /// accepts
+    fn write_compose_file(&self, install_path: &Path, compose_content: &str) -> Result<()> {
+        let compose_content = compose_schema().render(compose_content)?;
+        fs::write(install_path.join("docker-compose.yml"), compose_content)?;
+        Ok(())
+    }
+
     fn create_xray_compose_content(
         &self,
         server_config: &ServerConfig,
@@ -78,6 +89,8 @@ impl DockerComposeTemplate {
     environment:
       - XRAY_LOCATION_ASSET=/usr/share/xray
     command: ["run", "-config", "/etc/xray/config.json"]
+    labels:
+      - {managed_label}=true
     logging:
       driver: "json-file"
       options:
@@ -99,6 +112,8 @@ impl DockerComposeTemplate {
       - WATCHTOWER_REVIVE_STOPPED=false
       - WATCHTOWER_LIFECYCLE_HOOKS=false
     command: ["--cleanup", "--include-restarting=false", "--include-stopped=false"]
+    labels:
+      - {managed_label}=true
     networks:
       - vpn-network
 
@@ -110,7 +125,8 @@ networks:
             server_config.port,
             server_config.port,
             restart_policy,
-            subnet_config = Self::format_subnet_config(subnet)
+            subnet_config = Self::format_subnet_config(subnet),
+            managed_label = MANAGED_LABEL,
         );
 
         Ok(compose)
@@ -142,6 +158,8 @@ networks:
       - ./management:/opt/outline/management
     environment:
       - LOG_LEVEL={}
+    labels:
+      - {managed_label}=true
     networks:
       - vpn-network
 
@@ -154,6 +172,8 @@ networks:
     environment:
       - WATCHTOWER_CLEANUP=true
       - WATCHTOWER_POLL_INTERVAL=86400
+    labels:
+      - {managed_label}=true
     networks:
       - vpn-network
 
@@ -166,7 +186,8 @@ networks:
             server_config.port,
             server_config.log_level.as_str(),
             restart_policy,
-            subnet_config = Self::format_subnet_config(subnet)
+            subnet_config = Self::format_subnet_config(subnet),
+            managed_label = MANAGED_LABEL,
         );
 
         Ok(compose)
@@ -206,8 +227,7 @@ networks:
         let compose_content =
             self.create_wireguard_compose_content(server_config, options, subnet)?;
 
-        let compose_file = install_path.join("docker-compose.yml");
-        fs::write(&compose_file, compose_content)?;
+        self.write_compose_file(install_path, &compose_content)?;
 
         // Create directory structure
         self.create_wireguard_directories(install_path)?;
@@ -252,6 +272,8 @@ networks:
       - "{}:{}/udp"
     sysctls:
       - net.ipv4.conf.all.src_valid_mark=1
+    labels:
+      - {managed_label}=true
     restart: {}
     networks:
       - vpn-network
@@ -264,7 +286,8 @@ networks:
             server_config.port,
             server_config.port,
             restart_policy,
-            subnet_config = Self::format_subnet_config(subnet)
+            subnet_config = Self::format_subnet_config(subnet),
+            managed_label = MANAGED_LABEL,
         );
 
         Ok(compose)
@@ -346,8 +369,39 @@ networks:
                 "access": "/var/log/xray/access.log",
                 "error": "/var/log/xray/error.log"
             },
+            "api": {
+                "tag": API_TAG,
+                "services": ["HandlerService", "StatsService", "RoutingService"]
+            },
+            "stats": {},
+            "policy": {
+                "levels": {
+                    DEFAULT_USER_LEVEL.to_string(): {
+                        "statsUserUplink": true,
+                        "statsUserDownlink": true
+                    },
+                    THROTTLED_USER_LEVEL.to_string(): {
+                        "statsUserUplink": true,
+                        "statsUserDownlink": true,
+                        "bufferSize": 4,
+                        "connIdle": 60
+                    }
+                },
+                "system": {
+                    "statsInboundUplink": true,
+                    "statsInboundDownlink": true
+                }
+            },
+            "routing": {
+                "domainStrategy": "AsIs",
+                "rules": [{
+                    "type": "field",
+                    "inboundTag": [API_TAG],
+                    "outboundTag": API_TAG
+                }]
+            },
             "inbounds": [{
-                "tag": "vless-in",
+                "tag": VLESS_INBOUND_TAG,
                 "port": server_config.port,
                 "protocol": "vless",
                 "settings": {
@@ -373,11 +427,22 @@ networks:
                         }
                     }
                 }
+            }, {
+                "tag": API_TAG,
+                "listen": "127.0.0.1",
+                "port": API_PORT,
+                "protocol": "dokodemo-door",
+                "settings": {
+                    "address": "127.0.0.1"
+                }
             }],
             "outbounds": [{
-                "tag": "direct",
+                "tag": DIRECT_OUTBOUND_TAG,
                 "protocol": "freedom",
                 "settings": {}
+            }, {
+                "tag": BLOCK_OUTBOUND_TAG,
+                "protocol": "blackhole"
             }]
         });
 
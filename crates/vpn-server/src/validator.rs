@@ -1,7 +1,6 @@
 use crate::error::Result;
 use std::path::Path;
-use std::process::Command;
-use vpn_docker::{ContainerManager, HealthChecker};
+use vpn_docker::{ComposeProject, ContainerManager, HealthChecker};
 use vpn_network::{PortChecker, SniValidator};
 
 pub struct ConfigValidator {
@@ -132,32 +131,15 @@ impl ConfigValidator {
         }
 
         // Validate docker-compose.yml syntax
-        let output = Command::new("docker-compose")
-            .arg("-f")
-            .arg(&compose_file)
-            .arg("config")
-            .output();
-
-        match output {
-            Ok(output) => {
-                if output.status.success() {
-                    self.add_pass(result, check_name, "Docker Compose configuration is valid");
-                } else {
-                    self.add_error(
-                        result,
-                        check_name,
-                        &format!(
-                            "Docker Compose validation failed: {}",
-                            String::from_utf8_lossy(&output.stderr)
-                        ),
-                    );
-                }
+        match ComposeProject::new(&compose_file).validate().await {
+            Ok(()) => {
+                self.add_pass(result, check_name, "Docker Compose configuration is valid");
             }
             Err(e) => {
                 self.add_error(
                     result,
                     check_name,
-                    &format!("Failed to validate Docker Compose: {}", e),
+                    &format!("Docker Compose validation failed: {}", e),
                 );
             }
         }
@@ -243,6 +225,11 @@ impl ConfigValidator {
         // Check port configuration
         if let Some(inbounds) = config["inbounds"].as_array() {
             for inbound in inbounds {
+                // Loopback inbounds such as the Xray API are not published
+                if inbound["listen"].as_str() == Some("127.0.0.1") {
+                    continue;
+                }
+
                 if let Some(port) = inbound["port"].as_u64() {
                     let port = port as u16;
 
@@ -0,0 +1,254 @@
+//! Apply user changes to a running Xray through its API
+//!
+//! Clients are added and removed with `xray api adu`/`rmu` inside the
+//! container, so other users keep their connections. Changes the handler
+//! API cannot express, such as new routing rules or policy levels, fall
+//! back to restarting the Xray container.
+
+use crate::error::{Result, ServerError};
+use std::collections::HashMap;
+use std::path::Path;
+use vpn_docker::ContainerManager;
+use vpn_users::config::{Client, XrayConfig, API_PORT, VLESS_INBOUND_TAG};
+
+const XRAY_CONTAINER: &str = "xray";
+/// Where the installation's `config` directory is mounted in the container
+const CONTAINER_CONFIG_DIR: &str = "/etc/xray";
+/// Scratch file handed to `xray api adu`
+const ADD_USERS_FILE: &str = "api-add-users.json";
+
+/// Difference between the config Xray is running and the regenerated one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserSyncPlan {
+    /// Clients to add to the VLESS inbound
+    pub add: Vec<Client>,
+    /// Emails of clients to remove from the VLESS inbound
+    pub remove: Vec<String>,
+    /// Something changed that the handler API cannot apply
+    pub restart_required: bool,
+}
+
+impl UserSyncPlan {
+    pub fn between(running: &XrayConfig, desired: &XrayConfig) -> Self {
+        let mut plan = Self {
+            // Without the API section there is nothing to talk to
+            restart_required: running.api.is_none()
+                || !same_json(&running.routing, &desired.routing)
+                || !same_json(&running.policy, &desired.policy)
+                || !same_json(&running.outbounds, &desired.outbounds),
+            ..Self::default()
+        };
+
+        let running_clients = clients_by_email(running);
+        let desired_clients = clients_by_email(desired);
+        let (running_clients, desired_clients) = match (running_clients, desired_clients) {
+            (Some(running), Some(desired)) => (running, desired),
+            // Clients without an email cannot be removed through the API
+            _ => {
+                plan.restart_required = true;
+                return plan;
+            }
+        };
+
+        for (email, client) in &desired_clients {
+            match running_clients.get(email) {
+                Some(current) if current == client => {}
+                Some(_) => {
+                    plan.remove.push(email.clone());
+                    plan.add.push(client.clone());
+                }
+                None => plan.add.push(client.clone()),
+            }
+        }
+        for email in running_clients.keys() {
+            if !desired_clients.contains_key(email) {
+                plan.remove.push(email.clone());
+            }
+        }
+
+        plan.add.sort_by(|a, b| a.email.cmp(&b.email));
+        plan.remove.sort();
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.remove.is_empty() && !self.restart_required
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// Nothing to apply
+    Unchanged,
+    /// Xray is not running; it reads the new config when started
+    NotRunning,
+    /// Applied through the API without a restart
+    Applied,
+    /// The Xray container was restarted
+    Restarted,
+}
+
+pub struct XrayUserSync {
+    container_manager: ContainerManager,
+}
+
+impl XrayUserSync {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            container_manager: ContainerManager::new()?,
+        })
+    }
+
+    /// Apply `plan` to the running Xray, restarting its container if the
+    /// API cannot apply it or fails
+    pub async fn apply(
+        &self,
+        install_path: &Path,
+        desired: &XrayConfig,
+        plan: &UserSyncPlan,
+    ) -> Result<SyncOutcome> {
+        if plan.is_empty() {
+            return Ok(SyncOutcome::Unchanged);
+        }
+
+        if !self
+            .container_manager
+            .container_exists(XRAY_CONTAINER)
+            .await
+        {
+            return Ok(SyncOutcome::NotRunning);
+        }
+
+        if !plan.restart_required {
+            match self.apply_through_api(install_path, desired, plan).await {
+                Ok(()) => return Ok(SyncOutcome::Applied),
+                Err(e) => tracing::warn!("Xray API sync failed, restarting instead: {}", e),
+            }
+        }
+
+        self.container_manager
+            .restart_container(XRAY_CONTAINER, Some(10))
+            .await?;
+        Ok(SyncOutcome::Restarted)
+    }
+
+    async fn apply_through_api(
+        &self,
+        install_path: &Path,
+        desired: &XrayConfig,
+        plan: &UserSyncPlan,
+    ) -> Result<()> {
+        let server = format!("--server=127.0.0.1:{}", API_PORT);
+
+        // Changed clients are removed and re-added, so remove first
+        if !plan.remove.is_empty() {
+            let tag = format!("-tag={}", VLESS_INBOUND_TAG);
+            let mut cmd = vec!["xray", "api", "rmu", server.as_str(), tag.as_str()];
+            cmd.extend(plan.remove.iter().map(String::as_str));
+            self.run_api_command(cmd).await?;
+        }
+
+        if !plan.add.is_empty() {
+            let mut inbound = desired.inbound(VLESS_INBOUND_TAG).cloned().ok_or_else(|| {
+                ServerError::ValidationError(format!(
+                    "Xray config has no '{}' inbound",
+                    VLESS_INBOUND_TAG
+                ))
+            })?;
+            inbound.settings.clients = plan.add.clone();
+
+            let host_file = install_path.join("config").join(ADD_USERS_FILE);
+            let content = serde_json::json!({ "inbounds": [inbound] });
+            std::fs::write(&host_file, serde_json::to_string_pretty(&content)?)?;
+
+            let container_file = format!("{}/{}", CONTAINER_CONFIG_DIR, ADD_USERS_FILE);
+            let result = self
+                .run_api_command(vec![
+                    "xray",
+                    "api",
+                    "adu",
+                    server.as_str(),
+                    container_file.as_str(),
+                ])
+                .await;
+            let _ = std::fs::remove_file(&host_file);
+            result?;
+        }
+
+        Ok(())
+    }
+
+    /// `exec` does not report the exit code, so failures are detected from
+    /// the output
+    async fn run_api_command(&self, cmd: Vec<&str>) -> Result<()> {
+        let output = self
+            .container_manager
+            .exec_command(XRAY_CONTAINER, cmd)
+            .await?;
+
+        let lower = output.to_lowercase();
+        if lower.contains("failed") || lower.contains("error") {
+            return Err(ServerError::LifecycleError(format!(
+                "xray api: {}",
+                output.trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn clients_by_email(config: &XrayConfig) -> Option<HashMap<String, Client>> {
+    let clients = config
+        .inbound(VLESS_INBOUND_TAG)
+        .map(|inbound| inbound.settings.clients.as_slice())
+        .unwrap_or_default();
+
+    clients
+        .iter()
+        .map(|client| client.email.clone().map(|email| (email, client.clone())))
+        .collect()
+}
+
+fn same_json<T: serde::Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vpn_users::config::{ConfigGenerator, ServerConfig};
+    use vpn_users::{TrafficPolicy, User, VpnProtocol};
+
+    fn config(users: &[User]) -> XrayConfig {
+        ConfigGenerator::generate_xray_config(users, &ServerConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn test_sync_plan() {
+        let alice = User::new("alice".to_string(), VpnProtocol::Vless);
+        let bob = User::new("bob".to_string(), VpnProtocol::Vless);
+        let running = config(&[alice.clone(), bob.clone()]);
+
+        // Adding and removing users needs no restart
+        let carol = User::new("carol".to_string(), VpnProtocol::Vless);
+        let plan = UserSyncPlan::between(&running, &config(&[alice.clone(), carol.clone()]));
+        assert!(!plan.restart_required);
+        assert_eq!(plan.add.len(), 1);
+        assert_eq!(plan.add[0].id, carol.id);
+        assert_eq!(plan.remove, vec![format!("{}@vpn", bob.id)]);
+
+        // Throttling changes the client's level: re-add it
+        let throttled = bob.clone().with_traffic(TrafficPolicy::Throttle);
+        let plan = UserSyncPlan::between(&running, &config(&[alice.clone(), throttled]));
+        assert!(!plan.restart_required);
+        assert_eq!(plan.add.len(), 1);
+        assert_eq!(plan.remove.len(), 1);
+
+        // Blocking adds a routing rule
+        let blocked = bob.with_traffic(TrafficPolicy::Block);
+        let plan = UserSyncPlan::between(&running, &config(&[alice, blocked]));
+        assert!(plan.restart_required);
+
+        assert!(UserSyncPlan::between(&running, &running).is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Per-connection TCP byte counters for vpn-telemetry's `ebpf` collector.
+//
+// Build with:
+//   bpftool btf dump file /sys/kernel/btf/vmlinux format c > vmlinux.h
+//   clang -O2 -g -target bpf -c conn_bytes.bpf.c -o conn_bytes.bpf.o
+//
+// The layout of `conn_key` and `conn_bytes` must match `ConnKey` and
+// `ConnBytes` in src/performance/ebpf.rs.
+
+#include "vmlinux.h"
+#include <bpf/bpf_core_read.h>
+#include <bpf/bpf_endian.h>
+#include <bpf/bpf_helpers.h>
+#include <bpf/bpf_tracing.h>
+
+#define AF_INET 2
+#define MAX_CONNECTIONS 65536
+
+struct conn_key {
+	__u32 saddr;	/* network byte order */
+	__u32 daddr;	/* network byte order */
+	__u16 sport;	/* host byte order */
+	__u16 dport;	/* host byte order */
+};
+
+struct conn_bytes {
+	__u64 sent;
+	__u64 received;
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_LRU_HASH);
+	__uint(max_entries, MAX_CONNECTIONS);
+	__type(key, struct conn_key);
+	__type(value, struct conn_bytes);
+} CONN_BYTES SEC(".maps");
+
+static __always_inline int conn_key_of(struct sock *sk, struct conn_key *key)
+{
+	if (BPF_CORE_READ(sk, __sk_common.skc_family) != AF_INET)
+		return -1;
+
+	key->saddr = BPF_CORE_READ(sk, __sk_common.skc_rcv_saddr);
+	key->daddr = BPF_CORE_READ(sk, __sk_common.skc_daddr);
+	key->sport = BPF_CORE_READ(sk, __sk_common.skc_num);
+	key->dport = bpf_ntohs(BPF_CORE_READ(sk, __sk_common.skc_dport));
+	return 0;
+}
+
+static __always_inline void count(struct sock *sk, __u64 sent, __u64 received)
+{
+	struct conn_key key = {};
+	struct conn_bytes *bytes;
+
+	if (conn_key_of(sk, &key))
+		return;
+
+	bytes = bpf_map_lookup_elem(&CONN_BYTES, &key);
+	if (!bytes) {
+		struct conn_bytes zero = {};
+
+		bpf_map_update_elem(&CONN_BYTES, &key, &zero, BPF_NOEXIST);
+		bytes = bpf_map_lookup_elem(&CONN_BYTES, &key);
+		if (!bytes)
+			return;
+	}
+
+	__sync_fetch_and_add(&bytes->sent, sent);
+	__sync_fetch_and_add(&bytes->received, received);
+}
+
+SEC("kprobe/tcp_sendmsg")
+int BPF_KPROBE(tcp_sendmsg, struct sock *sk, struct msghdr *msg, size_t size)
+{
+	count(sk, size, 0);
+	return 0;
+}
+
+SEC("kprobe/tcp_cleanup_rbuf")
+int BPF_KPROBE(tcp_cleanup_rbuf, struct sock *sk, int copied)
+{
+	if (copied > 0)
+		count(sk, 0, copied);
+	return 0;
+}
+
+SEC("kprobe/tcp_close")
+int BPF_KPROBE(tcp_close, struct sock *sk)
+{
+	struct conn_key key = {};
+
+	if (!conn_key_of(sk, &key))
+		bpf_map_delete_elem(&CONN_BYTES, &key);
+	return 0;
+}
+
+char LICENSE[] SEC("license") = "GPL";
@@ -0,0 +1,347 @@
+//! Admin endpoint for inspecting and controlling a running telemetry system
+//!
+//! The endpoint is a small HTTP/1.1 API, bound to localhost by default:
+//!
+//! | Method | Path                         | Action                          |
+//! |--------|------------------------------|---------------------------------|
+//! | GET    | `/status`                    | [`TelemetryStatus`] as JSON     |
+//! | POST   | `/flush`                     | Collect and export metrics now  |
+//! | GET    | `/metrics`                   | Current metrics, Prometheus text|
+//! | PUT    | `/sampling?ratio=<0.0-1.0>`  | Change the trace sampling ratio |
+//! | PUT    | `/dashboard?enabled=<bool>`  | Start or stop the dashboard     |
+//!
+//! [`AdminClient`] is the matching client used by `vpn telemetry`.
+
+use crate::{error::Result, exporters::ExporterStatus, TelemetryError, TelemetrySystem};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+/// Runtime state of a telemetry system
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelemetryStatus {
+    pub service_name: String,
+    pub running: bool,
+    pub tracing_initialized: bool,
+    pub sampling_ratio: f32,
+    pub dashboard_enabled: bool,
+    pub dashboard_running: bool,
+    pub dashboard_url: Option<String>,
+    pub exporters: Vec<ExporterStatus>,
+}
+
+/// Admin HTTP server bound to a telemetry system
+pub struct AdminServer {
+    local_addr: SocketAddr,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl AdminServer {
+    /// Bind the admin endpoint and start serving `system`
+    pub async fn bind(system: Arc<TelemetrySystem>, bind_addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(bind_addr).await.map_err(|e| {
+            TelemetryError::InitializationFailed {
+                reason: format!("Failed to bind admin endpoint to {}: {}", bind_addr, e),
+            }
+        })?;
+        let local_addr = listener.local_addr()?;
+
+        info!("Telemetry admin endpoint listening on {}", local_addr);
+
+        let handle = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let system = system.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = Self::handle_connection(stream, &system).await {
+                                warn!("Error handling admin request: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Error accepting admin connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { local_addr, handle })
+    }
+
+    /// Address the endpoint is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop serving
+    pub fn shutdown(self) {
+        self.handle.abort();
+    }
+
+    async fn handle_connection(mut stream: TcpStream, system: &TelemetrySystem) -> Result<()> {
+        let (method, target) = read_request(&mut stream).await?;
+        let response = route(system, &method, &target).await;
+        stream.write_all(response.to_http().as_bytes()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Method and target of the request on `stream`
+pub(crate) async fn read_request(stream: &mut TcpStream) -> Result<(String, String)> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    // Requests carry their arguments in the query string, so the
+    // headers and any body can be skipped
+    let mut line = String::new();
+    while reader.read_line(&mut line).await? > 0 {
+        if line.trim().is_empty() {
+            break;
+        }
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("GET").to_string();
+    let target = parts.next().unwrap_or("/").to_string();
+    Ok((method, target))
+}
+
+/// Response before HTTP encoding
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) content_type: &'static str,
+    pub(crate) body: String,
+}
+
+impl Response {
+    pub(crate) fn json<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self {
+                status: 200,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Self::error(500, &e.to_string()),
+        }
+    }
+
+    fn text(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+
+    pub(crate) fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+
+    pub(crate) fn to_http(&self) -> String {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+async fn route(system: &TelemetrySystem, method: &str, target: &str) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    match (method, path) {
+        ("GET", "/status") => Response::json(&system.status().await),
+        ("GET", "/metrics") => match system.export_metrics().await {
+            Ok(metrics) => Response::text(metrics),
+            Err(e) => Response::error(500, &e.to_string()),
+        },
+        ("POST", "/flush") => match system.flush().await {
+            Ok(()) => Response::json(&system.status().await),
+            Err(e) => Response::error(500, &e.to_string()),
+        },
+        ("PUT", "/sampling") => {
+            let Some(ratio) = query_param(query, "ratio").and_then(|v| v.parse().ok()) else {
+                return Response::error(400, "Expected ?ratio=<0.0-1.0>");
+            };
+            match system.set_sampling_ratio(ratio).await {
+                Ok(()) => Response::json(&system.status().await),
+                Err(e) => Response::error(400, &e.to_string()),
+            }
+        }
+        ("PUT", "/dashboard") => {
+            let Some(enabled) = query_param(query, "enabled").and_then(|v| v.parse().ok()) else {
+                return Response::error(400, "Expected ?enabled=<true|false>");
+            };
+            match system.set_dashboard_enabled(enabled).await {
+                Ok(()) => Response::json(&system.status().await),
+                Err(e) => Response::error(500, &e.to_string()),
+            }
+        }
+        (_, "/status" | "/metrics" | "/flush" | "/sampling" | "/dashboard") => {
+            Response::error(405, &format!("{} not allowed on {}", method, path))
+        }
+        _ => Response::error(404, &format!("Unknown admin path {}", path)),
+    }
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Client for a telemetry admin endpoint
+pub struct AdminClient {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl AdminClient {
+    /// Create a client for the endpoint at `base_url`, e.g. `http://127.0.0.1:9464`
+    pub fn new(base_url: impl Into<String>) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+
+        Ok(Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+
+    /// Exporter, tracing and dashboard state
+    pub async fn status(&self) -> Result<TelemetryStatus> {
+        let response = self.send(reqwest::Method::GET, "/status").await?;
+        Ok(response.json().await?)
+    }
+
+    /// Force metrics collection and export
+    pub async fn flush(&self) -> Result<TelemetryStatus> {
+        let response = self.send(reqwest::Method::POST, "/flush").await?;
+        Ok(response.json().await?)
+    }
+
+    /// Current metric values in Prometheus text format
+    pub async fn metrics(&self) -> Result<String> {
+        let response = self.send(reqwest::Method::GET, "/metrics").await?;
+        Ok(response.text().await?)
+    }
+
+    /// Change the trace sampling ratio
+    pub async fn set_sampling_ratio(&self, ratio: f32) -> Result<TelemetryStatus> {
+        let path = format!("/sampling?ratio={}", ratio);
+        let response = self.send(reqwest::Method::PUT, &path).await?;
+        Ok(response.json().await?)
+    }
+
+    /// Start or stop the dashboard
+    pub async fn set_dashboard_enabled(&self, enabled: bool) -> Result<TelemetryStatus> {
+        let path = format!("/dashboard?enabled={}", enabled);
+        let response = self.send(reqwest::Method::PUT, &path).await?;
+        Ok(response.json().await?)
+    }
+
+    async fn send(&self, method: reqwest::Method, path: &str) -> Result<reqwest::Response> {
+        let response = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path))
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let status = response.status();
+        let message = response
+            .json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|body| body["error"].as_str().map(str::to_string))
+            .unwrap_or_else(|| status.to_string());
+
+        Err(TelemetryError::OperationFailed {
+            operation: format!("admin {}", path),
+            message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TelemetryConfig;
+
+    async fn test_system() -> Arc<TelemetrySystem> {
+        let mut config = TelemetryConfig::default();
+        // Tracing installs a global subscriber and the dashboard binds a
+        // fixed port; neither is needed here
+        config.tracing.enabled = false;
+        config.dashboard_enabled = false;
+        Arc::new(TelemetrySystem::new(config).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_route_controls_system() {
+        let system = test_system().await;
+
+        let response = route(&system, "PUT", "/sampling?ratio=0.25").await;
+        assert_eq!(response.status, 200);
+        assert_eq!(system.sampling_ratio().await, 0.25);
+
+        let response = route(&system, "PUT", "/sampling?ratio=2").await;
+        assert_eq!(response.status, 400);
+        assert_eq!(system.sampling_ratio().await, 0.25);
+
+        assert_eq!(route(&system, "GET", "/sampling").await.status, 405);
+        assert_eq!(route(&system, "GET", "/nope").await.status, 404);
+        assert_eq!(route(&system, "POST", "/flush").await.status, 200);
+        assert_eq!(route(&system, "GET", "/metrics").await.status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_admin_client_roundtrip() {
+        let system = test_system().await;
+        let server = AdminServer::bind(system.clone(), "127.0.0.1:0")
+            .await
+            .unwrap();
+        let client = AdminClient::new(format!("http://{}", server.local_addr())).unwrap();
+
+        let status = client.status().await.unwrap();
+        assert_eq!(status.service_name, "vpn-system");
+        assert!(!status.dashboard_enabled);
+
+        let status = client.set_sampling_ratio(0.5).await.unwrap();
+        assert_eq!(status.sampling_ratio, 0.5);
+
+        let error = client.set_sampling_ratio(-1.0).await.unwrap_err();
+        assert!(error.to_string().contains("between 0.0 and 1.0"));
+
+        server.shutdown();
+    }
+}
@@ -0,0 +1,1278 @@
+//! Continuous alert rule evaluation
+//!
+//! [`AlertEngine`] samples [`VpnMetrics`] and, when given a
+//! [`NodeStatusSource`], the cluster's node states every
+//! `evaluation_interval`, and evaluates each [`AlertRule`] against them:
+//!
+//! - threshold rules compare a metric to a value, e.g. active connections
+//!   above 1000;
+//! - rate rules compare how fast a counter grows over a window, e.g. more
+//!   than 10 network errors per second over 5 minutes;
+//! - error rate rules compare the share of failed connections over a
+//!   window, e.g. above 5% over 5 minutes;
+//! - node down rules watch cluster nodes that failed or are unavailable;
+//! - burn rate rules watch how fast a service level objective spends its
+//!   error budget, firing only while both a long and a short window burn
+//!   too fast, so they page quickly yet stop soon after a recovery.
+//!
+//! A rule fires once its condition has held for the rule's `for` duration
+//! and resolves as soon as it stops holding. Both transitions are sent to
+//! every notifier: a webhook, email over SMTP and Telegram. Rules keep
+//! being evaluated during a planned maintenance window, but nothing is
+//! sent.
+
+use crate::billing::WebhookConfig;
+use crate::config::AlertingConfig;
+use crate::error::Result;
+use crate::exporters::AuthenticationType;
+use crate::metrics::{MetricsCollector, VpnMetrics};
+use crate::slo::{window_label, SloTracker};
+use crate::TelemetryError;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+use vpn_cluster::{ClusterManager, NodeStatus};
+use vpn_server::MaintenanceSchedule;
+
+/// How urgent an alert is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSeverity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for AlertSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// A value read from [`VpnMetrics`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    ActiveUsers,
+    ActiveConnections,
+    /// Bytes per second
+    Bandwidth,
+    TotalDataTransferred,
+    SuccessfulConnections,
+    FailedConnections,
+    NetworkErrors,
+    CpuPercent,
+    MemoryPercent,
+    DiskPercent,
+    LoadAverage,
+    FailedContainers,
+    ContainerRestarts,
+    /// A metric recorded with `record_metric`
+    Custom(String),
+}
+
+impl AlertMetric {
+    pub fn value(&self, metrics: &VpnMetrics) -> Option<f64> {
+        let percent =
+            |used: u64, total: u64| (total > 0).then(|| used as f64 / total as f64 * 100.0);
+
+        match self {
+            Self::ActiveUsers => Some(metrics.active_users as f64),
+            Self::ActiveConnections => Some(metrics.active_connections as f64),
+            Self::Bandwidth => Some(metrics.current_bandwidth),
+            Self::TotalDataTransferred => Some(metrics.total_data_transferred as f64),
+            Self::SuccessfulConnections => Some(metrics.server.successful_connections as f64),
+            Self::FailedConnections => Some(metrics.server.failed_connections as f64),
+            Self::NetworkErrors => Some(metrics.server.network_errors as f64),
+            Self::CpuPercent => Some(metrics.system.cpu_percent),
+            Self::MemoryPercent => {
+                percent(metrics.system.memory_usage, metrics.system.memory_total)
+            }
+            Self::DiskPercent => percent(metrics.system.disk_usage, metrics.system.disk_total),
+            Self::LoadAverage => Some(metrics.system.load_avg_1m),
+            Self::FailedContainers => Some(metrics.containers.failed as f64),
+            Self::ContainerRestarts => Some(metrics.containers.restart_count as f64),
+            Self::Custom(name) => metrics.custom.get(name).copied(),
+        }
+    }
+}
+
+impl fmt::Display for AlertMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Custom(name) => write!(f, "{}", name),
+            other => {
+                let name = serde_json::to_value(other)
+                    .ok()
+                    .and_then(|value| value.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                write!(f, "{}", name.replace('_', " "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+impl Comparison {
+    fn holds(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Above => value > threshold,
+            Self::Below => value < threshold,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Self::Above => ">",
+            Self::Below => "<",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// The current value of `metric` compared to `value`
+    Threshold {
+        metric: AlertMetric,
+        comparison: Comparison,
+        value: f64,
+    },
+    /// The growth of counter `metric` per second over `window`
+    Rate {
+        metric: AlertMetric,
+        comparison: Comparison,
+        per_second: f64,
+        window: Duration,
+    },
+    /// Failed connections as a percentage of all connections over `window`
+    ErrorRate {
+        above_percent: f64,
+        window: Duration,
+    },
+    /// A cluster node, or the named one, failed or is unavailable
+    NodeDown {
+        #[serde(default)]
+        node: Option<String>,
+    },
+    /// A service level objective, or the named one, spends its error
+    /// budget more than `above` times faster than sustainable over both
+    /// `long_window` and `short_window`
+    BurnRate {
+        #[serde(default)]
+        slo: Option<String>,
+        above: f64,
+        long_window: Duration,
+        short_window: Duration,
+    },
+}
+
+impl AlertCondition {
+    /// Longest history the condition looks at
+    fn window(&self) -> Duration {
+        match self {
+            Self::Rate { window, .. } | Self::ErrorRate { window, .. } => *window,
+            Self::Threshold { .. } | Self::NodeDown { .. } | Self::BurnRate { .. } => {
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    /// Unique name, e.g. `high-error-rate`
+    pub name: String,
+    #[serde(default)]
+    pub severity: AlertSeverity,
+    pub condition: AlertCondition,
+    /// How long the condition must hold before the alert fires
+    #[serde(default, rename = "for")]
+    pub for_duration: Duration,
+}
+
+impl AlertRule {
+    /// Rules enabled when the configuration names none
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self {
+                name: "high-error-rate".to_string(),
+                severity: AlertSeverity::Critical,
+                condition: AlertCondition::ErrorRate {
+                    above_percent: 5.0,
+                    window: Duration::from_secs(300),
+                },
+                for_duration: Duration::ZERO,
+            },
+            Self {
+                name: "node-down".to_string(),
+                severity: AlertSeverity::Critical,
+                condition: AlertCondition::NodeDown { node: None },
+                for_duration: Duration::from_secs(60),
+            },
+            Self {
+                name: "high-cpu".to_string(),
+                severity: AlertSeverity::Warning,
+                condition: AlertCondition::Threshold {
+                    metric: AlertMetric::CpuPercent,
+                    comparison: Comparison::Above,
+                    value: 90.0,
+                },
+                for_duration: Duration::from_secs(600),
+            },
+            // Spending 2% of a 30 day budget in an hour
+            Self {
+                name: "slo-fast-burn".to_string(),
+                severity: AlertSeverity::Critical,
+                condition: AlertCondition::BurnRate {
+                    slo: None,
+                    above: 14.4,
+                    long_window: Duration::from_secs(3600),
+                    short_window: Duration::from_secs(300),
+                },
+                for_duration: Duration::ZERO,
+            },
+            // Spending 5% of a 30 day budget in six hours
+            Self {
+                name: "slo-slow-burn".to_string(),
+                severity: AlertSeverity::Warning,
+                condition: AlertCondition::BurnRate {
+                    slo: None,
+                    above: 6.0,
+                    long_window: Duration::from_secs(6 * 3600),
+                    short_window: Duration::from_secs(1800),
+                },
+                for_duration: Duration::ZERO,
+            },
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+/// A rule starting or stopping to fire, as sent to notifiers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertEvent {
+    pub rule: String,
+    pub severity: AlertSeverity,
+    pub state: AlertState,
+    /// Node or SLO the alert is about, for node and burn rate rules
+    pub subject: Option<String>,
+    /// Observed value when the state changed
+    pub value: Option<f64>,
+    pub summary: String,
+    /// Service that raised the alert
+    pub source: String,
+    pub at: DateTime<Utc>,
+}
+
+impl AlertEvent {
+    /// One-line text for email subjects and chat messages
+    pub fn title(&self) -> String {
+        let state = match self.state {
+            AlertState::Firing => "FIRING",
+            AlertState::Resolved => "RESOLVED",
+        };
+        format!(
+            "[{}] {} ({}) on {}",
+            state, self.rule, self.severity, self.source
+        )
+    }
+
+    pub fn text(&self) -> String {
+        format!(
+            "{}\n{}\nAt: {}",
+            self.title(),
+            self.summary,
+            self.at.to_rfc3339()
+        )
+    }
+}
+
+/// Metrics to evaluate rules against
+#[async_trait]
+pub trait MetricsSource: Send + Sync {
+    async fn current_metrics(&self) -> Result<VpnMetrics>;
+}
+
+#[async_trait]
+impl MetricsSource for RwLock<MetricsCollector> {
+    async fn current_metrics(&self) -> Result<VpnMetrics> {
+        self.read().await.get_current_metrics().await
+    }
+}
+
+/// A cluster node as node rules see it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeState {
+    pub id: String,
+    pub name: String,
+    pub status: NodeStatus,
+}
+
+impl NodeState {
+    fn is_down(&self) -> bool {
+        matches!(self.status, NodeStatus::Failed | NodeStatus::Unavailable)
+    }
+}
+
+/// Node states for node rules
+#[async_trait]
+pub trait NodeStatusSource: Send + Sync {
+    async fn node_states(&self) -> Result<Vec<NodeState>>;
+}
+
+#[async_trait]
+impl NodeStatusSource for ClusterManager {
+    async fn node_states(&self) -> Result<Vec<NodeState>> {
+        let nodes =
+            self.get_cluster_nodes()
+                .await
+                .map_err(|e| TelemetryError::OperationFailed {
+                    operation: "get_cluster_nodes".to_string(),
+                    message: e.to_string(),
+                })?;
+
+        Ok(nodes
+            .into_iter()
+            .map(|node| NodeState {
+                id: node.id.to_string(),
+                name: node.name,
+                status: node.status,
+            })
+            .collect())
+    }
+}
+
+/// Destination of alert events
+#[async_trait]
+pub trait AlertNotifier: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn notify(&self, event: &AlertEvent) -> Result<()>;
+}
+
+/// POSTs events as JSON
+pub struct WebhookNotifier {
+    config: WebhookConfig,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: "alert-webhook".to_string(),
+                message: format!("Failed to create HTTP client: {}", e),
+            })?;
+
+        Ok(Self { config, client })
+    }
+}
+
+#[async_trait]
+impl AlertNotifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "alert-webhook"
+    }
+
+    async fn notify(&self, event: &AlertEvent) -> Result<()> {
+        let mut request = self.client.post(&self.config.url).json(event);
+
+        for (key, value) in &self.config.headers {
+            request = request.header(key, value);
+        }
+
+        if let Some(auth) = &self.config.authentication {
+            match &auth.auth_type {
+                AuthenticationType::Basic => {
+                    if let (Some(username), Some(password)) = (&auth.username, &auth.password) {
+                        request = request.basic_auth(username, Some(password));
+                    }
+                }
+                AuthenticationType::Bearer => {
+                    if let Some(token) = &auth.token {
+                        request = request.bearer_auth(token);
+                    }
+                }
+                AuthenticationType::ApiKey => {
+                    if let Some(api_key) = &auth.api_key {
+                        request = request.header("X-API-Key", api_key);
+                    }
+                }
+                AuthenticationType::None => {}
+            }
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP request failed: {}", e),
+            })?;
+
+        if !response.status().is_success() {
+            return Err(TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP error: {}", response.status()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// How the SMTP connection is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection, e.g. to a local relay
+    None,
+    /// Upgrade with STARTTLS, usually on port 587
+    #[default]
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+}
+
+/// SMTP server sending alert emails
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// e.g. `VPN Alerts <alerts@example.com>`
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default = "default_notifier_timeout")]
+    pub timeout: Duration,
+}
+
+/// Telegram bot posting alerts to a chat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    /// Chat ID or `@channel` name
+    pub chat_id: String,
+    #[serde(default = "default_telegram_api")]
+    pub api_url: String,
+    #[serde(default = "default_notifier_timeout")]
+    pub timeout: Duration,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_telegram_api() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+fn default_notifier_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Sends one plain-text email per event
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl EmailNotifier {
+    pub fn new(config: SmtpConfig) -> Result<Self> {
+        let invalid = |message: String| TelemetryError::ConfigError { message };
+
+        let builder = match config.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            }
+            SmtpSecurity::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                    .map_err(|e| invalid(format!("Invalid SMTP host {}: {}", config.host, e)))?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+                .map_err(|e| invalid(format!("Invalid SMTP host {}: {}", config.host, e)))?,
+        };
+        let mut builder = builder.port(config.port).timeout(Some(config.timeout));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        let from = config
+            .from
+            .parse::<Mailbox>()
+            .map_err(|e| invalid(format!("Invalid sender {}: {}", config.from, e)))?;
+        let to = config
+            .to
+            .iter()
+            .map(|address| {
+                address
+                    .parse::<Mailbox>()
+                    .map_err(|e| invalid(format!("Invalid recipient {}: {}", address, e)))
+            })
+            .collect::<Result<Vec<Mailbox>>>()?;
+        if to.is_empty() {
+            return Err(invalid(
+                "Alert email needs at least one recipient".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+            to,
+        })
+    }
+}
+
+#[async_trait]
+impl AlertNotifier for EmailNotifier {
+    fn name(&self) -> &str {
+        "alert-email"
+    }
+
+    async fn notify(&self, event: &AlertEvent) -> Result<()> {
+        let failed = |message: String| TelemetryError::ExportError {
+            exporter: self.name().to_string(),
+            message,
+        };
+
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(event.title());
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .header(ContentType::TEXT_PLAIN)
+            .body(event.text())
+            .map_err(|e| failed(format!("Failed to build email: {}", e)))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| failed(format!("SMTP delivery failed: {}", e)))?;
+        Ok(())
+    }
+}
+
+/// Posts events through the Bot API's `sendMessage`
+pub struct TelegramNotifier {
+    config: TelegramConfig,
+    client: reqwest::Client,
+}
+
+impl TelegramNotifier {
+    pub fn new(config: TelegramConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: "alert-telegram".to_string(),
+                message: format!("Failed to create HTTP client: {}", e),
+            })?;
+
+        Ok(Self { config, client })
+    }
+}
+
+#[async_trait]
+impl AlertNotifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "alert-telegram"
+    }
+
+    async fn notify(&self, event: &AlertEvent) -> Result<()> {
+        let url = format!(
+            "{}/bot{}/sendMessage",
+            self.config.api_url.trim_end_matches('/'),
+            self.config.bot_token
+        );
+
+        // The URL holds the bot token, so errors must not include it
+        let response = self
+            .client
+            .post(&url)
+            .json(&serde_json::json!({
+                "chat_id": self.config.chat_id,
+                "text": event.text(),
+                "disable_web_page_preview": true,
+            }))
+            .send()
+            .await
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP request failed: {}", e.without_url()),
+            })?;
+
+        if !response.status().is_success() {
+            return Err(TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP error: {}", response.status()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Where a rule stands for one subject
+#[derive(Debug, Clone, Copy)]
+struct RuleProgress {
+    /// When the condition started to hold
+    since: DateTime<Utc>,
+    firing: bool,
+}
+
+/// Evaluates alert rules on an interval and notifies on state changes
+pub struct AlertEngine {
+    config: AlertingConfig,
+    source: String,
+    metrics: Arc<dyn MetricsSource>,
+    nodes: Option<Arc<dyn NodeStatusSource>>,
+    slos: Option<Arc<SloTracker>>,
+    notifiers: Vec<Box<dyn AlertNotifier>>,
+    samples: Mutex<VecDeque<(DateTime<Utc>, VpnMetrics)>>,
+    /// Keyed by rule name and subject
+    progress: Mutex<HashMap<(String, String), RuleProgress>>,
+    running: Arc<RwLock<bool>>,
+}
+
+impl AlertEngine {
+    /// Engine with the notifiers `config` describes; `source` names this
+    /// service in the alerts
+    pub fn new(
+        config: AlertingConfig,
+        source: String,
+        metrics: Arc<dyn MetricsSource>,
+    ) -> Result<Self> {
+        let mut notifiers: Vec<Box<dyn AlertNotifier>> = Vec::new();
+        if let Some(webhook) = &config.webhook {
+            notifiers.push(Box::new(WebhookNotifier::new(webhook.clone())?));
+        }
+        if let Some(email) = &config.email {
+            notifiers.push(Box::new(EmailNotifier::new(email.clone())?));
+        }
+        if let Some(telegram) = &config.telegram {
+            notifiers.push(Box::new(TelegramNotifier::new(telegram.clone())?));
+        }
+
+        Ok(Self {
+            config,
+            source,
+            metrics,
+            nodes: None,
+            slos: None,
+            notifiers,
+            samples: Mutex::new(VecDeque::new()),
+            progress: Mutex::new(HashMap::new()),
+            running: Arc::new(RwLock::new(false)),
+        })
+    }
+
+    /// Evaluate node rules against `nodes`
+    pub fn with_nodes(mut self, nodes: Arc<dyn NodeStatusSource>) -> Self {
+        self.nodes = Some(nodes);
+        self
+    }
+
+    /// Evaluate burn rate rules against the objectives `slos` tracks
+    pub fn with_slos(mut self, slos: Arc<SloTracker>) -> Self {
+        self.slos = Some(slos);
+        self
+    }
+
+    /// Add a notifier besides the configured ones
+    pub fn with_notifier(mut self, notifier: Box<dyn AlertNotifier>) -> Self {
+        self.notifiers.push(notifier);
+        self
+    }
+
+    pub fn rules(&self) -> &[AlertRule] {
+        &self.config.rules
+    }
+
+    /// Names of the rules firing now, with their subjects
+    pub async fn firing(&self) -> Vec<(String, Option<String>)> {
+        let progress = self.progress.lock().await;
+        let mut firing: Vec<_> = progress
+            .iter()
+            .filter(|(_, progress)| progress.firing)
+            .map(|((rule, subject), _)| {
+                (
+                    rule.clone(),
+                    Some(subject.clone()).filter(|s| !s.is_empty()),
+                )
+            })
+            .collect();
+        firing.sort();
+        firing
+    }
+
+    /// Evaluate every rule against `metrics` and `nodes` observed at `at`,
+    /// returning the alerts that started or stopped firing
+    pub async fn evaluate(
+        &self,
+        at: DateTime<Utc>,
+        metrics: VpnMetrics,
+        nodes: &[NodeState],
+    ) -> Vec<AlertEvent> {
+        let mut samples = self.samples.lock().await;
+        samples.push_back((at, metrics));
+
+        // Keep one sample at or before the longest window, to compute
+        // rates across the whole window
+        let horizon = self
+            .config
+            .rules
+            .iter()
+            .map(|rule| rule.condition.window())
+            .max()
+            .unwrap_or_default();
+        let horizon =
+            at - ChronoDuration::from_std(horizon).unwrap_or_else(|_| ChronoDuration::zero());
+        while samples.len() > 1 && samples[1].0 <= horizon {
+            samples.pop_front();
+        }
+
+        let mut progress = self.progress.lock().await;
+        let mut events = Vec::new();
+
+        for rule in &self.config.rules {
+            let holding =
+                holding_subjects(&rule.condition, at, &samples, nodes, self.slos.as_deref());
+            let hold_for = ChronoDuration::from_std(rule.for_duration)
+                .unwrap_or_else(|_| ChronoDuration::zero());
+
+            for (subject, value, summary) in &holding {
+                let key = (rule.name.clone(), subject.clone());
+                let entry = progress.entry(key).or_insert(RuleProgress {
+                    since: at,
+                    firing: false,
+                });
+                if !entry.firing && at - entry.since >= hold_for {
+                    entry.firing = true;
+                    events.push(self.event(rule, AlertState::Firing, subject, *value, summary, at));
+                }
+            }
+
+            let stopped: Vec<(String, String)> = progress
+                .keys()
+                .filter(|(name, subject)| {
+                    *name == rule.name && !holding.iter().any(|(held, _, _)| held == subject)
+                })
+                .cloned()
+                .collect();
+            for key in stopped {
+                let Some(previous) = progress.remove(&key) else {
+                    continue;
+                };
+                if previous.firing {
+                    let summary = match key.1.as_str() {
+                        "" => "Condition no longer holds".to_string(),
+                        subject => format!("{} recovered", subject),
+                    };
+                    events.push(self.event(rule, AlertState::Resolved, &key.1, None, &summary, at));
+                }
+            }
+        }
+
+        events
+    }
+
+    fn event(
+        &self,
+        rule: &AlertRule,
+        state: AlertState,
+        subject: &str,
+        value: Option<f64>,
+        summary: &str,
+        at: DateTime<Utc>,
+    ) -> AlertEvent {
+        AlertEvent {
+            rule: rule.name.clone(),
+            severity: rule.severity,
+            state,
+            subject: Some(subject.to_string()).filter(|s| !s.is_empty()),
+            value,
+            summary: summary.to_string(),
+            source: self.source.clone(),
+            at,
+        }
+    }
+
+    /// Send `event` to every notifier; failures are logged, not retried
+    pub async fn notify(&self, event: &AlertEvent) {
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.notify(event).await {
+                warn!(
+                    "Failed to send alert {} to {}: {}",
+                    event.rule,
+                    notifier.name(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Sample the sources, evaluate and notify, as the background task does
+    pub async fn run_once(&self) -> Result<Vec<AlertEvent>> {
+        let metrics = self.metrics.current_metrics().await?;
+        let nodes = match &self.nodes {
+            Some(source) => source.node_states().await?,
+            None => Vec::new(),
+        };
+
+        let now = Utc::now();
+        let events = self.evaluate(now, metrics, &nodes).await;
+        if let Some(install_path) = &self.config.maintenance_path {
+            if !events.is_empty() && MaintenanceSchedule::in_maintenance(install_path, now) {
+                info!(
+                    "Holding back {} alert notification(s) during maintenance",
+                    events.len()
+                );
+                return Ok(events);
+            }
+        }
+
+        for event in &events {
+            info!("{}", event.title());
+            self.notify(event).await;
+        }
+        Ok(events)
+    }
+
+    /// Run [`run_once`](Self::run_once) every `evaluation_interval` until
+    /// stopped
+    pub async fn start(self: Arc<Self>) -> Result<()> {
+        let mut running = self.running.write().await;
+        if *running {
+            return Ok(());
+        }
+        *running = true;
+        info!(
+            "Started alert evaluation ({} rules)",
+            self.config.rules.len()
+        );
+
+        let engine = self.clone();
+        tokio::spawn(async move {
+            let mut interval_timer = tokio::time::interval(engine.config.evaluation_interval);
+
+            loop {
+                interval_timer.tick().await;
+
+                if !*engine.running.read().await {
+                    break;
+                }
+
+                if let Err(e) = engine.run_once().await {
+                    warn!("Alert evaluation failed: {}", e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        *self.running.write().await = false;
+        info!("Stopped alert evaluation");
+        Ok(())
+    }
+}
+
+/// Subjects `condition` holds for, with the observed value and a summary;
+/// metric rules have the single subject `""`
+fn holding_subjects(
+    condition: &AlertCondition,
+    at: DateTime<Utc>,
+    samples: &VecDeque<(DateTime<Utc>, VpnMetrics)>,
+    nodes: &[NodeState],
+    slos: Option<&SloTracker>,
+) -> Vec<(String, Option<f64>, String)> {
+    let Some((_, latest)) = samples.back() else {
+        return Vec::new();
+    };
+
+    // Oldest sample within `window`, and the seconds since it
+    let window_start = |window: &Duration| {
+        let start =
+            at - ChronoDuration::from_std(*window).unwrap_or_else(|_| ChronoDuration::zero());
+        samples
+            .iter()
+            .find(|(sampled, _)| *sampled >= start)
+            .filter(|(sampled, _)| *sampled < at)
+            .map(|(sampled, metrics)| (metrics, (at - *sampled).num_milliseconds() as f64 / 1000.0))
+    };
+    // Counters going backwards were reset: no rate until the window refills
+    let increase = |metric: &AlertMetric, earlier: &VpnMetrics| {
+        let delta = metric.value(latest)? - metric.value(earlier)?;
+        (delta >= 0.0).then_some(delta)
+    };
+
+    match condition {
+        AlertCondition::Threshold {
+            metric,
+            comparison,
+            value,
+        } => metric
+            .value(latest)
+            .filter(|current| comparison.holds(*current, *value))
+            .map(|current| {
+                vec![(
+                    String::new(),
+                    Some(current),
+                    format!(
+                        "{} is {:.2} ({} {})",
+                        metric,
+                        current,
+                        comparison.symbol(),
+                        value
+                    ),
+                )]
+            })
+            .unwrap_or_default(),
+        AlertCondition::Rate {
+            metric,
+            comparison,
+            per_second,
+            window,
+        } => window_start(window)
+            .and_then(|(earlier, seconds)| Some(increase(metric, earlier)? / seconds))
+            .filter(|rate| comparison.holds(*rate, *per_second))
+            .map(|rate| {
+                vec![(
+                    String::new(),
+                    Some(rate),
+                    format!(
+                        "{} grows {:.2}/s over {}s ({} {})",
+                        metric,
+                        rate,
+                        window.as_secs(),
+                        comparison.symbol(),
+                        per_second
+                    ),
+                )]
+            })
+            .unwrap_or_default(),
+        AlertCondition::ErrorRate {
+            above_percent,
+            window,
+        } => window_start(window)
+            .and_then(|(earlier, _)| {
+                let failed = increase(&AlertMetric::FailedConnections, earlier)?;
+                let succeeded = increase(&AlertMetric::SuccessfulConnections, earlier)?;
+                let total = failed + succeeded;
+                (total > 0.0).then(|| failed / total * 100.0)
+            })
+            .filter(|percent| percent > above_percent)
+            .map(|percent| {
+                vec![(
+                    String::new(),
+                    Some(percent),
+                    format!(
+                        "Connection error rate is {:.2}% over {}s (> {}%)",
+                        percent,
+                        window.as_secs(),
+                        above_percent
+                    ),
+                )]
+            })
+            .unwrap_or_default(),
+        AlertCondition::NodeDown { node } => nodes
+            .iter()
+            .filter(|state| state.is_down())
+            .filter(|state| match node {
+                Some(wanted) => *wanted == state.name || *wanted == state.id,
+                None => true,
+            })
+            .map(|state| {
+                (
+                    state.name.clone(),
+                    None,
+                    format!("Node {} ({}) is {:?}", state.name, state.id, state.status),
+                )
+            })
+            .collect(),
+        AlertCondition::BurnRate {
+            slo,
+            above,
+            long_window,
+            short_window,
+        } => {
+            let Some(slos) = slos else {
+                return Vec::new();
+            };
+            slos.objectives()
+                .iter()
+                .filter(|objective| match slo {
+                    Some(wanted) => *wanted == objective.name,
+                    None => true,
+                })
+                .filter_map(|objective| {
+                    let long = slos.burn_rate(&objective.name, *long_window, at)?;
+                    let short = slos.burn_rate(&objective.name, *short_window, at)?;
+                    (long > *above && short > *above).then(|| {
+                        (
+                            objective.name.clone(),
+                            Some(long),
+                            format!(
+                                "SLO {} burns {:.1}x its budget over {} and {:.1}x over {} (> {})",
+                                objective.name,
+                                long,
+                                window_label(*long_window),
+                                short,
+                                window_label(*short_window),
+                                above
+                            ),
+                        )
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TelemetryConfig;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+            + ChronoDuration::minutes(minutes)
+    }
+
+    async fn metrics(succeeded: u64, failed: u64, connections: u64) -> VpnMetrics {
+        let collector = MetricsCollector::new(&TelemetryConfig::default())
+            .await
+            .unwrap();
+        let mut metrics = collector.get_current_metrics().await.unwrap();
+        metrics.server.successful_connections = succeeded;
+        metrics.server.failed_connections = failed;
+        metrics.active_connections = connections;
+        metrics
+    }
+
+    async fn engine(rules: Vec<AlertRule>) -> AlertEngine {
+        let config = AlertingConfig {
+            enabled: true,
+            rules,
+            ..AlertingConfig::default()
+        };
+        let collector = MetricsCollector::new(&TelemetryConfig::default())
+            .await
+            .unwrap();
+        AlertEngine::new(config, "test".to_string(), Arc::new(RwLock::new(collector))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_error_rate_fires_and_resolves() {
+        let engine = engine(AlertRule::defaults()[..1].to_vec()).await;
+
+        assert!(engine
+            .evaluate(at(0), metrics(0, 0, 0).await, &[])
+            .await
+            .is_empty());
+        // 4% of the connections in the last five minutes failed
+        assert!(engine
+            .evaluate(at(1), metrics(96, 4, 0).await, &[])
+            .await
+            .is_empty());
+
+        let events = engine.evaluate(at(2), metrics(100, 10, 0).await, &[]).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Firing);
+        assert_eq!(events[0].rule, "high-error-rate");
+        assert!((events[0].value.unwrap() - 100.0 / 11.0).abs() < 1e-9);
+
+        // Still firing: no repeat notification
+        assert!(engine
+            .evaluate(at(3), metrics(110, 10, 0).await, &[])
+            .await
+            .is_empty());
+
+        // By minute seven the window starts after the failures
+        let events = engine
+            .evaluate(at(7), metrics(1000, 10, 0).await, &[])
+            .await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Resolved);
+        assert!(engine.firing().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_threshold_waits_for_duration() {
+        let engine = engine(vec![AlertRule {
+            name: "too-many-connections".to_string(),
+            severity: AlertSeverity::Warning,
+            condition: AlertCondition::Threshold {
+                metric: AlertMetric::ActiveConnections,
+                comparison: Comparison::Above,
+                value: 100.0,
+            },
+            for_duration: Duration::from_secs(120),
+        }])
+        .await;
+
+        assert!(engine
+            .evaluate(at(0), metrics(0, 0, 150).await, &[])
+            .await
+            .is_empty());
+        assert!(engine
+            .evaluate(at(1), metrics(0, 0, 150).await, &[])
+            .await
+            .is_empty());
+        // Dipping below restarts the clock
+        assert!(engine
+            .evaluate(at(2), metrics(0, 0, 50).await, &[])
+            .await
+            .is_empty());
+        assert!(engine
+            .evaluate(at(3), metrics(0, 0, 150).await, &[])
+            .await
+            .is_empty());
+
+        let events = engine.evaluate(at(5), metrics(0, 0, 150).await, &[]).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, Some(150.0));
+    }
+
+    #[tokio::test]
+    async fn test_node_down_per_node() {
+        let mut rule = AlertRule::defaults()[1].clone();
+        rule.for_duration = Duration::ZERO;
+        let engine = engine(vec![rule]).await;
+
+        let node = |name: &str, status: NodeStatus| NodeState {
+            id: format!("id-{}", name),
+            name: name.to_string(),
+            status,
+        };
+
+        let nodes = [
+            node("a", NodeStatus::Failed),
+            node("b", NodeStatus::Healthy),
+            node("c", NodeStatus::Unavailable),
+        ];
+        let events = engine.evaluate(at(0), metrics(0, 0, 0).await, &nodes).await;
+        let subjects: Vec<_> = events.iter().filter_map(|e| e.subject.clone()).collect();
+        assert_eq!(subjects, vec!["a", "c"]);
+
+        let nodes = [
+            node("a", NodeStatus::Healthy),
+            node("b", NodeStatus::Healthy),
+            node("c", NodeStatus::Unavailable),
+        ];
+        let events = engine.evaluate(at(1), metrics(0, 0, 0).await, &nodes).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Resolved);
+        assert_eq!(events[0].subject.as_deref(), Some("a"));
+        assert_eq!(
+            engine.firing().await,
+            vec![("node-down".to_string(), Some("c".to_string()))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_burn_rate_needs_both_windows() {
+        let slos = Arc::new(SloTracker::new(TelemetryConfig::default().slo.objectives));
+        let rule = AlertRule::defaults()
+            .into_iter()
+            .find(|rule| rule.name == "slo-fast-burn")
+            .unwrap();
+        let engine = engine(vec![rule]).await.with_slos(slos.clone());
+
+        // Half an hour ago 9% of connects failed, nothing since
+        for i in 0..1100 {
+            slos.record_at(at(-30), "proxy_connect", i >= 100, None);
+        }
+        assert!(engine
+            .evaluate(at(0), metrics(0, 0, 0).await, &[])
+            .await
+            .is_empty());
+
+        // Failing again now burns both windows
+        for i in 0..12 {
+            slos.record_at(at(0), "proxy_connect", i >= 2, None);
+        }
+        let events = engine.evaluate(at(0), metrics(0, 0, 0).await, &[]).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Firing);
+        assert_eq!(events[0].subject.as_deref(), Some("proxy-availability"));
+        assert!(events[0].value.unwrap() > 14.4);
+
+        // The short window recovers first
+        for _ in 0..100 {
+            slos.record_at(at(10), "proxy_connect", true, None);
+        }
+        let events = engine.evaluate(at(10), metrics(0, 0, 0).await, &[]).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Resolved);
+    }
+
+    #[test]
+    fn test_rules_from_toml() {
+        let rules: HashMap<String, Vec<AlertRule>> = toml::from_str(
+            r#"
+            [[rules]]
+            name = "error-burst"
+            severity = "critical"
+            for = { secs = 60, nanos = 0 }
+            condition = { type = "rate", metric = "network_errors", comparison = "above", per_second = 10.0, window = { secs = 300, nanos = 0 } }
+            "#,
+        )
+        .unwrap();
+
+        let rule = &rules["rules"][0];
+        assert_eq!(rule.for_duration, Duration::from_secs(60));
+        assert_eq!(
+            rule.condition,
+            AlertCondition::Rate {
+                metric: AlertMetric::NetworkErrors,
+                comparison: Comparison::Above,
+                per_second: 10.0,
+                window: Duration::from_secs(300),
+            }
+        );
+    }
+}
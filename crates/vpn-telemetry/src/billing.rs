@@ -0,0 +1,1033 @@
+//! Per-user data usage export for billing systems
+//!
+//! [`BillingExporter`] samples cumulative per-user traffic counters from a
+//! [`UsageSource`] (usually the cluster's [`TrafficAggregator`]), turns them
+//! into usage per billing period and, once a period is over, pushes a
+//! [`UsageReport`] as CSV or JSON to a webhook and/or an S3 bucket.
+//!
+//! Closing a period is idempotent: each period is closed once, its report
+//! gets revision 1 and a delivery key derived from the period and revision,
+//! so re-sending after a crash or a failed delivery overwrites the same S3
+//! object and carries the same `Idempotency-Key` to the webhook.
+//!
+//! A period is closed only after `grace_period` has passed since its end.
+//! Usage that still turns up for a closed period, e.g. from a node that was
+//! unreachable at the time, is not folded into the report already sent: it
+//! is reported as an adjustment with the next revision, holding only the
+//! additional usage.
+
+use crate::config::BillingConfig;
+use crate::error::Result;
+use crate::exporters::{AuthenticationConfig, AuthenticationType};
+use crate::TelemetryError;
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, TimeZone, Timelike, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, warn};
+use vpn_cluster::{TrafficAggregator, UserTraffic};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of a billing period
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BillingInterval {
+    Hourly,
+    Daily,
+    #[default]
+    Monthly,
+}
+
+impl BillingInterval {
+    /// The period `at` falls in
+    pub fn period_containing(&self, at: DateTime<Utc>) -> BillingPeriod {
+        let start = match self {
+            Self::Hourly => Utc
+                .with_ymd_and_hms(at.year(), at.month(), at.day(), at.hour(), 0, 0)
+                .unwrap(),
+            Self::Daily => Utc
+                .with_ymd_and_hms(at.year(), at.month(), at.day(), 0, 0, 0)
+                .unwrap(),
+            Self::Monthly => Utc
+                .with_ymd_and_hms(at.year(), at.month(), 1, 0, 0, 0)
+                .unwrap(),
+        };
+        let end = match self {
+            Self::Hourly => start + ChronoDuration::hours(1),
+            Self::Daily => start + ChronoDuration::days(1),
+            Self::Monthly => {
+                let (year, month) = if start.month() == 12 {
+                    (start.year() + 1, 1)
+                } else {
+                    (start.year(), start.month() + 1)
+                };
+                Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap()
+            }
+        };
+
+        let id = match self {
+            Self::Hourly => start.format("%Y-%m-%dT%H").to_string(),
+            Self::Daily => start.format("%Y-%m-%d").to_string(),
+            Self::Monthly => start.format("%Y-%m").to_string(),
+        };
+
+        BillingPeriod { id, start, end }
+    }
+}
+
+/// One billing period, `[start, end)`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BillingPeriod {
+    /// e.g. `2024-05` for a monthly period
+    pub id: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Format of the exported reports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BillingFormat {
+    Csv,
+    #[default]
+    Json,
+}
+
+impl BillingFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Json => "application/json",
+        }
+    }
+}
+
+/// Usage of one user in one period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserUsage {
+    pub user_id: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub total_bytes: u64,
+    pub connections: u64,
+}
+
+/// Usage of every user in a closed period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub period: BillingPeriod,
+    /// 1 for the period's usage; higher revisions are adjustments holding
+    /// only usage that arrived after the previous revision was issued
+    pub revision: u32,
+    pub closed_at: DateTime<Utc>,
+    pub users: Vec<UserUsage>,
+}
+
+impl UsageReport {
+    fn new(
+        period: BillingPeriod,
+        revision: u32,
+        closed_at: DateTime<Utc>,
+        usage: &BTreeMap<String, UserTraffic>,
+    ) -> Self {
+        let users = usage
+            .iter()
+            .map(|(user_id, traffic)| UserUsage {
+                user_id: user_id.clone(),
+                bytes_sent: traffic.bytes_sent,
+                bytes_received: traffic.bytes_received,
+                total_bytes: traffic.total_bytes(),
+                connections: traffic.connections,
+            })
+            .collect();
+
+        Self {
+            period,
+            revision,
+            closed_at,
+            users,
+        }
+    }
+
+    /// Whether this report corrects an earlier one of the same period
+    pub fn is_adjustment(&self) -> bool {
+        self.revision > 1
+    }
+
+    /// Stable across retries, so receivers can drop duplicate deliveries
+    pub fn delivery_key(&self) -> String {
+        format!("{}-r{}", self.period.id, self.revision)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "period,revision,user_id,bytes_sent,bytes_received,total_bytes,connections\n",
+        );
+        for user in &self.users {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                self.period.id,
+                self.revision,
+                csv_field(&user.user_id),
+                user.bytes_sent,
+                user.bytes_received,
+                user.total_bytes,
+                user.connections
+            ));
+        }
+        csv
+    }
+
+    pub fn render(&self, format: BillingFormat) -> Result<Vec<u8>> {
+        Ok(match format {
+            BillingFormat::Csv => self.to_csv().into_bytes(),
+            BillingFormat::Json => serde_json::to_vec_pretty(self)?,
+        })
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Cumulative per-user traffic counters to bill from
+#[async_trait]
+pub trait UsageSource: Send + Sync {
+    /// Counters keyed by user ID; they only grow, except when reset
+    async fn user_usage(&self) -> Result<HashMap<String, UserTraffic>>;
+}
+
+#[async_trait]
+impl UsageSource for TrafficAggregator {
+    async fn user_usage(&self) -> Result<HashMap<String, UserTraffic>> {
+        self.cluster_traffic()
+            .await
+            .map_err(|e| TelemetryError::OperationFailed {
+                operation: "cluster_traffic".to_string(),
+                message: e.to_string(),
+            })
+    }
+}
+
+/// Destination of closed-period reports
+#[async_trait]
+pub trait BillingSink: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Deliver `body`, the rendering of `report` in `format`. Must be safe
+    /// to repeat for the same report.
+    async fn deliver(&self, report: &UsageReport, format: BillingFormat, body: &[u8])
+        -> Result<()>;
+}
+
+/// Webhook receiving reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub authentication: Option<AuthenticationConfig>,
+    #[serde(default = "default_sink_timeout")]
+    pub timeout: Duration,
+}
+
+/// S3 or S3-compatible bucket receiving reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or a MinIO URL
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    /// Prepended to the object keys, e.g. `billing/`
+    #[serde(default)]
+    pub prefix: String,
+    /// Falls back to `AWS_ACCESS_KEY_ID`
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+    /// Falls back to `AWS_SECRET_ACCESS_KEY`
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+    #[serde(default = "default_sink_timeout")]
+    pub timeout: Duration,
+}
+
+fn default_sink_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// POSTs reports with an `Idempotency-Key` header
+pub struct WebhookSink {
+    config: WebhookConfig,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: "billing-webhook".to_string(),
+                message: format!("Failed to create HTTP client: {}", e),
+            })?;
+
+        Ok(Self { config, client })
+    }
+}
+
+#[async_trait]
+impl BillingSink for WebhookSink {
+    fn name(&self) -> &str {
+        "billing-webhook"
+    }
+
+    async fn deliver(
+        &self,
+        report: &UsageReport,
+        format: BillingFormat,
+        body: &[u8],
+    ) -> Result<()> {
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .header("Content-Type", format.content_type())
+            .header("Idempotency-Key", report.delivery_key());
+
+        for (key, value) in &self.config.headers {
+            request = request.header(key, value);
+        }
+
+        if let Some(auth) = &self.config.authentication {
+            match &auth.auth_type {
+                AuthenticationType::Basic => {
+                    if let (Some(username), Some(password)) = (&auth.username, &auth.password) {
+                        request = request.basic_auth(username, Some(password));
+                    }
+                }
+                AuthenticationType::Bearer => {
+                    if let Some(token) = &auth.token {
+                        request = request.bearer_auth(token);
+                    }
+                }
+                AuthenticationType::ApiKey => {
+                    if let Some(api_key) = &auth.api_key {
+                        request = request.header("X-API-Key", api_key);
+                    }
+                }
+                AuthenticationType::None => {}
+            }
+        }
+
+        let response =
+            request
+                .body(body.to_vec())
+                .send()
+                .await
+                .map_err(|e| TelemetryError::ExportError {
+                    exporter: self.name().to_string(),
+                    message: format!("HTTP request failed: {}", e),
+                })?;
+
+        if !response.status().is_success() {
+            return Err(TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP error: {}", response.status()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// PUTs reports to `<prefix><period>-r<revision>.<ext>`, signed with AWS
+/// Signature Version 4 and addressed path-style
+pub struct S3Sink {
+    config: S3Config,
+    client: reqwest::Client,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl S3Sink {
+    pub fn new(config: S3Config) -> Result<Self> {
+        let credential = |configured: &Option<String>, var: &str| {
+            configured
+                .clone()
+                .or_else(|| std::env::var(var).ok())
+                .ok_or_else(|| TelemetryError::ConfigError {
+                    message: format!("S3 billing sink needs {} or the matching setting", var),
+                })
+        };
+        let access_key_id = credential(&config.access_key_id, "AWS_ACCESS_KEY_ID")?;
+        let secret_access_key = credential(&config.secret_access_key, "AWS_SECRET_ACCESS_KEY")?;
+
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: "billing-s3".to_string(),
+                message: format!("Failed to create HTTP client: {}", e),
+            })?;
+
+        Ok(Self {
+            config,
+            client,
+            access_key_id,
+            secret_access_key,
+        })
+    }
+
+    /// Object key of `report` in `format`
+    pub fn object_key(&self, report: &UsageReport, format: BillingFormat) -> String {
+        format!(
+            "{}{}.{}",
+            self.config.prefix,
+            report.delivery_key(),
+            format.extension()
+        )
+    }
+
+    /// `Authorization` header value for a PUT of `payload_hash` to `path`
+    fn authorization(
+        &self,
+        host: &str,
+        path: &str,
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> String {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+
+        let canonical_request = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            path, host, payload_hash, amz_date, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = hmac_sha256(
+            format!("AWS4{}", self.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        );
+        for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, scope, signed_headers, signature
+        )
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode an S3 object path, keeping `/`
+fn s3_path_encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[async_trait]
+impl BillingSink for S3Sink {
+    fn name(&self) -> &str {
+        "billing-s3"
+    }
+
+    async fn deliver(
+        &self,
+        report: &UsageReport,
+        format: BillingFormat,
+        body: &[u8],
+    ) -> Result<()> {
+        let endpoint = reqwest::Url::parse(&self.config.endpoint).map_err(|e| {
+            TelemetryError::ConfigError {
+                message: format!("Invalid S3 endpoint {}: {}", self.config.endpoint, e),
+            }
+        })?;
+        let host = match (endpoint.host_str(), endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => {
+                return Err(TelemetryError::ConfigError {
+                    message: format!("S3 endpoint {} has no host", self.config.endpoint),
+                })
+            }
+        };
+
+        let path = s3_path_encode(&format!(
+            "/{}/{}",
+            self.config.bucket,
+            self.object_key(report, format)
+        ));
+        let payload_hash = hex::encode(Sha256::digest(body));
+        let now = Utc::now();
+
+        let response = self
+            .client
+            .put(format!(
+                "{}{}",
+                self.config.endpoint.trim_end_matches('/'),
+                path
+            ))
+            .header("Content-Type", format.content_type())
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string())
+            .header(
+                "Authorization",
+                self.authorization(&host, &path, &payload_hash, now),
+            )
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP request failed: {}", e),
+            })?;
+
+        if !response.status().is_success() {
+            return Err(TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP error: {}", response.status()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Usage of one period still being accumulated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PeriodUsage {
+    period: BillingPeriod,
+    users: BTreeMap<String, UserTraffic>,
+}
+
+/// A report and the sinks that have it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingReport {
+    report: UsageReport,
+    delivered_to: BTreeSet<String>,
+}
+
+/// Billing state, persisted so restarts neither lose usage nor close a
+/// period twice
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct BillingLedger {
+    /// Counters of the latest sample, to compute usage from
+    counters: HashMap<String, UserTraffic>,
+    /// Open periods by ID
+    open: BTreeMap<String, PeriodUsage>,
+    /// Closed periods by ID, with the latest revision issued
+    closed: BTreeMap<String, u32>,
+    /// Usage that arrived for closed periods, not reported yet
+    late: BTreeMap<String, PeriodUsage>,
+    /// Reports not yet delivered to every sink
+    outbox: Vec<PendingReport>,
+}
+
+impl BillingLedger {
+    fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Written aside and renamed, so a crash never leaves half a ledger
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Fold a sample of cumulative counters taken at `at` into the periods
+    fn record(
+        &mut self,
+        interval: BillingInterval,
+        at: DateTime<Utc>,
+        sample: HashMap<String, UserTraffic>,
+    ) {
+        for (user_id, current) in sample {
+            let previous = self.counters.get(&user_id).copied().unwrap_or_default();
+            let usage = usage_since(&previous, &current);
+            self.counters.insert(user_id.clone(), current);
+
+            if usage.total_bytes() == 0 && usage.connections == 0 {
+                continue;
+            }
+
+            // Bill the usage when it happened, if the source knows
+            let happened = current
+                .last_activity
+                .and_then(|ts| Utc.timestamp_opt(ts as i64, 0).single())
+                .map_or(at, |activity| activity.min(at));
+            let period = interval.period_containing(happened);
+
+            let periods = if self.closed.contains_key(&period.id) {
+                &mut self.late
+            } else {
+                &mut self.open
+            };
+            periods
+                .entry(period.id.clone())
+                .or_insert_with(|| PeriodUsage {
+                    period,
+                    users: BTreeMap::new(),
+                })
+                .users
+                .entry(user_id)
+                .or_default()
+                .add(&usage);
+        }
+    }
+
+    /// Close the periods that ended at least `grace` before `now` and issue
+    /// adjustments for late usage, returning the new reports
+    fn close_due(&mut self, now: DateTime<Utc>, grace: ChronoDuration) -> Vec<UsageReport> {
+        let mut reports = Vec::new();
+
+        let due: Vec<String> = self
+            .open
+            .values()
+            .filter(|usage| usage.period.end + grace <= now)
+            .map(|usage| usage.period.id.clone())
+            .collect();
+        for id in due {
+            let Some(usage) = self.open.remove(&id) else {
+                continue;
+            };
+            // Closing is idempotent: a period closed before only gets
+            // adjustments
+            let revision = self.closed.get(&id).map_or(1, |revision| revision + 1);
+            self.closed.insert(id, revision);
+            reports.push(UsageReport::new(usage.period, revision, now, &usage.users));
+        }
+
+        for (id, usage) in std::mem::take(&mut self.late) {
+            let revision = self.closed.get(&id).copied().unwrap_or(0) + 1;
+            self.closed.insert(id, revision);
+            reports.push(UsageReport::new(usage.period, revision, now, &usage.users));
+        }
+
+        for report in &reports {
+            self.outbox.push(PendingReport {
+                report: report.clone(),
+                delivered_to: BTreeSet::new(),
+            });
+        }
+        reports
+    }
+}
+
+/// Usage between two samples of cumulative counters; counters that went
+/// backwards were reset, so everything counted since is new
+fn usage_since(previous: &UserTraffic, current: &UserTraffic) -> UserTraffic {
+    let reset = current.bytes_sent < previous.bytes_sent
+        || current.bytes_received < previous.bytes_received
+        || current.connections < previous.connections;
+    if reset {
+        return *current;
+    }
+
+    UserTraffic {
+        bytes_sent: current.bytes_sent - previous.bytes_sent,
+        bytes_received: current.bytes_received - previous.bytes_received,
+        connections: current.connections - previous.connections,
+        last_activity: current.last_activity,
+    }
+}
+
+/// Aggregates per-user usage into billing periods and pushes closed
+/// periods to the configured sinks
+pub struct BillingExporter {
+    config: BillingConfig,
+    source: Arc<dyn UsageSource>,
+    sinks: Vec<Box<dyn BillingSink>>,
+    ledger: Mutex<BillingLedger>,
+    running: Arc<RwLock<bool>>,
+}
+
+impl BillingExporter {
+    /// Exporter with the sinks `config` describes, resuming from the ledger
+    /// at `config.state_path`
+    pub fn new(config: BillingConfig, source: Arc<dyn UsageSource>) -> Result<Self> {
+        let mut sinks: Vec<Box<dyn BillingSink>> = Vec::new();
+        if let Some(webhook) = &config.webhook {
+            sinks.push(Box::new(WebhookSink::new(webhook.clone())?));
+        }
+        if let Some(s3) = &config.s3 {
+            sinks.push(Box::new(S3Sink::new(s3.clone())?));
+        }
+
+        let ledger = BillingLedger::load(&config.state_path)?;
+
+        Ok(Self {
+            config,
+            source,
+            sinks,
+            ledger: Mutex::new(ledger),
+            running: Arc::new(RwLock::new(false)),
+        })
+    }
+
+    /// Add a sink besides the configured ones
+    pub fn with_sink(mut self, sink: Box<dyn BillingSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    pub fn state_path(&self) -> &Path {
+        &self.config.state_path
+    }
+
+    /// Sample the source and fold the counters into the open periods
+    pub async fn sample(&self, at: DateTime<Utc>) -> Result<()> {
+        let usage = self.source.user_usage().await?;
+        let mut ledger = self.ledger.lock().await;
+        ledger.record(self.config.interval, at, usage);
+        ledger.save(&self.config.state_path)
+    }
+
+    /// Close the periods that are due at `now`, returning the reports issued
+    pub async fn close_due(&self, now: DateTime<Utc>) -> Result<Vec<UsageReport>> {
+        let grace = ChronoDuration::from_std(self.config.grace_period)
+            .unwrap_or_else(|_| ChronoDuration::zero());
+        let mut ledger = self.ledger.lock().await;
+        let reports = ledger.close_due(now, grace);
+        for report in &reports {
+            info!(
+                "Closed billing period {} (revision {}, {} users)",
+                report.period.id,
+                report.revision,
+                report.users.len()
+            );
+        }
+        ledger.save(&self.config.state_path)?;
+        Ok(reports)
+    }
+
+    /// Usage of the periods still open, as it would be reported now
+    pub async fn open_usage(&self) -> Vec<UsageReport> {
+        let ledger = self.ledger.lock().await;
+        ledger
+            .open
+            .values()
+            .map(|usage| UsageReport::new(usage.period.clone(), 1, Utc::now(), &usage.users))
+            .collect()
+    }
+
+    /// Number of reports not yet delivered to every sink
+    pub async fn pending_reports(&self) -> usize {
+        self.ledger.lock().await.outbox.len()
+    }
+
+    /// Deliver pending reports; failed deliveries stay queued for the next
+    /// attempt. Returns the number of reports fully delivered.
+    pub async fn deliver(&self) -> Result<usize> {
+        let mut ledger = self.ledger.lock().await;
+        let mut delivered = 0;
+
+        for pending in ledger.outbox.iter_mut() {
+            let body = pending.report.render(self.config.format)?;
+            for sink in &self.sinks {
+                if pending.delivered_to.contains(sink.name()) {
+                    continue;
+                }
+                match sink
+                    .deliver(&pending.report, self.config.format, &body)
+                    .await
+                {
+                    Ok(()) => {
+                        debug!(
+                            "Delivered billing report {} to {}",
+                            pending.report.delivery_key(),
+                            sink.name()
+                        );
+                        pending.delivered_to.insert(sink.name().to_string());
+                    }
+                    Err(e) => warn!(
+                        "Failed to deliver billing report {} to {}: {}",
+                        pending.report.delivery_key(),
+                        sink.name(),
+                        e
+                    ),
+                }
+            }
+        }
+
+        let sinks: Vec<&str> = self.sinks.iter().map(|sink| sink.name()).collect();
+        ledger.outbox.retain(|pending| {
+            let done = sinks
+                .iter()
+                .all(|sink| pending.delivered_to.contains(*sink));
+            if done {
+                delivered += 1;
+            }
+            !done
+        });
+
+        ledger.save(&self.config.state_path)?;
+        Ok(delivered)
+    }
+
+    /// Sample, close due periods and deliver, as the background task does
+    pub async fn run_once(&self) -> Result<()> {
+        let now = Utc::now();
+        self.sample(now).await?;
+        self.close_due(now).await?;
+        self.deliver().await?;
+        Ok(())
+    }
+
+    /// Run [`run_once`](Self::run_once) every `sample_interval` until stopped
+    pub async fn start(self: Arc<Self>) -> Result<()> {
+        let mut running = self.running.write().await;
+        if *running {
+            return Ok(());
+        }
+        *running = true;
+        info!("Started billing export");
+
+        let exporter = self.clone();
+        tokio::spawn(async move {
+            let mut interval_timer = tokio::time::interval(exporter.config.sample_interval);
+
+            loop {
+                interval_timer.tick().await;
+
+                if !*exporter.running.read().await {
+                    break;
+                }
+
+                if let Err(e) = exporter.run_once().await {
+                    warn!("Billing export failed: {}", e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        *self.running.write().await = false;
+        info!("Stopped billing export");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn traffic(sent: u64, received: u64) -> UserTraffic {
+        UserTraffic {
+            bytes_sent: sent,
+            bytes_received: received,
+            connections: 1,
+            last_activity: None,
+        }
+    }
+
+    #[test]
+    fn test_billing_periods() {
+        let period = BillingInterval::Monthly.period_containing(at("2024-12-31T23:59:59Z"));
+        assert_eq!(period.id, "2024-12");
+        assert_eq!(period.end, at("2025-01-01T00:00:00Z"));
+
+        let period = BillingInterval::Hourly.period_containing(at("2024-05-03T07:30:00Z"));
+        assert_eq!(period.id, "2024-05-03T07");
+        assert_eq!(period.start, at("2024-05-03T07:00:00Z"));
+    }
+
+    #[test]
+    fn test_ledger_closes_periods_once() {
+        let mut ledger = BillingLedger::default();
+        let interval = BillingInterval::Daily;
+        let grace = ChronoDuration::minutes(10);
+
+        let sample = HashMap::from([("alice".to_string(), traffic(100, 50))]);
+        ledger.record(interval, at("2024-05-01T12:00:00Z"), sample);
+        // Counters reset after a restart: the new counts are all usage
+        let sample = HashMap::from([("alice".to_string(), traffic(10, 5))]);
+        ledger.record(interval, at("2024-05-01T18:00:00Z"), sample);
+
+        // Still within the grace period
+        assert!(ledger
+            .close_due(at("2024-05-02T00:05:00Z"), grace)
+            .is_empty());
+
+        let reports = ledger.close_due(at("2024-05-02T00:10:00Z"), grace);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].period.id, "2024-05-01");
+        assert_eq!(reports[0].revision, 1);
+        assert_eq!(reports[0].users[0].total_bytes, 165);
+        assert_eq!(reports[0].users[0].connections, 2);
+
+        assert!(ledger
+            .close_due(at("2024-05-02T01:00:00Z"), grace)
+            .is_empty());
+        assert_eq!(ledger.outbox.len(), 1);
+    }
+
+    #[test]
+    fn test_ledger_reports_late_usage_as_adjustment() {
+        let mut ledger = BillingLedger::default();
+        let interval = BillingInterval::Daily;
+
+        let sample = HashMap::from([("alice".to_string(), traffic(100, 0))]);
+        ledger.record(interval, at("2024-05-01T12:00:00Z"), sample);
+        ledger.close_due(at("2024-05-03T00:00:00Z"), ChronoDuration::zero());
+
+        // A node that was unreachable reports traffic from May 1st
+        let mut late = traffic(150, 0);
+        late.last_activity = Some(at("2024-05-01T23:00:00Z").timestamp() as u64);
+        ledger.record(
+            interval,
+            at("2024-05-03T00:30:00Z"),
+            HashMap::from([("alice".to_string(), late)]),
+        );
+
+        let reports = ledger.close_due(at("2024-05-03T01:00:00Z"), ChronoDuration::zero());
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].is_adjustment());
+        assert_eq!(reports[0].delivery_key(), "2024-05-01-r2");
+        assert_eq!(reports[0].users[0].bytes_sent, 50);
+        assert!(ledger.open.is_empty());
+    }
+
+    #[test]
+    fn test_report_csv() {
+        let period = BillingInterval::Monthly.period_containing(at("2024-05-03T07:30:00Z"));
+        let usage = BTreeMap::from([("a,b".to_string(), traffic(1, 2))]);
+        let report = UsageReport::new(period, 1, at("2024-06-01T00:00:00Z"), &usage);
+
+        assert_eq!(
+            report.to_csv(),
+            "period,revision,user_id,bytes_sent,bytes_received,total_bytes,connections\n\
+             2024-05,1,\"a,b\",1,2,3,1\n"
+        );
+    }
+
+    struct FixedUsage(HashMap<String, UserTraffic>);
+
+    #[async_trait]
+    impl UsageSource for FixedUsage {
+        async fn user_usage(&self) -> Result<HashMap<String, UserTraffic>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    struct FlakySink {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl BillingSink for FlakySink {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn deliver(&self, _: &UsageReport, _: BillingFormat, _: &[u8]) -> Result<()> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(TelemetryError::ExportError {
+                    exporter: "flaky".to_string(),
+                    message: "unavailable".to_string(),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exporter_retries_delivery_across_restarts() {
+        let dir = std::env::temp_dir().join(format!("vpn-billing-{}", uuid::Uuid::new_v4()));
+        let config = BillingConfig {
+            enabled: true,
+            state_path: dir.join("ledger.json"),
+            ..BillingConfig::default()
+        };
+        let source = Arc::new(FixedUsage(HashMap::from([(
+            "alice".to_string(),
+            traffic(10, 10),
+        )])));
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let exporter = BillingExporter::new(config.clone(), source.clone())
+            .unwrap()
+            .with_sink(Box::new(FlakySink {
+                attempts: attempts.clone(),
+            }));
+        exporter.sample(at("2024-05-01T12:00:00Z")).await.unwrap();
+        let reports = exporter
+            .close_due(at("2024-06-02T00:00:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(exporter.deliver().await.unwrap(), 0);
+        drop(exporter);
+
+        // The undelivered report and the closed period survive a restart
+        let exporter = BillingExporter::new(config, source)
+            .unwrap()
+            .with_sink(Box::new(FlakySink {
+                attempts: attempts.clone(),
+            }));
+        assert_eq!(exporter.pending_reports().await, 1);
+        assert!(exporter
+            .close_due(at("2024-06-03T00:00:00Z"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(exporter.deliver().await.unwrap(), 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,6 +1,13 @@
 //! Telemetry configuration module
 
+use crate::alerting::{AlertRule, SmtpConfig, TelegramConfig};
+use crate::billing::{BillingFormat, BillingInterval, S3Config, WebhookConfig};
+use crate::dashboard_access::DashboardView;
+use crate::log_shipping::{ElasticsearchConfig, LogFormat, LokiConfig};
+use crate::slo::{SloIndicator, SloObjective};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::Duration;
 
 /// Configuration for the telemetry system
@@ -35,6 +42,26 @@ pub struct TelemetryConfig {
 
     /// Performance monitoring configuration
     pub performance: PerformanceConfig,
+
+    /// Admin endpoint used by `vpn telemetry`
+    #[serde(default)]
+    pub admin: AdminConfig,
+
+    /// Per-user usage export for billing systems
+    #[serde(default)]
+    pub billing: BillingConfig,
+
+    /// Alert rules and where alerts are sent
+    #[serde(default)]
+    pub alerting: AlertingConfig,
+
+    /// Container log shipping to Loki or Elasticsearch
+    #[serde(default)]
+    pub log_shipping: LogShippingConfig,
+
+    /// Service level objectives tracked for burn rate alerts
+    #[serde(default)]
+    pub slo: SloConfig,
 }
 
 /// Tracing configuration
@@ -102,6 +129,75 @@ pub struct MetricsConfig {
 
     /// Custom metrics to collect
     pub custom_metrics: Vec<CustomMetricConfig>,
+
+    /// How users appear in metric labels
+    #[serde(default)]
+    pub per_user: PerUserMetricsConfig,
+
+    /// Limits on the label sets of each metric
+    #[serde(default)]
+    pub cardinality: CardinalityConfig,
+}
+
+/// Per-user metric labels
+///
+/// Every distinct user label is a new series in the metrics backend, so
+/// the number of labels is capped; users seen after the cap is reached are
+/// reported together under one overflow label.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PerUserMetricsConfig {
+    /// Whether to label metrics per user at all; when disabled every user
+    /// is reported under the overflow label
+    pub enabled: bool,
+
+    /// Label with a salted hash of the user ID instead of the ID itself
+    pub hash_user_ids: bool,
+
+    /// Salt mixed into the hash, so hashes cannot be matched against
+    /// guessed IDs
+    pub hash_salt: String,
+
+    /// Most distinct user labels
+    pub max_users: usize,
+}
+
+/// Label set limits of the collector's metrics
+///
+/// A label fed with per-connection values, such as a port or a client
+/// address, makes a new series per connection and soon overwhelms the
+/// exporters and Prometheus. Each metric may therefore hold at most
+/// `max_series` label sets; label sets seen after that are dropped or
+/// reported together, and the call site recording them is logged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CardinalityConfig {
+    /// Whether label sets are counted and limited at all
+    pub enabled: bool,
+
+    /// Most label sets of a metric
+    pub max_series: usize,
+
+    /// Per-metric overrides of `max_series`, keyed by the full metric
+    /// name, e.g. `vpn_user_connections_total`
+    pub limits: HashMap<String, usize>,
+
+    /// What happens to label sets past the limit
+    pub overflow: CardinalityOverflow,
+
+    /// Values each label may take, keyed by label name; other values are
+    /// reported as the overflow label. Labels not listed take any value.
+    pub allowed_values: HashMap<String, Vec<String>>,
+}
+
+/// What happens to label sets past a metric's limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CardinalityOverflow {
+    /// The value is not recorded
+    Reject,
+    /// The value is recorded with every label set to the overflow label
+    Aggregate,
 }
 
 /// Prometheus configuration
@@ -168,6 +264,53 @@ pub struct DashboardConfig {
 
     /// Password for basic auth
     pub password: Option<String>,
+
+    /// vpn-identity tokens, sessions and per-role views
+    #[serde(default)]
+    pub auth: DashboardAuthConfig,
+
+    /// HTTPS for the dashboard
+    #[serde(default)]
+    pub tls: DashboardTlsConfig,
+}
+
+/// Dashboard authentication backed by vpn-identity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DashboardAuthConfig {
+    /// Secret vpn-identity signs its HS256 access tokens with
+    pub identity_jwt_secret: Option<String>,
+
+    /// Required `iss` claim of access tokens
+    pub identity_issuer: String,
+
+    /// Audience access tokens must be issued for, if any
+    pub identity_audience: Option<String>,
+
+    /// Lifetime of a dashboard session cookie
+    pub session_ttl: Duration,
+
+    /// View granted to each vpn-identity role; tokens carrying none of
+    /// these roles are refused
+    pub role_views: HashMap<String, DashboardView>,
+}
+
+/// Dashboard TLS configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DashboardTlsConfig {
+    /// Whether to serve the dashboard over HTTPS
+    pub enabled: bool,
+
+    /// PEM certificate chain; a self-signed certificate is generated
+    /// when neither this nor `key_path` is set
+    pub cert_path: Option<PathBuf>,
+
+    /// PEM private key for `cert_path`
+    pub key_path: Option<PathBuf>,
+
+    /// Subject alternative names of the self-signed certificate
+    pub self_signed_names: Vec<String>,
 }
 
 /// Health monitoring configuration
@@ -184,6 +327,35 @@ pub struct HealthConfig {
 
     /// Thresholds for health alerts
     pub thresholds: HealthThresholds,
+
+    /// Installation whose disk usage is reported; the root filesystem
+    /// when unset or missing
+    #[serde(default)]
+    pub install_path: Option<PathBuf>,
+
+    /// Liveness and readiness probe endpoint
+    #[serde(default)]
+    pub endpoint: HealthEndpointConfig,
+}
+
+/// Liveness and readiness probe endpoint configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthEndpointConfig {
+    /// Whether to serve `/healthz` and `/readyz`
+    pub enabled: bool,
+
+    /// Probe endpoint bind address
+    pub bind_address: String,
+
+    /// Probe endpoint port
+    pub port: u16,
+
+    /// Longest a single readiness check may take
+    pub check_timeout: Duration,
+
+    /// Containers that must be running for the node to be ready
+    pub containers: Vec<String>,
 }
 
 /// Health thresholds
@@ -216,6 +388,146 @@ pub struct PerformanceConfig {
 
     /// Whether to enable benchmark comparison
     pub benchmark_enabled: bool,
+
+    /// How long samples and their rollups are kept
+    #[serde(default)]
+    pub retention: RetentionConfig,
+}
+
+/// Retention of the embedded time-series store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Directory for minute and hour chunks; in memory only when unset
+    pub data_dir: Option<PathBuf>,
+
+    /// How long raw samples are kept
+    pub raw: Duration,
+
+    /// How long one-minute rollups are kept
+    pub minute: Duration,
+
+    /// How long one-hour rollups are kept
+    pub hour: Duration,
+}
+
+/// Admin endpoint configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Whether to serve the admin endpoint
+    pub enabled: bool,
+
+    /// Admin endpoint bind address
+    pub bind_address: String,
+
+    /// Admin endpoint port
+    pub port: u16,
+}
+
+/// Billing export configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BillingConfig {
+    /// Whether to export per-user usage
+    pub enabled: bool,
+
+    /// Length of a billing period
+    pub interval: BillingInterval,
+
+    /// How often traffic counters are sampled
+    pub sample_interval: Duration,
+
+    /// How long after its end a period stays open for late usage
+    pub grace_period: Duration,
+
+    /// Format of the exported reports
+    pub format: BillingFormat,
+
+    /// Ledger of open and closed periods, kept across restarts
+    pub state_path: PathBuf,
+
+    /// Webhook receiving the reports
+    pub webhook: Option<WebhookConfig>,
+
+    /// S3 bucket receiving the reports
+    pub s3: Option<S3Config>,
+}
+
+/// Alerting configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertingConfig {
+    /// Whether to evaluate alert rules
+    pub enabled: bool,
+
+    /// How often rules are evaluated
+    pub evaluation_interval: Duration,
+
+    /// Rules to evaluate
+    pub rules: Vec<AlertRule>,
+
+    /// Webhook receiving alerts as JSON
+    pub webhook: Option<WebhookConfig>,
+
+    /// SMTP server emailing alerts
+    pub email: Option<SmtpConfig>,
+
+    /// Telegram chat receiving alerts
+    pub telegram: Option<TelegramConfig>,
+
+    /// Installation whose maintenance windows hold back notifications
+    pub maintenance_path: Option<PathBuf>,
+}
+
+/// Container log shipping configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogShippingConfig {
+    /// Whether to ship container logs
+    pub enabled: bool,
+
+    /// Containers whose logs are followed
+    pub containers: Vec<String>,
+
+    /// Log format per container; others are detected from the name
+    pub formats: HashMap<String, LogFormat>,
+
+    /// Largest number of events sent to a sink at once
+    pub batch_size: usize,
+
+    /// How often partial batches are sent
+    pub flush_interval: Duration,
+
+    /// Log lines read ahead of the shipper before streams are held back
+    pub channel_capacity: usize,
+
+    /// Events each sink keeps in memory while it is unreachable
+    pub buffer_capacity: usize,
+
+    /// Directory holding events that did not fit in memory; they are
+    /// dropped when unset
+    pub spool_dir: Option<PathBuf>,
+
+    /// Loki receiving the events
+    pub loki: Option<LokiConfig>,
+
+    /// Elasticsearch receiving the events
+    pub elasticsearch: Option<ElasticsearchConfig>,
+}
+
+/// Service level objective configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SloConfig {
+    /// Objectives to track
+    pub objectives: Vec<SloObjective>,
+}
+
+impl AdminConfig {
+    /// Base URL of the admin endpoint
+    pub fn url(&self) -> String {
+        format!("http://{}:{}", self.bind_address, self.port)
+    }
 }
 
 impl Default for TelemetryConfig {
@@ -231,6 +543,11 @@ impl Default for TelemetryConfig {
             dashboard_enabled: true,
             health: HealthConfig::default(),
             performance: PerformanceConfig::default(),
+            admin: AdminConfig::default(),
+            billing: BillingConfig::default(),
+            alerting: AlertingConfig::default(),
+            log_shipping: LogShippingConfig::default(),
+            slo: SloConfig::default(),
         }
     }
 }
@@ -266,6 +583,31 @@ impl Default for MetricsConfig {
             prometheus: PrometheusConfig::default(),
             collection_interval: Duration::from_secs(10),
             custom_metrics: vec![],
+            per_user: PerUserMetricsConfig::default(),
+            cardinality: CardinalityConfig::default(),
+        }
+    }
+}
+
+impl Default for PerUserMetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            hash_user_ids: true,
+            hash_salt: String::new(),
+            max_users: 500,
+        }
+    }
+}
+
+impl Default for CardinalityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_series: 2000,
+            limits: HashMap::new(),
+            overflow: CardinalityOverflow::Aggregate,
+            allowed_values: HashMap::new(),
         }
     }
 }
@@ -291,6 +633,35 @@ impl Default for DashboardConfig {
             auth_enabled: false,
             username: None,
             password: None,
+            auth: DashboardAuthConfig::default(),
+            tls: DashboardTlsConfig::default(),
+        }
+    }
+}
+
+impl Default for DashboardAuthConfig {
+    fn default() -> Self {
+        Self {
+            identity_jwt_secret: None,
+            identity_issuer: "vpn-identity".to_string(),
+            identity_audience: Some("vpn-services".to_string()),
+            session_ttl: Duration::from_secs(8 * 3600),
+            role_views: HashMap::from([
+                ("admin".to_string(), DashboardView::Full),
+                ("operator".to_string(), DashboardView::Full),
+                ("auditor".to_string(), DashboardView::Metrics),
+            ]),
+        }
+    }
+}
+
+impl Default for DashboardTlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cert_path: None,
+            key_path: None,
+            self_signed_names: vec!["localhost".to_string()],
         }
     }
 }
@@ -307,6 +678,21 @@ impl Default for HealthConfig {
                 "server".to_string(),
             ],
             thresholds: HealthThresholds::default(),
+            install_path: Some(PathBuf::from("/opt/vpn")),
+            endpoint: HealthEndpointConfig::default(),
+        }
+    }
+}
+
+impl Default for HealthEndpointConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            // Probed by the kubelet and load balancers from outside the host
+            bind_address: "0.0.0.0".to_string(),
+            port: 8086,
+            check_timeout: Duration::from_secs(2),
+            containers: vec!["vpn-server".to_string()],
         }
     }
 }
@@ -329,6 +715,100 @@ impl Default for PerformanceConfig {
             collection_interval: Duration::from_secs(60),
             sample_size: 1000,
             benchmark_enabled: true,
+            retention: RetentionConfig::default(),
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: None,
+            raw: Duration::from_secs(3600),
+            minute: Duration::from_secs(24 * 3600),
+            hour: Duration::from_secs(30 * 24 * 3600),
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            // Runtime controls are local-only by default
+            bind_address: "127.0.0.1".to_string(),
+            port: 9464,
+        }
+    }
+}
+
+impl Default for BillingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: BillingInterval::Monthly,
+            sample_interval: Duration::from_secs(300),
+            grace_period: Duration::from_secs(3600),
+            format: BillingFormat::Json,
+            state_path: PathBuf::from("/var/lib/vpn/billing-ledger.json"),
+            webhook: None,
+            s3: None,
+        }
+    }
+}
+
+impl Default for AlertingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            evaluation_interval: Duration::from_secs(30),
+            rules: AlertRule::defaults(),
+            webhook: None,
+            email: None,
+            telegram: None,
+            maintenance_path: Some(PathBuf::from("/opt/vpn")),
+        }
+    }
+}
+
+impl Default for LogShippingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            containers: vec!["vpn-server".to_string()],
+            formats: HashMap::from([("vpn-server".to_string(), LogFormat::Xray)]),
+            batch_size: 500,
+            flush_interval: Duration::from_secs(5),
+            channel_capacity: 1024,
+            buffer_capacity: 10_000,
+            spool_dir: Some(PathBuf::from("/var/lib/vpn/log-spool")),
+            loki: None,
+            elasticsearch: None,
+        }
+    }
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        Self {
+            objectives: vec![
+                SloObjective {
+                    name: "proxy-availability".to_string(),
+                    event: "proxy_connect".to_string(),
+                    indicator: SloIndicator::Availability,
+                    objective: 99.5,
+                    window: Duration::from_secs(30 * 24 * 60 * 60),
+                },
+                SloObjective {
+                    name: "proxy-latency".to_string(),
+                    event: "proxy_connect".to_string(),
+                    indicator: SloIndicator::Latency {
+                        threshold: Duration::from_millis(300),
+                    },
+                    objective: 95.0,
+                    window: Duration::from_secs(30 * 24 * 60 * 60),
+                },
+            ],
         }
     }
 }
@@ -381,12 +861,125 @@ impl TelemetryConfig {
             });
         }
 
+        if self.metrics.per_user.enabled && self.metrics.per_user.max_users == 0 {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Per-user metrics need a user label cap above zero".to_string(),
+            });
+        }
+
+        if self.admin.enabled && self.admin.port == 0 {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Admin port must be specified when the admin endpoint is enabled"
+                    .to_string(),
+            });
+        }
+
+        if self.health.endpoint.enabled && self.health.endpoint.port == 0 {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Health endpoint port must be specified when it is enabled".to_string(),
+            });
+        }
+
+        if self.billing.enabled && self.billing.webhook.is_none() && self.billing.s3.is_none() {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "A webhook or S3 bucket must be configured when billing export is enabled"
+                    .to_string(),
+            });
+        }
+
+        if self.alerting.enabled
+            && self.alerting.webhook.is_none()
+            && self.alerting.email.is_none()
+            && self.alerting.telegram.is_none()
+        {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "A webhook, email or Telegram notifier must be configured when alerting is enabled"
+                    .to_string(),
+            });
+        }
+
+        if self.log_shipping.enabled
+            && self.log_shipping.loki.is_none()
+            && self.log_shipping.elasticsearch.is_none()
+        {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Loki or Elasticsearch must be configured when log shipping is enabled"
+                    .to_string(),
+            });
+        }
+
+        if self.log_shipping.enabled && self.log_shipping.batch_size == 0 {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Log shipping batch size must be above zero".to_string(),
+            });
+        }
+
+        let mut rule_names = std::collections::HashSet::new();
+        if let Some(rule) = self
+            .alerting
+            .rules
+            .iter()
+            .find(|rule| !rule_names.insert(rule.name.as_str()))
+        {
+            return Err(crate::TelemetryError::ConfigError {
+                message: format!("Duplicate alert rule name: {}", rule.name),
+            });
+        }
+
+        let mut objective_names = std::collections::HashSet::new();
+        for objective in &self.slo.objectives {
+            if !objective_names.insert(objective.name.as_str()) {
+                return Err(crate::TelemetryError::ConfigError {
+                    message: format!("Duplicate SLO name: {}", objective.name),
+                });
+            }
+            if !(objective.objective > 0.0 && objective.objective < 100.0) {
+                return Err(crate::TelemetryError::ConfigError {
+                    message: format!(
+                        "SLO {} objective must be between 0 and 100 percent",
+                        objective.name
+                    ),
+                });
+            }
+            if objective.window.is_zero() {
+                return Err(crate::TelemetryError::ConfigError {
+                    message: format!("SLO {} window must be above zero", objective.name),
+                });
+            }
+        }
+
         if self.dashboard_enabled && self.dashboard.port == 0 {
             return Err(crate::TelemetryError::ConfigError {
                 message: "Dashboard port must be specified when dashboard is enabled".to_string(),
             });
         }
 
+        let dashboard = &self.dashboard;
+        let has_password = dashboard.username.is_some() && dashboard.password.is_some();
+        if dashboard.auth_enabled && !has_password && dashboard.auth.identity_jwt_secret.is_none() {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Dashboard authentication needs a username and password or a vpn-identity JWT secret".to_string(),
+            });
+        }
+        if dashboard.auth.session_ttl.is_zero() {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Dashboard session TTL must be above zero".to_string(),
+            });
+        }
+        if dashboard.tls.cert_path.is_some() != dashboard.tls.key_path.is_some() {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Dashboard TLS needs both cert_path and key_path, or neither for a self-signed certificate".to_string(),
+            });
+        }
+        if dashboard.tls.enabled
+            && dashboard.tls.cert_path.is_none()
+            && dashboard.tls.self_signed_names.is_empty()
+        {
+            return Err(crate::TelemetryError::ConfigError {
+                message: "Self-signed dashboard certificate needs at least one name".to_string(),
+            });
+        }
+
         Ok(())
     }
 }
@@ -400,6 +993,7 @@ pub mod env {
     pub const JAEGER_ENDPOINT: &str = "VPN_JAEGER_ENDPOINT";
     pub const PROMETHEUS_PORT: &str = "VPN_PROMETHEUS_PORT";
     pub const DASHBOARD_PORT: &str = "VPN_DASHBOARD_PORT";
+    pub const ADMIN_PORT: &str = "VPN_TELEMETRY_ADMIN_PORT";
 }
 
 /// Load configuration from environment variables
@@ -440,6 +1034,12 @@ pub fn from_env() -> TelemetryConfig {
         }
     }
 
+    if let Ok(admin_port) = std::env::var(env::ADMIN_PORT) {
+        if let Ok(port) = admin_port.parse() {
+            config.admin.port = port;
+        }
+    }
+
     config
 }
 
@@ -465,6 +1065,34 @@ mod tests {
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_slo_validation() {
+        let mut config = TelemetryConfig::default();
+        config.slo.objectives[1].name = config.slo.objectives[0].name.clone();
+        assert!(config.validate().is_err());
+
+        let mut config = TelemetryConfig::default();
+        config.slo.objectives[0].objective = 100.0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_dashboard_access_validation() {
+        let mut config = TelemetryConfig::default();
+        config.dashboard.auth_enabled = true;
+        assert!(config.validate().is_err());
+
+        config.dashboard.auth.identity_jwt_secret = Some("secret".to_string());
+        assert!(config.validate().is_ok());
+
+        config.dashboard.tls.enabled = true;
+        config.dashboard.tls.cert_path = Some(PathBuf::from("/etc/vpn/dashboard.crt"));
+        assert!(config.validate().is_err());
+
+        config.dashboard.tls.key_path = Some(PathBuf::from("/etc/vpn/dashboard.key"));
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn test_config_serialization() {
         let config = TelemetryConfig::default();
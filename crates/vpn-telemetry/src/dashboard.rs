@@ -1,11 +1,13 @@
 //! Real-time performance dashboard
 
+use crate::dashboard_access::{self, AuthFailure, DashboardAuth, DashboardView, Principal};
 use crate::{config::TelemetryConfig, error::Result, TelemetryError};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
 use tokio::sync::RwLock;
+use tokio_rustls::TlsAcceptor;
 use tracing::{info, warn};
 
 /// Dashboard configuration
@@ -172,6 +174,7 @@ pub struct DashboardManager {
     dashboard_data: Arc<RwLock<DashboardData>>,
     running: Arc<RwLock<bool>>,
     server_handle: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
+    auth: Arc<DashboardAuth>,
 }
 
 impl DashboardManager {
@@ -184,6 +187,7 @@ impl DashboardManager {
             dashboard_data,
             running: Arc::new(RwLock::new(false)),
             server_handle: Arc::new(RwLock::new(None)),
+            auth: Arc::new(DashboardAuth::new(&config.dashboard)),
         })
     }
 
@@ -204,6 +208,12 @@ impl DashboardManager {
             self.config.dashboard.bind_address, self.config.dashboard.port
         );
 
+        let tls = if self.config.dashboard.tls.enabled {
+            Some(dashboard_access::tls_acceptor(&self.config.dashboard.tls)?)
+        } else {
+            None
+        };
+
         info!("Starting dashboard server on {}", bind_addr);
 
         let listener =
@@ -216,9 +226,12 @@ impl DashboardManager {
         let dashboard_data = self.dashboard_data.clone();
         let config = self.config.clone();
         let running_flag = self.running.clone();
+        let auth = self.auth.clone();
 
         let server_task = tokio::spawn(async move {
-            if let Err(e) = Self::run_server(listener, dashboard_data, config, running_flag).await {
+            if let Err(e) =
+                Self::run_server(listener, tls, dashboard_data, config, auth, running_flag).await
+            {
                 warn!("Dashboard server error: {}", e);
             }
         });
@@ -248,15 +261,43 @@ impl DashboardManager {
         Ok(())
     }
 
+    /// Enable or disable the dashboard, starting or stopping the server
+    /// when `active` (the telemetry system is running)
+    pub async fn set_enabled(&mut self, enabled: bool, active: bool) -> Result<()> {
+        self.config.dashboard_enabled = enabled;
+
+        if enabled && active {
+            self.start().await
+        } else {
+            self.stop().await
+        }
+    }
+
+    /// Whether the dashboard is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.config.dashboard_enabled
+    }
+
+    /// Whether the dashboard server is running
+    pub async fn is_running(&self) -> bool {
+        *self.running.read().await
+    }
+
     /// Get the dashboard URL
     pub fn get_url(&self) -> Option<String> {
         if !self.config.dashboard_enabled {
             return None;
         }
 
+        let scheme = if self.config.dashboard.tls.enabled {
+            "https"
+        } else {
+            "http"
+        };
+
         Some(format!(
-            "http://{}:{}",
-            self.config.dashboard.bind_address, self.config.dashboard.port
+            "{}://{}:{}",
+            scheme, self.config.dashboard.bind_address, self.config.dashboard.port
         ))
     }
 
@@ -275,8 +316,10 @@ impl DashboardManager {
     /// Run the HTTP server
     async fn run_server(
         listener: TcpListener,
+        tls: Option<TlsAcceptor>,
         dashboard_data: Arc<RwLock<DashboardData>>,
         config: TelemetryConfig,
+        auth: Arc<DashboardAuth>,
         running: Arc<RwLock<bool>>,
     ) -> Result<()> {
         // use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -290,11 +333,33 @@ impl DashboardManager {
 
                     let dashboard_data = dashboard_data.clone();
                     let config = config.clone();
+                    let auth = auth.clone();
+                    let tls = tls.clone();
 
                     tokio::spawn(async move {
-                        if let Err(e) =
-                            Self::handle_connection(&mut stream, dashboard_data, config).await
-                        {
+                        let result = match tls {
+                            Some(acceptor) => match acceptor.accept(stream).await {
+                                Ok(mut stream) => {
+                                    Self::handle_connection(
+                                        &mut stream,
+                                        dashboard_data,
+                                        config,
+                                        auth,
+                                    )
+                                    .await
+                                }
+                                Err(e) => {
+                                    warn!("Dashboard TLS handshake with {} failed: {}", addr, e);
+                                    return;
+                                }
+                            },
+                            None => {
+                                Self::handle_connection(&mut stream, dashboard_data, config, auth)
+                                    .await
+                            }
+                        };
+
+                        if let Err(e) = result {
                             warn!("Error handling dashboard connection: {}", e);
                         }
                     });
@@ -313,31 +378,58 @@ impl DashboardManager {
     }
 
     /// Handle a single HTTP connection
-    async fn handle_connection(
-        stream: &mut tokio::net::TcpStream,
+    async fn handle_connection<S>(
+        stream: &mut S,
         dashboard_data: Arc<RwLock<DashboardData>>,
         config: TelemetryConfig,
-    ) -> Result<()> {
+        auth: Arc<DashboardAuth>,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
         let mut reader = BufReader::new(&mut *stream);
         let mut request_line = String::new();
         reader.read_line(&mut request_line).await?;
 
-        let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("GET");
+        let path = parts.next().unwrap_or("/");
 
-        // Read and discard the rest of the HTTP headers
+        // Keep the credentials, discard the other HTTP headers
+        let mut authorization = None;
+        let mut cookie = None;
         let mut line = String::new();
         while reader.read_line(&mut line).await? > 0 {
             if line.trim().is_empty() {
                 break;
             }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                } else if name.eq_ignore_ascii_case("cookie") {
+                    cookie = Some(value.trim().to_string());
+                }
+            }
             line.clear();
         }
 
-        let response = match path {
-            "/" => Self::serve_dashboard_html(&config).await?,
-            "/api/data" => Self::serve_dashboard_data(dashboard_data).await?,
-            "/api/health" => Self::serve_health_check().await?,
-            _ => Self::serve_404().await?,
+        let response = match (method, path) {
+            (_, "/api/health") => Self::serve_health_check().await?,
+            ("DELETE", "/api/session") => Self::serve_logout(&auth, cookie.as_deref()).await?,
+            _ => match auth
+                .authenticate(authorization.as_deref(), cookie.as_deref())
+                .await
+            {
+                Ok(principal) => match (method, path) {
+                    ("POST", "/api/session") => Self::serve_session(&auth, principal).await?,
+                    (_, "/") => Self::serve_dashboard_html(&config).await?,
+                    (_, "/api/data") => {
+                        Self::serve_dashboard_data(dashboard_data, principal.view).await?
+                    }
+                    _ => Self::serve_404().await?,
+                },
+                Err(failure) => Self::serve_auth_failure(&config, failure).await?,
+            },
         };
 
         stream.write_all(response.as_bytes()).await?;
@@ -609,10 +701,13 @@ impl DashboardManager {
         Ok(response)
     }
 
-    /// Serve dashboard data as JSON
-    async fn serve_dashboard_data(dashboard_data: Arc<RwLock<DashboardData>>) -> Result<String> {
-        let data = dashboard_data.read().await;
-        let json = serde_json::to_string(&*data)?;
+    /// Serve dashboard data as JSON, limited to what `view` may see
+    async fn serve_dashboard_data(
+        dashboard_data: Arc<RwLock<DashboardData>>,
+        view: DashboardView,
+    ) -> Result<String> {
+        let data = dashboard_access::restrict(&*dashboard_data.read().await, view);
+        let json = serde_json::to_string(&data)?;
 
         let response = format!(
             "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
@@ -640,6 +735,63 @@ impl DashboardManager {
         Ok(response)
     }
 
+    /// Start a session for an authenticated user and set its cookie
+    async fn serve_session(auth: &DashboardAuth, principal: Principal) -> Result<String> {
+        let json = serde_json::to_string(&principal)?;
+        let id = auth.create_session(principal).await;
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nSet-Cookie: {}\r\nContent-Length: {}\r\n\r\n{}",
+            auth.session_cookie(&id),
+            json.len(),
+            json
+        );
+
+        Ok(response)
+    }
+
+    /// End the caller's session and clear its cookie
+    async fn serve_logout(auth: &DashboardAuth, cookie: Option<&str>) -> Result<String> {
+        auth.end_session(cookie).await;
+
+        let response = format!(
+            "HTTP/1.1 204 No Content\r\nSet-Cookie: {}\r\nContent-Length: 0\r\n\r\n",
+            auth.expired_session_cookie()
+        );
+
+        Ok(response)
+    }
+
+    /// Serve 401 with the accepted challenges, or 403 for users without a
+    /// dashboard role
+    async fn serve_auth_failure(config: &TelemetryConfig, failure: AuthFailure) -> Result<String> {
+        let (status, challenge, message) = match failure {
+            AuthFailure::Unauthenticated(message) => {
+                let mut challenges = Vec::new();
+                if config.dashboard.auth.identity_jwt_secret.is_some() {
+                    challenges.push("Bearer".to_string());
+                }
+                if config.dashboard.username.is_some() {
+                    challenges.push(format!("Basic realm=\"{}\"", config.dashboard.title));
+                }
+                let challenge = format!("WWW-Authenticate: {}\r\n", challenges.join(", "));
+                ("401 Unauthorized", challenge, message)
+            }
+            AuthFailure::Forbidden(message) => ("403 Forbidden", String::new(), message),
+        };
+
+        let json = serde_json::json!({ "error": message }).to_string();
+        let response = format!(
+            "HTTP/1.1 {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            challenge,
+            json.len(),
+            json
+        );
+
+        Ok(response)
+    }
+
     /// Serve 404 response
     async fn serve_404() -> Result<String> {
         let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
@@ -0,0 +1,510 @@
+//! Authentication, per-role views and TLS for the dashboard
+//!
+//! Requests authenticate with a vpn-identity access token
+//! (`Authorization: Bearer`), the configured dashboard username and password
+//! (`Authorization: Basic`), or the session cookie returned by
+//! `POST /api/session` for either of those. Each vpn-identity role maps to a
+//! [`DashboardView`]; the metrics view replaces user identities with
+//! pseudonyms, so an auditor sees usage without seeing who it belongs to.
+
+use crate::config::{DashboardConfig, DashboardTlsConfig};
+use crate::dashboard::DashboardData;
+use crate::{error::Result, TelemetryError};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::RwLock;
+use tokio_rustls::TlsAcceptor;
+use uuid::Uuid;
+
+/// Name of the dashboard session cookie
+pub const SESSION_COOKIE: &str = "vpn_dashboard_session";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What an authenticated user may see on the dashboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DashboardView {
+    /// Metrics with user identities replaced by pseudonyms
+    Metrics,
+    /// Everything, including user identities
+    Full,
+}
+
+/// An authenticated dashboard user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Principal {
+    pub subject: String,
+    pub view: DashboardView,
+}
+
+/// Why a request was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthFailure {
+    /// Missing or invalid credentials
+    Unauthenticated(String),
+    /// Valid credentials without a dashboard role
+    Forbidden(String),
+}
+
+/// The claims of a vpn-identity access token the dashboard relies on
+#[derive(Debug, Clone, Deserialize)]
+struct IdentityClaims {
+    sub: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    roles: Vec<String>,
+    exp: i64,
+    #[serde(default)]
+    iss: String,
+    #[serde(default)]
+    aud: Vec<String>,
+}
+
+struct Session {
+    principal: Principal,
+    expires_at: Instant,
+}
+
+/// Credential checks and session store for the dashboard
+pub struct DashboardAuth {
+    config: DashboardConfig,
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+impl DashboardAuth {
+    pub fn new(config: &DashboardConfig) -> Self {
+        Self {
+            config: config.clone(),
+            sessions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Whether requests must authenticate
+    pub fn is_enabled(&self) -> bool {
+        self.config.auth_enabled
+    }
+
+    /// Authenticate a request from its `Authorization` and `Cookie` headers.
+    /// With authentication disabled every request gets the full view.
+    pub async fn authenticate(
+        &self,
+        authorization: Option<&str>,
+        cookie: Option<&str>,
+    ) -> std::result::Result<Principal, AuthFailure> {
+        if !self.config.auth_enabled {
+            return Ok(Principal {
+                subject: "anonymous".to_string(),
+                view: DashboardView::Full,
+            });
+        }
+
+        if let Some(authorization) = authorization {
+            return if let Some(token) = authorization.strip_prefix("Bearer ") {
+                self.verify_token(token.trim(), chrono::Utc::now().timestamp())
+            } else if let Some(encoded) = authorization.strip_prefix("Basic ") {
+                self.verify_password(encoded.trim())
+            } else {
+                Err(AuthFailure::Unauthenticated(
+                    "Unsupported authorization scheme".to_string(),
+                ))
+            };
+        }
+
+        match cookie.and_then(session_id) {
+            Some(id) => self
+                .session(id)
+                .await
+                .ok_or_else(|| AuthFailure::Unauthenticated("Session expired".to_string())),
+            None => Err(AuthFailure::Unauthenticated(
+                "Authentication required".to_string(),
+            )),
+        }
+    }
+
+    /// Start a session for `principal` and return its id
+    pub async fn create_session(&self, principal: Principal) -> String {
+        let id = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let now = Instant::now();
+
+        let mut sessions = self.sessions.write().await;
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            id.clone(),
+            Session {
+                principal,
+                expires_at: now + self.config.auth.session_ttl,
+            },
+        );
+        id
+    }
+
+    /// End the session named in a `Cookie` header, if any
+    pub async fn end_session(&self, cookie: Option<&str>) {
+        if let Some(id) = cookie.and_then(session_id) {
+            self.sessions.write().await.remove(id);
+        }
+    }
+
+    /// `Set-Cookie` value carrying session `id`
+    pub fn session_cookie(&self, id: &str) -> String {
+        format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}",
+            SESSION_COOKIE,
+            id,
+            self.config.auth.session_ttl.as_secs(),
+            self.secure_attribute()
+        )
+    }
+
+    /// `Set-Cookie` value removing the session cookie
+    pub fn expired_session_cookie(&self) -> String {
+        format!(
+            "{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0{}",
+            SESSION_COOKIE,
+            self.secure_attribute()
+        )
+    }
+
+    fn secure_attribute(&self) -> &'static str {
+        if self.config.tls.enabled {
+            "; Secure"
+        } else {
+            ""
+        }
+    }
+
+    async fn session(&self, id: &str) -> Option<Principal> {
+        self.sessions
+            .read()
+            .await
+            .get(id)
+            .filter(|session| session.expires_at > Instant::now())
+            .map(|session| session.principal.clone())
+    }
+
+    /// Check an HS256 access token issued by vpn-identity at unix time `now`
+    fn verify_token(&self, token: &str, now: i64) -> std::result::Result<Principal, AuthFailure> {
+        let unauthenticated = |message: &str| AuthFailure::Unauthenticated(message.to_string());
+
+        let secret = self
+            .config
+            .auth
+            .identity_jwt_secret
+            .as_deref()
+            .ok_or_else(|| unauthenticated("Token authentication is not configured"))?;
+
+        let mut parts = token.split('.');
+        let (encoded_header, payload, signature) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(header), Some(payload), Some(signature), None) => {
+                    (header, payload, signature)
+                }
+                _ => return Err(unauthenticated("Malformed token")),
+            };
+
+        let header: serde_json::Value = URL_SAFE_NO_PAD
+            .decode(encoded_header)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| unauthenticated("Malformed token header"))?;
+        if header["alg"] != "HS256" {
+            return Err(unauthenticated("Unsupported token algorithm"));
+        }
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| unauthenticated("Malformed token signature"))?;
+        let mut mac =
+            HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}.{}", encoded_header, payload).as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| unauthenticated("Invalid token signature"))?;
+
+        let claims: IdentityClaims = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| unauthenticated("Malformed token claims"))?;
+
+        if claims.exp <= now {
+            return Err(unauthenticated("Token expired"));
+        }
+        if claims.iss != self.config.auth.identity_issuer {
+            return Err(unauthenticated("Token issuer not trusted"));
+        }
+        if let Some(audience) = &self.config.auth.identity_audience {
+            if !claims.aud.contains(audience) {
+                return Err(unauthenticated("Token not issued for this audience"));
+            }
+        }
+
+        let subject = if claims.username.is_empty() {
+            claims.sub
+        } else {
+            claims.username
+        };
+        let view = claims
+            .roles
+            .iter()
+            .filter_map(|role| self.config.auth.role_views.get(role))
+            .max()
+            .copied()
+            .ok_or_else(|| AuthFailure::Forbidden(format!("{} has no dashboard role", subject)))?;
+
+        Ok(Principal { subject, view })
+    }
+
+    /// Check `Basic` credentials against the configured username and password
+    fn verify_password(&self, encoded: &str) -> std::result::Result<Principal, AuthFailure> {
+        let invalid = || AuthFailure::Unauthenticated("Invalid username or password".to_string());
+
+        let (Some(username), Some(password)) = (&self.config.username, &self.config.password)
+        else {
+            return Err(AuthFailure::Unauthenticated(
+                "Password authentication is not configured".to_string(),
+            ));
+        };
+
+        let decoded = STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+        let (given_username, given_password) = decoded.split_once(':').ok_or_else(invalid)?;
+
+        // Evaluate both comparisons so timing does not reveal which one failed
+        let username_ok = constant_time_eq(given_username.as_bytes(), username.as_bytes());
+        let password_ok = constant_time_eq(given_password.as_bytes(), password.as_bytes());
+        if !(username_ok & password_ok) {
+            return Err(invalid());
+        }
+
+        Ok(Principal {
+            subject: username.clone(),
+            view: DashboardView::Full,
+        })
+    }
+}
+
+/// Copy of `data` limited to what `view` may see
+pub fn restrict(data: &DashboardData, view: DashboardView) -> DashboardData {
+    let mut data = data.clone();
+    if view < DashboardView::Full {
+        for (rank, user) in data.user_activity.top_users.iter_mut().enumerate() {
+            user.user_id = format!("user-{}", rank + 1);
+        }
+    }
+    data
+}
+
+/// TLS acceptor for the dashboard, using the configured certificate or a
+/// freshly generated self-signed one
+pub fn tls_acceptor(config: &DashboardTlsConfig) -> Result<TlsAcceptor> {
+    let (certs, key) = match (&config.cert_path, &config.key_path) {
+        (Some(cert_path), Some(key_path)) => (load_certs(cert_path)?, load_key(key_path)?),
+        _ => self_signed(&config.self_signed_names)?,
+    };
+
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| TelemetryError::DashboardError {
+            message: format!("Invalid dashboard certificate: {}", e),
+        })?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn self_signed(names: &[String]) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert = rcgen::generate_simple_self_signed(names.to_vec()).map_err(|e| {
+        TelemetryError::DashboardError {
+            message: format!("Failed to generate self-signed certificate: {}", e),
+        }
+    })?;
+    let der = cert
+        .serialize_der()
+        .map_err(|e| TelemetryError::DashboardError {
+            message: format!("Failed to serialize self-signed certificate: {}", e),
+        })?;
+    let key = PrivatePkcs8KeyDer::from(cert.serialize_private_key_der());
+
+    Ok((vec![CertificateDer::from(der)], PrivateKeyDer::Pkcs8(key)))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = read_file(path)?;
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| TelemetryError::DashboardError {
+            message: format!("Invalid PEM in {}: {}", path.display(), e),
+        })?;
+
+    if certs.is_empty() {
+        return Err(TelemetryError::DashboardError {
+            message: format!("No certificates found in {}", path.display()),
+        });
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let pem = read_file(path)?;
+    rustls_pemfile::private_key(&mut &pem[..])
+        .map_err(|e| TelemetryError::DashboardError {
+            message: format!("Invalid PEM in {}: {}", path.display(), e),
+        })?
+        .ok_or_else(|| TelemetryError::DashboardError {
+            message: format!("No private key found in {}", path.display()),
+        })
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| TelemetryError::DashboardError {
+        message: format!("Failed to read {}: {}", path.display(), e),
+    })
+}
+
+/// Session id from a `Cookie` header
+fn session_id(cookie: &str) -> Option<&str> {
+    cookie
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dashboard::UserStats;
+
+    fn sign(claims: serde_json::Value, secret: &str) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{}.{}", header, payload).as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{}.{}.{}", header, payload, signature)
+    }
+
+    fn claims(roles: &[&str], exp: i64) -> serde_json::Value {
+        serde_json::json!({
+            "sub": "0b6c1f0e-3c55-4f4e-9d1a-6a1f2c3d4e5f",
+            "email": "alice@example.com",
+            "username": "alice",
+            "roles": roles,
+            "exp": exp,
+            "iat": 0,
+            "iss": "vpn-identity",
+            "aud": ["vpn-services"],
+        })
+    }
+
+    fn auth() -> DashboardAuth {
+        let mut config = DashboardConfig {
+            auth_enabled: true,
+            username: Some("admin".to_string()),
+            password: Some("hunter2".to_string()),
+            ..DashboardConfig::default()
+        };
+        config.auth.identity_jwt_secret = Some("identity-secret".to_string());
+        DashboardAuth::new(&config)
+    }
+
+    #[tokio::test]
+    async fn test_identity_tokens_map_roles_to_views() {
+        let auth = auth();
+        let now = 1_700_000_000;
+
+        let token = sign(claims(&["auditor"], now + 60), "identity-secret");
+        let principal = auth.verify_token(&token, now).unwrap();
+        assert_eq!(principal.subject, "alice");
+        assert_eq!(principal.view, DashboardView::Metrics);
+
+        // The broadest view of any role wins
+        let token = sign(claims(&["auditor", "admin"], now + 60), "identity-secret");
+        assert_eq!(
+            auth.verify_token(&token, now).unwrap().view,
+            DashboardView::Full
+        );
+
+        let token = sign(claims(&["user"], now + 60), "identity-secret");
+        assert!(matches!(
+            auth.verify_token(&token, now),
+            Err(AuthFailure::Forbidden(_))
+        ));
+
+        let token = sign(claims(&["admin"], now - 1), "identity-secret");
+        assert!(matches!(
+            auth.verify_token(&token, now),
+            Err(AuthFailure::Unauthenticated(_))
+        ));
+
+        let token = sign(claims(&["admin"], now + 60), "other-secret");
+        assert!(matches!(
+            auth.verify_token(&token, now),
+            Err(AuthFailure::Unauthenticated(_))
+        ));
+
+        // Password login opens a session usable through the cookie alone
+        let basic = format!("Basic {}", STANDARD.encode("admin:hunter2"));
+        let principal = auth.authenticate(Some(&basic), None).await.unwrap();
+        let id = auth.create_session(principal.clone()).await;
+        let cookie = format!("theme=dark; {}={}", SESSION_COOKIE, id);
+        assert_eq!(
+            auth.authenticate(None, Some(&cookie)).await.unwrap(),
+            principal
+        );
+
+        auth.end_session(Some(&cookie)).await;
+        assert!(auth.authenticate(None, Some(&cookie)).await.is_err());
+        assert!(auth.authenticate(None, None).await.is_err());
+
+        let wrong = format!("Basic {}", STANDARD.encode("admin:hunter3"));
+        assert!(auth.authenticate(Some(&wrong), None).await.is_err());
+    }
+
+    #[test]
+    fn test_metrics_view_hides_user_identities() {
+        let mut data = DashboardData::default();
+        data.user_activity.top_users = vec![UserStats {
+            user_id: "alice".to_string(),
+            sessions: 3,
+            data_transferred: 1024,
+            last_seen: chrono::Utc::now(),
+        }];
+
+        let restricted = restrict(&data, DashboardView::Metrics);
+        assert_eq!(restricted.user_activity.top_users[0].user_id, "user-1");
+        assert_eq!(restricted.user_activity.top_users[0].sessions, 3);
+
+        let full = restrict(&data, DashboardView::Full);
+        assert_eq!(full.user_activity.top_users[0].user_id, "alice");
+    }
+
+    #[test]
+    fn test_self_signed_tls_acceptor() {
+        assert!(tls_acceptor(&DashboardTlsConfig {
+            enabled: true,
+            ..DashboardTlsConfig::default()
+        })
+        .is_ok());
+    }
+}
@@ -483,6 +483,15 @@ impl TelemetryExporter for HttpExporter {
     }
 }
 
+/// Exporter state reported by the admin endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExporterStatus {
+    pub name: String,
+    pub endpoint: String,
+    pub enabled: bool,
+    pub healthy: bool,
+}
+
 /// Exporter manager that coordinates multiple exporters
 pub struct ExporterManager {
     exporters: Vec<Box<dyn TelemetryExporter + Send + Sync>>,
@@ -546,6 +555,28 @@ impl ExporterManager {
     pub fn list_exporters(&self) -> Vec<&str> {
         self.exporters.iter().map(|e| e.name()).collect()
     }
+
+    /// Configuration and health of every exporter
+    pub async fn exporter_status(&self) -> Vec<ExporterStatus> {
+        let mut statuses = Vec::with_capacity(self.exporters.len());
+
+        for exporter in &self.exporters {
+            let config = exporter.config();
+            statuses.push(ExporterStatus {
+                name: exporter.name().to_string(),
+                endpoint: config.endpoint.clone(),
+                enabled: config.enabled,
+                healthy: exporter.health_check().await.unwrap_or(false),
+            });
+        }
+
+        statuses
+    }
+
+    /// Whether any exporter is configured
+    pub fn is_empty(&self) -> bool {
+        self.exporters.is_empty()
+    }
 }
 
 impl Default for ExporterManager {
@@ -1,5 +1,6 @@
 //! Health monitoring and collection
 
+use crate::host::HostSampler;
 use crate::{config::TelemetryConfig, error::Result, TelemetryError, TelemetryProvider};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -45,11 +46,44 @@ pub struct ComponentHealth {
 pub struct SystemMetrics {
     pub cpu_usage_percent: f64,
     pub memory_usage_percent: f64,
+    /// Disk usage of the install path
     pub disk_usage_percent: f64,
     pub load_average: f64,
+    /// Established TCP connections
     pub network_connections: u64,
     pub open_files: u64,
     pub process_count: u64,
+    #[serde(default)]
+    pub cpu_per_core_percent: Vec<f64>,
+    #[serde(default)]
+    pub memory_used_bytes: u64,
+    #[serde(default)]
+    pub memory_total_bytes: u64,
+    #[serde(default)]
+    pub disk_used_bytes: u64,
+    #[serde(default)]
+    pub disk_total_bytes: u64,
+    /// Throughput of each interface except loopback
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceThroughput>,
+    #[serde(default)]
+    pub open_files_max: u64,
+    #[serde(default)]
+    pub conntrack_entries: u64,
+    #[serde(default)]
+    pub conntrack_max: u64,
+    #[serde(default)]
+    pub conntrack_usage_percent: f64,
+}
+
+/// Traffic counters and rates of a network interface
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InterfaceThroughput {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_bytes_per_sec: f64,
+    pub tx_bytes_per_sec: f64,
 }
 
 /// Health check configuration for a component
@@ -165,9 +199,10 @@ impl HealthCollector {
         let system_health = self.current_health.clone();
         let running_flag = self.running.clone();
         let system_interval = self.config.health.check_interval;
+        let sampler = HostSampler::new(self.config.health.install_path.clone());
 
         let system_handle = tokio::spawn(async move {
-            Self::system_metrics_loop(system_health, running_flag, system_interval).await;
+            Self::system_metrics_loop(system_health, running_flag, system_interval, sampler).await;
         });
 
         handles.push(system_handle);
@@ -388,36 +423,18 @@ impl HealthCollector {
         current_health: Arc<RwLock<SystemHealth>>,
         running: Arc<RwLock<bool>>,
         interval: Duration,
+        mut sampler: HostSampler,
     ) {
         let mut timer = tokio::time::interval(interval);
 
         while *running.read().await {
             timer.tick().await;
 
-            if let Ok(system_metrics) = Self::collect_system_metrics().await {
-                let mut health = current_health.write().await;
-                health.system_metrics = system_metrics;
-            }
+            debug!("Collecting system metrics");
+            let system_metrics = sampler.sample();
+            current_health.write().await.system_metrics = system_metrics;
         }
     }
-
-    /// Collect system metrics
-    async fn collect_system_metrics() -> Result<SystemMetrics> {
-        // This is a simplified implementation
-        // In a real implementation, you would use system APIs to collect actual metrics
-
-        debug!("Collecting system metrics");
-
-        Ok(SystemMetrics {
-            cpu_usage_percent: 0.0,
-            memory_usage_percent: 0.0,
-            disk_usage_percent: 0.0,
-            load_average: 0.0,
-            network_connections: 0,
-            open_files: 0,
-            process_count: 0,
-        })
-    }
 }
 
 impl Default for SystemMetrics {
@@ -430,6 +447,16 @@ impl Default for SystemMetrics {
             network_connections: 0,
             open_files: 0,
             process_count: 0,
+            cpu_per_core_percent: Vec::new(),
+            memory_used_bytes: 0,
+            memory_total_bytes: 0,
+            disk_used_bytes: 0,
+            disk_total_bytes: 0,
+            interfaces: Vec::new(),
+            open_files_max: 0,
+            conntrack_entries: 0,
+            conntrack_max: 0,
+            conntrack_usage_percent: 0.0,
         }
     }
 }
@@ -452,6 +479,7 @@ impl Default for HealthCheckConfig {
 mod tests {
     use super::*;
     use crate::config::TelemetryConfig;
+    use async_trait::async_trait;
 
     struct MockTelemetryProvider {
         name: String,
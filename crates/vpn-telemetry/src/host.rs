@@ -0,0 +1,371 @@
+//! Host resource sampling
+//!
+//! [`HostSampler`] reads CPU, memory, network, file descriptor and
+//! conntrack figures from procfs and disk usage of the install path from
+//! `df`. CPU usage and NIC throughput are rates, so they are computed from
+//! the difference to the previous sample; the first sample reports CPU
+//! usage since boot and no throughput.
+
+use crate::health::{InterfaceThroughput, SystemMetrics};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+/// Received and transmitted bytes per interface
+type NetCounters = HashMap<String, (u64, u64)>;
+
+/// Jiffies a CPU spent busy and in total
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+impl CpuTimes {
+    /// Busy percentage between `previous` and this reading
+    fn usage_since(&self, previous: &CpuTimes) -> f64 {
+        let total = self.total.saturating_sub(previous.total);
+        if total == 0 {
+            return 0.0;
+        }
+        self.busy.saturating_sub(previous.busy) as f64 / total as f64 * 100.0
+    }
+}
+
+/// Samples host resources, keeping the previous reading for rates
+pub struct HostSampler {
+    proc_root: PathBuf,
+    install_path: Option<PathBuf>,
+    /// Aggregate first, then one entry per core
+    previous_cpu: Vec<CpuTimes>,
+    previous_net: Option<(Instant, NetCounters)>,
+    last: Option<SystemMetrics>,
+}
+
+impl HostSampler {
+    /// Sampler reporting disk usage of `install_path`, or of the root
+    /// filesystem when unset or missing
+    pub fn new(install_path: Option<PathBuf>) -> Self {
+        Self {
+            proc_root: PathBuf::from("/proc"),
+            install_path,
+            previous_cpu: Vec::new(),
+            previous_net: None,
+            last: None,
+        }
+    }
+
+    /// Read procfs from `proc_root` instead of `/proc`
+    pub fn with_proc_root(mut self, proc_root: impl Into<PathBuf>) -> Self {
+        self.proc_root = proc_root.into();
+        self
+    }
+
+    /// The most recent sample
+    pub fn last(&self) -> Option<&SystemMetrics> {
+        self.last.as_ref()
+    }
+
+    /// Take a sample; figures whose source is missing are left at zero
+    pub fn sample(&mut self) -> SystemMetrics {
+        let mut metrics = SystemMetrics::default();
+
+        if let Some(stat) = self.read("stat") {
+            let cpu = parse_cpu_times(&stat);
+            let usage: Vec<f64> = cpu
+                .iter()
+                .enumerate()
+                .map(|(i, times)| {
+                    let previous = self.previous_cpu.get(i).copied().unwrap_or_default();
+                    times.usage_since(&previous)
+                })
+                .collect();
+
+            if let Some((aggregate, cores)) = usage.split_first() {
+                metrics.cpu_usage_percent = *aggregate;
+                metrics.cpu_per_core_percent = cores.to_vec();
+            }
+            self.previous_cpu = cpu;
+        }
+
+        if let Some(meminfo) = self.read("meminfo") {
+            let (used, total) = parse_meminfo(&meminfo);
+            metrics.memory_used_bytes = used;
+            metrics.memory_total_bytes = total;
+            metrics.memory_usage_percent = percent(used, total);
+        }
+
+        if let Some(loadavg) = self.read("loadavg") {
+            let mut fields = loadavg.split_whitespace();
+            metrics.load_average = fields.next().and_then(|v| v.parse().ok()).unwrap_or(0.0);
+            // Fourth field is running/total scheduling entities
+            metrics.process_count = fields
+                .nth(2)
+                .and_then(|v| v.split_once('/'))
+                .and_then(|(_, total)| total.parse().ok())
+                .unwrap_or(0);
+        }
+
+        let disk_path = self
+            .install_path
+            .as_deref()
+            .filter(|path| path.exists())
+            .unwrap_or(Path::new("/"));
+        if let Some((used, total)) = disk_usage(disk_path) {
+            metrics.disk_used_bytes = used;
+            metrics.disk_total_bytes = total;
+            metrics.disk_usage_percent = percent(used, total);
+        }
+
+        if let Some(net_dev) = self.read("net/dev") {
+            metrics.interfaces = self.throughput(parse_net_dev(&net_dev));
+        }
+
+        metrics.network_connections = ["net/tcp", "net/tcp6"]
+            .iter()
+            .filter_map(|file| self.read(file))
+            .map(|table| count_established(&table))
+            .sum();
+
+        if let Some(file_nr) = self.read("sys/fs/file-nr") {
+            let fields: Vec<u64> = file_nr
+                .split_whitespace()
+                .filter_map(|v| v.parse().ok())
+                .collect();
+            if let [allocated, free, max] = fields[..] {
+                metrics.open_files = allocated.saturating_sub(free);
+                metrics.open_files_max = max;
+            }
+        }
+
+        let read_u64 = |file: &str| self.read(file).and_then(|v| v.trim().parse::<u64>().ok());
+        if let (Some(entries), Some(max)) = (
+            read_u64("sys/net/netfilter/nf_conntrack_count"),
+            read_u64("sys/net/netfilter/nf_conntrack_max"),
+        ) {
+            metrics.conntrack_entries = entries;
+            metrics.conntrack_max = max;
+            metrics.conntrack_usage_percent = percent(entries, max);
+        }
+
+        self.last = Some(metrics.clone());
+        metrics
+    }
+
+    fn read(&self, file: &str) -> Option<String> {
+        fs::read_to_string(self.proc_root.join(file)).ok()
+    }
+
+    /// Per-interface rates since the previous sample
+    fn throughput(&mut self, counters: NetCounters) -> Vec<InterfaceThroughput> {
+        let now = Instant::now();
+        let elapsed = self
+            .previous_net
+            .as_ref()
+            .map(|(at, _)| now.duration_since(*at).as_secs_f64());
+
+        let mut interfaces: Vec<InterfaceThroughput> = counters
+            .iter()
+            .map(|(name, &(rx, tx))| {
+                let previous = self
+                    .previous_net
+                    .as_ref()
+                    .and_then(|(_, previous)| previous.get(name));
+                let rate = |current: u64, before: u64| match elapsed {
+                    Some(secs) if secs > 0.0 => current.saturating_sub(before) as f64 / secs,
+                    _ => 0.0,
+                };
+
+                InterfaceThroughput {
+                    name: name.clone(),
+                    rx_bytes: rx,
+                    tx_bytes: tx,
+                    rx_bytes_per_sec: previous.map_or(0.0, |&(before, _)| rate(rx, before)),
+                    tx_bytes_per_sec: previous.map_or(0.0, |&(_, before)| rate(tx, before)),
+                }
+            })
+            .collect();
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+        self.previous_net = Some((now, counters));
+        interfaces
+    }
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        used as f64 / total as f64 * 100.0
+    }
+}
+
+/// `cpu` and `cpuN` lines of `/proc/stat`, aggregate first
+fn parse_cpu_times(stat: &str) -> Vec<CpuTimes> {
+    stat.lines()
+        .filter(|line| line.starts_with("cpu"))
+        .map(|line| {
+            // user nice system idle iowait irq softirq steal
+            let values: Vec<u64> = line
+                .split_whitespace()
+                .skip(1)
+                .take(8)
+                .filter_map(|v| v.parse().ok())
+                .collect();
+            let total: u64 = values.iter().sum();
+            let idle = values.get(3).copied().unwrap_or(0) + values.get(4).copied().unwrap_or(0);
+            CpuTimes {
+                busy: total.saturating_sub(idle),
+                total,
+            }
+        })
+        .collect()
+}
+
+/// Used and total memory in bytes
+fn parse_meminfo(meminfo: &str) -> (u64, u64) {
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find(|line| line.starts_with(name))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|kb| kb.parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+            .unwrap_or(0)
+    };
+
+    let total = field("MemTotal:");
+    (total.saturating_sub(field("MemAvailable:")), total)
+}
+
+/// Received and transmitted byte counters per interface, loopback excluded
+fn parse_net_dev(net_dev: &str) -> NetCounters {
+    net_dev
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (name, counters) = line.split_once(':')?;
+            let name = name.trim();
+            if name == "lo" {
+                return None;
+            }
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .filter_map(|v| v.parse().ok())
+                .collect();
+            Some((name.to_string(), (*counters.first()?, *counters.get(8)?)))
+        })
+        .collect()
+}
+
+/// Rows of `/proc/net/tcp` in the ESTABLISHED state
+fn count_established(table: &str) -> u64 {
+    table
+        .lines()
+        .skip(1)
+        .filter(|line| line.split_whitespace().nth(3) == Some("01"))
+        .count() as u64
+}
+
+/// Used and total bytes of the filesystem holding `path`
+fn disk_usage(path: &Path) -> Option<(u64, u64)> {
+    let output = Command::new("df")
+        .arg("-B1")
+        .arg("-P")
+        .arg(path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fields: Vec<&str> = stdout.lines().nth(1)?.split_whitespace().collect();
+    Some((fields.get(2)?.parse().ok()?, fields.get(1)?.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_proc(root: &Path, stat: &str, rx: u64, tx: u64) {
+        fs::create_dir_all(root.join("net")).unwrap();
+        fs::create_dir_all(root.join("sys/fs")).unwrap();
+        fs::create_dir_all(root.join("sys/net/netfilter")).unwrap();
+
+        fs::write(root.join("stat"), stat).unwrap();
+        fs::write(
+            root.join("meminfo"),
+            "MemTotal:        4000000 kB\nMemFree:          500000 kB\nMemAvailable:    1000000 kB\n",
+        )
+        .unwrap();
+        fs::write(root.join("loadavg"), "0.50 0.40 0.30 2/345 6789\n").unwrap();
+        fs::write(
+            root.join("net/dev"),
+            format!(
+                "Inter-|   Receive                                                |  Transmit\n \
+                 face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n    \
+                 lo: 9999 10 0 0 0 0 0 0 9999 10 0 0 0 0 0 0\n  \
+                 eth0: {} 100 0 0 0 0 0 0 {} 80 0 0 0 0 0 0\n",
+                rx, tx
+            ),
+        )
+        .unwrap();
+        fs::write(
+            root.join("net/tcp"),
+            "  sl  local_address rem_address   st\n   \
+             0: 0100007F:0050 00000000:0000 0A\n   \
+             1: 0100007F:0050 0100007F:9C40 01\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("sys/fs/file-nr"),
+            "1536\t0\t9223372036854775807\n",
+        )
+        .unwrap();
+        fs::write(root.join("sys/net/netfilter/nf_conntrack_count"), "750\n").unwrap();
+        fs::write(root.join("sys/net/netfilter/nf_conntrack_max"), "1000\n").unwrap();
+    }
+
+    #[test]
+    fn test_sample_from_procfs() {
+        let proc_root = tempfile::tempdir().unwrap();
+        let mut sampler = HostSampler::new(None).with_proc_root(proc_root.path());
+
+        write_proc(
+            proc_root.path(),
+            "cpu  100 0 100 800 0 0 0 0\ncpu0 50 0 50 400 0 0 0 0\ncpu1 50 0 50 400 0 0 0 0\n",
+            1000,
+            500,
+        );
+        let first = sampler.sample();
+        assert_eq!(first.cpu_usage_percent, 20.0);
+        assert_eq!(first.interfaces.len(), 1);
+        assert_eq!(first.interfaces[0].rx_bytes_per_sec, 0.0);
+
+        // cpu0 fully busy and cpu1 idle since the first sample
+        write_proc(
+            proc_root.path(),
+            "cpu  200 0 100 900 0 0 0 0\ncpu0 150 0 50 400 0 0 0 0\ncpu1 50 0 50 500 0 0 0 0\n",
+            3000,
+            500,
+        );
+        let second = sampler.sample();
+        assert_eq!(second.cpu_usage_percent, 50.0);
+        assert_eq!(second.cpu_per_core_percent, vec![100.0, 0.0]);
+        assert!(second.interfaces[0].rx_bytes_per_sec > 0.0);
+        assert_eq!(second.interfaces[0].tx_bytes_per_sec, 0.0);
+
+        assert_eq!(second.memory_total_bytes, 4_096_000_000);
+        assert_eq!(second.memory_used_bytes, 3_072_000_000);
+        assert_eq!(second.memory_usage_percent, 75.0);
+        assert_eq!(second.load_average, 0.5);
+        assert_eq!(second.process_count, 345);
+        assert_eq!(second.network_connections, 1);
+        assert_eq!(second.open_files, 1536);
+        assert_eq!(second.conntrack_usage_percent, 75.0);
+        assert!(sampler.last().is_some());
+    }
+}
@@ -3,23 +3,53 @@
 //! Provides comprehensive observability for the VPN system using OpenTelemetry.
 //! Includes distributed tracing, custom metrics, and real-time dashboards.
 
+pub mod admin;
+pub mod alerting;
+pub mod billing;
 pub mod config;
 pub mod dashboard;
+pub mod dashboard_access;
 pub mod error;
 pub mod exporters;
 pub mod health;
+pub mod host;
+pub mod log_shipping;
 pub mod metrics;
 pub mod performance;
+pub mod probes;
+pub mod slo;
+pub mod timeseries;
 pub mod tracing;
 
 // Re-export commonly used types
+pub use admin::{AdminClient, AdminServer, TelemetryStatus};
+pub use alerting::{
+    AlertCondition, AlertEngine, AlertEvent, AlertNotifier, AlertRule, AlertSeverity,
+    NodeStatusSource,
+};
+pub use billing::{BillingExporter, BillingInterval, BillingSink, UsageReport, UsageSource};
 pub use config::TelemetryConfig;
 pub use dashboard::{DashboardConfig, DashboardManager};
+pub use dashboard_access::{DashboardAuth, DashboardView, Principal};
 pub use error::{Result, TelemetryError};
-pub use health::{HealthCollector, SystemHealth};
-pub use metrics::{MetricsCollector, VpnMetrics};
+pub use exporters::{ExporterManager, ExporterStatus, TelemetryExporter};
+pub use health::{HealthCollector, InterfaceThroughput, SystemHealth};
+pub use host::HostSampler;
+pub use log_shipping::{
+    LogEvent, LogFormat, LogLevel, LogParser, LogShipper, LogShippingStats, LogSink,
+};
+pub use metrics::{
+    CardinalityGuard, ConnectionProtocol, LatencyPhase, LatencySummary, MetricsCollector,
+    ProtocolLatency, UserLabeler, UserMetrics, VpnMetrics,
+};
 pub use performance::{PerformanceMetrics, PerformanceMonitor};
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+pub use performance::ebpf::{ConnectionThroughput, EbpfThroughputCollector};
+pub use probes::{HealthProbes, HealthServer, ReadinessCheck, ReadinessReport};
+pub use slo::{SloIndicator, SloObjective, SloStatus, SloTracker};
+pub use timeseries::{Resolution, SeriesPoint, TimeSeriesStore};
 pub use tracing::{TraceContext, TracingManager};
+pub use vpn_types::CorrelationId;
 
 use async_trait::async_trait;
 use std::sync::Arc;
@@ -33,6 +63,11 @@ pub struct TelemetrySystem {
     dashboard_manager: Arc<RwLock<DashboardManager>>,
     health_collector: Arc<RwLock<HealthCollector>>,
     performance_monitor: Arc<RwLock<PerformanceMonitor>>,
+    exporters: Arc<RwLock<ExporterManager>>,
+    billing: Arc<RwLock<Option<Arc<BillingExporter>>>>,
+    alerting: Arc<RwLock<Option<Arc<AlertEngine>>>>,
+    log_shipper: Arc<RwLock<Option<Arc<LogShipper>>>>,
+    health_server: Arc<RwLock<Option<HealthServer>>>,
     running: Arc<RwLock<bool>>,
 }
 
@@ -56,6 +91,11 @@ impl TelemetrySystem {
             dashboard_manager,
             health_collector,
             performance_monitor,
+            exporters: Arc::new(RwLock::new(ExporterManager::new())),
+            billing: Arc::new(RwLock::new(None)),
+            alerting: Arc::new(RwLock::new(None)),
+            log_shipper: Arc::new(RwLock::new(None)),
+            health_server: Arc::new(RwLock::new(None)),
             running: Arc::new(RwLock::new(false)),
         })
     }
@@ -92,9 +132,11 @@ impl TelemetrySystem {
         }
 
         // Start dashboard if enabled
-        if self.config.dashboard_enabled {
+        {
             let mut dashboard_manager = self.dashboard_manager.write().await;
-            dashboard_manager.start().await?;
+            if dashboard_manager.is_enabled() {
+                dashboard_manager.start().await?;
+            }
         }
 
         *running = true;
@@ -109,6 +151,26 @@ impl TelemetrySystem {
             return Ok(()); // Already stopped
         }
 
+        // Stop billing export
+        if let Some(billing) = self.billing.write().await.take() {
+            billing.stop().await?;
+        }
+
+        // Stop alert evaluation
+        if let Some(alerting) = self.alerting.write().await.take() {
+            alerting.stop().await?;
+        }
+
+        // Stop log shipping
+        if let Some(log_shipper) = self.log_shipper.write().await.take() {
+            log_shipper.stop().await?;
+        }
+
+        // Stop serving health probes
+        if let Some(health_server) = self.health_server.write().await.take() {
+            health_server.shutdown();
+        }
+
         // Stop dashboard
         {
             let mut dashboard_manager = self.dashboard_manager.write().await;
@@ -155,6 +217,24 @@ impl TelemetrySystem {
         metrics_collector.get_current_metrics().await
     }
 
+    /// Feed this node's current load into cluster health scoring, which may
+    /// drain the node; returns the resulting health score
+    pub async fn report_node_load(
+        &self,
+        coordinator: &vpn_cluster::ClusterCoordinator,
+        node_id: &vpn_cluster::NodeId,
+    ) -> Result<u8> {
+        let load = self.get_metrics().await?.node_load();
+
+        coordinator
+            .report_node_load(node_id, load)
+            .await
+            .map_err(|e| TelemetryError::OperationFailed {
+                operation: "report_node_load".to_string(),
+                message: e.to_string(),
+            })
+    }
+
     /// Get system health status
     pub async fn get_health(&self) -> Result<SystemHealth> {
         let health_collector = self.health_collector.read().await;
@@ -167,6 +247,16 @@ impl TelemetrySystem {
         performance_monitor.get_current_metrics().await
     }
 
+    /// Get performance metrics over the last `range`, at the finest
+    /// resolution still retained for it
+    pub async fn get_performance_history(
+        &self,
+        range: std::time::Duration,
+    ) -> Result<(Resolution, Vec<PerformanceMetrics>)> {
+        let performance_monitor = self.performance_monitor.read().await;
+        performance_monitor.get_performance_history(range).await
+    }
+
     /// Create a new trace span for an operation
     pub async fn start_span(&self, operation: &str) -> Result<TraceContext> {
         let tracing_manager = self.tracing_manager.read().await;
@@ -184,6 +274,25 @@ impl TelemetrySystem {
         metrics_collector.record_metric(name, value, labels).await
     }
 
+    /// Record an occurrence of an SLO event, e.g. a `proxy_connect` and
+    /// how long it took
+    pub async fn record_slo_event(
+        &self,
+        event: &str,
+        success: bool,
+        latency: Option<std::time::Duration>,
+    ) {
+        let metrics_collector = self.metrics_collector.read().await;
+        metrics_collector.record_slo_event(event, success, latency);
+    }
+
+    /// Compliance, remaining error budget and burn rates of every service
+    /// level objective
+    pub async fn slo_status(&self) -> Vec<SloStatus> {
+        let metrics_collector = self.metrics_collector.read().await;
+        metrics_collector.slo_status()
+    }
+
     /// Record a custom event
     pub async fn record_event(&self, event: &str, details: serde_json::Value) -> Result<()> {
         let tracing_manager = self.tracing_manager.read().await;
@@ -198,13 +307,212 @@ impl TelemetrySystem {
 
     /// Get dashboard URL if enabled
     pub async fn get_dashboard_url(&self) -> Option<String> {
-        if !self.config.dashboard_enabled {
-            return None;
-        }
-
         let dashboard_manager = self.dashboard_manager.read().await;
         dashboard_manager.get_url()
     }
+
+    /// Enable or disable the dashboard at runtime
+    pub async fn set_dashboard_enabled(&self, enabled: bool) -> Result<()> {
+        let active = self.is_running().await;
+        let mut dashboard_manager = self.dashboard_manager.write().await;
+        dashboard_manager.set_enabled(enabled, active).await
+    }
+
+    /// Current trace sampling ratio
+    pub async fn sampling_ratio(&self) -> f32 {
+        self.tracing_manager.read().await.sampling_ratio()
+    }
+
+    /// Change the trace sampling ratio at runtime
+    pub async fn set_sampling_ratio(&self, ratio: f32) -> Result<()> {
+        let mut tracing_manager = self.tracing_manager.write().await;
+        tracing_manager.set_sampling_ratio(ratio)
+    }
+
+    /// Register an exporter that receives flushed telemetry
+    pub async fn add_exporter(&self, exporter: Box<dyn TelemetryExporter + Send + Sync>) {
+        self.exporters.write().await.add_exporter(exporter);
+    }
+
+    /// Collect metrics now and push them to every exporter
+    pub async fn flush(&self) -> Result<()> {
+        {
+            let metrics_collector = self.metrics_collector.read().await;
+            metrics_collector.collect_now().await?;
+        }
+
+        let exporters = self.exporters.read().await;
+        if exporters.is_empty() {
+            return Ok(());
+        }
+
+        let metrics = self.get_metrics().await?;
+        exporters
+            .export_to_all(exporters::ExportData {
+                timestamp: chrono::Utc::now(),
+                source: self.config.service_name.clone(),
+                data_type: exporters::DataType::Metrics,
+                metrics: Some(serde_json::to_value(metrics)?),
+                traces: None,
+                logs: None,
+                events: None,
+            })
+            .await
+    }
+
+    /// Start exporting per-user usage from `source` as `config.billing`
+    /// describes, e.g. from the cluster's traffic aggregator
+    pub async fn start_billing(
+        &self,
+        source: Arc<dyn UsageSource>,
+    ) -> Result<Arc<BillingExporter>> {
+        if !self.config.billing.enabled {
+            return Err(TelemetryError::ConfigError {
+                message: "Billing export is not enabled".to_string(),
+            });
+        }
+
+        let exporter = Arc::new(BillingExporter::new(self.config.billing.clone(), source)?);
+        exporter.clone().start().await?;
+
+        if let Some(previous) = self.billing.write().await.replace(exporter.clone()) {
+            previous.stop().await?;
+        }
+        Ok(exporter)
+    }
+
+    /// Start evaluating `config.alerting` rules against this system's
+    /// metrics and service level objectives, and against `nodes` for node
+    /// rules
+    pub async fn start_alerting(
+        &self,
+        nodes: Option<Arc<dyn NodeStatusSource>>,
+    ) -> Result<Arc<AlertEngine>> {
+        if !self.config.alerting.enabled {
+            return Err(TelemetryError::ConfigError {
+                message: "Alerting is not enabled".to_string(),
+            });
+        }
+
+        let mut engine = AlertEngine::new(
+            self.config.alerting.clone(),
+            self.config.service_name.clone(),
+            self.metrics_collector.clone(),
+        )?;
+        if let Some(nodes) = nodes {
+            engine = engine.with_nodes(nodes);
+        }
+        engine = engine.with_slos(self.metrics_collector.read().await.slo_tracker());
+        let engine = Arc::new(engine);
+        engine.clone().start().await?;
+
+        if let Some(previous) = self.alerting.write().await.replace(engine.clone()) {
+            previous.stop().await?;
+        }
+        Ok(engine)
+    }
+
+    /// Start shipping the logs of `config.log_shipping.containers`, followed
+    /// through `streamer`, to Loki or Elasticsearch
+    pub async fn start_log_shipping(
+        &self,
+        streamer: Arc<vpn_docker::LogStreamer>,
+    ) -> Result<Arc<LogShipper>> {
+        if !self.config.log_shipping.enabled {
+            return Err(TelemetryError::ConfigError {
+                message: "Log shipping is not enabled".to_string(),
+            });
+        }
+
+        let shipper = Arc::new(LogShipper::new(self.config.log_shipping.clone())?);
+        shipper.clone().start(streamer).await?;
+
+        if let Some(previous) = self.log_shipper.write().await.replace(shipper.clone()) {
+            previous.stop().await?;
+        }
+        Ok(shipper)
+    }
+
+    /// Counters of the running log shipper, if any
+    pub async fn log_shipping_stats(&self) -> Option<LogShippingStats> {
+        match self.log_shipper.read().await.as_ref() {
+            Some(shipper) => Some(shipper.stats().await),
+            None => None,
+        }
+    }
+
+    /// Serve `/healthz` and `/readyz` as `config.health.endpoint`
+    /// describes; with `cluster`, readiness also requires this node to be a
+    /// cluster member and the cluster to have a leader
+    pub async fn start_health_server(
+        &self,
+        cluster: Option<Arc<vpn_cluster::ClusterManager>>,
+    ) -> Result<std::net::SocketAddr> {
+        let endpoint = &self.config.health.endpoint;
+        if !endpoint.enabled {
+            return Err(TelemetryError::ConfigError {
+                message: "Health endpoint is not enabled".to_string(),
+            });
+        }
+
+        let mut probes = HealthProbes::new(endpoint.check_timeout).with_check(Arc::new(
+            probes::ComponentCheck::new(self.health_collector.clone()),
+        ));
+        if !endpoint.containers.is_empty() {
+            probes = probes.with_check(Arc::new(probes::ContainerCheck::new(
+                endpoint.containers.clone(),
+            )?));
+        }
+        if let Some(cluster) = cluster {
+            probes = probes
+                .with_check(Arc::new(probes::ClusterMembershipCheck::new(
+                    cluster.clone(),
+                )))
+                .with_check(Arc::new(probes::ConsensusLeaderCheck::new(cluster)));
+        }
+
+        // Release the port before binding it again
+        let mut health_server = self.health_server.write().await;
+        if let Some(previous) = health_server.take() {
+            previous.shutdown();
+        }
+
+        let bind_addr = format!("{}:{}", endpoint.bind_address, endpoint.port);
+        let server = HealthServer::bind(Arc::new(probes), &bind_addr).await?;
+        let local_addr = server.local_addr();
+        *health_server = Some(server);
+        Ok(local_addr)
+    }
+
+    /// Snapshot of the telemetry system's runtime state
+    pub async fn status(&self) -> TelemetryStatus {
+        let (dashboard_enabled, dashboard_running, dashboard_url) = {
+            let dashboard_manager = self.dashboard_manager.read().await;
+            (
+                dashboard_manager.is_enabled(),
+                dashboard_manager.is_running().await,
+                dashboard_manager.get_url(),
+            )
+        };
+        let (tracing_initialized, sampling_ratio) = {
+            let tracing_manager = self.tracing_manager.read().await;
+            (
+                tracing_manager.is_initialized(),
+                tracing_manager.sampling_ratio(),
+            )
+        };
+
+        TelemetryStatus {
+            service_name: self.config.service_name.clone(),
+            running: self.is_running().await,
+            tracing_initialized,
+            sampling_ratio,
+            dashboard_enabled,
+            dashboard_running,
+            dashboard_url,
+            exporters: self.exporters.read().await.exporter_status().await,
+        }
+    }
 }
 
 /// Trait for components that can provide telemetry data
@@ -223,8 +531,12 @@ pub trait TelemetryProvider {
 /// Global telemetry instance for easy access throughout the application
 static TELEMETRY: tokio::sync::OnceCell<Arc<TelemetrySystem>> = tokio::sync::OnceCell::const_new();
 
+/// Admin endpoint serving the global telemetry system
+static ADMIN_SERVER: tokio::sync::Mutex<Option<AdminServer>> = tokio::sync::Mutex::const_new(None);
+
 /// Initialize the global telemetry system
 pub async fn init_telemetry(config: TelemetryConfig) -> Result<()> {
+    let admin = config.admin.clone();
     let telemetry = Arc::new(TelemetrySystem::new(config).await?);
     TELEMETRY
         .set(telemetry.clone())
@@ -233,6 +545,12 @@ pub async fn init_telemetry(config: TelemetryConfig) -> Result<()> {
         })?;
 
     telemetry.start().await?;
+
+    if admin.enabled {
+        let bind_addr = format!("{}:{}", admin.bind_address, admin.port);
+        let server = AdminServer::bind(telemetry, &bind_addr).await?;
+        *ADMIN_SERVER.lock().await = Some(server);
+    }
     Ok(())
 }
 
@@ -243,6 +561,10 @@ pub fn telemetry() -> Option<Arc<TelemetrySystem>> {
 
 /// Shutdown the global telemetry system
 pub async fn shutdown_telemetry() -> Result<()> {
+    if let Some(server) = ADMIN_SERVER.lock().await.take() {
+        server.shutdown();
+    }
+
     if let Some(telemetry) = TELEMETRY.get() {
         telemetry.stop().await?;
     }
@@ -0,0 +1,1395 @@
+//! Structured log shipping to Loki and Elasticsearch
+//!
+//! [`LogShipper`] follows the logs of the VPN containers through the Docker
+//! [`LogStreamer`], parses Xray, Shadowsocks and WireGuard lines into
+//! [`LogEvent`]s with a level and extracted fields, and ships them in
+//! batches to the configured [`LogSink`]s.
+//!
+//! Each sink has its own [`LogBuffer`], so one unreachable backend neither
+//! holds back nor duplicates deliveries to the other. A buffer keeps up to
+//! `buffer_capacity` events in memory; past that, the oldest half is spilled
+//! to a spool file when `spool_dir` is set, and the oldest events are
+//! dropped otherwise. Spooled events are shipped first once the sink
+//! catches up, and survive restarts.
+//!
+//! Log lines reach the shipper through a bounded channel. While a batch is
+//! being delivered nothing is read from it, so a slow sink makes the
+//! container streams wait rather than growing memory without bound.
+
+use crate::config::LogShippingConfig;
+use crate::error::Result;
+use crate::exporters::{AuthenticationConfig, AuthenticationType};
+use crate::TelemetryError;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tracing::{debug, info, warn};
+use vpn_docker::logs::{LogEntry, LogStream};
+use vpn_docker::LogStreamer;
+
+/// Delay before following a container again after its stream ended
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Format of a container's log lines
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Xray,
+    Shadowsocks,
+    #[serde(rename = "wireguard")]
+    WireGuard,
+    /// vpn-proxy's JSON access log
+    Proxy,
+    /// Lines are shipped as they are
+    #[default]
+    Plain,
+}
+
+impl LogFormat {
+    /// Format guessed from a container name
+    pub fn detect(container: &str) -> Self {
+        let name = container.to_lowercase();
+        if name.contains("xray") || name.contains("vless") {
+            Self::Xray
+        } else if name.contains("shadowsocks") || name.contains("outline") {
+            Self::Shadowsocks
+        } else if name.contains("wireguard") || name.contains("wg") {
+            Self::WireGuard
+        } else if name.contains("proxy") {
+            Self::Proxy
+        } else {
+            Self::Plain
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Xray => "xray",
+            Self::Shadowsocks => "shadowsocks",
+            Self::WireGuard => "wireguard",
+            Self::Proxy => "proxy",
+            Self::Plain => "plain",
+        }
+    }
+}
+
+/// Severity of a log event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl LogLevel {
+    fn parse(level: &str) -> Self {
+        match level.to_lowercase().as_str() {
+            "trace" | "debug" => Self::Debug,
+            "warn" | "warning" => Self::Warning,
+            "error" | "err" | "fatal" | "critical" | "panic" => Self::Error,
+            _ => Self::Info,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// One parsed log line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEvent {
+    pub timestamp: DateTime<Utc>,
+    pub container: String,
+    /// `stdout` or `stderr`
+    pub stream: String,
+    pub format: LogFormat,
+    pub level: LogLevel,
+    pub message: String,
+    /// Values extracted from the line, e.g. `source` and `destination` of
+    /// an Xray access log entry
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Turns raw container log lines into [`LogEvent`]s
+pub struct LogParser {
+    docker_timestamp: Regex,
+    xray_access: Regex,
+    xray_error: Regex,
+    shadowsocks: Regex,
+    shadowsocks_legacy: Regex,
+    wireguard_kernel: Regex,
+    wireguard_quick: Regex,
+    wireguard_tracing: Regex,
+}
+
+impl LogParser {
+    pub fn new() -> Self {
+        Self {
+            // Prefix Docker adds when following with timestamps
+            docker_timestamp: Regex::new(r"^(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?Z) ")
+                .unwrap(),
+            // 2024/05/01 12:00:00 1.2.3.4:5678 accepted tcp:example.com:443 [vless-in -> direct] email: alice
+            xray_access: Regex::new(
+                r"^(\d{4}/\d{2}/\d{2} \d{2}:\d{2}:\d{2})(?:\.\d+)? (?:from )?(\S+) (accepted|rejected) (\S+)(?: \[([^\]]+)\])?(?: email: (\S+))?",
+            )
+            .unwrap(),
+            // 2024/05/01 12:00:00 [Warning] [1234567] app/proxyman/inbound: connection ends
+            xray_error: Regex::new(
+                r"^(\d{4}/\d{2}/\d{2} \d{2}:\d{2}:\d{2})(?:\.\d+)? \[(\w+)\] (?:\[(\w+)\] )?(.+)",
+            )
+            .unwrap(),
+            // 2024-05-01T12:00:00.123456+00:00 INFO  shadowsocks_service::server: message
+            shadowsocks: Regex::new(
+                r"^(\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:?\d{2})?) +(\w+) +(?:\[(\w+)\] )?(?:([\w:]+): )?(.+)",
+            )
+            .unwrap(),
+            // [2024-05-01 12:00:00] INFO [server] message
+            shadowsocks_legacy: Regex::new(r"^\[([^\]]+)\] (\w+) (?:\[(\w+)\] )?(.+)").unwrap(),
+            // wireguard: wg0: Handshake for peer 1 (1.2.3.4:51820) did not complete ...
+            wireguard_kernel: Regex::new(r"wireguard: (\w+): (.+)").unwrap(),
+            // [#] ip link add wg0 type wireguard
+            wireguard_quick: Regex::new(r"^\[#\] (.+)").unwrap(),
+            // boringtun and other userspace implementations log through tracing
+            wireguard_tracing: Regex::new(
+                r"^(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?Z?) +(\w+) +(?:([\w:]+): )?(.+)",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Parse a line `container` logged in `format`. Lines that do not match
+    /// the format are kept as plain events.
+    pub fn parse(&self, entry: &LogEntry, format: LogFormat) -> LogEvent {
+        let (received, line) = self.split_docker_timestamp(entry);
+        let stream = match entry.stream {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        };
+
+        let mut event = LogEvent {
+            timestamp: received,
+            container: entry.container.clone(),
+            stream: stream.to_string(),
+            format,
+            level: LogLevel::Info,
+            message: line.to_string(),
+            fields: BTreeMap::new(),
+        };
+
+        let parsed = match format {
+            LogFormat::Xray => self.parse_xray(line, &mut event),
+            LogFormat::Shadowsocks => self.parse_shadowsocks(line, &mut event),
+            LogFormat::WireGuard => self.parse_wireguard(line, &mut event),
+            LogFormat::Proxy => self.parse_proxy(line, &mut event),
+            LogFormat::Plain => true,
+        };
+        if !parsed {
+            event.format = LogFormat::Plain;
+        }
+        event
+    }
+
+    /// Time Docker received the line, and the line without it
+    fn split_docker_timestamp<'a>(&self, entry: &'a LogEntry) -> (DateTime<Utc>, &'a str) {
+        if let Some(captures) = self.docker_timestamp.captures(&entry.message) {
+            if let Ok(timestamp) = DateTime::parse_from_rfc3339(&captures[1]) {
+                let line = &entry.message[captures[0].len()..];
+                return (timestamp.with_timezone(&Utc), line);
+            }
+        }
+        (
+            DateTime::<Utc>::from(entry.timestamp),
+            entry.message.as_str(),
+        )
+    }
+
+    fn parse_xray(&self, line: &str, event: &mut LogEvent) -> bool {
+        if let Some(captures) = self.xray_access.captures(line) {
+            if let Some(timestamp) = parse_xray_time(&captures[1]) {
+                event.timestamp = timestamp;
+            }
+            event
+                .fields
+                .insert("kind".to_string(), "access".to_string());
+            event
+                .fields
+                .insert("source".to_string(), captures[2].to_string());
+            event
+                .fields
+                .insert("status".to_string(), captures[3].to_string());
+            event
+                .fields
+                .insert("destination".to_string(), captures[4].to_string());
+            if let Some(route) = captures.get(5) {
+                let mut hops = route.as_str().splitn(2, " -> ");
+                if let Some(inbound) = hops.next() {
+                    event
+                        .fields
+                        .insert("inbound".to_string(), inbound.trim().to_string());
+                }
+                if let Some(outbound) = hops.next() {
+                    event
+                        .fields
+                        .insert("outbound".to_string(), outbound.trim().to_string());
+                }
+            }
+            if let Some(email) = captures.get(6) {
+                event
+                    .fields
+                    .insert("user".to_string(), email.as_str().to_string());
+            }
+            event.level = if &captures[3] == "rejected" {
+                LogLevel::Warning
+            } else {
+                LogLevel::Info
+            };
+            event.message = line[captures[1].len()..].trim().to_string();
+            return true;
+        }
+
+        if let Some(captures) = self.xray_error.captures(line) {
+            if let Some(timestamp) = parse_xray_time(&captures[1]) {
+                event.timestamp = timestamp;
+            }
+            event.level = LogLevel::parse(&captures[2]);
+            if let Some(session) = captures.get(3) {
+                event
+                    .fields
+                    .insert("session".to_string(), session.as_str().to_string());
+            }
+            let message = &captures[4];
+            if let Some((component, rest)) = message.split_once(": ") {
+                if component.contains('/') && !component.contains(' ') {
+                    event
+                        .fields
+                        .insert("component".to_string(), component.to_string());
+                }
+                event.message = if event.fields.contains_key("component") {
+                    rest.to_string()
+                } else {
+                    message.to_string()
+                };
+            } else {
+                event.message = message.to_string();
+            }
+            return true;
+        }
+
+        false
+    }
+
+    fn parse_shadowsocks(&self, line: &str, event: &mut LogEvent) -> bool {
+        if let Some(captures) = self.shadowsocks.captures(line) {
+            if let Some(timestamp) = parse_iso_time(&captures[1]) {
+                event.timestamp = timestamp;
+            }
+            event.level = LogLevel::parse(&captures[2]);
+            if let Some(component) = captures.get(3).or(captures.get(4)) {
+                event
+                    .fields
+                    .insert("component".to_string(), component.as_str().to_string());
+            }
+            event.message = captures[5].to_string();
+            extract_peer(&event.message.clone(), event);
+            return true;
+        }
+
+        if let Some(captures) = self.shadowsocks_legacy.captures(line) {
+            if let Some(timestamp) = parse_iso_time(&captures[1]) {
+                event.timestamp = timestamp;
+            }
+            event.level = LogLevel::parse(&captures[2]);
+            if let Some(component) = captures.get(3) {
+                event
+                    .fields
+                    .insert("component".to_string(), component.as_str().to_string());
+            }
+            event.message = captures[4].to_string();
+            extract_peer(&event.message.clone(), event);
+            return true;
+        }
+
+        false
+    }
+
+    fn parse_wireguard(&self, line: &str, event: &mut LogEvent) -> bool {
+        if let Some(captures) = self.wireguard_kernel.captures(line) {
+            event
+                .fields
+                .insert("interface".to_string(), captures[1].to_string());
+            let message = captures[2].to_string();
+            if let Some(peer) = message
+                .strip_prefix("Handshake for peer ")
+                .or_else(|| message.strip_prefix("Receiving handshake initiation from peer "))
+                .or_else(|| message.strip_prefix("Sending handshake initiation to peer "))
+                .and_then(|rest| rest.split_whitespace().next())
+            {
+                event.fields.insert("peer".to_string(), peer.to_string());
+            }
+            extract_peer(&message, event);
+            event.level = if message.contains("did not complete") || message.contains("Invalid") {
+                LogLevel::Warning
+            } else {
+                LogLevel::Info
+            };
+            event.message = message;
+            return true;
+        }
+
+        if let Some(captures) = self.wireguard_quick.captures(line) {
+            event
+                .fields
+                .insert("command".to_string(), captures[1].to_string());
+            event.level = LogLevel::Info;
+            event.message = captures[1].to_string();
+            return true;
+        }
+
+        if let Some(captures) = self.wireguard_tracing.captures(line) {
+            if let Some(timestamp) = parse_iso_time(&captures[1]) {
+                event.timestamp = timestamp;
+            }
+            event.level = LogLevel::parse(&captures[2]);
+            if let Some(component) = captures.get(3) {
+                event
+                    .fields
+                    .insert("component".to_string(), component.as_str().to_string());
+            }
+            event.message = captures[4].to_string();
+            extract_peer(&event.message.clone(), event);
+            return true;
+        }
+
+        false
+    }
+
+    /// Access log lines vpn-proxy writes to standard output, tagged with
+    /// `"log": "proxy_access"`; its other output is kept as plain events
+    fn parse_proxy(&self, line: &str, event: &mut LogEvent) -> bool {
+        let record = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(serde_json::Value::Object(record)) => record,
+            _ => return false,
+        };
+        if record.get("log").and_then(|kind| kind.as_str()) != Some("proxy_access") {
+            return false;
+        }
+
+        if let Some(timestamp) = record
+            .get("timestamp")
+            .and_then(|timestamp| timestamp.as_str())
+            .and_then(parse_iso_time)
+        {
+            event.timestamp = timestamp;
+        }
+        event
+            .fields
+            .insert("kind".to_string(), "access".to_string());
+        for (key, value) in &record {
+            let value = match value {
+                serde_json::Value::Null => continue,
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            if key != "log" && key != "timestamp" {
+                event.fields.insert(key.clone(), value);
+            }
+        }
+
+        let field = |name: &str| event.fields.get(name).map(String::as_str).unwrap_or("-");
+        let termination = field("termination");
+        let level = match termination {
+            "error" => LogLevel::Error,
+            "auth_failed" | "blocked" | "rate_limited" | "connection_limit" | "connect_failed" => {
+                LogLevel::Warning
+            }
+            _ => LogLevel::Info,
+        };
+        let message = format!(
+            "{} {} {}",
+            field("method"),
+            field("destination"),
+            termination
+        );
+        event.level = level;
+        event.message = message;
+        true
+    }
+}
+
+impl Default for LogParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Xray logs local time without a zone; containers run in UTC
+fn parse_xray_time(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, "%Y/%m/%d %H:%M:%S")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+fn parse_iso_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(time) = DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f%z") {
+        return Some(time.with_timezone(&Utc));
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), format).ok())
+        .map(|time| time.and_utc())
+}
+
+/// Record the first `ip:port` in `message` as the event's `peer_address`
+fn extract_peer(message: &str, event: &mut LogEvent) {
+    let address = message
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == ',')
+        .find(|word| word.parse::<std::net::SocketAddr>().is_ok());
+    if let Some(address) = address {
+        event
+            .fields
+            .insert("peer_address".to_string(), address.to_string());
+    }
+}
+
+/// Backend receiving log events
+#[async_trait]
+pub trait LogSink: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Deliver `events` as one batch; on error the whole batch is retried
+    async fn ship(&self, events: &[LogEvent]) -> Result<()>;
+}
+
+/// Loki push API endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LokiConfig {
+    /// Base URL, e.g. `http://loki:3100`
+    pub url: String,
+    /// Labels added to every stream besides `container`, `format` and `level`
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Sent as `X-Scope-OrgID` to multi-tenant Loki
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    #[serde(default)]
+    pub authentication: Option<AuthenticationConfig>,
+    #[serde(default = "default_sink_timeout")]
+    pub timeout: Duration,
+}
+
+/// Elasticsearch bulk API endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElasticsearchConfig {
+    /// Base URL, e.g. `http://elasticsearch:9200`
+    pub url: String,
+    /// Index name; `strftime` escapes are expanded with the event's date,
+    /// e.g. `vpn-logs-%Y.%m.%d`
+    #[serde(default = "default_index")]
+    pub index: String,
+    #[serde(default)]
+    pub authentication: Option<AuthenticationConfig>,
+    #[serde(default = "default_sink_timeout")]
+    pub timeout: Duration,
+}
+
+fn default_sink_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_index() -> String {
+    "vpn-logs-%Y.%m.%d".to_string()
+}
+
+fn http_client(exporter: &str, timeout: Duration) -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| TelemetryError::ExportError {
+            exporter: exporter.to_string(),
+            message: format!("Failed to create HTTP client: {}", e),
+        })
+}
+
+fn authenticate(
+    mut request: reqwest::RequestBuilder,
+    auth: Option<&AuthenticationConfig>,
+) -> reqwest::RequestBuilder {
+    if let Some(auth) = auth {
+        match &auth.auth_type {
+            AuthenticationType::Basic => {
+                if let (Some(username), Some(password)) = (&auth.username, &auth.password) {
+                    request = request.basic_auth(username, Some(password));
+                }
+            }
+            AuthenticationType::Bearer => {
+                if let Some(token) = &auth.token {
+                    request = request.bearer_auth(token);
+                }
+            }
+            AuthenticationType::ApiKey => {
+                if let Some(api_key) = &auth.api_key {
+                    request = request.header("X-API-Key", api_key);
+                }
+            }
+            AuthenticationType::None => {}
+        }
+    }
+    request
+}
+
+/// Pushes events to Loki, one stream per container, format and level
+pub struct LokiSink {
+    config: LokiConfig,
+    client: reqwest::Client,
+}
+
+impl LokiSink {
+    pub fn new(config: LokiConfig) -> Result<Self> {
+        let client = http_client("loki", config.timeout)?;
+        Ok(Self { config, client })
+    }
+
+    /// Body of a push request. Lines are JSON objects holding the message
+    /// and extracted fields, so they can be queried with `| json`.
+    pub fn payload(&self, events: &[LogEvent]) -> serde_json::Value {
+        let mut streams: BTreeMap<(&str, LogFormat, LogLevel), Vec<serde_json::Value>> =
+            BTreeMap::new();
+        for event in events {
+            let mut line = serde_json::Map::new();
+            line.insert("message".to_string(), event.message.clone().into());
+            line.insert("stream".to_string(), event.stream.clone().into());
+            for (key, value) in &event.fields {
+                line.insert(key.clone(), value.clone().into());
+            }
+            let timestamp = event
+                .timestamp
+                .timestamp_nanos_opt()
+                .unwrap_or_default()
+                .to_string();
+
+            streams
+                .entry((&event.container, event.format, event.level))
+                .or_default()
+                .push(serde_json::json!([
+                    timestamp,
+                    serde_json::Value::Object(line).to_string()
+                ]));
+        }
+
+        let streams: Vec<_> = streams
+            .into_iter()
+            .map(|((container, format, level), values)| {
+                let mut labels: BTreeMap<&str, &str> = self
+                    .config
+                    .labels
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect();
+                labels.insert("container", container);
+                labels.insert("format", format.as_str());
+                labels.insert("level", level.as_str());
+                serde_json::json!({ "stream": labels, "values": values })
+            })
+            .collect();
+
+        serde_json::json!({ "streams": streams })
+    }
+}
+
+#[async_trait]
+impl LogSink for LokiSink {
+    fn name(&self) -> &str {
+        "loki"
+    }
+
+    async fn ship(&self, events: &[LogEvent]) -> Result<()> {
+        let url = format!("{}/loki/api/v1/push", self.config.url.trim_end_matches('/'));
+        let mut request = self.client.post(&url).json(&self.payload(events));
+        if let Some(tenant_id) = &self.config.tenant_id {
+            request = request.header("X-Scope-OrgID", tenant_id);
+        }
+
+        let response = authenticate(request, self.config.authentication.as_ref())
+            .send()
+            .await
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP request failed: {}", e),
+            })?;
+
+        if !response.status().is_success() {
+            return Err(TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP error: {}", response.status()),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Indexes events into Elasticsearch through the bulk API
+pub struct ElasticsearchSink {
+    config: ElasticsearchConfig,
+    client: reqwest::Client,
+}
+
+impl ElasticsearchSink {
+    pub fn new(config: ElasticsearchConfig) -> Result<Self> {
+        let client = http_client("elasticsearch", config.timeout)?;
+        Ok(Self { config, client })
+    }
+
+    /// NDJSON body of a bulk request
+    pub fn bulk_body(&self, events: &[LogEvent]) -> Result<String> {
+        let mut body = String::new();
+        for event in events {
+            let index = event.timestamp.format(&self.config.index).to_string();
+            let mut document = serde_json::Map::new();
+            document.insert(
+                "@timestamp".to_string(),
+                event.timestamp.to_rfc3339().into(),
+            );
+            document.insert("container".to_string(), event.container.clone().into());
+            document.insert("stream".to_string(), event.stream.clone().into());
+            document.insert("format".to_string(), event.format.as_str().into());
+            document.insert("level".to_string(), event.level.as_str().into());
+            document.insert("message".to_string(), event.message.clone().into());
+            if !event.fields.is_empty() {
+                document.insert("fields".to_string(), serde_json::to_value(&event.fields)?);
+            }
+
+            body.push_str(&serde_json::json!({ "index": { "_index": index } }).to_string());
+            body.push('\n');
+            body.push_str(&serde_json::Value::Object(document).to_string());
+            body.push('\n');
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl LogSink for ElasticsearchSink {
+    fn name(&self) -> &str {
+        "elasticsearch"
+    }
+
+    async fn ship(&self, events: &[LogEvent]) -> Result<()> {
+        let url = format!("{}/_bulk", self.config.url.trim_end_matches('/'));
+        let request = self
+            .client
+            .post(&url)
+            .header("Content-Type", "application/x-ndjson")
+            .body(self.bulk_body(events)?);
+
+        let response = authenticate(request, self.config.authentication.as_ref())
+            .send()
+            .await
+            .map_err(|e| TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP request failed: {}", e),
+            })?;
+
+        if !response.status().is_success() {
+            return Err(TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: format!("HTTP error: {}", response.status()),
+            });
+        }
+
+        // The bulk API answers 200 even when individual documents failed
+        let result: serde_json::Value =
+            response
+                .json()
+                .await
+                .map_err(|e| TelemetryError::ExportError {
+                    exporter: self.name().to_string(),
+                    message: format!("Invalid bulk response: {}", e),
+                })?;
+        if result["errors"].as_bool().unwrap_or(false) {
+            return Err(TelemetryError::ExportError {
+                exporter: self.name().to_string(),
+                message: "Bulk request rejected some documents".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Events waiting for one sink: in memory up to a capacity, then spilled to
+/// a spool file or dropped, oldest first. Spooled events are always older
+/// than the ones in memory and are shipped first.
+pub struct LogBuffer {
+    events: VecDeque<LogEvent>,
+    capacity: usize,
+    spool_path: Option<PathBuf>,
+    spooled: usize,
+    dropped: u64,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize, spool_path: Option<PathBuf>) -> Self {
+        // Events left over from a previous run are shipped first
+        let spooled = spool_path
+            .as_ref()
+            .and_then(|path| std::fs::File::open(path).ok())
+            .map(|file| BufReader::new(file).lines().count())
+            .unwrap_or(0);
+
+        Self {
+            events: VecDeque::new(),
+            capacity: capacity.max(2),
+            spool_path,
+            spooled,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, event: LogEvent) {
+        if self.events.len() >= self.capacity {
+            self.evict();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Up to `max` of the oldest events, left in place until
+    /// [`ack`](Self::ack)ed
+    pub fn peek_batch(&mut self, max: usize) -> Vec<LogEvent> {
+        if self.spooled > 0 {
+            match self.read_spool(max) {
+                Ok(batch) => return batch,
+                Err(e) => {
+                    warn!("Failed to read log spool, discarding it: {}", e);
+                    self.discard_spool();
+                }
+            }
+        }
+        self.events.iter().take(max).cloned().collect()
+    }
+
+    /// Remove the `count` events last returned by
+    /// [`peek_batch`](Self::peek_batch), once delivered
+    pub fn ack(&mut self, count: usize) {
+        if self.spooled == 0 {
+            let count = count.min(self.events.len());
+            self.events.drain(..count);
+            return;
+        }
+
+        if let Err(e) = self.trim_spool(count) {
+            warn!("Failed to trim log spool, discarding it: {}", e);
+            self.discard_spool();
+        }
+    }
+
+    /// Events waiting in memory and in the spool
+    pub fn len(&self) -> usize {
+        self.events.len() + self.spooled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn spooled(&self) -> usize {
+        self.spooled
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Make room by spilling the oldest half to the spool, or by dropping
+    /// the oldest event without one
+    fn evict(&mut self) {
+        let Some(path) = &self.spool_path else {
+            self.events.pop_front();
+            self.dropped += 1;
+            return;
+        };
+
+        let count = (self.capacity / 2).min(self.events.len());
+        let evicted: Vec<LogEvent> = self.events.drain(..count).collect();
+        match append_spool(path, &evicted) {
+            Ok(()) => self.spooled += evicted.len(),
+            Err(e) => {
+                warn!("Failed to spool log events: {}", e);
+                self.dropped += evicted.len() as u64;
+            }
+        }
+    }
+
+    fn read_spool(&mut self, max: usize) -> Result<Vec<LogEvent>> {
+        let Some(path) = &self.spool_path else {
+            return Ok(Vec::new());
+        };
+        let file = std::fs::File::open(path)?;
+        BufReader::new(file)
+            .lines()
+            .take(max)
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+
+    fn trim_spool(&mut self, count: usize) -> Result<()> {
+        let Some(path) = &self.spool_path else {
+            return Ok(());
+        };
+        let file = std::fs::File::open(path)?;
+        let remaining: Vec<String> = BufReader::new(file)
+            .lines()
+            .skip(count)
+            .collect::<std::io::Result<_>>()?;
+
+        if remaining.is_empty() {
+            std::fs::remove_file(path)?;
+        } else {
+            let mut contents = remaining.join("\n");
+            contents.push('\n');
+            std::fs::write(path, contents)?;
+        }
+        self.spooled = remaining.len();
+        Ok(())
+    }
+
+    fn discard_spool(&mut self) {
+        if let Some(path) = &self.spool_path {
+            let _ = std::fs::remove_file(path);
+        }
+        self.dropped += self.spooled as u64;
+        self.spooled = 0;
+    }
+}
+
+fn append_spool(path: &Path, events: &[LogEvent]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    for event in events {
+        writeln!(file, "{}", serde_json::to_string(event)?)?;
+    }
+    Ok(())
+}
+
+/// Counters of a running [`LogShipper`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogShippingStats {
+    /// Lines read from containers
+    pub received: u64,
+    /// Events delivered, summed over sinks
+    pub shipped: u64,
+    /// Batches a sink failed to accept
+    pub failed_batches: u64,
+    /// Events waiting, summed over sinks
+    pub buffered: usize,
+    /// Of which in spool files
+    pub spooled: usize,
+    /// Events lost to full buffers, summed over sinks
+    pub dropped: u64,
+}
+
+struct SinkQueue {
+    sink: Box<dyn LogSink>,
+    buffer: Mutex<LogBuffer>,
+}
+
+/// Follows container logs and ships them as structured events
+pub struct LogShipper {
+    config: LogShippingConfig,
+    parser: LogParser,
+    queues: Vec<SinkQueue>,
+    received: AtomicU64,
+    shipped: AtomicU64,
+    failed_batches: AtomicU64,
+    running: Arc<RwLock<bool>>,
+}
+
+impl LogShipper {
+    /// Shipper with the sinks `config` describes
+    pub fn new(config: LogShippingConfig) -> Result<Self> {
+        let mut shipper = Self {
+            config,
+            parser: LogParser::new(),
+            queues: Vec::new(),
+            received: AtomicU64::new(0),
+            shipped: AtomicU64::new(0),
+            failed_batches: AtomicU64::new(0),
+            running: Arc::new(RwLock::new(false)),
+        };
+
+        if let Some(loki) = shipper.config.loki.clone() {
+            shipper = shipper.with_sink(Box::new(LokiSink::new(loki)?));
+        }
+        if let Some(elasticsearch) = shipper.config.elasticsearch.clone() {
+            shipper = shipper.with_sink(Box::new(ElasticsearchSink::new(elasticsearch)?));
+        }
+        Ok(shipper)
+    }
+
+    /// Add a sink besides the configured ones
+    pub fn with_sink(mut self, sink: Box<dyn LogSink>) -> Self {
+        let spool_path = self
+            .config
+            .spool_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.ndjson", sink.name())));
+        self.queues.push(SinkQueue {
+            buffer: Mutex::new(LogBuffer::new(self.config.buffer_capacity, spool_path)),
+            sink,
+        });
+        self
+    }
+
+    /// Format of `container`'s logs, as configured or detected from its name
+    pub fn format_of(&self, container: &str) -> LogFormat {
+        self.config
+            .formats
+            .get(container)
+            .copied()
+            .unwrap_or_else(|| LogFormat::detect(container))
+    }
+
+    /// Parse `entry` and queue it for every sink
+    pub async fn ingest(&self, entry: &LogEntry) -> LogEvent {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        let event = self.parser.parse(entry, self.format_of(&entry.container));
+        for queue in &self.queues {
+            queue.buffer.lock().await.push(event.clone());
+        }
+        event
+    }
+
+    /// Whether any sink has a full batch waiting
+    pub async fn batch_ready(&self) -> bool {
+        for queue in &self.queues {
+            if queue.buffer.lock().await.len() >= self.config.batch_size {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Ship one batch to each sink with events waiting. Batches a sink
+    /// rejects are kept for the next flush. Returns the number of events
+    /// delivered.
+    pub async fn flush(&self) -> usize {
+        let mut delivered = 0;
+        for queue in &self.queues {
+            // Held while shipping, so ingestion waits for a slow sink
+            let mut buffer = queue.buffer.lock().await;
+            let batch = buffer.peek_batch(self.config.batch_size);
+            if batch.is_empty() {
+                continue;
+            }
+
+            match queue.sink.ship(&batch).await {
+                Ok(()) => {
+                    debug!(
+                        "Shipped {} log events to {}",
+                        batch.len(),
+                        queue.sink.name()
+                    );
+                    buffer.ack(batch.len());
+                    delivered += batch.len();
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to ship {} log events to {}: {}",
+                        batch.len(),
+                        queue.sink.name(),
+                        e
+                    );
+                    self.failed_batches.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        self.shipped.fetch_add(delivered as u64, Ordering::Relaxed);
+        delivered
+    }
+
+    pub async fn stats(&self) -> LogShippingStats {
+        let mut stats = LogShippingStats {
+            received: self.received.load(Ordering::Relaxed),
+            shipped: self.shipped.load(Ordering::Relaxed),
+            failed_batches: self.failed_batches.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for queue in &self.queues {
+            let buffer = queue.buffer.lock().await;
+            stats.buffered += buffer.len();
+            stats.spooled += buffer.spooled();
+            stats.dropped += buffer.dropped();
+        }
+        stats
+    }
+
+    /// Follow `config.containers` through `streamer` and ship their logs
+    /// until stopped
+    pub async fn start(self: Arc<Self>, streamer: Arc<LogStreamer>) -> Result<()> {
+        let mut running = self.running.write().await;
+        if *running {
+            return Ok(());
+        }
+        *running = true;
+        info!(
+            "Started log shipping for {} containers",
+            self.config.containers.len()
+        );
+
+        let (tx, mut rx) = mpsc::channel(self.config.channel_capacity.max(1));
+        for container in self.config.containers.clone() {
+            let streamer = streamer.clone();
+            let running = self.running.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while *running.read().await {
+                    if let Err(e) = streamer.stream_logs(&container, tx.clone()).await {
+                        warn!("Log stream of {} failed: {}", container, e);
+                    }
+                    if tx.is_closed() {
+                        break;
+                    }
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            });
+        }
+        drop(tx);
+
+        let shipper = self.clone();
+        tokio::spawn(async move {
+            let mut flush_timer = tokio::time::interval(shipper.config.flush_interval);
+            // A reconnected stream replays the container's whole log
+            let mut last_seen: HashMap<String, DateTime<Utc>> = HashMap::new();
+
+            loop {
+                tokio::select! {
+                    entry = rx.recv() => {
+                        let Some(entry) = entry else { break };
+                        let (timestamp, _) = shipper.parser.split_docker_timestamp(&entry);
+                        if last_seen
+                            .get(&entry.container)
+                            .is_some_and(|last| timestamp <= *last)
+                        {
+                            continue;
+                        }
+                        last_seen.insert(entry.container.clone(), timestamp);
+
+                        shipper.ingest(&entry).await;
+                        if shipper.batch_ready().await {
+                            shipper.flush().await;
+                        }
+                    }
+                    _ = flush_timer.tick() => {
+                        if !*shipper.running.read().await {
+                            break;
+                        }
+                        shipper.flush().await;
+                    }
+                }
+            }
+
+            // Ship what is left; undelivered events stay in the spool
+            shipper.flush().await;
+        });
+
+        Ok(())
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        *self.running.write().await = false;
+        info!("Stopped log shipping");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::time::SystemTime;
+
+    fn entry(container: &str, message: &str) -> LogEntry {
+        LogEntry {
+            timestamp: SystemTime::now(),
+            container: container.to_string(),
+            stream: LogStream::Stdout,
+            message: message.to_string(),
+        }
+    }
+
+    fn event(message: &str) -> LogEvent {
+        LogParser::new().parse(&entry("vpn-server", message), LogFormat::Plain)
+    }
+
+    #[test]
+    fn test_parse_xray_lines() {
+        let parser = LogParser::new();
+
+        let access = parser.parse(
+            &entry(
+                "vpn-server",
+                "2024-05-01T12:00:01.5Z 2024/05/01 12:00:00 1.2.3.4:5678 accepted tcp:example.com:443 [vless-in -> direct] email: alice",
+            ),
+            LogFormat::Xray,
+        );
+        assert_eq!(access.format, LogFormat::Xray);
+        assert_eq!(access.level, LogLevel::Info);
+        assert_eq!(access.timestamp.to_rfc3339(), "2024-05-01T12:00:00+00:00");
+        assert_eq!(access.fields["source"], "1.2.3.4:5678");
+        assert_eq!(access.fields["destination"], "tcp:example.com:443");
+        assert_eq!(access.fields["inbound"], "vless-in");
+        assert_eq!(access.fields["outbound"], "direct");
+        assert_eq!(access.fields["user"], "alice");
+
+        let error = parser.parse(
+            &entry(
+                "vpn-server",
+                "2024/05/01 12:00:00 [Warning] [1234567] app/proxyman/inbound: connection ends",
+            ),
+            LogFormat::Xray,
+        );
+        assert_eq!(error.level, LogLevel::Warning);
+        assert_eq!(error.fields["session"], "1234567");
+        assert_eq!(error.fields["component"], "app/proxyman/inbound");
+        assert_eq!(error.message, "connection ends");
+
+        // Lines in another format are kept verbatim
+        let other = parser.parse(&entry("vpn-server", "starting up"), LogFormat::Xray);
+        assert_eq!(other.format, LogFormat::Plain);
+        assert_eq!(other.message, "starting up");
+    }
+
+    #[test]
+    fn test_parse_shadowsocks_and_wireguard_lines() {
+        let parser = LogParser::new();
+
+        let ss = parser.parse(
+            &entry(
+                "shadowsocks",
+                "2024-05-01T12:00:00.123456+00:00 ERROR shadowsocks_service::server: tcp handshake failed, peer: 5.6.7.8:1234",
+            ),
+            LogFormat::detect("shadowsocks"),
+        );
+        assert_eq!(ss.format, LogFormat::Shadowsocks);
+        assert_eq!(ss.level, LogLevel::Error);
+        assert_eq!(ss.fields["component"], "shadowsocks_service::server");
+        assert_eq!(ss.fields["peer_address"], "5.6.7.8:1234");
+
+        let handshake = parser.parse(
+            &entry(
+                "wireguard",
+                "wireguard: wg0: Handshake for peer 3 (9.9.9.9:51820) did not complete after 5 seconds, retrying (try 2)",
+            ),
+            LogFormat::detect("wireguard"),
+        );
+        assert_eq!(handshake.format, LogFormat::WireGuard);
+        assert_eq!(handshake.level, LogLevel::Warning);
+        assert_eq!(handshake.fields["interface"], "wg0");
+        assert_eq!(handshake.fields["peer"], "3");
+        assert_eq!(handshake.fields["peer_address"], "9.9.9.9:51820");
+
+        let command = parser.parse(
+            &entry("wireguard", "[#] ip link add wg0 type wireguard"),
+            LogFormat::WireGuard,
+        );
+        assert_eq!(command.fields["command"], "ip link add wg0 type wireguard");
+    }
+
+    #[test]
+    fn test_parse_proxy_access_lines() {
+        let parser = LogParser::new();
+
+        let access = parser.parse(
+            &entry(
+                "vpn-socks5-proxy",
+                r#"{"log":"proxy_access","timestamp":"2026-10-18T12:00:00Z","protocol":"http","user":"alice","source":"1.2.3.4:5678","destination":"example.com:443","method":"CONNECT","status":200,"bytes_up":1024,"bytes_down":5120,"duration_ms":350,"termination":"rate_limited"}"#,
+            ),
+            LogFormat::detect("vpn-socks5-proxy"),
+        );
+        assert_eq!(access.format, LogFormat::Proxy);
+        assert_eq!(access.level, LogLevel::Warning);
+        assert_eq!(access.timestamp.to_rfc3339(), "2026-10-18T12:00:00+00:00");
+        assert_eq!(access.fields["user"], "alice");
+        assert_eq!(access.fields["bytes_down"], "5120");
+        assert_eq!(access.message, "CONNECT example.com:443 rate_limited");
+        assert!(!access.fields.contains_key("log"));
+
+        let other = parser.parse(
+            &entry("vpn-socks5-proxy", "INFO vpn_proxy: listening"),
+            LogFormat::Proxy,
+        );
+        assert_eq!(other.format, LogFormat::Plain);
+    }
+
+    #[test]
+    fn test_sink_payloads() {
+        let mut access = event("accepted");
+        access
+            .fields
+            .insert("user".to_string(), "alice".to_string());
+        let events = vec![access, event("second")];
+
+        let loki = LokiSink::new(LokiConfig {
+            url: "http://loki:3100".to_string(),
+            labels: HashMap::from([("node".to_string(), "eu-1".to_string())]),
+            tenant_id: None,
+            authentication: None,
+            timeout: default_sink_timeout(),
+        })
+        .unwrap();
+        let payload = loki.payload(&events);
+        let streams = payload["streams"].as_array().unwrap();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0]["stream"]["container"], "vpn-server");
+        assert_eq!(streams[0]["stream"]["node"], "eu-1");
+        assert_eq!(streams[0]["stream"]["level"], "info");
+        let values = streams[0]["values"].as_array().unwrap();
+        assert_eq!(values.len(), 2);
+        let line: serde_json::Value = serde_json::from_str(values[0][1].as_str().unwrap()).unwrap();
+        assert_eq!(line["message"], "accepted");
+        assert_eq!(line["user"], "alice");
+
+        let elasticsearch = ElasticsearchSink::new(ElasticsearchConfig {
+            url: "http://es:9200".to_string(),
+            index: default_index(),
+            authentication: None,
+            timeout: default_sink_timeout(),
+        })
+        .unwrap();
+        let body = elasticsearch.bulk_body(&events).unwrap();
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0]["index"]["_index"],
+            events[0].timestamp.format("vpn-logs-%Y.%m.%d").to_string()
+        );
+        assert_eq!(lines[1]["fields"]["user"], "alice");
+        assert_eq!(lines[3]["message"], "second");
+    }
+
+    #[test]
+    fn test_buffer_spills_to_spool() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = dir.path().join("loki.ndjson");
+        let messages = |batch: Vec<LogEvent>| -> Vec<String> {
+            batch.into_iter().map(|e| e.message).collect()
+        };
+
+        let mut buffer = LogBuffer::new(4, Some(spool.clone()));
+        for i in 0..6 {
+            buffer.push(event(&i.to_string()));
+        }
+        assert_eq!(buffer.len(), 6);
+        assert_eq!(buffer.spooled(), 2);
+        assert_eq!(buffer.dropped(), 0);
+
+        // A restarted shipper picks the spool up again
+        let mut restarted = LogBuffer::new(4, Some(spool.clone()));
+        assert_eq!(restarted.spooled(), 2);
+        assert_eq!(messages(restarted.peek_batch(10)), ["0", "1"]);
+
+        // Spooled events go first; unacknowledged batches are offered again
+        assert_eq!(messages(buffer.peek_batch(1)), ["0"]);
+        assert_eq!(messages(buffer.peek_batch(1)), ["0"]);
+        buffer.ack(1);
+        assert_eq!(messages(buffer.peek_batch(10)), ["1"]);
+        buffer.ack(1);
+        assert!(!spool.exists());
+        assert_eq!(messages(buffer.peek_batch(10)), ["2", "3", "4", "5"]);
+        buffer.ack(4);
+        assert!(buffer.is_empty());
+
+        // Without a spool, the oldest events are dropped
+        let mut buffer = LogBuffer::new(4, None);
+        for i in 0..6 {
+            buffer.push(event(&i.to_string()));
+        }
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.dropped(), 2);
+        assert_eq!(messages(buffer.peek_batch(1)), ["2"]);
+    }
+
+    struct FlakySink {
+        fail: AtomicBool,
+        shipped: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl LogSink for Arc<FlakySink> {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn ship(&self, events: &[LogEvent]) -> Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(TelemetryError::NetworkError {
+                    message: "unreachable".to_string(),
+                });
+            }
+            let mut shipped = self.shipped.lock().await;
+            shipped.extend(events.iter().map(|e| e.message.clone()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shipper_retries_failed_batches() {
+        let sink = Arc::new(FlakySink {
+            fail: AtomicBool::new(true),
+            shipped: Mutex::new(Vec::new()),
+        });
+        let shipper = LogShipper::new(LogShippingConfig {
+            batch_size: 2,
+            ..Default::default()
+        })
+        .unwrap()
+        .with_sink(Box::new(sink.clone()));
+
+        for line in ["a", "b", "c"] {
+            shipper.ingest(&entry("vpn-server", line)).await;
+        }
+        assert!(shipper.batch_ready().await);
+        assert_eq!(shipper.flush().await, 0);
+
+        sink.fail.store(false, Ordering::SeqCst);
+        assert_eq!(shipper.flush().await, 2);
+        assert_eq!(shipper.flush().await, 1);
+        assert_eq!(*sink.shipped.lock().await, ["a", "b", "c"]);
+
+        let stats = shipper.stats().await;
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.shipped, 3);
+        assert_eq!(stats.failed_batches, 1);
+        assert_eq!(stats.buffered, 0);
+    }
+}
@@ -1,12 +1,22 @@
 //! Metrics collection and Prometheus integration
 
-use crate::{config::TelemetryConfig, error::Result, TelemetryError};
+use crate::{
+    config::{CardinalityConfig, CardinalityOverflow, PerUserMetricsConfig, TelemetryConfig},
+    error::Result,
+    health,
+    host::HostSampler,
+    slo::{SloStatus, SloTracker},
+    TelemetryError,
+};
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
 use prometheus::{
     Counter, CounterVec, Gauge, GaugeVec, Histogram, HistogramVec, Registry, TextEncoder,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::panic::Location;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
@@ -37,6 +47,305 @@ pub struct VpnMetrics {
 
     /// Custom metrics
     pub custom: HashMap<String, f64>,
+
+    /// Per-user figures keyed by user label, see [`UserLabeler`]
+    #[serde(default)]
+    pub users: HashMap<String, UserMetrics>,
+
+    /// Connection latencies of every protocol that has seen a connection
+    #[serde(default)]
+    pub protocols: HashMap<ConnectionProtocol, ProtocolLatency>,
+}
+
+impl VpnMetrics {
+    /// Load figures for this node's cluster health score
+    pub fn node_load(&self) -> vpn_cluster::NodeLoad {
+        let memory_usage = if self.system.memory_total > 0 {
+            self.system.memory_usage as f64 / self.system.memory_total as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        vpn_cluster::NodeLoad {
+            cpu_usage: self.system.cpu_percent,
+            memory_usage,
+            connections: self.active_connections,
+        }
+    }
+}
+
+/// Traffic and connections of one user label
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserMetrics {
+    /// Bytes received from the user
+    pub bytes_in: u64,
+
+    /// Bytes sent to the user
+    pub bytes_out: u64,
+
+    /// Connections currently open
+    pub active_connections: u64,
+
+    /// Failed authentication attempts
+    pub auth_failures: u64,
+}
+
+/// Label users past the cardinality cap are reported under
+pub const OTHER_USERS_LABEL: &str = "_other";
+
+/// Maps user IDs to metric label values
+///
+/// IDs are optionally replaced by a salted hash, and once `max_users`
+/// labels have been handed out every new user gets [`OTHER_USERS_LABEL`].
+/// A user keeps its label for the life of the process so its series stay
+/// continuous.
+pub struct UserLabeler {
+    config: PerUserMetricsConfig,
+    labels: Mutex<HashMap<String, String>>,
+}
+
+impl UserLabeler {
+    pub fn new(config: PerUserMetricsConfig) -> Self {
+        Self {
+            config,
+            labels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Label value for `user_id`
+    pub fn label(&self, user_id: &str) -> String {
+        if !self.config.enabled {
+            return OTHER_USERS_LABEL.to_string();
+        }
+
+        let mut labels = self.labels.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(label) = labels.get(user_id) {
+            return label.clone();
+        }
+        if labels.len() >= self.config.max_users {
+            return OTHER_USERS_LABEL.to_string();
+        }
+
+        let label = if self.config.hash_user_ids {
+            self.hash(user_id)
+        } else {
+            user_id.to_string()
+        };
+        labels.insert(user_id.to_string(), label.clone());
+        label
+    }
+
+    /// Number of users with their own label
+    pub fn labeled_users(&self) -> usize {
+        self.labels.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    fn hash(&self, user_id: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(self.config.hash_salt.as_bytes())
+            .chain_update(user_id.as_bytes())
+            .finalize();
+        // 64 bits keep collisions unlikely at any realistic user count
+        hex::encode(&digest[..8])
+    }
+}
+
+/// Label value of label sets past a metric's limit and of label values
+/// outside the allowlist
+pub const OVERFLOW_LABEL: &str = "_other";
+
+/// Limits the label sets each metric holds
+///
+/// Label values outside [`CardinalityConfig::allowed_values`] become
+/// [`OVERFLOW_LABEL`]. Every metric may then hold up to its limit of label
+/// sets; a new label set past it is dropped or recorded with every label
+/// set to [`OVERFLOW_LABEL`], per [`CardinalityConfig::overflow`]. The
+/// first overflow of each metric from each call site is logged.
+pub struct CardinalityGuard {
+    config: CardinalityConfig,
+    series: Mutex<HashMap<String, HashSet<Vec<String>>>>,
+    overflowed: Mutex<HashMap<String, u64>>,
+    logged: Mutex<HashSet<(String, &'static Location<'static>)>>,
+}
+
+impl CardinalityGuard {
+    pub fn new(config: CardinalityConfig) -> Self {
+        Self {
+            config,
+            series: Mutex::new(HashMap::new()),
+            overflowed: Mutex::new(HashMap::new()),
+            logged: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Label values to record `metric` with, `None` when the value is
+    /// dropped. `labels` are the metric's label names, in the order of
+    /// `values`.
+    #[track_caller]
+    pub fn admit(&self, metric: &str, labels: &[&str], values: &[&str]) -> Option<Vec<String>> {
+        let values: Vec<String> = labels
+            .iter()
+            .zip(values)
+            .map(|(label, value)| self.allowed(label, value).to_string())
+            .collect();
+        if !self.config.enabled {
+            return Some(values);
+        }
+
+        let limit = self
+            .config
+            .limits
+            .get(metric)
+            .copied()
+            .unwrap_or(self.config.max_series);
+        {
+            let mut series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+            let known = series.entry(metric.to_string()).or_default();
+            if known.contains(&values) {
+                return Some(values);
+            }
+            if known.len() < limit {
+                known.insert(values.clone());
+                return Some(values);
+            }
+        }
+
+        *self
+            .overflowed
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(metric.to_string())
+            .or_default() += 1;
+
+        let caller = Location::caller();
+        let first = self
+            .logged
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((metric.to_string(), caller));
+        if first {
+            warn!(
+                "Metric {} reached its limit of {} label sets at {}; {{{}}} {}",
+                metric,
+                limit,
+                caller,
+                labels
+                    .iter()
+                    .zip(&values)
+                    .map(|(label, value)| format!("{}=\"{}\"", label, value))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                match self.config.overflow {
+                    CardinalityOverflow::Reject => "is dropped",
+                    CardinalityOverflow::Aggregate => "is recorded as overflow",
+                }
+            );
+        }
+
+        match self.config.overflow {
+            CardinalityOverflow::Reject => None,
+            CardinalityOverflow::Aggregate => Some(vec![OVERFLOW_LABEL.to_string(); values.len()]),
+        }
+    }
+
+    fn allowed<'a>(&self, label: &str, value: &'a str) -> &'a str {
+        match self.config.allowed_values.get(label) {
+            Some(allowed) if !allowed.iter().any(|a| a == value) => OVERFLOW_LABEL,
+            _ => value,
+        }
+    }
+
+    /// Number of label sets `metric` holds
+    pub fn series(&self, metric: &str) -> usize {
+        self.series
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(metric)
+            .map_or(0, HashSet::len)
+    }
+
+    /// Values recorded past each metric's limit, keyed by metric name
+    pub fn overflowed(&self) -> HashMap<String, u64> {
+        self.overflowed
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+/// Protocol a client connection was made over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionProtocol {
+    Vless,
+    Shadowsocks,
+    Wireguard,
+    HttpProxy,
+    Socks5,
+}
+
+impl ConnectionProtocol {
+    /// Value of the `protocol` label
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConnectionProtocol::Vless => "vless",
+            ConnectionProtocol::Shadowsocks => "shadowsocks",
+            ConnectionProtocol::Wireguard => "wireguard",
+            ConnectionProtocol::HttpProxy => "http_proxy",
+            ConnectionProtocol::Socks5 => "socks5",
+        }
+    }
+}
+
+/// Stage of connection setup a latency is measured for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyPhase {
+    /// Until the transport connection is established
+    Connect,
+    /// Until the protocol handshake completes
+    Handshake,
+    /// Until the first payload byte reaches the client
+    FirstByte,
+}
+
+/// Observations of one latency histogram
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySummary {
+    /// Number of observations
+    pub count: u64,
+
+    /// Sum of all observations (seconds)
+    pub sum_seconds: f64,
+}
+
+impl LatencySummary {
+    /// Mean latency in seconds, `None` without observations
+    pub fn mean_seconds(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum_seconds / self.count as f64)
+    }
+}
+
+/// Connection latencies of one protocol
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProtocolLatency {
+    /// See [`LatencyPhase::Connect`]
+    pub connect: LatencySummary,
+
+    /// See [`LatencyPhase::Handshake`]
+    pub handshake: LatencySummary,
+
+    /// See [`LatencyPhase::FirstByte`]
+    pub first_byte: LatencySummary,
+}
+
+impl ProtocolLatency {
+    fn phase_mut(&mut self, phase: LatencyPhase) -> &mut LatencySummary {
+        match phase {
+            LatencyPhase::Connect => &mut self.connect,
+            LatencyPhase::Handshake => &mut self.handshake,
+            LatencyPhase::FirstByte => &mut self.first_byte,
+        }
+    }
 }
 
 /// Container-related metrics
@@ -127,6 +436,18 @@ pub struct MetricsCollector {
     data_transferred: CounterVec,
     connection_duration: HistogramVec,
 
+    // Per-user metrics
+    user_active_connections: GaugeVec,
+    user_auth_failures: CounterVec,
+    user_labeler: Arc<UserLabeler>,
+    user_stats: Arc<Mutex<HashMap<String, UserMetrics>>>,
+
+    // Per-protocol connection latency
+    protocol_connect_duration: HistogramVec,
+    protocol_handshake_duration: HistogramVec,
+    protocol_first_byte_duration: HistogramVec,
+    protocol_stats: Arc<Mutex<HashMap<ConnectionProtocol, ProtocolLatency>>>,
+
     // Container metrics
     container_operations: CounterVec,
     container_start_duration: HistogramVec,
@@ -142,6 +463,25 @@ pub struct MetricsCollector {
     system_memory_usage: Gauge,
     system_disk_usage: Gauge,
     system_network_bytes: CounterVec,
+    system_cpu_core_usage: GaugeVec,
+    system_memory_total: Gauge,
+    system_disk_total: Gauge,
+    system_network_throughput: GaugeVec,
+    system_open_files: Gauge,
+    system_open_files_max: Gauge,
+    system_conntrack_entries: Gauge,
+    system_conntrack_max: Gauge,
+    host: Arc<Mutex<HostSampler>>,
+
+    // Service level objectives
+    slo_compliance: GaugeVec,
+    slo_error_budget_remaining: GaugeVec,
+    slo_burn_rate: GaugeVec,
+    slo_tracker: Arc<SloTracker>,
+
+    // Label set limits
+    series_overflowed: CounterVec,
+    cardinality: Arc<CardinalityGuard>,
 
     // Custom metrics
     custom_counters: Arc<RwLock<HashMap<String, Counter>>>,
@@ -192,6 +532,54 @@ impl MetricsCollector {
             message: format!("Failed to create connection_duration metric: {}", e),
         })?;
 
+        // Per-user metrics
+        let user_active_connections = GaugeVec::new(
+            prometheus::Opts::new("user_active_connections", "Open connections per user")
+                .namespace("vpn"),
+            &["user_id"],
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create user_active_connections metric: {}", e),
+        })?;
+
+        let user_auth_failures = CounterVec::new(
+            prometheus::Opts::new(
+                "user_auth_failures_total",
+                "Failed authentication attempts per user",
+            )
+            .namespace("vpn"),
+            &["user_id"],
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create user_auth_failures metric: {}", e),
+        })?;
+
+        // Per-protocol connection latency, 1ms to ~33s
+        let latency_buckets = prometheus::exponential_buckets(0.001, 2.0, 16)?;
+        let protocol_latency = |name: &str, help: &str| {
+            HistogramVec::new(
+                prometheus::HistogramOpts::new(name, help)
+                    .namespace("vpn")
+                    .buckets(latency_buckets.clone()),
+                &["protocol"],
+            )
+            .map_err(|e| TelemetryError::MetricsError {
+                message: format!("Failed to create {} metric: {}", name, e),
+            })
+        };
+        let protocol_connect_duration = protocol_latency(
+            "protocol_connect_duration_seconds",
+            "Time to establish a client connection per protocol",
+        )?;
+        let protocol_handshake_duration = protocol_latency(
+            "protocol_handshake_duration_seconds",
+            "Time to complete the protocol handshake per protocol",
+        )?;
+        let protocol_first_byte_duration = protocol_latency(
+            "protocol_first_byte_duration_seconds",
+            "Time until the first payload byte reaches the client per protocol",
+        )?;
+
         // Container metrics
         let container_operations = CounterVec::new(
             prometheus::Opts::new("container_operations_total", "Total container operations")
@@ -273,10 +661,117 @@ impl MetricsCollector {
             message: format!("Failed to create system_network_bytes metric: {}", e),
         })?;
 
+        let system_cpu_core_usage = GaugeVec::new(
+            prometheus::Opts::new("system_cpu_core_usage_percent", "CPU usage per core"),
+            &["core"],
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create system_cpu_core_usage metric: {}", e),
+        })?;
+
+        let system_memory_total = Gauge::new("system_memory_total_bytes", "System memory in bytes")
+            .map_err(|e| TelemetryError::MetricsError {
+                message: format!("Failed to create system_memory_total metric: {}", e),
+            })?;
+
+        let system_disk_total = Gauge::new(
+            "system_disk_total_bytes",
+            "Size of the filesystem holding the install path in bytes",
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create system_disk_total metric: {}", e),
+        })?;
+
+        let system_network_throughput = GaugeVec::new(
+            prometheus::Opts::new(
+                "system_network_throughput_bytes_per_second",
+                "Network interface throughput",
+            ),
+            &["interface", "direction"],
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create system_network_throughput metric: {}", e),
+        })?;
+
+        let system_open_files =
+            Gauge::new("system_open_files", "Open file descriptors").map_err(|e| {
+                TelemetryError::MetricsError {
+                    message: format!("Failed to create system_open_files metric: {}", e),
+                }
+            })?;
+
+        let system_open_files_max = Gauge::new("system_open_files_max", "File descriptor limit")
+            .map_err(|e| TelemetryError::MetricsError {
+                message: format!("Failed to create system_open_files_max metric: {}", e),
+            })?;
+
+        let system_conntrack_entries =
+            Gauge::new("system_conntrack_entries", "Conntrack table entries").map_err(|e| {
+                TelemetryError::MetricsError {
+                    message: format!("Failed to create system_conntrack_entries metric: {}", e),
+                }
+            })?;
+
+        let system_conntrack_max = Gauge::new("system_conntrack_max", "Conntrack table size")
+            .map_err(|e| TelemetryError::MetricsError {
+                message: format!("Failed to create system_conntrack_max metric: {}", e),
+            })?;
+
+        // Service level objective metrics
+        let slo_compliance = GaugeVec::new(
+            prometheus::Opts::new(
+                "slo_compliance_ratio",
+                "Share of good events over the SLO window",
+            )
+            .namespace("vpn"),
+            &["slo"],
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create slo_compliance metric: {}", e),
+        })?;
+
+        let slo_error_budget_remaining = GaugeVec::new(
+            prometheus::Opts::new(
+                "slo_error_budget_remaining_ratio",
+                "Share of the SLO error budget not yet spent",
+            )
+            .namespace("vpn"),
+            &["slo"],
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create slo_error_budget_remaining metric: {}", e),
+        })?;
+
+        let slo_burn_rate = GaugeVec::new(
+            prometheus::Opts::new("slo_burn_rate", "SLO error budget burn rate over a window")
+                .namespace("vpn"),
+            &["slo", "window"],
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create slo_burn_rate metric: {}", e),
+        })?;
+
+        let series_overflowed = CounterVec::new(
+            prometheus::Opts::new(
+                "metric_series_overflow_total",
+                "Values recorded past a metric's label set limit",
+            )
+            .namespace("vpn"),
+            &["metric"],
+        )
+        .map_err(|e| TelemetryError::MetricsError {
+            message: format!("Failed to create series_overflowed metric: {}", e),
+        })?;
+
         // Register all metrics
         registry.register(Box::new(user_connections.clone()))?;
         registry.register(Box::new(data_transferred.clone()))?;
         registry.register(Box::new(connection_duration.clone()))?;
+        registry.register(Box::new(user_active_connections.clone()))?;
+        registry.register(Box::new(user_auth_failures.clone()))?;
+        registry.register(Box::new(protocol_connect_duration.clone()))?;
+        registry.register(Box::new(protocol_handshake_duration.clone()))?;
+        registry.register(Box::new(protocol_first_byte_duration.clone()))?;
         registry.register(Box::new(container_operations.clone()))?;
         registry.register(Box::new(container_start_duration.clone()))?;
         registry.register(Box::new(container_status.clone()))?;
@@ -287,6 +782,18 @@ impl MetricsCollector {
         registry.register(Box::new(system_memory_usage.clone()))?;
         registry.register(Box::new(system_disk_usage.clone()))?;
         registry.register(Box::new(system_network_bytes.clone()))?;
+        registry.register(Box::new(system_cpu_core_usage.clone()))?;
+        registry.register(Box::new(system_memory_total.clone()))?;
+        registry.register(Box::new(system_disk_total.clone()))?;
+        registry.register(Box::new(system_network_throughput.clone()))?;
+        registry.register(Box::new(system_open_files.clone()))?;
+        registry.register(Box::new(system_open_files_max.clone()))?;
+        registry.register(Box::new(system_conntrack_entries.clone()))?;
+        registry.register(Box::new(system_conntrack_max.clone()))?;
+        registry.register(Box::new(slo_compliance.clone()))?;
+        registry.register(Box::new(slo_error_budget_remaining.clone()))?;
+        registry.register(Box::new(slo_burn_rate.clone()))?;
+        registry.register(Box::new(series_overflowed.clone()))?;
 
         Ok(Self {
             config: config.clone(),
@@ -294,6 +801,14 @@ impl MetricsCollector {
             user_connections,
             data_transferred,
             connection_duration,
+            user_active_connections,
+            user_auth_failures,
+            user_labeler: Arc::new(UserLabeler::new(config.metrics.per_user.clone())),
+            user_stats: Arc::new(Mutex::new(HashMap::new())),
+            protocol_connect_duration,
+            protocol_handshake_duration,
+            protocol_first_byte_duration,
+            protocol_stats: Arc::new(Mutex::new(HashMap::new())),
             container_operations,
             container_start_duration,
             container_status,
@@ -304,6 +819,23 @@ impl MetricsCollector {
             system_memory_usage,
             system_disk_usage,
             system_network_bytes,
+            system_cpu_core_usage,
+            system_memory_total,
+            system_disk_total,
+            system_network_throughput,
+            system_open_files,
+            system_open_files_max,
+            system_conntrack_entries,
+            system_conntrack_max,
+            host: Arc::new(Mutex::new(HostSampler::new(
+                config.health.install_path.clone(),
+            ))),
+            slo_compliance,
+            slo_error_budget_remaining,
+            slo_burn_rate,
+            slo_tracker: Arc::new(SloTracker::new(config.slo.objectives.clone())),
+            series_overflowed,
+            cardinality: Arc::new(CardinalityGuard::new(config.metrics.cardinality.clone())),
             custom_counters: Arc::new(RwLock::new(HashMap::new())),
             custom_gauges: Arc::new(RwLock::new(HashMap::new())),
             custom_histograms: Arc::new(RwLock::new(HashMap::new())),
@@ -342,7 +874,7 @@ impl MetricsCollector {
                     break;
                 }
 
-                if let Err(e) = Self::collect_metrics(collector.clone()).await {
+                if let Err(e) = collector.read().await.collect_now().await {
                     warn!("Failed to collect metrics: {}", e);
                 }
             }
@@ -359,56 +891,102 @@ impl MetricsCollector {
         Ok(())
     }
 
-    /// Collect current metrics
-    async fn collect_metrics(collector: Arc<RwLock<Self>>) -> Result<()> {
-        let collector_guard = collector.write().await;
-
+    /// Collect current metrics immediately instead of waiting for the next
+    /// collection interval
+    pub async fn collect_now(&self) -> Result<()> {
         // Update system metrics
-        if let Ok(system_info) = Self::get_system_info().await {
-            collector_guard
-                .system_cpu_usage
-                .set(system_info.cpu_percent);
-            collector_guard
-                .system_memory_usage
-                .set(system_info.memory_usage as f64);
-            collector_guard
-                .system_disk_usage
-                .set(system_info.disk_usage as f64);
-            collector_guard
-                .system_network_bytes
-                .with_label_values(&["rx"])
-                .inc_by(system_info.network_rx as f64);
-            collector_guard
-                .system_network_bytes
-                .with_label_values(&["tx"])
-                .inc_by(system_info.network_tx as f64);
-        }
+        let host = self.host.lock().unwrap_or_else(|e| e.into_inner()).sample();
+        self.record_host_metrics(&host);
+        self.record_slo_metrics();
+        self.record_cardinality_metrics();
 
         // Update server metrics
-        collector_guard
-            .server_uptime
-            .set(Self::get_uptime().await as f64);
+        self.server_uptime.set(Self::get_uptime().await as f64);
 
-        *collector_guard.last_update.write().await = Instant::now();
+        *self.last_update.write().await = Instant::now();
         debug!("Collected metrics successfully");
 
         Ok(())
     }
 
-    /// Get current system information
-    async fn get_system_info() -> Result<SystemMetrics> {
-        // This is a simplified implementation
-        // In a real implementation, you would use system APIs to get actual metrics
-        Ok(SystemMetrics {
-            cpu_percent: 0.0,
-            memory_usage: 0,
-            memory_total: 0,
-            disk_usage: 0,
-            disk_total: 0,
-            network_rx: 0,
-            network_tx: 0,
-            load_avg_1m: 0.0,
-        })
+    /// Set the system gauges from a host sample
+    fn record_host_metrics(&self, host: &health::SystemMetrics) {
+        self.system_cpu_usage.set(host.cpu_usage_percent);
+        for (core, usage) in host.cpu_per_core_percent.iter().enumerate() {
+            self.system_cpu_core_usage
+                .with_label_values(&[core.to_string().as_str()])
+                .set(*usage);
+        }
+
+        self.system_memory_usage.set(host.memory_used_bytes as f64);
+        self.system_memory_total.set(host.memory_total_bytes as f64);
+        self.system_disk_usage.set(host.disk_used_bytes as f64);
+        self.system_disk_total.set(host.disk_total_bytes as f64);
+
+        // Every container adds a veth interface
+        for interface in &host.interfaces {
+            let name = interface.name.as_str();
+            if let Some(gauge) = self.series(&self.system_network_throughput, &[name, "rx"]) {
+                gauge.set(interface.rx_bytes_per_sec);
+            }
+            if let Some(gauge) = self.series(&self.system_network_throughput, &[name, "tx"]) {
+                gauge.set(interface.tx_bytes_per_sec);
+            }
+        }
+
+        // The counters follow the kernel's totals; they stall rather than
+        // go backwards when an interface disappears
+        let system = Self::system_metrics(host);
+        for (direction, total) in [("rx", system.network_rx), ("tx", system.network_tx)] {
+            let counter = self.system_network_bytes.with_label_values(&[direction]);
+            let delta = total as f64 - counter.get();
+            if delta > 0.0 {
+                counter.inc_by(delta);
+            }
+        }
+
+        self.system_open_files.set(host.open_files as f64);
+        self.system_open_files_max.set(host.open_files_max as f64);
+        self.system_conntrack_entries
+            .set(host.conntrack_entries as f64);
+        self.system_conntrack_max.set(host.conntrack_max as f64);
+    }
+
+    /// Set the SLO gauges from the tracker's current status; objectives
+    /// without events have no series
+    fn record_slo_metrics(&self) {
+        for status in self.slo_tracker.status() {
+            let name = status.name.as_str();
+            if let Some(compliance) = status.compliance {
+                self.slo_compliance
+                    .with_label_values(&[name])
+                    .set(compliance / 100.0);
+            }
+            if let Some(remaining) = status.error_budget_remaining {
+                self.slo_error_budget_remaining
+                    .with_label_values(&[name])
+                    .set(remaining);
+            }
+            for (window, rate) in &status.burn_rates {
+                self.slo_burn_rate
+                    .with_label_values(&[name, window.as_str()])
+                    .set(*rate);
+            }
+        }
+    }
+
+    /// Summary of a host sample for [`VpnMetrics`]
+    fn system_metrics(host: &health::SystemMetrics) -> SystemMetrics {
+        SystemMetrics {
+            cpu_percent: host.cpu_usage_percent,
+            memory_usage: host.memory_used_bytes,
+            memory_total: host.memory_total_bytes,
+            disk_usage: host.disk_used_bytes,
+            disk_total: host.disk_total_bytes,
+            network_rx: host.interfaces.iter().map(|i| i.rx_bytes).sum(),
+            network_tx: host.interfaces.iter().map(|i| i.tx_bytes).sum(),
+            load_avg_1m: host.load_average,
+        }
     }
 
     /// Get server uptime
@@ -473,22 +1051,30 @@ impl MetricsCollector {
                 memory_usage: 0,
                 network_errors: 0,
             },
-            system: SystemMetrics {
-                cpu_percent: 0.0,
-                memory_usage: 0,
-                memory_total: 0,
-                disk_usage: 0,
-                disk_total: 0,
-                network_rx: 0,
-                network_tx: 0,
-                load_avg_1m: 0.0,
-            },
+            system: Self::system_metrics(
+                self.host
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .last()
+                    .unwrap_or(&health::SystemMetrics::default()),
+            ),
             custom: HashMap::new(),
+            users: self
+                .user_stats
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            protocols: self
+                .protocol_stats
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
         })
     }
 
     /// Export metrics in Prometheus format
     pub async fn export_metrics(&self) -> Result<String> {
+        self.record_cardinality_metrics();
         let encoder = TextEncoder::new();
         let metric_families = self.registry.gather();
 
@@ -499,41 +1085,181 @@ impl MetricsCollector {
             })
     }
 
+    /// Label value metrics use for `user_id`
+    pub fn user_label(&self, user_id: &str) -> String {
+        self.user_labeler.label(user_id)
+    }
+
+    /// Tracker the service level objectives are computed by
+    pub fn slo_tracker(&self) -> Arc<SloTracker> {
+        self.slo_tracker.clone()
+    }
+
+    /// Record an occurrence of an SLO event, e.g. `proxy_connect`, with
+    /// its latency when known
+    pub fn record_slo_event(&self, event: &str, success: bool, latency: Option<Duration>) {
+        self.slo_tracker.record(event, success, latency);
+    }
+
+    /// Current status of every service level objective
+    pub fn slo_status(&self) -> Vec<SloStatus> {
+        self.slo_tracker.status()
+    }
+
+    /// Guard limiting the label sets of the collector's metrics
+    pub fn cardinality_guard(&self) -> Arc<CardinalityGuard> {
+        self.cardinality.clone()
+    }
+
+    /// Series of `vec` with label `values`, `None` when the cardinality
+    /// guard drops it
+    #[track_caller]
+    fn series<T: MetricVecBuilder>(&self, vec: &MetricVec<T>, values: &[&str]) -> Option<T::M> {
+        let desc = vec.desc()[0];
+        let labels: Vec<&str> = desc.variable_labels.iter().map(String::as_str).collect();
+        let values = self.cardinality.admit(&desc.fq_name, &labels, values)?;
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+        Some(vec.with_label_values(&values))
+    }
+
+    /// Bring the overflow counters up to the guard's totals
+    fn record_cardinality_metrics(&self) {
+        for (metric, total) in self.cardinality.overflowed() {
+            let counter = self.series_overflowed.with_label_values(&[metric.as_str()]);
+            let delta = total as f64 - counter.get();
+            if delta > 0.0 {
+                counter.inc_by(delta);
+            }
+        }
+    }
+
     /// Record user connection
+    #[track_caller]
     pub fn record_user_connection(&self, user_id: &str, protocol: &str, success: bool) {
         let status = if success { "success" } else { "failure" };
-        self.user_connections
-            .with_label_values(&[user_id, protocol, status])
-            .inc();
+        let user = self.user_label(user_id);
+        if let Some(counter) = self.series(&self.user_connections, &[&user, protocol, status]) {
+            counter.inc();
+        }
     }
 
     /// Record data transfer
+    #[track_caller]
     pub fn record_data_transfer(&self, direction: &str, user_id: &str, bytes: u64) {
-        self.data_transferred
-            .with_label_values(&[direction, user_id])
-            .inc_by(bytes as f64);
+        let user = self.user_label(user_id);
+        if let Some(counter) = self.series(&self.data_transferred, &[direction, &user]) {
+            counter.inc_by(bytes as f64);
+        }
+    }
+
+    /// Record traffic received from (`bytes_in`) and sent to (`bytes_out`)
+    /// a user
+    #[track_caller]
+    pub fn record_user_traffic(&self, user_id: &str, bytes_in: u64, bytes_out: u64) {
+        let user = self.user_label(user_id);
+        if let Some(counter) = self.series(&self.data_transferred, &["in", &user]) {
+            counter.inc_by(bytes_in as f64);
+        }
+        if let Some(counter) = self.series(&self.data_transferred, &["out", &user]) {
+            counter.inc_by(bytes_out as f64);
+        }
+
+        self.update_user_stats(user, |stats| {
+            stats.bytes_in += bytes_in;
+            stats.bytes_out += bytes_out;
+        });
+    }
+
+    /// Record a user's connection opening
+    #[track_caller]
+    pub fn record_user_connected(&self, user_id: &str) {
+        let user = self.user_label(user_id);
+        if let Some(gauge) = self.series(&self.user_active_connections, &[&user]) {
+            gauge.inc();
+        }
+        self.update_user_stats(user, |stats| stats.active_connections += 1);
+    }
+
+    /// Record a user's connection closing
+    #[track_caller]
+    pub fn record_user_disconnected(&self, user_id: &str) {
+        let user = self.user_label(user_id);
+        let mut open = 0;
+        self.update_user_stats(user.clone(), |stats| {
+            stats.active_connections = stats.active_connections.saturating_sub(1);
+            open = stats.active_connections;
+        });
+        if let Some(gauge) = self.series(&self.user_active_connections, &[&user]) {
+            gauge.set(open as f64);
+        }
+    }
+
+    /// Record a failed authentication attempt by a user
+    #[track_caller]
+    pub fn record_auth_failure(&self, user_id: &str) {
+        let user = self.user_label(user_id);
+        if let Some(counter) = self.series(&self.user_auth_failures, &[&user]) {
+            counter.inc();
+        }
+        self.update_user_stats(user, |stats| stats.auth_failures += 1);
+    }
+
+    fn update_user_stats(&self, user: String, update: impl FnOnce(&mut UserMetrics)) {
+        let mut stats = self.user_stats.lock().unwrap_or_else(|e| e.into_inner());
+        update(stats.entry(user).or_default());
     }
 
     /// Record connection duration
+    #[track_caller]
     pub fn record_connection_duration(&self, user_id: &str, protocol: &str, duration: Duration) {
-        self.connection_duration
-            .with_label_values(&[user_id, protocol])
-            .observe(duration.as_secs_f64());
+        let user = self.user_label(user_id);
+        if let Some(histogram) = self.series(&self.connection_duration, &[&user, protocol]) {
+            histogram.observe(duration.as_secs_f64());
+        }
+    }
+
+    /// Record how long a stage of connection setup took for `protocol`
+    pub fn record_protocol_latency(
+        &self,
+        protocol: ConnectionProtocol,
+        phase: LatencyPhase,
+        duration: Duration,
+    ) {
+        let histogram = match phase {
+            LatencyPhase::Connect => &self.protocol_connect_duration,
+            LatencyPhase::Handshake => &self.protocol_handshake_duration,
+            LatencyPhase::FirstByte => &self.protocol_first_byte_duration,
+        };
+        let seconds = duration.as_secs_f64();
+        histogram
+            .with_label_values(&[protocol.label()])
+            .observe(seconds);
+
+        let mut stats = self
+            .protocol_stats
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let summary = stats.entry(protocol).or_default().phase_mut(phase);
+        summary.count += 1;
+        summary.sum_seconds += seconds;
     }
 
     /// Record container operation
+    #[track_caller]
     pub fn record_container_operation(&self, operation: &str, container_type: &str, success: bool) {
         let status = if success { "success" } else { "failure" };
-        self.container_operations
-            .with_label_values(&[operation, container_type, status])
-            .inc();
+        let values = [operation, container_type, status];
+        if let Some(counter) = self.series(&self.container_operations, &values) {
+            counter.inc();
+        }
     }
 
     /// Record container start duration
+    #[track_caller]
     pub fn record_container_start_duration(&self, container_type: &str, duration: Duration) {
-        self.container_start_duration
-            .with_label_values(&[container_type])
-            .observe(duration.as_secs_f64());
+        if let Some(histogram) = self.series(&self.container_start_duration, &[container_type]) {
+            histogram.observe(duration.as_secs_f64());
+        }
     }
 }
 
@@ -545,6 +1271,14 @@ impl Clone for MetricsCollector {
             user_connections: self.user_connections.clone(),
             data_transferred: self.data_transferred.clone(),
             connection_duration: self.connection_duration.clone(),
+            user_active_connections: self.user_active_connections.clone(),
+            user_auth_failures: self.user_auth_failures.clone(),
+            user_labeler: self.user_labeler.clone(),
+            user_stats: self.user_stats.clone(),
+            protocol_connect_duration: self.protocol_connect_duration.clone(),
+            protocol_handshake_duration: self.protocol_handshake_duration.clone(),
+            protocol_first_byte_duration: self.protocol_first_byte_duration.clone(),
+            protocol_stats: self.protocol_stats.clone(),
             container_operations: self.container_operations.clone(),
             container_start_duration: self.container_start_duration.clone(),
             container_status: self.container_status.clone(),
@@ -555,6 +1289,21 @@ impl Clone for MetricsCollector {
             system_memory_usage: self.system_memory_usage.clone(),
             system_disk_usage: self.system_disk_usage.clone(),
             system_network_bytes: self.system_network_bytes.clone(),
+            system_cpu_core_usage: self.system_cpu_core_usage.clone(),
+            system_memory_total: self.system_memory_total.clone(),
+            system_disk_total: self.system_disk_total.clone(),
+            system_network_throughput: self.system_network_throughput.clone(),
+            system_open_files: self.system_open_files.clone(),
+            system_open_files_max: self.system_open_files_max.clone(),
+            system_conntrack_entries: self.system_conntrack_entries.clone(),
+            system_conntrack_max: self.system_conntrack_max.clone(),
+            host: self.host.clone(),
+            slo_compliance: self.slo_compliance.clone(),
+            slo_error_budget_remaining: self.slo_error_budget_remaining.clone(),
+            slo_burn_rate: self.slo_burn_rate.clone(),
+            slo_tracker: self.slo_tracker.clone(),
+            series_overflowed: self.series_overflowed.clone(),
+            cardinality: self.cardinality.clone(),
             custom_counters: self.custom_counters.clone(),
             custom_gauges: self.custom_gauges.clone(),
             custom_histograms: self.custom_histograms.clone(),
@@ -600,6 +1349,196 @@ mod tests {
         assert!(!*collector.running.read().await);
     }
 
+    #[tokio::test]
+    async fn test_node_load_from_metrics() {
+        let config = TelemetryConfig::default();
+        let collector = MetricsCollector::new(&config).await.unwrap();
+        let mut metrics = collector.get_current_metrics().await.unwrap();
+
+        metrics.active_connections = 120;
+        metrics.system.cpu_percent = 75.0;
+        metrics.system.memory_usage = 3 * 1024;
+        metrics.system.memory_total = 4 * 1024;
+
+        let load = metrics.node_load();
+        assert_eq!(load.cpu_usage, 75.0);
+        assert_eq!(load.memory_usage, 75.0);
+        assert_eq!(load.connections, 120);
+    }
+
+    #[test]
+    fn test_user_labels() {
+        let labeler = UserLabeler::new(PerUserMetricsConfig {
+            max_users: 2,
+            hash_salt: "salt".to_string(),
+            ..PerUserMetricsConfig::default()
+        });
+
+        let alice = labeler.label("alice");
+        assert_eq!(alice.len(), 16);
+        assert_ne!(alice, "alice");
+        assert_eq!(labeler.label("alice"), alice);
+        assert_ne!(labeler.label("bob"), alice);
+
+        // Past the cap new users share the overflow label
+        assert_eq!(labeler.label("carol"), OTHER_USERS_LABEL);
+        assert_eq!(labeler.label("alice"), alice);
+        assert_eq!(labeler.labeled_users(), 2);
+
+        // Another salt gives other hashes
+        let resalted = UserLabeler::new(PerUserMetricsConfig {
+            hash_salt: "pepper".to_string(),
+            ..PerUserMetricsConfig::default()
+        });
+        assert_ne!(resalted.label("alice"), alice);
+
+        let plain = UserLabeler::new(PerUserMetricsConfig {
+            hash_user_ids: false,
+            ..PerUserMetricsConfig::default()
+        });
+        assert_eq!(plain.label("alice"), "alice");
+    }
+
+    #[tokio::test]
+    async fn test_per_user_metrics() {
+        let config = TelemetryConfig::default();
+        let collector = MetricsCollector::new(&config).await.unwrap();
+
+        collector.record_user_connected("alice");
+        collector.record_user_connected("alice");
+        collector.record_user_disconnected("alice");
+        collector.record_user_traffic("alice", 100, 400);
+        collector.record_auth_failure("bob");
+
+        let metrics = collector.get_current_metrics().await.unwrap();
+        let alice = &metrics.users[&collector.user_label("alice")];
+        assert_eq!(alice.active_connections, 1);
+        assert_eq!(alice.bytes_in, 100);
+        assert_eq!(alice.bytes_out, 400);
+        assert_eq!(metrics.users[&collector.user_label("bob")].auth_failures, 1);
+
+        let exported = collector.export_metrics().await.unwrap();
+        assert!(exported.contains("vpn_user_auth_failures_total"));
+        assert!(!exported.contains("alice"));
+    }
+
+    #[test]
+    fn test_cardinality_guard() {
+        let guard = CardinalityGuard::new(CardinalityConfig {
+            max_series: 2,
+            limits: HashMap::from([("vpn_container_status".to_string(), 1)]),
+            allowed_values: HashMap::from([(
+                "direction".to_string(),
+                vec!["in".to_string(), "out".to_string()],
+            )]),
+            ..CardinalityConfig::default()
+        });
+        let labels = ["direction", "port"];
+
+        assert_eq!(
+            guard.admit("vpn_bytes", &labels, &["in", "40001"]),
+            Some(vec!["in".to_string(), "40001".to_string()])
+        );
+        assert_eq!(
+            guard.admit("vpn_bytes", &labels, &["sideways", "40002"]),
+            Some(vec![OVERFLOW_LABEL.to_string(), "40002".to_string()])
+        );
+
+        // Known label sets still pass once the limit is reached
+        assert!(guard
+            .admit("vpn_bytes", &labels, &["in", "40001"])
+            .is_some());
+        assert_eq!(
+            guard.admit("vpn_bytes", &labels, &["out", "40003"]),
+            Some(vec![OVERFLOW_LABEL.to_string(); 2])
+        );
+        assert_eq!(guard.series("vpn_bytes"), 2);
+        assert_eq!(guard.overflowed()["vpn_bytes"], 1);
+
+        assert!(guard
+            .admit("vpn_container_status", &["id"], &["a"])
+            .is_some());
+        assert_eq!(
+            guard.admit("vpn_container_status", &["id"], &["b"]),
+            Some(vec![OVERFLOW_LABEL.to_string()])
+        );
+
+        let rejecting = CardinalityGuard::new(CardinalityConfig {
+            max_series: 1,
+            overflow: CardinalityOverflow::Reject,
+            ..CardinalityConfig::default()
+        });
+        assert!(rejecting.admit("vpn_bytes", &["port"], &["1"]).is_some());
+        assert_eq!(rejecting.admit("vpn_bytes", &["port"], &["2"]), None);
+    }
+
+    #[tokio::test]
+    async fn test_cardinality_limits_collector() {
+        let mut config = TelemetryConfig::default();
+        config.metrics.per_user.hash_user_ids = false;
+        config.metrics.cardinality.max_series = 2;
+        let collector = MetricsCollector::new(&config).await.unwrap();
+
+        for port in 40000..40005 {
+            collector.record_container_operation("start", &port.to_string(), true);
+        }
+        collector.record_user_connected("alice");
+
+        let exported = collector.export_metrics().await.unwrap();
+        assert!(exported.contains("container_type=\"40001\""));
+        assert!(!exported.contains("container_type=\"40002\""));
+        assert!(exported.contains(
+            "vpn_container_operations_total{container_type=\"_other\",operation=\"_other\",status=\"_other\"} 3"
+        ));
+        assert!(exported.contains(
+            "vpn_metric_series_overflow_total{metric=\"vpn_container_operations_total\"} 3"
+        ));
+        assert!(exported.contains("vpn_user_active_connections{user_id=\"alice\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_latency_histograms() {
+        let config = TelemetryConfig::default();
+        let collector = MetricsCollector::new(&config).await.unwrap();
+
+        collector.record_protocol_latency(
+            ConnectionProtocol::Vless,
+            LatencyPhase::Handshake,
+            Duration::from_millis(20),
+        );
+        collector.record_protocol_latency(
+            ConnectionProtocol::Vless,
+            LatencyPhase::Handshake,
+            Duration::from_millis(40),
+        );
+        collector.record_protocol_latency(
+            ConnectionProtocol::Socks5,
+            LatencyPhase::Connect,
+            Duration::from_millis(3),
+        );
+
+        let metrics = collector.get_current_metrics().await.unwrap();
+        let vless = &metrics.protocols[&ConnectionProtocol::Vless];
+        assert_eq!(vless.handshake.count, 2);
+        assert!((vless.handshake.mean_seconds().unwrap() - 0.03).abs() < 1e-9);
+        assert_eq!(vless.connect.mean_seconds(), None);
+        assert_eq!(
+            metrics.protocols[&ConnectionProtocol::Socks5].connect.count,
+            1
+        );
+        assert!(!metrics
+            .protocols
+            .contains_key(&ConnectionProtocol::Wireguard));
+
+        let exported = collector.export_metrics().await.unwrap();
+        assert!(exported.contains(
+            "vpn_protocol_handshake_duration_seconds_bucket{protocol=\"vless\",le=\"0.032\"} 1"
+        ));
+        assert!(
+            exported.contains("vpn_protocol_connect_duration_seconds_count{protocol=\"socks5\"} 1")
+        );
+    }
+
     #[tokio::test]
     async fn test_custom_metric_recording() {
         let config = TelemetryConfig::default();
@@ -620,4 +1559,33 @@ mod tests {
         let metrics = result.unwrap();
         assert!(metrics.contains("vpn_"));
     }
+
+    #[tokio::test]
+    async fn test_host_metrics_gauges() {
+        let config = TelemetryConfig::default();
+        let collector = MetricsCollector::new(&config).await.unwrap();
+
+        let host = health::SystemMetrics {
+            cpu_per_core_percent: vec![10.0, 25.0],
+            conntrack_entries: 750,
+            conntrack_max: 1000,
+            interfaces: vec![health::InterfaceThroughput {
+                name: "eth0".to_string(),
+                rx_bytes: 4096,
+                tx_bytes: 1024,
+                rx_bytes_per_sec: 512.0,
+                tx_bytes_per_sec: 128.0,
+            }],
+            ..Default::default()
+        };
+        collector.record_host_metrics(&host);
+
+        let exported = collector.export_metrics().await.unwrap();
+        assert!(exported.contains("system_cpu_core_usage_percent{core=\"1\"} 25"));
+        assert!(exported.contains("system_conntrack_entries 750"));
+        assert!(exported.contains(
+            "system_network_throughput_bytes_per_second{direction=\"rx\",interface=\"eth0\"} 512"
+        ));
+        assert!(exported.contains("system_network_bytes_total{direction=\"rx\"} 4096"));
+    }
 }
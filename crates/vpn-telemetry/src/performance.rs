@@ -1,13 +1,17 @@
 //! Performance monitoring and benchmarking
 
+use crate::timeseries::{Resolution, SeriesPoint, TimeSeriesStore};
 use crate::{config::TelemetryConfig, error::Result, TelemetryError};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn};
 
+#[cfg(all(target_os = "linux", feature = "ebpf"))]
+pub mod ebpf;
+
 /// Performance metrics for the VPN system
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PerformanceMetrics {
@@ -96,6 +100,7 @@ struct PerformanceSample {
 pub struct PerformanceMonitor {
     config: TelemetryConfig,
     samples: Arc<RwLock<VecDeque<PerformanceSample>>>,
+    history: Arc<RwLock<TimeSeriesStore>>,
     running: Arc<RwLock<bool>>,
     collection_handle: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
     benchmarks: Arc<RwLock<HashMap<String, BenchmarkResult>>>,
@@ -108,6 +113,9 @@ impl PerformanceMonitor {
         Ok(Self {
             config: config.clone(),
             samples: Arc::new(RwLock::new(VecDeque::new())),
+            history: Arc::new(RwLock::new(TimeSeriesStore::open(
+                config.performance.retention.clone(),
+            )?)),
             running: Arc::new(RwLock::new(false)),
             collection_handle: Arc::new(RwLock::new(None)),
             benchmarks: Arc::new(RwLock::new(HashMap::new())),
@@ -127,11 +135,12 @@ impl PerformanceMonitor {
 
         // Start background collection task
         let samples = self.samples.clone();
+        let history = self.history.clone();
         let config = self.config.clone();
         let running_flag = self.running.clone();
 
         let collection_task = tokio::spawn(async move {
-            Self::collection_loop(samples, config, running_flag).await;
+            Self::collection_loop(samples, history, config, running_flag).await;
         });
 
         *self.collection_handle.write().await = Some(collection_task);
@@ -191,6 +200,22 @@ impl PerformanceMonitor {
         Ok(trends)
     }
 
+    /// Get performance history over `range`, e.g. the last 24 hours
+    ///
+    /// Longer ranges come from coarser rollups: each returned metrics value
+    /// holds the averages over its minute or hour, stamped with its start.
+    pub async fn get_performance_history(
+        &self,
+        range: Duration,
+    ) -> Result<(Resolution, Vec<PerformanceMetrics>)> {
+        let (resolution, points) = self.history.read().await.query(range, chrono::Utc::now());
+        let metrics = points
+            .iter()
+            .map(PerformanceMetrics::from_point)
+            .collect::<Result<_>>()?;
+        Ok((resolution, metrics))
+    }
+
     /// Run a performance benchmark
     pub async fn run_benchmark(
         &self,
@@ -308,6 +333,7 @@ impl PerformanceMonitor {
     /// Performance collection loop
     async fn collection_loop(
         samples: Arc<RwLock<VecDeque<PerformanceSample>>>,
+        history: Arc<RwLock<TimeSeriesStore>>,
         config: TelemetryConfig,
         running: Arc<RwLock<bool>>,
     ) {
@@ -318,6 +344,10 @@ impl PerformanceMonitor {
 
             match Self::collect_current_metrics().await {
                 Ok(metrics) => {
+                    if let Err(e) = history.write().await.record(metrics.to_point()) {
+                        warn!("Failed to store performance history: {}", e);
+                    }
+
                     let sample = PerformanceSample {
                         timestamp: Instant::now(),
                         metrics,
@@ -498,6 +528,52 @@ pub struct PerformanceSummary {
     pub memory_usage_max: u64,
 }
 
+impl PerformanceMetrics {
+    /// Numeric fields keyed by their dotted path, e.g.
+    /// `system_performance.cpu_usage_percent`; benchmarks are left out
+    fn to_point(&self) -> SeriesPoint {
+        let mut values = BTreeMap::new();
+        if let Ok(serde_json::Value::Object(sections)) = serde_json::to_value(self) {
+            for (section, fields) in sections {
+                let serde_json::Value::Object(fields) = fields else {
+                    continue;
+                };
+                if section == "benchmarks" {
+                    continue;
+                }
+                for (field, value) in fields {
+                    if let Some(value) = value.as_f64() {
+                        values.insert(format!("{}.{}", section, field), value);
+                    }
+                }
+            }
+        }
+        SeriesPoint::sample(self.timestamp, values)
+    }
+
+    /// Metrics holding the means of `point`, rounded for integer fields
+    fn from_point(point: &SeriesPoint) -> Result<Self> {
+        let mut template = serde_json::to_value(Self::default())?;
+        for (path, stats) in &point.fields {
+            let Some((section, field)) = path.split_once('.') else {
+                continue;
+            };
+            let Some(slot) = template.get_mut(section).and_then(|s| s.get_mut(field)) else {
+                continue;
+            };
+            *slot = if slot.is_u64() {
+                serde_json::Value::from(stats.mean.round().max(0.0) as u64)
+            } else {
+                serde_json::Value::from(stats.mean)
+            };
+        }
+
+        let mut metrics: Self = serde_json::from_value(template)?;
+        metrics.timestamp = point.at;
+        Ok(metrics)
+    }
+}
+
 impl Default for PerformanceMetrics {
     fn default() -> Self {
         Self {
@@ -614,6 +690,24 @@ mod tests {
         assert_eq!(metrics.user_performance.active_sessions, 0);
     }
 
+    #[test]
+    fn test_metrics_round_trip_through_point() {
+        let mut metrics = PerformanceMetrics::default();
+        metrics.system_performance.cpu_usage_percent = 42.5;
+        metrics.network_performance.connection_count = 7;
+
+        let point = metrics.to_point();
+        assert_eq!(
+            point.mean("system_performance.cpu_usage_percent"),
+            Some(42.5)
+        );
+
+        let restored = PerformanceMetrics::from_point(&point).unwrap();
+        assert_eq!(restored.timestamp, metrics.timestamp);
+        assert_eq!(restored.system_performance.cpu_usage_percent, 42.5);
+        assert_eq!(restored.network_performance.connection_count, 7);
+    }
+
     #[tokio::test]
     async fn test_performance_collection() {
         let metrics = PerformanceMonitor::collect_current_metrics().await;
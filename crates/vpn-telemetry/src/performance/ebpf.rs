@@ -0,0 +1,278 @@
+//! Per-connection throughput from eBPF socket tracing
+//!
+//! [`EbpfThroughputCollector`] loads `bpf/conn_bytes.bpf.c`, compiled to a
+//! BPF object, and attaches it to `tcp_sendmsg`, `tcp_cleanup_rbuf` and
+//! `tcp_close`. The kernel side counts the bytes each IPv4 TCP connection
+//! hands to and reads from its socket, so traffic is attributed to single
+//! proxy and VPN connections without iptables counters. Like
+//! [`HostSampler`](crate::host::HostSampler), rates are the difference to
+//! the previous sample; the first sample reports no throughput.
+//!
+//! Loading the object needs `CAP_BPF` and `CAP_PERFMON` (or root) and a
+//! kernel with BTF.
+
+use crate::error::{Result, TelemetryError};
+use aya::maps::{HashMap as BpfHashMap, MapData};
+use aya::programs::KProbe;
+use aya::{Bpf, Pod};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::Path;
+use std::time::Instant;
+
+/// Name of the per-connection counter map in the BPF object
+const CONN_BYTES_MAP: &str = "CONN_BYTES";
+
+/// Kernel functions probed, each by the program of the same name
+const PROBES: [&str; 3] = ["tcp_sendmsg", "tcp_cleanup_rbuf", "tcp_close"];
+
+/// Key of the counter map; mirrors `struct conn_key`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ConnKey {
+    /// Local address in network byte order
+    saddr: u32,
+    /// Remote address in network byte order
+    daddr: u32,
+    sport: u16,
+    dport: u16,
+}
+
+// SAFETY: `ConnKey` is `repr(C)` with no padding and every bit pattern valid
+unsafe impl Pod for ConnKey {}
+
+impl ConnKey {
+    fn local(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::from(self.saddr.to_ne_bytes()), self.sport)
+    }
+
+    fn remote(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::from(self.daddr.to_ne_bytes()), self.dport)
+    }
+}
+
+/// Value of the counter map; mirrors `struct conn_bytes`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct ConnBytes {
+    sent: u64,
+    received: u64,
+}
+
+// SAFETY: `ConnBytes` is `repr(C)` with no padding and every bit pattern valid
+unsafe impl Pod for ConnBytes {}
+
+/// Throughput of one TCP connection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionThroughput {
+    pub local: SocketAddrV4,
+    pub remote: SocketAddrV4,
+    pub sent_bytes_per_sec: f64,
+    pub received_bytes_per_sec: f64,
+    /// Bytes sent since the connection was first seen
+    pub total_sent: u64,
+    /// Bytes received since the connection was first seen
+    pub total_received: u64,
+}
+
+impl ConnectionThroughput {
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.sent_bytes_per_sec + self.received_bytes_per_sec
+    }
+}
+
+/// Turns counter readings into rates, keeping the previous reading
+#[derive(Debug, Default)]
+struct ThroughputTracker {
+    previous: HashMap<ConnKey, ConnBytes>,
+    last_sample: Option<Instant>,
+}
+
+impl ThroughputTracker {
+    /// Rates between the previous reading and `current`, busiest
+    /// connection first. Closed connections drop out of the next reading.
+    fn sample(
+        &mut self,
+        current: HashMap<ConnKey, ConnBytes>,
+        now: Instant,
+    ) -> Vec<ConnectionThroughput> {
+        let elapsed = self
+            .last_sample
+            .map(|last| now.duration_since(last).as_secs_f64())
+            .filter(|elapsed| *elapsed > 0.0);
+
+        let mut connections: Vec<ConnectionThroughput> = current
+            .iter()
+            .map(|(key, bytes)| {
+                let previous = self.previous.get(key).copied().unwrap_or_default();
+                let rate = |total: u64, before: u64| {
+                    elapsed.map_or(0.0, |elapsed| total.saturating_sub(before) as f64 / elapsed)
+                };
+                ConnectionThroughput {
+                    local: key.local(),
+                    remote: key.remote(),
+                    sent_bytes_per_sec: rate(bytes.sent, previous.sent),
+                    received_bytes_per_sec: rate(bytes.received, previous.received),
+                    total_sent: bytes.sent,
+                    total_received: bytes.received,
+                }
+            })
+            .collect();
+        connections.sort_by(|a, b| b.bytes_per_sec().total_cmp(&a.bytes_per_sec()));
+
+        self.previous = current;
+        self.last_sample = Some(now);
+        connections
+    }
+}
+
+/// Collects per-connection throughput from the loaded BPF programs, which
+/// stay attached for as long as the collector lives
+pub struct EbpfThroughputCollector {
+    // Keeps the programs and their kprobe links alive
+    _bpf: Bpf,
+    counters: BpfHashMap<MapData, ConnKey, ConnBytes>,
+    local_ports: HashSet<u16>,
+    tracker: ThroughputTracker,
+}
+
+impl EbpfThroughputCollector {
+    /// Load the compiled BPF object at `object_path` and attach its probes
+    pub fn load(object_path: &Path) -> Result<Self> {
+        let mut bpf =
+            Bpf::load_file(object_path).map_err(|e| TelemetryError::InitializationFailed {
+                reason: format!("Cannot load BPF object {}: {}", object_path.display(), e),
+            })?;
+
+        for probe in PROBES {
+            let program: &mut KProbe = bpf
+                .program_mut(probe)
+                .ok_or_else(|| TelemetryError::InitializationFailed {
+                    reason: format!("BPF object has no program {}", probe),
+                })?
+                .try_into()
+                .map_err(|e| probe_error(probe, e))?;
+            program.load().map_err(|e| probe_error(probe, e))?;
+            program
+                .attach(probe, 0)
+                .map_err(|e| probe_error(probe, e))?;
+        }
+
+        let map =
+            bpf.take_map(CONN_BYTES_MAP)
+                .ok_or_else(|| TelemetryError::InitializationFailed {
+                    reason: format!("BPF object has no map {}", CONN_BYTES_MAP),
+                })?;
+        let counters =
+            BpfHashMap::try_from(map).map_err(|e| TelemetryError::InitializationFailed {
+                reason: format!("Unexpected layout of map {}: {}", CONN_BYTES_MAP, e),
+            })?;
+
+        Ok(Self {
+            _bpf: bpf,
+            counters,
+            local_ports: HashSet::new(),
+            tracker: ThroughputTracker::default(),
+        })
+    }
+
+    /// Only report connections on these local ports, e.g. the proxy and
+    /// VPN listeners; all connections when empty
+    pub fn with_local_ports(mut self, ports: impl IntoIterator<Item = u16>) -> Self {
+        self.local_ports = ports.into_iter().collect();
+        self
+    }
+
+    /// Throughput of every tracked connection since the previous sample,
+    /// busiest first
+    pub fn sample(&mut self) -> Result<Vec<ConnectionThroughput>> {
+        let mut current = HashMap::new();
+        for entry in self.counters.iter() {
+            let (key, bytes) = entry.map_err(|e| TelemetryError::PerformanceError {
+                message: format!("Cannot read map {}: {}", CONN_BYTES_MAP, e),
+            })?;
+            if self.local_ports.is_empty() || self.local_ports.contains(&key.sport) {
+                current.insert(key, bytes);
+            }
+        }
+
+        Ok(self.tracker.sample(current, Instant::now()))
+    }
+}
+
+fn probe_error(probe: &str, error: impl std::fmt::Display) -> TelemetryError {
+    TelemetryError::InitializationFailed {
+        reason: format!("Cannot attach BPF program {}: {}", probe, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn key(local_port: u16, remote: [u8; 4], remote_port: u16) -> ConnKey {
+        ConnKey {
+            saddr: u32::from_ne_bytes([10, 0, 0, 1]),
+            daddr: u32::from_ne_bytes(remote),
+            sport: local_port,
+            dport: remote_port,
+        }
+    }
+
+    #[test]
+    fn test_connection_addresses() {
+        let key = key(8443, [203, 0, 113, 7], 51234);
+        assert_eq!(key.local(), "10.0.0.1:8443".parse().unwrap());
+        assert_eq!(key.remote(), "203.0.113.7:51234".parse().unwrap());
+    }
+
+    #[test]
+    fn test_throughput_rates() {
+        let mut tracker = ThroughputTracker::default();
+        let start = Instant::now();
+        let client = key(8443, [203, 0, 113, 7], 51234);
+        let other = key(1080, [198, 51, 100, 2], 40000);
+
+        let first = tracker.sample(
+            HashMap::from([(
+                client,
+                ConnBytes {
+                    sent: 1000,
+                    received: 500,
+                },
+            )]),
+            start,
+        );
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].bytes_per_sec(), 0.0);
+
+        let second = tracker.sample(
+            HashMap::from([
+                (
+                    client,
+                    ConnBytes {
+                        sent: 3000,
+                        received: 1500,
+                    },
+                ),
+                (
+                    other,
+                    ConnBytes {
+                        sent: 20_000,
+                        received: 0,
+                    },
+                ),
+            ]),
+            start + Duration::from_secs(2),
+        );
+        assert_eq!(second.len(), 2);
+        // A connection first seen now counts all its bytes
+        assert_eq!(second[0].local.port(), 1080);
+        assert_eq!(second[0].sent_bytes_per_sec, 10_000.0);
+        assert_eq!(second[1].sent_bytes_per_sec, 1000.0);
+        assert_eq!(second[1].received_bytes_per_sec, 500.0);
+        assert_eq!(second[1].total_sent, 3000);
+    }
+}
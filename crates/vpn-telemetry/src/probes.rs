@@ -0,0 +1,402 @@
+//! Liveness and readiness probes for Kubernetes and load balancers
+//!
+//! | Method | Path       | Status                                           |
+//! |--------|------------|--------------------------------------------------|
+//! | GET    | `/healthz` | 200 while the process serves requests            |
+//! | GET    | `/readyz`  | 200 when every [`ReadinessCheck`] passes, else 503 |
+//!
+//! Both return JSON. Readiness runs its checks on every request, each
+//! bounded by `check_timeout`: VPN containers running, this node joined to
+//! the cluster, the cluster's consensus having a leader, and no component
+//! of the [`HealthCollector`] critical.
+
+use crate::admin::{read_request, Response};
+use crate::health::{HealthCollector, HealthStatus};
+use crate::{error::Result, TelemetryError};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use vpn_cluster::{ClusterManager, NodeStatus};
+use vpn_docker::HealthChecker;
+
+/// A condition the service must meet to receive traffic
+#[async_trait]
+pub trait ReadinessCheck: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// A short description of the state when ready, the reason otherwise
+    async fn check(&self) -> std::result::Result<String, String>;
+}
+
+/// Outcome of one readiness check
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub ready: bool,
+    pub message: String,
+}
+
+/// Body of a `/readyz` response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+/// Body of a `/healthz` response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LivenessReport {
+    pub alive: bool,
+    pub uptime_seconds: u64,
+}
+
+/// The named containers are running
+pub struct ContainerCheck {
+    checker: HealthChecker,
+    containers: Vec<String>,
+}
+
+impl ContainerCheck {
+    pub fn new(containers: Vec<String>) -> Result<Self> {
+        let checker = HealthChecker::new().map_err(|e| TelemetryError::InitializationFailed {
+            reason: format!("Failed to connect to Docker: {}", e),
+        })?;
+        Ok(Self {
+            checker,
+            containers,
+        })
+    }
+}
+
+#[async_trait]
+impl ReadinessCheck for ContainerCheck {
+    fn name(&self) -> &str {
+        "containers"
+    }
+
+    async fn check(&self) -> std::result::Result<String, String> {
+        let mut down = Vec::new();
+        for container in &self.containers {
+            match self.checker.check_container_health(container).await {
+                Ok(status) if status.is_running => {}
+                Ok(status) => down.push(format!("{} is {}", container, status.status)),
+                Err(e) => down.push(format!("{}: {}", container, e)),
+            }
+        }
+
+        if down.is_empty() {
+            Ok(format!("{} running", self.containers.join(", ")))
+        } else {
+            Err(down.join("; "))
+        }
+    }
+}
+
+/// This node is a healthy member of its cluster
+pub struct ClusterMembershipCheck {
+    cluster: Arc<ClusterManager>,
+}
+
+impl ClusterMembershipCheck {
+    pub fn new(cluster: Arc<ClusterManager>) -> Self {
+        Self { cluster }
+    }
+}
+
+#[async_trait]
+impl ReadinessCheck for ClusterMembershipCheck {
+    fn name(&self) -> &str {
+        "cluster"
+    }
+
+    async fn check(&self) -> std::result::Result<String, String> {
+        let state = self.cluster.get_cluster_state().await;
+        match state.get_node(&state.node_id).map(|node| &node.status) {
+            Some(NodeStatus::Healthy) => Ok(format!(
+                "Member of {} ({} nodes)",
+                state.cluster_name,
+                state.size()
+            )),
+            Some(status) => Err(format!("Node is {}", status)),
+            None => Err(format!("Not joined to {}", state.cluster_name)),
+        }
+    }
+}
+
+/// The cluster's consensus has elected a leader
+pub struct ConsensusLeaderCheck {
+    cluster: Arc<ClusterManager>,
+}
+
+impl ConsensusLeaderCheck {
+    pub fn new(cluster: Arc<ClusterManager>) -> Self {
+        Self { cluster }
+    }
+}
+
+#[async_trait]
+impl ReadinessCheck for ConsensusLeaderCheck {
+    fn name(&self) -> &str {
+        "consensus"
+    }
+
+    async fn check(&self) -> std::result::Result<String, String> {
+        match self.cluster.get_leader().await {
+            Some(leader) => Ok(format!("Leader is {}", leader)),
+            None => Err("No leader elected".to_string()),
+        }
+    }
+}
+
+/// No component registered with the health collector is critical
+pub struct ComponentCheck {
+    collector: Arc<RwLock<HealthCollector>>,
+}
+
+impl ComponentCheck {
+    pub fn new(collector: Arc<RwLock<HealthCollector>>) -> Self {
+        Self { collector }
+    }
+}
+
+#[async_trait]
+impl ReadinessCheck for ComponentCheck {
+    fn name(&self) -> &str {
+        "components"
+    }
+
+    async fn check(&self) -> std::result::Result<String, String> {
+        let health = self
+            .collector
+            .read()
+            .await
+            .get_current_health()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut critical: Vec<String> = health
+            .components
+            .values()
+            .filter(|component| component.status == HealthStatus::Critical)
+            .map(|component| format!("{}: {}", component.name, component.message))
+            .collect();
+        critical.sort();
+
+        if critical.is_empty() {
+            Ok(format!("{} components healthy", health.components.len()))
+        } else {
+            Err(critical.join("; "))
+        }
+    }
+}
+
+/// Readiness checks and the process start time behind the probe endpoints
+pub struct HealthProbes {
+    checks: Vec<Arc<dyn ReadinessCheck>>,
+    check_timeout: Duration,
+    started: Instant,
+}
+
+impl HealthProbes {
+    pub fn new(check_timeout: Duration) -> Self {
+        Self {
+            checks: Vec::new(),
+            check_timeout,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn with_check(mut self, check: Arc<dyn ReadinessCheck>) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    pub fn liveness(&self) -> LivenessReport {
+        LivenessReport {
+            alive: true,
+            uptime_seconds: self.started.elapsed().as_secs(),
+        }
+    }
+
+    /// Run every check; a check that times out is not ready
+    pub async fn readiness(&self) -> ReadinessReport {
+        let mut checks = Vec::with_capacity(self.checks.len());
+        for check in &self.checks {
+            let outcome = tokio::time::timeout(self.check_timeout, check.check())
+                .await
+                .unwrap_or_else(|_| {
+                    Err(format!(
+                        "Timed out after {}ms",
+                        self.check_timeout.as_millis()
+                    ))
+                });
+            let (ready, message) = match outcome {
+                Ok(message) => (true, message),
+                Err(message) => (false, message),
+            };
+            checks.push(CheckResult {
+                name: check.name().to_string(),
+                ready,
+                message,
+            });
+        }
+
+        ReadinessReport {
+            ready: checks.iter().all(|check| check.ready),
+            checks,
+        }
+    }
+}
+
+/// HTTP server for the probe endpoints
+pub struct HealthServer {
+    local_addr: SocketAddr,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl HealthServer {
+    /// Bind the probe endpoints and start serving `probes`
+    pub async fn bind(probes: Arc<HealthProbes>, bind_addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(bind_addr).await.map_err(|e| {
+            TelemetryError::InitializationFailed {
+                reason: format!("Failed to bind health endpoint to {}: {}", bind_addr, e),
+            }
+        })?;
+        let local_addr = listener.local_addr()?;
+
+        info!("Health endpoint listening on {}", local_addr);
+
+        let handle = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let probes = probes.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = Self::handle_connection(stream, &probes).await {
+                                warn!("Error handling health request: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Error accepting health connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Self { local_addr, handle })
+    }
+
+    /// Address the endpoint is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop serving
+    pub fn shutdown(self) {
+        self.handle.abort();
+    }
+
+    async fn handle_connection(mut stream: TcpStream, probes: &HealthProbes) -> Result<()> {
+        let (method, target) = read_request(&mut stream).await?;
+        let response = route(probes, &method, &target).await;
+        stream.write_all(response.to_http().as_bytes()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+async fn route(probes: &HealthProbes, method: &str, target: &str) -> Response {
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+
+    match (method, path) {
+        ("GET", "/healthz") => Response::json(&probes.liveness()),
+        ("GET", "/readyz") => {
+            let report = probes.readiness().await;
+            let mut response = Response::json(&report);
+            if !report.ready {
+                response.status = 503;
+            }
+            response
+        }
+        (_, "/healthz" | "/readyz") => {
+            Response::error(405, &format!("{} not allowed on {}", method, path))
+        }
+        _ => Response::error(404, &format!("Unknown health path {}", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed {
+        name: &'static str,
+        outcome: std::result::Result<&'static str, &'static str>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl ReadinessCheck for Fixed {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn check(&self) -> std::result::Result<String, String> {
+            tokio::time::sleep(self.delay).await;
+            self.outcome.map(str::to_string).map_err(str::to_string)
+        }
+    }
+
+    fn fixed(
+        name: &'static str,
+        outcome: std::result::Result<&'static str, &'static str>,
+    ) -> Arc<dyn ReadinessCheck> {
+        Arc::new(Fixed {
+            name,
+            outcome,
+            delay: Duration::ZERO,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_readiness_reflects_checks() {
+        let probes = HealthProbes::new(Duration::from_millis(50))
+            .with_check(fixed("containers", Ok("vpn-server running")))
+            .with_check(fixed("consensus", Ok("Leader is node-1")));
+
+        let response = route(&probes, "GET", "/readyz").await;
+        assert_eq!(response.status, 200);
+        assert_eq!(route(&probes, "GET", "/healthz").await.status, 200);
+
+        let probes = HealthProbes::new(Duration::from_millis(50))
+            .with_check(fixed("containers", Ok("vpn-server running")))
+            .with_check(fixed("consensus", Err("No leader elected")))
+            .with_check(Arc::new(Fixed {
+                name: "cluster",
+                outcome: Ok("Member"),
+                delay: Duration::from_secs(5),
+            }));
+
+        let response = route(&probes, "GET", "/readyz").await;
+        assert_eq!(response.status, 503);
+        let report: ReadinessReport = serde_json::from_str(&response.body).unwrap();
+        assert!(!report.ready);
+        let failed: Vec<_> = report
+            .checks
+            .iter()
+            .filter(|check| !check.ready)
+            .map(|check| check.name.as_str())
+            .collect();
+        assert_eq!(failed, vec!["consensus", "cluster"]);
+
+        // Liveness does not depend on readiness
+        assert_eq!(route(&probes, "GET", "/healthz").await.status, 200);
+        assert_eq!(route(&probes, "POST", "/readyz").await.status, 405);
+        assert_eq!(route(&probes, "GET", "/nope").await.status, 404);
+    }
+}
@@ -0,0 +1,375 @@
+//! Service level objectives and error budget burn rates
+//!
+//! Each [`SloObjective`] names an event, e.g. `proxy_connect`, and what
+//! makes one occurrence of it good: it succeeded, or it completed within a
+//! latency threshold. A p95 latency target of 300ms becomes "95% of
+//! connects complete within 300ms".
+//!
+//! [`SloTracker`] counts good and total events per objective in one-minute
+//! buckets over the objective's rolling window. From those it reports
+//! compliance, how much of the error budget is left and the burn rate over
+//! any shorter window: how many times faster than sustainable the budget is
+//! being spent. Burn rates feed the multi-window burn rate alert rules of
+//! [`AlertEngine`](crate::AlertEngine).
+
+use chrono::{DateTime, Duration as ChronoDuration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Width of the buckets events are counted in
+const BUCKET: Duration = Duration::from_secs(60);
+
+/// Windows burn rates are reported for in [`SloStatus`] and metrics
+pub const BURN_RATE_WINDOWS: [Duration; 6] = [
+    Duration::from_secs(5 * 60),
+    Duration::from_secs(30 * 60),
+    Duration::from_secs(60 * 60),
+    Duration::from_secs(6 * 60 * 60),
+    Duration::from_secs(24 * 60 * 60),
+    Duration::from_secs(3 * 24 * 60 * 60),
+];
+
+/// What makes an event good
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SloIndicator {
+    /// The event succeeded
+    Availability,
+    /// The event completed within `threshold`; events recorded without a
+    /// latency are not counted
+    Latency { threshold: Duration },
+}
+
+/// A target share of good events over a rolling window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SloObjective {
+    /// Unique name, e.g. `proxy-availability`
+    pub name: String,
+    /// Event counted, e.g. `proxy_connect`
+    pub event: String,
+    pub indicator: SloIndicator,
+    /// Target percentage of good events, e.g. 99.5
+    pub objective: f64,
+    /// Rolling window compliance is computed over
+    #[serde(default = "SloObjective::default_window")]
+    pub window: Duration,
+}
+
+impl SloObjective {
+    fn default_window() -> Duration {
+        Duration::from_secs(30 * 24 * 60 * 60)
+    }
+
+    /// Share of events allowed to be bad
+    pub fn error_budget(&self) -> f64 {
+        1.0 - self.objective / 100.0
+    }
+
+    /// Whether an occurrence of the event is good, or `None` when it does
+    /// not count towards this objective
+    fn classify(&self, success: bool, latency: Option<Duration>) -> Option<bool> {
+        match &self.indicator {
+            SloIndicator::Availability => Some(success),
+            SloIndicator::Latency { threshold } => latency.map(|latency| latency <= *threshold),
+        }
+    }
+}
+
+/// Where an objective stands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SloStatus {
+    pub name: String,
+    pub event: String,
+    /// Target percentage of good events
+    pub objective: f64,
+    pub window: Duration,
+    /// Events over the window
+    pub good: u64,
+    pub total: u64,
+    /// Percentage of good events over the window, `None` without events
+    pub compliance: Option<f64>,
+    /// Share of the window's error budget not yet spent, negative once
+    /// overspent
+    pub error_budget_remaining: Option<f64>,
+    /// Burn rate per window label, e.g. `1h`, for windows with events
+    pub burn_rates: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    start: DateTime<Utc>,
+    good: u64,
+    total: u64,
+}
+
+/// Rolling good and total event counts per objective
+pub struct SloTracker {
+    objectives: Vec<SloObjective>,
+    /// Buckets per objective, oldest first
+    buckets: Mutex<Vec<VecDeque<Bucket>>>,
+}
+
+impl SloTracker {
+    pub fn new(objectives: Vec<SloObjective>) -> Self {
+        let buckets = Mutex::new(vec![VecDeque::new(); objectives.len()]);
+        Self {
+            objectives,
+            buckets,
+        }
+    }
+
+    pub fn objectives(&self) -> &[SloObjective] {
+        &self.objectives
+    }
+
+    /// Record an occurrence of `event` now
+    pub fn record(&self, event: &str, success: bool, latency: Option<Duration>) {
+        self.record_at(Utc::now(), event, success, latency);
+    }
+
+    /// Record an occurrence of `event` at `at`
+    pub fn record_at(
+        &self,
+        at: DateTime<Utc>,
+        event: &str,
+        success: bool,
+        latency: Option<Duration>,
+    ) {
+        let start = bucket_start(at);
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        for (objective, series) in self.objectives.iter().zip(buckets.iter_mut()) {
+            if objective.event != event {
+                continue;
+            }
+            let Some(good) = objective.classify(success, latency) else {
+                continue;
+            };
+
+            // Late events land in the newest bucket rather than reordering
+            match series.back_mut() {
+                Some(bucket) if bucket.start >= start => {
+                    bucket.good += u64::from(good);
+                    bucket.total += 1;
+                }
+                _ => series.push_back(Bucket {
+                    start,
+                    good: u64::from(good),
+                    total: 1,
+                }),
+            }
+
+            let horizon = at - to_chrono(objective.window);
+            while series.front().is_some_and(|bucket| bucket.start < horizon) {
+                series.pop_front();
+            }
+        }
+    }
+
+    /// Good and total events of objective `name` over the `window` up to
+    /// `at`
+    pub fn counts(&self, name: &str, window: Duration, at: DateTime<Utc>) -> Option<(u64, u64)> {
+        let index = self.index(name)?;
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        Some(counts(&buckets[index], window, at))
+    }
+
+    /// How many times faster than sustainable objective `name` spent its
+    /// error budget over the `window` up to `at`; `None` without events
+    pub fn burn_rate(&self, name: &str, window: Duration, at: DateTime<Utc>) -> Option<f64> {
+        let objective = &self.objectives[self.index(name)?];
+        let (good, total) = self.counts(name, window, at)?;
+        burn_rate(objective, good, total)
+    }
+
+    /// Every objective as of now
+    pub fn status(&self) -> Vec<SloStatus> {
+        self.status_at(Utc::now())
+    }
+
+    /// Every objective as of `at`
+    pub fn status_at(&self, at: DateTime<Utc>) -> Vec<SloStatus> {
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        self.objectives
+            .iter()
+            .zip(buckets.iter())
+            .map(|(objective, series)| {
+                let (good, total) = counts(series, objective.window, at);
+                let compliance = (total > 0).then(|| good as f64 / total as f64 * 100.0);
+                let error_budget_remaining =
+                    burn_rate(objective, good, total).map(|rate| 1.0 - rate);
+                let burn_rates = BURN_RATE_WINDOWS
+                    .iter()
+                    .filter(|window| **window <= objective.window)
+                    .filter_map(|window| {
+                        let (good, total) = counts(series, *window, at);
+                        Some((window_label(*window), burn_rate(objective, good, total)?))
+                    })
+                    .collect();
+
+                SloStatus {
+                    name: objective.name.clone(),
+                    event: objective.event.clone(),
+                    objective: objective.objective,
+                    window: objective.window,
+                    good,
+                    total,
+                    compliance,
+                    error_budget_remaining,
+                    burn_rates,
+                }
+            })
+            .collect()
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.objectives.iter().position(|o| o.name == name)
+    }
+}
+
+/// Short label for a window, e.g. `5m`, `6h` or `30d`
+pub fn window_label(window: Duration) -> String {
+    let seconds = window.as_secs();
+    match seconds {
+        s if s > 0 && s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s > 0 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s > 0 && s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+fn to_chrono(duration: Duration) -> ChronoDuration {
+    ChronoDuration::from_std(duration).unwrap_or_else(|_| ChronoDuration::zero())
+}
+
+fn bucket_start(at: DateTime<Utc>) -> DateTime<Utc> {
+    at.duration_trunc(to_chrono(BUCKET)).unwrap_or(at)
+}
+
+/// Counts of the buckets overlapping the `window` up to `at`
+fn counts(series: &VecDeque<Bucket>, window: Duration, at: DateTime<Utc>) -> (u64, u64) {
+    // Windows shorter than a bucket still see the current one
+    let from = (bucket_start(at - to_chrono(window)) + to_chrono(BUCKET)).min(bucket_start(at));
+    series
+        .iter()
+        .rev()
+        .take_while(|bucket| bucket.start >= from)
+        .filter(|bucket| bucket.start <= at)
+        .fold((0, 0), |(good, total), bucket| {
+            (good + bucket.good, total + bucket.total)
+        })
+}
+
+fn burn_rate(objective: &SloObjective, good: u64, total: u64) -> Option<f64> {
+    if total == 0 {
+        return None;
+    }
+    let error_rate = (total - good) as f64 / total as f64;
+    let budget = objective.error_budget();
+    Some(if budget > 0.0 {
+        error_rate / budget
+    } else if error_rate > 0.0 {
+        f64::INFINITY
+    } else {
+        0.0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+            + ChronoDuration::minutes(minutes)
+    }
+
+    fn tracker() -> SloTracker {
+        SloTracker::new(vec![
+            SloObjective {
+                name: "proxy-availability".to_string(),
+                event: "proxy_connect".to_string(),
+                indicator: SloIndicator::Availability,
+                objective: 99.0,
+                window: Duration::from_secs(24 * 3600),
+            },
+            SloObjective {
+                name: "proxy-latency".to_string(),
+                event: "proxy_connect".to_string(),
+                indicator: SloIndicator::Latency {
+                    threshold: Duration::from_millis(300),
+                },
+                objective: 95.0,
+                window: Duration::from_secs(24 * 3600),
+            },
+        ])
+    }
+
+    #[test]
+    fn test_compliance_and_burn_rates() {
+        let tracker = tracker();
+        let fast = Some(Duration::from_millis(100));
+        let slow = Some(Duration::from_millis(800));
+
+        // An hour ago: 100 good connects
+        for _ in 0..100 {
+            tracker.record_at(at(-60), "proxy_connect", true, fast);
+        }
+        // Now: 10 connects, 2 failed and 5 slow
+        for i in 0..10 {
+            let latency = if i < 5 { slow } else { fast };
+            tracker.record_at(at(0), "proxy_connect", i >= 2, latency);
+        }
+        tracker.record_at(at(0), "other_event", false, None);
+
+        let status = tracker.status_at(at(0));
+        let availability = &status[0];
+        assert_eq!((availability.good, availability.total), (108, 110));
+        assert!((availability.compliance.unwrap() - 108.0 / 110.0 * 100.0).abs() < 1e-9);
+
+        // Over 5 minutes 20% failed against a 1% budget
+        let burn = tracker
+            .burn_rate("proxy-availability", Duration::from_secs(300), at(0))
+            .unwrap();
+        assert!((burn - 20.0).abs() < 1e-9);
+        assert_eq!(availability.burn_rates.get("5m"), Some(&burn));
+
+        // Spending 2 of the 1.1 allowed bad events overspends the budget
+        assert!(availability.error_budget_remaining.unwrap() < 0.0);
+
+        let latency = &status[1];
+        assert_eq!((latency.good, latency.total), (105, 110));
+        let burn = tracker
+            .burn_rate("proxy-latency", Duration::from_secs(300), at(0))
+            .unwrap();
+        assert!((burn - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_events_leave_window() {
+        let tracker = tracker();
+        tracker.record_at(at(0), "proxy_connect", false, None);
+        tracker.record_at(at(25 * 60), "proxy_connect", true, None);
+
+        let status = tracker.status_at(at(25 * 60));
+        assert_eq!((status[0].good, status[0].total), (1, 1));
+        assert_eq!(status[0].error_budget_remaining, Some(1.0));
+        assert_eq!(status[1].compliance, None);
+        assert!(tracker
+            .burn_rate("proxy-latency", Duration::from_secs(3600), at(0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_window_label() {
+        assert_eq!(window_label(Duration::from_secs(300)), "5m");
+        assert_eq!(window_label(Duration::from_secs(6 * 3600)), "6h");
+        assert_eq!(window_label(Duration::from_secs(30 * 86400)), "30d");
+        assert_eq!(window_label(Duration::from_secs(90)), "90s");
+    }
+}
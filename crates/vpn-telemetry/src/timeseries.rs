@@ -0,0 +1,376 @@
+//! Embedded time-series store for performance samples
+//!
+//! Samples land in an in-memory ring buffer at full resolution and are
+//! rolled up into one-minute and one-hour points as each period closes.
+//! Every tier keeps its points for its own retention, so a day of minutes
+//! or a month of hours stays queryable without an external TSDB.
+//!
+//! With a data directory, closed rollups are also appended to daily chunk
+//! files (`<dir>/<tier>/<date>.jsonl`), read back when the store is opened
+//! and deleted once they fall out of retention.
+
+use crate::config::RetentionConfig;
+use crate::error::Result;
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// How far apart a tier's points are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    /// One point per collected sample
+    Raw,
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    /// Length of a rollup bucket; raw points are not bucketed
+    fn bucket(self) -> Option<i64> {
+        match self {
+            Self::Raw => None,
+            Self::Minute => Some(60),
+            Self::Hour => Some(3600),
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Raw => write!(f, "raw"),
+            Self::Minute => write!(f, "minute"),
+            Self::Hour => write!(f, "hour"),
+        }
+    }
+}
+
+/// Aggregate of one field over the samples in a point
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FieldStats {
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Numeric fields observed at, or rolled up from, `at`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeriesPoint {
+    /// Sample time, or the start of the rollup bucket
+    pub at: DateTime<Utc>,
+    /// Samples rolled up into this point
+    pub count: u64,
+    pub fields: BTreeMap<String, FieldStats>,
+}
+
+impl SeriesPoint {
+    /// Point for a single sample
+    pub fn sample(at: DateTime<Utc>, values: BTreeMap<String, f64>) -> Self {
+        Self {
+            at,
+            count: 1,
+            fields: values
+                .into_iter()
+                .map(|(name, value)| {
+                    let stats = FieldStats {
+                        mean: value,
+                        min: value,
+                        max: value,
+                    };
+                    (name, stats)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn mean(&self, field: &str) -> Option<f64> {
+        self.fields.get(field).map(|stats| stats.mean)
+    }
+
+    /// Fold `other` into this point, weighting means by sample count
+    fn merge(&mut self, other: &SeriesPoint) {
+        let total = (self.count + other.count) as f64;
+        for (name, theirs) in &other.fields {
+            match self.fields.get_mut(name) {
+                Some(ours) => {
+                    ours.mean =
+                        (ours.mean * self.count as f64 + theirs.mean * other.count as f64) / total;
+                    ours.min = ours.min.min(theirs.min);
+                    ours.max = ours.max.max(theirs.max);
+                }
+                None => {
+                    self.fields.insert(name.clone(), *theirs);
+                }
+            }
+        }
+        self.count += other.count;
+    }
+}
+
+struct Tier {
+    resolution: Resolution,
+    retention: ChronoDuration,
+    points: VecDeque<SeriesPoint>,
+    /// Bucket still receiving points
+    open: Option<SeriesPoint>,
+}
+
+impl Tier {
+    fn new(resolution: Resolution, retention: Duration) -> Self {
+        Self {
+            resolution,
+            retention: ChronoDuration::from_std(retention).unwrap_or(ChronoDuration::MAX),
+            points: VecDeque::new(),
+            open: None,
+        }
+    }
+
+    /// Fold `point` into the open bucket, returning the bucket it closed
+    fn roll(&mut self, point: &SeriesPoint) -> Option<SeriesPoint> {
+        let bucket = self.resolution.bucket()?;
+        let start = point.at.timestamp();
+        let start = DateTime::from_timestamp(start - start.rem_euclid(bucket), 0)?;
+
+        match &mut self.open {
+            Some(open) if open.at == start => {
+                open.merge(point);
+                None
+            }
+            open => {
+                let mut next = point.clone();
+                next.at = start;
+                open.replace(next)
+            }
+        }
+    }
+
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let Some(cutoff) = now.checked_sub_signed(self.retention) else {
+            return;
+        };
+        while self.points.front().is_some_and(|point| point.at < cutoff) {
+            self.points.pop_front();
+        }
+    }
+}
+
+/// Raw samples in a ring buffer with minute and hour rollups
+pub struct TimeSeriesStore {
+    config: RetentionConfig,
+    raw: Tier,
+    minute: Tier,
+    hour: Tier,
+}
+
+impl TimeSeriesStore {
+    /// In-memory store; use [`open`](Self::open) to read and write chunks
+    pub fn new(config: RetentionConfig) -> Self {
+        Self {
+            raw: Tier::new(Resolution::Raw, config.raw),
+            minute: Tier::new(Resolution::Minute, config.minute),
+            hour: Tier::new(Resolution::Hour, config.hour),
+            config,
+        }
+    }
+
+    /// Store with the rollups kept in `config.data_dir` loaded
+    pub fn open(config: RetentionConfig) -> Result<Self> {
+        let mut store = Self::new(config);
+        let Some(dir) = store.config.data_dir.clone() else {
+            return Ok(store);
+        };
+
+        let now = Utc::now();
+        for tier in [&mut store.minute, &mut store.hour] {
+            let tier_dir = dir.join(tier.resolution.to_string());
+            fs::create_dir_all(&tier_dir)?;
+
+            let mut chunks: Vec<_> = fs::read_dir(&tier_dir)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+                .collect();
+            chunks.sort();
+
+            for chunk in chunks {
+                for line in fs::read_to_string(&chunk)?.lines() {
+                    if let Ok(point) = serde_json::from_str::<SeriesPoint>(line) {
+                        tier.points.push_back(point);
+                    }
+                }
+            }
+            tier.points.make_contiguous().sort_by_key(|point| point.at);
+            tier.prune(now);
+        }
+        Ok(store)
+    }
+
+    /// Add a sample, rolling up any minute or hour it completes
+    pub fn record(&mut self, point: SeriesPoint) -> Result<()> {
+        let now = point.at;
+
+        if let Some(minute) = self.minute.roll(&point) {
+            self.persist(Resolution::Minute, &minute)?;
+            if let Some(hour) = self.hour.roll(&minute) {
+                self.persist(Resolution::Hour, &hour)?;
+                self.hour.points.push_back(hour);
+                self.prune_chunks(now)?;
+            }
+            self.minute.points.push_back(minute);
+        }
+        self.raw.points.push_back(point);
+
+        for tier in [&mut self.raw, &mut self.minute, &mut self.hour] {
+            tier.prune(now);
+        }
+        Ok(())
+    }
+
+    /// Points of the last `range` before `now` from the finest tier that
+    /// still covers it, including the partly filled current bucket
+    pub fn query(&self, range: Duration, now: DateTime<Utc>) -> (Resolution, Vec<SeriesPoint>) {
+        let range = ChronoDuration::from_std(range).unwrap_or(ChronoDuration::MAX);
+        let tier = [&self.raw, &self.minute]
+            .into_iter()
+            .find(|tier| tier.retention >= range)
+            .unwrap_or(&self.hour);
+
+        let cutoff = now
+            .checked_sub_signed(range)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+        let points = tier
+            .points
+            .iter()
+            .chain(tier.open.iter())
+            .filter(|point| point.at >= cutoff && point.at <= now)
+            .cloned()
+            .collect();
+        (tier.resolution, points)
+    }
+
+    fn persist(&self, resolution: Resolution, point: &SeriesPoint) -> Result<()> {
+        let Some(dir) = &self.config.data_dir else {
+            return Ok(());
+        };
+
+        let tier_dir = dir.join(resolution.to_string());
+        fs::create_dir_all(&tier_dir)?;
+        let chunk = tier_dir.join(format!("{}.jsonl", point.at.format("%Y-%m-%d")));
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(chunk)?;
+        writeln!(file, "{}", serde_json::to_string(point)?)?;
+        Ok(())
+    }
+
+    /// Delete chunk files whose whole day is past retention
+    fn prune_chunks(&self, now: DateTime<Utc>) -> Result<()> {
+        let Some(dir) = &self.config.data_dir else {
+            return Ok(());
+        };
+
+        for tier in [&self.minute, &self.hour] {
+            if let Some(cutoff) = now.checked_sub_signed(tier.retention) {
+                let tier_dir = dir.join(tier.resolution.to_string());
+                remove_chunks_before(&tier_dir, cutoff.date_naive())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn remove_chunks_before(dir: &Path, cutoff: NaiveDate) -> Result<()> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        let day = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+        if day.is_some_and(|day| day < cutoff) {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sample(secs: i64, cpu: f64) -> SeriesPoint {
+        let at = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + ChronoDuration::seconds(secs);
+        SeriesPoint::sample(at, BTreeMap::from([("cpu".to_string(), cpu)]))
+    }
+
+    fn config(dir: Option<&Path>) -> RetentionConfig {
+        RetentionConfig {
+            data_dir: dir.map(Path::to_path_buf),
+            raw: Duration::from_secs(120),
+            minute: Duration::from_secs(3600),
+            hour: Duration::from_secs(86400 * 2),
+        }
+    }
+
+    #[test]
+    fn test_downsampling() {
+        let mut store = TimeSeriesStore::new(config(None));
+        // Two hours of a sample every 10s; cpu is the minute of the hour
+        for secs in (0..7200).step_by(10) {
+            store.record(sample(secs, (secs / 60 % 60) as f64)).unwrap();
+        }
+        let now = sample(7199, 0.0).at;
+
+        // Raw samples only reach back two minutes
+        let (resolution, points) = store.query(Duration::from_secs(60), now);
+        assert_eq!(resolution, Resolution::Raw);
+        assert_eq!(points.len(), 6);
+
+        let (resolution, points) = store.query(Duration::from_secs(1800), now);
+        assert_eq!(resolution, Resolution::Minute);
+        // Twenty-nine closed minutes and the one still filling
+        assert_eq!(points.len(), 30);
+        assert!(points.iter().all(|point| point.count == 6));
+        assert_eq!(points.last().unwrap().mean("cpu"), Some(59.0));
+
+        let (resolution, points) = store.query(Duration::from_secs(86400), now);
+        assert_eq!(resolution, Resolution::Hour);
+        assert_eq!(points.len(), 2);
+        let first = &points[0].fields["cpu"];
+        assert_eq!((first.mean, first.min, first.max), (29.5, 0.0, 59.0));
+        assert_eq!(points[0].count, 360);
+    }
+
+    #[test]
+    fn test_chunks_survive_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = TimeSeriesStore::new(config(Some(dir.path())));
+        for secs in (0..7200).step_by(30) {
+            store.record(sample(secs, 1.0)).unwrap();
+        }
+        assert!(dir.path().join("minute/2024-01-01.jsonl").exists());
+
+        // Chunks from 2024 are far out of retention now
+        let reopened = TimeSeriesStore::open(config(Some(dir.path()))).unwrap();
+        assert!(reopened.minute.points.is_empty());
+
+        let retained = RetentionConfig {
+            minute: Duration::MAX,
+            hour: Duration::MAX,
+            ..config(Some(dir.path()))
+        };
+        let reopened = TimeSeriesStore::open(retained).unwrap();
+        // The last minute and hour are still open
+        assert_eq!(reopened.minute.points.len(), 119);
+        assert_eq!(reopened.hour.points.len(), 1);
+    }
+}
@@ -3,9 +3,11 @@
 use crate::{config::TelemetryConfig, error::Result, TelemetryError};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tracing::{info, Span};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
+use vpn_types::CorrelationId;
 
 /// Trace context for managing distributed traces
 #[derive(Debug, Clone)]
@@ -18,9 +20,12 @@ pub struct TraceContext {
 }
 
 impl TraceContext {
-    /// Create a new trace context
+    /// Create a new trace context. Traces of one command or request share
+    /// its correlation ID as their trace ID.
     pub fn new(operation: &str, span: Span) -> Self {
-        let trace_id = uuid::Uuid::new_v4().to_string();
+        let trace_id = CorrelationId::current()
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
         let span_id = uuid::Uuid::new_v4().to_string();
 
         Self {
@@ -82,6 +87,7 @@ impl TraceContext {
 pub struct TracingManager {
     config: TelemetryConfig,
     initialized: bool,
+    spans_started: AtomicU64,
 }
 
 impl TracingManager {
@@ -90,6 +96,7 @@ impl TracingManager {
         Ok(Self {
             config: config.clone(),
             initialized: false,
+            spans_started: AtomicU64::new(0),
         })
     }
 
@@ -136,10 +143,45 @@ impl TracingManager {
             });
         }
 
-        let span = tracing::info_span!("vpn_operation", operation = operation);
+        let correlation_id = CorrelationId::current();
+        let span = if self.should_sample() {
+            tracing::info_span!(
+                "vpn_operation",
+                operation = operation,
+                correlation_id = correlation_id.as_ref().map(CorrelationId::as_str)
+            )
+        } else {
+            Span::none()
+        };
         Ok(TraceContext::new(operation, span))
     }
 
+    /// Current sampling ratio
+    pub fn sampling_ratio(&self) -> f32 {
+        self.config.tracing.sampling_ratio
+    }
+
+    /// Change the sampling ratio (0.0 to 1.0) without restarting
+    pub fn set_sampling_ratio(&mut self, ratio: f32) -> Result<()> {
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(TelemetryError::ConfigError {
+                message: "Sampling ratio must be between 0.0 and 1.0".to_string(),
+            });
+        }
+
+        self.config.tracing.sampling_ratio = ratio;
+        info!("Trace sampling ratio set to {}", ratio);
+        Ok(())
+    }
+
+    /// Deterministic ratio sampler: span `n` is kept when it carries the
+    /// running count of kept spans, `floor(n * ratio)`, over to the next integer
+    fn should_sample(&self) -> bool {
+        let ratio = f64::from(self.config.tracing.sampling_ratio);
+        let n = self.spans_started.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * ratio).floor() > (n * ratio).floor()
+    }
+
     /// Record an event with structured data
     pub async fn record_event(&self, event: &str, details: Value) -> Result<()> {
         if !self.initialized {
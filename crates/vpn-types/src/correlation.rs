@@ -0,0 +1,133 @@
+//! Correlation IDs tying together everything one operation did
+//!
+//! A [`CorrelationId`] is generated where an operation enters the system,
+//! such as a CLI command or a REST request, and reported by every crate
+//! working on it: in tracing spans, on Docker containers and in audit
+//! events. Users are shown it as the reference ID of an error, so a failed
+//! run can be followed through the logs of every service.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+tokio::task_local! {
+    static TASK_ID: CorrelationId;
+}
+
+static PROCESS_ID: OnceLock<CorrelationId> = OnceLock::new();
+
+/// ID of one CLI command or REST request
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CorrelationId(String);
+
+impl CorrelationId {
+    /// HTTP header carrying the ID of a request
+    pub const HEADER: &'static str = "x-correlation-id";
+
+    /// Environment variable passing the ID on to child processes
+    pub const ENV: &'static str = "VPN_CORRELATION_ID";
+
+    /// Docker label marking containers with the ID of the operation that
+    /// created them
+    pub const LABEL: &'static str = "vpn.correlation-id";
+
+    /// A new random ID, short enough to read out from an error message
+    pub fn generate() -> Self {
+        let mut id = Uuid::new_v4().simple().to_string();
+        id.truncate(12);
+        Self(id)
+    }
+
+    /// Accept an ID from outside, e.g. from a request header. None if it is
+    /// empty, longer than 64 characters or has characters other than ASCII
+    /// letters, digits, `-`, `_` and `.`, which could break log lines or
+    /// header values.
+    pub fn parse(s: &str) -> Option<Self> {
+        let valid = !s.is_empty()
+            && s.len() <= 64
+            && s
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        valid.then(|| Self(s.to_string()))
+    }
+
+    /// ID passed down by a parent process in [`Self::ENV`], or a new one
+    pub fn from_env_or_generate() -> Self {
+        std::env::var(Self::ENV)
+            .ok()
+            .and_then(|id| Self::parse(&id))
+            .unwrap_or_else(Self::generate)
+    }
+
+    /// Make this the ID of everything the process does outside a
+    /// [`Self::scope`]. Only the first call takes effect; the ID in effect
+    /// is returned.
+    pub fn init_process(self) -> Self {
+        PROCESS_ID.get_or_init(|| self).clone()
+    }
+
+    /// Run `future` with this as the current ID, e.g. to handle one request
+    /// of a server
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        TASK_ID.scope(self, future).await
+    }
+
+    /// ID of the operation running now: that of the innermost
+    /// [`Self::scope`], else the process's
+    pub fn current() -> Option<Self> {
+        TASK_ID
+            .try_with(Clone::clone)
+            .ok()
+            .or_else(|| PROCESS_ID.get().cloned())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_generate() {
+        let id = CorrelationId::generate();
+        assert_eq!(id.as_str().len(), 12);
+        assert_eq!(CorrelationId::parse(id.as_str()), Some(id));
+
+        assert!(CorrelationId::parse("req-42_a.b").is_some());
+        assert!(CorrelationId::parse("").is_none());
+        assert!(CorrelationId::parse("bad id").is_none());
+        assert!(CorrelationId::parse("line\nbreak").is_none());
+        assert!(CorrelationId::parse(&"x".repeat(65)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scope_overrides_process_id() {
+        let process = CorrelationId::parse("process").unwrap().init_process();
+        assert_eq!(CorrelationId::current(), Some(process.clone()));
+
+        let request = CorrelationId::parse("request").unwrap();
+        let seen = request
+            .clone()
+            .scope(async { CorrelationId::current() })
+            .await;
+        assert_eq!(seen, Some(request));
+
+        // Tasks spawned outside a scope fall back to the process ID
+        let spawned = tokio::spawn(async { CorrelationId::current() })
+            .await
+            .unwrap();
+        assert_eq!(spawned, Some(process));
+    }
+}
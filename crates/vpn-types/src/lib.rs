@@ -4,15 +4,21 @@
 //! direct dependencies between service crates.
 
 pub mod container;
+pub mod correlation;
 pub mod error;
+pub mod lockout;
 pub mod network;
 pub mod protocol;
+pub mod trace_context;
 pub mod user;
 pub mod validation;
 
 pub use container::*;
+pub use correlation::*;
 pub use error::*;
+pub use lockout::*;
 pub use network::*;
 pub use protocol::*;
+pub use trace_context::*;
 pub use user::*;
 pub use validation::*;
@@ -0,0 +1,486 @@
+//! Brute-force protection for password authentication
+//!
+//! [`LoginGuard`] counts failed attempts per source IP and per account.
+//! When either reaches its limit within the policy window, that IP or
+//! account is locked out. Every further lockout of the same key doubles the
+//! lockout, up to `max_lockout_secs`. After `escalate_after_lockouts`
+//! lockouts the key is escalated, which services can answer with a CAPTCHA
+//! or a webhook call. Failures, lockouts, escalations and rejected attempts
+//! are reported to every [`AuthObserver`] as [`AuthAuditEvent`]s.
+
+use crate::correlation::CorrelationId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits on failed authentication attempts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LockoutPolicy {
+    /// Whether attempts are counted at all
+    pub enabled: bool,
+
+    /// Failed attempts from one IP before it is locked out
+    pub max_attempts_per_ip: u32,
+
+    /// Failed attempts against one account before it is locked out
+    pub max_attempts_per_account: u32,
+
+    /// How long failed attempts count towards a lockout, in seconds
+    pub window_secs: u64,
+
+    /// First lockout in seconds; doubled for every further lockout
+    pub base_lockout_secs: u64,
+
+    /// Longest lockout in seconds
+    pub max_lockout_secs: u64,
+
+    /// Lockouts after which a key is escalated; 0 never escalates
+    pub escalate_after_lockouts: u32,
+
+    /// Seconds without failures after which a key's lockouts are forgotten
+    pub reset_after_secs: u64,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts_per_ip: 20,
+            max_attempts_per_account: 5,
+            window_secs: 900,
+            base_lockout_secs: 60,
+            max_lockout_secs: 3600,
+            escalate_after_lockouts: 3,
+            reset_after_secs: 86400,
+        }
+    }
+}
+
+impl LockoutPolicy {
+    fn lockout_for(&self, lockouts: u32) -> Duration {
+        let factor = 1u64 << lockouts.saturating_sub(1).min(20);
+        Duration::from_secs(
+            self.base_lockout_secs
+                .saturating_mul(factor)
+                .min(self.max_lockout_secs),
+        )
+    }
+
+    fn limit(&self, key: &AttemptKey) -> u32 {
+        match key {
+            AttemptKey::Ip(_) => self.max_attempts_per_ip,
+            AttemptKey::Account(_) => self.max_attempts_per_account,
+        }
+    }
+}
+
+/// What failed attempts are counted against
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum AttemptKey {
+    Ip(IpAddr),
+    Account(String),
+}
+
+impl fmt::Display for AttemptKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(ip) => write!(f, "address {}", ip),
+            Self::Account(account) => write!(f, "account {}", account),
+        }
+    }
+}
+
+/// An attempt refused because its IP or account is locked out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lockout {
+    pub key: AttemptKey,
+    pub retry_after: Duration,
+    pub escalated: bool,
+}
+
+impl fmt::Display for Lockout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Too many failed attempts for {}; retry in {}s",
+            self.key,
+            self.retry_after.as_secs().max(1)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthAuditKind {
+    Success,
+    Failure,
+    /// An IP or account was locked out
+    LockedOut,
+    /// An IP or account was locked out `escalate_after_lockouts` times
+    Escalated,
+    /// An attempt was refused during a lockout
+    Rejected,
+}
+
+/// Record of an authentication attempt for audit logs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthAuditEvent {
+    pub at: DateTime<Utc>,
+    pub kind: AuthAuditKind,
+    /// Service that saw the attempt, e.g. `identity` or `proxy`
+    pub service: String,
+    pub ip: IpAddr,
+    pub account: String,
+    /// Key locked out, escalated or refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<AttemptKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockout_secs: Option<u64>,
+    /// Request or command the attempt was part of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<CorrelationId>,
+}
+
+/// Receives audit events, e.g. to log them or to escalate lockouts
+pub trait AuthObserver: Send + Sync {
+    fn observe(&self, event: &AuthAuditEvent);
+}
+
+#[derive(Debug, Clone)]
+struct KeyState {
+    failures: u32,
+    window_start: Instant,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+    lockouts: u32,
+}
+
+/// Counts failed attempts and enforces a [`LockoutPolicy`]
+pub struct LoginGuard {
+    policy: LockoutPolicy,
+    service: String,
+    state: Mutex<HashMap<AttemptKey, KeyState>>,
+    observers: Vec<Arc<dyn AuthObserver>>,
+}
+
+impl LoginGuard {
+    pub fn new(policy: LockoutPolicy, service: impl Into<String>) -> Self {
+        Self {
+            policy,
+            service: service.into(),
+            state: Mutex::new(HashMap::new()),
+            observers: Vec::new(),
+        }
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn AuthObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    pub fn policy(&self) -> &LockoutPolicy {
+        &self.policy
+    }
+
+    /// Refuse the attempt if `ip` or `account` is locked out
+    pub fn check(&self, ip: IpAddr, account: &str) -> Result<(), Lockout> {
+        self.check_at(ip, account, Instant::now())
+    }
+
+    /// Count a failed attempt, returning the lockout it caused, if any
+    pub fn record_failure(&self, ip: IpAddr, account: &str) -> Option<Lockout> {
+        self.record_failure_at(ip, account, Instant::now())
+    }
+
+    /// Forget the account's failed attempts; the IP's still count
+    pub fn record_success(&self, ip: IpAddr, account: &str) {
+        if !self.policy.enabled {
+            return;
+        }
+
+        self.state
+            .lock()
+            .unwrap()
+            .remove(&AttemptKey::Account(account.to_string()));
+        self.emit(vec![self.event(
+            AuthAuditKind::Success,
+            ip,
+            account,
+            None,
+            None,
+        )]);
+    }
+
+    /// Whether `ip` or `account` has been escalated, e.g. to require a
+    /// CAPTCHA
+    pub fn is_escalated(&self, ip: IpAddr, account: &str) -> bool {
+        let threshold = self.policy.escalate_after_lockouts;
+        if !self.policy.enabled || threshold == 0 {
+            return false;
+        }
+
+        let state = self.state.lock().unwrap();
+        keys(ip, account).iter().any(|key| {
+            state
+                .get(key)
+                .is_some_and(|entry| entry.lockouts >= threshold)
+        })
+    }
+
+    /// Drop keys that are not locked out and whose lockouts were forgotten
+    pub fn prune(&self) {
+        self.prune_at(Instant::now());
+    }
+
+    fn check_at(&self, ip: IpAddr, account: &str, now: Instant) -> Result<(), Lockout> {
+        if !self.policy.enabled {
+            return Ok(());
+        }
+
+        let lockout = {
+            let state = self.state.lock().unwrap();
+            keys(ip, account).into_iter().find_map(|key| {
+                let entry = state.get(&key)?;
+                let until = entry.locked_until.filter(|until| *until > now)?;
+                Some(Lockout {
+                    retry_after: until - now,
+                    escalated: self.escalated(entry),
+                    key,
+                })
+            })
+        };
+
+        match lockout {
+            Some(lockout) => {
+                self.emit(vec![self.event(
+                    AuthAuditKind::Rejected,
+                    ip,
+                    account,
+                    Some(lockout.key.clone()),
+                    Some(lockout.retry_after),
+                )]);
+                Err(lockout)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn record_failure_at(&self, ip: IpAddr, account: &str, now: Instant) -> Option<Lockout> {
+        if !self.policy.enabled {
+            return None;
+        }
+
+        let window = Duration::from_secs(self.policy.window_secs);
+        let reset_after = Duration::from_secs(self.policy.reset_after_secs);
+        let mut events = vec![self.event(AuthAuditKind::Failure, ip, account, None, None)];
+        let mut lockout = None;
+
+        {
+            let mut state = self.state.lock().unwrap();
+            for key in keys(ip, account) {
+                let limit = self.policy.limit(&key);
+                let entry = state.entry(key.clone()).or_insert(KeyState {
+                    failures: 0,
+                    window_start: now,
+                    last_failure: now,
+                    locked_until: None,
+                    lockouts: 0,
+                });
+
+                if now.duration_since(entry.last_failure) > reset_after {
+                    entry.lockouts = 0;
+                }
+                if now.duration_since(entry.window_start) > window {
+                    entry.failures = 0;
+                    entry.window_start = now;
+                }
+                entry.failures += 1;
+                entry.last_failure = now;
+
+                if limit == 0 || entry.failures < limit {
+                    continue;
+                }
+
+                entry.failures = 0;
+                entry.window_start = now;
+                entry.lockouts += 1;
+                let duration = self.policy.lockout_for(entry.lockouts);
+                entry.locked_until = Some(now + duration);
+
+                events.push(self.event(
+                    AuthAuditKind::LockedOut,
+                    ip,
+                    account,
+                    Some(key.clone()),
+                    Some(duration),
+                ));
+                let escalated = self.escalated(entry);
+                if escalated && entry.lockouts == self.policy.escalate_after_lockouts {
+                    events.push(self.event(
+                        AuthAuditKind::Escalated,
+                        ip,
+                        account,
+                        Some(key.clone()),
+                        Some(duration),
+                    ));
+                }
+                lockout.get_or_insert(Lockout {
+                    key,
+                    retry_after: duration,
+                    escalated,
+                });
+            }
+        }
+
+        self.emit(events);
+        lockout
+    }
+
+    fn prune_at(&self, now: Instant) {
+        let reset_after = Duration::from_secs(self.policy.reset_after_secs);
+        self.state.lock().unwrap().retain(|_, entry| {
+            entry.locked_until.is_some_and(|until| until > now)
+                || now.duration_since(entry.last_failure) <= reset_after
+        });
+    }
+
+    fn escalated(&self, entry: &KeyState) -> bool {
+        self.policy.escalate_after_lockouts > 0
+            && entry.lockouts >= self.policy.escalate_after_lockouts
+    }
+
+    fn event(
+        &self,
+        kind: AuthAuditKind,
+        ip: IpAddr,
+        account: &str,
+        key: Option<AttemptKey>,
+        lockout: Option<Duration>,
+    ) -> AuthAuditEvent {
+        AuthAuditEvent {
+            at: Utc::now(),
+            kind,
+            service: self.service.clone(),
+            ip,
+            account: account.to_string(),
+            key,
+            lockout_secs: lockout.map(|duration| duration.as_secs()),
+            correlation_id: CorrelationId::current(),
+        }
+    }
+
+    /// Observers run outside the state lock so they may call back in
+    fn emit(&self, events: Vec<AuthAuditEvent>) {
+        for event in &events {
+            for observer in &self.observers {
+                observer.observe(event);
+            }
+        }
+    }
+}
+
+fn keys(ip: IpAddr, account: &str) -> [AttemptKey; 2] {
+    // IPv4 clients on a dual-stack listener show up as mapped addresses
+    [
+        AttemptKey::Ip(ip.to_canonical()),
+        AttemptKey::Account(account.to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Recorder(Mutex<Vec<AuthAuditKind>>);
+
+    impl AuthObserver for Recorder {
+        fn observe(&self, event: &AuthAuditEvent) {
+            self.0.lock().unwrap().push(event.kind);
+        }
+    }
+
+    fn guard() -> LoginGuard {
+        LoginGuard::new(
+            LockoutPolicy {
+                max_attempts_per_ip: 5,
+                max_attempts_per_account: 3,
+                escalate_after_lockouts: 2,
+                ..Default::default()
+            },
+            "test",
+        )
+    }
+
+    #[test]
+    fn test_account_lockout_doubles() {
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        let guard = guard().with_observer(recorder.clone());
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(guard.record_failure_at(ip, "alice", start).is_none());
+        assert!(guard.record_failure_at(ip, "alice", start).is_none());
+        let lockout = guard.record_failure_at(ip, "alice", start).unwrap();
+        assert_eq!(lockout.key, AttemptKey::Account("alice".to_string()));
+        assert_eq!(lockout.retry_after, Duration::from_secs(60));
+        assert!(!lockout.escalated);
+
+        // Another account from the same IP is not locked
+        assert!(guard.check_at(ip, "alice", start).is_err());
+        assert!(guard.check_at(ip, "bob", start).is_ok());
+
+        // The second lockout doubles and escalates, whatever the address
+        let later = start + Duration::from_secs(61);
+        let other: IpAddr = "198.51.100.1".parse().unwrap();
+        assert!(guard.check_at(other, "alice", later).is_ok());
+        for _ in 0..2 {
+            guard.record_failure_at(other, "alice", later);
+        }
+        let lockout = guard.record_failure_at(other, "alice", later).unwrap();
+        assert_eq!(lockout.retry_after, Duration::from_secs(120));
+        assert!(lockout.escalated);
+        assert!(guard.is_escalated(other, "alice"));
+
+        let kinds = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            kinds
+                .iter()
+                .filter(|kind| **kind == AuthAuditKind::LockedOut)
+                .count(),
+            2
+        );
+        assert!(kinds.contains(&AuthAuditKind::Escalated));
+        assert!(kinds.contains(&AuthAuditKind::Rejected));
+    }
+
+    #[test]
+    fn test_ip_lockout_and_success() {
+        let guard = guard();
+        let ip: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+
+        // Spraying one password across accounts locks the IP
+        for account in ["a", "b", "c", "d"] {
+            assert!(guard.record_failure_at(ip, account, start).is_none());
+        }
+        let lockout = guard.record_failure_at(ip, "e", start).unwrap();
+        assert_eq!(lockout.key, AttemptKey::Ip("192.0.2.1".parse().unwrap()));
+
+        // A success clears the account's failures
+        let other: IpAddr = "198.51.100.1".parse().unwrap();
+        guard.record_failure_at(other, "f", start);
+        guard.record_failure_at(other, "f", start);
+        guard.record_success(other, "f");
+        assert!(guard.record_failure_at(other, "f", start).is_none());
+
+        // Quiet keys are forgotten
+        guard.prune_at(start + Duration::from_secs(86400 * 2));
+        assert!(guard.state.lock().unwrap().is_empty());
+    }
+}
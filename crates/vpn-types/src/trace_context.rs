@@ -0,0 +1,187 @@
+//! W3C trace context propagation
+//!
+//! A [`TraceContext`] identifies the trace a piece of work belongs to and
+//! the span it runs under. It travels between processes as a W3C
+//! `traceparent` header (`00-<trace id>-<span id>-<flags>`) so that one user
+//! request can be followed from the CLI through cluster RPCs and proxied
+//! connections to the container action it ends in.
+//!
+//! Within a process the context is task-local: [`TraceContext::scope`] runs
+//! a future under a context and [`TraceContext::current`] reads it back.
+
+use std::fmt;
+use std::future::Future;
+
+/// Header and gRPC metadata key carrying the context
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// The only `traceparent` version this node emits
+const VERSION: u8 = 0;
+
+/// Trace flag asking downstream services to record the trace
+const FLAG_SAMPLED: u8 = 0x01;
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// Position of a piece of work within a distributed trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    trace_id: u128,
+    span_id: u64,
+    flags: u8,
+}
+
+impl TraceContext {
+    /// Start a new, sampled trace
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: nonzero(rand::random),
+            span_id: nonzero(rand::random),
+            flags: FLAG_SAMPLED,
+        }
+    }
+
+    /// Context for work started under this one: same trace, new span
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: nonzero(rand::random),
+            ..*self
+        }
+    }
+
+    /// Parse a `traceparent` header. Malformed values yield `None`, upon
+    /// which the receiver starts a new trace as the W3C spec asks.
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parse_hex(parts.next()?, 2)? as u8;
+        let trace_id = parse_hex(parts.next()?, 32)?;
+        let span_id = parse_hex(parts.next()?, 16)? as u64;
+        let flags = parse_hex(parts.next()?, 2)? as u8;
+
+        // Version ff is invalid; later versions may append fields
+        let trailing = parts.next().is_some();
+        if version == 0xff || (version == VERSION && trailing) {
+            return None;
+        }
+        if trace_id == 0 || span_id == 0 {
+            return None;
+        }
+
+        Some(Self {
+            trace_id,
+            span_id,
+            flags,
+        })
+    }
+
+    /// Trace id as 32 lowercase hex digits, as Jaeger shows it
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    /// Span id as 16 lowercase hex digits
+    pub fn span_id(&self) -> String {
+        format!("{:016x}", self.span_id)
+    }
+
+    /// Whether the trace should be recorded
+    pub fn is_sampled(&self) -> bool {
+        self.flags & FLAG_SAMPLED != 0
+    }
+
+    /// Context of the current task, if it runs under one
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|context| *context).ok()
+    }
+
+    /// Child of the current context, or a new trace outside of one
+    pub fn current_child() -> Self {
+        Self::current()
+            .map(|context| context.child())
+            .unwrap_or_else(Self::new_root)
+    }
+
+    /// Run `future` with this as the current context
+    pub fn scope<F: Future>(self, future: F) -> impl Future<Output = F::Output> {
+        CURRENT.scope(self, future)
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}-{:032x}-{:016x}-{:02x}",
+            VERSION, self.trace_id, self.span_id, self.flags
+        )
+    }
+}
+
+fn nonzero<T: Default + PartialEq>(mut random: impl FnMut() -> T) -> T {
+    loop {
+        let value = random();
+        if value != T::default() {
+            return value;
+        }
+    }
+}
+
+/// Exactly `digits` lowercase hex digits
+fn parse_hex(field: &str, digits: usize) -> Option<u128> {
+    if field.len() != digits
+        || !field
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        return None;
+    }
+    u128::from_str_radix(field, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent_round_trip() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(header).unwrap();
+        assert_eq!(context.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id(), "00f067aa0ba902b7");
+        assert!(context.is_sampled());
+        assert_eq!(context.to_string(), header);
+
+        let child = context.child();
+        assert_eq!(child.trace_id(), context.trace_id());
+        assert_ne!(child.span_id(), context.span_id());
+
+        // Future versions may carry extra fields; version 00 may not
+        assert!(TraceContext::parse(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra"
+        )
+        .is_some());
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "garbage",
+        ] {
+            assert!(TraceContext::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scope_sets_current_context() {
+        assert!(TraceContext::current().is_none());
+
+        let root = TraceContext::new_root();
+        let child = root.scope(async { TraceContext::current_child() }).await;
+        assert_eq!(child.trace_id(), root.trace_id());
+        assert_ne!(child.span_id(), root.span_id());
+    }
+}
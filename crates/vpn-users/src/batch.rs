@@ -2,13 +2,14 @@ use crate::error::Result;
 use crate::manager::UserManager;
 use crate::user::{User, UserStatus};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::mpsc;
 use tokio::task::JoinSet;
+use vpn_crypto::BatchKeyGenerator;
 use vpn_types::protocol::VpnProtocol;
 
 pub struct BatchOperations {
@@ -97,6 +98,58 @@ pub struct RollbackInfo {
     pub can_rollback: bool,
 }
 
+/// Status change for many users at once; see
+/// [`BatchOperations::update_status_bulk`]
+#[derive(Debug, Clone)]
+pub struct BulkStatusRequest {
+    pub user_ids: Vec<String>,
+    pub status: UserStatus,
+    /// Most users updated at the same time
+    pub concurrency: usize,
+    /// Most users updated per second, unlimited if None
+    pub rate_per_second: Option<u32>,
+    /// Identifies the batch across runs, so re-running it skips users
+    /// already changed by an earlier run
+    pub idempotency_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkStatusResult {
+    pub operation_id: String,
+    pub applied: Vec<String>,
+    /// Users already in the target status, or changed by an earlier run
+    /// with the same idempotency token
+    pub skipped: Vec<String>,
+    pub failed: HashMap<String, String>,
+    pub duration_ms: u128,
+}
+
+/// Users changed under an idempotency token, kept between runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BulkStatusLedger {
+    token: String,
+    status: UserStatus,
+    applied: HashSet<String>,
+    created_at: u64,
+}
+
+enum StatusChange {
+    Applied,
+    Unchanged,
+}
+
+impl BulkStatusRequest {
+    pub fn new(user_ids: Vec<String>, status: UserStatus) -> Self {
+        Self {
+            user_ids,
+            status,
+            concurrency: 8,
+            rate_per_second: None,
+            idempotency_token: None,
+        }
+    }
+}
+
 impl BatchOperations {
     pub fn new(user_manager: Arc<UserManager>) -> Self {
         Self {
@@ -234,9 +287,14 @@ impl BatchOperations {
             .create_progress_tracker(operation_id.clone(), total_items, true)
             .await;
 
+        // Keys for the whole batch up front, in parallel
+        let keypairs = BatchKeyGenerator::new()
+            .generate_keypairs_async(total_items)
+            .await?;
+
         let mut tasks = JoinSet::new();
 
-        for (index, name) in request.names.iter().enumerate() {
+        for ((index, name), keypair) in request.names.iter().enumerate().zip(keypairs) {
             let name = name.clone();
             let protocol = request.protocol;
             let email = request
@@ -251,7 +309,10 @@ impl BatchOperations {
             tasks.spawn(async move {
                 tracker_clone.set_current_item(Some(name.clone()));
 
-                match user_manager.create_user(name.clone(), protocol).await {
+                match user_manager
+                    .create_user_with_keypair(name.clone(), protocol, keypair)
+                    .await
+                {
                     Ok(mut user) => {
                         if let Some(email) = email {
                             user.email = Some(email);
@@ -315,8 +376,12 @@ impl BatchOperations {
             .create_progress_tracker(operation_id.clone(), total_items, true)
             .await;
 
+        let keypairs = BatchKeyGenerator::new()
+            .generate_keypairs_async(total_items)
+            .await?;
+
         // Process items one by one for resumability
-        for (index, name) in request.names.iter().enumerate() {
+        for ((index, name), keypair) in request.names.iter().enumerate().zip(keypairs) {
             tracker.set_current_item(Some(name.clone()));
 
             let protocol = request.protocol;
@@ -326,7 +391,11 @@ impl BatchOperations {
                 .and_then(|emails| emails.get(index))
                 .cloned();
 
-            match self.user_manager.create_user(name.clone(), protocol).await {
+            match self
+                .user_manager
+                .create_user_with_keypair(name.clone(), protocol, keypair)
+                .await
+            {
                 Ok(mut user) => {
                     if let Some(email) = email {
                         user.email = Some(email);
@@ -736,6 +805,188 @@ impl BatchOperations {
         })
     }
 
+    /// Set the status of many users with at most `request.concurrency`
+    /// updates in flight and at most `request.rate_per_second` started per
+    /// second. `progress` is called after each user. Failures are reported
+    /// per user instead of aborting the batch.
+    ///
+    /// With an idempotency token the users changed are recorded as they
+    /// are applied, and a later run with the same token skips them, so a
+    /// failed batch can be re-run without changing anyone twice.
+    pub async fn update_status_bulk(
+        &self,
+        request: BulkStatusRequest,
+        mut progress: impl FnMut(&ProgressInfo),
+    ) -> Result<BulkStatusResult> {
+        let start_time = Instant::now();
+        let mut ledger = match &request.idempotency_token {
+            Some(token) => Some(self.load_status_ledger(token, request.status)?),
+            None => None,
+        };
+        let operation_id = match &ledger {
+            Some(ledger) => format!("bulk_status_{}", ledger.token),
+            None => format!("bulk_status_{}", uuid::Uuid::new_v4()),
+        };
+
+        let mut user_ids = Vec::new();
+        for user_id in request.user_ids {
+            if !user_ids.contains(&user_id) {
+                user_ids.push(user_id);
+            }
+        }
+        let (tracker, _receiver) = self
+            .create_progress_tracker(operation_id.clone(), user_ids.len(), true)
+            .await;
+
+        let mut applied = Vec::new();
+        let mut skipped = Vec::new();
+        let mut failed = HashMap::new();
+        let mut pending = user_ids.into_iter();
+        let mut tasks = JoinSet::new();
+        let mut pace = request
+            .rate_per_second
+            .filter(|rate| *rate > 0)
+            .map(|rate| {
+                let mut interval =
+                    tokio::time::interval(std::time::Duration::from_secs_f64(1.0 / rate as f64));
+                interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                interval
+            });
+
+        loop {
+            // Start updates until the concurrency bound is reached
+            while tasks.len() < request.concurrency.max(1) {
+                let Some(user_id) = pending.next() else {
+                    break;
+                };
+                if ledger
+                    .as_ref()
+                    .is_some_and(|ledger| ledger.applied.contains(&user_id))
+                {
+                    tracker.increment_completed();
+                    progress(&ProgressInfo {
+                        current_item: Some(user_id.clone()),
+                        ..tracker.get_progress()
+                    });
+                    skipped.push(user_id);
+                    continue;
+                }
+                if let Some(pace) = pace.as_mut() {
+                    pace.tick().await;
+                }
+
+                let user_manager = Arc::clone(&self.user_manager);
+                let status = request.status;
+                tasks.spawn(async move {
+                    let result = apply_status(&user_manager, &user_id, status).await;
+                    (user_id, result)
+                });
+            }
+
+            let Some(joined) = tasks.join_next().await else {
+                break;
+            };
+            let (user_id, result) = match joined {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    tracker.increment_failed();
+                    failed.insert("unknown".to_string(), e.to_string());
+                    continue;
+                }
+            };
+
+            match result {
+                Ok(StatusChange::Applied) => {
+                    tracker.increment_completed();
+                    if let Some(ledger) = ledger.as_mut() {
+                        ledger.applied.insert(user_id.clone());
+                        self.save_status_ledger(ledger)?;
+                    }
+                    applied.push(user_id.clone());
+                }
+                Ok(StatusChange::Unchanged) => {
+                    tracker.increment_completed();
+                    skipped.push(user_id.clone());
+                }
+                Err(e) => {
+                    tracker.increment_failed();
+                    failed.insert(user_id.clone(), e.to_string());
+                }
+            }
+            progress(&ProgressInfo {
+                current_item: Some(user_id),
+                ..tracker.get_progress()
+            });
+        }
+
+        self.remove_progress_tracker(&operation_id).await;
+
+        Ok(BulkStatusResult {
+            operation_id,
+            applied,
+            skipped,
+            failed,
+            duration_ms: start_time.elapsed().as_millis(),
+        })
+    }
+
+    fn status_ledger_path(&self, token: &str) -> std::path::PathBuf {
+        self.user_manager
+            .get_users_directory()
+            .join("batches")
+            .join(format!("{}.json", token))
+    }
+
+    /// Ledger of an earlier run with `token`, or a new one. A token is
+    /// bound to the status it was first used with.
+    fn load_status_ledger(&self, token: &str, status: UserStatus) -> Result<BulkStatusLedger> {
+        if token.is_empty()
+            || !token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(crate::error::UserError::ValidationError {
+                field: "idempotency_token".to_string(),
+                message: "Use only letters, digits, '-' and '_'".to_string(),
+            });
+        }
+
+        let path = self.status_ledger_path(token);
+        if !path.exists() {
+            return Ok(BulkStatusLedger {
+                token: token.to_string(),
+                status,
+                applied: HashSet::new(),
+                created_at: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            });
+        }
+
+        let ledger: BulkStatusLedger = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if ledger.status != status {
+            return Err(crate::error::UserError::ValidationError {
+                field: "idempotency_token".to_string(),
+                message: format!(
+                    "Token '{}' was used to set status {:?}, not {:?}",
+                    token, ledger.status, status
+                ),
+            });
+        }
+
+        Ok(ledger)
+    }
+
+    fn save_status_ledger(&self, ledger: &BulkStatusLedger) -> Result<()> {
+        let path = self.status_ledger_path(&ledger.token);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(ledger)?)?;
+        Ok(())
+    }
+
     pub async fn generate_all_qr_codes(&self, output_dir: &Path) -> Result<BatchOperationResult> {
         let users = self.user_manager.list_users(None).await?;
         let mut successful = Vec::new();
@@ -783,7 +1034,19 @@ impl BatchOperations {
         let mut failed = HashMap::new();
         let total_count = imported_users.len();
 
+        // Fresh keys for every user are generated up front, in parallel
+        let mut new_keys = if options.generate_new_keys {
+            BatchKeyGenerator::new()
+                .generate_keypairs_async(total_count)
+                .await?
+                .into_iter()
+        } else {
+            Vec::new().into_iter()
+        };
+
         for mut user in imported_users {
+            let new_keypair = new_keys.next();
+
             // Check if user already exists
             if self.user_manager.get_user(&user.id).await.is_ok() {
                 if !options.overwrite_existing {
@@ -795,19 +1058,10 @@ impl BatchOperations {
                 }
             }
 
-            // Generate new keys if requested
-            if options.generate_new_keys {
-                let key_manager = vpn_crypto::X25519KeyManager::new();
-                match key_manager.generate_keypair() {
-                    Ok(keypair) => {
-                        user.config.private_key = Some(keypair.private_key_base64());
-                        user.config.public_key = Some(keypair.public_key_base64());
-                    }
-                    Err(e) => {
-                        failed.insert(user.name.clone(), e.to_string());
-                        continue;
-                    }
-                }
+            // Use the new keys if requested
+            if let Some(keypair) = new_keypair {
+                user.config.private_key = Some(keypair.private_key_base64());
+                user.config.public_key = Some(keypair.public_key_base64());
             }
 
             // Validate configuration if requested
@@ -906,6 +1160,20 @@ impl BatchOperations {
     }
 }
 
+async fn apply_status(
+    user_manager: &UserManager,
+    user_id: &str,
+    status: UserStatus,
+) -> Result<StatusChange> {
+    let mut user = user_manager.get_user(user_id).await?;
+    if user.status == status {
+        return Ok(StatusChange::Unchanged);
+    }
+    user.status = status;
+    user_manager.update_user(user).await?;
+    Ok(StatusChange::Applied)
+}
+
 impl Default for ImportOptions {
     fn default() -> Self {
         Self {
@@ -1006,4 +1274,56 @@ mod tests {
         assert_eq!(result.successful.len(), 2);
         assert!(result.failed.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_bulk_status_idempotency() {
+        let temp_dir = tempdir().unwrap();
+        let user_manager =
+            Arc::new(UserManager::new(temp_dir.path(), ServerConfig::default()).unwrap());
+        let batch_ops = BatchOperations::new(user_manager.clone());
+
+        let mut ids = Vec::new();
+        for name in ["alice", "bob", "carol"] {
+            let user = user_manager
+                .create_user(name.to_string(), VpnProtocol::Vless)
+                .await
+                .unwrap();
+            ids.push(user.id);
+        }
+
+        let mut request = BulkStatusRequest::new(
+            vec![ids[0].clone(), ids[1].clone(), "missing".to_string()],
+            UserStatus::Suspended,
+        );
+        request.concurrency = 2;
+        request.rate_per_second = Some(100);
+        request.idempotency_token = Some("suspend-1".to_string());
+
+        let mut updates = 0;
+        let result = batch_ops
+            .update_status_bulk(request.clone(), |_| updates += 1)
+            .await
+            .unwrap();
+        assert_eq!(updates, 3);
+        assert_eq!(result.applied.len(), 2);
+        assert!(result.failed.contains_key("missing"));
+
+        // A user reactivated since is not suspended again by a re-run
+        let mut bob = user_manager.get_user(&ids[1]).await.unwrap();
+        bob.status = UserStatus::Active;
+        user_manager.update_user(bob).await.unwrap();
+
+        request.user_ids = ids.clone();
+        let result = batch_ops
+            .update_status_bulk(request.clone(), |_| {})
+            .await
+            .unwrap();
+        assert_eq!(result.applied, vec![ids[2].clone()]);
+        assert_eq!(result.skipped.len(), 2);
+        let bob = user_manager.get_user(&ids[1]).await.unwrap();
+        assert_eq!(bob.status, UserStatus::Active);
+
+        request.status = UserStatus::Active;
+        assert!(batch_ops.update_status_bulk(request, |_| {}).await.is_err());
+    }
 }
@@ -0,0 +1,304 @@
+//! Cluster-wide user management
+//!
+//! [`ClusterUserManager`] wraps a node-local [`UserManager`] and mirrors every
+//! change into a [`UserReplication`] store shared by the cluster. Other nodes
+//! pick the change up from the store and rewrite their local user files and
+//! server configuration to match. The cluster crate implements the store on
+//! top of its distributed storage.
+
+use crate::error::{Result, UserError};
+use crate::manager::{UserListOptions, UserManager};
+use crate::user::User;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use vpn_types::protocol::VpnProtocol;
+
+/// User records shared by all nodes of a cluster. Records are opaque JSON
+/// documents keyed by user ID.
+#[async_trait]
+pub trait UserReplication: Send + Sync {
+    /// All user records keyed by user ID
+    async fn list(&self) -> Result<HashMap<String, Value>>;
+
+    /// Insert or replace a user record
+    async fn put(&self, id: &str, record: Value) -> Result<()>;
+
+    /// Remove a user record; returns `false` if it did not exist
+    async fn remove(&self, id: &str) -> Result<bool>;
+
+    /// Receive a message whenever the set of records changes
+    async fn watch(&self) -> Result<mpsc::Receiver<()>>;
+}
+
+pub struct ClusterUserManager {
+    local: Arc<UserManager>,
+    store: Arc<dyn UserReplication>,
+}
+
+impl ClusterUserManager {
+    pub fn new(local: Arc<UserManager>, store: Arc<dyn UserReplication>) -> Self {
+        Self { local, store }
+    }
+
+    /// The node-local user manager
+    pub fn local(&self) -> &UserManager {
+        &self.local
+    }
+
+    /// Join the replicated store. If the cluster has no users yet, the users
+    /// already present on this node are published; otherwise the local users
+    /// are replaced by the cluster's.
+    pub async fn bootstrap(&self) -> Result<()> {
+        if self.store.list().await?.is_empty() {
+            for user in self.local.list_users(None).await? {
+                self.publish(&user).await?;
+            }
+            return Ok(());
+        }
+
+        self.sync().await?;
+        Ok(())
+    }
+
+    /// Apply the replicated user set locally. Returns `true` if anything changed.
+    pub async fn sync(&self) -> Result<bool> {
+        let users = self
+            .store
+            .list()
+            .await?
+            .into_values()
+            .map(serde_json::from_value)
+            .collect::<std::result::Result<Vec<User>, _>>()?;
+
+        self.local.replace_users(users).await
+    }
+
+    /// Keep the local users in sync with the cluster until the store's
+    /// change stream ends
+    pub fn spawn_sync(self: Arc<Self>) -> JoinHandle<Result<()>> {
+        tokio::spawn(async move {
+            let mut changes = self.store.watch().await?;
+
+            // Catch up on anything written before the watch was registered
+            self.sync().await?;
+
+            while changes.recv().await.is_some() {
+                if let Err(e) = self.sync().await {
+                    tracing::warn!("Failed to apply replicated users: {}", e);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    pub async fn create_user(&self, name: String, protocol: VpnProtocol) -> Result<User> {
+        // Pick up users created elsewhere so name checks see the whole cluster
+        self.sync().await?;
+
+        let user = self.local.create_user(name, protocol).await?;
+        if let Err(e) = self.publish(&user).await {
+            self.local.delete_user(&user.id).await?;
+            return Err(e);
+        }
+
+        Ok(user)
+    }
+
+    pub async fn update_user(&self, user: User) -> Result<()> {
+        self.sync().await?;
+
+        let id = user.id.clone();
+        self.local.update_user(user).await?;
+        self.publish(&self.local.get_user(&id).await?).await
+    }
+
+    pub async fn delete_user(&self, id: &str) -> Result<()> {
+        self.sync().await?;
+
+        if !self.store.remove(id).await? {
+            return Err(UserError::UserNotFound(id.to_string()));
+        }
+
+        self.local.delete_user(id).await
+    }
+
+    pub async fn get_user(&self, id: &str) -> Result<User> {
+        self.local.get_user(id).await
+    }
+
+    pub async fn get_user_by_name(&self, name: &str) -> Result<User> {
+        self.local.get_user_by_name(name).await
+    }
+
+    pub async fn list_users(&self, options: Option<UserListOptions>) -> Result<Vec<User>> {
+        self.local.list_users(options).await
+    }
+
+    async fn publish(&self, user: &User) -> Result<()> {
+        self.store.put(&user.id, serde_json::to_value(user)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::user::UserStatus;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    /// In-process stand-in for the cluster's replicated store
+    #[derive(Default)]
+    struct MemoryReplication {
+        users: Mutex<HashMap<String, Value>>,
+        watchers: Mutex<Vec<mpsc::Sender<()>>>,
+    }
+
+    impl MemoryReplication {
+        fn notify(&self) {
+            self.watchers
+                .lock()
+                .unwrap()
+                .retain(|tx| !matches!(tx.try_send(()), Err(mpsc::error::TrySendError::Closed(_))));
+        }
+    }
+
+    #[async_trait]
+    impl UserReplication for MemoryReplication {
+        async fn list(&self) -> Result<HashMap<String, Value>> {
+            Ok(self.users.lock().unwrap().clone())
+        }
+
+        async fn put(&self, id: &str, record: Value) -> Result<()> {
+            self.users.lock().unwrap().insert(id.to_string(), record);
+            self.notify();
+            Ok(())
+        }
+
+        async fn remove(&self, id: &str) -> Result<bool> {
+            let removed = self.users.lock().unwrap().remove(id).is_some();
+            if removed {
+                self.notify();
+            }
+            Ok(removed)
+        }
+
+        async fn watch(&self) -> Result<mpsc::Receiver<()>> {
+            let (tx, rx) = mpsc::channel(16);
+            self.watchers.lock().unwrap().push(tx);
+            Ok(rx)
+        }
+    }
+
+    fn node(dir: &TempDir, store: &Arc<MemoryReplication>) -> ClusterUserManager {
+        let local = UserManager::new(dir.path(), ServerConfig::default()).unwrap();
+        ClusterUserManager::new(Arc::new(local), store.clone())
+    }
+
+    #[tokio::test]
+    async fn test_changes_propagate_between_nodes() {
+        let store = Arc::new(MemoryReplication::default());
+        let (dir_a, dir_b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let node_a = node(&dir_a, &store);
+        let node_b = node(&dir_b, &store);
+
+        let user = node_a
+            .create_user("alice".to_string(), VpnProtocol::Vless)
+            .await
+            .unwrap();
+        // Keep an active user around, the server config needs at least one client
+        node_a
+            .create_user("bob".to_string(), VpnProtocol::Vless)
+            .await
+            .unwrap();
+
+        assert!(node_b.sync().await.unwrap());
+        assert_eq!(node_b.get_user(&user.id).await.unwrap().name, "alice");
+        assert!(dir_b
+            .path()
+            .join("users")
+            .join(&user.id)
+            .join("config.json")
+            .exists());
+
+        let mut updated = node_b.get_user(&user.id).await.unwrap();
+        updated.status = UserStatus::Suspended;
+        node_b.update_user(updated).await.unwrap();
+
+        node_a.sync().await.unwrap();
+        assert!(matches!(
+            node_a.get_user(&user.id).await.unwrap().status,
+            UserStatus::Suspended
+        ));
+
+        node_b.delete_user(&user.id).await.unwrap();
+        node_a.sync().await.unwrap();
+        assert!(node_a.get_user(&user.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_name_rejected_across_nodes() {
+        let store = Arc::new(MemoryReplication::default());
+        let (dir_a, dir_b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let node_a = node(&dir_a, &store);
+        let node_b = node(&dir_b, &store);
+
+        node_a
+            .create_user("alice".to_string(), VpnProtocol::Vless)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            node_b
+                .create_user("alice".to_string(), VpnProtocol::Vless)
+                .await,
+            Err(UserError::UserAlreadyExists(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_publishes_existing_users() {
+        let store = Arc::new(MemoryReplication::default());
+        let dir = TempDir::new().unwrap();
+        let local = UserManager::new(dir.path(), ServerConfig::default()).unwrap();
+        let user = local
+            .create_user("alice".to_string(), VpnProtocol::Vless)
+            .await
+            .unwrap();
+
+        let manager = ClusterUserManager::new(Arc::new(local), store.clone());
+        manager.bootstrap().await.unwrap();
+
+        assert!(store.list().await.unwrap().contains_key(&user.id));
+    }
+
+    #[tokio::test]
+    async fn test_spawned_sync_applies_remote_changes() {
+        let store = Arc::new(MemoryReplication::default());
+        let (dir_a, dir_b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let node_a = node(&dir_a, &store);
+        let node_b = Arc::new(node(&dir_b, &store));
+        node_b.clone().spawn_sync();
+
+        // Let the sync task register its watch
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let user = node_a
+            .create_user("alice".to_string(), VpnProtocol::Vless)
+            .await
+            .unwrap();
+
+        for _ in 0..50 {
+            if node_b.get_user(&user.id).await.is_ok() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("user was not replicated to the second node");
+    }
+}
@@ -1,8 +1,34 @@
 use crate::error::{Result, UserError};
-use crate::user::User;
+use crate::user::{LinkedCredentials, TrafficPolicy, User};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
+use vpn_types::protocol::VpnProtocol;
+
+/// Tag of the VLESS inbound that carries user clients
+pub const VLESS_INBOUND_TAG: &str = "vless-in";
+/// Tag shared by the Xray API inbound, outbound and service
+pub const API_TAG: &str = "api";
+/// Loopback port the Xray API listens on inside the container
+pub const API_PORT: u16 = 10085;
+pub const DIRECT_OUTBOUND_TAG: &str = "direct";
+pub const BLOCK_OUTBOUND_TAG: &str = "block";
+/// Policy level for users without restrictions
+pub const DEFAULT_USER_LEVEL: u32 = 0;
+/// Policy level for throttled users
+pub const THROTTLED_USER_LEVEL: u32 = 1;
+
+/// Email Xray knows a user by. The stats API and routing rules match on
+/// it, so it is derived from the immutable user ID rather than the
+/// optional contact email.
+pub fn xray_user_email(user: &User) -> String {
+    format!("{}@vpn", user.id)
+}
+
+/// Email Xray knows a merged duplicate's credentials by
+pub fn xray_linked_email(credentials: &LinkedCredentials) -> String {
+    format!("{}@vpn", credentials.id)
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
@@ -19,10 +45,81 @@ pub struct ServerConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct XrayConfig {
     pub log: LogConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<ApiConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<StatsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<PolicyConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing: Option<RoutingConfig>,
     pub inbounds: Vec<Inbound>,
     pub outbounds: Vec<Outbound>,
 }
 
+impl XrayConfig {
+    pub fn inbound(&self, tag: &str) -> Option<&Inbound> {
+        self.inbounds.iter().find(|inbound| inbound.tag == tag)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiConfig {
+    pub tag: String,
+    pub services: Vec<String>,
+}
+
+/// Enables the stats counters; Xray takes an empty object
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatsConfig {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyConfig {
+    pub levels: BTreeMap<String, PolicyLevel>,
+    #[serde(default)]
+    pub system: SystemPolicy,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyLevel {
+    pub stats_user_uplink: bool,
+    pub stats_user_downlink: bool,
+    /// Per-connection buffer in KB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer_size: Option<u32>,
+    /// Seconds an idle connection is kept open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conn_idle: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemPolicy {
+    pub stats_inbound_uplink: bool,
+    pub stats_inbound_downlink: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingConfig {
+    pub domain_strategy: String,
+    pub rules: Vec<RoutingRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingRule {
+    #[serde(rename = "type")]
+    pub rule_type: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inbound_tag: Vec<String>,
+    /// Xray user emails the rule applies to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user: Vec<String>,
+    pub outbound_tag: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogConfig {
     pub level: String,
@@ -33,6 +130,8 @@ pub struct LogConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Inbound {
     pub tag: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listen: Option<String>,
     pub port: u16,
     pub protocol: String,
     pub settings: InboundSettings,
@@ -41,16 +140,22 @@ pub struct Inbound {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InboundSettings {
+    #[serde(default)]
     pub clients: Vec<Client>,
     pub decryption: Option<String>,
     pub fallbacks: Option<Vec<Fallback>>,
+    /// Forward address of a dokodemo-door inbound
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Client {
     pub id: String,
     pub flow: Option<String>,
     pub email: Option<String>,
+    #[serde(default)]
+    pub level: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,11 +169,17 @@ pub struct Outbound {
     pub tag: String,
     pub protocol: String,
     pub settings: Option<OutboundSettings>,
+    /// Remaining fields of operator-defined outbounds, e.g. `streamSettings`
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutboundSettings {
     pub freedom: Option<HashMap<String, serde_json::Value>>,
+    /// Protocol settings of operator-defined outbounds
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,13 +218,34 @@ impl ConfigGenerator {
         users: &[User],
         server_config: &ServerConfig,
     ) -> Result<XrayConfig> {
-        let clients: Vec<Client> = users
+        let active: Vec<&User> = users.iter().filter(|u| u.is_active()).collect();
+
+        let clients: Vec<Client> = active
             .iter()
-            .filter(|u| u.is_active())
-            .map(|u| Client {
-                id: u.id.clone(),
-                flow: u.config.flow.clone(),
-                email: u.email.clone(),
+            .flat_map(|u| {
+                let level = match u.traffic {
+                    TrafficPolicy::Throttle => THROTTLED_USER_LEVEL,
+                    _ => DEFAULT_USER_LEVEL,
+                };
+                let primary = Client {
+                    id: u.id.clone(),
+                    flow: u.config.flow.clone(),
+                    email: Some(xray_user_email(u)),
+                    level,
+                };
+                // Merged duplicates keep their UUIDs; Xray needs a distinct
+                // email per client
+                let linked = u
+                    .linked_credentials
+                    .iter()
+                    .filter(|c| c.protocol == VpnProtocol::Vless)
+                    .map(move |c| Client {
+                        id: c.id.clone(),
+                        flow: c.config.flow.clone(),
+                        email: Some(xray_linked_email(c)),
+                        level,
+                    });
+                std::iter::once(primary).chain(linked)
             })
             .collect();
 
@@ -121,6 +253,7 @@ impl ConfigGenerator {
             clients,
             decryption: Some("none".to_string()),
             fallbacks: None,
+            address: None,
         };
 
         let reality_settings = if server_config.private_key.is_some() {
@@ -159,32 +292,156 @@ impl ConfigGenerator {
         };
 
         let inbound = Inbound {
-            tag: "vless-in".to_string(),
+            tag: VLESS_INBOUND_TAG.to_string(),
+            listen: None,
             port: server_config.port,
             protocol: "vless".to_string(),
             settings: inbound_settings,
             stream_settings: Some(stream_settings),
         };
 
-        let outbound = Outbound {
-            tag: "direct".to_string(),
-            protocol: "freedom".to_string(),
-            settings: Some(OutboundSettings {
-                freedom: Some(HashMap::new()),
-            }),
+        // Only reachable from inside the container, for `xray api`
+        let api_inbound = Inbound {
+            tag: API_TAG.to_string(),
+            listen: Some("127.0.0.1".to_string()),
+            port: API_PORT,
+            protocol: "dokodemo-door".to_string(),
+            settings: InboundSettings {
+                clients: Vec::new(),
+                decryption: None,
+                fallbacks: None,
+                address: Some("127.0.0.1".to_string()),
+            },
+            stream_settings: None,
         };
 
+        // The first outbound is Xray's default route
+        let outbounds = vec![
+            Outbound {
+                tag: DIRECT_OUTBOUND_TAG.to_string(),
+                protocol: "freedom".to_string(),
+                settings: Some(OutboundSettings {
+                    freedom: Some(HashMap::new()),
+                    extra: serde_json::Map::new(),
+                }),
+                extra: serde_json::Map::new(),
+            },
+            Outbound {
+                tag: BLOCK_OUTBOUND_TAG.to_string(),
+                protocol: "blackhole".to_string(),
+                settings: None,
+                extra: serde_json::Map::new(),
+            },
+        ];
+
         Ok(XrayConfig {
             log: LogConfig {
                 level: "warning".to_string(),
                 access: Some("/opt/v2ray/logs/access.log".to_string()),
                 error: Some("/opt/v2ray/logs/error.log".to_string()),
             },
-            inbounds: vec![inbound],
-            outbounds: vec![outbound],
+            api: Some(ApiConfig {
+                tag: API_TAG.to_string(),
+                services: vec![
+                    "HandlerService".to_string(),
+                    "StatsService".to_string(),
+                    "RoutingService".to_string(),
+                ],
+            }),
+            stats: Some(StatsConfig::default()),
+            policy: Some(Self::user_policy()),
+            routing: Some(RoutingConfig {
+                domain_strategy: "AsIs".to_string(),
+                rules: Self::user_routing_rules(&active),
+            }),
+            inbounds: vec![inbound, api_inbound],
+            outbounds,
         })
     }
 
+    /// Policy levels referenced by [`Client::level`]. Both count per-user
+    /// traffic; Xray has no rate limiter, so the throttled level slows a
+    /// user down with small buffers and short idle timeouts instead.
+    fn user_policy() -> PolicyConfig {
+        let mut levels = BTreeMap::new();
+        levels.insert(
+            DEFAULT_USER_LEVEL.to_string(),
+            PolicyLevel {
+                stats_user_uplink: true,
+                stats_user_downlink: true,
+                buffer_size: None,
+                conn_idle: None,
+            },
+        );
+        levels.insert(
+            THROTTLED_USER_LEVEL.to_string(),
+            PolicyLevel {
+                stats_user_uplink: true,
+                stats_user_downlink: true,
+                buffer_size: Some(4),
+                conn_idle: Some(60),
+            },
+        );
+
+        PolicyConfig {
+            levels,
+            system: SystemPolicy {
+                stats_inbound_uplink: true,
+                stats_inbound_downlink: true,
+            },
+        }
+    }
+
+    /// API traffic first, then one rule per outbound that users are sent
+    /// to other than the default
+    fn user_routing_rules(users: &[&User]) -> Vec<RoutingRule> {
+        let mut rules = vec![RoutingRule {
+            rule_type: "field".to_string(),
+            inbound_tag: vec![API_TAG.to_string()],
+            user: Vec::new(),
+            outbound_tag: API_TAG.to_string(),
+        }];
+
+        let mut by_outbound: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for user in users {
+            let outbound = match &user.traffic {
+                TrafficPolicy::Block => BLOCK_OUTBOUND_TAG,
+                TrafficPolicy::Outbound(tag) => tag.as_str(),
+                TrafficPolicy::Direct | TrafficPolicy::Throttle => continue,
+            };
+            let emails = by_outbound.entry(outbound).or_default();
+            emails.push(xray_user_email(user));
+            emails.extend(
+                user.linked_credentials
+                    .iter()
+                    .filter(|c| c.protocol == VpnProtocol::Vless)
+                    .map(xray_linked_email),
+            );
+        }
+
+        rules.extend(
+            by_outbound
+                .into_iter()
+                .map(|(outbound, emails)| RoutingRule {
+                    rule_type: "field".to_string(),
+                    inbound_tag: Vec::new(),
+                    user: emails,
+                    outbound_tag: outbound.to_string(),
+                }),
+        );
+        rules
+    }
+
+    /// Keep outbounds from `previous` that `config` does not define, so
+    /// operator-added outbounds survive regeneration
+    pub fn carry_over_outbounds(config: &mut XrayConfig, previous: &XrayConfig) {
+        for outbound in &previous.outbounds {
+            if !config.outbounds.iter().any(|o| o.tag == outbound.tag) {
+                config.outbounds.push(outbound.clone());
+            }
+        }
+    }
+
     pub fn generate_shadowsocks_config(
         users: &[User],
         server_config: &ServerConfig,
@@ -246,13 +503,29 @@ impl ConfigGenerator {
                 ));
             }
 
-            if inbound.settings.clients.is_empty() {
+            if inbound.protocol == "vless" && inbound.settings.clients.is_empty() {
                 return Err(UserError::InvalidConfiguration(
                     "No clients configured for inbound".to_string(),
                 ));
             }
         }
 
+        if let Some(routing) = &config.routing {
+            let outbounds: HashSet<&str> =
+                config.outbounds.iter().map(|o| o.tag.as_str()).collect();
+            for rule in &routing.rules {
+                let known = outbounds.contains(rule.outbound_tag.as_str())
+                    || config.api.as_ref().map(|api| api.tag.as_str())
+                        == Some(rule.outbound_tag.as_str());
+                if !known {
+                    return Err(UserError::InvalidConfiguration(format!(
+                        "Routing rule uses unknown outbound '{}'",
+                        rule.outbound_tag
+                    )));
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -271,3 +544,57 @@ impl Default for ServerConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_user_tags_and_routing() {
+        let users = vec![
+            User::new("alice".to_string(), VpnProtocol::Vless),
+            User::new("bob".to_string(), VpnProtocol::Vless).with_traffic(TrafficPolicy::Block),
+            User::new("carol".to_string(), VpnProtocol::Vless)
+                .with_traffic(TrafficPolicy::Throttle),
+            User::new("dave".to_string(), VpnProtocol::Vless)
+                .with_traffic(TrafficPolicy::Outbound("warp".to_string())),
+        ];
+
+        let mut config =
+            ConfigGenerator::generate_xray_config(&users, &ServerConfig::default()).unwrap();
+
+        let clients = &config.inbound(VLESS_INBOUND_TAG).unwrap().settings.clients;
+        assert_eq!(clients[0].email, Some(format!("{}@vpn", users[0].id)));
+        assert_eq!(clients[2].level, THROTTLED_USER_LEVEL);
+        assert_eq!(clients[3].level, DEFAULT_USER_LEVEL);
+
+        let rules = &config.routing.as_ref().unwrap().rules;
+        assert_eq!(rules[0].outbound_tag, API_TAG);
+        assert_eq!(rules[1].outbound_tag, BLOCK_OUTBOUND_TAG);
+        assert_eq!(rules[1].user, vec![xray_user_email(&users[1])]);
+        assert_eq!(rules[2].outbound_tag, "warp");
+        assert_eq!(rules.len(), 3);
+
+        // "warp" is not defined until an operator adds it
+        assert!(ConfigGenerator::validate_config(&config).is_err());
+
+        let previous: XrayConfig = serde_json::from_value(serde_json::json!({
+            "log": { "level": "warning", "access": null, "error": null },
+            "inbounds": [],
+            "outbounds": [{
+                "tag": "warp",
+                "protocol": "wireguard",
+                "settings": { "secretKey": "key", "peers": [] },
+                "streamSettings": { "sockopt": { "mark": 255 } }
+            }]
+        }))
+        .unwrap();
+        ConfigGenerator::carry_over_outbounds(&mut config, &previous);
+        ConfigGenerator::validate_config(&config).unwrap();
+
+        let json = serde_json::to_value(&config).unwrap();
+        let warp = &json["outbounds"][2];
+        assert_eq!(warp["settings"]["secretKey"], "key");
+        assert_eq!(warp["streamSettings"]["sockopt"]["mark"], 255);
+    }
+}
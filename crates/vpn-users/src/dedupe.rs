@@ -0,0 +1,431 @@
+//! Merging duplicate users across protocol installations
+//!
+//! Every protocol installation keeps its own `users/` directory, so the
+//! same person tends to exist several times under different IDs.
+//! [`UserDeduplicator`] finds users sharing a name or email across the
+//! unified store and other install paths, and merges each group into one
+//! user of the unified store. The merged user carries the credentials of
+//! every duplicate as [`LinkedCredentials`], so clients set up with an old
+//! ID keep working, and share links move to the surviving ID.
+//!
+//! Other install paths are only read. Their users stay in place and, once
+//! merged, are recognised by their linked IDs and not reported again.
+
+use crate::error::Result;
+use crate::manager::UserManager;
+use crate::share::ShareLinkStore;
+use crate::user::{LinkedCredentials, User};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What a group of duplicates has in common
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchReason {
+    Name,
+    Email,
+}
+
+impl fmt::Display for MatchReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name => write!(f, "name"),
+            Self::Email => write!(f, "email"),
+        }
+    }
+}
+
+/// A user and the install path it was read from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub install_path: PathBuf,
+    pub user: User,
+}
+
+/// Users that are the same person
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub reason: MatchReason,
+    /// User the others are merged into
+    pub primary: UserRecord,
+    pub duplicates: Vec<UserRecord>,
+}
+
+impl DuplicateGroup {
+    /// The primary with the duplicates' credentials, tags, notes and
+    /// traffic folded in
+    pub fn merged(&self) -> User {
+        let mut user = self.primary.user.clone();
+
+        for record in &self.duplicates {
+            let duplicate = &record.user;
+            if user.answers_to(&duplicate.id) {
+                continue;
+            }
+
+            user.linked_credentials.push(LinkedCredentials {
+                id: duplicate.id.clone(),
+                short_id: duplicate.short_id.clone(),
+                protocol: duplicate.protocol,
+                config: duplicate.config.clone(),
+                source: Some(record.install_path.display().to_string()),
+            });
+            user.linked_credentials.extend(
+                duplicate
+                    .linked_credentials
+                    .iter()
+                    .filter(|c| !user.answers_to(&c.id))
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            );
+
+            if user.email.is_none() {
+                user.email = duplicate.email.clone();
+            }
+            for (key, value) in &duplicate.tags {
+                user.tags
+                    .entry(key.clone())
+                    .or_insert_with(|| value.clone());
+            }
+            if let Some(notes) = &duplicate.notes {
+                user.notes = match user.notes.take() {
+                    Some(existing) if existing.contains(notes.as_str()) => Some(existing),
+                    Some(existing) => Some(format!("{}\n{}", existing, notes)),
+                    None => Some(notes.clone()),
+                };
+            }
+
+            user.created_at = user.created_at.min(duplicate.created_at);
+            user.last_active = user.last_active.max(duplicate.last_active);
+            user.stats.bytes_sent += duplicate.stats.bytes_sent;
+            user.stats.bytes_received += duplicate.stats.bytes_received;
+            user.stats.connection_count += duplicate.stats.connection_count;
+            user.stats.total_uptime += duplicate.stats.total_uptime;
+            user.stats.last_connection = user
+                .stats
+                .last_connection
+                .max(duplicate.stats.last_connection);
+        }
+
+        user
+    }
+}
+
+/// Duplicates found by [`UserDeduplicator::scan`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DedupePlan {
+    pub groups: Vec<DuplicateGroup>,
+}
+
+impl DedupePlan {
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Number of users that would be merged away
+    pub fn duplicate_count(&self) -> usize {
+        self.groups.iter().map(|g| g.duplicates.len()).sum()
+    }
+}
+
+/// Outcome of [`UserDeduplicator::apply`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DedupeReport {
+    /// Users in the unified store that absorbed duplicates
+    pub merged_users: usize,
+    /// Duplicates deleted from the unified store
+    pub removed_users: usize,
+    /// Credentials now linked to a merged user
+    pub linked_credentials: usize,
+    /// Share links moved to the merged user
+    pub reassigned_share_links: usize,
+}
+
+/// Finds and merges duplicate users
+pub struct UserDeduplicator {
+    target: PathBuf,
+    sources: Vec<PathBuf>,
+}
+
+impl UserDeduplicator {
+    /// Deduplicator merging into the unified store at `target`
+    pub fn new<P: AsRef<Path>>(target: P) -> Self {
+        Self {
+            target: target.as_ref().to_path_buf(),
+            sources: Vec::new(),
+        }
+    }
+
+    /// Also look for duplicates among the users of another install path
+    pub fn with_source<P: AsRef<Path>>(mut self, source: P) -> Self {
+        let source = source.as_ref().to_path_buf();
+        if source != self.target && !self.sources.contains(&source) {
+            self.sources.push(source);
+        }
+        self
+    }
+
+    /// Group users sharing a case-insensitive name or email
+    pub fn scan(&self) -> Result<DedupePlan> {
+        let mut records: Vec<UserRecord> = load_users(&self.target)?
+            .into_iter()
+            .map(|user| UserRecord {
+                install_path: self.target.clone(),
+                user,
+            })
+            .collect();
+
+        // IDs already merged into the unified store
+        let merged: HashSet<String> = records
+            .iter()
+            .flat_map(|r| r.user.linked_credentials.iter().map(|c| c.id.clone()))
+            .collect();
+
+        for source in &self.sources {
+            records.extend(
+                load_users(source)?
+                    .into_iter()
+                    .filter(|user| !merged.contains(&user.id))
+                    .map(|user| UserRecord {
+                        install_path: source.clone(),
+                        user,
+                    }),
+            );
+        }
+
+        Ok(DedupePlan {
+            groups: self.group(records),
+        })
+    }
+
+    /// Merge every group of `plan` into `manager`, which must manage the
+    /// unified store
+    pub async fn apply(&self, plan: &DedupePlan, manager: &UserManager) -> Result<DedupeReport> {
+        let share_links = ShareLinkStore::new(&self.target);
+        let mut report = DedupeReport::default();
+
+        for group in &plan.groups {
+            let merged = group.merged();
+
+            for record in &group.duplicates {
+                if record.install_path == self.target {
+                    manager.delete_user(&record.user.id).await?;
+                    report.removed_users += 1;
+                }
+                report.reassigned_share_links +=
+                    share_links.reassign_user(&record.user.id, &merged)?;
+            }
+
+            if group.primary.install_path == self.target {
+                manager.update_user(merged.clone()).await?;
+            } else {
+                manager.import_user(merged.clone()).await?;
+            }
+
+            report.merged_users += 1;
+            report.linked_credentials +=
+                merged.linked_credentials.len() - group.primary.user.linked_credentials.len();
+        }
+
+        Ok(report)
+    }
+
+    fn group(&self, records: Vec<UserRecord>) -> Vec<DuplicateGroup> {
+        let mut parent: Vec<usize> = (0..records.len()).collect();
+        fn find(parent: &mut [usize], i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            parent[i] = root;
+            root
+        }
+
+        let mut first_by_key: HashMap<(MatchReason, String), usize> = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            let keys = [
+                Some((MatchReason::Name, normalize(&record.user.name))),
+                record
+                    .user
+                    .email
+                    .as_deref()
+                    .map(|email| (MatchReason::Email, normalize(email))),
+            ];
+            for key in keys.into_iter().flatten() {
+                if key.1.is_empty() {
+                    continue;
+                }
+                match first_by_key.get(&key) {
+                    Some(&first) => {
+                        let (a, b) = (find(&mut parent, first), find(&mut parent, i));
+                        parent[b] = a;
+                    }
+                    None => {
+                        first_by_key.insert(key, i);
+                    }
+                }
+            }
+        }
+
+        let mut members: BTreeMap<usize, Vec<UserRecord>> = BTreeMap::new();
+        for (i, record) in records.into_iter().enumerate() {
+            let root = find(&mut parent, i);
+            members.entry(root).or_default().push(record);
+        }
+
+        members
+            .into_values()
+            .filter(|members| members.len() > 1)
+            .map(|mut members| {
+                let reason = if members
+                    .iter()
+                    .all(|r| normalize(&r.user.name) == normalize(&members[0].user.name))
+                {
+                    MatchReason::Name
+                } else {
+                    MatchReason::Email
+                };
+
+                // Prefer a user already in the unified store, then the oldest
+                members.sort_by_key(|r| (r.install_path != self.target, r.user.created_at));
+                let primary = members.remove(0);
+                DuplicateGroup {
+                    reason,
+                    primary,
+                    duplicates: members,
+                }
+            })
+            .collect()
+    }
+}
+
+fn normalize(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+/// Users stored under `install_path`, skipping unreadable entries
+fn load_users(install_path: &Path) -> Result<Vec<User>> {
+    let users_dir = install_path.join("users");
+    if !users_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut users = Vec::new();
+    for entry in fs::read_dir(&users_dir)? {
+        let config_file = entry?.path().join("config.json");
+        if !config_file.exists() {
+            continue;
+        }
+        match fs::read_to_string(&config_file)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str::<User>(&content).map_err(|e| e.to_string()))
+        {
+            Ok(user) => users.push(user),
+            Err(e) => eprintln!(
+                "Failed to read user config {}: {}",
+                config_file.display(),
+                e
+            ),
+        }
+    }
+    Ok(users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigGenerator, ServerConfig, VLESS_INBOUND_TAG};
+    use tempfile::TempDir;
+    use vpn_types::protocol::VpnProtocol;
+
+    fn store(path: &Path, users: &[User]) {
+        for user in users {
+            let dir = path.join("users").join(&user.id);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(
+                dir.join("config.json"),
+                serde_json::to_string(user).unwrap(),
+            )
+            .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_across_install_paths() {
+        let unified = TempDir::new().unwrap();
+        let legacy = TempDir::new().unwrap();
+
+        let mut alice = User::new("alice".to_string(), VpnProtocol::Vless);
+        alice.created_at -= chrono::Duration::days(1);
+        alice.stats.bytes_sent = 10;
+        let alice_again = User::new("Alice".to_string(), VpnProtocol::Vless)
+            .with_email("alice@example.com".to_string());
+        let bob = User::new("bob".to_string(), VpnProtocol::Vless);
+        store(unified.path(), &[alice.clone(), alice_again.clone(), bob]);
+
+        let mut legacy_alice = User::new("alice-ss".to_string(), VpnProtocol::Outline)
+            .with_email("ALICE@example.com".to_string())
+            .with_tag("plan".to_string(), "pro".to_string());
+        legacy_alice.stats.bytes_sent = 5;
+        let carol = User::new("carol".to_string(), VpnProtocol::Outline);
+        store(legacy.path(), &[legacy_alice.clone(), carol]);
+
+        let manager = UserManager::new(unified.path(), ServerConfig::default()).unwrap();
+        let share_links = ShareLinkStore::new(unified.path());
+        share_links
+            .create(
+                &alice_again,
+                "vless://old".to_string(),
+                chrono::Duration::hours(1),
+            )
+            .unwrap();
+
+        let deduplicator = UserDeduplicator::new(unified.path()).with_source(legacy.path());
+        let plan = deduplicator.scan().unwrap();
+        assert_eq!(plan.groups.len(), 1);
+        assert_eq!(plan.duplicate_count(), 2);
+        let group = &plan.groups[0];
+        assert_eq!(group.reason, MatchReason::Email);
+        assert_eq!(group.primary.install_path, unified.path());
+
+        let report = deduplicator.apply(&plan, &manager).await.unwrap();
+        assert_eq!(report.merged_users, 1);
+        assert_eq!(report.removed_users, 1);
+        assert_eq!(report.linked_credentials, 2);
+        assert_eq!(report.reassigned_share_links, 1);
+
+        assert_eq!(manager.get_user_count().await, 2);
+        let merged = manager.get_user(&group.primary.user.id).await.unwrap();
+        assert!(merged.answers_to(&alice_again.id));
+        assert!(merged.answers_to(&legacy_alice.id));
+        assert_eq!(merged.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(merged.tags["plan"], "pro");
+        assert_eq!(merged.stats.bytes_sent, 15);
+        assert_eq!(share_links.list().unwrap()[0].user_id, merged.id);
+
+        // Old VLESS UUIDs stay valid; the Outline credentials do not
+        // belong in the Xray config
+        let xray = ConfigGenerator::generate_xray_config(
+            &manager.list_users(None).await.unwrap(),
+            &ServerConfig::default(),
+        )
+        .unwrap();
+        let clients: Vec<&str> = xray
+            .inbound(VLESS_INBOUND_TAG)
+            .unwrap()
+            .settings
+            .clients
+            .iter()
+            .map(|c| c.id.as_str())
+            .collect();
+        assert!(clients.contains(&alice_again.id.as_str()));
+        assert!(!clients.contains(&legacy_alice.id.as_str()));
+
+        // Sources are left alone, and what was merged is not found again
+        assert_eq!(load_users(legacy.path()).unwrap().len(), 2);
+        assert!(deduplicator.scan().unwrap().is_empty());
+    }
+}
@@ -32,6 +32,9 @@ pub enum UserError {
     #[error("Network error: {0}")]
     NetworkError(#[from] vpn_network::NetworkError),
 
+    #[error("Replication error: {0}")]
+    ReplicationError(String),
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
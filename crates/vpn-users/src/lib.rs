@@ -1,18 +1,30 @@
 pub mod batch;
+pub mod cluster;
 pub mod config;
+pub mod dedupe;
 pub mod error;
 pub mod links;
 pub mod manager;
+pub mod provisioning;
+pub mod routing;
+pub mod share;
 pub mod user;
 
 #[cfg(test)]
 pub mod proptest;
 
-pub use batch::BatchOperations;
+pub use batch::{BatchOperations, BulkStatusRequest};
+pub use cluster::{ClusterUserManager, UserReplication};
+pub use dedupe::{DedupePlan, DedupeReport, DuplicateGroup, UserDeduplicator};
 pub use error::{Result, UserError};
 pub use links::ConnectionLinkGenerator;
 pub use manager::UserManager;
-pub use user::{User, UserConfig, UserStats, UserStatus};
+pub use provisioning::{CredentialProvisioner, ProxyCredential};
+pub use routing::RoutingProfile;
+pub use share::{ShareLink, ShareLinkStore};
+pub use user::{
+    LinkedCredentials, TagFilter, TrafficPolicy, User, UserConfig, UserStats, UserStatus,
+};
 
 // Re-export VpnProtocol for external use
 pub use vpn_types::protocol::VpnProtocol;
@@ -1,5 +1,6 @@
 use crate::config::ServerConfig;
 use crate::error::{Result, UserError};
+use crate::routing::{DIRECT_OUTBOUND_TAG, PROXY_OUTBOUND_TAG};
 use crate::user::User;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use url::Url;
@@ -181,6 +182,83 @@ impl ConnectionLinkGenerator {
         ))
     }
 
+    /// Full client configuration for protocols whose clients can apply the
+    /// user's routing profile: a WireGuard config file or an Xray JSON config
+    pub fn generate_client_config(user: &User, server_config: &ServerConfig) -> Result<String> {
+        match user.protocol {
+            VpnProtocol::Wireguard => Ok(Self::generate_wireguard_config(user, server_config)),
+            VpnProtocol::Vless => Self::generate_xray_client_config(user, server_config),
+            _ => Err(UserError::LinkGenerationError(format!(
+                "Client configs are not supported for {}",
+                user.protocol.display_name()
+            ))),
+        }
+    }
+
+    fn generate_wireguard_config(user: &User, server_config: &ServerConfig) -> String {
+        format!(
+            "[Interface]\nPrivateKey = {}\n\n[Peer]\nPublicKey = {}\nEndpoint = {}:{}\nAllowedIPs = {}\n",
+            user.config
+                .private_key
+                .as_deref()
+                .unwrap_or("MISSING_PRIVATE_KEY"),
+            server_config
+                .public_key
+                .as_deref()
+                .unwrap_or("MISSING_PUBLIC_KEY"),
+            server_config.host,
+            server_config.port,
+            user.routing.wireguard_allowed_ips().join(", ")
+        )
+    }
+
+    fn generate_xray_client_config(user: &User, server_config: &ServerConfig) -> Result<String> {
+        let config = serde_json::json!({
+            "log": { "loglevel": "warning" },
+            "inbounds": [{
+                "tag": "socks-in",
+                "listen": "127.0.0.1",
+                "port": 10808,
+                "protocol": "socks",
+                "settings": { "udp": true }
+            }],
+            "outbounds": [
+                {
+                    "tag": PROXY_OUTBOUND_TAG,
+                    "protocol": "vless",
+                    "settings": {
+                        "vnext": [{
+                            "address": server_config.host,
+                            "port": server_config.port,
+                            "users": [{
+                                "id": user.id,
+                                "encryption": "none",
+                                "flow": user.config.flow.as_deref().unwrap_or("xtls-rprx-vision")
+                            }]
+                        }]
+                    },
+                    "streamSettings": {
+                        "network": "tcp",
+                        "security": "reality",
+                        "realitySettings": {
+                            "serverName": server_config.sni.as_deref().unwrap_or("www.google.com"),
+                            "fingerprint": "chrome",
+                            "publicKey": server_config.public_key.as_deref().unwrap_or(""),
+                            "shortId": server_config.short_id.as_deref().unwrap_or("")
+                        }
+                    }
+                },
+                {
+                    "tag": DIRECT_OUTBOUND_TAG,
+                    "protocol": "freedom"
+                }
+            ],
+            "routing": user.routing.xray_routing()
+        });
+
+        Ok(serde_json::to_string_pretty(&config)?)
+    }
+
     pub fn parse_vless_link(link: &str) -> Result<(String, String, u16, Vec<(String, String)>)> {
         let url = Url::parse(link).map_err(|e| UserError::LinkGenerationError(e.to_string()))?;
 
@@ -284,6 +362,7 @@ impl ConnectionLinkGenerator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::routing::RoutingProfile;
     use crate::user::User;
     use vpn_types::protocol::VpnProtocol;
 
@@ -310,4 +389,30 @@ mod tests {
         assert_eq!(host, "example.com");
         assert_eq!(port, 443);
     }
+
+    #[test]
+    fn test_client_config_applies_routing_profile() {
+        let server_config = ServerConfig::default();
+
+        let wireguard = User::new("wg-user".to_string(), VpnProtocol::Wireguard).with_routing(
+            RoutingProfile::split_tunnel(vec![], vec!["10.8.0.0/24".to_string()]).unwrap(),
+        );
+        let config =
+            ConnectionLinkGenerator::generate_client_config(&wireguard, &server_config).unwrap();
+        assert!(config.contains("AllowedIPs = 10.8.0.0/24\n"));
+
+        let vless = User::new("vless-user".to_string(), VpnProtocol::Vless)
+            .with_routing(RoutingProfile::BypassLocal);
+        let config =
+            ConnectionLinkGenerator::generate_client_config(&vless, &server_config).unwrap();
+        let config: serde_json::Value = serde_json::from_str(&config).unwrap();
+        assert_eq!(
+            config["routing"],
+            RoutingProfile::BypassLocal.xray_routing()
+        );
+        assert_eq!(config["outbounds"][0]["tag"], PROXY_OUTBOUND_TAG);
+
+        let proxy = User::new("proxy-user".to_string(), VpnProtocol::HttpProxy);
+        assert!(ConnectionLinkGenerator::generate_client_config(&proxy, &server_config).is_err());
+    }
 }
@@ -1,21 +1,27 @@
 use crate::config::{ConfigGenerator, ServerConfig};
 use crate::error::{Result, UserError};
 use crate::links::ConnectionLinkGenerator;
-use crate::user::{User, UserStatus};
+use crate::provisioning::{CredentialProvisioner, ProxyCredential};
+use crate::user::{TagFilter, User, UserStatus};
 use dashmap::DashMap;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
 use vpn_crypto::QrCodeGenerator;
 use vpn_types::protocol::VpnProtocol;
 use vpn_types::validation::UsernameValidator;
 
 pub struct UserManager {
     users: DashMap<String, User>,
+    /// Tag key -> IDs of the users carrying it
+    tag_index: DashMap<String, HashSet<String>>,
     storage_path: PathBuf,
     max_users: Option<usize>,
     server_config: ServerConfig,
     read_only_mode: bool,
+    provisioner: Option<Arc<dyn CredentialProvisioner>>,
 }
 
 #[derive(Debug, Clone)]
@@ -24,6 +30,10 @@ pub struct UserListOptions {
     pub protocol_filter: Option<VpnProtocol>,
     pub sort_by: SortBy,
     pub limit: Option<usize>,
+    /// Users must carry every listed tag
+    pub tag_filters: Vec<TagFilter>,
+    /// Case-insensitive text matched against name, email, ID, notes and tags
+    pub search: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -58,10 +68,12 @@ impl UserManager {
 
         let manager = Self {
             users: DashMap::new(),
+            tag_index: DashMap::new(),
             storage_path,
             max_users: None,
             server_config,
             read_only_mode,
+            provisioner: None,
         };
 
         manager.load_users_from_disk()?;
@@ -73,6 +85,13 @@ impl UserManager {
         self
     }
 
+    /// Register the credentials of proxy users created from now on with
+    /// `provisioner`, and revoke them when the users are deleted
+    pub fn with_provisioner(mut self, provisioner: Arc<dyn CredentialProvisioner>) -> Self {
+        self.provisioner = Some(provisioner);
+        self
+    }
+
     pub fn is_read_only(&self) -> bool {
         self.read_only_mode
     }
@@ -82,6 +101,23 @@ impl UserManager {
     }
 
     pub async fn create_user(&self, name: String, protocol: VpnProtocol) -> Result<User> {
+        // Generate crypto keys for the user
+        let key_manager = vpn_crypto::X25519KeyManager::new();
+        let keypair = key_manager
+            .generate_keypair()
+            .map_err(|e| UserError::CryptoError(e))?;
+
+        self.create_user_with_keypair(name, protocol, keypair).await
+    }
+
+    /// Create a user with a keypair generated ahead of time, e.g. by a
+    /// [`vpn_crypto::BatchKeyGenerator`] for a whole batch at once
+    pub async fn create_user_with_keypair(
+        &self,
+        name: String,
+        protocol: VpnProtocol,
+        keypair: vpn_crypto::KeyPair,
+    ) -> Result<User> {
         if self.read_only_mode {
             return Err(UserError::ReadOnlyMode);
         }
@@ -108,22 +144,76 @@ impl UserManager {
 
         let mut user = User::new(name, protocol);
 
-        // Generate crypto keys for the user
-        let key_manager = vpn_crypto::X25519KeyManager::new();
-        let keypair = key_manager
-            .generate_keypair()
-            .map_err(|e| UserError::CryptoError(e))?;
-
         user.config.private_key = Some(keypair.private_key_base64());
         user.config.public_key = Some(keypair.public_key_base64());
         user.config.server_host = self.server_config.host.clone();
         user.config.server_port = self.server_config.port;
         user.config.sni = self.server_config.sni.clone();
 
-        self.users.insert(user.id.clone(), user.clone());
+        self.put_user(user.clone());
 
         self.save_user_to_disk(&user).await?;
         self.regenerate_server_config().await?;
+        self.provision_credential(&user).await;
+
+        Ok(user)
+    }
+
+    /// Add an existing user while keeping its ID, e.g. when migrating from
+    /// another installation. Missing keys are generated.
+    pub async fn import_user(&self, mut user: User) -> Result<User> {
+        if self.read_only_mode {
+            return Err(UserError::ReadOnlyMode);
+        }
+
+        Uuid::parse_str(&user.id).map_err(|e| UserError::ValidationError {
+            field: "id".to_string(),
+            message: e.to_string(),
+        })?;
+
+        let username_validator = UsernameValidator::default();
+        username_validator
+            .validate(&user.name)
+            .map_err(|e| UserError::ValidationError {
+                field: "username".to_string(),
+                message: e.to_string(),
+            })?;
+
+        if let Some(max) = self.max_users {
+            if self.users.len() >= max {
+                return Err(UserError::UserLimitExceeded(max));
+            }
+        }
+
+        if self.users.contains_key(&user.id) {
+            return Err(UserError::UserAlreadyExists(user.id));
+        }
+        if self
+            .users
+            .iter()
+            .any(|entry| entry.value().name == user.name)
+        {
+            return Err(UserError::UserAlreadyExists(user.name));
+        }
+
+        if user.config.private_key.is_none() || user.config.public_key.is_none() {
+            let key_manager = vpn_crypto::X25519KeyManager::new();
+            let keypair = key_manager
+                .generate_keypair()
+                .map_err(UserError::CryptoError)?;
+
+            user.config.private_key = Some(keypair.private_key_base64());
+            user.config.public_key = Some(keypair.public_key_base64());
+        }
+        user.config.server_host = self.server_config.host.clone();
+        user.config.server_port = self.server_config.port;
+        user.config.sni = self.server_config.sni.clone();
+
+        self.put_user(user.clone());
+
+        self.save_user_to_disk(&user).await?;
+        self.regenerate_server_config().await?;
+        self.provision_credential(&user).await;
 
         Ok(user)
     }
@@ -153,7 +243,7 @@ impl UserManager {
         }
 
         user.update_last_active();
-        self.users.insert(user.id.clone(), user.clone());
+        self.put_user(user.clone());
 
         self.save_user_to_disk(&user).await?;
         self.regenerate_server_config().await?;
@@ -167,27 +257,119 @@ impl UserManager {
         }
 
         let user = self
-            .users
-            .remove(id)
-            .map(|(_, user)| user)
+            .take_user(id)
             .ok_or_else(|| UserError::UserNotFound(id.to_string()))?;
 
         self.delete_user_from_disk(&user).await?;
         self.regenerate_server_config().await?;
+        self.revoke_credential(&user).await;
 
         Ok(())
     }
 
-    pub async fn list_users(&self, options: Option<UserListOptions>) -> Result<Vec<User>> {
-        let mut user_list: Vec<User> = self
+    /// Hand the credential of a new proxy user to the provisioner. The user
+    /// is saved either way; if the proxy cannot be reached it picks the
+    /// user up on its next restart.
+    async fn provision_credential(&self, user: &User) {
+        let (Some(provisioner), Some(credential)) =
+            (&self.provisioner, ProxyCredential::for_user(user))
+        else {
+            return;
+        };
+
+        if let Err(e) = provisioner.provision(&credential).await {
+            eprintln!(
+                "Warning: Could not provision proxy credential for {}: {}",
+                user.name, e
+            );
+        }
+    }
+
+    async fn revoke_credential(&self, user: &User) {
+        let (Some(provisioner), Some(_)) = (&self.provisioner, ProxyCredential::for_user(user))
+        else {
+            return;
+        };
+
+        if let Err(e) = provisioner.revoke(&user.name).await {
+            eprintln!(
+                "Warning: Could not revoke proxy credential for {}: {}",
+                user.name, e
+            );
+        }
+    }
+
+    /// Make the local user set match `users`, e.g. when applying state
+    /// replicated from another node. Returns `true` if anything changed.
+    pub async fn replace_users(&self, users: Vec<User>) -> Result<bool> {
+        if self.read_only_mode {
+            return Err(UserError::ReadOnlyMode);
+        }
+
+        let mut changed = false;
+
+        let wanted: HashMap<String, User> = users
+            .into_iter()
+            .map(|user| (user.id.clone(), user))
+            .collect();
+
+        let stale: Vec<User> = self
             .users
             .iter()
+            .filter(|entry| !wanted.contains_key(entry.key()))
             .map(|entry| entry.value().clone())
             .collect();
 
+        for user in stale {
+            self.take_user(&user.id);
+            self.delete_user_from_disk(&user).await?;
+            changed = true;
+        }
+
+        for (id, user) in wanted {
+            let unchanged = match self.users.get(&id) {
+                Some(existing) => {
+                    serde_json::to_value(existing.value())? == serde_json::to_value(&user)?
+                }
+                None => false,
+            };
+
+            if !unchanged {
+                self.put_user(user.clone());
+                self.save_user_to_disk(&user).await?;
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.regenerate_server_config().await?;
+        }
+
+        Ok(changed)
+    }
+
+    pub async fn list_users(&self, options: Option<UserListOptions>) -> Result<Vec<User>> {
         let options = options.unwrap_or_default();
 
+        let mut user_list: Vec<User> = match self.tagged_user_ids(&options.tag_filters) {
+            Some(ids) => ids
+                .iter()
+                .filter_map(|id| self.users.get(id).map(|entry| entry.value().clone()))
+                .collect(),
+            None => self
+                .users
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect(),
+        };
+
         // Apply filters
+        user_list.retain(|u| options.tag_filters.iter().all(|tag| u.matches_tag(tag)));
+
+        if let Some(query) = options.search.as_deref().filter(|q| !q.is_empty()) {
+            user_list.retain(|u| u.matches_search(query));
+        }
+
         if let Some(status) = options.status_filter {
             user_list.retain(|u| u.status == status);
         }
@@ -235,6 +417,12 @@ impl UserManager {
         ConnectionLinkGenerator::generate(&user, &self.server_config)
     }
 
+    /// Client configuration rendered with the user's routing profile
+    pub async fn generate_client_config(&self, user_id: &str) -> Result<String> {
+        let user = self.get_user(user_id).await?;
+        ConnectionLinkGenerator::generate_client_config(&user, &self.server_config)
+    }
+
     pub async fn generate_qr_code(&self, user_id: &str, output_path: &Path) -> Result<()> {
         let link = self.generate_connection_link(user_id).await?;
         let qr_gen = QrCodeGenerator::new();
@@ -244,6 +432,57 @@ impl UserManager {
         Ok(())
     }
 
+    /// Users carrying every tag key in `filters`, narrowed through the tag
+    /// index. `None` when there is nothing to narrow by.
+    fn tagged_user_ids(&self, filters: &[TagFilter]) -> Option<HashSet<String>> {
+        let mut ids: Option<HashSet<String>> = None;
+
+        for filter in filters {
+            let tagged = self
+                .tag_index
+                .get(&filter.key)
+                .map(|entry| entry.value().clone())
+                .unwrap_or_default();
+
+            ids = Some(match ids {
+                Some(ids) => ids.intersection(&tagged).cloned().collect(),
+                None => tagged,
+            });
+        }
+
+        ids
+    }
+
+    fn put_user(&self, user: User) {
+        if let Some(previous) = self.users.get(&user.id).map(|entry| entry.value().clone()) {
+            self.unindex_tags(&previous);
+        }
+
+        for key in user.tags.keys() {
+            self.tag_index
+                .entry(key.clone())
+                .or_default()
+                .insert(user.id.clone());
+        }
+
+        self.users.insert(user.id.clone(), user);
+    }
+
+    fn take_user(&self, id: &str) -> Option<User> {
+        let (_, user) = self.users.remove(id)?;
+        self.unindex_tags(&user);
+        Some(user)
+    }
+
+    fn unindex_tags(&self, user: &User) {
+        for key in user.tags.keys() {
+            if let Some(mut ids) = self.tag_index.get_mut(key) {
+                ids.remove(&user.id);
+            }
+        }
+        self.tag_index.retain(|_, ids| !ids.is_empty());
+    }
+
     async fn save_user_to_disk(&self, user: &User) -> Result<()> {
         let user_dir = self.storage_path.join("users").join(&user.id);
         fs::create_dir_all(&user_dir)?;
@@ -258,6 +497,15 @@ impl UserManager {
             fs::write(link_file, link)?;
         }
 
+        // Save client config for protocols that support one
+        if let Ok(client_config) = self.generate_client_config(&user.id).await {
+            let file_name = match user.protocol {
+                VpnProtocol::Wireguard => "client.conf",
+                _ => "client.json",
+            };
+            fs::write(user_dir.join(file_name), client_config)?;
+        }
+
         Ok(())
     }
 
@@ -327,8 +575,8 @@ impl UserManager {
         }
 
         // Insert users into DashMap
-        for (id, user) in users {
-            self.users.insert(id, user);
+        for user in users.into_values() {
+            self.put_user(user);
         }
 
         Ok(())
@@ -341,10 +589,17 @@ impl UserManager {
             .map(|entry| entry.value().clone())
             .collect();
 
-        let xray_config = ConfigGenerator::generate_xray_config(&user_list, &self.server_config)?;
-        ConfigGenerator::validate_config(&xray_config)?;
+        let mut xray_config =
+            ConfigGenerator::generate_xray_config(&user_list, &self.server_config)?;
 
         let config_path = self.storage_path.join("config").join("config.json");
+        if config_path.exists() {
+            if let Ok(previous) = ConfigGenerator::load_config_from_file(&config_path) {
+                ConfigGenerator::carry_over_outbounds(&mut xray_config, &previous);
+            }
+        }
+        ConfigGenerator::validate_config(&xray_config)?;
+
         if let Some(parent) = config_path.parent() {
             fs::create_dir_all(parent)?;
         }
@@ -362,6 +617,161 @@ impl Default for UserListOptions {
             protocol_filter: None,
             sort_by: SortBy::CreatedAt,
             limit: None,
+            tag_filters: Vec::new(),
+            search: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn server_config() -> ServerConfig {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 443,
+            sni: Some("www.google.com".to_string()),
+            public_key: Some("test_public_key".to_string()),
+            private_key: Some("test_private_key".to_string()),
+            short_id: Some("test_short_id".to_string()),
+            reality_dest: Some("www.google.com:443".to_string()),
+            reality_server_names: vec!["www.google.com".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_users_by_tag_and_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = UserManager::new(temp_dir.path(), server_config()).unwrap();
+
+        for (name, billing) in [("alice", "paid"), ("alina", "trial"), ("bob", "paid")] {
+            let mut user = manager
+                .create_user(name.to_string(), VpnProtocol::Vless)
+                .await
+                .unwrap();
+            user.set_tag("billing".to_string(), billing.to_string());
+            manager.update_user(user).await.unwrap();
+        }
+
+        let options = UserListOptions {
+            sort_by: SortBy::Name,
+            tag_filters: vec!["billing:paid".parse().unwrap()],
+            ..Default::default()
+        };
+        let names: Vec<String> = manager
+            .list_users(Some(options.clone()))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.name)
+            .collect();
+        assert_eq!(names, vec!["alice", "bob"]);
+
+        let options = UserListOptions {
+            search: Some("ali".to_string()),
+            ..options
+        };
+        let names: Vec<String> = manager
+            .list_users(Some(options))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.name)
+            .collect();
+        assert_eq!(names, vec!["alice"]);
+
+        // The index follows tag removal and user deletion
+        let mut bob = manager.get_user_by_name("bob").await.unwrap();
+        bob.remove_tag("billing");
+        manager.update_user(bob).await.unwrap();
+        let alice = manager.get_user_by_name("alice").await.unwrap();
+        manager.delete_user(&alice.id).await.unwrap();
+
+        let options = UserListOptions {
+            tag_filters: vec!["billing".parse().unwrap()],
+            ..Default::default()
+        };
+        let users = manager.list_users(Some(options)).await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].name, "alina");
+    }
+
+    #[tokio::test]
+    async fn test_user_import_keeps_id() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = UserManager::new(temp_dir.path(), server_config()).unwrap();
+
+        let id = Uuid::new_v4().to_string();
+        let user = User::new("legacy".to_string(), VpnProtocol::Vless).with_id(id.clone());
+        let imported = manager.import_user(user.clone()).await.unwrap();
+
+        assert_eq!(imported.id, id);
+        assert!(imported.config.private_key.is_some());
+
+        // Same ID or name cannot be imported twice
+        assert!(manager.import_user(user).await.is_err());
+        let renamed = User::new("legacy".to_string(), VpnProtocol::Vless);
+        assert!(manager.import_user(renamed).await.is_err());
+
+        // Non-UUID IDs are rejected
+        let invalid =
+            User::new("other".to_string(), VpnProtocol::Vless).with_id("other".to_string());
+        assert!(manager.import_user(invalid).await.is_err());
+
+        // Imported users survive a reload
+        let reloaded = UserManager::new(temp_dir.path(), server_config()).unwrap();
+        assert_eq!(reloaded.get_user(&id).await.unwrap().name, "legacy");
+    }
+
+    #[derive(Default)]
+    struct RecordingProvisioner {
+        provisioned: std::sync::Mutex<Vec<String>>,
+        revoked: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl CredentialProvisioner for RecordingProvisioner {
+        async fn provision(&self, credential: &ProxyCredential) -> Result<()> {
+            self.provisioned
+                .lock()
+                .unwrap()
+                .push(credential.username.clone());
+            Ok(())
         }
+
+        async fn revoke(&self, username: &str) -> Result<()> {
+            self.revoked.lock().unwrap().push(username.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_users_are_provisioned() {
+        let temp_dir = TempDir::new().unwrap();
+        let provisioner = Arc::new(RecordingProvisioner::default());
+        let manager = UserManager::new(temp_dir.path(), server_config())
+            .unwrap()
+            .with_provisioner(provisioner.clone());
+
+        let proxy = manager
+            .create_user("alice".to_string(), VpnProtocol::HttpProxy)
+            .await
+            .unwrap();
+        let vless = manager
+            .create_user("bob".to_string(), VpnProtocol::Vless)
+            .await
+            .unwrap();
+        // The inbound needs a client left once bob is gone
+        manager
+            .create_user("carol".to_string(), VpnProtocol::Vless)
+            .await
+            .unwrap();
+        manager.delete_user(&vless.id).await.unwrap();
+        manager.delete_user(&proxy.id).await.unwrap();
+
+        assert_eq!(*provisioner.provisioned.lock().unwrap(), vec!["alice"]);
+        assert_eq!(*provisioner.revoked.lock().unwrap(), vec!["alice"]);
     }
 }
@@ -0,0 +1,79 @@
+//! Credential provisioning for proxy users
+//!
+//! A running proxy only learns about users that existed when it started.
+//! A [`CredentialProvisioner`] registered with
+//! [`UserManager::with_provisioner`](crate::UserManager::with_provisioner)
+//! is handed the credential of every proxy user as it is created and told
+//! when the user is deleted, so it can register the credential with the
+//! proxy's auth backend right away.
+
+use crate::error::Result;
+use crate::user::User;
+use async_trait::async_trait;
+use vpn_types::protocol::VpnProtocol;
+
+/// Username and password a proxy user authenticates with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyCredential {
+    pub user_id: String,
+    pub username: String,
+    pub password: String,
+}
+
+impl ProxyCredential {
+    /// Credential of `user`, or `None` if it is not a proxy user. The
+    /// password is the user's private key, falling back to its ID, as the
+    /// proxy's `vpn-users` backend expects.
+    pub fn for_user(user: &User) -> Option<Self> {
+        if !matches!(
+            user.protocol,
+            VpnProtocol::HttpProxy | VpnProtocol::Socks5Proxy | VpnProtocol::ProxyServer
+        ) {
+            return None;
+        }
+
+        Some(Self {
+            user_id: user.id.clone(),
+            username: user.name.clone(),
+            password: user
+                .config
+                .private_key
+                .clone()
+                .unwrap_or_else(|| user.id.clone()),
+        })
+    }
+}
+
+/// Registers proxy credentials with a running proxy
+#[async_trait]
+pub trait CredentialProvisioner: Send + Sync {
+    /// Make `credential` valid on the proxy
+    async fn provision(&self, credential: &ProxyCredential) -> Result<()>;
+
+    /// Make the credential of `username` invalid on the proxy
+    async fn revoke(&self, username: &str) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credential_only_for_proxy_users() {
+        let mut proxy = User::new("alice".to_string(), VpnProtocol::Socks5Proxy);
+        proxy.config.private_key = Some("secret".to_string());
+        let credential = ProxyCredential::for_user(&proxy).unwrap();
+        assert_eq!(credential.username, "alice");
+        assert_eq!(credential.password, "secret");
+        assert_eq!(credential.user_id, proxy.id);
+
+        let keyless = User::new("bob".to_string(), VpnProtocol::HttpProxy);
+        assert_eq!(
+            ProxyCredential::for_user(&keyless).unwrap().password,
+            keyless.id
+        );
+
+        let vless = User::new("carol".to_string(), VpnProtocol::Vless);
+        assert!(ProxyCredential::for_user(&vless).is_none());
+    }
+}
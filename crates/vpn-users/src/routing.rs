@@ -0,0 +1,294 @@
+//! Per-user routing profiles
+//!
+//! A routing profile decides which traffic a client sends through the VPN.
+//! Profiles are stored with the user and rendered into the client
+//! configuration: `AllowedIPs` for WireGuard and routing rules for Xray.
+
+use crate::error::{Result, UserError};
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Outbound tag of the VPN connection in Xray client configs
+pub const PROXY_OUTBOUND_TAG: &str = "proxy";
+
+/// Outbound tag of the direct connection in Xray client configs
+pub const DIRECT_OUTBOUND_TAG: &str = "direct";
+
+/// Private and link-local IPv4 ranges kept off the tunnel by `BypassLocal`
+const LOCAL_IPV4: &[(Ipv4Addr, u8)] = &[
+    (Ipv4Addr::new(10, 0, 0, 0), 8),
+    (Ipv4Addr::new(127, 0, 0, 0), 8),
+    (Ipv4Addr::new(169, 254, 0, 0), 16),
+    (Ipv4Addr::new(172, 16, 0, 0), 12),
+    (Ipv4Addr::new(192, 168, 0, 0), 16),
+];
+
+/// Unique local and link-local IPv6 ranges kept off the tunnel by `BypassLocal`
+const LOCAL_IPV6: &[(Ipv6Addr, u8)] = &[
+    (Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7),
+    (Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoutingProfile {
+    /// Send all traffic through the VPN
+    #[default]
+    FullTunnel,
+    /// Send only the listed destinations through the VPN
+    SplitTunnel {
+        #[serde(default)]
+        domains: Vec<String>,
+        #[serde(default)]
+        cidrs: Vec<String>,
+    },
+    /// Send everything except private and link-local networks through the VPN
+    BypassLocal,
+}
+
+impl RoutingProfile {
+    /// Build a split-tunnel profile, validating and normalising its entries
+    pub fn split_tunnel(domains: Vec<String>, cidrs: Vec<String>) -> Result<Self> {
+        let domains = domains
+            .into_iter()
+            .map(|domain| domain.trim().to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .map(|domain| {
+                if domain.contains(|c: char| c.is_whitespace() || c == '/') {
+                    Err(UserError::ValidationError {
+                        field: "domains".to_string(),
+                        message: format!("'{}' is not a valid domain", domain),
+                    })
+                } else {
+                    Ok(domain)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let cidrs = cidrs
+            .iter()
+            .map(|cidr| cidr.trim())
+            .filter(|cidr| !cidr.is_empty())
+            .map(|cidr| {
+                cidr.parse::<IpNetwork>()
+                    .map(|network| format!("{}/{}", network.network(), network.prefix()))
+                    .map_err(|e| UserError::ValidationError {
+                        field: "cidrs".to_string(),
+                        message: format!("'{}': {}", cidr, e),
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if domains.is_empty() && cidrs.is_empty() {
+            return Err(UserError::ValidationError {
+                field: "routing".to_string(),
+                message: "split tunnel needs at least one domain or CIDR".to_string(),
+            });
+        }
+
+        Ok(Self::SplitTunnel { domains, cidrs })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoutingProfile::FullTunnel => "full-tunnel",
+            RoutingProfile::SplitTunnel { .. } => "split-tunnel",
+            RoutingProfile::BypassLocal => "bypass-local",
+        }
+    }
+
+    /// `AllowedIPs` entries for a WireGuard peer.
+    ///
+    /// WireGuard routes by address only, so the domains of a split tunnel
+    /// are not represented here.
+    pub fn wireguard_allowed_ips(&self) -> Vec<String> {
+        match self {
+            RoutingProfile::FullTunnel => vec!["0.0.0.0/0".to_string(), "::/0".to_string()],
+            RoutingProfile::SplitTunnel { cidrs, .. } => cidrs.clone(),
+            RoutingProfile::BypassLocal => {
+                let ipv4: Vec<_> = LOCAL_IPV4
+                    .iter()
+                    .map(|(addr, prefix)| (u32::from(*addr) as u128, *prefix))
+                    .collect();
+                let ipv6: Vec<_> = LOCAL_IPV6
+                    .iter()
+                    .map(|(addr, prefix)| (u128::from(*addr), *prefix))
+                    .collect();
+
+                let mut allowed = Vec::new();
+                for (network, prefix) in complement(&ipv4, 32) {
+                    allowed.push(format!("{}/{}", Ipv4Addr::from(network as u32), prefix));
+                }
+                for (network, prefix) in complement(&ipv6, 128) {
+                    allowed.push(format!("{}/{}", Ipv6Addr::from(network), prefix));
+                }
+                allowed
+            }
+        }
+    }
+
+    /// The `routing` section of an Xray client config. Rules refer to the
+    /// [`PROXY_OUTBOUND_TAG`] and [`DIRECT_OUTBOUND_TAG`] outbounds.
+    pub fn xray_routing(&self) -> Value {
+        let rules = match self {
+            RoutingProfile::FullTunnel => vec![],
+            RoutingProfile::SplitTunnel { domains, cidrs } => {
+                let mut rules = Vec::new();
+                if !domains.is_empty() {
+                    let domains: Vec<String> = domains.iter().map(|d| xray_domain(d)).collect();
+                    rules.push(json!({
+                        "type": "field",
+                        "domain": domains,
+                        "outboundTag": PROXY_OUTBOUND_TAG
+                    }));
+                }
+                if !cidrs.is_empty() {
+                    rules.push(json!({
+                        "type": "field",
+                        "ip": cidrs,
+                        "outboundTag": PROXY_OUTBOUND_TAG
+                    }));
+                }
+                rules.push(json!({
+                    "type": "field",
+                    "network": "tcp,udp",
+                    "outboundTag": DIRECT_OUTBOUND_TAG
+                }));
+                rules
+            }
+            RoutingProfile::BypassLocal => vec![json!({
+                "type": "field",
+                "ip": ["geoip:private"],
+                "outboundTag": DIRECT_OUTBOUND_TAG
+            })],
+        };
+
+        json!({
+            "domainStrategy": "IPIfNonMatch",
+            "rules": rules
+        })
+    }
+}
+
+/// Plain domains match their subdomains too; entries that already carry an
+/// Xray matcher prefix are passed through.
+fn xray_domain(domain: &str) -> String {
+    const PREFIXES: &[&str] = &["domain:", "full:", "regexp:", "keyword:", "geosite:"];
+
+    if PREFIXES.iter().any(|prefix| domain.starts_with(prefix)) {
+        domain.to_string()
+    } else {
+        format!("domain:{}", domain)
+    }
+}
+
+/// The smallest set of networks covering the whole `bits`-wide address
+/// space except `excluded`
+fn complement(excluded: &[(u128, u8)], bits: u8) -> Vec<(u128, u8)> {
+    let mut networks = Vec::new();
+    subtract((0, 0), excluded, bits, &mut networks);
+    networks
+}
+
+fn subtract(block: (u128, u8), excluded: &[(u128, u8)], bits: u8, out: &mut Vec<(u128, u8)>) {
+    let contains = |outer: (u128, u8), inner: (u128, u8)| {
+        outer.1 <= inner.1 && mask(inner.0, outer.1, bits) == outer.0
+    };
+
+    if excluded.iter().any(|&e| contains(e, block)) {
+        return;
+    }
+    if !excluded.iter().any(|&e| contains(block, e)) {
+        out.push(block);
+        return;
+    }
+
+    let (network, prefix) = block;
+    let half = 1u128 << (bits - prefix - 1);
+    subtract((network, prefix + 1), excluded, bits, out);
+    subtract((network | half, prefix + 1), excluded, bits, out);
+}
+
+fn mask(addr: u128, prefix: u8, bits: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        addr & (u128::MAX << (bits - prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_tunnel_routes_everything() {
+        let profile = RoutingProfile::default();
+        assert_eq!(profile.wireguard_allowed_ips(), vec!["0.0.0.0/0", "::/0"]);
+        assert_eq!(profile.xray_routing()["rules"], json!([]));
+    }
+
+    #[test]
+    fn test_split_tunnel_normalises_entries() {
+        let profile = RoutingProfile::split_tunnel(
+            vec![" Example.COM ".to_string(), "geosite:netflix".to_string()],
+            vec!["10.1.2.3/16".to_string(), "2001:db8::1/32".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            profile.wireguard_allowed_ips(),
+            vec!["10.1.0.0/16", "2001:db8::/32"]
+        );
+
+        let rules = profile.xray_routing()["rules"].clone();
+        assert_eq!(
+            rules[0]["domain"],
+            json!(["domain:example.com", "geosite:netflix"])
+        );
+        assert_eq!(rules[1]["ip"], json!(["10.1.0.0/16", "2001:db8::/32"]));
+        assert_eq!(rules[2]["outboundTag"], json!(DIRECT_OUTBOUND_TAG));
+    }
+
+    #[test]
+    fn test_split_tunnel_validation() {
+        assert!(RoutingProfile::split_tunnel(vec![], vec![]).is_err());
+        assert!(RoutingProfile::split_tunnel(vec![], vec!["10.0.0.0/33".to_string()]).is_err());
+        assert!(RoutingProfile::split_tunnel(vec!["bad domain".to_string()], vec![]).is_err());
+    }
+
+    #[test]
+    fn test_bypass_local_excludes_private_ranges() {
+        let allowed = RoutingProfile::BypassLocal.wireguard_allowed_ips();
+        let networks: Vec<IpNetwork> = allowed.iter().map(|n| n.parse().unwrap()).collect();
+
+        for private in [
+            "10.1.2.3",
+            "172.20.0.1",
+            "192.168.1.1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            let addr = private.parse().unwrap();
+            assert!(!networks.iter().any(|n| n.contains(addr)), "{}", private);
+        }
+        for public in ["1.1.1.1", "172.32.0.1", "8.8.8.8", "2001:4860::8888"] {
+            let addr = public.parse().unwrap();
+            assert!(networks.iter().any(|n| n.contains(addr)), "{}", public);
+        }
+    }
+
+    #[test]
+    fn test_profile_serialization() {
+        let profile =
+            RoutingProfile::split_tunnel(vec!["example.com".to_string()], vec![]).unwrap();
+        let json = serde_json::to_value(&profile).unwrap();
+        assert_eq!(json["type"], "split_tunnel");
+
+        let parsed: RoutingProfile =
+            serde_json::from_value(json!({"type": "bypass_local"})).unwrap();
+        assert_eq!(parsed, RoutingProfile::BypassLocal);
+    }
+}
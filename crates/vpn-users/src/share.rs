@@ -0,0 +1,497 @@
+//! Short share links for connection payloads
+//!
+//! A share link maps a random token to a user's connection link so it can be
+//! handed out as a short URL. Opening the URL shows a small page with the
+//! link, copy buttons and a QR code. Tokens expire and every visit is logged.
+//!
+//! Links are kept in `share_links.json` under the user storage directory and
+//! re-read on every operation, so links created from the CLI are picked up by
+//! a running share server. Every operation holds an advisory lock on
+//! `share_links.json.lock`, so concurrent processes never lose each other's
+//! updates.
+
+use crate::error::{Result, UserError};
+use crate::user::User;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use vpn_crypto::QrCodeGenerator;
+
+const STORE_FILE: &str = "share_links.json";
+
+/// Visits kept per link in the access log
+const MAX_ACCESS_LOG: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareLink {
+    pub token: String,
+    pub user_id: String,
+    pub user_name: String,
+    pub payload: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default)]
+    pub access_log: Vec<ShareAccess>,
+}
+
+/// A single visit of a share page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareAccess {
+    pub accessed_at: DateTime<Utc>,
+    pub remote_addr: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ShareLink {
+    pub fn is_expired(&self) -> bool {
+        Utc::now() >= self.expires_at
+    }
+
+    /// Short URL of this link below `base_url`
+    pub fn url(&self, base_url: &str) -> String {
+        format!("{}/s/{}", base_url.trim_end_matches('/'), self.token)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoreFile {
+    links: Vec<ShareLink>,
+}
+
+pub struct ShareLinkStore {
+    path: PathBuf,
+}
+
+impl ShareLinkStore {
+    pub fn new<P: AsRef<Path>>(storage_path: P) -> Self {
+        Self {
+            path: storage_path.as_ref().join(STORE_FILE),
+        }
+    }
+
+    /// Create a link to `payload` for `user`, valid for `ttl`
+    pub fn create(&self, user: &User, payload: String, ttl: Duration) -> Result<ShareLink> {
+        if ttl <= Duration::zero() {
+            return Err(UserError::ValidationError {
+                field: "ttl".to_string(),
+                message: "share links must be valid for a positive duration".to_string(),
+            });
+        }
+
+        let now = Utc::now();
+        let link = ShareLink {
+            token: generate_token(),
+            user_id: user.id.clone(),
+            user_name: user.name.clone(),
+            payload,
+            created_at: now,
+            expires_at: now + ttl,
+            access_log: Vec::new(),
+        };
+
+        self.modify(|store| store.links.push(link.clone()))?;
+        Ok(link)
+    }
+
+    /// Look up a link for a visitor, recording the visit. Expired and
+    /// unknown tokens resolve to `None`.
+    pub fn resolve(
+        &self,
+        token: &str,
+        remote_addr: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<Option<ShareLink>> {
+        self.modify(|store| {
+            let link = store
+                .links
+                .iter_mut()
+                .find(|link| link.token == token && !link.is_expired())?;
+
+            link.access_log.push(ShareAccess {
+                accessed_at: Utc::now(),
+                remote_addr,
+                user_agent,
+            });
+            if link.access_log.len() > MAX_ACCESS_LOG {
+                link.access_log.remove(0);
+            }
+
+            Some(link.clone())
+        })
+    }
+
+    /// Remove a link; returns `false` if it did not exist
+    pub fn revoke(&self, token: &str) -> Result<bool> {
+        self.modify(|store| {
+            let before = store.links.len();
+            store.links.retain(|link| link.token != token);
+            store.links.len() != before
+        })
+    }
+
+    /// Revoke every link of a user, e.g. after the user was deleted
+    pub fn revoke_user(&self, user_id: &str) -> Result<usize> {
+        self.modify(|store| {
+            let before = store.links.len();
+            store.links.retain(|link| link.user_id != user_id);
+            before - store.links.len()
+        })
+    }
+
+    /// Point every link of `from_id` at `to`, e.g. after the user was
+    /// merged into `to`; returns how many links moved
+    pub fn reassign_user(&self, from_id: &str, to: &User) -> Result<usize> {
+        self.modify(|store| {
+            let mut moved = 0;
+            for link in store.links.iter_mut().filter(|l| l.user_id == from_id) {
+                link.user_id = to.id.clone();
+                link.user_name = to.name.clone();
+                moved += 1;
+            }
+            moved
+        })
+    }
+
+    /// Drop expired links, returning how many were removed
+    pub fn purge_expired(&self) -> Result<usize> {
+        self.modify(|store| {
+            let before = store.links.len();
+            store.links.retain(|link| !link.is_expired());
+            before - store.links.len()
+        })
+    }
+
+    pub fn get(&self, token: &str) -> Result<Option<ShareLink>> {
+        Ok(self.list()?.into_iter().find(|link| link.token == token))
+    }
+
+    pub fn list(&self) -> Result<Vec<ShareLink>> {
+        let lock = self.lock_file()?;
+        lock.lock_shared()?;
+        Ok(self.load()?.links)
+    }
+
+    fn modify<T>(&self, f: impl FnOnce(&mut StoreFile) -> T) -> Result<T> {
+        // Held until the store is renamed into place; dropping the file
+        // releases the lock
+        let lock = self.lock_file()?;
+        lock.lock_exclusive()?;
+
+        let mut store = self.load()?;
+        let result = f(&mut store);
+
+        // Write through a temporary file so readers never see a partial store
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&store)?)?;
+        fs::rename(&tmp, &self.path)?;
+
+        Ok(result)
+    }
+
+    /// Open the lock file next to the store. The store itself cannot carry
+    /// the lock because it is replaced by rename on every write.
+    fn lock_file(&self) -> Result<File> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("json.lock"))?)
+    }
+
+    fn load(&self) -> Result<StoreFile> {
+        if !self.path.exists() {
+            return Ok(StoreFile::default());
+        }
+
+        let content = fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+/// 122 random bits of a v4 UUID, URL-safe
+fn generate_token() -> String {
+    URL_SAFE_NO_PAD.encode(Uuid::new_v4().as_bytes())
+}
+
+/// Render the share page for `link`, titled with `brand`
+pub fn render_share_page(link: &ShareLink, brand: &str) -> Result<String> {
+    let qr = QrCodeGenerator::new().generate_qr_code(&link.payload)?;
+    let qr_svg = String::from_utf8_lossy(&qr);
+    let qr_svg = qr_svg
+        .find("<svg")
+        .map(|start| &qr_svg[start..])
+        .unwrap_or(&qr_svg);
+
+    let brand = html_escape(brand);
+    let user_name = html_escape(&link.user_name);
+    let payload = html_escape(&link.payload);
+    let expires = link.expires_at.format("%Y-%m-%d %H:%M UTC");
+
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>{brand}</title>
+<style>
+body {{ font-family: -apple-system, system-ui, sans-serif; margin: 0; padding: 1.5rem; background: #f4f5f7; color: #222; }}
+main {{ max-width: 28rem; margin: 0 auto; background: #fff; border-radius: 0.75rem; padding: 1.5rem; box-shadow: 0 1px 4px rgba(0,0,0,.1); }}
+h1 {{ font-size: 1.4rem; margin: 0 0 .25rem; }}
+.qr svg {{ width: 100%; height: auto; }}
+textarea {{ width: 100%; box-sizing: border-box; font-family: monospace; font-size: .8rem; }}
+button, .button {{ display: block; box-sizing: border-box; width: 100%; padding: .75rem; margin-top: .5rem; font-size: 1rem; text-align: center; text-decoration: none; border: 0; border-radius: .5rem; background: #2563eb; color: #fff; }}
+.muted {{ color: #666; font-size: .85rem; }}
+</style>
+</head>
+<body>
+<main>
+<h1>{brand}</h1>
+<p class="muted">Connection for {user_name}</p>
+<div class="qr">{qr_svg}</div>
+<textarea id="payload" rows="5" readonly>{payload}</textarea>
+<button onclick="copyPayload(this)">Copy connection link</button>
+<a class="button" href="{payload}">Open in app</a>
+<p class="muted">This page expires on {expires}.</p>
+</main>
+<script>
+function copyPayload(button) {{
+  var field = document.getElementById("payload");
+  field.select();
+  var done = function() {{ button.textContent = "Copied"; }};
+  if (navigator.clipboard) {{
+    navigator.clipboard.writeText(field.value).then(done);
+  }} else {{
+    document.execCommand("copy");
+    done();
+  }}
+}}
+</script>
+</body>
+</html>
+"#
+    ))
+}
+
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// HTTP server for share pages
+#[cfg(feature = "share-server")]
+pub mod server {
+    use super::{render_share_page, ShareLinkStore};
+    use crate::error::Result;
+    use axum::extract::{ConnectInfo, Path, State};
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::response::{Html, IntoResponse, Response};
+    use axum::routing::get;
+    use axum::Router;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    struct ShareState {
+        store: ShareLinkStore,
+        brand: String,
+    }
+
+    /// Routes serving `GET /s/<token>`
+    pub fn router(store: ShareLinkStore, brand: String) -> Router {
+        Router::new()
+            .route("/s/:token", get(share_page))
+            .with_state(Arc::new(ShareState { store, brand }))
+    }
+
+    /// Serve share pages on `addr` until the server fails
+    pub async fn serve(store: ShareLinkStore, brand: String, addr: SocketAddr) -> Result<()> {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        axum::serve(
+            listener,
+            router(store, brand).into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn share_page(
+        State(state): State<Arc<ShareState>>,
+        Path(token): Path<String>,
+        remote: Option<ConnectInfo<SocketAddr>>,
+        headers: HeaderMap,
+    ) -> Response {
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let remote_addr = remote.map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        let link = match state.store.resolve(&token, remote_addr, user_agent) {
+            Ok(Some(link)) => link,
+            Ok(None) => {
+                return (StatusCode::NOT_FOUND, "Link not found or expired").into_response()
+            }
+            Err(e) => {
+                tracing::error!("Failed to resolve share link: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+
+        match render_share_page(&link, &state.brand) {
+            Ok(page) => (
+                [
+                    (header::CACHE_CONTROL, "no-store"),
+                    (header::REFERRER_POLICY, "no-referrer"),
+                ],
+                Html(page),
+            )
+                .into_response(),
+            Err(e) => {
+                tracing::error!("Failed to render share page: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use vpn_types::protocol::VpnProtocol;
+
+    fn user() -> User {
+        User::new("alice".to_string(), VpnProtocol::Vless)
+    }
+
+    #[test]
+    fn test_create_and_resolve_logs_access() {
+        let dir = TempDir::new().unwrap();
+        let store = ShareLinkStore::new(dir.path());
+        let link = store
+            .create(&user(), "vless://example".to_string(), Duration::hours(1))
+            .unwrap();
+
+        assert_eq!(
+            link.url("https://vpn.example.com/"),
+            format!("https://vpn.example.com/s/{}", link.token)
+        );
+
+        // A second store instance sees links written by the first
+        let other = ShareLinkStore::new(dir.path());
+        let resolved = other
+            .resolve(&link.token, Some("203.0.113.7".to_string()), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(resolved.payload, "vless://example");
+
+        let stored = store.get(&link.token).unwrap().unwrap();
+        assert_eq!(stored.access_log.len(), 1);
+        assert_eq!(
+            stored.access_log[0].remote_addr.as_deref(),
+            Some("203.0.113.7")
+        );
+
+        assert!(store.resolve("unknown", None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_expired_links() {
+        let dir = TempDir::new().unwrap();
+        let store = ShareLinkStore::new(dir.path());
+        let link = store
+            .create(&user(), "vless://example".to_string(), Duration::hours(1))
+            .unwrap();
+
+        store
+            .modify(|file| file.links[0].expires_at = Utc::now() - Duration::seconds(1))
+            .unwrap();
+
+        assert!(store.resolve(&link.token, None, None).unwrap().is_none());
+        assert_eq!(store.purge_expired().unwrap(), 1);
+        assert!(store.list().unwrap().is_empty());
+
+        assert!(store
+            .create(&user(), "vless://example".to_string(), Duration::zero())
+            .is_err());
+    }
+
+    #[test]
+    fn test_concurrent_stores_keep_every_link() {
+        let dir = TempDir::new().unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let path = dir.path().to_path_buf();
+                std::thread::spawn(move || {
+                    // A separate store per thread, like separate processes
+                    let store = ShareLinkStore::new(path);
+                    for _ in 0..5 {
+                        store
+                            .create(&user(), "vless://example".to_string(), Duration::hours(1))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(ShareLinkStore::new(dir.path()).list().unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_revoke() {
+        let dir = TempDir::new().unwrap();
+        let store = ShareLinkStore::new(dir.path());
+        let alice = user();
+        let link = store
+            .create(&alice, "vless://a".to_string(), Duration::hours(1))
+            .unwrap();
+        store
+            .create(&alice, "vless://b".to_string(), Duration::hours(1))
+            .unwrap();
+
+        assert!(store.revoke(&link.token).unwrap());
+        assert!(!store.revoke(&link.token).unwrap());
+        assert_eq!(store.revoke_user(&alice.id).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_share_page_escapes_payload() {
+        let mut alice = user();
+        alice.name = "<alice>".to_string();
+        let store_dir = TempDir::new().unwrap();
+        let link = ShareLinkStore::new(store_dir.path())
+            .create(
+                &alice,
+                "vless://id@host?a=1&b=2#x".to_string(),
+                Duration::hours(1),
+            )
+            .unwrap();
+
+        let page = render_share_page(&link, "Acme VPN").unwrap();
+        assert!(page.contains("<title>Acme VPN</title>"));
+        assert!(page.contains("&lt;alice&gt;"));
+        assert!(page.contains("vless://id@host?a=1&amp;b=2#x"));
+        assert!(page.contains("<svg"));
+    }
+}
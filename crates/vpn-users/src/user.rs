@@ -1,5 +1,9 @@
+use crate::routing::RoutingProfile;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 use vpn_types::protocol::VpnProtocol;
 
@@ -15,6 +19,33 @@ pub struct User {
     pub protocol: VpnProtocol,
     pub config: UserConfig,
     pub stats: UserStats,
+    #[serde(default)]
+    pub routing: RoutingProfile,
+    /// Free-form operator notes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Key/value labels such as `billing=paid` or `team=ops`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// How the server handles this user's traffic
+    #[serde(default)]
+    pub traffic: TrafficPolicy,
+    /// Credentials of duplicates merged into this user, still accepted so
+    /// clients set up with them keep working
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub linked_credentials: Vec<LinkedCredentials>,
+}
+
+/// Credentials a user carried under another ID before it was merged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkedCredentials {
+    pub id: String,
+    pub short_id: String,
+    pub protocol: VpnProtocol,
+    pub config: UserConfig,
+    /// Install path the duplicate was found in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,6 +71,22 @@ pub struct UserStats {
     pub total_uptime: u64, // seconds
 }
 
+/// Server-side handling of a user's traffic, applied through Xray routing
+/// rules and policy levels keyed by the user's Xray email
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", content = "outbound", rename_all = "snake_case")]
+pub enum TrafficPolicy {
+    /// Send traffic out through the default outbound
+    #[default]
+    Direct,
+    /// Drop all traffic while keeping the user's credentials valid
+    Block,
+    /// Use the throttled policy level with small connection buffers
+    Throttle,
+    /// Send traffic through an outbound defined in the server config
+    Outbound(String),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum UserStatus {
     Active,
@@ -69,9 +116,23 @@ impl User {
             protocol,
             config: UserConfig::default(),
             stats: UserStats::default(),
+            routing: RoutingProfile::default(),
+            notes: None,
+            tags: BTreeMap::new(),
+            traffic: TrafficPolicy::default(),
+            linked_credentials: Vec::new(),
         }
     }
 
+    pub fn with_id(mut self, id: String) -> Self {
+        let uuid_gen = vpn_crypto::UuidGenerator::new();
+        self.short_id = uuid_gen
+            .generate_short_id(&id)
+            .unwrap_or_else(|_| "default".to_string());
+        self.id = id;
+        self
+    }
+
     pub fn with_email(mut self, email: String) -> Self {
         self.email = Some(email);
         self
@@ -82,6 +143,63 @@ impl User {
         self
     }
 
+    pub fn with_routing(mut self, routing: RoutingProfile) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    pub fn with_notes(mut self, notes: String) -> Self {
+        self.notes = Some(notes);
+        self
+    }
+
+    pub fn with_traffic(mut self, traffic: TrafficPolicy) -> Self {
+        self.traffic = traffic;
+        self
+    }
+
+    pub fn with_tag(mut self, key: String, value: String) -> Self {
+        self.tags.insert(key, value);
+        self
+    }
+
+    pub fn set_tag(&mut self, key: String, value: String) -> Option<String> {
+        self.tags.insert(key, value)
+    }
+
+    pub fn remove_tag(&mut self, key: &str) -> Option<String> {
+        self.tags.remove(key)
+    }
+
+    pub fn matches_tag(&self, filter: &TagFilter) -> bool {
+        match (self.tags.get(&filter.key), &filter.value) {
+            (Some(value), Some(wanted)) => value == wanted,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Case-insensitive substring match over the name, email, ID, notes and
+    /// tags
+    pub fn matches_search(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        let contains = |text: &str| text.to_lowercase().contains(&query);
+
+        contains(&self.name)
+            || contains(&self.id)
+            || self.email.as_deref().is_some_and(contains)
+            || self.notes.as_deref().is_some_and(contains)
+            || self
+                .tags
+                .iter()
+                .any(|(key, value)| contains(key) || contains(value))
+    }
+
+    /// Whether `id` is this user's ID or one merged into it
+    pub fn answers_to(&self, id: &str) -> bool {
+        self.id == id || self.linked_credentials.iter().any(|c| c.id == id)
+    }
+
     pub fn is_active(&self) -> bool {
         matches!(self.status, UserStatus::Active)
     }
@@ -166,6 +284,83 @@ impl UserStatus {
     }
 }
 
+/// Selects users by tag: `key` matches any value, `key:value` (or
+/// `key=value`) only that value
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TagFilter {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl FromStr for TagFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (key, value) = match s.find([':', '=']) {
+            Some(pos) => (&s[..pos], Some(s[pos + 1..].trim().to_string())),
+            None => (s, None),
+        };
+
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("Invalid tag filter '{}': missing key", s));
+        }
+
+        Ok(Self {
+            key: key.to_string(),
+            value,
+        })
+    }
+}
+
+impl fmt::Display for TagFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}:{}", self.key, value),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+impl FromStr for TrafficPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(tag) = s.strip_prefix("outbound:") {
+            let tag = tag.trim();
+            if tag.is_empty() {
+                return Err(format!(
+                    "Invalid traffic policy '{}': missing outbound tag",
+                    s
+                ));
+            }
+            return Ok(Self::Outbound(tag.to_string()));
+        }
+
+        match s.to_lowercase().as_str() {
+            "direct" => Ok(Self::Direct),
+            "block" => Ok(Self::Block),
+            "throttle" => Ok(Self::Throttle),
+            _ => Err(format!(
+                "Invalid traffic policy '{}': expected direct, block, throttle or outbound:<tag>",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TrafficPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Direct => write!(f, "direct"),
+            Self::Block => write!(f, "block"),
+            Self::Throttle => write!(f, "throttle"),
+            Self::Outbound(tag) => write!(f, "outbound:{}", tag),
+        }
+    }
+}
+
 // VpnProtocol methods are now provided by vpn_types::protocol::VpnProtocol
 
 #[cfg(test)]
@@ -245,4 +440,38 @@ mod tests {
         assert_eq!(user.protocol, VpnProtocol::Vless);
         assert_eq!(user.name, "testuser");
     }
+
+    #[test]
+    fn test_tag_filter_parsing() {
+        let filter: TagFilter = "billing:paid".parse().unwrap();
+        assert_eq!(filter.key, "billing");
+        assert_eq!(filter.value.as_deref(), Some("paid"));
+
+        let filter: TagFilter = "team=ops".parse().unwrap();
+        assert_eq!(filter.key, "team");
+        assert_eq!(filter.value.as_deref(), Some("ops"));
+
+        let filter: TagFilter = "vip".parse().unwrap();
+        assert_eq!(filter.value, None);
+
+        assert!(":paid".parse::<TagFilter>().is_err());
+    }
+
+    #[test]
+    fn test_tag_and_search_matching() {
+        let user = User::new("alice".to_string(), VpnProtocol::Vless)
+            .with_email("alice@example.com".to_string())
+            .with_notes("Moved to the annual plan".to_string())
+            .with_tag("billing".to_string(), "paid".to_string());
+
+        assert!(user.matches_tag(&"billing".parse().unwrap()));
+        assert!(user.matches_tag(&"billing:paid".parse().unwrap()));
+        assert!(!user.matches_tag(&"billing:trial".parse().unwrap()));
+        assert!(!user.matches_tag(&"team".parse().unwrap()));
+
+        assert!(user.matches_search("ALICE"));
+        assert!(user.matches_search("annual"));
+        assert!(user.matches_search("paid"));
+        assert!(!user.matches_search("bob"));
+    }
 }
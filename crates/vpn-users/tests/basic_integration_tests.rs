@@ -167,6 +167,11 @@ fn test_user_struct_serialization() -> Result<(), Box<dyn std::error::Error>> {
             last_connection: None,
             total_uptime: 0,
         },
+        routing: Default::default(),
+        notes: None,
+        tags: Default::default(),
+        traffic: Default::default(),
+        linked_credentials: Vec::new(),
     };
 
     // Test JSON serialization